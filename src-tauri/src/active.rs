@@ -0,0 +1,138 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::events::Event;
+use crate::ffmpeg::ProgressPayload;
+use crate::tray;
+
+// ==========================================
+// ACTIVE JOBS
+// ==========================================
+// Every queue and direct job while it runs, as its events describe it: an
+// entry appears with `job-started`, follows progress and pause changes,
+// and goes when the job ends (queue.rs), however it ended. Events are
+// stamped with the job they belong to from here (see events::emit), the
+// tray shows it, and get_active_jobs hands all of it to a frontend that
+// reloaded or crashed halfway through an encode.
+
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct ActiveJob {
+    pub job_id: u64,
+    pub input: String,
+    pub output: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoder: Option<String>,
+    pub percent: f32,
+    pub paused: bool,
+    // Unix seconds
+    pub started_at: u64,
+    // The last encode progress, with its speed and ETA
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<ProgressPayload>,
+}
+
+// Which job an event came from, on every envelope that has one.
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct JobRef {
+    pub job_id: u64,
+    // None until the job has said what it's working on (job-started)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
+}
+
+#[derive(Default)]
+pub struct ActiveJobs {
+    jobs: Mutex<BTreeMap<u64, ActiveJob>>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Called for every event before it goes out, with the job it's from.
+// True when what the tray shows changed: progress only counts when the
+// whole percentage does.
+pub fn observe(app: &AppHandle, event: &Event, job_id: Option<u64>) -> bool {
+    let Some(active) = app.try_state::<ActiveJobs>() else { return false };
+    let mut jobs = active.jobs.lock().unwrap();
+    match event {
+        Event::JobStarted(started) => match started.job_id {
+            // A later phase of the same job starts again from 0%
+            Some(id) => {
+                let (paused, started_at) = jobs.get(&id).map_or((false, now_secs()), |j| (j.paused, j.started_at));
+                let job = ActiveJob {
+                    job_id: id,
+                    input: started.input.clone(),
+                    output: started.output.clone(),
+                    encoder: started.encoder.clone(),
+                    percent: 0.0,
+                    paused,
+                    started_at,
+                    progress: None,
+                };
+                jobs.insert(id, job);
+                true
+            }
+            None => false,
+        },
+        Event::JobProgress(progress) => match jobs.get_mut(&progress.job_id) {
+            Some(job) => {
+                let redraw = job.percent.floor() != progress.percent.floor();
+                job.percent = progress.percent;
+                redraw
+            }
+            None => false,
+        },
+        Event::CompressionProgress(payload) => {
+            if let Some(job) = job_id.and_then(|id| jobs.get_mut(&id)) {
+                job.progress = Some(payload.clone());
+            }
+            false
+        }
+        Event::JobPaused(change) | Event::JobResumed(change) => match jobs.get_mut(&change.job_id) {
+            Some(job) => {
+                job.paused = matches!(event, Event::JobPaused(_));
+                true
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+// A queue or direct job is over.
+pub fn job_ended(app: &AppHandle, job_id: u64) {
+    let removed = app.try_state::<ActiveJobs>().is_some_and(|a| a.jobs.lock().unwrap().remove(&job_id).is_some());
+    tray::job_ended(app, removed);
+}
+
+pub fn job_ref(app: &AppHandle, job_id: u64) -> JobRef {
+    let input = app.try_state::<ActiveJobs>().and_then(|a| a.jobs.lock().unwrap().get(&job_id).map(|j| j.input.clone()));
+    JobRef { job_id, input }
+}
+
+// Oldest first
+pub fn snapshot(app: &AppHandle) -> Vec<ActiveJob> {
+    app.try_state::<ActiveJobs>().map(|a| a.jobs.lock().unwrap().values().cloned().collect()).unwrap_or_default()
+}
+
+// The latest one started, and how many others there are.
+pub fn latest(app: &AppHandle) -> (Option<ActiveJob>, usize) {
+    let Some(active) = app.try_state::<ActiveJobs>() else { return (None, 0) };
+    let jobs = active.jobs.lock().unwrap();
+    (jobs.values().next_back().cloned(), jobs.len().saturating_sub(1))
+}
+
+// ==========================================
+// COMMAND: GET ACTIVE JOBS
+// ==========================================
+// What's running right now; waiting queue jobs are in get_queue.
+#[tauri::command]
+pub fn get_active_jobs(app: AppHandle) -> Vec<ActiveJob> {
+    snapshot(&app)
+}
@@ -0,0 +1,529 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::events::{self, Event};
+use crate::timeline;
+
+const MANIFEST_VERSION: u32 = 1;
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+// Resumable hashes checkpoint at every segment boundary, so a restart loses
+// at most this much work
+const SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+// sha256 is the default, so manifests match `sha256sum`. sha256-tree is
+// just as strong and a 100 GB hash can pick up where it left off after a
+// restart, but its digests are its own, so it's opt-in. xxh3/crc32 are much
+// faster on huge inputs but only protect against accidental corruption, not
+// tampering.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashAlgo {
+    #[default]
+    Sha256,
+    Sha256Tree,
+    Xxh3,
+    Crc32,
+}
+
+impl HashAlgo {
+    fn name(self) -> &'static str {
+        match self {
+            HashAlgo::Sha256 => "sha256",
+            HashAlgo::Sha256Tree => "sha256-tree",
+            HashAlgo::Xxh3 => "xxh3",
+            HashAlgo::Crc32 => "crc32",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sha256" => Some(HashAlgo::Sha256),
+            "sha256-tree" => Some(HashAlgo::Sha256Tree),
+            "xxh3" => Some(HashAlgo::Xxh3),
+            "crc32" => Some(HashAlgo::Crc32),
+            _ => None,
+        }
+    }
+}
+
+// sha256-tree: SHA-256 of the concatenated (lowercase hex) SHA-256 digests
+// of the file's 64 MiB segments, the last one possibly shorter. An empty
+// file is one empty segment. Finished segments are all the state there is,
+// which is what makes it checkpointable.
+#[derive(Clone)]
+struct TreeHasher {
+    segments: Vec<String>,
+    current: Sha256,
+    filled: u64,
+}
+
+impl TreeHasher {
+    fn new(segments: Vec<String>) -> Self {
+        TreeHasher { segments, current: Sha256::new(), filled: 0 }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = ((SEGMENT_SIZE - self.filled) as usize).min(data.len());
+            self.current.update(&data[..take]);
+            self.filled += take as u64;
+            data = &data[take..];
+            if self.filled == SEGMENT_SIZE {
+                self.close_segment();
+            }
+        }
+    }
+
+    fn close_segment(&mut self) {
+        let segment = std::mem::replace(&mut self.current, Sha256::new());
+        self.segments.push(format!("{:x}", segment.finalize()));
+        self.filled = 0;
+    }
+
+    fn finish_hex(mut self) -> String {
+        if self.filled > 0 || self.segments.is_empty() {
+            self.close_segment();
+        }
+        let mut top = Sha256::new();
+        for segment in &self.segments {
+            top.update(segment.as_bytes());
+        }
+        format!("{:x}", top.finalize())
+    }
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Sha256Tree(TreeHasher),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+    Crc32(crc32fast::Hasher),
+}
+
+impl Hasher {
+    fn new(algo: HashAlgo) -> Self {
+        match algo {
+            HashAlgo::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgo::Sha256Tree => Hasher::Sha256Tree(TreeHasher::new(vec![])),
+            HashAlgo::Xxh3 => Hasher::Xxh3(Box::new(xxhash_rust::xxh3::Xxh3::new())),
+            HashAlgo::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+        }
+    }
+
+    // Back from a checkpoint taken `offset` bytes in; None when the saved
+    // state doesn't belong to `algo`.
+    fn resume(algo: HashAlgo, state: &SavedState, offset: u64) -> Option<Self> {
+        match (algo, state) {
+            (HashAlgo::Sha256Tree, SavedState::Sha256Tree { segments }) if segments.len() as u64 * SEGMENT_SIZE == offset => {
+                Some(Hasher::Sha256Tree(TreeHasher::new(segments.clone())))
+            }
+            (HashAlgo::Crc32, SavedState::Crc32 { crc }) => Some(Hasher::Crc32(crc32fast::Hasher::new_with_initial_len(*crc, offset))),
+            _ => None,
+        }
+    }
+
+    // None for hashes whose running state can't be saved (sha256, xxh3)
+    fn checkpoint(&self) -> Option<SavedState> {
+        match self {
+            Hasher::Sha256Tree(h) if h.filled == 0 => Some(SavedState::Sha256Tree { segments: h.segments.clone() }),
+            Hasher::Crc32(h) => Some(SavedState::Crc32 { crc: h.clone().finalize() }),
+            _ => None,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Sha256Tree(h) => h.update(data),
+            Hasher::Xxh3(h) => h.update(data),
+            Hasher::Crc32(h) => h.update(data),
+        }
+    }
+
+    fn finish_hex(self) -> String {
+        match self {
+            Hasher::Sha256(h) => format!("{:x}", h.finalize()),
+            Hasher::Sha256Tree(h) => h.finish_hex(),
+            Hasher::Xxh3(h) => format!("{:016x}", h.digest()),
+            Hasher::Crc32(h) => format!("{:08x}", h.finalize()),
+        }
+    }
+}
+
+// ==========================================
+// HASH CHECKPOINTS
+// ==========================================
+// app_cache_dir/archive-hash/<key>.json, one per (file, algorithm) being
+// hashed, rewritten at every segment boundary and removed once the hash is
+// done. Creating or verifying a manifest again after a restart resumes from
+// it, as long as the file's size and mtime are what they were.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum SavedState {
+    Sha256Tree { segments: Vec<String> },
+    Crc32 { crc: u32 },
+}
+
+#[derive(Serialize, Deserialize)]
+struct HashCheckpoint {
+    path: String,
+    algo: String,
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    offset: u64,
+    state: SavedState,
+}
+
+fn checkpoint_path(app: &AppHandle, path: &str, algo: HashAlgo) -> Option<PathBuf> {
+    let key = xxhash_rust::xxh3::xxh3_64(format!("{}\n{}", algo.name(), path).as_bytes());
+    app.path().app_cache_dir().ok().map(|d| d.join("archive-hash").join(format!("{:016x}.json", key)))
+}
+
+fn mtime(meta: &fs::Metadata) -> (u64, u32) {
+    meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or((0, 0), |d| (d.as_secs(), d.subsec_nanos()))
+}
+
+fn save_checkpoint(target: &Path, checkpoint: &HashCheckpoint) {
+    if let Some(dir) = target.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let partial = target.with_extension("json.part");
+    let written = serde_json::to_vec(checkpoint).map_err(|e| e.to_string()).and_then(|json| fs::write(&partial, json).map_err(|e| e.to_string()));
+    if let Err(e) = written.and_then(|_| fs::rename(&partial, target).map_err(|e| e.to_string())) {
+        println!("⚠️ Could not save the hash checkpoint for {}: {}", checkpoint.path, e);
+    }
+}
+
+// Where hashing can start: the checkpoint's offset and state when it's still
+// about this exact file, else the beginning.
+fn resume_point(app: &AppHandle, target: &Path, path: &str, algo: HashAlgo, meta: &fs::Metadata) -> (u64, Hasher) {
+    let fresh = (0, Hasher::new(algo));
+    let Some(saved) = fs::read_to_string(target).ok().and_then(|t| serde_json::from_str::<HashCheckpoint>(&t).ok()) else {
+        return fresh;
+    };
+    let (secs, nanos) = mtime(meta);
+    if saved.size != meta.len() || (saved.mtime_secs, saved.mtime_nanos) != (secs, nanos) {
+        println!("♻️ {} changed since its hash checkpoint, hashing it again from the start", path);
+        timeline::record(app, timeline::HASH_RESTARTED, &[("path", path.to_string()), ("checkpoint_offset", saved.offset.to_string())]);
+        let _ = fs::remove_file(target);
+        return fresh;
+    }
+    match Hasher::resume(algo, &saved.state, saved.offset).filter(|_| saved.path == path && saved.offset <= meta.len()) {
+        Some(hasher) => {
+            println!("⏩ Resuming the hash of {} at {} bytes", path, saved.offset);
+            timeline::record(app, timeline::HASH_RESUMED, &[("path", path.to_string()), ("offset", saved.offset.to_string())]);
+            (saved.offset, hasher)
+        }
+        None => fresh,
+    }
+}
+
+// ==========================================
+// MANIFEST
+// ==========================================
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ManifestFile {
+    pub path: String,
+    pub size: u64,
+    pub hash: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ArchiveManifest {
+    pub version: u32,
+    // Kept as a plain string so manifests written by newer versions still load
+    // and can be rejected with a clear message instead of a parse error.
+    pub hash_algo: String,
+    pub created_at: u64,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct HashProgress {
+    path: String,
+    bytes_done: u64,
+    bytes_total: u64,
+    // Where this run started, > 0 when it picked up a checkpoint
+    resumed_from: u64,
+}
+
+#[derive(Serialize, Clone)]
+pub struct FileCheck {
+    pub path: String,
+    pub ok: bool,
+    pub expected: String,
+    pub actual: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct VerifyReport {
+    pub ok: bool,
+    pub hash_algo: String,
+    pub files: Vec<FileCheck>,
+}
+
+// Streams the file in large chunks, emitting `archive-hash-progress` as it
+// goes, and checkpoints resumable hashes at every segment boundary.
+fn hash_file(app: &AppHandle, path: &str, algo: HashAlgo) -> Result<(u64, String), String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let meta = file.metadata().map_err(|e| format!("{}: {}", path, e))?;
+    hash_stream(app, path, file, &meta, algo)
+}
+
+// hash_file past the open: `file` reads the file at `path`, described by `meta`
+fn hash_stream(app: &AppHandle, path: &str, mut file: impl Read + Seek, meta: &fs::Metadata, algo: HashAlgo) -> Result<(u64, String), String> {
+    let total = meta.len();
+    let checkpoint = checkpoint_path(app, path, algo).filter(|_| Hasher::new(algo).checkpoint().is_some());
+    let (resumed_from, mut hasher) = match &checkpoint {
+        Some(target) => resume_point(app, target, path, algo, meta),
+        None => (0, Hasher::new(algo)),
+    };
+    file.seek(SeekFrom::Start(resumed_from)).map_err(|e| format!("{}: {}", path, e))?;
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut done: u64 = resumed_from;
+
+    loop {
+        // Reads stop at segment boundaries so checkpoints land on them
+        let to_boundary = SEGMENT_SIZE - done % SEGMENT_SIZE;
+        let want = (to_boundary as usize).min(buf.len());
+        let n = file.read(&mut buf[..want]).map_err(|e| format!("{}: {}", path, e))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        done += n as u64;
+        if done.is_multiple_of(SEGMENT_SIZE) {
+            if let (Some(target), Some(state)) = (&checkpoint, hasher.checkpoint()) {
+                let (mtime_secs, mtime_nanos) = mtime(meta);
+                save_checkpoint(target, &HashCheckpoint { path: path.to_string(), algo: algo.name().to_string(), size: total, mtime_secs, mtime_nanos, offset: done, state });
+            }
+        }
+        events::emit(app, Event::ArchiveHashProgress(HashProgress {
+            path: path.to_string(),
+            bytes_done: done,
+            bytes_total: total,
+            resumed_from,
+        }));
+    }
+    if let Some(target) = &checkpoint {
+        let _ = fs::remove_file(target);
+    }
+    Ok((done, hasher.finish_hex()))
+}
+
+async fn hash_file_blocking(app: &AppHandle, path: String, algo: HashAlgo) -> Result<(u64, String), String> {
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || hash_file(&app, &path, algo))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// ==========================================
+// COMMAND: WRITE ARCHIVE MANIFEST
+// ==========================================
+#[tauri::command]
+pub async fn create_archive_manifest(
+    app: AppHandle,
+    files: Vec<String>,
+    manifest_path: String,
+    hash_algo: Option<HashAlgo>,
+) -> Result<ArchiveManifest, String> {
+    let algo = hash_algo.unwrap_or_default();
+    println!("🔐 Hashing {} files ({})...", files.len(), algo.name());
+
+    let mut entries = vec![];
+    for path in files {
+        let (size, hash) = hash_file_blocking(&app, path.clone(), algo).await?;
+        entries.push(ManifestFile { path, size, hash });
+    }
+
+    let manifest = ArchiveManifest {
+        version: MANIFEST_VERSION,
+        hash_algo: algo.name().to_string(),
+        created_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        files: entries,
+    };
+    let json = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+    fs::write(&manifest_path, json).map_err(|e| e.to_string())?;
+    Ok(manifest)
+}
+
+// ==========================================
+// COMMAND: VERIFY ARCHIVE
+// ==========================================
+#[tauri::command]
+pub async fn verify_archive(app: AppHandle, manifest_path: String) -> Result<VerifyReport, String> {
+    let text = fs::read_to_string(&manifest_path).map_err(|e| e.to_string())?;
+    let manifest: ArchiveManifest = serde_json::from_str(&text)
+        .map_err(|e| format!("Not a valid archive manifest: {}", e))?;
+
+    let algo = HashAlgo::from_name(&manifest.hash_algo).ok_or_else(|| {
+        format!(
+            "This manifest uses the '{}' hash, which this version doesn't know. It was probably created by a newer version of the app.",
+            manifest.hash_algo
+        )
+    })?;
+
+    let mut checks = vec![];
+    for file in &manifest.files {
+        let check = match hash_file_blocking(&app, file.path.clone(), algo).await {
+            Ok((size, hash)) => FileCheck {
+                path: file.path.clone(),
+                ok: size == file.size && hash == file.hash,
+                expected: file.hash.clone(),
+                actual: Some(hash),
+                error: if size != file.size {
+                    Some(format!("Size changed: {} bytes, expected {}", size, file.size))
+                } else {
+                    None
+                },
+            },
+            Err(e) => FileCheck {
+                path: file.path.clone(),
+                ok: false,
+                expected: file.hash.clone(),
+                actual: None,
+                error: Some(e),
+            },
+        };
+        checks.push(check);
+    }
+
+    Ok(VerifyReport {
+        ok: checks.iter().all(|c| c.ok),
+        hash_algo: manifest.hash_algo,
+        files: checks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::Harness;
+    use std::io;
+
+    // Reads like `file` until `left` runs out, then fails the way a drive
+    // pulled mid-hash would
+    struct CutOff {
+        file: File,
+        left: u64,
+    }
+
+    impl Read for CutOff {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.left == 0 {
+                return Err(io::Error::other("the drive went away"));
+            }
+            let want = (buf.len() as u64).min(self.left) as usize;
+            let n = self.file.read(&mut buf[..want])?;
+            self.left -= n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for CutOff {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.file.seek(pos)
+        }
+    }
+
+    // Two and a half segments, different all the way through so a segment
+    // hashed twice or skipped shows
+    fn big_file(h: &Harness) -> String {
+        let path = h.file("big.bin");
+        let mut bytes = Vec::with_capacity((SEGMENT_SIZE * 5 / 2) as usize);
+        for block in 0..SEGMENT_SIZE * 5 / 2 / 4096 {
+            bytes.extend_from_slice(&[(block % 251) as u8; 4096]);
+        }
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn cut_off(h: &Harness, path: &str, algo: HashAlgo, after: u64) -> Result<(u64, String), String> {
+        let file = File::open(path).unwrap();
+        let meta = file.metadata().unwrap();
+        hash_stream(h.handle(), path, CutOff { file, left: after }, &meta, algo)
+    }
+
+    fn saved(h: &Harness, path: &str, algo: HashAlgo) -> Option<HashCheckpoint> {
+        let target = checkpoint_path(h.handle(), path, algo).unwrap();
+        fs::read_to_string(target).ok().map(|t| serde_json::from_str(&t).unwrap())
+    }
+
+    #[test]
+    fn plain_sha256_is_the_default() {
+        assert_eq!(HashAlgo::default(), HashAlgo::Sha256);
+        assert_eq!(Hasher::new(HashAlgo::default()).checkpoint(), None);
+    }
+
+    #[test]
+    fn an_interrupted_hash_resumes_to_the_same_digest() {
+        let h = Harness::new("archive-resume", "{}");
+        let path = big_file(&h);
+        let dir = h.app.path().app_cache_dir().unwrap().join("archive-hash");
+        for algo in [HashAlgo::Sha256Tree, HashAlgo::Crc32] {
+            let whole = hash_file(h.handle(), &path, algo).unwrap();
+            assert_eq!(whole.0, SEGMENT_SIZE * 5 / 2);
+            assert!(saved(&h, &path, algo).is_none(), "a finished hash leaves no checkpoint");
+
+            // Mid first segment, right on a boundary, and mid third segment
+            for after in [SEGMENT_SIZE / 3, SEGMENT_SIZE, SEGMENT_SIZE * 2 + 12345] {
+                assert!(cut_off(&h, &path, algo, after).is_err());
+                let checkpoint = saved(&h, &path, algo);
+                let boundary = after / SEGMENT_SIZE * SEGMENT_SIZE;
+                assert_eq!(checkpoint.as_ref().map(|c| c.offset), Some(boundary).filter(|b| *b > 0), "{:?} cut off at {}", algo, after);
+                if let Some(checkpoint) = checkpoint {
+                    assert!(checkpoint_path(h.handle(), &path, algo).unwrap().starts_with(&dir));
+                    assert_eq!((checkpoint.path.as_str(), checkpoint.size), (path.as_str(), SEGMENT_SIZE * 5 / 2));
+                    assert_eq!(checkpoint.algo, algo.name());
+                }
+                assert_eq!(hash_file(h.handle(), &path, algo).unwrap(), whole, "{:?} resumed after {}", algo, after);
+                assert!(saved(&h, &path, algo).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn a_tree_checkpoint_holds_one_digest_per_finished_segment() {
+        let h = Harness::new("archive-segments", "{}");
+        let path = big_file(&h);
+        assert!(cut_off(&h, &path, HashAlgo::Sha256Tree, SEGMENT_SIZE * 2 + 1).is_err());
+        let Some(SavedState::Sha256Tree { segments }) = saved(&h, &path, HashAlgo::Sha256Tree).map(|c| c.state) else {
+            panic!("no sha256-tree checkpoint");
+        };
+        let bytes = fs::read(&path).unwrap();
+        let expected: Vec<String> = bytes.chunks(SEGMENT_SIZE as usize).take(2).map(|s| format!("{:x}", Sha256::digest(s))).collect();
+        assert_eq!(segments, expected);
+    }
+
+    #[test]
+    fn a_file_changed_since_its_checkpoint_is_hashed_from_the_start() {
+        let h = Harness::new("archive-changed", "{}");
+        let path = big_file(&h);
+        assert!(cut_off(&h, &path, HashAlgo::Crc32, SEGMENT_SIZE + 1).is_err());
+        assert!(saved(&h, &path, HashAlgo::Crc32).is_some());
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.truncate(SEGMENT_SIZE as usize * 2);
+        bytes[0] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        let (size, hash) = hash_file(h.handle(), &path, HashAlgo::Crc32).unwrap();
+        assert_eq!((size, hash), (bytes.len() as u64, format!("{:08x}", crc32fast::hash(&bytes))));
+    }
+
+    #[test]
+    fn sha256_tree_of_a_short_file_is_the_digest_of_its_one_segment() {
+        let mut hasher = Hasher::new(HashAlgo::Sha256Tree);
+        hasher.update(b"hello");
+        let segment = format!("{:x}", Sha256::digest(b"hello"));
+        assert_eq!(hasher.finish_hex(), format!("{:x}", Sha256::digest(segment.as_bytes())));
+    }
+}
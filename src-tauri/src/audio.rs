@@ -0,0 +1,431 @@
+use base64::Engine;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Cursor;
+use std::time::Instant;
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::audio_format;
+use crate::cancel;
+use crate::events::Event;
+use crate::ffmpeg::{self, TrackedOutput};
+use crate::history::{self, HistoryEntry};
+use crate::inputs;
+use crate::outputs;
+use crate::paths;
+use crate::plan;
+use crate::probe::{self, MediaInfo, StreamInfo};
+use crate::queue;
+use crate::progress::ProgressTracker;
+use crate::quality::QualityLevel;
+use crate::request::AudioCompressRequest;
+use crate::resources::{self, ProcessReport};
+
+// ==========================================
+// AUDIO TARGETS
+// ==========================================
+// Output extension -> how it's encoded and which tags/cover art survive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AudioTarget {
+    Mp3,
+    M4a,
+    Opus,
+    Vorbis,
+    Flac,
+    Wav,
+}
+
+// How the cover ends up in the output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CoverSupport {
+    // Mapped as an attached_pic stream (ID3 APIC, MP4 covr, FLAC PICTURE)
+    Stream,
+    // Ogg has no picture streams: a base64 FLAC picture block in a comment
+    PictureBlock,
+    None,
+}
+
+// Tags the MP4 muxer writes as iTunes atoms; anything else is silently dropped.
+const M4A_TAGS: &[&str] = &[
+    "title", "artist", "album_artist", "album", "date", "genre", "comment", "composer", "track", "disc",
+    "copyright", "description", "lyrics", "grouping", "compilation", "encoder", "sort_name", "sort_artist",
+    "sort_album", "sort_album_artist", "sort_composer",
+];
+// RIFF INFO chunk equivalents
+const WAV_TAGS: &[&str] = &[
+    "title", "artist", "album", "comment", "date", "genre", "copyright", "language", "track", "encoder", "encoded_by",
+];
+// Written by ffmpeg itself, never worth reporting
+const IGNORED_TAGS: &[&str] = &["encoder", "major_brand", "minor_version", "compatible_brands"];
+
+pub const DEFAULT_BITRATE_KBPS: u32 = 192;
+
+impl AudioTarget {
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        Some(match ext {
+            "mp3" => AudioTarget::Mp3,
+            "m4a" | "aac" => AudioTarget::M4a,
+            "opus" => AudioTarget::Opus,
+            "ogg" | "oga" => AudioTarget::Vorbis,
+            "flac" => AudioTarget::Flac,
+            "wav" => AudioTarget::Wav,
+            _ => return None,
+        })
+    }
+
+    pub fn codec(self) -> &'static str {
+        match self {
+            AudioTarget::Mp3 => "libmp3lame",
+            AudioTarget::M4a => "aac",
+            AudioTarget::Opus => "libopus",
+            AudioTarget::Vorbis => "libvorbis",
+            AudioTarget::Flac => "flac",
+            AudioTarget::Wav => "pcm_s16le",
+        }
+    }
+
+    // Rate flags for a quality level: true VBR where the encoder has a good
+    // one (LAME -q:a, Vorbis -q:a, Opus's native VBR), a bitrate otherwise
+    // (ffmpeg's own AAC encoder has no dependable VBR).
+    fn level_args(self, level: QualityLevel) -> Vec<String> {
+        let pick = |low: u32, medium: u32, high: u32| match level {
+            QualityLevel::Low => low,
+            QualityLevel::Medium => medium,
+            QualityLevel::High => high,
+        };
+        match self {
+            AudioTarget::Mp3 => vec!["-q:a".into(), pick(7, 4, 0).to_string()],
+            AudioTarget::Vorbis => vec!["-q:a".into(), pick(3, 5, 8).to_string()],
+            AudioTarget::Opus => vec!["-vbr".into(), "on".into(), "-b:a".into(), format!("{}k", pick(64, 96, 160))],
+            AudioTarget::M4a => vec!["-b:a".into(), format!("{}k", pick(96, 160, 256))],
+            AudioTarget::Flac | AudioTarget::Wav => vec![],
+        }
+    }
+
+    fn lossless(self) -> bool {
+        matches!(self, AudioTarget::Flac | AudioTarget::Wav)
+    }
+
+    fn cover(self) -> CoverSupport {
+        match self {
+            AudioTarget::Mp3 | AudioTarget::M4a | AudioTarget::Flac => CoverSupport::Stream,
+            AudioTarget::Opus | AudioTarget::Vorbis => CoverSupport::PictureBlock,
+            AudioTarget::Wav => CoverSupport::None,
+        }
+    }
+
+    // ID3v2 (TXXX frames) and Vorbis comments take arbitrary keys, which is
+    // also how REPLAYGAIN_* tags travel; MP4 and RIFF only know a fixed set.
+    pub fn keeps_tag(self, key: &str) -> bool {
+        let key = key.to_lowercase();
+        match self {
+            AudioTarget::Mp3 | AudioTarget::Opus | AudioTarget::Vorbis | AudioTarget::Flac => true,
+            AudioTarget::M4a => M4A_TAGS.contains(&key.as_str()),
+            AudioTarget::Wav => WAV_TAGS.contains(&key.as_str()),
+        }
+    }
+}
+
+fn user_tags(tags: &BTreeMap<String, String>) -> impl Iterator<Item = &String> {
+    tags.keys().filter(|k| !IGNORED_TAGS.contains(&k.to_lowercase().as_str()))
+}
+
+// Source tags the target format can't hold.
+pub fn dropped_tags(target: AudioTarget, tags: &BTreeMap<String, String>) -> Vec<String> {
+    user_tags(tags).filter(|k| !target.keeps_tag(k)).cloned().collect()
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AudioJobResult {
+    // Both canonical (see paths::secure)
+    pub input: String,
+    pub output: String,
+    pub codec: String,
+    pub cover_art: bool,
+    pub tags_kept: usize,
+    // Tags that exist in the source but have no place in the output format
+    pub dropped_tags: Vec<String>,
+    // e.g. "24-bit 96 kHz → 32-bit float 48 kHz" when the encoder needed it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_conversion: Option<String>,
+    pub warnings: Vec<String>,
+    // The thread cap and priority ffmpeg ran with (see resources.rs)
+    pub process: ProcessReport,
+}
+
+fn cover_stream(media: &MediaInfo) -> Option<&StreamInfo> {
+    media.streams.iter().find(|s| s.attached_pic)
+}
+
+// ==========================================
+// METADATA_BLOCK_PICTURE
+// ==========================================
+// FLAC picture block (big-endian): type, mime, description, width, height,
+// depth, palette size, data. Vorbis/Opus comments carry it base64-encoded.
+pub fn picture_block(data: &[u8], mime: &str, width: u32, height: u32) -> Vec<u8> {
+    let mut block = vec![];
+    block.extend_from_slice(&3u32.to_be_bytes()); // front cover
+    block.extend_from_slice(&(mime.len() as u32).to_be_bytes());
+    block.extend_from_slice(mime.as_bytes());
+    block.extend_from_slice(&0u32.to_be_bytes()); // empty description
+    for value in [width, height, 24, 0, data.len() as u32] {
+        block.extend_from_slice(&value.to_be_bytes());
+    }
+    block.extend_from_slice(data);
+    block
+}
+
+// ffmetadata values escape `=`, `;`, `#`, `\` and newlines with a backslash.
+pub fn escape_ffmetadata(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+// Pulls the cover out as JPEG/PNG bytes (other codecs get converted to PNG).
+async fn read_cover(app: &AppHandle, input: &str, cover: &StreamInfo) -> Result<(Vec<u8>, &'static str), String> {
+    let copy = matches!(cover.codec_name.as_deref(), Some("mjpeg") | Some("png"));
+    let mime = if cover.codec_name.as_deref() == Some("mjpeg") { "image/jpeg" } else { "image/png" };
+    let map = format!("0:{}", cover.index);
+    let mut args = vec!["-v", "error", "-i", input, "-map", &map, "-frames:v", "1"];
+    args.extend(if copy { ["-c", "copy"] } else { ["-c:v", "png"] });
+    args.extend(["-f", "image2pipe", "-"]);
+    let output = ffmpeg::command(app)?.args(args).tracked_output(app).await.map_err(|e| e.to_string())?;
+    if !output.success() || output.stdout.is_empty() {
+        return Err("Could not read the cover art".to_string());
+    }
+    Ok((output.stdout, mime))
+}
+
+// ffmetadata file with the source tags plus the cover as a picture block.
+async fn ogg_metadata_file(app: &AppHandle, input: &str, media: &MediaInfo, cover: &StreamInfo) -> Result<std::path::PathBuf, String> {
+    let (data, mime) = read_cover(app, input, cover).await?;
+    let (width, height) = image::ImageReader::new(Cursor::new(&data))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .into_dimensions()
+        .map_err(|e| e.to_string())?;
+    let block = base64::engine::general_purpose::STANDARD.encode(picture_block(&data, mime, width, height));
+
+    let mut text = String::from(";FFMETADATA1\n");
+    for (key, value) in &media.tags {
+        text.push_str(&format!("{}={}\n", escape_ffmetadata(key), escape_ffmetadata(value)));
+    }
+    text.push_str(&format!("METADATA_BLOCK_PICTURE={}\n", escape_ffmetadata(&block)));
+
+    let dir = app.path().app_cache_dir().map_err(|e| e.to_string())?.join("audio");
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let path = dir.join(format!("{:x}.ffmeta", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0)));
+    fs::write(&path, text).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+// ==========================================
+// HELPER: CONVERT ONE AUDIO FILE
+// ==========================================
+// ffmpeg writes `staged`; `request.output` is where it ends up.
+async fn encode_audio(app: &AppHandle, request: &AudioCompressRequest, staged: &str) -> Result<AudioJobResult, String> {
+    let (input, output) = (&request.input, &request.output);
+    inputs::preflight(input).map_err(|e| e.to_string())?;
+    paths::ensure_not_input(input, output)?;
+    let target = request.target().ok_or("Unsupported audio output format")?;
+
+    let media = probe::probe(app, input).await?;
+    if !media.has_audio {
+        return Err("The input has no audio stream".to_string());
+    }
+    let mut warnings = vec![];
+    let cover = cover_stream(&media).filter(|_| request.keep_cover);
+
+    let mut args = media.probe_level.input_args();
+    args.extend(["-i".into(), input.clone()]);
+    let mut metadata_file = None;
+    let mut cover_art = false;
+    match (cover, target.cover()) {
+        (Some(_), CoverSupport::None) => warnings.push("Cover art dropped: WAV can't hold it".to_string()),
+        (Some(c), CoverSupport::PictureBlock) => match ogg_metadata_file(app, input, &media, c).await {
+            Ok(path) => {
+                args.extend(["-i".into(), path.to_string_lossy().to_string()]);
+                metadata_file = Some(cancel::TempFile::new(path));
+            }
+            Err(e) => warnings.push(format!("Cover art dropped: {}", e)),
+        },
+        _ => {}
+    }
+
+    args.extend(["-map".into(), "0:a:0".into()]);
+    if let (Some(c), CoverSupport::Stream) = (cover, target.cover()) {
+        let copy = matches!(c.codec_name.as_deref(), Some("mjpeg") | Some("png"));
+        args.extend([
+            "-map".into(), format!("0:{}", c.index),
+            "-c:v".into(), if copy { "copy".into() } else { "mjpeg".into() },
+            "-disposition:v:0".into(), "attached_pic".into(),
+        ]);
+        if target == AudioTarget::Mp3 {
+            args.extend(["-metadata:s:v".into(), "comment=Cover (front)".into()]);
+        }
+        cover_art = true;
+    }
+    cover_art |= metadata_file.is_some();
+
+    // Tags (including REPLAYGAIN_*) come from the ffmetadata file when one was built
+    let metadata_source = if metadata_file.is_some() { "1" } else { "0" };
+    args.extend(["-map_metadata".into(), metadata_source.into()]);
+
+    args.extend(["-c:a".into(), target.codec().into()]);
+    let source_audio = media.streams.iter().find(|s| s.codec_type == "audio");
+    let conversion = source_audio.and_then(|s| audio_format::for_stream(target.codec(), s));
+    if let Some(c) = &conversion {
+        args.extend(c.args());
+    }
+    if !target.lossless() {
+        match (request.bitrate_kbps, request.quality) {
+            (None, Some(level)) => args.extend(target.level_args(level)),
+            (kbps, _) => args.extend(["-b:a".into(), format!("{}k", kbps.unwrap_or(DEFAULT_BITRATE_KBPS))]),
+        }
+    }
+    match target {
+        // v2.3 is what most players and car stereos read
+        AudioTarget::Mp3 => args.extend(["-id3v2_version".into(), "3".into()]),
+        AudioTarget::Flac => args.extend(["-compression_level".into(), request.compression_level.unwrap_or(8).to_string()]),
+        _ => {}
+    }
+    args.extend(["-y".into(), staged.to_string()]);
+
+    let tracker = ProgressTracker::for_duration(media.duration);
+    ffmpeg::run_with_progress(app, args, tracker, Event::CompressionProgress).await?;
+    drop(metadata_file);
+
+    let dropped = dropped_tags(target, &media.tags);
+    if !dropped.is_empty() {
+        warnings.push(format!("Tags not supported by .{}: {}", request.extension(), dropped.join(", ")));
+    }
+    Ok(AudioJobResult {
+        input: request.input.clone(),
+        output: output.clone(),
+        codec: target.codec().to_string(),
+        cover_art,
+        tags_kept: user_tags(&media.tags).count() - dropped.len(),
+        dropped_tags: dropped,
+        sample_conversion: conversion.and_then(|c| c.description),
+        warnings,
+        process: resources::report(),
+    })
+}
+
+// Validation + encode + history record; shared by the command and the queue.
+pub async fn run_audio_job(app: &AppHandle, request: AudioCompressRequest) -> Result<AudioJobResult, String> {
+    resources::scope(request.process, audio_job(app, request)).await
+}
+
+async fn audio_job(app: &AppHandle, mut request: AudioCompressRequest) -> Result<AudioJobResult, String> {
+    request.validate().map_err(|e| e.to_string())?;
+    (request.input, request.output) = paths::secure(app, &request.input, &request.output, request.create_dirs)?;
+    let started = Instant::now();
+    let mut reservation = outputs::claim_for_job(app, &request.output, None, None)?;
+    reservation.check_space(plan::estimated_output_bytes(app, "audio", fs::metadata(&request.input).map(|m| m.len()).unwrap_or(0)), false)?;
+    request.output = reservation.path_str();
+    queue::JobStarted::new(&request.input, &request.output).emit(app);
+    let result = match encode_audio(app, &request, &reservation.staged_str()).await {
+        Ok(r) => reservation.commit().map(|path| AudioJobResult { output: path.to_string_lossy().to_string(), ..r }),
+        Err(e) => Err(reservation.classify(e)),
+    };
+    let output = result.as_ref().map_or(request.output.clone(), |r| r.output.clone());
+    let mut entry = HistoryEntry::finished("audio", &request.input, &output, started, result.as_ref().err().cloned())
+        .with_annotations(&request.annotations);
+    if let Ok(r) = &result {
+        entry.encoder = Some(r.codec.clone());
+        entry.warnings = r.warnings.clone();
+    }
+    history::record(app, entry);
+    result
+}
+
+// ==========================================
+// COMMAND: COMPRESS AUDIO
+// ==========================================
+#[tauri::command]
+pub async fn compress_audio(app: AppHandle, request: AudioCompressRequest) -> Result<AudioJobResult, String> {
+    run_audio_job(&app, request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(keys: &[&str]) -> BTreeMap<String, String> {
+        keys.iter().map(|k| (k.to_string(), "x".to_string())).collect()
+    }
+
+    fn request(value: serde_json::Value) -> AudioCompressRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn the_extension_picks_the_target() {
+        assert_eq!(AudioTarget::from_extension("oga"), Some(AudioTarget::Vorbis));
+        assert_eq!(AudioTarget::from_extension("aac").map(AudioTarget::codec), Some("aac"));
+        assert_eq!(AudioTarget::from_extension("wma"), None);
+        assert!(AudioTarget::Flac.lossless() && !AudioTarget::Opus.lossless());
+        assert!(AudioTarget::Wav.level_args(QualityLevel::High).is_empty());
+        assert_eq!(AudioTarget::Mp3.level_args(QualityLevel::High), ["-q:a", "0"]);
+        assert_eq!(AudioTarget::Opus.level_args(QualityLevel::Low), ["-vbr", "on", "-b:a", "64k"]);
+    }
+
+    #[test]
+    fn fixed_tag_formats_report_what_they_drop() {
+        let source = tags(&["TITLE", "REPLAYGAIN_TRACK_GAIN", "encoder", "major_brand", "lyrics"]);
+        assert!(dropped_tags(AudioTarget::Flac, &source).is_empty());
+        assert_eq!(dropped_tags(AudioTarget::M4a, &source), ["REPLAYGAIN_TRACK_GAIN"]);
+        assert_eq!(dropped_tags(AudioTarget::Wav, &source), ["REPLAYGAIN_TRACK_GAIN", "lyrics"]);
+    }
+
+    #[test]
+    fn covers_go_where_the_format_keeps_them() {
+        assert_eq!(AudioTarget::Mp3.cover(), CoverSupport::Stream);
+        assert_eq!(AudioTarget::Opus.cover(), CoverSupport::PictureBlock);
+        assert_eq!(AudioTarget::Wav.cover(), CoverSupport::None);
+        let media = MediaInfo {
+            streams: vec![
+                StreamInfo { index: 0, codec_type: "audio".to_string(), ..Default::default() },
+                StreamInfo { index: 1, codec_type: "video".to_string(), attached_pic: true, ..Default::default() },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(cover_stream(&media).map(|s| s.index), Some(1));
+    }
+
+    #[test]
+    fn the_picture_block_is_laid_out_big_endian() {
+        let block = picture_block(b"JPEG", "image/jpeg", 600, 400);
+        let word = |at: usize| u32::from_be_bytes(block[at..at + 4].try_into().unwrap());
+        assert_eq!(word(0), 3);
+        assert_eq!(word(4), 10);
+        assert_eq!(&block[8..18], b"image/jpeg");
+        assert_eq!([word(18), word(22), word(26), word(30), word(34), word(38)], [0, 600, 400, 24, 0, 4]);
+        assert_eq!(&block[42..], b"JPEG");
+    }
+
+    #[test]
+    fn ffmetadata_values_are_escaped() {
+        assert_eq!(escape_ffmetadata("a=b; #1 \\ end\nnext"), "a\\=b\\; \\#1 \\\\ end\\\nnext");
+        assert_eq!(escape_ffmetadata("Ünïcødé"), "Ünïcødé");
+    }
+
+    #[test]
+    fn audio_requests_check_the_target_and_its_options() {
+        assert!(request(serde_json::json!({"input": "a.wav", "output": "b.opus", "bitrate_kbps": 128})).validate().is_ok());
+        let fields = |r: AudioCompressRequest| r.validate().unwrap_err().0.into_iter().map(|i| i.field).collect::<Vec<_>>();
+        assert_eq!(fields(request(serde_json::json!({"input": "a.wav", "output": "b.wma"}))), ["output"]);
+        assert_eq!(fields(request(serde_json::json!({"input": "a.wav", "output": "b.mp3", "bitrate_kbps": 8, "compression_level": 5}))), ["bitrate_kbps", "compression_level"]);
+        assert_eq!(fields(request(serde_json::json!({"input": "a.wav", "output": "b.flac", "compression_level": 13}))), ["compression_level"]);
+        assert!(request(serde_json::json!({"input": "a.wav", "output": "b.flac"})).keep_cover);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::probe::StreamInfo;
+
+// ==========================================
+// SAMPLE FORMAT / RATE NEGOTIATION
+// ==========================================
+// Field recorders write 24-bit (s32) or float audio at 88.2/96 kHz, and some
+// encoder builds refuse it ("Specified sample format s32 is invalid or not
+// supported") instead of converting. So the conversion is chosen here, per
+// encoder: the source's format and rate when the encoder takes them,
+// otherwise the best it does take, and passed as explicit -sample_fmt / -ar.
+
+// What an encoder accepts. `rates: None` means any rate.
+struct EncoderSupport {
+    codec: &'static str,
+    formats: &'static [&'static str],
+    rates: Option<&'static [u32]>,
+}
+
+const ENCODERS: &[EncoderSupport] = &[
+    EncoderSupport {
+        codec: "aac",
+        formats: &["fltp"],
+        rates: Some(&[96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350]),
+    },
+    EncoderSupport { codec: "libopus", formats: &["s16", "flt"], rates: Some(&[48000, 24000, 16000, 12000, 8000]) },
+    EncoderSupport {
+        codec: "libmp3lame",
+        formats: &["s32p", "fltp", "s16p"],
+        rates: Some(&[48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000]),
+    },
+    EncoderSupport { codec: "libvorbis", formats: &["fltp"], rates: None },
+    EncoderSupport { codec: "flac", formats: &["s16", "s32"], rates: None },
+    EncoderSupport { codec: "pcm_s16le", formats: &["s16"], rates: None },
+];
+
+// Higher is more precise. Planar and packed variants rank the same.
+fn rank(format: &str) -> u32 {
+    match format.trim_end_matches('p') {
+        "dbl" => 5,
+        "flt" => 4,
+        "s64" => 3,
+        "s32" => 2,
+        "s16" => 1,
+        _ => 0,
+    }
+}
+
+// "24-bit", "32-bit float"...; `bits` is the stream's real depth, if known.
+fn depth_label(format: &str, bits: Option<u32>) -> String {
+    match format.trim_end_matches('p') {
+        "flt" => "32-bit float".to_string(),
+        "dbl" => "64-bit float".to_string(),
+        "u8" => "8-bit".to_string(),
+        "s16" => "16-bit".to_string(),
+        "s32" => format!("{}-bit", bits.filter(|&b| b <= 32).unwrap_or(32)),
+        "s64" => "64-bit".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn rate_label(rate: u32) -> String {
+    if rate.is_multiple_of(1000) {
+        format!("{} kHz", rate / 1000)
+    } else {
+        format!("{:.1} kHz", rate as f64 / 1000.0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conversion {
+    // None: the source's value is fine as it is
+    pub sample_fmt: Option<&'static str>,
+    pub sample_rate: Option<u32>,
+    // "24-bit 96 kHz → 16-bit 48 kHz"; None when only the layout (planar
+    // vs packed) changes, which loses nothing
+    pub description: Option<String>,
+}
+
+impl Conversion {
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(fmt) = self.sample_fmt {
+            args.extend(["-sample_fmt".to_string(), fmt.to_string()]);
+        }
+        if let Some(rate) = self.sample_rate {
+            args.extend(["-ar".to_string(), rate.to_string()]);
+        }
+        args
+    }
+}
+
+// The most precise format the encoder takes; ties (planar/packed) go to the
+// one matching the source's layout.
+fn pick_format(source: &str, formats: &[&'static str]) -> &'static str {
+    let planar = source.ends_with('p');
+    formats.iter().copied().max_by_key(|f| (rank(f), f.ends_with('p') == planar)).unwrap_or(formats[0])
+}
+
+// The nearest rate that keeps everything the source has, else the highest one.
+fn pick_rate(source: u32, rates: &[u32]) -> u32 {
+    let above = rates.iter().copied().filter(|&r| r >= source).min();
+    above.or_else(|| rates.iter().copied().max()).unwrap_or(source)
+}
+
+// None when the encoder isn't in the table, nothing is known about the
+// source, or the source already fits.
+pub fn negotiate(codec: &str, sample_fmt: Option<&str>, bits: Option<u32>, sample_rate: Option<u32>) -> Option<Conversion> {
+    let support = ENCODERS.iter().find(|e| e.codec == codec)?;
+    let source_fmt = sample_fmt.filter(|f| !f.is_empty());
+
+    let target_fmt = source_fmt.filter(|f| !support.formats.contains(f)).map(|f| pick_format(f, support.formats));
+    let target_rate = match (sample_rate, support.rates) {
+        (Some(rate), Some(rates)) if !rates.contains(&rate) => Some(pick_rate(rate, rates)),
+        _ => None,
+    };
+    if target_fmt.is_none() && target_rate.is_none() {
+        return None;
+    }
+
+    let from_depth = source_fmt.map(|f| depth_label(f, bits));
+    // A 24-bit source in s32 stays 24-bit in another s32 layout
+    let to_depth = target_fmt.map(|f| depth_label(f, if rank(f) == 2 { bits } else { None })).or(from_depth.clone());
+    let from_rate = sample_rate.map(rate_label);
+    let to_rate = target_rate.map(rate_label).or(from_rate.clone());
+    let describe = |depth: Option<String>, rate: Option<String>| [depth, rate].into_iter().flatten().collect::<Vec<_>>().join(" ");
+    let (from, to) = (describe(from_depth, from_rate), describe(to_depth, to_rate));
+    Some(Conversion {
+        sample_fmt: target_fmt,
+        sample_rate: target_rate,
+        description: (from != to).then(|| format!("{} → {}", from, to)),
+    })
+}
+
+pub fn for_stream(codec: &str, stream: &StreamInfo) -> Option<Conversion> {
+    negotiate(codec, stream.sample_fmt.as_deref(), stream.bits_per_sample, stream.sample_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_the_encoder_takes_need_nothing() {
+        assert_eq!(negotiate("aac", Some("fltp"), None, Some(48000)), None);
+        assert_eq!(negotiate("libvorbis", Some("fltp"), None, Some(192000)), None);
+        assert_eq!(negotiate("some_encoder", Some("s32"), Some(24), Some(96000)), None);
+        assert_eq!(negotiate("aac", None, None, None), None);
+    }
+
+    #[test]
+    fn field_recordings_are_converted_for_aac() {
+        let conversion = negotiate("aac", Some("s32"), Some(24), Some(96000)).unwrap();
+        assert_eq!(conversion.sample_fmt, Some("fltp"));
+        assert_eq!(conversion.sample_rate, None);
+        assert_eq!(conversion.description.as_deref(), Some("24-bit 96 kHz → 32-bit float 96 kHz"));
+        assert_eq!(conversion.args(), ["-sample_fmt", "fltp"]);
+    }
+
+    #[test]
+    fn opus_gets_the_nearest_rate_that_keeps_everything() {
+        let conversion = negotiate("libopus", Some("s32p"), Some(24), Some(44100)).unwrap();
+        assert_eq!((conversion.sample_fmt, conversion.sample_rate), (Some("flt"), Some(48000)));
+        assert_eq!(conversion.description.as_deref(), Some("24-bit 44.1 kHz → 32-bit float 48 kHz"));
+        assert_eq!(conversion.args(), ["-sample_fmt", "flt", "-ar", "48000"]);
+        // Above every rate it has: the highest one
+        assert_eq!(negotiate("libopus", Some("flt"), None, Some(96000)).unwrap().sample_rate, Some(48000));
+    }
+
+    #[test]
+    fn a_layout_change_alone_isnt_described() {
+        let conversion = negotiate("flac", Some("s32p"), Some(24), Some(96000)).unwrap();
+        assert_eq!(conversion.sample_fmt, Some("s32"));
+        assert_eq!(conversion.description, None);
+    }
+
+    #[test]
+    fn the_most_precise_format_wins_and_ties_keep_the_layout() {
+        assert_eq!(pick_format("dbl", &["s16p", "s32p", "fltp"]), "fltp");
+        assert_eq!(pick_format("s64p", &["s32", "s32p"]), "s32p");
+        assert_eq!(pick_format("s64", &["s32", "s32p"]), "s32");
+        assert_eq!(rate_label(22050), "22.1 kHz");
+        assert_eq!(depth_label("s32", Some(24)), "24-bit");
+        assert_eq!(depth_label("s32", None), "32-bit");
+    }
+}
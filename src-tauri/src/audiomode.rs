@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+
+use crate::probe::StreamInfo;
+use crate::support;
+
+// ==========================================
+// AUDIO: COPY, RE-ENCODE OR DROP
+// ==========================================
+// The audio used to be re-encoded with the container's codec every time:
+// generational loss for a source that already has good AAC / Opus, and a
+// failed job for multichannel PCM the encoder can't take. With `audio`
+// "auto" (default) each kept track is looked at first:
+//   - a lossy codec the output container holds, at or under the ceiling
+//     for its channels (or with no bitrate recorded), is copied;
+//   - anything else is re-encoded with the container's codec at a rate
+//     for its channels (128k stereo, 384k 5.1). The channels stay as they
+//     are: a downmix (-ac) only happens when `transcode` asks for one.
+// Filters on the audio (A/V offset, loudness), a playback_target, resumable
+// parts and salvage always re-encode. "copy" and "transcode" are the same
+// decisions made by hand, and fail rather than give something else;
+// "remove" drops the audio (-an), for silent screen recordings.
+
+// "auto" | "copy" | "remove" | { "transcode": { codec, bitrate_kbps, channels } }
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioMode {
+    #[default]
+    Auto,
+    Copy,
+    Transcode(Transcode),
+    Remove,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Transcode {
+    // A codec ("aac", "opus") or an encoder ("libopus"); None = the container's
+    pub codec: Option<String>,
+    // None = the rate for the channels (see default_kbps)
+    pub bitrate_kbps: Option<u32>,
+    // Downmix to this many channels; None keeps the source's
+    pub channels: Option<u32>,
+}
+
+pub const BITRATE_RANGE: std::ops::RangeInclusive<u32> = 32..=1024;
+pub const MAX_CHANNELS: u32 = 8;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioAction {
+    Copied,
+    Transcoded,
+    Removed,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AudioPlan {
+    pub action: AudioAction,
+    // What goes after -c:a ("copy" when copied, unused when removed)
+    pub encoder: &'static str,
+    // -b:a / -ac, or -an
+    pub args: Vec<String>,
+    // Why, for the explanation: "requested", "lossless", "over_ceiling", ...
+    pub reason: String,
+}
+
+impl AudioPlan {
+    pub fn copy(reason: &str) -> Self {
+        AudioPlan { action: AudioAction::Copied, encoder: "copy", args: vec![], reason: reason.to_string() }
+    }
+}
+
+// What the job knows when it decides.
+pub struct AudioContext<'a> {
+    pub ext: &'a str,
+    // The container's codec (see container_codecs in lib.rs)
+    pub default_encoder: &'static str,
+    // The tracks the output keeps, in output order; empty when the probe failed
+    pub tracks: &'a [&'a StreamInfo],
+    // The option that needs the audio decoded, if any
+    pub must_encode: Option<&'static str>,
+    // What a size target leaves the audio (see quality::AUDIO_BUDGET_KBPS)
+    pub budget_kbps: Option<u32>,
+}
+
+// Pure: the rate a re-encode gets for this many channels.
+pub fn default_kbps(channels: u32) -> u32 {
+    match channels {
+        0 | 2 => 128,
+        1 => 96,
+        n => 64 * n,
+    }
+}
+
+// Sources up to this are copied rather than re-encoded to default_kbps
+fn ceiling_kbps(channels: u32) -> u32 {
+    default_kbps(channels) * 3 / 2
+}
+
+fn max_channels(encoder: &str) -> u32 {
+    match encoder {
+        "libmp3lame" | "wmav2" => 2,
+        _ => MAX_CHANNELS,
+    }
+}
+
+fn codec(name: &str) -> Option<&'static support::Codec> {
+    support::CODECS.iter().find(|c| c.kind == support::MediaKind::Audio && (c.name == name || c.encoders.contains(&name)))
+}
+
+fn accepted(ext: &str, codec: &str) -> bool {
+    support::container(ext).is_none_or(|c| c.audio.contains(&codec))
+}
+
+fn track_name(track: &StreamInfo) -> String {
+    format!("audio stream {} ({})", track.index, track.codec_name.as_deref().unwrap_or("unknown codec"))
+}
+
+// Why a track has to be re-encoded, or None when it can be copied.
+fn copy_blocker(track: &StreamInfo, ext: &str) -> Option<&'static str> {
+    let Some(name) = track.codec_name.as_deref() else { return Some("unknown_codec") };
+    if !accepted(ext, name) {
+        return Some("container");
+    }
+    codec(name).filter(|c| c.lossless).map(|_| "lossless")
+}
+
+// -b:a per output track (lossless encoders take none) and the downmix.
+fn transcode_args(encoder: &str, tracks: &[&StreamInfo], bitrate: Option<u32>, channels: Option<u32>, budget: Option<u32>) -> Vec<String> {
+    let mut args = vec![];
+    if !codec(encoder).is_some_and(|c| c.lossless) {
+        let rate = |ch: Option<u32>| {
+            let kbps = bitrate.unwrap_or_else(|| default_kbps(channels.or(ch).unwrap_or(2)));
+            format!("{}k", budget.filter(|_| bitrate.is_none()).map_or(kbps, |b| kbps.min(b)))
+        };
+        match tracks {
+            [] => args.extend(["-b:a".to_string(), rate(None)]),
+            tracks => {
+                for (i, track) in tracks.iter().enumerate() {
+                    args.extend([format!("-b:a:{}", i), rate(track.channels)]);
+                }
+            }
+        }
+    }
+    if let Some(ch) = channels {
+        args.extend(["-ac".to_string(), ch.to_string()]);
+    }
+    args
+}
+
+fn check_channels(encoder: &str, tracks: &[&StreamInfo], downmix: Option<u32>) -> Result<(), String> {
+    let max = max_channels(encoder);
+    if let Some(ch) = downmix {
+        return match ch > max {
+            true => Err(format!("{} takes at most {} audio channels, so it can't downmix to {}", encoder, max, ch)),
+            false => Ok(()),
+        };
+    }
+    match tracks.iter().find(|t| t.channels.is_some_and(|c| c > max)) {
+        Some(track) => Err(format!(
+            "{} has {} channels and {} takes at most {}. Set audio to {{ \"transcode\": {{ \"channels\": {} }} }} to downmix it",
+            track_name(track),
+            track.channels.unwrap_or(0),
+            encoder,
+            max,
+            max.min(2)
+        )),
+        None => Ok(()),
+    }
+}
+
+pub fn plan(mode: &AudioMode, cx: &AudioContext) -> Result<AudioPlan, String> {
+    match mode {
+        AudioMode::Remove => Ok(AudioPlan { action: AudioAction::Removed, encoder: "none", args: vec!["-an".to_string()], reason: "requested".to_string() }),
+        AudioMode::Copy => {
+            if let Some(option) = cx.must_encode {
+                return Err(format!("The audio can't be copied with {}: it has to be re-encoded for it", option));
+            }
+            if let Some(track) = cx.tracks.iter().find(|t| t.codec_name.as_deref().is_some_and(|name| !accepted(cx.ext, name))) {
+                return Err(format!("{} can't be copied into .{}; use audio \"auto\" or \"transcode\"", track_name(track), cx.ext));
+            }
+            Ok(AudioPlan::copy("requested"))
+        }
+        AudioMode::Transcode(t) => {
+            let encoder = match &t.codec {
+                Some(name) => {
+                    let found = codec(name).ok_or_else(|| format!("{} isn't an audio codec this app encodes", name))?;
+                    if !accepted(cx.ext, found.name) {
+                        return Err(format!(".{} can't hold {} audio", cx.ext, found.name));
+                    }
+                    found.encoders[0]
+                }
+                None => cx.default_encoder,
+            };
+            check_channels(encoder, cx.tracks, t.channels)?;
+            let args = transcode_args(encoder, cx.tracks, t.bitrate_kbps, t.channels, None);
+            Ok(AudioPlan { action: AudioAction::Transcoded, encoder, args, reason: "requested".to_string() })
+        }
+        AudioMode::Auto => {
+            let ceiling = |ch: u32| cx.budget_kbps.map_or(ceiling_kbps(ch), |b| ceiling_kbps(ch).min(b));
+            let over = |t: &StreamInfo| t.bit_rate.is_some_and(|b| b > ceiling(t.channels.unwrap_or(2)) as u64 * 1000);
+            let reason = match cx.must_encode {
+                Some(option) => Some(option),
+                None if cx.tracks.is_empty() => Some("not_probed"),
+                None => cx.tracks.iter().find_map(|t| copy_blocker(t, cx.ext).or(over(t).then_some("over_ceiling"))),
+            };
+            let Some(reason) = reason else { return Ok(AudioPlan::copy("compatible")) };
+            check_channels(cx.default_encoder, cx.tracks, None)?;
+            let args = transcode_args(cx.default_encoder, cx.tracks, None, None, cx.budget_kbps);
+            Ok(AudioPlan { action: AudioAction::Transcoded, encoder: cx.default_encoder, args, reason: reason.to_string() })
+        }
+    }
+}
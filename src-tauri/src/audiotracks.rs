@@ -0,0 +1,123 @@
+use serde::Serialize;
+
+use crate::probe::StreamInfo;
+
+// What became of one audio stream of the input.
+#[derive(Serialize, Clone, Debug)]
+pub struct AudioTrackOutcome {
+    pub index: u32,
+    pub codec: String,
+    pub language: Option<String>,
+    pub channels: Option<u32>,
+    pub kept: bool,
+}
+
+// ==========================================
+// AUDIO TRACKS
+// ==========================================
+// Screen recordings (mic + desktop) and downloads often carry several audio
+// tracks. Without `keep_all_streams` the output gets one, as it always did:
+// the first, when the encode maps streams itself (cover art, subtitles), or
+// ffmpeg's own pick otherwise (most channels, the first of a tie). With it
+// every track is mapped, and copied or re-encoded as `audio` says;
+// subtitles are already mapped per container by subtitles.rs either way.
+
+// Every audio stream; `explicit` says whether the encode has `-map`s of its own.
+pub fn plan(streams: &[StreamInfo], keep_all: bool, explicit: bool) -> Vec<AudioTrackOutcome> {
+    let audio: Vec<&StreamInfo> = streams.iter().filter(|s| s.codec_type == "audio").collect();
+    let picked = if explicit {
+        audio.first().map(|s| s.index)
+    } else {
+        // max_by_key keeps the last of a tie, so the streams go in reversed
+        audio.iter().rev().max_by_key(|s| s.channels.unwrap_or(0)).map(|s| s.index)
+    };
+    audio
+        .iter()
+        .map(|s| AudioTrackOutcome {
+            index: s.index,
+            codec: s.codec_name.clone().unwrap_or_default(),
+            language: s.language.clone(),
+            channels: s.channels,
+            kept: keep_all || Some(s.index) == picked,
+        })
+        .collect()
+}
+
+// The audio `-map` once streams are mapped explicitly.
+pub fn mapping_args(keep_all: bool) -> Vec<String> {
+    let audio = if keep_all { "0:a?" } else { "0:a:0?" };
+    vec!["-map".to_string(), audio.to_string()]
+}
+
+pub fn warning(tracks: &[AudioTrackOutcome]) -> Option<String> {
+    let dropped = tracks.iter().filter(|t| !t.kept).count();
+    (dropped > 0).then(|| format!("The input has {} audio tracks and only one was kept; keep_all_streams keeps them all", tracks.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(index: u32, kind: &str, channels: Option<u32>) -> StreamInfo {
+        StreamInfo { index, codec_type: kind.to_string(), codec_name: Some("aac".to_string()), channels, ..Default::default() }
+    }
+
+    // A screen recording: video, mic (mono), desktop (stereo), a subtitle, then a second stereo track
+    fn recording() -> Vec<StreamInfo> {
+        vec![stream(0, "video", None), stream(1, "audio", Some(1)), stream(2, "audio", Some(2)), stream(3, "subtitle", None), stream(4, "audio", Some(2))]
+    }
+
+    fn kept(tracks: &[AudioTrackOutcome]) -> Vec<u32> {
+        tracks.iter().filter(|t| t.kept).map(|t| t.index).collect()
+    }
+
+    #[test]
+    fn without_keep_all_one_track_is_kept() {
+        // ffmpeg's pick: most channels, the first of a tie
+        let tracks = plan(&recording(), false, false);
+        assert_eq!(tracks.iter().map(|t| t.index).collect::<Vec<_>>(), [1, 2, 4]);
+        assert_eq!(kept(&tracks), [2]);
+        // Mapped explicitly, 0:a:0 is the first audio stream
+        assert_eq!(kept(&plan(&recording(), false, true)), [1]);
+        assert_eq!(mapping_args(false), ["-map", "0:a:0?"]);
+        assert_eq!(warning(&tracks).unwrap(), "The input has 3 audio tracks and only one was kept; keep_all_streams keeps them all");
+    }
+
+    #[test]
+    fn keep_all_keeps_every_track() {
+        for explicit in [false, true] {
+            let tracks = plan(&recording(), true, explicit);
+            assert_eq!(kept(&tracks), [1, 2, 4]);
+            assert_eq!(warning(&tracks), None);
+        }
+        assert_eq!(mapping_args(true), ["-map", "0:a?"]);
+    }
+
+    #[test]
+    fn a_track_without_a_channel_count_loses_to_one_with() {
+        let streams = [stream(0, "video", None), stream(1, "audio", None), stream(2, "audio", Some(6))];
+        assert_eq!(kept(&plan(&streams, false, false)), [2]);
+        // With none known, the first
+        let streams = [stream(0, "video", None), stream(1, "audio", None), stream(2, "audio", None)];
+        assert_eq!(kept(&plan(&streams, false, false)), [1]);
+    }
+
+    #[test]
+    fn no_audio_track_plans_nothing_and_warns_about_nothing() {
+        // The `?` in the map lets ffmpeg go on without the missing track
+        let streams = [stream(0, "video", None), stream(1, "subtitle", None)];
+        for (keep_all, explicit) in [(false, false), (false, true), (true, true)] {
+            let tracks = plan(&streams, keep_all, explicit);
+            assert!(tracks.is_empty());
+            assert_eq!(warning(&tracks), None);
+        }
+        assert!(mapping_args(false)[1].ends_with('?'));
+    }
+
+    #[test]
+    fn a_single_track_is_kept_without_a_warning() {
+        let tracks = plan(&[stream(0, "video", None), stream(1, "audio", Some(2))], false, true);
+        assert_eq!(kept(&tracks), [1]);
+        assert_eq!(warning(&tracks), None);
+    }
+}
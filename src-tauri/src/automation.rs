@@ -0,0 +1,265 @@
+use axum::extract::{Path, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tauri::Manager;
+use tokio::sync::oneshot;
+
+use crate::AppHandle;
+use crate::presets::{self, Preset};
+use crate::queue::{self, JobSpec, Priority, QueueSnapshot, QueuedJob};
+use crate::settings::SettingsStore;
+
+// ==========================================
+// LOCAL AUTOMATION API
+// ==========================================
+// Tiny HTTP front door onto the job queue for scripts. Opt-in, bound to
+// 127.0.0.1 only, and every request needs `Authorization: Bearer <token>`.
+// Request/response bodies are the same serde types the Tauri commands use.
+
+struct RunningServer {
+    addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    // Resolves once the listener is closed and the port is free again
+    task: tauri::async_runtime::JoinHandle<()>,
+}
+
+// Managed state: the listener that is currently up, if any.
+#[derive(Default)]
+pub struct AutomationServer {
+    running: Mutex<Option<RunningServer>>,
+    // A stopped server that may still be draining; `start` waits on it
+    stopping: Mutex<Option<tauri::async_runtime::JoinHandle<()>>>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct AutomationStatus {
+    pub enabled: bool,
+    // e.g. "http://127.0.0.1:47821" while the listener is up
+    pub url: Option<String>,
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EnqueueRequest {
+    specs: Vec<JobSpec>,
+    priority: Option<Priority>,
+}
+
+#[derive(Serialize)]
+struct EnqueueResponse {
+    ids: Vec<u64>,
+}
+
+type ApiError = (StatusCode, String);
+
+fn new_token() -> Result<String, String> {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes).map_err(|e| e.to_string())?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// Compares every byte regardless of where the first mismatch is, so response
+// timing doesn't leak how much of a guessed token was right.
+fn tokens_match(given: &[u8], expected: &[u8]) -> bool {
+    if given.len() != expected.len() {
+        return false;
+    }
+    given.iter().zip(expected).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+async fn require_token(State(token): State<Arc<String>>, request: Request, next: Next) -> Response {
+    let given = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or("");
+    if !tokens_match(given.as_bytes(), token.as_bytes()) {
+        return (StatusCode::UNAUTHORIZED, "Missing or invalid bearer token").into_response();
+    }
+    next.run(request).await
+}
+
+async fn create_jobs(State(app): State<AppHandle>, Json(body): Json<EnqueueRequest>) -> Result<Json<EnqueueResponse>, ApiError> {
+    let ids = queue::enqueue(&app, body.specs, body.priority).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(EnqueueResponse { ids }))
+}
+
+async fn list_jobs(State(app): State<AppHandle>) -> Json<QueueSnapshot> {
+    Json(queue::snapshot(&app))
+}
+
+async fn get_job(State(app): State<AppHandle>, Path(id): Path<u64>) -> Result<Json<QueuedJob>, ApiError> {
+    queue::find_job(&app, id)
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, format!("Job {} not found", id)))
+}
+
+async fn cancel_job(State(app): State<AppHandle>, Path(id): Path<u64>) -> Result<StatusCode, ApiError> {
+    if queue::find_job(&app, id).is_none() {
+        return Err((StatusCode::NOT_FOUND, format!("Job {} not found", id)));
+    }
+    queue::cancel_job(app.clone(), app.state(), id).map_err(|e| (StatusCode::CONFLICT, e))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_presets(State(app): State<AppHandle>) -> Json<Vec<Preset>> {
+    Json(presets::all(&app))
+}
+
+// Binds the listener and serves until `stop` is called. Replaces any server
+// that's already running (e.g. after a port change).
+pub async fn start(app: &AppHandle, port: u16, token: String) -> Result<SocketAddr, String> {
+    // The old listener may still hold the port; wait for it to let go
+    stop(app);
+    let draining = app.state::<AutomationServer>().stopping.lock().unwrap().take();
+    if let Some(task) = draining {
+        let _ = task.await;
+    }
+
+    let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .await
+        .map_err(|e| format!("Could not bind 127.0.0.1:{}: {}", port, e))?;
+    let addr = listener.local_addr().map_err(|e| e.to_string())?;
+
+    let router = Router::new()
+        .route("/jobs", get(list_jobs).post(create_jobs))
+        .route("/jobs/{id}", get(get_job).delete(cancel_job))
+        .route("/presets", get(list_presets))
+        .layer(middleware::from_fn_with_state(Arc::new(token), require_token))
+        .with_state(app.clone());
+
+    let (shutdown, signal) = oneshot::channel::<()>();
+    let task = tauri::async_runtime::spawn(async move {
+        let served = axum::serve(listener, router)
+            .with_graceful_shutdown(async {
+                let _ = signal.await;
+            })
+            .await;
+        if let Err(e) = served {
+            println!("⚠️ Automation API stopped: {}", e);
+        }
+    });
+
+    println!("🔌 Automation API listening on http://{}", addr);
+    *app.state::<AutomationServer>().running.lock().unwrap() = Some(RunningServer { addr, shutdown, task });
+    Ok(addr)
+}
+
+// Graceful: in-flight requests finish, then the listener closes.
+pub fn stop(app: &AppHandle) {
+    let Some(server) = app.try_state::<AutomationServer>() else { return };
+    let running = server.running.lock().unwrap().take();
+    if let Some(running) = running {
+        let _ = running.shutdown.send(());
+        *server.stopping.lock().unwrap() = Some(running.task);
+        println!("🔌 Automation API on {} shut down", running.addr);
+    }
+}
+
+// Called from setup: brings the API back up if the user left it enabled.
+pub fn start_if_enabled(app: &AppHandle) {
+    let settings = app.state::<SettingsStore>().get();
+    let (true, Some(token)) = (settings.automation_api, settings.automation_token) else { return };
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = start(&app, settings.automation_port, token).await {
+            println!("⚠️ {}", e);
+        }
+    });
+}
+
+fn status(app: &AppHandle) -> AutomationStatus {
+    let settings = app.state::<SettingsStore>().get();
+    let url = app
+        .state::<AutomationServer>()
+        .running
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|r| format!("http://{}", r.addr));
+    AutomationStatus { enabled: settings.automation_api, url, token: settings.automation_token }
+}
+
+// ==========================================
+// COMMANDS
+// ==========================================
+#[tauri::command]
+pub fn get_automation_api(app: AppHandle) -> AutomationStatus {
+    status(&app)
+}
+
+// Turning it on generates a token the first time; turning it off shuts the
+// listener down right away.
+#[tauri::command]
+pub async fn set_automation_api(app: AppHandle, enabled: bool, port: Option<u16>) -> Result<AutomationStatus, String> {
+    let store = app.state::<SettingsStore>();
+    let token = match store.get().automation_token {
+        Some(t) => t,
+        None => new_token()?,
+    };
+    let settings = store.update(|s| {
+        s.automation_api = enabled;
+        if let Some(p) = port {
+            s.automation_port = p;
+        }
+        s.automation_token = Some(token.clone());
+    })?;
+
+    if enabled {
+        start(&app, settings.automation_port, token).await?;
+    } else {
+        stop(&app);
+    }
+    Ok(status(&app))
+}
+
+// Invalidates the old token immediately; scripts need the new one.
+#[tauri::command]
+pub async fn regenerate_automation_token(app: AppHandle) -> Result<AutomationStatus, String> {
+    let token = new_token()?;
+    let settings = app.state::<SettingsStore>().update(|s| s.automation_token = Some(token.clone()))?;
+    if settings.automation_api {
+        start(&app, settings.automation_port, token).await?;
+    }
+    Ok(status(&app))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::Harness;
+
+    fn restart(app: &AppHandle, port: u16) -> Result<SocketAddr, String> {
+        tauri::async_runtime::block_on(start(app, port, "token".to_string()))
+    }
+
+    #[test]
+    fn restarting_on_the_same_port_waits_for_the_old_listener() {
+        let h = Harness::new("automation_restart", "{}");
+        let first = restart(h.handle(), 0).unwrap();
+        for _ in 0..5 {
+            let again = restart(h.handle(), first.port()).expect("the old listener released the port");
+            assert_eq!(again, first);
+        }
+        stop(h.handle());
+        assert!(status(h.handle()).url.is_none());
+    }
+
+    #[test]
+    fn stopping_then_starting_rebinds_the_port() {
+        let h = Harness::new("automation_stop_start", "{}");
+        let first = restart(h.handle(), 0).unwrap();
+        stop(h.handle());
+        let again = restart(h.handle(), first.port()).expect("the port is free after stop");
+        assert_eq!(again.port(), first.port());
+        assert_eq!(status(h.handle()).url, Some(format!("http://{}", again)));
+        stop(h.handle());
+    }
+}
@@ -0,0 +1,185 @@
+use serde::Serialize;
+
+use crate::AppHandle;
+use crate::ffmpeg::{self, TrackedOutput};
+use crate::progress;
+
+// How much of the input the detector looks at
+const ANALYSIS_SECS: u32 = 60;
+const SAMPLE_RATE: usize = 8000;
+// Onset envelope resolution
+const HOP_MS: i64 = 10;
+// Offsets the detector considers
+const SEARCH_MS: i64 = 1000;
+const MIN_SCENE_CHANGES: usize = 4;
+// Estimates below this are reported but never applied automatically
+pub const AUTO_APPLY_CONFIDENCE: f64 = 0.35;
+
+// What the caller asked for. `offset_ms` shifts the audio: positive delays it,
+// negative pulls it earlier (a capture whose audio is 300 ms late needs -300).
+#[derive(Clone, Debug, Default)]
+pub struct AvSyncOptions {
+    pub offset_ms: Option<i64>,
+    pub detect: bool,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct AvSyncReport {
+    // Offset that went into the encode (None = untouched)
+    pub applied_ms: Option<i64>,
+    // What detection found, when it ran and found anything
+    pub estimated_ms: Option<i64>,
+    // 0..1; only estimates >= AUTO_APPLY_CONFIDENCE are applied
+    pub confidence: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OffsetEstimate {
+    pub offset_ms: i64,
+    pub confidence: f64,
+}
+
+// ==========================================
+// ANALYSIS (pure)
+// ==========================================
+// Positive energy jumps per HOP_MS frame of mono s16le PCM. Log energy so a
+// quiet clap counts about as much as a loud one.
+pub fn onset_envelope(pcm: &[u8]) -> Vec<f64> {
+    let hop = SAMPLE_RATE * HOP_MS as usize / 1000;
+    let samples: Vec<f64> = pcm
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]) as f64 / i16::MAX as f64)
+        .collect();
+    let energy: Vec<f64> = samples
+        .chunks(hop)
+        .map(|frame| (frame.iter().map(|s| s * s).sum::<f64>() / frame.len() as f64 + 1e-9).ln())
+        .collect();
+    let mut onsets = vec![0.0; energy.len()];
+    for i in 1..energy.len() {
+        onsets[i] = (energy[i] - energy[i - 1]).max(0.0);
+    }
+    onsets
+}
+
+// Scene changes tend to come with a sound (cut to a new shot, a clap, a door).
+// Slide the onset envelope against the cut times and see which lag lines
+// them up best. Confidence is how far the best lag stands out from the best
+// lag that isn't right next to it.
+pub fn estimate_offset(onsets: &[f64], scene_times: &[f64]) -> Option<OffsetEstimate> {
+    if scene_times.len() < MIN_SCENE_CHANGES || onsets.is_empty() {
+        return None;
+    }
+    let strength_near = |ms: i64| -> f64 {
+        let center = ms / HOP_MS;
+        (center - 2..=center + 2)
+            .filter(|&i| i >= 0 && (i as usize) < onsets.len())
+            .map(|i| onsets[i as usize])
+            .fold(0.0, f64::max)
+    };
+
+    let scores: Vec<(i64, f64)> = (-SEARCH_MS / HOP_MS..=SEARCH_MS / HOP_MS)
+        .map(|step| {
+            let lag = step * HOP_MS;
+            let total: f64 = scene_times.iter().map(|t| strength_near((t * 1000.0) as i64 + lag)).sum();
+            (lag, total / scene_times.len() as f64)
+        })
+        .collect();
+
+    let &(best_lag, best) = scores.iter().max_by(|a, b| a.1.total_cmp(&b.1))?;
+    if best <= 0.0 {
+        return None;
+    }
+    let runner_up = scores
+        .iter()
+        .filter(|(lag, _)| (lag - best_lag).abs() > 5 * HOP_MS)
+        .map(|(_, s)| *s)
+        .fold(0.0, f64::max);
+
+    // Audio onsets at cut + lag means the audio is `lag` late: shift it back
+    Some(OffsetEstimate { offset_ms: -best_lag, confidence: ((best - runner_up) / best).clamp(0.0, 1.0) })
+}
+
+// `lavfi.scd.time=12.345` lines printed by `metadata=print`
+pub fn parse_scene_times(stderr: &str) -> Vec<f64> {
+    stderr
+        .lines()
+        .filter_map(|l| progress::field(l, "lavfi.scd.time"))
+        .filter_map(progress::parse_number)
+        .collect()
+}
+
+// -af value that shifts the audio by `offset_ms`.
+pub fn audio_filter(offset_ms: i64) -> Option<String> {
+    match offset_ms {
+        0 => None,
+        ms if ms > 0 => Some(format!("adelay={}:all=1", ms)),
+        ms => Some(format!("atrim=start={:.3},asetpts=PTS-STARTPTS", (-ms) as f64 / 1000.0)),
+    }
+}
+
+// ==========================================
+// DETECTION (runs ffmpeg twice over the first minute)
+// ==========================================
+pub async fn detect(app: &AppHandle, input: &str) -> Result<Option<OffsetEstimate>, String> {
+    let limit = ANALYSIS_SECS.to_string();
+    let pcm = ffmpeg::command(app)?
+        .args(["-v", "error", "-t", &limit, "-i", input, "-vn", "-ac", "1", "-ar", &SAMPLE_RATE.to_string(), "-f", "s16le", "-"])
+        .tracked_output(app)
+        .await
+        .map_err(|e| e.to_string())?;
+    if !pcm.success() {
+        return Err("Could not decode audio for sync detection".to_string());
+    }
+
+    let scenes = ffmpeg::command(app)?
+        .args(["-hide_banner", "-t", &limit, "-i", input, "-an", "-vf", "scdet=threshold=10,metadata=print:key=lavfi.scd.time", "-f", "null", "-"])
+        .tracked_output(app)
+        .await
+        .map_err(|e| e.to_string())?;
+    let scene_times = parse_scene_times(&String::from_utf8_lossy(&scenes.stderr));
+
+    Ok(estimate_offset(&onset_envelope(&pcm.stdout), &scene_times))
+}
+
+// Explicit offsets win; otherwise detection (if asked for) decides.
+pub async fn resolve(app: &AppHandle, input: &str, options: &AvSyncOptions) -> AvSyncReport {
+    if let Some(ms) = options.offset_ms {
+        return AvSyncReport { applied_ms: Some(ms).filter(|ms| *ms != 0), ..Default::default() };
+    }
+    if !options.detect {
+        return AvSyncReport::default();
+    }
+    match detect(app, input).await {
+        Ok(Some(estimate)) => {
+            println!("🔊 A/V offset estimate: {} ms (confidence {:.2})", estimate.offset_ms, estimate.confidence);
+            AvSyncReport {
+                applied_ms: (estimate.confidence >= AUTO_APPLY_CONFIDENCE && estimate.offset_ms != 0)
+                    .then_some(estimate.offset_ms),
+                estimated_ms: Some(estimate.offset_ms),
+                confidence: Some(estimate.confidence),
+            }
+        }
+        Ok(None) => AvSyncReport::default(),
+        Err(e) => {
+            println!("⚠️ A/V sync detection failed: {}", e);
+            AvSyncReport::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scene_times_read_with_either_separator() {
+        let stderr = "\
+[Parsed_metadata_1 @ 0x55] frame:41   pts:41041   pts_time:1.36803
+[Parsed_metadata_1 @ 0x55] lavfi.scd.score=42.1
+[Parsed_metadata_1 @ 0x55] lavfi.scd.time=1.368033
+[Parsed_metadata_1 @ 0x55] lavfi.scd.time=12,345
+[Parsed_metadata_1 @ 0x55] lavfi.scd.time=n/a
+";
+        assert_eq!(parse_scene_times(stderr), vec![1.368033, 12.345]);
+    }
+}
@@ -0,0 +1,257 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use tauri::State;
+use tokio_util::sync::CancellationToken;
+
+use crate::AppHandle;
+use crate::cancel;
+use crate::dryrun::DryRunPlan;
+use crate::events::{self, Event};
+use crate::presets;
+use crate::queue::{self, JobSpec};
+use crate::sizefit::{self, FitReport, FitSummary};
+
+// More than this in parallel only makes encoders fight over the machine
+const MAX_CONCURRENCY: usize = 4;
+
+// ==========================================
+// BATCH COMPRESSION
+// ==========================================
+// A set of jobs run straight away by a small worker pool, outside the
+// persistent queue (which has one global limit): the command resolves with
+// every job's outcome. Each job gets a job id up front, so cancel_job stops
+// a running one or drops a waiting one before it starts; cancel_batch does
+// that for all of them. A failed job doesn't stop the others.
+//
+// Parallel jobs only make sense on the GPU: a batch with any CPU video
+// encode (no auto_gpu) runs one job at a time whatever it asks for.
+
+#[derive(Default)]
+pub struct Batches {
+    next_id: Mutex<u64>,
+    // Batch id -> tokens of its jobs
+    running: Mutex<HashMap<u64, Vec<CancellationToken>>>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchJobStatus {
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct BatchJobResult {
+    pub index: usize,
+    pub job_id: u64,
+    pub input: String,
+    pub output: String,
+    pub status: BatchJobStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    // Size-ladder rung of a `fit_size_mb` video job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fit: Option<FitReport>,
+    // What the job would have run, for a dry-run batch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<DryRunPlan>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BatchSummary {
+    pub batch_id: u64,
+    // What actually ran in parallel (see above)
+    pub concurrency: usize,
+    pub jobs: Vec<BatchJobResult>,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    // Input minus output over the successful jobs; negative when they grew
+    pub bytes_saved: i64,
+    // Where the size-capped jobs landed on their ladders, when there were any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fit: Option<FitSummary>,
+}
+
+// `batch-started`: which job id each index got, before anything runs.
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct BatchStarted {
+    pub batch_id: u64,
+    pub job_ids: Vec<u64>,
+}
+
+// `job-finished` / `job-failed`
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct BatchJobEvent {
+    pub batch_id: u64,
+    #[serde(flatten)]
+    pub result: BatchJobResult,
+}
+
+// Index, spec, job id and token of a job that hasn't started
+type Waiting = (usize, JobSpec, u64, CancellationToken);
+
+fn concurrency(specs: &[JobSpec], requested: Option<usize>) -> usize {
+    let gpu_only = specs.iter().all(|s| match s {
+        JobSpec::Video(request) => request.options.wants_gpu(),
+        _ => true,
+    });
+    let requested = requested.unwrap_or(1).clamp(1, MAX_CONCURRENCY);
+    if gpu_only { requested } else { 1 }
+}
+
+fn file_size(path: &str) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+// A video job's result keeps its size-ladder report, and video and image
+// jobs their dry-run plan; the rest run as the queue runs them.
+async fn run_job(app: &AppHandle, spec: JobSpec) -> Result<(Option<FitReport>, Option<DryRunPlan>), String> {
+    match spec {
+        JobSpec::Video(request) => crate::run_video_job(app, *request).await.map(|r| (r.fit, r.dry_run)),
+        JobSpec::Image(request) => crate::run_image_job(app, request).await.map(|r| (None, r.dry_run)),
+        other => queue::run_spec(app, other).await.map(|_| (None, None)),
+    }
+}
+
+async fn run_one(app: &AppHandle, batch_id: u64, index: usize, spec: JobSpec, job_id: u64, token: CancellationToken) -> BatchJobResult {
+    let (input, output) = (spec.input().to_string(), spec.output().to_string());
+    let input_bytes = file_size(&input);
+    let outcome = queue::run_reserved(app, job_id, token, run_job(app, spec)).await;
+    let (status, error, (fit, dry_run)) = match outcome {
+        Ok(done) => (BatchJobStatus::Done, None, done),
+        Err(e) if e == cancel::CANCELLED => (BatchJobStatus::Cancelled, Some(e), (None, None)),
+        Err(e) => (BatchJobStatus::Failed, Some(e), (None, None)),
+    };
+    // A dry run wrote nothing, whatever is at the path already
+    let output_bytes = if status == BatchJobStatus::Done && dry_run.is_none() { file_size(&output) } else { 0 };
+    let result = BatchJobResult { index, job_id, input, output, status, error, input_bytes, output_bytes, fit, dry_run };
+    let event = BatchJobEvent { batch_id, result: result.clone() };
+    events::emit(app, if status == BatchJobStatus::Done { Event::JobFinished(event) } else { Event::JobFailed(event) });
+    result
+}
+
+fn summarize(batch_id: u64, concurrency: usize, mut jobs: Vec<BatchJobResult>) -> BatchSummary {
+    jobs.sort_by_key(|j| j.index);
+    let count = |status| jobs.iter().filter(|j| j.status == status).count();
+    let bytes_saved = jobs
+        .iter()
+        .filter(|j| j.status == BatchJobStatus::Done && j.dry_run.is_none())
+        .map(|j| j.input_bytes as i64 - j.output_bytes as i64)
+        .sum();
+    BatchSummary {
+        batch_id,
+        concurrency,
+        succeeded: count(BatchJobStatus::Done),
+        failed: count(BatchJobStatus::Failed),
+        cancelled: count(BatchJobStatus::Cancelled),
+        bytes_saved,
+        fit: sizefit::summarize(
+            jobs.iter().filter_map(|j| j.fit.as_ref()),
+            jobs.iter().filter(|j| j.error.as_ref().is_some_and(|e| e.starts_with(sizefit::SIZE_CAP_MISSED))).count(),
+        ),
+        jobs,
+    }
+}
+
+// ==========================================
+// COMMAND: COMPRESS BATCH
+// ==========================================
+// Nothing runs unless every spec is valid. Per-job progress is on
+// `job-progress` (by job id); `job-started` carries the job id as well.
+// With `preset`, every video job takes that preset's options in place of
+// its own (paths, overwrite policy and annotations stay). With `dry_run`,
+// every job stops before ffmpeg starts and its result carries the command
+// (see dryrun.rs); that covers video and image jobs only.
+#[tauri::command]
+pub async fn compress_batch(
+    app: AppHandle,
+    batches: State<'_, Batches>,
+    mut jobs: Vec<JobSpec>,
+    max_concurrent: Option<usize>,
+    preset: Option<String>,
+    dry_run: Option<bool>,
+) -> Result<BatchSummary, String> {
+    if let Some(name) = &preset {
+        let options = presets::options(&app, name)?;
+        for spec in jobs.iter_mut() {
+            if let JobSpec::Video(request) = spec {
+                request.options = options.clone();
+            }
+        }
+    }
+    if dry_run == Some(true) {
+        for (i, spec) in jobs.iter_mut().enumerate() {
+            match spec {
+                JobSpec::Video(request) => request.dry_run = true,
+                JobSpec::Image(request) => request.dry_run = true,
+                _ => return Err(format!("Job {}: dry runs cover video and image jobs only", i + 1)),
+            }
+        }
+    }
+    for (i, spec) in jobs.iter().enumerate() {
+        spec.validate().map_err(|e| format!("Job {}: {}", i + 1, e))?;
+    }
+    Ok(run(&app, &batches, jobs, max_concurrent).await)
+}
+
+// The batch itself, for specs already checked (compress_directory's too).
+pub async fn run(app: &AppHandle, batches: &Batches, jobs: Vec<JobSpec>, max_concurrent: Option<usize>) -> BatchSummary {
+    let batch_id = {
+        let mut next = batches.next_id.lock().unwrap();
+        *next += 1;
+        *next
+    };
+    let workers = concurrency(&jobs, max_concurrent);
+    let reserved: Vec<(u64, CancellationToken)> = jobs.iter().map(|_| queue::reserve_direct(app)).collect();
+    batches.running.lock().unwrap().insert(batch_id, reserved.iter().map(|(_, t)| t.clone()).collect());
+    events::emit(app, Event::BatchStarted(BatchStarted { batch_id, job_ids: reserved.iter().map(|(id, _)| *id).collect() }));
+    println!("📦 Batch {}: {} jobs, {} at a time", batch_id, jobs.len(), workers);
+
+    // Reversed, so popping goes in submission order
+    let pending: Arc<Mutex<Vec<Waiting>>> = Arc::new(Mutex::new(
+        jobs.into_iter().zip(reserved).enumerate().map(|(i, (spec, (id, token)))| (i, spec, id, token)).rev().collect(),
+    ));
+    let results = Arc::new(Mutex::new(vec![]));
+    let mut handles = vec![];
+    for _ in 0..workers {
+        let (app, pending, results) = (app.clone(), pending.clone(), results.clone());
+        handles.push(tauri::async_runtime::spawn(async move {
+            loop {
+                let Some((index, spec, job_id, token)) = pending.lock().unwrap().pop() else { return };
+                let result = run_one(&app, batch_id, index, spec, job_id, token).await;
+                results.lock().unwrap().push(result);
+            }
+        }));
+    }
+    for handle in handles {
+        let _ = handle.await;
+    }
+    batches.running.lock().unwrap().remove(&batch_id);
+
+    let jobs = std::mem::take(&mut *results.lock().unwrap());
+    let summary = summarize(batch_id, workers, jobs);
+    println!("📦 Batch {} done: {} ok, {} failed, {} cancelled", batch_id, summary.succeeded, summary.failed, summary.cancelled);
+    summary
+}
+
+// ==========================================
+// COMMAND: CANCEL BATCH
+// ==========================================
+// Running jobs stop, waiting ones never start; compress_batch still resolves
+// with the summary.
+#[tauri::command]
+pub fn cancel_batch(batches: State<'_, Batches>, batch_id: u64) -> Result<(), String> {
+    let running = batches.running.lock().unwrap();
+    let tokens = running.get(&batch_id).ok_or_else(|| format!("Batch {} isn't running", batch_id))?;
+    for token in tokens {
+        token.cancel();
+    }
+    Ok(())
+}
@@ -0,0 +1,236 @@
+use serde::Deserialize;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+// ==========================================
+// STUB FFMPEG / FFPROBE
+// ==========================================
+// Stands in for both tools when a debug build runs with
+// COMPRESSIO_STUB_FFMPEG set to this binary (see ffmpeg.rs), and in the job
+// tests (src/jobtests.rs). The app tells it which tool it is through
+// STUB_TOOL; what it does comes from the JSON scenario at
+// STUB_FFMPEG_SCENARIO:
+//
+// {
+//   "ffprobe": { ...what `ffprobe -print_format json` would print... },
+//   "probes": [
+//     { "stdout": { ...as "ffprobe"... }, "stderr": "...", "exit_code": 1 }
+//   ],
+//   "ffprobe_output": { ...as "ffprobe", for files a run wrote... },
+//   "runs": [
+//     {
+//       "stderr": [{ "line": "frame=1 ... time=00:00:01.00 ...", "after_ms": 200 }],
+//       "stdout": ["frame=30", "out_time_us=1000000"],
+//       "exit_code": 0,
+//       "output_bytes": 4096,
+//       "partial_bytes": 1024,
+//       "echo_args": false,
+//       "abort": false,
+//       "hang": false
+//     }
+//   ]
+// }
+//
+// Every ffmpeg run takes the next entry of `runs` (the last one repeats),
+// so a two-pass encode is two entries. ffprobe prints `ffprobe` every time,
+// unless there are `probes`, which it takes in turn the same way. A file an
+// earlier run wrote gets `ffprobe_output` instead when there is one, so an
+// encode's output can read back unlike its input. `abort` dies from a signal instead
+// of exiting, `hang` never exits after its lines (a stall); `partial_bytes`
+// is what either wrote to its outputs before that. Each run's
+// arguments are appended to <scenario>.log, one JSON array per line.
+// `output_bytes` goes to every output of the run: the last argument, and
+// any earlier one that follows an option's value (every ffmpeg option
+// takes one value at most, so two plain arguments in a row end an output).
+// With `echo_args` those bytes are the run's other arguments over and over
+// instead of zeros, so two runs write the same output only when they were
+// given the same command line apart from where it goes.
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Scenario {
+    ffprobe: serde_json::Value,
+    probes: Vec<Probe>,
+    ffprobe_output: serde_json::Value,
+    runs: Vec<Run>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Probe {
+    stdout: serde_json::Value,
+    stderr: String,
+    exit_code: i32,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Run {
+    stderr: Vec<Line>,
+    // Printed before the stderr lines (what -progress pipe:1 writes)
+    stdout: Vec<String>,
+    exit_code: i32,
+    // Written to the output path (the last argument) before exiting
+    output_bytes: Option<u64>,
+    // Written to the outputs after the stderr lines, before a hang or abort
+    partial_bytes: Option<u64>,
+    // Fill the outputs with the arguments instead of zeros
+    echo_args: bool,
+    abort: bool,
+    hang: bool,
+}
+
+#[derive(Deserialize)]
+struct Line {
+    line: String,
+    #[serde(default)]
+    after_ms: u64,
+}
+
+fn sidecar_path(scenario: &Path, extension: &str) -> PathBuf {
+    let mut name = scenario.as_os_str().to_owned();
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+// How many runs of the tool came before this one, counted in a file next
+// to the scenario (".count" for ffmpeg, ".probes" for ffprobe).
+fn next_run(scenario: &Path, counter: &str) -> usize {
+    let counter = sidecar_path(scenario, counter);
+    let done: usize = fs::read_to_string(&counter).ok().and_then(|t| t.trim().parse().ok()).unwrap_or(0);
+    let _ = fs::write(&counter, (done + 1).to_string());
+    done
+}
+
+fn log_args(scenario: &Path, args: &[String]) {
+    let line = serde_json::to_string(args).unwrap_or_default();
+    if let Ok(mut log) = fs::OpenOptions::new().create(true).append(true).open(sidecar_path(scenario, ".log")) {
+        let _ = writeln!(log, "{}", line);
+    }
+}
+
+fn is_option(arg: &str) -> bool {
+    arg.starts_with('-') && arg.len() > 1 && arg.parse::<f64>().is_err()
+}
+
+// Null sinks and pipes aren't files to write.
+fn is_sink(arg: &str) -> bool {
+    is_option(arg) || arg == "NUL" || arg == "/dev/null" || arg.starts_with("pipe:")
+}
+
+fn output_paths(args: &[String]) -> Vec<&str> {
+    let mut paths: Vec<&str> = args
+        .windows(2)
+        .filter(|pair| !is_option(&pair[0]) && !is_option(&pair[1]))
+        .map(|pair| pair[1].as_str())
+        .collect();
+    if let Some(last) = args.last() {
+        if !paths.contains(&last.as_str()) {
+            paths.push(last);
+        }
+    }
+    paths.retain(|p| !is_sink(p));
+    paths
+}
+
+fn run_ffmpeg(scenario: &Path, plan: Scenario, args: &[String]) -> i32 {
+    let index = next_run(scenario, ".count");
+    let Some(run) = plan.runs.get(index).or(plan.runs.last()) else {
+        eprintln!("stub-ffmpeg: the scenario has no runs");
+        return 1;
+    };
+    for line in &run.stdout {
+        println!("{}", line);
+    }
+    for line in &run.stderr {
+        thread::sleep(Duration::from_millis(line.after_ms));
+        eprintln!("{}", line.line);
+    }
+    if let Some(bytes) = run.partial_bytes {
+        if !write_outputs(scenario, args, bytes, run.echo_args) {
+            return 1;
+        }
+    }
+    if run.hang {
+        loop {
+            thread::sleep(Duration::from_secs(60));
+        }
+    }
+    if run.abort {
+        std::process::abort();
+    }
+    if let Some(bytes) = run.output_bytes {
+        if !write_outputs(scenario, args, bytes, run.echo_args) {
+            return 1;
+        }
+    }
+    run.exit_code
+}
+
+// False if one couldn't be written
+fn write_outputs(scenario: &Path, args: &[String], bytes: u64, echo_args: bool) -> bool {
+    let outputs = output_paths(args);
+    let content = match echo_args {
+        true => {
+            let rest: Vec<&str> = args.iter().map(String::as_str).filter(|a| !outputs.contains(a)).collect();
+            rest.join("\0").into_bytes().into_iter().cycle().take(bytes as usize).collect()
+        }
+        false => vec![0u8; bytes as usize],
+    };
+    for path in outputs {
+        if let Err(e) = fs::write(path, &content) {
+            eprintln!("stub-ffmpeg: couldn't write {}: {}", path, e);
+            return false;
+        }
+        if let Ok(mut written) = fs::OpenOptions::new().create(true).append(true).open(sidecar_path(scenario, ".outputs")) {
+            let _ = writeln!(written, "{}", path);
+        }
+    }
+    true
+}
+
+fn run_ffprobe(scenario: &Path, plan: Scenario, args: &[String]) -> i32 {
+    let written = fs::read_to_string(sidecar_path(scenario, ".outputs")).unwrap_or_default();
+    if !plan.ffprobe_output.is_null() && args.last().is_some_and(|file| written.lines().any(|w| w == file)) {
+        println!("{}", plan.ffprobe_output);
+        return 0;
+    }
+    if plan.probes.is_empty() {
+        println!("{}", plan.ffprobe);
+        return 0;
+    }
+    let index = next_run(scenario, ".probes");
+    let probe = plan.probes.get(index).or(plan.probes.last()).unwrap();
+    if !probe.stdout.is_null() {
+        println!("{}", probe.stdout);
+    }
+    if !probe.stderr.is_empty() {
+        eprintln!("{}", probe.stderr);
+    }
+    probe.exit_code
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(scenario) = std::env::var_os("STUB_FFMPEG_SCENARIO").map(PathBuf::from) else {
+        eprintln!("stub-ffmpeg: STUB_FFMPEG_SCENARIO isn't set");
+        std::process::exit(2);
+    };
+    let plan: Scenario = match fs::read_to_string(&scenario).map_err(|e| e.to_string()).and_then(|t| serde_json::from_str(&t).map_err(|e| e.to_string())) {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("stub-ffmpeg: bad scenario {}: {}", scenario.display(), e);
+            std::process::exit(2);
+        }
+    };
+    log_args(&scenario, &args);
+
+    let code = match std::env::var("STUB_TOOL").as_deref() {
+        Ok("ffprobe") => run_ffprobe(&scenario, plan, &args),
+        _ => run_ffmpeg(&scenario, plan, &args),
+    };
+    std::process::exit(code);
+}
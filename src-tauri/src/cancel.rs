@@ -0,0 +1,89 @@
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+
+// Error a cancelled job (or phase) resolves with, so the UI can tell it
+// apart from a crash
+pub const CANCELLED: &str = "Cancelled";
+
+// ==========================================
+// CANCELLATION
+// ==========================================
+// Whoever starts a job (the queue, the quality ladder, the self-test) runs
+// it inside `scope` with its own token. Every ffmpeg spawn checks the token
+// first, and every event loop over a running ffmpeg selects on it (see
+// ffmpeg::next_event, which Sidecar and the quiet runs of tracked_output
+// both read through), so cancelling lands within one event whichever phase is
+// active, and a multi-step pipeline never starts its next process after it.
+tokio::task_local! {
+    static TOKEN: CancellationToken;
+}
+
+pub async fn scope<F: Future>(token: CancellationToken, f: F) -> F::Output {
+    TOKEN.scope(token, f).await
+}
+
+// Token of the job running on this task; None outside any scope.
+pub fn current() -> Option<CancellationToken> {
+    TOKEN.try_with(|t| t.clone()).ok()
+}
+
+pub fn is_cancelled() -> bool {
+    TOKEN.try_with(|t| t.is_cancelled()).unwrap_or(false)
+}
+
+// What a job run under `token` ends with. A step that fails once the token
+// has fired (a probe whose ffmpeg was killed, say) is reported as cancelled,
+// however the step worded it.
+pub fn settle<T>(token: &CancellationToken, result: Result<T, String>) -> Result<T, String> {
+    match result {
+        Err(_) if token.is_cancelled() => Err(CANCELLED.to_string()),
+        result => result,
+    }
+}
+
+// For `?` before starting anything expensive.
+pub fn check() -> Result<(), String> {
+    if is_cancelled() { Err(CANCELLED.to_string()) } else { Ok(()) }
+}
+
+// A phase's temporary output. Removed when dropped, which covers success
+// (after it's been renamed or consumed), errors and cancellation alike.
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    pub fn new(path: PathBuf) -> Self {
+        TempFile(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+// The same for a folder of them, made empty on creation.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(path: PathBuf) -> Result<Self, String> {
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).map_err(|e| e.to_string())?;
+        Ok(TempDir(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,470 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::events::{self, Event};
+use crate::ffmpeg::{self, TrackedOutput};
+use crate::fingerprint::{self, Fingerprint};
+use crate::probe::StreamInfo;
+use crate::store;
+
+// What the bundled ffmpeg build can do. Detected once per session (per
+// binary) from `-codecs`, `-encoders` and `-filters`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Capabilities {
+    // Codec names (as ffprobe reports them) this build can decode
+    pub decodable_codecs: HashSet<String>,
+    pub encoders: HashSet<String>,
+    pub filters: HashSet<String>,
+    // -fps_mode (ffmpeg 5.1+) rather than only -vsync; false in files
+    // from before this was detected, where -vsync still works
+    #[serde(default)]
+    pub fps_mode: bool,
+}
+
+impl Capabilities {
+    pub fn can_decode(&self, codec: &str) -> bool {
+        self.decodable_codecs.contains(codec)
+    }
+
+    pub fn has_encoder(&self, encoder: &str) -> bool {
+        self.encoders.contains(encoder)
+    }
+
+    pub fn has_filter(&self, filter: &str) -> bool {
+        self.filters.contains(filter)
+    }
+}
+
+// --- `ffmpeg -codecs` ---
+// We check decodability here rather than in `-decoders`: decoder names don't
+// always match codec names (AV1 is often only there as `libdav1d`), while the
+// codec list uses the same names ffprobe does and flags decode support.
+//
+//  -------
+//  D.VI.S 012v                 Uncompressed 4:2:2 10-bit
+//  DEV.L. av1                  Alliance for Open Media AV1 (decoders: libdav1d av1 )
+//  .EA.L. libcodec2            codec2
+pub fn parse_decodable_codecs(text: &str) -> HashSet<String> {
+    listing_rows(text)
+        .filter(|(flags, _)| flags.starts_with('D'))
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+// --- `ffmpeg -encoders` ---
+//  ------
+//  V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC (codec h264)
+pub fn parse_encoders(text: &str) -> HashSet<String> {
+    listing_rows(text).map(|(_, name)| name.to_string()).collect()
+}
+
+// Rows after the " ------" separator: (flags, name).
+fn listing_rows(text: &str) -> impl Iterator<Item = (&str, &str)> {
+    text.lines()
+        .skip_while(|l| !l.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|l| {
+            let mut parts = l.split_whitespace();
+            Some((parts.next()?, parts.next()?))
+        })
+}
+
+// --- `ffmpeg -filters` ---
+// No separator line here; filter rows are the ones with an "X->Y" io column.
+//  T.C drawtext          V->V       Draw text on top of video frames using libfreetype library.
+pub fn parse_filters(text: &str) -> HashSet<String> {
+    text.lines()
+        .filter_map(|l| {
+            let mut parts = l.split_whitespace();
+            let _flags = parts.next()?;
+            let name = parts.next()?;
+            let io = parts.next()?;
+            io.contains("->").then(|| name.to_string())
+        })
+        .collect()
+}
+
+// `flag` may be several words ("-h long")
+async fn ffmpeg_listing(app: &AppHandle, flag: &str) -> Result<String, String> {
+    let output = ffmpeg::command(app)?
+        .arg("-hide_banner")
+        .args(flag.split_whitespace())
+        .tracked_output(app)
+        .await
+        .map_err(|e| e.to_string())?;
+    if !output.success() {
+        return Err(format!("ffmpeg {} failed", flag));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+async fn detect(app: &AppHandle) -> Result<Capabilities, String> {
+    Ok(Capabilities {
+        decodable_codecs: parse_decodable_codecs(&ffmpeg_listing(app, "-codecs").await?),
+        encoders: parse_encoders(&ffmpeg_listing(app, "-encoders").await?),
+        filters: parse_filters(&ffmpeg_listing(app, "-filters").await?),
+        fps_mode: ffmpeg_listing(app, "-h long").await.is_ok_and(|help| help.contains("-fps_mode")),
+    })
+}
+
+// How long a starting job waits for detection before going ahead without
+// it (CPU encoder, no pre-flight checks)
+const JOB_WAIT: Duration = Duration::from_secs(10);
+
+// ==========================================
+// CAPABILITY CACHE (managed state)
+// ==========================================
+// Detection is single-flight: whoever holds `detecting` runs it (the startup
+// check from the moment setup calls it, or the first job), everyone else
+// waits for the lock and then finds the result in `caps`.
+#[derive(Default)]
+pub struct CapabilityCache {
+    caps: Mutex<Option<Arc<Capabilities>>>,
+    detecting: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl CapabilityCache {
+    // Forget everything detected so far (the ffmpeg binary changed).
+    pub fn invalidate(&self) {
+        *self.caps.lock().unwrap() = None;
+    }
+
+    // As if detection had found `caps` (the job tests)
+    #[cfg(test)]
+    pub fn seed(&self, caps: Capabilities) {
+        *self.caps.lock().unwrap() = Some(Arc::new(caps));
+    }
+}
+
+// capabilities.json: what was detected last session and in which environment.
+#[derive(Serialize, Deserialize)]
+struct StoredCapabilities {
+    fingerprint: Fingerprint,
+    capabilities: Capabilities,
+}
+
+// Payload of `capabilities-changed`: encoders that appeared or went away
+// since the last session.
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct CapabilitiesChanged {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+fn stored_path(app: &AppHandle) -> Option<PathBuf> {
+    app.path().app_data_dir().ok().map(|dir| dir.join("capabilities.json"))
+}
+
+fn load_stored(app: &AppHandle) -> Option<StoredCapabilities> {
+    store::load_json(app, "capabilities", &stored_path(app)?)
+}
+
+fn save_stored(app: &AppHandle, stored: &StoredCapabilities) {
+    let Some(path) = stored_path(app) else { return };
+    if let Err(e) = store::save_json(&path, stored) {
+        println!("⚠️ Could not save capabilities: {}", e);
+    }
+}
+
+pub fn encoder_changes(old: &Capabilities, new: &Capabilities) -> CapabilitiesChanged {
+    let mut added: Vec<String> = new.encoders.difference(&old.encoders).cloned().collect();
+    let mut removed: Vec<String> = old.encoders.difference(&new.encoders).cloned().collect();
+    added.sort();
+    removed.sort();
+    CapabilitiesChanged { added, removed }
+}
+
+// ==========================================
+// STARTUP CHECK (runs in the background)
+// ==========================================
+// Same environment as last time: reuse the stored list and skip detection.
+// Anything different: detect again, and tell the UI if the encoders changed.
+pub fn refresh_on_startup(app: &AppHandle) {
+    let app = app.clone();
+    // Taken before spawning, so a job that starts right away waits for this
+    // check instead of detecting alongside it
+    let lock = app.state::<CapabilityCache>().detecting.clone().try_lock_owned();
+    tauri::async_runtime::spawn(async move {
+        let _lock = lock;
+        let current = fingerprint::collect(&app).await;
+        let stored = load_stored(&app);
+        let cache = app.state::<CapabilityCache>();
+
+        if let Some(stored) = stored.as_ref().filter(|s| s.fingerprint == current) {
+            let mut caps = cache.caps.lock().unwrap();
+            if caps.is_none() {
+                *caps = Some(Arc::new(stored.capabilities.clone()));
+            }
+            return;
+        }
+
+        println!("🔄 GPU driver or ffmpeg build changed since last run, re-detecting capabilities");
+        let detected = match detect(&app).await {
+            Ok(caps) => caps,
+            Err(e) => {
+                println!("⚠️ Could not detect ffmpeg capabilities: {}", e);
+                return;
+            }
+        };
+        if let Some(old) = &stored {
+            let changes = encoder_changes(&old.capabilities, &detected);
+            if !changes.added.is_empty() || !changes.removed.is_empty() {
+                events::emit(&app, Event::CapabilitiesChanged(changes));
+            }
+        }
+        *cache.caps.lock().unwrap() = Some(Arc::new(detected.clone()));
+        save_stored(&app, &StoredCapabilities { fingerprint: current, capabilities: detected });
+    });
+}
+
+// What's been detected so far, without detecting (dry runs).
+pub fn cached(app: &AppHandle) -> Option<Arc<Capabilities>> {
+    app.state::<CapabilityCache>().caps.lock().unwrap().clone()
+}
+
+// Detects on first use, once however many callers race here. Runs as its
+// own task so a caller that gives up waiting doesn't cancel it.
+pub async fn get(app: &AppHandle) -> Result<Arc<Capabilities>, String> {
+    if let Some(caps) = cached(app) {
+        return Ok(caps);
+    }
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let lock = app.state::<CapabilityCache>().detecting.clone();
+        let _turn = lock.lock().await;
+        if let Some(caps) = cached(&app) {
+            return Ok(caps);
+        }
+        let caps = Arc::new(detect(&app).await?);
+        *app.state::<CapabilityCache>().caps.lock().unwrap() = Some(caps.clone());
+        Ok(caps)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// For a job about to start: Ok(None) means detection is still running
+// after JOB_WAIT and the job should go ahead CPU-only. Detection carries on,
+// so jobs still queued by the time it lands get the real capabilities.
+pub async fn get_for_job(app: &AppHandle) -> Result<Option<Arc<Capabilities>>, String> {
+    match tokio::time::timeout(JOB_WAIT, get(app)).await {
+        Ok(caps) => caps.map(Some),
+        Err(_) => {
+            println!("⏳ Capability detection is still running, starting this job without it");
+            Ok(None)
+        }
+    }
+}
+
+// ==========================================
+// PRE-FLIGHT: CAN WE DECODE THE INPUT?
+// ==========================================
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind")]
+pub enum PreflightError {
+    DecoderUnavailable {
+        codec: String,
+        // Absolute stream index in the input
+        stream: u32,
+        codec_type: String,
+        // What would still work, when the other stream type is fine
+        suggestion: Option<String>,
+    },
+}
+
+impl fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightError::DecoderUnavailable { codec, stream, codec_type, suggestion } => {
+                write!(
+                    f,
+                    "This ffmpeg build can't decode the {} codec \"{}\" (stream {})",
+                    codec_type, codec, stream
+                )?;
+                if let Some(s) = suggestion {
+                    write!(f, ". {}", s)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn undecodable<'a>(caps: &Capabilities, stream: Option<&'a StreamInfo>) -> Option<(&'a StreamInfo, &'a str)> {
+    let stream = stream?;
+    let codec = stream.codec_name.as_deref()?;
+    (!caps.can_decode(codec)).then_some((stream, codec))
+}
+
+// Checks the first video and first audio stream, which are the ones the
+// encode maps. Streams with no codec name are left for ffmpeg to judge.
+// `decode_video` is false when the video is stream-copied.
+pub fn check_decoders(caps: &Capabilities, streams: &[StreamInfo], decode_video: bool) -> Result<(), PreflightError> {
+    let video = streams.iter().find(|s| s.codec_type == "video");
+    let audio = streams.iter().find(|s| s.codec_type == "audio");
+    let bad_video = if decode_video { undecodable(caps, video) } else { None };
+    let bad_audio = undecodable(caps, audio);
+
+    let (stream, codec, suggestion) = match (bad_video, bad_audio) {
+        (Some((s, c)), None) => (s, c, audio.map(|_| {
+            "The audio can still be processed: try video_mode \"copy\" to keep the video stream as-is".to_string()
+        })),
+        (None, Some((s, c))) => (s, c, video.map(|_| {
+            "The video can still be re-encoded if the audio track is dropped".to_string()
+        })),
+        (Some((s, c)), Some(_)) => (s, c, None),
+        (None, None) => return Ok(()),
+    };
+
+    Err(PreflightError::DecoderUnavailable {
+        codec: codec.to_string(),
+        stream: stream.index,
+        codec_type: stream.codec_type.clone(),
+        suggestion,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::TempDir;
+    use std::fs;
+
+    fn caps(encoders: &[&str]) -> Capabilities {
+        Capabilities { encoders: encoders.iter().map(|e| e.to_string()).collect(), ..Default::default() }
+    }
+
+    #[test]
+    fn listings_are_parsed_past_their_headers() {
+        let codecs = "Codecs:\n D..... = Decoding supported\n -------\n D.VI.S 012v Uncompressed\n DEV.L. av1 AV1\n .EA.L. libcodec2 codec2\n";
+        let mut decodable: Vec<String> = parse_decodable_codecs(codecs).into_iter().collect();
+        decodable.sort();
+        assert_eq!(decodable, ["012v", "av1"]);
+        let encoders = "Encoders:\n V..... = Video\n ------\n V....D libx264 H.264\n A....D aac AAC\n";
+        assert_eq!(parse_encoders(encoders), caps(&["libx264", "aac"]).encoders);
+        let filters = "Filters:\n  T.. = Timeline support\n T.C drawtext V->V Draw text\n ... abuffer |->A Buffer audio\n";
+        assert_eq!(parse_filters(filters), ["drawtext", "abuffer"].map(String::from).into_iter().collect());
+    }
+
+    #[test]
+    fn encoder_changes_are_sorted_both_ways() {
+        let changes = encoder_changes(&caps(&["libx264", "h264_nvenc", "hevc_nvenc"]), &caps(&["libx264", "h264_qsv", "av1_qsv"]));
+        assert_eq!(changes.added, ["av1_qsv", "h264_qsv"]);
+        assert_eq!(changes.removed, ["h264_nvenc", "hevc_nvenc"]);
+    }
+
+    #[test]
+    fn stored_capabilities_survive_a_damaged_file() {
+        let dir = TempDir::new(std::env::temp_dir().join(format!("capabilities-test-{}-stored", std::process::id()))).unwrap();
+        let path = dir.path().join("capabilities.json");
+        let fingerprint = Fingerprint { gpus: vec!["NVIDIA GeForce RTX 3060 | 551.86".to_string()], ffmpeg: Some("abc".to_string()) };
+        for encoders in [&["libx264"][..], &["libx264", "h264_nvenc"][..]] {
+            store::save_json(&path, &StoredCapabilities { fingerprint: fingerprint.clone(), capabilities: caps(encoders) }).unwrap();
+        }
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &text[..text.len() - 3]).unwrap();
+
+        let stored: StoredCapabilities = store::read_json(&path).value().unwrap();
+        assert_eq!(stored.fingerprint, fingerprint);
+        assert_eq!(stored.capabilities.encoders, caps(&["libx264"]).encoders);
+    }
+
+    fn stream(index: u32, codec_type: &str, codec: Option<&str>) -> StreamInfo {
+        StreamInfo { index, codec_type: codec_type.to_string(), codec_name: codec.map(String::from), ..Default::default() }
+    }
+
+    fn decoding(codecs: &[&str]) -> Capabilities {
+        Capabilities { decodable_codecs: codecs.iter().map(|c| c.to_string()).collect(), ..Default::default() }
+    }
+
+    #[test]
+    fn an_undecodable_video_stream_suggests_copying_it() {
+        let streams = [stream(0, "video", Some("prores_raw")), stream(1, "audio", Some("aac"))];
+        let err = check_decoders(&decoding(&["aac"]), &streams, true).unwrap_err();
+        let PreflightError::DecoderUnavailable { codec, stream, codec_type, suggestion } = err.clone();
+        assert_eq!((codec.as_str(), stream, codec_type.as_str()), ("prores_raw", 0, "video"));
+        assert!(suggestion.unwrap().contains("video_mode \"copy\""));
+        assert!(err.to_string().starts_with("This ffmpeg build can't decode the video codec \"prores_raw\" (stream 0)"));
+        // A copied video stream is never decoded
+        assert_eq!(check_decoders(&decoding(&["aac"]), &streams, false), Ok(()));
+    }
+
+    #[test]
+    fn an_undecodable_audio_stream_suggests_dropping_it() {
+        let streams = [stream(0, "video", Some("h264")), stream(2, "audio", Some("ac4"))];
+        let err = check_decoders(&decoding(&["h264"]), &streams, true).unwrap_err();
+        let PreflightError::DecoderUnavailable { stream, suggestion, .. } = err;
+        assert_eq!(stream, 2);
+        assert!(suggestion.unwrap().contains("audio track is dropped"));
+    }
+
+    #[test]
+    fn nothing_is_suggested_when_neither_stream_decodes() {
+        let streams = [stream(0, "video", Some("prores_raw")), stream(1, "audio", Some("ac4"))];
+        let err = check_decoders(&decoding(&[]), &streams, true).unwrap_err();
+        let PreflightError::DecoderUnavailable { codec_type, suggestion, .. } = err;
+        assert_eq!(codec_type, "video");
+        assert_eq!(suggestion, None);
+        // A lone unreadable stream has nothing else to fall back on either
+        let err = check_decoders(&decoding(&[]), &streams[..1], true).unwrap_err();
+        assert!(matches!(err, PreflightError::DecoderUnavailable { suggestion: None, .. }));
+    }
+
+    #[test]
+    fn only_the_first_stream_of_each_type_and_named_codecs_count() {
+        let caps = decoding(&["h264", "aac"]);
+        let streams = [stream(0, "video", Some("h264")), stream(1, "video", Some("prores_raw")), stream(2, "audio", None), stream(3, "subtitle", Some("hdmv_pgs_subtitle"))];
+        assert_eq!(check_decoders(&caps, &streams, true), Ok(()));
+        assert_eq!(check_decoders(&caps, &[], true), Ok(()));
+    }
+
+    fn listings(h: &crate::jobtests::Harness, flag: &str) -> usize {
+        h.runs().iter().filter(|r| r.iter().any(|a| a == flag)).count()
+    }
+
+    #[test]
+    fn concurrent_callers_share_one_detection() {
+        let h = crate::jobtests::Harness::new("caps-single-flight", r#"{ "runs": [{ "stderr": [{ "line": "listing", "after_ms": 100 }] }] }"#);
+        h.handle().state::<CapabilityCache>().invalidate();
+        let callers: Vec<_> = (0..8)
+            .map(|_| {
+                let app = h.handle().clone();
+                tauri::async_runtime::spawn(async move { get(&app).await })
+            })
+            .collect();
+        let results: Vec<Arc<Capabilities>> = crate::jobtests::run(async move {
+            let mut results = vec![];
+            for caller in callers {
+                results.push(caller.await.unwrap().unwrap());
+            }
+            results
+        });
+
+        assert!(results.iter().all(|caps| Arc::ptr_eq(caps, &results[0])));
+        for flag in ["-codecs", "-encoders", "-filters", "long"] {
+            assert_eq!(listings(&h, flag), 1, "{}", flag);
+        }
+        assert_eq!(h.runs().len(), 4);
+    }
+
+    #[test]
+    fn a_caller_that_stops_waiting_leaves_detection_running() {
+        let h = crate::jobtests::Harness::new("caps-give-up", r#"{ "runs": [{ "stderr": [{ "line": "listing", "after_ms": 150 }] }] }"#);
+        h.handle().state::<CapabilityCache>().invalidate();
+        let app = h.handle().clone();
+        let waited = crate::jobtests::run(async move { tokio::time::timeout(Duration::from_millis(50), get(&app)).await });
+        assert!(waited.is_err());
+
+        h.wait_for("detection to finish", |h| cached(h.handle()).is_some());
+        let app = h.handle().clone();
+        crate::jobtests::run(async move { get(&app).await }).unwrap();
+        assert_eq!(listings(&h, "-encoders"), 1);
+    }
+}
@@ -0,0 +1,390 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::clock;
+use crate::events::{self, Event};
+use crate::history::{HistoryEntry, HistoryStore, JobStatus};
+use crate::paths;
+use crate::settings::SettingsStore;
+use crate::undo;
+
+const DAY_SECS: u64 = 86_400;
+// A file modified this long after its job finished was written by something else
+const MTIME_SLACK_SECS: u64 = 60;
+
+// ==========================================
+// MANAGED OUTPUT FOLDERS
+// ==========================================
+// A folder the user lets the app tidy up ("Compressed" next to the sources).
+// Cleanup walks it and looks every file up in history by output path. The
+// one invariant: a file without a history record is never touched, whatever
+// the rules say, and neither is one whose size no longer matches its record
+// (something else has written it since). Only jobs that wrote their output
+// count as records: a failed job throws its staged file away and never
+// touches the path, and a rehearsal writes nothing, so whatever sits there
+// belongs to someone else (or to an earlier job that did succeed).
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct CleanupRules {
+    // Time-limited previews
+    pub delete_previews_older_than_days: Option<u32>,
+    // Report outputs whose source file is gone...
+    pub flag_missing_sources: bool,
+    // ...and delete them once their record is this old
+    pub delete_missing_sources_older_than_days: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ManagedFolder {
+    pub root: String,
+    #[serde(default)]
+    pub rules: CleanupRules,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupAction {
+    Delete,
+    Flag,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupReason {
+    StalePreview,
+    MissingSource,
+}
+
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct CleanupItem {
+    pub path: String,
+    pub history_id: u64,
+    pub reason: CleanupReason,
+    pub action: CleanupAction,
+    pub bytes: u64,
+    // Set when deleting it failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct CleanupReport {
+    pub root: String,
+    pub dry_run: bool,
+    pub ran_at: u64,
+    pub ran_at_local: String,
+    pub items: Vec<CleanupItem>,
+    // Files in the folder without a history record, left alone
+    pub unrecorded_files: usize,
+    pub deleted_files: usize,
+    pub deleted_bytes: u64,
+    // For undo_action while this session lasts (not for dry runs)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub undo_action_id: Option<u64>,
+    // Where the audit copy of this report went (not for dry runs)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report_path: Option<String>,
+}
+
+fn now_unix() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Resolved paths, folded on case-insensitive volumes, so history and the
+// folder listing compare equal.
+fn key(path: &Path, fold: bool) -> Option<String> {
+    let resolved = paths::resolve(path)?.to_string_lossy().to_string();
+    Some(if fold { resolved.to_lowercase() } else { resolved })
+}
+
+// The newest job that wrote each output path; an older one that wrote the
+// same file doesn't speak for it any more.
+fn index(entries: &[HistoryEntry], fold: bool) -> HashMap<String, &HistoryEntry> {
+    let mut by_output = HashMap::new();
+    for entry in entries.iter().filter(|e| e.status == JobStatus::Success && !e.simulated) {
+        if let Some(k) = key(Path::new(&entry.output), fold) {
+            by_output.insert(k, entry);
+        }
+    }
+    by_output
+}
+
+// Regular files only; symlinks could point anywhere.
+fn walk(dir: &Path, files: &mut Vec<(PathBuf, fs::Metadata)>) {
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let Ok(meta) = fs::symlink_metadata(entry.path()) else { continue };
+        if meta.is_dir() {
+            walk(&entry.path(), files);
+        } else if meta.is_file() {
+            files.push((entry.path(), meta));
+        }
+    }
+}
+
+fn older_than(entry: &HistoryEntry, days: Option<u32>, now: u64) -> bool {
+    days.is_some_and(|d| now.saturating_sub(entry.finished_at) >= d as u64 * DAY_SECS)
+}
+
+// What the rules want done with a recorded file, if anything.
+fn judge(entry: &HistoryEntry, rules: &CleanupRules, now: u64) -> Option<(CleanupReason, CleanupAction)> {
+    if entry.partial && older_than(entry, rules.delete_previews_older_than_days, now) {
+        return Some((CleanupReason::StalePreview, CleanupAction::Delete));
+    }
+    if (rules.flag_missing_sources || rules.delete_missing_sources_older_than_days.is_some()) && !Path::new(&entry.input).exists() {
+        let action = if older_than(entry, rules.delete_missing_sources_older_than_days, now) {
+            CleanupAction::Delete
+        } else {
+            CleanupAction::Flag
+        };
+        return Some((CleanupReason::MissingSource, action));
+    }
+    None
+}
+
+pub fn plan(folder: &ManagedFolder, entries: &[HistoryEntry], now: u64) -> (Vec<CleanupItem>, usize) {
+    let root = Path::new(&folder.root);
+    let fold = paths::is_case_insensitive(root);
+    let records = index(entries, fold);
+    let mut files = vec![];
+    walk(root, &mut files);
+
+    let mut items = vec![];
+    let mut unrecorded = 0;
+    for (path, meta) in files {
+        let Some(entry) = key(&path, fold).and_then(|k| records.get(&k)) else {
+            unrecorded += 1;
+            continue;
+        };
+        let rewritten = meta.modified().ok().and_then(|m| m.duration_since(UNIX_EPOCH).ok()).is_some_and(|m| m.as_secs() > entry.finished_at + MTIME_SLACK_SECS);
+        if meta.len() != entry.output_bytes || rewritten {
+            unrecorded += 1;
+            continue;
+        }
+        if let Some((reason, action)) = judge(entry, &folder.rules, now) {
+            items.push(CleanupItem {
+                path: path.to_string_lossy().to_string(),
+                history_id: entry.id,
+                reason,
+                action,
+                bytes: meta.len(),
+                error: None,
+            });
+        }
+    }
+    items.sort_by(|a, b| a.path.cmp(&b.path));
+    (items, unrecorded)
+}
+
+fn save_report(app: &AppHandle, report: &CleanupReport) -> Option<String> {
+    let dir = app.path().app_data_dir().ok()?.join("cleanup-reports");
+    fs::create_dir_all(&dir).ok()?;
+    let path = dir.join(format!("cleanup-{}.json", report.ran_at));
+    let json = serde_json::to_string_pretty(report).ok()?;
+    fs::write(&path, json).ok()?;
+    Some(path.to_string_lossy().to_string())
+}
+
+// ==========================================
+// COMMANDS: MANAGED FOLDERS
+// ==========================================
+#[tauri::command]
+pub fn set_managed_folder(store: State<'_, SettingsStore>, root: String, rules: CleanupRules) -> Result<ManagedFolder, String> {
+    let resolved = paths::resolve(Path::new(&root)).filter(|p| p.is_dir()).ok_or_else(|| format!("{} is not a folder", root))?;
+    let folder = ManagedFolder { root: resolved.to_string_lossy().to_string(), rules };
+    store.update(|s| match s.managed_folders.iter_mut().find(|f| f.root == folder.root) {
+        Some(existing) => *existing = folder.clone(),
+        None => s.managed_folders.push(folder.clone()),
+    })?;
+    Ok(folder)
+}
+
+#[tauri::command]
+pub fn remove_managed_folder(store: State<'_, SettingsStore>, root: String) -> Result<(), String> {
+    let resolved = paths::resolve(Path::new(&root)).map(|p| p.to_string_lossy().to_string()).unwrap_or(root);
+    store.update(|s| s.managed_folders.retain(|f| f.root != resolved)).map(|_| ())
+}
+
+// ==========================================
+// COMMAND: RUN CLEANUP
+// ==========================================
+// With `dry_run` nothing changes: the report is the plan to confirm. A real
+// run plans again (the folder may have changed since), deletes, saves an
+// audit copy of the report and emits it as `cleanup-finished`.
+#[tauri::command]
+pub fn run_cleanup(
+    app: AppHandle,
+    settings: State<'_, SettingsStore>,
+    history: State<'_, HistoryStore>,
+    root: String,
+    dry_run: bool,
+) -> Result<CleanupReport, String> {
+    let resolved = paths::resolve(Path::new(&root)).map(|p| p.to_string_lossy().to_string()).unwrap_or(root.clone());
+    let folder = settings
+        .get()
+        .managed_folders
+        .into_iter()
+        .find(|f| f.root == resolved)
+        .ok_or_else(|| format!("{} isn't a managed folder", root))?;
+
+    let now = now_unix();
+    let (mut items, unrecorded_files) = plan(&folder, &history.all(), now);
+    let (mut deleted_files, mut deleted_bytes) = (0, 0);
+    let mut undo_action_id = None;
+    if !dry_run {
+        // Held aside rather than deleted, so the whole run can be undone
+        let id = undo::next_id();
+        let mut held = vec![];
+        for item in items.iter_mut().filter(|i| i.action == CleanupAction::Delete) {
+            match undo::hold(Path::new(&item.path), id, false) {
+                Ok(file) => {
+                    held.push(file);
+                    deleted_files += 1;
+                    deleted_bytes += item.bytes;
+                }
+                Err(e) => item.error = Some(e),
+            }
+        }
+        let description = format!("Cleanup of {} ({} files)", folder.root, deleted_files);
+        undo_action_id = undo::register(&app, id, undo::UndoKind::Cleanup, description, held);
+    }
+
+    let mut report = CleanupReport {
+        root: folder.root,
+        dry_run,
+        ran_at: now,
+        ran_at_local: clock::render_local(now),
+        items,
+        unrecorded_files,
+        deleted_files,
+        deleted_bytes,
+        undo_action_id,
+        report_path: None,
+    };
+    if !dry_run {
+        report.report_path = save_report(&app, &report);
+        println!("🧹 Cleanup of {}: removed {} files ({} bytes)", report.root, deleted_files, deleted_bytes);
+        events::emit(&app, Event::CleanupFinished(report.clone()));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::TempDir;
+    use std::time::Instant;
+
+    const WEEK: u64 = 7 * DAY_SECS;
+
+    fn folder(name: &str) -> TempDir {
+        TempDir::new(std::env::temp_dir().join(format!("cleanup-test-{}-{}", std::process::id(), name))).unwrap()
+    }
+
+    fn write(dir: &TempDir, name: &str, bytes: usize) -> String {
+        let path = dir.path().join(name);
+        fs::write(&path, vec![0u8; bytes]).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn done(id: u64, input: &str, output: &str) -> HistoryEntry {
+        HistoryEntry { id, ..HistoryEntry::finished("video", input, output, Instant::now(), None) }
+    }
+
+    fn failed(id: u64, input: &str, output: &str) -> HistoryEntry {
+        HistoryEntry { id, ..HistoryEntry::finished("video", input, output, Instant::now(), Some("boom".to_string())) }
+    }
+
+    fn managed(dir: &TempDir, rules: CleanupRules) -> ManagedFolder {
+        ManagedFolder { root: dir.path().to_string_lossy().to_string(), rules }
+    }
+
+    fn every_rule() -> CleanupRules {
+        CleanupRules { delete_previews_older_than_days: Some(1), flag_missing_sources: true, delete_missing_sources_older_than_days: Some(1) }
+    }
+
+    #[test]
+    fn failed_record_over_an_existing_file_never_deletes_it() {
+        let dir = folder("failed");
+        let output = write(&dir, "clip.mp4", 100);
+        let entries = [failed(1, "/nowhere/clip.mov", &output)];
+        let (items, unrecorded) = plan(&managed(&dir, every_rule()), &entries, now_unix() + WEEK);
+        assert!(items.is_empty());
+        assert_eq!(unrecorded, 1);
+    }
+
+    #[test]
+    fn later_failure_does_not_take_over_an_earlier_success() {
+        let dir = folder("retry");
+        let output = write(&dir, "clip.mp4", 100);
+        let source = write(&dir, "clip.mov", 10);
+        let entries = [done(1, &source, &output), failed(2, &source, &output)];
+        let (items, unrecorded) = plan(&managed(&dir, every_rule()), &entries, now_unix() + WEEK);
+        // The source is there and the output isn't a preview: nothing to do
+        assert!(items.is_empty());
+        assert_eq!(unrecorded, 1, "only the source, which no job wrote");
+    }
+
+    #[test]
+    fn unrecorded_files_are_left_alone() {
+        let dir = folder("unrecorded");
+        write(&dir, "mine.mp4", 100);
+        let (items, unrecorded) = plan(&managed(&dir, every_rule()), &[], now_unix() + WEEK);
+        assert!(items.is_empty());
+        assert_eq!(unrecorded, 1);
+    }
+
+    #[test]
+    fn a_file_rewritten_since_its_job_is_left_alone() {
+        let dir = folder("rewritten");
+        let output = write(&dir, "clip_preview.mp4", 100);
+        let entry = HistoryEntry { partial: true, ..done(1, "/nowhere/clip.mov", &output) };
+        write(&dir, "clip_preview.mp4", 200);
+        let (items, _) = plan(&managed(&dir, every_rule()), &[entry], now_unix() + WEEK);
+        assert!(items.is_empty());
+    }
+
+    #[test]
+    fn stale_previews_are_deleted_and_fresh_ones_kept() {
+        let dir = folder("previews");
+        let output = write(&dir, "clip_preview.mp4", 100);
+        let source = write(&dir, "clip.mov", 10);
+        let entries = [HistoryEntry { partial: true, ..done(1, &source, &output) }];
+        let rules = CleanupRules { delete_previews_older_than_days: Some(7), ..Default::default() };
+
+        let (fresh, _) = plan(&managed(&dir, rules.clone()), &entries, now_unix());
+        assert!(fresh.is_empty());
+        let (stale, _) = plan(&managed(&dir, rules), &entries, now_unix() + WEEK);
+        assert_eq!(stale.len(), 1);
+        assert_eq!((stale[0].reason, stale[0].action, stale[0].history_id), (CleanupReason::StalePreview, CleanupAction::Delete, 1));
+    }
+
+    #[test]
+    fn missing_sources_are_flagged_then_deleted() {
+        let dir = folder("missing");
+        let output = write(&dir, "clip.mp4", 100);
+        let entries = [done(1, "/nowhere/clip.mov", &output)];
+        let rules = CleanupRules { flag_missing_sources: true, delete_missing_sources_older_than_days: Some(30), ..Default::default() };
+
+        let (flagged, _) = plan(&managed(&dir, rules.clone()), &entries, now_unix());
+        assert_eq!(flagged[0].action, CleanupAction::Flag);
+        let (deleted, _) = plan(&managed(&dir, rules), &entries, now_unix() + 31 * DAY_SECS);
+        assert_eq!((deleted[0].reason, deleted[0].action), (CleanupReason::MissingSource, CleanupAction::Delete));
+    }
+
+    #[test]
+    fn rehearsals_are_not_records() {
+        let dir = folder("rehearsal");
+        let output = write(&dir, "clip.mp4", 100);
+        let entries = [HistoryEntry { simulated: true, ..done(1, "/nowhere/clip.mov", &output) }];
+        let (items, unrecorded) = plan(&managed(&dir, every_rule()), &entries, now_unix() + WEEK);
+        assert!(items.is_empty());
+        assert_eq!(unrecorded, 1);
+    }
+}
@@ -0,0 +1,128 @@
+use chrono::{DateTime, Duration, Local, LocalResult, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+
+// ==========================================
+// TIMESTAMPS
+// ==========================================
+// Everything persisted is Unix seconds (UTC). The system's local zone only
+// comes in at the edges: rendering for people, and turning local calendar
+// days or wall-clock times from the UI into instants.
+
+pub fn utc(unix_secs: u64) -> DateTime<Utc> {
+    DateTime::from_timestamp(unix_secs as i64, 0).unwrap_or_default()
+}
+
+// "2026-03-29T01:30:00Z"
+pub fn render_utc(unix_secs: u64) -> String {
+    utc(unix_secs).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// "2026-03-29T03:30:00+02:00", in the system zone
+pub fn render_local(unix_secs: u64) -> String {
+    utc(unix_secs).with_timezone(&Local).to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+// A local wall-clock time as an instant. A time the clocks skip (spring
+// forward) becomes the first moment after the jump; a time that happens
+// twice (fall back) is its first occurrence.
+pub fn resolve<Tz: TimeZone>(tz: &Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    let mut t = local;
+    // Gaps are an hour or two; a day bounds the search for any real zone
+    for _ in 0..24 * 60 {
+        match tz.from_local_datetime(&t) {
+            LocalResult::Single(d) | LocalResult::Ambiguous(d, _) => return d.with_timezone(&Utc),
+            LocalResult::None => t += Duration::minutes(1),
+        }
+    }
+    local.and_utc()
+}
+
+// Midnight starting `day` in the system zone, as Unix seconds.
+pub fn local_day_start(day: NaiveDate) -> u64 {
+    resolve(&Local, day.and_time(Default::default())).timestamp().max(0) as u64
+}
+
+// "YYYY-MM" of the local month an instant falls in.
+pub fn local_month(unix_secs: u64) -> String {
+    utc(unix_secs).with_timezone(&Local).format("%Y-%m").to_string()
+}
+
+// The zone is shared with the schedule tests
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use chrono::{FixedOffset, NaiveTime};
+
+    // Central Europe in 2026: CET (+1), and CEST (+2) from 29 March 01:00
+    // UTC to 25 October 01:00 UTC. The system zone can't be relied on in tests.
+    #[derive(Clone, Copy, Debug)]
+    pub struct Berlin2026;
+
+    fn at(y: i32, m: u32, d: u32, h: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(h, min, 0).unwrap()
+    }
+
+    pub fn utc_at(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        at(y, m, d, h, min).and_utc()
+    }
+
+    fn offset(secs: i32) -> FixedOffset {
+        FixedOffset::east_opt(secs).unwrap()
+    }
+
+    impl TimeZone for Berlin2026 {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Berlin2026
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_time(NaiveTime::MIN))
+        }
+
+        // The earlier instant (the larger offset) first when it's ambiguous
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            let valid: Vec<FixedOffset> = [7200, 3600].into_iter().map(offset).filter(|o| self.offset_from_utc_datetime(&(*local - *o)) == *o).collect();
+            match valid[..] {
+                [] => LocalResult::None,
+                [one] => LocalResult::Single(one),
+                [earlier, later, ..] => LocalResult::Ambiguous(earlier, later),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            let summer = *utc >= at(2026, 3, 29, 1, 0) && *utc < at(2026, 10, 25, 1, 0);
+            offset(if summer { 7200 } else { 3600 })
+        }
+    }
+
+    #[test]
+    fn the_test_zone_has_a_gap_and_an_overlap() {
+        assert!(matches!(Berlin2026.from_local_datetime(&at(2026, 3, 29, 2, 30)), LocalResult::None));
+        assert!(matches!(Berlin2026.from_local_datetime(&at(2026, 10, 25, 2, 30)), LocalResult::Ambiguous(..)));
+        assert!(matches!(Berlin2026.from_local_datetime(&at(2026, 7, 1, 12, 0)), LocalResult::Single(_)));
+    }
+
+    #[test]
+    fn skipped_times_resolve_to_the_end_of_the_gap() {
+        // 02:30 doesn't exist; 03:00 CEST is 01:00 UTC
+        assert_eq!(resolve(&Berlin2026, at(2026, 3, 29, 2, 30)), utc_at(2026, 3, 29, 1, 0));
+    }
+
+    #[test]
+    fn repeated_times_resolve_to_their_first_occurrence() {
+        // 02:30 CEST, not 02:30 CET an hour later
+        assert_eq!(resolve(&Berlin2026, at(2026, 10, 25, 2, 30)), utc_at(2026, 10, 25, 0, 30));
+        assert_eq!(resolve(&Berlin2026, at(2026, 7, 1, 12, 0)), utc_at(2026, 7, 1, 10, 0));
+    }
+
+    #[test]
+    fn utc_renderings_are_rfc3339() {
+        assert_eq!(render_utc(1_774_747_800), "2026-03-29T01:30:00Z");
+        assert_eq!(render_utc(0), "1970-01-01T00:00:00Z");
+    }
+}
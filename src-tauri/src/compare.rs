@@ -0,0 +1,162 @@
+use image::{GenericImageView, ImageBuffer, Rgb, RgbImage};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::State;
+
+use crate::AppHandle;
+use crate::ffmpeg;
+use crate::history::HistoryStore;
+use crate::paths;
+use crate::probe::{self, MediaInfo};
+
+// Differences are small; this makes a one-step error visible as dark red
+const HEATMAP_GAIN: f32 = 8.0;
+// How far before the end a clamped timestamp lands, so there's a frame there
+const END_MARGIN_SECS: f64 = 0.1;
+
+// ==========================================
+// BEFORE / AFTER FRAME COMPARISON
+// ==========================================
+// The same presentation timestamp out of the original and the compressed
+// file, at the original's size, plus a heatmap of where they differ (black
+// = identical, through red to yellow/white = far apart).
+//
+// Our encodes always start at the source's first frame (previews only cut
+// the end, A/V offsets only move the audio), so the same timestamp is the
+// same frame. The history record is still looked up so the result says
+// which job made the compressed file.
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MatchingFrames {
+    pub original: String,
+    pub compressed: String,
+    pub heatmap: String,
+    // Where the frames were actually taken (after clamping)
+    pub timestamp_secs: f64,
+    pub width: u32,
+    pub height: u32,
+    // Mean absolute difference over all channels, 0-255
+    pub mean_difference: f64,
+    // History entry that produced `compressed`, when it's one of ours
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_id: Option<u64>,
+    pub warnings: Vec<String>,
+}
+
+// Keeps `at` inside both files.
+fn clamp(at: f64, original: &MediaInfo, compressed: &MediaInfo, warnings: &mut Vec<String>) -> f64 {
+    let mut at = at.max(0.0);
+    for (label, media) in [("original", original), ("compressed file", compressed)] {
+        if let Some(duration) = media.duration.filter(|d| at > d - END_MARGIN_SECS) {
+            let clamped = (duration - END_MARGIN_SECS).max(0.0);
+            warnings.push(format!("{:.3}s is past the end of the {} ({:.3}s), using {:.3}s", at, label, duration, clamped));
+            at = clamped;
+        }
+    }
+    at
+}
+
+async fn extract(app: &AppHandle, input: &str, at: f64, size: (u32, u32), target: &Path) -> Result<(), String> {
+    ffmpeg::run_quiet(app, vec![
+        "-v".to_string(), "error".to_string(),
+        // After -i so the seek decodes up to the exact timestamp
+        "-i".to_string(), input.to_string(),
+        "-ss".to_string(), format!("{:.3}", at),
+        "-frames:v".to_string(), "1".to_string(),
+        "-vf".to_string(), format!("scale={}:{}:flags=lanczos", size.0, size.1),
+        "-y".to_string(), target.to_string_lossy().to_string(),
+    ])
+    .await
+    .map_err(|e| format!("Could not extract the frame from {}: {}", input, e))
+}
+
+// Black -> red -> yellow -> white as the difference grows.
+fn heat(value: f32) -> Rgb<u8> {
+    let v = (value * HEATMAP_GAIN / 255.0).clamp(0.0, 1.0) * 3.0;
+    let channel = |x: f32| (x.clamp(0.0, 1.0) * 255.0) as u8;
+    Rgb([channel(v), channel(v - 1.0), channel(v - 2.0)])
+}
+
+fn heatmap(original: &Path, compressed: &Path, target: &Path) -> Result<f64, String> {
+    let a = image::open(original).map_err(|e| e.to_string())?.to_rgb8();
+    let mut b = image::open(compressed).map_err(|e| e.to_string())?;
+    // Extraction already scales, but a file whose frame size changes
+    // mid-stream can still come out different
+    if b.dimensions() != a.dimensions() {
+        b = b.resize_exact(a.width(), a.height(), image::imageops::FilterType::Lanczos3);
+    }
+    let b = b.to_rgb8();
+    let mut total = 0u64;
+    let map: RgbImage = ImageBuffer::from_fn(a.width(), a.height(), |x, y| {
+        let (pa, pb) = (a.get_pixel(x, y).0, b.get_pixel(x, y).0);
+        let diffs = [0, 1, 2].map(|c| pa[c].abs_diff(pb[c]));
+        total += diffs.iter().map(|d| *d as u64).sum::<u64>();
+        heat(*diffs.iter().max().unwrap_or(&0) as f32)
+    });
+    map.save(target).map_err(|e| e.to_string())?;
+    let samples = a.width() as u64 * a.height() as u64 * 3;
+    Ok(if samples == 0 { 0.0 } else { total as f64 / samples as f64 })
+}
+
+fn frame_path(dir: &Path, source: &str, at: f64, label: &str) -> PathBuf {
+    let stem = Path::new(source).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    dir.join(format!("{}_{:.3}s_{}.png", stem, at, label))
+}
+
+// ==========================================
+// COMMAND: EXTRACT MATCHING FRAMES
+// ==========================================
+#[tauri::command]
+pub async fn extract_matching_frames(
+    app: AppHandle,
+    history: State<'_, HistoryStore>,
+    original: String,
+    compressed: String,
+    timestamp_secs: f64,
+    output_dir: String,
+) -> Result<MatchingFrames, String> {
+    if !timestamp_secs.is_finite() {
+        return Err("The timestamp must be a number of seconds".to_string());
+    }
+    let (original, compressed) = (paths::secure_input(&original)?, paths::secure_input(&compressed)?);
+    let dir = PathBuf::from(&output_dir);
+    fs::create_dir_all(&dir).map_err(|e| format!("Can't use {}: {}", output_dir, e))?;
+
+    let (source, encoded) = (probe::probe(&app, &original).await?, probe::probe(&app, &compressed).await?);
+    if !source.has_video || !encoded.has_video {
+        return Err("Both files need a video stream".to_string());
+    }
+    let size = source.width.zip(source.height).ok_or("Could not read the original's frame size")?;
+    let history_id = history.all().into_iter().rev().find(|e| paths::same_file(&e.output, &compressed)).map(|e| e.id);
+
+    let mut warnings = vec![];
+    let at = clamp(timestamp_secs, &source, &encoded, &mut warnings);
+    if encoded.width.zip(encoded.height).is_some_and(|s| s != size) {
+        warnings.push(format!("The compressed file is scaled to the original's {}x{} for comparison", size.0, size.1));
+    }
+
+    let secure = |path: PathBuf| paths::secure_output(&app, &path.to_string_lossy(), false).map(PathBuf::from);
+    let paths = (
+        secure(frame_path(&dir, &original, at, "original"))?,
+        secure(frame_path(&dir, &compressed, at, "compressed"))?,
+        secure(frame_path(&dir, &compressed, at, "diff"))?,
+    );
+    extract(&app, &original, at, size, &paths.0).await?;
+    extract(&app, &compressed, at, size, &paths.1).await?;
+    let (a, b, c) = paths.clone();
+    let mean_difference = tauri::async_runtime::spawn_blocking(move || heatmap(&a, &b, &c)).await.map_err(|e| e.to_string())??;
+
+    let (original, compressed, heatmap) = paths;
+    Ok(MatchingFrames {
+        original: original.to_string_lossy().to_string(),
+        compressed: compressed.to_string_lossy().to_string(),
+        heatmap: heatmap.to_string_lossy().to_string(),
+        timestamp_secs: at,
+        width: size.0,
+        height: size.1,
+        mean_difference,
+        history_id,
+        warnings,
+    })
+}
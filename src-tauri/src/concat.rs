@@ -0,0 +1,297 @@
+use serde::Serialize;
+use std::time::Instant;
+
+use crate::AppHandle;
+use crate::duration::Expected;
+use crate::events::Event;
+use crate::ffmpeg;
+use crate::history::{self, HistoryEntry};
+use crate::inputs;
+use crate::outputs;
+use crate::paths;
+use crate::probe::{self, MediaInfo};
+use crate::progress::ProgressTracker;
+use crate::verify;
+
+// Every clip is conformed to the first clip's canvas before joining.
+// Both the plain concat filter and xfade need identical resolution, SAR,
+// frame rate and timebase, so this step is shared by every join mode.
+#[derive(Clone, Copy, Debug)]
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    pub fps: f64,
+}
+
+impl Canvas {
+    fn from_info(info: &MediaInfo) -> Self {
+        // libx264 + yuv420p refuse odd dimensions
+        let even = |v: u32| v.max(2) & !1;
+        Canvas {
+            width: even(info.width.unwrap_or(1920)),
+            height: even(info.height.unwrap_or(1080)),
+            fps: info.fps.unwrap_or(30.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Segment {
+    pub duration: f64,
+    pub has_audio: bool,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ConcatResult {
+    pub output: String,
+    pub segments: usize,
+    pub duration_secs: f64,
+    pub audio_crossfade_ms: Option<u32>,
+    pub video_crossfade_ms: Option<u32>,
+}
+
+// Which stream(s) actually overlap at each join.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Overlap {
+    None,
+    Audio(f64),
+    Video(f64),
+    Both(f64),
+}
+
+impl Overlap {
+    fn secs(self) -> f64 {
+        match self {
+            Overlap::None => 0.0,
+            Overlap::Audio(s) | Overlap::Video(s) | Overlap::Both(s) => s,
+        }
+    }
+}
+
+// ==========================================
+// VALIDATION
+// ==========================================
+fn resolve_overlap(audio_ms: Option<u32>, video_ms: Option<u32>) -> Result<Overlap, String> {
+    let audio = audio_ms.filter(|ms| *ms > 0);
+    let video = video_ms.filter(|ms| *ms > 0);
+    match (audio, video) {
+        (None, None) => Ok(Overlap::None),
+        (Some(a), None) => Ok(Overlap::Audio(a as f64 / 1000.0)),
+        (None, Some(v)) => Ok(Overlap::Video(v as f64 / 1000.0)),
+        (Some(a), Some(v)) if a == v => Ok(Overlap::Both(a as f64 / 1000.0)),
+        (Some(a), Some(v)) => Err(format!(
+            "Audio crossfade ({}ms) and video crossfade ({}ms) must be equal, otherwise audio and video drift apart at every join",
+            a, v
+        )),
+    }
+}
+
+// The first and last clip are overlapped once, clips in the middle twice
+// (once at each end), so those need room for two crossfades.
+fn validate_overlap(segments: &[Segment], overlap: f64) -> Result<(), String> {
+    if overlap <= 0.0 {
+        return Ok(());
+    }
+    let last = segments.len() - 1;
+    for (i, seg) in segments.iter().enumerate() {
+        let joins = if i == 0 || i == last { 1.0 } else { 2.0 };
+        if overlap * joins >= seg.duration {
+            return Err(format!(
+                "Crossfade of {}ms is too long: clip {} is only {:.2}s",
+                (overlap * 1000.0).round(),
+                i + 1,
+                seg.duration
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Length of the joined output: each join eats `overlap` seconds.
+pub fn output_duration(segments: &[Segment], overlap: f64) -> f64 {
+    let total: f64 = segments.iter().map(|s| s.duration).sum();
+    let joins = segments.len().saturating_sub(1) as f64;
+    (total - joins * overlap).max(0.0)
+}
+
+// ==========================================
+// FILTER GRAPH
+// ==========================================
+fn normalize_video(i: usize, canvas: Canvas, trim_to: Option<f64>) -> String {
+    let mut chain = format!(
+        "[{i}:v:0]scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,setsar=1,fps={fps:.3},format=yuv420p",
+        i = i,
+        w = canvas.width,
+        h = canvas.height,
+        fps = canvas.fps
+    );
+    if let Some(d) = trim_to {
+        chain.push_str(&format!(",trim=duration={:.3}", d));
+    }
+    chain.push_str(&format!(",settb=AVTB,setpts=PTS-STARTPTS[v{}]", i));
+    chain
+}
+
+fn normalize_audio(i: usize, seg: Segment, trim_to: Option<f64>) -> String {
+    // Silent clips get a generated silent track so every join has audio on both sides
+    let mut chain = if seg.has_audio {
+        format!("[{}:a:0]aformat=sample_fmts=fltp:sample_rates=48000:channel_layouts=stereo", i)
+    } else {
+        format!("anullsrc=r=48000:cl=stereo,atrim=duration={:.3}", seg.duration)
+    };
+    if let Some(d) = trim_to {
+        chain.push_str(&format!(",atrim=duration={:.3}", d));
+    }
+    chain.push_str(&format!(",asetpts=PTS-STARTPTS[a{}]", i));
+    chain
+}
+
+fn build_filter_graph(segments: &[Segment], canvas: Canvas, overlap: Overlap) -> String {
+    let n = segments.len();
+    let secs = overlap.secs();
+    let video_fades = matches!(overlap, Overlap::Video(_) | Overlap::Both(_));
+    let audio_fades = matches!(overlap, Overlap::Audio(_) | Overlap::Both(_));
+
+    // When only one stream crossfades, the other is shortened by the same amount
+    // at every join so both end up the same length.
+    let trim_for = |i: usize, fades: bool| -> Option<f64> {
+        if secs > 0.0 && !fades && i + 1 < n {
+            Some(segments[i].duration - secs)
+        } else {
+            None
+        }
+    };
+
+    let mut parts: Vec<String> = vec![];
+    for (i, seg) in segments.iter().enumerate() {
+        parts.push(normalize_video(i, canvas, trim_for(i, video_fades)));
+        parts.push(normalize_audio(i, *seg, trim_for(i, audio_fades)));
+    }
+
+    if video_fades {
+        let mut prev = "v0".to_string();
+        let mut running = segments[0].duration;
+        for (k, seg) in segments.iter().enumerate().skip(1) {
+            let offset = running - secs;
+            let out = if k + 1 == n { "vout".to_string() } else { format!("vx{}", k) };
+            parts.push(format!(
+                "[{}][v{}]xfade=transition=fade:duration={:.3}:offset={:.3}[{}]",
+                prev, k, secs, offset, out
+            ));
+            running = offset + seg.duration;
+            prev = out;
+        }
+    } else {
+        let inputs: String = (0..n).map(|i| format!("[v{}]", i)).collect();
+        parts.push(format!("{}concat=n={}:v=1:a=0[vout]", inputs, n));
+    }
+
+    if audio_fades {
+        let mut prev = "a0".to_string();
+        for k in 1..n {
+            let out = if k + 1 == n { "aout".to_string() } else { format!("ax{}", k) };
+            parts.push(format!(
+                "[{}][a{}]acrossfade=d={:.3}:c1=tri:c2=tri[{}]",
+                prev, k, secs, out
+            ));
+            prev = out;
+        }
+    } else {
+        let inputs: String = (0..n).map(|i| format!("[a{}]", i)).collect();
+        parts.push(format!("{}concat=n={}:v=0:a=1[aout]", inputs, n));
+    }
+
+    parts.join(";")
+}
+
+// ==========================================
+// COMMAND: CONCAT VIDEOS
+// ==========================================
+#[tauri::command]
+pub async fn concat_videos(
+    app: AppHandle,
+    mut inputs: Vec<String>,
+    output: String,
+    audio_crossfade_ms: Option<u32>,
+    video_crossfade_ms: Option<u32>,
+) -> Result<ConcatResult, String> {
+    if inputs.len() < 2 {
+        return Err("Pick at least two clips to join".to_string());
+    }
+    for input in inputs.iter_mut() {
+        *input = paths::secure_input(input)?;
+    }
+    let output = paths::secure_output(&app, &output, false)?;
+    for input in &inputs {
+        inputs::preflight(input).map_err(|e| e.to_string())?;
+        paths::ensure_not_input(input, &output)?;
+    }
+    let overlap = resolve_overlap(audio_crossfade_ms, video_crossfade_ms)?;
+
+    println!("🎞️ Joining {} clips...", inputs.len());
+
+    let mut infos = vec![];
+    for input in &inputs {
+        let info = probe::probe(&app, input).await?;
+        if !info.has_video {
+            return Err(format!("No video stream in {}", input));
+        }
+        infos.push(info);
+    }
+
+    let mut segments = vec![];
+    for (input, info) in inputs.iter().zip(&infos) {
+        let duration = info.duration.ok_or_else(|| format!("Could not read the duration of {}", input))?;
+        segments.push(Segment { duration, has_audio: info.has_audio });
+    }
+    validate_overlap(&segments, overlap.secs())?;
+
+    let canvas = Canvas::from_info(&infos[0]);
+    let graph = build_filter_graph(&segments, canvas, overlap);
+    let duration_secs = output_duration(&segments, overlap.secs());
+    // ffmpeg writes a temp file (see outputs.rs); the real name only gets a
+    // joined file that reads back whole
+    let mut reservation = outputs::claim_for_job(&app, &output, None, None)?;
+    let staged = reservation.staged_str();
+
+    let mut args: Vec<String> = vec![];
+    for input in &inputs {
+        args.push("-i".to_string());
+        args.push(input.clone());
+    }
+    args.extend([
+        "-filter_complex".to_string(), graph,
+        "-map".to_string(), "[vout]".to_string(),
+        "-map".to_string(), "[aout]".to_string(),
+        "-c:v".to_string(), "libx264".to_string(),
+        "-preset".to_string(), "medium".to_string(),
+        "-c:a".to_string(), "aac".to_string(),
+        "-y".to_string(), staged.clone(),
+    ]);
+
+    let started = Instant::now();
+    let tracker = ProgressTracker::for_duration(Some(duration_secs));
+    let encoded = async {
+        ffmpeg::run_with_progress(&app, args, tracker, Event::ConcatProgress).await?;
+        verify::read_back(&app, &staged, &Expected { secs: Some(duration_secs), frames: None }).await.map(|_| ())
+    };
+    let result = match encoded.await {
+        Ok(()) => reservation.commit().map(|path| path.to_string_lossy().to_string()),
+        Err(e) => Err(reservation.classify(e)),
+    };
+    let output = result.as_ref().map_or(reservation.path_str(), |path| path.clone());
+
+    let mut entry = HistoryEntry::finished("concat", &inputs.join(" + "), &output, started, result.as_ref().err().cloned());
+    entry.encoder = Some("libx264".to_string());
+    entry.duration_secs = Some(duration_secs);
+    history::record(&app, entry);
+    result?;
+
+    Ok(ConcatResult {
+        output,
+        segments: segments.len(),
+        duration_secs,
+        audio_crossfade_ms: audio_crossfade_ms.filter(|ms| *ms > 0),
+        video_crossfade_ms: video_crossfade_ms.filter(|ms| *ms > 0),
+    })
+}
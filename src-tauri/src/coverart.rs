@@ -0,0 +1,195 @@
+use serde::Serialize;
+
+use crate::probe::StreamInfo;
+
+// What happens to the cover art of the input.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CoverAction {
+    Copy,
+    Drop,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct CoverOutcome {
+    pub index: u32,
+    pub codec: String,
+    pub action: CoverAction,
+    pub warning: Option<String>,
+}
+
+// ==========================================
+// COVER ART IN VIDEO CONTAINERS
+// ==========================================
+// MP4 and MKV files can carry a poster as an attached_pic "video" stream:
+// one JPEG/PNG frame. Picked up like any other video stream it's
+// re-encoded into a one-frame track that bloats the file and shows up as a
+// second angle in some players. So once an input has one, the encode maps
+// streams explicitly: the real video gets the encoder, the cover is copied
+// as it is with its disposition (MP4 writes it as `covr`, Matroska as an
+// attachment), or left out with a warning where that can't work.
+
+// Pure: (cover codec, target container) -> action (+ warning).
+pub fn decide(codec: &str, container: &str) -> (CoverAction, Option<String>) {
+    match container {
+        "mkv" => (CoverAction::Copy, None),
+        // covr atoms only hold JPEG, PNG and BMP
+        "mp4" | "m4v" | "mov" if matches!(codec, "mjpeg" | "png" | "bmp") => (CoverAction::Copy, None),
+        "mp4" | "m4v" | "mov" => (CoverAction::Drop, Some(format!("{} cover art can't go into {} and was dropped", codec, container.to_uppercase()))),
+        _ => (CoverAction::Drop, Some(format!("{} files can't carry cover art; it was dropped", container.to_uppercase()))),
+    }
+}
+
+// The first attached picture, decided for `container`. Resumable encodes
+// write Matroska parts that are joined afterwards, which would repeat the
+// cover in every part, so they drop it.
+pub fn plan(streams: &[StreamInfo], container: &str, resumable: bool) -> Option<CoverOutcome> {
+    let cover = streams.iter().find(|s| s.codec_type == "video" && s.attached_pic)?;
+    let codec = cover.codec_name.clone().unwrap_or_default();
+    let (action, warning) = match decide(&codec, container) {
+        (CoverAction::Copy, _) if resumable => (CoverAction::Drop, Some("Cover art isn't carried through resumable encodes; it was dropped".to_string())),
+        decided => decided,
+    };
+    Some(CoverOutcome { index: cover.index, codec, action, warning })
+}
+
+// `-map` args for the cover as output video stream 1, copied; they go after
+// the real video's map (`0:V:0?`, `V` skips attached pictures). Video filters
+// must be given as `-filter:v:0` then: a filter on the copied cover is an error.
+pub fn mapping_args(cover: &CoverOutcome) -> Vec<String> {
+    if cover.action != CoverAction::Copy {
+        return vec![];
+    }
+    vec![
+        "-map".to_string(), format!("0:{}", cover.index),
+        "-c:v:1".to_string(), "copy".to_string(),
+        "-disposition:v:1".to_string(), "attached_pic".to_string(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{run_video, video, Harness, ENCODE};
+
+    fn cover(index: u32, codec: &str) -> StreamInfo {
+        StreamInfo { index, codec_type: "video".to_string(), codec_name: Some(codec.to_string()), attached_pic: true, ..Default::default() }
+    }
+
+    // A real video stream, its audio and a poster after them
+    fn streams(codec: &str) -> Vec<StreamInfo> {
+        vec![
+            StreamInfo { index: 0, codec_type: "video".to_string(), codec_name: Some("h264".to_string()), ..Default::default() },
+            StreamInfo { index: 1, codec_type: "audio".to_string(), codec_name: Some("aac".to_string()), ..Default::default() },
+            cover(2, codec),
+        ]
+    }
+
+    #[test]
+    fn each_container_copies_or_drops_the_cover() {
+        for codec in ["mjpeg", "png", "bmp", "webp", "gif"] {
+            assert_eq!(decide(codec, "mkv"), (CoverAction::Copy, None), "{}", codec);
+        }
+        for container in ["mp4", "m4v", "mov"] {
+            for codec in ["mjpeg", "png", "bmp"] {
+                assert_eq!(decide(codec, container), (CoverAction::Copy, None), "{} in {}", codec, container);
+            }
+            let (action, warning) = decide("webp", container);
+            assert_eq!(action, CoverAction::Drop);
+            assert_eq!(warning.unwrap(), format!("webp cover art can't go into {} and was dropped", container.to_uppercase()));
+        }
+        for container in ["webm", "avi", "ogv", "wmv", "gif"] {
+            let (action, warning) = decide("mjpeg", container);
+            assert_eq!(action, CoverAction::Drop);
+            assert_eq!(warning.unwrap(), format!("{} files can't carry cover art; it was dropped", container.to_uppercase()));
+        }
+    }
+
+    #[test]
+    fn the_real_video_and_an_attached_jpeg_map_separately() {
+        let outcome = plan(&streams("mjpeg"), "mp4", false).unwrap();
+        assert_eq!((outcome.index, outcome.codec.as_str(), outcome.action), (2, "mjpeg", CoverAction::Copy));
+        assert_eq!(outcome.warning, None);
+        assert_eq!(mapping_args(&outcome), ["-map", "0:2", "-c:v:1", "copy", "-disposition:v:1", "attached_pic"]);
+
+        let dropped = plan(&streams("mjpeg"), "webm", false).unwrap();
+        assert_eq!(dropped.action, CoverAction::Drop);
+        assert_eq!(dropped.warning.as_deref(), Some("WEBM files can't carry cover art; it was dropped"));
+        assert!(mapping_args(&dropped).is_empty());
+    }
+
+    #[test]
+    fn resumable_encodes_drop_even_a_copyable_cover() {
+        let outcome = plan(&streams("png"), "mkv", true).unwrap();
+        assert_eq!(outcome.action, CoverAction::Drop);
+        assert_eq!(outcome.warning.as_deref(), Some("Cover art isn't carried through resumable encodes; it was dropped"));
+        // Already dropped: its own warning stands
+        let outcome = plan(&streams("webp"), "mp4", true).unwrap();
+        assert_eq!(outcome.warning.as_deref(), Some("webp cover art can't go into MP4 and was dropped"));
+    }
+
+    #[test]
+    fn only_attached_pictures_are_covers() {
+        let mut plain = streams("mjpeg");
+        plain.pop();
+        assert!(plan(&plain, "mp4", false).is_none());
+        // A second real video stream isn't one either
+        plain.push(StreamInfo { index: 2, codec_type: "video".to_string(), codec_name: Some("mjpeg".to_string()), ..Default::default() });
+        assert!(plan(&plain, "mkv", false).is_none());
+        // The first of two is
+        plain.extend([cover(3, "png"), cover(4, "mjpeg")]);
+        assert_eq!(plan(&plain, "mkv", false).unwrap().index, 3);
+    }
+
+    const WITH_COVER: &str = r#"{
+        "streams": [
+            { "index": 0, "codec_type": "video", "codec_name": "h264", "width": 1280, "height": 720, "pix_fmt": "yuv420p", "r_frame_rate": "30/1", "avg_frame_rate": "30/1" },
+            { "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "48000" },
+            { "index": 2, "codec_type": "video", "codec_name": "CODEC", "width": 600, "height": 600, "disposition": { "attached_pic": 1 } }
+        ],
+        "format": { "duration": "10.000000", "bit_rate": "8000000", "format_name": "mov,mp4,m4a,3gp,3g2,mj2" }
+    }"#;
+
+    // The encode's args for `output`, with a `codec` poster in the input
+    fn encode(name: &str, codec: &str, output: &str) -> (crate::VideoJobResult, Vec<String>) {
+        let probe = WITH_COVER.replace("CODEC", codec);
+        let h = Harness::new(name, &format!(r#"{{ "ffprobe": {}, "runs": {} }}"#, probe, ENCODE));
+        let result = run_video(&h, video(&h, output)).unwrap();
+        let args = h.runs().into_iter().find(|r| r.iter().any(|a| a == "libx264")).unwrap();
+        (result, args)
+    }
+
+    fn has(args: &[String], run: &[&str]) -> bool {
+        args.windows(run.len()).any(|w| w == run)
+    }
+
+    #[test]
+    fn an_mp4_encode_copies_a_jpeg_cover_next_to_the_video() {
+        let (result, args) = encode("cover-mp4", "mjpeg", "small.mp4");
+        assert!(has(&args, &["-map", "0:V:0?"]));
+        assert!(has(&args, &["-map", "0:2", "-c:v:1", "copy", "-disposition:v:1", "attached_pic"]));
+        // Never fed to the encoder with the real video
+        assert!(!has(&args, &["-map", "0"]));
+        assert!(!args.iter().any(|a| a == "-vf"));
+        let cover = result.cover_art.unwrap();
+        assert_eq!((cover.index, cover.action), (2, CoverAction::Copy));
+        assert!(!result.warnings.iter().any(|w| w.contains("cover art")));
+    }
+
+    #[test]
+    fn an_mkv_encode_keeps_any_cover_as_an_attachment() {
+        let (result, args) = encode("cover-mkv", "webp", "small.mkv");
+        assert!(has(&args, &["-map", "0:2", "-c:v:1", "copy", "-disposition:v:1", "attached_pic"]));
+        assert_eq!(result.cover_art.unwrap().action, CoverAction::Copy);
+    }
+
+    #[test]
+    fn a_cover_the_mp4_cant_hold_is_dropped_with_a_warning() {
+        let (result, args) = encode("cover-dropped", "webp", "small.mp4");
+        // The video still mapped on its own, the cover not at all
+        assert!(has(&args, &["-map", "0:V:0?"]));
+        assert!(!args.iter().any(|a| a == "0:2" || a == "-c:v:1"));
+        assert_eq!(result.cover_art.unwrap().action, CoverAction::Drop);
+        assert!(result.warnings.contains(&"webp cover art can't go into MP4 and was dropped".to_string()));
+    }
+}
@@ -0,0 +1,231 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use sysinfo::System;
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::clock;
+use crate::events::{self, Event};
+use crate::history::now_unix;
+use crate::instance;
+use crate::queue::{self, JobSpec, Priority};
+use crate::store;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+// "Idle": overall CPU use under this for IDLE_POLLS checks in a row, i.e.
+// two quiet minutes
+const IDLE_CPU_PERCENT: f32 = 20.0;
+const IDLE_POLLS: u32 = 4;
+// A run_at this far in the past is a mistake, not "now"
+const PAST_SLACK_SECS: u64 = 60;
+
+// ==========================================
+// SCHEDULED JOBS
+// ==========================================
+// Jobs that wait before they even join the queue: until `run_at`, until
+// the machine is idle, or both. A background task checks every
+// POLL_INTERVAL and hands the ones that are due to queue::enqueue, after
+// which they're ordinary queued jobs (schedule window, priorities and
+// cancel_job all apply). Until then cancel_scheduled drops them.
+//
+// Idle is a CPU-load heuristic, not input idle time: the job starts once
+// the whole machine has been quiet for a while, which also keeps it from
+// piling onto a render that's already running. Waiting jobs are saved to
+// scheduled.json, so they survive a restart; a run_at that passed while
+// the app was closed starts on the first check.
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScheduledJob {
+    pub id: u64,
+    pub spec: JobSpec,
+    #[serde(default)]
+    pub priority: Option<Priority>,
+    // Unix seconds
+    pub run_at: Option<u64>,
+    pub only_when_idle: bool,
+    pub scheduled_at: u64,
+}
+
+impl ScheduledJob {
+    // Pure: whether the job should be queued at `now`, given the idle check.
+    pub fn is_due(&self, now: u64, idle: bool) -> bool {
+        self.run_at.is_none_or(|at| at <= now) && (!self.only_when_idle || idle)
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ScheduledView {
+    #[serde(flatten)]
+    pub job: ScheduledJob,
+    pub run_at_utc: Option<String>,
+    pub run_at_local: Option<String>,
+    // What it's still waiting for: "time", "idle", or both
+    pub waiting_for: Vec<&'static str>,
+}
+
+fn view(job: &ScheduledJob, now: u64) -> ScheduledView {
+    let mut waiting_for = vec![];
+    if job.run_at.is_some_and(|at| at > now) {
+        waiting_for.push("time");
+    }
+    if job.only_when_idle {
+        waiting_for.push("idle");
+    }
+    ScheduledView {
+        job: job.clone(),
+        run_at_utc: job.run_at.map(clock::render_utc),
+        run_at_local: job.run_at.map(clock::render_local),
+        waiting_for,
+    }
+}
+
+// ==========================================
+// MANAGED STATE
+// ==========================================
+pub struct ScheduledJobs {
+    path: Option<PathBuf>,
+    jobs: Mutex<Vec<ScheduledJob>>,
+}
+
+impl ScheduledJobs {
+    pub fn load(app: &AppHandle) -> Self {
+        // In memory only for a secondary instance, like the queue
+        let path = app.path().app_data_dir().ok().map(|dir| dir.join("scheduled.json")).filter(|_| !instance::is_secondary(app));
+        let jobs: Vec<ScheduledJob> = path.as_deref().and_then(|p| store::load_json(app, "scheduled", p)).unwrap_or_default();
+        if !jobs.is_empty() {
+            println!("⏰ Restoring {} scheduled jobs", jobs.len());
+        }
+        ScheduledJobs { path, jobs: Mutex::new(jobs) }
+    }
+
+    fn mutate<T>(&self, app: &AppHandle, f: impl FnOnce(&mut Vec<ScheduledJob>) -> T) -> T {
+        let (out, jobs) = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let out = f(&mut jobs);
+            (out, jobs.clone())
+        };
+        if let Err(e) = self.write(&jobs) {
+            println!("⚠️ Could not save scheduled jobs: {}", e);
+        }
+        let now = now_unix();
+        events::emit(app, Event::ScheduledChanged(jobs.iter().map(|j| view(j, now)).collect()));
+        out
+    }
+
+    fn write(&self, jobs: &[ScheduledJob]) -> Result<(), String> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let json = serde_json::to_string_pretty(jobs).map_err(|e| e.to_string())?;
+        store::write_atomic(path, json.as_bytes(), is_valid)
+    }
+
+    fn has_idle_jobs(&self) -> bool {
+        self.jobs.lock().unwrap().iter().any(|j| j.only_when_idle)
+    }
+
+    fn take_due(&self, app: &AppHandle, now: u64, idle: bool) -> Vec<ScheduledJob> {
+        if !self.jobs.lock().unwrap().iter().any(|j| j.is_due(now, idle)) {
+            return vec![];
+        }
+        self.mutate(app, |jobs| {
+            let (due, waiting) = std::mem::take(jobs).into_iter().partition(|j| j.is_due(now, idle));
+            *jobs = waiting;
+            due
+        })
+    }
+}
+
+pub fn is_valid(text: &str) -> bool {
+    store::parses::<Vec<ScheduledJob>>(text)
+}
+
+// Load over consecutive checks. refresh_cpu_usage measures since the last
+// refresh, so each reading covers a whole POLL_INTERVAL.
+struct IdleWatch {
+    system: System,
+    quiet_polls: u32,
+}
+
+impl IdleWatch {
+    fn poll(&mut self) -> bool {
+        self.system.refresh_cpu_usage();
+        match self.system.global_cpu_usage() < IDLE_CPU_PERCENT {
+            true => self.quiet_polls += 1,
+            false => self.quiet_polls = 0,
+        }
+        self.quiet_polls >= IDLE_POLLS
+    }
+}
+
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut idle = IdleWatch { system: System::new(), quiet_polls: 0 };
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let scheduled = app.state::<ScheduledJobs>();
+            // Sampled every time, so a job added mid-quiet doesn't start the count over
+            let is_idle = idle.poll() && scheduled.has_idle_jobs();
+            for job in scheduled.take_due(&app, now_unix(), is_idle) {
+                match queue::enqueue(&app, vec![job.spec], job.priority) {
+                    Ok(ids) => println!("⏰ Scheduled job {} is due, queued as {:?}", job.id, ids),
+                    Err(e) => println!("⚠️ Scheduled job {} could not be queued: {}", job.id, e),
+                }
+            }
+        }
+    });
+}
+
+// ==========================================
+// COMMANDS: SCHEDULED JOBS
+// ==========================================
+// Validated now, so a bad request fails while the user is still there.
+#[tauri::command]
+pub fn schedule_job(
+    app: AppHandle,
+    scheduled: State<'_, ScheduledJobs>,
+    job: JobSpec,
+    run_at: Option<DateTime<Utc>>,
+    only_when_idle: bool,
+    priority: Option<Priority>,
+) -> Result<ScheduledView, String> {
+    if run_at.is_none() && !only_when_idle {
+        return Err("Give a time, only_when_idle or both; to run it now, queue it with enqueue_jobs".to_string());
+    }
+    job.validate().map_err(|e| e.to_string())?;
+    let now = now_unix();
+    let run_at = run_at.map(|t| t.timestamp().max(0) as u64);
+    if run_at.is_some_and(|at| at + PAST_SLACK_SECS < now) {
+        return Err("That time has already passed".to_string());
+    }
+    let job = scheduled.mutate(&app, |jobs| {
+        let id = jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        let job = ScheduledJob { id, spec: job, priority, run_at, only_when_idle, scheduled_at: now };
+        jobs.push(job.clone());
+        job
+    });
+    println!("⏰ Scheduled job {}: {} ({})", job.id, job.spec.input(), view(&job, now).waiting_for.join(" + "));
+    Ok(view(&job, now))
+}
+
+#[tauri::command]
+pub fn list_scheduled(scheduled: State<'_, ScheduledJobs>) -> Vec<ScheduledView> {
+    let now = now_unix();
+    scheduled.jobs.lock().unwrap().iter().map(|j| view(j, now)).collect()
+}
+
+// Only before it's queued; after that it's cancel_job's.
+#[tauri::command]
+pub fn cancel_scheduled(app: AppHandle, scheduled: State<'_, ScheduledJobs>, id: u64) -> Result<(), String> {
+    let removed = scheduled.mutate(&app, |jobs| {
+        let before = jobs.len();
+        jobs.retain(|j| j.id != id);
+        jobs.len() < before
+    });
+    match removed {
+        true => Ok(()),
+        false => Err(format!("No scheduled job {} (it may have been queued already)", id)),
+    }
+}
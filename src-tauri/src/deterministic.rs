@@ -0,0 +1,162 @@
+use sha2::{Digest, Sha256};
+
+use crate::AppHandle;
+use crate::health;
+use crate::request::{VideoOptions, REQUEST_VERSION};
+
+// What every deterministic output says it was made at
+pub const CREATION_TIME: &str = "1970-01-01T00:00:00.000000Z";
+
+// ==========================================
+// DETERMINISTIC OUTPUT
+// ==========================================
+// For asset pipelines that key caches on content: the same input with the
+// same options (and the same ffmpeg build) gives the same bytes. Tags are
+// dropped and the creation time fixed, muxers and codecs run bitexact (no
+// version strings, no random ids), and every ffmpeg of the job runs on one
+// thread, since x264 and x265 decide differently with more. Hardware
+// encoders differ between drivers and are refused outright.
+//
+// The result carries a hash of what went into the output besides the
+// input, for the pipeline to key on next to its own hash of the input.
+
+// Pure: options deterministic can't go with, and why.
+pub fn conflicts(options: &VideoOptions) -> Vec<(&'static str, &'static str)> {
+    let mut found = vec![];
+    if options.auto_gpu_decides() {
+        found.push(("auto_gpu", "hardware encoders give different bytes on different drivers"));
+    }
+    if options.preference().is_vendor() {
+        found.push(("encoder_preference", "hardware encoders give different bytes on different drivers"));
+    }
+    if options.surgical {
+        found.push(("surgical", "surgical mode keeps the source's tags"));
+    }
+    if options.process.threads.is_some_and(|t| t != 1) {
+        found.push(("threads", "deterministic output runs on one thread"));
+    }
+    found
+}
+
+// Output options, in place of the metadata mode's.
+pub fn output_args() -> Vec<String> {
+    [
+        "-map_metadata", "-1",
+        "-metadata", &format!("creation_time={}", CREATION_TIME),
+        "-fflags", "+bitexact",
+        "-flags:v", "+bitexact",
+        "-flags:a", "+bitexact",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+// x265 has its own thread pools, which -threads doesn't reach. Merged into
+// an -x265-params that's there already (HDR jobs set one).
+pub fn pin_x265(codec_args: &mut Vec<String>) {
+    const SINGLE: &str = "pools=1:frame-threads=1";
+    match codec_args.iter().position(|a| a == "-x265-params").and_then(|i| codec_args.get_mut(i + 1)) {
+        Some(params) => {
+            params.push(':');
+            params.push_str(SINGLE);
+        }
+        None => codec_args.extend(["-x265-params".to_string(), SINGLE.to_string()]),
+    }
+}
+
+// Pure: sha256 over the request version, the options, the output's
+// container and the ffmpeg build.
+pub fn hash(options: &VideoOptions, output_ext: &str, ffmpeg_version: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(REQUEST_VERSION.to_le_bytes());
+    hasher.update(serde_json::to_vec(options).unwrap_or_default());
+    hasher.update(b"\0");
+    hasher.update(output_ext.to_lowercase().as_bytes());
+    hasher.update(b"\0");
+    hasher.update(ffmpeg_version.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+pub async fn settings_hash(app: &AppHandle, options: &VideoOptions, output: &str) -> String {
+    let ext = std::path::Path::new(output).extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+    let version = health::version_line(app).await.unwrap_or_default();
+    hash(options, &ext, &version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::EncoderPreference;
+    use crate::jobtests::{self, clip_scenario, Harness};
+    use crate::request::VideoCompressRequest;
+
+    #[test]
+    fn gpu_surgical_and_threads_conflict() {
+        assert!(conflicts(&VideoOptions::default()).is_empty());
+        let names = |options: VideoOptions| conflicts(&options).into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names(VideoOptions { auto_gpu: true, ..Default::default() }), ["auto_gpu"]);
+        assert_eq!(names(VideoOptions { encoder_preference: Some(EncoderPreference::Nvenc), surgical: true, ..Default::default() }), ["encoder_preference", "surgical"]);
+        // Asking for the CPU is fine, and so is one thread
+        let mut cpu = VideoOptions { auto_gpu: true, encoder_preference: Some(EncoderPreference::Cpu), ..Default::default() };
+        cpu.process.threads = Some(1);
+        assert!(conflicts(&cpu).is_empty());
+        cpu.process.threads = Some(4);
+        assert_eq!(names(cpu), ["threads"]);
+    }
+
+    #[test]
+    fn x265_threads_are_pinned_into_existing_params() {
+        let mut args = vec!["-crf".to_string(), "28".to_string()];
+        pin_x265(&mut args);
+        assert_eq!(args, ["-crf", "28", "-x265-params", "pools=1:frame-threads=1"]);
+
+        let mut hdr = vec!["-x265-params".to_string(), "hdr10=1".to_string(), "-tag:v".to_string(), "hvc1".to_string()];
+        pin_x265(&mut hdr);
+        assert_eq!(hdr, ["-x265-params", "hdr10=1:pools=1:frame-threads=1", "-tag:v", "hvc1"]);
+    }
+
+    #[test]
+    fn the_hash_covers_options_container_and_build() {
+        let options = VideoOptions { deterministic: true, ..Default::default() };
+        let base = hash(&options, "mp4", "ffmpeg version 7.1");
+        assert_eq!(base.len(), 64);
+        assert_eq!(base, hash(&options, "MP4", "ffmpeg version 7.1"));
+        assert_ne!(base, hash(&options, "mkv", "ffmpeg version 7.1"));
+        assert_ne!(base, hash(&options, "mp4", "ffmpeg version 7.0"));
+        assert_ne!(base, hash(&VideoOptions { crf: Some(20), ..options.clone() }, "mp4", "ffmpeg version 7.1"));
+    }
+
+    #[test]
+    fn output_args_fix_the_creation_time() {
+        let args = output_args();
+        assert!(args.windows(2).any(|w| w == ["-map_metadata", "-1"]));
+        assert!(args.contains(&format!("creation_time={}", CREATION_TIME)));
+    }
+
+    #[test]
+    fn the_same_input_encodes_to_the_same_bytes_twice() {
+        // The stub writes its arguments, so the bytes only match if the command lines do
+        let h = Harness::new("deterministic-twice", &clip_scenario(r#"[{ "stderr": [], "output_bytes": 4096, "echo_args": true }]"#));
+        let input = h.input("clip.mp4", 100_000);
+        let options = VideoOptions { deterministic: true, ..Default::default() };
+        let encode = |name: &str| {
+            let request = VideoCompressRequest::new(input.clone(), h.file(name), options.clone());
+            let result = jobtests::run_video(&h, request).unwrap();
+            let bytes = std::fs::read(&result.output).unwrap();
+            assert!(bytes.iter().any(|b| *b != 0), "the stub didn't write its arguments");
+            (format!("{:x}", Sha256::digest(bytes)), result.settings_hash)
+        };
+
+        let (first, first_settings) = encode("one.mp4");
+        let (second, second_settings) = encode("two.mp4");
+        assert_eq!(first, second);
+        assert!(first_settings.is_some());
+        assert_eq!(first_settings, second_settings);
+        let encodes: Vec<Vec<String>> = h.runs().into_iter().filter(|r| r.iter().any(|a| a == "-c:v")).collect();
+        assert_eq!(encodes.len(), 2);
+        for args in encodes {
+            assert!(args.windows(2).any(|w| w == ["-threads", "1"]), "{:?}", args);
+            assert!(args.windows(2).any(|w| w == ["-fflags", "+bitexact"]), "{:?}", args);
+        }
+    }
+}
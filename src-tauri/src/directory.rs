@@ -0,0 +1,168 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::State;
+use walkdir::WalkDir;
+
+use crate::AppHandle;
+use crate::batch::{self, BatchJobStatus, BatchSummary, Batches};
+use crate::paths;
+use crate::queue::JobSpec;
+use crate::request::{VideoCompressRequest, VideoOptions};
+use crate::support;
+use crate::watch;
+
+const DEFAULT_CONTAINER: &str = "mp4";
+
+// ==========================================
+// FOLDER COMPRESSION
+// ==========================================
+// A dropped folder: every video below `input_dir` (extensions from the
+// allowlist, watch folders' by default) becomes a batch job writing the
+// same relative path under `output_dir`, in `container`. Outputs that are
+// already there are skipped, so running it again after a cancel or a crash
+// picks up where it stopped.
+//
+// An output folder inside the input folder is left out of the walk, or a
+// second run would compress its own outputs; the input folder itself can't
+// be the output folder. Hidden files (macOS `._clip.mp4` sidecars, .DS_Store)
+// are never inputs.
+
+// One folder of the tree; `dir` is relative to input_dir ("" for itself).
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct DirectorySummary {
+    pub dir: String,
+    pub processed: usize,
+    // Already compressed
+    pub skipped: usize,
+    // Failed or cancelled
+    pub failed: usize,
+    // Input minus output over the processed files
+    pub bytes_saved: i64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DirectoryResult {
+    pub input_dir: String,
+    pub output_dir: String,
+    // Depth-first, parents before their subfolders
+    pub directories: Vec<DirectorySummary>,
+    // Inputs whose output was already there
+    pub skipped: Vec<String>,
+    pub batch: BatchSummary,
+}
+
+// Pure: "Vacation/Day 1/clip.MOV" -> "<output>/Vacation/Day 1/clip.mp4"
+pub fn output_for(output_root: &Path, relative: &Path, container: &str) -> PathBuf {
+    output_root.join(relative).with_extension(container)
+}
+
+fn relative_dir(relative: &Path) -> String {
+    relative.parent().map(|p| p.to_string_lossy().replace('\\', "/")).unwrap_or_default()
+}
+
+fn allowed(path: &Path, extensions: &[String]) -> bool {
+    path.extension().is_some_and(|e| extensions.contains(&e.to_string_lossy().to_lowercase()))
+}
+
+// Inputs under `input_root`, relative to it, in a stable order.
+fn walk(input_root: &Path, output_root: &Path, extensions: &[String]) -> Vec<PathBuf> {
+    WalkDir::new(input_root)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !(e.file_name().to_string_lossy().starts_with('.') || e.path().starts_with(output_root)))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && allowed(e.path(), extensions))
+        .filter_map(|e| e.path().strip_prefix(input_root).ok().map(Path::to_path_buf))
+        .collect()
+}
+
+// Pure: the batch's outcomes (and the skips) folded per folder.
+pub fn summarize(skipped: &[PathBuf], queued: &[PathBuf], batch: &BatchSummary) -> Vec<DirectorySummary> {
+    let mut dirs: BTreeMap<String, DirectorySummary> = BTreeMap::new();
+    for relative in skipped {
+        let dir = relative_dir(relative);
+        dirs.entry(dir.clone()).or_insert_with(|| DirectorySummary { dir, ..Default::default() }).skipped += 1;
+    }
+    for job in &batch.jobs {
+        let Some(relative) = queued.get(job.index) else { continue };
+        let dir = relative_dir(relative);
+        let summary = dirs.entry(dir.clone()).or_insert_with(|| DirectorySummary { dir, ..Default::default() });
+        match job.status {
+            BatchJobStatus::Done => {
+                summary.processed += 1;
+                summary.bytes_saved += job.input_bytes as i64 - job.output_bytes as i64;
+            }
+            BatchJobStatus::Failed | BatchJobStatus::Cancelled => summary.failed += 1,
+        }
+    }
+    dirs.into_values().collect()
+}
+
+// ==========================================
+// COMMAND: COMPRESS DIRECTORY
+// ==========================================
+// `extensions` replaces the allowlist ("mov" or ".MOV" alike); `container`
+// is the outputs' extension, mp4 when not given. Per-job progress and
+// cancel_batch work as for compress_batch (the batch id is in
+// `batch-started`).
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn compress_directory(
+    app: AppHandle,
+    batches: State<'_, Batches>,
+    input_dir: String,
+    output_dir: String,
+    options: VideoOptions,
+    extensions: Option<Vec<String>>,
+    container: Option<String>,
+    max_concurrent: Option<usize>,
+) -> Result<DirectoryResult, String> {
+    let input_root = fs::canonicalize(&input_dir).map_err(|e| format!("{}: {}", input_dir, e))?;
+    if !input_root.is_dir() {
+        return Err(format!("{} isn't a folder", input_dir));
+    }
+    let output_root = paths::resolve(Path::new(&output_dir)).ok_or_else(|| format!("{} isn't a usable output folder", output_dir))?;
+    if paths::paths_equal(&input_root, &output_root, paths::is_case_insensitive(&input_root)) {
+        return Err("The output folder can't be the input folder: every later run would compress the outputs again".to_string());
+    }
+    let container = container.unwrap_or_else(|| DEFAULT_CONTAINER.to_string()).trim_start_matches('.').to_lowercase();
+    if support::video_container(&container).is_none() {
+        return Err(format!(".{} isn't a video format we can write ({})", container, support::video_extensions().join(", ")));
+    }
+    let extensions: Vec<String> = match extensions {
+        Some(list) => list.iter().map(|e| e.trim().trim_start_matches('.').to_lowercase()).filter(|e| !e.is_empty()).collect(),
+        None => watch::WATCH_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+    };
+    if output_root.starts_with(&input_root) {
+        println!("📁 {} is inside {}; it's left out of the walk", output_root.display(), input_root.display());
+    }
+
+    let (mut skipped, mut queued, mut jobs) = (vec![], vec![], vec![]);
+    for relative in walk(&input_root, &output_root, &extensions) {
+        let output = output_for(&output_root, &relative, &container);
+        if output.exists() {
+            skipped.push(relative);
+            continue;
+        }
+        let input = input_root.join(&relative).to_string_lossy().to_string();
+        let mut request = VideoCompressRequest::new(input, output.to_string_lossy().to_string(), options.clone());
+        request.create_dirs = true;
+        let spec = JobSpec::Video(Box::new(request));
+        spec.validate().map_err(|e| format!("{}: {}", relative.display(), e))?;
+        queued.push(relative);
+        jobs.push(spec);
+    }
+    println!("📁 {}: {} files to compress, {} already done", input_root.display(), jobs.len(), skipped.len());
+
+    let batch = batch::run(&app, &batches, jobs, max_concurrent).await;
+    Ok(DirectoryResult {
+        input_dir: input_root.to_string_lossy().to_string(),
+        output_dir: output_root.to_string_lossy().to_string(),
+        directories: summarize(&skipped, &queued, &batch),
+        skipped: skipped.iter().map(|p| input_root.join(p).to_string_lossy().to_string()).collect(),
+        batch,
+    })
+}
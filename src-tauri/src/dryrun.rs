@@ -0,0 +1,394 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::audiomode::AudioMode;
+use crate::capabilities::Capabilities;
+use crate::deterministic;
+use crate::encoders;
+use crate::explain::{self, Explanation};
+use crate::hdr::{HdrPlan, StaticPlan};
+use crate::joblog;
+use crate::metadata;
+use crate::playability;
+use crate::probe::MediaInfo;
+use crate::quality::{self, QualityOptions};
+use crate::request::VideoOptions;
+use crate::resources;
+use crate::sourcetool::{self, Fixup, SourceTool};
+use crate::support::VideoCodec;
+use crate::surgical::{self, LedgerEntry};
+use crate::vfr;
+use crate::{RatePlan, VideoMode};
+
+// ==========================================
+// DRY RUNS
+// ==========================================
+// With `dry_run`, a job goes through everything that decides its command
+// (routing, validation, probing, the HDR check) and stops where the encode
+// would start: no output is claimed or written, nothing goes into history
+// and no ffmpeg is started. What comes back is the command itself, to debug
+// with or to paste into a terminal.
+//
+// ffprobe still reads the input. Encoder and capability detection come from
+// what's been detected already (a dry run before that plans for the CPU),
+// and the loudness, A/V offset and telecine passes are left out: the plan
+// says what each would add. The checks that only read the output back are
+// skipped. Resumable jobs show the command a
+// single run would get: the real encode splits it into parts. Subtitle
+// files that aren't UTF-8 are muxed from a converted copy next to the
+// output, which a dry run doesn't keep.
+
+#[derive(Serialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DryRunPlan {
+    // What the picture would be encoded with ("native" when no ffmpeg would run)
+    pub encoder: String,
+    // Every ffmpeg run of the encode, in order, as spawn() would get it
+    // (thread cap included); the last one writes the output. Two-pass and
+    // GIF jobs have two.
+    pub commands: Vec<Vec<String>>,
+    // The same, quoted for a shell
+    pub command_lines: Vec<String>,
+    // The video filter graph, when there is one
+    pub filter_graph: Option<String>,
+    pub audio_filter: Option<String>,
+    // What the job would warn about before encoding (CPU fallbacks, HDR or
+    // subtitle substitutions, ...)
+    pub warnings: Vec<String>,
+}
+
+impl DryRunPlan {
+    pub fn new(encoder: &str, commands: Vec<Vec<String>>, warnings: Vec<String>) -> Self {
+        let commands: Vec<Vec<String>> = commands.into_iter().map(resources::with_threads).collect();
+        let command_lines = commands.iter().map(|args| joblog::command_line("ffmpeg", args)).collect();
+        DryRunPlan { encoder: encoder.to_string(), commands, command_lines, filter_graph: None, audio_filter: None, warnings }
+    }
+}
+
+// ==========================================
+// VIDEO ARGUMENTS
+// ==========================================
+// Which encoder a video job gets and the arguments that go with it
+// (quality, HDR flags, player caps, preset, timing, tags, fixups), from the
+// options and what was found out about the machine and the source. Nothing
+// here spawns or reads anything, so the routing can be tested without a
+// GPU; encode_video does the detecting and hands the results in.
+
+// What's known about the machine.
+#[derive(Default)]
+pub struct Detected<'a> {
+    // None when it couldn't be read or wasn't needed
+    pub caps: Option<&'a Capabilities>,
+    // Detection was still running: no pre-flight checks, no GPU
+    pub caps_pending: bool,
+    // What hardware::choose picked
+    pub gpu_encoder: Option<&'static str>,
+}
+
+// What's known about the source.
+#[derive(Default)]
+pub struct Source<'a> {
+    pub ext: &'a str,
+    pub media: Option<&'a MediaInfo>,
+    // Where a cut starts, and how long the output runs
+    pub start: f64,
+    pub length: Option<f64>,
+    pub short: bool,
+    pub hdr: Option<&'a HdrPlan>,
+    pub static_hdr: Option<&'a StaticPlan>,
+    // A screen recording's tool, and the fixups it gets
+    pub tool: Option<SourceTool>,
+    pub fixups: &'a [Fixup],
+}
+
+pub struct VideoArgs {
+    pub encoder: &'static str,
+    // The container's audio encoder ("copy" for copy_only); audiomode has
+    // the last word
+    pub audio: &'static str,
+    // From -c:v (or surgical mode's stream args) up to the maps and audio
+    pub codec_args: Vec<String>,
+    pub ledger: Option<Vec<LedgerEntry>>,
+    pub rate: QualityOptions,
+    pub two_pass: bool,
+    pub single_pass_for_short: bool,
+    // preserve_vfr on something that gets encoded
+    pub keep_timing: bool,
+    // In the order encode_video reports them, after the container's
+    pub explanations: Vec<Explanation>,
+}
+
+pub fn build_ffmpeg_args(options: &VideoOptions, detected: &Detected, source: &Source) -> Result<VideoArgs, String> {
+    let (ext, media, codec) = (source.ext, source.media, options.codec);
+    let copy_video = options.video_mode == VideoMode::Copy || options.copy_only;
+    let mut why = vec![];
+    let codecs = crate::container_codecs(ext, codec, detected.gpu_encoder);
+    // HEVC / AV1 on the CPU need an encoder not every build has
+    if !copy_video && !codecs.gpu && codecs.encoder == codec.cpu_encoder() && codec != VideoCodec::H264 && !detected.caps_pending && detected.caps.is_some_and(|c| !c.has_encoder(codecs.encoder)) {
+        return Err(format!("This ffmpeg build has no {} encoder, so {} output isn't available without a working GPU encoder", codecs.encoder, codec.label()));
+    }
+    let (mut encoder, mut audio, mut extra_args) = (codecs.encoder, codecs.audio, codecs.extra_args);
+
+    if let Some(plan) = source.hdr {
+        why.push(explain::hdr(&plan.action_name(), plan.encoder));
+        encoder = plan.encoder;
+        extra_args = plan.encoder_args.clone();
+    }
+    if let Some(plan) = source.static_hdr {
+        why.push(explain::hdr(plan.action_name(), encoder));
+        extra_args.extend(plan.args.iter().cloned());
+    }
+
+    let RatePlan { options: rate, single_pass_for_short, two_pass } = crate::resolve_rate(&options.rate, options.crf, options.resumable, encoder, source.short, copy_video);
+    if single_pass_for_short {
+        why.push(explain::short_input("single_pass"));
+    }
+    why.push(explain::rate(&rate, encoder, copy_video, two_pass));
+    if !copy_video {
+        let duration = match (media.and_then(|m| m.duration).map(|d| d - source.start), source.length) {
+            (Some(d), Some(limit)) => Some(d.min(limit)),
+            (d, limit) => d.or(limit),
+        };
+        let has_audio = media.is_none_or(|m| m.has_audio) && options.audio != AudioMode::Remove;
+        if let Some(args) = rate.encoder_args(encoder, duration, has_audio)? {
+            quality::strip_rate_args(&mut extra_args);
+            extra_args.extend(args);
+        }
+    }
+
+    if copy_video {
+        encoder = "copy";
+        // The audio gets its rate from its plan
+        extra_args.clear();
+    }
+    if options.copy_only {
+        audio = "copy";
+    }
+    // Apple players only accept HEVC tagged hvc1 (hdr.rs adds it itself)
+    let apple_container = matches!(ext, "mp4" | "m4v" | "mov");
+    // A copied stream is the source's codec (and its tag, often hev1)
+    let source_video = media.and_then(|m| m.streams.iter().find(|s| s.codec_type == "video" && !s.attached_pic));
+    let video_codec = if copy_video { source_video.and_then(|s| s.codec_name.as_deref()).unwrap_or_default() } else { encoders::profile(encoder).codec };
+    if apple_container && video_codec == "hevc" && !extra_args.iter().any(|a| a == "-tag:v") {
+        extra_args.extend(["-tag:v".to_string(), "hvc1".to_string()]);
+    }
+    // Browsers decode 8-bit 4:2:0 only, older phones H.264 up to High@4.1.
+    // HDR and forced formats set -pix_fmt themselves, and a profile already
+    // picked (HDR's main10) stays. Surgical jobs change nothing they needn't.
+    let web = !options.surgical && options.web_optimized.unwrap_or(apple_container);
+    if web && !copy_video && options.playback_target.is_none() {
+        let caps = encoders::profile(encoder).web_caps;
+        let pix = source_video.and_then(|s| s.pix_fmt.as_deref()).unwrap_or("yuv420p");
+        let is_420 = !(pix.contains("422") || pix.contains("444") || pix.starts_with("gbr") || pix.starts_with("rgb"));
+        let eight_bit_420 = is_420 && !pix.contains("10") && !pix.contains("12");
+        // 10-bit 4:2:0 is fine for HEVC / AV1, not for High@4.1
+        if !eight_bit_420 && (!is_420 || !caps.is_empty()) && !extra_args.iter().any(|a| a == "-pix_fmt") {
+            extra_args.extend(["-pix_fmt".to_string(), "yuv420p".to_string()]);
+        }
+        if !extra_args.iter().any(|a| a == "-profile:v") {
+            extra_args.extend(caps.iter().map(|a| a.to_string()));
+        }
+    }
+    // A player profile's own caps instead. Flags HDR already set stay, and
+    // the lint after the encode says whether that still plays
+    if let Some(target) = options.playback_target.filter(|_| !copy_video) {
+        let audio_rate = media.and_then(|m| m.streams.iter().find(|s| s.codec_type == "audio")).and_then(|s| s.sample_rate);
+        let audio_rate = audio_rate.filter(|_| audio != "copy");
+        for pair in playability::encode_args(target, encoder, audio_rate).chunks(2) {
+            if !extra_args.contains(&pair[0]) {
+                extra_args.extend_from_slice(pair);
+            }
+        }
+    }
+
+    // NO HARDWARE DECODE (CPU Reads -> Safe)
+    let ledger = media.filter(|_| options.surgical).map(|m| surgical::plan(&m.streams, encoder, audio));
+    let mut codec_args = match &ledger {
+        Some(ledger) => surgical::stream_args(ledger),
+        None => vec!["-c:v".to_string(), encoder.to_string()],
+    };
+    if let Some(preset) = encoders::profile(encoder).preset.filter(|_| encoder != "copy") {
+        codec_args.push("-preset".to_string());
+        codec_args.push(preset.to_string());
+    }
+    codec_args.extend(extra_args);
+    if options.deterministic && encoder == "libx265" {
+        deterministic::pin_x265(&mut codec_args);
+    }
+    // Copied video keeps its timestamps anyway
+    let keep_timing = options.preserve_vfr && !copy_video;
+    if keep_timing {
+        // Assumed for a build that hasn't been looked at yet (the bundled one has it)
+        let args = vfr::timing_args(detected.caps.is_none_or(|c| c.fps_mode));
+        why.push(explain::timing_passthrough(&args[0]));
+        codec_args.extend(args);
+    }
+    if ledger.is_none() {
+        // Surgical mode carries every tag itself
+        if options.deterministic {
+            codec_args.extend(deterministic::output_args());
+            why.push(Explanation::new(explain::DETERMINISTIC, &[]));
+        } else {
+            codec_args.extend(metadata::video_args(options.metadata, ext, copy_video));
+        }
+        for &fixup in source.fixups {
+            if let (Some(tool), true) = (source.tool, keep_timing && fixup == Fixup::ConstantFrameRate) {
+                why.push(explain::timing_cfr_skipped(tool));
+                continue;
+            }
+            if let (Some(tool), Some(args)) = (source.tool, sourcetool::args(fixup, encoder, ext, copy_video)) {
+                sourcetool::push_args(&mut codec_args, args);
+                why.push(explain::source_fixup(tool, fixup));
+            }
+        }
+        // The index up front, so playback can start while the file downloads
+        if web && apple_container && !codec_args.iter().any(|a| a.contains("faststart")) {
+            sourcetool::push_args(&mut codec_args, vec!["-movflags".to_string(), "+faststart".to_string()]);
+        }
+    }
+
+    Ok(VideoArgs { encoder, audio, codec_args, ledger, rate, two_pass, single_pass_for_short, keep_timing, explanations: why })
+}
+
+// The encode's ffmpeg runs for one full command line (inputs and output
+// options, no output path): itself, or a first pass that only writes the
+// log and a second that reads it.
+pub fn encode_commands(mut command: Vec<String>, staged: &str, passlog: &str, two_pass: bool) -> Vec<Vec<String>> {
+    if !two_pass {
+        command.extend(["-y".to_string(), staged.to_string()]);
+        return vec![command];
+    }
+    let mut first = command.clone();
+    first.extend(["-pass", "1", "-passlogfile", passlog, "-an", "-sn", "-f", "null", "-"].map(String::from));
+    command.extend(["-pass", "2", "-passlogfile", passlog].map(String::from));
+    command.extend(["-y".to_string(), staged.to_string()]);
+    vec![first, command]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::StreamInfo;
+
+    fn strs(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn args_for(options: &VideoOptions, detected: &Detected, ext: &str) -> VideoArgs {
+        build_ffmpeg_args(options, detected, &Source { ext, ..Default::default() }).unwrap()
+    }
+
+    fn caps_with(encoders: &[&str]) -> Capabilities {
+        Capabilities { encoders: encoders.iter().map(|e| e.to_string()).collect(), fps_mode: true, ..Default::default() }
+    }
+
+    #[test]
+    fn the_cpu_gets_x264_with_the_web_caps() {
+        let args = args_for(&VideoOptions::default(), &Detected::default(), "mp4");
+        assert_eq!(args.encoder, "libx264");
+        assert_eq!(args.audio, "aac");
+        assert_eq!(
+            args.codec_args,
+            strs(&["-c:v", "libx264", "-preset", "medium", "-profile:v", "high", "-level:v", "4.1", "-map_metadata", "0", "-movflags", "use_metadata_tags+faststart"])
+        );
+    }
+
+    #[test]
+    fn a_detected_gpu_encoder_takes_the_job_with_its_own_quality_flags() {
+        let options = VideoOptions { auto_gpu: true, crf: Some(23), ..Default::default() };
+        let args = args_for(&options, &Detected { gpu_encoder: Some("h264_nvenc"), ..Default::default() }, "mp4");
+        assert_eq!(args.encoder, "h264_nvenc");
+        assert_eq!(
+            args.codec_args,
+            strs(&["-c:v", "h264_nvenc", "-preset", "p4", "-pix_fmt", "yuv420p", "-cq", "23", "-b:v", "0", "-profile:v", "high", "-level:v", "4.1", "-map_metadata", "0", "-movflags", "use_metadata_tags+faststart"])
+        );
+
+        let qsv = args_for(&options, &Detected { gpu_encoder: Some("h264_qsv"), ..Default::default() }, "mkv");
+        assert_eq!(qsv.codec_args, strs(&["-c:v", "h264_qsv", "-preset", "medium", "-global_quality", "23", "-map_metadata", "0"]));
+    }
+
+    #[test]
+    fn a_gpu_encoder_only_goes_where_the_container_takes_its_codec() {
+        let args = args_for(&VideoOptions { auto_gpu: true, ..Default::default() }, &Detected { gpu_encoder: Some("h264_nvenc"), ..Default::default() }, "webm");
+        assert_eq!((args.encoder, args.audio), ("libvpx-vp9", "libopus"));
+    }
+
+    #[test]
+    fn an_explicit_crf_replaces_the_containers() {
+        let args = args_for(&VideoOptions { crf: Some(60), ..Default::default() }, &Detected::default(), "webm");
+        assert_eq!(args.codec_args, strs(&["-c:v", "libvpx-vp9", "-crf", "60", "-b:v", "0", "-map_metadata", "0"]));
+    }
+
+    #[test]
+    fn hevc_on_the_cpu_needs_the_encoder_in_the_build() {
+        let options = VideoOptions { codec: VideoCodec::Hevc, ..Default::default() };
+        let without = caps_with(&["libx264"]);
+        let error = build_ffmpeg_args(&options, &Detected { caps: Some(&without), ..Default::default() }, &Source { ext: "mp4", ..Default::default() });
+        assert!(error.is_err_and(|e| e.contains("no libx265 encoder")));
+        // Still being detected: the job goes ahead and ffmpeg has the last word
+        let pending = Detected { caps: None, caps_pending: true, ..Default::default() };
+        assert_eq!(args_for(&options, &pending, "mp4").encoder, "libx265");
+
+        let with = caps_with(&["libx264", "libx265"]);
+        let args = args_for(&options, &Detected { caps: Some(&with), ..Default::default() }, "mp4");
+        assert_eq!(args.encoder, "libx265");
+        assert!(args.codec_args.windows(2).any(|w| w == ["-tag:v", "hvc1"]));
+        // No High@4.1 for HEVC
+        assert!(!args.codec_args.contains(&"-profile:v".to_string()));
+    }
+
+    #[test]
+    fn copying_has_no_preset_or_quality() {
+        let options = VideoOptions { copy_only: true, crf: Some(20), ..Default::default() };
+        let args = args_for(&options, &Detected { gpu_encoder: Some("h264_nvenc"), ..Default::default() }, "mkv");
+        assert_eq!((args.encoder, args.audio), ("copy", "copy"));
+        assert_eq!(args.codec_args, strs(&["-c:v", "copy", "-map_metadata", "0", "-map_metadata:s:v", "0:s:v"]));
+    }
+
+    #[test]
+    fn a_dynamic_hdr_plan_swaps_the_encoder_and_its_args() {
+        let plan = HdrPlan {
+            report: crate::hdr::HdrReport { dolby_vision: None, hdr10_plus: true, action: crate::hdr::HdrAction::StrippedToHdr10 },
+            encoder: "libx265",
+            encoder_args: strs(&["-pix_fmt", "yuv420p10le", "-crf", "22"]),
+            strip_side_data: true,
+            warning: None,
+        };
+        let source = Source { ext: "mkv", hdr: Some(&plan), ..Default::default() };
+        let args = build_ffmpeg_args(&VideoOptions::default(), &Detected { gpu_encoder: Some("h264_nvenc"), ..Default::default() }, &source).unwrap();
+        assert_eq!(args.encoder, "libx265");
+        assert_eq!(args.codec_args, strs(&["-c:v", "libx265", "-preset", "medium", "-pix_fmt", "yuv420p10le", "-crf", "22", "-map_metadata", "0"]));
+    }
+
+    #[test]
+    fn preserved_timing_follows_the_build() {
+        let options = VideoOptions { preserve_vfr: true, ..Default::default() };
+        let old = Capabilities { fps_mode: false, ..Default::default() };
+        let args = args_for(&options, &Detected { caps: Some(&old), ..Default::default() }, "mkv");
+        assert!(args.keep_timing);
+        assert!(args.codec_args.windows(2).any(|w| w == ["-vsync", "passthrough"]));
+        let args = args_for(&options, &Detected::default(), "mkv");
+        assert!(args.codec_args.windows(2).any(|w| w == ["-fps_mode", "passthrough"]));
+    }
+
+    #[test]
+    fn ten_bit_sources_are_brought_down_for_the_web() {
+        let media = MediaInfo {
+            has_video: true,
+            streams: vec![StreamInfo { codec_type: "video".to_string(), pix_fmt: Some("yuv420p10le".to_string()), ..Default::default() }],
+            ..Default::default()
+        };
+        let source = Source { ext: "mp4", media: Some(&media), ..Default::default() };
+        let args = build_ffmpeg_args(&VideoOptions::default(), &Detected::default(), &source).unwrap();
+        assert!(args.codec_args.windows(2).any(|w| w == ["-pix_fmt", "yuv420p"]));
+    }
+
+    #[test]
+    fn two_passes_share_the_log_and_only_the_second_writes() {
+        let command = strs(&["-i", "in.mov", "-c:v", "libx264"]);
+        assert_eq!(encode_commands(command.clone(), "out.mp4", "out.mp4.passlog", false), vec![strs(&["-i", "in.mov", "-c:v", "libx264", "-y", "out.mp4"])]);
+        let passes = encode_commands(command, "out.mp4", "out.mp4.passlog", true);
+        assert_eq!(passes.len(), 2);
+        assert_eq!(passes[0][4..], strs(&["-pass", "1", "-passlogfile", "out.mp4.passlog", "-an", "-sn", "-f", "null", "-"]));
+        assert_eq!(passes[1][4..], strs(&["-pass", "2", "-passlogfile", "out.mp4.passlog", "-y", "out.mp4"]));
+    }
+}
@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+
+use crate::probe::MediaInfo;
+
+// IVTC drops one frame in five (29.97 -> 23.976) without changing the length
+pub const DECIMATE_FACTOR: f64 = 0.8;
+// An output this far from the expected length gets a warning
+const TOLERANCE_SECS: f64 = 0.5;
+const TOLERANCE_RATIO: f64 = 0.02;
+// Sub-2s clips come out padded to a whole GOP or audio frame more often
+// than not, which is a lot relative to their length
+const SHORT_TOLERANCE_SECS: f64 = 1.0;
+// Audio and video further apart than this share of the longer one count
+// as mismatched
+const MISMATCH_RATIO: f64 = 0.02;
+
+// ==========================================
+// EXPECTED OUTPUT DURATION
+// ==========================================
+// Some options make the output shorter or longer than the input, or change
+// its frame count, and they stack. Each one declares what it does here,
+// encode_video resolves them in pipeline order once, and both the progress
+// tracker and the final check use the result instead of the input's
+// duration. A new duration-changing option is a new variant.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transform {
+    // Frames kept per source frame, length unchanged (decimate / IVTC)
+    FrameRate(f64),
+    // Audio pushed back by this much; the file ends when the audio does
+    AudioDelay(f64),
+    // Output cut off after this many seconds (`-t`)
+    Limit(f64),
+    // Only this part of the source is read (`-ss` and an end); None runs to EOF
+    Cut(f64, Option<f64>),
+    // duration_policy on mismatched streams: the video's length, then the
+    // output's (see StreamLengths)
+    Streams(f64, f64),
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Expected {
+    pub secs: Option<f64>,
+    pub frames: Option<f64>,
+}
+
+impl Transform {
+    fn apply(self, e: Expected) -> Expected {
+        match self {
+            Transform::Streams(video, target) => Expected { secs: Some(target), frames: e.frames.map(|f| f * target / video) },
+            Transform::FrameRate(factor) => Expected { frames: e.frames.map(|f| f * factor), ..e },
+            Transform::AudioDelay(delay) => Expected { secs: e.secs.map(|s| s + delay.max(0.0)), ..e },
+            Transform::Limit(limit) => match e.secs {
+                Some(secs) if secs > limit => Expected { secs: Some(limit), frames: e.frames.map(|f| f * limit / secs) },
+                Some(_) => e,
+                None => Expected { secs: Some(limit), frames: None },
+            },
+            Transform::Cut(start, end) => match e.secs {
+                Some(secs) => {
+                    let kept = (end.unwrap_or(secs).min(secs) - start).max(0.0);
+                    Expected { secs: Some(kept), frames: e.frames.map(|f| f * kept / secs) }
+                }
+                None => Expected { secs: end.map(|end| end - start), frames: None },
+            },
+        }
+    }
+}
+
+impl Expected {
+    // From the probed source; frames fall back to fps x duration.
+    pub fn source(secs: Option<f64>, frames: Option<u64>, fps: Option<f64>) -> Self {
+        let secs = secs.filter(|s| *s > 0.0);
+        let frames = frames.filter(|n| *n > 0).map(|n| n as f64).or_else(|| Some(fps? * secs?));
+        Expected { secs, frames }
+    }
+
+    pub fn resolve(self, transforms: &[Transform]) -> Self {
+        transforms.iter().fold(self, |e, t| t.apply(e))
+    }
+
+    fn tolerance(expected: f64) -> f64 {
+        if expected < crate::probe::SHORT_INPUT_SECS { SHORT_TOLERANCE_SECS } else { TOLERANCE_SECS.max(expected * TOLERANCE_RATIO) }
+    }
+
+    // A warning when the probed output length is off by more than the tolerance.
+    pub fn check(&self, actual_secs: Option<f64>) -> Option<String> {
+        let (expected, actual) = (self.secs?, actual_secs?);
+        ((actual - expected).abs() > Self::tolerance(expected))
+            .then(|| format!("The output is {:.1}s long, but {:.1}s was expected", actual, expected))
+    }
+
+    // Shorter by more than the tolerance: what an encode that stopped early
+    // leaves. Longer is only ever worth a warning.
+    pub fn truncated(&self, actual_secs: Option<f64>) -> bool {
+        let (Some(expected), Some(actual)) = (self.secs, actual_secs) else { return false };
+        expected - actual > Self::tolerance(expected)
+    }
+}
+
+// ==========================================
+// MISMATCHED STREAM LENGTHS
+// ==========================================
+// Screen recorders that keep the mic running after the capture stops, and
+// edits exported with a tail of music, leave audio and video of different
+// lengths. Players disagree about what to show past the shorter one, so
+// `duration_policy` decides: cut both to the shorter (`-shortest`), stretch
+// the shorter to the longer (silence via apad, the last frame held via
+// tpad), or keep the video's length and trim the audio (the default).
+// Streams within MISMATCH_RATIO of each other are left alone.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DurationPolicy {
+    Shortest,
+    Longest,
+    #[default]
+    Video,
+}
+
+impl DurationPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DurationPolicy::Shortest => "shortest",
+            DurationPolicy::Longest => "longest",
+            DurationPolicy::Video => "video",
+        }
+    }
+}
+
+// Of the first video and audio streams, audio including any A/V delay
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct StreamLengths {
+    pub video_secs: f64,
+    pub audio_secs: f64,
+}
+
+// How the job evens them out
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamPlan {
+    pub audio_filter: Option<String>,
+    // Appended to the video filter graph
+    pub video_filter: Option<String>,
+    pub shortest: bool,
+}
+
+impl StreamLengths {
+    // None unless both streams say how long they are and they're mismatched.
+    pub fn mismatched(media: &MediaInfo, audio_delay_secs: f64) -> Option<Self> {
+        let video = media.streams.iter().find(|s| s.codec_type == "video" && !s.attached_pic)?.duration?;
+        let audio = media.streams.iter().find(|s| s.codec_type == "audio")?.duration? + audio_delay_secs.max(0.0);
+        let lengths = StreamLengths { video_secs: video, audio_secs: audio };
+        ((video - audio).abs() > video.max(audio) * MISMATCH_RATIO).then_some(lengths)
+    }
+
+    // Length of the output under the policy, from the source's start
+    pub fn target(&self, policy: DurationPolicy) -> f64 {
+        match policy {
+            DurationPolicy::Shortest => self.video_secs.min(self.audio_secs),
+            DurationPolicy::Longest => self.video_secs.max(self.audio_secs),
+            DurationPolicy::Video => self.video_secs,
+        }
+    }
+
+    pub fn warning(&self, policy: DurationPolicy) -> String {
+        format!(
+            "The source's video is {:.1}s long and its audio {:.1}s; duration_policy \"{}\" made the output {:.1}s",
+            self.video_secs,
+            self.audio_secs,
+            policy.as_str(),
+            self.target(policy)
+        )
+    }
+
+    // Pure. `start` is where a cut begins (timestamps restart there);
+    // copied streams can't be filtered, so the muxer cuts them instead.
+    pub fn plan(&self, policy: DurationPolicy, start: f64, copy_video: bool, copy_audio: bool) -> StreamPlan {
+        let audio_longer = self.audio_secs > self.video_secs;
+        match (policy, audio_longer) {
+            (DurationPolicy::Shortest, _) => StreamPlan { shortest: true, ..Default::default() },
+            (DurationPolicy::Video, true) if copy_audio => StreamPlan { shortest: true, ..Default::default() },
+            (DurationPolicy::Video, true) => StreamPlan { audio_filter: Some(format!("atrim=end={:.3}", (self.video_secs - start).max(0.0))), ..Default::default() },
+            // Audio that ends first just ends there
+            (DurationPolicy::Video, false) => StreamPlan::default(),
+            (DurationPolicy::Longest, true) if copy_video => StreamPlan::default(),
+            (DurationPolicy::Longest, true) => StreamPlan {
+                video_filter: Some(format!("tpad=stop_mode=clone:stop_duration={:.3}", self.audio_secs - self.video_secs)),
+                ..Default::default()
+            },
+            (DurationPolicy::Longest, false) if copy_audio => StreamPlan::default(),
+            // Silence without end, cut where the video stops
+            (DurationPolicy::Longest, false) => StreamPlan { audio_filter: Some("apad".to_string()), shortest: true, ..Default::default() },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::StreamInfo;
+
+    fn stream(kind: &str, duration: f64) -> StreamInfo {
+        StreamInfo { codec_type: kind.to_string(), duration: Some(duration), ..Default::default() }
+    }
+
+    #[test]
+    fn frames_fall_back_to_fps_times_length() {
+        assert_eq!(Expected::source(Some(10.0), None, Some(25.0)), Expected { secs: Some(10.0), frames: Some(250.0) });
+        assert_eq!(Expected::source(Some(10.0), Some(240), Some(25.0)).frames, Some(240.0));
+        assert_eq!(Expected::source(Some(0.0), Some(0), Some(25.0)), Expected::default());
+    }
+
+    #[test]
+    fn transforms_stack_in_pipeline_order() {
+        let source = Expected { secs: Some(600.0), frames: Some(18000.0) };
+        // Read 60-360s, IVTC, then cut at 100s
+        let expected = source.resolve(&[Transform::Cut(60.0, Some(360.0)), Transform::FrameRate(DECIMATE_FACTOR), Transform::Limit(100.0)]);
+        assert_eq!(expected.secs, Some(100.0));
+        assert!((expected.frames.unwrap() - 2400.0).abs() < 1e-6);
+
+        let delayed = source.resolve(&[Transform::AudioDelay(1.5), Transform::AudioDelay(-3.0)]);
+        assert_eq!(delayed, Expected { secs: Some(601.5), frames: Some(18000.0) });
+    }
+
+    #[test]
+    fn cuts_past_the_end_keep_what_is_there() {
+        let source = Expected { secs: Some(100.0), frames: Some(1000.0) };
+        assert_eq!(source.resolve(&[Transform::Cut(90.0, Some(200.0))]), Expected { secs: Some(10.0), frames: Some(100.0) });
+        assert_eq!(source.resolve(&[Transform::Cut(150.0, None)]).secs, Some(0.0));
+        // Unknown length: only the cut itself says anything
+        let unknown = Expected::default();
+        assert_eq!(unknown.resolve(&[Transform::Cut(10.0, Some(40.0))]).secs, Some(30.0));
+        assert_eq!(unknown.resolve(&[Transform::Limit(5.0)]).secs, Some(5.0));
+    }
+
+    #[test]
+    fn lengths_are_checked_with_a_relative_tolerance() {
+        let long = Expected { secs: Some(600.0), frames: None };
+        // 2% of 600s is 12s
+        assert_eq!(long.check(Some(610.0)), None);
+        assert!(long.check(Some(580.0)).unwrap().contains("580.0s long, but 600.0s"));
+        assert!(long.truncated(Some(580.0)));
+        assert!(!long.truncated(Some(620.0)));
+
+        let clip = Expected { secs: Some(1.0), frames: None };
+        assert_eq!(clip.check(Some(1.9)), None);
+        assert!(!clip.truncated(None));
+    }
+
+    #[test]
+    fn mismatched_streams_are_evened_out_by_policy() {
+        let media = MediaInfo { streams: vec![stream("video", 60.0), stream("audio", 65.0)], ..Default::default() };
+        let lengths = StreamLengths::mismatched(&media, 0.0).unwrap();
+        assert_eq!(lengths.target(DurationPolicy::Shortest), 60.0);
+        assert_eq!(lengths.target(DurationPolicy::Longest), 65.0);
+        assert_eq!(lengths.plan(DurationPolicy::Video, 10.0, false, false).audio_filter.as_deref(), Some("atrim=end=50.000"));
+        assert!(lengths.plan(DurationPolicy::Video, 0.0, false, true).shortest);
+        assert_eq!(lengths.plan(DurationPolicy::Longest, 0.0, false, false).video_filter.as_deref(), Some("tpad=stop_mode=clone:stop_duration=5.000"));
+        assert_eq!(lengths.plan(DurationPolicy::Longest, 0.0, true, false), StreamPlan::default());
+
+        let close = MediaInfo { streams: vec![stream("video", 60.0), stream("audio", 60.5)], ..Default::default() };
+        assert_eq!(StreamLengths::mismatched(&close, 0.0), None);
+        // The A/V delay counts towards the audio
+        assert!(StreamLengths::mismatched(&close, 3.0).is_some());
+    }
+}
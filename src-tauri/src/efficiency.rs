@@ -0,0 +1,305 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::AppHandle;
+use crate::probe::{self, MediaInfo};
+use crate::remux::JobMode;
+use crate::request::VideoCompressRequest;
+use crate::support;
+use crate::VideoMode;
+
+// Savings a lean source is still expected to give, for the message
+pub const EXPECTED_SAVINGS_PERCENT: u32 = 5;
+
+// ==========================================
+// ALREADY-EFFICIENT INPUTS
+// ==========================================
+// Re-encoding a lean HEVC file to H.264 makes it bigger and worse. Before a
+// plain re-encode, the source's codec and bits per pixel (video bitrate /
+// (width * height * fps)) are compared with the target's:
+//   - a newer codec generation than the target's is flagged outright;
+//   - otherwise the bpp, converted to the target codec's terms with the
+//     efficiency factors below, is flagged under its resolution tier's
+//     threshold (or efficiency_threshold_bpp when set).
+// Flagged jobs return AlreadyOptimized straight away with skip_optimized,
+// and otherwise go on with an `efficiency-advisory` event and a warning.
+// Requests that ask for a change (size or bitrate targets, downscaling,
+// filters, a playback profile) aren't checked: there the re-encode is the
+// point.
+
+// Codec, generation (bigger = newer), efficiency relative to H.264 at the
+// same quality. Intra-only and mezzanine codecs (ProRes, DNxHD, MJPEG,
+// lossless) aren't here: those always compress.
+const GENERATIONS: &[(&str, u8, f64)] = &[
+    ("mpeg1video", 1, 0.4),
+    ("mpeg2video", 1, 0.5),
+    ("h263", 2, 0.6),
+    ("msmpeg4v3", 2, 0.6),
+    ("wmv2", 2, 0.6),
+    ("mpeg4", 2, 0.7),
+    ("theora", 2, 0.7),
+    ("wmv3", 2, 0.75),
+    ("vc1", 2, 0.8),
+    ("vp8", 3, 0.9),
+    ("h264", 3, 1.0),
+    ("vp9", 4, 1.4),
+    ("hevc", 4, 1.5),
+    ("av1", 5, 1.8),
+    ("vvc", 5, 2.0),
+];
+
+// (pixels per frame up to, H.264 bpp under which a medium re-encode
+// rarely saves EXPECTED_SAVINGS_PERCENT). Bigger pictures need fewer bits
+// per pixel for the same look.
+const BPP_TIERS: &[(u64, f64)] = &[
+    (640 * 480, 0.10),
+    (1280 * 720, 0.075),
+    (1920 * 1080, 0.055),
+    (2560 * 1440, 0.045),
+    (u64::MAX, 0.035),
+];
+
+#[derive(Serialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Efficiency {
+    pub codec: String,
+    pub target_codec: String,
+    // None when the source doesn't say its bitrate (a newer codec is
+    // flagged without it)
+    pub bits_per_pixel: Option<f64>,
+    // The threshold it was held against, in the target codec's terms
+    pub threshold_bpp: f64,
+    pub newer_codec: bool,
+    // For the UI, as is
+    pub message: String,
+}
+
+// Payload of `efficiency-advisory`.
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct EfficiencyAdvisory {
+    pub job_id: Option<u64>,
+    pub input: String,
+    #[serde(flatten)]
+    pub efficiency: Efficiency,
+}
+
+fn generation(codec: &str) -> Option<(u8, f64)> {
+    GENERATIONS.iter().find(|(name, _, _)| *name == codec).map(|(_, g, e)| (*g, *e))
+}
+
+pub fn tier_threshold(width: u32, height: u32) -> f64 {
+    let pixels = width as u64 * height as u64;
+    BPP_TIERS.iter().find(|(max, _)| pixels <= *max).map_or(0.035, |(_, bpp)| *bpp)
+}
+
+// Pure: the video stream's bits per pixel per frame. Without a stream
+// bitrate, the container's minus the audio's.
+pub fn bits_per_pixel(media: &MediaInfo) -> Option<f64> {
+    let video = media.streams.iter().find(|s| s.codec_type == "video" && !s.attached_pic)?;
+    let audio: u64 = media.streams.iter().filter(|s| s.codec_type == "audio").filter_map(|s| s.bit_rate).sum();
+    let bitrate = video.bit_rate.or_else(|| media.bit_rate.map(|b| b.saturating_sub(audio)).filter(|b| *b > 0))?;
+    let (w, h, fps) = (video.width.or(media.width)?, video.height.or(media.height)?, media.fps?);
+    let pixels_per_sec = w as f64 * h as f64 * fps;
+    (pixels_per_sec > 0.0).then(|| bitrate as f64 / pixels_per_sec)
+}
+
+// Pure: Some when re-encoding `media` to `target_codec` is unlikely to pay.
+pub fn assess(media: &MediaInfo, target_codec: &str, threshold_override: Option<f64>) -> Option<Efficiency> {
+    let video = media.streams.iter().find(|s| s.codec_type == "video" && !s.attached_pic)?;
+    let codec = video.codec_name.clone()?;
+    let (source_gen, source_eff) = generation(&codec)?;
+    let (target_gen, target_eff) = generation(target_codec)?;
+    let bpp = bits_per_pixel(media);
+    let threshold = match threshold_override {
+        Some(t) => t,
+        None => tier_threshold(video.width.or(media.width)?, video.height.or(media.height)?),
+    };
+    let newer_codec = source_gen > target_gen;
+    // What the source's rate would be worth in the target codec
+    let equivalent = bpp.map(|b| b * source_eff / target_eff);
+    let lean = equivalent.is_some_and(|e| e < threshold);
+    if !newer_codec && !lean {
+        return None;
+    }
+    let rate = bpp.map(|b| format!(" at {:.3} bits per pixel", b)).unwrap_or_default();
+    let message = match newer_codec {
+        true => format!(
+            "This file is already {}{}, a newer codec than the {} it would become: the output would likely be bigger or look worse",
+            codec, rate, target_codec
+        ),
+        false => format!("This file is already well compressed ({}{}): expected savings < {}%", codec, rate, EXPECTED_SAVINGS_PERCENT),
+    };
+    Some(Efficiency { codec, target_codec: target_codec.to_string(), bits_per_pixel: bpp, threshold_bpp: threshold, newer_codec, message })
+}
+
+// Whether the request is a plain re-encode the check applies to.
+fn applies(request: &VideoCompressRequest) -> bool {
+    let o = &request.options;
+    let rate_target = o.rate.target_bitrate_kbps.is_some() || o.rate.target_size_mb.is_some() || o.rate.max_filesize_mb.is_some() || o.fit_size_mb.is_some();
+    let reencode = o.video_mode == VideoMode::Reencode && !o.copy_only && o.mode != JobMode::Remux;
+    reencode && !rate_target && o.first_picture_option().is_none() && o.playback_target.is_none() && !o.surgical && !o.salvage
+}
+
+// The codec the output would get (see support::default_video_encoder).
+fn target_codec(request: &VideoCompressRequest) -> Option<&'static str> {
+    let ext = Path::new(&request.output).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    support::default_video_encoder(&ext, false, request.options.codec).and_then(support::codec_of)
+}
+
+pub async fn check(app: &AppHandle, request: &VideoCompressRequest) -> Option<Efficiency> {
+    if !applies(request) {
+        return None;
+    }
+    let target = target_codec(request)?;
+    let media = probe::probe(app, &request.input).await.ok()?;
+    let found = assess(&media, target, request.options.efficiency_threshold_bpp)?;
+    println!("🪶 {}: {}", request.input, found.message);
+    Some(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{run_video, video, Harness, ENCODE};
+    use crate::probe::tests::info;
+
+    // A video stream with `extra` fields beside 128 kbit/s of aac, in a
+    // container whose bit_rate is `container_rate`
+    fn fixture(codec: &str, width: u32, height: u32, fps: &str, extra: &str, container_rate: Option<u64>) -> String {
+        let container = container_rate.map(|r| format!(r#", "bit_rate": "{}""#, r)).unwrap_or_default();
+        format!(
+            r#"{{ "streams": [
+                {{ "index": 0, "codec_type": "video", "codec_name": "{}", "width": {}, "height": {}, "avg_frame_rate": "{}"{} }},
+                {{ "index": 1, "codec_type": "audio", "codec_name": "aac", "sample_rate": "48000", "channels": 2, "bit_rate": "128000" }}
+            ], "format": {{ "duration": "60.000000"{} }} }}"#,
+            codec, width, height, fps, extra, container
+        )
+    }
+
+    fn rated(codec: &str, width: u32, height: u32, fps: &str, video_rate: u64) -> String {
+        fixture(codec, width, height, fps, &format!(r#", "bit_rate": "{}""#, video_rate), None)
+    }
+
+    // A phone's 1080p30 HEVC at 4 Mbit/s
+    fn phone() -> String {
+        rated("hevc", 1920, 1080, "30/1", 4_000_000)
+    }
+
+    #[test]
+    fn bits_per_pixel_come_from_the_video_rate_or_the_container_less_the_audio() {
+        let bpp = |json: &str| bits_per_pixel(&info(json)).map(|b| (b * 10_000.0).round() / 10_000.0);
+        assert_eq!(bpp(&phone()), Some(0.0643));
+        assert_eq!(bpp(&fixture("h264", 1280, 720, "30/1", "", Some(1_628_000))), Some(0.0543));
+        assert_eq!(bpp(&fixture("h264", 1280, 720, "30/1", "", None)), None);
+        assert_eq!(bpp(&rated("h264", 1280, 720, "0/0", 1_500_000)), None);
+    }
+
+    #[test]
+    fn resolution_tiers_end_at_their_pixel_counts() {
+        assert_eq!(tier_threshold(640, 480), 0.10);
+        assert_eq!(tier_threshold(641, 480), 0.075);
+        assert_eq!(tier_threshold(1280, 720), 0.075);
+        assert_eq!(tier_threshold(1920, 1080), 0.055);
+        assert_eq!(tier_threshold(2560, 1440), 0.045);
+        assert_eq!(tier_threshold(3840, 2160), 0.035);
+        // Portrait is the same pixels
+        assert_eq!(tier_threshold(1080, 1920), 0.055);
+    }
+
+    #[test]
+    fn a_newer_codec_than_the_target_is_flagged_whatever_its_rate() {
+        let found = assess(&info(&phone()), "h264", None).unwrap();
+        assert!(found.newer_codec);
+        assert_eq!(found.threshold_bpp, 0.055);
+        assert!(found.message.starts_with("This file is already hevc at 0.064 bits per pixel, a newer codec than the h264"), "{}", found.message);
+        // AV1 into HEVC, and without a bitrate to go by
+        let av1 = assess(&info(&fixture("av1", 3840, 2160, "60/1", "", None)), "hevc", None).unwrap();
+        assert!(av1.newer_codec && av1.bits_per_pixel.is_none());
+        assert!(av1.message.starts_with("This file is already av1, a newer codec"), "{}", av1.message);
+        // A bloated HEVC camera file still is, into H.264
+        assert!(assess(&info(&rated("hevc", 3840, 2160, "30/1", 100_000_000)), "h264", None).is_some_and(|f| f.newer_codec));
+    }
+
+    #[test]
+    fn the_same_generation_is_flagged_only_under_its_tiers_threshold() {
+        // The phone's 0.064 is above 1080p's 0.055 in HEVC's own terms
+        assert_eq!(assess(&info(&phone()), "hevc", None), None);
+        // Half the rate isn't
+        let lean = assess(&info(&rated("hevc", 1920, 1080, "30/1", 2_000_000)), "hevc", None).unwrap();
+        assert!(!lean.newer_codec);
+        assert_eq!(lean.message, "This file is already well compressed (hevc at 0.032 bits per pixel): expected savings < 5%");
+        // A 720p30 H.264 screen capture at 1.5 Mbit/s, against 720p's 0.075
+        assert!(assess(&info(&rated("h264", 1280, 720, "30/1", 1_500_000)), "h264", None).is_some());
+        // A camera's 1080p H.264 at 20 Mbit/s has plenty to give
+        assert_eq!(assess(&info(&rated("h264", 1920, 1080, "30/1", 20_000_000)), "h264", None), None);
+        // No bitrate, no verdict
+        assert_eq!(assess(&info(&fixture("h264", 1280, 720, "30/1", "", None)), "h264", None), None);
+    }
+
+    #[test]
+    fn older_codecs_are_weighed_in_the_targets_terms() {
+        // A DVD's MPEG-2 at 5 Mbit/s is worth half that in H.264: still plenty
+        assert_eq!(assess(&info(&rated("mpeg2video", 720, 480, "30000/1001", 5_000_000)), "h264", None), None);
+        // H.264 at 0.064 is over 1080p's 0.055, but only 0.043 in HEVC's terms
+        let h264 = rated("h264", 1920, 1080, "30/1", 4_000_000);
+        assert_eq!(assess(&info(&h264), "h264", None), None);
+        assert!(assess(&info(&h264), "hevc", None).is_some_and(|f| !f.newer_codec));
+    }
+
+    #[test]
+    fn codecs_outside_the_table_always_compress() {
+        assert_eq!(assess(&info(&rated("prores", 1920, 1080, "30/1", 1_000_000)), "h264", None), None);
+        assert_eq!(assess(&info(&rated("mjpeg", 1920, 1080, "30/1", 1_000_000)), "h264", None), None);
+        assert_eq!(assess(&info(&phone()), "gif", None), None);
+        // Cover art isn't the video
+        let cover = r#"{ "streams": [{ "index": 0, "codec_type": "video", "codec_name": "hevc", "width": 600, "height": 600, "disposition": { "attached_pic": 1 } }] }"#;
+        assert_eq!(assess(&info(cover), "h264", None), None);
+    }
+
+    #[test]
+    fn a_threshold_override_replaces_the_tier() {
+        let capture = rated("h264", 1920, 1080, "30/1", 20_000_000);
+        let found = assess(&info(&capture), "h264", Some(0.5)).unwrap();
+        assert_eq!(found.threshold_bpp, 0.5);
+        assert_eq!(assess(&info(&rated("hevc", 1920, 1080, "30/1", 2_000_000)), "hevc", Some(0.01)), None);
+    }
+
+    fn lean_job(name: &str, skip_optimized: bool) -> (Harness, crate::VideoJobResult) {
+        let h = Harness::new(name, &format!(r#"{{ "ffprobe": {}, "runs": {} }}"#, phone(), ENCODE));
+        let mut request = video(&h, "out.mp4");
+        request.options.skip_optimized = skip_optimized;
+        let result = run_video(&h, request).unwrap();
+        (h, result)
+    }
+
+    #[test]
+    fn skip_optimized_returns_without_encoding() {
+        let (h, result) = lean_job("efficiency-skip", true);
+        let found = result.already_optimized.unwrap();
+        assert!(found.newer_codec);
+        assert!(result.warnings[0].ends_with("so it wasn't re-encoded (skip_optimized)"));
+        assert_eq!(result.encoder, "none");
+        assert!(!h.runs().iter().any(|r| r.iter().any(|a| a == "-i")));
+        assert_eq!(h.files(), ["clip.mp4"]);
+    }
+
+    #[test]
+    fn otherwise_the_job_encodes_with_an_advisory() {
+        let (h, result) = lean_job("efficiency-advise", false);
+        assert!(result.already_optimized.is_none());
+        let advisory = h.emitted("efficiency-advisory");
+        assert_eq!(advisory.len(), 1);
+        assert_eq!(advisory[0]["codec"], "hevc");
+        assert_eq!(advisory[0]["input"], h.file("clip.mp4"));
+        assert!(result.warnings.contains(&advisory[0]["message"].as_str().unwrap().to_string()));
+        assert!(h.runs().iter().any(|r| r.iter().any(|a| a == "libx264")));
+
+        // A size target is a reason to re-encode: no advice
+        let h = Harness::new("efficiency-target", &format!(r#"{{ "ffprobe": {}, "runs": {} }}"#, phone(), ENCODE));
+        let mut request = video(&h, "out.mp4");
+        request.options.rate.target_bitrate_kbps = Some(1000);
+        request.options.skip_optimized = true;
+        assert!(run_video(&h, request).unwrap().already_optimized.is_none());
+        assert!(h.emitted("efficiency-advisory").is_empty());
+    }
+}
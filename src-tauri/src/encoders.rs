@@ -0,0 +1,109 @@
+// ==========================================
+// ENCODER PROFILES
+// ==========================================
+// How each video encoder we drive takes its preset and quality. Encoders
+// of one family share a lot but not everything (NVENC's AV1 has the same
+// -cq as its H.264, x265 the same -crf as x264 but other levels), so it's
+// one row per encoder rather than rules by name. Anything not listed is
+// treated like libx264.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QualityFlag {
+    // -crf q
+    Crf,
+    // -cq q (NVENC constant quality)
+    Cq,
+    // -global_quality q (Quick Sync)
+    GlobalQuality,
+    // -rc cqp -qp_i q -qp_p q (AMF)
+    Cqp,
+    // -q:v 1-100, higher is better (VideoToolbox)
+    Scale100,
+    // -q:v 0-10, higher is better (Theora)
+    Scale10,
+    // -q:v 2-31, lower is better (WMV / MPEG-4 Part 2): a fifth of the CRF
+    Qscale,
+}
+
+pub struct EncoderProfile {
+    pub encoder: &'static str,
+    // Codec it writes, as in support::CODECS
+    pub codec: &'static str,
+    // None = the encoder has no -preset
+    pub preset: Option<&'static str>,
+    pub quality: QualityFlag,
+    // Top of its CRF scale
+    pub crf_max: u32,
+    // CRF for the low / medium / high levels
+    pub levels: (u32, u32, u32),
+    // `-b:v 0` next to the quality flag, or the encoder caps the bitrate anyway
+    pub zero_bitrate: bool,
+    pub two_pass: bool,
+    // Forced pixel format
+    pub pix_fmt: Option<&'static str>,
+    // Profile / level caps for web_optimized output (High@4.1, what browsers
+    // and older phones decode); empty = none for this encoder
+    pub web_caps: &'static [&'static str],
+}
+
+const X264_LEVELS: (u32, u32, u32) = (28, 23, 18);
+// H.264 High@4.1; Quick Sync takes the level as a number, the others by name
+const H264_WEB: &[&str] = &["-profile:v", "high", "-level:v", "4.1"];
+const QSV_WEB: &[&str] = &["-profile:v", "high", "-level:v", "41"];
+
+pub const PROFILES: &[EncoderProfile] = &[
+    EncoderProfile { encoder: "libx264", codec: "h264", preset: Some("medium"), quality: QualityFlag::Crf, crf_max: 51, levels: X264_LEVELS, zero_bitrate: false, two_pass: true, pix_fmt: None, web_caps: H264_WEB },
+    // PIXEL FIX (Prevents crash on 10-bit videos)
+    EncoderProfile { encoder: "h264_nvenc", codec: "h264", preset: Some("p4"), quality: QualityFlag::Cq, crf_max: 51, levels: X264_LEVELS, zero_bitrate: true, two_pass: false, pix_fmt: Some("yuv420p"), web_caps: H264_WEB },
+    EncoderProfile { encoder: "h264_qsv", codec: "h264", preset: Some("medium"), quality: QualityFlag::GlobalQuality, crf_max: 51, levels: X264_LEVELS, zero_bitrate: false, two_pass: false, pix_fmt: None, web_caps: QSV_WEB },
+    EncoderProfile { encoder: "h264_amf", codec: "h264", preset: None, quality: QualityFlag::Cqp, crf_max: 51, levels: X264_LEVELS, zero_bitrate: false, two_pass: false, pix_fmt: None, web_caps: H264_WEB },
+    EncoderProfile { encoder: "h264_videotoolbox", codec: "h264", preset: None, quality: QualityFlag::Scale100, crf_max: 51, levels: X264_LEVELS, zero_bitrate: false, two_pass: false, pix_fmt: None, web_caps: H264_WEB },
+    EncoderProfile { encoder: "libx265", codec: "hevc", preset: Some("medium"), quality: QualityFlag::Crf, crf_max: 51, levels: (30, 26, 22), zero_bitrate: false, two_pass: false, pix_fmt: None, web_caps: &[] },
+    EncoderProfile { encoder: "hevc_nvenc", codec: "hevc", preset: Some("p4"), quality: QualityFlag::Cq, crf_max: 51, levels: (30, 26, 22), zero_bitrate: true, two_pass: false, pix_fmt: None, web_caps: &[] },
+    EncoderProfile { encoder: "hevc_qsv", codec: "hevc", preset: Some("medium"), quality: QualityFlag::GlobalQuality, crf_max: 51, levels: (30, 26, 22), zero_bitrate: false, two_pass: false, pix_fmt: None, web_caps: &[] },
+    EncoderProfile { encoder: "hevc_amf", codec: "hevc", preset: None, quality: QualityFlag::Cqp, crf_max: 51, levels: (30, 26, 22), zero_bitrate: false, two_pass: false, pix_fmt: None, web_caps: &[] },
+    EncoderProfile { encoder: "hevc_videotoolbox", codec: "hevc", preset: None, quality: QualityFlag::Scale100, crf_max: 51, levels: (30, 26, 22), zero_bitrate: false, two_pass: false, pix_fmt: None, web_caps: &[] },
+    // SVT-AV1 presets run 0 (slowest) to 13; 8 is about x264's medium
+    EncoderProfile { encoder: "libsvtav1", codec: "av1", preset: Some("8"), quality: QualityFlag::Crf, crf_max: 63, levels: (40, 34, 27), zero_bitrate: false, two_pass: false, pix_fmt: None, web_caps: &[] },
+    EncoderProfile { encoder: "av1_nvenc", codec: "av1", preset: Some("p4"), quality: QualityFlag::Cq, crf_max: 51, levels: (36, 30, 24), zero_bitrate: true, two_pass: false, pix_fmt: None, web_caps: &[] },
+    EncoderProfile { encoder: "av1_qsv", codec: "av1", preset: Some("medium"), quality: QualityFlag::GlobalQuality, crf_max: 51, levels: (36, 30, 24), zero_bitrate: false, two_pass: false, pix_fmt: None, web_caps: &[] },
+    EncoderProfile { encoder: "libvpx-vp9", codec: "vp9", preset: None, quality: QualityFlag::Crf, crf_max: 63, levels: (40, 33, 26), zero_bitrate: true, two_pass: true, pix_fmt: None, web_caps: &[] },
+    EncoderProfile { encoder: "libtheora", codec: "theora", preset: None, quality: QualityFlag::Scale10, crf_max: 51, levels: X264_LEVELS, zero_bitrate: false, two_pass: false, pix_fmt: None, web_caps: &[] },
+    EncoderProfile { encoder: "wmv2", codec: "wmv2", preset: None, quality: QualityFlag::Qscale, crf_max: 51, levels: X264_LEVELS, zero_bitrate: false, two_pass: false, pix_fmt: Some("yuv420p"), web_caps: &[] },
+];
+
+const FALLBACK: EncoderProfile = EncoderProfile {
+    encoder: "",
+    codec: "",
+    preset: Some("medium"),
+    quality: QualityFlag::Crf,
+    crf_max: 51,
+    levels: X264_LEVELS,
+    zero_bitrate: false,
+    two_pass: false,
+    pix_fmt: None,
+    web_caps: &[],
+};
+
+pub fn profile(encoder: &str) -> &'static EncoderProfile {
+    PROFILES.iter().find(|p| p.encoder == encoder).unwrap_or(&FALLBACK)
+}
+
+// The quality flags for one encoder.
+pub fn quality_args(encoder: &str, crf: u32) -> Vec<String> {
+    let profile = profile(encoder);
+    let max = profile.crf_max;
+    let mut args: Vec<String> = match profile.quality {
+        QualityFlag::Crf => vec!["-crf".to_string(), crf.to_string()],
+        QualityFlag::Cq => vec!["-cq".to_string(), crf.max(1).to_string()],
+        QualityFlag::GlobalQuality => vec!["-global_quality".to_string(), crf.max(1).to_string()],
+        QualityFlag::Cqp => vec!["-rc".to_string(), "cqp".to_string(), "-qp_i".to_string(), crf.to_string(), "-qp_p".to_string(), crf.to_string()],
+        QualityFlag::Scale100 => vec!["-q:v".to_string(), (100 - crf.min(max) * 99 / max).to_string()],
+        QualityFlag::Scale10 => vec!["-q:v".to_string(), (10 - crf.min(max) * 10 / max).to_string()],
+        QualityFlag::Qscale => vec!["-q:v".to_string(), (crf / 5).clamp(2, 31).to_string()],
+    };
+    if profile.zero_bitrate {
+        args.extend(["-b:v".to_string(), "0".to_string()]);
+    }
+    args
+}
@@ -0,0 +1,236 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::cancel;
+use crate::ffmpeg;
+use crate::hardware;
+use crate::macos;
+use crate::outputs;
+use crate::support;
+
+// stderr lines kept for an error's "show details"
+pub const DETAIL_LINES: usize = 50;
+// Failed runs whose details are kept until a command picks them up
+const MAX_KEPT: usize = 32;
+
+// stderr lines that say what went wrong, wherever in the run they were.
+// The first one seen explains a failure better than the last line does
+// (usually a progress line or "Conversion failed!").
+pub const TELLING_PATTERNS: &[&str] = &[
+    "No space left on device",
+    "not enough space on the disk",
+    "Disk quota exceeded",
+    "Permission denied",
+    "Access is denied",
+    "Operation not permitted",
+    "Invalid data found when processing input",
+    "moov atom not found",
+    "Unknown encoder",
+    "Encoder not found",
+    "No such file or directory",
+];
+
+const DISK_FULL: &[&str] = &["No space left on device", "not enough space on the disk", "Disk quota exceeded"];
+const PERMISSION: &[&str] = &["Permission denied", "Access is denied", "Operation not permitted"];
+const UNSUPPORTED: &[&str] = &["Invalid data found when processing input", "moov atom not found", " is empty (0 bytes)", " but is actually "];
+
+// ==========================================
+// TYPED COMMAND ERRORS
+// ==========================================
+// Inside the app errors stay strings with a known start (MEMORY_LIMIT_ERROR,
+// WORK_VOLUME_FULL, ...), which is what history and the batch report keep.
+// The compress commands turn them into one of these on the way out, so the
+// frontend can show something to do about it instead of a stderr line.
+// `message` is always the readable error.
+#[derive(Serialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobError {
+    InputNotFound { path: String, message: String },
+    UnsupportedFormat { message: String },
+    EncoderNotAvailable { encoder: String, message: String },
+    DiskFull { message: String },
+    PermissionDenied { message: String },
+    // macOS privacy protection (TCC) kept us out of `path`'s folder;
+    // `guidance` says where to allow it (see open_privacy_settings)
+    MacPermissionDenied { path: String, guidance: String, message: String },
+    Cancelled { message: String },
+    // ffmpeg exited non-zero; `detail` is the end of its stderr
+    FfmpegError { code: Option<i32>, message: String, detail: Vec<String> },
+    Other { message: String },
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            JobError::InputNotFound { message, .. }
+            | JobError::UnsupportedFormat { message }
+            | JobError::EncoderNotAvailable { message, .. }
+            | JobError::DiskFull { message }
+            | JobError::PermissionDenied { message }
+            | JobError::MacPermissionDenied { message, .. }
+            | JobError::Cancelled { message }
+            | JobError::FfmpegError { message, .. }
+            | JobError::Other { message } => message,
+        };
+        f.write_str(message)
+    }
+}
+
+// "Error (Code 1): ..." from ffmpeg::run_with_progress
+fn exit_code(error: &str) -> Option<i32> {
+    let rest = error.split("(Code ").nth(1)?;
+    rest.split(|c: char| !c.is_ascii_digit() && c != '-').next()?.parse().ok()
+}
+
+// "Unknown encoder 'libsvtav1'" -> libsvtav1
+fn encoder_name(error: &str) -> Option<String> {
+    let rest = error.split("encoder '").nth(1)?;
+    Some(rest.split('\'').next()?.to_string())
+}
+
+// Pure: the error string (+ the run's stderr tail) -> kind.
+pub fn classify(error: &str, detail: Vec<String>) -> JobError {
+    let message = error.to_string();
+    if error == cancel::CANCELLED {
+        return JobError::Cancelled { message };
+    }
+    if let Some(path) = error.strip_prefix("Input file not found: ") {
+        return JobError::InputNotFound { path: path.to_string(), message };
+    }
+    if error.starts_with(outputs::WORK_VOLUME_FULL) || error.starts_with(outputs::OUTPUT_VOLUME_FULL) || DISK_FULL.iter().any(|p| error.contains(p)) {
+        return JobError::DiskFull { message };
+    }
+    if cfg!(target_os = "macos") {
+        if let Some(path) = macos::tcc_denied_path(std::iter::once(error).chain(detail.iter().map(String::as_str))) {
+            return JobError::MacPermissionDenied { guidance: macos::guidance(&path), path, message };
+        }
+    }
+    if PERMISSION.iter().any(|p| error.contains(p)) {
+        return JobError::PermissionDenied { message };
+    }
+    if error.starts_with(hardware::ENCODER_NOT_AVAILABLE) || error.contains("Unknown encoder") || error.contains("Encoder not found") {
+        return JobError::EncoderNotAvailable { encoder: encoder_name(error).unwrap_or_default(), message };
+    }
+    if error.starts_with(support::CODEC_MISMATCH) || UNSUPPORTED.iter().any(|p| error.contains(p)) {
+        return JobError::UnsupportedFormat { message };
+    }
+    match exit_code(error) {
+        Some(code) => JobError::FfmpegError { code: Some(code), message, detail },
+        // Missing, or killed by a signal before it could exit
+        None if error.starts_with(ffmpeg::FFMPEG_MISSING_ERROR) || error.starts_with(ffmpeg::KILLED_BY_SIGNAL) => JobError::FfmpegError { code: None, message, detail },
+        None => JobError::Other { message },
+    }
+}
+
+// ==========================================
+// STDERR DETAILS (managed state)
+// ==========================================
+// The last DETAIL_LINES lines of a job's failed ffmpeg run, by job id,
+// until the command that started it turns its error into a JobError.
+#[derive(Default)]
+pub struct FailureDetails {
+    runs: Mutex<HashMap<u64, Vec<String>>>,
+    order: Mutex<VecDeque<u64>>,
+}
+
+pub fn keep(app: &AppHandle, job_id: u64, lines: Vec<String>) {
+    let Some(details) = app.try_state::<FailureDetails>() else { return };
+    let mut order = details.order.lock().unwrap();
+    let mut runs = details.runs.lock().unwrap();
+    if runs.insert(job_id, lines).is_none() {
+        order.push_back(job_id);
+    }
+    // Queue jobs never come to pick theirs up
+    while order.len() > MAX_KEPT {
+        if let Some(old) = order.pop_front() {
+            runs.remove(&old);
+        }
+    }
+}
+
+// `error` of the job `job_id`, typed, with its stderr tail.
+pub fn for_job(app: &AppHandle, job_id: u64, error: String) -> JobError {
+    let detail = app.try_state::<FailureDetails>().and_then(|d| d.runs.lock().unwrap().remove(&job_id)).unwrap_or_default();
+    classify(&error, detail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The serialized `kind`, which is what the frontend matches on
+    fn kind(error: &JobError) -> String {
+        serde_json::to_value(error).unwrap()["kind"].as_str().unwrap().to_string()
+    }
+
+    // A failed run's telling stderr line, as run_with_progress reports it
+    fn failed_with(line: &str) -> JobError {
+        classify(&format!("Error (Code 1): {}", line), vec![line.to_string()])
+    }
+
+    #[test]
+    fn telling_stderr_lines_map_to_their_kind() {
+        let table = [
+            ("/out/clip.mp4: No space left on device", "disk_full"),
+            ("There is not enough space on the disk.", "disk_full"),
+            ("av_interleaved_write_frame(): Disk quota exceeded", "disk_full"),
+            ("/out/clip.mp4: Permission denied", "permission_denied"),
+            ("C:\\out\\clip.mp4: Access is denied.", "permission_denied"),
+            ("Unknown encoder 'libsvtav1'", "encoder_not_available"),
+            ("Encoder not found", "encoder_not_available"),
+            ("/in/clip.mp4: Invalid data found when processing input", "unsupported_format"),
+            ("[mov,mp4,m4a,3gp,3g2,mj2 @ 0x7f] moov atom not found", "unsupported_format"),
+            ("Conversion failed!", "ffmpeg_error"),
+        ];
+        for (line, expected) in table {
+            assert_eq!(kind(&failed_with(line)), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn the_app_s_own_errors_map_to_their_kind() {
+        let table = [
+            (cancel::CANCELLED.to_string(), "cancelled"),
+            ("Input file not found: /in/gone.mp4".to_string(), "input_not_found"),
+            (format!("{}: 12 GB needed, 3 GB free", outputs::WORK_VOLUME_FULL), "disk_full"),
+            (format!("{}: 12 GB needed, 3 GB free", outputs::OUTPUT_VOLUME_FULL), "disk_full"),
+            (format!("{}: h264_nvenc", hardware::ENCODER_NOT_AVAILABLE), "encoder_not_available"),
+            (format!("{}: vp9 in .mov", support::CODEC_MISMATCH), "unsupported_format"),
+            ("/in/empty.mp4 is empty (0 bytes)".to_string(), "unsupported_format"),
+            (format!("{}: no bundled or system ffmpeg", ffmpeg::FFMPEG_MISSING_ERROR), "ffmpeg_error"),
+            (format!("{}: ffmpeg died from signal 9 (Conversion failed!)", ffmpeg::KILLED_BY_SIGNAL), "ffmpeg_error"),
+        ];
+        for (error, expected) in table {
+            assert_eq!(kind(&classify(&error, vec![])), expected, "{}", error);
+        }
+    }
+
+    #[test]
+    fn anything_else_is_other_with_its_message() {
+        let error = "The watch folder was removed";
+        assert_eq!(classify(error, vec!["frame=10".to_string()]), JobError::Other { message: error.to_string() });
+    }
+
+    #[test]
+    fn an_ffmpeg_failure_keeps_its_exit_code_and_stderr_tail() {
+        let detail = vec!["frame=120 fps=30".to_string(), "Conversion failed!".to_string()];
+        let error = "Error (Code -22): Conversion failed!";
+        assert_eq!(
+            classify(error, detail.clone()),
+            JobError::FfmpegError { code: Some(-22), message: error.to_string(), detail }
+        );
+    }
+
+    #[test]
+    fn a_missing_encoder_is_named() {
+        match failed_with("Unknown encoder 'libsvtav1'") {
+            JobError::EncoderNotAvailable { encoder, .. } => assert_eq!(encoder, "libsvtav1"),
+            other => panic!("{:?}", other),
+        }
+    }
+}
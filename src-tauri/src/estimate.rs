@@ -0,0 +1,222 @@
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::cancel::TempDir;
+use crate::errors;
+use crate::events::Event;
+use crate::explain;
+use crate::ffmpeg;
+use crate::probe;
+use crate::progress::ProgressTracker;
+use crate::queue;
+use crate::request::VideoCompressRequest;
+
+const SAMPLE_SECS: f64 = 5.0;
+// Where the samples start, as fractions of the part of the input the job encodes
+const POSITIONS: [f64; 3] = [0.1, 0.5, 0.9];
+// Added either side of the samples' spread, for the parts they didn't see
+const MARGIN: f64 = 0.15;
+
+// ==========================================
+// SIZE AND TIME ESTIMATES
+// ==========================================
+// "~420 MB, ~35 min" before an hour-long encode starts. The request goes
+// through a dry run first (see dryrun.rs), so the samples run the exact
+// commands the job would: three 5-second pieces at 10%, 50% and 90% of what
+// it encodes, each cut with -ss / -t and written to a folder in the app's
+// cache that goes when the estimate ends, however it ends. Their bytes and
+// wall time per source second are scaled to the whole; the bounds are the
+// lowest and highest sample, MARGIN further out. A clip shorter than twice
+// the samples together is encoded whole, and the numbers are measured.
+//
+// It runs as a direct job: job-started gives its id, cancel_job stops it
+// and progress goes out as `estimate-progress`. The samples carry ffmpeg's
+// start-up, so the time comes out a little long for short samples; the
+// analysis passes the dry run already made (loudness, telecine) aren't in
+// it. fit_size_mb jobs are estimated at their first rung.
+
+#[derive(Serialize, Clone, Debug)]
+pub struct EstimateSample {
+    // Where in the source it started
+    pub start_secs: f64,
+    pub secs: f64,
+    pub bytes: u64,
+    pub wall_secs: f64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SizeEstimate {
+    pub input: String,
+    pub output: String,
+    pub encoder: String,
+    // Seconds of the source the job encodes
+    pub duration_secs: f64,
+    pub estimated_bytes: u64,
+    pub low_bytes: u64,
+    pub high_bytes: u64,
+    pub estimated_wall_secs: f64,
+    pub low_wall_secs: f64,
+    pub high_wall_secs: f64,
+    // Source frames encoded per second, over all samples
+    pub encode_fps: Option<f64>,
+    // The sample was the whole input
+    pub exact: bool,
+    pub samples: Vec<EstimateSample>,
+    pub warnings: Vec<String>,
+}
+
+// Pure: (start, length) of each sample within [start, start + length).
+pub fn sample_windows(start: f64, length: f64) -> Vec<(f64, f64)> {
+    if length <= SAMPLE_SECS * POSITIONS.len() as f64 * 2.0 {
+        return vec![(start, length)];
+    }
+    POSITIONS.iter().map(|p| ((start + length * p).min(start + length - SAMPLE_SECS), SAMPLE_SECS)).collect()
+}
+
+// Pure: one of the plan's commands, cut to `secs` from `at` seconds into
+// the source and writing to `output` (a "-" output stays). The seek goes
+// before the first input, where a cut's own -ss is; the length goes last
+// among the output options, where a preview's -t is.
+pub fn sample_args(args: &[String], at: f64, secs: f64, output: &str) -> Vec<String> {
+    let mut args = args.to_vec();
+    let (at, secs) = (format!("{:.3}", at), format!("{:.3}", secs));
+    let first_input = args.iter().position(|a| a == "-i").unwrap_or(0);
+    match args[..first_input].iter().position(|a| a == "-ss") {
+        Some(i) => args[i + 1] = at,
+        None => {
+            args.insert(first_input, at);
+            args.insert(first_input, "-ss".to_string());
+        }
+    }
+    let outputs_from = args.iter().rposition(|a| a == "-i").map_or(0, |i| i + 2);
+    let last = args.len().saturating_sub(1);
+    match args[outputs_from.min(last)..last].iter().rposition(|a| a == "-t") {
+        Some(i) => args[outputs_from + i + 1] = secs,
+        None => {
+            args.insert(last, secs);
+            args.insert(last, "-t".to_string());
+        }
+    }
+    if let Some(path) = args.last_mut().filter(|a| *a != "-") {
+        *path = output.to_string();
+    }
+    args
+}
+
+// Pure: (estimate, low, high) for `duration` seconds, from (amount, secs)
+// per sample.
+fn scaled(samples: &[(f64, f64)], duration: f64, margin: f64) -> (f64, f64, f64) {
+    let (amount, secs) = samples.iter().fold((0.0, 0.0), |(a, s), (amount, secs)| (a + amount, s + secs));
+    let rates = samples.iter().filter(|(_, s)| *s > 0.0).map(|(a, s)| a / s);
+    let low = rates.clone().fold(f64::INFINITY, f64::min);
+    let high = rates.fold(0.0, f64::max);
+    let estimate = if secs > 0.0 { amount / secs * duration } else { 0.0 };
+    (estimate, (low * duration * (1.0 - margin)).min(estimate), (high * duration * (1.0 + margin)).max(estimate))
+}
+
+// ==========================================
+// COMMAND: ESTIMATE OUTPUT SIZE
+// ==========================================
+#[tauri::command]
+pub async fn estimate_output_size(app: AppHandle, request: VideoCompressRequest) -> Result<SizeEstimate, errors::JobError> {
+    let (job_id, result) = queue::run_direct(&app, estimate(&app, request)).await;
+    result.map_err(|e| errors::for_job(&app, job_id, e))
+}
+
+async fn estimate(app: &AppHandle, request: VideoCompressRequest) -> Result<SizeEstimate, String> {
+    let fit_cap = request.options.fit_size_mb;
+    let planned = crate::run_video_job(app, VideoCompressRequest { dry_run: true, ..request.clone() }).await?;
+    if let Some(found) = &planned.already_optimized {
+        return Err(format!("Nothing would be encoded: {}", found.message));
+    }
+    let other_pipeline = planned.explanations.iter().any(|e| e.code == explain::PIPELINE_REROUTED || e.code == explain::SINGLE_FRAME_IMAGE);
+    let plan = match planned.dry_run {
+        Some(plan) if !other_pipeline && !plan.commands.is_empty() => plan,
+        _ => return Err("Only video encodes can be estimated; this output would be written by another pipeline".to_string()),
+    };
+    let media = probe::probe(app, &planned.input).await?;
+    let options = &request.options;
+    let start = options.start_secs.unwrap_or(0.0);
+    let source_end = media.duration.ok_or("The estimate needs the input's duration, and it couldn't be read")?;
+    let end = [options.end_secs, options.limit_duration_secs.map(|l| start + l)].into_iter().flatten().fold(source_end, f64::min);
+    let duration = end - start;
+    if duration <= 0.0 {
+        return Err("The job would encode nothing: the cut starts at or after the end of the input".to_string());
+    }
+
+    let job_id = queue::running_job_id().unwrap_or_default();
+    let dir = TempDir::new(app.path().app_cache_dir().map_err(|e| e.to_string())?.join("estimate").join(job_id.to_string()))?;
+    queue::JobStarted { encoder: Some(plan.encoder.clone()), ..queue::JobStarted::new(&planned.input, &planned.output) }.emit(app);
+    println!("📐 Estimating {} from samples", planned.input);
+
+    let windows = sample_windows(start, duration);
+    let steps = (windows.len() * plan.commands.len()) as f32;
+    let mut samples = vec![];
+    for (k, &(at, secs)) in windows.iter().enumerate() {
+        // Outputs a later command reads (a GIF's palette) go to the folder too
+        let mut moved: Vec<(String, String)> = vec![];
+        let mut bytes = 0;
+        let started = Instant::now();
+        for (n, command) in plan.commands.iter().enumerate() {
+            let original = command.last().cloned().unwrap_or_default();
+            let ext = Path::new(&original).extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+            let target = dir.path().join(format!("sample_{}_{}.{}", k, n, ext)).to_string_lossy().to_string();
+            let mut args = sample_args(command, at, secs, &target);
+            for arg in args.iter_mut() {
+                if let Some((_, to)) = moved.iter().find(|(from, _)| from == arg) {
+                    *arg = to.clone();
+                }
+            }
+            if let Some(i) = args.iter().position(|a| a == "-passlogfile") {
+                args[i + 1] = dir.path().join(format!("passlog_{}", k)).to_string_lossy().to_string();
+            }
+            // Already thread-capped by the dry run, which ran under the request's limits
+            let step = (k * plan.commands.len() + n) as f32;
+            let tracker = ProgressTracker::for_duration(Some(secs)).with_span(step / steps * 100.0, (step + 1.0) / steps * 100.0);
+            ffmpeg::run_with_progress(app, args, tracker, Event::EstimateProgress).await?;
+            if n + 1 == plan.commands.len() {
+                bytes = fs::metadata(&target).map(|m| m.len()).unwrap_or(0);
+            } else if original != "-" {
+                moved.push((original, target));
+            }
+        }
+        samples.push(EstimateSample { start_secs: at, secs, bytes, wall_secs: started.elapsed().as_secs_f64() });
+    }
+
+    let exact = windows.len() == 1;
+    let margin = if exact { 0.0 } else { MARGIN };
+    let (estimated_bytes, low_bytes, mut high_bytes) = scaled(&samples.iter().map(|s| (s.bytes as f64, s.secs)).collect::<Vec<_>>(), duration, margin);
+    let (estimated_wall_secs, low_wall_secs, high_wall_secs) = scaled(&samples.iter().map(|s| (s.wall_secs, s.secs)).collect::<Vec<_>>(), duration, margin);
+    let (sampled_secs, wall): (f64, f64) = samples.iter().fold((0.0, 0.0), |(s, w), sample| (s + sample.secs, w + sample.wall_secs));
+    let encode_fps = media.fps.filter(|_| wall > 0.0).map(|fps| sampled_secs * fps / wall);
+
+    let mut warnings = plan.warnings.clone();
+    if let Some(cap) = fit_cap {
+        high_bytes = high_bytes.min(cap * 1024.0 * 1024.0);
+        warnings.push("fit_size_mb: this is the first rung's estimate; if it doesn't fit, the encode steps down and takes longer".to_string());
+    }
+    if options.resumable {
+        warnings.push("Resumable encodes run in parts, which come out slightly larger than this".to_string());
+    }
+    println!("📐 ~{:.0} MB, ~{:.0}s", estimated_bytes / 1024.0 / 1024.0, estimated_wall_secs);
+    Ok(SizeEstimate {
+        input: planned.input,
+        output: planned.output,
+        encoder: plan.encoder,
+        duration_secs: duration,
+        estimated_bytes: estimated_bytes.round() as u64,
+        low_bytes: low_bytes.round() as u64,
+        high_bytes: high_bytes.round() as u64,
+        estimated_wall_secs,
+        low_wall_secs,
+        high_wall_secs,
+        encode_fps,
+        exact,
+        samples,
+        warnings,
+    })
+}
@@ -0,0 +1,335 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use tauri::{Emitter, Manager, State};
+
+use crate::AppHandle;
+use crate::active::{self, JobRef};
+use crate::archive::HashProgress;
+use crate::batch::{BatchJobEvent, BatchStarted};
+use crate::capabilities::CapabilitiesChanged;
+use crate::cleanup::CleanupReport;
+use crate::deferred::ScheduledView;
+use crate::efficiency::EfficiencyAdvisory;
+use crate::extended_ffmpeg::DownloadProgress;
+use crate::ffmpeg::{ProgressPayload, SizeWarning};
+use crate::hardware::CpuFallback;
+use crate::health::FfmpegHealth;
+use crate::image_batch::BatchProgress;
+use crate::instance::InstanceStatus;
+use crate::ladder::LadderProgress;
+use crate::nightplan::{NightPlan, NightSummary};
+use crate::package::PackageProgress;
+use crate::pause::JobPauseChanged;
+use crate::plan::PlanProgress;
+use crate::queue::{self, JobProgress, JobStarted, QueueSnapshot};
+use crate::selftest::SelfTestProgress;
+use crate::stats::Totals;
+use crate::store::Recovered;
+use crate::timeline::TimelinePayload;
+use crate::tray;
+use crate::upload::UploadProgress;
+use crate::watch::{WatchPickedUp, WatchRejected};
+
+// Bumped whenever a variant or payload changes shape
+pub const EVENT_SCHEMA_VERSION: u32 = 2;
+const CHANNEL: &str = "compressio-event";
+// Every event, on one of the two by kind (see Event::is_progress)
+pub const PROGRESS_CHANNEL: &str = "compressio://progress";
+pub const STATUS_CHANNEL: &str = "compressio://status";
+
+// ==========================================
+// EVENTS
+// ==========================================
+// Everything the backend tells the frontend, as one type. On the wire it's
+// `{ "kind": "job-progress", "data": {...}, "job": {...} }`: every event
+// goes out on `compressio://progress` (the frequent ones: percentages and
+// raw ffmpeg lines) or `compressio://status` (the rest), and on
+// `compressio-event` to whoever subscribed to its kind (see
+// subscribe_events), with the matching subscription ids alongside.
+// `get_event_schema` describes all of it, so the TypeScript side is
+// generated from this enum.
+//
+// `job` is the job the event came from: the one its payload names, or else
+// the one running on the task that sent it. Two encodes at once (a batch,
+// or an image next to a video) are told apart by it, since their progress
+// payloads look alike. A reloaded frontend catches up with get_active_jobs.
+//
+// Compatibility: every event still goes out on its old channel too (named
+// after the kind, carrying just `data`). Those go away in the next release.
+#[derive(Serialize, Clone, JsonSchema)]
+#[serde(tag = "kind", content = "data", rename_all = "kebab-case")]
+pub enum Event {
+    // Progress of an encode: compress_video, queue and batch jobs, pip
+    CompressionProgress(ProgressPayload),
+    ConcatProgress(ProgressPayload),
+    LadderSampleProgress(ProgressPayload),
+    // Sample encodes of estimate_output_size, across all of them
+    EstimateProgress(ProgressPayload),
+    // A finished video job's scoring pass (compute_quality_score)
+    QualityScoreProgress(ProgressPayload),
+    // The source is already lean; the job goes on anyway (see efficiency.rs)
+    EfficiencyAdvisory(EfficiencyAdvisory),
+    // An encode is heading well past its max_filesize_mb
+    SizeWarning(SizeWarning),
+    // Raw ffmpeg stderr lines, for debugging
+    FfmpegProgress(String),
+    JobStarted(Box<JobStarted>),
+    JobProgress(JobProgress),
+    JobTimeline(TimelinePayload),
+    // pause_job / resume_job went through
+    JobPaused(JobPauseChanged),
+    JobResumed(JobPauseChanged),
+    // Batch jobs (compress_batch)
+    BatchStarted(BatchStarted),
+    JobFinished(BatchJobEvent),
+    JobFailed(BatchJobEvent),
+    // The whole queue after every change; described loosely in the schema
+    QueueChanged(#[schemars(with = "serde_json::Value")] QueueSnapshot),
+    ImageBatchProgress(BatchProgress),
+    LadderProgress(LadderProgress),
+    PlanProgress(PlanProgress),
+    SelfTestProgress(SelfTestProgress),
+    ArchiveHashProgress(HashProgress),
+    // package_outputs, per chunk of each entry
+    PackageProgress(PackageProgress),
+    ExtendedFfmpegProgress(DownloadProgress),
+    // The startup check couldn't run ffmpeg or ffprobe (see health.rs)
+    FfmpegUnavailable(FfmpegHealth),
+    CleanupFinished(CleanupReport),
+    StatsUpdated(Totals),
+    StoreRecovered(Recovered),
+    CapabilitiesChanged(CapabilitiesChanged),
+    SecondaryInstance(InstanceStatus),
+    // One part of an upload (see upload.rs) went through
+    UploadProgress(UploadProgress),
+    // A hardware encode failed mid-job; it's re-run with the CPU encoder
+    FallbackToCpu(CpuFallback),
+    // The schedule window opened and its jobs were planned (see nightplan.rs)
+    NightPlan(NightPlan),
+    // ...and how that went, when it closed
+    NightSummary(NightSummary),
+    // A watch folder queued a new file
+    WatchFilePickedUp(WatchPickedUp),
+    // ...or left a file alone: empty, or not the media its name says
+    WatchFileRejected(WatchRejected),
+    // Jobs waiting to be queued (see deferred.rs), after every change
+    ScheduledChanged(#[schemars(with = "serde_json::Value")] Vec<ScheduledView>),
+}
+
+impl Event {
+    pub fn is_progress(&self) -> bool {
+        matches!(
+            self,
+            Event::CompressionProgress(_)
+                | Event::ConcatProgress(_)
+                | Event::LadderSampleProgress(_)
+                | Event::EstimateProgress(_)
+                | Event::QualityScoreProgress(_)
+                | Event::FfmpegProgress(_)
+                | Event::JobProgress(_)
+                | Event::ImageBatchProgress(_)
+                | Event::LadderProgress(_)
+                | Event::PlanProgress(_)
+                | Event::SelfTestProgress(_)
+                | Event::ArchiveHashProgress(_)
+                | Event::PackageProgress(_)
+                | Event::ExtendedFfmpegProgress(_)
+                | Event::UploadProgress(_)
+        )
+    }
+
+    // The job the payload itself names, if any.
+    fn job_id(&self) -> Option<u64> {
+        match self {
+            Event::JobStarted(started) => started.job_id,
+            Event::JobProgress(progress) => Some(progress.job_id),
+            Event::JobPaused(change) | Event::JobResumed(change) => Some(change.job_id),
+            Event::JobFinished(e) | Event::JobFailed(e) => Some(e.result.job_id),
+            Event::EfficiencyAdvisory(advisory) => advisory.job_id,
+            Event::SizeWarning(warning) => warning.job_id,
+            Event::FallbackToCpu(fallback) => fallback.job_id,
+            _ => None,
+        }
+    }
+}
+
+// What goes out on the channels. `subscriptions` is only on `compressio-event`.
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct Envelope<'a> {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub subscriptions: Vec<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job: Option<JobRef>,
+    #[serde(flatten)]
+    pub event: &'a Event,
+}
+
+// ==========================================
+// SUBSCRIPTIONS (managed state)
+// ==========================================
+// Subscription id -> kinds it wants (empty = all of them).
+#[derive(Default)]
+pub struct Subscriptions {
+    next_id: Mutex<u64>,
+    kinds: Mutex<HashMap<u64, HashSet<String>>>,
+}
+
+impl Subscriptions {
+    fn matching(&self, kind: &str) -> Vec<u64> {
+        let kinds = self.kinds.lock().unwrap();
+        let mut ids: Vec<u64> = kinds.iter().filter(|(_, k)| k.is_empty() || k.contains(kind)).map(|(id, _)| *id).collect();
+        ids.sort();
+        ids
+    }
+}
+
+pub fn emit(app: &AppHandle, event: Event) {
+    let job_id = event.job_id().or_else(queue::running_job_id);
+    if active::observe(app, &event, job_id) {
+        tray::refresh(app);
+    }
+    let Ok(value) = serde_json::to_value(&event) else { return };
+    let kind = value["kind"].as_str().unwrap_or_default();
+    let _ = app.emit(kind, &value["data"]);
+    // After observe, so the job-started that registers the input carries it too
+    let job = job_id.map(|id| active::job_ref(app, id));
+    let channel = if event.is_progress() { PROGRESS_CHANNEL } else { STATUS_CHANNEL };
+    let _ = app.emit(channel, Envelope { subscriptions: vec![], job: job.clone(), event: &event });
+    let subscriptions = app.try_state::<Subscriptions>().map(|s| s.matching(kind)).unwrap_or_default();
+    if !subscriptions.is_empty() {
+        let _ = app.emit(CHANNEL, Envelope { subscriptions, job, event: &event });
+    }
+}
+
+pub fn schema() -> serde_json::Value {
+    let mut schema = serde_json::to_value(schemars::schema_for!(Envelope<'static>)).unwrap_or_default();
+    schema["$comment"] = format!("compress-io events, schema version {}", EVENT_SCHEMA_VERSION).into();
+    schema
+}
+
+// Every `kind`, read off the schema so the list can't drift from the enum.
+pub fn kinds() -> Vec<String> {
+    let schema = serde_json::to_value(schemars::schema_for!(Event)).unwrap_or_default();
+    let variants = schema["oneOf"].as_array().cloned().unwrap_or_default();
+    variants
+        .iter()
+        .filter_map(|v| v["properties"]["kind"]["enum"][0].as_str().map(String::from))
+        .collect()
+}
+
+// ==========================================
+// COMMANDS: EVENT SUBSCRIPTIONS
+// ==========================================
+// An empty list subscribes to every kind.
+#[tauri::command]
+pub fn subscribe_events(subscriptions: State<'_, Subscriptions>, kinds: Vec<String>) -> Result<u64, String> {
+    let known = self::kinds();
+    if let Some(unknown) = kinds.iter().find(|k| !known.contains(k)) {
+        return Err(format!("Unknown event kind \"{}\"", unknown));
+    }
+    let id = {
+        let mut next = subscriptions.next_id.lock().unwrap();
+        *next += 1;
+        *next
+    };
+    subscriptions.kinds.lock().unwrap().insert(id, kinds.into_iter().collect());
+    Ok(id)
+}
+
+#[tauri::command]
+pub fn unsubscribe_events(subscriptions: State<'_, Subscriptions>, subscription_id: u64) -> bool {
+    subscriptions.kinds.lock().unwrap().remove(&subscription_id).is_some()
+}
+
+// JSON Schema (draft 7) of what the channels carry.
+#[tauri::command]
+pub fn get_event_schema() -> serde_json::Value {
+    schema()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::Harness;
+    use std::sync::Arc;
+    use tauri::Listener;
+
+    // Checked in, so a change to what the frontend gets shows up in review.
+    // After a deliberate change: bump EVENT_SCHEMA_VERSION and run the tests
+    // with UPDATE_EVENT_SCHEMA=1 to write the new one.
+    const SNAPSHOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/event-schema.json");
+
+    fn totals() -> Totals {
+        Totals { jobs: 3, successful_jobs: 2, failed_jobs: 1, input_bytes: 3000, output_bytes: 1200, saved_bytes: 1800 }
+    }
+
+    #[test]
+    fn the_schema_only_changes_with_a_version_bump() {
+        let current = serde_json::to_string_pretty(&schema()).unwrap() + "\n";
+        if std::env::var_os("UPDATE_EVENT_SCHEMA").is_some() {
+            std::fs::write(SNAPSHOT, &current).unwrap();
+        }
+        let snapshot = std::fs::read_to_string(SNAPSHOT).unwrap();
+        assert!(current == snapshot, "The event schema changed: bump EVENT_SCHEMA_VERSION and update {} (see above)", SNAPSHOT);
+    }
+
+    #[test]
+    fn kinds_are_unique_kebab_case_names() {
+        let kinds = kinds();
+        assert!(kinds.iter().all(|k| !k.is_empty() && k.chars().all(|c| c.is_ascii_lowercase() || c == '-')), "{:?}", kinds);
+        let unique: HashSet<&String> = kinds.iter().collect();
+        assert_eq!(unique.len(), kinds.len());
+        for kind in ["compression-progress", "job-started", "queue-changed", "stats-updated", "watch-file-picked-up", "scheduled-changed"] {
+            assert!(kinds.iter().any(|k| k == kind), "{} is missing", kind);
+        }
+    }
+
+    #[test]
+    fn an_envelope_round_trips_through_json() {
+        let event = Event::StatsUpdated(totals());
+        let envelope = Envelope { subscriptions: vec![4, 7], job: Some(JobRef { job_id: 12, input: Some("in.mov".to_string()) }), event: &event };
+        let text = serde_json::to_string(&envelope).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+
+        let mut keys: Vec<&str> = value.as_object().unwrap().keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, ["data", "job", "kind", "subscriptions"]);
+        assert_eq!(value["kind"], "stats-updated");
+        assert_eq!(value["subscriptions"], serde_json::json!([4, 7]));
+        assert_eq!(value["job"], serde_json::json!({ "job_id": 12, "input": "in.mov" }));
+        assert_eq!(serde_json::from_value::<Totals>(value["data"].clone()).unwrap(), totals());
+
+        // What the status channels carry: no subscriptions, no job when there's none
+        let bare = serde_json::to_value(Envelope { subscriptions: vec![], job: None, event: &Event::FfmpegProgress("frame=1".to_string()) }).unwrap();
+        assert_eq!(bare, serde_json::json!({ "kind": "ffmpeg-progress", "data": "frame=1" }));
+    }
+
+    #[test]
+    fn an_event_goes_to_its_channel_its_old_name_and_its_subscribers() {
+        let h = Harness::new("events", r#"{ "runs": [] }"#);
+        let heard: Arc<Mutex<Vec<(String, serde_json::Value)>>> = Arc::default();
+        for channel in [CHANNEL, "stats-updated", STATUS_CHANNEL, PROGRESS_CHANNEL] {
+            let heard = heard.clone();
+            h.handle().listen_any(channel, move |e| heard.lock().unwrap().push((channel.to_string(), serde_json::from_str(e.payload()).unwrap())));
+        }
+        let subscriptions = h.handle().state::<Subscriptions>();
+        let stats = subscribe_events(subscriptions.clone(), vec!["stats-updated".to_string()]).unwrap();
+        let everything = subscribe_events(subscriptions.clone(), vec![]).unwrap();
+        subscribe_events(subscriptions.clone(), vec!["job-progress".to_string()]).unwrap();
+        assert!(subscribe_events(subscriptions.clone(), vec!["no-such-kind".to_string()]).is_err());
+
+        emit(h.handle(), Event::StatsUpdated(totals()));
+        let heard = heard.lock().unwrap().clone();
+        let on = |channel: &str| heard.iter().filter(|(c, _)| c == channel).map(|(_, v)| v.clone()).collect::<Vec<_>>();
+        assert_eq!(on("stats-updated"), [serde_json::to_value(totals()).unwrap()]);
+        assert_eq!(on(STATUS_CHANNEL).len(), 1);
+        assert!(on(PROGRESS_CHANNEL).is_empty());
+        let subscribed = on(CHANNEL);
+        assert_eq!(subscribed.len(), 1);
+        assert_eq!(subscribed[0]["subscriptions"], serde_json::json!([stats, everything]));
+        assert!(unsubscribe_events(subscriptions.clone(), stats));
+        assert!(!unsubscribe_events(subscriptions, stats));
+    }
+}
@@ -0,0 +1,718 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::AppHandle;
+use crate::audiomode::{self, AudioAction, AudioContext, AudioPlan};
+use crate::audiotracks;
+use crate::avsync::AvSyncReport;
+use crate::duration::{DurationPolicy, StreamLengths};
+use crate::filters::VideoFilters;
+use crate::hardware;
+use crate::interlace::{FieldAction, FieldReport};
+use crate::loudness::Measured;
+use crate::probe::MediaInfo;
+use crate::quality::QualityOptions;
+use crate::queue;
+use crate::request::VideoCompressRequest;
+use crate::sizefit::FitReport;
+use crate::sourcetool::{Detection, Fixup, SourceTool};
+use crate::subtitles::{self, SubtitleAction, SubtitleOutcome};
+use crate::support::{self, VideoCodec};
+use crate::timeline;
+
+// ==========================================
+// DECISION EXPLANATIONS
+// ==========================================
+// "Why did it pick these settings?" Every automatic choice encode_video
+// makes (encoder, rate control, field handling, A/V offset, subtitles,
+// shortcuts for short inputs, skipped outputs) leaves one reason here. Like
+// timeline entries they're a stable code plus string params, so the UI
+// words them itself.
+//
+// Each builder below returns a reason for every branch it covers (there's
+// no "nothing to say" case), which is what keeps the list complete. The
+// same builders feed plan_batch, with `DEFERRED` for what only the encode
+// can find out (scene detection, idet, HDR side data).
+
+// --- ENCODER ---
+pub const ENCODER_CONTAINER: &str = "encoder.container";
+pub const ENCODER_DEFAULT: &str = "encoder.default";
+pub const ENCODER_GPU: &str = "encoder.gpu";
+// auto_gpu was on but no hardware encoder passed its test encode
+pub const ENCODER_GPU_UNAVAILABLE: &str = "encoder.gpu_unavailable";
+// auto_gpu was on but the container can't hold the codec
+pub const ENCODER_GPU_UNSUPPORTED: &str = "encoder.gpu_unsupported_container";
+// Capability detection was still running, so the job went to the CPU
+pub const ENCODER_GPU_PENDING: &str = "encoder.gpu_detection_pending";
+// `action`: stripped_to_hdr10 / preserved (dynamic metadata, see hdr::plan),
+// tonemapped / passed_through (static HDR, see hdr::static_plan)
+pub const ENCODER_HDR: &str = "encoder.hdr";
+pub const ENCODER_COPY: &str = "encoder.copy";
+// --- RATE CONTROL ---
+pub const RATE_CONTAINER_DEFAULT: &str = "rate.container_default";
+pub const RATE_QUALITY: &str = "rate.quality";
+pub const RATE_BITRATE: &str = "rate.bitrate";
+pub const RATE_SIZE_CAP: &str = "rate.size_cap";
+pub const RATE_TWO_PASS: &str = "rate.two_pass";
+// A size target without two passes (encoder has none, resumable, short)
+pub const RATE_SINGLE_PASS: &str = "rate.single_pass";
+pub const RATE_COPY: &str = "rate.copy";
+// --- FIELDS ---
+pub const FIELDS_NOT_REQUESTED: &str = "fields.not_requested";
+pub const FIELDS_PROGRESSIVE: &str = "fields.progressive";
+pub const FIELDS_DEINTERLACED: &str = "fields.deinterlaced";
+pub const FIELDS_INVERSE_TELECINE: &str = "fields.inverse_telecine";
+pub const FIELDS_COPY: &str = "fields.copy";
+// --- A/V SYNC ---
+pub const AV_NOT_APPLICABLE: &str = "av_sync.not_applicable";
+pub const AV_NOT_REQUESTED: &str = "av_sync.not_requested";
+pub const AV_GIVEN: &str = "av_sync.given";
+pub const AV_DETECTED: &str = "av_sync.detected";
+pub const AV_LOW_CONFIDENCE: &str = "av_sync.low_confidence";
+pub const AV_IN_SYNC: &str = "av_sync.in_sync";
+// --- RESOLUTION ---
+// The orientation the probe saw (after rotation metadata), nothing capped
+pub const RESOLUTION_ORIENTATION: &str = "resolution.orientation";
+// max_long_edge became a cap on `side` ("width", "height", or "both"
+// when the size couldn't be read)
+pub const RESOLUTION_LONG_EDGE: &str = "resolution.long_edge";
+// --- SUBTITLES ---
+pub const SUBTITLE_COPIED: &str = "subtitles.copied";
+pub const SUBTITLE_CONVERTED: &str = "subtitles.converted";
+pub const SUBTITLE_EXTRACTED: &str = "subtitles.extracted";
+pub const SUBTITLE_DROPPED: &str = "subtitles.dropped";
+// --- FILTERS ---
+// A filter spec was merged in; `graph` is the whole -vf value
+pub const FILTERS_CUSTOM: &str = "filters.custom";
+// --- SOURCE ---
+// A known screen-recording tool made the input; `signals` say how it showed
+pub const SOURCE_DETECTED: &str = "source.detected";
+// One per fixup applied for that tool
+pub const SOURCE_FIXUP: &str = "source.fixup";
+// The tool's fixups weren't applied; `reason` is not_requested, disabled
+// or surgical
+pub const SOURCE_FIXUPS_OFF: &str = "source.fixups_off";
+// --- FRAME TIMING ---
+// preserve_vfr: source timestamps passed through with `flag` (-fps_mode or
+// -vsync)
+pub const TIMING_PASSTHROUGH: &str = "timing.passthrough";
+// A constant-rate fixup for the recording tool was left out for it
+pub const TIMING_CFR_SKIPPED: &str = "timing.cfr_fixup_skipped";
+// Audio and video lengths differ; `policy` is the duration_policy the
+// output follows
+pub const TIMING_STREAMS_MISMATCHED: &str = "timing.streams_mismatched";
+// --- AUDIO ---
+// normalize_audio in two passes; `measured_i` is the source's loudness
+// in LUFS and `offset` the gain left for the second pass
+pub const AUDIO_LOUDNESS_MEASURED: &str = "audio.loudness_measured";
+// Measuring failed, so one pass normalized as it went
+pub const AUDIO_LOUDNESS_SINGLE_PASS: &str = "audio.loudness_single_pass";
+// What the `audio` option decided (see audiomode.rs); `reason` is
+// "requested", "compatible", "container", "lossless", "over_ceiling" or the
+// option that needs it re-encoded
+pub const AUDIO_COPIED: &str = "audio.copied";
+pub const AUDIO_TRANSCODED: &str = "audio.transcoded";
+pub const AUDIO_REMOVED: &str = "audio.removed";
+// --- SIZE CAP ---
+// Rung of the size ladder the output fit on (see sizefit.rs)
+pub const FIT_RUNG: &str = "fit.rung";
+// --- JOB ---
+// One per shortcut taken for a sub-2s input (same decisions as the timeline)
+pub const SHORT_INPUT: &str = "job.short_input";
+pub const SINGLE_FRAME_IMAGE: &str = "job.single_frame_image";
+// Another pipeline wrote the output (see routing.rs); `to` is which
+pub const PIPELINE_REROUTED: &str = "job.pipeline_rerouted";
+pub const SKIPPED_LARGER: &str = "job.skipped_larger";
+// skip_optimized: the source was already lean, nothing was encoded
+pub const ALREADY_OPTIMIZED: &str = "job.already_optimized";
+// Tags dropped, creation time fixed, bitexact and single-threaded
+pub const DETERMINISTIC: &str = "job.deterministic";
+// dry_run: the command was built and nothing ran it (see dryrun.rs)
+pub const DRY_RUN: &str = "job.dry_run";
+// Dry runs only: `what` is settled during the encode
+pub const DEFERRED: &str = "plan.decided_at_encode";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Explanation {
+    pub code: String,
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+}
+
+impl Explanation {
+    pub fn new(code: &str, params: &[(&str, String)]) -> Self {
+        Explanation {
+            code: code.to_string(),
+            params: params.iter().take(timeline::MAX_PARAMS).map(|(k, v)| (k.to_string(), timeline::truncate(v))).collect(),
+        }
+    }
+}
+
+// --- BUILDERS ---
+// What the container branch picked (see container_codecs in lib.rs).
+pub fn container(ext: &str, encoder: &str, audio: &str, gpu: bool) -> Explanation {
+    let params = [("container", ext.to_string()), ("encoder", encoder.to_string()), ("audio", audio.to_string())];
+    let code = if gpu {
+        ENCODER_GPU
+    } else if support::accepts_video(ext, "h264") && encoder == VideoCodec::H264.cpu_encoder() {
+        ENCODER_DEFAULT
+    } else {
+        ENCODER_CONTAINER
+    };
+    Explanation::new(code, &params)
+}
+
+// Why auto_gpu didn't end up on the GPU; None when it did (or wasn't asked).
+pub fn gpu_fallback(auto_gpu: bool, on_gpu: bool, caps_pending: bool, ext: &str, codec: VideoCodec, failed: &[String]) -> Option<Explanation> {
+    if !auto_gpu || on_gpu {
+        return None;
+    }
+    Some(if caps_pending {
+        Explanation::new(ENCODER_GPU_PENDING, &[])
+    } else if !support::accepts_video(ext, codec.name()) {
+        Explanation::new(ENCODER_GPU_UNSUPPORTED, &[("container", ext.to_string()), ("codec", codec.name().to_string())])
+    } else {
+        Explanation::new(ENCODER_GPU_UNAVAILABLE, &[("codec", codec.name().to_string()), ("failed", failed.join(","))])
+    })
+}
+
+pub fn hdr(action: &str, encoder: &str) -> Explanation {
+    Explanation::new(ENCODER_HDR, &[("action", action.to_string()), ("encoder", encoder.to_string())])
+}
+
+pub fn rate(rate: &QualityOptions, encoder: &str, copy: bool, two_pass: bool) -> Explanation {
+    let encoder = ("encoder", encoder.to_string());
+    if copy {
+        return Explanation::new(RATE_COPY, &[]);
+    }
+    match (rate.target_size_mb, rate.max_filesize_mb, rate.target_bitrate_kbps, rate.quality) {
+        (Some(mb), ..) if two_pass => Explanation::new(RATE_TWO_PASS, &[encoder, ("target_size_mb", mb.to_string())]),
+        (Some(mb), ..) => Explanation::new(RATE_SINGLE_PASS, &[encoder, ("target_size_mb", mb.to_string())]),
+        (None, Some(mb), ..) => Explanation::new(RATE_SIZE_CAP, &[encoder, ("max_filesize_mb", mb.to_string())]),
+        (None, None, Some(kbps), _) => Explanation::new(RATE_BITRATE, &[encoder, ("kbps", kbps.to_string())]),
+        (None, None, None, Some(q)) => Explanation::new(RATE_QUALITY, &[("crf", q.crf(&encoder.1).to_string()), encoder]),
+        (None, None, None, None) => Explanation::new(RATE_CONTAINER_DEFAULT, &[encoder]),
+    }
+}
+
+pub fn fields(report: &FieldReport, copy: bool, requested: bool) -> Explanation {
+    let detected = ("detected", serde_json::to_value(report.detected).ok().and_then(|v| v.as_str().map(String::from)).unwrap_or_default());
+    match report.action {
+        _ if copy => Explanation::new(FIELDS_COPY, &[]),
+        FieldAction::Deinterlace => Explanation::new(FIELDS_DEINTERLACED, &[detected]),
+        FieldAction::InverseTelecine => Explanation::new(FIELDS_INVERSE_TELECINE, &[detected]),
+        FieldAction::None if requested => Explanation::new(FIELDS_PROGRESSIVE, &[detected]),
+        FieldAction::None => Explanation::new(FIELDS_NOT_REQUESTED, &[]),
+    }
+}
+
+pub fn av_sync(report: &AvSyncReport, given: Option<i64>, detect: bool, has_av: bool) -> Explanation {
+    let confidence = || ("confidence", report.confidence.map(|c| format!("{:.2}", c)).unwrap_or_default());
+    match (report.applied_ms, report.estimated_ms) {
+        _ if !has_av => Explanation::new(AV_NOT_APPLICABLE, &[]),
+        _ if given.is_some() => Explanation::new(AV_GIVEN, &[("offset_ms", given.unwrap_or(0).to_string())]),
+        (Some(ms), _) => Explanation::new(AV_DETECTED, &[("offset_ms", ms.to_string()), confidence()]),
+        (None, Some(ms)) if ms != 0 => Explanation::new(AV_LOW_CONFIDENCE, &[("estimated_ms", ms.to_string()), confidence()]),
+        (None, _) if detect => Explanation::new(AV_IN_SYNC, &[]),
+        (None, _) => Explanation::new(AV_NOT_REQUESTED, &[]),
+    }
+}
+
+// `side` is where max_long_edge ended up (see VideoFilters::apply_long_edge).
+pub fn resolution(media: Option<&MediaInfo>, side: Option<(&str, u32)>) -> Explanation {
+    let mut params = vec![];
+    params.extend(media.and_then(|m| m.orientation()).map(|o| ("orientation", o.as_str().to_string())));
+    if let Some((w, h)) = media.and_then(|m| m.display_size()) {
+        params.extend([("width", w.to_string()), ("height", h.to_string())]);
+    }
+    params.extend(media.map(|m| m.rotation).filter(|r| *r != 0).map(|r| ("rotation", r.to_string())));
+    match side {
+        Some((side, cap)) => {
+            params.extend([("side", side.to_string()), ("cap", cap.to_string())]);
+            Explanation::new(RESOLUTION_LONG_EDGE, &params)
+        }
+        None => Explanation::new(RESOLUTION_ORIENTATION, &params),
+    }
+}
+
+pub fn subtitle(outcome: &SubtitleOutcome) -> Explanation {
+    let mut params = vec![("index", outcome.index.to_string()), ("codec", outcome.codec.clone())];
+    params.extend(outcome.sidecar.clone().map(|path| ("sidecar", path)));
+    let code = match &outcome.action {
+        SubtitleAction::Copy => SUBTITLE_COPIED,
+        SubtitleAction::Convert { codec } => {
+            params.push(("to", codec.clone()));
+            SUBTITLE_CONVERTED
+        }
+        SubtitleAction::Extract { path } => {
+            params.push(("path", path.clone()));
+            SUBTITLE_EXTRACTED
+        }
+        SubtitleAction::Drop => SUBTITLE_DROPPED,
+    };
+    Explanation::new(code, &params)
+}
+
+pub fn short_input(decision: &str) -> Explanation {
+    Explanation::new(SHORT_INPUT, &[("decision", decision.to_string())])
+}
+
+pub fn custom_filters(graph: &str) -> Explanation {
+    Explanation::new(FILTERS_CUSTOM, &[("graph", graph.to_string())])
+}
+
+pub fn source_detected(detection: &Detection) -> Explanation {
+    Explanation::new(SOURCE_DETECTED, &[("tool", detection.tool.as_str().to_string()), ("signals", detection.signals.join(","))])
+}
+
+pub fn source_fixup(tool: SourceTool, fixup: Fixup) -> Explanation {
+    Explanation::new(SOURCE_FIXUP, &[("tool", tool.as_str().to_string()), ("fixup", fixup.as_str().to_string())])
+}
+
+pub fn source_fixups_off(tool: SourceTool, reason: &str) -> Explanation {
+    Explanation::new(SOURCE_FIXUPS_OFF, &[("tool", tool.as_str().to_string()), ("reason", reason.to_string())])
+}
+
+pub fn timing_passthrough(flag: &str) -> Explanation {
+    Explanation::new(TIMING_PASSTHROUGH, &[("flag", flag.to_string())])
+}
+
+pub fn timing_cfr_skipped(tool: SourceTool) -> Explanation {
+    Explanation::new(TIMING_CFR_SKIPPED, &[("tool", tool.as_str().to_string())])
+}
+
+pub fn streams_mismatched(lengths: &StreamLengths, policy: DurationPolicy) -> Explanation {
+    Explanation::new(
+        TIMING_STREAMS_MISMATCHED,
+        &[
+            ("video_secs", format!("{:.1}", lengths.video_secs)),
+            ("audio_secs", format!("{:.1}", lengths.audio_secs)),
+            ("policy", policy.as_str().to_string()),
+        ],
+    )
+}
+
+pub fn loudness(measured: Option<&Measured>) -> Explanation {
+    match measured {
+        Some(m) => Explanation::new(
+            AUDIO_LOUDNESS_MEASURED,
+            &[("measured_i", m.input_i.trim().to_string()), ("offset", m.target_offset.trim().to_string())],
+        ),
+        None => Explanation::new(AUDIO_LOUDNESS_SINGLE_PASS, &[]),
+    }
+}
+
+pub fn audio(plan: &AudioPlan) -> Explanation {
+    let code = match plan.action {
+        AudioAction::Copied => AUDIO_COPIED,
+        AudioAction::Transcoded => AUDIO_TRANSCODED,
+        AudioAction::Removed => AUDIO_REMOVED,
+    };
+    Explanation::new(code, &[("encoder", plan.encoder.to_string()), ("reason", plan.reason.clone())])
+}
+
+pub fn fit_rung(report: &FitReport) -> Explanation {
+    let first = report.rungs.get(report.first_choice).map(|r| r.label()).unwrap_or_default();
+    Explanation::new(
+        FIT_RUNG,
+        &[
+            ("rung", report.used_label()),
+            ("predicted_rung", first),
+            ("attempts", report.attempts.to_string()),
+            ("cap_bytes", report.cap_bytes.to_string()),
+            ("output_bytes", report.output_bytes.to_string()),
+        ],
+    )
+}
+
+pub fn deferred(what: &str) -> Explanation {
+    Explanation::new(DEFERRED, &[("what", what.to_string())])
+}
+
+// ==========================================
+// DRY RUN
+// ==========================================
+// The decisions themselves, for whoever needs more than the explanations
+// (preview_preset).
+pub struct Prediction {
+    pub encoder: &'static str,
+    // The container's default audio encoder
+    pub audio: &'static str,
+    // What the audio tracks get (copied, transcoded, dropped); None for a
+    // surgical request or an input without audio
+    pub audio_plan: Option<AudioPlan>,
+    pub gpu: bool,
+    pub copy: bool,
+    pub single_frame_image: bool,
+    pub rate: QualityOptions,
+    pub two_pass: bool,
+    // As far as the probe can tell: deinterlacing and HDR are decided at encode
+    pub filters: VideoFilters,
+    pub explanations: Vec<Explanation>,
+}
+
+// What encode_video would decide for this request, from the probe alone.
+// Hardware detection is cached for the session, so asking for the GPU
+// encoder here costs the test encodes at most once.
+pub async fn predict(app: &AppHandle, request: &VideoCompressRequest, media: &MediaInfo) -> Vec<Explanation> {
+    resolve(app, request, media).await.explanations
+}
+
+pub async fn resolve(app: &AppHandle, request: &VideoCompressRequest, media: &MediaInfo) -> Prediction {
+    let options = &request.options;
+    let ext = Path::new(&request.output).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let copy = options.copies_video();
+    let short = media.is_short();
+    let mut filters = VideoFilters::from_options(options);
+    let mut why = vec![];
+
+    // A named vendor that doesn't work fails the job itself, not the prediction
+    let gpu = hardware::choose(app, options.codec, options.auto_gpu, options.preference()).await.unwrap_or(None);
+    let codecs = crate::container_codecs(&ext, options.codec, gpu);
+    let mut prediction = Prediction {
+        encoder: codecs.encoder,
+        audio: codecs.audio,
+        audio_plan: None,
+        gpu: codecs.gpu,
+        copy,
+        single_frame_image: false,
+        rate: QualityOptions::default(),
+        two_pass: false,
+        filters: VideoFilters::default(),
+        explanations: vec![],
+    };
+    if options.single_frame_as_image && media.is_single_frame() {
+        prediction.single_frame_image = true;
+        prediction.explanations = vec![Explanation::new(SINGLE_FRAME_IMAGE, &[])];
+        return prediction;
+    }
+    if copy {
+        why.push(Explanation::new(ENCODER_COPY, &[]));
+    } else {
+        why.push(container(&ext, codecs.encoder, codecs.audio, codecs.gpu));
+        let failed = hardware::get(app).await.failed_for(options.codec);
+        why.extend(gpu_fallback(options.auto_gpu_decides(), codecs.gpu, false, &ext, options.codec, &failed));
+        if media.has_video {
+            why.push(deferred("hdr"));
+        }
+    }
+
+    if media.has_video || options.max_long_edge.is_some() {
+        let side = options.max_long_edge.filter(|_| !copy).map(|cap| (filters.apply_long_edge(cap, media.orientation()), cap));
+        why.push(resolution(Some(media), side));
+    }
+
+    let rate = crate::resolve_rate(&options.rate, options.crf, options.resumable, codecs.encoder, short, copy);
+    if rate.single_pass_for_short {
+        why.push(short_input("single_pass"));
+    }
+    why.push(self::rate(&rate.options, codecs.encoder, copy, rate.two_pass));
+
+    let fields_requested = options.deinterlace || options.detect_telecine;
+    if fields_requested && !copy {
+        why.push(deferred("fields"));
+    } else {
+        why.push(fields(&FieldReport::default(), copy, false));
+    }
+    if short && options.detect_telecine {
+        why.push(short_input("skip_telecine_detection"));
+    }
+
+    let has_av = media.has_video && media.has_audio;
+    if has_av && options.av_offset_ms.is_none() && options.detect_av_offset && !short {
+        why.push(deferred("av_offset"));
+    } else {
+        why.push(av_sync(&AvSyncReport::default(), options.av_offset_ms, false, has_av));
+    }
+    if short && options.detect_av_offset {
+        why.push(short_input("skip_av_offset_detection"));
+    }
+    // Before any detected offset, which only the encode finds
+    let delay_secs = options.av_offset_ms.map_or(0.0, |ms| ms as f64 / 1000.0);
+    if let Some(lengths) = StreamLengths::mismatched(media, delay_secs).filter(|_| has_av) {
+        why.push(streams_mismatched(&lengths, options.duration_policy));
+    }
+    if options.normalize_audio && media.has_audio && !options.copy_only {
+        why.push(deferred("loudness"));
+    }
+    // As ffmpeg would pick the track without -maps; an error is the encode's to report
+    if !options.surgical && media.has_audio {
+        let tracks = audiotracks::plan(&media.streams, options.keep_all_streams, false);
+        let kept: Vec<_> = media.streams.iter().filter(|s| tracks.iter().any(|t| t.kept && t.index == s.index)).collect();
+        let context = AudioContext {
+            ext: &ext,
+            default_encoder: prediction.audio,
+            tracks: &kept,
+            must_encode: options.reencodes_audio(),
+            budget_kbps: options.rate.target_size_mb.or(options.rate.max_filesize_mb).map(|_| crate::quality::AUDIO_BUDGET_KBPS),
+        };
+        let plan = if options.copy_only { Ok(AudioPlan::copy("copy_only")) } else { audiomode::plan(&options.audio, &context) };
+        prediction.audio_plan = plan.ok();
+        why.extend(prediction.audio_plan.as_ref().map(audio));
+    }
+
+    if !options.surgical {
+        why.extend(subtitles::plan(&media.streams, &request.output, &ext, options.extract_incompatible_subs).iter().map(subtitle));
+    }
+    if !options.surgical && !options.resumable {
+        let sidecars = subtitles::sidecar_plan(&options.include_sidecar_subs, &request.input, &ext).unwrap_or_default();
+        why.extend(sidecars.iter().map(subtitle));
+    }
+    // Without deinterlacing, which only the encode decides
+    if options.filters.is_some() && !copy {
+        let name = Path::new(&request.input).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if let Some(graph) = filters.build(Some(media), &FieldReport::default(), &name, options.start_secs.unwrap_or(0.0)) {
+            why.push(custom_filters(&graph));
+        }
+    }
+    prediction.rate = rate.options;
+    prediction.two_pass = rate.two_pass;
+    prediction.filters = filters;
+    prediction.explanations = why;
+    prediction
+}
+
+// ==========================================
+// COMMAND: EXPLAIN JOB
+// ==========================================
+#[tauri::command]
+pub fn explain_job(app: AppHandle, job_id: u64) -> Result<Vec<Explanation>, String> {
+    queue::explanations(&app, job_id).ok_or_else(|| format!("Job {} not found", job_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::{EncoderPreference, HwCache, HwCapabilities};
+    use crate::interlace::ScanType;
+    use crate::jobtests::{run, Harness};
+    use crate::probe::StreamInfo;
+    use crate::quality::{Quality, QualityLevel};
+    use crate::request::VideoOptions;
+    use crate::VideoMode;
+    use tauri::Manager;
+
+    fn stream(kind: &str, codec: &str) -> StreamInfo {
+        StreamInfo { codec_type: kind.to_string(), codec_name: Some(codec.to_string()), ..Default::default() }
+    }
+
+    fn clip(secs: f64, width: u32, height: u32) -> MediaInfo {
+        MediaInfo {
+            duration: Some(secs),
+            width: Some(width),
+            height: Some(height),
+            fps: Some(30.0),
+            has_video: true,
+            has_audio: true,
+            streams: vec![StreamInfo { index: 0, ..stream("video", "h264") }, StreamInfo { index: 1, ..stream("audio", "aac") }],
+            ..Default::default()
+        }
+    }
+
+    // A harness whose hardware round already found `hw`, so nothing runs
+    fn harness(name: &str, hw: HwCapabilities) -> Harness {
+        let h = Harness::new(name, "{}");
+        h.handle().state::<HwCache>().seed(hw);
+        h
+    }
+
+    fn resolved(h: &Harness, output: &str, options: VideoOptions, media: &MediaInfo) -> Vec<Explanation> {
+        let (app, media) = (h.handle().clone(), media.clone());
+        let request = VideoCompressRequest::new(h.file("clip.mp4"), h.file(output), options);
+        run(async move { resolve(&app, &request, &media).await.explanations })
+    }
+
+    fn codes(why: &[Explanation]) -> Vec<&str> {
+        why.iter().map(|e| e.code.as_str()).collect()
+    }
+
+    fn find<'a>(why: &'a [Explanation], code: &str) -> &'a Explanation {
+        why.iter().find(|e| e.code == code).unwrap_or_else(|| panic!("no {} in {:?}", code, codes(why)))
+    }
+
+    fn param<'a>(e: &'a Explanation, key: &str) -> &'a str {
+        e.params.get(key).map(String::as_str).unwrap_or_else(|| panic!("{} has no {}", e.code, key))
+    }
+
+    // The reasons one setting left: its own codes, or a deferral to the encode
+    fn reasons<'a>(why: &'a [Explanation], prefix: &str, deferred: Option<&str>) -> Vec<&'a Explanation> {
+        why.iter().filter(|e| e.code.starts_with(prefix) || (e.code == DEFERRED && deferred.is_some_and(|d| e.params["what"] == d))).collect()
+    }
+
+    #[test]
+    fn every_setting_has_exactly_one_reason_across_the_resolver() {
+        let h = harness("explain-walk", HwCapabilities { failed: vec!["h264_nvenc".to_string()], ..Default::default() });
+        let rates = [
+            (None, QualityOptions::default()),
+            (Some(20), QualityOptions::default()),
+            (None, QualityOptions { quality: Some(Quality::Level(QualityLevel::High)), ..Default::default() }),
+            (None, QualityOptions { target_bitrate_kbps: Some(2000), ..Default::default() }),
+            (None, QualityOptions { max_filesize_mb: Some(50.0), ..Default::default() }),
+            (None, QualityOptions { target_size_mb: Some(20.0), ..Default::default() }),
+        ];
+        let mut walked = 0;
+        for ext in ["mp4", "mkv", "webm", "avi"] {
+            for video_mode in [VideoMode::Reencode, VideoMode::Copy] {
+                for auto_gpu in [false, true] {
+                    for (crf, rate) in &rates {
+                        for (deinterlace, max_long_edge) in [(false, None), (true, Some(720))] {
+                            for (av_offset_ms, detect_av_offset) in [(None, false), (Some(120), false), (None, true)] {
+                                for secs in [1.0, 60.0] {
+                                    let options = VideoOptions {
+                                        video_mode,
+                                        auto_gpu,
+                                        crf: *crf,
+                                        rate: rate.clone(),
+                                        deinterlace,
+                                        max_long_edge,
+                                        av_offset_ms,
+                                        detect_av_offset,
+                                        ..Default::default()
+                                    };
+                                    let why = resolved(&h, &format!("out.{}", ext), options, &clip(secs, 1920, 1080));
+                                    let encoder = why.iter().filter(|e| [ENCODER_COPY, ENCODER_CONTAINER, ENCODER_DEFAULT, ENCODER_GPU].contains(&e.code.as_str()));
+                                    assert_eq!(encoder.count(), 1, "{} {:?}", ext, codes(&why));
+                                    for (prefix, deferred) in [("rate.", None), ("fields.", Some("fields")), ("av_sync.", Some("av_offset")), ("resolution.", None)] {
+                                        assert_eq!(reasons(&why, prefix, deferred).len(), 1, "{} in {} {:?}", prefix, ext, codes(&why));
+                                    }
+                                    walked += 1;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        assert_eq!(walked, 4 * 2 * 2 * 6 * 2 * 3 * 2);
+        // All of it from the seeded capabilities
+        assert!(h.runs().is_empty());
+    }
+
+    #[test]
+    fn the_rate_reason_names_the_option_it_came_from() {
+        let h = harness("explain-rate", HwCapabilities::default());
+        let media = clip(60.0, 1920, 1080);
+        let rate = |crf: Option<u32>, rate: QualityOptions| {
+            let why = resolved(&h, "out.mp4", VideoOptions { crf, rate, ..Default::default() }, &media);
+            reasons(&why, "rate.", None)[0].clone()
+        };
+
+        let default = rate(None, QualityOptions::default());
+        assert_eq!((default.code.as_str(), param(&default, "encoder")), (RATE_CONTAINER_DEFAULT, "libx264"));
+        // The flat crf, unless rate.quality says otherwise
+        let legacy = rate(Some(20), QualityOptions::default());
+        assert_eq!((legacy.code.as_str(), param(&legacy, "crf")), (RATE_QUALITY, "20"));
+        let quality = rate(Some(20), QualityOptions { quality: Some(Quality::Crf(30)), ..Default::default() });
+        assert_eq!((quality.code.as_str(), param(&quality, "crf")), (RATE_QUALITY, "30"));
+        // A bitrate over a quality, a size over both, a target over a cap
+        let bitrate = rate(Some(20), QualityOptions { target_bitrate_kbps: Some(2000), ..Default::default() });
+        assert_eq!((bitrate.code.as_str(), param(&bitrate, "kbps")), (RATE_BITRATE, "2000"));
+        let cap = rate(None, QualityOptions { max_filesize_mb: Some(50.0), target_bitrate_kbps: Some(2000), ..Default::default() });
+        assert_eq!((cap.code.as_str(), param(&cap, "max_filesize_mb")), (RATE_SIZE_CAP, "50"));
+        let target = rate(None, QualityOptions { target_size_mb: Some(20.0), max_filesize_mb: Some(50.0), ..Default::default() });
+        assert_eq!((target.code.as_str(), param(&target, "target_size_mb")), (RATE_TWO_PASS, "20"));
+
+        // Resumable jobs and short clips get one pass; the short clip's
+        // target became a cap, and says so
+        let sized = QualityOptions { target_size_mb: Some(20.0), ..Default::default() };
+        let why = resolved(&h, "out.mp4", VideoOptions { resumable: true, rate: sized.clone(), ..Default::default() }, &media);
+        assert_eq!(reasons(&why, "rate.", None)[0].code, RATE_SINGLE_PASS);
+        let why = resolved(&h, "out.mp4", VideoOptions { rate: sized.clone(), ..Default::default() }, &clip(1.0, 1920, 1080));
+        assert_eq!(param(find(&why, SHORT_INPUT), "decision"), "single_pass");
+        assert_eq!(param(find(&why, RATE_SIZE_CAP), "max_filesize_mb"), "20");
+        // Nothing is encoded to rate
+        let why = resolved(&h, "out.mp4", VideoOptions { video_mode: VideoMode::Copy, rate: sized, ..Default::default() }, &media);
+        assert_eq!(reasons(&why, "rate.", None)[0].code, RATE_COPY);
+    }
+
+    #[test]
+    fn the_encoder_reason_names_the_container_the_codec_or_the_gpu() {
+        let media = clip(60.0, 1920, 1080);
+        let h = harness("explain-encoder", HwCapabilities { failed: vec!["h264_nvenc".to_string()], ..Default::default() });
+        let encoder = |output: &str, options: VideoOptions| resolved(&h, output, options, &media);
+
+        let why = encoder("out.mp4", VideoOptions::default());
+        assert_eq!(param(find(&why, ENCODER_DEFAULT), "encoder"), "libx264");
+        let why = encoder("out.mp4", VideoOptions { codec: VideoCodec::Hevc, ..Default::default() });
+        assert_eq!(param(find(&why, ENCODER_CONTAINER), "encoder"), "libx265");
+        let why = encoder("out.webm", VideoOptions::default());
+        assert_eq!(param(find(&why, ENCODER_CONTAINER), "container"), "webm");
+        let why = encoder("out.mkv", VideoOptions { video_mode: VideoMode::Copy, ..Default::default() });
+        assert_eq!(reasons(&why, "encoder.", None).len(), 1);
+        assert_eq!(codes(&why)[0], ENCODER_COPY);
+
+        // auto_gpu with nothing working says which encoders failed
+        let why = encoder("out.mp4", VideoOptions { auto_gpu: true, ..Default::default() });
+        find(&why, ENCODER_DEFAULT);
+        assert_eq!(param(find(&why, ENCODER_GPU_UNAVAILABLE), "failed"), "h264_nvenc");
+        // and a container that can't hold the codec says that instead
+        let why = encoder("out.avi", VideoOptions { auto_gpu: true, codec: VideoCodec::Hevc, ..Default::default() });
+        let unsupported = find(&why, ENCODER_GPU_UNSUPPORTED);
+        assert_eq!((param(unsupported, "container"), param(unsupported, "codec")), ("avi", "hevc"));
+
+        let working = HwCapabilities { working: vec!["h264_nvenc".to_string()], preferred: Some("h264_nvenc".to_string()), ..Default::default() };
+        let h = harness("explain-gpu", working);
+        let why = resolved(&h, "out.mp4", VideoOptions { auto_gpu: true, ..Default::default() }, &media);
+        assert_eq!(param(find(&why, ENCODER_GPU), "encoder"), "h264_nvenc");
+        assert!(!codes(&why).iter().any(|c| c.starts_with("encoder.gpu_")));
+        // encoder_preference decides instead of auto_gpu
+        let why = resolved(&h, "out.mp4", VideoOptions { auto_gpu: true, encoder_preference: Some(EncoderPreference::Cpu), ..Default::default() }, &media);
+        find(&why, ENCODER_DEFAULT);
+        assert!(!codes(&why).contains(&ENCODER_GPU_UNAVAILABLE));
+    }
+
+    #[test]
+    fn field_sync_and_size_reasons_follow_their_options() {
+        let h = harness("explain-settings", HwCapabilities::default());
+        let media = clip(60.0, 1920, 1080);
+        let walk = |options: VideoOptions, media: &MediaInfo| resolved(&h, "out.mp4", options, media);
+
+        let why = walk(VideoOptions::default(), &media);
+        for code in [FIELDS_NOT_REQUESTED, AV_NOT_REQUESTED, RESOLUTION_ORIENTATION] {
+            find(&why, code);
+        }
+        assert_eq!(param(find(&why, RESOLUTION_ORIENTATION), "orientation"), "landscape");
+
+        // Detection is the encode's to do
+        let why = walk(VideoOptions { deinterlace: true, detect_av_offset: true, ..Default::default() }, &media);
+        assert_eq!(reasons(&why, "fields.", Some("fields"))[0].code, DEFERRED);
+        assert_eq!(reasons(&why, "av_sync.", Some("av_offset"))[0].code, DEFERRED);
+        // unless a copy or a given offset settles it
+        let why = walk(VideoOptions { deinterlace: true, video_mode: VideoMode::Copy, av_offset_ms: Some(-80), detect_av_offset: true, ..Default::default() }, &media);
+        find(&why, FIELDS_COPY);
+        assert_eq!(param(find(&why, AV_GIVEN), "offset_ms"), "-80");
+        // A short clip skips it and says so
+        let why = walk(VideoOptions { detect_av_offset: true, ..Default::default() }, &clip(1.0, 1920, 1080));
+        find(&why, AV_NOT_REQUESTED);
+        assert_eq!(param(find(&why, SHORT_INPUT), "decision"), "skip_av_offset_detection");
+        // Nothing to line up without audio
+        let silent = MediaInfo { has_audio: false, streams: media.streams[..1].to_vec(), ..media.clone() };
+        find(&walk(VideoOptions { av_offset_ms: Some(100), ..Default::default() }, &silent), AV_NOT_APPLICABLE);
+
+        let why = walk(VideoOptions { max_long_edge: Some(720), ..Default::default() }, &media);
+        let capped = find(&why, RESOLUTION_LONG_EDGE);
+        assert_eq!((param(capped, "side"), param(capped, "cap")), ("width", "720"));
+        let why = walk(VideoOptions { max_long_edge: Some(720), ..Default::default() }, &clip(60.0, 1080, 1920));
+        assert_eq!(param(find(&why, RESOLUTION_LONG_EDGE), "side"), "height");
+    }
+
+    #[test]
+    fn the_encodes_own_findings_each_have_a_reason() {
+        let report = |detected, action| FieldReport { detected, action, stats: None };
+        assert_eq!(fields(&report(ScanType::Interlaced, FieldAction::Deinterlace), false, true).code, FIELDS_DEINTERLACED);
+        assert_eq!(fields(&report(ScanType::Telecine, FieldAction::InverseTelecine), false, true).code, FIELDS_INVERSE_TELECINE);
+        let progressive = fields(&report(ScanType::Progressive, FieldAction::None), false, true);
+        assert_eq!((progressive.code.as_str(), param(&progressive, "detected")), (FIELDS_PROGRESSIVE, "progressive"));
+        assert_eq!(fields(&report(ScanType::Interlaced, FieldAction::Deinterlace), true, true).code, FIELDS_COPY);
+
+        let sync = |applied_ms, estimated_ms, confidence| AvSyncReport { applied_ms, estimated_ms, confidence };
+        let detected = av_sync(&sync(Some(200), Some(200), Some(0.91)), None, true, true);
+        assert_eq!((param(&detected, "offset_ms"), param(&detected, "confidence")), ("200", "0.91"));
+        assert_eq!(av_sync(&sync(None, Some(200), Some(0.3)), None, true, true).code, AV_LOW_CONFIDENCE);
+        assert_eq!(av_sync(&sync(None, Some(0), Some(0.95)), None, true, true).code, AV_IN_SYNC);
+        // A given offset wins over whatever detection thought
+        assert_eq!(av_sync(&sync(Some(200), Some(200), Some(0.91)), Some(50), true, true).code, AV_GIVEN);
+    }
+}
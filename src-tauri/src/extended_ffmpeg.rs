@@ -0,0 +1,409 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::capabilities::CapabilityCache;
+use crate::events::{self, Event};
+use crate::ffmpeg::{self, FfmpegBinary, TrackedOutput};
+use crate::hardware::HwCache;
+use crate::settings::{ExtendedBuild, SettingsStore};
+
+// ==========================================
+// EXTENDED FFMPEG
+// ==========================================
+// Optional full-featured ffmpeg (libvmaf, libjxl, vidstab, ...) downloaded
+// into app_data_dir/ffmpeg-extended. The download lands in a `.part` file
+// (resumed with a Range request if interrupted; a 416 to that means it's
+// complete already) and only becomes the real executable after its SHA-256
+// matches the pinned checksum. It's re-verified at every startup before
+// being switched on.
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "kind")]
+pub enum DownloadError {
+    NotConfigured { platform: String },
+    Network { message: String },
+    HttpStatus { status: u16 },
+    ChecksumMismatch { expected: String, actual: String },
+    Io { message: String },
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::NotConfigured { platform } => {
+                write!(f, "No extended ffmpeg build is configured for {}", platform)
+            }
+            DownloadError::Network { message } => write!(f, "Download failed: {}", message),
+            DownloadError::HttpStatus { status } => write!(f, "Download failed: server returned HTTP {}", status),
+            DownloadError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Downloaded ffmpeg failed verification (expected sha256 {}, got {}); it was deleted",
+                expected, actual
+            ),
+            DownloadError::Io { message } => write!(f, "Could not save the download: {}", message),
+        }
+    }
+}
+
+fn io_err(e: impl fmt::Display) -> DownloadError {
+    DownloadError::Io { message: e.to_string() }
+}
+
+fn net_err(e: reqwest::Error) -> DownloadError {
+    DownloadError::Network { message: e.to_string() }
+}
+
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct DownloadProgress {
+    pub downloaded_bytes: u64,
+    pub total_bytes: Option<u64>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FfmpegInfo {
+    // "sidecar" | "extended"
+    pub active: String,
+    pub path: Option<String>,
+    // First line of `ffmpeg -version`
+    pub version: Option<String>,
+}
+
+pub fn platform_key() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+fn install_dir(app: &AppHandle) -> Result<PathBuf, DownloadError> {
+    let base = app.path().app_data_dir().map_err(io_err)?;
+    Ok(base.join("ffmpeg-extended"))
+}
+
+// Disk used by the downloaded build; 0 when there's none.
+pub fn installed_bytes(app: &AppHandle) -> u64 {
+    install_dir(app).map_or(0, |dir| crate::ladder::dir_size(&dir))
+}
+
+fn binary_name() -> &'static str {
+    if cfg!(windows) { "ffmpeg.exe" } else { "ffmpeg" }
+}
+
+fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 4 * 1024 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn verify(path: &Path, expected: &str) -> Result<(), DownloadError> {
+    let actual = sha256_file(path).map_err(io_err)?;
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(DownloadError::ChecksumMismatch { expected: expected.trim().to_lowercase(), actual })
+    }
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+// Points every ffmpeg call at `path` (or back at the sidecar) and drops
+// everything detected about the previous binary.
+fn switch_to(app: &AppHandle, path: Option<PathBuf>) {
+    app.state::<FfmpegBinary>().set_extended(path);
+    app.state::<CapabilityCache>().invalidate();
+    app.state::<HwCache>().invalidate();
+}
+
+// Downloads into `part`, continuing from whatever is already there.
+async fn fetch(app: &AppHandle, url: &str, part: &Path) -> Result<(), DownloadError> {
+    let already = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+    let client = reqwest::Client::new();
+    let mut request = client.get(url);
+    if already > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", already));
+    }
+    let mut response = request.send().await.map_err(net_err)?;
+
+    let status = response.status();
+    // 416 = there's nothing past the end of the `.part`: it was all there
+    // and only the rename was missed. The checksum says whether it's whole.
+    if already > 0 && status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+        println!("⏩ ffmpeg download already complete at {} bytes", already);
+        return Ok(());
+    }
+    // 206 = server honoured the range; 200 = it sent the whole file again
+    let resuming = status == reqwest::StatusCode::PARTIAL_CONTENT;
+    if !status.is_success() {
+        return Err(DownloadError::HttpStatus { status: status.as_u16() });
+    }
+    let mut downloaded = if resuming { already } else { 0 };
+    let total = response.content_length().map(|len| len + downloaded);
+    if resuming {
+        println!("⏩ Resuming ffmpeg download at {} bytes", already);
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resuming)
+        .truncate(!resuming)
+        .open(part)
+        .map_err(io_err)?;
+
+    while let Some(chunk) = response.chunk().await.map_err(net_err)? {
+        file.write_all(&chunk).map_err(io_err)?;
+        downloaded += chunk.len() as u64;
+        events::emit(app, Event::ExtendedFfmpegProgress(DownloadProgress { downloaded_bytes: downloaded, total_bytes: total }));
+    }
+    file.flush().map_err(io_err)?;
+    Ok(())
+}
+
+// Called from setup: switches to the extended build only if it's still there
+// and still matches its checksum. Hashing runs off the main thread; jobs use
+// the sidecar until it's done.
+pub fn activate_if_installed(app: &AppHandle) {
+    let Some(build) = app.state::<SettingsStore>().get().extended_ffmpeg else { return };
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let Ok(dir) = install_dir(&app) else { return };
+        let path = dir.join(binary_name());
+        match verify(&path, &build.sha256) {
+            Ok(()) => {
+                println!("🧰 Using extended ffmpeg at {}", path.display());
+                switch_to(&app, Some(path));
+            }
+            Err(e) => println!("⚠️ Not using extended ffmpeg, falling back to the bundled one: {}", e),
+        }
+    });
+}
+
+// ==========================================
+// COMMANDS
+// ==========================================
+// Where to get the build for this platform, and the checksum it must match.
+#[tauri::command]
+pub fn set_extended_ffmpeg_source(app: AppHandle, url: String, sha256: String) -> Result<(), String> {
+    let sha256 = sha256.trim().to_lowercase();
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("sha256 must be 64 hex characters".to_string());
+    }
+    app.state::<SettingsStore>()
+        .update(|s| {
+            s.extended_ffmpeg_builds.insert(platform_key(), ExtendedBuild { url, sha256 });
+        })
+        .map(|_| ())
+}
+
+#[tauri::command]
+pub async fn download_extended_ffmpeg(app: AppHandle) -> Result<FfmpegInfo, DownloadError> {
+    let store = app.state::<SettingsStore>();
+    let platform = platform_key();
+    let build: ExtendedBuild = store
+        .get()
+        .extended_ffmpeg_builds
+        .get(&platform)
+        .cloned()
+        .ok_or(DownloadError::NotConfigured { platform })?;
+
+    let dir = install_dir(&app)?;
+    fs::create_dir_all(&dir).map_err(io_err)?;
+    let part = dir.join(format!("{}.part", binary_name()));
+    fetch(&app, &build.url, &part).await?;
+
+    let checked = {
+        let part = part.clone();
+        let expected = build.sha256.clone();
+        tauri::async_runtime::spawn_blocking(move || verify(&part, &expected)).await.map_err(io_err)?
+    };
+    if let Err(e) = checked {
+        // Never keep (or resume onto) bytes that failed verification
+        let _ = fs::remove_file(&part);
+        return Err(e);
+    }
+
+    let path = dir.join(binary_name());
+    make_executable(&part).map_err(io_err)?;
+    fs::rename(&part, &path).map_err(io_err)?;
+    store
+        .update(|s| s.extended_ffmpeg = Some(build))
+        .map_err(|message| DownloadError::Io { message })?;
+    switch_to(&app, Some(path));
+    Ok(get_ffmpeg_info(app).await)
+}
+
+#[tauri::command]
+pub async fn get_ffmpeg_info(app: AppHandle) -> FfmpegInfo {
+    let extended = app.state::<FfmpegBinary>().extended();
+    let version = match ffmpeg::command(&app) {
+        Ok(cmd) => cmd
+            .args(["-version"])
+            .tracked_output(&app)
+            .await
+            .ok()
+            .and_then(|o| String::from_utf8_lossy(&o.stdout).lines().next().map(|l| l.to_string())),
+        Err(_) => None,
+    };
+    FfmpegInfo {
+        active: if extended.is_some() { "extended" } else { "sidecar" }.to_string(),
+        path: extended.map(|p| p.to_string_lossy().to_string()),
+        version,
+    }
+}
+
+#[tauri::command]
+pub async fn remove_extended_ffmpeg(app: AppHandle) -> Result<FfmpegInfo, String> {
+    switch_to(&app, None);
+    app.state::<SettingsStore>().update(|s| s.extended_ffmpeg = None)?;
+    if let Ok(dir) = install_dir(&app) {
+        let _ = fs::remove_dir_all(dir);
+    }
+    Ok(get_ffmpeg_info(app).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{run, Harness};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // What the "build" is: a script, so switching to it runs
+    const BUILD: &[u8] = b"#!/bin/sh\necho 'ffmpeg version extended-test'\n# padding to resume into\n";
+
+    // Serves BUILD at any path, one request per connection, honouring
+    // `Range: bytes=N-` like a CDN does (416 past the end). Returns the URL
+    // and the Range header of every request.
+    fn serve_build() -> (String, Arc<Mutex<Vec<Option<String>>>>) {
+        let ranges = Arc::new(Mutex::new(vec![]));
+        let url = run({
+            let ranges = ranges.clone();
+            async move {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let url = format!("http://{}/ffmpeg", listener.local_addr().unwrap());
+                tauri::async_runtime::spawn(async move {
+                    while let Ok((mut stream, _)) = listener.accept().await {
+                        let mut head = vec![];
+                        while !head.ends_with(b"\r\n\r\n") {
+                            let mut byte = [0u8; 1];
+                            if stream.read(&mut byte).await.unwrap_or(0) == 0 {
+                                break;
+                            }
+                            head.push(byte[0]);
+                        }
+                        let head = String::from_utf8_lossy(&head).to_lowercase();
+                        let range = head.lines().find_map(|l| l.strip_prefix("range: bytes=")).map(|r| r.trim_end_matches('-').to_string());
+                        ranges.lock().unwrap().push(range.clone());
+                        let from: usize = range.map_or(0, |r| r.parse().unwrap());
+                        let (status, body) = match from {
+                            0 => ("200 OK", BUILD),
+                            n if n < BUILD.len() => ("206 Partial Content", &BUILD[n..]),
+                            _ => ("416 Range Not Satisfiable", &b""[..]),
+                        };
+                        let reply = format!("HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n", status, body.len());
+                        let _ = stream.write_all(&[reply.as_bytes(), body].concat()).await;
+                    }
+                });
+                url
+            }
+        });
+        (url, ranges)
+    }
+
+    fn sha256_hex(bytes: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(bytes))
+    }
+
+    fn configured(h: &Harness, url: &str, sha256: &str) -> PathBuf {
+        set_extended_ffmpeg_source(h.handle().clone(), url.to_string(), sha256.to_string()).unwrap();
+        let dir = install_dir(h.handle()).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn download(h: &Harness) -> Result<FfmpegInfo, DownloadError> {
+        let app = h.handle().clone();
+        run(async move { download_extended_ffmpeg(app).await })
+    }
+
+    #[test]
+    fn an_interrupted_download_resumes_from_the_part_file() {
+        let h = Harness::new("extended-resume", "{}");
+        let (url, ranges) = serve_build();
+        let dir = configured(&h, &url, &sha256_hex(BUILD));
+        fs::write(dir.join(format!("{}.part", binary_name())), &BUILD[..20]).unwrap();
+
+        download(&h).unwrap();
+        assert_eq!(*ranges.lock().unwrap(), [Some("20".to_string())]);
+        assert_eq!(fs::read(dir.join(binary_name())).unwrap(), BUILD);
+        assert!(!dir.join(format!("{}.part", binary_name())).exists());
+        assert_eq!(h.handle().state::<FfmpegBinary>().extended(), Some(dir.join(binary_name())));
+    }
+
+    #[test]
+    fn a_complete_part_file_is_verified_and_installed() {
+        let h = Harness::new("extended-complete", "{}");
+        let (url, ranges) = serve_build();
+        let dir = configured(&h, &url, &sha256_hex(BUILD));
+        fs::write(dir.join(format!("{}.part", binary_name())), BUILD).unwrap();
+
+        download(&h).unwrap();
+        // The server had nothing past the end, and that's not an error
+        assert_eq!(*ranges.lock().unwrap(), [Some(BUILD.len().to_string())]);
+        assert_eq!(fs::read(dir.join(binary_name())).unwrap(), BUILD);
+        assert!(h.handle().state::<SettingsStore>().get().extended_ffmpeg.is_some());
+    }
+
+    #[test]
+    fn a_checksum_mismatch_deletes_the_download() {
+        let h = Harness::new("extended-mismatch", "{}");
+        let (url, _) = serve_build();
+        let dir = configured(&h, &url, &"0".repeat(64));
+
+        let error = download(&h).unwrap_err();
+        assert!(matches!(&error, DownloadError::ChecksumMismatch { actual, .. } if *actual == sha256_hex(BUILD)), "{:?}", error);
+        assert!(error.to_string().contains("it was deleted"));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        assert_eq!(h.handle().state::<FfmpegBinary>().extended(), None);
+        assert!(h.handle().state::<SettingsStore>().get().extended_ffmpeg.is_none());
+    }
+
+    #[test]
+    fn a_full_part_file_that_doesnt_match_is_deleted_too() {
+        let h = Harness::new("extended-full-mismatch", "{}");
+        let (url, _) = serve_build();
+        let dir = configured(&h, &url, &sha256_hex(BUILD));
+        // As long as the build, but not it: the 416 doesn't make it trusted
+        let corrupt = vec![b'x'; BUILD.len()];
+        fs::write(dir.join(format!("{}.part", binary_name())), &corrupt).unwrap();
+
+        let error = download(&h).unwrap_err();
+        assert!(matches!(error, DownloadError::ChecksumMismatch { .. }), "{:?}", error);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        // Nothing left to resume onto, so the next try starts over
+        download(&h).unwrap();
+        assert_eq!(fs::read(dir.join(binary_name())).unwrap(), BUILD);
+    }
+}
@@ -0,0 +1,562 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::async_runtime::Receiver;
+use tokio_util::sync::CancellationToken;
+use tauri::Manager;
+use tauri_plugin_shell::ShellExt;
+use tauri_plugin_shell::process::{Command, CommandEvent};
+
+use crate::AppHandle;
+use crate::cancel;
+use crate::errors;
+use crate::events::{self, Event};
+use crate::flood::{self, Collapser, StderrLog};
+use crate::hardware;
+use crate::joblog;
+use crate::pause;
+use crate::procgroup::{self, TrackedChild};
+use crate::progress::{self, ProgressTracker};
+use crate::queue;
+use crate::resources::{self, MemoryGuard, DEFAULT_MAX_MEMORY_MB};
+use crate::settings::SettingsStore;
+use crate::speedseries::{self, SpeedSample};
+use crate::support;
+
+// Start of the error a job fails with when the memory guard kills it
+pub const MEMORY_LIMIT_ERROR: &str = "MemoryLimitExceeded";
+// Start of the error when no ffmpeg can be started at all (video jobs need
+// one; images fall back to the native backend, see native_image.rs)
+pub const FFMPEG_MISSING_ERROR: &str = "FfmpegMissing";
+// Start of the error when ffmpeg dies from a signal nobody here sent
+pub const KILLED_BY_SIGNAL: &str = "KilledBySignal";
+
+// Start of the error when ffmpeg fails on a line that points at the
+// hardware encoder; auto_gpu jobs are run again on the CPU (run_video_job)
+pub const HW_ENCODE_FAILED: &str = "HardwareEncodeFailed";
+// The 256x256 test encode passes and the real input doesn't: 10-bit or
+// odd pixel formats, sizes older chips can't do, sessions running out
+const HW_FAILURE_PATTERNS: &[&str] = &[
+    "No capable devices found",
+    "No NVENC capable devices found",
+    "OpenEncodeSessionEx failed",
+    "10 bit encode not supported",
+    "Impossible to convert between the formats",
+    "Frame Dimension less than the minimum supported value",
+    "Error creating a MFX session",
+    "Failed to initialise VAAPI connection",
+    "Error while opening encoder",
+];
+
+fn missing(e: impl std::fmt::Display) -> String {
+    format!("{}: ffmpeg couldn't be started ({})", FFMPEG_MISSING_ERROR, e)
+}
+
+// Debug builds started with this pointing at a stub-ffmpeg build run it in
+// place of both ffmpeg and ffprobe, so whole jobs (arg building, progress,
+// cancellation, temp files) can be driven without real media. See
+// src/bin/stub-ffmpeg.rs for the scenario format.
+pub const STUB_ENV: &str = "COMPRESSIO_STUB_FFMPEG";
+
+fn stub_from_env() -> Option<PathBuf> {
+    if !cfg!(debug_assertions) {
+        return None;
+    }
+    let path = PathBuf::from(std::env::var_os(STUB_ENV)?);
+    println!("🧪 Running ffmpeg and ffprobe through the stub at {}", path.display());
+    Some(path)
+}
+
+// Which ffmpeg binary jobs run (managed state). None means the bundled sidecar;
+// Some is a downloaded build that has already passed checksum verification.
+// The stub, when set, wins over both.
+pub struct FfmpegBinary {
+    extended: Mutex<Option<PathBuf>>,
+    stub: Option<PathBuf>,
+    // The stub's scenario, when it isn't STUB_FFMPEG_SCENARIO from the
+    // environment: the job tests give every app its own
+    scenario: Option<PathBuf>,
+}
+
+impl Default for FfmpegBinary {
+    fn default() -> Self {
+        FfmpegBinary { extended: Mutex::new(None), stub: stub_from_env(), scenario: None }
+    }
+}
+
+impl FfmpegBinary {
+    #[cfg(test)]
+    pub fn stubbed(stub: PathBuf, scenario: PathBuf) -> Self {
+        FfmpegBinary { extended: Mutex::new(None), stub: Some(stub), scenario: Some(scenario) }
+    }
+
+    pub fn stub(&self) -> Option<PathBuf> {
+        self.stub.clone()
+    }
+
+    // The stub, told which tool it's standing in for
+    fn stub_command(&self, app: &AppHandle, tool: &str) -> Option<Command> {
+        let command = app.shell().command(self.stub.as_ref()?).env("STUB_TOOL", tool);
+        Some(match &self.scenario {
+            Some(scenario) => command.env("STUB_FFMPEG_SCENARIO", scenario),
+            None => command,
+        })
+    }
+
+    pub fn extended(&self) -> Option<PathBuf> {
+        self.extended.lock().unwrap().clone()
+    }
+
+    pub fn set_extended(&self, path: Option<PathBuf>) {
+        *self.extended.lock().unwrap() = path;
+    }
+}
+
+// Numbers in ffmpeg's output must not depend on the user's locale (a build
+// that calls setlocale prints "2,5x" under de_DE). The parsers in
+// progress.rs take both separators anyway.
+fn c_locale(command: Command) -> Command {
+    command.env("LC_ALL", "C").env("LANG", "C")
+}
+
+// Every ffmpeg invocation goes through here so switching binaries is one
+// place, and so nothing new starts once the job has been cancelled.
+pub fn command(app: &AppHandle) -> Result<Command, String> {
+    cancel::check()?;
+    let binary = app.try_state::<FfmpegBinary>();
+    if let Some(stub) = binary.as_ref().and_then(|b| b.stub_command(app, "ffmpeg")) {
+        return Ok(stub);
+    }
+    let command = match binary.and_then(|b| b.extended()) {
+        Some(path) => app.shell().command(path),
+        None => app.shell().sidecar("ffmpeg").map_err(missing)?,
+    };
+    Ok(c_locale(command))
+}
+
+// Same for ffprobe, which always comes from the bundle.
+pub fn ffprobe_command(app: &AppHandle) -> Result<Command, String> {
+    cancel::check()?;
+    if let Some(stub) = app.try_state::<FfmpegBinary>().and_then(|b| b.stub_command(app, "ffprobe")) {
+        return Ok(stub);
+    }
+    app.shell().sidecar("ffprobe").map(c_locale).map_err(|e| e.to_string())
+}
+
+// A running ffmpeg. Events are read through `next`, which gives up with
+// cancel::CANCELLED as soon as the job's token fires; dropping a Sidecar
+// whose process hasn't exited kills its process group.
+pub struct Sidecar {
+    rx: Receiver<CommandEvent>,
+    child: Option<TrackedChild>,
+    token: Option<CancellationToken>,
+}
+
+impl Sidecar {
+    pub fn pid(&self) -> Option<u32> {
+        self.child.as_ref().map(|c| c.pid())
+    }
+
+    pub async fn next(&mut self) -> Result<Option<CommandEvent>, String> {
+        let event = match next_event(&mut self.rx, self.token.as_ref()).await {
+            Ok(event) => event,
+            Err(e) => {
+                self.kill();
+                return Err(e);
+            }
+        };
+        if matches!(event, None | Some(CommandEvent::Terminated(_))) {
+            // Exited on its own: just unregister
+            self.child.take();
+        }
+        Ok(event)
+    }
+
+    pub fn kill(&mut self) {
+        if let Some(child) = self.child.take() {
+            child.kill();
+        }
+    }
+}
+
+impl Drop for Sidecar {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+// The next event from a running child, or cancel::CANCELLED if the token
+// fires first. Both Sidecar and tracked_output read through this.
+async fn next_event(rx: &mut Receiver<CommandEvent>, token: Option<&CancellationToken>) -> Result<Option<CommandEvent>, String> {
+    match token {
+        Some(token) => tokio::select! {
+            event = rx.recv() => Ok(event),
+            _ = token.cancelled() => Err(cancel::CANCELLED.to_string()),
+        },
+        None => Ok(rx.recv().await),
+    }
+}
+
+// Spawns the child into its own group and registers it, so cancelling takes
+// everything it started.
+// The job's thread cap and priority go on it here too (see resources.rs).
+pub fn spawn(app: &AppHandle, args: Vec<String>) -> Result<Sidecar, String> {
+    spawn_limited(app, args, None)
+}
+
+// `spawn` with the kernel memory cap of strict_memory_limit, where there is one.
+fn spawn_limited(app: &AppHandle, args: Vec<String>, hard_limit_mb: Option<u64>) -> Result<Sidecar, String> {
+    let args = resources::with_threads(args);
+    support::check_command(&args)?;
+    // Before the log, so it only lists what really ran
+    let command = command(app)?;
+    joblog::record_command(app, "ffmpeg", &args);
+    let mut command: std::process::Command = command.args(args).into();
+    let limited = hard_limit_mb.is_some_and(|mb| resources::hard_limit(&mut command, mb));
+    if hard_limit_mb.is_some() && !limited {
+        println!("⚠️ No hard memory limit on this OS, relying on RSS sampling only");
+    }
+    let (rx, child) = match procgroup::spawn(command) {
+        Ok(spawned) => spawned,
+        Err(e) if limited && e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(format!("ffmpeg couldn't be started with the strict memory limit ({})", e))
+        }
+        Err(e) => return Err(missing(e)),
+    };
+    resources::apply_priority(child.pid());
+    Ok(Sidecar { rx, child: Some(TrackedChild::new(app, child)), token: cancel::current() })
+}
+
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct ProgressPayload {
+    pub percent: Option<f32>,
+    pub out_time_secs: f64,
+    pub total_secs: Option<f64>,
+    pub speed: Option<f64>,
+    pub fps: Option<f32>,
+    pub out_size_bytes: Option<u64>,
+    pub eta_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bottleneck: Option<&'static str>,
+    // What the output is heading for at this rate (see progress::ProgressUpdate)
+    pub projected_size_bytes: Option<u64>,
+    // The point this update added to the job's speed series (see speedseries.rs)
+    pub sample: Option<SpeedSample>,
+}
+
+// `size-warning`: a quarter or more into the encode, the projected size is
+// over 120% of max_filesize_mb. Once per run, so the UI can offer to cancel
+// and retry at a lower bitrate.
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct SizeWarning {
+    pub job_id: Option<u64>,
+    pub projected_size_bytes: u64,
+    pub max_size_bytes: u64,
+    pub percent: Option<f32>,
+}
+
+// ==========================================
+// HELPER: RUN FFMPEG AND REPORT PROGRESS
+// ==========================================
+// Spawns the sidecar, forwards raw stderr on `ffmpeg-progress` (like the
+// single-file commands do) and a percentage as `progress_event` (one of the
+// Event variants taking a ProgressPayload) computed by `tracker`. The tracker is handed back so callers can see what it learned
+// (e.g. that the probed duration was wrong).
+pub async fn run_with_progress(
+    app: &AppHandle,
+    args: Vec<String>,
+    mut tracker: ProgressTracker,
+    progress_event: fn(ProgressPayload) -> Event,
+) -> Result<ProgressTracker, String> {
+    // Only a hardware encode's failures get HW_ENCODE_FAILED
+    let hw_encode = args.windows(2).any(|w| w[0] == "-c:v" && hardware::is_hardware(&w[1]));
+    let (limit_mb, strict) = app
+        .try_state::<SettingsStore>()
+        .map(|s| s.get())
+        .map(|s| (s.max_memory_mb, s.strict_memory_limit))
+        .unwrap_or((DEFAULT_MAX_MEMORY_MB, false));
+    let job_id = queue::running_job_id();
+    // Held until this run ends, however it ends
+    let _session = match hw_encode {
+        true => Some(hardware::session(app, job_id).await?),
+        false => None,
+    };
+    let mut sidecar = spawn_limited(app, args, strict.then_some(limit_mb))?;
+    let pid = sidecar.pid().unwrap_or_default();
+    // For the tracker: pauses that happen during this run
+    let started = Instant::now();
+    let paused_before = job_id.map_or(Duration::ZERO, |id| pause::paused_total(app, id));
+
+    let mut guard = MemoryGuard::new(pid, limit_mb);
+    let mut sampler = tokio::time::interval(Duration::from_secs(1));
+
+    let mut last_log_error = String::from("Unknown FFmpeg Error");
+    // The first line that says what went wrong, and the end of stderr (see errors.rs)
+    let mut telling: Option<String> = None;
+    let mut recent: VecDeque<String> = VecDeque::with_capacity(errors::DETAIL_LINES);
+    let mut hw_failure: Option<String> = None;
+    // Floods of identical errors are collapsed and the log is capped (see flood.rs)
+    let mut collapser = Collapser::default();
+    let mut log = StderrLog::default();
+    let abort_on_flood = flood::abort_enabled(app);
+
+    loop {
+        let event = tokio::select! {
+            event = sidecar.next() => match event? {
+                Some(event) => event,
+                None => break,
+            },
+            _ = sampler.tick() => {
+                if guard.sample() {
+                    sidecar.kill();
+                    save_log(app, &mut collapser, &mut log);
+                    return Err(format!(
+                        "{}: ffmpeg reached {} MB (limit {} MB)",
+                        MEMORY_LIMIT_ERROR,
+                        guard.peak_mb(),
+                        limit_mb
+                    ));
+                }
+                continue;
+            }
+        };
+        match event {
+            CommandEvent::Stderr(line_bytes) => {
+                let chunk = String::from_utf8_lossy(&line_bytes).to_string();
+                let at = tracker.last_time();
+                tracker.stderr_warnings.observe(&chunk, at);
+                if tracker.stderr_warnings.give_up(abort_on_flood) {
+                    sidecar.kill();
+                    save_log(app, &mut collapser, &mut log);
+                    return Err(format!(
+                        "{}: over {} decode errors a second for {} seconds, the input is too damaged to finish ({})",
+                        flood::TOO_MANY_DECODE_ERRORS,
+                        tracker.stderr_warnings.flood_threshold(),
+                        flood::FLOOD_SECS,
+                        last_log_error
+                    ));
+                }
+                if let Some(id) = job_id {
+                    tracker.set_paused(started.elapsed(), pause::paused_total(app, id).saturating_sub(paused_before));
+                }
+                if let Some(update) = tracker.update(&chunk) {
+                    queue::report_progress(app, update.percent);
+                    let sample = speedseries::record(app, &update);
+                    events::emit(app, progress_event(ProgressPayload {
+                        percent: update.percent,
+                        out_time_secs: update.out_time_secs,
+                        total_secs: tracker.total_secs(),
+                        speed: update.speed,
+                        fps: update.fps,
+                        out_size_bytes: update.out_size_bytes,
+                        eta_secs: update.eta_secs,
+                        bottleneck: update.bottleneck,
+                        projected_size_bytes: update.projected_size_bytes,
+                        sample,
+                    }));
+                    if let (true, Some(projected), Some(cap)) = (update.size_warning, update.projected_size_bytes, tracker.size_cap_bytes()) {
+                        println!("📏 Output is heading for {} MB, over the {} MB cap", projected / (1024 * 1024), cap / (1024 * 1024));
+                        events::emit(app, Event::SizeWarning(SizeWarning { job_id, projected_size_bytes: projected, max_size_bytes: cap, percent: update.percent }));
+                    }
+                }
+                for l in chunk.lines().filter(|l| !l.trim().is_empty()) {
+                    // Progress lines differ only in their numbers; they're never a "repeat"
+                    let is_progress = progress::is_report(l);
+                    let passed = if is_progress { vec![l.to_string()] } else { collapser.push(l) };
+                    for line in passed {
+                        if hw_encode && hw_failure.is_none() && HW_FAILURE_PATTERNS.iter().any(|p| line.contains(p)) {
+                            hw_failure = Some(line.clone());
+                        }
+                        log.push(&line);
+                        joblog::record_line(app, &line);
+                        events::emit(app, Event::FfmpegProgress(line.clone()));
+                        // A progress line never explains a failure
+                        if is_progress {
+                            continue;
+                        }
+                        if telling.is_none() && errors::TELLING_PATTERNS.iter().any(|p| line.contains(p)) {
+                            telling = Some(line.clone());
+                        }
+                        if recent.len() == errors::DETAIL_LINES {
+                            recent.pop_front();
+                        }
+                        recent.push_back(line.clone());
+                        last_log_error = line;
+                    }
+                }
+            }
+            CommandEvent::Terminated(payload) => {
+                save_log(app, &mut collapser, &mut log);
+                if let Some(code) = payload.code {
+                    if code != 0 {
+                        if let Some(job_id) = job_id {
+                            errors::keep(app, job_id, recent.drain(..).collect());
+                        }
+                        let reason = telling.take().unwrap_or(last_log_error);
+                        if let Some(line) = hw_failure {
+                            return Err(format!("{}: {} (Code {}: {})", HW_ENCODE_FAILED, line, code, reason));
+                        }
+                        return Err(format!("Error (Code {}): {}", code, reason));
+                    }
+                } else if let Some(signal) = payload.signal {
+                    // Killed from outside (a crash, the OOM killer): what it wrote is unfinished
+                    cancel::check()?;
+                    if let Some(job_id) = job_id {
+                        errors::keep(app, job_id, recent.drain(..).collect());
+                    }
+                    return Err(format!("{}: ffmpeg died from signal {} ({})", KILLED_BY_SIGNAL, signal, telling.take().unwrap_or(last_log_error)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Done is done, even when the total was never known
+    tracker.finish();
+    events::emit(app, progress_event(ProgressPayload {
+        percent: Some(tracker.finished_percent()),
+        out_time_secs: tracker.last_time(),
+        total_secs: tracker.total_secs(),
+        speed: None,
+        fps: None,
+        out_size_bytes: tracker.last_size_bytes(),
+        eta_secs: Some(0.0),
+        bottleneck: None,
+        projected_size_bytes: tracker.last_size_bytes(),
+        sample: None,
+    }));
+    tracker.stderr_tail = recent.into_iter().collect();
+    Ok(tracker)
+}
+
+// End of a run: the pending "repeated" note, then the job's log file.
+fn save_log(app: &AppHandle, collapser: &mut Collapser, log: &mut StderrLog) {
+    if let Some(note) = collapser.flush() {
+        log.push(&note);
+    }
+    if let Some(job_id) = queue::current_job_id() {
+        flood::write_job_log(app, job_id, log);
+    }
+    *log = StderrLog::default();
+}
+
+// What Command::output gives, for a child that's registered while it runs,
+// so kill_ffmpeg and closing the window stop probes and analysis passes
+// too, not only encodes. Cancelling the job kills it the same way, and the
+// run fails with an io error whose message is cancel::CANCELLED.
+pub struct Output {
+    pub code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl Output {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+pub trait TrackedOutput {
+    fn tracked_output(self, app: &AppHandle) -> impl std::future::Future<Output = Result<Output, tauri_plugin_shell::Error>> + Send;
+}
+
+impl TrackedOutput for Command {
+    // Same collection as the plugin's `output` (stdout / stderr line by
+    // line, each line ending in a newline)
+    async fn tracked_output(self, app: &AppHandle) -> Result<Output, tauri_plugin_shell::Error> {
+        let (mut rx, child) = procgroup::spawn(self)?;
+        let tracked = TrackedChild::new(app, child);
+        let token = cancel::current();
+        let mut output = Output { code: None, stdout: vec![], stderr: vec![] };
+        loop {
+            let event = match next_event(&mut rx, token.as_ref()).await {
+                Ok(Some(event)) => event,
+                Ok(None) => break,
+                Err(e) => {
+                    tracked.kill();
+                    return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, e).into());
+                }
+            };
+            match event {
+                CommandEvent::Terminated(payload) => output.code = payload.code,
+                CommandEvent::Stdout(line) => {
+                    output.stdout.extend(line);
+                    output.stdout.push(b'\n');
+                }
+                CommandEvent::Stderr(line) => {
+                    output.stderr.extend(line);
+                    output.stderr.push(b'\n');
+                }
+                _ => {}
+            }
+        }
+        Ok(output)
+    }
+}
+
+// Short helper runs (extractions, probes of our own outputs) that don't need progress.
+pub async fn run_quiet(app: &AppHandle, args: Vec<String>) -> Result<(), String> {
+    support::check_command(&args)?;
+    joblog::record_command(app, "ffmpeg", &args);
+    let output = command(app)?
+        .args(args)
+        .tracked_output(app)
+        .await
+        .map_err(missing)?;
+    if output.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        for line in stderr.lines() {
+            joblog::record_line(app, line);
+        }
+        Err(stderr.lines().last().unwrap_or("Unknown FFmpeg Error").to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tauri::async_runtime::channel;
+    use tauri_plugin_shell::process::TerminatedPayload;
+
+    #[tokio::test]
+    async fn a_run_that_never_ends_gives_up_when_cancelled() {
+        // Pass 1 of a two-pass encode: it has printed something and is
+        // still going when the job is cancelled
+        let (tx, mut rx) = channel(8);
+        tx.send(CommandEvent::Stderr(b"frame=  10 fps=5.0 time=00:00:01.00".to_vec())).await.unwrap();
+        let token = CancellationToken::new();
+        assert!(matches!(next_event(&mut rx, Some(&token)).await, Ok(Some(CommandEvent::Stderr(_)))));
+
+        let cancel = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            cancel.cancel();
+        });
+        let next = tokio::time::timeout(Duration::from_secs(5), next_event(&mut rx, Some(&token))).await;
+        assert_eq!(next.expect("cancel wasn't noticed").unwrap_err(), cancel::CANCELLED);
+        drop(tx);
+    }
+
+    #[tokio::test]
+    async fn events_come_through_without_a_token() {
+        let (tx, mut rx) = channel(8);
+        tx.send(CommandEvent::Terminated(TerminatedPayload { code: Some(0), signal: None })).await.unwrap();
+        drop(tx);
+        assert!(matches!(next_event(&mut rx, None).await, Ok(Some(CommandEvent::Terminated(_)))));
+        assert!(matches!(next_event(&mut rx, None).await, Ok(None)));
+    }
+
+    #[test]
+    fn failures_after_a_cancel_are_cancellations() {
+        let token = CancellationToken::new();
+        assert_eq!(cancel::settle::<()>(&token, Err("Probe failed".to_string())).unwrap_err(), "Probe failed");
+        token.cancel();
+        assert_eq!(cancel::settle::<()>(&token, Err("Probe failed".to_string())).unwrap_err(), cancel::CANCELLED);
+        assert_eq!(cancel::settle(&token, Ok(1)), Ok(1));
+    }
+}
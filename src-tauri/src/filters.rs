@@ -0,0 +1,449 @@
+use serde::{Deserialize, Serialize};
+
+use crate::filterspec::FilterSpec;
+use crate::hdr;
+use crate::interlace::{FieldAction, FieldReport};
+use crate::overlay::{self, escape_option_value, TextOverlay};
+use crate::probe::{MediaInfo, Orientation};
+use crate::request::VideoOptions;
+
+pub const MAX_BLUR_REGIONS: usize = 8;
+const DEFAULT_BLUR_STRENGTH: u32 = 10;
+// Background blur for pad_style blur; strong enough that nothing in it reads
+const CANVAS_BLUR_STRENGTH: u32 = 20;
+
+// Rectangle to blur, in source pixel coordinates.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlurRegion {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+    // boxblur radius; capped to what the region size allows
+    #[serde(default)]
+    pub strength: Option<u32>,
+}
+
+// What fills the bars when the picture doesn't match the canvas shape.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PadStyle {
+    // Plain bars in `color` (black unless set)
+    #[default]
+    Color,
+    // The frame itself, scaled up to fill the canvas and blurred, like the
+    // social apps do
+    Blur,
+}
+
+// Fit the picture into a fixed `width`x`height` canvas (a 1080x1920 story,
+// a 1920x1080 slide): scaled to fit, up or down, with bars on two sides.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FitCanvas {
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub pad_style: PadStyle,
+    // Bar color for pad_style color; same forms as the filter spec's pad
+    #[serde(default)]
+    pub color: Option<String>,
+}
+
+// Per-job picture filters. Kept together so the encode pipeline builds one
+// `-vf` chain in a fixed order.
+#[derive(Clone, Debug, Default)]
+pub struct VideoFilters {
+    pub overlay_text: Option<TextOverlay>,
+    pub blur_regions: Vec<BlurRegion>,
+    pub deinterlace: bool,
+    // Look for 3:2 pulldown and undo it (see interlace::resolve)
+    pub detect_telecine: bool,
+    // Downscale caps, applied last so overlays and blur use source coordinates.
+    // A long-edge cap is folded into these once the orientation is known
+    // (see apply_long_edge)
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    // Frame rate cap; sources at or below it keep theirs
+    pub max_fps: Option<f64>,
+    // Drop Dolby Vision / HDR10+ side data from decoded frames (see hdr.rs)
+    pub strip_dynamic_hdr: bool,
+    // Take PQ / HLG down to SDR before anything draws on or scales the frame
+    pub tonemap: bool,
+    // The request's own chain (see filterspec.rs)
+    pub custom: Option<FilterSpec>,
+    // Final canvas, after the caps
+    pub fit_canvas: Option<FitCanvas>,
+}
+
+impl VideoFilters {
+    // What the request asks for, before the probe adds anything (long-edge
+    // caps, HDR stripping).
+    pub fn from_options(options: &VideoOptions) -> Self {
+        VideoFilters {
+            overlay_text: options.overlay_text.clone(),
+            blur_regions: options.blur_regions.clone(),
+            deinterlace: options.deinterlace,
+            detect_telecine: options.detect_telecine,
+            max_width: options.max_width,
+            max_height: options.max_height,
+            max_fps: options.max_fps,
+            strip_dynamic_hdr: false,
+            tonemap: false,
+            custom: options.filters.clone(),
+            fit_canvas: options.fit_canvas.clone(),
+        }
+    }
+
+    // Checks regions against the probed frame size before anything runs.
+    pub fn validate(&self, media: Option<&MediaInfo>) -> Result<(), String> {
+        if self.blur_regions.is_empty() {
+            return Ok(());
+        }
+        if self.blur_regions.len() > MAX_BLUR_REGIONS {
+            return Err(format!("At most {} blur regions are supported", MAX_BLUR_REGIONS));
+        }
+        // Regions are drawn on the frame as shown, after any rotation
+        let Some((width, height)) = media.and_then(|m| m.display_size()) else {
+            return Err("Can't place blur regions: the video size couldn't be read".to_string());
+        };
+        for (i, r) in self.blur_regions.iter().enumerate() {
+            if r.w == 0 || r.h == 0 {
+                return Err(format!("Blur region {} has no area", i + 1));
+            }
+            // checked: a huge x or w would wrap right past the comparison
+            if r.x.checked_add(r.w).is_none_or(|right| right > width) || r.y.checked_add(r.h).is_none_or(|bottom| bottom > height) {
+                return Err(format!(
+                    "Blur region {} ({}x{} at {},{}) goes outside the {}x{} frame",
+                    i + 1, r.w, r.h, r.x, r.y, width, height
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Turns a cap on the longer side into a width cap (landscape, square) or
+    // a height cap (portrait), tightening any max_width / max_height already
+    // set. Unknown orientation caps both, which fits the same box. Returns
+    // the side that got it.
+    pub fn apply_long_edge(&mut self, cap: u32, orientation: Option<Orientation>) -> &'static str {
+        let tighten = |side: &mut Option<u32>| *side = Some(side.map_or(cap, |s| s.min(cap)));
+        match orientation {
+            Some(Orientation::Portrait) => {
+                tighten(&mut self.max_height);
+                "height"
+            }
+            Some(_) => {
+                tighten(&mut self.max_width);
+                "width"
+            }
+            None => {
+                tighten(&mut self.max_width);
+                tighten(&mut self.max_height);
+                "both"
+            }
+        }
+    }
+
+    // Source frame rate after deinterlacing / IVTC
+    fn field_fps(media: Option<&MediaInfo>, fields: &FieldReport) -> Option<f64> {
+        // decimate keeps 4 of every 5 frames, so frame-based timecodes run at the film rate
+        let ivtc = fields.action == FieldAction::InverseTelecine;
+        media.and_then(|m| m.fps).map(|f| if ivtc { f * 4.0 / 5.0 } else { f })
+    }
+
+    // The rate max_fps brings the output down to, when the source is faster.
+    // An unknown source rate is left alone: `fps=` would duplicate frames of
+    // a slower one.
+    pub fn fps_cap(&self, media: Option<&MediaInfo>, fields: &FieldReport) -> Option<f64> {
+        let max = self.max_fps?;
+        Self::field_fps(media, fields).filter(|f| *f > max + 0.01).map(|_| max)
+    }
+
+    // Never upscales. With only a height the width follows (-2 keeps it
+    // even); with a width the box is fitted and force_divisible_by rounds
+    // both sides to even, which 4:2:0 encoders need.
+    fn scale_filter(&self) -> Option<String> {
+        match (self.max_width, self.max_height) {
+            (None, None) => None,
+            (None, Some(h)) => Some(format!("scale=-2:'min(ih,{})'", h)),
+            (Some(w), None) => Some(format!("scale='min(iw,{})':-2", w)),
+            (Some(w), Some(h)) => Some(format!("scale='min(iw,{})':'min(ih,{})':force_original_aspect_ratio=decrease:force_divisible_by=2", w, h)),
+        }
+    }
+
+    // Frame size scale_filter ends at for a `width`x`height` source: never
+    // bigger, aspect kept, even sides once it scales. A filter spec's
+    // geometry comes first, as in build; a canvas is the size it says.
+    pub fn output_size(&self, size: (u32, u32)) -> (u32, u32) {
+        if let Some(canvas) = &self.fit_canvas {
+            return (canvas.width, canvas.height);
+        }
+        let (width, height) = self.custom.as_ref().map_or(size, |spec| spec.output_size(size));
+        let even = |side: f64| ((side as u32) / 2 * 2).max(2);
+        let (w, h) = (width as f64, height as f64);
+        match (self.max_width, self.max_height) {
+            (None, None) => (width, height),
+            (None, Some(max_h)) if height <= max_h => (even(w), height),
+            (None, Some(max_h)) => (even(w * max_h as f64 / h), max_h),
+            (Some(max_w), None) if width <= max_w => (width, even(h)),
+            (Some(max_w), None) => (max_w, even(h * max_w as f64 / w)),
+            (Some(max_w), Some(max_h)) => {
+                let factor = (max_w as f64 / w).min(max_h as f64 / h).min(1.0);
+                (even(w * factor), even(h * factor))
+            }
+        }
+    }
+
+    // The whole `-vf` value, or None when there's nothing to do. Order:
+    //   0. dynamic HDR metadata removal, which doesn't touch the pixels
+    //   1. deinterlace / inverse telecine, so everything after sees whole frames
+    //   2. the frame-rate cap, so later filters process fewer frames
+    //   3. blur regions, in source coordinates (so nothing has moved yet)
+    //   3b. the request's filter spec, on the blurred source-sized picture
+    //       (filterspec.rs); the caps below still hold after it
+    //   4. geometry changes, once there are any
+    //   5. burned-in text, last, so it's drawn at output size and never blurred
+    //   6. the canvas fit, after the caps so it decides the final size. With
+    //      a canvas the text moves after it: it belongs on the canvas, and
+    //      drawn before a blur fit it would show up a second time, blurred,
+    //      in the background
+    // Frames never live on the GPU here (encode_video decodes on the CPU),
+    // so the same chain feeds the hardware encoders.
+    // `offset_secs` is where in the source this encode starts (resumed parts).
+    pub fn build(&self, media: Option<&MediaInfo>, fields: &FieldReport, filename: &str, offset_secs: f64) -> Option<String> {
+        let mut chain: Vec<String> = vec![];
+        if self.strip_dynamic_hdr {
+            chain.push(hdr::strip_filter().to_string());
+        }
+        if let Some(filter) = fields.filter(self.deinterlace) {
+            chain.push(filter.to_string());
+        }
+        // After the field filter: zscale's chroma resampling would mix fields
+        if self.tonemap {
+            chain.push(hdr::tonemap_filter().to_string());
+        }
+        let fps_cap = self.fps_cap(media, fields);
+        if let Some(fps) = fps_cap {
+            chain.push(format!("fps={}", fps));
+        }
+        if let Some(graph) = blur_graph(&self.blur_regions) {
+            chain.push(graph);
+        }
+        if let Some(graph) = self.custom.as_ref().and_then(FilterSpec::compile) {
+            chain.push(graph);
+        }
+        let text = self.overlay_text.as_ref().map(|o| {
+            let fps = fps_cap.or(Self::field_fps(media, fields));
+            let timecode = media.and_then(|m| m.timecode.as_deref());
+            overlay::drawtext_filter(o, filename, fps, timecode, offset_secs)
+        });
+        let (text_before, text_after) = match self.fit_canvas {
+            Some(_) => (None, text),
+            None => (text, None),
+        };
+        chain.extend(text_before);
+        if let Some(scale) = self.scale_filter() {
+            chain.push(scale);
+        }
+        chain.extend(self.fit_canvas.as_ref().map(canvas_graph));
+        chain.extend(text_after);
+        if chain.is_empty() { None } else { Some(chain.join(",")) }
+    }
+}
+
+// The frame fitted into the canvas, whichever way round the shapes are
+// (portrait into landscape gets bars left and right, landscape into
+// portrait above and below). Color pads:
+//   scale=W:H:force_original_aspect_ratio=decrease,pad=W:H:(ow-iw)/2:(oh-ih)/2:color=C
+// Blur fills the bars from a second copy of the frame, scaled up until it
+// covers the canvas, cropped to it and blurred, with the sharp one on top:
+//   split[ca][cb];[ca]scale=..increase,crop=W:H,boxblur=20[cbg];[cb]scale=..decrease[cfg];[cbg][cfg]overlay=(W-w)/2:(H-h)/2
+// Single input, single unlabeled output, like blur_graph, so it chains
+// with commas either side. setsar=1 keeps players from stretching it again.
+pub fn canvas_graph(canvas: &FitCanvas) -> String {
+    let (w, h) = (canvas.width, canvas.height);
+    let fitted = format!("scale={}:{}:force_original_aspect_ratio=decrease:force_divisible_by=2", w, h);
+    match canvas.pad_style {
+        PadStyle::Color => {
+            let color = canvas.color.as_deref().map_or("black".to_string(), escape_option_value);
+            format!("{},pad={}:{}:(ow-iw)/2:(oh-ih)/2:color={},setsar=1", fitted, w, h, color)
+        }
+        PadStyle::Blur => {
+            // boxblur rejects a radius larger than half the (chroma) plane
+            let radius = CANVAS_BLUR_STRENGTH.min((w.min(h) / 4).max(1));
+            format!(
+                "split[ca][cb];[ca]scale={w}:{h}:force_original_aspect_ratio=increase,crop={w}:{h},boxblur={radius}[cbg];[cb]{fitted}[cfg];[cbg][cfg]overlay=(W-w)/2:(H-h)/2,setsar=1",
+                w = w,
+                h = h,
+                radius = radius,
+                fitted = fitted,
+            )
+        }
+    }
+}
+
+// One split/crop/boxblur/overlay stage per region, chained through labels:
+//   split[r0a][r0b];[r0b]crop=w:h:x:y,boxblur=r[r0c];[r0a][r0c]overlay=x:y[r1];[r1]split...
+// It's a single-input, single-output graph, so it can go in `-vf` and the
+// usual `-map` handling keeps working. The last overlay is left unlabeled so
+// more filters can be appended with a comma.
+pub fn blur_graph(regions: &[BlurRegion]) -> Option<String> {
+    if regions.is_empty() {
+        return None;
+    }
+    let mut stages = vec![];
+    for (i, r) in regions.iter().enumerate() {
+        let input = if i == 0 { String::new() } else { format!("[r{}]", i) };
+        let output = if i + 1 == regions.len() { String::new() } else { format!("[r{}]", i + 1) };
+        // boxblur rejects a radius larger than half the (chroma) plane
+        let max_radius = (r.w.min(r.h) / 4).max(1);
+        let radius = r.strength.unwrap_or(DEFAULT_BLUR_STRENGTH).clamp(1, max_radius);
+        stages.push(format!(
+            "{input}split[r{i}a][r{i}b];[r{i}b]crop={w}:{h}:{x}:{y},boxblur={radius}[r{i}c];[r{i}a][r{i}c]overlay={x}:{y}{output}",
+            input = input,
+            output = output,
+            i = i,
+            w = r.w,
+            h = r.h,
+            x = r.x,
+            y = r.y,
+            radius = radius,
+        ));
+    }
+    Some(stages.join(";"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32) -> MediaInfo {
+        MediaInfo { width: Some(width), height: Some(height), ..Default::default() }
+    }
+
+    fn with_region(x: u32, y: u32, w: u32, h: u32) -> VideoFilters {
+        VideoFilters { blur_regions: vec![BlurRegion { x, y, w, h, strength: None }], ..Default::default() }
+    }
+
+    #[test]
+    fn regions_inside_the_frame_pass() {
+        assert!(with_region(0, 0, 1920, 1080).validate(Some(&frame(1920, 1080))).is_ok());
+        assert!(with_region(100, 50, 200, 100).validate(Some(&frame(1920, 1080))).is_ok());
+    }
+
+    #[test]
+    fn regions_past_the_edge_fail() {
+        assert!(with_region(1800, 0, 200, 100).validate(Some(&frame(1920, 1080))).is_err());
+        assert!(with_region(0, 1000, 100, 100).validate(Some(&frame(1920, 1080))).is_err());
+    }
+
+    #[test]
+    fn huge_coordinates_fail_instead_of_wrapping() {
+        assert!(with_region(u32::MAX, 0, 2, 10).validate(Some(&frame(1920, 1080))).is_err());
+        assert!(with_region(0, u32::MAX - 5, 10, 10).validate(Some(&frame(1920, 1080))).is_err());
+        assert!(with_region(u32::MAX, u32::MAX, u32::MAX, u32::MAX).validate(Some(&frame(1920, 1080))).is_err());
+    }
+
+    #[test]
+    fn empty_regions_fail() {
+        assert!(with_region(0, 0, 0, 10).validate(Some(&frame(1920, 1080))).is_err());
+    }
+
+    fn canvas(width: u32, height: u32, pad_style: PadStyle, color: Option<&str>) -> FitCanvas {
+        FitCanvas { width, height, pad_style, color: color.map(String::from) }
+    }
+
+    fn fitted(source: (u32, u32), canvas: FitCanvas, text: Option<&str>) -> String {
+        let filters = VideoFilters {
+            fit_canvas: Some(canvas),
+            overlay_text: text.map(|t| TextOverlay { template: t.to_string(), position: Default::default(), font_size: None, boxed: false }),
+            ..Default::default()
+        };
+        filters.build(Some(&frame(source.0, source.1)), &FieldReport::default(), "clip.mp4", 0.0).unwrap()
+    }
+
+    #[test]
+    fn color_bars_go_on_the_sides_the_shapes_leave() {
+        // A phone's portrait frame on a 16:9 slide: bars left and right
+        let slide = fitted((1080, 1920), canvas(1920, 1080, PadStyle::Color, None), None);
+        assert_eq!(
+            slide,
+            "scale=1920:1080:force_original_aspect_ratio=decrease:force_divisible_by=2,pad=1920:1080:(ow-iw)/2:(oh-ih)/2:color=black,setsar=1"
+        );
+        // A landscape frame in a story: above and below, in the asked color
+        let story = fitted((1920, 1080), canvas(1080, 1920, PadStyle::Color, Some("white@0.5")), None);
+        assert_eq!(
+            story,
+            "scale=1080:1920:force_original_aspect_ratio=decrease:force_divisible_by=2,pad=1080:1920:(ow-iw)/2:(oh-ih)/2:color=white@0.5,setsar=1"
+        );
+        assert!(canvas_graph(&canvas(1080, 1920, PadStyle::Color, Some("#1a2b3c"))).contains(":color=#1a2b3c,"));
+    }
+
+    #[test]
+    fn blur_fills_the_bars_from_the_frame_itself() {
+        let slide = fitted((1080, 1920), canvas(1920, 1080, PadStyle::Blur, None), None);
+        assert_eq!(
+            slide,
+            "split[ca][cb];[ca]scale=1920:1080:force_original_aspect_ratio=increase,crop=1920:1080,boxblur=20[cbg];\
+             [cb]scale=1920:1080:force_original_aspect_ratio=decrease:force_divisible_by=2[cfg];[cbg][cfg]overlay=(W-w)/2:(H-h)/2,setsar=1"
+        );
+        let story = fitted((1920, 1080), canvas(1080, 1920, PadStyle::Blur, None), None);
+        assert_eq!(
+            story,
+            "split[ca][cb];[ca]scale=1080:1920:force_original_aspect_ratio=increase,crop=1080:1920,boxblur=20[cbg];\
+             [cb]scale=1080:1920:force_original_aspect_ratio=decrease:force_divisible_by=2[cfg];[cbg][cfg]overlay=(W-w)/2:(H-h)/2,setsar=1"
+        );
+        // A tiny canvas can't take the full radius
+        assert!(canvas_graph(&canvas(32, 64, PadStyle::Blur, None)).contains(",boxblur=8[cbg]"));
+    }
+
+    #[test]
+    fn text_and_blurred_regions_compose_around_the_canvas_in_order() {
+        for (source, target) in [((1080, 1920), (1920, 1080)), ((1920, 1080), (1080, 1920))] {
+            let filters = VideoFilters {
+                blur_regions: vec![BlurRegion { x: 0, y: 0, w: 200, h: 100, strength: None }],
+                overlay_text: Some(TextOverlay { template: "Demo".to_string(), position: Default::default(), font_size: None, boxed: false }),
+                max_width: Some(720),
+                fit_canvas: Some(canvas(target.0, target.1, PadStyle::Blur, None)),
+                ..Default::default()
+            };
+            let chain = filters.build(Some(&frame(source.0, source.1)), &FieldReport::default(), "clip.mp4", 0.0).unwrap();
+            let at = |what: &str| chain.find(what).unwrap_or_else(|| panic!("no {} in {}", what, chain));
+            // Regions on the source, then the cap, then the canvas, and
+            // the text on the canvas: drawn once, sharp
+            assert!(at("crop=200:100:0:0") < at("scale='min(iw,720)'"));
+            assert!(at("scale='min(iw,720)'") < at("split[ca][cb]"));
+            assert!(at("overlay=(W-w)/2:(H-h)/2,setsar=1") < at("drawtext="));
+            assert_eq!(chain.matches("drawtext=").count(), 1);
+        }
+        // Without a canvas the text goes before the cap, as always
+        let plain = VideoFilters {
+            overlay_text: Some(TextOverlay { template: "Demo".to_string(), position: Default::default(), font_size: None, boxed: false }),
+            max_width: Some(720),
+            ..Default::default()
+        };
+        let chain = plain.build(Some(&frame(1920, 1080)), &FieldReport::default(), "clip.mp4", 0.0).unwrap();
+        assert!(chain.find("drawtext=").unwrap() < chain.find("scale=").unwrap());
+    }
+
+    #[test]
+    fn the_canvas_decides_the_output_size() {
+        let filters = VideoFilters { max_width: Some(720), fit_canvas: Some(canvas(1080, 1920, PadStyle::Blur, None)), ..Default::default() };
+        assert_eq!(filters.output_size((1920, 1080)), (1080, 1920));
+        assert_eq!(filters.output_size((1080, 1920)), (1080, 1920));
+    }
+
+    #[test]
+    fn blur_regions_chain_through_labels_with_a_capped_radius() {
+        let regions = [
+            BlurRegion { x: 10, y: 20, w: 200, h: 100, strength: None },
+            BlurRegion { x: 0, y: 0, w: 8, h: 40, strength: Some(30) },
+        ];
+        assert_eq!(
+            blur_graph(&regions).unwrap(),
+            "split[r0a][r0b];[r0b]crop=200:100:10:20,boxblur=10[r0c];[r0a][r0c]overlay=10:20[r1];\
+             [r1]split[r1a][r1b];[r1b]crop=8:40:0:0,boxblur=2[r1c];[r1a][r1c]overlay=0:0"
+        );
+        assert_eq!(blur_graph(&[]), None);
+    }
+}
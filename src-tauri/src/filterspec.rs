@@ -0,0 +1,612 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::AppHandle;
+use crate::overlay::{escape_expansion, escape_filtergraph, escape_option_value};
+use crate::probe::{self, MediaInfo};
+use crate::request::{FONT_SIZE_RANGE, MAX_DIMENSION};
+
+pub const MAX_STEPS: usize = 32;
+const DEFAULT_FONT_SIZE: u32 = 24;
+// Pixel formats with an alpha plane (pal8 can carry one in its palette)
+const ALPHA_FORMATS: &[&str] = &["rgba", "bgra", "argb", "abgr", "yuva", "gbrap", "ya", "pal8"];
+
+// ==========================================
+// FILTER SPECS
+// ==========================================
+// Power users who want more than the built-in picture options describe a
+// chain of known filters with typed parameters instead of a raw `-vf`
+// string. It's checked like every other option (problems() with the
+// request, check_frames() against the probe), and compiled here into a
+// filtergraph with the escaping and label wiring done right, so it composes
+// with the app's own filters: VideoFilters::build puts it after blur
+// regions and before the burned-in text and the size caps, which still hold.
+//
+// `overlay` steps draw one of the spec's named `inputs` (a logo, a frame)
+// on the picture. The input comes in through a `movie=` source, which keeps
+// the whole graph single-input, single-output: it still goes in `-vf`, and
+// the usual `-map` handling keeps working.
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(default)]
+pub struct FilterSpec {
+    // Name -> file, for `overlay` steps
+    pub inputs: BTreeMap<String, String>,
+    // Applied in order
+    pub steps: Vec<FilterStep>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(tag = "filter", rename_all = "snake_case")]
+pub enum FilterStep {
+    // -1 follows the other side's scaling, -2 too but rounded to even
+    Scale { width: i32, height: i32 },
+    // Centered when x / y are left out
+    Crop { width: u32, height: u32, x: Option<u32>, y: Option<u32> },
+    Pad { width: u32, height: u32, x: Option<u32>, y: Option<u32>, color: Option<String> },
+    Fps { fps: f64 },
+    // Denoise strengths; ffmpeg's defaults for the ones left out
+    Hqdn3d { luma_spatial: Option<f64>, chroma_spatial: Option<f64>, luma_temporal: Option<f64>, chroma_temporal: Option<f64> },
+    // Negative amounts blur
+    Unsharp { amount: f64, size: Option<u32> },
+    Eq { brightness: Option<f64>, contrast: Option<f64>, saturation: Option<f64>, gamma: Option<f64> },
+    // Literal text; `{...}` isn't expanded like overlay_text templates are
+    Drawtext { text: String, x: u32, y: u32, font_size: Option<u32>, color: Option<String> },
+    // Draws `inputs[input]` with its top-left corner at x,y
+    Overlay { input: String, x: i32, y: i32, opacity: Option<f64> },
+}
+
+// What check_frames needs to know about a named input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputFrame {
+    pub width: u32,
+    pub height: u32,
+    pub alpha: bool,
+}
+
+pub(crate) fn valid_color(color: &str) -> bool {
+    !color.is_empty() && color.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '#' | '@' | '.'))
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn in_range(value: Option<f64>, min: f64, max: f64) -> bool {
+    value.is_none_or(|v| v.is_finite() && (min..=max).contains(&v))
+}
+
+pub fn has_alpha(pix_fmt: &str) -> bool {
+    ALPHA_FORMATS.iter().any(|f| pix_fmt.starts_with(f))
+}
+
+impl FilterStep {
+    fn name(&self) -> &'static str {
+        match self {
+            FilterStep::Scale { .. } => "scale",
+            FilterStep::Crop { .. } => "crop",
+            FilterStep::Pad { .. } => "pad",
+            FilterStep::Fps { .. } => "fps",
+            FilterStep::Hqdn3d { .. } => "hqdn3d",
+            FilterStep::Unsharp { .. } => "unsharp",
+            FilterStep::Eq { .. } => "eq",
+            FilterStep::Drawtext { .. } => "drawtext",
+            FilterStep::Overlay { .. } => "overlay",
+        }
+    }
+
+    // Parameter problems that need no probe.
+    fn problem(&self, inputs: &BTreeMap<String, String>) -> Option<String> {
+        let dimension = |v: i32| v == -1 || v == -2 || (1..=MAX_DIMENSION as i32).contains(&v);
+        match self {
+            FilterStep::Scale { width, height } if !dimension(*width) || !dimension(*height) => {
+                Some(format!("{}x{} isn't a size (sides are 1-{}, or -1 / -2 to keep the aspect ratio)", width, height, MAX_DIMENSION))
+            }
+            FilterStep::Scale { width, height } if *width < 0 && *height < 0 => Some("Only one side can follow the other".to_string()),
+            FilterStep::Crop { width, height, .. } | FilterStep::Pad { width, height, .. }
+                if *width == 0 || *height == 0 || *width > MAX_DIMENSION || *height > MAX_DIMENSION =>
+            {
+                Some(format!("{}x{} isn't a size (sides are 1-{})", width, height, MAX_DIMENSION))
+            }
+            FilterStep::Pad { color: Some(color), .. } | FilterStep::Drawtext { color: Some(color), .. } if !valid_color(color) => {
+                Some(format!("\"{}\" isn't a color (a name like white, or #rrggbb, optionally @alpha)", color))
+            }
+            FilterStep::Fps { fps } if !fps.is_finite() || *fps <= 0.0 || *fps > 240.0 => Some(format!("{} isn't a frame rate (0-240)", fps)),
+            FilterStep::Hqdn3d { luma_spatial, chroma_spatial, luma_temporal, chroma_temporal }
+                if ![luma_spatial, chroma_spatial, luma_temporal, chroma_temporal].iter().all(|v| in_range(**v, 0.0, 100.0)) =>
+            {
+                Some("Strengths are 0-100".to_string())
+            }
+            FilterStep::Unsharp { amount, .. } if !in_range(Some(*amount), -2.0, 5.0) => Some(format!("Amount {} is outside -2 to 5", amount)),
+            FilterStep::Unsharp { size: Some(size), .. } if !(3..=23).contains(size) || size % 2 == 0 => Some(format!("Size {} isn't an odd number from 3 to 23", size)),
+            FilterStep::Eq { brightness, contrast, saturation, gamma }
+                if !(in_range(*brightness, -1.0, 1.0) && in_range(*contrast, -1000.0, 1000.0) && in_range(*saturation, 0.0, 3.0) && in_range(*gamma, 0.1, 10.0)) =>
+            {
+                Some("brightness is -1 to 1, contrast -1000 to 1000, saturation 0 to 3, gamma 0.1 to 10".to_string())
+            }
+            FilterStep::Drawtext { text, .. } if text.trim().is_empty() => Some("The text is empty".to_string()),
+            FilterStep::Drawtext { font_size: Some(size), .. } if !FONT_SIZE_RANGE.contains(size) => {
+                Some(format!("Font size {} is outside {}-{}", size, FONT_SIZE_RANGE.start(), FONT_SIZE_RANGE.end()))
+            }
+            FilterStep::Overlay { input, .. } if !inputs.contains_key(input) => Some(format!("There's no input named \"{}\"", input)),
+            FilterStep::Overlay { opacity, .. } if !in_range(*opacity, 0.0, 1.0) => Some("Opacity is 0 to 1".to_string()),
+            _ => None,
+        }
+    }
+
+    // Frame size after this step, for a `width`x`height` picture going in.
+    fn size_after(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        let follow = |side: f64, even: bool| {
+            let side = side.round().max(1.0) as u32;
+            if even { (side / 2 * 2).max(2) } else { side }
+        };
+        match *self {
+            FilterStep::Scale { width: w, height: h } => match (w, h) {
+                (w, h) if w < 0 => (follow(width as f64 * h as f64 / height.max(1) as f64, w == -2), h as u32),
+                (w, h) if h < 0 => (w as u32, follow(height as f64 * w as f64 / width.max(1) as f64, h == -2)),
+                (w, h) => (w as u32, h as u32),
+            },
+            FilterStep::Crop { width: w, height: h, .. } | FilterStep::Pad { width: w, height: h, .. } => (w, h),
+            _ => (width, height),
+        }
+    }
+
+    // The step as one escaped filter, for everything but `overlay`.
+    fn filter(&self) -> String {
+        let mut options: Vec<String> = vec![];
+        let mut opt = |key: &str, value: Option<String>| options.extend(value.map(|v| format!("{}={}", key, v)));
+        match self {
+            FilterStep::Scale { width, height } => {
+                opt("w", Some(width.to_string()));
+                opt("h", Some(height.to_string()));
+            }
+            FilterStep::Crop { width, height, x, y } => {
+                opt("w", Some(width.to_string()));
+                opt("h", Some(height.to_string()));
+                opt("x", x.map(|v| v.to_string()));
+                opt("y", y.map(|v| v.to_string()));
+            }
+            FilterStep::Pad { width, height, x, y, color } => {
+                opt("w", Some(width.to_string()));
+                opt("h", Some(height.to_string()));
+                // pad puts the picture top-left unless told otherwise
+                opt("x", Some(x.map_or("(ow-iw)/2".to_string(), |v| v.to_string())));
+                opt("y", Some(y.map_or("(oh-ih)/2".to_string(), |v| v.to_string())));
+                opt("color", color.as_deref().map(escape_option_value));
+            }
+            FilterStep::Fps { fps } => opt("fps", Some(fps.to_string())),
+            FilterStep::Hqdn3d { luma_spatial, chroma_spatial, luma_temporal, chroma_temporal } => {
+                opt("luma_spatial", luma_spatial.map(|v| v.to_string()));
+                opt("chroma_spatial", chroma_spatial.map(|v| v.to_string()));
+                opt("luma_tmp", luma_temporal.map(|v| v.to_string()));
+                opt("chroma_tmp", chroma_temporal.map(|v| v.to_string()));
+            }
+            FilterStep::Unsharp { amount, size } => {
+                let size = size.unwrap_or(5);
+                opt("luma_msize_x", Some(size.to_string()));
+                opt("luma_msize_y", Some(size.to_string()));
+                opt("luma_amount", Some(amount.to_string()));
+            }
+            FilterStep::Eq { brightness, contrast, saturation, gamma } => {
+                opt("brightness", brightness.map(|v| v.to_string()));
+                opt("contrast", contrast.map(|v| v.to_string()));
+                opt("saturation", saturation.map(|v| v.to_string()));
+                opt("gamma", gamma.map(|v| v.to_string()));
+            }
+            FilterStep::Drawtext { text, x, y, font_size, color } => {
+                opt("text", Some(escape_option_value(&escape_expansion(text))));
+                opt("x", Some(x.to_string()));
+                opt("y", Some(y.to_string()));
+                opt("fontsize", Some(font_size.unwrap_or(DEFAULT_FONT_SIZE).to_string()));
+                opt("fontcolor", Some(color.as_deref().map_or("white".to_string(), escape_option_value)));
+            }
+            FilterStep::Overlay { .. } => {}
+        }
+        let name = self.name();
+        if options.is_empty() { name.to_string() } else { escape_filtergraph(&format!("{}={}", name, options.join(":"))) }
+    }
+}
+
+impl FilterSpec {
+    // Problems that need no probe, for request validation.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.steps.is_empty() {
+            problems.push("The filter spec has no steps".to_string());
+        }
+        if self.steps.len() > MAX_STEPS {
+            problems.push(format!("At most {} filter steps are supported", MAX_STEPS));
+        }
+        for (name, path) in &self.inputs {
+            if !valid_name(name) {
+                problems.push(format!("Input name \"{}\" may only use letters, digits and _", name));
+            }
+            if path.trim().is_empty() {
+                problems.push(format!("Input \"{}\" has no file", name));
+            }
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            problems.extend(step.problem(&self.inputs).map(|p| format!("Step {} ({}): {}", i + 1, step.name(), p)));
+        }
+        problems
+    }
+
+    pub fn draws_text(&self) -> bool {
+        self.steps.iter().any(|s| matches!(s, FilterStep::Drawtext { .. }))
+    }
+
+    // Frame size at the end of the spec for a `width`x`height` picture.
+    pub fn output_size(&self, size: (u32, u32)) -> (u32, u32) {
+        self.steps.iter().fold(size, |size, step| step.size_after(size))
+    }
+
+    // Pure: walks the frame size through the steps and checks every one
+    // against the picture it gets. `frame` is the picture as shown (after
+    // rotation), `inputs` the probed named inputs. Returns the final size.
+    pub fn check_frames(&self, frame: (u32, u32), inputs: &BTreeMap<String, InputFrame>) -> Result<(u32, u32), String> {
+        let mut size = frame;
+        for (i, step) in self.steps.iter().enumerate() {
+            let (w, h) = size;
+            let at = |problem: String| format!("Filter step {} ({}): {}", i + 1, step.name(), problem);
+            match *step {
+                FilterStep::Crop { width, height, x, y } => {
+                    let (x, y) = (x.unwrap_or((w.saturating_sub(width)) / 2), y.unwrap_or((h.saturating_sub(height)) / 2));
+                    if x + width > w || y + height > h {
+                        return Err(at(format!("{}x{} at {},{} goes outside the {}x{} frame", width, height, x, y, w, h)));
+                    }
+                }
+                FilterStep::Pad { width, height, x, y, .. } => {
+                    let (x, y) = (x.unwrap_or((width.saturating_sub(w)) / 2), y.unwrap_or((height.saturating_sub(h)) / 2));
+                    if x + w > width || y + h > height {
+                        return Err(at(format!("a {}x{} frame doesn't fit in {}x{} at {},{}", w, h, width, height, x, y)));
+                    }
+                }
+                FilterStep::Drawtext { x, y, .. } if x >= w || y >= h => {
+                    return Err(at(format!("{},{} is outside the {}x{} frame", x, y, w, h)));
+                }
+                FilterStep::Overlay { ref input, x, y, opacity } => {
+                    let Some(picture) = inputs.get(input) else { return Err(at(format!("input \"{}\" couldn't be read", input))) };
+                    let (right, bottom) = (x + picture.width as i32, y + picture.height as i32);
+                    if right <= 0 || bottom <= 0 || x >= w as i32 || y >= h as i32 {
+                        return Err(at(format!("\"{}\" at {},{} lands outside the {}x{} frame", input, x, y, w, h)));
+                    }
+                    // An opaque picture over the whole frame leaves nothing of the video
+                    let covers = x <= 0 && y <= 0 && right >= w as i32 && bottom >= h as i32;
+                    if covers && !picture.alpha && opacity.is_none_or(|o| o >= 1.0) {
+                        return Err(at(format!("\"{}\" has no transparency and covers the whole frame; give it an opacity or make it smaller", input)));
+                    }
+                }
+                _ => {}
+            }
+            size = step.size_after(size);
+        }
+        Ok(size)
+    }
+
+    // Pure: the spec as one filtergraph that can follow a comma and be
+    // followed by one. Each overlay closes the chain so far under a label,
+    // opens its input with `movie=`, and continues from the overlay:
+    //   eq=contrast=1.1[fs1];movie=filename=logo.png[fi1];[fs1][fi1]overlay=x=10:y=10,...
+    pub fn compile(&self) -> Option<String> {
+        if self.steps.is_empty() {
+            return None;
+        }
+        let mut graph: Vec<String> = vec![];
+        let mut chain: Vec<String> = vec![];
+        for (i, step) in self.steps.iter().enumerate() {
+            let FilterStep::Overlay { input, x, y, opacity } = step else {
+                chain.push(step.filter());
+                continue;
+            };
+            let path = self.inputs.get(input).map(String::as_str).unwrap_or_default();
+            let main = if chain.is_empty() { "null".to_string() } else { chain.join(",") };
+            graph.push(format!("{}[fs{}]", main, i));
+            let mut source = escape_filtergraph(&format!("movie=filename={}", escape_option_value(path)));
+            if let Some(opacity) = opacity {
+                source.push_str(&format!(",format=rgba,colorchannelmixer=aa={}", opacity));
+            }
+            graph.push(format!("{}[fi{}]", source, i));
+            chain = vec![format!("[fs{i}][fi{i}]overlay=x={}:y={}", x, y, i = i)];
+        }
+        graph.push(chain.join(","));
+        Some(graph.join(";"))
+    }
+}
+
+// Probes the named inputs and checks the spec against them and `media`.
+pub async fn validate(app: &AppHandle, spec: &FilterSpec, media: Option<&MediaInfo>) -> Result<(), String> {
+    let Some(frame) = media.filter(|m| m.has_video).and_then(|m| m.display_size()) else {
+        return Err("The filter spec needs a video stream, and its size couldn't be read".to_string());
+    };
+    let mut inputs = BTreeMap::new();
+    for (name, path) in &spec.inputs {
+        let info = probe::probe(app, path).await.map_err(|e| format!("Filter input \"{}\" ({}) couldn't be read: {}", name, path, e))?;
+        let video = info.streams.iter().find(|s| s.codec_type == "video");
+        let (Some(width), Some(height)) = (video.and_then(|s| s.width), video.and_then(|s| s.height)) else {
+            return Err(format!("Filter input \"{}\" ({}) has no picture", name, path));
+        };
+        let alpha = video.and_then(|s| s.pix_fmt.as_deref()).is_some_and(has_alpha);
+        inputs.insert(name.clone(), InputFrame { width, height, alpha });
+    }
+    spec.check_frames(frame, &inputs).map(|_| ())
+}
+
+// ==========================================
+// COMMAND: FILTER SPEC SCHEMA
+// ==========================================
+#[tauri::command]
+pub fn get_filter_spec_schema() -> serde_json::Value {
+    serde_json::to_value(schemars::schema_for!(FilterSpec)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::{BlurRegion, VideoFilters};
+    use crate::interlace::FieldReport;
+    use crate::overlay::TextOverlay;
+
+    fn spec(json: &str) -> FilterSpec {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn frame(width: u32, height: u32, alpha: bool) -> InputFrame {
+        InputFrame { width, height, alpha }
+    }
+
+    // ==========================================
+    // COMPILING
+    // ==========================================
+    #[test]
+    fn steps_compile_in_the_order_given() {
+        let steps = [
+            (r#"{ "filter": "crop", "width": 640, "height": 360 }"#, "crop=w=640:h=360"),
+            (r#"{ "filter": "crop", "width": 640, "height": 360, "x": 8, "y": 0 }"#, "crop=w=640:h=360:x=8:y=0"),
+            (r#"{ "filter": "scale", "width": -2, "height": 720 }"#, "scale=w=-2:h=720"),
+            (r#"{ "filter": "fps", "fps": 23.976 }"#, "fps=fps=23.976"),
+            (r#"{ "filter": "hqdn3d" }"#, "hqdn3d"),
+            (r#"{ "filter": "hqdn3d", "luma_spatial": 4, "chroma_temporal": 6.5 }"#, "hqdn3d=luma_spatial=4:chroma_tmp=6.5"),
+            (r#"{ "filter": "unsharp", "amount": 1.5 }"#, "unsharp=luma_msize_x=5:luma_msize_y=5:luma_amount=1.5"),
+            (r#"{ "filter": "unsharp", "amount": -0.5, "size": 7 }"#, "unsharp=luma_msize_x=7:luma_msize_y=7:luma_amount=-0.5"),
+            (r#"{ "filter": "eq", "contrast": 1.1, "saturation": 1.2 }"#, "eq=contrast=1.1:saturation=1.2"),
+            (r#"{ "filter": "pad", "width": 1280, "height": 720 }"#, "pad=w=1280:h=720:x=(ow-iw)/2:y=(oh-ih)/2"),
+            (r#"{ "filter": "pad", "width": 1280, "height": 720, "x": 0, "y": 4, "color": "black@0.5" }"#, "pad=w=1280:h=720:x=0:y=4:color=black@0.5"),
+            (r#"{ "filter": "drawtext", "text": "Draft", "x": 10, "y": 20 }"#, "drawtext=text=Draft:x=10:y=20:fontsize=24:fontcolor=white"),
+        ];
+        for (json, filter) in steps {
+            assert_eq!(spec(&format!(r#"{{ "steps": [{}] }}"#, json)).compile().unwrap(), filter, "{}", json);
+        }
+        let forwards: Vec<&str> = steps.iter().map(|(json, _)| *json).collect();
+        let compiled = spec(&format!(r#"{{ "steps": [{}] }}"#, forwards.join(","))).compile().unwrap();
+        assert_eq!(compiled, steps.map(|(_, filter)| filter).join(","));
+        let backwards: Vec<&str> = forwards.iter().rev().copied().collect();
+        let compiled = spec(&format!(r#"{{ "steps": [{}] }}"#, backwards.join(","))).compile().unwrap();
+        assert_eq!(compiled, steps.iter().rev().map(|(_, filter)| *filter).collect::<Vec<_>>().join(","));
+    }
+
+    #[test]
+    fn an_empty_spec_compiles_to_nothing() {
+        assert_eq!(FilterSpec::default().compile(), None);
+    }
+
+    #[test]
+    fn text_is_escaped_for_the_option_and_the_graph() {
+        let text = spec(r##"{ "steps": [{ "filter": "drawtext", "text": "50%: it's a, [test]; ok\\", "x": 0, "y": 0, "font_size": 48, "color": "#ff0000" }] }"##);
+        // %-expansion, then the option value (\ ' :), then the graph (\ ' [ ] , ;)
+        assert_eq!(
+            text.compile().unwrap(),
+            r"drawtext=text=50\\\\%\\: it\\\'s a\, \[test\]\; ok\\\\\\\\:x=0:y=0:fontsize=48:fontcolor=#ff0000"
+        );
+        assert!(text.draws_text());
+    }
+
+    #[test]
+    fn overlays_close_the_chain_and_wire_their_input_in() {
+        let logo = spec(
+            r#"{
+                "inputs": { "logo": "/media/logo [v2].png" },
+                "steps": [
+                    { "filter": "eq", "contrast": 1.1 },
+                    { "filter": "overlay", "input": "logo", "x": 10, "y": 10 },
+                    { "filter": "scale", "width": 1280, "height": -2 },
+                    { "filter": "overlay", "input": "logo", "x": -5, "y": 20, "opacity": 0.5 }
+                ]
+            }"#,
+        );
+        assert_eq!(
+            logo.compile().unwrap(),
+            [
+                "eq=contrast=1.1[fs1]",
+                r"movie=filename=/media/logo \[v2\].png[fi1]",
+                "[fs1][fi1]overlay=x=10:y=10,scale=w=1280:h=-2[fs3]",
+                r"movie=filename=/media/logo \[v2\].png,format=rgba,colorchannelmixer=aa=0.5[fi3]",
+                "[fs3][fi3]overlay=x=-5:y=20",
+            ]
+            .join(";")
+        );
+        assert!(!logo.draws_text());
+
+        // First thing in the spec: the picture goes in as it is
+        let first = spec(r#"{ "inputs": { "logo": "C:\\logos\\it's.png" }, "steps": [{ "filter": "overlay", "input": "logo", "x": 0, "y": 0 }] }"#);
+        assert_eq!(first.compile().unwrap(), r"null[fs0];movie=filename=C\\:\\\\logos\\\\it\\\'s.png[fi0];[fs0][fi0]overlay=x=0:y=0");
+    }
+
+    #[test]
+    fn the_spec_goes_after_blur_and_before_text_and_caps() {
+        let filters = VideoFilters {
+            blur_regions: vec![BlurRegion { x: 0, y: 0, w: 100, h: 100, strength: None }],
+            custom: Some(spec(r#"{ "steps": [{ "filter": "eq", "gamma": 1.2 }, { "filter": "hqdn3d" }] }"#)),
+            overlay_text: Some(TextOverlay { template: "Draft".to_string(), position: Default::default(), font_size: None, boxed: false }),
+            max_width: Some(640),
+            ..Default::default()
+        };
+        let graph = filters.build(None, &FieldReport::default(), "clip.mp4", 0.0).unwrap();
+        let at = |filter: &str| graph.find(filter).unwrap_or_else(|| panic!("{} not in {}", filter, graph));
+        assert!(at("boxblur") < at("eq=gamma=1.2,hqdn3d"));
+        assert!(at("hqdn3d") < at("drawtext"));
+        assert!(at("drawtext") < at("scale='min(iw,640)':-2"));
+        assert!(graph.ends_with("scale='min(iw,640)':-2"));
+    }
+
+    // ==========================================
+    // REJECTING
+    // ==========================================
+    #[test]
+    fn unknown_filters_and_untyped_parameters_dont_parse() {
+        for json in [
+            r#"{ "steps": [{ "filter": "lut3d", "file": "look.cube" }] }"#,
+            r#"{ "steps": [{ "width": 640, "height": 360 }] }"#,
+            r#"{ "steps": [{ "filter": "scale", "width": 640 }] }"#,
+            r#"{ "steps": [{ "filter": "crop", "width": -1, "height": 360 }] }"#,
+            r#"{ "steps": [{ "filter": "fps", "fps": "30" }] }"#,
+            r#"{ "steps": [{ "filter": "drawtext", "text": 5, "x": 0, "y": 0 }] }"#,
+            r#"{ "steps": { "filter": "hqdn3d" } }"#,
+        ] {
+            assert!(serde_json::from_str::<FilterSpec>(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn invalid_parameters_are_reported_by_step() {
+        let bad = spec(
+            r#"{
+                "inputs": { "logo": "logo.png", "two words": "", "ok_2": " " },
+                "steps": [
+                    { "filter": "scale", "width": 0, "height": 720 },
+                    { "filter": "scale", "width": -1, "height": -2 },
+                    { "filter": "crop", "width": 20000, "height": 10 },
+                    { "filter": "pad", "width": 1920, "height": 1080, "color": "red;drawbox" },
+                    { "filter": "fps", "fps": 0 },
+                    { "filter": "fps", "fps": 300 },
+                    { "filter": "hqdn3d", "luma_temporal": 101 },
+                    { "filter": "unsharp", "amount": 6 },
+                    { "filter": "unsharp", "amount": 1, "size": 4 },
+                    { "filter": "eq", "saturation": -1 },
+                    { "filter": "drawtext", "text": "  ", "x": 0, "y": 0 },
+                    { "filter": "drawtext", "text": "a", "x": 0, "y": 0, "font_size": 2 },
+                    { "filter": "drawtext", "text": "a", "x": 0, "y": 0, "color": "" },
+                    { "filter": "overlay", "input": "watermark", "x": 0, "y": 0 },
+                    { "filter": "overlay", "input": "logo", "x": 0, "y": 0, "opacity": 1.5 },
+                    { "filter": "scale", "width": 16384, "height": -1 }
+                ]
+            }"#,
+        );
+        assert_eq!(
+            bad.problems(),
+            [
+                "Input \"ok_2\" has no file",
+                "Input name \"two words\" may only use letters, digits and _",
+                "Input \"two words\" has no file",
+                "Step 1 (scale): 0x720 isn't a size (sides are 1-16384, or -1 / -2 to keep the aspect ratio)",
+                "Step 2 (scale): Only one side can follow the other",
+                "Step 3 (crop): 20000x10 isn't a size (sides are 1-16384)",
+                "Step 4 (pad): \"red;drawbox\" isn't a color (a name like white, or #rrggbb, optionally @alpha)",
+                "Step 5 (fps): 0 isn't a frame rate (0-240)",
+                "Step 6 (fps): 300 isn't a frame rate (0-240)",
+                "Step 7 (hqdn3d): Strengths are 0-100",
+                "Step 8 (unsharp): Amount 6 is outside -2 to 5",
+                "Step 9 (unsharp): Size 4 isn't an odd number from 3 to 23",
+                "Step 10 (eq): brightness is -1 to 1, contrast -1000 to 1000, saturation 0 to 3, gamma 0.1 to 10",
+                "Step 11 (drawtext): The text is empty",
+                "Step 12 (drawtext): Font size 2 is outside 4-512",
+                "Step 13 (drawtext): \"\" isn't a color (a name like white, or #rrggbb, optionally @alpha)",
+                "Step 14 (overlay): There's no input named \"watermark\"",
+                "Step 15 (overlay): Opacity is 0 to 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn specs_need_between_one_and_max_steps() {
+        assert_eq!(FilterSpec::default().problems(), ["The filter spec has no steps"]);
+        let hqdn3d = r#"{ "filter": "hqdn3d" }"#;
+        let full = spec(&format!(r#"{{ "steps": [{}] }}"#, vec![hqdn3d; MAX_STEPS].join(",")));
+        assert!(full.problems().is_empty());
+        let over = spec(&format!(r#"{{ "steps": [{}] }}"#, vec![hqdn3d; MAX_STEPS + 1].join(",")));
+        assert_eq!(over.problems(), [format!("At most {} filter steps are supported", MAX_STEPS)]);
+    }
+
+    // ==========================================
+    // AGAINST THE PICTURE
+    // ==========================================
+    #[test]
+    fn the_frame_size_follows_the_steps() {
+        let resize = spec(r#"{ "steps": [{ "filter": "scale", "width": -2, "height": 720 }] }"#);
+        assert_eq!(resize.output_size((1920, 1080)), (1280, 720));
+        // -2 rounds down to even, -1 doesn't
+        assert_eq!(resize.output_size((1918, 1080)), (1278, 720));
+        assert_eq!(spec(r#"{ "steps": [{ "filter": "scale", "width": -1, "height": 720 }] }"#).output_size((1918, 1080)), (1279, 720));
+        assert_eq!(spec(r#"{ "steps": [{ "filter": "scale", "width": 640, "height": -2 }] }"#).output_size((1080, 1920)), (640, 1138));
+
+        let boxed = spec(r#"{ "steps": [{ "filter": "crop", "width": 1000, "height": 1000 }, { "filter": "eq", "gamma": 2 }, { "filter": "pad", "width": 1080, "height": 1920 }] }"#);
+        assert_eq!(boxed.check_frames((1920, 1080), &BTreeMap::new()), Ok((1080, 1920)));
+    }
+
+    #[test]
+    fn steps_are_checked_against_the_picture_they_get() {
+        let none = BTreeMap::new();
+        let check = |json: &str, frame_size: (u32, u32)| spec(json).check_frames(frame_size, &none).unwrap_err();
+        assert_eq!(
+            check(r#"{ "steps": [{ "filter": "crop", "width": 640, "height": 360, "x": 1300, "y": 0 }] }"#, (1920, 1080)),
+            "Filter step 1 (crop): 640x360 at 1300,0 goes outside the 1920x1080 frame"
+        );
+        // Centered, but bigger than the frame
+        assert_eq!(
+            check(r#"{ "steps": [{ "filter": "crop", "width": 2000, "height": 360 }] }"#, (1920, 1080)),
+            "Filter step 1 (crop): 2000x360 at 0,360 goes outside the 1920x1080 frame"
+        );
+        assert_eq!(
+            check(r#"{ "steps": [{ "filter": "pad", "width": 1280, "height": 720 }] }"#, (1920, 1080)),
+            "Filter step 1 (pad): a 1920x1080 frame doesn't fit in 1280x720 at 0,0"
+        );
+        // The text is placed on the scaled picture, not the source
+        assert_eq!(
+            check(r#"{ "steps": [{ "filter": "scale", "width": 640, "height": -2 }, { "filter": "drawtext", "text": "a", "x": 700, "y": 10 }] }"#, (1920, 1080)),
+            "Filter step 2 (drawtext): 700,10 is outside the 640x360 frame"
+        );
+    }
+
+    #[test]
+    fn overlays_are_checked_against_their_input() {
+        let logo = |x: i32, y: i32, opacity: Option<f64>| {
+            let opacity = opacity.map_or(String::new(), |o| format!(r#", "opacity": {}"#, o));
+            spec(&format!(r#"{{ "inputs": {{ "logo": "logo.png" }}, "steps": [{{ "filter": "overlay", "input": "logo", "x": {}, "y": {}{} }}] }}"#, x, y, opacity))
+        };
+        let small = BTreeMap::from([("logo".to_string(), frame(200, 100, false))]);
+        let full = BTreeMap::from([("logo".to_string(), frame(1920, 1080, false))]);
+        let full_alpha = BTreeMap::from([("logo".to_string(), frame(1920, 1080, true))]);
+
+        assert_eq!(logo(10, 10, None).check_frames((1920, 1080), &small), Ok((1920, 1080)));
+        // Partly off the edge is fine, wholly off isn't
+        assert!(logo(-150, 1000, None).check_frames((1920, 1080), &small).is_ok());
+        assert_eq!(
+            logo(-200, 0, None).check_frames((1920, 1080), &small).unwrap_err(),
+            "Filter step 1 (overlay): \"logo\" at -200,0 lands outside the 1920x1080 frame"
+        );
+        assert!(logo(1920, 0, None).check_frames((1920, 1080), &small).is_err());
+        assert_eq!(
+            logo(0, 0, None).check_frames((1920, 1080), &BTreeMap::new()).unwrap_err(),
+            "Filter step 1 (overlay): input \"logo\" couldn't be read"
+        );
+        // Covering everything needs transparency of some kind
+        assert_eq!(
+            logo(0, 0, None).check_frames((1920, 1080), &full).unwrap_err(),
+            "Filter step 1 (overlay): \"logo\" has no transparency and covers the whole frame; give it an opacity or make it smaller"
+        );
+        assert!(logo(0, 0, Some(1.0)).check_frames((1920, 1080), &full).is_err());
+        assert!(logo(0, 0, Some(0.4)).check_frames((1920, 1080), &full).is_ok());
+        assert!(logo(0, 0, None).check_frames((1920, 1080), &full_alpha).is_ok());
+        assert!(logo(1, 0, None).check_frames((1920, 1080), &full).is_ok());
+    }
+
+    #[test]
+    fn alpha_formats_are_recognised() {
+        for pix_fmt in ["rgba", "bgra", "yuva420p", "yuva444p10le", "gbrap", "ya8", "pal8"] {
+            assert!(has_alpha(pix_fmt), "{}", pix_fmt);
+        }
+        for pix_fmt in ["yuv420p", "rgb24", "gbrp", "gray", "nv12"] {
+            assert!(!has_alpha(pix_fmt), "{}", pix_fmt);
+        }
+    }
+}
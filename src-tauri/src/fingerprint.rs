@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+use tauri::Manager;
+use tauri_plugin_shell::ShellExt;
+use tauri_plugin_shell::process::CommandEvent;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::AppHandle;
+use crate::ffmpeg::FfmpegBinary;
+use crate::procgroup::{self, TrackedChild};
+
+// Upper bound for each external probe (nvidia-smi, PowerShell, ...). They
+// run in the background, but a hung driver tool must never hold up the
+// capability check forever.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+// ==========================================
+// ENVIRONMENT FINGERPRINT
+// ==========================================
+// What the detected capabilities depend on. When any of it changes between
+// sessions (driver update, eGPU unplugged, different ffmpeg build) the
+// stored capability list can't be trusted anymore.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Fingerprint {
+    // "NVIDIA GeForce RTX 3060 | 551.86", one per adapter, sorted
+    pub gpus: Vec<String>,
+    // xxh3 of the ffmpeg binary jobs run with
+    pub ffmpeg: Option<String>,
+}
+
+async fn run_with_timeout(app: &AppHandle, program: &str, args: &[&str]) -> Option<String> {
+    run_within(app, program, args, PROBE_TIMEOUT).await
+}
+
+// stdout of a successful run. Spawned and registered like our ffmpegs, so a
+// tool that hangs past `limit` is killed with everything it started, and
+// kill_all reaches it meanwhile.
+async fn run_within(app: &AppHandle, program: &str, args: &[&str], limit: Duration) -> Option<String> {
+    let (mut rx, child) = procgroup::spawn(app.shell().command(program).args(args)).ok()?;
+    let tracked = TrackedChild::new(app, child);
+    let collect = async {
+        let (mut stdout, mut code) = (vec![], None);
+        while let Some(event) = rx.recv().await {
+            match event {
+                // Lines come with their newline
+                CommandEvent::Stdout(line) => stdout.extend(line),
+                CommandEvent::Terminated(payload) => code = payload.code,
+                _ => {}
+            }
+        }
+        (code == Some(0)).then(|| String::from_utf8_lossy(&stdout).to_string())
+    };
+    match tokio::time::timeout(limit, collect).await {
+        Ok(stdout) => stdout,
+        Err(_) => {
+            println!("⚠️ {} didn't answer within {}s, killed it", program, limit.as_secs());
+            tracked.kill();
+            None
+        }
+    }
+}
+
+// Driver versions for NVIDIA cards, on every platform that has the tool.
+async fn nvidia_gpus(app: &AppHandle) -> Vec<String> {
+    run_with_timeout(app, "nvidia-smi", &["--query-gpu=name,driver_version", "--format=csv,noheader"])
+        .await
+        .map(|text| {
+            text.lines()
+                .filter(|l| !l.trim().is_empty())
+                .map(|l| l.split(',').map(str::trim).collect::<Vec<_>>().join(" | "))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "windows")]
+async fn system_gpus(app: &AppHandle) -> Vec<String> {
+    let script = "Get-CimInstance Win32_VideoController | ForEach-Object { $_.Name + ' | ' + $_.DriverVersion }";
+    run_with_timeout(app, "powershell", &["-NoProfile", "-NonInteractive", "-Command", script])
+        .await
+        .map(|text| text.lines().map(str::trim).filter(|l| !l.is_empty()).map(String::from).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "macos")]
+async fn system_gpus(app: &AppHandle) -> Vec<String> {
+    run_with_timeout(app, "system_profiler", &["SPDisplaysDataType", "-detailLevel", "mini"])
+        .await
+        .map(|text| {
+            text.lines()
+                .filter_map(|l| l.trim().strip_prefix("Chipset Model:"))
+                .map(|m| m.trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// /sys/class/drm: PCI vendor:device and the kernel driver bound to each card.
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+async fn system_gpus(_app: &AppHandle) -> Vec<String> {
+    let read = |p: PathBuf| std::fs::read_to_string(p).map(|s| s.trim().to_string()).unwrap_or_default();
+    let Ok(entries) = std::fs::read_dir("/sys/class/drm") else { return vec![] };
+    entries
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            name.starts_with("card") && !name.contains('-')
+        })
+        .map(|e| {
+            let device = e.path().join("device");
+            let driver = std::fs::read_link(device.join("driver"))
+                .ok()
+                .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+                .unwrap_or_default();
+            format!("{}:{} | {}", read(device.join("vendor")), read(device.join("device")), driver)
+        })
+        .collect()
+}
+
+// The sidecar sits next to the app executable (externalBin, triple stripped).
+fn ffmpeg_path(app: &AppHandle) -> Option<PathBuf> {
+    if let Some(path) = app.try_state::<FfmpegBinary>().and_then(|b| b.extended()) {
+        return Some(path);
+    }
+    let dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+    let name = if cfg!(windows) { "ffmpeg.exe" } else { "ffmpeg" };
+    Some(dir.join(name))
+}
+
+fn hash_file(path: PathBuf) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let mut hasher = Xxh3::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf).ok()?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Some(format!("{:016x}", hasher.digest()))
+}
+
+// Best effort: every part that can't be read is just left out.
+pub async fn collect(app: &AppHandle) -> Fingerprint {
+    let mut gpus = system_gpus(app).await;
+    gpus.extend(nvidia_gpus(app).await);
+    gpus.sort();
+    gpus.dedup();
+
+    let ffmpeg = match ffmpeg_path(app) {
+        Some(path) => tauri::async_runtime::spawn_blocking(move || hash_file(path)).await.ok().flatten(),
+        None => None,
+    };
+    Fingerprint { gpus, ffmpeg }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{self, Harness};
+
+    #[test]
+    fn a_probe_answers_with_its_stdout() {
+        let h = Harness::new("fingerprint-probe", "{}");
+        let app = h.handle().clone();
+        let answer = jobtests::run(async move { run_within(&app, "echo", &["RTX 3060, 551.86"], Duration::from_secs(5)).await });
+        assert_eq!(answer.as_deref(), Some("RTX 3060, 551.86\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_hung_probe_is_killed_at_the_timeout() {
+        let h = Harness::new("fingerprint-hang", "{}");
+        let pid_file = h.file("pid");
+        let script = format!("echo $$ > '{}'; sleep 30", pid_file);
+        let app = h.handle().clone();
+        let answer = jobtests::run(async move { run_within(&app, "sh", &["-c", &script], Duration::from_millis(500)).await });
+        assert_eq!(answer, None);
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap().trim().to_string();
+        let alive = || std::process::Command::new("kill").args(["-0", &pid]).status().is_ok_and(|s| s.success());
+        h.wait_for("the probe to be killed", |_| !alive());
+        // Unregistered as well
+        assert_eq!(procgroup::kill_all(h.handle()), 0);
+    }
+}
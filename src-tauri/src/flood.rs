@@ -0,0 +1,269 @@
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::time::Instant;
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::settings::SettingsStore;
+
+// First and last part of a run's stderr that make it into the job log
+const HEAD_BYTES: usize = 64 * 1024;
+const TAIL_BYTES: usize = 256 * 1024;
+// Decode errors per second that count as a flood, and for how many
+// seconds in a row before the job is given up on
+pub const FLOOD_ERRORS_PER_SEC: u32 = 200;
+pub const FLOOD_SECS: u32 = 10;
+pub const TOO_MANY_DECODE_ERRORS: &str = "TooManyDecodeErrors";
+
+// ==========================================
+// STDERR FLOODS
+// ==========================================
+// Some broken inputs make ffmpeg print an error for every packet, tens of
+// MB a minute. None of it is worth keeping line by line:
+//   - runs of the same message (numbers and addresses aside) are collapsed
+//     into "Last message repeated N times", before the UI or the log sees them
+//   - the job log keeps the first HEAD_BYTES and the last TAIL_BYTES of each
+//     run, with a marker where the middle was dropped
+//   - with abort_on_decode_flood on, FLOOD_SECS seconds of more than
+//     FLOOD_ERRORS_PER_SEC decode errors fail the job with
+//     TOO_MANY_DECODE_ERRORS instead of grinding on to the end (salvage
+//     jobs have their own rate, see salvage.rs)
+
+// A line with its digits and 0x addresses masked, so "[h264 @ 0x55d2]
+// error at MB 12 40" and "[h264 @ 0x55d2] error at MB 13 40" are one message.
+fn shape(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '0' && chars.peek() == Some(&'x') {
+            chars.next();
+            while chars.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+                chars.next();
+            }
+            out.push('#');
+        } else if c.is_ascii_digit() {
+            while chars.peek().is_some_and(|c| c.is_ascii_digit() || *c == '.') {
+                chars.next();
+            }
+            out.push('#');
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Collapser {
+    last: Option<String>,
+    repeats: u32,
+}
+
+impl Collapser {
+    // Lines to pass on for `line`: nothing while it repeats the last one,
+    // else the pending "repeated" note (if any) and the line itself.
+    pub fn push(&mut self, line: &str) -> Vec<String> {
+        let shape = shape(line);
+        if self.last.as_deref() == Some(shape.as_str()) {
+            self.repeats += 1;
+            return vec![];
+        }
+        let mut out: Vec<String> = self.flush().into_iter().collect();
+        out.push(line.to_string());
+        self.last = Some(shape);
+        out
+    }
+
+    // The note for a run still being collapsed, at the end of the output.
+    pub fn flush(&mut self) -> Option<String> {
+        let repeats = std::mem::take(&mut self.repeats);
+        (repeats > 0).then(|| format!("Last message repeated {} times", repeats))
+    }
+}
+
+// One run's stderr, head and tail only.
+#[derive(Clone, Debug, Default)]
+pub struct StderrLog {
+    head: String,
+    tail: VecDeque<String>,
+    tail_bytes: usize,
+    dropped_lines: u64,
+    dropped_bytes: u64,
+}
+
+impl StderrLog {
+    pub fn push(&mut self, line: &str) {
+        if self.head.len() + line.len() < HEAD_BYTES && self.tail.is_empty() {
+            self.head.push_str(line);
+            self.head.push('\n');
+            return;
+        }
+        self.tail_bytes += line.len() + 1;
+        self.tail.push_back(line.to_string());
+        while self.tail_bytes > TAIL_BYTES {
+            let Some(old) = self.tail.pop_front() else { break };
+            self.tail_bytes -= old.len() + 1;
+            self.dropped_lines += 1;
+            self.dropped_bytes += old.len() as u64 + 1;
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = self.head.clone();
+        if self.dropped_lines > 0 {
+            text.push_str(&format!("[... {} lines ({} KB) left out ...]\n", self.dropped_lines, self.dropped_bytes / 1024));
+        }
+        for line in &self.tail {
+            text.push_str(line);
+            text.push('\n');
+        }
+        text
+    }
+}
+
+// Decode errors per wall-clock second, and how many seconds in a row were
+// over the threshold.
+#[derive(Clone, Debug)]
+pub struct FloodMeter {
+    // Errors a second that count as flooding
+    threshold: u32,
+    second_start: Instant,
+    this_second: u32,
+    flooded_secs: u32,
+}
+
+impl Default for FloodMeter {
+    fn default() -> Self {
+        FloodMeter { threshold: FLOOD_ERRORS_PER_SEC, second_start: Instant::now(), this_second: 0, flooded_secs: 0 }
+    }
+}
+
+impl FloodMeter {
+    pub fn with_threshold(threshold: u32) -> Self {
+        FloodMeter { threshold, ..Default::default() }
+    }
+
+    pub fn threshold(&self) -> u32 {
+        self.threshold
+    }
+
+    pub fn record(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.second_start).as_secs();
+        if elapsed >= 1 {
+            // A whole quiet second in between ends the streak
+            let over = self.this_second > self.threshold && elapsed == 1;
+            self.flooded_secs = if over { self.flooded_secs + 1 } else { 0 };
+            self.second_start = now;
+            self.this_second = 0;
+        }
+        self.this_second += 1;
+    }
+
+    pub fn flooded(&self) -> bool {
+        self.flooded_secs >= FLOOD_SECS
+    }
+}
+
+pub fn abort_enabled(app: &AppHandle) -> bool {
+    app.try_state::<SettingsStore>().is_some_and(|s| s.get().abort_on_decode_flood)
+}
+
+// Appends one run's stderr to app_log_dir/jobs/<job id>.log (a two-pass
+// encode is two runs). Best effort: a log that can't be written changes
+// nothing about the job.
+pub fn write_job_log(app: &AppHandle, job_id: u64, log: &StderrLog) {
+    let Ok(dir) = app.path().app_log_dir().map(|d| d.join("jobs")) else { return };
+    let written = fs::create_dir_all(&dir).and_then(|_| {
+        let mut file = OpenOptions::new().create(true).append(true).open(dir.join(format!("{}.log", job_id)))?;
+        file.write_all(log.text().as_bytes())
+    });
+    if let Err(e) = written {
+        println!("⚠️ Could not write the log of job {}: {}", job_id, e);
+    }
+}
+
+// ==========================================
+// COMMAND: ABORT ON DECODE FLOODS
+// ==========================================
+#[tauri::command]
+pub fn set_abort_on_decode_flood(store: State<'_, SettingsStore>, enabled: bool) -> Result<(), String> {
+    store.update(|s| s.abort_on_decode_flood = enabled).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn numbers_and_addresses_dont_make_a_new_message() {
+        assert_eq!(shape("[h264 @ 0x55d2a1] error while decoding MB 12 40"), shape("[h264 @ 0x7f00] error while decoding MB 13 4"));
+        assert_ne!(shape("[h264 @ 0x55d2] error while decoding MB 12 40"), shape("[h264 @ 0x55d2] concealing 12 DC"));
+    }
+
+    #[test]
+    fn repeats_are_collapsed_into_a_note() {
+        let mut collapser = Collapser::default();
+        let mut out = vec![];
+        for line in ["error at MB 1", "error at MB 2", "error at MB 3", "frame=10 fps=5"] {
+            out.extend(collapser.push(line));
+        }
+        assert_eq!(out, ["error at MB 1", "Last message repeated 2 times", "frame=10 fps=5"]);
+        assert_eq!(collapser.flush(), None);
+        collapser.push("frame=11 fps=5");
+        assert_eq!(collapser.flush().as_deref(), Some("Last message repeated 1 times"));
+    }
+
+    #[test]
+    fn the_log_keeps_the_head_and_the_tail() {
+        let mut log = StderrLog::default();
+        let line = "x".repeat(1023);
+        // 1 MB of lines: the first 64 KB and the last 256 KB survive
+        for _ in 0..1024 {
+            log.push(&line);
+        }
+        log.push("the last line");
+        let text = log.text();
+        assert!(text.len() <= HEAD_BYTES + TAIL_BYTES + 100);
+        assert!(text.contains("lines (") && text.contains("KB) left out"));
+        assert!(text.starts_with(&line) && text.ends_with("the last line\n"));
+
+        let mut short = StderrLog::default();
+        short.push("only line");
+        assert_eq!(short.text(), "only line\n");
+    }
+
+    fn meter_over(seconds: u64, per_second: u32, start: Instant) -> FloodMeter {
+        let mut meter = FloodMeter { second_start: start, ..FloodMeter::default() };
+        for sec in 0..seconds {
+            for i in 0..per_second {
+                meter.record(start + Duration::from_secs(sec) + Duration::from_micros(i as u64 * 1_000_000 / per_second as u64));
+            }
+        }
+        meter
+    }
+
+    #[test]
+    fn ten_seconds_over_the_rate_is_a_flood() {
+        let start = Instant::now();
+        // The tenth flooded second is counted once the eleventh begins
+        assert!(meter_over(FLOOD_SECS as u64 + 1, 250, start).flooded());
+        assert!(!meter_over(FLOOD_SECS as u64, 250, start).flooded());
+        assert!(!meter_over(30, 150, start).flooded());
+    }
+
+    #[test]
+    fn a_quiet_second_ends_the_streak() {
+        let start = Instant::now();
+        let mut meter = meter_over(9, 250, start);
+        // Nothing for two seconds, then four more flooded ones: 13 in all,
+        // but not in a row
+        for sec in 11..15 {
+            for i in 0..250 {
+                meter.record(start + Duration::from_secs(sec) + Duration::from_millis(i * 4));
+            }
+        }
+        assert!(!meter.flooded());
+    }
+}
@@ -0,0 +1,79 @@
+use crate::AppHandle;
+use crate::events;
+use crate::ffmpeg;
+use crate::filters::VideoFilters;
+use crate::progress::ProgressTracker;
+
+pub const DEFAULT_FPS: f64 = 15.0;
+pub const DEFAULT_WIDTH: u32 = 480;
+// GIF frame delays are in hundredths of a second; faster plays wrong in browsers
+pub const MAX_FPS: f64 = 50.0;
+
+// ==========================================
+// GIF OUTPUT (PALETTEGEN + PALETTEUSE)
+// ==========================================
+// ffmpeg's gif encoder on its own maps everything to one fixed 256-colour
+// palette, which bands badly and dithers noise into every frame (big files).
+// Two passes instead:
+//   1. palettegen builds the best 256 colours for this clip into a PNG
+//   2. paletteuse maps the frames onto it
+// Both passes see the same fps/scale chain, or the palette is made from
+// different pixels than it's applied to. Each pass is half the progress.
+
+// `fps=..,scale=..`: gif_fps / gif_width (or the defaults), brought down by
+// max_fps / max_width / max_height. GIF has no even-size rule.
+pub fn picture_filter(fps: Option<f64>, width: Option<u32>, caps: &VideoFilters) -> String {
+    let fps = fps.unwrap_or(DEFAULT_FPS);
+    let fps = caps.max_fps.map_or(fps, |max| fps.min(max));
+    let width = width.unwrap_or(DEFAULT_WIDTH);
+    let width = caps.max_width.map_or(width, |max| width.min(max));
+    let scale = match caps.max_height {
+        Some(h) => format!("scale='min(iw,{})':'min(ih,{})':force_original_aspect_ratio=decrease", width, h),
+        None => format!("scale='min(iw,{})':-1", width),
+    };
+    format!("fps={},{}:flags=lanczos", fps, scale)
+}
+
+fn palette_path(staged: &str) -> String {
+    format!("{}.palette.png", staged)
+}
+
+// Pure: the palettegen and paletteuse runs. `input_args` go before the
+// input (a cut's -ss), `output_args` after everything else (-t).
+pub fn pass_args(input: &str, staged: &str, input_args: &[String], output_args: &[String], picture: &str) -> (Vec<String>, Vec<String>) {
+    let palette = palette_path(staged);
+    let mut first = input_args.to_vec();
+    first.extend(["-i".to_string(), input.to_string()]);
+    // diff weighs what moves, which is where banding shows
+    first.extend(["-vf".to_string(), format!("{},palettegen=stats_mode=diff", picture)]);
+    first.extend(output_args.iter().cloned());
+    first.extend(["-y".to_string(), palette.clone()]);
+
+    let mut second = input_args.to_vec();
+    second.extend(["-i".to_string(), input.to_string(), "-i".to_string(), palette]);
+    second.extend(["-lavfi".to_string(), format!("[0:v]{}[x];[x][1:v]paletteuse=dither=sierra2_4a:diff_mode=rectangle", picture)]);
+    second.extend(output_args.iter().cloned());
+    second.extend(["-y".to_string(), staged.to_string()]);
+    (first, second)
+}
+
+pub async fn encode(
+    app: &AppHandle,
+    input: &str,
+    staged: &str,
+    input_args: &[String],
+    output_args: &[String],
+    picture: &str,
+    tracker: ProgressTracker,
+) -> Result<ProgressTracker, String> {
+    let palette = palette_path(staged);
+    let (first, second) = pass_args(input, staged, input_args, output_args, picture);
+    let passes = async {
+        ffmpeg::run_with_progress(app, first, tracker.clone().with_span(0.0, 50.0), events::Event::CompressionProgress).await?;
+        ffmpeg::run_with_progress(app, second, tracker.with_span(50.0, 100.0), events::Event::CompressionProgress).await
+    };
+    let result = passes.await;
+    // Failed and cancelled runs end up here too
+    let _ = std::fs::remove_file(&palette);
+    result
+}
@@ -0,0 +1,502 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::cancel;
+use crate::capabilities;
+use crate::ffmpeg::{self, TrackedOutput};
+use crate::pause;
+use crate::support::VideoCodec;
+
+// In the order auto_gpu prefers them, per codec
+pub const ENCODERS: [&str; 4] = ["h264_nvenc", "h264_qsv", "h264_amf", "h264_videotoolbox"];
+pub const HEVC_ENCODERS: [&str; 4] = ["hevc_nvenc", "hevc_qsv", "hevc_amf", "hevc_videotoolbox"];
+pub const AV1_ENCODERS: [&str; 2] = ["av1_nvenc", "av1_qsv"];
+// Big enough for every encoder's minimum frame size (HEVC NVENC wants > 128)
+const TEST_SIZE: &str = "256x256";
+// Start of the error when the encoder_preference one doesn't work
+pub const ENCODER_NOT_AVAILABLE: &str = "EncoderNotAvailable";
+
+// ==========================================
+// HARDWARE ENCODERS
+// ==========================================
+// A build listing h264_nvenc says nothing about a card being there, so each
+// listed hardware encoder (H.264, HEVC and AV1) gets a tiny test encode. That's a few seconds, so
+// it happens once (on the first auto_gpu job or when the UI asks) and the
+// result is kept until the ffmpeg binary changes or the user refreshes
+// after a driver update.
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct HwCapabilities {
+    // Encoders whose test encode went through, in preference order
+    pub working: Vec<String>,
+    // What auto_gpu uses for H.264; None = it falls back to libx264
+    pub preferred: Option<String>,
+    // Same for codec: "hevc" (else libx265) and "av1" (else libsvtav1)
+    pub preferred_hevc: Option<String>,
+    pub preferred_av1: Option<String>,
+    // Listed by the build but the test encode failed (no device, old driver)
+    pub failed: Vec<String>,
+    pub detected_at: u64,
+}
+
+impl HwCapabilities {
+    // Listed but failing hardware encoders for `codec`
+    pub fn failed_for(&self, codec: VideoCodec) -> Vec<String> {
+        self.failed.iter().filter(|e| candidates(codec).contains(&e.as_str())).cloned().collect()
+    }
+
+    // The encoder auto_gpu should use for `codec`, if any works.
+    pub fn preferred_for(&self, codec: VideoCodec) -> Option<&'static str> {
+        let preferred = match codec {
+            VideoCodec::H264 => &self.preferred,
+            VideoCodec::Hevc => &self.preferred_hevc,
+            VideoCodec::Av1 => &self.preferred_av1,
+        };
+        candidates(codec).iter().copied().find(|e| preferred.as_deref() == Some(*e))
+    }
+}
+
+// Sent as `fallback-to-cpu` when an auto_gpu job's hardware encode failed
+// partway and the job starts over on the CPU.
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct CpuFallback {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<u64>,
+    pub input: String,
+    // The ffmpeg line that gave the hardware encoder away
+    pub error: String,
+}
+
+#[derive(Default)]
+pub struct HwCache {
+    caps: Mutex<Option<Arc<HwCapabilities>>>,
+    // Only one round of test encodes at a time
+    detecting: tokio::sync::Mutex<()>,
+    // Single encoders tested for an encoder_preference before (or without)
+    // the full round
+    tested: Mutex<HashMap<&'static str, bool>>,
+}
+
+impl HwCache {
+    pub fn invalidate(&self) {
+        *self.caps.lock().unwrap() = None;
+        self.tested.lock().unwrap().clear();
+    }
+
+    // As if the round of test encodes had found `caps` (the job tests)
+    #[cfg(test)]
+    pub fn seed(&self, caps: HwCapabilities) {
+        *self.caps.lock().unwrap() = Some(Arc::new(caps));
+    }
+}
+
+// `encoder_preference` of a video request. Auto (or none) is auto_gpu's
+// order; anything else is that vendor's encoder or the CPU, and nothing
+// else: a preferred encoder that doesn't work fails the job.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EncoderPreference {
+    #[default]
+    Auto,
+    Cpu,
+    Nvenc,
+    Videotoolbox,
+    Amf,
+    Qsv,
+}
+
+impl EncoderPreference {
+    pub const ALL: [EncoderPreference; 6] = [Self::Auto, Self::Cpu, Self::Nvenc, Self::Videotoolbox, Self::Amf, Self::Qsv];
+
+    // For the dropdown and messages
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Auto => "Automatic",
+            Self::Cpu => "CPU (software)",
+            Self::Nvenc => "NVIDIA NVENC",
+            Self::Videotoolbox => "Apple VideoToolbox",
+            Self::Amf => "AMD AMF",
+            Self::Qsv => "Intel Quick Sync",
+        }
+    }
+
+    // The suffix of its encoders' names; None for auto and cpu
+    fn suffix(self) -> Option<&'static str> {
+        match self {
+            Self::Auto | Self::Cpu => None,
+            Self::Nvenc => Some("_nvenc"),
+            Self::Videotoolbox => Some("_videotoolbox"),
+            Self::Amf => Some("_amf"),
+            Self::Qsv => Some("_qsv"),
+        }
+    }
+
+    fn encoder(self, codec: VideoCodec) -> Option<&'static str> {
+        let suffix = self.suffix()?;
+        candidates(codec).iter().copied().find(|e| e.ends_with(suffix))
+    }
+
+    // Picks an encoder itself rather than leaving it to auto_gpu
+    pub fn is_explicit(self) -> bool {
+        self != Self::Auto
+    }
+
+    // One vendor's hardware encoder
+    pub fn is_vendor(self) -> bool {
+        self.suffix().is_some()
+    }
+}
+
+pub fn is_hardware(encoder: &str) -> bool {
+    ENCODERS.iter().chain(HEVC_ENCODERS.iter()).chain(AV1_ENCODERS.iter()).any(|e| *e == encoder)
+}
+
+pub fn candidates(codec: VideoCodec) -> &'static [&'static str] {
+    match codec {
+        VideoCodec::H264 => &ENCODERS,
+        VideoCodec::Hevc => &HEVC_ENCODERS,
+        VideoCodec::Av1 => &AV1_ENCODERS,
+    }
+}
+
+// ==========================================
+// GPU SESSION LIMITER (managed state)
+// ==========================================
+// Consumer cards open only a few encode sessions at once (NVENC's cap was 3
+// for years), and the encode over the limit fails instead of waiting. So
+// every hardware encode holds a session while its ffmpeg runs, the test
+// encodes below included, and waits for one when they're all taken. A
+// paused job's session is lent out: that's how the express lane gets a
+// session when they're all in use (see queue.rs).
+pub const DEFAULT_GPU_SESSIONS: usize = 3;
+// How often a waiting encode looks for a free session
+const SESSION_POLL: Duration = Duration::from_millis(100);
+
+pub struct GpuSessions {
+    limit: AtomicUsize,
+    // Open sessions: their id and the job holding it (None: a test encode)
+    open: Mutex<Vec<(u64, Option<u64>)>>,
+    next_id: AtomicU64,
+}
+
+impl Default for GpuSessions {
+    fn default() -> Self {
+        GpuSessions { limit: AtomicUsize::new(DEFAULT_GPU_SESSIONS), open: Mutex::new(vec![]), next_id: AtomicU64::new(0) }
+    }
+}
+
+impl GpuSessions {
+    // As if the card allowed `limit` sessions (the job tests)
+    #[cfg(test)]
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::SeqCst);
+    }
+}
+
+// Sessions held by encodes that are running (not paused).
+fn sessions_in_use(app: &AppHandle, open: &[(u64, Option<u64>)]) -> usize {
+    open.iter().filter(|(_, job)| job.is_none_or(|id| !pause::is_paused(app, id))).count()
+}
+
+// No session left for another hardware encode.
+pub fn sessions_full(app: &AppHandle) -> bool {
+    let sessions = app.state::<GpuSessions>();
+    let open = sessions.open.lock().unwrap();
+    sessions_in_use(app, &open) >= sessions.limit.load(Ordering::SeqCst)
+}
+
+// Queue jobs holding a session right now; pausing one frees it.
+pub fn session_jobs(app: &AppHandle) -> Vec<u64> {
+    let sessions = app.state::<GpuSessions>();
+    let open = sessions.open.lock().unwrap();
+    open.iter().filter_map(|(_, job)| *job).collect()
+}
+
+// A held session; dropping it gives it back.
+pub struct GpuSession {
+    app: AppHandle,
+    id: u64,
+}
+
+impl Drop for GpuSession {
+    fn drop(&mut self) {
+        self.app.state::<GpuSessions>().open.lock().unwrap().retain(|(id, _)| *id != self.id);
+    }
+}
+
+// Waits for a free session. Gives up with cancel::CANCELLED when the job is
+// cancelled meanwhile.
+pub async fn session(app: &AppHandle, job_id: Option<u64>) -> Result<GpuSession, String> {
+    let sessions = app.state::<GpuSessions>();
+    let mut waited = false;
+    loop {
+        {
+            let mut open = sessions.open.lock().unwrap();
+            if sessions_in_use(app, &open) < sessions.limit.load(Ordering::SeqCst) {
+                let id = sessions.next_id.fetch_add(1, Ordering::SeqCst);
+                open.push((id, job_id));
+                return Ok(GpuSession { app: app.clone(), id });
+            }
+        }
+        if !waited {
+            println!("🎮 All GPU encode sessions are in use, waiting for one");
+            waited = true;
+        }
+        match cancel::current() {
+            Some(token) => tokio::select! {
+                _ = token.cancelled() => return Err(cancel::CANCELLED.to_string()),
+                _ = tokio::time::sleep(SESSION_POLL) => {}
+            },
+            None => tokio::time::sleep(SESSION_POLL).await,
+        }
+    }
+}
+
+async fn test_encode(app: AppHandle, encoder: &str) -> bool {
+    let source = format!("color=s={}:d=0.1", TEST_SIZE);
+    let args = [
+        "-hide_banner", "-v", "error",
+        "-f", "lavfi", "-i", &source,
+        "-c:v", encoder,
+        "-f", "null", "-",
+    ];
+    let Ok(command) = ffmpeg::command(&app) else { return false };
+    // A test encode opens a session like any other
+    let Ok(_session) = session(&app, None).await else { return false };
+    command.args(args).tracked_output(&app).await.is_ok_and(|o| o.success())
+}
+
+async fn detect(app: &AppHandle) -> HwCapabilities {
+    // Encoders the build doesn't list at all aren't worth a process
+    let listed = capabilities::get(app).await.ok();
+    let listed = |encoder: &str| listed.as_ref().is_none_or(|c| c.has_encoder(encoder));
+
+    let mut caps = HwCapabilities { detected_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0), ..Default::default() };
+    // One codec at a time, its candidates at once as far as the session
+    // limiter lets them
+    for codec in [VideoCodec::H264, VideoCodec::Hevc, VideoCodec::Av1] {
+        let tests: Vec<_> = candidates(codec)
+            .iter()
+            .filter(|e| listed(e))
+            .map(|&encoder| (encoder, tauri::async_runtime::spawn(test_encode(app.clone(), encoder))))
+            .collect();
+        let mut working = vec![];
+        for (encoder, test) in tests {
+            if test.await.unwrap_or(false) {
+                working.push(encoder.to_string());
+            } else {
+                caps.failed.push(encoder.to_string());
+            }
+        }
+        let preferred = working.first().cloned();
+        match codec {
+            VideoCodec::H264 => caps.preferred = preferred,
+            VideoCodec::Hevc => caps.preferred_hevc = preferred,
+            VideoCodec::Av1 => caps.preferred_av1 = preferred,
+        }
+        caps.working.extend(working);
+    }
+    println!("🎮 Hardware encoders: {:?} (failed: {:?})", caps.working, caps.failed);
+    caps
+}
+
+// What the full round found, without running it.
+pub fn cached(app: &AppHandle) -> Option<Arc<HwCapabilities>> {
+    app.state::<HwCache>().caps.lock().unwrap().clone()
+}
+
+pub async fn get(app: &AppHandle) -> Arc<HwCapabilities> {
+    let cache = app.state::<HwCache>();
+    if let Some(caps) = cached(app) {
+        return caps;
+    }
+    let _turn = cache.detecting.lock().await;
+    if let Some(caps) = cache.caps.lock().unwrap().clone() {
+        return caps;
+    }
+    let caps = Arc::new(detect(app).await);
+    *cache.caps.lock().unwrap() = Some(caps.clone());
+    caps
+}
+
+// The encoder auto_gpu should use for `codec`, if any works.
+pub async fn preferred(app: &AppHandle, codec: VideoCodec) -> Option<&'static str> {
+    get(app).await.preferred_for(codec)
+}
+
+// Whether one encoder works as far as anything tested so far says; None
+// when it hasn't been.
+fn known_to_work(app: &AppHandle, encoder: &str) -> Option<bool> {
+    if let Some(caps) = cached(app) {
+        return Some(caps.working.iter().any(|e| e == encoder));
+    }
+    app.state::<HwCache>().tested.lock().unwrap().get(encoder).copied()
+}
+
+// Whether one encoder works, from the full round if it ran, else by a test
+// encode of its own (kept until the next refresh).
+async fn works(app: &AppHandle, encoder: &'static str) -> bool {
+    if let Some(known) = known_to_work(app, encoder) {
+        return known;
+    }
+    let ok = test_encode(app.clone(), encoder).await;
+    let cache = app.state::<HwCache>();
+    cache.tested.lock().unwrap().insert(encoder, ok);
+    ok
+}
+
+// The hardware encoder for a job, or None for the CPU. `auto_gpu` decides
+// for Auto only; an explicit vendor that has no encoder for `codec`, or
+// whose encoder fails its test, is an error instead of a quiet CPU encode.
+pub async fn choose(app: &AppHandle, codec: VideoCodec, auto_gpu: bool, preference: EncoderPreference) -> Result<Option<&'static str>, String> {
+    match preference {
+        EncoderPreference::Auto if auto_gpu => Ok(preferred(app, codec).await),
+        EncoderPreference::Auto | EncoderPreference::Cpu => Ok(None),
+        vendor => {
+            let encoder = vendor_encoder(vendor, codec)?;
+            if !works(app, encoder).await {
+                return Err(failed_test(vendor, encoder));
+            }
+            Ok(Some(encoder))
+        }
+    }
+}
+
+// Same as choose, from what's been detected already and without a test
+// encode of its own: dry runs don't start ffmpeg. Auto stays on the CPU
+// until the full round has run; a vendor nothing has tested yet is taken
+// to work.
+pub fn choose_detected(app: &AppHandle, codec: VideoCodec, auto_gpu: bool, preference: EncoderPreference) -> Result<Option<&'static str>, String> {
+    match preference {
+        EncoderPreference::Auto if auto_gpu => Ok(cached(app).and_then(|caps| caps.preferred_for(codec))),
+        EncoderPreference::Auto | EncoderPreference::Cpu => Ok(None),
+        vendor => {
+            let encoder = vendor_encoder(vendor, codec)?;
+            if known_to_work(app, encoder) == Some(false) {
+                return Err(failed_test(vendor, encoder));
+            }
+            Ok(Some(encoder))
+        }
+    }
+}
+
+fn vendor_encoder(vendor: EncoderPreference, codec: VideoCodec) -> Result<&'static str, String> {
+    vendor.encoder(codec).ok_or_else(|| format!("{}: {} has no {} encoder", ENCODER_NOT_AVAILABLE, vendor.label(), codec.label()))
+}
+
+fn failed_test(vendor: EncoderPreference, encoder: &str) -> String {
+    format!("{}: encoder '{}' ({}) didn't pass its test encode on this machine", ENCODER_NOT_AVAILABLE, encoder, vendor.label())
+}
+
+// One row of the encoder dropdown.
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct EncoderChoice {
+    pub preference: EncoderPreference,
+    pub name: &'static str,
+    // Its encoders that passed the test encode (auto and cpu: none)
+    pub encoders: Vec<String>,
+    // Worth offering: auto and cpu always, a vendor when one of its encoders works
+    pub available: bool,
+}
+
+// ==========================================
+// COMMANDS
+// ==========================================
+#[tauri::command]
+pub async fn get_hw_capabilities(app: AppHandle) -> HwCapabilities {
+    (*get(&app).await).clone()
+}
+
+// Every encoder_preference value, with what was detected for it.
+#[tauri::command]
+pub async fn list_encoders(app: AppHandle) -> Vec<EncoderChoice> {
+    let caps = get(&app).await;
+    EncoderPreference::ALL
+        .into_iter()
+        .map(|preference| {
+            let encoders: Vec<String> = match preference.suffix() {
+                Some(suffix) => caps.working.iter().filter(|e| e.ends_with(suffix)).cloned().collect(),
+                None => vec![],
+            };
+            EncoderChoice { preference, name: preference.label(), available: preference.suffix().is_none() || !encoders.is_empty(), encoders }
+        })
+        .collect()
+}
+
+// After a driver update or plugging in an eGPU.
+#[tauri::command]
+pub async fn refresh_hw_capabilities(app: AppHandle, cache: State<'_, HwCache>) -> Result<HwCapabilities, String> {
+    cache.invalidate();
+    Ok((*get(&app).await).clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{self, clip_scenario, Harness, ENCODE};
+
+    fn nvenc_runs(h: &Harness) -> usize {
+        h.runs().iter().filter(|r| r.iter().any(|a| a == "h264_nvenc")).count()
+    }
+
+    #[test]
+    fn a_test_encode_waits_for_a_free_session() {
+        let h = Harness::new("gpu-session-test-encode", r#"{ "runs": [{ "stderr": [] }] }"#);
+        h.handle().state::<GpuSessions>().set_limit(1);
+        let held = jobtests::run({
+            let app = h.handle().clone();
+            async move { session(&app, Some(7)).await }
+        })
+        .unwrap();
+
+        let app = h.handle().clone();
+        let test = tauri::async_runtime::spawn(async move { works(&app, "h264_nvenc").await });
+        std::thread::sleep(Duration::from_millis(400));
+        assert_eq!(nvenc_runs(&h), 0, "the test encode started while the only session was taken");
+        drop(held);
+        assert!(tauri::async_runtime::block_on(test).unwrap());
+        assert_eq!(nvenc_runs(&h), 1);
+        assert!(h.handle().state::<GpuSessions>().open.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn hardware_encodes_hold_a_session_while_they_run() {
+        let h = Harness::new("gpu-session-encode", &clip_scenario(ENCODE));
+        h.handle().state::<GpuSessions>().set_limit(1);
+        let held = jobtests::run({
+            let app = h.handle().clone();
+            async move { session(&app, None).await }
+        })
+        .unwrap();
+        let mut request = jobtests::video(&h, "out.mp4");
+        request.options.auto_gpu = true;
+        h.handle().state::<HwCache>().seed(HwCapabilities { working: vec!["h264_nvenc".to_string()], preferred: Some("h264_nvenc".to_string()), ..Default::default() });
+
+        let app = h.handle().clone();
+        let job = tauri::async_runtime::spawn(async move { crate::run_direct_video(&app, request).await });
+        std::thread::sleep(Duration::from_millis(400));
+        assert_eq!(nvenc_runs(&h), 0, "the encode started while the only session was taken");
+        drop(held);
+        assert!(tauri::async_runtime::block_on(job).unwrap().is_ok());
+        assert_eq!(nvenc_runs(&h), 1);
+        assert!(h.handle().state::<GpuSessions>().open.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn waiting_for_a_session_ends_with_a_cancel() {
+        let h = Harness::new("gpu-session-cancel", "{}");
+        h.handle().state::<GpuSessions>().set_limit(0);
+        let token = tokio_util::sync::CancellationToken::new();
+        let app = h.handle().clone();
+        let waiting = tauri::async_runtime::spawn(cancel::scope(token.clone(), async move { session(&app, Some(1)).await.map(drop) }));
+        std::thread::sleep(Duration::from_millis(150));
+        token.cancel();
+        assert_eq!(tauri::async_runtime::block_on(waiting).unwrap(), Err(cancel::CANCELLED.to_string()));
+    }
+}
@@ -0,0 +1,593 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::AppHandle;
+use crate::cancel;
+use crate::capabilities::Capabilities;
+use crate::ffmpeg::{self, TrackedOutput};
+use crate::progress;
+
+// ==========================================
+// DOLBY VISION / HDR10+ DYNAMIC METADATA
+// ==========================================
+// Re-encoding used to drop the Dolby Vision configuration but keep the RPU
+// side data on the frames (or the other way round, depending on the build),
+// and wrote PQ-tagged 8-bit H.264 with no mastering metadata: a half-HDR file
+// TVs show with wrong brightness or not at all. Sources with dynamic metadata
+// now leave as one of two clean states:
+//   - plain HDR10 (default): dynamic metadata removed from every frame,
+//     10-bit HEVC with the source's static metadata written into the stream
+//   - preserved (`preserve_dynamic_hdr`): x265 carries the RPU / HDR10+ SEI
+// Anything that can't reach either state fails before encoding.
+
+// What our x265 path can write dynamic metadata into
+const HDR_CONTAINERS: &[&str] = &["mp4", "mkv", "mov", "m4v"];
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct DolbyVision {
+    pub profile: u32,
+    pub level: u32,
+    // 0 means the base layer is IPT, not HDR10 (profile 5)
+    pub bl_compat_id: u32,
+}
+
+// What the first video frame and stream say about HDR.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HdrInfo {
+    // PQ transfer (smpte2084), i.e. HDR10-style
+    pub pq: bool,
+    // HLG transfer (arib-std-b67), broadcast and phone HDR
+    pub hlg: bool,
+    pub dolby_vision: Option<DolbyVision>,
+    pub hdr10_plus: bool,
+    // x265 `master-display` value, G(x,y)B(x,y)R(x,y)WP(x,y)L(max,min)
+    pub master_display: Option<String>,
+    // x265 `max-cll` value, "MaxCLL,MaxFALL"
+    pub max_cll: Option<String>,
+}
+
+impl HdrInfo {
+    pub fn has_dynamic(&self) -> bool {
+        self.dolby_vision.is_some() || self.hdr10_plus
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        self.pq || self.hlg
+    }
+
+    fn transfer(&self) -> &'static str {
+        if self.pq { "smpte2084" } else { "arib-std-b67" }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HdrAction {
+    // Dynamic metadata removed, written as plain HDR10
+    StrippedToHdr10,
+    Preserved,
+}
+
+// `hdr` of a job result; only present for sources with dynamic metadata.
+#[derive(Serialize, Clone, Debug)]
+pub struct HdrReport {
+    pub dolby_vision: Option<DolbyVision>,
+    pub hdr10_plus: bool,
+    pub action: HdrAction,
+}
+
+// How encode_video has to change for this source.
+#[derive(Clone, Debug)]
+pub struct HdrPlan {
+    pub report: HdrReport,
+    pub encoder: &'static str,
+    // Replaces the container branch's extra args (pixel format, quality)
+    pub encoder_args: Vec<String>,
+    // Remove dynamic side data from frames before they reach the encoder
+    pub strip_side_data: bool,
+    pub warning: Option<String>,
+}
+
+impl HdrPlan {
+    // As the timeline and the explanations name it
+    pub fn action_name(&self) -> String {
+        serde_json::to_value(self.report.action).ok().and_then(|v| v.as_str().map(String::from)).unwrap_or_default()
+    }
+}
+
+// "35400/50000" -> 0.708
+fn rational(value: Option<&Value>) -> Option<f64> {
+    let s = value?.as_str()?;
+    let (num, den) = s.split_once('/').unwrap_or((s, "1"));
+    let (num, den) = (progress::parse_number(num)?, progress::parse_number(den)?);
+    (den != 0.0).then(|| num / den)
+}
+
+// x265 wants chromaticities in 0.00002 units and luminance in 0.0001 cd/m².
+fn master_display(side: &Value) -> Option<String> {
+    let chroma = |key: &str| rational(side.get(key)).map(|v| (v * 50000.0).round() as u64);
+    let luma = |key: &str| rational(side.get(key)).map(|v| (v * 10000.0).round() as u64);
+    Some(format!(
+        "G({},{})B({},{})R({},{})WP({},{})L({},{})",
+        chroma("green_x")?, chroma("green_y")?,
+        chroma("blue_x")?, chroma("blue_y")?,
+        chroma("red_x")?, chroma("red_y")?,
+        chroma("white_point_x")?, chroma("white_point_y")?,
+        luma("max_luminance")?, luma("min_luminance")?,
+    ))
+}
+
+fn side_data(v: &Value) -> impl Iterator<Item = &Value> {
+    v.get("side_data_list").and_then(|l| l.as_array()).into_iter().flatten()
+}
+
+fn side_type(side: &Value) -> &str {
+    side.get("side_data_type").and_then(|t| t.as_str()).unwrap_or("")
+}
+
+// Parses `ffprobe -show_streams -show_frames` JSON for the first video stream.
+pub fn parse(json: &str) -> HdrInfo {
+    let mut info = HdrInfo::default();
+    let Ok(root) = serde_json::from_str::<Value>(json) else { return info };
+    let streams = root.get("streams").and_then(|s| s.as_array()).into_iter().flatten();
+    let frames = root.get("frames").and_then(|f| f.as_array()).into_iter().flatten();
+    let number = |v: &Value, key: &str| v.get(key).and_then(|n| n.as_u64()).unwrap_or(0) as u32;
+
+    for stream in streams {
+        let transfer = stream.get("color_transfer").and_then(|t| t.as_str());
+        info.pq |= transfer == Some("smpte2084");
+        info.hlg |= transfer == Some("arib-std-b67");
+        for side in side_data(stream) {
+            if side_type(side) == "DOVI configuration record" {
+                info.dolby_vision = Some(DolbyVision {
+                    profile: number(side, "dv_profile"),
+                    level: number(side, "dv_level"),
+                    bl_compat_id: number(side, "dv_bl_signal_compatibility_id"),
+                });
+            }
+        }
+    }
+    for frame in frames {
+        for side in side_data(frame) {
+            let kind = side_type(side);
+            if kind.contains("SMPTE2094-40") || kind.contains("HDR10+") {
+                info.hdr10_plus = true;
+            } else if kind == "Mastering display metadata" {
+                info.master_display = info.master_display.take().or_else(|| master_display(side));
+            } else if kind == "Content light level metadata" {
+                info.max_cll = Some(format!("{},{}", number(side, "max_content"), number(side, "max_average")));
+            }
+        }
+    }
+    info
+}
+
+// One frame is enough: the side data repeats on every frame that has it.
+pub async fn detect(app: &AppHandle, input: &str) -> Result<HdrInfo, String> {
+    cancel::check()?;
+    let output = ffmpeg::ffprobe_command(app)?
+        .args([
+            "-v", "error",
+            "-select_streams", "v:0",
+            "-read_intervals", "%+#1",
+            "-show_streams", "-show_frames",
+            "-print_format", "json",
+            input,
+        ])
+        .tracked_output(app)
+        .await
+        .map_err(|e| e.to_string())?;
+    if !output.success() {
+        return Err("ffprobe couldn't read the video's HDR metadata".to_string());
+    }
+    Ok(parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn x265_args(info: &HdrInfo, ext: &str, preserve_dv: bool) -> Vec<String> {
+    let mut params = vec![
+        "hdr10=1".to_string(),
+        "hdr10-opt=1".to_string(),
+        "repeat-headers=1".to_string(),
+        "colorprim=bt2020".to_string(),
+        "transfer=smpte2084".to_string(),
+        "colormatrix=bt2020nc".to_string(),
+    ];
+    if let Some(md) = &info.master_display {
+        params.push(format!("master-display={}", md));
+    }
+    if let Some(cll) = &info.max_cll {
+        params.push(format!("max-cll={}", cll));
+    }
+    let mut args: Vec<String> = [
+        "-pix_fmt", "yuv420p10le",
+        "-color_primaries", "bt2020",
+        "-color_trc", "smpte2084",
+        "-colorspace", "bt2020nc",
+    ]
+    .map(String::from)
+    .to_vec();
+    args.extend(["-x265-params".to_string(), params.join(":")]);
+    if preserve_dv {
+        args.extend(["-dolbyvision".to_string(), "1".to_string()]);
+    }
+    // Apple players only accept HEVC tagged hvc1
+    if ext == "mp4" || ext == "mov" || ext == "m4v" {
+        args.extend(["-tag:v".to_string(), "hvc1".to_string()]);
+    }
+    args
+}
+
+fn describe(info: &HdrInfo) -> String {
+    match (info.dolby_vision, info.hdr10_plus) {
+        (Some(dv), true) => format!("Dolby Vision (profile {}) and HDR10+", dv.profile),
+        (Some(dv), false) => format!("Dolby Vision (profile {})", dv.profile),
+        _ => "HDR10+".to_string(),
+    }
+}
+
+// The decision matrix. Ok(None) means the source has no dynamic metadata
+// and the normal encode path applies unchanged.
+pub fn plan(info: &HdrInfo, ext: &str, preserve: bool, caps: Option<&Capabilities>) -> Result<Option<HdrPlan>, String> {
+    if !info.has_dynamic() {
+        return Ok(None);
+    }
+    let what = describe(info);
+    if !HDR_CONTAINERS.contains(&ext) {
+        return Err(format!("The source has {} metadata; only mp4, mkv and mov outputs can keep it HDR", what));
+    }
+    if caps.is_some_and(|c| !c.has_encoder("libx265")) {
+        return Err(format!("The source has {} metadata, and HDR output needs libx265, which this ffmpeg build doesn't have", what));
+    }
+
+    // Profile 7 carries an enhancement layer x265 can't rebuild, so it can
+    // only go down to its HDR10 base layer.
+    let dv_preservable = info.dolby_vision.is_none_or(|dv| dv.profile != 7);
+    let mut warning = None;
+    if preserve && dv_preservable {
+        return Ok(Some(HdrPlan {
+            report: HdrReport { dolby_vision: info.dolby_vision, hdr10_plus: info.hdr10_plus, action: HdrAction::Preserved },
+            encoder: "libx265",
+            encoder_args: x265_args(info, ext, info.dolby_vision.is_some()),
+            strip_side_data: false,
+            warning: None,
+        }));
+    }
+    if preserve {
+        warning = Some("Dolby Vision profile 7 has an enhancement layer that can't be re-encoded, so it was converted to plain HDR10".to_string());
+    }
+
+    // Profile 5's base layer is IPT, which looks green and purple without
+    // the RPU: there's no HDR10 to fall back to.
+    if info.dolby_vision.is_some_and(|dv| dv.bl_compat_id == 0 && dv.profile == 5) {
+        return Err("Dolby Vision profile 5 has no HDR10 base layer, so it can't be converted without its dynamic metadata; set preserve_dynamic_hdr to keep it".to_string());
+    }
+    if caps.is_some_and(|c| !c.has_filter("sidedata")) {
+        return Err(format!("Removing the {} metadata needs ffmpeg's sidedata filter, which this build doesn't have", what));
+    }
+    if !info.pq {
+        return Err(format!("The source has {} metadata but isn't tagged PQ, so there's no HDR10 to convert it to", what));
+    }
+    Ok(Some(HdrPlan {
+        report: HdrReport { dolby_vision: info.dolby_vision, hdr10_plus: info.hdr10_plus, action: HdrAction::StrippedToHdr10 },
+        encoder: "libx265",
+        encoder_args: x265_args(info, ext, false),
+        strip_side_data: true,
+        warning: warning.or_else(|| {
+            Some(format!(
+                "The {} metadata was removed and the output is plain HDR10; set preserve_dynamic_hdr to keep it",
+                what
+            ))
+        }),
+    }))
+}
+
+// `-vf` stage that drops dynamic metadata from every frame.
+pub fn strip_filter() -> &'static str {
+    "sidedata=mode=delete:type=DOVI_RPU_BUFFER,sidedata=mode=delete:type=DOVI_METADATA,sidedata=mode=delete:type=DYNAMIC_HDR_PLUS"
+}
+
+// ==========================================
+// STATIC HDR: TONEMAP OR PASS THROUGH
+// ==========================================
+// HDR10 and HLG sources without dynamic metadata. H.264 (and VP9 / Theora
+// on our 8-bit paths) can't carry HDR: PQ or HLG samples in an SDR file
+// play washed-out and grey. Those get tonemapped to BT.709 SDR, as does
+// anything with `tonemap_to_sdr`. HEVC and AV1 outputs stay HDR: 10-bit,
+// the source's transfer, and its mastering-display / content-light
+// metadata where the encoder takes it.
+//
+// Tonemapping is a CPU filter chain. We never decode on the GPU (frames
+// reach every encoder from system memory), so there's no hwaccel path to
+// drop for it.
+
+// Codecs that can carry HDR
+const HDR_CODECS: &[&str] = &["hevc", "av1"];
+
+// How encode_video has to change for a static HDR source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StaticPlan {
+    // Run tonemap_filter before everything else
+    pub tonemap: bool,
+    // Joined onto the encoder's args (color flags, pixel format, params)
+    pub args: Vec<String>,
+    pub warning: Option<String>,
+}
+
+impl StaticPlan {
+    pub fn action_name(&self) -> &'static str {
+        if self.tonemap { "tonemapped" } else { "passed_through" }
+    }
+}
+
+// `-vf` stage that takes PQ / HLG down to BT.709 SDR. zscale linearizes
+// (npl = SDR white at 100 nits), tonemap needs float RGB, and the output
+// goes back to 8-bit 4:2:0 what every H.264 player takes.
+pub fn tonemap_filter() -> &'static str {
+    "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p"
+}
+
+// SVT-AV1 wants the same numbers as x265, as plain decimals:
+// G(13250,34500)...L(10000000,50) -> G(0.2650,0.6900)...L(1000.0000,0.0050)
+fn svt_master_display(x265: &str) -> Option<String> {
+    let numbers: Vec<f64> = x265
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| s.parse().ok())
+        .collect();
+    let [gx, gy, bx, by, rx, ry, wx, wy, max, min] = numbers[..] else { return None };
+    let c = |v: f64| format!("{:.4}", v / 50000.0);
+    let l = |v: f64| format!("{:.4}", v / 10000.0);
+    Some(format!(
+        "G({},{})B({},{})R({},{})WP({},{})L({},{})",
+        c(gx), c(gy), c(bx), c(by), c(rx), c(ry), c(wx), c(wy), l(max), l(min)
+    ))
+}
+
+fn passthrough_args(info: &HdrInfo, encoder: &str) -> (Vec<String>, Option<String>) {
+    let software = matches!(encoder, "libx265" | "libsvtav1");
+    let mut args: Vec<String> = [
+        "-pix_fmt", if software { "yuv420p10le" } else { "p010le" },
+        "-color_primaries", "bt2020",
+        "-color_trc", info.transfer(),
+        "-colorspace", "bt2020nc",
+    ]
+    .map(String::from)
+    .to_vec();
+    let mut warning = None;
+    match encoder {
+        "libx265" => {
+            let mut params = vec![
+                "repeat-headers=1".to_string(),
+                "colorprim=bt2020".to_string(),
+                format!("transfer={}", info.transfer()),
+                "colormatrix=bt2020nc".to_string(),
+            ];
+            if info.pq {
+                params.insert(0, "hdr10=1".to_string());
+            }
+            params.extend(info.master_display.as_ref().map(|md| format!("master-display={}", md)));
+            params.extend(info.max_cll.as_ref().map(|cll| format!("max-cll={}", cll)));
+            args.extend(["-x265-params".to_string(), params.join(":")]);
+        }
+        "libsvtav1" => {
+            let mut params = vec![];
+            params.extend(info.master_display.as_deref().and_then(svt_master_display).map(|md| format!("mastering-display={}", md)));
+            params.extend(info.max_cll.as_ref().map(|cll| format!("content-light={}", cll)));
+            if !params.is_empty() {
+                args.extend(["-svtav1-params".to_string(), params.join(":")]);
+            }
+        }
+        _ if info.master_display.is_some() || info.max_cll.is_some() => {
+            warning = Some(format!("{} can't write mastering-display or content-light metadata, so the output only has the HDR color tags", encoder));
+        }
+        _ => {}
+    }
+    (args, warning)
+}
+
+// The decision for sources hdr::plan leaves alone. None = not HDR (or a
+// copy), nothing changes. `codec` is the encoder's, as in encoders.rs.
+pub fn static_plan(info: &HdrInfo, encoder: &str, codec: &str, tonemap_to_sdr: bool, caps: Option<&Capabilities>) -> Result<Option<StaticPlan>, String> {
+    if info.dolby_vision.is_some_and(|dv| dv.bl_compat_id == 0 && dv.profile == 5) {
+        return Err("Dolby Vision profile 5 has no HDR10 base layer, so it can't be tonemapped to SDR".to_string());
+    }
+    if !info.is_hdr() {
+        return Ok(None);
+    }
+    if tonemap_to_sdr || !HDR_CODECS.contains(&codec) {
+        let missing: Vec<&str> = ["zscale", "tonemap"].into_iter().filter(|f| caps.is_some_and(|c| !c.has_filter(f))).collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "The source is HDR and needs tonemapping for {} output, but this ffmpeg build has no {} filter",
+                if codec.is_empty() { encoder } else { codec },
+                missing.join(" or ")
+            ));
+        }
+        let args = ["-color_primaries", "bt709", "-color_trc", "bt709", "-colorspace", "bt709"].map(String::from).to_vec();
+        return Ok(Some(StaticPlan { tonemap: true, args, warning: None }));
+    }
+    let (args, warning) = passthrough_args(info, encoder);
+    Ok(Some(StaticPlan { tonemap: false, args, warning }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capabilities::{self, CapabilityCache};
+    use crate::jobtests::{run_video, video, Harness, CLIP, ENCODE};
+    use tauri::Manager;
+
+    const DV_PROBE: &str = r#"{
+        "streams": [{
+            "color_transfer": "smpte2084",
+            "side_data_list": [{ "side_data_type": "DOVI configuration record", "dv_profile": 8, "dv_level": 6, "dv_bl_signal_compatibility_id": 1 }]
+        }],
+        "frames": [{
+            "side_data_list": [
+                { "side_data_type": "Mastering display metadata",
+                  "red_x": "35400/50000", "red_y": "14600/50000", "green_x": "8500/50000", "green_y": "39850/50000",
+                  "blue_x": "6550/50000", "blue_y": "2300/50000", "white_point_x": "15635/50000", "white_point_y": "16450/50000",
+                  "min_luminance": "50/10000", "max_luminance": "10000000/10000" },
+                { "side_data_type": "Content light level metadata", "max_content": 1000, "max_average": 400 },
+                { "side_data_type": "HDR Dynamic Metadata SMPTE2094-40 (HDR10+)" }
+            ]
+        }]
+    }"#;
+
+    fn dolby_vision(profile: u32, bl_compat_id: u32) -> HdrInfo {
+        HdrInfo { pq: true, dolby_vision: Some(DolbyVision { profile, level: 6, bl_compat_id }), ..Default::default() }
+    }
+
+    #[test]
+    fn probe_json_gives_the_dynamic_and_static_metadata() {
+        let info = parse(DV_PROBE);
+        assert!(info.pq && !info.hlg && info.hdr10_plus);
+        assert_eq!(info.dolby_vision, Some(DolbyVision { profile: 8, level: 6, bl_compat_id: 1 }));
+        assert_eq!(info.master_display.as_deref(), Some("G(8500,39850)B(6550,2300)R(35400,14600)WP(15635,16450)L(10000000,50)"));
+        assert_eq!(info.max_cll.as_deref(), Some("1000,400"));
+        assert_eq!(parse("not json"), HdrInfo::default());
+    }
+
+    #[test]
+    fn comma_locale_fractions_give_the_same_metadata() {
+        let german = DV_PROBE.replace("\"35400/50000\"", "\"0,708\"").replace("\"50/10000\"", "\"0,005\"");
+        assert_eq!(parse(&german).master_display, parse(DV_PROBE).master_display);
+    }
+
+    #[test]
+    fn sources_without_dynamic_metadata_are_left_alone() {
+        let hdr10 = HdrInfo { pq: true, ..Default::default() };
+        assert!(plan(&hdr10, "mp4", false, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn dynamic_metadata_is_stripped_to_hdr10_by_default() {
+        let plan = plan(&parse(DV_PROBE), "mp4", false, None).unwrap().unwrap();
+        assert_eq!(plan.report.action, HdrAction::StrippedToHdr10);
+        assert_eq!(plan.action_name(), "stripped_to_hdr10");
+        assert!(plan.strip_side_data);
+        assert!(!plan.encoder_args.contains(&"-dolbyvision".to_string()));
+        assert!(plan.encoder_args.ends_with(&["-tag:v".to_string(), "hvc1".to_string()]));
+        assert!(plan.encoder_args.iter().any(|a| a.contains("master-display=G(8500,39850)") && a.contains("max-cll=1000,400")));
+    }
+
+    #[test]
+    fn preserving_keeps_the_rpu_except_for_profile_7() {
+        let kept = plan(&dolby_vision(8, 1), "mkv", true, None).unwrap().unwrap();
+        assert_eq!(kept.report.action, HdrAction::Preserved);
+        assert!(!kept.strip_side_data);
+        assert!(kept.encoder_args.windows(2).any(|w| w == ["-dolbyvision", "1"]));
+        // No hvc1 tag outside the Apple containers
+        assert!(!kept.encoder_args.contains(&"hvc1".to_string()));
+
+        let profile7 = plan(&dolby_vision(7, 6), "mkv", true, None).unwrap().unwrap();
+        assert_eq!(profile7.report.action, HdrAction::StrippedToHdr10);
+        assert!(profile7.warning.unwrap().contains("profile 7"));
+    }
+
+    #[test]
+    fn sources_that_cant_reach_a_clean_state_fail() {
+        assert!(plan(&dolby_vision(5, 0), "mp4", false, None).unwrap_err().contains("profile 5"));
+        assert!(plan(&dolby_vision(8, 1), "webm", false, None).unwrap_err().contains("only mp4, mkv and mov"));
+        let no_x265 = Capabilities::default();
+        assert!(plan(&dolby_vision(8, 1), "mp4", false, Some(&no_x265)).unwrap_err().contains("libx265"));
+        let hlg_plus = HdrInfo { hlg: true, hdr10_plus: true, ..Default::default() };
+        assert!(plan(&hlg_plus, "mp4", false, None).unwrap_err().contains("isn't tagged PQ"));
+    }
+
+    #[test]
+    fn svt_takes_the_mastering_display_as_decimals() {
+        assert_eq!(
+            svt_master_display("G(13250,34500)B(7500,3000)R(34000,16000)WP(15635,16450)L(10000000,50)").as_deref(),
+            Some("G(0.2650,0.6900)B(0.1500,0.0600)R(0.6800,0.3200)WP(0.3127,0.3290)L(1000.0000,0.0050)")
+        );
+        assert_eq!(svt_master_display("G(1,2)"), None);
+    }
+
+    #[test]
+    fn static_hdr_is_tonemapped_for_h264_and_kept_for_hevc() {
+        let hdr10 = HdrInfo { pq: true, max_cll: Some("1000,400".to_string()), ..Default::default() };
+        let tonemapped = static_plan(&hdr10, "libx264", "h264", false, None).unwrap().unwrap();
+        assert_eq!(tonemapped.action_name(), "tonemapped");
+        assert!(tonemapped.args.contains(&"bt709".to_string()));
+
+        let kept = static_plan(&hdr10, "libx265", "hevc", false, None).unwrap().unwrap();
+        assert_eq!(kept.action_name(), "passed_through");
+        assert!(kept.args.iter().any(|a| a.starts_with("hdr10=1") && a.contains("max-cll=1000,400")));
+        assert!(static_plan(&hdr10, "libx265", "hevc", true, None).unwrap().unwrap().tonemap);
+
+        // A hardware encoder keeps the tags but can't write the metadata
+        let nvenc = static_plan(&hdr10, "hevc_nvenc", "hevc", false, None).unwrap().unwrap();
+        assert!(nvenc.args.contains(&"p010le".to_string()) && nvenc.warning.is_some());
+
+        let no_zscale = Capabilities::default();
+        assert!(static_plan(&hdr10, "libx264", "h264", false, Some(&no_zscale)).unwrap_err().contains("zscale or tonemap"));
+        assert!(static_plan(&HdrInfo::default(), "libx264", "h264", false, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn hlg_is_tonemapped_for_h264_like_pq() {
+        let hlg = HdrInfo { hlg: true, ..Default::default() };
+        let plan = static_plan(&hlg, "libx264", "h264", false, None).unwrap().unwrap();
+        assert!(plan.tonemap);
+        assert_eq!(plan.args, ["-color_primaries", "bt709", "-color_trc", "bt709", "-colorspace", "bt709"]);
+        // Kept as HLG where the codec carries it
+        let kept = static_plan(&hlg, "libx265", "hevc", false, None).unwrap().unwrap();
+        assert!(kept.args.windows(2).any(|w| w == ["-color_trc", "arib-std-b67"]));
+        assert!(!kept.args.iter().any(|a| a.contains("hdr10=1")));
+    }
+
+    // ==========================================
+    // THROUGH A JOB
+    // ==========================================
+    // The test clip, its video tagged with `transfer`
+    fn tagged(name: &str, transfer: Option<&str>) -> Harness {
+        let mut probe: Value = serde_json::from_str(CLIP).unwrap();
+        if let Some(transfer) = transfer {
+            probe["streams"][0]["color_transfer"] = Value::from(transfer);
+            probe["streams"][0]["color_primaries"] = Value::from("bt2020");
+        }
+        Harness::new(name, &format!(r#"{{ "ffprobe": {}, "runs": {} }}"#, probe, ENCODE))
+    }
+
+    fn encode_of(h: &Harness) -> Vec<String> {
+        h.runs().into_iter().find(|r| r.iter().any(|a| a == "libx264")).expect("an x264 encode")
+    }
+
+    #[test]
+    fn pq_and_hlg_inputs_are_tonemapped_on_the_way_to_h264() {
+        for transfer in ["smpte2084", "arib-std-b67"] {
+            let h = tagged(&format!("hdr-{}", transfer), Some(transfer));
+            run_video(&h, video(&h, "out.mp4")).unwrap();
+
+            let encode = encode_of(&h);
+            assert!(encode.iter().any(|a| a.contains(tonemap_filter())), "{}: {:?}", transfer, encode);
+            assert!(encode.windows(2).any(|w| w == ["-color_trc", "bt709"]), "{:?}", encode);
+        }
+    }
+
+    #[test]
+    fn an_sdr_input_is_encoded_as_it_is() {
+        for transfer in [None, Some("bt709")] {
+            let h = tagged(&format!("sdr-{}", transfer.unwrap_or("untagged")), transfer);
+            run_video(&h, video(&h, "out.mp4")).unwrap();
+
+            let encode = encode_of(&h);
+            assert!(!encode.iter().any(|a| a.contains("zscale") || a.contains("tonemap")), "{:?}", encode);
+            assert!(!encode.iter().any(|a| a == "-color_trc"), "{:?}", encode);
+        }
+    }
+
+    #[test]
+    fn an_hdr_input_fails_before_encoding_without_the_tonemap_filters() {
+        let h = tagged("hdr-no-zscale", Some("smpte2084"));
+        let mut caps = (*capabilities::cached(h.handle()).unwrap()).clone();
+        caps.filters.remove("zscale");
+        h.handle().state::<CapabilityCache>().seed(caps);
+
+        let error = run_video(&h, video(&h, "out.mp4")).err().expect("no zscale to tonemap with");
+        assert!(error.to_string().contains("no zscale filter"), "{}", error);
+        assert!(!h.runs().iter().any(|r| r.iter().any(|a| a == "libx264")));
+        assert_eq!(h.files(), ["clip.mp4"]);
+    }
+}
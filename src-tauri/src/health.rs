@@ -0,0 +1,133 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::capabilities;
+use crate::events::{self, Event};
+use crate::ffmpeg::{self, FfmpegBinary, TrackedOutput};
+
+// ==========================================
+// FFMPEG HEALTH CHECK
+// ==========================================
+// A missing sidecar (a broken install) or one built for another
+// architecture fails every job the same way. Setup checks once that ffmpeg
+// and ffprobe start at all and fires `ffmpeg-unavailable` when they don't,
+// so the frontend can show one setup screen instead of a failure per job.
+// check_ffmpeg runs the same check for the about / diagnostics screens.
+
+#[derive(Serialize, Clone, Debug, Default, JsonSchema)]
+pub struct FfmpegHealth {
+    // ffmpeg and ffprobe both ran
+    pub available: bool,
+    // "sidecar" | "extended" | "stub"
+    pub active: String,
+    // "6.1.1", from the first line of `ffmpeg -version`
+    pub version: Option<String>,
+    pub version_line: Option<String>,
+    pub ffprobe_version: Option<String>,
+    // Names from `-encoders` and `-muxers`
+    pub encoders: Vec<String>,
+    pub muxers: Vec<String>,
+    // Why it isn't available
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Pure: "ffmpeg version n6.1.1-7-g9b8d Copyright (c) ..." -> "n6.1.1-7-g9b8d"
+pub fn parse_version(line: &str) -> Option<String> {
+    let mut words = line.split_whitespace();
+    words.find(|w| *w == "version")?;
+    words.next().map(String::from)
+}
+
+// Pure: `ffmpeg -muxers`. Rows come after a " --" line:
+//  --
+//   E 3g2             3GP2 (3GPP2 file format)
+pub fn parse_muxers(text: &str) -> Vec<String> {
+    text.lines()
+        .skip_while(|l| l.trim() != "--")
+        .skip(1)
+        .filter_map(|l| {
+            let mut parts = l.split_whitespace();
+            let flags = parts.next()?;
+            let name = parts.next()?;
+            flags.contains('E').then(|| name.to_string())
+        })
+        .collect()
+}
+
+async fn ffmpeg_output(app: &AppHandle, args: &[&str]) -> Result<String, String> {
+    let output = ffmpeg::command(app)?.args(args).tracked_output(app).await.map_err(|e| format!("ffmpeg couldn't be started: {}", e))?;
+    if !output.success() {
+        return Err(format!("ffmpeg {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+async fn ffprobe_version(app: &AppHandle) -> Result<String, String> {
+    let output = ffmpeg::ffprobe_command(app)?.args(["-version"]).tracked_output(app).await.map_err(|e| format!("ffprobe couldn't be started: {}", e))?;
+    if !output.success() {
+        return Err("ffprobe -version failed".to_string());
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    Ok(text.lines().next().and_then(parse_version).unwrap_or_default())
+}
+
+// First line of `ffmpeg -version`, which names the exact build.
+pub async fn version_line(app: &AppHandle) -> Option<String> {
+    ffmpeg_output(app, &["-hide_banner", "-version"]).await.ok()?.lines().next().map(String::from)
+}
+
+pub async fn check(app: &AppHandle) -> FfmpegHealth {
+    let binary = app.state::<FfmpegBinary>();
+    let active = if binary.stub().is_some() {
+        "stub"
+    } else if binary.extended().is_some() {
+        "extended"
+    } else {
+        "sidecar"
+    };
+    let mut health = FfmpegHealth { active: active.to_string(), ..Default::default() };
+    let version = match ffmpeg_output(app, &["-hide_banner", "-version"]).await {
+        Ok(text) => text,
+        Err(e) => {
+            health.error = Some(e);
+            return health;
+        }
+    };
+    health.version_line = version.lines().next().map(String::from);
+    health.version = health.version_line.as_deref().and_then(parse_version);
+    match ffprobe_version(app).await {
+        Ok(v) => health.ffprobe_version = Some(v),
+        Err(e) => health.error = Some(e),
+    }
+    // Listing failures leave the lists empty; ffmpeg itself runs
+    health.encoders = ffmpeg_output(app, &["-hide_banner", "-encoders"]).await.map(|t| capabilities::parse_encoders(&t).into_iter().collect()).unwrap_or_default();
+    health.encoders.sort();
+    health.muxers = ffmpeg_output(app, &["-hide_banner", "-muxers"]).await.map(|t| parse_muxers(&t)).unwrap_or_default();
+    health.available = health.error.is_none();
+    health
+}
+
+pub fn check_on_startup(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let health = check(&app).await;
+        match &health.error {
+            None => println!("🎬 ffmpeg {} ({})", health.version.as_deref().unwrap_or("?"), health.active),
+            Some(e) => {
+                println!("❌ ffmpeg is unavailable: {}", e);
+                events::emit(&app, Event::FfmpegUnavailable(health));
+            }
+        }
+    });
+}
+
+// ==========================================
+// COMMAND: CHECK FFMPEG
+// ==========================================
+#[tauri::command]
+pub async fn check_ffmpeg(app: AppHandle) -> FfmpegHealth {
+    check(&app).await
+}
@@ -0,0 +1,1027 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::clock;
+use crate::events::{self, Event};
+use crate::settings::SettingsStore;
+use crate::explain::Explanation;
+use crate::instance::{self, InstanceGuard};
+use crate::queue;
+use crate::request::Annotations;
+use crate::sizefit::FitReport;
+use crate::sourcetool::SourceTool;
+use crate::speedseries::{self, SpeedSample};
+use crate::stats::Stats;
+use crate::store;
+use crate::thumbs;
+use crate::timeline::{self, TimelineEntry};
+use crate::tray;
+use crate::upload::UploadRecord;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Success,
+    Failed,
+}
+
+// One finished job. This is both the on-disk record (one JSON object per
+// line in history.jsonl) and what the history/report APIs hand out.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HistoryEntry {
+    pub id: u64,
+    // Batch/queue the job belonged to, if any
+    #[serde(default)]
+    pub group: Option<String>,
+    // "video" | "image" | "audio" | "concat" | "pip"
+    pub kind: String,
+    pub input: String,
+    pub output: String,
+    pub status: JobStatus,
+    #[serde(default)]
+    pub error: Option<String>,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    #[serde(default)]
+    pub encoder: Option<String>,
+    // Media duration of the output, when known
+    #[serde(default)]
+    pub duration_secs: Option<f64>,
+    pub wall_time_secs: f64,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    // Audio shift applied to fix A/V sync (positive = audio delayed)
+    #[serde(default)]
+    pub av_offset_ms: Option<i64>,
+    // Screen-recording tool the input came from (see sourcetool.rs)
+    #[serde(default)]
+    pub source_tool: Option<SourceTool>,
+    // Size-ladder rung a `fit_size_mb` job fit on (see sizefit.rs)
+    #[serde(default)]
+    pub fit: Option<FitReport>,
+    // Watch folder that picked up the input, if any
+    #[serde(default)]
+    pub watch_folder: Option<String>,
+    // A time-limited preview, not a full encode
+    #[serde(default)]
+    pub partial: bool,
+    // Rehearsal mode: nothing ran, the sizes and times are estimates (see
+    // rehearsal.rs)
+    #[serde(default)]
+    pub simulated: bool,
+    // Encode speed over the job, thinned (see speedseries.rs)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub speed_series: Vec<SpeedSample>,
+    // Lifecycle events of the queue job (see timeline.rs)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub timeline: Vec<TimelineEntry>,
+    // Reasons behind the automatic choices (see explain.rs)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub explanations: Vec<Explanation>,
+    // From the job spec; editable afterwards (update_history_entry)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub note: Option<String>,
+    // Copy to the upload destination, when the job asked for one (see upload.rs)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload: Option<UploadRecord>,
+    // Unix seconds (UTC); see clock.rs for local renderings
+    pub finished_at: u64,
+}
+
+impl HistoryEntry {
+    // Fills in everything that can be read off disk / the clock; callers set
+    // encoder, duration, warnings etc. before recording.
+    pub fn finished(kind: &str, input: &str, output: &str, started: Instant, error: Option<String>) -> Self {
+        let status = if error.is_none() { JobStatus::Success } else { JobStatus::Failed };
+        HistoryEntry {
+            id: 0,
+            group: None,
+            kind: kind.to_string(),
+            input: input.to_string(),
+            output: output.to_string(),
+            status,
+            error,
+            input_bytes: file_size(input),
+            output_bytes: if status == JobStatus::Success { file_size(output) } else { 0 },
+            encoder: None,
+            duration_secs: None,
+            wall_time_secs: started.elapsed().as_secs_f64(),
+            warnings: vec![],
+            av_offset_ms: None,
+            source_tool: None,
+            fit: None,
+            watch_folder: None,
+            partial: false,
+            simulated: false,
+            speed_series: vec![],
+            timeline: vec![],
+            explanations: vec![],
+            tags: vec![],
+            note: None,
+            upload: None,
+            finished_at: now_unix(),
+        }
+    }
+
+    pub fn with_annotations(mut self, annotations: &Annotations) -> Self {
+        let annotations = annotations.normalized();
+        self.tags = annotations.tags;
+        self.note = annotations.note;
+        self
+    }
+
+    // output / input, e.g. 0.25 when the file shrank to a quarter
+    pub fn ratio(&self) -> Option<f64> {
+        if self.input_bytes == 0 || self.status != JobStatus::Success {
+            return None;
+        }
+        Some(self.output_bytes as f64 / self.input_bytes as f64)
+    }
+}
+
+fn file_size(path: &str) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+pub fn now_unix() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// ==========================================
+// STORE (managed state)
+// ==========================================
+// Entries and the aggregates derived from them share one lock, so stats are
+// always exactly the sum of what's in the list.
+pub struct HistoryStore {
+    // Secondary instance: history.jsonl belongs to the other one
+    read_only: bool,
+    inner: Mutex<Inner>,
+    // history-folded.json (see RETENTION); None without a data dir
+    folded_path: Option<PathBuf>,
+    // Writes the file new entries go to: history.jsonl, or the side file of
+    // a secondary instance (see instance.rs). Nothing else writes it.
+    writer: Option<mpsc::Sender<FileWrite>>,
+}
+
+const DEFAULT_PAGE: usize = 50;
+const READ_ONLY: &str = "History can't be changed while another instance of the app owns it";
+
+struct Inner {
+    entries: Vec<HistoryEntry>,
+    // folded.stats plus the entries'
+    stats: Stats,
+    folded: Folded,
+}
+
+// ==========================================
+// WRITER THREAD
+// ==========================================
+// Finished jobs shouldn't wait on the disk, so appends are handed to one
+// thread and the caller moves on. Rewrites (deletes, edits, clear) go
+// through the same thread and wait for it: everything reaches the file in
+// the order it was sent, which is the order of the ids, and a rewrite can
+// never be followed by an append of an entry it already contains.
+enum FileWrite {
+    Append(Box<HistoryEntry>),
+    Rewrite(Vec<HistoryEntry>, mpsc::Sender<Result<(), String>>),
+    Flush(mpsc::Sender<()>),
+}
+
+fn spawn_writer(path: PathBuf) -> Option<mpsc::Sender<FileWrite>> {
+    let (tx, rx) = mpsc::channel();
+    let spawned = thread::Builder::new().name("history-writer".to_string()).spawn(move || {
+        for write in rx {
+            match write {
+                FileWrite::Append(entry) => {
+                    if let Err(e) = append_line(&path, &entry) {
+                        println!("⚠️ Could not write history: {}", e);
+                    }
+                }
+                FileWrite::Rewrite(entries, done) => {
+                    let _ = done.send(rewrite(&path, &entries));
+                }
+                FileWrite::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    });
+    match spawned {
+        Ok(_) => Some(tx),
+        Err(e) => {
+            println!("⚠️ Could not start the history writer, history won't be saved: {}", e);
+            None
+        }
+    }
+}
+
+impl HistoryStore {
+    pub fn load(app: &AppHandle) -> Self {
+        let dir = app.path().app_data_dir().ok();
+        let main = dir.as_ref().map(|d| d.join("history.jsonl"));
+        let folded_path = dir.as_ref().map(|d| d.join(FOLDED_FILE));
+        let secondary = app.try_state::<InstanceGuard>().filter(|_| instance::is_secondary(app)).map(|g| g.owner());
+        let (path, entries, folded) = match secondary {
+            // Read as-is: cleaning up or backing up the file is the owner's job
+            Some(owner) => {
+                let folded: Folded = folded_path.as_deref().and_then(|p| fs::read_to_string(p).ok()).and_then(|t| serde_json::from_str(&t).ok()).unwrap_or_default();
+                let mut entries = main.as_deref().and_then(|p| fs::read_to_string(p).ok()).map(|t| parse_entries(&t).0).unwrap_or_default();
+                entries.retain(|e| !folded.pending.contains(&e.id));
+                (dir.map(|d| d.join(owner.side_file_name())), entries, folded)
+            }
+            None => {
+                let folded: Folded = folded_path.as_deref().and_then(|p| store::load_json(app, "history aggregates", p)).unwrap_or_default();
+                let mut entries = main.as_deref().map(|p| load_entries(app, p)).unwrap_or_default();
+                if let Some(main) = &main {
+                    finish_compaction(main, &folded, &mut entries);
+                    merge_side_files(main, folded.last_id, &mut entries);
+                }
+                (main, entries, folded)
+            }
+        };
+        let stats = Stats::with_folded(&folded.stats, &entries);
+        let writer = path.and_then(spawn_writer);
+        HistoryStore { read_only: secondary.is_some(), inner: Mutex::new(Inner { entries, stats, folded }), folded_path, writer }
+    }
+
+    // Called with the lock held, like every write, so writes keep their order
+    fn rewrite_file(&self, entries: &[HistoryEntry]) -> Result<(), String> {
+        let Some(writer) = &self.writer else { return Ok(()) };
+        let (done, wait) = mpsc::channel();
+        writer.send(FileWrite::Rewrite(entries.to_vec(), done)).map_err(|_| "The history writer has stopped".to_string())?;
+        wait.recv().map_err(|_| "The history writer has stopped".to_string())?
+    }
+
+    // Waits until everything sent so far is on disk (on exit).
+    pub fn flush(&self) {
+        let Some(writer) = &self.writer else { return };
+        let (done, wait) = mpsc::channel();
+        if writer.send(FileWrite::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+
+    pub fn all(&self) -> Vec<HistoryEntry> {
+        self.inner.lock().unwrap().entries.clone()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.inner.lock().unwrap().stats.clone()
+    }
+
+    fn append(&self, mut entry: HistoryEntry) -> HistoryEntry {
+        let mut inner = self.inner.lock().unwrap();
+        // Pruned ids stay used: a compaction's pending list names them
+        entry.id = inner.entries.iter().map(|e| e.id).max().unwrap_or(0).max(inner.folded.last_id) + 1;
+
+        // Sent while holding the lock, so lines land in id order; one thread
+        // writing whole lines keeps concurrent jobs from interleaving them
+        if let Some(writer) = &self.writer {
+            let _ = writer.send(FileWrite::Append(Box::new(entry.clone())));
+        }
+        inner.stats.add(&entry);
+        inner.entries.push(entry.clone());
+        entry
+    }
+
+    // Rewrites history.jsonl without the given ids and recomputes the stats.
+    // Returns how many entries were removed.
+    pub fn delete(&self, ids: &[u64]) -> Result<usize, String> {
+        if self.read_only {
+            return Err(READ_ONLY.to_string());
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let kept: Vec<HistoryEntry> = inner.entries.iter().filter(|e| !ids.contains(&e.id)).cloned().collect();
+        let removed = inner.entries.len() - kept.len();
+        if removed == 0 {
+            return Ok(0);
+        }
+        self.rewrite_file(&kept)?;
+        inner.stats = Stats::with_folded(&inner.folded.stats, &kept);
+        inner.entries = kept;
+        Ok(removed)
+    }
+
+    // Rewrites the file from what's loaded (repair_stores).
+    pub fn persist(&self) -> Result<(), String> {
+        if self.read_only {
+            return Err(READ_ONLY.to_string());
+        }
+        let inner = self.inner.lock().unwrap();
+        self.rewrite_file(&inner.entries)
+    }
+
+    pub fn count(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    // Ids of the entries finished before `cutoff` (unix seconds).
+    pub fn ids_before(&self, cutoff: u64) -> Vec<u64> {
+        self.inner.lock().unwrap().entries.iter().filter(|e| e.finished_at < cutoff).map(|e| e.id).collect()
+    }
+
+    // Empties history.jsonl, and forgets what earlier compactions folded
+    // in. Returns how many entries there were.
+    pub fn clear(&self) -> Result<usize, String> {
+        if self.read_only {
+            return Err(READ_ONLY.to_string());
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let removed = inner.entries.len();
+        let folded = Folded { last_id: inner.folded.last_id.max(inner.entries.iter().map(|e| e.id).max().unwrap_or(0)), ..Folded::default() };
+        if let Some(path) = &self.folded_path {
+            save_folded(path, &folded)?;
+        }
+        self.rewrite_file(&[])?;
+        inner.entries.clear();
+        inner.stats = Stats::default();
+        inner.folded = folded;
+        Ok(removed)
+    }
+
+    // The entries the retention limits are past, oldest first.
+    pub fn prunable(&self, retention: Retention, now: u64) -> Vec<HistoryEntry> {
+        let inner = self.inner.lock().unwrap();
+        let ids = prunable_ids(&inner.entries, retention, now);
+        inner.entries.iter().filter(|e| ids.contains(&e.id)).cloned().collect()
+    }
+
+    // Moves `ids` out of the history in three steps, each on disk before the
+    // next starts: the entries to `archive`, their totals into the folded
+    // stats (with the ids as pending), then history.jsonl without them. A
+    // crash after the second step is finished on the next load; one before
+    // it leaves everything as it was (and maybe a spare archive). Returns
+    // the pruned entries.
+    fn compact(&self, ids: &[u64], archive: &Path) -> Result<Vec<HistoryEntry>, String> {
+        if self.read_only {
+            return Err(READ_ONLY.to_string());
+        }
+        let Some(folded_path) = &self.folded_path else { return Ok(vec![]) };
+        let mut inner = self.inner.lock().unwrap();
+        let (folded, kept, pruned) = fold(&inner.folded, &inner.entries, ids);
+        if pruned.is_empty() {
+            return Ok(vec![]);
+        }
+        rewrite(archive, &pruned)?;
+        save_folded(folded_path, &folded)?;
+        if let Err(e) = self.rewrite_file(&kept) {
+            // Not folded after all; if this fails too, the next load finishes the prune
+            let _ = save_folded(folded_path, &inner.folded);
+            return Err(e);
+        }
+        inner.stats = Stats::with_folded(&folded.stats, &kept);
+        inner.entries = kept;
+        inner.folded = folded;
+        Ok(pruned)
+    }
+
+    // Newest first, `offset` entries in.
+    fn page(&self, limit: usize, offset: usize) -> HistoryPage {
+        let inner = self.inner.lock().unwrap();
+        let entries = inner.entries.iter().rev().skip(offset).take(limit).cloned().collect();
+        HistoryPage { total: inner.entries.len(), entries }
+    }
+
+    // For fields that don't feed the stats (annotations, uploads), so only
+    // the file is rewritten.
+    fn edit(&self, id: u64, f: impl FnOnce(&mut HistoryEntry)) -> Result<HistoryEntry, String> {
+        if self.read_only {
+            return Err(READ_ONLY.to_string());
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let mut entries = inner.entries.clone();
+        let entry = entries.iter_mut().find(|e| e.id == id).ok_or_else(|| format!("History entry {} not found", id))?;
+        f(entry);
+        let updated = entry.clone();
+        self.rewrite_file(&entries)?;
+        inner.entries = entries;
+        Ok(updated)
+    }
+
+    fn annotate(&self, id: u64, annotations: Annotations) -> Result<HistoryEntry, String> {
+        self.edit(id, |entry| {
+            entry.tags = annotations.tags;
+            entry.note = annotations.note;
+        })
+    }
+
+    pub fn set_upload(&self, id: u64, upload: UploadRecord) -> Result<HistoryEntry, String> {
+        self.edit(id, |entry| entry.upload = Some(upload))
+    }
+
+    pub fn get(&self, id: u64) -> Option<HistoryEntry> {
+        self.inner.lock().unwrap().entries.iter().find(|e| e.id == id).cloned()
+    }
+
+    // Newest first. A linear scan: 10k entries take a few milliseconds,
+    // well under what an index would be worth.
+    fn search(&self, query: &HistoryQuery) -> Vec<HistoryEntry> {
+        let words: Vec<String> = query.query.split_whitespace().map(str::to_lowercase).collect();
+        let tags: Vec<String> = query.tags.iter().map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty()).collect();
+        let inner = self.inner.lock().unwrap();
+        inner.entries.iter().rev().filter(|e| query.matches(e, &words, &tags)).cloned().collect()
+    }
+}
+
+// Unix-second bounds, both inclusive and both optional. `from_day`/`to_day`
+// are the same as local calendar days ("2026-03-29", inclusive) for UIs
+// that think in dates; they're resolved in the system zone.
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(default)]
+pub struct DateRange {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub from_day: Option<NaiveDate>,
+    pub to_day: Option<NaiveDate>,
+}
+
+impl DateRange {
+    fn bounds(&self) -> (Option<u64>, Option<u64>) {
+        let from_day = self.from_day.map(clock::local_day_start);
+        let to_day = self.to_day.and_then(|d| d.succ_opt()).map(|d| clock::local_day_start(d).saturating_sub(1));
+        (self.from.max(from_day), [self.to, to_day].into_iter().flatten().min())
+    }
+
+    pub fn contains(&self, finished_at: u64) -> bool {
+        let (from, to) = self.bounds();
+        from.is_none_or(|from| finished_at >= from) && to.is_none_or(|to| finished_at <= to)
+    }
+}
+
+// Every given criterion has to hold (AND). Each word of `query` has to appear
+// in the note, a tag or the input's file name; each of `tags` has to be one
+// of the entry's tags. Case doesn't matter anywhere.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct HistoryQuery {
+    pub query: String,
+    pub tags: Vec<String>,
+    pub date_range: Option<DateRange>,
+}
+
+impl HistoryQuery {
+    fn matches(&self, entry: &HistoryEntry, words: &[String], tags: &[String]) -> bool {
+        if self.date_range.is_some_and(|range| !range.contains(entry.finished_at)) {
+            return false;
+        }
+        let entry_tags: Vec<String> = entry.tags.iter().map(|t| t.to_lowercase()).collect();
+        if !tags.iter().all(|t| entry_tags.contains(t)) {
+            return false;
+        }
+        if words.is_empty() {
+            return true;
+        }
+        let name = Path::new(&entry.input).file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let note = entry.note.as_deref().unwrap_or("").to_lowercase();
+        words.iter().all(|w| name.contains(w.as_str()) || note.contains(w.as_str()) || entry_tags.iter().any(|t| t.contains(w.as_str())))
+    }
+}
+
+// Readable entries, and whether anything besides a half-written last line
+// (an append cut off by a crash, which loses nothing else) was unreadable.
+fn parse_entries(text: &str) -> (Vec<HistoryEntry>, bool) {
+    let lines: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+    let mut entries = vec![];
+    let mut damaged = false;
+    for (i, line) in lines.iter().enumerate() {
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => damaged |= i + 1 < lines.len(),
+        }
+    }
+    (entries, damaged)
+}
+
+pub fn is_valid(text: &str) -> bool {
+    !parse_entries(text).1
+}
+
+// Unreadable lines are skipped rather than throwing the whole history away.
+// If there were more than a cut-off tail, entries only the backup still has
+// are merged back in (by id).
+fn load_entries(app: &AppHandle, path: &Path) -> Vec<HistoryEntry> {
+    let Ok(text) = fs::read_to_string(path) else { return vec![] };
+    let (mut entries, damaged) = parse_entries(&text);
+    if !damaged {
+        // Drop a cut-off tail now, or the next append would glue onto it
+        let cut_off = text.lines().filter(|l| !l.trim().is_empty()).count() > entries.len();
+        if cut_off {
+            if let Err(e) = rewrite(path, &entries) {
+                println!("⚠️ Could not clean up history: {}", e);
+            }
+        }
+        store::refresh_backup(path);
+        return entries;
+    }
+    let backup = fs::read_to_string(store::backup_path(path)).ok().map(|t| parse_entries(&t).0);
+    let restored = backup.as_ref().is_some_and(|b| !b.is_empty());
+    for entry in backup.into_iter().flatten() {
+        if !entries.iter().any(|e| e.id == entry.id) {
+            entries.push(entry);
+        }
+    }
+    entries.sort_by_key(|e| e.id);
+    store::report_recovery(app, "history", restored);
+    entries
+}
+
+// Entries secondary instances wrote, once those instances have exited. They
+// get fresh ids, go into history.jsonl in one atomic rewrite, and only then
+// are the side files removed.
+fn merge_side_files(main: &Path, last_folded_id: u64, entries: &mut Vec<HistoryEntry>) {
+    let Some(dir) = main.parent() else { return };
+    let mut merged = vec![];
+    for file in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let name = file.file_name().to_string_lossy().to_string();
+        let Some(owner) = instance::Owner::from_side_file(&name) else { continue };
+        if owner.alive() {
+            continue;
+        }
+        let side = fs::read_to_string(file.path()).map(|t| parse_entries(&t).0).unwrap_or_default();
+        let mut next = entries.iter().map(|e| e.id).max().unwrap_or(0).max(last_folded_id);
+        for mut entry in side {
+            next += 1;
+            entry.id = next;
+            entries.push(entry);
+        }
+        merged.push(file.path());
+    }
+    if merged.is_empty() {
+        return;
+    }
+    if let Err(e) = rewrite(main, entries) {
+        println!("⚠️ Could not merge history from another instance: {}", e);
+        return;
+    }
+    println!("🔀 Merged history from {} other instance(s)", merged.len());
+    for path in merged {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn append_line(path: &Path, entry: &HistoryEntry) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| e.to_string())?;
+    writeln!(file, "{}", line).map_err(|e| e.to_string())
+}
+
+// Atomic (see store.rs), so a crash mid-write can't lose history.
+fn rewrite(path: &Path, entries: &[HistoryEntry]) -> Result<(), String> {
+    let mut text = String::new();
+    for entry in entries {
+        text.push_str(&serde_json::to_string(entry).map_err(|e| e.to_string())?);
+        text.push('\n');
+    }
+    store::write_atomic(path, text.as_bytes(), is_valid)
+}
+
+// Records a finished job; a missing store (e.g. during early startup) is not an error.
+// Fires `stats-updated` with the new lifetime totals.
+pub fn record(app: &AppHandle, mut entry: HistoryEntry) -> Option<HistoryEntry> {
+    let store = app.try_state::<HistoryStore>()?;
+    if entry.watch_folder.is_none() {
+        entry.watch_folder = queue::current_watch_folder(app);
+    }
+    if entry.speed_series.is_empty() {
+        entry.speed_series = speedseries::for_history(app);
+    }
+    if entry.timeline.is_empty() {
+        entry.timeline = timeline::finish(app, entry.error.as_deref(), entry.warnings.len());
+    }
+    let entry = store.append(entry);
+    events::emit(app, Event::StatsUpdated(store.stats().lifetime));
+    tray::notify_finished(app, &entry);
+    if retention(app).max_entries.is_some_and(|max| store.count() as u64 > max + COMPACT_SLACK) {
+        compact_in_background(app);
+    }
+    Some(entry)
+}
+
+// ==========================================
+// RETENTION
+// ==========================================
+// A year of heavy use is tens of thousands of entries, and every search and
+// page walks them all. Entries past history_max_entries (the oldest go) or
+// older than history_max_age_days are pruned by a background compaction, at
+// startup and whenever the count runs COMPACT_SLACK past the limit. Pruning
+// keeps the numbers: the pruned entries' totals are folded into
+// history-folded.json, which the lifetime and monthly stats add on top of
+// what's left. The entries themselves are written to
+// history-archive/history-<time>.jsonl first (the same format as
+// history.jsonl), so nothing is gone for good, and thumbnails only they
+// pointed at are queued for removal.
+//
+// history-folded.json also keeps the highest id it folded in, so a pruned
+// id is never given out again, and the ids of the last compaction, so one
+// that crashed before history.jsonl was rewritten is finished on load
+// instead of counted twice.
+
+pub const DEFAULT_HISTORY_MAX_ENTRIES: u64 = 20_000;
+pub const DEFAULT_HISTORY_MAX_AGE_DAYS: u64 = 730;
+// Below this a limit is more likely a typo than a wish
+pub const MIN_HISTORY_MAX_ENTRIES: u64 = 100;
+// Entries over max_entries before a compaction runs between startups
+const COMPACT_SLACK: u64 = 500;
+const FOLDED_FILE: &str = "history-folded.json";
+const ARCHIVE_DIR: &str = "history-archive";
+
+// None = no limit of that kind
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Retention {
+    pub max_entries: Option<u64>,
+    pub max_age_days: Option<u64>,
+}
+
+pub fn retention(app: &AppHandle) -> Retention {
+    let settings = app.try_state::<SettingsStore>().map(|s| s.get());
+    Retention {
+        max_entries: settings.as_ref().map_or(Some(DEFAULT_HISTORY_MAX_ENTRIES), |s| s.history_max_entries),
+        max_age_days: settings.as_ref().map_or(Some(DEFAULT_HISTORY_MAX_AGE_DAYS), |s| s.history_max_age_days),
+    }
+}
+
+// What pruned entries leave behind.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+struct Folded {
+    stats: Stats,
+    last_id: u64,
+    // The ids the last compaction folded into `stats`
+    pending: Vec<u64>,
+}
+
+fn save_folded(path: &Path, folded: &Folded) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(folded).map_err(|e| e.to_string())?;
+    store::write_atomic(path, text.as_bytes(), store::parses::<Folded>)
+}
+
+// Pure: ids past the limits at `now` (unix seconds). `entries` are in id
+// order, which is the order they finished in.
+pub fn prunable_ids(entries: &[HistoryEntry], retention: Retention, now: u64) -> Vec<u64> {
+    let cutoff = retention.max_age_days.map(|days| now.saturating_sub(days * 24 * 60 * 60));
+    let over = retention.max_entries.map_or(0, |max| entries.len().saturating_sub(max as usize));
+    entries
+        .iter()
+        .enumerate()
+        .filter(|(i, e)| *i < over || cutoff.is_some_and(|c| e.finished_at < c))
+        .map(|(_, e)| e.id)
+        .collect()
+}
+
+// Pure: `folded` with `ids` folded in, the entries that stay and the ones
+// that go. Stats over the kept entries plus the new folded stats are the
+// stats over all of them, as before.
+fn fold(folded: &Folded, entries: &[HistoryEntry], ids: &[u64]) -> (Folded, Vec<HistoryEntry>, Vec<HistoryEntry>) {
+    let (pruned, kept): (Vec<HistoryEntry>, Vec<HistoryEntry>) = entries.iter().cloned().partition(|e| ids.contains(&e.id));
+    let mut stats = folded.stats.clone();
+    for entry in &pruned {
+        stats.add(entry);
+    }
+    let last_id = pruned.iter().map(|e| e.id).max().unwrap_or(0).max(folded.last_id);
+    let pending = pruned.iter().map(|e| e.id).collect();
+    (Folded { stats, last_id, pending }, kept, pruned)
+}
+
+// Load: a compaction that folded its entries but crashed before removing
+// them from history.jsonl.
+fn finish_compaction(main: &Path, folded: &Folded, entries: &mut Vec<HistoryEntry>) {
+    let before = entries.len();
+    entries.retain(|e| !folded.pending.contains(&e.id));
+    if entries.len() == before {
+        return;
+    }
+    match rewrite(main, entries) {
+        Ok(()) => println!("🗜️ Finished an interrupted history compaction ({} entries)", before - entries.len()),
+        Err(e) => println!("⚠️ Could not finish an interrupted history compaction: {}", e),
+    }
+}
+
+// Inputs and outputs only the pruned entries name.
+fn orphaned_paths(pruned: &[HistoryEntry], kept: &[HistoryEntry]) -> Vec<String> {
+    let named = |e: &HistoryEntry, p: &String| e.input == *p || e.output == *p;
+    let mut paths: Vec<String> = pruned.iter().flat_map(|e| [e.input.clone(), e.output.clone()]).collect();
+    paths.sort();
+    paths.dedup();
+    paths.retain(|p| !kept.iter().any(|e| named(e, p)));
+    paths
+}
+
+fn compact(app: &AppHandle) -> Result<usize, String> {
+    let Some(store) = app.try_state::<HistoryStore>() else { return Ok(0) };
+    if store.read_only {
+        return Ok(0);
+    }
+    let ids: Vec<u64> = store.prunable(retention(app), now_unix()).iter().map(|e| e.id).collect();
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?.join(ARCHIVE_DIR);
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let mut archive = dir.join(format!("history-{}.jsonl", stamp));
+    let mut n = 2;
+    while archive.exists() {
+        archive = dir.join(format!("history-{}-{}.jsonl", stamp, n));
+        n += 1;
+    }
+    let pruned = store.compact(&ids, &archive)?;
+    thumbs::queue_cleanup(app, &orphaned_paths(&pruned, &store.all()));
+    println!("🗜️ Pruned {} history entries past the retention limits, archived to {}", pruned.len(), archive.display());
+    Ok(pruned.len())
+}
+
+// Off the calling thread: the archive and the rewrite are a few MB.
+pub fn compact_in_background(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        if let Err(e) = compact(&app) {
+            println!("⚠️ History compaction failed, nothing was pruned: {}", e);
+        }
+    });
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct HistoryPage {
+    pub entries: Vec<HistoryEntry>,
+    // Entries in the whole history, for the pager
+    pub total: usize,
+}
+
+// ==========================================
+// COMMAND: GET HISTORY
+// ==========================================
+// Newest first. `limit` defaults to DEFAULT_PAGE.
+#[tauri::command]
+pub fn get_history(history: State<'_, HistoryStore>, limit: Option<usize>, offset: Option<usize>) -> HistoryPage {
+    history.page(limit.unwrap_or(DEFAULT_PAGE), offset.unwrap_or(0))
+}
+
+// ==========================================
+// COMMAND: CLEAR HISTORY
+// ==========================================
+// Also resets the lifetime stats, which are derived from the entries.
+#[tauri::command]
+pub fn clear_history(app: AppHandle, history: State<'_, HistoryStore>) -> Result<usize, String> {
+    let removed = history.clear()?;
+    if removed > 0 {
+        println!("🧹 Cleared {} history entries", removed);
+        events::emit(&app, Event::StatsUpdated(history.stats().lifetime));
+    }
+    Ok(removed)
+}
+
+// ==========================================
+// COMMAND: DELETE HISTORY ENTRIES
+// ==========================================
+#[tauri::command]
+pub fn delete_history_entries(app: AppHandle, history: State<'_, HistoryStore>, ids: Vec<u64>) -> Result<usize, String> {
+    let removed = history.delete(&ids)?;
+    if removed > 0 {
+        events::emit(&app, Event::StatsUpdated(history.stats().lifetime));
+    }
+    Ok(removed)
+}
+
+// ==========================================
+// COMMAND: SEARCH HISTORY
+// ==========================================
+#[tauri::command]
+pub fn search_history(history: State<'_, HistoryStore>, query: HistoryQuery) -> Vec<HistoryEntry> {
+    history.search(&query)
+}
+
+// ==========================================
+// COMMAND: UPDATE HISTORY ENTRY
+// ==========================================
+// Replaces the entry's tags and note (not a merge: pass the full new set).
+#[tauri::command]
+pub fn update_history_entry(
+    history: State<'_, HistoryStore>,
+    id: u64,
+    tags: Vec<String>,
+    note: Option<String>,
+) -> Result<HistoryEntry, String> {
+    let annotations = Annotations { tags, note };
+    annotations.validate().map_err(|e| e.to_string())?;
+    history.annotate(id, annotations.normalized())
+}
+
+// ==========================================
+// COMMAND: HISTORY RETENTION
+// ==========================================
+// None lifts that limit. Entries already past a new limit are archived and
+// pruned right away, in the background.
+#[tauri::command]
+pub fn set_history_retention(app: AppHandle, store: State<'_, SettingsStore>, max_entries: Option<u64>, max_age_days: Option<u64>) -> Result<(), String> {
+    if max_entries.is_some_and(|n| n < MIN_HISTORY_MAX_ENTRIES) {
+        return Err(format!("Keep at least {} history entries", MIN_HISTORY_MAX_ENTRIES));
+    }
+    if max_age_days == Some(0) {
+        return Err("History needs to be kept at least one day".to_string());
+    }
+    store.update(|s| {
+        s.history_max_entries = max_entries;
+        s.history_max_age_days = max_age_days;
+    })?;
+    compact_in_background(&app);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const DAY: u64 = 24 * 60 * 60;
+    // 2026-03-15 12:00 UTC
+    const NOW: u64 = 1_773_576_000;
+
+    fn entry(id: u64, input: &str, finished_at: u64) -> HistoryEntry {
+        HistoryEntry {
+            id,
+            input: input.to_string(),
+            output: format!("{}.out.mp4", input),
+            input_bytes: 1000,
+            output_bytes: 400,
+            finished_at,
+            ..HistoryEntry::finished("video", "", "", Instant::now(), None)
+        }
+    }
+
+    // In memory only: no data dir, so nothing is written
+    fn store(entries: Vec<HistoryEntry>) -> HistoryStore {
+        let stats = Stats::from_entries(&entries);
+        HistoryStore { read_only: false, inner: Mutex::new(Inner { entries, stats, folded: Folded::default() }), folded_path: None, writer: None }
+    }
+
+    // A year and a bit of heavy use, the default retention limit
+    fn big_history() -> HistoryStore {
+        let entries = (1..=DEFAULT_HISTORY_MAX_ENTRIES)
+            .map(|id| {
+                let mut e = entry(id, &format!("/videos/2026/clip-{:05}.mov", id), NOW - (DEFAULT_HISTORY_MAX_ENTRIES - id) * 1800);
+                e.tags = vec![["client-a", "client-b", "personal"][id as usize % 3].to_string()];
+                if id % 100 == 0 {
+                    e.note = Some(format!("Sent to the Review team, batch {}", id / 100));
+                }
+                e
+            })
+            .collect();
+        store(entries)
+    }
+
+    fn ids(entries: &[HistoryEntry]) -> Vec<u64> {
+        entries.iter().map(|e| e.id).collect()
+    }
+
+    #[test]
+    fn searching_a_full_history_takes_milliseconds() {
+        let history = big_history();
+        let queries = [
+            HistoryQuery { query: "review".to_string(), ..Default::default() },
+            HistoryQuery { query: "CLIP-1".to_string(), tags: vec!["Client-A".to_string()], ..Default::default() },
+            HistoryQuery { date_range: Some(DateRange { from: Some(NOW - DAY), ..Default::default() }), ..Default::default() },
+            HistoryQuery { query: "nothing-matches-this".to_string(), ..Default::default() },
+        ];
+        let started = Instant::now();
+        let found: Vec<Vec<HistoryEntry>> = queries.iter().map(|q| history.search(q)).collect();
+        let took = started.elapsed();
+
+        assert_eq!(found[0].len(), 200);
+        assert_eq!(found[0][0].id, DEFAULT_HISTORY_MAX_ENTRIES);
+        assert!(found[1].iter().all(|e| e.tags == ["client-a"] && e.input.contains("clip-1")));
+        assert_eq!(found[1].len(), 3333);
+        // Half-hourly: the last day is 48 entries and the one a day ago
+        assert_eq!(found[2].len(), 49);
+        assert!(found[3].is_empty());
+        // Generous for debug builds on a slow CI machine; release is ~10x faster
+        assert!(took < Duration::from_millis(1500), "four searches over {} entries took {:?}", DEFAULT_HISTORY_MAX_ENTRIES, took);
+    }
+
+    #[test]
+    fn search_results_are_newest_first_and_every_criterion_has_to_hold() {
+        let mut entries = vec![entry(1, "/a/Holiday.mov", NOW - 3 * DAY), entry(2, "/a/holiday-2.mov", NOW - DAY), entry(3, "/a/work.mov", NOW)];
+        entries[0].tags = vec!["family".to_string()];
+        entries[2].note = Some("holiday footage, recut".to_string());
+        let history = store(entries);
+        let search = |query: &str, tags: &[&str]| ids(&history.search(&HistoryQuery { query: query.to_string(), tags: tags.iter().map(|t| t.to_string()).collect(), date_range: None }));
+
+        assert_eq!(search("HOLIDAY", &[]), [3, 2, 1]);
+        assert_eq!(search("holiday", &["Family"]), [1]);
+        assert_eq!(search("holiday recut", &[]), [3]);
+        assert_eq!(search("", &[" family "]), [1]);
+        assert_eq!(search("fam", &[]), [1]);
+        assert_eq!(search("", &[]), [3, 2, 1]);
+    }
+
+    fn retention(max_entries: Option<u64>, max_age_days: Option<u64>) -> Retention {
+        Retention { max_entries, max_age_days }
+    }
+
+    // Ten entries, a day apart, the last finished now; the fourth failed
+    fn ten_days() -> Vec<HistoryEntry> {
+        (1..=10)
+            .map(|id| {
+                let mut e = entry(id, &format!("/in/{}.mov", id), NOW - (10 - id) * DAY);
+                if id == 4 {
+                    e.status = JobStatus::Failed;
+                    e.output_bytes = 0;
+                }
+                e
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_oldest_entries_past_either_limit_are_prunable() {
+        let entries = ten_days();
+        assert_eq!(prunable_ids(&entries, retention(Some(7), None), NOW), [1, 2, 3]);
+        // Older than five days: the ones finished six or more days ago
+        assert_eq!(prunable_ids(&entries, retention(None, Some(5)), NOW), [1, 2, 3, 4]);
+        assert_eq!(prunable_ids(&entries, retention(Some(8), Some(7)), NOW), [1, 2]);
+        assert_eq!(prunable_ids(&entries, retention(Some(2), Some(30)), NOW), [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(prunable_ids(&entries, retention(None, None), NOW).is_empty());
+        assert!(prunable_ids(&entries, retention(Some(10), Some(30)), NOW).is_empty());
+    }
+
+    #[test]
+    fn folding_entries_away_keeps_the_totals() {
+        let entries = ten_days();
+        let before = Stats::from_entries(&entries);
+        let (folded, kept, pruned) = fold(&Folded::default(), &entries, &[1, 2, 3, 4]);
+        assert_eq!(ids(&pruned), [1, 2, 3, 4]);
+        assert_eq!(ids(&kept), [5, 6, 7, 8, 9, 10]);
+        assert_eq!((folded.last_id, folded.pending.clone()), (4, vec![1, 2, 3, 4]));
+        assert_eq!(folded.stats.lifetime.jobs, 4);
+        assert_eq!(folded.stats.lifetime.failed_jobs, 1);
+        assert_eq!(folded.stats.lifetime.saved_bytes, 3 * 600);
+        assert_eq!(Stats::with_folded(&folded.stats, &kept), before);
+
+        // A second prune adds to the first
+        let (again, kept, _) = fold(&folded, &kept, &[5, 6]);
+        assert_eq!(again.stats.lifetime.jobs, 6);
+        assert_eq!(again.last_id, 6);
+        assert_eq!(again.pending, [5, 6]);
+        assert_eq!(Stats::with_folded(&again.stats, &kept), before);
+    }
+
+    #[test]
+    fn a_compaction_archives_the_pruned_entries_and_never_reuses_their_ids() {
+        let dir = crate::cancel::TempDir::new(std::env::temp_dir().join(format!("history-test-{}-compact", std::process::id()))).unwrap();
+        let history = HistoryStore { folded_path: Some(dir.path().join(FOLDED_FILE)), ..store(ten_days()) };
+        let before = history.stats();
+        let archive = dir.path().join(ARCHIVE_DIR).join("history-1.jsonl");
+
+        let ids_past = ids(&history.prunable(retention(Some(7), None), NOW));
+        let pruned = history.compact(&ids_past, &archive).unwrap();
+        assert_eq!(ids(&pruned), [1, 2, 3]);
+        assert_eq!(ids(&history.all()), [4, 5, 6, 7, 8, 9, 10]);
+        assert_eq!(history.stats(), before);
+        let (archived, damaged) = parse_entries(&fs::read_to_string(&archive).unwrap());
+        assert_eq!((ids(&archived), damaged), (vec![1, 2, 3], false));
+        let on_disk: Folded = serde_json::from_str(&fs::read_to_string(dir.path().join(FOLDED_FILE)).unwrap()).unwrap();
+        assert_eq!(on_disk, history.inner.lock().unwrap().folded);
+
+        // Everything left goes too; new entries still count on from the highest id
+        history.delete(&(4..=10).collect::<Vec<u64>>()).unwrap();
+        assert_eq!(history.stats().lifetime.jobs, 3);
+        assert_eq!(history.append(entry(0, "/in/new.mov", NOW)).id, 4);
+        history.compact(&[4], &dir.path().join("history-2.jsonl")).unwrap();
+        assert_eq!(history.append(entry(0, "/in/newer.mov", NOW)).id, 5);
+        assert_eq!(history.stats().lifetime.jobs, 5);
+    }
+
+    #[test]
+    fn a_compaction_cut_short_is_finished_on_load() {
+        let dir = crate::cancel::TempDir::new(std::env::temp_dir().join(format!("history-test-{}-finish", std::process::id()))).unwrap();
+        let main = dir.path().join("history.jsonl");
+        let mut entries = ten_days();
+        rewrite(&main, &entries).unwrap();
+        let (folded, _, _) = fold(&Folded::default(), &entries, &[1, 2]);
+
+        finish_compaction(&main, &folded, &mut entries);
+        assert_eq!(ids(&entries), [3, 4, 5, 6, 7, 8, 9, 10]);
+        assert_eq!(ids(&parse_entries(&fs::read_to_string(&main).unwrap()).0), [3, 4, 5, 6, 7, 8, 9, 10]);
+        // Counted once: folded plus what's left is what there was
+        assert_eq!(Stats::with_folded(&folded.stats, &entries), Stats::from_entries(&ten_days()));
+    }
+
+    #[test]
+    fn only_paths_no_kept_entry_names_are_orphaned() {
+        let mut entries = ten_days();
+        entries[4].input = entries[0].input.clone();
+        let orphans = orphaned_paths(&entries[..2], &entries[2..]);
+        assert_eq!(orphans, ["/in/1.mov.out.mp4", "/in/2.mov", "/in/2.mov.out.mp4"]);
+    }
+}
@@ -0,0 +1,167 @@
+use image::RgbaImage;
+use std::collections::HashSet;
+
+// ==========================================
+// IMAGE CONTENT HEURISTICS
+// ==========================================
+// Everything that decides "which format is this picture best stored in" lives
+// here, measured on a small thumbnail so huge inputs stay cheap.
+
+pub const THUMBNAIL_SIZE: u32 = 256;
+// At or below this many distinct colours a palette (PNG8) is lossless
+pub const PALETTE_MAX_COLORS: usize = 256;
+// Flat UI / diagrams rarely go past a few thousand colours, even anti-aliased
+pub const GRAPHIC_MAX_COLORS: usize = 4096;
+// Share of neighbouring pixel pairs with a hard luminance step
+pub const GRAPHIC_MIN_EDGE_DENSITY: f64 = 0.08;
+// Luminance difference (0-255) that counts as a hard step
+const EDGE_STEP: i32 = 48;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageTraits {
+    pub unique_colors: usize,
+    pub has_alpha: bool,
+    pub edge_density: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetFormat {
+    WebpLossy,
+    WebpLossless,
+    PalettePng,
+}
+
+impl TargetFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            TargetFormat::WebpLossy | TargetFormat::WebpLossless => "webp",
+            TargetFormat::PalettePng => "png",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TargetFormat::WebpLossy => "webp",
+            TargetFormat::WebpLossless => "webp-lossless",
+            TargetFormat::PalettePng => "png-palette",
+        }
+    }
+}
+
+fn luma(p: &image::Rgba<u8>) -> i32 {
+    (p[0] as i32 * 299 + p[1] as i32 * 587 + p[2] as i32 * 114) / 1000
+}
+
+pub fn measure(thumb: &RgbaImage) -> ImageTraits {
+    let mut colors = HashSet::new();
+    let mut has_alpha = false;
+    for p in thumb.pixels() {
+        colors.insert([p[0], p[1], p[2]]);
+        has_alpha |= p[3] < 255;
+    }
+
+    let (w, h) = thumb.dimensions();
+    let mut pairs = 0u64;
+    let mut edges = 0u64;
+    for y in 0..h {
+        for x in 0..w {
+            let here = luma(thumb.get_pixel(x, y));
+            for (nx, ny) in [(x + 1, y), (x, y + 1)] {
+                if nx < w && ny < h {
+                    pairs += 1;
+                    if (here - luma(thumb.get_pixel(nx, ny))).abs() >= EDGE_STEP {
+                        edges += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    ImageTraits {
+        unique_colors: colors.len(),
+        has_alpha,
+        edge_density: if pairs == 0 { 0.0 } else { edges as f64 / pairs as f64 },
+    }
+}
+
+// Picks a target format and says why, in words the UI can show as-is.
+pub fn choose(traits: &ImageTraits) -> (TargetFormat, String) {
+    let facts = format!(
+        "{} colours, {:.0}% hard edges{}",
+        traits.unique_colors,
+        traits.edge_density * 100.0,
+        if traits.has_alpha { ", has transparency" } else { "" }
+    );
+    if traits.unique_colors <= PALETTE_MAX_COLORS && !traits.has_alpha {
+        return (TargetFormat::PalettePng, format!("Few colours ({}): a palette PNG is lossless and small", facts));
+    }
+    if traits.has_alpha {
+        if traits.unique_colors <= GRAPHIC_MAX_COLORS {
+            return (TargetFormat::WebpLossless, format!("Transparent graphic ({}): lossless WebP keeps edges and alpha", facts));
+        }
+        return (TargetFormat::WebpLossy, format!("Transparent photo-like image ({}): WebP keeps alpha at a lossy size", facts));
+    }
+    if traits.unique_colors <= GRAPHIC_MAX_COLORS && traits.edge_density >= GRAPHIC_MIN_EDGE_DENSITY {
+        return (TargetFormat::WebpLossless, format!("Looks like a screenshot or graphic ({}): lossless WebP avoids blurry text", facts));
+    }
+    (TargetFormat::WebpLossy, format!("Looks like a photo ({}): lossy WebP", facts))
+}
+
+// What stands in for a WebP target when this ffmpeg has no libwebp: the
+// format label for image_auto's encode_args and a note for the reasoning.
+// Transparency stays in a full RGBA PNG, opaque photos go to JPEG, and only
+// images that already fit a palette are palettized: anything else would
+// come out posterized.
+pub fn without_webp(target: TargetFormat, traits: &ImageTraits) -> (&'static str, &'static str) {
+    if traits.has_alpha {
+        return ("png-rgba", " (no WebP encoder available, using a lossless PNG to keep the transparency)");
+    }
+    match target {
+        TargetFormat::WebpLossy => ("jpeg", " (no WebP encoder available, using JPEG)"),
+        _ if traits.unique_colors <= PALETTE_MAX_COLORS => ("png-palette", " (no WebP encoder available, using a palette PNG)"),
+        _ => ("png", " (no WebP encoder available, using a lossless PNG)"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn traits(unique_colors: usize, has_alpha: bool, edge_density: f64) -> ImageTraits {
+        ImageTraits { unique_colors, has_alpha, edge_density }
+    }
+
+    #[test]
+    fn choose_picks_by_content() {
+        assert_eq!(choose(&traits(16, false, 0.5)).0, TargetFormat::PalettePng);
+        assert_eq!(choose(&traits(16, true, 0.5)).0, TargetFormat::WebpLossless);
+        assert_eq!(choose(&traits(100_000, true, 0.01)).0, TargetFormat::WebpLossy);
+        assert_eq!(choose(&traits(2000, false, 0.2)).0, TargetFormat::WebpLossless);
+        assert_eq!(choose(&traits(100_000, false, 0.01)).0, TargetFormat::WebpLossy);
+    }
+
+    #[test]
+    fn without_webp_keeps_transparency_lossless() {
+        assert_eq!(without_webp(TargetFormat::WebpLossy, &traits(100_000, true, 0.01)).0, "png-rgba");
+        assert_eq!(without_webp(TargetFormat::WebpLossless, &traits(16, true, 0.5)).0, "png-rgba");
+    }
+
+    #[test]
+    fn without_webp_only_palettizes_flat_images() {
+        assert_eq!(without_webp(TargetFormat::WebpLossy, &traits(100_000, false, 0.01)).0, "jpeg");
+        // A screenshot with anti-aliasing has too many colours for 256
+        assert_eq!(without_webp(TargetFormat::WebpLossless, &traits(2000, false, 0.2)).0, "png");
+        assert_eq!(without_webp(TargetFormat::WebpLossless, &traits(200, false, 0.2)).0, "png-palette");
+    }
+
+    #[test]
+    fn measure_sees_alpha_and_colours() {
+        let mut img = RgbaImage::from_pixel(4, 4, image::Rgba([10, 20, 30, 255]));
+        assert_eq!(measure(&img), traits(1, false, 0.0));
+        img.put_pixel(0, 0, image::Rgba([200, 200, 200, 128]));
+        let measured = measure(&img);
+        assert!(measured.has_alpha);
+        assert_eq!(measured.unique_colors, 2);
+        assert!(measured.edge_density > 0.0);
+    }
+}
@@ -0,0 +1,179 @@
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::AppHandle;
+use crate::capabilities;
+use crate::ffmpeg;
+use crate::history::{self, HistoryEntry};
+use crate::image_analysis;
+use crate::inputs;
+use crate::paths;
+use crate::staging;
+
+#[derive(Serialize)]
+pub struct AutoImageResult {
+    pub output: String,
+    // "webp" | "webp-lossless" | "png-palette" | "png-rgba" | "png" | "jpeg" | original extension
+    pub format: String,
+    pub reasoning: String,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    // The picked format came out bigger, so the original format was optimized instead
+    pub fell_back: bool,
+}
+
+// libwebp quality is 0-100 like ours; mjpeg's -q:v runs 2 (best) .. 31
+fn jpeg_qscale(quality: u8) -> u32 {
+    31 - (quality.min(100) as u32 * 29 / 100)
+}
+
+fn encode_args(input: &str, output: &str, format: &str, quality: u8) -> Vec<String> {
+    let mut args: Vec<String> = vec!["-i".into(), input.into()];
+    match format {
+        "webp" => args.extend(["-c:v".into(), "libwebp".into(), "-quality".into(), quality.to_string(), "-compression_level".into(), "6".into()]),
+        "webp-lossless" => args.extend(["-c:v".into(), "libwebp".into(), "-lossless".into(), "1".into(), "-compression_level".into(), "6".into()]),
+        "png-palette" => args.extend([
+            "-vf".into(),
+            "split[a][b];[a]palettegen=max_colors=256:reserve_transparent=0[p];[b][p]paletteuse=dither=none".into(),
+        ]),
+        "jpg" | "jpeg" => args.extend(["-q:v".into(), jpeg_qscale(quality).to_string()]),
+        "png" => args.extend(["-compression_level".into(), "9".into(), "-pred".into(), "mixed".into()]),
+        "png-rgba" => args.extend(["-pix_fmt".into(), "rgba".into(), "-compression_level".into(), "9".into(), "-pred".into(), "mixed".into()]),
+        _ => {}
+    }
+    args.extend(["-frames:v".into(), "1".into(), "-y".into(), output.into()]);
+    args
+}
+
+fn size(path: &Path) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+// A failed or cancelled run leaves no temp file behind
+async fn encode_staged(app: &AppHandle, staged: &Path, args: Vec<String>) -> Result<(), String> {
+    let result = ffmpeg::run_quiet(app, args).await;
+    if result.is_err() {
+        staging::discard(staged);
+    }
+    result
+}
+
+async fn analyse(input: &str) -> Result<image_analysis::ImageTraits, String> {
+    let input = input.to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let img = image::open(&input).map_err(|e| format!("Could not read image: {}", e))?;
+        let thumb = img
+            .thumbnail(image_analysis::THUMBNAIL_SIZE, image_analysis::THUMBNAIL_SIZE)
+            .to_rgba8();
+        Ok(image_analysis::measure(&thumb))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+async fn run(app: &AppHandle, input: &str, output_dir: &str, quality: u8) -> Result<AutoImageResult, String> {
+    let input_path = Path::new(input);
+    inputs::preflight(input).map_err(|e| e.to_string())?;
+    let stem = input_path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let original_ext = input_path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    let input_bytes = size(input_path);
+
+    let traits = analyse(input).await?;
+    let (target, mut reasoning) = image_analysis::choose(&traits);
+    // No libwebp in this ffmpeg build (see image_analysis::without_webp)
+    let has_webp = capabilities::get(app).await.map(|c| c.has_encoder("libwebp")).unwrap_or(true);
+    let mut format = target.label().to_string();
+    if !has_webp && target.extension() == "webp" {
+        let (fallback, note) = image_analysis::without_webp(target, &traits);
+        format = fallback.to_string();
+        reasoning.push_str(note);
+    }
+    let ext = match format.as_str() {
+        "jpeg" => "jpg",
+        "webp" | "webp-lossless" => "webp",
+        _ => "png",
+    };
+
+    let output: PathBuf = Path::new(output_dir).join(format!("{}.{}", stem, ext));
+    let output_str = output.to_string_lossy().to_string();
+    paths::ensure_not_input(input, &output_str)?;
+    fs::create_dir_all(output_dir).map_err(|e| e.to_string())?;
+    // Written under a temp name, so a folder watcher never sees a half-written image
+    let staged = staging::temp_path_for(&output);
+    encode_staged(app, &staged, encode_args(input, &staged.to_string_lossy(), &format, quality)).await?;
+
+    let mut result = AutoImageResult {
+        output: output_str,
+        format,
+        reasoning,
+        input_bytes,
+        output_bytes: size(&staged),
+        fell_back: false,
+    };
+    if result.output_bytes < input_bytes || original_ext.is_empty() {
+        staging::commit(&staged, &output)?;
+        return Ok(result);
+    }
+
+    // Bigger than what we started with: optimize in the original format instead
+    staging::discard(&staged);
+    let fallback = Path::new(output_dir).join(format!("{}.{}", stem, original_ext));
+    let fallback_str = fallback.to_string_lossy().to_string();
+    paths::ensure_not_input(input, &fallback_str)?;
+    let staged = staging::temp_path_for(&fallback);
+    encode_staged(app, &staged, encode_args(input, &staged.to_string_lossy(), &original_ext, quality)).await?;
+    if size(&staged) >= input_bytes {
+        // Even that didn't help: the original is already as small as we can make it
+        if let Err(e) = fs::copy(input, &staged) {
+            staging::discard(&staged);
+            return Err(e.to_string());
+        }
+    }
+    staging::commit(&staged, &fallback)?;
+    result.reasoning = format!(
+        "{}; that came out larger than the original, so it was kept as .{} instead",
+        result.reasoning, original_ext
+    );
+    result.output_bytes = size(&fallback);
+    result.output = fallback_str;
+    result.format = original_ext;
+    result.fell_back = true;
+    Ok(result)
+}
+
+// ==========================================
+// COMMAND: COMPRESS IMAGE (AUTO FORMAT)
+// ==========================================
+#[tauri::command]
+pub async fn compress_image_auto(app: AppHandle, input: String, output_dir: String, quality: Option<u8>) -> Result<AutoImageResult, String> {
+    let started = Instant::now();
+    let result = run(&app, &input, &output_dir, quality.unwrap_or(80)).await;
+    let output = result.as_ref().map(|r| r.output.clone()).unwrap_or_else(|_| output_dir.clone());
+    let mut entry = HistoryEntry::finished("image", &input, &output, started, result.as_ref().err().cloned());
+    if let Ok(r) = &result {
+        entry.encoder = Some(r.format.clone());
+    }
+    history::record(&app, entry);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgba_png_keeps_the_alpha_channel() {
+        let args = encode_args("in.png", "out.png", "png-rgba", 80);
+        assert!(args.windows(2).any(|w| w == ["-pix_fmt", "rgba"]));
+        assert!(!args.iter().any(|a| a.contains("palettegen")));
+    }
+
+    #[test]
+    fn jpeg_quality_maps_onto_qscale() {
+        assert_eq!(jpeg_qscale(100), 2);
+        assert_eq!(jpeg_qscale(0), 31);
+        assert_eq!(jpeg_qscale(255), 2);
+    }
+}
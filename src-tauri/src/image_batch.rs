@@ -0,0 +1,331 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::AppHandle;
+use crate::dryrun::DryRunPlan;
+use crate::events::{self, Event};
+use crate::ffmpeg;
+use crate::history::{self, HistoryEntry};
+use crate::inputs;
+use crate::metadata;
+use crate::outputs::{self, Reservation};
+use crate::paths;
+use crate::request::ImageCompressRequest;
+use crate::support;
+
+// Files per ffmpeg run. With 1,000 thumbnails that's 20 spawns instead of 1,000.
+pub const CHUNK_FILES: usize = 50;
+// Every input of a run is decoded at once, so big photos make smaller chunks
+const CHUNK_BYTES: u64 = 256 * 1024 * 1024;
+
+// ==========================================
+// IMAGE BATCHES: MANY FILES PER FFMPEG
+// ==========================================
+// For small images, starting ffmpeg costs more than the encode. Requests that
+// only differ in their paths (same scale, quality and output format) share
+// one invocation: every input gets its own `-i`, and every output its own
+// `-map N:v` with the common options. A failed run is retried file by file,
+// so one broken image costs its own job and nothing else. Everything else
+// (history, output reservations) behaves as for single images.
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ImageBatchItem {
+    pub input: String,
+    // Final path, which can differ from the requested one (see outputs.rs)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ImageBatchResult {
+    // In request order
+    pub items: Vec<ImageBatchItem>,
+    // How many ffmpeg processes the batch took
+    pub ffmpeg_runs: usize,
+    // dry_run: the runs it would take instead, in order
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<Vec<DryRunPlan>>,
+}
+
+// Payload of `image-batch-progress`.
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct BatchProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+// Output options shared by a group. Animated inputs (GIF) aren't grouped:
+// an output with several frames needs a muxer setup of its own.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct GroupKey {
+    scale: Option<String>,
+    quality: Vec<String>,
+    metadata: Vec<String>,
+    ext: String,
+}
+
+fn ext_of(path: &str) -> String {
+    Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default()
+}
+
+fn group_key(request: &ImageCompressRequest) -> Option<GroupKey> {
+    if ext_of(&request.input) == "gif" || ext_of(&request.output) == "gif" {
+        return None;
+    }
+    Some(GroupKey {
+        scale: request.scale_filter(),
+        quality: request.quality_args(),
+        metadata: metadata::image_args(request.metadata),
+        ext: ext_of(&request.output),
+    })
+}
+
+// Request indexes per ffmpeg run: groups cut into chunks, loners alone.
+fn plan_runs(requests: &[ImageCompressRequest]) -> Vec<Vec<usize>> {
+    let mut groups: BTreeMap<GroupKey, Vec<usize>> = BTreeMap::new();
+    let mut runs = vec![];
+    for (i, request) in requests.iter().enumerate() {
+        match group_key(request) {
+            Some(key) => groups.entry(key).or_default().push(i),
+            None => runs.push(vec![i]),
+        }
+    }
+    for members in groups.into_values() {
+        let mut chunk = vec![];
+        let mut bytes = 0;
+        for i in members {
+            let size = std::fs::metadata(&requests[i].input).map(|m| m.len()).unwrap_or(0);
+            if !chunk.is_empty() && (chunk.len() >= CHUNK_FILES || bytes + size > CHUNK_BYTES) {
+                runs.push(std::mem::take(&mut chunk));
+                bytes = 0;
+            }
+            chunk.push(i);
+            bytes += size;
+        }
+        if !chunk.is_empty() {
+            runs.push(chunk);
+        }
+    }
+    runs
+}
+
+// A prepared file: slot in its chunk, request, claimed output.
+type Job<'a> = (usize, &'a ImageCompressRequest, Reservation);
+
+// Pure: one ffmpeg writing each request to the path next to it.
+fn run_args(targets: &[(&ImageCompressRequest, String)]) -> Vec<String> {
+    let mut args = vec!["-y".to_string()];
+    for (request, _) in targets {
+        args.extend(["-i".to_string(), request.input.clone()]);
+    }
+    for (n, (request, path)) in targets.iter().enumerate() {
+        args.extend(["-map".to_string(), format!("{}:v:0", n)]);
+        if let Some(scale) = request.scale_filter() {
+            args.extend(["-vf".to_string(), scale]);
+        }
+        args.extend(request.quality_args());
+        args.extend(metadata::image_args(request.metadata));
+        args.push(path.clone());
+    }
+    args
+}
+
+// Every staged output of `jobs`.
+fn staged_args(jobs: &[Job]) -> Vec<String> {
+    run_args(&jobs.iter().map(|(_, request, reservation)| (*request, reservation.staged_str())).collect::<Vec<_>>())
+}
+
+// The per-file checks single images get, plus the output claim.
+fn prepare(app: &AppHandle, request: &ImageCompressRequest) -> Result<Reservation, String> {
+    inputs::preflight(&request.input).map_err(|e| e.to_string())?;
+    let reservation = outputs::claim_for_job(app, &request.output, None, None)?;
+    paths::ensure_not_input(&request.input, &reservation.path_str())?;
+    Ok(reservation)
+}
+
+fn finish(app: &AppHandle, request: &ImageCompressRequest, started: Instant, result: Result<String, String>) -> ImageBatchItem {
+    let output = result.as_ref().map_or(request.output.clone(), |o| o.clone());
+    let entry = HistoryEntry::finished("image", &request.input, &output, started, result.as_ref().err().cloned())
+        .with_annotations(&request.annotations);
+    history::record(app, entry);
+    match result {
+        Ok(output) => ImageBatchItem { input: request.input.clone(), output: Some(output), error: None },
+        Err(error) => ImageBatchItem { input: request.input.clone(), output: None, error: Some(error) },
+    }
+}
+
+fn commit(mut reservation: Reservation) -> Result<String, String> {
+    reservation.commit().map(|p| p.to_string_lossy().to_string())
+}
+
+// Runs one planned chunk; returns its items (in chunk order) and ffmpeg runs.
+async fn run_chunk(app: &AppHandle, requests: Vec<&ImageCompressRequest>) -> (Vec<(usize, ImageBatchItem)>, usize) {
+    let started = Instant::now();
+    let mut items = vec![];
+    let mut jobs = vec![];
+    for (slot, request) in requests.into_iter().enumerate() {
+        match prepare(app, request) {
+            Ok(reservation) => jobs.push((slot, request, reservation)),
+            Err(e) => items.push((slot, finish(app, request, started, Err(e)))),
+        }
+    }
+    if jobs.is_empty() {
+        return (items, 0);
+    }
+
+    let result = ffmpeg::run_quiet(app, staged_args(&jobs)).await;
+    if result.is_ok() || jobs.len() == 1 {
+        for (slot, request, reservation) in jobs {
+            let outcome = result.clone().and_then(|_| commit(reservation));
+            items.push((slot, finish(app, request, started, outcome)));
+        }
+        return (items, 1);
+    }
+
+    println!("⚠️ Batched image run failed ({}), retrying its {} files one by one", result.unwrap_err(), jobs.len());
+    let mut runs = 1;
+    for job in jobs {
+        let outcome = ffmpeg::run_quiet(app, staged_args(std::slice::from_ref(&job))).await;
+        runs += 1;
+        let (slot, request, reservation) = job;
+        let outcome = outcome.and_then(|_| commit(reservation));
+        items.push((slot, finish(app, request, started, outcome)));
+    }
+    (items, runs)
+}
+
+// dry_run: the same runs, writing the requested paths, with nothing
+// claimed. A file the checks turn down is left out of its run.
+fn plan_batch(requests: &[ImageCompressRequest]) -> ImageBatchResult {
+    let mut items = vec![];
+    let mut plans = vec![];
+    for run in plan_runs(requests) {
+        let mut targets = vec![];
+        for &i in &run {
+            let request = &requests[i];
+            let checked = inputs::preflight(&request.input).map_err(|e| e.to_string()).and_then(|_| paths::ensure_not_input(&request.input, &request.output));
+            match checked {
+                Ok(()) => {
+                    items.push((i, ImageBatchItem { input: request.input.clone(), output: Some(request.output.clone()), error: None }));
+                    targets.push((request, request.output.clone()));
+                }
+                Err(e) => items.push((i, ImageBatchItem { input: request.input.clone(), output: None, error: Some(e) })),
+            }
+        }
+        if let Some((first, _)) = targets.first() {
+            let encoder = support::image_encoder(&ext_of(&first.output)).unwrap_or("ffmpeg");
+            plans.push(DryRunPlan { filter_graph: first.scale_filter(), ..DryRunPlan::new(encoder, vec![run_args(&targets)], vec![]) });
+        }
+    }
+    items.sort_by_key(|(i, _)| *i);
+    ImageBatchResult { items: items.into_iter().map(|(_, item)| item).collect(), ffmpeg_runs: 0, dry_run: Some(plans) }
+}
+
+// ==========================================
+// COMMAND: COMPRESS IMAGE BATCH
+// ==========================================
+// Nothing runs unless every request is valid. With `dry_run` (or any
+// request's own) nothing runs at all: the result has the ffmpeg runs the
+// batch would take.
+#[tauri::command]
+pub async fn compress_image_batch(app: AppHandle, requests: Vec<ImageCompressRequest>, dry_run: Option<bool>) -> Result<ImageBatchResult, String> {
+    for (i, request) in requests.iter().enumerate() {
+        request.validate().map_err(|e| format!("Image {}: {}", i + 1, e))?;
+    }
+    if dry_run == Some(true) || requests.iter().any(|r| r.dry_run) {
+        return Ok(plan_batch(&requests));
+    }
+    let total = requests.len();
+    let mut items: Vec<Option<ImageBatchItem>> = vec![None; total];
+    let mut ffmpeg_runs = 0;
+    let mut done = 0;
+    for run in plan_runs(&requests) {
+        let (chunk_items, runs) = run_chunk(&app, run.iter().map(|&i| &requests[i]).collect()).await;
+        ffmpeg_runs += runs;
+        for (slot, item) in chunk_items {
+            items[run[slot]] = Some(item);
+        }
+        done += run.len();
+        events::emit(&app, Event::ImageBatchProgress(BatchProgress { done, total }));
+    }
+    println!("🖼️ Image batch: {} files in {} ffmpeg runs", total, ffmpeg_runs);
+    Ok(ImageBatchResult { items: items.into_iter().flatten().collect(), ffmpeg_runs, dry_run: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{image_request, run, Harness};
+    use std::fs;
+
+    fn photos(h: &Harness, count: usize) -> Vec<ImageCompressRequest> {
+        (0..count)
+            .map(|i| {
+                let input = h.file(&format!("photo{}.png", i));
+                image::RgbImage::from_pixel(16, 12, image::Rgb([i as u8, 100, 50])).save(&input).unwrap();
+                ImageCompressRequest { quality: Some(80), ..image_request(input, h.file(&format!("photo{}.jpg", i))) }
+            })
+            .collect()
+    }
+
+    fn batch(h: &Harness, requests: Vec<ImageCompressRequest>) -> ImageBatchResult {
+        let app = h.handle().clone();
+        run(async move { compress_image_batch(app, requests, None).await }).unwrap()
+    }
+
+    #[test]
+    fn images_with_the_same_settings_share_one_ffmpeg_run() {
+        let h = Harness::new("image-batch", r#"{ "runs": [{ "output_bytes": 256 }] }"#);
+        let result = batch(&h, photos(&h, 8));
+
+        assert_eq!(result.ffmpeg_runs, 1);
+        let runs = h.runs();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].iter().filter(|a| *a == "-i").count(), 8);
+        assert_eq!(runs[0].iter().filter(|a| *a == "-map").count(), 8);
+        for (i, item) in result.items.iter().enumerate() {
+            assert_eq!(item.error, None);
+            assert_eq!(item.output.as_deref(), Some(h.file(&format!("photo{}.jpg", i)).as_str()));
+            assert_eq!(fs::metadata(h.file(&format!("photo{}.jpg", i))).unwrap().len(), 256);
+        }
+    }
+
+    #[test]
+    fn each_group_of_settings_and_each_chunk_is_a_run_of_its_own() {
+        let h = Harness::new("image-batch-groups", r#"{ "runs": [{ "output_bytes": 256 }] }"#);
+        let mut requests = photos(&h, CHUNK_FILES + 3);
+        requests[0].quality = Some(40);
+        requests[1].width = Some(8);
+        let result = batch(&h, requests);
+
+        // The two odd ones alone, the other 51 in a full chunk and a short one
+        assert_eq!(result.ffmpeg_runs, 4);
+        let mut inputs: Vec<usize> = h.runs().iter().map(|r| r.iter().filter(|a| *a == "-i").count()).collect();
+        inputs.sort();
+        assert_eq!(inputs, [1, 1, 1, CHUNK_FILES]);
+        assert!(result.items.iter().all(|i| i.error.is_none()));
+    }
+
+    #[test]
+    fn a_failed_run_is_retried_file_by_file() {
+        let h = Harness::new("image-batch-retry", r#"{ "runs": [
+            { "stderr": [{ "line": "photo2.png: Invalid data found when processing input" }], "exit_code": 1 },
+            { "output_bytes": 256 },
+            { "output_bytes": 256 },
+            { "stderr": [{ "line": "photo2.png: Invalid data found when processing input" }], "exit_code": 1 },
+            { "output_bytes": 256 }
+        ] }"#);
+        let result = batch(&h, photos(&h, 4));
+
+        assert_eq!(result.ffmpeg_runs, 5);
+        assert_eq!(h.runs().len(), 5);
+        let failed: Vec<bool> = result.items.iter().map(|i| i.error.is_some()).collect();
+        assert_eq!(failed, [false, false, true, false]);
+        assert!(!fs::exists(h.file("photo2.jpg")).unwrap());
+    }
+}
@@ -0,0 +1,448 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Semaphore;
+
+use crate::AppHandle;
+use crate::capabilities;
+use crate::events::{self, Event};
+use crate::ffmpeg;
+use crate::filterspec;
+use crate::history::{self, HistoryEntry};
+use crate::image_batch::BatchProgress;
+use crate::inputs;
+use crate::metadata::{self, MetadataMode};
+use crate::outputs::{self, OverwritePolicy};
+use crate::paths;
+use crate::probe;
+use crate::request::MAX_DIMENSION;
+
+// Below this, a file is already smaller than any re-encode's headers
+const TINY_BYTES: u64 = 2 * 1024;
+const DEFAULT_QUALITY: u32 = 80;
+const DEFAULT_BACKGROUND: &str = "white";
+pub const HEIC_UNSUPPORTED: &str = "HEIC decode unsupported";
+
+// ==========================================
+// IMAGE FORMAT CONVERSION
+// ==========================================
+// convert_images: a folder of PNGs and JPEGs into WebP or AVIF (or back).
+// Every input gets `stem.<ext>` next to it (or in output_dir), claimed with
+// Rename so nothing already there is replaced, and files run side by side,
+// one ffmpeg each, up to the CPU count at once.
+//
+// Alpha is kept where the target holds it: WebP and PNG directly, AVIF as
+// the second (alpha) image the muxer pairs with the colour one. JPEG has
+// none, so transparent pixels are laid over `background` first instead of
+// coming out black. HEIC inputs need an ffmpeg that reads HEIF (7.1+, with
+// an HEVC decoder); anything else fails with HEIC_UNSUPPORTED.
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConvertFormat {
+    Webp,
+    Avif,
+    Jpeg,
+    Png,
+}
+
+impl ConvertFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ConvertFormat::Webp => "webp",
+            ConvertFormat::Avif => "avif",
+            ConvertFormat::Jpeg => "jpg",
+            ConvertFormat::Png => "png",
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConvertStatus {
+    Converted,
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ConvertItem {
+    pub input: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    pub status: ConvertStatus,
+    // Why it was skipped or failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ConvertResult {
+    // In request order
+    pub items: Vec<ConvertItem>,
+    pub converted: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+// What every file of a run shares.
+#[derive(Clone, Debug)]
+struct Plan {
+    format: ConvertFormat,
+    encoder: String,
+    quality: u32,
+    max_dimension: Option<u32>,
+    background: String,
+    output_dir: Option<String>,
+}
+
+fn ext_of(path: &str) -> String {
+    Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default()
+}
+
+fn is_heic(path: &str) -> bool {
+    matches!(ext_of(path).as_str(), "heic" | "heif")
+}
+
+// Pixel formats with an alpha channel ("pal8" may carry one in PNG/GIF).
+pub fn pix_fmt_has_alpha(pix_fmt: &str) -> bool {
+    ["yuva", "rgba", "argb", "bgra", "abgr", "gbrap", "ya8", "ya16", "pal8"].iter().any(|p| pix_fmt.starts_with(p))
+}
+
+// Pure: where `input` goes, `stem.<ext>` beside it or in `output_dir`.
+pub fn output_for(input: &str, format: ConvertFormat, output_dir: Option<&str>) -> PathBuf {
+    let path = Path::new(input);
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let dir = output_dir.map(PathBuf::from).or_else(|| path.parent().map(Path::to_path_buf)).unwrap_or_default();
+    dir.join(format!("{}.{}", stem, format.extension()))
+}
+
+// Pure: the full ffmpeg command for one file. `alpha` is whether the
+// input has transparency to keep (or, for JPEG, to flatten).
+#[allow(clippy::too_many_arguments)]
+pub fn convert_args(input: &str, staged: &str, format: ConvertFormat, encoder: &str, quality: u32, max_dimension: Option<u32>, alpha: bool, background: &str) -> Vec<String> {
+    let s = |v: &str| v.to_string();
+    let q = quality.clamp(1, 100);
+    // Long edge capped, never enlarged; AVIF and JPEG chroma want even sides
+    let scale = max_dimension
+        .map(|d| format!("scale='min(iw,{d})':'min(ih,{d})':force_original_aspect_ratio=decrease:force_divisible_by=2,", d = d))
+        .unwrap_or_default();
+    let mut args = vec![s("-i"), s(input)];
+    let graph = match (format, alpha) {
+        (ConvertFormat::Jpeg, true) => {
+            format!("[0:v]{}format=rgba[fg];color=c={}[c];[c][fg]scale2ref[bg][fg2];[bg][fg2]overlay=shortest=1,format=yuvj420p[out]", scale, background)
+        }
+        (ConvertFormat::Avif, true) => format!("[0:v]{}format=yuva444p,split[c][a];[c]format=yuv420p[out];[a]alphaextract[alpha]", scale),
+        (ConvertFormat::Webp, true) => format!("[0:v]{}format=yuva420p[out]", scale),
+        (ConvertFormat::Png, true) => format!("[0:v]{}format=rgba[out]", scale),
+        (ConvertFormat::Avif, false) => format!("[0:v]{}format=yuv420p[out]", scale),
+        (_, false) => format!("[0:v]{}null[out]", scale),
+    };
+    args.extend([s("-filter_complex"), graph, s("-map"), s("[out]")]);
+    if format == ConvertFormat::Avif && alpha {
+        args.extend([s("-map"), s("[alpha]")]);
+    }
+    args.extend([s("-c:v"), s(encoder)]);
+    match format {
+        ConvertFormat::Webp => args.extend([s("-quality"), q.to_string(), s("-compression_level"), s("6")]),
+        ConvertFormat::Avif => {
+            // CRF 63 (worst) down to 0; svt's starts at 1
+            let crf = (63 - (q * 63) / 100).max(if encoder == "libsvtav1" { 1 } else { 0 });
+            args.extend([s("-still-picture"), s("1"), s("-crf"), crf.to_string()]);
+            if encoder == "libaom-av1" {
+                args.extend([s("-b:v"), s("0")]);
+            }
+        }
+        // mjpeg's -q:v runs 2 (best) to 31 (worst)
+        ConvertFormat::Jpeg => args.extend([s("-q:v"), (31 - (q * 29) / 100).to_string()]),
+        // Lossless: quality doesn't apply, always the smallest file
+        ConvertFormat::Png => args.extend([s("-compression_level"), s("9"), s("-pred"), s("mixed")]),
+    }
+    args.extend(metadata::image_args(MetadataMode::default()));
+    args.extend([s("-frames:v"), s("1"), s("-y"), s(staged)]);
+    args
+}
+
+pub fn validate(format: ConvertFormat, quality: Option<u32>, max_dimension: Option<u32>, background: Option<&str>) -> Result<(), String> {
+    if let Some(q) = quality.filter(|q| !(1..=100).contains(q)) {
+        return Err(format!("Quality {} is outside 1-100", q));
+    }
+    if let Some(d) = max_dimension.filter(|d| *d < 16 || *d > MAX_DIMENSION) {
+        return Err(format!("max_dimension {} px is outside 16-{}", d, MAX_DIMENSION));
+    }
+    if let Some(color) = background {
+        if format != ConvertFormat::Jpeg {
+            return Err("background only applies to JPEG output; the other formats keep transparency".to_string());
+        }
+        if !filterspec::valid_color(color) {
+            return Err(format!("\"{}\" isn't a color ffmpeg knows (a name like white, or #RRGGBB)", color));
+        }
+    }
+    Ok(())
+}
+
+// Some(reason) when there's no point converting the file.
+fn skip_reason(input: &str, input_bytes: u64, format: ConvertFormat) -> Option<String> {
+    let ext = ext_of(input);
+    if ext == format.extension() || (format == ConvertFormat::Jpeg && ext == "jpeg") {
+        return Some(format!("Already .{}", ext));
+    }
+    if input_bytes < TINY_BYTES {
+        return Some(format!("Only {} bytes: already smaller than any re-encode would be", input_bytes));
+    }
+    None
+}
+
+// Whether the source has transparency; HEIC also has to be readable at all.
+async fn inspect(app: &AppHandle, input: &str) -> Result<bool, String> {
+    let heic = is_heic(input);
+    if heic {
+        let caps = capabilities::get(app).await?;
+        if !caps.can_decode("hevc") {
+            return Err(format!("{}: this ffmpeg build has no HEVC decoder", HEIC_UNSUPPORTED));
+        }
+    }
+    let info = match probe::probe(app, input).await {
+        Ok(info) => info,
+        Err(e) if heic => return Err(format!("{}: this ffmpeg can't read the HEIF container ({})", HEIC_UNSUPPORTED, e)),
+        Err(e) => return Err(e),
+    };
+    let video = info.streams.iter().find(|s| s.codec_type == "video");
+    if video.is_none() {
+        return Err(match heic {
+            true => format!("{}: no picture this ffmpeg can read", HEIC_UNSUPPORTED),
+            false => format!("No picture in {}", input),
+        });
+    }
+    Ok(video.and_then(|s| s.pix_fmt.as_deref()).is_some_and(pix_fmt_has_alpha))
+}
+
+async fn encode(app: &AppHandle, input: &str, plan: &Plan) -> Result<String, String> {
+    let input = &paths::secure_input(input)?;
+    inputs::preflight(input).map_err(|e| e.to_string())?;
+    let alpha = inspect(app, input).await?;
+    let target = paths::secure_output(app, &output_for(input, plan.format, plan.output_dir.as_deref()).to_string_lossy(), false)?;
+    let mut reservation = outputs::claim_for_job(app, &target, Some(OverwritePolicy::Rename), None)?;
+    paths::ensure_not_input(input, &reservation.path_str())?;
+    let args = convert_args(
+        input,
+        &reservation.staged_str(),
+        plan.format,
+        &plan.encoder,
+        plan.quality,
+        plan.max_dimension,
+        alpha,
+        &plan.background,
+    );
+    match ffmpeg::run_quiet(app, args).await {
+        Ok(()) => reservation.commit().map(|p| p.to_string_lossy().to_string()),
+        Err(e) => Err(reservation.classify(e)),
+    }
+}
+
+async fn convert_one(app: &AppHandle, input: String, plan: &Plan) -> ConvertItem {
+    let input_bytes = fs::metadata(&input).map(|m| m.len()).unwrap_or(0);
+    if let Some(reason) = skip_reason(&input, input_bytes, plan.format) {
+        return ConvertItem { input, output: None, status: ConvertStatus::Skipped, reason: Some(reason), input_bytes, output_bytes: 0 };
+    }
+    let started = Instant::now();
+    let result = encode(app, &input, plan).await;
+    let output = result.as_ref().map_or_else(|_| output_for(&input, plan.format, plan.output_dir.as_deref()).to_string_lossy().to_string(), |o| o.clone());
+    let mut entry = HistoryEntry::finished("image", &input, &output, started, result.as_ref().err().cloned());
+    entry.encoder = Some(plan.encoder.clone());
+    history::record(app, entry);
+    match result {
+        Ok(output) => {
+            let output_bytes = fs::metadata(&output).map(|m| m.len()).unwrap_or(0);
+            ConvertItem { input, output: Some(output), status: ConvertStatus::Converted, reason: None, input_bytes, output_bytes }
+        }
+        Err(e) => ConvertItem { input, output: None, status: ConvertStatus::Failed, reason: Some(e), input_bytes, output_bytes: 0 },
+    }
+}
+
+// ==========================================
+// COMMAND: CONVERT IMAGES
+// ==========================================
+// One bad file costs its own item; the command only fails when the run
+// can't start (bad options, no encoder for the target).
+#[tauri::command]
+pub async fn convert_images(
+    app: AppHandle,
+    inputs: Vec<String>,
+    target_format: ConvertFormat,
+    quality: Option<u32>,
+    max_dimension: Option<u32>,
+    background: Option<String>,
+    output_dir: Option<String>,
+) -> Result<ConvertResult, String> {
+    validate(target_format, quality, max_dimension, background.as_deref())?;
+    if inputs.is_empty() {
+        return Err("No images to convert".to_string());
+    }
+    let encoder = crate::image_encoder(&app, target_format.extension()).await;
+    let caps = capabilities::get(&app).await?;
+    if !caps.has_encoder(&encoder) {
+        return Err(format!("This ffmpeg build has no {} encoder, so it can't write .{}", encoder, target_format.extension()));
+    }
+    if let Some(dir) = &output_dir {
+        fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir, e))?;
+    }
+    let plan = Arc::new(Plan {
+        format: target_format,
+        encoder,
+        quality: quality.unwrap_or(DEFAULT_QUALITY),
+        max_dimension,
+        background: background.unwrap_or_else(|| DEFAULT_BACKGROUND.to_string()),
+        output_dir,
+    });
+
+    // Each ffmpeg threads a little on its own; one per core keeps them all busy
+    let slots = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+    let permits = Arc::new(Semaphore::new(slots));
+    let total = inputs.len();
+    let done = Arc::new(AtomicUsize::new(0));
+    println!("🖼️ Converting {} images to {} ({} at a time)", total, target_format.extension(), slots);
+    let mut tasks = vec![];
+    for input in inputs {
+        let (app, plan, permits, done) = (app.clone(), plan.clone(), permits.clone(), done.clone());
+        tasks.push(tauri::async_runtime::spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let item = convert_one(&app, input, &plan).await;
+            let done = done.fetch_add(1, Ordering::SeqCst) + 1;
+            events::emit(&app, Event::ImageBatchProgress(BatchProgress { done, total }));
+            item
+        }));
+    }
+    let mut items = vec![];
+    for task in tasks {
+        items.push(task.await.map_err(|e| e.to_string())?);
+    }
+    let count = |status| items.iter().filter(|i| i.status == status).count();
+    let (converted, skipped, failed) = (count(ConvertStatus::Converted), count(ConvertStatus::Skipped), count(ConvertStatus::Failed));
+    println!("🖼️ Converted {}, skipped {}, failed {}", converted, skipped, failed);
+    Ok(ConvertResult { items, converted, skipped, failed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{run, Harness};
+    use serde_json::json;
+
+    const ALL: [ConvertFormat; 4] = [ConvertFormat::Webp, ConvertFormat::Avif, ConvertFormat::Jpeg, ConvertFormat::Png];
+
+    fn args(format: ConvertFormat, encoder: &str, alpha: bool) -> Vec<String> {
+        convert_args("/in/logo.png", "/out/.logo.staged", format, encoder, 80, None, alpha, "white")
+    }
+
+    fn after<'a>(args: &'a [String], flag: &str) -> &'a str {
+        &args[args.iter().position(|a| a == flag).unwrap_or_else(|| panic!("no {} in {:?}", flag, args)) + 1]
+    }
+
+    #[test]
+    fn each_format_has_its_name_and_extension() {
+        let names: Vec<String> = ALL.iter().map(|f| serde_json::to_value(f).unwrap().as_str().unwrap().to_string()).collect();
+        assert_eq!(names, ["webp", "avif", "jpeg", "png"]);
+        assert_eq!(ALL.map(ConvertFormat::extension), ["webp", "avif", "jpg", "png"]);
+        for format in ALL {
+            assert!(output_for("/photos/IMG_0001.HEIC", format, None).ends_with(format!("IMG_0001.{}", format.extension())));
+        }
+    }
+
+    #[test]
+    fn an_unsupported_target_format_is_refused() {
+        for name in ["gif", "bmp", "heic", "jpg", "WEBP"] {
+            let error = serde_json::from_value::<ConvertFormat>(json!(name)).unwrap_err().to_string();
+            assert!(error.contains("unknown variant"), "{}: {}", name, error);
+        }
+    }
+
+    #[test]
+    fn the_output_goes_beside_the_input_or_into_output_dir() {
+        assert_eq!(output_for("/photos/beach.jpeg", ConvertFormat::Webp, None), PathBuf::from("/photos/beach.webp"));
+        assert_eq!(output_for("/photos/beach.jpeg", ConvertFormat::Avif, Some("/converted")), PathBuf::from("/converted/beach.avif"));
+        // Only the last extension goes
+        assert_eq!(output_for("/photos/scan.v2.png", ConvertFormat::Jpeg, None), PathBuf::from("/photos/scan.v2.jpg"));
+    }
+
+    #[test]
+    fn a_file_already_in_the_target_format_or_tiny_is_skipped() {
+        assert_eq!(skip_reason("/p/a.webp", 50_000, ConvertFormat::Webp).unwrap(), "Already .webp");
+        assert_eq!(skip_reason("/p/a.JPEG", 50_000, ConvertFormat::Jpeg).unwrap(), "Already .jpeg");
+        assert_eq!(skip_reason("/p/a.jpg", 50_000, ConvertFormat::Jpeg).unwrap(), "Already .jpg");
+        assert!(skip_reason("/p/a.png", 100, ConvertFormat::Webp).unwrap().starts_with("Only 100 bytes"));
+        assert_eq!(skip_reason("/p/a.png", 50_000, ConvertFormat::Webp), None);
+        assert_eq!(skip_reason("/p/a.jpg", 50_000, ConvertFormat::Png), None);
+    }
+
+    #[test]
+    fn each_format_gets_its_quality_scale() {
+        let webp = args(ConvertFormat::Webp, "libwebp", false);
+        assert_eq!((after(&webp, "-c:v"), after(&webp, "-quality")), ("libwebp", "80"));
+        let avif = args(ConvertFormat::Avif, "libaom-av1", false);
+        assert_eq!((after(&avif, "-crf"), after(&avif, "-b:v"), after(&avif, "-still-picture")), ("13", "0", "1"));
+        let jpeg = args(ConvertFormat::Jpeg, "mjpeg", false);
+        assert_eq!(after(&jpeg, "-q:v"), "8");
+        let png = args(ConvertFormat::Png, "png", false);
+        assert_eq!(after(&png, "-compression_level"), "9");
+        for args in [webp, avif, jpeg, png] {
+            assert_eq!(args.last().unwrap(), "/out/.logo.staged");
+            assert_eq!(after(&args, "-frames:v"), "1");
+        }
+    }
+
+    #[test]
+    fn transparency_is_kept_or_flattened_by_format() {
+        let avif = args(ConvertFormat::Avif, "libsvtav1", true);
+        assert!(avif.windows(2).any(|w| w == ["-map", "[alpha]"]), "{:?}", avif);
+        assert!(after(&avif, "-filter_complex").contains("alphaextract"));
+        assert!(after(&args(ConvertFormat::Webp, "libwebp", true), "-filter_complex").contains("yuva420p"));
+        let jpeg = after(&args(ConvertFormat::Jpeg, "mjpeg", true), "-filter_complex").to_string();
+        assert!(jpeg.contains("color=c=white") && jpeg.contains("overlay"), "{}", jpeg);
+        // Nothing to keep: no alpha map, no flattening
+        assert!(!args(ConvertFormat::Avif, "libsvtav1", false).iter().any(|a| a == "[alpha]"));
+        assert!(!after(&args(ConvertFormat::Jpeg, "mjpeg", false), "-filter_complex").contains("overlay"));
+    }
+
+    #[test]
+    fn bad_options_are_refused_before_anything_runs() {
+        assert_eq!(validate(ConvertFormat::Webp, Some(0), None, None).unwrap_err(), "Quality 0 is outside 1-100");
+        assert!(validate(ConvertFormat::Webp, None, Some(8), None).unwrap_err().starts_with("max_dimension 8 px"));
+        assert!(validate(ConvertFormat::Png, None, None, Some("white")).unwrap_err().starts_with("background only applies to JPEG"));
+        assert!(validate(ConvertFormat::Jpeg, None, None, Some("not-a-colour")).is_err());
+        assert!(validate(ConvertFormat::Jpeg, Some(100), Some(4096), Some("#ffffff")).is_ok());
+    }
+
+    #[test]
+    fn a_target_the_ffmpeg_build_cannot_write_fails_the_run() {
+        // The test build has libwebp but no AV1 encoder
+        let h = Harness::new("convert-no-avif", r#"{ "runs": [{ "output_bytes": 1500 }] }"#);
+        let (app, input) = (h.handle().clone(), h.input("logo.png", 10_000));
+        let error = run(async move { convert_images(app, vec![input], ConvertFormat::Avif, None, None, None, None).await }).unwrap_err();
+
+        assert_eq!(error, "This ffmpeg build has no libsvtav1 encoder, so it can't write .avif");
+        assert!(h.runs().is_empty());
+        assert_eq!(h.files(), ["logo.png"]);
+    }
+
+    #[test]
+    fn a_png_with_transparency_converts_to_webp_beside_it() {
+        let png = json!({ "streams": [{ "index": 0, "codec_type": "video", "codec_name": "png", "width": 512, "height": 512, "pix_fmt": "rgba" }], "format": { "format_name": "png_pipe" } });
+        let h = Harness::new("convert-webp", &json!({ "ffprobe": png, "runs": [{ "output_bytes": 1500 }] }).to_string());
+        let (app, input) = (h.handle().clone(), h.input("logo.png", 10_000));
+        let result = run(async move { convert_images(app, vec![input], ConvertFormat::Webp, None, None, None, None).await }).unwrap();
+
+        assert_eq!((result.converted, result.skipped, result.failed), (1, 0, 0));
+        assert_eq!(result.items[0].output.as_deref(), Some(h.file("logo.webp").as_str()));
+        assert_eq!(result.items[0].output_bytes, 1500);
+        assert_eq!(h.files(), ["logo.png", "logo.webp"]);
+        let encodes: Vec<Vec<String>> = h.runs().into_iter().filter(|r| r.iter().any(|a| a == "-c:v")).collect();
+        assert_eq!(encodes.len(), 1);
+        assert!(after(&encodes[0], "-filter_complex").contains("yuva420p"), "{:?}", encodes[0]);
+    }
+}
@@ -0,0 +1,189 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+// ==========================================
+// INPUT SANITY CHECKS
+// ==========================================
+// Batch drops are full of failed downloads: zero-byte .mp4s and HTML error
+// pages saved with a video extension. These are caught from the file itself
+// (size + magic bytes) before anything is handed to ffprobe/ffmpeg.
+
+#[derive(Serialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(tag = "kind")]
+pub enum InputError {
+    NotFound { path: String },
+    EmptyInput { path: String },
+    // `detected` is what the bytes say: "html", "text", "matroska", "png", ...
+    MisleadingExtension { path: String, claimed: String, detected: String },
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::NotFound { path } => write!(f, "Input file not found: {}", path),
+            InputError::EmptyInput { path } => write!(f, "{} is empty (0 bytes), probably a failed download", path),
+            InputError::MisleadingExtension { path, claimed, detected } => write!(
+                f,
+                "{} is named .{} but is actually {}",
+                path,
+                claimed,
+                if detected == "html" || detected == "text" { format!("a {} file", detected) } else { format!("{} data", detected) }
+            ),
+        }
+    }
+}
+
+// Container family an extension promises. None = we don't judge it.
+pub fn family_for_extension(ext: &str) -> Option<&'static str> {
+    Some(match ext {
+        "mp4" | "m4v" | "mov" | "3gp" | "m4a" => "mp4",
+        "mkv" | "webm" | "mka" => "matroska",
+        "avi" => "avi",
+        "flv" => "flv",
+        "ts" | "mts" | "m2ts" => "mpegts",
+        "wmv" | "asf" | "wma" => "asf",
+        "ogv" | "ogg" | "oga" | "opus" => "ogg",
+        "wav" => "wav",
+        "mp3" => "mp3",
+        "gif" => "gif",
+        "jpg" | "jpeg" => "jpeg",
+        "png" => "png",
+        "webp" => "webp",
+        _ => return None,
+    })
+}
+
+fn looks_like_text(head: &[u8]) -> bool {
+    let text = match std::str::from_utf8(head) {
+        Ok(t) => t,
+        // A multi-byte char cut off at the end of the buffer is still text
+        Err(e) if e.valid_up_to() + 4 >= head.len() => std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or(""),
+        Err(_) => return false,
+    };
+    !text.is_empty() && text.chars().all(|c| !c.is_control() || c.is_whitespace())
+}
+
+// What the first bytes of a file say it is.
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    let starts = |magic: &[u8]| head.starts_with(magic);
+    let riff = |kind: &[u8]| starts(b"RIFF") && head.len() >= 12 && &head[8..12] == kind;
+
+    if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        return Some("mp4");
+    }
+    if starts(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        return Some("matroska");
+    }
+    if riff(b"AVI ") {
+        return Some("avi");
+    }
+    if riff(b"WAVE") {
+        return Some("wav");
+    }
+    if riff(b"WEBP") {
+        return Some("webp");
+    }
+    if starts(b"FLV") {
+        return Some("flv");
+    }
+    if starts(&[0x30, 0x26, 0xB2, 0x75]) {
+        return Some("asf");
+    }
+    if starts(b"OggS") {
+        return Some("ogg");
+    }
+    if starts(b"GIF8") {
+        return Some("gif");
+    }
+    if starts(&[0xFF, 0xD8, 0xFF]) {
+        return Some("jpeg");
+    }
+    if starts(&[0x89, b'P', b'N', b'G']) {
+        return Some("png");
+    }
+    if head.len() > 188 && head[0] == 0x47 && head[188] == 0x47 {
+        return Some("mpegts");
+    }
+    if starts(b"ID3") || (head.len() >= 2 && head[0] == 0xFF && head[1] & 0xE0 == 0xE0) {
+        return Some("mp3");
+    }
+
+    let trimmed = head.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(head);
+    let start = trimmed.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(0);
+    let lead = String::from_utf8_lossy(&trimmed[start..trimmed.len().min(start + 15)]).to_lowercase();
+    if lead.starts_with("<!doctype html") || lead.starts_with("<html") {
+        return Some("html");
+    }
+    if looks_like_text(head) {
+        return Some("text");
+    }
+    None
+}
+
+// Result of the pure checks, separated from file I/O.
+pub fn judge(path: &str, claimed_ext: &str, size: u64, head: &[u8]) -> Result<Option<String>, InputError> {
+    if size == 0 {
+        return Err(InputError::EmptyInput { path: path.to_string() });
+    }
+    let Some(claimed) = family_for_extension(claimed_ext) else { return Ok(None) };
+    let Some(detected) = sniff(head) else { return Ok(None) };
+    if detected == claimed {
+        return Ok(None);
+    }
+    let misleading = InputError::MisleadingExtension {
+        path: path.to_string(),
+        claimed: claimed_ext.to_string(),
+        detected: detected.to_string(),
+    };
+    match detected {
+        // Not media at all: nothing downstream can make sense of it
+        "html" | "text" => Err(misleading),
+        // Real media in the "wrong" container usually still decodes fine
+        _ => Ok(Some(misleading.to_string())),
+    }
+}
+
+// ==========================================
+// HELPER: PRE-FLIGHT AN INPUT
+// ==========================================
+// Ok(Some(warning)) for media whose extension is wrong but still usable.
+pub fn preflight(path: &str) -> Result<Option<String>, InputError> {
+    let p = Path::new(path);
+    let meta = fs::metadata(p).map_err(|_| InputError::NotFound { path: path.to_string() })?;
+    let mut head = vec![0u8; 512];
+    let read = File::open(p)
+        .and_then(|mut f| f.read(&mut head))
+        .map_err(|_| InputError::NotFound { path: path.to_string() })?;
+    head.truncate(read);
+    let ext = p.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    judge(path, &ext, meta.len(), &head)
+}
+
+#[derive(Serialize)]
+pub struct InputCheck {
+    pub path: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<InputError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+// ==========================================
+// COMMAND: CLASSIFY INPUTS
+// ==========================================
+// Lets the UI offer "rename" / "skip" before a batch is queued.
+#[tauri::command]
+pub fn classify_inputs(paths: Vec<String>) -> Vec<InputCheck> {
+    paths
+        .into_iter()
+        .map(|path| match preflight(&path) {
+            Ok(warning) => InputCheck { path, ok: true, error: None, warning },
+            Err(e) => InputCheck { path, ok: false, error: Some(e), warning: None },
+        })
+        .collect()
+}
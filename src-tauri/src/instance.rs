@@ -0,0 +1,379 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::events::{self, Event};
+
+// A lock file nobody could parse is only taken over once it's this old: a
+// starting instance may be halfway through writing it
+const UNREADABLE_GRACE: Duration = Duration::from_secs(10);
+
+// ==========================================
+// INSTANCE LOCK
+// ==========================================
+// app_data_dir/instance.lock holds the pid and process start time of the
+// instance that owns the stores. A second instance that finds a live owner
+// runs as "secondary": no watch folders, no queue.json, and its history goes
+// to a side file (history.side-<pid>-<start>.jsonl) that the next primary
+// merges once that process is gone. The start time is what makes a reused
+// pid look dead instead of alive.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Owner {
+    pub pid: u32,
+    // Seconds since the epoch, as the OS reports it
+    pub started_at: u64,
+}
+
+impl Owner {
+    pub fn current() -> Self {
+        let pid = std::process::id();
+        Owner { pid, started_at: start_time(pid).unwrap_or(0) }
+    }
+
+    // Start times are compared loosely: some platforms round them
+    pub fn alive(&self) -> bool {
+        start_time(self.pid).is_some_and(|t| t.abs_diff(self.started_at) <= 2)
+    }
+
+    pub fn side_file_name(&self) -> String {
+        format!("history.side-{}-{}.jsonl", self.pid, self.started_at)
+    }
+
+    // Back from a side file name
+    pub fn from_side_file(name: &str) -> Option<Self> {
+        let (pid, started_at) = name.strip_prefix("history.side-")?.strip_suffix(".jsonl")?.split_once('-')?;
+        Some(Owner { pid: pid.parse().ok()?, started_at: started_at.parse().ok()? })
+    }
+}
+
+fn start_time(pid: u32) -> Option<u64> {
+    let pid = Pid::from_u32(pid);
+    let mut system = System::new();
+    system.refresh_processes_specifics(ProcessesToUpdate::Some(&[pid]), true, ProcessRefreshKind::nothing());
+    system.process(pid).map(|p| p.start_time())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Acquired {
+    Primary,
+    // The live instance holding the lock (None = it's still writing it)
+    Secondary(Option<Owner>),
+}
+
+enum Holder {
+    Live(Option<Owner>),
+    Stale(String),
+}
+
+fn holder(path: &Path) -> Result<Holder, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    match serde_json::from_str::<Owner>(&text) {
+        Ok(owner) if owner.alive() => Ok(Holder::Live(Some(owner))),
+        Ok(_) => Ok(Holder::Stale(text)),
+        Err(_) => {
+            let age = fs::metadata(path).and_then(|m| m.modified()).ok().and_then(|t| SystemTime::now().duration_since(t).ok());
+            if age.is_some_and(|a| a >= UNREADABLE_GRACE) { Ok(Holder::Stale(text)) } else { Ok(Holder::Live(None)) }
+        }
+    }
+}
+
+// Moves a stale lock aside. Renames are atomic, so of several instances
+// reclaiming at once only one gets the file; if what it got isn't the stale
+// lock it looked at (someone else reclaimed and locked in between), it's
+// put back untouched.
+fn reclaim(path: &Path, stale: &str, me: &Owner) -> Result<(), String> {
+    let aside = path.with_file_name(format!("instance.lock.stale-{}", me.pid));
+    match fs::rename(path, &aside) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.to_string()),
+    }
+    if fs::read_to_string(&aside).ok().as_deref() == Some(stale) {
+        let _ = fs::remove_file(&aside);
+    } else if !path.exists() {
+        let _ = fs::rename(&aside, path);
+    } else {
+        let _ = fs::remove_file(&aside);
+    }
+    Ok(())
+}
+
+fn try_create(path: &Path, me: &Owner) -> std::io::Result<()> {
+    let mut file = File::options().write(true).create_new(true).open(path)?;
+    file.write_all(serde_json::to_string(me).unwrap_or_default().as_bytes())?;
+    file.sync_all()
+}
+
+pub fn acquire(path: &Path, me: &Owner) -> Result<Acquired, String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    // A few rounds: each lost race means someone else changed the file
+    for _ in 0..3 {
+        match try_create(path, me) {
+            Ok(()) => return Ok(Acquired::Primary),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e.to_string()),
+        }
+        match holder(path) {
+            Ok(Holder::Live(owner)) => return Ok(Acquired::Secondary(owner)),
+            Ok(Holder::Stale(text)) => {
+                println!("🔓 Reclaiming the instance lock of an instance that's gone");
+                reclaim(path, &text, me)?;
+            }
+            // Released between our create and read
+            Err(_) => {}
+        }
+    }
+    Err("Could not settle who owns the instance lock".to_string())
+}
+
+// Only removes the lock if it's still ours.
+pub fn release(path: &Path, me: &Owner) {
+    let ours = fs::read_to_string(path).ok().and_then(|t| serde_json::from_str::<Owner>(&t).ok()) == Some(*me);
+    if ours {
+        let _ = fs::remove_file(path);
+    }
+}
+
+// ==========================================
+// MANAGED STATE
+// ==========================================
+pub struct InstanceGuard {
+    me: Owner,
+    lock: Option<PathBuf>,
+    pub mode: Acquired,
+}
+
+impl InstanceGuard {
+    // Runs before any store is loaded. Without a data dir, or when the lock
+    // can't be settled, the instance runs as primary like it always did.
+    pub fn acquire(app: &AppHandle) -> Self {
+        let me = Owner::current();
+        let lock = app.path().app_data_dir().ok().map(|d| d.join("instance.lock"));
+        let mode = match lock.as_deref().map(|p| acquire(p, &me)) {
+            Some(Ok(mode)) => mode,
+            Some(Err(e)) => {
+                println!("⚠️ Instance lock: {}", e);
+                Acquired::Primary
+            }
+            None => Acquired::Primary,
+        };
+        if let Acquired::Secondary(owner) = mode {
+            println!("👥 Another instance owns the stores ({:?}), running as secondary", owner.map(|o| o.pid));
+        }
+        InstanceGuard { me, lock, mode }
+    }
+
+    pub fn owner(&self) -> Owner {
+        self.me
+    }
+
+    pub fn release(&self) {
+        if let (Acquired::Primary, Some(lock)) = (self.mode, &self.lock) {
+            release(lock, &self.me);
+        }
+    }
+}
+
+pub fn is_secondary(app: &AppHandle) -> bool {
+    app.try_state::<InstanceGuard>().is_some_and(|g| g.mode != Acquired::Primary)
+}
+
+// ==========================================
+// SECONDARY-INSTANCE EVENT / COMMAND
+// ==========================================
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct InstanceStatus {
+    pub secondary: bool,
+    // Pid of the instance that owns the stores, when known
+    pub primary_pid: Option<u32>,
+    pub limitations: Vec<&'static str>,
+}
+
+fn status(guard: &InstanceGuard) -> InstanceStatus {
+    let (secondary, primary_pid) = match guard.mode {
+        Acquired::Primary => (false, None),
+        Acquired::Secondary(owner) => (true, owner.map(|o| o.pid)),
+    };
+    let limitations = if secondary {
+        vec![
+            "Watch folders are off",
+            "The queue isn't saved, so unfinished jobs are lost on exit",
+            "History can't be edited; new entries are merged in when the other instance starts next",
+        ]
+    } else {
+        vec![]
+    };
+    InstanceStatus { secondary, primary_pid, limitations }
+}
+
+// Emitted once at startup; the command is there for a UI that loads later.
+pub fn announce(app: &AppHandle) {
+    let guard = app.state::<InstanceGuard>();
+    if guard.mode != Acquired::Primary {
+        events::emit(app, Event::SecondaryInstance(status(&guard)));
+    }
+}
+
+#[tauri::command]
+pub fn get_instance_status(guard: State<'_, InstanceGuard>) -> InstanceStatus {
+    status(&guard)
+}
+
+// The other instances are `sleep` processes
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::cancel::TempDir;
+    use std::process::{Child, Command};
+    use std::sync::{Arc, Barrier};
+
+    fn folder(name: &str) -> TempDir {
+        TempDir::new(std::env::temp_dir().join(format!("instance-test-{}-{}", std::process::id(), name))).unwrap()
+    }
+
+    fn write_lock(path: &Path, owner: &Owner) {
+        fs::write(path, serde_json::to_string(owner).unwrap()).unwrap();
+    }
+
+    fn lock_owner(path: &Path) -> Owner {
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    // A live process other than this one, with its own start time
+    fn sleeper() -> (Child, Owner) {
+        let child = Command::new("sleep").arg("30").spawn().unwrap();
+        let pid = child.id();
+        let started_at = start_time(pid).unwrap();
+        (child, Owner { pid, started_at })
+    }
+
+    fn gone_pid() -> u32 {
+        let mut child = Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        child.id()
+    }
+
+    #[test]
+    fn the_first_instance_is_primary_and_releases_only_its_own_lock() {
+        let dir = folder("first");
+        let lock = dir.path().join("instance.lock");
+        let me = Owner::current();
+        assert_eq!(acquire(&lock, &me).unwrap(), Acquired::Primary);
+        assert_eq!(lock_owner(&lock), me);
+
+        release(&lock, &Owner { pid: me.pid + 1, ..me });
+        assert!(lock.exists());
+        release(&lock, &me);
+        assert!(!lock.exists());
+    }
+
+    #[test]
+    fn a_live_owner_makes_the_next_instance_secondary() {
+        let dir = folder("live");
+        let lock = dir.path().join("instance.lock");
+        let (mut child, owner) = sleeper();
+        write_lock(&lock, &owner);
+        assert!(owner.alive());
+        assert_eq!(acquire(&lock, &Owner::current()).unwrap(), Acquired::Secondary(Some(owner)));
+        assert_eq!(lock_owner(&lock), owner);
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
+    #[test]
+    fn a_dead_owners_lock_is_taken_over() {
+        let dir = folder("stale");
+        let lock = dir.path().join("instance.lock");
+        let dead = Owner { pid: gone_pid(), started_at: 1 };
+        write_lock(&lock, &dead);
+        let me = Owner::current();
+        assert_eq!(acquire(&lock, &me).unwrap(), Acquired::Primary);
+        assert_eq!(lock_owner(&lock), me);
+        assert!(!dir.path().join(format!("instance.lock.stale-{}", me.pid)).exists());
+    }
+
+    #[test]
+    fn a_reused_pid_with_another_start_time_is_dead() {
+        let dir = folder("reused");
+        let lock = dir.path().join("instance.lock");
+        let me = Owner::current();
+        // Our own pid, but an instance that started long before this process
+        let earlier = Owner { pid: me.pid, started_at: me.started_at.saturating_sub(3600) };
+        assert!(!earlier.alive());
+        write_lock(&lock, &earlier);
+        assert_eq!(acquire(&lock, &me).unwrap(), Acquired::Primary);
+        assert_eq!(lock_owner(&lock), me);
+    }
+
+    #[test]
+    fn an_unreadable_lock_waits_out_the_grace_period() {
+        let dir = folder("unreadable");
+        let lock = dir.path().join("instance.lock");
+        fs::write(&lock, "{\"pid\": 12").unwrap();
+        let me = Owner::current();
+        // Someone may still be writing it
+        assert_eq!(acquire(&lock, &me).unwrap(), Acquired::Secondary(None));
+
+        let old = SystemTime::now() - UNREADABLE_GRACE - Duration::from_secs(5);
+        File::options().write(true).open(&lock).unwrap().set_modified(old).unwrap();
+        assert_eq!(acquire(&lock, &me).unwrap(), Acquired::Primary);
+        assert_eq!(lock_owner(&lock), me);
+    }
+
+    #[test]
+    fn racing_instances_agree_on_one_primary() {
+        for round in 0..20 {
+            let dir = folder(&format!("race-{}", round));
+            let lock = dir.path().join("instance.lock");
+            // Start from a stale lock so every instance also races to reclaim it
+            write_lock(&lock, &Owner { pid: gone_pid(), started_at: 1 });
+
+            let sleepers: Vec<(Child, Owner)> = (0..6).map(|_| sleeper()).collect();
+            let barrier = Arc::new(Barrier::new(sleepers.len()));
+            let handles: Vec<_> = sleepers
+                .iter()
+                .map(|(_, owner)| {
+                    let (owner, lock, barrier) = (*owner, lock.clone(), barrier.clone());
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        (owner, acquire(&lock, &owner).unwrap())
+                    })
+                })
+                .collect();
+            let results: Vec<(Owner, Acquired)> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+            let primaries: Vec<Owner> = results.iter().filter(|(_, a)| *a == Acquired::Primary).map(|(o, _)| *o).collect();
+            assert_eq!(primaries.len(), 1, "round {}: {:?}", round, results);
+            let winner = primaries[0];
+            assert_eq!(lock_owner(&lock), winner);
+            for (_, acquired) in &results {
+                assert!(matches!(acquired, Acquired::Primary | Acquired::Secondary(None)) || *acquired == Acquired::Secondary(Some(winner)), "round {}: {:?}", round, results);
+            }
+            // Nothing left behind by the reclaims
+            assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+            for (mut child, _) in sleepers {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
+
+    #[test]
+    fn side_file_names_round_trip() {
+        let owner = Owner { pid: 4242, started_at: 1_700_000_000 };
+        assert_eq!(owner.side_file_name(), "history.side-4242-1700000000.jsonl");
+        assert_eq!(Owner::from_side_file(&owner.side_file_name()), Some(owner));
+        assert_eq!(Owner::from_side_file("history.jsonl"), None);
+        assert_eq!(Owner::from_side_file("history.side-x-1.jsonl"), None);
+    }
+}
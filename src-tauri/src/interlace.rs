@@ -0,0 +1,288 @@
+use serde::Serialize;
+
+use crate::AppHandle;
+use crate::ffmpeg::{self, TrackedOutput};
+use crate::probe::MediaInfo;
+
+// Frames of the source `idet` looks at, taken a little way in so studio
+// logos and black leaders don't dominate.
+const SAMPLE_SKIP_SECS: u32 = 30;
+const SAMPLE_FRAMES: u32 = 1000;
+// Fewer classified frames than this and we don't decide anything
+const MIN_FRAMES: u64 = 100;
+
+// Ratios that drive the decision; see `decide`.
+const PROGRESSIVE_MAX_INTERLACED: f64 = 0.05;
+const TELECINE_MIN_REPEATED: f64 = 0.10;
+const TELECINE_MIN_INTERLACED: f64 = 0.20;
+const TELECINE_MAX_INTERLACED: f64 = 0.60;
+
+// Deinterlacer for frames that are flagged or detected as interlaced;
+// progressive frames pass through untouched.
+const DEINTERLACE_FILTER: &str = "bwdif=mode=send_frame:deint=interlaced";
+// Inverse telecine: rebuild the film frames, then drop the duplicate in each
+// group of five (29.97 -> 23.976). Leftover combed frames (edits made after
+// the telecine) go through the deinterlacer when that option is on too.
+const IVTC_FILTER: &str = "fieldmatch=order=auto:combmatch=full,decimate";
+const IVTC_DEINTERLACE_FILTER: &str = "fieldmatch=order=auto:combmatch=full,bwdif=mode=send_frame:deint=interlaced,decimate";
+
+// Totals from the summary `idet` prints when it's done.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct IdetStats {
+    pub tff: u64,
+    pub bff: u64,
+    pub progressive: u64,
+    pub undetermined: u64,
+    // Repeated-field counters: a pulled-down frame repeats its top or bottom field
+    pub repeated_neither: u64,
+    pub repeated_top: u64,
+    pub repeated_bottom: u64,
+}
+
+impl IdetStats {
+    fn classified(&self) -> u64 {
+        self.tff + self.bff + self.progressive
+    }
+
+    fn interlaced_ratio(&self) -> f64 {
+        (self.tff + self.bff) as f64 / self.classified().max(1) as f64
+    }
+
+    fn repeated_ratio(&self) -> f64 {
+        let total = self.repeated_neither + self.repeated_top + self.repeated_bottom;
+        (self.repeated_top + self.repeated_bottom) as f64 / total.max(1) as f64
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanType {
+    Progressive,
+    Interlaced,
+    Telecine,
+    #[default]
+    Unknown,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldAction {
+    #[default]
+    None,
+    Deinterlace,
+    InverseTelecine,
+}
+
+// What was found and what was done about it, for the job result.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct FieldReport {
+    pub detected: ScanType,
+    pub action: FieldAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<IdetStats>,
+}
+
+impl FieldReport {
+    pub fn filter(&self, deinterlace: bool) -> Option<&'static str> {
+        match self.action {
+            FieldAction::None => None,
+            FieldAction::Deinterlace => Some(DEINTERLACE_FILTER),
+            FieldAction::InverseTelecine if deinterlace => Some(IVTC_DEINTERLACE_FILTER),
+            FieldAction::InverseTelecine => Some(IVTC_FILTER),
+        }
+    }
+}
+
+// Numbers after each `Label:` in one idet summary line.
+fn counts(line: &str, label: &str) -> Option<u64> {
+    let start = line.find(label)? + label.len();
+    line[start..].split_whitespace().next()?.parse().ok()
+}
+
+// ==========================================
+// IDET OUTPUT PARSING
+// ==========================================
+//   [Parsed_idet_0 @ 0x..] Repeated Fields: Neither:   812 Top:    94 Bottom:    94
+//   [Parsed_idet_0 @ 0x..] Single frame detection: TFF:   380 BFF:     0 Progressive:   572 Undetermined:    48
+//   [Parsed_idet_0 @ 0x..] Multi frame detection: TFF:   401 BFF:     0 Progressive:   590 Undetermined:     9
+// Multi-frame detection is steadier, so that's the one used.
+pub fn parse_idet(stderr: &str) -> Option<IdetStats> {
+    let mut stats = IdetStats::default();
+    let mut found = false;
+    for line in stderr.lines() {
+        // A count that doesn't parse is taken as 0 rather than losing the line
+        if line.contains("Multi frame detection:") {
+            let (tff, bff, progressive) = (counts(line, "TFF:"), counts(line, "BFF:"), counts(line, "Progressive:"));
+            found |= tff.or(bff).or(progressive).is_some();
+            stats.tff = tff.unwrap_or(0);
+            stats.bff = bff.unwrap_or(0);
+            stats.progressive = progressive.unwrap_or(0);
+            stats.undetermined = counts(line, "Undetermined:").unwrap_or(0);
+        } else if line.contains("Repeated Fields:") {
+            stats.repeated_neither = counts(line, "Neither:").unwrap_or(0);
+            stats.repeated_top = counts(line, "Top:").unwrap_or(0);
+            stats.repeated_bottom = counts(line, "Bottom:").unwrap_or(0);
+        }
+    }
+    found.then_some(stats)
+}
+
+// ==========================================
+// DECISION
+// ==========================================
+// Hard telecine (3:2 pulldown) shows up as fields repeated in 2 of every 5
+// frames, and as combing in the frames that mix two film frames: roughly
+// 40% interlaced, never close to 100%. True interlaced video is combed almost
+// everywhere there's motion.
+pub fn decide(stats: &IdetStats) -> ScanType {
+    if stats.classified() < MIN_FRAMES {
+        return ScanType::Unknown;
+    }
+    let interlaced = stats.interlaced_ratio();
+    let repeated = stats.repeated_ratio();
+    if interlaced < PROGRESSIVE_MAX_INTERLACED && repeated < TELECINE_MIN_REPEATED {
+        ScanType::Progressive
+    } else if repeated >= TELECINE_MIN_REPEATED
+        || (TELECINE_MIN_INTERLACED..TELECINE_MAX_INTERLACED).contains(&interlaced)
+    {
+        ScanType::Telecine
+    } else if interlaced >= TELECINE_MAX_INTERLACED {
+        ScanType::Interlaced
+    } else {
+        ScanType::Unknown
+    }
+}
+
+// Telecine only turns 23.976 film into 29.97 NTSC video.
+fn is_ntsc_rate(fps: Option<f64>) -> bool {
+    fps.is_some_and(|f| (f - 29.97).abs() < 0.05)
+}
+
+pub async fn analyze(app: &AppHandle, input: &str) -> Result<Option<IdetStats>, String> {
+    let output = ffmpeg::command(app)?
+        .args([
+            "-hide_banner",
+            "-ss", &SAMPLE_SKIP_SECS.to_string(),
+            "-i", input,
+            "-an", "-sn",
+            "-frames:v", &SAMPLE_FRAMES.to_string(),
+            "-vf", "idet",
+            "-f", "null", "-",
+        ])
+        .tracked_output(app)
+        .await
+        .map_err(|e| e.to_string())?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // Clips shorter than the skip give no frames; retry from the start
+    match parse_idet(&stderr).filter(|s| s.classified() > 0) {
+        Some(stats) => Ok(Some(stats)),
+        None => {
+            let output = ffmpeg::command(app)?
+                .args(["-hide_banner", "-i", input, "-an", "-sn", "-frames:v", &SAMPLE_FRAMES.to_string(), "-vf", "idet", "-f", "null", "-"])
+                .tracked_output(app)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(parse_idet(&String::from_utf8_lossy(&output.stderr)))
+        }
+    }
+}
+
+// ==========================================
+// RESOLVE: WHICH FIELD FILTER (IF ANY) TO USE
+// ==========================================
+// Sources the container marks progressive are never analyzed or filtered.
+// IVTC wins over plain deinterlacing whenever telecine is found.
+pub async fn resolve(app: &AppHandle, input: &str, media: Option<&MediaInfo>, deinterlace: bool, detect_telecine: bool) -> FieldReport {
+    if !deinterlace && !detect_telecine {
+        return FieldReport::default();
+    }
+    if media.and_then(|m| m.field_order.as_deref()) == Some("progressive") {
+        return FieldReport { detected: ScanType::Progressive, ..Default::default() };
+    }
+
+    let fallback = if deinterlace { FieldAction::Deinterlace } else { FieldAction::None };
+    if !detect_telecine || !is_ntsc_rate(media.and_then(|m| m.fps)) {
+        return FieldReport { action: fallback, ..Default::default() };
+    }
+
+    let stats = match analyze(app, input).await {
+        Ok(stats) => stats,
+        Err(e) => {
+            println!("⚠️ Telecine detection failed: {}", e);
+            None
+        }
+    };
+    let detected = stats.as_ref().map(decide).unwrap_or_default();
+    let action = match detected {
+        ScanType::Progressive => FieldAction::None,
+        ScanType::Telecine => FieldAction::InverseTelecine,
+        ScanType::Interlaced | ScanType::Unknown => fallback,
+    };
+    println!("🎞️ Field analysis: {:?} -> {:?}", detected, action);
+    FieldReport { detected, action, stats }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TELECINED: &str = "\
+[Parsed_idet_0 @ 0x5581] Repeated Fields: Neither:   812 Top:    94 Bottom:    94
+[Parsed_idet_0 @ 0x5581] Single frame detection: TFF:   380 BFF:     0 Progressive:   572 Undetermined:    48
+[Parsed_idet_0 @ 0x5581] Multi frame detection: TFF:   401 BFF:     0 Progressive:   590 Undetermined:     9
+";
+
+    fn multi(tff: u64, bff: u64, progressive: u64) -> IdetStats {
+        IdetStats { tff, bff, progressive, ..Default::default() }
+    }
+
+    #[test]
+    fn the_multi_frame_summary_is_the_one_read() {
+        let stats = parse_idet(TELECINED).unwrap();
+        assert_eq!(
+            stats,
+            IdetStats { tff: 401, bff: 0, progressive: 590, undetermined: 9, repeated_neither: 812, repeated_top: 94, repeated_bottom: 94 }
+        );
+        assert_eq!(parse_idet("frame= 1000 fps=500 time=00:00:33.36\n"), None);
+        // A mangled count is 0, not a lost summary
+        assert_eq!(parse_idet("Multi frame detection: TFF: x BFF: 3 Progressive: 7"), Some(IdetStats { bff: 3, progressive: 7, ..Default::default() }));
+    }
+
+    #[test]
+    fn a_comma_locale_summary_reads_the_same() {
+        // idet prints counts only; the decimals around them don't get in the way
+        let german = TELECINED.replace("Undetermined:     9", "Undetermined:     9 (99,1%)");
+        assert_eq!(parse_idet(&german), parse_idet(TELECINED));
+        assert_eq!(parse_idet("frame= 1000 fps=500,0 time=00:00:33,36\n"), None);
+    }
+
+    #[test]
+    fn the_ratios_tell_the_scan_types_apart() {
+        assert_eq!(decide(&parse_idet(TELECINED).unwrap()), ScanType::Telecine);
+        assert_eq!(decide(&multi(950, 0, 50)), ScanType::Interlaced);
+        assert_eq!(decide(&multi(0, 10, 990)), ScanType::Progressive);
+        // Some combing without the pulldown pattern is nothing to act on
+        assert_eq!(decide(&multi(100, 0, 900)), ScanType::Unknown);
+        // Pulldown is found by its combing even when the repeat counters say nothing
+        assert_eq!(decide(&multi(400, 0, 600)), ScanType::Telecine);
+        assert_eq!(decide(&multi(10, 0, 89)), ScanType::Unknown);
+    }
+
+    #[test]
+    fn the_filter_follows_the_action() {
+        let report = |action| FieldReport { action, ..Default::default() };
+        assert_eq!(report(FieldAction::None).filter(true), None);
+        assert_eq!(report(FieldAction::Deinterlace).filter(false), Some(DEINTERLACE_FILTER));
+        assert_eq!(report(FieldAction::InverseTelecine).filter(false), Some(IVTC_FILTER));
+        assert_eq!(report(FieldAction::InverseTelecine).filter(true), Some(IVTC_DEINTERLACE_FILTER));
+    }
+
+    #[test]
+    fn only_ntsc_rates_can_be_telecined() {
+        assert!(is_ntsc_rate(Some(29.97)));
+        assert!(is_ntsc_rate(Some(30000.0 / 1001.0)));
+        assert!(!is_ntsc_rate(Some(25.0)));
+        assert!(!is_ntsc_rate(Some(23.976)));
+        assert!(!is_ntsc_rate(None));
+    }
+}
@@ -0,0 +1,142 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::sync::Mutex;
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::paths;
+use crate::queue;
+
+// Stderr lines kept per job, and jobs kept at all
+const MAX_LINES: usize = 2000;
+const MAX_JOBS: usize = 20;
+
+// ==========================================
+// JOB LOGS (managed state)
+// ==========================================
+// The whole story of a job's ffmpeg runs, for bug reports: the exact
+// command line of each run (as the router built it, threads included) and
+// the last MAX_LINES lines of stderr, after flood collapsing. Kept in
+// memory by job id for the last MAX_JOBS jobs, or until cleared; the log
+// file in app_log_dir/jobs (see flood.rs) is the long-term copy.
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct JobLog {
+    pub job_id: u64,
+    // One per ffmpeg / ffprobe run, in order
+    pub commands: Vec<String>,
+    pub lines: VecDeque<String>,
+    // Older lines pushed out of the buffer
+    pub dropped_lines: u64,
+}
+
+#[derive(Default)]
+pub struct JobLogs {
+    logs: Mutex<HashMap<u64, JobLog>>,
+    order: Mutex<VecDeque<u64>>,
+}
+
+impl JobLogs {
+    fn with_log(&self, job_id: u64, f: impl FnOnce(&mut JobLog)) {
+        let mut order = self.order.lock().unwrap();
+        let mut logs = self.logs.lock().unwrap();
+        let log = logs.entry(job_id).or_insert_with(|| {
+            order.push_back(job_id);
+            JobLog { job_id, ..Default::default() }
+        });
+        f(log);
+        while order.len() > MAX_JOBS {
+            if let Some(old) = order.pop_front() {
+                logs.remove(&old);
+            }
+        }
+    }
+
+    pub fn get(&self, job_id: u64) -> Option<JobLog> {
+        self.logs.lock().unwrap().get(&job_id).cloned()
+    }
+}
+
+// Pure: an argument as a POSIX shell would need it to come out the same
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=+,@%".contains(c));
+    if plain { arg.to_string() } else { format!("'{}'", arg.replace('\'', r"'\''")) }
+}
+
+// Pure: `ffmpeg -i 'my clip.mov' ...`, pasteable into a terminal.
+pub fn command_line(program: &str, args: &[String]) -> String {
+    std::iter::once(program.to_string()).chain(args.iter().map(|a| quote(a))).collect::<Vec<_>>().join(" ")
+}
+
+// A run of the job on this task is starting; outside a job it's not kept.
+pub fn record_command(app: &AppHandle, program: &str, args: &[String]) {
+    let (Some(job_id), Some(logs)) = (queue::running_job_id(), app.try_state::<JobLogs>()) else { return };
+    let line = command_line(program, args);
+    logs.with_log(job_id, |log| {
+        log.lines.push_back(format!("$ {}", line));
+        log.commands.push(line);
+    });
+}
+
+pub fn record_line(app: &AppHandle, line: &str) {
+    let (Some(job_id), Some(logs)) = (queue::running_job_id(), app.try_state::<JobLogs>()) else { return };
+    logs.with_log(job_id, |log| {
+        if log.lines.len() == MAX_LINES {
+            log.lines.pop_front();
+            log.dropped_lines += 1;
+        }
+        log.lines.push_back(line.to_string());
+    });
+}
+
+// Pure: what export_job_log writes.
+pub fn text(log: &JobLog) -> String {
+    let mut text = format!("Job {}\n\nCommands:\n", log.job_id);
+    for command in &log.commands {
+        text.push_str(&format!("  {}\n", command));
+    }
+    text.push_str("\nOutput:\n");
+    if log.dropped_lines > 0 {
+        text.push_str(&format!("[... {} earlier lines left out ...]\n", log.dropped_lines));
+    }
+    for line in &log.lines {
+        text.push_str(line);
+        text.push('\n');
+    }
+    text
+}
+
+// ==========================================
+// COMMANDS: JOB LOGS
+// ==========================================
+#[tauri::command]
+pub fn get_job_log(logs: State<'_, JobLogs>, job_id: u64) -> Result<JobLog, String> {
+    logs.get(job_id).ok_or_else(|| format!("No log kept for job {}", job_id))
+}
+
+#[tauri::command]
+pub fn export_job_log(app: AppHandle, logs: State<'_, JobLogs>, job_id: u64, path: String) -> Result<String, String> {
+    let log = logs.get(job_id).ok_or_else(|| format!("No log kept for job {}", job_id))?;
+    let path = paths::secure_output(&app, &path, false)?;
+    fs::write(&path, text(&log)).map_err(|e| format!("{}: {}", path, e))?;
+    println!("📝 Log of job {} written to {}", job_id, path);
+    Ok(path)
+}
+
+// One job's log, or all of them.
+#[tauri::command]
+pub fn clear_job_logs(logs: State<'_, JobLogs>, job_id: Option<u64>) {
+    let mut order = logs.order.lock().unwrap();
+    let mut kept = logs.logs.lock().unwrap();
+    match job_id {
+        Some(id) => {
+            kept.remove(&id);
+            order.retain(|o| *o != id);
+        }
+        None => {
+            kept.clear();
+            order.clear();
+        }
+    }
+}
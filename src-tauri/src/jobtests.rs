@@ -0,0 +1,807 @@
+// ==========================================
+// WHOLE JOBS AGAINST THE STUB FFMPEG
+// ==========================================
+// compress_video, compress_image and queued jobs from request to result, on
+// tauri's mock runtime (see `Runtime` in lib.rs) with the stub standing in
+// for ffmpeg and ffprobe (src/bin/stub-ffmpeg.rs). Each test gets its own
+// app identifier, so its stores, caches and logs are its own, and its own
+// scratch folder for inputs, outputs and the scenario; both go when it ends.
+// Other modules' tests borrow the Harness when they need a whole app.
+
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Once};
+use std::time::Duration;
+use tauri::test::{mock_builder, mock_context, noop_assets, MockRuntime};
+use tauri::{Listener, Manager};
+
+use crate::capabilities::{Capabilities, CapabilityCache};
+use crate::events::{PROGRESS_CHANNEL, STATUS_CHANNEL};
+use crate::ffmpeg::FfmpegBinary;
+use crate::errors::JobError;
+use crate::history::HistoryStore;
+use crate::procgroup;
+use crate::queue::{self, JobSpec, QueueStatus, QueuedJob};
+use crate::request::{ImageCompressRequest, VideoCompressRequest, VideoOptions, REQUEST_VERSION};
+use crate::AppHandle;
+
+// cargo builds the bin targets next to the test binaries' deps folder
+// whenever there are integration tests (tests/stub_ffmpeg.rs)
+fn stub_binary() -> PathBuf {
+    let deps = std::env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    let stub = deps.parent().unwrap().join(format!("stub-ffmpeg{}", std::env::consts::EXE_SUFFIX));
+    assert!(stub.exists(), "{} is missing; build it with `cargo build --bin stub-ffmpeg`", stub.display());
+    stub
+}
+
+// What the bundled build is assumed to have, so jobs don't detect (which
+// would take the scenario's runs)
+fn capabilities() -> Capabilities {
+    let set = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<HashSet<String>>();
+    Capabilities {
+        decodable_codecs: set(&["h264", "hevc", "aac", "mp3", "mjpeg", "png", "gif"]),
+        encoders: set(&["libx264", "libx265", "aac", "mjpeg", "png", "libwebp"]),
+        filters: set(&["scale", "drawtext", "zscale", "tonemap"]),
+        fps_mode: true,
+    }
+}
+
+// One `kind` and its `data`, as the frontend gets them
+#[derive(Clone, Debug)]
+pub(crate) struct Emitted {
+    pub(crate) kind: String,
+    pub(crate) data: Value,
+}
+
+// The app's runtime (see set_async_runtime), which can only be set once
+fn runtime() {
+    static SET: Once = Once::new();
+    SET.call_once(crate::set_async_runtime);
+}
+
+pub(crate) struct Harness {
+    pub(crate) app: tauri::App<MockRuntime>,
+    pub(crate) dir: PathBuf,
+    events: Arc<Mutex<Vec<Emitted>>>,
+}
+
+impl Harness {
+    pub(crate) fn new(name: &str, scenario: &str) -> Harness {
+        runtime();
+        let dir = std::env::temp_dir().join(format!("compressio-job-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("scenario.json"), scenario).unwrap();
+
+        let mut context = mock_context(noop_assets());
+        context.config_mut().identifier = format!("io.compressio.jobtest.{}.{}", std::process::id(), name);
+        let app = mock_builder().plugin(tauri_plugin_shell::init()).plugin(tauri_plugin_notification::init()).plugin(tauri_plugin_clipboard_manager::init()).build(context).unwrap();
+        let handle = app.handle();
+        // Taken first, so manage_state's own FfmpegBinary is the one ignored
+        handle.manage(FfmpegBinary::stubbed(stub_binary(), dir.join("scenario.json")));
+        crate::manage_state(handle);
+        handle.state::<CapabilityCache>().seed(capabilities());
+
+        let events = Arc::new(Mutex::new(vec![]));
+        for channel in [PROGRESS_CHANNEL, STATUS_CHANNEL] {
+            let events = events.clone();
+            handle.listen_any(channel, move |event| {
+                let envelope: Value = serde_json::from_str(event.payload()).unwrap();
+                let kind = envelope["kind"].as_str().unwrap_or_default().to_string();
+                events.lock().unwrap().push(Emitted { kind, data: envelope["data"].clone() });
+            });
+        }
+        Harness { app, dir, events }
+    }
+
+    pub(crate) fn handle(&self) -> &AppHandle {
+        self.app.handle()
+    }
+
+    pub(crate) fn file(&self, name: &str) -> String {
+        self.dir.join(name).to_string_lossy().to_string()
+    }
+
+    pub(crate) fn input(&self, name: &str, bytes: usize) -> String {
+        let path = self.file(name);
+        fs::write(&path, vec![1u8; bytes]).unwrap();
+        path
+    }
+
+    // What's in the scratch folder besides the scenario and its side files
+    pub(crate) fn files(&self) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(&self.dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|n| !n.starts_with("scenario.json"))
+            .collect();
+        names.sort();
+        names
+    }
+
+    // Arguments of every ffmpeg and ffprobe run, in order
+    pub(crate) fn runs(&self) -> Vec<Vec<String>> {
+        let log = fs::read_to_string(self.dir.join("scenario.json.log")).unwrap_or_default();
+        log.lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+    }
+
+    pub(crate) fn emitted(&self, kind: &str) -> Vec<Value> {
+        self.events.lock().unwrap().iter().filter(|e| e.kind == kind).map(|e| e.data.clone()).collect()
+    }
+
+    // Waits up to 20 seconds for `check` to hold
+    pub(crate) fn wait_for(&self, what: &str, check: impl Fn(&Harness) -> bool) {
+        for _ in 0..400 {
+            if check(self) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        panic!("timed out waiting for {}", what);
+    }
+
+    // The queue's record of job `id`, once it has stopped running
+    pub(crate) fn settled(&self, id: u64) -> QueuedJob {
+        let settled = |h: &Harness| queue::find_job(h.handle(), id).is_some_and(|j| !matches!(j.status, QueueStatus::Queued | QueueStatus::WaitingForDisk | QueueStatus::Running));
+        self.wait_for("the queued job to finish", settled);
+        queue::find_job(self.handle(), id).unwrap()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let path = self.app.path();
+        let dirs = [path.app_data_dir(), path.app_cache_dir(), path.app_config_dir(), path.app_local_data_dir(), path.app_log_dir()];
+        for dir in dirs.into_iter().flatten() {
+            let _ = fs::remove_dir_all(dir);
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// ffprobe's answer for a 10 s 1280x720 h264 + aac clip
+pub(crate) const CLIP: &str = r#"{
+    "streams": [
+        { "index": 0, "codec_type": "video", "codec_name": "h264", "width": 1280, "height": 720, "pix_fmt": "yuv420p", "r_frame_rate": "30/1", "avg_frame_rate": "30/1" },
+        { "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "48000" }
+    ],
+    "format": { "duration": "10.000000", "bit_rate": "8000000", "format_name": "mov,mp4,m4a,3gp,3g2,mj2" }
+}"#;
+
+pub(crate) fn clip_scenario(runs: &str) -> String {
+    format!(r#"{{ "ffprobe": {}, "runs": {} }}"#, CLIP, runs)
+}
+
+pub(crate) const ENCODE: &str = r#"[{
+    "stderr": [
+        { "line": "frame=150 fps=30 q=28.0 size=512kB time=00:00:05.00 bitrate=838.9kbits/s speed=1.0x" },
+        { "line": "frame=300 fps=30 q=28.0 size=1024kB time=00:00:10.00 bitrate=838.9kbits/s speed=1.0x", "after_ms": 20 }
+    ],
+    "output_bytes": 4096
+}]"#;
+
+// To the end, as a task on the app's runtime like a command's
+pub(crate) fn run<T: Send + 'static>(job: impl Future<Output = T> + Send + 'static) -> T {
+    tauri::async_runtime::block_on(tauri::async_runtime::spawn(job)).unwrap()
+}
+
+pub(crate) fn run_video(h: &Harness, request: VideoCompressRequest) -> Result<crate::VideoJobResult, JobError> {
+    let app = h.handle().clone();
+    run(async move { crate::run_direct_video(&app, request).await })
+}
+
+pub(crate) fn image_request(input: String, output: String) -> ImageCompressRequest {
+    ImageCompressRequest {
+        version: REQUEST_VERSION,
+        input,
+        output,
+        create_dirs: false,
+        width: None,
+        height: None,
+        quality: None,
+        skip_if_larger: false,
+        metadata: Default::default(),
+        process: Default::default(),
+        dry_run: false,
+        annotations: Default::default(),
+    }
+}
+
+pub(crate) fn video(h: &Harness, output: &str) -> VideoCompressRequest {
+    VideoCompressRequest::new(h.input("clip.mp4", 100_000), h.file(output), VideoOptions::default())
+}
+
+#[test]
+fn a_video_job_writes_its_output_and_reports_progress() {
+    let h = Harness::new("video", &clip_scenario(ENCODE));
+    let result = run_video(&h, video(&h, "small.mp4")).unwrap();
+
+    assert_eq!(result.output, h.file("small.mp4"));
+    assert_eq!(fs::metadata(&result.output).unwrap().len(), 4096);
+    // No staged or side files left behind
+    assert_eq!(h.files(), ["clip.mp4", "small.mp4"]);
+    let encodes: Vec<Vec<String>> = h.runs().into_iter().filter(|r| r.iter().any(|a| a == "libx264")).collect();
+    assert_eq!(encodes.len(), 1);
+    assert_eq!(encodes[0][encodes[0].iter().position(|a| a == "-i").unwrap() + 1], h.file("clip.mp4"));
+
+    let percents: Vec<f64> = h.emitted("compression-progress").iter().filter_map(|p| p["percent"].as_f64()).collect();
+    assert_eq!(percents.first().map(|p| p.round()), Some(50.0));
+    assert_eq!(percents.last(), Some(&100.0));
+    assert!(h.emitted("ffmpeg-progress").iter().any(|l| l.as_str().is_some_and(|l| l.contains("time=00:00:10.00"))));
+    assert_eq!(h.emitted("job-started").len(), 1);
+    let history = h.handle().state::<HistoryStore>().all();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].output, result.output);
+}
+
+#[test]
+fn a_failed_encode_leaves_nothing_behind() {
+    let h = Harness::new("video-failure", &clip_scenario(r#"[{
+        "stderr": [
+            { "line": "frame=150 fps=30 q=28.0 size=512kB time=00:00:05.00 bitrate=838.9kbits/s speed=1.0x" },
+            { "line": "[mp4 @ 0x1] Too many packets buffered for output stream 0:1." },
+            { "line": "Conversion failed!" }
+        ],
+        "exit_code": 187,
+        "output_bytes": 2048
+    }]"#));
+    let error = run_video(&h, video(&h, "small.mp4")).err().expect("the encode failed");
+
+    match &error {
+        JobError::FfmpegError { code, detail, .. } => {
+            assert_eq!(*code, Some(187));
+            assert!(detail.iter().any(|l| l.contains("Too many packets buffered")), "{:?}", detail);
+        }
+        other => panic!("expected an ffmpeg error, got {:?}", other),
+    }
+    // The staged output the stub wrote is gone and nothing took the name
+    assert_eq!(h.files(), ["clip.mp4"]);
+    let history = h.handle().state::<HistoryStore>().all();
+    assert_eq!(history.len(), 1);
+    assert!(history[0].error.is_some());
+}
+
+#[test]
+fn a_size_target_encodes_in_two_passes() {
+    let h = Harness::new("two-pass", &clip_scenario(r#"[
+        { "stderr": [{ "line": "frame=300 fps=60 q=0.0 size=N/A time=00:00:10.00 bitrate=N/A speed=2.0x" }] },
+        { "stderr": [{ "line": "frame=300 fps=30 q=28.0 size=1024kB time=00:00:10.00 bitrate=838.9kbits/s speed=1.0x" }], "output_bytes": 3000 }
+    ]"#));
+    let mut request = video(&h, "sized.mp4");
+    request.options.rate.target_size_mb = Some(1.0);
+    let result = run_video(&h, request).unwrap();
+
+    assert_eq!(fs::metadata(&result.output).unwrap().len(), 3000);
+    let passes: Vec<String> = h.runs().iter().filter_map(|r| r.iter().position(|a| a == "-pass").map(|i| r[i + 1].clone())).collect();
+    assert_eq!(passes, ["1", "2"]);
+    // Pass logs go with the job
+    assert_eq!(h.files(), ["clip.mp4", "sized.mp4"]);
+}
+
+#[test]
+fn a_cancel_between_the_passes_never_starts_pass_two() {
+    let h = Harness::new("two-pass-cancel", &clip_scenario(r#"[
+        { "stderr": [{ "line": "frame=150 fps=60 q=0.0 size=N/A time=00:00:05.00 bitrate=N/A speed=2.0x" }] },
+        { "stderr": [{ "line": "frame=300 fps=30 q=28.0 size=1024kB time=00:00:10.00 bitrate=838.9kbits/s speed=1.0x" }], "output_bytes": 3000 }
+    ]"#));
+    // Pass 1's closing report (no speed, half the bar) comes after its
+    // ffmpeg exited and before pass 2 is spawned
+    let app = h.handle().clone();
+    h.handle().listen_any(PROGRESS_CHANNEL, move |event| {
+        let envelope: Value = serde_json::from_str(event.payload()).unwrap();
+        let data = &envelope["data"];
+        if envelope["kind"] == "compression-progress" && data["speed"].is_null() && data["percent"].as_f64() == Some(50.0) {
+            queue::cancel_running(&app);
+        }
+    });
+    let mut request = video(&h, "sized.mp4");
+    request.options.rate.target_size_mb = Some(1.0);
+    let id = queue::enqueue(h.handle(), vec![JobSpec::Video(Box::new(request))], None).unwrap()[0];
+
+    assert_eq!(h.settled(id).status, QueueStatus::Cancelled);
+    let passes: Vec<String> = h.runs().iter().filter_map(|r| r.iter().position(|a| a == "-pass").map(|i| r[i + 1].clone())).collect();
+    assert_eq!(passes, ["1"]);
+    // Not even spawned and killed at once: the job log lists every ffmpeg started
+    let commands = h.handle().state::<crate::joblog::JobLogs>().get(id).unwrap().commands;
+    assert_eq!(commands.iter().filter(|c| c.contains("-pass")).count(), 1, "{:?}", commands);
+    // Neither an output nor the pass logs are left
+    assert_eq!(h.files(), ["clip.mp4"]);
+}
+
+#[test]
+fn nothing_is_spawned_once_cancelled() {
+    let h = Harness::new("spawn-cancelled", "{}");
+    let token = tokio_util::sync::CancellationToken::new();
+    token.cancel();
+    let app = h.handle().clone();
+    let refused = run(crate::cancel::scope(token, async move {
+        [crate::ffmpeg::command(&app).err(), crate::ffmpeg::ffprobe_command(&app).err()]
+    }));
+    assert_eq!(refused, [Some(crate::cancel::CANCELLED.to_string()), Some(crate::cancel::CANCELLED.to_string())]);
+    assert!(h.runs().is_empty());
+}
+
+#[test]
+fn a_cancelled_encode_stops_ffmpeg_and_cleans_up() {
+    let h = Harness::new("video-cancel", &clip_scenario(r#"[{
+        "stderr": [{ "line": "frame=150 fps=30 q=28.0 size=512kB time=00:00:05.00 bitrate=838.9kbits/s speed=1.0x" }],
+        "hang": true,
+        "output_bytes": 10
+    }]"#));
+    let (app, request) = (h.handle().clone(), video(&h, "small.mp4"));
+    let job = tauri::async_runtime::spawn(async move { crate::run_direct_video(&app, request).await });
+    h.wait_for("the first progress line", |h| !h.emitted("compression-progress").is_empty());
+    queue::cancel_running(h.handle());
+    let error = tauri::async_runtime::block_on(job).unwrap().err().expect("the job was cancelled");
+
+    assert!(matches!(error, JobError::Cancelled { .. }), "{:?}", error);
+    assert_eq!(h.files(), ["clip.mp4"]);
+    // Already killed and unregistered
+    assert_eq!(procgroup::kill_all(h.handle()), 0);
+}
+
+#[test]
+fn an_image_job_writes_its_output() {
+    let h = Harness::new("image", r#"{
+        "ffprobe": { "streams": [{ "index": 0, "codec_type": "video", "codec_name": "png", "width": 64, "height": 48 }], "format": { "format_name": "png_pipe" } },
+        "runs": [{ "stderr": [{ "line": "frame=1 fps=0.0 q=2.0 Lsize=1kB time=00:00:00.04 bitrate=N/A speed=1x" }], "output_bytes": 512 }]
+    }"#);
+    let input = h.file("photo.png");
+    image::RgbImage::from_pixel(64, 48, image::Rgb([200, 100, 50])).save(&input).unwrap();
+    let request = ImageCompressRequest { quality: Some(80), width: Some(32), ..image_request(input, h.file("photo.jpg")) };
+    let app = h.handle().clone();
+    let result = run(async move { crate::run_direct_image(&app, request).await }).unwrap();
+
+    assert_eq!(result.output, h.file("photo.jpg"));
+    assert_eq!(fs::metadata(&result.output).unwrap().len(), 512);
+    assert_eq!(h.files(), ["photo.jpg", "photo.png"]);
+    let encode = h.runs().into_iter().find(|r| r.last().is_some_and(|a| a.ends_with(".jpg") || a.contains("photo"))).unwrap();
+    assert!(encode.windows(2).any(|w| w[0] == "-vf" && w[1].contains("scale=32")), "{:?}", encode);
+    let started = h.emitted("job-started");
+    assert_eq!(started.len(), 1);
+    assert_eq!(started[0]["input"], h.file("photo.png"));
+}
+
+#[test]
+fn queued_jobs_run_in_turn_and_record_how_they_went() {
+    let h = Harness::new("queue", &clip_scenario(r#"[
+        { "stderr": [{ "line": "frame=300 fps=30 q=28.0 size=1024kB time=00:00:10.00 bitrate=838.9kbits/s speed=1.0x" }], "output_bytes": 4096 },
+        { "stderr": [{ "line": "Conversion failed!" }], "exit_code": 1 }
+    ]"#));
+    let first = video(&h, "first.mp4");
+    let second = VideoCompressRequest::new(h.input("other.mp4", 50_000), h.file("second.mp4"), VideoOptions::default());
+    queue::set_queue_limits(h.handle().clone(), h.handle().state(), Some(1), None, None, None);
+    let ids = queue::enqueue(h.handle(), vec![JobSpec::Video(Box::new(first)), JobSpec::Video(Box::new(second))], None).unwrap();
+
+    let (done, failed) = (h.settled(ids[0]), h.settled(ids[1]));
+    assert_eq!(done.status, QueueStatus::Done);
+    assert_eq!(failed.status, QueueStatus::Failed);
+    assert!(failed.error.as_deref().is_some_and(|e| e.contains("Conversion failed!")), "{:?}", failed.error);
+    assert_eq!(h.files(), ["clip.mp4", "first.mp4", "other.mp4"]);
+    assert_eq!(fs::metadata(h.file("first.mp4")).unwrap().len(), 4096);
+
+    // One after the other: the second started once the first had finished
+    let started: Vec<u64> = h.emitted("job-started").iter().filter_map(|e| e["job_id"].as_u64()).collect();
+    assert_eq!(started, ids);
+    let progress: Vec<u64> = h.emitted("job-progress").iter().filter_map(|e| e["job_id"].as_u64()).collect();
+    assert!(progress.contains(&ids[0]));
+    let snapshots = h.emitted("queue-changed");
+    assert!(!snapshots.is_empty());
+}
+
+#[test]
+fn a_queued_job_records_the_size_of_the_output_it_committed() {
+    let h = Harness::new("queue-committed", &clip_scenario(ENCODE));
+    fs::write(h.file("out.mp4"), b"already here").unwrap();
+    let ids = queue::enqueue(h.handle(), vec![JobSpec::Video(Box::new(video(&h, "out.mp4")))], None).unwrap();
+
+    let job = h.settled(ids[0]);
+    assert_eq!(job.status, QueueStatus::Done, "{:?}", job.error);
+    // Queue jobs move aside from an existing file, so the size is out (1).mp4's
+    assert_eq!(job.output_file, Some(PathBuf::from(h.file("out (1).mp4"))));
+    let space = job.space.expect("the scratch folder's volume has a reservation");
+    assert_eq!(space.written_bytes, Some(4096));
+    assert_eq!(fs::read(h.file("out.mp4")).unwrap(), b"already here");
+}
+
+// ==========================================
+// SKIP IF LARGER
+// ==========================================
+// The 100 kB clip comes out at 200 kB.
+
+fn larger_scenario() -> String {
+    clip_scenario(&ENCODE.replace("4096", "200000"))
+}
+
+fn skipping(h: &Harness) -> VideoCompressRequest {
+    let mut request = video(h, "out.mp4");
+    request.options.skip_if_larger = true;
+    request
+}
+
+#[test]
+fn a_larger_output_is_deleted_and_the_input_left_as_it_is() {
+    let h = Harness::new("skip-larger", &larger_scenario());
+    let result = run_video(&h, skipping(&h)).unwrap();
+
+    assert!(result.stats.skipped);
+    assert_eq!((result.stats.input_bytes, result.stats.output_bytes), (100_000, 200_000));
+    assert!(result.warnings.iter().any(|w| w.contains("skip_if_larger")), "{:?}", result.warnings);
+    // No output and no staged file; the original is untouched
+    assert_eq!(h.files(), ["clip.mp4"]);
+    assert_eq!(fs::read(h.file("clip.mp4")).unwrap(), vec![1u8; 100_000]);
+}
+
+#[test]
+fn a_skipped_queue_job_says_so_on_its_timeline() {
+    let h = Harness::new("skip-larger-queue", &larger_scenario());
+    let id = queue::enqueue(h.handle(), vec![JobSpec::Video(Box::new(skipping(&h)))], None).unwrap()[0];
+
+    let job = h.settled(id);
+    assert_eq!(job.status, QueueStatus::Done, "{:?}", job.error);
+    let skipped: Vec<Value> = h.emitted("job-timeline").into_iter().filter(|t| t["entry"]["code"] == "job.skipped_larger").collect();
+    assert_eq!(skipped.len(), 1, "{:?}", h.emitted("job-timeline"));
+    assert_eq!(skipped[0]["job_id"], id);
+    assert_eq!(skipped[0]["entry"]["params"]["input_bytes"], "100000");
+    assert_eq!(skipped[0]["entry"]["params"]["output_bytes"], "200000");
+    assert_eq!(h.files(), ["clip.mp4"]);
+}
+
+#[test]
+fn without_skip_if_larger_the_larger_output_is_kept() {
+    let h = Harness::new("skip-larger-off", &larger_scenario());
+    let result = run_video(&h, video(&h, "out.mp4")).unwrap();
+
+    assert!(!result.stats.skipped);
+    assert_eq!(fs::metadata(h.file("out.mp4")).unwrap().len(), 200_000);
+    assert_eq!(h.files(), ["clip.mp4", "out.mp4"]);
+}
+
+// ==========================================
+// SHORT INPUTS
+// ==========================================
+// Shorter than an estimate's samples (5 s), a resumable job's segments
+// (60 s) and the two-pass / detection passes' minimum (2 s), and one that
+// is a single frame.
+
+const SHORT_CLIP: &str = r#"{
+    "streams": [
+        { "index": 0, "codec_type": "video", "codec_name": "h264", "width": 640, "height": 360, "pix_fmt": "yuv420p", "r_frame_rate": "30/1", "avg_frame_rate": "30/1", "nb_frames": "9" },
+        { "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "48000" }
+    ],
+    "format": { "duration": "0.300000", "bit_rate": "2000000", "format_name": "mov,mp4,m4a,3gp,3g2,mj2" }
+}"#;
+
+const ONE_FRAME: &str = r#"{
+    "streams": [
+        { "index": 0, "codec_type": "video", "codec_name": "h264", "width": 640, "height": 360, "pix_fmt": "yuv420p", "r_frame_rate": "25/1", "avg_frame_rate": "25/1", "nb_frames": "1" }
+    ],
+    "format": { "duration": "0.040000", "bit_rate": "500000", "format_name": "mov,mp4,m4a,3gp,3g2,mj2" }
+}"#;
+
+const SHORT_ENCODE: &str = r#"[{
+    "stderr": [
+        { "line": "frame=4 fps=0.0 q=28.0 size=8kB time=00:00:00.13 bitrate=500.0kbits/s speed=1.0x" },
+        { "line": "frame=9 fps=0.0 q=28.0 size=16kB time=00:00:00.30 bitrate=436.9kbits/s speed=1.0x" }
+    ],
+    "output_bytes": 2048
+}]"#;
+
+fn short_scenario(probe: &str) -> String {
+    format!(r#"{{ "ffprobe": {}, "runs": {} }}"#, probe, SHORT_ENCODE)
+}
+
+fn encodes(h: &Harness) -> Vec<Vec<String>> {
+    h.runs().into_iter().filter(|r| r.iter().any(|a| a == "libx264")).collect()
+}
+
+#[test]
+fn a_short_clip_gets_one_capped_pass_and_no_detection_passes() {
+    let h = Harness::new("short-clip", &short_scenario(SHORT_CLIP));
+    let mut request = video(&h, "short.mp4");
+    request.options.rate.target_size_mb = Some(1.0);
+    request.options.detect_av_offset = true;
+    request.options.detect_telecine = true;
+    let result = run_video(&h, request).unwrap();
+
+    // Every ffmpeg run is the encode: no second pass, no idet or onset scan
+    let encodes = encodes(&h);
+    assert_eq!(encodes.len(), 1, "{:?}", h.runs());
+    assert!(!encodes[0].iter().any(|a| a == "-pass"));
+    assert!(encodes[0].windows(2).any(|w| w[0] == "-stats_period" && w[1] == "0.1"));
+    assert!(!h.runs().iter().any(|r| r.iter().any(|a| a.contains("idet") || a.contains("silencedetect"))));
+    assert!(result.warnings.iter().any(|w| w.contains("single capped pass")), "{:?}", result.warnings);
+    assert!(!result.duration_mismatch);
+    assert_eq!(fs::metadata(&result.output).unwrap().len(), 2048);
+    let percents: Vec<f64> = h.emitted("compression-progress").iter().filter_map(|p| p["percent"].as_f64()).collect();
+    assert_eq!(percents.last(), Some(&100.0));
+}
+
+#[test]
+fn a_resumable_clip_shorter_than_a_segment_is_one_segment() {
+    let h = Harness::new("short-resumable", &short_scenario(SHORT_CLIP));
+    let mut request = video(&h, "short.mp4");
+    request.options.resumable = true;
+    let result = run_video(&h, request).unwrap();
+
+    assert_eq!(result.output, h.file("short.mp4"));
+    assert_eq!(h.files(), ["clip.mp4", "short.mp4"]);
+    let segmented: Vec<Vec<String>> = encodes(&h).into_iter().filter(|r| r.iter().any(|a| a == "segment")).collect();
+    assert_eq!(segmented.len(), 1, "{:?}", h.runs());
+}
+
+#[test]
+fn a_clip_shorter_than_the_samples_is_estimated_whole() {
+    let h = Harness::new("short-estimate", &short_scenario(SHORT_CLIP));
+    let request = video(&h, "short.mp4");
+    let app = h.handle().clone();
+    let estimate = run(async move { crate::estimate::estimate_output_size(app, request).await }).unwrap();
+
+    assert!(estimate.exact);
+    assert_eq!(estimate.samples.len(), 1);
+    assert_eq!(estimate.samples[0].start_secs, 0.0);
+    assert!((estimate.samples[0].secs - 0.3).abs() < 1e-9);
+    assert_eq!(estimate.estimated_bytes, 2048);
+    assert_eq!(encodes(&h).len(), 1);
+    // Nothing is written where the job would write
+    assert_eq!(h.files(), ["clip.mp4"]);
+}
+
+#[test]
+fn a_short_clip_becomes_a_gif() {
+    let h = Harness::new("short-gif", &short_scenario(SHORT_CLIP));
+    let result = run_video(&h, video(&h, "short.gif")).unwrap();
+
+    assert_eq!(result.output, h.file("short.gif"));
+    assert_eq!(fs::metadata(&result.output).unwrap().len(), 2048);
+    assert!(!result.duration_mismatch);
+}
+
+#[test]
+fn a_single_frame_is_an_image_when_asked_and_a_warning_otherwise() {
+    let h = Harness::new("one-frame", &short_scenario(ONE_FRAME));
+    let result = run_video(&h, video(&h, "still.mp4")).unwrap();
+    assert!(result.warnings.iter().any(|w| w.contains("single_frame_as_image")), "{:?}", result.warnings);
+
+    let mut request = VideoCompressRequest::new(h.file("clip.mp4"), h.file("frame.mp4"), VideoOptions::default());
+    request.options.single_frame_as_image = true;
+    let result = run_video(&h, request).unwrap();
+    assert_eq!(result.output, h.file("frame.png"));
+    assert!(result.warnings.iter().any(|w| w.contains("saved as an image")), "{:?}", result.warnings);
+    assert_eq!(h.files(), ["clip.mp4", "frame.png", "still.mp4"]);
+}
+
+#[test]
+fn a_short_clips_thumbnail_is_its_first_frame() {
+    let h = Harness::new("short-thumb", &short_scenario(SHORT_CLIP));
+    let input = h.input("clip.mp4", 10_000);
+    let app = h.handle().clone();
+    run(async move { crate::thumbs::get_thumbnail(app.clone(), app.state(), input, 160, None).await }).unwrap();
+
+    let grab = h.runs().into_iter().find(|r| r.iter().any(|a| a == "-frames:v")).unwrap();
+    assert_eq!(&grab[..2], ["-ss", "0.000"]);
+}
+
+// ==========================================
+// ROTATED SOURCES
+// ==========================================
+// A phone clip stored 1920x1080 with a display matrix that turns it upright.
+// ffmpeg rotates it before the filters, so caps apply to the shown frame.
+
+const PHONE_CLIP: &str = r#"{
+    "streams": [
+        { "index": 0, "codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080, "pix_fmt": "yuv420p", "r_frame_rate": "30/1", "avg_frame_rate": "30/1",
+          "side_data_list": [{ "side_data_type": "Display Matrix", "rotation": -90 }] },
+        { "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "48000" }
+    ],
+    "format": { "duration": "10.000000", "bit_rate": "8000000", "format_name": "mov,mp4,m4a,3gp,3g2,mj2" }
+}"#;
+
+// The video filter graph of an encode
+fn vf(args: &[String]) -> &str {
+    args.iter().position(|a| a == "-filter:v:0").map(|i| args[i + 1].as_str()).unwrap_or_default()
+}
+
+#[test]
+fn a_rotated_portrait_clip_keeps_its_orientation_under_a_long_edge_cap() {
+    let h = Harness::new("portrait", &format!(r#"{{ "ffprobe": {}, "runs": {} }}"#, PHONE_CLIP, ENCODE));
+    let mut request = video(&h, "upright.mp4");
+    request.options.max_long_edge = Some(1280);
+    let result = run_video(&h, request).unwrap();
+
+    // The cap lands on the height, not on the stored frame's wider side
+    let encode = &encodes(&h)[0];
+    assert_eq!(vf(encode), "scale=-2:'min(ih,1280)'");
+    assert!(!encode.iter().any(|a| a == "-noautorotate" || a.contains("transpose")));
+    let cap = result.explanations.iter().find(|e| e.code == crate::explain::RESOLUTION_LONG_EDGE).unwrap();
+    let params: Vec<(&str, &str)> = cap.params.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    assert_eq!(params, [("cap", "1280"), ("height", "1920"), ("orientation", "portrait"), ("rotation", "90"), ("side", "height"), ("width", "1080")]);
+}
+
+#[test]
+fn the_same_frame_unrotated_is_capped_on_its_width() {
+    let h = Harness::new("landscape", &format!(r#"{{ "ffprobe": {}, "runs": {} }}"#, PHONE_CLIP.replace("-90", "0"), ENCODE));
+    let mut request = video(&h, "wide.mp4");
+    request.options.max_long_edge = Some(1280);
+    let result = run_video(&h, request).unwrap();
+
+    assert_eq!(vf(&encodes(&h)[0]), "scale='min(iw,1280)':-2");
+    let cap = result.explanations.iter().find(|e| e.code == crate::explain::RESOLUTION_LONG_EDGE).unwrap();
+    assert_eq!((cap.params["orientation"].as_str(), cap.params["side"].as_str()), ("landscape", "width"));
+    assert!(!cap.params.contains_key("rotation"));
+}
+
+// ==========================================
+// MISMATCHED STREAM LENGTHS
+// ==========================================
+// 10 s of video and 15 s of audio (what lavfi's testsrc=d=10 and sine=d=15
+// make), and the other way round, through each duration_policy. The stub's
+// probe answers the same for the output, so it always reads back at the
+// container's 15 s: right for "longest", a tail the policy should have cut
+// for the others.
+
+fn lengths(video_secs: u32, audio_secs: u32) -> String {
+    format!(
+        r#"{{
+    "streams": [
+        {{ "index": 0, "codec_type": "video", "codec_name": "h264", "width": 320, "height": 240, "pix_fmt": "yuv420p", "r_frame_rate": "25/1", "avg_frame_rate": "25/1", "duration": "{}.000000" }},
+        {{ "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 1, "sample_rate": "44100", "duration": "{}.000000" }}
+    ],
+    "format": {{ "duration": "{}.000000", "bit_rate": "400000", "format_name": "mov,mp4,m4a,3gp,3g2,mj2" }}
+}}"#,
+        video_secs,
+        audio_secs,
+        video_secs.max(audio_secs)
+    )
+}
+
+// The encode of `probe` under `policy`, its progress reaching `secs`, with
+// the audio copied or re-encoded
+fn even_out(name: &str, probe: &str, policy: &str, reencode_audio: bool, secs: u32) -> (Harness, crate::VideoJobResult) {
+    let encode = format!(r#"[{{ "stderr": [{{ "line": "frame={} fps=25 q=28.0 size=512kB time=00:00:{:02}.00 bitrate=400.0kbits/s speed=1.0x" }}], "output_bytes": 4096 }}]"#, secs * 25, secs);
+    let h = Harness::new(name, &format!(r#"{{ "ffprobe": {}, "runs": {} }}"#, probe, encode));
+    let mut request = video(&h, "out.mp4");
+    request.options.duration_policy = serde_json::from_value(serde_json::json!(policy)).unwrap();
+    if reencode_audio {
+        request.options.audio = serde_json::from_value(serde_json::json!({ "transcode": { "codec": "aac", "bitrate_kbps": 96 } })).unwrap();
+    }
+    let result = run_video(&h, request).unwrap();
+    (h, result)
+}
+
+fn has(args: &[String], run: &[&str]) -> bool {
+    args.windows(run.len()).any(|w| w == run)
+}
+
+fn policy_warning(video: u32, audio: u32, policy: &str, output: u32) -> String {
+    format!("The source's video is {}.0s long and its audio {}.0s; duration_policy \"{}\" made the output {}.0s", video, audio, policy, output)
+}
+
+#[test]
+fn the_video_policy_trims_the_audio_to_the_video() {
+    let (h, result) = even_out("lengths-video", &lengths(10, 15), "video", true, 10);
+    let encode = &encodes(&h)[0];
+    assert!(has(encode, &["-af", "atrim=end=10.000"]));
+    assert!(!encode.iter().any(|a| a == "-shortest"));
+    assert_eq!(vf(encode), "");
+
+    let why = result.explanations.iter().find(|e| e.code == crate::explain::TIMING_STREAMS_MISMATCHED).unwrap();
+    assert_eq!(why.params["policy"], "video");
+    // Verified against the policy's 10 s, so the untrimmed 15 s fails it
+    assert_eq!(result.warnings, [policy_warning(10, 15, "video", 10), "The output is 15.0s long, but 10.0s was expected".to_string()]);
+    assert!(!result.verified);
+}
+
+#[test]
+fn copied_audio_is_cut_by_the_muxer() {
+    for policy in ["video", "shortest"] {
+        let (h, result) = even_out(&format!("lengths-copied-{}", policy), &lengths(10, 15), policy, false, 10);
+        let encode = &encodes(&h)[0];
+        assert!(has(encode, &["-c:a", "copy", "-shortest"]), "{}", policy);
+        assert!(!encode.iter().any(|a| a == "-af"));
+        assert_eq!(result.warnings[0], policy_warning(10, 15, policy, 10));
+    }
+}
+
+#[test]
+fn the_shortest_policy_ends_with_the_shorter_stream() {
+    let (h, result) = even_out("lengths-shortest", &lengths(10, 15), "shortest", true, 10);
+    let encode = &encodes(&h)[0];
+    assert!(has(encode, &["-shortest"]));
+    assert!(!encode.iter().any(|a| a == "-af"));
+    assert_eq!(vf(encode), "");
+    assert!(result.warnings.contains(&"The output is 15.0s long, but 10.0s was expected".to_string()));
+}
+
+#[test]
+fn the_longest_policy_holds_the_last_frame_for_longer_audio() {
+    let (h, result) = even_out("lengths-longest", &lengths(10, 15), "longest", true, 15);
+    let encode = &encodes(&h)[0];
+    assert_eq!(vf(encode), "tpad=stop_mode=clone:stop_duration=5.000");
+    assert!(!encode.iter().any(|a| a == "-shortest" || a == "-af"));
+    // 15 s was expected, and progress ran to it
+    assert_eq!(result.warnings, [policy_warning(10, 15, "longest", 15)]);
+    assert!(result.verified);
+    assert!(!result.duration_mismatch);
+}
+
+#[test]
+fn the_longest_policy_pads_shorter_audio_with_silence() {
+    let (h, result) = even_out("lengths-longest-video", &lengths(15, 10), "longest", true, 15);
+    let encode = &encodes(&h)[0];
+    assert!(has(encode, &["-af", "apad"]));
+    assert!(has(encode, &["-shortest"]));
+    assert_eq!(vf(encode), "");
+    assert_eq!(result.warnings, [policy_warning(15, 10, "longest", 15)]);
+    assert!(result.verified);
+
+    // Under the video policy audio that ends first just ends
+    let (h, result) = even_out("lengths-video-longer", &lengths(15, 10), "video", true, 15);
+    let encode = &encodes(&h)[0];
+    assert!(!encode.iter().any(|a| a == "-shortest" || a == "-af"));
+    assert_eq!(result.warnings, [policy_warning(15, 10, "video", 15)]);
+    assert!(result.verified);
+}
+
+// ==========================================
+// EVERY COMMAND SECURES ITS PATHS
+// ==========================================
+// Refused before anything is probed or encoded
+#[test]
+fn commands_refuse_outputs_in_missing_folders_and_missing_inputs() {
+    let h = Harness::new("secure-paths", &clip_scenario(ENCODE));
+    let (app, clip, other) = (h.handle().clone(), h.input("clip.mp4", 1000), h.input("other.mp4", 1000));
+    let (nowhere, gone) = (h.file("missing/out.mp4"), h.file("gone.mp4"));
+    let refused = |result: Result<(), String>, why: &str| {
+        let error = result.expect_err("refused");
+        assert!(error.contains(why), "{}", error);
+    };
+
+    refused(run({
+        let (app, clip, other, nowhere) = (app.clone(), clip.clone(), other.clone(), nowhere.clone());
+        async move { crate::concat::concat_videos(app, vec![clip, other], nowhere, None, None).await.map(drop) }
+    }), "doesn't exist");
+    refused(run({
+        let (app, gone, other, out) = (app.clone(), gone.clone(), other.clone(), h.file("joined.mp4"));
+        async move { crate::concat::concat_videos(app, vec![gone, other], out, None, None).await.map(drop) }
+    }), "not found");
+    refused(run({
+        let (app, clip, nowhere) = (app.clone(), clip.clone(), nowhere.clone());
+        let outputs = vec![
+            serde_json::from_value(serde_json::json!({ "output": nowhere })).unwrap(),
+            serde_json::from_value(serde_json::json!({ "output": "relative.mp4" })).unwrap(),
+        ];
+        async move { crate::multi::compress_multi(app, clip, outputs).await.map(drop) }
+    }), "doesn't exist");
+    refused(run({
+        let (app, clip, nowhere) = (app.clone(), clip.clone(), nowhere.clone());
+        async move { crate::metadata::edit_metadata(app, clip, nowhere, Default::default(), vec![], false).await.map(drop) }
+    }), "doesn't exist");
+    refused(run({
+        let (app, gone) = (app.clone(), gone.clone());
+        async move { crate::metadata::edit_metadata(app, gone.clone(), gone, Default::default(), vec![], true).await.map(drop) }
+    }), "not found");
+    refused(run({
+        let (app, gone, clip, dir) = (app.clone(), gone.clone(), clip.clone(), h.file("frames"));
+        async move { crate::compare::extract_matching_frames(app.clone(), app.state(), gone, clip, 1.0, dir).await.map(drop) }
+    }), "not found");
+    refused(
+        crate::report::export_batch_report(app.clone(), app.state(), crate::report::JobSelection::Ids(vec![]), nowhere.replace(".mp4", ".csv"), crate::report::ReportFormat::Csv, None).map(drop),
+        "doesn't exist",
+    );
+    refused(run({
+        let (app, zip) = (app.clone(), h.file("missing/outputs.zip"));
+        async move { crate::package::package_outputs(app.clone(), app.state(), app.state(), crate::report::JobSelection::Ids(vec![1]), zip, None, false).await.map(drop) }
+    }), "doesn't exist");
+    let quick = run({
+        let (app, gone) = (app.clone(), gone.clone());
+        async move { crate::quickshare::quick_compress(app, gone).await.map(drop) }
+    });
+    assert!(matches!(quick, Err(JobError::Other { ref message }) if message.contains("not found")), "{:?}", quick.err());
+
+    assert!(h.runs().is_empty(), "{:?}", h.runs());
+    assert!(!Path::new(&h.file("missing")).exists() && !Path::new(&h.file("frames")).exists());
+}
@@ -0,0 +1,475 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use tauri::{Manager, State};
+use tokio_util::sync::CancellationToken;
+
+use crate::AppHandle;
+use crate::avsync;
+use crate::cancel;
+use crate::capabilities;
+use crate::events::{self, Event};
+use crate::ffmpeg::{self, TrackedOutput};
+use crate::inputs;
+use crate::paths;
+use crate::probe;
+use crate::progress::{self, ProgressTracker};
+use crate::store;
+
+const DEFAULT_SAMPLE_SECS: f64 = 10.0;
+const MAX_LADDER_STEPS: usize = 8;
+// Where in the file candidate sample windows start, as fractions of the duration
+const CANDIDATE_POSITIONS: [f64; 4] = [0.2, 0.4, 0.6, 0.8];
+// A cut this early in the chosen window becomes the sample start, so the
+// sample doesn't open on the tail of the previous shot
+const SNAP_FRACTION: f64 = 0.3;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LadderMetric {
+    Vmaf,
+    Ssim,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LadderSample {
+    pub crf: u8,
+    pub path: String,
+    pub thumbnail: Option<String>,
+    pub bytes: u64,
+    // Sample bitrate scaled to the whole input
+    pub estimated_full_bytes: Option<u64>,
+    // VMAF (0-100) or SSIM (0-1), depending on `LadderBundle::metric`
+    pub score: Option<f64>,
+    // Reused from an earlier run instead of encoded again
+    pub cached: bool,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LadderBundle {
+    pub input: String,
+    pub sample_start_secs: f64,
+    pub sample_secs: f64,
+    pub metric: Option<LadderMetric>,
+    pub samples: Vec<LadderSample>,
+    pub warnings: Vec<String>,
+}
+
+// Payload of `ladder-progress`: one event per step of each phase.
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct LadderProgress {
+    // "selecting" | "encoding" | "scoring" | "thumbnails"
+    phase: &'static str,
+    step: usize,
+    steps: usize,
+    crf: Option<u8>,
+}
+
+// Written next to the samples so a re-run skips the selection pass.
+#[derive(Serialize, Deserialize)]
+struct CachedWindow {
+    start_secs: f64,
+    sample_secs: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedScore {
+    metric: LadderMetric,
+    score: f64,
+}
+
+// ==========================================
+// LADDER STATE (managed state)
+// ==========================================
+// One ladder at a time, running under its own cancellation token.
+#[derive(Default)]
+pub struct QualityLadder {
+    running: AtomicBool,
+    token: Mutex<CancellationToken>,
+}
+
+struct RunningGuard<'a>(&'a QualityLadder);
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.0.running.store(false, Ordering::SeqCst);
+    }
+}
+
+// ==========================================
+// SAMPLE WINDOW SELECTION
+// ==========================================
+// `candidates` are (window start, scene cut times relative to it). The window
+// with the median number of cuts wins: not the static intro, not the busiest
+// action scene. The start then snaps to an early cut if there is one.
+pub fn pick_window(candidates: &[(f64, Vec<f64>)], sample_secs: f64, duration: f64) -> f64 {
+    let mut ranked: Vec<&(f64, Vec<f64>)> = candidates.iter().collect();
+    ranked.sort_by_key(|(_, cuts)| cuts.len());
+    let Some((start, cuts)) = ranked.get(ranked.len() / 2) else { return 0.0 };
+    let snapped = cuts
+        .iter()
+        .find(|t| **t > 0.0 && **t <= sample_secs * SNAP_FRACTION)
+        .map(|t| start + t)
+        .filter(|s| s + sample_secs <= duration);
+    snapped.unwrap_or(*start)
+}
+
+async fn scene_cuts(app: &AppHandle, input: &str, start: f64, secs: f64) -> Result<Vec<f64>, String> {
+    let output = ffmpeg::command(app)?
+        .args([
+            "-hide_banner",
+            "-ss", &format!("{:.3}", start),
+            "-t", &format!("{:.3}", secs),
+            "-i", input,
+            "-an", "-sn",
+            "-vf", "scdet=threshold=10,metadata=print:key=lavfi.scd.time",
+            "-f", "null", "-",
+        ])
+        .tracked_output(app)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(avsync::parse_scene_times(&String::from_utf8_lossy(&output.stderr)))
+}
+
+// ==========================================
+// QUALITY SCORES
+// ==========================================
+// ssim: "... SSIM Y:0.991 (20.5) U:0.995 (23.1) V:0.994 (22.6) All:0.992 (21.2)"
+pub fn parse_ssim(stderr: &str) -> Option<f64> {
+    stderr
+        .lines()
+        .rev()
+        .find(|l| l.contains("SSIM ") && l.contains("All:"))
+        .and_then(|l| l.split("All:").nth(1))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(progress::parse_number)
+}
+
+// libvmaf: "[Parsed_libvmaf_4 @ 0x..] VMAF score: 93.427361"
+pub fn parse_vmaf(stderr: &str) -> Option<f64> {
+    stderr
+        .lines()
+        .rev()
+        .find_map(|l| l.split("VMAF score:").nth(1))
+        .and_then(progress::parse_number)
+}
+
+async fn score(app: &AppHandle, sample: &Path, input: &str, start: f64, secs: f64, metric: LadderMetric) -> Result<f64, String> {
+    let compare = match metric {
+        LadderMetric::Vmaf => "libvmaf",
+        LadderMetric::Ssim => "ssim",
+    };
+    let graph = format!("[0:v]setpts=PTS-STARTPTS[d];[1:v]setpts=PTS-STARTPTS[r];[d][r]{}", compare);
+    let output = ffmpeg::command(app)?
+        .args([
+            "-hide_banner",
+            "-i", &sample.to_string_lossy(),
+            "-ss", &format!("{:.3}", start),
+            "-t", &format!("{:.3}", secs),
+            "-i", input,
+            "-lavfi", &graph,
+            "-f", "null", "-",
+        ])
+        .tracked_output(app)
+        .await
+        .map_err(|e| e.to_string())?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match metric {
+        LadderMetric::Vmaf => parse_vmaf(&stderr),
+        LadderMetric::Ssim => parse_ssim(&stderr),
+    }
+    .ok_or_else(|| format!("Could not read the {} score", compare))
+}
+
+// ==========================================
+// CACHE
+// ==========================================
+fn ladder_root(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app.path().app_cache_dir().map_err(|e| e.to_string())?.join("ladder"))
+}
+
+// Same input file (path, size, mtime) and sample length -> same directory,
+// so re-running a ladder with overlapping CRF values reuses what's there.
+fn cache_dir(app: &AppHandle, input: &str, sample_secs: f64) -> Result<PathBuf, String> {
+    let meta = fs::metadata(input).map_err(|e| e.to_string())?;
+    let mtime = meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()).unwrap_or(0);
+    let resolved = paths::resolve(Path::new(input)).unwrap_or_else(|| PathBuf::from(input));
+    let mut hasher = Sha256::new();
+    hasher.update(resolved.to_string_lossy().as_bytes());
+    hasher.update(format!("|{}|{}|{:.3}", meta.len(), mtime, sample_secs).as_bytes());
+    let key = format!("{:x}", hasher.finalize());
+    Ok(ladder_root(app)?.join(&key[..16]))
+}
+
+// Cache entries are only worth their backup: a lost one is recomputed.
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Option<T> {
+    store::read_json(path).value()
+}
+
+fn write_json<T: Serialize + for<'de> Deserialize<'de>>(path: &Path, value: &T) {
+    if let Err(e) = store::save_json(path, value) {
+        println!("⚠️ Could not cache {}: {}", path.display(), e);
+    }
+}
+
+fn emit(app: &AppHandle, phase: &'static str, step: usize, steps: usize, crf: Option<u8>) {
+    events::emit(app, Event::LadderProgress(LadderProgress { phase, step, steps, crf }));
+}
+
+// ==========================================
+// COMMAND: RUN QUALITY LADDER
+// ==========================================
+// Encodes one representative sample at each CRF (x264, same audio as a
+// normal job) and hands back everything a picker needs. Sample encodes also
+// report on `ladder-sample-progress` like normal jobs do.
+#[tauri::command]
+pub async fn run_quality_ladder(
+    app: AppHandle,
+    ladder: State<'_, QualityLadder>,
+    input: String,
+    crf_values: Vec<u8>,
+    sample_secs: Option<f64>,
+    metric: Option<LadderMetric>,
+) -> Result<LadderBundle, String> {
+    let mut crfs = crf_values;
+    crfs.sort_unstable();
+    crfs.dedup();
+    if crfs.is_empty() || crfs.len() > MAX_LADDER_STEPS {
+        return Err(format!("Pick between 1 and {} CRF values", MAX_LADDER_STEPS));
+    }
+    if let Some(bad) = crfs.iter().find(|c| **c > 51) {
+        return Err(format!("CRF {} is out of range (0-51)", bad));
+    }
+    inputs::preflight(&input).map_err(|e| e.to_string())?;
+
+    if ladder.running.swap(true, Ordering::SeqCst) {
+        return Err("A quality ladder is already running".to_string());
+    }
+    let _running = RunningGuard(&ladder);
+    let token = CancellationToken::new();
+    *ladder.token.lock().unwrap() = token.clone();
+    cancel::settle(&token, cancel::scope(token.clone(), ladder_steps(&app, input, crfs, sample_secs, metric)).await)
+}
+
+async fn ladder_steps(
+    app: &AppHandle,
+    input: String,
+    crfs: Vec<u8>,
+    sample_secs: Option<f64>,
+    metric: Option<LadderMetric>,
+) -> Result<LadderBundle, String> {
+    let media = probe::probe(app, &input).await?;
+    if !media.has_video {
+        return Err("The input has no video stream".to_string());
+    }
+    let duration = media.duration.ok_or("The input's duration couldn't be read")?;
+    let sample_secs = sample_secs.unwrap_or(DEFAULT_SAMPLE_SECS).clamp(2.0, 60.0).min(duration);
+    let mut warnings = vec![];
+
+    let metric = match metric {
+        Some(LadderMetric::Vmaf) => match capabilities::get(app).await {
+            Ok(caps) if caps.has_filter("libvmaf") => Some(LadderMetric::Vmaf),
+            _ => {
+                warnings.push("This ffmpeg build has no libvmaf; scored with SSIM instead".to_string());
+                Some(LadderMetric::Ssim)
+            }
+        },
+        other => other,
+    };
+
+    let dir = cache_dir(app, &input, sample_secs)?;
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+
+    // --- Phase 1: pick the sample window ---
+    let window_file = dir.join("window.json");
+    let start = match read_json::<CachedWindow>(&window_file) {
+        Some(w) if (w.sample_secs - sample_secs).abs() < 0.001 => w.start_secs,
+        _ if duration <= sample_secs * 2.0 => 0.0,
+        _ => {
+            let mut candidates = vec![];
+            for (i, fraction) in CANDIDATE_POSITIONS.iter().enumerate() {
+                cancel::check()?;
+                emit(app, "selecting", i + 1, CANDIDATE_POSITIONS.len(), None);
+                let at = (duration * fraction).min(duration - sample_secs);
+                candidates.push((at, scene_cuts(app, &input, at, sample_secs).await?));
+            }
+            let start = pick_window(&candidates, sample_secs, duration);
+            write_json(&window_file, &CachedWindow { start_secs: start, sample_secs });
+            start
+        }
+    };
+    println!("🪜 Quality ladder sample: {:.1}s from {:.1}s", sample_secs, start);
+
+    // --- Phase 2: encode each step ---
+    let mut samples = vec![];
+    for (i, crf) in crfs.iter().enumerate() {
+        let path = dir.join(format!("crf_{}.mp4", crf));
+        let cached = path.exists();
+        if !cached {
+            emit(app, "encoding", i + 1, crfs.len(), Some(*crf));
+            // Written under a temp name, so a cancelled encode is never reused
+            let partial = cancel::TempFile::new(dir.join(format!("crf_{}.part.mp4", crf)));
+            let args: Vec<String> = vec![
+                "-ss".to_string(), format!("{:.3}", start),
+                "-t".to_string(), format!("{:.3}", sample_secs),
+                "-i".to_string(), input.clone(),
+                "-c:v".to_string(), "libx264".to_string(),
+                "-crf".to_string(), crf.to_string(),
+                "-preset".to_string(), "medium".to_string(),
+                "-c:a".to_string(), "aac".to_string(),
+                "-sn".to_string(),
+                "-y".to_string(), partial.path().to_string_lossy().to_string(),
+            ];
+            ffmpeg::run_with_progress(app, args, ProgressTracker::for_duration(Some(sample_secs)), Event::LadderSampleProgress).await?;
+            fs::rename(partial.path(), &path).map_err(|e| e.to_string())?;
+        }
+        let bytes = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        samples.push(LadderSample {
+            crf: *crf,
+            path: path.to_string_lossy().to_string(),
+            thumbnail: None,
+            bytes,
+            estimated_full_bytes: Some((bytes as f64 / sample_secs * duration).round() as u64),
+            score: None,
+            cached,
+        });
+    }
+
+    // --- Phase 3: scores ---
+    if let Some(metric) = metric {
+        let steps = samples.len();
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let score_file = dir.join(format!("crf_{}.score.json", sample.crf));
+            if let Some(cached) = read_json::<CachedScore>(&score_file).filter(|s| s.metric == metric) {
+                sample.score = Some(cached.score);
+                continue;
+            }
+            cancel::check()?;
+            emit(app, "scoring", i + 1, steps, Some(sample.crf));
+            match score(app, Path::new(&sample.path), &input, start, sample_secs, metric).await {
+                Ok(value) => {
+                    sample.score = Some(value);
+                    write_json(&score_file, &CachedScore { metric, score: value });
+                }
+                Err(e) => warnings.push(format!("CRF {}: {}", sample.crf, e)),
+            }
+        }
+    }
+
+    // --- Phase 4: thumbnails (middle of each sample) ---
+    let steps = samples.len();
+    for (i, sample) in samples.iter_mut().enumerate() {
+        let thumb = dir.join(format!("crf_{}.jpg", sample.crf));
+        if !thumb.exists() {
+            cancel::check()?;
+            emit(app, "thumbnails", i + 1, steps, Some(sample.crf));
+            let result = ffmpeg::run_quiet(app, vec![
+                "-ss".to_string(), format!("{:.3}", sample_secs / 2.0),
+                "-i".to_string(), sample.path.clone(),
+                "-frames:v".to_string(), "1".to_string(),
+                "-vf".to_string(), "scale=480:-2".to_string(),
+                "-y".to_string(), thumb.to_string_lossy().to_string(),
+            ])
+            .await;
+            if let Err(e) = result {
+                warnings.push(format!("CRF {}: thumbnail failed: {}", sample.crf, e));
+                continue;
+            }
+        }
+        sample.thumbnail = Some(thumb.to_string_lossy().to_string());
+    }
+
+    Ok(LadderBundle { input, sample_start_secs: start, sample_secs, metric, samples, warnings })
+}
+
+#[tauri::command]
+pub fn cancel_quality_ladder(ladder: State<'_, QualityLadder>) {
+    ladder.token.lock().unwrap().cancel();
+}
+
+// Returns the bytes freed.
+#[tauri::command]
+pub fn clear_ladder_samples(app: AppHandle) -> Result<u64, String> {
+    clear_samples(&app)
+}
+
+pub fn clear_samples(app: &AppHandle) -> Result<u64, String> {
+    if app.state::<QualityLadder>().running.load(Ordering::SeqCst) {
+        return Err("Can't clear samples while a quality ladder is running".to_string());
+    }
+    let root = ladder_root(app)?;
+    let freed = dir_size(&root);
+    if root.exists() {
+        fs::remove_dir_all(&root).map_err(|e| e.to_string())?;
+    }
+    Ok(freed)
+}
+
+pub fn samples_bytes(app: &AppHandle) -> u64 {
+    ladder_root(app).map_or(0, |root| dir_size(&root))
+}
+
+pub(crate) fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else { return 0 };
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| match e.metadata() {
+            Ok(m) if m.is_dir() => dir_size(&e.path()),
+            Ok(m) => m.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::TempDir;
+
+    #[test]
+    fn the_window_with_the_median_cut_count_wins() {
+        let candidates = vec![(0.0, vec![]), (100.0, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]), (50.0, vec![8.0, 9.0])];
+        assert_eq!(pick_window(&candidates, 10.0, 600.0), 50.0);
+        // A cut early in the window moves the start onto it
+        let candidates = vec![(0.0, vec![]), (50.0, vec![2.0, 9.0]), (100.0, vec![1.0, 2.0, 3.0])];
+        assert_eq!(pick_window(&candidates, 10.0, 600.0), 52.0);
+        // ...unless the sample would then run past the end
+        assert_eq!(pick_window(&candidates, 10.0, 61.0), 50.0);
+        assert_eq!(pick_window(&[], 10.0, 600.0), 0.0);
+    }
+
+    #[test]
+    fn scores_come_from_the_last_summary_line() {
+        let ssim = "[Parsed_ssim_2 @ 0x55] SSIM Y:0.991 (20.5) U:0.995 (23.1) V:0.994 (22.6) All:0.992 (21.2)\n";
+        assert_eq!(parse_ssim(ssim), Some(0.992));
+        let vmaf = "frame=  240 fps=30\n[Parsed_libvmaf_4 @ 0x7f] VMAF score: 93.427361\n";
+        assert_eq!(parse_vmaf(vmaf), Some(93.427361));
+        assert_eq!(parse_ssim("frame=1 fps=1"), None);
+        assert_eq!(parse_vmaf("VMAF score: n/a"), None);
+    }
+
+    #[test]
+    fn comma_locale_scores_read_like_point_ones() {
+        let ssim = "[Parsed_ssim_2 @ 0x55] SSIM Y:0,991 (20,5) U:0,995 (23,1) V:0,994 (22,6) All:0,992 (21,2)\n";
+        assert_eq!(parse_ssim(ssim), Some(0.992));
+        assert_eq!(parse_vmaf("[Parsed_libvmaf_4 @ 0x7f] VMAF score: 93,427361\n"), Some(93.427361));
+    }
+
+    #[test]
+    fn a_damaged_score_file_falls_back_to_its_backup() {
+        let dir = TempDir::new(std::env::temp_dir().join(format!("ladder-test-{}-cache", std::process::id()))).unwrap();
+        let path = dir.path().join("crf-28.vmaf.json");
+        write_json(&path, &CachedScore { metric: LadderMetric::Vmaf, score: 91.0 });
+        write_json(&path, &CachedScore { metric: LadderMetric::Vmaf, score: 92.5 });
+        assert_eq!(read_json::<CachedScore>(&path).map(|s| s.score), Some(92.5));
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &text[..text.len() / 2]).unwrap();
+        assert_eq!(read_json::<CachedScore>(&path).map(|s| s.score), Some(91.0));
+    }
+}
@@ -1,214 +1,1804 @@
-use tauri::{AppHandle, Emitter, WindowEvent};
-use tauri_plugin_shell::ShellExt;
+use serde::{Deserialize, Serialize};
+use tauri::{Manager, RunEvent, WindowEvent};
 use tauri_plugin_shell::process::CommandEvent;
-use std::path::Path;
-use std::process::Command;
-
-// --- HELPER: Test if an encoder actually works ---
-// We keep this for Mac/AMD/Intel, but we SKIP it for NVIDIA below.
-async fn is_encoder_supported(app: &AppHandle, encoder: &str) -> bool {
-    let args = vec![
-        "-f", "lavfi", "-i", "color=s=64x64:d=0.1", 
-        "-c:v", encoder, 
-        "-f", "null", "-"
-    ];
-    let output = app.shell().sidecar("ffmpeg")
-        .expect("failed to create sidecar")
-        .args(args)
-        .output()
-        .await;
-    match output {
-        Ok(o) => o.status.success(),
-        Err(_) => false,
-    }
-}
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+mod active;
+mod archive;
+mod audio;
+mod audio_format;
+mod audiomode;
+mod automation;
+mod audiotracks;
+mod avsync;
+mod batch;
+mod cancel;
+mod capabilities;
+mod cleanup;
+mod clock;
+mod compare;
+mod concat;
+mod coverart;
+mod deferred;
+mod deterministic;
+mod directory;
+mod dryrun;
+mod duration;
+mod efficiency;
+mod encoders;
+mod estimate;
+mod events;
+mod explain;
+mod errors;
+mod extended_ffmpeg;
+mod ffmpeg;
+mod filters;
+mod filterspec;
+mod flood;
+mod gif;
+mod fingerprint;
+mod hardware;
+mod hdr;
+mod health;
+mod history;
+mod image_analysis;
+mod image_batch;
+mod image_auto;
+mod image_convert;
+mod inputs;
+mod instance;
+mod interlace;
+#[cfg(test)]
+mod jobtests;
+mod joblog;
+mod ladder;
+mod loudness;
+mod macos;
+mod maintenance;
+mod metadata;
+mod multi;
+mod native_image;
+mod nightplan;
+mod options;
+mod outputs;
+mod overlay;
+mod package;
+mod paths;
+mod pause;
+mod pip;
+mod plan;
+mod playability;
+mod presets;
+mod preview;
+mod probe;
+mod procgroup;
+mod progress;
+mod quality;
+mod queue;
+mod quickshare;
+mod rehearsal;
+mod remux;
+mod replace;
+mod report;
+mod request;
+mod resources;
+mod resume;
+mod risk;
+mod routing;
+mod salvage;
+mod schedule;
+mod scoring;
+mod selftest;
+mod settings;
+mod simple;
+mod sizefit;
+mod sourcetool;
+mod speedseries;
+mod staging;
+mod stats;
+mod store;
+mod subtitles;
+mod support;
+mod surgical;
+mod thumbs;
+mod throttle;
+mod timeline;
+mod tray;
+mod undo;
+mod upload;
+mod verify;
+mod vfr;
+mod volumes;
+mod watch;
+
+use history::HistoryEntry;
+use progress::ProgressTracker;
+
+// The runtime the app runs on. Unit tests run whole jobs on tauri's mock
+// runtime instead (see jobtests.rs), so everything names these, not Wry.
+#[cfg(not(test))]
+pub(crate) type Runtime = tauri::Wry;
+#[cfg(test)]
+pub(crate) type Runtime = tauri::test::MockRuntime;
+pub(crate) type AppHandle = tauri::AppHandle<Runtime>;
 
 // ==========================================
 // 1. COMMAND: KILL FFMPEG
 // ==========================================
+// Last resort "cancel everything": every job's token first, so they resolve
+// as cancelled, then any process this app spawned (and their children),
+// never other apps' ffmpeg. cancel_job stops a single one.
 #[tauri::command]
-fn kill_ffmpeg() {
+fn kill_ffmpeg(app: AppHandle) {
     println!("🛑 FORCE STOP: Killing all FFmpeg processes...");
-    #[cfg(target_os = "windows")]
-    { let _ = Command::new("taskkill").args(["/F", "/IM", "ffmpeg*", "/T"]).spawn(); }
-    #[cfg(not(target_os = "windows"))]
-    { let _ = Command::new("pkill").arg("-f").arg("ffmpeg").spawn(); }
+    queue::cancel_running(&app);
+    let killed = procgroup::kill_all(&app);
+    println!("🛑 Stopped {} process groups", killed);
 }
 
 // ==========================================
 // 2. COMMAND: COMPRESS VIDEO (UNIVERSAL + FORCE NVIDIA)
 // ==========================================
+// "copy" keeps the video bitstream untouched and only re-encodes audio: a big
+// win on screen recordings with efficient h264 but huge PCM audio.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoMode {
+    #[default]
+    Reencode,
+    Copy,
+}
+
+#[derive(Serialize, Clone)]
+pub struct VideoJobResult {
+    // Both canonical (see paths::secure)
+    pub input: String,
+    pub output: String,
+    // The encoder actually used (auto_gpu decides it internally)
+    pub encoder: String,
+    // Fate of every subtitle stream in the input
+    pub subtitles: Vec<subtitles::SubtitleOutcome>,
+    // ...and of every audio stream
+    pub audio_tracks: Vec<audiotracks::AudioTrackOutcome>,
+    // What became of the input's cover art, when it had any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover_art: Option<coverart::CoverOutcome>,
+    // The container's duration metadata was wrong; progress fell back to frame counts
+    pub duration_mismatch: bool,
+    // Audio shift that was applied and/or detected
+    pub av_sync: avsync::AvSyncReport,
+    // Interlacing/telecine found in the source and what was done about it
+    pub fields: interlace::FieldReport,
+    // ffmpeg warnings that predict stutter/sync problems, even on exit code 0
+    pub quality_risk: risk::QualityRisk,
+    // Tier the output was checked at and what it found
+    pub verification: verify::VerifyReport,
+    // The output was read back, had the expected length and no decode
+    // errors (false for single frames saved as images, which aren't probed)
+    pub verified: bool,
+    // Per-stream promise of a surgical job, checked against the output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub surgical: Option<Vec<surgical::LedgerEntry>>,
+    // How much of a salvage encode's timeline was recovered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub salvage: Option<salvage::SalvageReport>,
+    // Dolby Vision / HDR10+ found in the source and what became of it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hdr: Option<hdr::HdrReport>,
+    // The screen-recording tool the source came from, when it's a known one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_tool: Option<sourcetool::SourceTool>,
+    // Which size-ladder rung a `fit_size_mb` job ended on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fit: Option<sizefit::FitReport>,
+    // skip_optimized found the source already lean: nothing was encoded,
+    // so nothing is at `output`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub already_optimized: Option<efficiency::Efficiency>,
+    // dry_run: the commands the job would have run; nothing is at `output`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<dryrun::DryRunPlan>,
+    // Made with `deterministic`; `settings_hash` covers the options, the
+    // container and the ffmpeg build, for keying caches
+    pub deterministic: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settings_hash: Option<String>,
+    // Only the first `limit_duration_secs` were encoded
+    pub partial: bool,
+    pub warnings: Vec<String>,
+    // Sizes, saving and time taken; filled in by run_video_job
+    #[serde(flatten)]
+    pub stats: stats::JobStats,
+    // Why the automatic choices went the way they did (see explain.rs)
+    pub explanations: Vec<explain::Explanation>,
+    // Copy sent to the upload destination, when `upload` was on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload: Option<upload::UploadRecord>,
+    // Set for jobs started by a command rather than the queue (cancel_job takes it)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<u64>,
+}
+
+// Typed entry point, and the only one that takes new options. With
+// `preset`, the request's options are that preset's (paths, overwrite
+// policy and annotations stay), as in compress_batch.
 #[tauri::command]
-async fn compress_video(app: AppHandle, input: String, output: String, auto_gpu: bool) -> Result<(), String> {
-    let input_path = Path::new(&input);
-    if !input_path.exists() {
-        return Err("Input file not found".to_string());
+async fn compress_video_request(app: AppHandle, mut request: request::VideoCompressRequest, preset: Option<String>) -> Result<VideoJobResult, errors::JobError> {
+    if let Some(name) = &preset {
+        request.options = presets::options(&app, name).map_err(|message| errors::JobError::Other { message })?;
     }
+    run_direct_video(&app, request).await
+}
 
-    println!("🎥 Starting Compression (Universal Force Mode)...");
+// Commands run their job under an id of its own, so it can be cancelled
+// alone. Their errors go out typed (see errors.rs).
+pub(crate) async fn run_direct_video(app: &AppHandle, request: request::VideoCompressRequest) -> Result<VideoJobResult, errors::JobError> {
+    let (job_id, result) = queue::run_direct(app, run_video_job(app, request)).await;
+    result.map(|r| VideoJobResult { job_id: Some(job_id), ..r }).map_err(|e| errors::for_job(app, job_id, e))
+}
 
-    let ext = Path::new(&output)
-        .extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("")
-        .to_lowercase();
+// The original flat signature, kept for older frontends. Frozen: options
+// added since go through compress_video_request only.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn compress_video(
+    app: AppHandle,
+    input: String,
+    output: String,
+    auto_gpu: bool,
+    extract_incompatible_subs: Option<bool>,
+    resumable: Option<bool>,
+    overlay_text: Option<overlay::TextOverlay>,
+    blur_regions: Option<Vec<filters::BlurRegion>>,
+    av_offset_ms: Option<i64>,
+    detect_av_offset: Option<bool>,
+    video_mode: Option<VideoMode>,
+    deinterlace: Option<bool>,
+    detect_telecine: Option<bool>,
+) -> Result<VideoJobResult, errors::JobError> {
+    let options = request::VideoOptions {
+        auto_gpu,
+        video_mode: video_mode.unwrap_or_default(),
+        extract_incompatible_subs: extract_incompatible_subs.unwrap_or(false),
+        resumable: resumable.unwrap_or(false),
+        overlay_text,
+        blur_regions: blur_regions.unwrap_or_default(),
+        av_offset_ms,
+        detect_av_offset: detect_av_offset.unwrap_or(false),
+        deinterlace: deinterlace.unwrap_or(false),
+        detect_telecine: detect_telecine.unwrap_or(false),
+        ..Default::default()
+    };
+    run_direct_video(&app, request::VideoCompressRequest::new(input, output, options)).await
+}
 
-    let mut selected_encoder = "libx264";
-    let mut selected_audio = "aac";
-    let mut selected_preset = "medium";
-    let mut extra_args: Vec<String> = vec![];
+// ==========================================
+// CONTAINER DEFAULTS
+// ==========================================
+// What each output container gets before HDR, copy mode or an explicit
+// quality have their say. Also used by the dry run (explain::predict).
+// Presets and forced pixel formats come from the encoder's row in
+// encoders.rs.
+pub(crate) struct ContainerCodecs {
+    pub encoder: &'static str,
+    pub audio: &'static str,
+    pub extra_args: Vec<String>,
+    // The hardware encoder got it
+    pub gpu: bool,
+}
 
-    match ext.as_str() {
+// `gpu_encoder` is the working hardware encoder for `codec`, if any.
+pub(crate) fn container_codecs(ext: &str, codec: support::VideoCodec, gpu_encoder: Option<&'static str>) -> ContainerCodecs {
+    let mut codecs = ContainerCodecs { encoder: "libx264", audio: "aac", extra_args: vec![], gpu: false };
+    match ext {
         // --- VIDEO FORMATS ---
-        "mp4" | "mkv" | "mov" | "avi" | "flv" | "ts" | "m4v" | "wmv" => {
-            if auto_gpu {
-                // 🛑 LOGIC CHANGE: We check for NVIDIA *blindly* first.
-                // We assume if you are on Windows, you might have it.
-                // If this fails, it crashes (Good! The error tells us why).
-                
-                println!("💪 FORCING NVIDIA (Hybrid Mode + Pixel Fix)...");
-                selected_encoder = "h264_nvenc";
-                selected_preset = "p4";
-                
-                // PIXEL FIX (Prevents crash on 10-bit videos)
-                extra_args.push("-pix_fmt".to_string());
-                extra_args.push("yuv420p".to_string());
-
-                // Note: If you want to support Mac/AMD correctly on other computers,
-                // you would normally put checks here. But for YOUR laptop, we force NVIDIA.
+        // H.264 / HEVC / AV1, on the GPU when one works for it
+        ext if support::accepts_video(ext, codec.name()) => {
+            codecs.encoder = gpu_encoder.unwrap_or(codec.cpu_encoder());
+            codecs.gpu = gpu_encoder.is_some();
+            // webm only when it's AV1
+            if ext == "webm" {
+                codecs.audio = "libopus";
             }
         },
 
         // --- WEB FORMATS ---
         "webm" => {
-            println!("⚠️ WebM Format: Using VP9 Codec (CPU)");
-            selected_encoder = "libvpx-vp9";
-            selected_audio = "libopus";
-            extra_args.push("-b:v".to_string()); extra_args.push("0".to_string());
-            extra_args.push("-crf".to_string()); extra_args.push("30".to_string());
+            codecs.encoder = "libvpx-vp9";
+            codecs.audio = "libopus";
+            codecs.extra_args.extend(["-b:v", "0", "-crf", "30"].map(String::from));
         },
         "ogv" | "ogg" => {
-            println!("⚠️ Ogg Format: Using Theora Codec (CPU)");
-            selected_encoder = "libtheora";
-            selected_audio = "libvorbis";
-            extra_args.push("-q:v".to_string()); extra_args.push("6".to_string());
+            codecs.encoder = "libtheora";
+            codecs.audio = "libvorbis";
+            codecs.extra_args.extend(["-q:v", "6"].map(String::from));
+        },
+        // ASF only plays with its own codecs; wmv2 defaults to 200 kb/s
+        "wmv" => {
+            codecs.encoder = "wmv2";
+            codecs.audio = "wmav2";
+            codecs.extra_args.extend(["-q:v", "4"].map(String::from));
         },
+        // encode_video has its own path for these
         "gif" => {
-             println!("⚠️ GIF Detected: Using GIF Encoder");
-             let args = vec![
-                 "-i".to_string(), input.clone(),
-                 "-vf".to_string(), "fps=15,scale=480:-1:flags=lanczos".to_string(),
-                 "-y".to_string(), output.clone()
-             ];
-             let sidecar_command = app.shell().sidecar("ffmpeg").map_err(|e| e.to_string())?.args(args);
-             let (mut rx, _) = sidecar_command.spawn().map_err(|e| e.to_string())?;
-             while let Some(event) = rx.recv().await {
-                 if let CommandEvent::Terminated(payload) = event {
-                     if let Some(code) = payload.code {
-                         if code != 0 { return Err(format!("GIF Error: {}", code)); }
-                     }
-                 }
-             }
-             return Ok(());
+            codecs.encoder = "gif";
+            codecs.audio = "none";
         },
         _ => {}
     }
+    if let Some(pix_fmt) = encoders::profile(codecs.encoder).pix_fmt {
+        codecs.extra_args.extend(["-pix_fmt".to_string(), pix_fmt.to_string()]);
+    }
+    codecs
+}
 
-    println!("⚡ Encoder: {}", selected_encoder);
+pub(crate) struct RatePlan {
+    pub options: quality::QualityOptions,
+    // A short clip's size target became a one-pass cap
+    pub single_pass_for_short: bool,
+    pub two_pass: bool,
+}
 
-    let mut args = vec![];
-    // NO HARDWARE DECODE (CPU Reads -> Safe)
-    
-    args.push("-i".to_string());
-    args.push(input.clone());
-    args.push("-c:v".to_string());
-    args.push(selected_encoder.to_string());
+// An explicit quality or bitrate replaces whatever the container branch picked
+pub(crate) fn resolve_rate(rate: &quality::QualityOptions, crf: Option<u32>, resumable: bool, encoder: &str, short: bool, copy: bool) -> RatePlan {
+    let rate = quality::QualityOptions { quality: rate.quality.or(crf.map(quality::Quality::Crf)), ..rate.clone() };
+    let single_pass_for_short = short && !copy && rate.two_pass(encoder);
+    let options = if single_pass_for_short {
+        quality::QualityOptions { max_filesize_mb: rate.target_size_mb, target_size_mb: None, ..rate }
+    } else {
+        rate
+    };
+    let two_pass = !copy && !resumable && options.two_pass(encoder);
+    RatePlan { options, single_pass_for_short, two_pass }
+}
 
-    if selected_encoder != "libvpx-vp9" && selected_encoder != "libtheora" && selected_encoder != "h264_videotoolbox" {
-        args.push("-preset".to_string());
-        args.push(selected_preset.to_string());
+// Validation + encode + history record; shared by the commands and the queue.
+// Everything it runs gets the request's thread cap and priority (see resources.rs).
+pub(crate) async fn run_video_job(app: &AppHandle, request: request::VideoCompressRequest) -> Result<VideoJobResult, String> {
+    let process = if request.options.deterministic {
+        resources::ProcessOptions { threads: Some(1), ..request.options.process }
+    } else {
+        request.options.process
+    };
+    resources::scope(process, video_job(app, request)).await
+}
+
+async fn video_job(app: &AppHandle, mut request: request::VideoCompressRequest) -> Result<VideoJobResult, String> {
+    // Before validation, which would turn a .jpg output down
+    if let Some(routed) = routing::from_video(app, &request).await {
+        return routed;
+    }
+    request.validate().map_err(|e| e.to_string())?;
+    (request.input, request.output) = paths::secure(app, &request.input, &request.output, request.create_dirs)?;
+    let remux_warning = remux::resolve(app, &request.input, &request.output, &mut request.options).await?;
+    if request.options.single_frame_as_image {
+        if let Some(routed) = single_frame_to_image(app, &request).await {
+            return routed;
+        }
+    }
+    let mut request = request.with_preview_output();
+    paths::ensure_not_input(&request.input, &request.output)?;
+    // Before the output is claimed, so a skipped job leaves nothing behind
+    let advisory = match efficiency::check(app, &request).await {
+        Some(found) if request.options.skip_optimized => return Ok(already_optimized(app, &request, found)),
+        Some(found) => {
+            let advisory = efficiency::EfficiencyAdvisory { job_id: queue::running_job_id(), input: request.input.clone(), efficiency: found.clone() };
+            events::emit(app, events::Event::EfficiencyAdvisory(advisory));
+            Some(found.message)
+        }
+        None => None,
+    };
+    // Nothing claimed, written or recorded: the encode stops where ffmpeg would start
+    if request.dry_run {
+        let mut result = sizefit::encode(app, request.clone(), &request.output).await?;
+        let notes: Vec<String> = remux_warning.into_iter().chain(advisory).collect();
+        if let Some(plan) = result.dry_run.as_mut() {
+            plan.warnings.extend(notes.iter().cloned());
+        }
+        result.warnings.extend(notes);
+        return Ok(result);
     }
+    let mut reservation = outputs::claim_for_job(app, &request.output, request.overwrite_policy, request.work_dir.as_deref())?;
+    request.output = reservation.path_str();
+    reservation.throttle_writes(throttle::write_mbps(app, request.options.io_throttle_mbps, &request.output));
+    let input_bytes = file_len(Path::new(&request.input)).unwrap_or(0);
+    // A remux or copy writes about as much as it reads; a size target or a
+    // bitrate says what comes out better than history does
+    let estimated = if request.options.copy_only || request.options.video_mode == VideoMode::Copy {
+        input_bytes
+    } else {
+        let duration = match request.options.rate.target_bitrate_kbps {
+            Some(_) => probe::probe(app, &request.input).await.ok().and_then(|m| m.duration),
+            None => None,
+        };
+        let duration = request.options.limit_duration_secs.or(duration);
+        let fit = request.options.fit_size_mb.map(|mb| (mb * 1024.0 * 1024.0) as u64);
+        fit.or_else(|| request.options.rate.expected_bytes(duration)).unwrap_or_else(|| plan::estimated_output_bytes(app, "video", input_bytes))
+    };
+    reservation.check_space(estimated, request.options.force)?;
+    let input = request.input.clone();
+    let annotations = request.annotations.clone();
+    let skip_if_larger = request.options.skip_if_larger;
+    let wants_upload = request.options.upload;
+    // Where the output starts in the source, when it's to be scored
+    let score_from = request.options.compute_quality_score.then_some(request.options.start_secs);
+    let settings_hash = if request.options.deterministic {
+        Some(deterministic::settings_hash(app, &request.options, &request.output).await)
+    } else {
+        None
+    };
+    let started = Instant::now();
+    let mut discarded = None;
+    let encoded = match sizefit::encode(app, request.clone(), &reservation.staged_str()).await {
+        Err(e) if request.options.auto_gpu_decides() && e.starts_with(ffmpeg::HW_ENCODE_FAILED) && !cancel::is_cancelled() => {
+            cpu_fallback(app, request, &reservation.staged_str(), e).await
+        }
+        encoded => encoded,
+    };
+    let encoded = encoded.map(|mut r| {
+        r.warnings.extend(remux_warning);
+        r.warnings.extend(advisory);
+        r
+    });
+    let mut result = match encoded {
+        Ok(mut r) if skip_if_larger && grew(&input, &reservation.staged) => {
+            discarded = file_len(&reservation.staged);
+            skipped_larger(app, &input, discarded);
+            r.warnings.push(SKIPPED_LARGER.to_string());
+            r.explanations.push(explain::Explanation::new(explain::SKIPPED_LARGER, &[("output_bytes", discarded.unwrap_or(0).to_string())]));
+            Ok(r)
+        }
+        Ok(mut r) => {
+            let reserved = reservation.path.clone();
+            reservation.commit().map(|path| {
+                subtitles::follow_output(&mut r.subtitles, &reserved, &path);
+                VideoJobResult { output: path.to_string_lossy().to_string(), ..r }
+            })
+        }
+        Err(e) => Err(reservation.classify(e)),
+    };
+    let output = result.as_ref().map_or(reservation.path_str(), |r| r.output.clone());
+    // A discarded output is the source; nothing to score
+    let quality_score = match (score_from, result.as_mut()) {
+        (Some(start), Ok(r)) if discarded.is_none() => scoring::score_job(app, &r.output, &input, start, &mut r.warnings).await,
+        _ => None,
+    };
 
-    args.extend(extra_args);
-    args.push("-c:a".to_string()); args.push(selected_audio.to_string());
-    args.push("-y".to_string());
-    args.push(output.clone());
+    let mut entry = HistoryEntry::finished("video", &input, &output, started, result.as_ref().err().cloned()).with_annotations(&annotations);
+    if let Ok(r) = &result {
+        entry.partial = r.partial;
+        entry.encoder = Some(r.encoder.clone());
+        entry.warnings = r.warnings.clone();
+        entry.av_offset_ms = r.av_sync.applied_ms;
+        entry.source_tool = r.source_tool;
+        entry.fit = r.fit.clone();
+        entry.explanations = r.explanations.clone();
+        if let Some(job_id) = queue::current_job_id() {
+            queue::set_explanations(app, job_id, r.explanations.clone());
+        }
+    }
+    let mut stats = job_stats(&mut entry, discarded, started);
+    stats.quality_score = quality_score;
+    let recorded = history::record(app, entry);
 
-    let sidecar_command = app.shell().sidecar("ffmpeg")
-        .map_err(|e| e.to_string())?
-        .args(args);
+    // After the history entry exists, so a failed upload can be retried from it
+    let upload = match (&result, recorded) {
+        (Ok(r), Some(entry)) if wants_upload && discarded.is_none() => Some(upload::upload(app, entry.id, &r.output).await),
+        _ => None,
+    };
+    result.map(|r| VideoJobResult { stats, upload, settings_hash, ..r })
+}
 
-    let (mut rx, mut _child) = sidecar_command
-        .spawn()
-        .map_err(|e| e.to_string())?;
+// Once, with only auto_gpu turned off: quality, scaling, cuts and the rest
+// carry over, and the encoder becomes the codec's CPU one.
+async fn cpu_fallback(app: &AppHandle, mut request: request::VideoCompressRequest, staged: &str, gpu_error: String) -> Result<VideoJobResult, String> {
+    let error = gpu_error.trim_start_matches(ffmpeg::HW_ENCODE_FAILED).trim_start_matches(": ").to_string();
+    println!("🔁 Hardware encode failed ({}), running it again on the CPU", error);
+    events::emit(app, events::Event::FallbackToCpu(hardware::CpuFallback { job_id: queue::current_job_id(), input: request.input.clone(), error: error.clone() }));
+    timeline::record(app, timeline::CPU_FALLBACK, &[("error", error.clone())]);
+    if request.options.resumable {
+        resume::discard_parts(app, &request.input, staged);
+    }
+    request.options.auto_gpu = false;
+    let mut result = sizefit::encode(app, request, staged).await?;
+    result.warnings.push(format!("The hardware encoder failed partway ({}), so the job was encoded again on the CPU with {}", error, result.encoder));
+    Ok(result)
+}
 
-    let mut last_log_error = String::from("Unknown FFmpeg Error");
+const SKIPPED_LARGER: &str = "The output came out larger than the input, so it was deleted (skip_if_larger)";
 
-    while let Some(event) = rx.recv().await {
-        match event {
-            CommandEvent::Stderr(line_bytes) => {
-                let line = String::from_utf8_lossy(&line_bytes);
-                last_log_error = line.to_string(); 
-                let _ = app.emit("ffmpeg-progress", line.to_string());
-            }
-            CommandEvent::Terminated(payload) => {
-                if let Some(code) = payload.code {
-                    if code != 0 {
-                        return Err(format!("Error (Code {}): {}", code, last_log_error));
-                    }
+// skip_optimized: a success that encoded nothing. Like skip_if_larger,
+// history records it as saving nothing and the original stays as it is.
+fn already_optimized(app: &AppHandle, request: &request::VideoCompressRequest, found: efficiency::Efficiency) -> VideoJobResult {
+    let started = Instant::now();
+    let warning = format!("{}, so it wasn't re-encoded (skip_optimized)", found.message);
+    let mut entry = HistoryEntry::finished("video", &request.input, &request.output, started, None).with_annotations(&request.annotations);
+    entry.output_bytes = entry.input_bytes;
+    entry.warnings.push(warning.clone());
+    let stats = stats::JobStats::new(entry.input_bytes, entry.input_bytes, started.elapsed(), true);
+    history::record(app, entry);
+    let bpp = found.bits_per_pixel.map(|b| format!("{:.3}", b)).unwrap_or_default();
+    let explanation = explain::Explanation::new(explain::ALREADY_OPTIMIZED, &[("codec", found.codec.clone()), ("bits_per_pixel", bpp)]);
+    let result = routed_video_result(request.input.clone(), request.output.clone(), "none".to_string(), stats, warning, explanation);
+    VideoJobResult { already_optimized: Some(found), ..result }
+}
+
+// The job's `job-timeline` says the output was thrown away, and the sizes
+fn skipped_larger(app: &AppHandle, input: &str, output_bytes: Option<u64>) {
+    let sizes = [("input_bytes", file_len(Path::new(input))), ("output_bytes", output_bytes)];
+    let params: Vec<(&str, String)> = sizes.iter().map(|(k, v)| (*k, v.unwrap_or(0).to_string())).collect();
+    timeline::record(app, timeline::SKIPPED_LARGER, &params);
+}
+
+fn file_len(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).map(|m| m.len()).ok()
+}
+
+// The finished temp file is bigger than the input. With skip_if_larger it's
+// never moved into place; dropping the reservation removes it.
+fn grew(input: &str, staged: &Path) -> bool {
+    matches!((file_len(Path::new(input)), file_len(staged)), (Some(i), Some(o)) if o > i)
+}
+
+// Sizes for the result; `discarded` is the size of an output skip_if_larger
+// threw away. History records such a job as saving nothing, since the user
+// keeps the original.
+fn job_stats(entry: &mut HistoryEntry, discarded: Option<u64>, started: Instant) -> stats::JobStats {
+    let output_bytes = discarded.unwrap_or(entry.output_bytes);
+    if discarded.is_some() {
+        entry.output_bytes = entry.input_bytes;
+    }
+    stats::JobStats::new(entry.input_bytes, output_bytes, started.elapsed(), discarded.is_some())
+}
+
+// One-frame inputs go through the image pipeline instead, as a PNG next to
+// the requested output. None = not a single frame (or the probe failed), so
+// it's a normal video job.
+async fn single_frame_to_image(app: &AppHandle, request: &request::VideoCompressRequest) -> Option<Result<VideoJobResult, String>> {
+    let media = probe::probe(app, &request.input).await.ok()?;
+    if !media.is_single_frame() {
+        return None;
+    }
+    let output = Path::new(&request.output).with_extension("png").to_string_lossy().to_string();
+    println!("🖼️ {} is a single frame, writing {} instead", request.input, output);
+    timeline::record(app, timeline::SHORT_INPUT, &[("decision", "routed_to_image".to_string()), ("output", output.clone())]);
+    let warning = format!("The input is a single frame, so it was saved as an image instead of a {} video", request.output.rsplit('.').next().unwrap_or("").to_lowercase());
+    let explanation = explain::Explanation::new(explain::SINGLE_FRAME_IMAGE, &[]);
+    Some(run_image_job(app, image_request_for(request, output)).await.map(|r| VideoJobResult {
+        dry_run: r.dry_run,
+        ..routed_video_result(r.input, r.output, r.encoder, r.stats, warning, explanation)
+    }))
+}
+
+// The image job a video request turns into (see also routing.rs).
+pub(crate) fn image_request_for(request: &request::VideoCompressRequest, output: String) -> request::ImageCompressRequest {
+    request::ImageCompressRequest {
+        version: request::REQUEST_VERSION,
+        input: request.input.clone(),
+        output,
+        create_dirs: false,
+        width: None,
+        height: None,
+        quality: None,
+        skip_if_larger: request.options.skip_if_larger,
+        metadata: request.options.metadata,
+        process: request.options.process,
+        dry_run: request.dry_run,
+        annotations: request.annotations.clone(),
+    }
+}
+
+// A video job's result for work another pipeline did.
+pub(crate) fn routed_video_result(
+    input: String,
+    output: String,
+    encoder: String,
+    stats: stats::JobStats,
+    warning: String,
+    explanation: explain::Explanation,
+) -> VideoJobResult {
+    VideoJobResult {
+        input,
+        output,
+        encoder,
+        subtitles: vec![],
+        audio_tracks: vec![],
+        cover_art: None,
+        duration_mismatch: false,
+        av_sync: avsync::AvSyncReport::default(),
+        fields: interlace::FieldReport::default(),
+        quality_risk: risk::QualityRisk::default(),
+        verification: verify::VerifyReport::default(),
+        verified: false,
+        surgical: None,
+        salvage: None,
+        hdr: None,
+        source_tool: None,
+        fit: None,
+        deterministic: false,
+        settings_hash: None,
+        already_optimized: None,
+        dry_run: None,
+        partial: false,
+        warnings: vec![warning],
+        stats,
+        explanations: vec![explanation],
+        upload: None,
+        job_id: None,
+    }
+}
+
+// A dry run's result: the plan, and why it came out that way.
+fn planned_video_result(input: String, output: String, plan: dryrun::DryRunPlan, mut why: Vec<explain::Explanation>) -> VideoJobResult {
+    let explanation = explain::Explanation::new(explain::DRY_RUN, &[("commands", plan.commands.len().to_string())]);
+    why.insert(0, explanation.clone());
+    let result = routed_video_result(input, output, plan.encoder.clone(), stats::JobStats::default(), String::new(), explanation);
+    VideoJobResult { warnings: plan.warnings.clone(), explanations: why, dry_run: Some(plan), ..result }
+}
+
+// ffmpeg writes `staged`; `request.output` is where the result ends up (and
+// what side files like extracted subtitles are named after).
+pub(crate) async fn encode_video(app: &AppHandle, request: request::VideoCompressRequest, staged: &str) -> Result<VideoJobResult, String> {
+    let request::VideoCompressRequest { input, output, options, dry_run, .. } = request;
+    let mut filters = filters::VideoFilters::from_options(&options);
+    // From here on auto_gpu means auto_gpu picks the encoder
+    let (auto_gpu, preference) = (options.auto_gpu_decides(), options.preference());
+    let reencodes_audio = options.reencodes_audio();
+    let request::VideoOptions {
+        auto_gpu: _, video_mode, extract_incompatible_subs, resumable,
+        overlay_text: _, blur_regions: _, av_offset_ms, detect_av_offset, normalize_audio, audio, deinterlace: _, detect_telecine: _,
+        limit_duration_secs, start_secs, end_secs, duration_policy, copy_only, mode, allow_partial_transcode: _, io_throttle_mbps, crf: _, rate: _, max_width: _, max_height: _, max_long_edge, max_fps: _, fit_canvas: _, playback_target, compute_quality_score: _, preserve_vfr: _, surgical, preserve_dynamic_hdr, tonemap_to_sdr,
+        single_frame_as_image: _, skip_if_larger: _, skip_optimized: _, efficiency_threshold_bpp: _, upload: _, codec, web_optimized: _, encoder_preference: _, gif_fps, gif_width, metadata: _, keep_all_streams, include_sidecar_subs, salvage, force: _, filters: _, source_fixups, fit_size_mb: _, deterministic, process: _,
+    } = options.clone();
+    // A player profile's size cap holds whatever else was asked for
+    let max_long_edge = match (max_long_edge, playback_target.and_then(|t| playability::profile(t).max_long_edge)) {
+        (Some(asked), Some(cap)) => Some(asked.min(cap)),
+        (asked, cap) => asked.or(cap),
+    };
+    // Input-side `-ss` for a cut (fast seek), output-side `-t` for the cut's
+    // end and/or the preview length, placed after every other option
+    let start = start_secs.unwrap_or(0.0);
+    let cut_args: Vec<String> = start_secs
+        .map(|secs| vec!["-ss".to_string(), format!("{:.3}", secs)])
+        .unwrap_or_default();
+    let length = match (end_secs.map(|end| end - start), limit_duration_secs) {
+        (Some(cut), Some(limit)) => Some(cut.min(limit)),
+        (cut, limit) => cut.or(limit),
+    };
+    let limit_args: Vec<String> = length
+        .map(|secs| vec!["-t".to_string(), format!("{:.3}", secs)])
+        .unwrap_or_default();
+    let mut av_sync = avsync::AvSyncOptions { offset_ms: av_offset_ms, detect: detect_av_offset };
+
+    let input_path = Path::new(&input);
+    let input_warning = inputs::preflight(&input).map_err(|e| e.to_string())?;
+    paths::ensure_not_input(&input, &output)?;
+    let copy_video = video_mode == VideoMode::Copy || copy_only;
+
+    println!("🎥 Starting Compression (Universal Force Mode)...");
+
+    let ext = Path::new(&output)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    // Probe failures aren't fatal here: we just fall back to ffmpeg's default stream selection
+    let media = match probe::probe(app, &input).await {
+        Ok(info) => Some(info),
+        Err(e) => {
+            println!("⚠️ Probe failed, using default stream selection: {}", e);
+            None
+        }
+    };
+    if let Some(m) = &media {
+        let mut params = vec![];
+        params.extend(m.duration.map(|d| ("duration_secs", format!("{:.1}", d))));
+        let bytes = std::fs::metadata(&input).map(|f| f.len()).unwrap_or(0);
+        params.extend(m.duration.filter(|d| *d > 0.0).map(|d| ("bitrate_kbps", format!("{:.0}", bytes as f64 * 8.0 / d / 1000.0))));
+        params.extend(m.height.map(|h| ("height", h.to_string())));
+        if m.probe_level != probe::ProbeLevel::Standard {
+            params.push(("probe_level", m.probe_level.as_str().to_string()));
+        }
+        timeline::record(app, timeline::ANALYZED, &params);
+    }
+    if let Some(duration) = media.as_ref().and_then(|m| m.duration).filter(|d| start >= *d) {
+        return Err(format!("The cut starts at {:.1}s, but the input is only {:.1}s long", start, duration));
+    }
+    // Sub-2s clips: nothing for scene/telecine detection to find, and
+    // two-pass encoders choke on them
+    let short = media.as_ref().is_some_and(|m| m.is_short());
+    let mut why = vec![];
+    // Screen recordings from a known tool, and what to do about their quirks
+    let file_name = input_path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let source = media.as_ref().and_then(|m| sourcetool::detect(&file_name, m));
+    let source_tool = source.as_ref().map(|d| d.tool);
+    let mut planned_fixups: &[sourcetool::Fixup] = &[];
+    if let Some(detection) = &source {
+        println!("🖥️ Screen recording from {}", detection.tool.as_str());
+        let tool = detection.tool;
+        why.push(explain::source_detected(detection));
+        if !source_fixups {
+            why.push(explain::source_fixups_off(tool, "not_requested"));
+        } else if surgical {
+            why.push(explain::source_fixups_off(tool, "surgical"));
+        } else if !sourcetool::enabled(app, tool) {
+            why.push(explain::source_fixups_off(tool, "disabled"));
+        } else {
+            planned_fixups = tool.fixups();
+        }
+    }
+    if media.as_ref().is_some_and(|m| m.has_video) || max_long_edge.is_some() {
+        let orientation = media.as_ref().and_then(|m| m.orientation());
+        let side = max_long_edge.filter(|_| !copy_video).map(|cap| (filters.apply_long_edge(cap, orientation), cap));
+        why.push(explain::resolution(media.as_ref(), side));
+    }
+    let short_decision = |why: &mut Vec<explain::Explanation>, decision: &str| {
+        timeline::record(app, timeline::SHORT_INPUT, &[("decision", decision.to_string())]);
+        why.push(explain::short_input(decision));
+    };
+    if short {
+        if av_sync.detect {
+            av_sync.detect = false;
+            short_decision(&mut why, "skip_av_offset_detection");
+        }
+        if filters.detect_telecine {
+            filters.detect_telecine = false;
+            short_decision(&mut why, "skip_telecine_detection");
+        }
+        short_decision(&mut why, "wide_duration_tolerance");
+    }
+    filters.validate(media.as_ref())?;
+    if let Some(spec) = filters.custom.as_ref().filter(|_| !copy_video) {
+        filterspec::validate(app, spec, media.as_ref()).await?;
+    }
+    let draws_text = filters.overlay_text.is_some() || filters.custom.as_ref().is_some_and(filterspec::FilterSpec::draws_text);
+    // Detection still running at launch: the job goes ahead on the CPU.
+    // Dry runs take what's there, since detecting runs ffmpeg.
+    let mut caps_pending = false;
+    let mut job_caps = None;
+    if media.is_some() || draws_text || auto_gpu || codec != support::VideoCodec::H264 {
+        let found = if dry_run { Ok(capabilities::cached(app)) } else { capabilities::get_for_job(app).await };
+        match found {
+            Ok(None) => caps_pending = true,
+            Ok(Some(caps)) => {
+                job_caps = Some(caps.clone());
+                if let Some(m) = &media {
+                    capabilities::check_decoders(&caps, &m.streams, !copy_video).map_err(|e| e.to_string())?;
+                }
+                if draws_text && !caps.has_filter("drawtext") {
+                    return Err("This ffmpeg build has no drawtext filter (it needs libfreetype), so text overlays aren't available".to_string());
                 }
             }
-            _ => {}
+            Err(e) => println!("⚠️ Could not read ffmpeg capabilities, skipping pre-flight checks: {}", e),
         }
     }
+    if surgical && media.is_none() {
+        return Err("Surgical mode needs to probe the input first, and the probe failed".to_string());
+    }
+    // Surgical mode maps every stream itself (and fails rather than drop one)
+    let mut subtitle_plan = media
+        .as_ref()
+        .filter(|_| !surgical)
+        .map(|m| subtitles::plan(&m.streams, &output, &ext, extract_incompatible_subs))
+        .unwrap_or_default();
+    // ... and files next to the input after them, as inputs 1, 2, ...
+    if !surgical && !resumable {
+        subtitle_plan.extend(subtitles::sidecar_plan(&include_sidecar_subs, &input, &ext)?);
+    }
+    let sidecars = subtitles::prepare(&mut subtitle_plan, staged)?;
+    // Surgical jobs copy the cover themselves
+    let cover_art = media.as_ref().filter(|_| !surgical).and_then(|m| coverart::plan(&m.streams, &ext, resumable));
 
-    Ok(())
+    // Detected once per session, see hardware.rs. A named vendor is tested
+    // even while detection runs: it's that or fail.
+    let gpu_encoder = if copy_video || (caps_pending && !preference.is_vendor()) {
+        None
+    } else if dry_run {
+        hardware::choose_detected(app, codec, auto_gpu, preference)?
+    } else {
+        hardware::choose(app, codec, auto_gpu, preference).await?
+    };
+    // Only what picks the encoder and its arguments; build_ffmpeg_args
+    // takes it from there
+    let codecs = container_codecs(&ext, codec, gpu_encoder);
+    if codecs.gpu {
+        println!("💪 Hardware encoding with {}", codecs.encoder);
+    }
+    why.push(if copy_video { explain::Explanation::new(explain::ENCODER_COPY, &[]) } else { explain::container(&ext, codecs.encoder, codecs.audio, codecs.gpu) });
+    let hw_found = match (auto_gpu && !caps_pending, dry_run) {
+        (false, _) => None,
+        (true, true) => hardware::cached(app),
+        (true, false) => Some(hardware::get(app).await),
+    };
+    let failed = hw_found.as_ref().map(|hw| hw.failed_for(codec)).unwrap_or_default();
+    why.extend(explain::gpu_fallback(auto_gpu, codecs.gpu, caps_pending, &ext, codec, &failed));
+    let mut dry_run_warnings = vec![];
+    if dry_run && auto_gpu && !caps_pending && hw_found.is_none() && !copy_video {
+        dry_run_warnings.push("The hardware encoders haven't been tested yet this session, so the plan is for the CPU; a real run tests them first and may pick one".to_string());
+    }
+
+    if ext == "gif" {
+        println!("⚠️ GIF Detected: Using GIF Encoder");
+        let mut transforms = vec![];
+        if start_secs.is_some() || end_secs.is_some() {
+            transforms.push(duration::Transform::Cut(start, end_secs));
+        }
+        if let Some(limit) = limit_duration_secs {
+            transforms.push(duration::Transform::Limit(limit));
+        }
+        // The frame count changes with the GIF's fps, so progress goes by time
+        let expected = duration::Expected::source(media.as_ref().and_then(|m| m.duration), None, None).resolve(&transforms);
+        let picture = gif::picture_filter(gif_fps, gif_width, &filters);
+        if dry_run {
+            let (palette, encode) = gif::pass_args(&input, staged, &cut_args, &limit_args, &picture);
+            let plan = dryrun::DryRunPlan { filter_graph: Some(picture), ..dryrun::DryRunPlan::new("gif", vec![palette, encode], vec![]) };
+            return Ok(planned_video_result(input, output, plan, why));
+        }
+        let tracker = gif::encode(app, &input, staged, &cut_args, &limit_args, &picture, ProgressTracker::expecting(&expected)).await?;
+        let probed_output = verify::read_back(app, staged, &expected).await?;
+        let duration_warning = expected.check(probed_output.duration);
+        return Ok(VideoJobResult {
+            input,
+            output,
+            encoder: "gif".to_string(),
+            subtitles: vec![],
+            audio_tracks: vec![],
+            cover_art: None,
+            duration_mismatch: tracker.duration_mismatch,
+            av_sync: avsync::AvSyncReport::default(),
+            fields: interlace::FieldReport::default(),
+            quality_risk: risk::QualityRisk::default(),
+            verification: verify::VerifyReport::default(),
+            verified: duration_warning.is_none(),
+            surgical: None,
+            salvage: None,
+            hdr: None,
+            source_tool,
+            fit: None,
+            deterministic: false,
+            settings_hash: None,
+            already_optimized: None,
+            dry_run: None,
+            partial: limit_duration_secs.is_some(),
+            warnings: duration_warning.into_iter().collect(),
+            stats: stats::JobStats::default(),
+            explanations: why,
+            upload: None,
+            job_id: None,
+        });
+    }
+
+    // Dolby Vision / HDR10+ sources go through x265 as plain or dynamic HDR,
+    // never the 8-bit path above. Other HDR is tonemapped for codecs that
+    // can't carry it and kept for the ones that can.
+    let (hdr_plan, static_hdr) = match media.as_ref().filter(|m| m.has_video && !copy_video) {
+        Some(_) => {
+            let info = hdr::detect(app, &input).await?;
+            let caps = job_caps.as_deref();
+            // An explicit tonemap takes dynamic sources down to SDR as well
+            let plan = if tonemap_to_sdr { None } else { hdr::plan(&info, &ext, preserve_dynamic_hdr, caps)? };
+            let static_plan = match plan {
+                Some(_) => None,
+                None => hdr::static_plan(&info, codecs.encoder, encoders::profile(codecs.encoder).codec, tonemap_to_sdr, caps)?,
+            };
+            (plan, static_plan)
+        }
+        None => (None, None),
+    };
+    if let Some(plan) = &hdr_plan {
+        println!("🌈 Dynamic HDR source: {:?}", plan.report.action);
+        timeline::record(app, timeline::HDR_DECISION, &[("action", plan.action_name())]);
+        filters.strip_dynamic_hdr = plan.strip_side_data;
+    }
+    if let Some(plan) = &static_hdr {
+        println!("🌈 HDR source: {}", plan.action_name());
+        timeline::record(app, timeline::HDR_DECISION, &[("action", plan.action_name().to_string())]);
+        filters.tonemap = plan.tonemap;
+    }
+
+    let detected = dryrun::Detected { caps: job_caps.as_deref(), caps_pending, gpu_encoder };
+    let source_info = dryrun::Source {
+        ext: &ext,
+        media: media.as_ref(),
+        start,
+        length,
+        short,
+        hdr: hdr_plan.as_ref(),
+        static_hdr: static_hdr.as_ref(),
+        tool: source_tool,
+        fixups: planned_fixups,
+    };
+    let dryrun::VideoArgs { encoder: selected_encoder, audio: mut selected_audio, mut codec_args, ledger, rate, two_pass, single_pass_for_short, keep_timing, explanations } =
+        dryrun::build_ffmpeg_args(&options, &detected, &source_info)?;
+    if single_pass_for_short {
+        timeline::record(app, timeline::SHORT_INPUT, &[("decision", "single_pass".to_string())]);
+    }
+    why.extend(explanations);
+    println!("⚡ Encoder: {}", selected_encoder);
+
+    // Once anything is mapped explicitly, video/audio must be mapped too
+    let explicit_maps = ledger.is_none() && (cover_art.is_some() || !subtitle_plan.is_empty() || keep_all_streams);
+    if explicit_maps {
+        codec_args.extend(["-map", "0:V:0?"].map(String::from));
+        if audio != audiomode::AudioMode::Remove {
+            codec_args.extend(audiotracks::mapping_args(keep_all_streams));
+        }
+    }
+    codec_args.extend(cover_art.as_ref().map(coverart::mapping_args).unwrap_or_default());
+    codec_args.extend(subtitles::mapping_args(&subtitle_plan));
+    // Surgical jobs keep every track and report them in their ledger
+    let mut audio_tracks = media
+        .as_ref()
+        .filter(|_| ledger.is_none())
+        .map(|m| audiotracks::plan(&m.streams, keep_all_streams, explicit_maps))
+        .unwrap_or_default();
+    // Copied, re-encoded or dropped, per what the kept tracks are (see audiomode.rs)
+    if ledger.is_none() {
+        let kept: Vec<&probe::StreamInfo> = media
+            .as_ref()
+            .map(|m| m.streams.iter().filter(|s| audio_tracks.iter().any(|t| t.kept && t.index == s.index)).collect())
+            .unwrap_or_default();
+        let context = audiomode::AudioContext {
+            ext: &ext,
+            default_encoder: selected_audio,
+            tracks: &kept,
+            must_encode: reencodes_audio,
+            budget_kbps: rate.target_size_mb.or(rate.max_filesize_mb).map(|_| quality::AUDIO_BUDGET_KBPS),
+        };
+        let plan = if copy_only { audiomode::AudioPlan::copy("copy_only") } else { audiomode::plan(&audio, &context)? };
+        if plan.action == audiomode::AudioAction::Removed {
+            audio_tracks.iter_mut().for_each(|t| t.kept = false);
+        } else {
+            codec_args.extend(["-c:a".to_string(), plan.encoder.to_string()]);
+        }
+        println!("🔊 Audio: {:?} ({}, {})", plan.action, plan.encoder, plan.reason);
+        codec_args.extend(plan.args.iter().cloned());
+        why.push(explain::audio(&plan));
+        selected_audio = plan.encoder;
+    }
+
+    // An offset always has the audio re-encoded (see reencodes_audio), so
+    // it's just an audio filter
+    let has_av = media.as_ref().is_some_and(|m| m.has_video && m.has_audio) && audio != audiomode::AudioMode::Remove;
+    // Dry runs leave out the analysis passes, and say what they'd add
+    let detects_offset = has_av && av_sync.detect && av_sync.offset_ms.is_none();
+    if dry_run && detects_offset {
+        av_sync.detect = false;
+        dry_run_warnings.push("A real run first estimates the A/V offset (detect_av_offset) and may delay the audio by it".to_string());
+    }
+    let av_report = if has_av { avsync::resolve(app, &input, &av_sync).await } else { avsync::AvSyncReport::default() };
+    let mut audio_filters = vec![];
+    if let Some(filter) = av_report.applied_ms.and_then(avsync::audio_filter) {
+        if resumable {
+            return Err("A/V offset correction can't be combined with resumable encodes yet".to_string());
+        }
+        audio_filters.push(filter);
+    }
+    // Audio and video of different lengths follow duration_policy. Surgical
+    // jobs keep both as they are, and resumable parts can't be padded or
+    // trimmed one at a time.
+    let delay_secs = av_report.applied_ms.map_or(0.0, |ms| ms as f64 / 1000.0);
+    let stream_lengths = media.as_ref().filter(|_| has_av).and_then(|m| duration::StreamLengths::mismatched(m, delay_secs));
+    let evened = stream_lengths.filter(|_| ledger.is_none() && !resumable);
+    let stream_plan = evened.map(|l| l.plan(duration_policy, start, copy_video, selected_audio == "copy")).unwrap_or_default();
+    if let Some(lengths) = &stream_lengths {
+        println!("⏱️ Video is {:.1}s, audio {:.1}s", lengths.video_secs, lengths.audio_secs);
+        why.push(explain::streams_mismatched(lengths, duration_policy));
+    }
+    // After the delay, so trimming goes by where the audio ends up
+    audio_filters.extend(stream_plan.audio_filter.clone());
+    // Loudness last, measured over the part of the input the encode reads
+    // (copy_only never gets here, see VideoOptions::validate)
+    let has_audio = media.as_ref().is_some_and(|m| m.has_audio);
+    let normalizes = normalize_audio && has_audio && ledger.is_none() && selected_audio != "copy";
+    if dry_run && normalizes {
+        let sample_rate = media.as_ref().and_then(|m| m.streams.iter().find(|s| s.codec_type == "audio")).and_then(|s| s.sample_rate);
+        audio_filters.push(loudness::filter(None, sample_rate));
+        why.push(explain::deferred("loudness"));
+        dry_run_warnings.push("A real run first measures the loudness (one more ffmpeg pass) and puts the measured values in loudnorm; this is the single-pass filter".to_string());
+    }
+    let loudness = if normalizes && !dry_run {
+        let mut measure_args = media.as_ref().map(|m| m.probe_level.input_args()).unwrap_or_default();
+        measure_args.extend(cut_args.iter().cloned());
+        let total = length.or_else(|| media.as_ref().and_then(|m| m.duration).map(|d| (d - start).max(0.0)));
+        let measured = loudness::measure(app, &input, &measure_args, &limit_args, ProgressTracker::for_duration(total)).await?;
+        let sample_rate = media.as_ref().and_then(|m| m.streams.iter().find(|s| s.codec_type == "audio")).and_then(|s| s.sample_rate);
+        audio_filters.push(loudness::filter(measured.as_ref(), sample_rate));
+        why.push(explain::loudness(measured.as_ref()));
+        Some(measured)
+    } else {
+        None
+    };
+    if !audio_filters.is_empty() {
+        codec_args.push("-af".to_string());
+        codec_args.push(audio_filters.join(","));
+    }
+    if stream_plan.shortest {
+        codec_args.push("-shortest".to_string());
+    }
+
+    let analyzes_fields = filters.detect_telecine && !copy_video;
+    if dry_run && analyzes_fields {
+        dry_run_warnings.push("A real run first looks for telecine (detect_telecine) and may undo it or deinterlace".to_string());
+    }
+    let fields = if copy_video {
+        interlace::FieldReport::default()
+    } else {
+        interlace::resolve(app, &input, media.as_ref(), filters.deinterlace, filters.detect_telecine && !dry_run).await
+    };
+    why.push(if dry_run && analyzes_fields { explain::deferred("fields") } else { explain::fields(&fields, copy_video, filters.deinterlace || filters.detect_telecine) });
+    why.push(if dry_run && detects_offset { explain::deferred("av_offset") } else { explain::av_sync(&av_report, av_sync.offset_ms, av_sync.detect, has_av) });
+    why.extend(subtitle_plan.iter().map(explain::subtitle));
+
+    // `offset_secs` is where in the source the encode starts (non-zero for resumed parts)
+    let filename = input_path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let video_graph = |offset_secs: f64| match (filters.build(media.as_ref(), &fields, &filename, offset_secs), &stream_plan.video_filter) {
+        (Some(graph), Some(pad)) => Some(format!("{},{}", graph, pad)),
+        (graph, pad) => graph.or_else(|| pad.clone()),
+    };
+    if let Some(graph) = video_graph(start) {
+        println!("🧩 Video filters: {}", graph);
+        if filters.custom.is_some() {
+            why.push(explain::custom_filters(&graph));
+        }
+    }
+    let args_from = |offset_secs: f64| {
+        let mut args = codec_args.clone();
+        if let Some(graph) = video_graph(offset_secs) {
+            args.push("-filter:v:0".to_string());
+            args.push(graph);
+        }
+        args
+    };
+
+    // Progress and the final length check run against what the options
+    // make of the source, in the order ffmpeg applies them
+    let mut transforms = vec![];
+    if let Some(lengths) = &evened {
+        transforms.push(duration::Transform::Streams(lengths.video_secs, lengths.target(duration_policy)));
+    }
+    if start_secs.is_some() || end_secs.is_some() {
+        transforms.push(duration::Transform::Cut(start, end_secs));
+    }
+    if fields.action == interlace::FieldAction::InverseTelecine {
+        transforms.push(duration::Transform::FrameRate(duration::DECIMATE_FACTOR));
+    }
+    if let (Some(cap), Some(fps)) = (filters.fps_cap(media.as_ref(), &fields), media.as_ref().and_then(|m| m.fps)) {
+        let source = if fields.action == interlace::FieldAction::InverseTelecine { fps * duration::DECIMATE_FACTOR } else { fps };
+        transforms.push(duration::Transform::FrameRate(cap / source));
+    }
+    // The delay is already in the evened lengths
+    if let Some(ms) = av_report.applied_ms.filter(|_| evened.is_none()) {
+        transforms.push(duration::Transform::AudioDelay(ms as f64 / 1000.0));
+    }
+    if let Some(limit) = limit_duration_secs {
+        transforms.push(duration::Transform::Limit(limit));
+    }
+    // A VFR source's nominal rate says little about its frame count, so it's
+    // counted. Only checked for whole-file encodes: a cut's share of the
+    // frames isn't its share of the time. Dry runs never get to the check.
+    let frame_check = !dry_run && keep_timing && media.as_ref().is_some_and(|m| m.has_video) && start_secs.is_none() && end_secs.is_none() && limit_duration_secs.is_none();
+    let source_frames = if frame_check {
+        match vfr::count_frames(app, &input).await {
+            Ok(frames) => Some(frames),
+            Err(e) if e == cancel::CANCELLED => return Err(e),
+            Err(e) => {
+                println!("⚠️ {}, checking the output by its length only", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let expected = media
+        .as_ref()
+        .map_or_else(duration::Expected::default, |m| duration::Expected::source(m.duration, source_frames.or(m.frames), m.fps))
+        .resolve(&transforms);
+    let mut tracker = ProgressTracker::expecting(&expected);
+    if mode == remux::JobMode::Remux {
+        tracker = tracker.indeterminate();
+    }
+    // The measuring pass had the start of the bar
+    if loudness.is_some() {
+        tracker = tracker.with_span(loudness::MEASURE_SHARE, 100.0);
+    }
+    if salvage {
+        if resumable {
+            return Err("Salvage mode can't be combined with resumable encodes".to_string());
+        }
+        let rate = app.try_state::<settings::SettingsStore>().map_or(salvage::DEFAULT_SALVAGE_ERRORS_PER_SEC, |s| s.get().salvage_errors_per_sec);
+        tracker.stderr_warnings = risk::StderrWarnings::for_salvage(rate);
+    }
+
+    let throttle_mbps = throttle::resolve_mbps(app, io_throttle_mbps, &input, &output);
+    let input_bytes = std::fs::metadata(&input).map(|m| m.len()).unwrap_or(0);
+    let readrate = throttle_mbps.and_then(|mbps| throttle::readrate_for(mbps, input_bytes, media.as_ref().and_then(|m| m.duration)));
+    let mut input_args: Vec<String> = readrate
+        .map(|r| vec!["-readrate".to_string(), format!("{:.3}", r)])
+        .unwrap_or_default();
+    // Whatever it took to probe the input reads it for the encode too
+    let probe_level = media.as_ref().map(|m| m.probe_level).unwrap_or_default();
+    input_args.extend(probe_level.input_args());
+    input_args.extend(cut_args.iter().cloned());
+    if salvage {
+        input_args.extend(salvage::input_args());
+    }
+    if let Some(ledger) = &ledger {
+        input_args.extend(surgical::input_args(ledger));
+    }
+    // Default stats every 0.5s would be one or two lines for the whole clip
+    if short {
+        input_args.extend(["-stats_period".to_string(), "0.1".to_string()]);
+    }
+    let tracker = tracker.with_read_cap(readrate).with_size_cap(rate.max_filesize_mb);
+    // The input, then the subtitle files muxed in from next to it
+    let mut inputs = vec!["-i".to_string(), input.clone()];
+    inputs.extend(sidecars.input_args(&cut_args));
+    let mut command = input_args.clone();
+    command.extend(inputs);
+    command.extend(args_from(start));
+    command.extend(limit_args.iter().cloned());
+    // Pass 1 only writes the log; each pass is half the progress
+    let passlog = format!("{}.passlog", staged);
+    let commands = dryrun::encode_commands(command, staged, &passlog, two_pass);
+
+    let mut warnings: Vec<String> = input_warning.into_iter().collect();
+    if probe_level != probe::ProbeLevel::Standard {
+        warnings.push(format!(
+            "The input could only be read with lenient demuxer flags ({}), which the encode used too",
+            probe_level.input_args().join(" ")
+        ));
+    }
+    match &loudness {
+        Some(None) => warnings.push("The audio's loudness couldn't be measured, so it was normalized in a single pass (less even than two)".to_string()),
+        None if normalize_audio && !has_audio => warnings.push("normalize_audio was set, but the input has no audio".to_string()),
+        _ => {}
+    }
+    if single_pass_for_short {
+        warnings.push("The input is under 2 seconds, so the size target got a single capped pass instead of two".to_string());
+    }
+    if media.as_ref().is_some_and(|m| m.is_single_frame()) {
+        warnings.push("The input is a single frame; single_frame_as_image would save it as a (much smaller) PNG instead".to_string());
+    }
+    if caps_pending {
+        let cpu = if auto_gpu { " and ran on the CPU" } else { "" };
+        warnings.push(format!("ffmpeg's capabilities were still being detected, so this job skipped the pre-flight checks{}", cpu));
+    } else if auto_gpu && !copy_video && gpu_encoder.is_none() && support::accepts_video(&ext, codec.name()) {
+        warnings.push(format!("No hardware {} encoder works on this machine, so it was encoded on the CPU with {}", codec.label(), codec.cpu_encoder()));
+    }
+    if let Some(plan) = &hdr_plan {
+        warnings.extend(plan.warning.clone());
+        if gpu_encoder.is_some() {
+            warnings.push("The source has dynamic HDR metadata, so it was encoded on the CPU with libx265 instead of the GPU".to_string());
+        }
+    }
+    warnings.extend(static_hdr.and_then(|p| p.warning));
+    warnings.extend(subtitle_plan.iter().filter_map(|s| s.warning.clone()));
+    warnings.extend(cover_art.as_ref().and_then(|c| c.warning.clone()));
+    if audio != audiomode::AudioMode::Remove {
+        warnings.extend(audiotracks::warning(&audio_tracks));
+    }
+    match (&stream_lengths, &evened) {
+        (Some(lengths), Some(_)) => warnings.push(lengths.warning(duration_policy)),
+        (Some(lengths), None) => warnings.push(format!(
+            "The source's video is {:.1}s long and its audio {:.1}s; {} jobs keep both lengths as they are",
+            lengths.video_secs,
+            lengths.audio_secs,
+            if surgical { "surgical" } else { "resumable" }
+        )),
+        _ => {}
+    }
+    if dry_run {
+        warnings.extend(dry_run_warnings);
+        if resumable {
+            warnings.push("Resumable encodes run this command over 60-second parts of the input, one ffmpeg each".to_string());
+        }
+        let audio_filter = (!audio_filters.is_empty()).then(|| audio_filters.join(","));
+        let plan = dryrun::DryRunPlan { filter_graph: video_graph(start), audio_filter, ..dryrun::DryRunPlan::new(selected_encoder, commands, warnings) };
+        return Ok(planned_video_result(input, output, plan, why));
+    }
+
+    queue::JobStarted {
+        encoder: Some(selected_encoder.to_string()),
+        io_throttle_mbps: throttle_mbps,
+        readrate,
+        ..queue::JobStarted::new(&input, &output)
+    }
+    .emit(app);
+
+    // A salvage encode that dies keeps what it wrote; the report measures it
+    let fallback = tracker.clone();
+    let encoded = if resumable {
+        resume::encode_segmented(app, &input, staged, &input_args, args_from, tracker).await
+    } else if two_pass {
+        let passes = async {
+            ffmpeg::run_with_progress(app, commands[0].clone(), tracker.clone().with_span(0.0, 50.0), events::Event::CompressionProgress).await?;
+            timeline::record(app, timeline::PASS_FINISHED, &[("pass", "1".to_string())]);
+            ffmpeg::run_with_progress(app, commands[1].clone(), tracker.with_span(50.0, 100.0), events::Event::CompressionProgress).await
+        };
+        let result = passes.await;
+        for log in [format!("{}-0.log", passlog), format!("{}-0.log.mbtree", passlog)] {
+            let _ = std::fs::remove_file(log);
+        }
+        result
+    } else {
+        ffmpeg::run_with_progress(app, commands[0].clone(), tracker, events::Event::CompressionProgress).await
+    };
+    let tracker = match encoded {
+        Ok(tracker) => tracker,
+        // A hardware failure is left to the CPU fallback, which starts over
+        Err(e) if salvage && e != cancel::CANCELLED && !(auto_gpu && e.starts_with(ffmpeg::HW_ENCODE_FAILED)) => {
+            warnings.push(salvage::keep_partial(app, staged, &e).await?);
+            fallback
+        }
+        Err(e) => return Err(e),
+    };
+
+    timeline::record(app, timeline::ENCODE_FINISHED, &[("encoded_secs", format!("{:.1}", tracker.last_time()))]);
+
+    if tracker.duration_mismatch {
+        warnings.push(format!(
+            "Input reports a duration of {:.1}s but the encode covered {:.1}s",
+            tracker.total_secs().unwrap_or(0.0),
+            tracker.last_time()
+        ));
+    }
+    // A salvaged output is short by whatever couldn't be read; that's reported
+    // instead of failing it
+    let checked = if salvage { duration::Expected::default() } else { expected };
+    let probed_output = verify::read_back(app, staged, &checked).await?;
+    let duration_warning = checked.check(probed_output.duration);
+    let mut duration_ok = duration_warning.is_none();
+    warnings.extend(duration_warning);
+    if let (Some(expected_frames), true) = (expected.frames, source_frames.is_some()) {
+        let frame_warning = match vfr::count_frames(app, staged).await {
+            Ok(actual) => vfr::check_frames(expected_frames, actual),
+            Err(e) if e == cancel::CANCELLED => return Err(e),
+            Err(e) => Some(format!("The output's frames could not be counted: {}", e)),
+        };
+        duration_ok &= frame_warning.is_none();
+        warnings.extend(frame_warning);
+    }
+    let output_secs = probed_output.duration;
+    let salvage_report = salvage.then(|| salvage::report(expected.secs, output_secs, &tracker.stderr_warnings.damage));
+    if let Some(report) = &salvage_report {
+        timeline::record(app, timeline::SALVAGED, &[("recovered_pct", report.recovered_pct.map(|p| format!("{:.1}", p)).unwrap_or_default())]);
+        warnings.push(salvage::warning(report));
+    }
+    let surgical_ledger = match (ledger, &media) {
+        (Some(ledger), Some(source)) => {
+            let verified = surgical::verify(ledger, source, &probed_output)?;
+            timeline::record(app, timeline::VERIFIED, &[("streams", verified.len().to_string())]);
+            Some(verified)
+        }
+        _ => None,
+    };
+    let mut quality_risk = tracker.stderr_warnings.summary();
+    if quality_risk.level == risk::RiskLevel::High {
+        let ids: Vec<&str> = quality_risk.patterns.iter().map(|p| p.id).collect();
+        timeline::record(app, timeline::QUALITY_RISK, &[("patterns", ids.join(","))]);
+        warnings.push(format!("ffmpeg reported problems that often mean stutter or sync issues in the output: {}", ids.join(", ")));
+    }
+    let verification = match verify::run(app, staged, quality_risk.level, output_secs).await {
+        Ok(report) => report,
+        Err(e) if e == cancel::CANCELLED => return Err(e),
+        Err(e) => {
+            warnings.push(format!("Verification could not run: {}", e));
+            verify::VerifyReport::default()
+        }
+    };
+    if verification.upgraded_from.is_some() {
+        quality_risk.deep_verify = Some(risk::DeepVerify { errors: verification.errors, first_error: verification.first_error.clone() });
+    }
+    if verification.errors > 0 {
+        warnings.push(format!("The output has {} decode errors: {}", verification.errors, verification.first_error.as_deref().unwrap_or("")));
+    }
+    // Fixed up or failed here, before anything is committed
+    if let Some(target) = playback_target {
+        warnings.extend(playability::conform(app, staged, &ext, target).await?);
+    }
+    for extract in subtitles::extraction_args(&input, &subtitle_plan) {
+        if let Err(e) = ffmpeg::run_quiet(app, extract).await {
+            warnings.push(format!("Subtitle extraction failed: {}", e));
+        }
+    }
+
+    Ok(VideoJobResult {
+        input,
+        output,
+        encoder: selected_encoder.to_string(),
+        subtitles: subtitle_plan,
+        audio_tracks,
+        cover_art,
+        duration_mismatch: tracker.duration_mismatch,
+        av_sync: av_report,
+        fields,
+        quality_risk,
+        verified: duration_ok && verification.errors == 0,
+        verification,
+        surgical: surgical_ledger,
+        salvage: salvage_report,
+        hdr: hdr_plan.map(|p| p.report),
+        source_tool,
+        fit: None,
+        deterministic,
+        settings_hash: None,
+        already_optimized: None,
+        dry_run: None,
+        partial: limit_duration_secs.is_some(),
+        warnings,
+        stats: stats::JobStats::default(),
+        explanations: why,
+        upload: None,
+        job_id: None,
+    })
+}
+
+// What an image job wrote, and which backend did it.
+#[derive(Serialize, Clone)]
+pub struct ImageJobResult {
+    // Both canonical (see paths::secure)
+    pub input: String,
+    pub output: String,
+    pub backend: native_image::ImageBackend,
+    // "mjpeg", "png", "libwebp", ... ("native" for the in-process backend)
+    pub encoder: String,
+    #[serde(flatten)]
+    pub stats: stats::JobStats,
+    // See VideoJobResult::dry_run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<dryrun::DryRunPlan>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<u64>,
 }
 
 #[tauri::command]
-async fn compress_image(app: AppHandle, input: String, output: String, width: String, height: String) -> Result<(), String> {
-    let input_path = Path::new(&input);
-    if !input_path.exists() {
-        return Err("Input file not found".to_string());
+async fn compress_image_request(app: AppHandle, request: request::ImageCompressRequest) -> Result<ImageJobResult, errors::JobError> {
+    run_direct_image(&app, request).await
+}
+
+pub(crate) async fn run_direct_image(app: &AppHandle, request: request::ImageCompressRequest) -> Result<ImageJobResult, errors::JobError> {
+    let (job_id, result) = queue::run_direct(app, run_image_job(app, request)).await;
+    result.map(|r| ImageJobResult { job_id: Some(job_id), ..r }).map_err(|e| errors::for_job(app, job_id, e))
+}
+
+// Quality when compress_image isn't given one: without any, a JPEG comes
+// back at mjpeg's default and is often bigger than it went in.
+const DEFAULT_IMAGE_QUALITY: u8 = 80;
+
+// Missing sizes keep the original (one side alone keeps the aspect ratio).
+// Like compress_video, this flat signature takes nothing new: other image
+// options go through compress_image_request.
+#[tauri::command]
+async fn compress_image(
+    app: AppHandle,
+    input: String,
+    output: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    quality: Option<u8>,
+) -> Result<ImageJobResult, errors::JobError> {
+    for (name, value) in [("width", width), ("height", height)] {
+        if value == Some(0) {
+            return Err(errors::classify(&format!("The {} must be at least 1 px (leave it out to keep the original)", name), vec![]));
+        }
+    }
+    let request = request::ImageCompressRequest {
+        version: request::REQUEST_VERSION,
+        input,
+        output,
+        create_dirs: false,
+        width,
+        height,
+        quality: Some(quality.unwrap_or(DEFAULT_IMAGE_QUALITY) as u32),
+        skip_if_larger: false,
+        metadata: Default::default(),
+        process: Default::default(),
+        dry_run: false,
+        annotations: Default::default(),
+    };
+    run_direct_image(&app, request).await
+}
+
+pub(crate) async fn run_image_job(app: &AppHandle, request: request::ImageCompressRequest) -> Result<ImageJobResult, String> {
+    resources::scope(request.process, image_job(app, request)).await
+}
+
+async fn image_job(app: &AppHandle, mut request: request::ImageCompressRequest) -> Result<ImageJobResult, String> {
+    if let Some(routed) = routing::from_image(app, &request).await {
+        return routed;
+    }
+    request.validate().map_err(|e| e.to_string())?;
+    (request.input, request.output) = paths::secure(app, &request.input, &request.output, request.create_dirs)?;
+    if request.dry_run {
+        return plan_image(app, request).await;
+    }
+    let mut reservation = outputs::claim_for_job(app, &request.output, None, None)?;
+    reservation.check_space(plan::estimated_output_bytes(app, "image", file_len(Path::new(&request.input)).unwrap_or(0)), false)?;
+    request.output = reservation.path_str();
+    let input = request.input.clone();
+    let annotations = request.annotations.clone();
+    let skip_if_larger = request.skip_if_larger;
+    let ext = Path::new(&request.output).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let started = Instant::now();
+    queue::JobStarted::new(&input, &request.output).emit(app);
+    // The in-process fallback only steps in when no ffmpeg starts at all
+    let ffmpeg_missing = !cancel::is_cancelled() && capabilities::get(app).await.is_err();
+    let backend = if ffmpeg_missing && native_image::supports(&request) {
+        println!("🦀 No working ffmpeg, resizing {} in-process", input);
+        native_image::ImageBackend::Native
+    } else {
+        native_image::ImageBackend::Ffmpeg
+    };
+    let encoder = match backend {
+        native_image::ImageBackend::Native => "native".to_string(),
+        native_image::ImageBackend::Ffmpeg => image_encoder(app, &ext).await,
+    };
+    let encoded = match backend {
+        native_image::ImageBackend::Native => encode_image_native(request, reservation.staged.clone()).await,
+        native_image::ImageBackend::Ffmpeg => encode_image(app, request, &encoder, &reservation.staged_str()).await,
+    };
+    let mut discarded = None;
+    let result = match encoded {
+        Ok(()) if skip_if_larger && grew(&input, &reservation.staged) => {
+            discarded = file_len(&reservation.staged);
+            skipped_larger(app, &input, discarded);
+            Ok(reservation.path.clone())
+        }
+        Ok(()) => reservation.commit(),
+        Err(e) => Err(reservation.classify(e)),
+    };
+    let output = result.as_ref().map_or(reservation.path_str(), |p| p.to_string_lossy().to_string());
+    let mut entry = HistoryEntry::finished("image", &input, &output, started, result.as_ref().err().cloned()).with_annotations(&annotations);
+    if discarded.is_some() {
+        entry.warnings.push(SKIPPED_LARGER.to_string());
     }
-    let mut args = vec![ "-i".to_string(), input.clone() ];
-    if width != "0" && !width.is_empty() {
-        let h = if height.is_empty() || height == "0" { "-1" } else { &height };
+    let stats = job_stats(&mut entry, discarded, started);
+    history::record(app, entry);
+    result.map(|_| ImageJobResult { input, output, backend, encoder, stats, dry_run: None, job_id: None })
+}
+
+// dry_run: the same backend and encoder choice, and the command for the output itself.
+async fn plan_image(app: &AppHandle, request: request::ImageCompressRequest) -> Result<ImageJobResult, String> {
+    inputs::preflight(&request.input).map_err(|e| e.to_string())?;
+    paths::ensure_not_input(&request.input, &request.output)?;
+    let ext = Path::new(&request.output).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    // No detecting here, that runs ffmpeg: a build that can't even be found
+    // is what sends the job native
+    let caps = capabilities::cached(app);
+    let native = caps.is_none() && ffmpeg::command(app).is_err() && native_image::supports(&request);
+    let (backend, plan) = match native {
+        true => {
+            let warning = "No working ffmpeg: the image would be resized in-process, with no command to show".to_string();
+            (native_image::ImageBackend::Native, dryrun::DryRunPlan::new("native", vec![], vec![warning]))
+        }
+        false => {
+            let encoder = image_encoder_in(caps.as_deref(), &ext);
+            let plan = dryrun::DryRunPlan { filter_graph: request.scale_filter(), ..dryrun::DryRunPlan::new(&encoder, vec![image_args(&request, &encoder, &request.output)], vec![]) };
+            (native_image::ImageBackend::Ffmpeg, plan)
+        }
+    };
+    Ok(ImageJobResult {
+        input: request.input,
+        output: request.output,
+        backend,
+        encoder: plan.encoder.clone(),
+        stats: stats::JobStats::default(),
+        dry_run: Some(plan),
+        job_id: None,
+    })
+}
+
+async fn encode_image_native(request: request::ImageCompressRequest, staged: PathBuf) -> Result<(), String> {
+    inputs::preflight(&request.input).map_err(|e| e.to_string())?;
+    paths::ensure_not_input(&request.input, &request.output)?;
+    tauri::async_runtime::spawn_blocking(move || native_image::encode(&request, &staged))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// The format's usual encoder, except AVIF from libaom when the build has
+// no libsvtav1.
+pub(crate) async fn image_encoder(app: &AppHandle, ext: &str) -> String {
+    image_encoder_in(capabilities::get(app).await.ok().as_deref(), ext)
+}
+
+fn image_encoder_in(caps: Option<&capabilities::Capabilities>, ext: &str) -> String {
+    let preferred = support::image_encoder(ext).unwrap_or("ffmpeg");
+    match caps {
+        Some(caps) if ext == "avif" && !caps.has_encoder(preferred) && caps.has_encoder("libaom-av1") => "libaom-av1".to_string(),
+        _ => preferred.to_string(),
+    }
+}
+
+// Pure: the single ffmpeg run of an image job.
+fn image_args(request: &request::ImageCompressRequest, encoder: &str, staged: &str) -> Vec<String> {
+    let mut args = vec![ "-i".to_string(), request.input.clone() ];
+    if let Some(scale) = request.scale_filter() {
         args.push("-vf".to_string());
-        args.push(format!("scale={}:{}", width, h));
+        args.push(scale);
     }
+    args.extend(request.quality_args_for(encoder));
+    args.extend(metadata::image_args(request.metadata));
     args.push("-y".to_string());
-    args.push(output.clone());
+    args.push(staged.to_string());
+    args
+}
 
-    let sidecar_command = app.shell().sidecar("ffmpeg").map_err(|e| e.to_string())?.args(args);
-    let (mut rx, mut _child) = sidecar_command.spawn().map_err(|e| e.to_string())?;
+async fn encode_image(app: &AppHandle, request: request::ImageCompressRequest, encoder: &str, staged: &str) -> Result<(), String> {
+    let (input, output) = (&request.input, &request.output);
+    inputs::preflight(input).map_err(|e| e.to_string())?;
+    paths::ensure_not_input(input, output)?;
+    let mut sidecar = ffmpeg::spawn(app, image_args(&request, encoder, staged))?;
 
-    while let Some(event) = rx.recv().await {
+    let mut collapser = flood::Collapser::default();
+    while let Some(event) = sidecar.next().await? {
         if let CommandEvent::Stderr(line_bytes) = event {
-            let line = String::from_utf8_lossy(&line_bytes);
-            let _ = app.emit("ffmpeg-progress", line.to_string());
+            for line in String::from_utf8_lossy(&line_bytes).lines().flat_map(|l| collapser.push(l)) {
+                events::emit(app, events::Event::FfmpegProgress(line));
+            }
         }
     }
+    if let Some(note) = collapser.flush() {
+        events::emit(app, events::Event::FfmpegProgress(note));
+    }
     Ok(())
 }
 
+// Commands and queued jobs run as tasks on tauri's runtime. A whole video
+// job's future is deeper than tokio's default 2 MiB worker stacks allow in
+// debug builds, so the runtime is ours, with room to spare.
+const WORKER_STACK_BYTES: usize = 8 * 1024 * 1024;
+
+fn set_async_runtime() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_stack_size(WORKER_STACK_BYTES)
+        .build()
+        .expect("couldn't start the async runtime");
+    tauri::async_runtime::set(runtime.handle().clone());
+    // Lives as long as the app
+    std::mem::forget(runtime);
+}
+
+// Everything the commands and jobs find through app.state, before anything
+// starts. The job tests set up their mock app with it too.
+fn manage_state(app: &AppHandle) {
+    // Before any store is loaded: it decides how they're opened
+    app.manage(instance::InstanceGuard::acquire(app));
+    app.manage(events::Subscriptions::default());
+    app.manage(errors::FailureDetails::default());
+    app.manage(joblog::JobLogs::default());
+    app.manage(speedseries::SpeedSeries::default());
+    app.manage(history::HistoryStore::load(app));
+    app.manage(plan::PlanStore::default());
+    app.manage(batch::Batches::default());
+    app.manage(queue::JobQueue::load(app));
+    app.manage(deferred::ScheduledJobs::load(app));
+    app.manage(settings::SettingsStore::load(app));
+    app.manage(presets::PresetStore::load(app));
+    app.manage(automation::AutomationServer::default());
+    app.manage(capabilities::CapabilityCache::default());
+    app.manage(hardware::HwCache::default());
+    app.manage(hardware::GpuSessions::default());
+    app.manage(outputs::OutputReservations::default());
+    app.manage(ffmpeg::FfmpegBinary::default());
+    app.manage(watch::WatchScanner::default());
+    app.manage(ladder::QualityLadder::default());
+    app.manage(package::Packaging::default());
+    app.manage(simple::SimpleJobs::default());
+    app.manage(procgroup::SpawnedChildren::default());
+    app.manage(pause::PausedJobs::default());
+    app.manage(rehearsal::Rehearsal::default());
+    app.manage(selftest::SelfTest::default());
+    app.manage(thumbs::ThumbnailCache::default());
+    app.manage(scoring::QualityScoring::default());
+    app.manage(active::ActiveJobs::default());
+    app.manage(tray::TrayStatus::default());
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    tauri::Builder::default()
+    set_async_runtime();
+    tauri::Builder::<Runtime>::new()
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_updater::Builder::new().build())
         .plugin(tauri_plugin_process::init())
-        .invoke_handler(tauri::generate_handler![compress_video, compress_image, kill_ffmpeg])
-        .on_window_event(|_window, event| {
-            if let WindowEvent::Destroyed = event {
-                println!("❌ App Closing: Cleaning up processes...");
-                #[cfg(target_os = "windows")]
-                { let _ = Command::new("taskkill").args(["/F", "/IM", "ffmpeg*", "/T"]).spawn(); }
-                #[cfg(not(target_os = "windows"))]
-                { let _ = Command::new("pkill").arg("-f").arg("ffmpeg").spawn(); }
+        .plugin(tauri_plugin_notification::init())
+        .plugin(tauri_plugin_clipboard_manager::init())
+        .setup(|app| {
+            manage_state(app.handle());
+            extended_ffmpeg::activate_if_installed(app.handle());
+            replace::recover(app.handle());
+            undo::recover(app.handle());
+            queue::pump(app.handle());
+            automation::start_if_enabled(app.handle());
+            watch::start(app.handle());
+            capabilities::refresh_on_startup(app.handle());
+            health::check_on_startup(app.handle());
+            thumbs::start_sweeper(app.handle());
+            history::compact_in_background(app.handle());
+            quickshare::sweep(app.handle());
+            schedule::start(app.handle());
+            deferred::start(app.handle());
+            if let Err(e) = tray::create(app.handle()) {
+                println!("⚠️ Could not add the tray icon: {}", e);
+            }
+            instance::announce(app.handle());
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            compress_video,
+            compress_video_request,
+            replace::compress_in_place,
+            compress_image,
+            image_batch::compress_image_batch,
+            image_convert::convert_images,
+            pip::compose_pip,
+            compress_image_request,
+            audio::compress_audio,
+            image_auto::compress_image_auto,
+            quickshare::quick_compress,
+            quickshare::set_quick_compress_keep_days,
+            inputs::classify_inputs,
+            playability::analyze_playability,
+            playability::lint_output,
+            playability::auto_fix_output,
+            ladder::run_quality_ladder,
+            ladder::cancel_quality_ladder,
+            ladder::clear_ladder_samples,
+            kill_ffmpeg,
+            concat::concat_videos,
+            multi::compress_multi,
+            scoring::cancel_quality_score,
+            report::export_batch_report,
+            report::export_history,
+            package::package_outputs,
+            package::cancel_packaging,
+            history::get_history,
+            history::clear_history,
+            history::delete_history_entries,
+            history::search_history,
+            history::update_history_entry,
+            history::set_history_retention,
+            plan::plan_batch,
+            plan::cancel_plan,
+            plan::execute_plan,
+            plan::set_plan_ttl,
+            store::repair_stores,
+            maintenance::list_maintenance_actions,
+            maintenance::run_maintenance_action,
+            thumbs::get_thumbnail,
+            thumbs::generate_thumbnail,
+            thumbs::set_thumbnail_cache_limit,
+            compare::extract_matching_frames,
+            probe::probe_media,
+            hardware::get_hw_capabilities,
+            hardware::refresh_hw_capabilities,
+            hardware::list_encoders,
+            support::get_support_matrix,
+            schedule::get_schedule_status,
+            schedule::set_schedule_window,
+            deferred::schedule_job,
+            deferred::list_scheduled,
+            deferred::cancel_scheduled,
+            tray::set_close_to_tray,
+            active::get_active_jobs,
+            outputs::set_work_dir,
+            outputs::check_disk_space,
+            nightplan::set_night_plan,
+            instance::get_instance_status,
+            cleanup::set_managed_folder,
+            cleanup::remove_managed_folder,
+            cleanup::run_cleanup,
+            undo::list_undoable_actions,
+            undo::undo_action,
+            batch::compress_batch,
+            directory::compress_directory,
+            batch::cancel_batch,
+            events::subscribe_events,
+            events::unsubscribe_events,
+            events::get_event_schema,
+            filterspec::get_filter_spec_schema,
+            stats::get_lifetime_stats,
+            stats::get_stats_by_month,
+            stats::get_savings_summary,
+            queue::enqueue_jobs,
+            queue::get_queue,
+            queue::reorder_job,
+            queue::set_job_priority,
+            queue::set_queue_limits,
+            queue::cancel_job,
+            pause::pause_job,
+            pause::resume_job,
+            queue::redirect_output,
+            rehearsal::set_rehearsal_mode,
+            rehearsal::get_rehearsal_mode,
+            macos::open_privacy_settings,
+            macos::set_clear_quarantine,
+            archive::create_archive_manifest,
+            archive::verify_archive,
+            options::list_options,
+            joblog::get_job_log,
+            joblog::export_job_log,
+            joblog::clear_job_logs,
+            speedseries::get_job_speed_series,
+            options::describe_options,
+            options::get_option_schema,
+            metadata::edit_metadata,
+            settings::set_memory_limit,
+            simple::compress_simple,
+            risk::set_deep_verify_on_risk,
+            flood::set_abort_on_decode_flood,
+            salvage::set_salvage_error_rate,
+            sourcetool::set_source_fixups,
+            upload::set_upload_target,
+            upload::retry_upload,
+            verify::set_verify_tier,
+            selftest::self_test,
+            selftest::cancel_self_test_step,
+            selftest::cancel_self_test,
+            selftest::last_self_test,
+            throttle::set_volume_io_throttle,
+            timeline::get_job_timeline,
+            explain::explain_job,
+            estimate::estimate_output_size,
+            presets::list_presets,
+            presets::save_preset,
+            presets::delete_preset,
+            preview::preview_preset,
+            automation::get_automation_api,
+            automation::set_automation_api,
+            automation::regenerate_automation_token,
+            extended_ffmpeg::set_extended_ffmpeg_source,
+            extended_ffmpeg::download_extended_ffmpeg,
+            extended_ffmpeg::get_ffmpeg_info,
+            health::check_ffmpeg,
+            extended_ffmpeg::remove_extended_ffmpeg,
+            watch::list_watch_folders,
+            watch::update_watch_folder,
+            watch::remove_watch_folder,
+            watch::start_watch_folder,
+            watch::stop_watch_folder
+        ])
+        .on_window_event(|window, event| match event {
+            // Destroying the window would end the app and the encodes with
+            // it: with jobs left it's only hidden (see tray.rs)
+            WindowEvent::CloseRequested { api, .. } if !tray::keeps_running(window.app_handle()) && tray::has_active_jobs(window.app_handle()) => {
+                api.prevent_close();
+                if let Err(e) = window.hide() {
+                    println!("⚠️ Could not hide the window: {}", e);
+                }
+                println!("🪟 Jobs still running, hidden to the tray");
+            }
+            // The cleanup waits for the real exit
+            WindowEvent::Destroyed => {
+                if tray::keeps_running(window.app_handle()) {
+                    println!("🪟 Window closed, still running in the tray");
+                }
+                window.app_handle().state::<history::HistoryStore>().flush();
             }
+            _ => {}
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| match event {
+            // The last window closing asks to exit (code None); an explicit
+            // exit like the tray's Quit has a code and goes through
+            RunEvent::ExitRequested { code: None, api, .. } if tray::keeps_running(app) => api.prevent_exit(),
+            RunEvent::Exit => shut_down(app),
+            _ => {}
+        });
+}
+
+// Only on the real exit: closing the window alone never kills an encode.
+fn shut_down(app: &AppHandle) {
+    println!("❌ App Closing: Cleaning up processes...");
+    automation::stop(app);
+    procgroup::kill_all(app);
+    app.state::<history::HistoryStore>().flush();
+    app.state::<instance::InstanceGuard>().release();
 }
\ No newline at end of file
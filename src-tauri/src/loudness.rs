@@ -0,0 +1,171 @@
+use serde::{Deserialize, Serialize};
+use tauri_plugin_shell::process::CommandEvent;
+
+use crate::AppHandle;
+use crate::events::{self, Event};
+use crate::ffmpeg::{self, ProgressPayload};
+use crate::progress::{self, ProgressTracker};
+use crate::queue;
+
+// EBU R128 targets: -16 LUFS integrated (what streaming platforms and
+// podcasts aim for), -1.5 dBTP true peak, loudness range 11 LU
+const TARGET_I: f64 = -16.0;
+const TARGET_TP: f64 = -1.5;
+const TARGET_LRA: f64 = 11.0;
+// loudnorm works at 192 kHz; the output goes back to the source's rate
+const FALLBACK_SAMPLE_RATE: u32 = 48000;
+// Share of the job's progress the measuring pass takes (audio only, so
+// much quicker than the encode)
+pub const MEASURE_SHARE: f32 = 15.0;
+
+// ==========================================
+// LOUDNESS NORMALIZATION
+// ==========================================
+// normalize_audio evens out quiet and loud sources with loudnorm, in two
+// passes: the first only measures the (cut) audio, the second applies the
+// measured values, which is linear and exact where a single pass has to
+// guess as it goes. When measuring fails (or finds only silence) the
+// encode still runs, with single-pass loudnorm, and the result says so.
+
+// What the measuring pass prints at the end of stderr, values as strings:
+// { "input_i" : "-27.61", "input_tp" : "-4.47", ... }
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Measured {
+    pub input_i: String,
+    pub input_tp: String,
+    pub input_lra: String,
+    pub input_thresh: String,
+    pub target_offset: String,
+}
+
+impl Measured {
+    fn values(&mut self) -> [&mut String; 5] {
+        [&mut self.input_i, &mut self.input_tp, &mut self.input_lra, &mut self.input_thresh, &mut self.target_offset]
+    }
+
+    // Silent audio measures "-inf", which loudnorm won't take back. A build
+    // that ignored LC_ALL prints "-27,61", and a comma would end the second
+    // pass's filter, so those go back to points.
+    fn usable(mut self) -> Option<Self> {
+        for value in self.values() {
+            progress::parse_number(value)?;
+            *value = value.trim().replacen(',', ".", 1);
+        }
+        Some(self)
+    }
+}
+
+fn targets() -> String {
+    format!("loudnorm=I={}:TP={}:LRA={}", TARGET_I, TARGET_TP, TARGET_LRA)
+}
+
+// Pure: the last JSON block loudnorm printed, when it's usable.
+pub fn parse_measured(stderr: &str) -> Option<Measured> {
+    let start = stderr.rfind('{')?;
+    let end = start + stderr[start..].find('}')?;
+    let measured: Measured = serde_json::from_str(&stderr[start..=end]).ok()?;
+    measured.usable()
+}
+
+// Pure: the -af entry. Measured values make it the second of two passes;
+// without them it's single-pass (dynamic) loudnorm.
+pub fn filter(measured: Option<&Measured>, sample_rate: Option<u32>) -> String {
+    let loudnorm = match measured {
+        Some(m) => format!(
+            "{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+            targets(),
+            m.input_i.trim(),
+            m.input_tp.trim(),
+            m.input_lra.trim(),
+            m.input_thresh.trim(),
+            m.target_offset.trim()
+        ),
+        None => targets(),
+    };
+    format!("{},aresample={}", loudnorm, sample_rate.unwrap_or(FALLBACK_SAMPLE_RATE))
+}
+
+// The measuring pass, over what the encode will read: `input_args` before
+// -i (seek, lenient flags) and `limit_args` after it. Reports progress as
+// the first MEASURE_SHARE percent of the job.
+pub async fn measure(
+    app: &AppHandle,
+    input: &str,
+    input_args: &[String],
+    limit_args: &[String],
+    tracker: ProgressTracker,
+) -> Result<Option<Measured>, String> {
+    let mut args = vec!["-hide_banner".to_string()];
+    args.extend(input_args.iter().cloned());
+    args.extend(["-i".to_string(), input.to_string()]);
+    args.extend(limit_args.iter().cloned());
+    args.extend(["-vn", "-sn", "-dn", "-af"].map(String::from));
+    args.push(format!("{}:print_format=json", targets()));
+    args.extend(["-f", "null", "-"].map(String::from));
+
+    let mut tracker = tracker.with_span(0.0, MEASURE_SHARE);
+    let mut sidecar = ffmpeg::spawn(app, args)?;
+    let mut stderr = String::new();
+    while let Some(event) = sidecar.next().await? {
+        match event {
+            CommandEvent::Stderr(bytes) => {
+                let chunk = String::from_utf8_lossy(&bytes).to_string();
+                if let Some(update) = tracker.update(&chunk) {
+                    queue::report_progress(app, update.percent);
+                    events::emit(app, Event::CompressionProgress(ProgressPayload {
+                        percent: update.percent,
+                        out_time_secs: update.out_time_secs,
+                        total_secs: tracker.total_secs(),
+                        speed: update.speed,
+                        fps: None,
+                        out_size_bytes: None,
+                        eta_secs: None,
+                        bottleneck: None,
+                        projected_size_bytes: None,
+                        sample: None,
+                    }));
+                }
+                stderr.push_str(&chunk);
+                stderr.push('\n');
+            }
+            CommandEvent::Terminated(payload) if payload.code != Some(0) => {
+                println!("⚠️ Loudness measurement failed (code {:?})", payload.code);
+                return Ok(None);
+            }
+            _ => {}
+        }
+    }
+    Ok(parse_measured(&stderr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measuring_pass(input_i: &str, target_offset: &str) -> String {
+        format!(
+            "size=N/A time=00:00:30.00 bitrate=N/A speed= 120x\n[Parsed_loudnorm_0 @ 0x55]\n{{\n\t\"input_i\" : \"{}\",\n\t\"input_tp\" : \"-4.47\",\n\t\"input_lra\" : \"6.30\",\n\t\"input_thresh\" : \"-38.02\",\n\t\"output_i\" : \"-16.02\",\n\t\"normalization_type\" : \"dynamic\",\n\t\"target_offset\" : \"{}\"\n}}\n",
+            input_i, target_offset
+        )
+    }
+
+    #[test]
+    fn the_measured_block_becomes_the_second_pass() {
+        let measured = parse_measured(&measuring_pass("-27.61", "0.02")).unwrap();
+        assert_eq!(measured.input_i, "-27.61");
+        assert_eq!(
+            filter(Some(&measured), Some(44100)),
+            "loudnorm=I=-16:TP=-1.5:LRA=11:measured_I=-27.61:measured_TP=-4.47:measured_LRA=6.30:measured_thresh=-38.02:offset=0.02:linear=true,aresample=44100"
+        );
+        // Silence measures -inf, which leaves the single pass
+        assert_eq!(parse_measured(&measuring_pass("-inf", "0.02")), None);
+        assert_eq!(parse_measured("size=N/A time=00:00:30.00"), None);
+    }
+
+    #[test]
+    fn comma_locale_values_go_back_to_points() {
+        let measured = parse_measured(&measuring_pass("-27,61", " 0,02")).unwrap();
+        assert_eq!((measured.input_i.as_str(), measured.target_offset.as_str()), ("-27.61", "0.02"));
+        assert!(filter(Some(&measured), None).contains("measured_I=-27.61:"));
+    }
+}
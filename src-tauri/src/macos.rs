@@ -0,0 +1,194 @@
+use std::path::Path;
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::settings::SettingsStore;
+
+// Where TCC asks the user before an app may read or write: the home
+// folders it guards, iCloud Drive, and other volumes
+const PROTECTED: &[&str] = &["/Desktop/", "/Documents/", "/Downloads/", "/Library/Mobile Documents/"];
+const PROTECTED_ROOTS: &[&str] = &["/Volumes/"];
+// EPERM, which is what TCC answers with (a plain mode problem is EACCES,
+// "Permission denied")
+const EPERM: &str = "Operation not permitted";
+const PRIVACY_PANE: &str = "x-apple.systempreferences:com.apple.preference.security?Privacy_FilesAndFolders";
+
+// ==========================================
+// MACOS PERMISSIONS AND QUARANTINE
+// ==========================================
+// Two macOS-only ways a job goes wrong without anything being wrong with
+// it. Writing to Desktop / Documents / Downloads or an external drive
+// needs the user's say-so (TCC), and without it ffmpeg just fails with
+// "Operation not permitted"; errors::classify turns that into
+// MacPermissionDenied with the folder and what to do, and
+// open_privacy_settings takes the user there. And outputs can carry the
+// quarantine flag, which makes Finder warn on the first open, so it's
+// cleared from what we wrote (clear_quarantine, on by default).
+
+fn protected(path: &str) -> bool {
+    PROTECTED.iter().any(|p| path.contains(p)) || PROTECTED_ROOTS.iter().any(|p| path.starts_with(p))
+}
+
+// Pure: the path in the first line that's an EPERM on a TCC-guarded
+// location. ffmpeg says "[out#0/mp4 @ 0x...] Error opening output
+// /Users/me/Desktop/clip.mp4: Operation not permitted", our own moves say
+// "Could not copy the output next to /Users/...: Operation not permitted
+// (os error 1)".
+pub fn tcc_denied_path<'a>(lines: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    lines.into_iter().find_map(|line| {
+        let prefix = &line[..line.find(&format!(": {}", EPERM))?];
+        let start = if prefix.starts_with('/') { 0 } else { prefix.find(" /")? + 1 };
+        let path = prefix[start..].trim();
+        protected(path).then(|| path.to_string())
+    })
+}
+
+// Pure: what to tell the user about `path`.
+pub fn guidance(path: &str) -> String {
+    let folder = Path::new(path).parent().map(|p| p.display().to_string()).unwrap_or_else(|| path.to_string());
+    format!(
+        "macOS didn't let compress-io write to {}. Allow it under System Settings > Privacy & Security > Files and Folders (or Full Disk Access), then run the job again.",
+        folder
+    )
+}
+
+pub fn clear_quarantine_enabled(app: &AppHandle) -> bool {
+    app.try_state::<SettingsStore>().is_none_or(|s| s.get().clear_quarantine)
+}
+
+// Best effort, after an output is in place: an output without the flag,
+// or a filesystem without xattrs, is already fine.
+#[cfg(target_os = "macos")]
+pub fn clear_quarantine(path: &Path) {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let (Ok(path), Ok(name)) = (CString::new(path.as_os_str().as_bytes()), CString::new("com.apple.quarantine")) else { return };
+    // SAFETY: both are NUL-terminated and outlive the call
+    unsafe {
+        libc::removexattr(path.as_ptr(), name.as_ptr(), 0);
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn clear_quarantine(_path: &Path) {}
+
+// ==========================================
+// COMMANDS: MACOS PERMISSIONS
+// ==========================================
+#[tauri::command]
+pub fn open_privacy_settings() -> Result<(), String> {
+    if !cfg!(target_os = "macos") {
+        return Err("Privacy settings are a macOS thing; nothing to open here".to_string());
+    }
+    let status = std::process::Command::new("open").arg(PRIVACY_PANE).status().map_err(|e| e.to_string())?;
+    if status.success() { Ok(()) } else { Err("System Settings didn't open".to_string()) }
+}
+
+#[tauri::command]
+pub fn set_clear_quarantine(store: State<'_, SettingsStore>, enabled: bool) -> Result<(), String> {
+    store.update(|s| s.clear_quarantine = enabled).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{self, JobError};
+    use crate::jobtests::Harness;
+
+    const FFMPEG_DENIED: &str = "[out#0/mp4 @ 0x600001e4c000] Error opening output /Users/me/Desktop/clip.mp4: Operation not permitted";
+
+    #[test]
+    fn eperm_on_a_guarded_folder_names_the_path() {
+        let denied = |line: &str| tcc_denied_path([line]);
+        assert_eq!(denied(FFMPEG_DENIED).as_deref(), Some("/Users/me/Desktop/clip.mp4"));
+        assert_eq!(
+            denied("Could not copy the output next to /Users/me/Documents/Work/clip.mp4: Operation not permitted (os error 1)").as_deref(),
+            Some("/Users/me/Documents/Work/clip.mp4")
+        );
+        assert_eq!(denied("/Volumes/Backup/clip.mp4: Operation not permitted").as_deref(), Some("/Volumes/Backup/clip.mp4"));
+        assert_eq!(
+            denied("Error opening input file /Users/me/Library/Mobile Documents/com~apple~CloudDocs/clip.mov: Operation not permitted").as_deref(),
+            Some("/Users/me/Library/Mobile Documents/com~apple~CloudDocs/clip.mov")
+        );
+        // The first such line of several
+        let lines = ["frame=10 fps=5 time=00:00:01.00", FFMPEG_DENIED, "/Users/me/Downloads/b.mp4: Operation not permitted"];
+        assert_eq!(tcc_denied_path(lines).as_deref(), Some("/Users/me/Desktop/clip.mp4"));
+    }
+
+    #[test]
+    fn other_refusals_arent_tcc() {
+        // Not a guarded folder
+        assert_eq!(tcc_denied_path(["Error opening output /Users/me/Movies/clip.mp4: Operation not permitted"]), None);
+        assert_eq!(tcc_denied_path(["Error opening output /tmp/Volumes/clip.mp4: Operation not permitted"]), None);
+        // EACCES is the file's own mode, not TCC
+        assert_eq!(tcc_denied_path(["Error opening output /Users/me/Desktop/clip.mp4: Permission denied"]), None);
+        assert_eq!(tcc_denied_path(["Operation not permitted"]), None);
+    }
+
+    #[test]
+    fn guidance_names_the_folder() {
+        let told = guidance("/Users/me/Desktop/clip.mp4");
+        assert!(told.starts_with("macOS didn't let compress-io write to /Users/me/Desktop."));
+        assert!(told.contains("Privacy & Security > Files and Folders"));
+    }
+
+    #[test]
+    fn classify_turns_tcc_refusals_into_mac_permission_denied_on_macos_only() {
+        let error = "Error (Code 1): Conversion failed!";
+        let classified = errors::classify(error, vec!["frame=0".to_string(), FFMPEG_DENIED.to_string()]);
+        if cfg!(target_os = "macos") {
+            assert_eq!(
+                classified,
+                JobError::MacPermissionDenied {
+                    path: "/Users/me/Desktop/clip.mp4".to_string(),
+                    guidance: guidance("/Users/me/Desktop/clip.mp4"),
+                    message: error.to_string(),
+                }
+            );
+        } else {
+            assert!(matches!(classified, JobError::FfmpegError { code: Some(1), .. }));
+        }
+
+        // In the error itself, as our own moves fail
+        let moved = "Could not copy the output next to /Users/me/Desktop/clip.mp4: Operation not permitted (os error 1)";
+        match errors::classify(moved, vec![]) {
+            JobError::MacPermissionDenied { path, .. } => assert!(cfg!(target_os = "macos") && path == "/Users/me/Desktop/clip.mp4"),
+            JobError::PermissionDenied { message } => assert!(!cfg!(target_os = "macos") && message == moved),
+            other => panic!("{:?}", other),
+        }
+        // Outside the guarded folders it's the usual kind everywhere
+        let elsewhere = "Could not copy the output next to /Users/me/Movies/clip.mp4: Operation not permitted (os error 1)";
+        assert!(matches!(errors::classify(elsewhere, vec![]), JobError::PermissionDenied { .. }));
+    }
+
+    #[test]
+    fn clearing_quarantine_follows_the_setting() {
+        let h = Harness::new("quarantine-setting", "{}");
+        assert!(clear_quarantine_enabled(h.handle()));
+        set_clear_quarantine(h.handle().state::<SettingsStore>(), false).unwrap();
+        assert!(!clear_quarantine_enabled(h.handle()));
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn the_quarantine_flag_is_cleared_from_an_output() {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let h = Harness::new("quarantine-xattr", "{}");
+        let output = std::path::PathBuf::from(h.input("clip.mp4", 10));
+        let path = CString::new(output.as_os_str().as_bytes()).unwrap();
+        let name = CString::new("com.apple.quarantine").unwrap();
+        let flag = b"0083;6710b2c0;Safari;";
+        // SAFETY (both calls): NUL-terminated names; getxattr only sizes the value
+        let read = || unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0, 0, 0) };
+        assert_eq!(unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), flag.as_ptr().cast(), flag.len(), 0, 0) }, 0);
+        assert_eq!(read(), flag.len() as isize);
+
+        clear_quarantine(&output);
+        assert_eq!(read(), -1);
+        // Already clear is fine too
+        clear_quarantine(&output);
+    }
+}
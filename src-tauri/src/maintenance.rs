@@ -0,0 +1,514 @@
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::capabilities::CapabilityCache;
+use crate::events::{self, Event};
+use crate::extended_ffmpeg;
+use crate::hardware::{self, HwCache};
+use crate::history::HistoryStore;
+use crate::ladder;
+use crate::settings::{Settings, SettingsStore};
+use crate::store;
+use crate::thumbs;
+
+// prune_history drops entries older than this
+const PRUNE_AFTER_DAYS: u64 = 365;
+
+// ==========================================
+// MAINTENANCE ACTIONS
+// ==========================================
+// The settings screen's "maintenance" list, rendered from what
+// list_maintenance_actions says rather than one button per command: a new
+// action is a new variant here and nothing else. Impacts (what an action
+// would free or remove) are only worked out when asked for, since some of
+// them walk whole cache directories.
+//
+// Destructive actions lose something that can't be made again, and only
+// run with `confirm: true`.
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Destructiveness {
+    // Nothing is lost
+    Harmless,
+    // Only what the app makes again when it needs it
+    CacheOnly,
+    // User data or choices; needs `confirm`
+    Destructive,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    ClearThumbnails,
+    ClearLadderSamples,
+    RefreshEncoders,
+    RepairStores,
+    PruneHistory,
+    ClearHistory,
+    ResetSettings,
+    RemoveExtendedFfmpeg,
+}
+
+const ACTIONS: &[Action] = &[
+    Action::ClearThumbnails,
+    Action::ClearLadderSamples,
+    Action::RefreshEncoders,
+    Action::RepairStores,
+    Action::PruneHistory,
+    Action::ClearHistory,
+    Action::ResetSettings,
+    Action::RemoveExtendedFfmpeg,
+];
+
+impl Action {
+    fn id(self) -> &'static str {
+        match self {
+            Action::ClearThumbnails => "clear_thumbnails",
+            Action::ClearLadderSamples => "clear_ladder_samples",
+            Action::RefreshEncoders => "refresh_encoders",
+            Action::RepairStores => "repair_stores",
+            Action::PruneHistory => "prune_history",
+            Action::ClearHistory => "clear_history",
+            Action::ResetSettings => "reset_settings",
+            Action::RemoveExtendedFfmpeg => "remove_extended_ffmpeg",
+        }
+    }
+
+    fn from_id(id: &str) -> Option<Self> {
+        ACTIONS.iter().copied().find(|a| a.id() == id)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Action::ClearThumbnails => "Clear thumbnail cache",
+            Action::ClearLadderSamples => "Clear quality ladder samples",
+            Action::RefreshEncoders => "Re-detect encoders",
+            Action::RepairStores => "Repair data files",
+            Action::PruneHistory => "Prune old history",
+            Action::ClearHistory => "Clear history",
+            Action::ResetSettings => "Reset settings",
+            Action::RemoveExtendedFfmpeg => "Remove extended ffmpeg",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Action::ClearThumbnails => "Deletes every cached thumbnail and preview frame; they're made again when shown.",
+            Action::ClearLadderSamples => "Deletes the sample encodes quality ladders keep for re-runs.",
+            Action::RefreshEncoders => "Checks ffmpeg's codecs and filters and test-encodes on the GPU again, e.g. after a driver update.",
+            Action::RepairStores => "Checks settings, history, queue and presets files and rewrites damaged ones from what's loaded.",
+            Action::PruneHistory => "Removes history entries (and their share of the stats) older than a year.",
+            Action::ClearHistory => "Removes every history entry and resets the lifetime stats.",
+            Action::ResetSettings => "Puts job and cache settings back to their defaults. Watch folders, managed folders, the upload target, the automation API and the extended ffmpeg are kept.",
+            Action::RemoveExtendedFfmpeg => "Deletes the downloaded ffmpeg build and goes back to the bundled one.",
+        }
+    }
+
+    fn destructiveness(self) -> Destructiveness {
+        match self {
+            Action::RefreshEncoders | Action::RepairStores => Destructiveness::Harmless,
+            Action::ClearThumbnails | Action::ClearLadderSamples => Destructiveness::CacheOnly,
+            Action::PruneHistory | Action::ClearHistory | Action::ResetSettings | Action::RemoveExtendedFfmpeg => Destructiveness::Destructive,
+        }
+    }
+}
+
+// What running an action would do. `summary` is for showing as is; the
+// numbers are there for UIs that word it themselves.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Impact {
+    pub summary: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MaintenanceAction {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub destructiveness: Destructiveness,
+    pub requires_confirm: bool,
+    // Only with `with_impact`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub impact: Option<Impact>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MaintenanceOutcome {
+    pub id: &'static str,
+    pub summary: String,
+    // Disk space given back, for the actions that delete files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub freed_bytes: Option<u64>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MaintenanceError {
+    UnknownAction { id: String, message: String },
+    ConfirmationRequired { id: String, message: String },
+    Failed { id: String, message: String },
+}
+
+fn now_unix() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn prune_cutoff() -> u64 {
+    now_unix().saturating_sub(PRUNE_AFTER_DAYS * 24 * 3600)
+}
+
+fn gb(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 * 1024 {
+        format!("{:.1} GB", bytes as f64 / 1024.0 / 1024.0 / 1024.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / 1024.0 / 1024.0)
+    }
+}
+
+fn frees(bytes: u64) -> Impact {
+    Impact { summary: format!("Will free {}", gb(bytes)), bytes: Some(bytes), count: None }
+}
+
+fn entries(count: usize, what: &str) -> Impact {
+    Impact { summary: format!("Will remove {} {}", count, what), bytes: None, count: Some(count as u64) }
+}
+
+// What reset_settings puts back: everything but the settings that stand
+// for something set up outside this list (folders, credentials, installs).
+fn reset(current: &Settings) -> Settings {
+    Settings {
+        automation_api: current.automation_api,
+        automation_port: current.automation_port,
+        automation_token: current.automation_token.clone(),
+        extended_ffmpeg_builds: current.extended_ffmpeg_builds.clone(),
+        extended_ffmpeg: current.extended_ffmpeg.clone(),
+        watch_folders: current.watch_folders.clone(),
+        managed_folders: current.managed_folders.clone(),
+        upload_target: current.upload_target.clone(),
+        ..Settings::default()
+    }
+}
+
+// Pure: top-level settings that differ between the two.
+fn changed_settings(from: &Settings, to: &Settings) -> usize {
+    let (Ok(serde_json::Value::Object(from)), Ok(serde_json::Value::Object(to))) = (serde_json::to_value(from), serde_json::to_value(to)) else {
+        return 0;
+    };
+    from.iter().filter(|(key, value)| to.get(*key) != Some(*value)).count()
+}
+
+fn impact(app: &AppHandle, action: Action) -> Impact {
+    match action {
+        Action::ClearThumbnails => frees(thumbs::cache_bytes(app)),
+        Action::ClearLadderSamples => frees(ladder::samples_bytes(app)),
+        Action::RefreshEncoders => Impact { summary: "Takes a few seconds; running jobs aren't affected".to_string(), bytes: None, count: None },
+        Action::RepairStores => Impact { summary: "Only damaged files are rewritten".to_string(), bytes: None, count: None },
+        Action::PruneHistory => entries(app.state::<HistoryStore>().ids_before(prune_cutoff()).len(), "history entries"),
+        Action::ClearHistory => entries(app.state::<HistoryStore>().count(), "history entries"),
+        Action::ResetSettings => {
+            let current = app.state::<SettingsStore>().get();
+            let count = changed_settings(&current, &reset(&current));
+            Impact { summary: format!("Will reset {} changed settings", count), bytes: None, count: Some(count as u64) }
+        }
+        Action::RemoveExtendedFfmpeg => match extended_ffmpeg::installed_bytes(app) {
+            0 => Impact { summary: "No extended ffmpeg is installed".to_string(), bytes: Some(0), count: None },
+            bytes => frees(bytes),
+        },
+    }
+}
+
+async fn run(app: &AppHandle, action: Action) -> Result<MaintenanceOutcome, String> {
+    let outcome = |summary: String, freed_bytes: Option<u64>| MaintenanceOutcome { id: action.id(), summary, freed_bytes };
+    match action {
+        Action::ClearThumbnails => {
+            let freed = thumbs::clear(app)?;
+            Ok(outcome(format!("Freed {}", gb(freed)), Some(freed)))
+        }
+        Action::ClearLadderSamples => {
+            let freed = ladder::clear_samples(app)?;
+            Ok(outcome(format!("Freed {}", gb(freed)), Some(freed)))
+        }
+        Action::RefreshEncoders => {
+            app.state::<CapabilityCache>().invalidate();
+            app.state::<HwCache>().invalidate();
+            crate::capabilities::get(app).await?;
+            let hw = hardware::get(app).await;
+            Ok(outcome(format!("{} hardware encoders work on this machine", hw.working.len()), None))
+        }
+        Action::RepairStores => {
+            let statuses = store::repair_stores(app.clone(), app.state(), app.state(), app.state(), app.state());
+            if let Some(failed) = statuses.iter().find(|s| s.error.is_some()) {
+                return Err(format!("Could not repair {}: {}", failed.store, failed.error.as_deref().unwrap_or("")));
+            }
+            let repaired = statuses.iter().filter(|s| s.repaired).count();
+            Ok(outcome(format!("Checked {} files, repaired {}", statuses.len(), repaired), None))
+        }
+        Action::PruneHistory | Action::ClearHistory => {
+            let history = app.state::<HistoryStore>();
+            let removed = if action == Action::ClearHistory { history.clear()? } else { history.delete(&history.ids_before(prune_cutoff()))? };
+            if removed > 0 {
+                events::emit(app, Event::StatsUpdated(history.stats().lifetime));
+            }
+            Ok(outcome(format!("Removed {} history entries", removed), None))
+        }
+        Action::ResetSettings => {
+            let store = app.state::<SettingsStore>();
+            let current = store.get();
+            let next = reset(&current);
+            let count = changed_settings(&current, &next);
+            store.update(|s| *s = next)?;
+            Ok(outcome(format!("Reset {} settings", count), None))
+        }
+        Action::RemoveExtendedFfmpeg => {
+            let freed = extended_ffmpeg::installed_bytes(app);
+            extended_ffmpeg::remove_extended_ffmpeg(app.clone()).await?;
+            Ok(outcome(format!("Back on the bundled ffmpeg, freed {}", gb(freed)), Some(freed)))
+        }
+    }
+}
+
+// ==========================================
+// COMMANDS: MAINTENANCE
+// ==========================================
+#[tauri::command]
+pub async fn list_maintenance_actions(app: AppHandle, with_impact: Option<bool>) -> Result<Vec<MaintenanceAction>, String> {
+    let with_impact = with_impact.unwrap_or(false);
+    // Sizing caches walks directories, so it stays off the main thread
+    tauri::async_runtime::spawn_blocking(move || {
+        ACTIONS
+            .iter()
+            .map(|&action| MaintenanceAction {
+                id: action.id(),
+                name: action.name(),
+                description: action.description(),
+                destructiveness: action.destructiveness(),
+                requires_confirm: action.destructiveness() == Destructiveness::Destructive,
+                impact: with_impact.then(|| impact(&app, action)),
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn run_maintenance_action(app: AppHandle, id: String, confirm: Option<bool>) -> Result<MaintenanceOutcome, MaintenanceError> {
+    let Some(action) = Action::from_id(&id) else {
+        let message = format!("There's no maintenance action \"{}\"", id);
+        return Err(MaintenanceError::UnknownAction { id, message });
+    };
+    if action.destructiveness() == Destructiveness::Destructive && !confirm.unwrap_or(false) {
+        let message = format!("\"{}\" can't be undone; run it again with confirm", action.name());
+        return Err(MaintenanceError::ConfirmationRequired { id, message });
+    }
+    println!("🧰 Maintenance: {}", action.name());
+    run(&app, action).await.map_err(|message| MaintenanceError::Failed { id, message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffmpeg::FfmpegBinary;
+    use crate::history::{self, HistoryEntry};
+    use crate::jobtests::{run, Harness};
+    use crate::settings::ExtendedBuild;
+    use std::fs;
+    use std::path::Path;
+    use std::time::Instant;
+
+    const MB: u64 = 1024 * 1024;
+
+    fn act(h: &Harness, id: &str, confirm: Option<bool>) -> Result<MaintenanceOutcome, MaintenanceError> {
+        let (app, id) = (h.handle().clone(), id.to_string());
+        run(async move { run_maintenance_action(app, id, confirm).await })
+    }
+
+    fn listed(h: &Harness) -> Vec<MaintenanceAction> {
+        let app = h.handle().clone();
+        run(async move { list_maintenance_actions(app, Some(true)).await }).unwrap()
+    }
+
+    fn impact_of(h: &Harness, id: &str) -> Impact {
+        listed(h).into_iter().find(|a| a.id == id).and_then(|a| a.impact).unwrap()
+    }
+
+    fn fill(dir: &Path, files: &[(&str, u64)]) {
+        for (name, bytes) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, vec![0u8; *bytes as usize]).unwrap();
+        }
+    }
+
+    fn finished(h: &Harness, days_ago: u64) {
+        let mut entry = HistoryEntry::finished("video", "in.mov", "out.mp4", Instant::now(), None);
+        entry.finished_at = now_unix() - days_ago * 24 * 3600;
+        history::record(h.handle(), entry).unwrap();
+    }
+
+    fn confirmation_required(result: Result<MaintenanceOutcome, MaintenanceError>) -> bool {
+        matches!(result, Err(MaintenanceError::ConfirmationRequired { .. }))
+    }
+
+    #[test]
+    fn every_action_is_listed_once_with_its_confirm_rule() {
+        let h = Harness::new("maintenance-list", "{}");
+        let actions = listed(&h);
+        let ids: Vec<&str> = actions.iter().map(|a| a.id).collect();
+        assert_eq!(ids, ACTIONS.iter().map(|a| a.id()).collect::<Vec<_>>());
+        for action in &actions {
+            assert_eq!(Action::from_id(action.id).map(Action::name), Some(action.name));
+            assert_eq!(action.requires_confirm, action.destructiveness == Destructiveness::Destructive, "{}", action.id);
+            assert!(action.impact.is_some());
+        }
+        let error = act(&h, "defrag", Some(true)).err().unwrap();
+        assert_eq!(error, MaintenanceError::UnknownAction { id: "defrag".to_string(), message: "There's no maintenance action \"defrag\"".to_string() });
+    }
+
+    #[test]
+    fn clear_thumbnails_empties_the_thumbnail_cache() {
+        let h = Harness::new("maintenance-thumbs", "{}");
+        let thumbs = h.handle().path().app_cache_dir().unwrap().join("thumbs");
+        fill(&thumbs, &[("a.jpg", MB), ("frames/b.jpg", MB / 2)]);
+        // Samples aren't thumbnails
+        fill(&h.handle().path().app_cache_dir().unwrap().join("ladder"), &[("s.mp4", MB)]);
+        assert_eq!(impact_of(&h, "clear_thumbnails"), Impact { summary: "Will free 1.5 MB".to_string(), bytes: Some(3 * MB / 2), count: None });
+
+        let outcome = act(&h, "clear_thumbnails", None).unwrap();
+        assert_eq!((outcome.summary.as_str(), outcome.freed_bytes), ("Freed 1.5 MB", Some(3 * MB / 2)));
+        assert!(!thumbs.exists());
+        assert_eq!(impact_of(&h, "clear_ladder_samples").bytes, Some(MB));
+    }
+
+    #[test]
+    fn clear_ladder_samples_empties_the_sample_cache() {
+        let h = Harness::new("maintenance-ladder", "{}");
+        let samples = h.handle().path().app_cache_dir().unwrap().join("ladder");
+        fill(&samples, &[("one/crf23.mp4", 2 * MB), ("one/crf28.mp4", MB), ("two/crf23.mp4", 1024 * MB)]);
+        assert_eq!(impact_of(&h, "clear_ladder_samples").summary, "Will free 1.0 GB");
+
+        let outcome = act(&h, "clear_ladder_samples", None).unwrap();
+        assert_eq!(outcome.freed_bytes, Some(1027 * MB));
+        assert!(!samples.exists());
+        // Nothing left: nothing to free, and running it again is fine
+        assert_eq!(impact_of(&h, "clear_ladder_samples").bytes, Some(0));
+        assert_eq!(act(&h, "clear_ladder_samples", None).unwrap().freed_bytes, Some(0));
+    }
+
+    #[test]
+    fn refresh_encoders_detects_again() {
+        // An ffmpeg that lists nothing, where the job tests' caps had libx264
+        let h = Harness::new("maintenance-refresh", r#"{ "runs": [{}] }"#);
+        assert!(crate::capabilities::cached(h.handle()).unwrap().encoders.contains("libx264"));
+        assert_eq!(impact_of(&h, "refresh_encoders").summary, "Takes a few seconds; running jobs aren't affected");
+
+        let outcome = act(&h, "refresh_encoders", None).unwrap();
+        assert_eq!((outcome.summary.as_str(), outcome.freed_bytes), ("0 hardware encoders work on this machine", None));
+        assert!(h.runs().iter().any(|args| args.iter().any(|a| a == "-encoders")));
+        assert!(crate::capabilities::cached(h.handle()).unwrap().encoders.is_empty());
+    }
+
+    #[test]
+    fn refresh_encoders_reports_a_failed_detection() {
+        let h = Harness::new("maintenance-refresh-failed", r#"{ "runs": [{ "exit_code": 1 }] }"#);
+        let error = act(&h, "refresh_encoders", None).err().unwrap();
+        assert!(matches!(error, MaintenanceError::Failed { ref id, ref message } if id == "refresh_encoders" && message.contains("failed")), "{:?}", error);
+    }
+
+    #[test]
+    fn repair_stores_rewrites_a_damaged_file() {
+        let h = Harness::new("maintenance-repair", "{}");
+        h.handle().state::<SettingsStore>().update(|s| s.work_dir = Some("/work".to_string())).unwrap();
+        let settings = h.handle().path().app_data_dir().unwrap().join("settings.json");
+        fs::write(&settings, "{\"work_dir\": \"/wo").unwrap();
+
+        let outcome = act(&h, "repair_stores", None).unwrap();
+        assert_eq!(outcome.summary, "Checked 4 files, repaired 1");
+        // Back from what was loaded
+        let saved: Settings = serde_json::from_str(&fs::read_to_string(&settings).unwrap()).unwrap();
+        assert_eq!(saved.work_dir.as_deref(), Some("/work"));
+        assert_eq!(act(&h, "repair_stores", None).unwrap().summary, "Checked 4 files, repaired 0");
+    }
+
+    #[test]
+    fn prune_history_removes_only_entries_past_a_year() {
+        let h = Harness::new("maintenance-prune", "{}");
+        for days_ago in [800, 400, 364, 0] {
+            finished(&h, days_ago);
+        }
+        assert_eq!(impact_of(&h, "prune_history"), Impact { summary: "Will remove 2 history entries".to_string(), bytes: None, count: Some(2) });
+        assert!(confirmation_required(act(&h, "prune_history", None)));
+        assert!(confirmation_required(act(&h, "prune_history", Some(false))));
+        assert_eq!(h.handle().state::<HistoryStore>().count(), 4);
+
+        assert_eq!(act(&h, "prune_history", Some(true)).unwrap().summary, "Removed 2 history entries");
+        let history = h.handle().state::<HistoryStore>();
+        assert_eq!(history.count(), 2);
+        assert_eq!(history.stats().lifetime.jobs, 2);
+        assert_eq!(act(&h, "prune_history", Some(true)).unwrap().summary, "Removed 0 history entries");
+    }
+
+    #[test]
+    fn clear_history_removes_every_entry_and_the_stats() {
+        let h = Harness::new("maintenance-clear-history", "{}");
+        for days_ago in [400, 1, 0] {
+            finished(&h, days_ago);
+        }
+        assert_eq!(impact_of(&h, "clear_history").count, Some(3));
+        assert!(confirmation_required(act(&h, "clear_history", None)));
+
+        assert_eq!(act(&h, "clear_history", Some(true)).unwrap().summary, "Removed 3 history entries");
+        let history = h.handle().state::<HistoryStore>();
+        assert_eq!(history.count(), 0);
+        assert_eq!(history.stats().lifetime.jobs, 0);
+    }
+
+    #[test]
+    fn reset_settings_keeps_what_was_set_up_outside_the_list() {
+        let h = Harness::new("maintenance-reset", "{}");
+        let store = h.handle().state::<SettingsStore>();
+        store
+            .update(|s| {
+                s.work_dir = Some("/work".to_string());
+                s.strict_memory_limit = !s.strict_memory_limit;
+                s.automation_port = 4711;
+                s.automation_token = Some("secret".to_string());
+            })
+            .unwrap();
+        assert_eq!(impact_of(&h, "reset_settings").summary, "Will reset 2 changed settings");
+        assert!(confirmation_required(act(&h, "reset_settings", None)));
+
+        assert_eq!(act(&h, "reset_settings", Some(true)).unwrap().summary, "Reset 2 settings");
+        let settings = store.get();
+        assert_eq!(settings.work_dir, None);
+        assert_eq!(settings.strict_memory_limit, Settings::default().strict_memory_limit);
+        assert_eq!((settings.automation_port, settings.automation_token.as_deref()), (4711, Some("secret")));
+        assert_eq!(impact_of(&h, "reset_settings").count, Some(0));
+    }
+
+    #[test]
+    fn remove_extended_ffmpeg_goes_back_to_the_bundled_one() {
+        let h = Harness::new("maintenance-extended", "{}");
+        assert_eq!(impact_of(&h, "remove_extended_ffmpeg").summary, "No extended ffmpeg is installed");
+
+        let install = h.handle().path().app_data_dir().unwrap().join("ffmpeg-extended");
+        fill(&install, &[("ffmpeg", 3 * MB)]);
+        let build = ExtendedBuild { url: "https://example.com/ffmpeg.tar.xz".to_string(), sha256: "00".to_string() };
+        h.handle().state::<SettingsStore>().update(|s| s.extended_ffmpeg = Some(build)).unwrap();
+        h.handle().state::<FfmpegBinary>().set_extended(Some(install.join("ffmpeg")));
+        assert_eq!(impact_of(&h, "remove_extended_ffmpeg").bytes, Some(3 * MB));
+        assert!(confirmation_required(act(&h, "remove_extended_ffmpeg", None)));
+
+        let outcome = act(&h, "remove_extended_ffmpeg", Some(true)).unwrap();
+        assert_eq!((outcome.summary.as_str(), outcome.freed_bytes), ("Back on the bundled ffmpeg, freed 3.0 MB", Some(3 * MB)));
+        assert!(!install.exists());
+        assert!(h.handle().state::<SettingsStore>().get().extended_ffmpeg.is_none());
+        assert_eq!(h.handle().state::<FfmpegBinary>().extended(), None);
+    }
+}
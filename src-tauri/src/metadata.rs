@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::AppHandle;
+use crate::ffmpeg;
+use crate::paths;
+use crate::probe;
+use crate::staging;
+
+// Longest tag value we'll write; anything bigger is almost certainly pasted binary
+pub const MAX_VALUE_BYTES: usize = 4096;
+
+// Tags each container family can actually hold (ffmpeg silently drops or
+// renames others, which looks like the edit "didn't work").
+const MP4_KEYS: &[&str] = &[
+    "title", "comment", "date", "artist", "album", "album_artist", "genre",
+    "description", "synopsis", "copyright", "composer", "encoder", "show", "episode_id",
+];
+const MKV_KEYS: &[&str] = &[
+    "title", "comment", "date", "artist", "album", "genre", "description",
+    "copyright", "composer", "encoder", "language", "summary", "keywords",
+];
+
+pub fn allowed_keys(container: &str) -> Option<&'static [&'static str]> {
+    match container {
+        "mp4" | "m4v" | "mov" | "m4a" => Some(MP4_KEYS),
+        "mkv" | "webm" | "mka" => Some(MKV_KEYS),
+        _ => None,
+    }
+}
+
+pub fn validate(container: &str, set: &BTreeMap<String, String>, clear: &[String]) -> Result<(), String> {
+    let allowed = allowed_keys(container)
+        .ok_or_else(|| format!("Editing metadata isn't supported for .{} files", container))?;
+    for key in set.keys().chain(clear.iter()) {
+        if !allowed.contains(&key.as_str()) {
+            return Err(format!("\"{}\" isn't a tag .{} files support (allowed: {})", key, container, allowed.join(", ")));
+        }
+    }
+    for (key, value) in set {
+        if value.len() > MAX_VALUE_BYTES {
+            return Err(format!("Value for \"{}\" is too long ({} bytes, max {})", key, value.len(), MAX_VALUE_BYTES));
+        }
+        if value.chars().any(|c| c.is_control() && c != '\n' && c != '\t') {
+            return Err(format!("Value for \"{}\" contains binary/control characters", key));
+        }
+    }
+    Ok(())
+}
+
+// Stream-copy everything, keep existing tags, then apply the edits
+// (`-metadata key=` with an empty value removes a tag).
+pub fn remux_args(input: &str, output: &str, set: &BTreeMap<String, String>, clear: &[String]) -> Vec<String> {
+    let mut args: Vec<String> = ["-i", input, "-map", "0", "-c", "copy", "-map_metadata", "0"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    for (key, value) in set {
+        args.push("-metadata".to_string());
+        args.push(format!("{}={}", key, value));
+    }
+    for key in clear {
+        args.push("-metadata".to_string());
+        args.push(format!("{}=", key));
+    }
+    args.push("-y".to_string());
+    args.push(output.to_string());
+    args
+}
+
+// ==========================================
+// PRESERVE / STRIP ON COMPRESSION
+// ==========================================
+// What a compression does with tags (creation date, GPS, camera, title),
+// instead of leaving it to ffmpeg's defaults.
+//
+// Rotation isn't a tag to decide on: every encode here decodes on the CPU,
+// where ffmpeg turns the frames upright before any filter (and drops the
+// display matrix), so NVENC, VideoToolbox and the rest all get a portrait
+// picture. Stream copy keeps the display matrix with the stream either way;
+// Preserve also maps the video stream's tags, for files that still carry
+// the old `rotate` tag.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataMode {
+    #[default]
+    Preserve,
+    Strip,
+}
+
+// Output-side arguments for a video job.
+pub fn video_args(mode: MetadataMode, ext: &str, copy_video: bool) -> Vec<String> {
+    let s = |v: &str| v.to_string();
+    match mode {
+        MetadataMode::Preserve => {
+            let mut args = vec![s("-map_metadata"), s("0")];
+            if copy_video {
+                args.extend([s("-map_metadata:s:v"), s("0:s:v")]);
+            }
+            // Without it the mov muxer drops every key it has no atom for
+            if matches!(ext, "mp4" | "m4v" | "mov") {
+                args.extend([s("-movflags"), s("use_metadata_tags")]);
+            }
+            args
+        }
+        MetadataMode::Strip => vec![s("-map_metadata"), s("-1"), s("-map_chapters"), s("-1")],
+    }
+}
+
+// Output-side arguments for an image. ffmpeg's image encoders never write
+// EXIF, so Strip is thorough; Preserve keeps what the muxer can hold (PNG
+// text chunks, WebP/AVIF tags), not the camera's EXIF block.
+pub fn image_args(mode: MetadataMode) -> Vec<String> {
+    match mode {
+        MetadataMode::Preserve => vec!["-map_metadata".to_string(), "0".to_string()],
+        MetadataMode::Strip => vec!["-map_metadata".to_string(), "-1".to_string()],
+    }
+}
+
+#[derive(Serialize)]
+pub struct MetadataEditResult {
+    pub output: String,
+    pub set: Vec<String>,
+    pub cleared: Vec<String>,
+}
+
+// ==========================================
+// COMMAND: EDIT METADATA (NO RE-ENCODE)
+// ==========================================
+// `in_place` rewrites the source itself, but only after the staged copy has
+// been verified; until then the original is untouched.
+#[tauri::command]
+pub async fn edit_metadata(
+    app: AppHandle,
+    input: String,
+    output: String,
+    set: BTreeMap<String, String>,
+    clear: Vec<String>,
+    in_place: bool,
+) -> Result<MetadataEditResult, String> {
+    let input = paths::secure_input(&input)?;
+    // In place, the input is also what gets written
+    let dest = PathBuf::from(paths::secure_output(&app, if in_place { &input } else { &output }, false)?);
+    if !in_place {
+        paths::ensure_not_input(&input, &output)?;
+    }
+    let container = dest
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    validate(&container, &set, &clear)?;
+
+    let source = probe::probe(&app, &input).await?;
+    let staged = staging::temp_path_for(&dest);
+    let args = remux_args(&input, &staged.to_string_lossy(), &set, &clear);
+
+    let written = match ffmpeg::run_quiet(&app, args).await {
+        Ok(()) => staging::verify_remux(&app, &source, &staged).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = written {
+        staging::discard(&staged);
+        return Err(e);
+    }
+    staging::commit(&staged, &dest)?;
+
+    println!("🏷️ Metadata updated: {}", dest.display());
+    Ok(MetadataEditResult {
+        output: dest.to_string_lossy().to_string(),
+        set: set.into_keys().collect(),
+        cleared: clear,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn only_keys_the_container_holds_are_accepted() {
+        assert_eq!(validate("mp4", &tags(&[("title", "Trip"), ("show", "Vlog")]), &["comment".to_string()]), Ok(()));
+        assert!(validate("mkv", &tags(&[("show", "Vlog")]), &[]).unwrap_err().starts_with("\"show\" isn't a tag .mkv files support"));
+        assert!(validate("mp4", &tags(&[]), &["keywords".to_string()]).is_err());
+        assert_eq!(validate("avi", &tags(&[]), &[]), Err("Editing metadata isn't supported for .avi files".to_string()));
+    }
+
+    #[test]
+    fn values_have_to_be_short_text() {
+        assert_eq!(validate("mkv", &tags(&[("comment", "line one\nline two\ttabbed")]), &[]), Ok(()));
+        assert!(validate("mkv", &tags(&[("comment", "bad\u{0}byte")]), &[]).unwrap_err().contains("control characters"));
+        let long = "x".repeat(MAX_VALUE_BYTES + 1);
+        assert!(validate("mkv", &tags(&[("comment", long.as_str())]), &[]).unwrap_err().contains("too long"));
+        assert_eq!(validate("mkv", &tags(&[("comment", "é".repeat(MAX_VALUE_BYTES / 2).as_str())]), &[]), Ok(()));
+    }
+
+    #[test]
+    fn the_remux_copies_everything_then_edits() {
+        let args = remux_args("in.mp4", ".in.tmp.mp4", &tags(&[("title", "a=b"), ("artist", "Me")]), &["comment".to_string()]);
+        assert_eq!(
+            args,
+            [
+                "-i", "in.mp4", "-map", "0", "-c", "copy", "-map_metadata", "0",
+                "-metadata", "artist=Me", "-metadata", "title=a=b", "-metadata", "comment=",
+                "-y", ".in.tmp.mp4",
+            ]
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::AppHandle;
+use crate::duration::Expected;
+use crate::encoders;
+use crate::events::Event;
+use crate::ffmpeg;
+use crate::hardware;
+use crate::history::{self, HistoryEntry};
+use crate::inputs;
+use crate::outputs;
+use crate::paths;
+use crate::probe;
+use crate::progress::ProgressTracker;
+use crate::request::MAX_DIMENSION;
+use crate::stats::JobStats;
+use crate::support::{self, VideoCodec};
+use crate::verify;
+
+const MAX_OUTPUTS: usize = 8;
+const AUDIO_BITRATE: &str = "128k";
+
+// ==========================================
+// MULTI-OUTPUT ENCODE
+// ==========================================
+// Several renditions of one input (1080p, 720p, a small preview) from a
+// single ffmpeg run: the source is decoded once and `split` hands the
+// frames to one scale per output, each with its own encoder and file:
+//   [0:v]split=3[s0][s1][s2];[s0]scale=-2:'min(ih,1080)',format=yuv420p[v0];...
+//   -map [v0] -map 0:a:0? -c:v libx264 ... out0.mp4 -map [v1] ... out1.mp4
+// Progress is one percentage for the whole run. Every output is staged
+// like any other (see outputs.rs). When the run fails, nothing is kept.
+// When it succeeds, each output is read back on its own, so one bad file
+// doesn't cost the others.
+//
+// Hardware encoders: consumer NVENC allows only a few sessions at once, so
+// at most one output may ask for the GPU. The others run on the CPU.
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct OutputSpec {
+    pub output: String,
+    // Downscale to at most this height, width following; None keeps the
+    // source size. Never upscales.
+    #[serde(default)]
+    pub max_height: Option<u32>,
+    #[serde(default)]
+    pub codec: VideoCodec,
+    // Default: the encoder's medium level
+    #[serde(default)]
+    pub crf: Option<u32>,
+    // Use the hardware encoder auto_gpu would pick, if there is one
+    #[serde(default)]
+    pub gpu: bool,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Rendition {
+    pub output: String,
+    pub encoder: String,
+    #[serde(flatten)]
+    pub stats: JobStats,
+    // Set when this output failed its read-back; the others still count
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MultiResult {
+    pub input: String,
+    pub duration_secs: Option<f64>,
+    pub renditions: Vec<Rendition>,
+    pub warnings: Vec<String>,
+}
+
+pub fn validate(input: &str, outputs: &[OutputSpec]) -> Result<(), String> {
+    if outputs.len() < 2 {
+        return Err("Give at least two outputs; for one, use compress_video".to_string());
+    }
+    if outputs.len() > MAX_OUTPUTS {
+        return Err(format!("At most {} outputs per run", MAX_OUTPUTS));
+    }
+    if outputs.iter().filter(|o| o.gpu).count() > 1 {
+        return Err("Only one output can use the GPU encoder: consumer GPUs limit how many encodes run at once".to_string());
+    }
+    let mut seen = HashSet::new();
+    for (i, o) in outputs.iter().enumerate() {
+        paths::ensure_not_input(input, &o.output)?;
+        if !seen.insert(o.output.as_str()) {
+            return Err(format!("Output {} is the same file as an earlier one: {}", i + 1, o.output));
+        }
+        let ext = Path::new(&o.output).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let container = support::container(&ext).ok_or_else(|| format!("Output {}: .{} isn't a video format we write", i + 1, ext))?;
+        if !container.video.contains(&o.codec.name()) {
+            return Err(format!("Output {}: {} video can't go in .{}", i + 1, o.codec.label(), ext));
+        }
+        if let Some(h) = o.max_height.filter(|h| *h < 16 || *h > MAX_DIMENSION) {
+            return Err(format!("Output {}: {} px is outside 16-{}", i + 1, h, MAX_DIMENSION));
+        }
+    }
+    Ok(())
+}
+
+// Pure: the split and one scale chain per output, `[v0]`..`[vN]` out.
+pub fn filter_graph(outputs: &[OutputSpec]) -> String {
+    let labels: String = (0..outputs.len()).map(|i| format!("[s{}]", i)).collect();
+    let mut parts = vec![format!("[0:v]split={}{}", outputs.len(), labels)];
+    for (i, o) in outputs.iter().enumerate() {
+        // As VideoFilters' height cap: -2 keeps the width even
+        let scale = o.max_height.map(|h| format!("scale=-2:'min(ih,{})',", h)).unwrap_or_default();
+        parts.push(format!("[s{i}]{scale}format=yuv420p[v{i}]", i = i, scale = scale));
+    }
+    parts.join(";")
+}
+
+// Pure: one output's group, everything after the graph up to its file.
+pub fn output_args(index: usize, encoder: &str, crf: u32, staged: &str) -> Vec<String> {
+    let mut args = vec![
+        "-map".to_string(), format!("[v{}]", index),
+        "-map".to_string(), "0:a:0?".to_string(),
+        "-c:v".to_string(), encoder.to_string(),
+    ];
+    if let Some(preset) = encoders::profile(encoder).preset {
+        args.extend(["-preset".to_string(), preset.to_string()]);
+    }
+    args.extend(encoders::quality_args(encoder, crf));
+    args.extend([
+        "-c:a".to_string(), "aac".to_string(),
+        "-b:a".to_string(), AUDIO_BITRATE.to_string(),
+        "-y".to_string(), staged.to_string(),
+    ]);
+    args
+}
+
+// ==========================================
+// COMMAND: COMPRESS MULTI
+// ==========================================
+#[tauri::command]
+pub async fn compress_multi(app: AppHandle, input: String, mut outputs: Vec<OutputSpec>) -> Result<MultiResult, String> {
+    let input = paths::secure_input(&input)?;
+    for o in outputs.iter_mut() {
+        o.output = paths::secure_output(&app, &o.output, false)?;
+    }
+    inputs::preflight(&input).map_err(|e| e.to_string())?;
+    validate(&input, &outputs)?;
+    let info = probe::probe(&app, &input).await?;
+    if !info.has_video {
+        return Err(format!("No video stream in {}", input));
+    }
+    println!("🎛️ Encoding {} renditions of {} in one pass...", outputs.len(), input);
+
+    let mut warnings = vec![];
+    let mut encoders = vec![];
+    for o in &outputs {
+        let encoder = match o.gpu {
+            true => match hardware::preferred(&app, o.codec).await {
+                Some(encoder) => encoder,
+                None => {
+                    warnings.push(format!("No working {} hardware encoder here, so {} was encoded on the CPU", o.codec.label(), o.output));
+                    o.codec.cpu_encoder()
+                }
+            },
+            false => o.codec.cpu_encoder(),
+        };
+        encoders.push(encoder);
+    }
+
+    // All claimed before anything runs; dropping them removes every staged file
+    let mut reservations = vec![];
+    for o in &outputs {
+        reservations.push(outputs::claim_for_job(&app, &o.output, None, None)?);
+    }
+    let mut args = vec!["-i".to_string(), input.clone(), "-filter_complex".to_string(), filter_graph(&outputs)];
+    for (i, (o, reservation)) in outputs.iter().zip(&reservations).enumerate() {
+        let crf = o.crf.unwrap_or(encoders::profile(encoders[i]).levels.1);
+        args.extend(output_args(i, encoders[i], crf, &reservation.staged_str()));
+    }
+
+    let started = Instant::now();
+    let tracker = ProgressTracker::for_duration(info.duration);
+    let run = ffmpeg::run_with_progress(&app, args, tracker, Event::CompressionProgress).await;
+    if let Err(e) = run {
+        // One process: nothing it wrote is kept
+        for (o, (reservation, encoder)) in outputs.iter().zip(reservations.iter().zip(&encoders)) {
+            let mut entry = HistoryEntry::finished("multi", &input, &reservation.path_str(), started, Some(e.clone()));
+            entry.encoder = Some(encoder.to_string());
+            history::record(&app, entry);
+            println!("❌ {} not written", o.output);
+        }
+        return Err(reservations[0].classify(e));
+    }
+
+    let input_bytes = fs::metadata(&input).map(|m| m.len()).unwrap_or(0);
+    let expected = Expected { secs: info.duration, frames: None };
+    let mut renditions = vec![];
+    for (mut reservation, encoder) in reservations.into_iter().zip(encoders) {
+        let staged = reservation.staged_str();
+        let result = match verify::read_back(&app, &staged, &expected).await {
+            Ok(_) => reservation.commit().map(|path| path.to_string_lossy().to_string()),
+            Err(e) => Err(reservation.classify(e)),
+        };
+        let output = result.as_ref().map_or(reservation.path_str(), |path| path.clone());
+        let output_bytes = result.as_ref().ok().and_then(|p| fs::metadata(p).ok()).map(|m| m.len()).unwrap_or(0);
+        let mut entry = HistoryEntry::finished("multi", &input, &output, started, result.as_ref().err().cloned());
+        entry.encoder = Some(encoder.to_string());
+        entry.duration_secs = info.duration;
+        history::record(&app, entry);
+        renditions.push(Rendition {
+            output,
+            encoder: encoder.to_string(),
+            stats: JobStats::new(input_bytes, output_bytes, started.elapsed(), false),
+            error: result.err(),
+        });
+    }
+    Ok(MultiResult { input, duration_secs: info.duration, renditions, warnings })
+}
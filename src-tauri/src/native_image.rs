@@ -0,0 +1,150 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::request::ImageCompressRequest;
+
+// Quality the native JPEG encoder uses when the request has none
+const DEFAULT_JPEG_QUALITY: u8 = 80;
+
+// ==========================================
+// NATIVE IMAGE BACKEND
+// ==========================================
+// When no ffmpeg can be started (sidecar missing or broken), plain resize +
+// re-encode jobs still work in-process with the `image` crate. Only what it
+// does properly is taken: jpg/png/webp out, any format it can read in.
+// WebP comes out lossless (the crate has no lossy encoder), so those files
+// are bigger than ffmpeg's.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageBackend {
+    Ffmpeg,
+    Native,
+}
+
+fn ext_of(path: &str) -> String {
+    Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default()
+}
+
+pub fn supports(request: &ImageCompressRequest) -> bool {
+    let readable = ImageFormat::from_path(&request.input).is_ok_and(|f| f.reading_enabled());
+    readable && matches!(ext_of(&request.output).as_str(), "jpg" | "jpeg" | "png" | "webp")
+}
+
+// Same rules as ffmpeg's `scale=w:h` with -1 for a missing side: the other
+// side follows the aspect ratio, rounded to the nearest pixel.
+pub fn target_size(source: (u32, u32), width: Option<u32>, height: Option<u32>) -> (u32, u32) {
+    let (sw, sh) = (source.0.max(1) as f64, source.1.max(1) as f64);
+    match (width, height) {
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => (w, ((w as f64 * sh / sw).round() as u32).max(1)),
+        (None, Some(h)) => (((h as f64 * sw / sh).round() as u32).max(1), h),
+        (None, None) => source,
+    }
+}
+
+// Blocking: run it on a blocking thread. Writes `staged` like ffmpeg would.
+pub fn encode(request: &ImageCompressRequest, staged: &Path) -> Result<(), String> {
+    let img = image::open(&request.input).map_err(|e| format!("Could not read image: {}", e))?;
+    let (w, h) = target_size((img.width(), img.height()), request.width, request.height);
+    let img = if (w, h) == (img.width(), img.height()) {
+        img
+    } else {
+        img.resize_exact(w, h, FilterType::CatmullRom)
+    };
+
+    let file = BufWriter::new(File::create(staged).map_err(|e| e.to_string())?);
+    let result = match ext_of(&request.output).as_str() {
+        "jpg" | "jpeg" => {
+            let quality = request.quality.map_or(DEFAULT_JPEG_QUALITY, |q| q.clamp(1, 100) as u8);
+            // JPEG has no alpha channel
+            DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(JpegEncoder::new_with_quality(file, quality))
+        }
+        "png" => img.write_with_encoder(PngEncoder::new(file)),
+        "webp" => DynamicImage::ImageRgba8(img.to_rgba8()).write_with_encoder(WebPEncoder::new_lossless(file)),
+        other => return Err(format!("The native image backend can't write .{}", other)),
+    };
+    result.map_err(|e| format!("Could not write image: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::TempDir;
+    use crate::jobtests::image_request;
+    use std::process::Command;
+
+    // Something photo-like: smooth gradients with a little grain
+    fn photo(path: &Path) {
+        image::RgbImage::from_fn(640, 360, |x, y| {
+            let grain = ((x * 7 + y * 13) % 11) as u8;
+            image::Rgb([(x / 3) as u8 + grain, (y * 2 / 3) as u8 + grain, ((x + y) / 4) as u8])
+        })
+        .save(path)
+        .unwrap();
+    }
+
+    fn ffmpeg_encode(request: &ImageCompressRequest, output: &Path) -> bool {
+        let mut args = vec!["-v".to_string(), "error".to_string(), "-y".to_string(), "-i".to_string(), request.input.clone()];
+        if let Some(scale) = request.scale_filter() {
+            args.extend(["-vf".to_string(), scale]);
+        }
+        args.extend(request.quality_args());
+        args.push(output.to_string_lossy().to_string());
+        Command::new("ffmpeg").args(&args).status().is_ok_and(|s| s.success())
+    }
+
+    #[test]
+    fn a_missing_side_follows_the_aspect_ratio_like_ffmpeg_scale() {
+        assert_eq!(target_size((1920, 1080), Some(1280), None), (1280, 720));
+        assert_eq!(target_size((1920, 1080), None, Some(360)), (640, 360));
+        assert_eq!(target_size((333, 200), Some(100), None), (100, 60));
+        assert_eq!(target_size((4000, 10), Some(10), None), (10, 1));
+        assert_eq!(target_size((640, 480), Some(300), Some(300)), (300, 300));
+        assert_eq!(target_size((640, 480), None, None), (640, 480));
+    }
+
+    #[test]
+    fn only_formats_the_crate_reads_and_writes_properly_are_taken() {
+        assert!(supports(&image_request("a.png".to_string(), "a.jpg".to_string())));
+        assert!(supports(&image_request("a.tiff".to_string(), "a.webp".to_string())));
+        assert!(!supports(&image_request("a.png".to_string(), "a.avif".to_string())));
+        assert!(!supports(&image_request("a.heic".to_string(), "a.jpg".to_string())));
+    }
+
+    // Needs a real ffmpeg on PATH (CI installs one); without it there's
+    // nothing to compare against.
+    #[test]
+    fn native_output_matches_ffmpegs_in_size_format_and_roughly_bytes() {
+        if !Command::new("ffmpeg").arg("-version").output().is_ok_and(|o| o.status.success()) {
+            println!("⚠️ No ffmpeg on PATH, parity test skipped");
+            return;
+        }
+        let dir = TempDir::new(std::env::temp_dir().join(format!("native-image-test-{}", std::process::id()))).unwrap();
+        let input = dir.path().join("photo.png");
+        photo(&input);
+        let cases = [("jpg", Some(320), None, Some(80), ImageFormat::Jpeg), ("png", None, Some(180), Some(50), ImageFormat::Png), ("webp", Some(320), None, Some(100), ImageFormat::WebP)];
+        for (ext, width, height, quality, format) in cases {
+            let request = ImageCompressRequest { width, height, quality, ..image_request(input.to_string_lossy().to_string(), format!("out.{}", ext)) };
+            let (native, ffmpeg) = (dir.path().join(format!("native.{}", ext)), dir.path().join(format!("ffmpeg.{}", ext)));
+            encode(&request, &native).unwrap();
+            assert!(ffmpeg_encode(&request, &ffmpeg), "ffmpeg failed for .{}", ext);
+
+            let (a, b) = (image::open(&native).unwrap(), image::open(&ffmpeg).unwrap());
+            assert_eq!((a.width(), a.height()), (b.width(), b.height()), ".{}", ext);
+            assert_eq!((a.width(), a.height()), (320, 180), ".{}", ext);
+            for path in [&native, &ffmpeg] {
+                assert_eq!(ImageFormat::from_path(path).unwrap(), format);
+                assert_eq!(image::guess_format(&std::fs::read(path).unwrap()).unwrap(), format);
+            }
+            let ratio = std::fs::metadata(&native).unwrap().len() as f64 / std::fs::metadata(&ffmpeg).unwrap().len() as f64;
+            assert!((0.25..=4.0).contains(&ratio), ".{}: native is {:.2}x ffmpeg's size", ext, ratio);
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use chrono::{Local, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::clock;
+use crate::events::{self, Event};
+use crate::history::HistoryStore;
+use crate::plan;
+use crate::queue::{self, Priority, QueueStatus};
+use crate::settings::SettingsStore;
+
+pub const DEFAULT_NIGHT_OVERRUN_MINUTES: u64 = 15;
+
+// ==========================================
+// NIGHT PLAN
+// ==========================================
+// A schedule window is a budget: when it opens there's a fixed number of
+// hours until it closes, and usually more queued than fits. Instead of
+// starting jobs in queue order and leaving whatever is running at close to
+// finish, the window's opening estimates every pending job (same estimates
+// as plan_batch), takes them in order of bytes saved per hour of encoding
+// until the window is full, and holds the rest back as deferred, for the
+// next night. `night-plan` says what it decided; `night-summary` at close
+// says how it went. Jobs enqueued after the window opened aren't part of the
+// plan and start as usual.
+//
+// The packing is greedy, and the estimates are only estimates: a job is
+// taken when it's predicted to end no later than `night_overrun_minutes`
+// after the close. Jobs still running at close are left to finish, as
+// without a plan.
+
+// One pending job as the packer sees it.
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub job_id: u64,
+    pub priority: Priority,
+    pub saved_bytes: u64,
+    pub wall_secs: f64,
+}
+
+impl Candidate {
+    pub fn savings_per_hour(&self) -> f64 {
+        self.saved_bytes as f64 * 3600.0 / self.wall_secs.max(1.0)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Packing {
+    // In the order they should start
+    pub will_run: Vec<u64>,
+    pub deferred: Vec<u64>,
+    pub predicted_savings: u64,
+    // Seconds from the opening until the last planned job ends
+    pub predicted_secs: f64,
+}
+
+// --- PACKING (pure) ---
+// `lanes` jobs run at once; each job goes on the lane that frees up first,
+// as the queue would start it. Priority still comes first (the queue
+// dispatches by it), then savings per hour.
+pub fn pack(candidates: &[Candidate], window_secs: f64, lanes: usize, overrun_secs: f64) -> Packing {
+    let mut order: Vec<&Candidate> = candidates.iter().collect();
+    order.sort_by(|a, b| a.priority.cmp(&b.priority).then(b.savings_per_hour().total_cmp(&a.savings_per_hour())));
+
+    let mut lanes = vec![0.0f64; lanes.max(1)];
+    let mut packing = Packing::default();
+    for candidate in order {
+        let lane = lanes.iter_mut().min_by(|a, b| a.total_cmp(b)).expect("at least one lane");
+        let finish = *lane + candidate.wall_secs.max(0.0);
+        if fits(finish, window_secs, overrun_secs) {
+            *lane = finish;
+            packing.will_run.push(candidate.job_id);
+            packing.predicted_savings += candidate.saved_bytes;
+            packing.predicted_secs = packing.predicted_secs.max(finish);
+        } else {
+            packing.deferred.push(candidate.job_id);
+        }
+    }
+    packing
+}
+
+// The overrun rule: a job may end after the close, by up to the tolerance.
+pub fn fits(finish_secs: f64, window_secs: f64, overrun_secs: f64) -> bool {
+    finish_secs <= window_secs + overrun_secs.max(0.0)
+}
+
+// ==========================================
+// EVENTS
+// ==========================================
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct PlannedJob {
+    pub job_id: u64,
+    pub input: String,
+    pub predicted_saved_bytes: u64,
+    pub predicted_wall_secs: f64,
+}
+
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct NightPlan {
+    pub will_run: Vec<PlannedJob>,
+    pub deferred: Vec<PlannedJob>,
+    pub predicted_savings: u64,
+    // Unix seconds, plus the local rendering
+    pub predicted_finish: u64,
+    pub predicted_finish_local: String,
+    pub window_closes: u64,
+}
+
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct NightSummary {
+    pub completed: Vec<u64>,
+    pub failed: Vec<u64>,
+    // Planned jobs still running or not started when the window closed
+    pub unfinished: Vec<u64>,
+    pub deferred: Vec<u64>,
+    pub predicted_savings: u64,
+    // Input minus output size of the completed jobs
+    pub actual_savings: u64,
+}
+
+// What the schedule loop keeps between the opening and the close.
+pub struct Tonight {
+    plan: NightPlan,
+    // Job id -> input size when planned
+    input_bytes: HashMap<u64, u64>,
+}
+
+// ==========================================
+// WINDOW OPENING / CLOSE
+// ==========================================
+// Called by schedule.rs when the window opens. None when the night plan is
+// off, so it's asked again on the next poll.
+pub async fn open(app: &AppHandle) -> Option<Tonight> {
+    let settings = app.try_state::<SettingsStore>()?.get();
+    if !settings.night_plan {
+        return None;
+    }
+    let now = Utc::now();
+    let close = settings.schedule_window?.close_in(&Local, now)?;
+    let window_secs = (close - now).num_seconds().max(0) as f64;
+
+    let history = app.try_state::<HistoryStore>().map(|h| h.all()).unwrap_or_default();
+    let pending = queue::snapshot(app).pending;
+    let mut candidates = vec![];
+    let mut jobs = HashMap::new();
+    let mut input_bytes = HashMap::new();
+    for job in &pending {
+        let file = plan::estimate_spec(app, &job.spec, &history).await;
+        // A job that can't be estimated runs and fails as it would have
+        let saved = file.input_bytes.saturating_sub(file.estimated_output_bytes);
+        candidates.push(Candidate { job_id: job.id, priority: job.priority, saved_bytes: saved, wall_secs: file.estimated_wall_secs });
+        input_bytes.insert(job.id, file.input_bytes);
+        jobs.insert(job.id, PlannedJob { job_id: job.id, input: file.input, predicted_saved_bytes: saved, predicted_wall_secs: file.estimated_wall_secs });
+    }
+
+    let overrun_secs = settings.night_overrun_minutes as f64 * 60.0;
+    let packing = pack(&candidates, window_secs, queue::max_concurrent(app), overrun_secs);
+    let predicted_finish = now.timestamp().max(0) as u64 + packing.predicted_secs.round() as u64;
+    let planned = |ids: &[u64]| ids.iter().filter_map(|id| jobs.get(id).cloned()).collect();
+    let plan = NightPlan {
+        will_run: planned(&packing.will_run),
+        deferred: planned(&packing.deferred),
+        predicted_savings: packing.predicted_savings,
+        predicted_finish,
+        predicted_finish_local: clock::render_local(predicted_finish),
+        window_closes: close.timestamp().max(0) as u64,
+    };
+    println!("🌙 Night plan: {} jobs tonight, {} deferred", plan.will_run.len(), plan.deferred.len());
+    queue::plan(app, &packing.will_run, packing.deferred.into_iter().collect());
+    events::emit(app, Event::NightPlan(plan.clone()));
+    Some(Tonight { plan, input_bytes })
+}
+
+// Called by schedule.rs when the window closes: reports on the night and
+// lets the deferred jobs go again (nothing starts until the next opening).
+pub fn close(app: &AppHandle, tonight: Tonight) {
+    let mut summary = NightSummary {
+        completed: vec![],
+        failed: vec![],
+        unfinished: vec![],
+        deferred: tonight.plan.deferred.iter().map(|j| j.job_id).collect(),
+        predicted_savings: tonight.plan.predicted_savings,
+        actual_savings: 0,
+    };
+    for planned in &tonight.plan.will_run {
+        let job = queue::find_job(app, planned.job_id);
+        match job.as_ref().map(|j| j.status) {
+            Some(QueueStatus::Done) => {
+                summary.completed.push(planned.job_id);
+                let output = job.and_then(|j| fs::metadata(j.spec.output()).ok()).map_or(0, |m| m.len());
+                let input = tonight.input_bytes.get(&planned.job_id).copied().unwrap_or(0);
+                summary.actual_savings += input.saturating_sub(output);
+            }
+            Some(QueueStatus::Running | QueueStatus::Queued | QueueStatus::WaitingForDisk | QueueStatus::WaitingForSpace | QueueStatus::Deferred) => summary.unfinished.push(planned.job_id),
+            // Cancelled jobs are the user's doing, not the plan's
+            Some(QueueStatus::Cancelled) | None => {}
+            Some(_) => summary.failed.push(planned.job_id),
+        }
+    }
+    println!("☀️ Night summary: {} done, {} failed, {} unfinished", summary.completed.len(), summary.failed.len(), summary.unfinished.len());
+    queue::plan(app, &[], HashSet::new());
+    events::emit(app, Event::NightSummary(summary));
+}
+
+// ==========================================
+// COMMAND: NIGHT PLAN SETTINGS
+// ==========================================
+// Takes effect at the next opening (or the next poll, when the window is open).
+#[tauri::command]
+pub fn set_night_plan(store: State<'_, SettingsStore>, enabled: bool, overrun_minutes: Option<u64>) -> Result<(), String> {
+    store.update(|s| {
+        s.night_plan = enabled;
+        if let Some(minutes) = overrun_minutes {
+            s.night_overrun_minutes = minutes;
+        }
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::tests::{utc_at, Berlin2026};
+    use crate::schedule::ScheduleWindow;
+
+    const HOUR: f64 = 3600.0;
+    const GB: u64 = 1_000_000_000;
+
+    fn job(job_id: u64, saved_gb: f64, hours: f64) -> Candidate {
+        Candidate { job_id, priority: Priority::Normal, saved_bytes: (saved_gb * GB as f64) as u64, wall_secs: hours * HOUR }
+    }
+
+    // As open() works it out: from the moment it's noticed to the close
+    fn budget(window: &ScheduleWindow, now: chrono::DateTime<Utc>) -> f64 {
+        (window.close_in(&Berlin2026, now).unwrap() - now).num_seconds() as f64
+    }
+
+    #[test]
+    fn the_budget_is_what_is_left_of_the_window() {
+        let night = ScheduleWindow { start: "23:00".to_string(), end: "07:00".to_string() };
+        // 23:00 CEST is 21:00 UTC
+        assert_eq!(budget(&night, utc_at(2026, 7, 1, 21, 0)), 8.0 * HOUR);
+        // Noticed on a later poll, after midnight
+        assert_eq!(budget(&night, utc_at(2026, 7, 2, 0, 0)), 5.0 * HOUR);
+        // The clocks go back that night: an hour more to fill
+        assert_eq!(budget(&night, utc_at(2026, 10, 24, 21, 0)), 9.0 * HOUR);
+    }
+
+    #[test]
+    fn jobs_go_by_priority_then_savings_per_hour() {
+        let mut urgent = job(4, 0.1, 1.0);
+        urgent.priority = Priority::High;
+        let mut whenever = job(5, 50.0, 0.5);
+        whenever.priority = Priority::Low;
+        // 2 GB/h, 4 GB/h, 1 GB/h
+        let candidates = [job(1, 4.0, 2.0), job(2, 2.0, 0.5), job(3, 1.0, 1.0), urgent, whenever];
+        let packing = pack(&candidates, 8.0 * HOUR, 1, 0.0);
+        assert_eq!(packing.will_run, [4, 2, 1, 3, 5]);
+        assert!(packing.deferred.is_empty());
+        assert_eq!(packing.predicted_savings, (57.1 * GB as f64) as u64);
+        assert_eq!(packing.predicted_secs, 5.0 * HOUR);
+    }
+
+    #[test]
+    fn what_doesnt_fit_is_deferred_and_smaller_jobs_still_go() {
+        // The best rate doesn't fit; the packer doesn't stop at it
+        let candidates = [job(1, 30.0, 10.0), job(2, 6.0, 3.0), job(3, 5.0, 4.0), job(4, 1.0, 1.0)];
+        let packing = pack(&candidates, 8.0 * HOUR, 1, 0.0);
+        assert_eq!(packing.will_run, [2, 3, 4]);
+        assert_eq!(packing.deferred, [1]);
+        assert_eq!(packing.predicted_secs, 8.0 * HOUR);
+        assert_eq!(pack(&candidates, 0.0, 2, 0.0).deferred.len(), 4);
+    }
+
+    #[test]
+    fn jobs_take_the_lane_that_frees_up_first() {
+        let candidates = [job(1, 6.0, 3.0), job(2, 4.0, 2.0), job(3, 2.0, 1.0), job(4, 2.5, 5.0)];
+        // 1 and 2 start together; 3 follows 2; 4 needs 5 h from the first free lane (3 h in)
+        let packing = pack(&candidates, 6.0 * HOUR, 2, 0.0);
+        assert_eq!(packing.will_run, [1, 2, 3]);
+        assert_eq!(packing.deferred, [4]);
+        assert_eq!(packing.predicted_secs, 3.0 * HOUR);
+        assert_eq!(pack(&candidates, 8.0 * HOUR, 2, 0.0).predicted_secs, 8.0 * HOUR);
+        // No lanes is one lane
+        assert_eq!(pack(&candidates, 6.0 * HOUR, 0, 0.0), pack(&candidates, 6.0 * HOUR, 1, 0.0));
+    }
+
+    #[test]
+    fn a_job_may_overrun_by_the_tolerance_and_no_more() {
+        let overrun = DEFAULT_NIGHT_OVERRUN_MINUTES as f64 * 60.0;
+        assert!(fits(8.0 * HOUR, 8.0 * HOUR, 0.0));
+        assert!(fits(8.0 * HOUR + overrun, 8.0 * HOUR, overrun));
+        assert!(!fits(8.0 * HOUR + overrun + 1.0, 8.0 * HOUR, overrun));
+        // A negative tolerance is none
+        assert!(fits(8.0 * HOUR, 8.0 * HOUR, -600.0));
+
+        let late = [Candidate { wall_secs: 8.0 * HOUR + overrun, ..job(1, 1.0, 0.0) }];
+        assert_eq!(pack(&late, 8.0 * HOUR, 1, overrun).will_run, [1]);
+        assert_eq!(pack(&late, 8.0 * HOUR, 1, overrun - 1.0).deferred, [1]);
+    }
+}
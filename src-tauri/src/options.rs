@@ -0,0 +1,503 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::ops::RangeInclusive;
+use std::sync::OnceLock;
+
+use crate::AppHandle;
+use crate::gif;
+use crate::presets;
+use crate::quality::{QualityOptions, MAX_VIDEO_KBPS, MIN_VIDEO_KBPS};
+use crate::request::{
+    AudioCompressRequest, ImageCompressRequest, VideoCompressRequest, VideoOptions, AUDIO_BITRATE_RANGE, CRF_RANGE, EFFICIENCY_BPP_RANGE,
+    FLAC_LEVEL_RANGE, IMAGE_QUALITY_RANGE, MAX_AV_OFFSET_MS, MAX_DIMENSION, MAX_FPS,
+};
+use crate::resources::{ProcessOptions, MAX_THREADS};
+
+// Human-readable description of a job option, for settings screens and tooltips.
+#[derive(Serialize, Clone, Copy)]
+pub struct OptionInfo {
+    pub key: &'static str,
+    // "bool" | "number" | "string" | "object" | "array"
+    pub kind: &'static str,
+    pub description: &'static str,
+}
+
+// ==========================================
+// OPTION DESCRIPTORS
+// ==========================================
+// Everything a form needs to render an option without hardcoding it: type,
+// range or values, default, the presets that set it and the options it
+// can't go with. Each request struct has one table below, and `described!`
+// destructures the struct with every field the table names, so a field
+// added without a descriptor (or a descriptor for a field that's gone)
+// doesn't compile.
+//
+// Defaults come from what serde fills in for a request that leaves the
+// option out, and conflicts from the request validation itself: every pair
+// of options is set to a sample value and validated, and a pair that fails
+// where each alone passes is a conflict. Nothing here is written twice.
+
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct OptionDescriptor {
+    // "video" | "image" | "audio"
+    pub request: &'static str,
+    pub key: &'static str,
+    // Stable key for the UI's translations ("option.video.crf")
+    pub code: String,
+    // "bool" | "number" | "string" | "object" | "array"
+    pub kind: &'static str,
+    pub description: &'static str,
+    // What a request that leaves it out gets (null = not set)
+    pub default: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<&'static str>,
+    // Presets that set it to something other than the default
+    pub presets: Vec<String>,
+    // Options the validation refuses it together with
+    pub conflicts: Vec<&'static str>,
+}
+
+#[derive(Clone)]
+struct Spec {
+    kind: &'static str,
+    description: &'static str,
+    min: Option<f64>,
+    max: Option<f64>,
+    values: &'static [&'static str],
+    // Set for probing the conflicts, when the kind doesn't suggest one
+    sample: Option<Value>,
+}
+
+fn spec(kind: &'static str, description: &'static str) -> Spec {
+    Spec { kind, description, min: None, max: None, values: &[], sample: None }
+}
+
+fn flag(description: &'static str) -> Spec {
+    spec("bool", description)
+}
+
+fn number(description: &'static str) -> Spec {
+    spec("number", description)
+}
+
+fn text(description: &'static str) -> Spec {
+    spec("string", description)
+}
+
+fn object(description: &'static str) -> Spec {
+    spec("object", description)
+}
+
+fn list(description: &'static str) -> Spec {
+    spec("array", description)
+}
+
+impl Spec {
+    fn range(self, min: f64, max: f64) -> Self {
+        Spec { min: Some(min), max: Some(max), ..self }
+    }
+
+    fn within(self, range: RangeInclusive<u32>) -> Self {
+        self.range(*range.start() as f64, *range.end() as f64)
+    }
+
+    fn at_least(self, min: f64) -> Self {
+        Spec { min: Some(min), ..self }
+    }
+
+    fn values(self, values: &'static [&'static str]) -> Self {
+        Spec { values, ..self }
+    }
+
+    fn sample(self, sample: Value) -> Self {
+        Spec { sample: Some(sample), ..self }
+    }
+
+    // A valid non-default value, for the conflict probe
+    fn probe_value(&self, default: &Value) -> Option<Value> {
+        if let Some(sample) = &self.sample {
+            return Some(sample.clone());
+        }
+        match self.kind {
+            "bool" => Some(Value::Bool(!default.as_bool().unwrap_or(false))),
+            "number" => Some(json!(self.max.or(self.min).unwrap_or(1.0))),
+            "string" => self.values.iter().find(|v| default.as_str() != Some(v)).map(|v| json!(v)),
+            _ => None,
+        }
+    }
+}
+
+// `name: Struct { field => spec, ... } flatten { field => table } skip { field }`
+macro_rules! described {
+    ($name:ident: $ty:ident { $($field:ident => $spec:expr,)* } $(flatten { $($flat:ident => $table:ident),* })? $(skip { $($skip:ident),* })?) => {
+        fn $name() -> Vec<(&'static str, Spec)> {
+            let _ = |o: &$ty| {
+                let $ty { $($field: _,)* $($($flat: _,)*)? $($($skip: _,)*)? } = o;
+            };
+            #[allow(unused_mut)]
+            let mut table = vec![$((stringify!($field), $spec)),*];
+            $($(table.extend($table());)*)?
+            table
+        }
+    };
+}
+
+described! {
+    video_request: VideoCompressRequest {
+        create_dirs => flag("Make the output's folder (and the ones above it) when it doesn't exist, instead of failing the job."),
+        overwrite_policy => text("What happens when the output exists: \"overwrite\", \"fail\" or \"rename\" (name (1).ext, ...). Left out, queue jobs rename and direct commands overwrite.")
+            .values(&["overwrite", "fail", "rename"]),
+        work_dir => text("Folder the temporary output is written to before it's moved into place, in place of the work_dir setting."),
+        dry_run => flag("Run the routing, checks and encoder detection, then stop before ffmpeg starts: the result's dry_run has the exact commands, filter graph and warnings, and nothing is written."),
+    }
+    flatten { options => video_options }
+    skip { version, input, output, annotations }
+}
+
+described! {
+    video_options: VideoOptions {
+        auto_gpu => flag("Use the GPU encoder for mp4/mkv/mov/avi/flv/ts/m4v outputs: NVENC, Quick Sync, AMF or VideoToolbox, whichever works here (see get_hw_capabilities). Falls back to libx264."),
+        video_mode => text("\"reencode\" (default) or \"copy\": keep the video stream as-is; the audio still follows `audio`. Can't be combined with overlays, blurs or other picture changes.")
+            .values(&["reencode", "copy"]),
+        extract_incompatible_subs => flag("Save image-based (PGS) subtitles the output container can't hold as .sup files next to the output instead of dropping them."),
+        resumable => flag("Encode in 60-second parts so an interrupted job continues where it stopped after a restart. Costs a little efficiency: every part starts on a keyframe, so outputs are slightly larger."),
+        overlay_text => object("Burn text into the picture for review copies: { template, position, font_size, box }. The template can use {timecode}, {filename} and {frame}. Needs an ffmpeg build with drawtext.")
+            .sample(json!({ "template": "{timecode}" })),
+        blur_regions => list("Up to 8 rectangles to blur, e.g. an email address in a screen recording: [{ x, y, w, h, strength }] in source pixels.")
+            .sample(json!([{ "x": 0, "y": 0, "w": 64, "h": 64 }])),
+        av_offset_ms => number("Shift the audio by this many milliseconds to fix a constant A/V offset. Positive delays the audio; if the audio is 300 ms late, use -300.")
+            .range(-MAX_AV_OFFSET_MS as f64, MAX_AV_OFFSET_MS as f64),
+        detect_av_offset => flag("Experimental: estimate the A/V offset from the first minute (sound onsets vs. scene cuts). Only applied when the estimate is confident; the result always reports it."),
+        normalize_audio => flag("Normalize the audio's loudness to -16 LUFS (EBU R128, peaks at -1.5 dBTP). The audio is measured in a first pass, then adjusted evenly; if measuring fails a single pass adjusts as it goes and the result warns. Can't be combined with copy_only, resumable or keep_all_streams."),
+        audio => text("\"auto\" (default) copies audio the output container holds in a lossy codec at a sensible rate (up to 192 kbps stereo, 576 kbps 5.1) and re-encodes the rest with the container's codec at 128 kbps stereo / 384 kbps 5.1, keeping the channels. \"copy\" always copies, \"remove\" drops the audio (silent screen recordings), and { \"transcode\": { codec, bitrate_kbps, channels } } re-encodes with that codec (\"aac\", \"opus\", ...), rate and, when set, a downmix to that many channels. Loudness, A/V offset, playback_target, resumable and salvage always re-encode.")
+            .values(&["auto", "copy", "remove"])
+            .sample(json!({ "transcode": { "bitrate_kbps": 96 } })),
+        deinterlace => flag("Deinterlace frames that are interlaced (bwdif). Progressive frames pass through untouched."),
+        detect_telecine => flag("For 29.97 fps sources such as DVD rips: sample the video and, if it's telecined film, restore the original 23.976 fps (inverse telecine) instead of deinterlacing. Skipped for progressive sources."),
+        limit_duration_secs => number("Encode only the first N seconds with all other settings applied, to check a setup end-to-end. The output gets a _preview suffix and is marked partial.")
+            .sample(json!(10.0)),
+        start_secs => number("Start the output this many seconds into the source. The seek is fast (before decoding), so a cut from a long recording only reads the part it keeps.")
+            .at_least(0.0)
+            .sample(json!(5.0)),
+        end_secs => number("Stop at this point of the source, in seconds. Must be after start_secs; past the end of the input the cut runs to the end.")
+            .sample(json!(60.0)),
+        duration_policy => text("For sources whose audio and video lengths differ by more than 2%: \"video\" (default) keeps the video's length and trims the audio, \"shortest\" cuts both to the shorter, \"longest\" pads the shorter with silence or its last frame. The result warns with both lengths.")
+            .values(&["video", "shortest", "longest"]),
+        copy_only => flag("Cut without re-encoding: every stream is copied. Cuts snap to the keyframe at or before start_secs, so the output may begin slightly early."),
+        mode => text("\"compress\" (default) or \"remux\": change only the container, copying every stream (e.g. .mkv to .mp4 for iMovie). Fails when a stream doesn't fit the output container; progress has no percentage, copies take seconds.")
+            .values(&["compress", "remux"]),
+        allow_partial_transcode => flag("With mode remux: when the audio doesn't fit the output container (DTS in mp4), re-encode just the audio and still copy the video."),
+        io_throttle_mbps => number("Read the input, and copy the finished file to its destination, no faster than this many Mbit/s, so working against a NAS doesn't saturate the network. Volumes can have a default in settings.")
+            .at_least(1.0),
+        crf => number("Quality for the video encoder, 0-51 (0-63 for VP9 and SVT-AV1); lower is better and bigger. Hardware encoders get it in their own form (-cq on NVIDIA, -global_quality on Quick Sync).")
+            .within(CRF_RANGE),
+        max_width => number("Downscale sources wider than this many pixels, keeping the aspect ratio. Combined with max_height the picture is fitted inside both. Never upscales.")
+            .range(16.0, MAX_DIMENSION as f64),
+        max_height => number("Downscale sources taller than this many pixels, keeping the aspect ratio. Smaller sources are never upscaled.")
+            .range(16.0, MAX_DIMENSION as f64),
+        max_long_edge => number("Downscale so the longer side is at most this many pixels, whichever way the picture is turned: 1920 gives 1920x1080 for landscape and 1080x1920 for portrait phone video (rotation metadata included). Combines with max_width / max_height, the tightest wins.")
+            .range(16.0, MAX_DIMENSION as f64),
+        max_fps => number("Cap the frame rate, e.g. 30 for a 60 fps screen recording. Slower sources keep their rate. It also caps gif_fps.")
+            .range(1.0, MAX_FPS),
+        fit_canvas => object("Fit the picture into a fixed canvas, e.g. landscape footage into a 1080x1920 story: { width, height, pad_style, color }. Scaled to fit (up or down), the rest is bars. pad_style \"color\" (default) makes plain bars in color (black unless set), \"blur\" fills them with a blurred, enlarged copy of the picture. Burned-in text is drawn on the canvas.")
+            .sample(json!({ "width": 1080, "height": 1920, "pad_style": "blur" })),
+        playback_target => text("Encode for a kind of player and check the output against it afterwards: \"web\", \"tv\", \"mobile\" or \"office\" (PowerPoint / Outlook: mp4, H.264 Main at most 4.1, yuv420p, AAC-LC at most 48 kHz, at most 1080p, index up front). What a remux can fix is fixed; anything else fails the job, naming the rule it breaks. The office_compatible preset sets it.")
+            .values(&["web", "tv", "mobile", "office"]),
+        compute_quality_score => flag("After the encode, score the output against the source with VMAF (SSIM when this ffmpeg has no libvmaf) and return the mean in the job's stats, for tuning quality settings with numbers. Takes about as long as the encode again, reports its own progress and can be cancelled on its own (cancel_quality_score). Scaled outputs are scaled back up to compare. Skipped for GIF output."),
+        preserve_vfr => flag("Keep variable frame rate sources (screen recordings with long static stretches) as they are instead of filling the gaps with duplicate frames. The output's frame count is checked against the source's. mp4/m4v/mov/mkv/webm only; can't be combined with max_fps or detect_telecine."),
+        surgical => flag("Keep every stream, tag and chapter and re-encode only the targeted codecs. Options that would change anything else are rejected, and the output is checked stream by stream afterwards."),
+        preserve_dynamic_hdr => flag("Keep Dolby Vision / HDR10+ metadata when re-encoding (x265, mp4/mkv/mov). Without it such sources are converted to plain HDR10 and the result says so."),
+        tonemap_to_sdr => flag("Tonemap HDR10 / HLG sources down to SDR (BT.709) even when the output codec (HEVC, AV1) could keep them HDR. H.264 and the other 8-bit outputs are always tonemapped."),
+        single_frame_as_image => flag("When the input turns out to be a single frame, write it as a PNG next to the output instead of a one-frame video."),
+        skip_if_larger => flag("Delete the output when it comes out larger than the input; the result reports skipped: true and the original is left as it is."),
+        skip_optimized => flag("Don't re-encode sources that are already well compressed (a low bits-per-pixel rate for their resolution) or in a newer codec than the output's, e.g. lean HEVC going to H.264. The job succeeds without writing anything and the result carries already_optimized. Without it such sources are encoded anyway with an efficiency-advisory event and a warning. Not checked when a size or bitrate target, downscaling, filters or a playback_target is set."),
+        efficiency_threshold_bpp => number("Bits per pixel (video bitrate / (width x height x fps), in the output codec's terms) under which a source counts as already well compressed, instead of the resolution tier's default (0.10 at 480p down to 0.035 above 1440p).")
+            .range(*EFFICIENCY_BPP_RANGE.start(), *EFFICIENCY_BPP_RANGE.end()),
+        codec => text("h264 (default), hevc or av1 for mp4/mkv/mov and the other H.264 containers (av1 also in webm). With auto_gpu a working hardware encoder is used, else libx265 / libsvtav1.")
+            .values(&["h264", "hevc", "av1"]),
+        web_optimized => flag("Make the output start playing in a browser before it's fully downloaded (index moved to the front), and decode on older devices: 4:2:2 / 4:4:4 sources become 4:2:0, H.264 is capped at High@4.1. On by default for mp4/m4v/mov; set false to keep the source's chroma and the encoder's own profile.")
+            .sample(json!(false)),
+        encoder_preference => text("auto (default: auto_gpu's order), cpu, nvenc, videotoolbox, amf or qsv. Anything but auto uses only that encoder, and the job fails with EncoderNotAvailable when it doesn't work on this machine instead of falling back to the CPU.")
+            .values(&["auto", "cpu", "nvenc", "videotoolbox", "amf", "qsv"]),
+        gif_fps => number("Frame rate of GIF output (1-50, default 15). Lower makes smaller files.")
+            .range(1.0, gif::MAX_FPS),
+        gif_width => number("Width of GIF output in pixels (default 480); narrower sources keep their width.")
+            .range(16.0, MAX_DIMENSION as f64),
+        upload => flag("Upload the finished output to the S3-compatible destination set up in settings. A failed upload doesn't fail the job; it's recorded in history and can be retried."),
+        metadata => text("\"preserve\" (default) keeps the source's tags (creation date, GPS, camera); \"strip\" drops them and the chapters. Rotation is kept either way, so portrait phone video stays portrait.")
+            .values(&["preserve", "strip"]),
+        keep_all_streams => flag("Keep every audio track (commentary, other languages) instead of only the default one."),
+        include_sidecar_subs => text("Subtitle files next to the input (clip.srt, clip.en.srt, clip.ass, clip.vtt for clip.mp4) added as selectable tracks: \"auto\" (default) takes every matching file the output container can carry, \"none\" none, or a list of file names in the input's folder. Converted per container like embedded subtitles, with the language from the name (.en, .pt-BR); Windows-1252/1250 files are converted to UTF-8 first. Not for surgical or resumable jobs.")
+            .values(&["auto", "none"])
+            .sample(json!(["clip.srt"])),
+        salvage => flag("For damaged files: skip what can't be decoded and keep the rest. The result lists the gaps instead of failing the job."),
+        force => flag("Start even when the disk-space check says the output won't fit."),
+        filters => object("A chain of filter steps of its own ({ inputs, steps }, see get_filter_spec_schema), applied after the app's filters.")
+            .sample(json!({ "steps": [{ "filter": "scale", "width": 1280, "height": -2 }] })),
+        source_fixups => flag("When the source is a recording from a known screen-capture tool, apply that tool's fixups (constant frame rate, dropped timecode track, ...)."),
+        fit_size_mb => number("Hard size cap in MB: the encode steps down quality and then resolution until the output fits. H.264 / HEVC plain re-encodes only; leave crf, quality and bitrate settings out.")
+            .at_least(0.0)
+            .sample(json!(8.0)),
+        deterministic => flag("Byte-identical output for identical input and options, for content-addressed asset caches: tags dropped, a fixed creation time, bitexact muxing and a single thread. The result carries a settings_hash to key on. Can't be combined with auto_gpu, surgical, threads other than 1, or GIF output."),
+    }
+    flatten { rate => rate_options, process => process_options }
+}
+
+described! {
+    rate_options: QualityOptions {
+        quality => text("\"low\", \"medium\" or \"high\", or a CRF number (0-51, up to 63 for WebM). Mapped onto each encoder's own quality setting. Replaces crf.")
+            .values(&["low", "medium", "high"]),
+        target_bitrate_kbps => number("Average video bitrate in kbit/s (50-200000). Wins over quality when both are set.")
+            .range(MIN_VIDEO_KBPS as f64, MAX_VIDEO_KBPS as f64),
+        max_filesize_mb => number("Keep the output under this many MB: the video bitrate is worked out from the duration, leaving room for the audio.")
+            .at_least(0.0)
+            .sample(json!(8.0)),
+        target_size_mb => number("Aim for this output size, e.g. 8 for Discord or 25 for email. MP4/MKV (x264) and WebM (VP9) get a two-pass encode; the GPU encoder gets one capped pass. Refused when the size leaves less than 100 kbps for the video.")
+            .at_least(0.0)
+            .sample(json!(8.0)),
+    }
+}
+
+described! {
+    process_options: ProcessOptions {
+        threads => number("Encoder threads for the job's ffmpeg runs; left out, ffmpeg uses one per core.")
+            .range(1.0, MAX_THREADS as f64),
+        priority => text("OS priority of the job's ffmpeg runs: \"normal\" (default), \"low\" or \"background\", so a long batch doesn't slow the machine down.")
+            .values(&["normal", "low", "background"]),
+    }
+}
+
+described! {
+    image_request: ImageCompressRequest {
+        create_dirs => flag("Make the output's folder (and the ones above it) when it doesn't exist, instead of failing the job."),
+        width => number("Target width in pixels; with only one side set the other keeps the aspect ratio.")
+            .range(1.0, MAX_DIMENSION as f64),
+        height => number("Target height in pixels; with only one side set the other keeps the aspect ratio.")
+            .range(1.0, MAX_DIMENSION as f64),
+        quality => number("1-100, higher is better, for JPEG, WebP and AVIF output (100 is lossless WebP). PNG is lossless: any quality asks for the smallest file.")
+            .within(IMAGE_QUALITY_RANGE),
+        skip_if_larger => flag("Delete the output when it comes out larger than the input; the result reports skipped: true and the original is left as it is."),
+        metadata => text("\"preserve\" (default) keeps the source's EXIF tags; \"strip\" drops them (GPS included).")
+            .values(&["preserve", "strip"]),
+        dry_run => flag("Stop before ffmpeg starts and return its command in the result's dry_run; nothing is written."),
+    }
+    flatten { process => process_options }
+    skip { version, input, output, annotations }
+}
+
+described! {
+    audio_request: AudioCompressRequest {
+        create_dirs => flag("Make the output's folder (and the ones above it) when it doesn't exist, instead of failing the job."),
+        bitrate_kbps => number("Bitrate in kbit/s for lossy output; ignored for FLAC and WAV. Wins over quality when both are set.")
+            .within(AUDIO_BITRATE_RANGE),
+        quality => text("VBR level instead of a fixed bitrate: \"low\", \"medium\" or \"high\".")
+            .values(&["low", "medium", "high"]),
+        compression_level => number("FLAC only: 0 (fastest) to 12 (smallest), 8 when left out. Every level is lossless.")
+            .within(FLAC_LEVEL_RANGE),
+        keep_cover => flag("Keep the embedded cover art where the output format can hold it."),
+    }
+    flatten { process => process_options }
+    skip { version, input, output, annotations }
+}
+
+type Table = fn() -> Vec<(&'static str, Spec)>;
+
+// Each request kind with its table and a minimal request to probe it with
+const REQUESTS: &[(&str, Table, &str, &str)] = &[
+    ("video", video_request, "in.mov", "out.mp4"),
+    ("image", image_request, "in.png", "out.jpg"),
+    ("audio", audio_request, "in.wav", "out.mp3"),
+];
+
+// Whether a `kind` request with these options passes validation; None when
+// it doesn't even deserialize.
+fn validates(kind: &str, input: &str, output: &str, set: &[(&str, &Value)]) -> Option<bool> {
+    let mut request = json!({ "input": input, "output": output });
+    for (key, value) in set {
+        request[*key] = (*value).clone();
+    }
+    Some(match kind {
+        "video" => serde_json::from_value::<VideoCompressRequest>(request).ok()?.validate().is_ok(),
+        "image" => serde_json::from_value::<ImageCompressRequest>(request).ok()?.validate().is_ok(),
+        _ => serde_json::from_value::<AudioCompressRequest>(request).ok()?.validate().is_ok(),
+    })
+}
+
+// What a request that names only input and output deserializes to
+fn defaults(kind: &str, input: &str, output: &str) -> Value {
+    let request = json!({ "input": input, "output": output });
+    let value = match kind {
+        "video" => serde_json::from_value::<VideoCompressRequest>(request).map(serde_json::to_value),
+        "image" => serde_json::from_value::<ImageCompressRequest>(request).map(serde_json::to_value),
+        _ => serde_json::from_value::<AudioCompressRequest>(request).map(serde_json::to_value),
+    };
+    value.ok().and_then(Result::ok).unwrap_or_default()
+}
+
+fn describe(kind: &'static str, table: Vec<(&'static str, Spec)>, input: &str, output: &str) -> Vec<OptionDescriptor> {
+    let defaults = defaults(kind, input, output);
+    let default_of = |key: &str| defaults.get(key).cloned().unwrap_or(Value::Null);
+    // Only options that pass on their own can conflict with anything
+    let samples: Vec<Option<Value>> = table
+        .iter()
+        .map(|(key, spec)| spec.probe_value(&default_of(key)).filter(|v| validates(kind, input, output, &[(key, v)]) == Some(true)))
+        .collect();
+    let mut conflicts: Vec<Vec<&'static str>> = vec![vec![]; table.len()];
+    for i in 0..table.len() {
+        for j in i + 1..table.len() {
+            let (Some(a), Some(b)) = (&samples[i], &samples[j]) else { continue };
+            if validates(kind, input, output, &[(table[i].0, a), (table[j].0, b)]) == Some(false) {
+                conflicts[i].push(table[j].0);
+                conflicts[j].push(table[i].0);
+            }
+        }
+    }
+    table
+        .into_iter()
+        .zip(conflicts)
+        .map(|((key, spec), conflicts)| OptionDescriptor {
+            request: kind,
+            key,
+            code: format!("option.{}.{}", kind, key),
+            kind: spec.kind,
+            description: spec.description,
+            default: default_of(key),
+            min: spec.min,
+            max: spec.max,
+            values: spec.values.to_vec(),
+            presets: vec![],
+            conflicts,
+        })
+        .collect()
+}
+
+// The probe validates a thousand-odd requests, so it runs once
+fn descriptors() -> &'static [OptionDescriptor] {
+    static DESCRIPTORS: OnceLock<Vec<OptionDescriptor>> = OnceLock::new();
+    DESCRIPTORS.get_or_init(|| REQUESTS.iter().flat_map(|(kind, table, input, output)| describe(kind, table(), input, output)).collect())
+}
+
+// ==========================================
+// COMMAND: DESCRIBE OPTIONS
+// ==========================================
+// Presets are video options, and the user's own count too.
+#[tauri::command]
+pub fn describe_options(app: AppHandle) -> Vec<OptionDescriptor> {
+    let defaults = serde_json::to_value(VideoOptions::default()).unwrap_or_default();
+    let presets: Vec<(String, Value)> = presets::all(&app)
+        .into_iter()
+        .map(|p| (p.name, serde_json::to_value(p.options).unwrap_or_default()))
+        .collect();
+    descriptors()
+        .iter()
+        .cloned()
+        .map(|mut d| {
+            if d.request == "video" {
+                d.presets = presets
+                    .iter()
+                    .filter(|(_, options)| options.get(d.key).is_some_and(|v| Some(v) != defaults.get(d.key)))
+                    .map(|(name, _)| name.clone())
+                    .collect();
+            }
+            d
+        })
+        .collect()
+}
+
+// JSON Schema (draft 7) of what describe_options returns.
+#[tauri::command]
+pub fn get_option_schema() -> Value {
+    serde_json::to_value(schemars::schema_for!(Vec<OptionDescriptor>)).unwrap_or_default()
+}
+
+// ==========================================
+// COMMAND: LIST OPTIONS
+// ==========================================
+// The older, video-only shape of describe_options.
+#[tauri::command]
+pub fn list_options() -> Vec<OptionInfo> {
+    video_options()
+        .into_iter()
+        .map(|(key, spec)| OptionInfo { key, kind: spec.kind, description: spec.description })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::Harness;
+    use std::collections::BTreeSet;
+
+    fn described(kind: &str) -> BTreeSet<&'static str> {
+        descriptors().iter().filter(|d| d.request == kind).map(|d| d.key).collect()
+    }
+
+    // A field the table doesn't name fails to compile; this catches the
+    // rest: a key serde spells differently from the field, or a flattened
+    // struct whose own fields drifted from its table
+    #[test]
+    fn every_key_a_request_takes_has_exactly_one_descriptor() {
+        for (kind, table, input, output) in REQUESTS {
+            let mut taken: BTreeSet<String> = defaults(kind, input, output).as_object().unwrap().keys().cloned().collect();
+            for not_an_option in ["version", "input", "output"] {
+                assert!(taken.remove(not_an_option), "{}: {}", kind, not_an_option);
+            }
+            let described: BTreeSet<String> = described(kind).into_iter().map(String::from).collect();
+            assert_eq!(taken, described, "{} request", kind);
+            assert_eq!(table().len(), described.len(), "{}: a key is described twice", kind);
+        }
+    }
+
+    #[test]
+    fn descriptors_carry_the_requests_own_defaults_and_ranges() {
+        let find = |kind: &str, key: &str| descriptors().iter().find(|d| d.request == kind && d.key == key).unwrap();
+        let crf = find("video", "crf");
+        assert_eq!((crf.code.as_str(), crf.kind), ("option.video.crf", "number"));
+        assert_eq!((crf.min, crf.max), (Some(*CRF_RANGE.start() as f64), Some(*CRF_RANGE.end() as f64)));
+        assert_eq!(find("video", "video_mode").default, "reencode");
+        assert_eq!(find("video", "video_mode").values, ["reencode", "copy"]);
+        assert_eq!(find("audio", "keep_cover").default, json!(true));
+        assert_eq!(find("image", "quality").default, Value::Null);
+        // Flattened tables are each request's own
+        assert_eq!(find("image", "threads").code, "option.image.threads");
+        assert!(descriptors().iter().any(|d| d.request == "audio" && d.key == "priority"));
+    }
+
+    #[test]
+    fn conflicts_come_from_validating_pairs_and_go_both_ways() {
+        let conflicts = |key: &str| descriptors().iter().find(|d| d.request == "video" && d.key == key).unwrap().conflicts.clone();
+        assert!(conflicts("video_mode").contains(&"overlay_text"));
+        assert!(conflicts("overlay_text").contains(&"video_mode"));
+        assert!(conflicts("preserve_vfr").contains(&"max_fps"));
+        assert!(conflicts("resumable").contains(&"target_size_mb"));
+        for d in descriptors() {
+            for other in &d.conflicts {
+                let back = descriptors().iter().find(|o| o.request == d.request && o.key == *other).unwrap();
+                assert!(back.conflicts.contains(&d.key), "{} -> {} but not back", d.key, other);
+            }
+        }
+        // Options that never clash
+        assert!(conflicts("create_dirs").is_empty());
+    }
+
+    #[test]
+    fn presets_are_listed_on_the_options_they_change() {
+        let h = Harness::new("describe-options", "{}");
+        let all = describe_options(h.handle().clone());
+        let presets = |key: &str| all.iter().find(|d| d.request == "video" && d.key == key).unwrap().presets.clone();
+        assert!(presets("playback_target").contains(&"office_compatible".to_string()));
+        assert!(presets("keep_all_streams").iter().any(|p| p != "office_compatible"));
+        assert!(presets("dry_run").is_empty());
+        assert!(all.iter().filter(|d| d.request != "video").all(|d| d.presets.is_empty()));
+
+        // The older list is the video options, unchanged
+        let listed: Vec<&str> = list_options().iter().map(|o| o.key).collect();
+        assert_eq!(listed, video_options().iter().map(|(k, _)| *k).collect::<Vec<_>>());
+        assert!(get_option_schema()["definitions"]["OptionDescriptor"]["properties"]["conflicts"].is_object());
+    }
+}
@@ -0,0 +1,987 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::async_runtime::JoinHandle;
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::cancel;
+use crate::macos;
+use crate::paths;
+use crate::queue;
+use crate::settings::SettingsStore;
+use crate::throttle;
+use crate::volumes;
+
+// Give up renaming after this many taken names in a row
+pub const MAX_CANDIDATES: u32 = 1000;
+// How often a removable destination is checked for still being there
+const REMOVAL_POLL: Duration = Duration::from_secs(1);
+
+// Error prefix of jobs whose destination drive went away; the queue gives
+// them their own status so the UI can offer `redirect_output`.
+pub const DESTINATION_REMOVED: &str = "DestinationRemoved";
+// Error prefix of jobs that ran out of room where their temp output is
+// written (before they start, or while ffmpeg writes)
+pub const WORK_VOLUME_FULL: &str = "WorkVolumeFull";
+// Error prefix of jobs whose output wouldn't fit where it ends up, when
+// that's another volume than the temp output's
+pub const OUTPUT_VOLUME_FULL: &str = "OutputVolumeFull";
+// What ffmpeg passes on from the OS when a write hits a full disk
+const NO_SPACE_PATTERNS: &[&str] = &["No space left on device", "not enough space on the disk", "Disk quota exceeded"];
+// Free space wanted on the work volume, relative to the estimated output
+const SPACE_MARGIN: f64 = 1.2;
+
+// What to do when the output already exists. Whatever the policy, an output
+// that is the input is refused (paths::ensure_not_input): that always
+// destroys the source.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverwritePolicy {
+    Overwrite,
+    // Error out, before encoding and again at the final move
+    Fail,
+    // `name (1).ext`, `name (2).ext`, ... until one is free
+    Rename,
+}
+
+// ==========================================
+// OUTPUT RESERVATIONS (managed state)
+// ==========================================
+// Several queue jobs writing into one folder used to check "does clip.mp4
+// exist?" on their own and could both pick the same free name. Now a job
+// claims its output (and the temp file ffmpeg writes to) here under one lock
+// before anything runs, and holds it until the job ends however it ends.
+// The final move into place never replaces a file, so a name taken by
+// something outside the app in the meantime moves on to the next candidate.
+#[derive(Default)]
+pub struct OutputReservations {
+    // Resolved paths, case-folded on case-insensitive volumes
+    paths: Mutex<HashSet<String>>,
+}
+
+fn key(path: &Path) -> String {
+    let resolved = paths::resolve(path).unwrap_or_else(|| path.to_path_buf());
+    let s = resolved.to_string_lossy().to_string();
+    if paths::is_case_insensitive(&resolved) { s.to_lowercase() } else { s }
+}
+
+// `clip.mp4`, `clip (1).mp4`, `clip (2).mp4`, ...
+pub fn candidate(requested: &Path, n: u32) -> PathBuf {
+    if n == 0 {
+        return requested.to_path_buf();
+    }
+    let stem = requested.file_stem().unwrap_or_default();
+    requested.with_file_name(paths::file_name("", stem, &format!(" ({})", n), requested.extension()))
+}
+
+// A side file named after the output (`clip.3.sup` for `clip.mp4`), under
+// the name the output ended up with. None when it isn't named after it.
+pub fn follow(side: &Path, reserved: &Path, committed: &Path) -> Option<PathBuf> {
+    let name = side.file_name()?.to_str()?;
+    let rest = name.strip_prefix(reserved.file_stem()?.to_str()?)?;
+    if !rest.starts_with('.') {
+        return None;
+    }
+    Some(side.with_file_name(format!("{}{}", committed.file_stem()?.to_str()?, rest)))
+}
+
+// Where ffmpeg writes before the move. Derived from the final name only, so
+// a resumable job restarted after a crash finds its parts again.
+fn staged_path(dest: &Path) -> PathBuf {
+    dest.with_file_name(paths::file_name(".", dest.file_stem().unwrap_or_default(), ".partial", dest.extension()))
+}
+
+// In a work folder chosen by the user: named after the final path, for the
+// same reason as staged_path.
+fn work_dir_staged_path(dir: &Path, dest: &Path) -> PathBuf {
+    let hash = xxhash_rust::xxh3::xxh3_64(key(dest).as_bytes());
+    dir.join(paths::file_name("", OsStr::new(&format!("{:016x}", hash)), ".partial", dest.extension()))
+}
+
+// The work_dir setting (see set_work_dir); None = next to each output.
+pub fn configured_work_dir(app: &AppHandle) -> Option<PathBuf> {
+    app.try_state::<SettingsStore>().and_then(|s| s.get().work_dir).map(PathBuf::from)
+}
+
+// Removable destinations are staged on the local disk instead, so a drive
+// pulled at the last moment doesn't take a finished encode with it.
+fn local_staged_path(app: &AppHandle, dest: &Path) -> Option<PathBuf> {
+    let dir = app.path().app_data_dir().ok()?.join("staging");
+    fs::create_dir_all(&dir).ok()?;
+    let hash = xxhash_rust::xxh3::xxh3_64(key(dest).as_bytes());
+    let ext = dest.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    Some(dir.join(format!("{:016x}{}", hash, ext)))
+}
+
+// rename(), or copy + delete across volumes.
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+// One job's claim on its output. Dropping it releases both names and
+// removes a leftover temp file (failed or cancelled jobs).
+pub struct Reservation {
+    app: AppHandle,
+    requested: PathBuf,
+    // Candidate number of `path`
+    n: u32,
+    policy: OverwritePolicy,
+    pub path: PathBuf,
+    pub staged: PathBuf,
+    // Mount point of a removable destination
+    removable: Option<PathBuf>,
+    // Stranded finished output kept for redirect_output
+    keep_staged: bool,
+    watcher: Option<JoinHandle<()>>,
+    // Cap on copying the temp file over to the destination (see throttle.rs)
+    write_mbps: Option<u32>,
+}
+
+impl Reservation {
+    fn keys(&self) -> [String; 2] {
+        [key(&self.path), key(&self.staged)]
+    }
+
+    pub fn path_str(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+
+    pub fn staged_str(&self) -> String {
+        self.staged.to_string_lossy().to_string()
+    }
+
+    // Only matters when the temp output is on another volume, so the last
+    // step is a copy.
+    pub fn throttle_writes(&mut self, mbps: Option<u32>) {
+        self.write_mbps = mbps;
+    }
+
+    // The drive was unplugged (its mount point or our folder on it is gone).
+    pub fn destination_gone(&self) -> bool {
+        let Some(mount) = &self.removable else { return false };
+        !mount.exists() || self.path.parent().is_some_and(|dir| !dir.exists())
+    }
+
+    // The temp output is next to the final one, so the move is a rename.
+    fn staged_beside(&self) -> bool {
+        self.staged.parent() == self.path.parent()
+    }
+
+    fn work_mount(&self) -> String {
+        let dir = self.staged.parent().unwrap_or(Path::new("")).to_string_lossy().to_string();
+        volumes::volume_of(&dir).map_or(dir, |v| v.mount_point)
+    }
+
+    // Rewrites a job error as DestinationRemoved or WorkVolumeFull when
+    // that's what caused it: the write errors (or the cancel from the
+    // watcher) say little on their own.
+    pub fn classify(&self, error: String) -> String {
+        if self.destination_gone() {
+            let mount = self.removable.as_deref().unwrap_or(Path::new("")).display();
+            return format!("{}: the drive at {} was disconnected ({})", DESTINATION_REMOVED, mount, error);
+        }
+        if !error.starts_with(WORK_VOLUME_FULL) && NO_SPACE_PATTERNS.iter().any(|p| error.contains(p)) {
+            return format!(
+                "{}: {} ran out of space while the output was being written ({}). Free some up, or point the work folder at a bigger drive (set_work_dir)",
+                WORK_VOLUME_FULL,
+                self.work_mount(),
+                error
+            );
+        }
+        error
+    }
+
+    // Preflight before anything runs: room for `estimated_bytes` (plus a
+    // margin) where the temp output goes, and again where it's moved to
+    // when that's another volume (the move is a copy then). Unknown free
+    // space passes; `force` skips the check.
+    pub fn check_space(&self, estimated_bytes: u64, force: bool) -> Result<(), String> {
+        if force {
+            println!("💾 Skipping the disk-space check for {}", self.path.display());
+            return Ok(());
+        }
+        let work = space_check(self.staged.parent().unwrap_or(Path::new("")), estimated_bytes);
+        if !work.fits {
+            return Err(work_volume_full(&self.work_mount(), &work));
+        }
+        if self.staged_beside() {
+            return Ok(());
+        }
+        let dest = space_check(self.path.parent().unwrap_or(Path::new("")), estimated_bytes);
+        if dest.fits || dest.mount_point == work.mount_point {
+            return Ok(());
+        }
+        Err(output_volume_full(&dest))
+    }
+
+    // Cancels the running job as soon as the destination disappears, rather
+    // than letting ffmpeg grind through write errors.
+    fn watch_destination(&mut self) {
+        let (Some(mount), Some(token)) = (self.removable.clone(), cancel::current()) else { return };
+        let dir = self.path.parent().map(Path::to_path_buf).unwrap_or_else(|| mount.clone());
+        self.watcher = Some(tauri::async_runtime::spawn(async move {
+            while !token.is_cancelled() {
+                tokio::time::sleep(REMOVAL_POLL).await;
+                if !mount.exists() || !dir.exists() {
+                    println!("⏏️ {} disappeared, stopping the job", mount.display());
+                    token.cancel();
+                }
+            }
+        }));
+    }
+
+    // Moves the finished temp file into place and returns the final path,
+    // which differs from the reserved one if another writer got there first.
+    // A finished output whose drive vanished is kept for redirect_output.
+    pub fn commit(&mut self) -> Result<PathBuf, String> {
+        let result = self.move_into_place();
+        if let Err(e) = result {
+            if !self.destination_gone() {
+                return Err(e);
+            }
+            if let Some(job_id) = queue::current_job_id() {
+                self.keep_staged = true;
+                queue::strand_output(&self.app, job_id, self.staged.clone());
+            }
+            return Err(self.classify(e));
+        }
+        if let Ok(path) = &result {
+            if macos::clear_quarantine_enabled(&self.app) {
+                macos::clear_quarantine(path);
+            }
+            queue::track_output(&self.app, path);
+        }
+        result
+    }
+
+    fn move_into_place(&mut self) -> Result<PathBuf, String> {
+        // Staged elsewhere (a work folder, the local disk for a removable
+        // drive): next to the destination first, so the final step is still a
+        // same-volume rename. On the same volume that's a rename too;
+        // across devices it's a copy.
+        let mut renamed = false;
+        let mut source = if self.staged_beside() {
+            self.staged.clone()
+        } else {
+            let near = staged_path(&self.path);
+            renamed = fs::rename(&self.staged, &near).is_ok();
+            if !renamed {
+                throttle::copy_limited(&self.staged, &near, self.write_mbps).map_err(|e| {
+                    let _ = fs::remove_file(&near);
+                    format!("Could not copy the output next to {}: {}", self.path.display(), e)
+                })?;
+            }
+            near
+        };
+        let result = self.rename_into_place(&mut source);
+        match (&result, source == self.staged) {
+            (_, true) => {}
+            (Ok(_), false) => {
+                let _ = fs::remove_file(&self.staged);
+            }
+            // Back where it was, for a stranded output (see commit)
+            (Err(_), false) if renamed => {
+                let _ = fs::rename(&source, &self.staged);
+            }
+            (Err(_), false) => {
+                let _ = fs::remove_file(&source);
+            }
+        }
+        result
+    }
+
+    fn rename_into_place(&mut self, source: &mut PathBuf) -> Result<PathBuf, String> {
+        if self.policy == OverwritePolicy::Overwrite {
+            // An explicitly chosen output: replacing it is what was asked for
+            fs::rename(&*source, &self.path).map_err(|e| format!("Could not move the output into place: {}", e))?;
+            return Ok(self.path.clone());
+        }
+        let Some(reservations) = self.app.try_state::<OutputReservations>() else {
+            return Err("Output reservations aren't available".to_string());
+        };
+        let beside = *source == self.staged;
+        let placed = place(&reservations.paths, &self.requested, self.policy, &mut self.n, &mut self.path, source);
+        if beside {
+            self.staged = source.clone();
+        }
+        placed
+    }
+}
+
+// The no-replace move of `source` to `path`, going on to the next free
+// candidate while something outside the app keeps getting there first.
+fn place(
+    taken: &Mutex<HashSet<String>>,
+    requested: &Path,
+    policy: OverwritePolicy,
+    n: &mut u32,
+    path: &mut PathBuf,
+    source: &mut PathBuf,
+) -> Result<PathBuf, String> {
+    loop {
+        match rename_noreplace(source, path) {
+            Ok(()) => return Ok(path.clone()),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && policy == OverwritePolicy::Fail => {
+                return Err(format!("{} appeared while encoding and overwrite_policy is \"fail\"; the result was discarded", path.display()));
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                println!("🔀 {} appeared while encoding, trying the next name", path.display());
+                advance(&mut taken.lock().unwrap(), requested, n, path, source)?;
+            }
+            Err(e) => return Err(format!("Could not move the output into place: {}", e)),
+        }
+    }
+}
+
+// A finished file from elsewhere (a stranded output, see redirect_output)
+// moved to `requested` the way a job's own output is: the name is claimed
+// under the reservation lock and, unless the policy is Overwrite, nothing
+// that's there is replaced. Returns where it ended up.
+pub fn place_file(app: &AppHandle, from: &Path, requested: &str, policy: OverwritePolicy) -> Result<PathBuf, String> {
+    let requested = PathBuf::from(requested);
+    let reservations = app.state::<OutputReservations>();
+    let (mut n, mut path) = {
+        let mut taken = reservations.paths.lock().unwrap();
+        let (n, path) = pick(&taken, &requested, policy)?;
+        taken.insert(key(&path));
+        (n, path)
+    };
+    let placed = if policy == OverwritePolicy::Overwrite {
+        move_file(from, &path).map(|()| path.clone()).map_err(|e| format!("Could not move the output into place: {}", e))
+    } else {
+        place(&reservations.paths, &requested, policy, &mut n, &mut path, &mut from.to_path_buf())
+    };
+    reservations.paths.lock().unwrap().remove(&key(&path));
+    placed
+}
+
+// Swaps the final name for the next free candidate. A temp file named after
+// the old one (`.clip.partial.mp4`) is renamed along, so it never sits
+// under a name that's no longer ours.
+fn advance(taken: &mut HashSet<String>, requested: &Path, n: &mut u32, path: &mut PathBuf, source: &mut PathBuf) -> Result<(), String> {
+    let follows = *source == staged_path(path);
+    let mut from = *n + 1;
+    loop {
+        let (next, next_path) = free_candidate(taken, requested, from)?;
+        if follows {
+            let near = staged_path(&next_path);
+            match rename_noreplace(source, &near) {
+                Ok(()) => {
+                    // Only a reserved temp name is carried over
+                    if taken.remove(&key(source)) {
+                        taken.insert(key(&near));
+                    }
+                    *source = near;
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    from = next + 1;
+                    continue;
+                }
+                Err(e) => return Err(format!("Could not rename the temp output: {}", e)),
+            }
+        }
+        taken.remove(&key(path));
+        taken.insert(key(&next_path));
+        *n = next;
+        *path = next_path;
+        return Ok(());
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(watcher) = self.watcher.take() {
+            watcher.abort();
+        }
+        if !self.keep_staged {
+            let _ = fs::remove_file(&self.staged);
+        }
+        if let Some(reservations) = self.app.try_state::<OutputReservations>() {
+            let mut taken = reservations.paths.lock().unwrap();
+            for k in self.keys() {
+                taken.remove(&k);
+            }
+        }
+    }
+}
+
+fn free_candidate(taken: &HashSet<String>, requested: &Path, from: u32) -> Result<(u32, PathBuf), String> {
+    (from..from + MAX_CANDIDATES)
+        .map(|n| (n, candidate(requested, n)))
+        .find(|(_, p)| !p.exists() && !taken.contains(&key(p)) && !taken.contains(&key(&staged_path(p))))
+        .ok_or_else(|| format!("No free output name next to {}", requested.display()))
+}
+
+// The name `claim` takes, under the same lock as the insert.
+fn pick(taken: &HashSet<String>, requested: &Path, policy: OverwritePolicy) -> Result<(u32, PathBuf), String> {
+    if policy == OverwritePolicy::Rename {
+        free_candidate(taken, requested, 0)
+    } else if taken.contains(&key(requested)) {
+        Err(format!("Another job is already writing {}", requested.display()))
+    } else if policy == OverwritePolicy::Fail && requested.exists() {
+        Err(format!("{} already exists (overwrite_policy is \"fail\")", requested.display()))
+    } else {
+        Ok((0, requested.to_path_buf()))
+    }
+}
+
+// Claims `requested`, or with Rename the first free `name (n).ext` when it
+// exists on disk or another job holds it. With Overwrite an existing file
+// will be replaced, with Fail it's an error; another job's output is an
+// error for both.
+pub fn claim(app: &AppHandle, requested: &str, policy: OverwritePolicy, work_dir: Option<&Path>) -> Result<Reservation, String> {
+    let requested = PathBuf::from(requested);
+    let reservations = app.state::<OutputReservations>();
+    let mut taken = reservations.paths.lock().unwrap();
+    let (n, path) = pick(&taken, &requested, policy)?;
+    let removable = volumes::volume_of(&path.to_string_lossy()).filter(|v| v.removable).map(|v| PathBuf::from(v.mount_point));
+    let work_dir = work_dir.map(Path::to_path_buf).or_else(|| configured_work_dir(app)).filter(|d| fs::create_dir_all(d).is_ok());
+    let staged = match (work_dir, removable.as_ref()) {
+        (Some(dir), _) => work_dir_staged_path(&dir, &path),
+        (None, Some(_)) => local_staged_path(app, &path).unwrap_or_else(|| staged_path(&path)),
+        (None, None) => staged_path(&path),
+    };
+    taken.insert(key(&path));
+    taken.insert(key(&staged));
+    if n > 0 {
+        println!("🔀 {} is taken, writing {} instead", requested.display(), path.display());
+    }
+    let mut reservation = Reservation {
+        app: app.clone(),
+        requested,
+        n,
+        policy,
+        path,
+        staged,
+        removable,
+        keep_staged: false,
+        watcher: None,
+        write_mbps: None,
+    };
+    queue::track_output(app, &reservation.staged);
+    reservation.watch_destination();
+    Ok(reservation)
+}
+
+// Without a policy, queue jobs move aside to a free name and direct commands
+// replace, since the user picked that exact file (and the save dialog
+// already asked). Without a work dir, the work_dir setting applies.
+pub fn claim_for_job(app: &AppHandle, requested: &str, policy: Option<OverwritePolicy>, work_dir: Option<&str>) -> Result<Reservation, String> {
+    let default = if queue::current_job_id().is_some() { OverwritePolicy::Rename } else { OverwritePolicy::Overwrite };
+    claim(app, requested, policy.unwrap_or(default), work_dir.map(Path::new))
+}
+
+// ==========================================
+// NO-REPLACE RENAME
+// ==========================================
+// Fails with AlreadyExists instead of replacing `to`, atomically.
+#[cfg(target_os = "linux")]
+pub fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let from_c = CString::new(from.as_os_str().as_bytes())?;
+    let to_c = CString::new(to.as_os_str().as_bytes())?;
+    // SAFETY: both paths are NUL-terminated CStrings that outlive the call
+    let r = unsafe { libc::renameat2(libc::AT_FDCWD, from_c.as_ptr(), libc::AT_FDCWD, to_c.as_ptr(), libc::RENAME_NOREPLACE) };
+    if r == 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    // Filesystems without the flag (some network mounts) get the link trick
+    if e.raw_os_error() == Some(libc::EINVAL) {
+        return link_then_unlink(from, to);
+    }
+    Err(e)
+}
+
+#[cfg(target_os = "macos")]
+pub fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let from_c = CString::new(from.as_os_str().as_bytes())?;
+    let to_c = CString::new(to.as_os_str().as_bytes())?;
+    // SAFETY: both paths are NUL-terminated CStrings that outlive the call
+    let r = unsafe { libc::renamex_np(from_c.as_ptr(), to_c.as_ptr(), libc::RENAME_EXCL) };
+    if r == 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    // exFAT, FAT and SMB volumes don't support RENAME_EXCL
+    if e.raw_os_error() == Some(libc::ENOTSUP) {
+        return link_then_unlink(from, to);
+    }
+    Err(e)
+}
+
+// MoveFileEx without MOVEFILE_REPLACE_EXISTING fails on an existing target.
+#[cfg(windows)]
+pub fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::MoveFileExW;
+    let wide = |p: &Path| p.as_os_str().encode_wide().chain(std::iter::once(0)).collect::<Vec<u16>>();
+    let (from_w, to_w) = (wide(from), wide(to));
+    // SAFETY: both buffers are NUL-terminated and outlive the call
+    if unsafe { MoveFileExW(from_w.as_ptr(), to_w.as_ptr(), 0) } != 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    link_then_unlink(from, to)
+}
+
+// A hard link is never created over an existing file either. FAT and exFAT
+// (most USB sticks) have no hard links, so those get a no-replace copy.
+#[cfg(unix)]
+fn link_then_unlink(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::hard_link(from, to) {
+        Ok(()) => fs::remove_file(from),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(e),
+        Err(_) => copy_noreplace(from, to),
+    }
+}
+
+// Claims `to` with create_new (AlreadyExists if it's there), copies into it,
+// then removes `from`. A failed copy takes the half-written `to` with it.
+pub fn copy_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    let mut target = fs::OpenOptions::new().write(true).create_new(true).open(to)?;
+    let copied = fs::File::open(from).and_then(|mut source| io::copy(&mut source, &mut target)).and_then(|_| target.sync_all());
+    drop(target);
+    if let Err(e) = copied {
+        let _ = fs::remove_file(to);
+        return Err(e);
+    }
+    fs::remove_file(from)
+}
+
+// ==========================================
+// DISK-SPACE PREFLIGHT
+// ==========================================
+// What Reservation::check_space decides for one folder, and what
+// check_disk_space tells the frontend before it queues anything.
+#[derive(Serialize, Clone, Debug)]
+pub struct SpaceCheck {
+    pub mount_point: Option<String>,
+    // None when the volume couldn't be found
+    pub available_bytes: Option<u64>,
+    // What was asked for, plus SPACE_MARGIN
+    pub required_bytes: u64,
+    // Unknown free space counts as enough
+    pub fits: bool,
+}
+
+// `estimated_bytes` plus SPACE_MARGIN (the queue reserves the same).
+pub fn required_bytes(estimated_bytes: u64) -> u64 {
+    (estimated_bytes as f64 * SPACE_MARGIN) as u64
+}
+
+impl SpaceCheck {
+    // Pure: the estimate (plus the margin) against what's free there
+    pub fn new(mount_point: Option<String>, available_bytes: Option<u64>, estimated_bytes: u64) -> Self {
+        let required_bytes = required_bytes(estimated_bytes);
+        SpaceCheck { mount_point, available_bytes, required_bytes, fits: available_bytes.is_none_or(|free| free >= required_bytes) }
+    }
+}
+
+pub fn space_check(dir: &Path, estimated_bytes: u64) -> SpaceCheck {
+    let mount_point = volumes::volume_of(&dir.to_string_lossy()).map(|v| v.mount_point);
+    SpaceCheck::new(mount_point, volumes::available_bytes(dir), estimated_bytes)
+}
+
+// The refusals, in MB to read and in bytes to act on
+fn work_volume_full(mount: &str, work: &SpaceCheck) -> String {
+    format!(
+        "{}: {} has {} MB free and this job needs about {} MB for its temp output (required {} bytes, available {}). Free some up, or point the work folder at a bigger drive (set_work_dir)",
+        WORK_VOLUME_FULL,
+        mount,
+        work.available_bytes.unwrap_or(0) / 1_000_000,
+        work.required_bytes / 1_000_000,
+        work.required_bytes,
+        work.available_bytes.unwrap_or(0)
+    )
+}
+
+fn output_volume_full(dest: &SpaceCheck) -> String {
+    format!(
+        "{}: {} has {} MB free and this job's output needs about {} MB there (required {} bytes, available {}). Free some up, or pick another output folder",
+        OUTPUT_VOLUME_FULL,
+        dest.mount_point.as_deref().unwrap_or(""),
+        dest.available_bytes.unwrap_or(0) / 1_000_000,
+        dest.required_bytes / 1_000_000,
+        dest.required_bytes,
+        dest.available_bytes.unwrap_or(0)
+    )
+}
+
+#[tauri::command]
+pub fn check_disk_space(output_dir: String, required_bytes: u64) -> SpaceCheck {
+    space_check(Path::new(&output_dir), required_bytes)
+}
+
+// ==========================================
+// COMMAND: WORK FOLDER
+// ==========================================
+// Where temp outputs (and resumable parts) are written. None puts each one
+// next to its output, which keeps the final move a rename on the same
+// volume; a folder is for when the destination's volume is too small for
+// the temp file, or too slow.
+#[tauri::command]
+pub fn set_work_dir(store: tauri::State<'_, SettingsStore>, dir: Option<String>) -> Result<(), String> {
+    if let Some(dir) = &dir {
+        let path = Path::new(dir);
+        if !path.is_dir() {
+            return Err(format!("{} isn't a folder", dir));
+        }
+        let probe = path.join(format!(".compressio-write-test-{}", std::process::id()));
+        fs::write(&probe, b"").map_err(|e| format!("Can't write to {}: {}", dir, e))?;
+        let _ = fs::remove_file(&probe);
+    }
+    store.update(|s| s.work_dir = dir)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::TempDir;
+    use crate::jobtests::Harness;
+    use std::sync::Arc;
+
+    fn folder(name: &str) -> TempDir {
+        TempDir::new(std::env::temp_dir().join(format!("outputs-test-{}-{}", std::process::id(), name))).unwrap()
+    }
+
+    // What claim does for an output with its temp file next to it
+    fn claim_beside(taken: &Mutex<HashSet<String>>, requested: &Path) -> (u32, PathBuf, PathBuf) {
+        let mut taken = taken.lock().unwrap();
+        let (n, path) = pick(&taken, requested, OverwritePolicy::Rename).unwrap();
+        let staged = staged_path(&path);
+        taken.insert(key(&path));
+        taken.insert(key(&staged));
+        (n, path, staged)
+    }
+
+    #[test]
+    fn candidates_count_up_before_the_extension() {
+        let requested = Path::new("/out/clip.mp4");
+        assert_eq!(candidate(requested, 0), requested);
+        assert_eq!(candidate(requested, 1), Path::new("/out/clip (1).mp4"));
+        assert_eq!(candidate(requested, 12), Path::new("/out/clip (12).mp4"));
+        assert_eq!(candidate(Path::new("/out/archive.tar.gz"), 1), Path::new("/out/archive.tar (1).gz"));
+        assert_eq!(candidate(Path::new("/out/README"), 2), Path::new("/out/README (2)"));
+        assert_eq!(candidate(Path::new("/out/.hidden"), 1), Path::new("/out/.hidden (1)"));
+    }
+
+    #[test]
+    fn candidates_keep_awkward_names_as_they_are() {
+        assert_eq!(candidate(Path::new("/out/clip time=2.mov"), 1), Path::new("/out/clip time=2 (1).mov"));
+        assert_eq!(candidate(Path::new("/out/Bob's \"best\".mp4"), 3), Path::new("/out/Bob's \"best\" (3).mp4"));
+        assert_eq!(candidate(Path::new("/out/旅行 📹.mp4"), 1), Path::new("/out/旅行 📹 (1).mp4"));
+        assert_eq!(candidate(Path::new("/out/clip."), 1), Path::new("/out/clip (1)."));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn candidates_keep_bytes_that_arent_unicode() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+        let requested = Path::new("/out").join(std::ffi::OsStr::from_bytes(b"caf\xe9.mp4"));
+        let renamed = candidate(&requested, 1);
+        assert_eq!(renamed.file_name().unwrap().to_os_string().into_vec(), b"caf\xe9 (1).mp4".to_vec());
+    }
+
+    #[test]
+    fn concurrent_claims_get_different_final_paths() {
+        let dir = folder("concurrent");
+        let requested = dir.path().join("clip.mp4");
+        let taken = Arc::new(Mutex::new(HashSet::new()));
+        let jobs: Vec<_> = (0..8)
+            .map(|i| {
+                let (taken, requested) = (taken.clone(), requested.clone());
+                std::thread::spawn(move || {
+                    let (mut n, mut path, mut staged) = claim_beside(&taken, &requested);
+                    fs::write(&staged, format!("job {}", i)).unwrap();
+                    let placed = place(&taken, &requested, OverwritePolicy::Rename, &mut n, &mut path, &mut staged).unwrap();
+                    (i, placed)
+                })
+            })
+            .collect();
+        let placed: Vec<(i32, PathBuf)> = jobs.into_iter().map(|j| j.join().unwrap()).collect();
+
+        let distinct: HashSet<&PathBuf> = placed.iter().map(|(_, p)| p).collect();
+        assert_eq!(distinct.len(), 8);
+        for (i, path) in &placed {
+            assert_eq!(fs::read_to_string(path).unwrap(), format!("job {}", i));
+        }
+    }
+
+    #[test]
+    fn a_name_taken_from_outside_moves_the_temp_file_along() {
+        let dir = folder("outside");
+        let requested = dir.path().join("clip.mp4");
+        let taken = Mutex::new(HashSet::new());
+        let (mut n, mut path, mut staged) = claim_beside(&taken, &requested);
+        fs::write(&staged, "ours").unwrap();
+        // Something outside the app writes both names meanwhile
+        fs::write(&requested, "theirs").unwrap();
+        fs::write(dir.path().join("clip (1).mp4"), "theirs too").unwrap();
+
+        let placed = place(&taken, &requested, OverwritePolicy::Rename, &mut n, &mut path, &mut staged).unwrap();
+        assert_eq!(placed, dir.path().join("clip (2).mp4"));
+        assert_eq!(fs::read_to_string(&placed).unwrap(), "ours");
+        assert_eq!(fs::read_to_string(&requested).unwrap(), "theirs");
+        // The temp name followed the final one, and so did the reservation
+        assert_eq!(staged, staged_path(&placed));
+        let taken = taken.lock().unwrap();
+        assert!(taken.contains(&key(&placed)) && taken.contains(&key(&staged)));
+        assert!(!taken.contains(&key(&requested)) && !taken.contains(&key(&staged_path(&requested))));
+    }
+
+    #[test]
+    fn fail_policy_never_moves_on() {
+        let dir = folder("fail");
+        let requested = dir.path().join("clip.mp4");
+        let taken = Mutex::new(HashSet::new());
+        let (mut n, mut path, mut staged) = (0, requested.clone(), staged_path(&requested));
+        fs::write(&staged, "ours").unwrap();
+        fs::write(&requested, "theirs").unwrap();
+        assert!(place(&taken, &requested, OverwritePolicy::Fail, &mut n, &mut path, &mut staged).is_err());
+        assert_eq!(fs::read_to_string(&requested).unwrap(), "theirs");
+    }
+
+    #[test]
+    fn pick_refuses_another_jobs_output() {
+        let dir = folder("pick");
+        let requested = dir.path().join("clip.mp4");
+        let mut taken = HashSet::new();
+        taken.insert(key(&requested));
+        assert!(pick(&taken, &requested, OverwritePolicy::Overwrite).is_err());
+        assert_eq!(pick(&taken, &requested, OverwritePolicy::Rename).unwrap(), (1, dir.path().join("clip (1).mp4")));
+    }
+
+    // ==========================================
+    // INTO PLACE, ON THE SAME VOLUME OR ANOTHER
+    // ==========================================
+    #[cfg(unix)]
+    fn device(path: &Path) -> u64 {
+        use std::os::unix::fs::MetadataExt;
+        fs::metadata(path).unwrap().dev()
+    }
+
+    #[cfg(unix)]
+    fn inode(path: &Path) -> u64 {
+        use std::os::unix::fs::MetadataExt;
+        fs::metadata(path).unwrap().ino()
+    }
+
+    // A folder on another filesystem than the temp dir (tmpfs on Linux)
+    #[cfg(unix)]
+    fn other_volume(name: &str) -> Option<TempDir> {
+        let shm = Path::new("/dev/shm");
+        if !shm.is_dir() || device(shm) == device(&std::env::temp_dir()) {
+            println!("⏭️ No second volume to move across, skipping");
+            return None;
+        }
+        TempDir::new(shm.join(format!("outputs-test-{}-{}", std::process::id(), name))).ok()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn move_file_renames_on_one_volume_and_copies_across() {
+        let dir = folder("move");
+        let (from, to) = (dir.path().join("a.mp4"), dir.path().join("b.mp4"));
+        fs::write(&from, "ours").unwrap();
+        let before = inode(&from);
+        move_file(&from, &to).unwrap();
+        assert_eq!(inode(&to), before);
+        assert!(!from.exists());
+
+        let Some(other) = other_volume("move") else { return };
+        let across = other.path().join("c.mp4");
+        move_file(&to, &across).unwrap();
+        assert_eq!(fs::read_to_string(&across).unwrap(), "ours");
+        assert!(!to.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_work_folder_on_the_same_volume_is_renamed_into_place() {
+        let h = Harness::new("outputs-same-volume", "{}");
+        let requested = h.file("clip.mp4");
+        let work = h.file("work");
+        let mut reservation = claim(h.handle(), &requested, OverwritePolicy::Rename, Some(Path::new(&work))).unwrap();
+        assert_eq!(reservation.staged.parent(), Some(Path::new(&work)));
+        fs::write(&reservation.staged, "ours").unwrap();
+        let staged_inode = inode(&reservation.staged);
+
+        let placed = reservation.commit().unwrap();
+        assert_eq!(placed, Path::new(&requested));
+        // The same file, moved rather than copied
+        assert_eq!(inode(&placed), staged_inode);
+        drop(reservation);
+        assert_eq!(h.files(), ["clip.mp4", "work"]);
+        assert_eq!(fs::read_dir(&work).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn without_a_work_folder_the_temp_file_is_beside_the_output() {
+        let h = Harness::new("outputs-beside", "{}");
+        let reservation = claim(h.handle(), &h.file("clip.mp4"), OverwritePolicy::Rename, None).unwrap();
+        assert_eq!(reservation.staged, staged_path(Path::new(&h.file("clip.mp4"))));
+        assert!(reservation.staged_beside());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_work_folder_on_another_volume_is_copied_over_then_removed() {
+        let h = Harness::new("outputs-cross-device", "{}");
+        let Some(work) = other_volume("cross-device") else { return };
+        // Something outside the app has the name already
+        fs::write(h.file("clip.mp4"), "theirs").unwrap();
+        let mut reservation = claim(h.handle(), &h.file("clip.mp4"), OverwritePolicy::Rename, Some(work.path())).unwrap();
+        assert_ne!(device(work.path()), device(Path::new(&h.file(""))));
+        fs::write(&reservation.staged, "ours").unwrap();
+
+        let placed = reservation.commit().unwrap();
+        assert_eq!(placed, Path::new(&h.file("clip (1).mp4")));
+        assert_eq!(fs::read_to_string(&placed).unwrap(), "ours");
+        assert_eq!(fs::read_to_string(h.file("clip.mp4")).unwrap(), "theirs");
+        drop(reservation);
+        // Neither the copy next to the output nor the original is left
+        assert_eq!(h.files(), ["clip (1).mp4", "clip.mp4"]);
+        assert_eq!(fs::read_dir(work.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn copy_noreplace_moves_the_file_and_never_replaces() {
+        let dir = folder("copy-noreplace");
+        let (from, to) = (dir.path().join(".clip.partial.mp4"), dir.path().join("clip.mp4"));
+        fs::write(&from, "ours").unwrap();
+        fs::write(&to, "theirs").unwrap();
+        assert_eq!(copy_noreplace(&from, &to).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&to).unwrap(), "theirs");
+        assert_eq!(fs::read_to_string(&from).unwrap(), "ours");
+
+        fs::remove_file(&to).unwrap();
+        copy_noreplace(&from, &to).unwrap();
+        assert_eq!(fs::read_to_string(&to).unwrap(), "ours");
+        assert!(!from.exists());
+    }
+
+    #[test]
+    fn copy_noreplace_leaves_nothing_behind_when_the_source_is_gone() {
+        let dir = folder("copy-noreplace-missing");
+        let to = dir.path().join("clip.mp4");
+        assert!(copy_noreplace(&dir.path().join("gone.mp4"), &to).is_err());
+        assert!(!to.exists());
+    }
+
+    #[test]
+    fn a_vanished_removable_destination_is_detected_and_classified() {
+        let h = Harness::new("outputs-removed", "{}");
+        // A folder standing in for the drive's mount point
+        let mount = h.dir.join("usb");
+        fs::create_dir_all(mount.join("videos")).unwrap();
+        let mut reservation = claim(h.handle(), &h.file("usb/videos/clip.mp4"), OverwritePolicy::Rename, None).unwrap();
+        reservation.removable = Some(mount.clone());
+        fs::write(&reservation.staged, "finished").unwrap();
+        assert!(!reservation.destination_gone());
+        assert_eq!(reservation.classify("ffmpeg failed".to_string()), "ffmpeg failed");
+
+        // Our folder on it goes first, then the whole drive
+        fs::remove_dir_all(mount.join("videos")).unwrap();
+        assert!(reservation.destination_gone());
+        fs::remove_dir_all(&mount).unwrap();
+        assert!(reservation.destination_gone());
+        let error = reservation.classify("Error writing trailer: Input/output error".to_string());
+        assert!(error.starts_with(DESTINATION_REMOVED), "{}", error);
+        assert!(error.contains(&mount.display().to_string()) && error.contains("Input/output error"));
+        assert!(reservation.commit().unwrap_err().starts_with(DESTINATION_REMOVED));
+    }
+
+    #[test]
+    fn place_file_honours_the_overwrite_policy() {
+        let h = Harness::new("outputs-place-file", "{}");
+        let (from, to) = (h.file("stranded.mp4"), h.file("clip.mp4"));
+        fs::write(&to, "theirs").unwrap();
+
+        fs::write(&from, "ours").unwrap();
+        assert!(place_file(h.handle(), Path::new(&from), &to, OverwritePolicy::Fail).is_err());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "theirs");
+        assert_eq!(place_file(h.handle(), Path::new(&from), &to, OverwritePolicy::Rename).unwrap(), Path::new(&h.file("clip (1).mp4")));
+        assert_eq!(fs::read_to_string(h.file("clip (1).mp4")).unwrap(), "ours");
+
+        fs::write(&from, "ours again").unwrap();
+        assert_eq!(place_file(h.handle(), Path::new(&from), &to, OverwritePolicy::Overwrite).unwrap(), Path::new(&to));
+        assert_eq!(fs::read_to_string(&to).unwrap(), "ours again");
+        // Nothing stays reserved
+        assert!(h.handle().state::<OutputReservations>().paths.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn side_files_follow_the_output_name() {
+        let (reserved, committed) = (Path::new("/out/clip.mp4"), Path::new("/out/clip (1).mp4"));
+        assert_eq!(follow(Path::new("/out/clip.3.sup"), reserved, committed), Some(PathBuf::from("/out/clip (1).3.sup")));
+        assert_eq!(follow(Path::new("/out/clip.3.en.sup"), reserved, committed), Some(PathBuf::from("/out/clip (1).3.en.sup")));
+        assert_eq!(follow(Path::new("/out/clipper.3.sup"), reserved, committed), None);
+        assert_eq!(follow(Path::new("/out/other.3.sup"), reserved, committed), None);
+    }
+
+    #[test]
+    fn the_estimate_plus_its_margin_has_to_fit_in_what_is_free() {
+        assert_eq!(required_bytes(1_000_000), 1_200_000);
+        let check = |free: Option<u64>| SpaceCheck::new(Some("/mnt/scratch".to_string()), free, 1_000_000);
+        assert!(check(Some(1_200_000)).fits);
+        assert!(!check(Some(1_199_999)).fits);
+        // Free space above the estimate alone isn't enough
+        assert!(!check(Some(1_100_000)).fits);
+        assert!(check(None).fits, "unknown free space passes");
+        assert_eq!(check(Some(5)).required_bytes, 1_200_000);
+        assert!(SpaceCheck::new(None, Some(0), 0).fits);
+    }
+
+    #[test]
+    fn a_refusal_says_how_much_is_needed_and_what_to_do() {
+        let work = SpaceCheck::new(Some("/mnt/scratch".to_string()), Some(300_000_000), 1_000_000_000);
+        assert_eq!(
+            work_volume_full("/mnt/scratch", &work),
+            "WorkVolumeFull: /mnt/scratch has 300 MB free and this job needs about 1200 MB for its temp output (required 1200000000 bytes, available 300000000). Free some up, or point the work folder at a bigger drive (set_work_dir)"
+        );
+        let dest = SpaceCheck::new(Some("/Volumes/USB".to_string()), Some(50_000_000), 100_000_000);
+        assert_eq!(
+            output_volume_full(&dest),
+            "OutputVolumeFull: /Volumes/USB has 50 MB free and this job's output needs about 120 MB there (required 120000000 bytes, available 50000000). Free some up, or pick another output folder"
+        );
+        // Both come out of the commands as a full disk
+        for error in [work_volume_full("/mnt/scratch", &work), output_volume_full(&dest)] {
+            assert!(matches!(crate::errors::classify(&error, vec![]), crate::errors::JobError::DiskFull { .. }));
+        }
+    }
+
+    #[test]
+    fn check_space_refuses_more_than_the_volume_holds_unless_forced() {
+        let h = Harness::new("outputs-space", "{}");
+        let reservation = claim(h.handle(), &h.file("clip.mp4"), OverwritePolicy::Rename, None).unwrap();
+        // A sandbox may hide its mounts; unknown free space always passes
+        if volumes::available_bytes(&h.dir).is_none() {
+            return;
+        }
+        let error = reservation.check_space(u64::MAX / 2, false).unwrap_err();
+        assert!(error.starts_with(WORK_VOLUME_FULL), "{}", error);
+        assert!(error.contains(&format!("required {} bytes", required_bytes(u64::MAX / 2))), "{}", error);
+        assert!(reservation.check_space(u64::MAX / 2, true).is_ok());
+        assert!(reservation.check_space(1024, false).is_ok());
+    }
+}
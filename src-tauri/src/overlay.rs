@@ -0,0 +1,204 @@
+use serde::{Deserialize, Serialize};
+
+// Burned-in text for review copies. `template` may contain `{timecode}`,
+// `{filename}` and `{frame}`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TextOverlay {
+    pub template: String,
+    #[serde(default)]
+    pub position: OverlayPosition,
+    pub font_size: Option<u32>,
+    // Semi-transparent box behind the text
+    #[serde(default, rename = "box")]
+    pub boxed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverlayPosition {
+    TopLeft,
+    TopCenter,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomCenter,
+    BottomRight,
+}
+
+const MARGIN: u32 = 20;
+const DEFAULT_FONT_SIZE: u32 = 24;
+
+// --- ESCAPING ---
+// drawtext text goes through three parsers, innermost first:
+//   1. drawtext's own expansion: `\` and `%` are special
+//   2. the filter option parser: `\`, `'` and `:` are special
+//   3. the filtergraph parser: `\`, `'`, `[`, `]`, `,` and `;` are special
+// Literal user text gets all three; our own `%{...}` sequences only 2 and 3.
+fn backslash_escape(text: &str, special: &[char]) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(&c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+pub fn escape_expansion(text: &str) -> String {
+    backslash_escape(text, &['\\', '%'])
+}
+
+pub fn escape_option_value(text: &str) -> String {
+    backslash_escape(text, &['\\', '\'', ':'])
+}
+
+pub fn escape_filtergraph(text: &str) -> String {
+    backslash_escape(text, &['\\', '\'', '[', ']', ',', ';'])
+}
+
+// "01:00:00:00" (or "01:00:00;00" for drop-frame) -> frame count at `rate`.
+// Drop-frame is counted as non-drop; close enough for a review burn-in.
+pub fn parse_timecode(tc: &str, rate: u64) -> Option<u64> {
+    let parts: Vec<u64> = tc
+        .split([':', ';', '.'])
+        .map(|p| p.trim().parse().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let [h, m, s, f] = parts[..] else { return None };
+    Some(((h * 60 + m) * 60 + s) * rate + f)
+}
+
+// drawtext's own `timecode=` option always prints the timecode before the
+// text, so it can't sit in the middle of a template. Computing HH:MM:SS:FF
+// from the frame number with `eif` works anywhere.
+fn timecode_expansion(rate: u64, start_frames: u64) -> String {
+    let n = format!("(n+{})", start_frames);
+    [
+        format!("%{{eif:floor({}/{}):d:2}}", n, rate * 3600),
+        format!("%{{eif:mod(floor({}/{}),60):d:2}}", n, rate * 60),
+        format!("%{{eif:mod(floor({}/{}),60):d:2}}", n, rate),
+        format!("%{{eif:mod({},{}):d:2}}", n, rate),
+    ]
+    .join(":")
+}
+
+// Template -> drawtext text with expansion sequences (still needs levels 2 and 3).
+pub fn expand_template(template: &str, filename: &str, rate: u64, start_frames: u64) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&escape_expansion(&rest[..open]));
+        let after = &rest[open..];
+        let (token, replacement) = if after.starts_with("{timecode}") {
+            ("{timecode}", timecode_expansion(rate, start_frames))
+        } else if after.starts_with("{filename}") {
+            ("{filename}", escape_expansion(filename))
+        } else if after.starts_with("{frame}") {
+            ("{frame}", format!("%{{eif:n+{}:d}}", start_frames))
+        } else {
+            ("{", "{".to_string())
+        };
+        out.push_str(&replacement);
+        rest = &after[token.len()..];
+    }
+    out.push_str(&escape_expansion(rest));
+    out
+}
+
+fn position_exprs(position: OverlayPosition) -> (String, String) {
+    let left = MARGIN.to_string();
+    let center = "(w-text_w)/2".to_string();
+    let right = format!("w-text_w-{}", MARGIN);
+    let top = MARGIN.to_string();
+    let bottom = format!("h-text_h-{}", MARGIN);
+    match position {
+        OverlayPosition::TopLeft => (left, top),
+        OverlayPosition::TopCenter => (center, top),
+        OverlayPosition::TopRight => (right, top),
+        OverlayPosition::BottomLeft => (left, bottom),
+        OverlayPosition::BottomCenter => (center, bottom),
+        OverlayPosition::BottomRight => (right, bottom),
+    }
+}
+
+// ==========================================
+// HELPER: DRAWTEXT FILTER FOR AN OVERLAY
+// ==========================================
+// `start_timecode` is the source's embedded timecode (00:00:00:00 if none);
+// `offset_secs` is where this encode starts in the source (resumed parts).
+pub fn drawtext_filter(
+    overlay: &TextOverlay,
+    filename: &str,
+    fps: Option<f64>,
+    start_timecode: Option<&str>,
+    offset_secs: f64,
+) -> String {
+    let rate = fps.map(|f| f.round() as u64).filter(|r| *r > 0).unwrap_or(25);
+    let start_frames = start_timecode.and_then(|tc| parse_timecode(tc, rate)).unwrap_or(0)
+        + (offset_secs * rate as f64).round() as u64;
+
+    let text = expand_template(&overlay.template, filename, rate, start_frames);
+    let (x, y) = position_exprs(overlay.position);
+    let mut options = vec![
+        format!("text={}", escape_option_value(&text)),
+        format!("x={}", x),
+        format!("y={}", y),
+        format!("fontsize={}", overlay.font_size.unwrap_or(DEFAULT_FONT_SIZE)),
+        "fontcolor=white".to_string(),
+    ];
+    if overlay.boxed {
+        options.push("box=1".to_string());
+        options.push("boxcolor=black@0.5".to_string());
+        options.push("boxborderw=8".to_string());
+    }
+    escape_filtergraph(&format!("drawtext={}", options.join(":")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlay(template: &str) -> TextOverlay {
+        TextOverlay { template: template.to_string(), position: OverlayPosition::default(), font_size: None, boxed: false }
+    }
+
+    #[test]
+    fn timecodes_count_frames_at_the_rate() {
+        assert_eq!(parse_timecode("01:00:00:00", 25), Some(90_000));
+        assert_eq!(parse_timecode("00:00:01;05", 30), Some(35));
+        assert_eq!(parse_timecode("00:01:00.10", 24), Some(1450));
+        assert_eq!(parse_timecode("00:01:00", 24), None);
+        assert_eq!(parse_timecode("aa:00:00:00", 24), None);
+    }
+
+    #[test]
+    fn templates_expand_known_tokens_only() {
+        assert_eq!(expand_template("{frame}", "x.mov", 25, 10), "%{eif:n+10:d}");
+        assert_eq!(expand_template("{filename} at 50%", "a\\b%.mov", 25, 0), r"a\\b\%.mov at 50\%");
+        assert_eq!(expand_template("{nope} {", "x.mov", 25, 0), "{nope} {");
+        assert_eq!(
+            expand_template("TC {timecode}", "x.mov", 25, 0),
+            "TC %{eif:floor((n+0)/90000):d:2}:%{eif:mod(floor((n+0)/1500),60):d:2}:%{eif:mod(floor((n+0)/25),60):d:2}:%{eif:mod((n+0),25):d:2}"
+        );
+    }
+
+    #[test]
+    fn the_filter_is_escaped_for_every_parser() {
+        // The start frame is the embedded timecode plus where this part starts
+        assert_eq!(
+            drawtext_filter(&overlay("{frame}"), "x.mov", Some(29.97), Some("00:00:01:00"), 2.0),
+            r"drawtext=text=%{eif\\:n+90\\:d}:x=(w-text_w)/2:y=h-text_h-20:fontsize=24:fontcolor=white"
+        );
+        let text = drawtext_filter(&overlay("Bob's [cut], v2; ok"), "x.mov", None, None, 0.0);
+        assert!(text.starts_with(r"drawtext=text=Bob\\\'s \[cut\]\, v2\; ok:"), "{text}");
+    }
+
+    #[test]
+    fn position_and_box_options() {
+        let boxed = TextOverlay { position: OverlayPosition::TopRight, font_size: Some(40), boxed: true, ..overlay("hi") };
+        assert_eq!(
+            drawtext_filter(&boxed, "x.mov", Some(25.0), None, 0.0),
+            "drawtext=text=hi:x=w-text_w-20:y=20:fontsize=40:fontcolor=white:box=1:boxcolor=black@0.5:boxborderw=8"
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tauri::State;
+use tokio_util::sync::CancellationToken;
+use zip::write::{FileOptions, SimpleFileOptions};
+use zip::{AesMode, CompressionMethod, ZipWriter};
+
+use crate::AppHandle;
+use crate::cancel::{self, TempFile};
+use crate::events::{self, Event};
+use crate::history::{HistoryEntry, HistoryStore, JobStatus};
+use crate::outputs;
+use crate::paths;
+use crate::report::{self, JobSelection};
+
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+// Name of the batch report inside the archive
+const REPORT_NAME: &str = "report.json";
+
+// ==========================================
+// DELIVERY PACKAGES
+// ==========================================
+// The last step of a client delivery: a batch's outputs in one zip,
+// optionally AES-256 encrypted, with the batch report (the JSON of
+// export_batch_report) inside. Outputs keep their folders below the
+// directory they share, or all go in the top level with `flatten`, where a
+// second `clip.mp4` becomes `clip (1).mp4` as outputs are named on disk.
+//
+// The zip is written next to `zip_path` and only moved there when it's
+// complete: a failure or cancel_packaging (checked between entries) leaves
+// no partial archive, and the outputs themselves are only ever read.
+
+#[derive(Default)]
+pub struct Packaging {
+    running: AtomicBool,
+    token: Mutex<CancellationToken>,
+}
+
+struct RunningGuard<'a>(&'a Packaging);
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.0.running.store(false, Ordering::SeqCst);
+    }
+}
+
+// `package-progress`, per chunk written
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct PackageProgress {
+    pub zip_path: String,
+    // Name inside the archive
+    pub entry: String,
+    pub entries_done: usize,
+    pub entries_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PackageSummary {
+    pub zip_path: String,
+    // Outputs packaged, not counting the report
+    pub files: usize,
+    pub bytes_in: u64,
+    pub zip_bytes: u64,
+    pub encrypted: bool,
+    // Selected jobs that failed, or whose output is gone from disk
+    pub skipped: Vec<String>,
+}
+
+// Pure: the directory every path is in.
+fn common_dir(paths: &[PathBuf]) -> PathBuf {
+    let mut common: Option<PathBuf> = None;
+    for path in paths {
+        let parent = path.parent().unwrap_or(Path::new(""));
+        common = Some(match common {
+            None => parent.to_path_buf(),
+            Some(dir) => dir.components().zip(parent.components()).take_while(|(a, b)| a == b).map(|(a, _)| a).collect(),
+        });
+    }
+    common.unwrap_or_default()
+}
+
+// Zip entries use `/` whatever the platform
+fn entry_name(path: &Path) -> String {
+    path.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+// Pure: the archive name of each output. Flattened names that are taken
+// (case-insensitively, as most unzip targets are) get the next `(n)`;
+// the report's name is taken from the start.
+pub fn entry_names(paths: &[PathBuf], flatten: bool) -> Vec<String> {
+    let base = common_dir(paths);
+    let mut taken: HashSet<String> = HashSet::from([REPORT_NAME.to_string()]);
+    paths
+        .iter()
+        .map(|path| {
+            let relative = if flatten {
+                PathBuf::from(path.file_name().unwrap_or_default())
+            } else {
+                path.strip_prefix(&base).map(Path::to_path_buf).unwrap_or_else(|_| PathBuf::from(path.file_name().unwrap_or_default()))
+            };
+            let name = (0..)
+                .map(|n| entry_name(&outputs::candidate(&relative, n)))
+                .find(|name| !taken.contains(&name.to_lowercase()))
+                .unwrap_or_default();
+            taken.insert(name.to_lowercase());
+            name
+        })
+        .collect()
+}
+
+fn zip_error(e: zip::result::ZipError) -> String {
+    format!("Couldn't write the zip: {}", e)
+}
+
+struct Packer<'a> {
+    app: &'a AppHandle,
+    writer: ZipWriter<File>,
+    password: Option<&'a str>,
+    zip_path: &'a str,
+    entries_done: usize,
+    entries_total: usize,
+}
+
+impl<'a> Packer<'a> {
+    fn options(&self, method: CompressionMethod) -> FileOptions<'a, ()> {
+        let options = SimpleFileOptions::default().compression_method(method).large_file(true);
+        match self.password {
+            Some(password) => options.with_aes_encryption(AesMode::Aes256, password),
+            None => options,
+        }
+    }
+
+    // Compressed video and images don't get smaller with deflate, so
+    // outputs are stored and only the report is compressed
+    fn add_file(&mut self, name: &str, path: &Path, token: &CancellationToken) -> Result<u64, String> {
+        if token.is_cancelled() {
+            return Err(cancel::CANCELLED.to_string());
+        }
+        let mut file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let total = file.metadata().map(|m| m.len()).unwrap_or(0);
+        self.writer.start_file(name, self.options(CompressionMethod::Stored)).map_err(zip_error)?;
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        let mut done = 0;
+        loop {
+            let n = file.read(&mut buf).map_err(|e| format!("{}: {}", path.display(), e))?;
+            if n == 0 {
+                break;
+            }
+            self.writer.write_all(&buf[..n]).map_err(|e| format!("Couldn't write the zip: {}", e))?;
+            done += n as u64;
+            events::emit(self.app, Event::PackageProgress(PackageProgress {
+                zip_path: self.zip_path.to_string(),
+                entry: name.to_string(),
+                entries_done: self.entries_done,
+                entries_total: self.entries_total,
+                bytes_done: done,
+                bytes_total: total,
+            }));
+        }
+        self.entries_done += 1;
+        Ok(done)
+    }
+
+    fn add_report(&mut self, json: &str) -> Result<(), String> {
+        self.writer.start_file(REPORT_NAME, self.options(CompressionMethod::Deflated)).map_err(zip_error)?;
+        self.writer.write_all(json.as_bytes()).map_err(|e| format!("Couldn't write the zip: {}", e))
+    }
+}
+
+fn package(
+    app: &AppHandle,
+    entries: &[HistoryEntry],
+    zip_path: &str,
+    password: Option<&str>,
+    flatten: bool,
+    token: &CancellationToken,
+) -> Result<PackageSummary, String> {
+    let mut skipped = vec![];
+    let mut outputs: Vec<PathBuf> = vec![];
+    for entry in entries {
+        let path = PathBuf::from(&entry.output);
+        if entry.status != JobStatus::Success || !path.is_file() {
+            skipped.push(entry.output.clone());
+        } else if !outputs.contains(&path) {
+            outputs.push(path);
+        }
+    }
+    let names = entry_names(&outputs, flatten);
+    let report = report::json_report(entries)?;
+
+    let target = Path::new(zip_path);
+    let partial = TempFile::new(target.with_file_name(format!("{}.partial", target.file_name().unwrap_or_default().to_string_lossy())));
+    let file = File::create(partial.path()).map_err(|e| format!("{}: {}", partial.path().display(), e))?;
+    let mut packer = Packer { app, writer: ZipWriter::new(file), password, zip_path, entries_done: 0, entries_total: outputs.len() };
+    let mut bytes_in = 0;
+    for (path, name) in outputs.iter().zip(&names) {
+        bytes_in += packer.add_file(name, path, token)?;
+    }
+    packer.add_report(&report)?;
+    packer.writer.finish().map_err(zip_error)?;
+    fs::rename(partial.path(), target).map_err(|e| format!("{}: {}", zip_path, e))?;
+
+    Ok(PackageSummary {
+        zip_path: zip_path.to_string(),
+        files: outputs.len(),
+        bytes_in,
+        zip_bytes: fs::metadata(target).map(|m| m.len()).unwrap_or(0),
+        encrypted: password.is_some(),
+        skipped,
+    })
+}
+
+// ==========================================
+// COMMAND: PACKAGE OUTPUTS
+// ==========================================
+#[tauri::command]
+pub async fn package_outputs(
+    app: AppHandle,
+    history: State<'_, HistoryStore>,
+    packaging: State<'_, Packaging>,
+    job_ids_or_group: JobSelection,
+    zip_path: String,
+    password: Option<String>,
+    flatten: bool,
+) -> Result<PackageSummary, String> {
+    let password = password.filter(|p| !p.is_empty());
+    let zip_path = paths::secure_output(&app, &zip_path, false)?;
+    let entries: Vec<HistoryEntry> = history.all().into_iter().filter(|e| job_ids_or_group.matches(e)).collect();
+    if entries.is_empty() {
+        return Err("No jobs match the selection".to_string());
+    }
+    if packaging.running.swap(true, Ordering::SeqCst) {
+        return Err("Another package is being written".to_string());
+    }
+    let _running = RunningGuard(&packaging);
+    let token = CancellationToken::new();
+    *packaging.token.lock().unwrap() = token.clone();
+    println!("📦 Packaging {} jobs into {}", entries.len(), zip_path);
+
+    let worker = app.clone();
+    let summary = tauri::async_runtime::spawn_blocking(move || package(&worker, &entries, &zip_path, password.as_deref(), flatten, &token))
+        .await
+        .map_err(|e| e.to_string())??;
+    println!("📦 Wrote {} files ({} bytes) to {}", summary.files, summary.zip_bytes, summary.zip_path);
+    Ok(summary)
+}
+
+#[tauri::command]
+pub fn cancel_packaging(packaging: State<'_, Packaging>) {
+    packaging.token.lock().unwrap().cancel();
+}
@@ -0,0 +1,357 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::volumes;
+
+// ==========================================
+// CASE SENSITIVITY PER VOLUME
+// ==========================================
+// macOS (APFS/HFS+ default) and Windows (NTFS) treat `Clip.mp4` and
+// `clip.mp4` as the same file; Linux ext4 doesn't. We find out per mount by
+// creating a lowercase temp file and checking whether its uppercase name
+// resolves, and remember the answer for the session.
+fn cache() -> &'static Mutex<HashMap<String, bool>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, bool>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// What to assume when we can't probe (read-only dir, permission denied).
+fn platform_default() -> bool {
+    cfg!(any(target_os = "macos", target_os = "windows"))
+}
+
+fn probe_case_insensitive(dir: &Path) -> Option<bool> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.subsec_nanos();
+    let name = format!(".compress-io-case-{}-{}", std::process::id(), nanos);
+    let lower = dir.join(&name);
+    let upper = dir.join(name.to_uppercase());
+    fs::write(&lower, b"").ok()?;
+    let insensitive = upper.exists();
+    let _ = fs::remove_file(&lower);
+    Some(insensitive)
+}
+
+// `path` must already be resolved (see `resolve`).
+pub fn is_case_insensitive(path: &Path) -> bool {
+    let key = volumes::volume_of(&path.to_string_lossy())
+        .map(|v| v.mount_point)
+        .unwrap_or_default();
+    if let Some(known) = cache().lock().unwrap().get(&key) {
+        return *known;
+    }
+    let dir = path.ancestors().find(|p| p.is_dir()).unwrap_or(path);
+    let insensitive = probe_case_insensitive(dir).unwrap_or_else(platform_default);
+    cache().lock().unwrap().insert(key, insensitive);
+    insensitive
+}
+
+// Absolute, symlink-free version of `path` even when its tail doesn't exist
+// yet (outputs): canonicalizes the nearest existing ancestor and re-appends
+// the rest.
+pub fn resolve(path: &Path) -> Option<PathBuf> {
+    let mut missing: Vec<&std::ffi::OsStr> = vec![];
+    let mut current = path;
+    loop {
+        if let Ok(canonical) = current.canonicalize() {
+            let mut resolved = volumes::plain(&canonical);
+            for part in missing.iter().rev() {
+                resolved.push(part);
+            }
+            return Some(resolved);
+        }
+        missing.push(current.file_name()?);
+        current = current.parent()?;
+    }
+}
+
+// Component-wise comparison; `case_insensitive` folds each component with
+// Unicode lowercase so `FOO.MP4` == `foo.mp4`.
+pub fn paths_equal(a: &Path, b: &Path, case_insensitive: bool) -> bool {
+    let fold = |c: Component| {
+        let s = c.as_os_str().to_string_lossy().to_string();
+        if case_insensitive { s.to_lowercase() } else { s }
+    };
+    a.components().map(fold).eq(b.components().map(fold))
+}
+
+// True when `a` and `b` name the same file on disk (or would, once created).
+pub fn same_file(a: &str, b: &str) -> bool {
+    let (Some(a), Some(b)) = (resolve(Path::new(a)), resolve(Path::new(b))) else {
+        return false;
+    };
+    if a == b {
+        return true;
+    }
+    paths_equal(&a, &b, is_case_insensitive(&b))
+}
+
+// Guard for every command that writes a file derived from an input.
+pub fn ensure_not_input(input: &str, output: &str) -> Result<(), String> {
+    if same_file(input, output) {
+        return Err(format!("Output {} would overwrite the input file", output));
+    }
+    Ok(())
+}
+
+// Pure: `<prefix><stem><middle>.<ext>` (no dot without an extension). It's
+// put together from the OsStr pieces rather than format!, so a name that
+// isn't valid Unicode keeps its bytes instead of turning into U+FFFD.
+pub fn file_name(prefix: &str, stem: &OsStr, middle: &str, ext: Option<&OsStr>) -> OsString {
+    let mut name = OsString::from(prefix);
+    name.push(stem);
+    name.push(middle);
+    if let Some(ext) = ext {
+        name.push(".");
+        name.push(ext);
+    }
+    name
+}
+
+// `<dir>/<stem><suffix>.<ext>` next to `input`, or the same with `_2`, `_3`,
+// ... when that name is on disk already or `taken` says it's spoken for.
+pub fn unused_sibling(input: &Path, suffix: &str, ext: &str, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    let dir = input.parent().unwrap_or(Path::new(""));
+    let stem = input.file_stem().unwrap_or_default();
+    let mut n = 1;
+    loop {
+        let counter = if n == 1 { String::new() } else { format!("_{}", n) };
+        let candidate = dir.join(file_name("", stem, &format!("{}{}", suffix, counter), Some(OsStr::new(ext))));
+        if !candidate.exists() && !taken(&candidate) {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+// ==========================================
+// PATH SAFETY
+// ==========================================
+// Every job's input and output go through `secure` before anything runs.
+// Paths come back absolute and symlink-free, which also means ffmpeg can't
+// read one as an option (`-clip.mp4`) or a protocol (`concat:a|b`,
+// `pipe:1`): they all start with `/`, a drive or `\\server`. Relative paths
+// are refused, since the app's working directory is wherever it was
+// launched from. Windows paths lose the `\\?\` prefix canonicalize adds
+// (UNC shares become `\\server\share` again); Rust and ffmpeg both handle
+// long paths without it.
+//
+// Tauri hands us JSON strings, so names that aren't valid Unicode arrive
+// with U+FFFD in them and can't be found; the error says so instead of a
+// plain "not found". The same goes for an input whose symlink leads to such
+// a name. On Windows, output names that Win32 would quietly change (a
+// trailing dot or space is dropped) or refuse (`<>:"|?*`) are turned down
+// up front, with the reason.
+
+const ROOT_SYSTEM_DIRS: &[&str] = &["/bin", "/sbin", "/usr", "/etc", "/boot", "/dev", "/proc", "/sys", "/lib", "/lib64", "/System", "/private/etc"];
+const WINDOWS_SYSTEM_VARS: &[&str] = &["SystemRoot", "ProgramFiles", "ProgramFiles(x86)"];
+
+fn not_unicode(path: &str) -> bool {
+    path.contains('\u{FFFD}')
+}
+
+// Pure: why Windows can't create `name` as written, if it can't.
+fn windows_name_problem(name: &str) -> Option<&'static str> {
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Some("ends with a dot or a space, which Windows drops from file names");
+    }
+    name.contains(['<', '>', ':', '"', '|', '?', '*']).then_some("has one of < > : \" | ? * in it, which Windows doesn't allow in file names")
+}
+
+fn absolute(path: &str, what: &str) -> Result<PathBuf, String> {
+    let p = PathBuf::from(path.trim());
+    if path.trim().is_empty() {
+        return Err(format!("No {} file given", what));
+    }
+    // `\\?\C:\...` and `\\?\UNC\...` count as absolute too
+    if !p.is_absolute() && !path.starts_with(r"\\") {
+        return Err(format!("The {} path {} must be absolute", what, path));
+    }
+    Ok(p)
+}
+
+// Outputs can't go where the OS or the app keeps its own files.
+fn protected_dirs(app: &AppHandle) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = if cfg!(windows) {
+        WINDOWS_SYSTEM_VARS.iter().filter_map(std::env::var_os).map(PathBuf::from).collect()
+    } else {
+        ROOT_SYSTEM_DIRS.iter().map(PathBuf::from).collect()
+    };
+    dirs.extend(app.path().app_data_dir().ok());
+    dirs.extend(app.path().app_config_dir().ok());
+    dirs.iter().filter_map(|d| resolve(d)).collect()
+}
+
+fn inside(path: &Path, dir: &Path, case_insensitive: bool) -> bool {
+    let depth = dir.components().count();
+    path.components().count() > depth && paths_equal(&path.components().take(depth).collect::<PathBuf>(), dir, case_insensitive)
+}
+
+// The input as a path to hand on: it exists and is a regular file (not a
+// directory, a pipe ffmpeg would block on, or a symlink loop).
+pub fn secure_input(input: &str) -> Result<String, String> {
+    let path = absolute(input, "input")?;
+    let canonical = match path.canonicalize() {
+        Ok(p) => volumes::plain(&p),
+        Err(_) if not_unicode(input) => return Err(format!("{} has a name that isn't valid Unicode; rename it to pass it on", input)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(format!("Input file not found: {}", input)),
+        Err(e) => return Err(format!("The input {} can't be resolved: {}", input, e)),
+    };
+    let meta = fs::metadata(&canonical).map_err(|e| format!("The input {} can't be read: {}", input, e))?;
+    if meta.is_dir() {
+        return Err(format!("The input {} is a folder, not a file", input));
+    }
+    if !meta.is_file() {
+        return Err(format!("The input {} isn't a regular file (a device, pipe or socket)", input));
+    }
+    match canonical.to_str() {
+        Some(resolved) => Ok(resolved.to_string()),
+        None => Err(format!("{} leads to {}, a name that isn't valid Unicode; rename it to pass it on", input, canonical.display())),
+    }
+}
+
+// The output as a path to write to. Its folder has to exist, or is made
+// with `create_dirs`; it can't be a folder itself or lie in a protected
+// location.
+pub fn secure_output(app: &AppHandle, output: &str, create_dirs: bool) -> Result<String, String> {
+    let path = absolute(output, "output")?;
+    if not_unicode(output) {
+        return Err(format!("{} has a name that isn't valid Unicode", output));
+    }
+    if let Some(problem) = path.file_name().and_then(|n| n.to_str()).filter(|_| cfg!(windows)).and_then(windows_name_problem) {
+        return Err(format!("The output {} {}", output, problem));
+    }
+    let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).ok_or_else(|| format!("The output {} has no folder", output))?;
+    if !parent.exists() {
+        if !create_dirs {
+            return Err(format!("The output folder {} doesn't exist (set create_dirs to make it)", parent.display()));
+        }
+        fs::create_dir_all(parent).map_err(|e| format!("Couldn't create the output folder {}: {}", parent.display(), e))?;
+    } else if !parent.is_dir() {
+        return Err(format!("The output folder {} is a file", parent.display()));
+    }
+    let resolved = resolve(&path).ok_or_else(|| format!("The output {} can't be resolved", output))?;
+    if resolved.is_dir() {
+        return Err(format!("The output {} is a folder", output));
+    }
+    let case_insensitive = is_case_insensitive(&resolved);
+    // Straight into the root of a drive, or below a system folder
+    if resolved.parent().is_some_and(|p| p.parent().is_none()) || protected_dirs(app).iter().any(|dir| inside(&resolved, dir, case_insensitive)) {
+        return Err(format!("The output {} is in a protected location", output));
+    }
+    Ok(resolved.to_string_lossy().to_string())
+}
+
+// Both of a job's paths, checked and canonical.
+pub fn secure(app: &AppHandle, input: &str, output: &str, create_dirs: bool) -> Result<(String, String), String> {
+    Ok((secure_input(input)?, secure_output(app, output, create_dirs)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(prefix: &str, stem: &str, middle: &str, ext: Option<&str>) -> OsString {
+        file_name(prefix, OsStr::new(stem), middle, ext.map(OsStr::new))
+    }
+
+    #[test]
+    fn file_names_are_put_together_piece_by_piece() {
+        assert_eq!(name("", "clip", "_compressed", Some("mp4")), "clip_compressed.mp4");
+        assert_eq!(name(".", "clip", ".part", Some("mkv")), ".clip.part.mkv");
+        assert_eq!(name("", "README", " (1)", None), "README (1)");
+        assert_eq!(name("", "clip time=2", "", Some("mov")), "clip time=2.mov");
+        assert_eq!(name("", "Bob's \"best\" take", "_2", Some("mp4")), "Bob's \"best\" take_2.mp4");
+        assert_eq!(name("", "旅行 📹", "_compressed", Some("mp4")), "旅行 📹_compressed.mp4");
+        assert_eq!(name("", "trailing.", "", Some("")), "trailing..");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn names_that_arent_unicode_keep_their_bytes() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+        let stem = OsStr::from_bytes(b"caf\xe9");
+        let joined = file_name("", stem, "_compressed", Some(OsStr::new("mp4")));
+        assert_eq!(joined.into_vec(), b"caf\xe9_compressed.mp4".to_vec());
+    }
+
+    #[test]
+    fn windows_turns_down_trailing_dots_and_reserved_characters() {
+        let dropped = Some("ends with a dot or a space, which Windows drops from file names");
+        let refused = Some("has one of < > : \" | ? * in it, which Windows doesn't allow in file names");
+        assert_eq!(windows_name_problem("clip."), dropped);
+        assert_eq!(windows_name_problem("clip.mp4 "), dropped);
+        assert_eq!(windows_name_problem("..."), dropped);
+        assert_eq!(windows_name_problem("a:b.mp4"), refused);
+        assert_eq!(windows_name_problem("\"quoted\".mp4"), refused);
+        assert_eq!(windows_name_problem("what?.mp4"), refused);
+        assert_eq!(windows_name_problem("clip time=2.mov"), None);
+        assert_eq!(windows_name_problem("Bob's take.mp4"), None);
+        assert_eq!(windows_name_problem("旅行 📹.mp4"), None);
+        assert_eq!(windows_name_problem(".hidden"), None);
+    }
+
+    fn scratch(name: &str) -> crate::cancel::TempDir {
+        crate::cancel::TempDir::new(std::env::temp_dir().join(format!("paths-test-{}-{}", std::process::id(), name))).unwrap()
+    }
+
+    #[test]
+    fn case_folding_is_per_component() {
+        let (a, b) = (Path::new("/Volumes/Media/Clip.MP4"), Path::new("/volumes/media/clip.mp4"));
+        assert!(paths_equal(a, b, true));
+        assert!(!paths_equal(a, b, false));
+        assert!(paths_equal(Path::new("/a//b/./c"), Path::new("/a/b/c"), false));
+        assert!(!paths_equal(Path::new("/a/b"), Path::new("/a/b/c"), true));
+        assert!(paths_equal(Path::new("/Été/ÇA.mov"), Path::new("/été/ça.mov"), true));
+    }
+
+    #[test]
+    fn missing_tails_are_resolved_under_the_real_parent() {
+        let dir = scratch("resolve");
+        let real = resolve(dir.path()).unwrap();
+        assert_eq!(resolve(&dir.path().join("new/sub/out.mp4")), Some(real.join("new/sub/out.mp4")));
+        // A `..` in the missing part can't be resolved without guessing
+        assert_eq!(resolve(&dir.path().join("x/../out.mp4")), None);
+    }
+
+    #[test]
+    fn the_probe_answers_for_a_writable_dir_and_cleans_up() {
+        let dir = scratch("probe");
+        assert!(probe_case_insensitive(dir.path()).is_some());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+        assert_eq!(probe_case_insensitive(&dir.path().join("missing")), None);
+    }
+
+    #[test]
+    fn an_output_over_the_input_is_refused() {
+        let dir = scratch("alias");
+        let input = dir.path().join("clip.mp4");
+        fs::write(&input, b"x").unwrap();
+        let input = input.to_str().unwrap();
+        let spelled_differently = format!("{}/./clip.mp4", dir.path().display());
+        assert!(ensure_not_input(input, &spelled_differently).is_err());
+        assert!(ensure_not_input(input, &dir.path().join("clip_compressed.mp4").to_string_lossy()).is_ok());
+        // Different case is the same file only where the volume says so
+        let upper = dir.path().join("CLIP.mp4");
+        assert_eq!(same_file(input, &upper.to_string_lossy()), is_case_insensitive(&resolve(&upper).unwrap()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlink_to_the_input_is_the_input() {
+        let dir = scratch("symlink");
+        let input = dir.path().join("clip.mp4");
+        fs::write(&input, b"x").unwrap();
+        let link = dir.path().join("link.mp4");
+        std::os::unix::fs::symlink(&input, &link).unwrap();
+        assert!(same_file(&input.to_string_lossy(), &link.to_string_lossy()));
+        let linked_dir = dir.path().join("linked");
+        std::os::unix::fs::symlink(dir.path(), &linked_dir).unwrap();
+        assert!(ensure_not_input(&input.to_string_lossy(), &linked_dir.join("clip.mp4").to_string_lossy()).is_err());
+    }
+}
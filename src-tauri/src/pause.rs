@@ -0,0 +1,110 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::events::{self, Event};
+use crate::procgroup;
+use crate::queue;
+use crate::timeline;
+
+// ==========================================
+// PAUSE / RESUME
+// ==========================================
+// A 2-hour 4K encode doesn't have to be cancelled to get the machine back
+// for a while: the job's ffmpeg (and whatever it started) is suspended where
+// it is and picks up from the same frame later. Nothing is written while
+// it's stopped, so nothing is lost either.
+//
+// A job is only pausable while one of its ffmpeg runs is alive; a pass that
+// starts while its job is paused is suspended right away (see procgroup.rs).
+// ffmpeg's own speed figure counts the time it spent stopped, so the
+// progress tracker is told how much of the run was paused and leaves it out
+// of speed and ETA. Cancelling a paused job lets it go first, so it's
+// killed and cleaned up like any running job.
+
+#[derive(Default, Clone, Copy)]
+struct PauseClock {
+    since: Option<Instant>,
+    // Earlier pauses of the job
+    total: Duration,
+}
+
+#[derive(Default)]
+pub struct PausedJobs {
+    jobs: Mutex<HashMap<u64, PauseClock>>,
+}
+
+// Payload of `job-paused` / `job-resumed`.
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct JobPauseChanged {
+    pub job_id: u64,
+    // Everything the job has spent paused so far
+    pub paused_secs: f64,
+}
+
+pub fn is_paused(app: &AppHandle, job_id: u64) -> bool {
+    let Some(paused) = app.try_state::<PausedJobs>() else { return false };
+    let jobs = paused.jobs.lock().unwrap();
+    jobs.get(&job_id).is_some_and(|c| c.since.is_some())
+}
+
+// Time spent paused, including a pause still going on.
+pub fn paused_total(app: &AppHandle, job_id: u64) -> Duration {
+    let Some(paused) = app.try_state::<PausedJobs>() else { return Duration::ZERO };
+    let jobs = paused.jobs.lock().unwrap();
+    jobs.get(&job_id).map_or(Duration::ZERO, |c| c.total + c.since.map_or(Duration::ZERO, |s| s.elapsed()))
+}
+
+// A finished or cancelled job: resumed if it was paused, and forgotten.
+pub fn forget(app: &AppHandle, job_id: u64) {
+    let Some(paused) = app.try_state::<PausedJobs>() else { return };
+    let clock = paused.jobs.lock().unwrap().remove(&job_id);
+    if clock.is_some_and(|c| c.since.is_some()) {
+        procgroup::suspend_job(app, job_id, false);
+    }
+}
+
+// ==========================================
+// COMMANDS: PAUSE / RESUME
+// ==========================================
+#[tauri::command]
+pub fn pause_job(app: AppHandle, job_id: u64) -> Result<(), String> {
+    if is_paused(&app, job_id) {
+        return Err(format!("Job {} is paused already", job_id));
+    }
+    if procgroup::job_pids(&app, job_id).is_empty() {
+        return Err(format!("Job {} has no encode running right now", job_id));
+    }
+    if !procgroup::suspend_job(&app, job_id, true) {
+        return Err(format!("Job {}'s ffmpeg couldn't be suspended", job_id));
+    }
+    let paused = app.state::<PausedJobs>();
+    paused.jobs.lock().unwrap().entry(job_id).or_default().since = Some(Instant::now());
+    println!("⏸️ Job {} paused", job_id);
+    queue::set_paused(&app, job_id, true);
+    timeline::record_for(&app, job_id, timeline::PAUSED, &[]);
+    events::emit(&app, Event::JobPaused(JobPauseChanged { job_id, paused_secs: paused_total(&app, job_id).as_secs_f64() }));
+    Ok(())
+}
+
+#[tauri::command]
+pub fn resume_job(app: AppHandle, job_id: u64) -> Result<(), String> {
+    let paused = app.state::<PausedJobs>();
+    let pause = {
+        let mut jobs = paused.jobs.lock().unwrap();
+        let clock = jobs.get_mut(&job_id).filter(|c| c.since.is_some()).ok_or_else(|| format!("Job {} isn't paused", job_id))?;
+        let pause = clock.since.take().map_or(Duration::ZERO, |s| s.elapsed());
+        clock.total += pause;
+        pause
+    };
+    procgroup::suspend_job(&app, job_id, false);
+    println!("▶️ Job {} resumed after {:.0}s", job_id, pause.as_secs_f64());
+    queue::set_paused(&app, job_id, false);
+    timeline::record_for(&app, job_id, timeline::RESUMED, &[("paused_secs", format!("{:.0}", pause.as_secs_f64()))]);
+    events::emit(&app, Event::JobResumed(JobPauseChanged { job_id, paused_secs: paused_total(&app, job_id).as_secs_f64() }));
+    Ok(())
+}
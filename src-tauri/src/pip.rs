@@ -0,0 +1,323 @@
+use serde::Serialize;
+use std::fs;
+use std::time::Instant;
+
+use crate::AppHandle;
+use crate::events::Event;
+use crate::ffmpeg;
+use crate::history::{self, HistoryEntry};
+use crate::inputs;
+use crate::outputs;
+use crate::overlay::OverlayPosition;
+use crate::paths;
+use crate::plan;
+use crate::probe::{self, MediaInfo};
+use crate::progress::ProgressTracker;
+use crate::queue;
+use crate::request::{PipAudio, PipOptions, PipRequest};
+
+const MARGIN: u32 = 20;
+const ENCODER: &str = "libx264";
+
+// ==========================================
+// PICTURE-IN-PICTURE
+// ==========================================
+// A screen recording plus a webcam file in one corner, in one encode. The
+// overlay is lined up by start time (`overlay_offset_secs` moves it), scaled
+// relative to the main video, optionally with rounded corners, and dropped
+// once it runs out; the output always runs for the main input's length.
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PipResult {
+    // Both canonical (see paths::secure)
+    pub input: String,
+    pub output: String,
+    pub duration_secs: f64,
+    // Where the audio came from; None when the chosen source has none
+    pub audio_source: Option<PipAudio>,
+    pub warnings: Vec<String>,
+}
+
+// What the graph needs to know about the inputs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PipInputs {
+    pub main_width: u32,
+    pub main_has_audio: bool,
+    pub overlay_has_audio: bool,
+}
+
+// The finished graph and the labels the output maps.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PipGraph {
+    pub filter: String,
+    pub video: String,
+    // A `[label]` from the graph or a plain stream specifier; None = no audio
+    pub audio: Option<String>,
+}
+
+// Overlay position as overlay-filter x/y expressions.
+fn position(position: OverlayPosition) -> (String, String) {
+    let left = MARGIN.to_string();
+    let center = "(W-w)/2".to_string();
+    let right = format!("W-w-{}", MARGIN);
+    let top = MARGIN.to_string();
+    let bottom = format!("H-h-{}", MARGIN);
+    match position {
+        OverlayPosition::TopLeft => (left, top),
+        OverlayPosition::TopCenter => (center, top),
+        OverlayPosition::TopRight => (right, top),
+        OverlayPosition::BottomLeft => (left, bottom),
+        OverlayPosition::BottomCenter => (center, bottom),
+        OverlayPosition::BottomRight => (right, bottom),
+    }
+}
+
+// Alpha mask with corners cut to a radius of a tenth of the shorter side.
+fn rounding_mask() -> &'static str {
+    "format=yuva420p,geq=lum='p(X,Y)':cb='p(X,Y)':cr='p(X,Y)':\
+     a='if(gt(pow(max(0,max(min(W,H)/10-X,X-W+1+min(W,H)/10)),2)+pow(max(0,max(min(W,H)/10-Y,Y-H+1+min(W,H)/10)),2),pow(min(W,H)/10,2)),0,255)'"
+}
+
+// Errors name the audio source that can't be honoured.
+pub fn build_graph(options: &PipOptions, inputs: PipInputs) -> Result<PipGraph, String> {
+    // Even width, so 4:2:0 encoders take it
+    let width = ((inputs.main_width as u64 * options.scale_pct as u64 / 100).max(2) / 2 * 2) as u32;
+    let mut pip = format!("[1:v]scale={}:-2", width);
+    if options.rounded {
+        pip.push(',');
+        pip.push_str(rounding_mask());
+    }
+    pip.push_str("[pip]");
+    let (x, y) = position(options.position);
+    let mut parts = vec![pip, format!("[0:v][pip]overlay={}:{}:eof_action=pass[vout]", x, y)];
+
+    // The overlay's audio starts late when it's offset: fill the gap with silence
+    let overlay_audio = |volume: f64| format!("[1:a]aresample=async=1:first_pts=0,volume={:.3}", volume);
+    let audio = match options.audio_source {
+        PipAudio::Main if inputs.main_has_audio => Some("0:a:0".to_string()),
+        PipAudio::Main => None,
+        PipAudio::Overlay if inputs.overlay_has_audio => {
+            parts.push(format!("{}[aout]", overlay_audio(options.overlay_volume)));
+            Some("[aout]".to_string())
+        }
+        PipAudio::Overlay => return Err("The overlay has no audio to use".to_string()),
+        PipAudio::Mix if inputs.main_has_audio && inputs.overlay_has_audio => {
+            parts.push(format!("[0:a]volume={:.3}[amain]", options.main_volume));
+            parts.push(format!("{}[aover]", overlay_audio(options.overlay_volume)));
+            parts.push("[amain][aover]amix=inputs=2:duration=first:dropout_transition=0:normalize=0[aout]".to_string());
+            Some("[aout]".to_string())
+        }
+        PipAudio::Mix => return Err("Mixing needs audio in both the main input and the overlay".to_string()),
+    };
+    Ok(PipGraph { filter: parts.join(";"), video: "[vout]".to_string(), audio })
+}
+
+// Input options lining the overlay up: a later start is an input offset,
+// an earlier one skips into the overlay.
+fn overlay_input_args(offset_secs: f64, overlay: &str) -> Vec<String> {
+    let mut args = vec![];
+    if offset_secs > 0.0 {
+        args.extend(["-itsoffset".to_string(), format!("{:.3}", offset_secs)]);
+    } else if offset_secs < 0.0 {
+        args.extend(["-ss".to_string(), format!("{:.3}", -offset_secs)]);
+    }
+    args.extend(["-i".to_string(), overlay.to_string()]);
+    args
+}
+
+async fn probe_video(app: &AppHandle, path: &str, role: &str) -> Result<MediaInfo, String> {
+    inputs::preflight(path).map_err(|e| e.to_string())?;
+    let media = probe::probe(app, path).await?;
+    if !media.has_video {
+        return Err(format!("The {} input has no video stream", role));
+    }
+    Ok(media)
+}
+
+// ffmpeg writes `staged`; `request.output` is where it ends up.
+async fn encode_pip(app: &AppHandle, request: &PipRequest, staged: &str) -> Result<PipResult, String> {
+    paths::ensure_not_input(&request.input, &request.output)?;
+    paths::ensure_not_input(&request.overlay, &request.output)?;
+    let main = probe_video(app, &request.input, "main").await?;
+    let overlay = probe_video(app, &request.overlay, "overlay").await?;
+    let duration = main.duration.ok_or("Could not read the duration of the main input")?;
+    let main_width = main.width.ok_or("Could not read the size of the main input")?;
+
+    let graph = build_graph(&request.options, PipInputs {
+        main_width,
+        main_has_audio: main.has_audio,
+        overlay_has_audio: overlay.has_audio,
+    })?;
+    let mut warnings = vec![];
+    let offset = request.options.overlay_offset_secs;
+    if overlay.duration.is_some_and(|d| d + offset <= 0.0 || offset >= duration) {
+        warnings.push("The overlay doesn't overlap the main input at this offset, so it never shows".to_string());
+    }
+
+    let mut args = vec!["-i".to_string(), request.input.clone()];
+    args.extend(overlay_input_args(offset, &request.overlay));
+    args.extend(["-filter_complex".to_string(), graph.filter, "-map".to_string(), graph.video]);
+    match &graph.audio {
+        Some(audio) => args.extend(["-map".to_string(), audio.clone(), "-c:a".to_string(), "aac".to_string()]),
+        None => args.push("-an".to_string()),
+    }
+    args.extend([
+        "-c:v".to_string(), ENCODER.to_string(),
+        "-preset".to_string(), "medium".to_string(),
+        "-pix_fmt".to_string(), "yuv420p".to_string(),
+        "-t".to_string(), format!("{:.3}", duration),
+        "-y".to_string(), staged.to_string(),
+    ]);
+
+    queue::JobStarted {
+        encoder: Some(ENCODER.to_string()),
+        ..queue::JobStarted::new(&request.input, &request.output)
+    }
+    .emit(app);
+    println!("🖼️ Picture-in-picture: {} over {}", request.overlay, request.input);
+    ffmpeg::run_with_progress(app, args, ProgressTracker::for_duration(Some(duration)), Event::CompressionProgress).await?;
+
+    Ok(PipResult {
+        input: request.input.clone(),
+        output: request.output.clone(),
+        duration_secs: duration,
+        audio_source: graph.audio.is_some().then_some(request.options.audio_source),
+        warnings,
+    })
+}
+
+// Validation + encode + history record; shared by the command and the queue.
+pub async fn run_pip_job(app: &AppHandle, mut request: PipRequest) -> Result<PipResult, String> {
+    request.validate().map_err(|e| e.to_string())?;
+    (request.input, request.output) = paths::secure(app, &request.input, &request.output, request.create_dirs)?;
+    request.overlay = paths::secure_input(&request.overlay)?;
+    let started = Instant::now();
+    let mut reservation = outputs::claim_for_job(app, &request.output, None, None)?;
+    reservation.check_space(plan::estimated_output_bytes(app, "pip", fs::metadata(&request.input).map(|m| m.len()).unwrap_or(0)), false)?;
+    request.output = reservation.path_str();
+    let result = match encode_pip(app, &request, &reservation.staged_str()).await {
+        Ok(r) => reservation.commit().map(|path| PipResult { output: path.to_string_lossy().to_string(), ..r }),
+        Err(e) => Err(reservation.classify(e)),
+    };
+    let output = result.as_ref().map_or(request.output.clone(), |r| r.output.clone());
+    let mut entry = HistoryEntry::finished("pip", &request.input, &output, started, result.as_ref().err().cloned())
+        .with_annotations(&request.annotations);
+    if let Ok(r) = &result {
+        entry.encoder = Some(ENCODER.to_string());
+        entry.duration_secs = Some(r.duration_secs);
+        entry.warnings = r.warnings.clone();
+    }
+    history::record(app, entry);
+    result
+}
+
+// ==========================================
+// COMMAND: COMPOSE PICTURE-IN-PICTURE
+// ==========================================
+// The queue takes the same thing as a `pip` job spec.
+#[tauri::command]
+pub async fn compose_pip(
+    app: AppHandle,
+    main_input: String,
+    overlay_input: String,
+    output: String,
+    options: Option<PipOptions>,
+    create_dirs: Option<bool>,
+) -> Result<PipResult, String> {
+    let request = PipRequest {
+        version: crate::request::REQUEST_VERSION,
+        input: main_input,
+        overlay: overlay_input,
+        output,
+        create_dirs: create_dirs.unwrap_or(false),
+        options: options.unwrap_or_default(),
+        annotations: Default::default(),
+    };
+    run_pip_job(&app, request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOTH_AUDIO: PipInputs = PipInputs { main_width: 1920, main_has_audio: true, overlay_has_audio: true };
+
+    fn graph(options: PipOptions, inputs: PipInputs) -> PipGraph {
+        build_graph(&options, inputs).unwrap()
+    }
+
+    #[test]
+    fn each_corner_places_the_overlay_a_margin_in() {
+        let corners = [
+            (OverlayPosition::TopLeft, "overlay=20:20:"),
+            (OverlayPosition::TopCenter, "overlay=(W-w)/2:20:"),
+            (OverlayPosition::TopRight, "overlay=W-w-20:20:"),
+            (OverlayPosition::BottomLeft, "overlay=20:H-h-20:"),
+            (OverlayPosition::BottomCenter, "overlay=(W-w)/2:H-h-20:"),
+            (OverlayPosition::BottomRight, "overlay=W-w-20:H-h-20:"),
+        ];
+        for (position, overlay) in corners {
+            let g = graph(PipOptions { position, ..Default::default() }, BOTH_AUDIO);
+            assert_eq!(g.filter, format!("[1:v]scale=480:-2[pip];[0:v][pip]{}eof_action=pass[vout]", overlay), "{:?}", position);
+            assert_eq!(g.video, "[vout]");
+        }
+    }
+
+    #[test]
+    fn the_overlay_is_scaled_to_an_even_share_of_the_main_width() {
+        let scaled = |main_width: u32, scale_pct: u32| {
+            let g = graph(PipOptions { scale_pct, ..Default::default() }, PipInputs { main_width, ..BOTH_AUDIO });
+            g.filter.split("[pip]").next().unwrap().to_string()
+        };
+        assert_eq!(scaled(1920, 25), "[1:v]scale=480:-2");
+        assert_eq!(scaled(1280, 33), "[1:v]scale=422:-2");
+        assert_eq!(scaled(1366, 5), "[1:v]scale=68:-2");
+        assert_eq!(scaled(1920, 100), "[1:v]scale=1920:-2");
+        // Never below two pixels
+        assert_eq!(scaled(20, 5), "[1:v]scale=2:-2");
+    }
+
+    #[test]
+    fn rounded_corners_cut_the_overlay_with_an_alpha_mask() {
+        let g = graph(PipOptions { rounded: true, ..Default::default() }, BOTH_AUDIO);
+        assert!(g.filter.starts_with("[1:v]scale=480:-2,format=yuva420p,geq="), "{}", g.filter);
+        assert!(g.filter.contains("[pip];[0:v][pip]overlay="));
+    }
+
+    #[test]
+    fn audio_comes_from_the_chosen_source() {
+        let main = graph(PipOptions::default(), BOTH_AUDIO);
+        assert_eq!(main.audio.as_deref(), Some("0:a:0"));
+        assert!(!main.filter.contains("[aout]"));
+
+        let overlay = graph(PipOptions { audio_source: PipAudio::Overlay, overlay_volume: 0.5, ..Default::default() }, BOTH_AUDIO);
+        assert_eq!(overlay.audio.as_deref(), Some("[aout]"));
+        assert!(overlay.filter.ends_with(";[1:a]aresample=async=1:first_pts=0,volume=0.500[aout]"), "{}", overlay.filter);
+
+        let mix = graph(PipOptions { audio_source: PipAudio::Mix, main_volume: 0.8, overlay_volume: 1.5, ..Default::default() }, BOTH_AUDIO);
+        assert_eq!(mix.audio.as_deref(), Some("[aout]"));
+        assert!(mix.filter.contains(";[0:a]volume=0.800[amain];[1:a]aresample=async=1:first_pts=0,volume=1.500[aover];"));
+        assert!(mix.filter.ends_with("[amain][aover]amix=inputs=2:duration=first:dropout_transition=0:normalize=0[aout]"));
+    }
+
+    #[test]
+    fn an_overlay_without_audio_only_works_with_the_main_audio() {
+        let silent_overlay = PipInputs { overlay_has_audio: false, ..BOTH_AUDIO };
+        let g = graph(PipOptions::default(), silent_overlay);
+        assert_eq!(g.audio.as_deref(), Some("0:a:0"));
+        assert!(!g.filter.contains("[1:a]"));
+        for source in [PipAudio::Overlay, PipAudio::Mix] {
+            assert!(build_graph(&PipOptions { audio_source: source, ..Default::default() }, silent_overlay).is_err(), "{:?}", source);
+        }
+        // Both silent: a video-only output
+        let g = graph(PipOptions::default(), PipInputs { main_has_audio: false, ..silent_overlay });
+        assert_eq!(g.audio, None);
+    }
+
+    #[test]
+    fn the_offset_delays_or_skips_into_the_overlay() {
+        assert_eq!(overlay_input_args(0.0, "cam.mp4"), ["-i", "cam.mp4"]);
+        assert_eq!(overlay_input_args(2.5, "cam.mp4"), ["-itsoffset", "2.500", "-i", "cam.mp4"]);
+        assert_eq!(overlay_input_args(-1.25, "cam.mp4"), ["-ss", "1.250", "-i", "cam.mp4"]);
+    }
+}
@@ -0,0 +1,447 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{Manager, State};
+use tokio_util::sync::CancellationToken;
+
+use crate::AppHandle;
+use crate::cancel;
+use crate::events::{self, Event};
+use crate::explain::{self, Explanation};
+use crate::history::{HistoryEntry, HistoryStore, JobStatus};
+use crate::probe;
+use crate::queue::{self, JobSpec, Priority};
+use crate::settings::SettingsStore;
+use crate::subtitles::{self, SidecarSub};
+use crate::volumes;
+
+pub const DEFAULT_PLAN_TTL_MINUTES: u64 = 60;
+// Probes running at once while planning
+const PLAN_WORKERS: usize = 4;
+// Recent successful jobs per kind the estimates are based on
+const ESTIMATE_SAMPLE: usize = 200;
+// Input read per wall second with no history yet: an 8 Mbit/s video
+// encoded at realtime, audio at ~30x
+const DEFAULT_VIDEO_BYTES_PER_SEC: f64 = 1_000_000.0;
+const DEFAULT_AUDIO_BYTES_PER_SEC: f64 = 5_000_000.0;
+
+// ==========================================
+// BATCH PLANS (DRY RUN)
+// ==========================================
+// Before a 400-file run, `plan_batch` does only the analysis: probe every
+// input, estimate its output size and encode time, and add up what the run
+// will need. The plan is kept, so `execute_plan` enqueues exactly those jobs
+// without probing again. It stops being executable once it's older than
+// `plan_ttl_minutes` or any input changed on disk since it was made.
+//
+// Estimates come from this machine's own history (output/input ratio and
+// encode speed of recent jobs of the same kind), with rough defaults until
+// there is some.
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EstimateBasis {
+    History,
+    Default,
+}
+
+// Per-kind model the file estimates use.
+#[derive(Serialize, Clone, Debug)]
+pub struct KindEstimate {
+    pub kind: String,
+    pub basis: EstimateBasis,
+    // History entries it was computed from
+    pub samples: usize,
+    // output / input bytes
+    pub size_ratio: f64,
+    // Media seconds encoded per wall second (video, audio)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+    // Wall seconds per file (images, and media without a duration)
+    pub secs_per_file: f64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PlannedFile {
+    pub kind: String,
+    pub input: String,
+    pub output: String,
+    pub input_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<f64>,
+    pub estimated_output_bytes: u64,
+    pub estimated_wall_secs: f64,
+    // Analysis failed; the file is left out when the plan is executed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // What the encode is expected to decide, and why (video jobs)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub explanations: Vec<Explanation>,
+    // Subtitle files found next to a video input, whether or not the job
+    // takes them (see include_sidecar_subs)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sidecar_subs: Vec<SidecarSub>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BatchPlan {
+    pub plan_id: u64,
+    // In request order
+    pub files: Vec<PlannedFile>,
+    pub total_input_bytes: u64,
+    pub estimated_output_bytes: u64,
+    // Spread over the queue's concurrency limit
+    pub estimated_wall_secs: f64,
+    // Outputs plus the biggest local staging copy (removable destinations)
+    pub peak_disk_bytes: u64,
+    pub estimates: Vec<KindEstimate>,
+    // Unix seconds
+    pub expires_at: u64,
+}
+
+// Payload of `plan-progress`.
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct PlanProgress {
+    plan_id: u64,
+    done: usize,
+    total: usize,
+}
+
+// Size and mtime at analysis time, compared again on execution.
+#[derive(Clone, PartialEq)]
+struct InputStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+fn stamp(path: &str) -> Option<InputStamp> {
+    let meta = fs::metadata(path).ok()?;
+    Some(InputStamp { len: meta.len(), modified: meta.modified().ok() })
+}
+
+struct StoredPlan {
+    specs: Vec<JobSpec>,
+    priority: Option<Priority>,
+    stamps: Vec<Option<InputStamp>>,
+    // Files whose analysis failed aren't enqueued
+    skip: Vec<bool>,
+    created: Instant,
+}
+
+// ==========================================
+// PLAN STORE (managed state)
+// ==========================================
+#[derive(Default)]
+pub struct PlanStore {
+    next_id: Mutex<u64>,
+    plans: Mutex<HashMap<u64, StoredPlan>>,
+    // Plans still being analyzed, for cancel_plan
+    analyzing: Mutex<HashMap<u64, CancellationToken>>,
+}
+
+impl PlanStore {
+    fn allocate(&self) -> u64 {
+        let mut next = self.next_id.lock().unwrap();
+        *next += 1;
+        *next
+    }
+}
+
+fn ttl(app: &AppHandle) -> Duration {
+    let minutes = app.try_state::<SettingsStore>().map_or(DEFAULT_PLAN_TTL_MINUTES, |s| s.get().plan_ttl_minutes);
+    Duration::from_secs(minutes.max(1) * 60)
+}
+
+// --- ESTIMATES ---
+fn default_estimate(kind: &str) -> KindEstimate {
+    let (size_ratio, speed, secs_per_file) = match kind {
+        "video" => (0.4, Some(1.0), 60.0),
+        "audio" => (0.3, Some(30.0), 5.0),
+        "pip" => (0.5, Some(0.7), 60.0),
+        _ => (0.5, None, 0.5),
+    };
+    KindEstimate { kind: kind.to_string(), basis: EstimateBasis::Default, samples: 0, size_ratio, speed, secs_per_file }
+}
+
+fn recent<'a>(kind: &str, history: &'a [HistoryEntry]) -> Vec<&'a HistoryEntry> {
+    history
+        .iter()
+        .rev()
+        .filter(|e| e.kind == kind && e.status == JobStatus::Success && !e.partial && !e.simulated && e.input_bytes > 0)
+        .take(ESTIMATE_SAMPLE)
+        .collect()
+}
+
+fn estimate_for(kind: &str, history: &[HistoryEntry]) -> KindEstimate {
+    let recent = recent(kind, history);
+    let mut estimate = default_estimate(kind);
+    if recent.is_empty() {
+        return estimate;
+    }
+    let input: u64 = recent.iter().map(|e| e.input_bytes).sum();
+    let output: u64 = recent.iter().map(|e| e.output_bytes).sum();
+    estimate.basis = EstimateBasis::History;
+    estimate.samples = recent.len();
+    estimate.size_ratio = output as f64 / input as f64;
+    estimate.secs_per_file = recent.iter().map(|e| e.wall_time_secs).sum::<f64>() / recent.len() as f64;
+
+    let timed: Vec<(f64, f64)> = recent.iter().filter_map(|e| Some((e.duration_secs?, e.wall_time_secs))).collect();
+    let wall: f64 = timed.iter().map(|(_, w)| w).sum();
+    if estimate.speed.is_some() && wall > 0.0 {
+        estimate.speed = Some(timed.iter().map(|(d, _)| d).sum::<f64>() / wall);
+    }
+    estimate
+}
+
+// (output bytes, wall seconds)
+fn predicted(estimate: &KindEstimate, input_bytes: u64, duration_secs: Option<f64>) -> (u64, f64) {
+    let bytes = (input_bytes as f64 * estimate.size_ratio).round() as u64;
+    let secs = match (duration_secs, estimate.speed) {
+        (Some(d), Some(speed)) if speed > 0.0 => d / speed,
+        _ => estimate.secs_per_file,
+    };
+    (bytes, secs)
+}
+
+fn estimate_file(file: &mut PlannedFile, estimate: &KindEstimate) {
+    (file.estimated_output_bytes, file.estimated_wall_secs) = predicted(estimate, file.input_bytes, file.duration_secs);
+}
+
+// Size and time for an input that's been probed already (preview_preset).
+pub fn estimate_probed(app: &AppHandle, kind: &str, input_bytes: u64, duration_secs: Option<f64>) -> (u64, f64, EstimateBasis) {
+    let history = app.try_state::<HistoryStore>().map(|h| h.all()).unwrap_or_default();
+    let estimate = estimate_for(kind, &history);
+    let (bytes, secs) = predicted(&estimate, input_bytes, duration_secs);
+    (bytes, secs, estimate.basis)
+}
+
+// What a `kind` job usually makes of `input_bytes` here (the disk-space
+// preflight's guess, see outputs.rs). With no history to go on it's the
+// input's size: a guess too low is what runs a disk full halfway through.
+pub fn estimated_output_bytes(app: &AppHandle, kind: &str, input_bytes: u64) -> u64 {
+    let history = app.try_state::<HistoryStore>().map(|h| h.all()).unwrap_or_default();
+    let estimate = estimate_for(kind, &history);
+    match estimate.basis {
+        EstimateBasis::History => (input_bytes as f64 * estimate.size_ratio).round() as u64,
+        EstimateBasis::Default => input_bytes,
+    }
+}
+
+// Wall time of a `kind` job without probing it (the queue's express lane,
+// decided at enqueue time): its input at the rate recent jobs read theirs.
+pub fn estimated_wall_secs(app: &AppHandle, kind: &str, input_bytes: u64) -> f64 {
+    let history = app.try_state::<HistoryStore>().map(|h| h.all()).unwrap_or_default();
+    let recent = recent(kind, &history);
+    let input: u64 = recent.iter().map(|e| e.input_bytes).sum();
+    let wall: f64 = recent.iter().map(|e| e.wall_time_secs).sum();
+    let rate = match kind {
+        _ if wall > 0.0 && input > 0 => input as f64 / wall,
+        "image" => return default_estimate(kind).secs_per_file,
+        "audio" => DEFAULT_AUDIO_BYTES_PER_SEC,
+        _ => DEFAULT_VIDEO_BYTES_PER_SEC,
+    };
+    input_bytes as f64 / rate
+}
+
+// One job's estimate outside a plan (the night planner's), made the same way.
+pub async fn estimate_spec(app: &AppHandle, spec: &JobSpec, history: &[HistoryEntry]) -> PlannedFile {
+    let mut file = analyze(app, spec).await;
+    if file.error.is_none() {
+        let estimate = estimate_for(&file.kind, history);
+        estimate_file(&mut file, &estimate);
+    }
+    file
+}
+
+// --- ANALYSIS ---
+async fn analyze(app: &AppHandle, spec: &JobSpec) -> PlannedFile {
+    let mut file = PlannedFile {
+        kind: spec.kind().to_string(),
+        input: spec.input().to_string(),
+        output: spec.output().to_string(),
+        input_bytes: fs::metadata(spec.input()).map(|m| m.len()).unwrap_or(0),
+        duration_secs: None,
+        estimated_output_bytes: 0,
+        estimated_wall_secs: 0.0,
+        error: None,
+        explanations: vec![],
+        sidecar_subs: vec![],
+    };
+    if let Err(e) = spec.validate() {
+        file.error = Some(e.to_string());
+        return file;
+    }
+    if !matches!(spec, JobSpec::Image(_)) {
+        match probe::probe(app, spec.input()).await {
+            Ok(media) => {
+                file.duration_secs = media.duration;
+                if let JobSpec::Video(request) = spec {
+                    file.explanations = explain::predict(app, request, &media).await;
+                    file.sidecar_subs = subtitles::discover(spec.input());
+                }
+            }
+            Err(e) => file.error = Some(e),
+        }
+    }
+    file
+}
+
+// A few workers pull the next index until none is left; all of them stop
+// at the first cancellation.
+async fn analyze_all(app: &AppHandle, plan_id: u64, specs: Arc<Vec<JobSpec>>, token: CancellationToken) -> Result<Vec<PlannedFile>, String> {
+    let total = specs.len();
+    let next = Arc::new(Mutex::new(0usize));
+    let results: Arc<Mutex<Vec<Option<PlannedFile>>>> = Arc::new(Mutex::new(vec![None; total]));
+    let mut workers = vec![];
+    for _ in 0..PLAN_WORKERS.min(total.max(1)) {
+        let (app, specs, next, results, token) = (app.clone(), specs.clone(), next.clone(), results.clone(), token.clone());
+        workers.push(tauri::async_runtime::spawn(cancel::scope(token, async move {
+            loop {
+                if cancel::is_cancelled() {
+                    return;
+                }
+                let i = {
+                    let mut next = next.lock().unwrap();
+                    let i = *next;
+                    *next += 1;
+                    i
+                };
+                let Some(spec) = specs.get(i) else { return };
+                let file = analyze(&app, spec).await;
+                let done = {
+                    let mut results = results.lock().unwrap();
+                    results[i] = Some(file);
+                    results.iter().filter(|r| r.is_some()).count()
+                };
+                events::emit(&app, Event::PlanProgress(PlanProgress { plan_id, done, total }));
+            }
+        })));
+    }
+    for worker in workers {
+        let _ = worker.await;
+    }
+    if token.is_cancelled() {
+        return Err(cancel::CANCELLED.to_string());
+    }
+    let results = std::mem::take(&mut *results.lock().unwrap());
+    Ok(results.into_iter().flatten().collect())
+}
+
+fn now_unix() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// ==========================================
+// COMMAND: PLAN BATCH
+// ==========================================
+// Progress arrives on `plan-progress` with the plan id, which is also what
+// cancel_plan takes.
+#[tauri::command]
+pub async fn plan_batch(
+    app: AppHandle,
+    plans: State<'_, PlanStore>,
+    specs: Vec<JobSpec>,
+    priority: Option<Priority>,
+) -> Result<BatchPlan, String> {
+    let ttl = ttl(&app);
+    plans.plans.lock().unwrap().retain(|_, p| p.created.elapsed() < ttl);
+    let plan_id = plans.allocate();
+    let token = CancellationToken::new();
+    plans.analyzing.lock().unwrap().insert(plan_id, token.clone());
+
+    let stamps: Vec<Option<InputStamp>> = specs.iter().map(|s| stamp(s.input())).collect();
+    let specs = Arc::new(specs);
+    let analyzed = analyze_all(&app, plan_id, specs.clone(), token).await;
+    plans.analyzing.lock().unwrap().remove(&plan_id);
+    let mut files = analyzed?;
+
+    let history = app.try_state::<HistoryStore>().map(|h| h.all()).unwrap_or_default();
+    let estimates: Vec<KindEstimate> = ["video", "image", "audio", "pip"]
+        .into_iter()
+        .filter(|kind| files.iter().any(|f| f.kind == *kind))
+        .map(|kind| estimate_for(kind, &history))
+        .collect();
+    for file in files.iter_mut().filter(|f| f.error.is_none()) {
+        if let Some(estimate) = estimates.iter().find(|e| e.kind == file.kind) {
+            estimate_file(file, estimate);
+        }
+    }
+
+    let planned = || files.iter().filter(|f| f.error.is_none());
+    let estimated_output_bytes: u64 = planned().map(|f| f.estimated_output_bytes).sum();
+    let staging = planned()
+        .filter(|f| volumes::volume_of(&f.output).is_some_and(|v| v.removable))
+        .map(|f| f.estimated_output_bytes)
+        .max()
+        .unwrap_or(0);
+    let parallel = queue::max_concurrent(&app).max(1) as f64;
+    let plan = BatchPlan {
+        plan_id,
+        total_input_bytes: planned().map(|f| f.input_bytes).sum(),
+        estimated_output_bytes,
+        estimated_wall_secs: planned().map(|f| f.estimated_wall_secs).sum::<f64>() / parallel,
+        peak_disk_bytes: estimated_output_bytes + staging,
+        estimates,
+        expires_at: now_unix() + ttl.as_secs(),
+        files,
+    };
+
+    let specs = Arc::try_unwrap(specs).unwrap_or_else(|shared| (*shared).clone());
+    plans.plans.lock().unwrap().insert(plan_id, StoredPlan {
+        specs,
+        priority,
+        stamps,
+        skip: plan.files.iter().map(|f| f.error.is_some()).collect(),
+        created: Instant::now(),
+    });
+    println!("📋 Plan {}: {} files, ~{} s", plan_id, plan.files.len(), plan.estimated_wall_secs.round());
+    Ok(plan)
+}
+
+// ==========================================
+// COMMAND: CANCEL PLAN
+// ==========================================
+#[tauri::command]
+pub fn cancel_plan(plans: State<'_, PlanStore>, plan_id: u64) {
+    if let Some(token) = plans.analyzing.lock().unwrap().get(&plan_id) {
+        token.cancel();
+    }
+}
+
+// ==========================================
+// COMMAND: EXECUTE PLAN
+// ==========================================
+// Enqueues the plan's jobs as they were analyzed. A plan is used at most once.
+#[tauri::command]
+pub fn execute_plan(app: AppHandle, plans: State<'_, PlanStore>, plan_id: u64) -> Result<Vec<u64>, String> {
+    let plan = plans.plans.lock().unwrap().remove(&plan_id).ok_or_else(|| format!("Plan {} not found", plan_id))?;
+    if plan.created.elapsed() >= ttl(&app) {
+        return Err(format!("Plan {} has expired; plan the batch again", plan_id));
+    }
+    let changed: Vec<&str> = plan
+        .specs
+        .iter()
+        .zip(&plan.stamps)
+        .filter(|(spec, old)| stamp(spec.input()) != **old)
+        .map(|(spec, _)| spec.input())
+        .collect();
+    if !changed.is_empty() {
+        return Err(format!("Inputs changed since the plan was made ({}); plan the batch again", changed.join(", ")));
+    }
+    let specs: Vec<JobSpec> = plan.specs.into_iter().zip(plan.skip).filter(|(_, skip)| !skip).map(|(s, _)| s).collect();
+    queue::enqueue(&app, specs, plan.priority)
+}
+
+// ==========================================
+// COMMAND: PLAN LIFETIME
+// ==========================================
+#[tauri::command]
+pub fn set_plan_ttl(store: State<'_, SettingsStore>, minutes: u64) -> Result<(), String> {
+    if minutes == 0 {
+        return Err("Plans need a lifetime of at least one minute".to_string());
+    }
+    store.update(|s| s.plan_ttl_minutes = minutes).map(|_| ())
+}
@@ -0,0 +1,940 @@
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::AppHandle;
+use crate::duration::Expected;
+use crate::encoders;
+use crate::ffmpeg;
+use crate::inputs;
+use crate::paths;
+use crate::probe::{self, MediaInfo, StreamInfo};
+use crate::request::{VideoCompressRequest, VideoOptions};
+use crate::staging;
+use crate::support;
+use crate::verify;
+use crate::VideoMode;
+
+const REMUX_SUFFIX: &str = "_remux";
+const FIX_SUFFIX: &str = "_fixed";
+// For an audio track auto_fix_output has to re-encode
+const FIX_AUDIO_BITRATE: &str = "160k";
+// Containers where the moov atom's place matters (and -movflags applies)
+const MP4_FAMILY: &[&str] = &["mp4", "m4v", "mov"];
+
+// ==========================================
+// PLAYABILITY PREFLIGHT
+// ==========================================
+// "It won't play, please compress it" is often a container problem: a TS
+// capture, an MKV the TV refuses. The streams inside are fine, and a remux
+// takes seconds where a re-encode takes as long as the video. This checks
+// the streams against what a kind of player takes and says which of the
+// three it is. The remux it offers is a normal copy_only request, checked
+// by the same validation as anything enqueued, so it runs as offered.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetProfile {
+    // Browsers: <video> in Chrome, Firefox, Safari
+    Web,
+    // Smart TVs and set-top boxes from a USB stick / DLNA
+    Tv,
+    // iOS and Android players
+    Mobile,
+    // PowerPoint, Outlook and Windows' own player: the most conservative
+    Office,
+}
+
+impl TargetProfile {
+    // For messages
+    pub fn label(self) -> &'static str {
+        match self {
+            TargetProfile::Web => "web",
+            TargetProfile::Tv => "TV",
+            TargetProfile::Mobile => "mobile",
+            TargetProfile::Office => "Office",
+        }
+    }
+}
+
+// What one kind of player takes. Containers are in order of preference for
+// a remux; the first that holds the video codec is used.
+pub struct PlaybackProfile {
+    pub target: TargetProfile,
+    pub containers: &'static [&'static str],
+    pub video: &'static [&'static str],
+    pub audio: &'static [&'static str],
+    pub pix_fmts: &'static [&'static str],
+    // Highest H.264 level (ffprobe's number: 41 = 4.1)
+    pub max_h264_level: i32,
+    pub h264_profiles: &'static [&'static str],
+    // AAC profiles as ffprobe names them; empty = any
+    pub aac_profiles: &'static [&'static str],
+    // Players that stream the file need the index (moov) up front
+    pub faststart: bool,
+    pub max_sample_rate: Option<u32>,
+    // Longer side of the picture as shown, in pixels
+    pub max_long_edge: Option<u32>,
+}
+
+const EIGHT_BIT: &[&str] = &["yuv420p", "yuvj420p"];
+// 8-bit 4:2:0 H.264; High 10 / 4:2:2 / 4:4:4 are the ones hardware decoders skip.
+// Simplest first: encode_args asks for the last one.
+const H264_PROFILES: &[&str] = &["Constrained Baseline", "Baseline", "Main", "High"];
+
+pub const PROFILES: &[PlaybackProfile] = &[
+    PlaybackProfile {
+        target: TargetProfile::Web,
+        containers: &["mp4", "webm", "m4v"],
+        video: &["h264", "vp9", "av1"],
+        audio: &["aac", "mp3", "opus"],
+        pix_fmts: EIGHT_BIT,
+        max_h264_level: 51,
+        h264_profiles: H264_PROFILES,
+        aac_profiles: &[],
+        faststart: true,
+        max_sample_rate: None,
+        max_long_edge: None,
+    },
+    PlaybackProfile {
+        target: TargetProfile::Tv,
+        containers: &["mp4", "ts"],
+        video: &["h264", "hevc"],
+        audio: &["aac", "ac3", "eac3", "mp3"],
+        pix_fmts: &["yuv420p", "yuvj420p", "yuv420p10le"],
+        max_h264_level: 41,
+        h264_profiles: H264_PROFILES,
+        aac_profiles: &["LC"],
+        // Played from the stick, seeking is cheap
+        faststart: false,
+        max_sample_rate: None,
+        max_long_edge: None,
+    },
+    PlaybackProfile {
+        target: TargetProfile::Mobile,
+        containers: &["mp4", "mov", "m4v"],
+        video: &["h264", "hevc"],
+        audio: &["aac"],
+        pix_fmts: EIGHT_BIT,
+        max_h264_level: 42,
+        h264_profiles: H264_PROFILES,
+        aac_profiles: &["LC", "HE-AAC", "HE-AACv2"],
+        // iMessage and share sheets stream the file
+        faststart: true,
+        max_sample_rate: None,
+        max_long_edge: None,
+    },
+    // Media Foundation as Office embeds it: no High profile on older
+    // installs, nothing above 48 kHz or 1080p
+    PlaybackProfile {
+        target: TargetProfile::Office,
+        containers: &["mp4"],
+        video: &["h264"],
+        audio: &["aac"],
+        pix_fmts: &["yuv420p"],
+        max_h264_level: 41,
+        h264_profiles: &["Constrained Baseline", "Baseline", "Main"],
+        aac_profiles: &["LC"],
+        // Outlook attachments and PowerPoint links play as they load
+        faststart: true,
+        max_sample_rate: Some(48000),
+        max_long_edge: Some(1920),
+    },
+];
+
+pub fn profile(target: TargetProfile) -> &'static PlaybackProfile {
+    PROFILES.iter().find(|p| p.target == target).expect("every target has a profile")
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "recommendation", rename_all = "snake_case")]
+pub enum Recommendation {
+    AlreadyCompatible,
+    // `spec` can be enqueued as it is. With `audio_reencoded` only the video
+    // is copied (video_mode "copy"): the audio codec doesn't play there, or
+    // the target container can't hold it
+    RemuxSufficient { spec: Box<VideoCompressRequest>, audio_reencoded: bool },
+    ReencodeNeeded { reasons: Vec<String> },
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PlayabilityReport {
+    pub target: TargetProfile,
+    pub container: String,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    #[serde(flatten)]
+    pub recommendation: Recommendation,
+}
+
+fn first<'a>(media: &'a MediaInfo, kind: &str) -> Option<&'a StreamInfo> {
+    media.streams.iter().find(|s| s.codec_type == kind && !s.attached_pic)
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    // Won't play
+    Error,
+    // Plays, badly (a streamed file that can't start until it's all there)
+    Warning,
+}
+
+// The least it takes to make the rule pass.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Fix {
+    // Streams copied into another container / with the index moved
+    Remux,
+    // Video copied, audio encoded again
+    ReencodeAudio,
+    ReencodeVideo,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Violation {
+    // "container", "video_codec", "pix_fmt", "h264_profile", "h264_level",
+    // "frame_size", "audio_codec", "aac_profile", "sample_rate", "faststart"
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub fix: Fix,
+}
+
+impl Violation {
+    fn new(rule: &'static str, severity: Severity, fix: Fix, message: String) -> Self {
+        Violation { rule, severity, message, fix }
+    }
+}
+
+// Why the video stream itself can't play there; empty when it can.
+fn video_violations(profile: &PlaybackProfile, video: &StreamInfo) -> Vec<Violation> {
+    let mut found = vec![];
+    let mut add = |rule, message| found.push(Violation::new(rule, Severity::Error, Fix::ReencodeVideo, message));
+    let codec = video.codec_name.as_deref().unwrap_or("unknown");
+    if !profile.video.contains(&codec) {
+        add("video_codec", format!("{} video doesn't play there (needs {})", codec, profile.video.join(", ")));
+    }
+    if let Some(pix) = video.pix_fmt.as_deref().filter(|p| !profile.pix_fmts.contains(p)) {
+        add("pix_fmt", format!("{} pixels aren't supported (needs {})", pix, profile.pix_fmts.join(", ")));
+    }
+    if let Some(p) = video.profile.as_deref().filter(|p| codec == "h264" && !profile.h264_profiles.contains(p)) {
+        add("h264_profile", format!("H.264 {} profile isn't decoded there (needs {})", p, profile.h264_profiles.join(", ")));
+    }
+    if let Some(level) = video.level.filter(|l| codec == "h264" && *l > profile.max_h264_level) {
+        add("h264_level", format!("H.264 level {:.1} is above the {:.1} these players decode", level as f64 / 10.0, profile.max_h264_level as f64 / 10.0));
+    }
+    if let (Some(w), Some(h), Some(max)) = (video.width, video.height, profile.max_long_edge) {
+        if w.max(h) > max {
+            add("frame_size", format!("{} px on the long side is more than the {} these players take", w.max(h), max));
+        }
+    }
+    found
+}
+
+fn video_problems(profile: &PlaybackProfile, video: &StreamInfo) -> Vec<String> {
+    video_violations(profile, video).into_iter().map(|v| v.message).collect()
+}
+
+// The decision from the probe alone; `taken` says which output paths are
+// spoken for already (queued jobs).
+pub fn recommend(input: &str, media: &MediaInfo, target: TargetProfile, taken: impl Fn(&Path) -> bool) -> Result<PlayabilityReport, String> {
+    let profile = profile(target);
+    let input_path = Path::new(input);
+    let ext = input_path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let video = first(media, "video").ok_or_else(|| format!("{} has no video stream", input))?;
+    let audio = first(media, "audio");
+    let video_codec = video.codec_name.clone();
+    let audio_codec = audio.and_then(|a| a.codec_name.clone());
+    let report = |recommendation| PlayabilityReport {
+        target,
+        container: ext.clone(),
+        video_codec: video_codec.clone(),
+        audio_codec: audio_codec.clone(),
+        recommendation,
+    };
+
+    let reasons = video_problems(profile, video);
+    if !reasons.is_empty() {
+        return Ok(report(Recommendation::ReencodeNeeded { reasons }));
+    }
+    let codec = video_codec.as_deref().unwrap_or_default();
+    let audio_plays = audio_codec.as_deref().is_none_or(|a| profile.audio.contains(&a));
+    if audio_plays && profile.containers.contains(&ext.as_str()) {
+        return Ok(report(Recommendation::AlreadyCompatible));
+    }
+    let Some(container) = profile.containers.iter().copied().find(|c| support::accepts_video(c, codec)) else {
+        return Ok(report(Recommendation::ReencodeNeeded { reasons: vec![format!("None of {} can hold {} video", profile.containers.join(", "), codec)] }));
+    };
+    let audio_fits = audio_codec.as_deref().is_none_or(|a| support::container(container).is_some_and(|c| c.audio.contains(&a)));
+    let audio_reencoded = !(audio_plays && audio_fits);
+
+    let output = paths::unused_sibling(input_path, REMUX_SUFFIX, container, taken).to_string_lossy().to_string();
+    let options = if audio_reencoded {
+        VideoOptions { video_mode: VideoMode::Copy, ..Default::default() }
+    } else {
+        VideoOptions { copy_only: true, ..Default::default() }
+    };
+    let spec = VideoCompressRequest::new(input.to_string(), output, options);
+    // Offered only if it would be accepted as it is
+    if let Err(e) = spec.validate() {
+        return Ok(report(Recommendation::ReencodeNeeded { reasons: vec![format!("A remux wouldn't be accepted: {}", e)] }));
+    }
+    Ok(report(Recommendation::RemuxSufficient { spec: Box::new(spec), audio_reencoded }))
+}
+
+// ==========================================
+// COMMAND: ANALYZE PLAYABILITY
+// ==========================================
+#[tauri::command]
+pub async fn analyze_playability(app: AppHandle, input: String, target_profile: TargetProfile) -> Result<PlayabilityReport, String> {
+    inputs::preflight(&input).map_err(|e| e.to_string())?;
+    let media = probe::probe(&app, &input).await?;
+    recommend(&input, &media, target_profile, |_| false)
+}
+
+// ==========================================
+// OUTPUT LINT
+// ==========================================
+// The same tables, pointed at a finished file: what keeps it from playing
+// on the target, rule by rule, each with the least it takes to fix. Unlike
+// the preflight this also looks at the AAC profile and, for players that
+// stream the file, at where the index (moov atom) is.
+
+// Pure: whether an MP4/MOV's moov atom comes before its mdat, from the
+// top-level boxes. None when the file ends (or stops making sense) first.
+pub fn moov_before_mdat(reader: &mut (impl Read + Seek)) -> Option<bool> {
+    // Plenty for ftyp/free/wide/uuid boxes in front
+    for _ in 0..64 {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header).ok()?;
+        let mut size = u32::from_be_bytes(header[..4].try_into().ok()?) as u64;
+        let mut header_len = 8;
+        match &header[4..] {
+            b"moov" => return Some(true),
+            b"mdat" => return Some(false),
+            _ => {}
+        }
+        if size == 1 {
+            let mut large = [0u8; 8];
+            reader.read_exact(&mut large).ok()?;
+            size = u64::from_be_bytes(large);
+            header_len = 16;
+        }
+        // 0 = runs to the end of the file; anything smaller than its header is garbage
+        if size < header_len {
+            return None;
+        }
+        reader.seek(SeekFrom::Current((size - header_len) as i64)).ok()?;
+    }
+    None
+}
+
+fn faststart(path: &str, ext: &str) -> Option<bool> {
+    if !MP4_FAMILY.contains(&ext) {
+        return None;
+    }
+    moov_before_mdat(&mut File::open(path).ok()?)
+}
+
+fn ext_of(path: &str) -> String {
+    Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default()
+}
+
+// Pure: every rule `media` (a file with extension `ext`) breaks on `target`.
+// `faststart` is moov_before_mdat's answer, None when it doesn't apply.
+pub fn lint(ext: &str, media: &MediaInfo, faststart: Option<bool>, target: TargetProfile) -> Vec<Violation> {
+    let profile = profile(target);
+    let mut found = vec![];
+    let video = first(media, "video");
+    if let Some(video) = video {
+        found.extend(video_violations(profile, video));
+    }
+    if !profile.containers.contains(&ext) {
+        let codec = video.and_then(|v| v.codec_name.as_deref()).unwrap_or_default();
+        let fix = match video {
+            Some(_) if !profile.containers.iter().any(|c| support::accepts_video(c, codec)) => Fix::ReencodeVideo,
+            _ => Fix::Remux,
+        };
+        found.push(Violation::new("container", Severity::Error, fix, format!(".{} files don't play there (needs {})", ext, profile.containers.join(", "))));
+    }
+    if let Some(audio) = first(media, "audio") {
+        let codec = audio.codec_name.as_deref().unwrap_or("unknown");
+        if !profile.audio.contains(&codec) {
+            let message = format!("{} audio doesn't play there (needs {})", codec, profile.audio.join(", "));
+            found.push(Violation::new("audio_codec", Severity::Error, Fix::ReencodeAudio, message));
+        } else if let Some(p) = audio.profile.as_deref().filter(|p| codec == "aac" && !profile.aac_profiles.is_empty() && !profile.aac_profiles.contains(p)) {
+            let message = format!("AAC {} audio isn't decoded there (needs {})", p, profile.aac_profiles.join(", "));
+            found.push(Violation::new("aac_profile", Severity::Error, Fix::ReencodeAudio, message));
+        }
+        if let Some((rate, max)) = audio.sample_rate.zip(profile.max_sample_rate).filter(|(rate, max)| rate > max) {
+            let message = format!("{} Hz audio is more than the {} Hz these players take", rate, max);
+            found.push(Violation::new("sample_rate", Severity::Error, Fix::ReencodeAudio, message));
+        }
+    }
+    if profile.faststart && faststart == Some(false) {
+        let message = "The index is at the end, so a streamed copy can't start playing until it has all arrived".to_string();
+        found.push(Violation::new("faststart", Severity::Warning, Fix::Remux, message));
+    }
+    found
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LintReport {
+    pub target: TargetProfile,
+    pub path: String,
+    pub violations: Vec<Violation>,
+    // auto_fix_output can clear all of them
+    pub auto_fixable: bool,
+}
+
+async fn lint_file(app: &AppHandle, path: &str, target: TargetProfile) -> Result<Vec<Violation>, String> {
+    inputs::preflight(path).map_err(|e| e.to_string())?;
+    let media = probe::probe(app, path).await?;
+    let ext = ext_of(path);
+    Ok(lint(&ext, &media, faststart(path, &ext), target))
+}
+
+// ==========================================
+// COMMAND: LINT OUTPUT
+// ==========================================
+#[tauri::command]
+pub async fn lint_output(app: AppHandle, path: String, target_profile: TargetProfile) -> Result<LintReport, String> {
+    let violations = lint_file(&app, &path, target_profile).await?;
+    Ok(LintReport {
+        target: target_profile,
+        auto_fixable: violations.iter().all(|v| v.fix != Fix::ReencodeVideo),
+        path,
+        violations,
+    })
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FixReport {
+    // The fixed copy; the original is left as it is
+    pub output: String,
+    pub fixed: Vec<Violation>,
+    // What only a video re-encode would fix
+    pub remaining: Vec<Violation>,
+}
+
+// ==========================================
+// COMMAND: AUTO-FIX OUTPUT
+// ==========================================
+// Writes a "_fixed" sibling with what a remux can do: the streams copied
+// into a container the target plays, the index moved up front, and the
+// audio re-encoded to AAC only when it's the audio that doesn't play (or
+// doesn't fit the container). The video is never touched; rules only a
+// re-encode fixes come back in `remaining`.
+#[tauri::command]
+pub async fn auto_fix_output(app: AppHandle, path: String, target_profile: TargetProfile) -> Result<FixReport, String> {
+    let before = lint_file(&app, &path, target_profile).await?;
+    if before.iter().all(|v| v.fix == Fix::ReencodeVideo) {
+        return Err(match before.is_empty() {
+            true => format!("{} already plays there, nothing to fix", path),
+            false => format!("Only a video re-encode fixes {}: {}", path, before.iter().map(|v| v.message.as_str()).collect::<Vec<_>>().join("; ")),
+        });
+    }
+    let media = probe::probe(&app, &path).await?;
+    let profile = profile(target_profile);
+    let ext = ext_of(&path);
+    let video_codec = first(&media, "video").and_then(|v| v.codec_name.clone()).unwrap_or_default();
+    let container = match profile.containers.contains(&ext.as_str()) {
+        true => ext.as_str(),
+        false => profile
+            .containers
+            .iter()
+            .copied()
+            .find(|c| video_codec.is_empty() || support::accepts_video(c, &video_codec))
+            .unwrap_or(profile.containers[0]),
+    };
+    let audio_fits = first(&media, "audio")
+        .and_then(|a| a.codec_name.as_deref())
+        .is_none_or(|a| support::container(container).is_some_and(|c| c.audio.contains(&a)));
+    let reencode_audio = !audio_fits || before.iter().any(|v| v.fix == Fix::ReencodeAudio);
+
+    let output = paths::unused_sibling(Path::new(&path), FIX_SUFFIX, container, |_| false).to_string_lossy().to_string();
+    // V: video that isn't cover art, which most of these containers can't hold
+    let mut args: Vec<String> = ["-hide_banner", "-i", &path, "-map", "0:V?", "-map", "0:a?", "-c:v", "copy"].iter().map(|s| s.to_string()).collect();
+    match reencode_audio {
+        true => args.extend(["-c:a", "aac", "-b:a", FIX_AUDIO_BITRATE].map(String::from)),
+        false => args.extend(["-c:a", "copy"].map(String::from)),
+    }
+    if MP4_FAMILY.contains(&container) {
+        args.extend(["-movflags", "+faststart"].map(String::from));
+    }
+    // Under a temp name until it reads back whole
+    let staged = staging::temp_path_for(Path::new(&output));
+    args.push(staged.to_string_lossy().to_string());
+    println!("🩹 Fixing {} for {:?}: .{}{}", path, target_profile, container, if reencode_audio { ", audio re-encoded" } else { "" });
+    let written = match ffmpeg::run_quiet(&app, args).await {
+        Ok(()) => verify::read_back(&app, &staged.to_string_lossy(), &Expected { secs: media.duration, frames: None }).await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = written {
+        staging::discard(&staged);
+        return Err(e);
+    }
+    staging::commit(&staged, Path::new(&output))?;
+
+    let remaining = lint_file(&app, &output, target_profile).await?;
+    let fixed = before.into_iter().filter(|v| !remaining.iter().any(|r| r.rule == v.rule)).collect();
+    Ok(FixReport { output, fixed, remaining })
+}
+
+// ==========================================
+// ENCODING FOR A PROFILE
+// ==========================================
+// playback_target on a video job encodes for one of the profiles above and
+// checks the result against it, so the options, the encoder flags and the
+// lint all come from the same row. The "office_compatible" preset is that
+// for Office (see presets.rs).
+
+// The options a preset for `target` starts from.
+pub fn preset_options(target: TargetProfile) -> VideoOptions {
+    let profile = profile(target);
+    VideoOptions {
+        playback_target: Some(target),
+        max_long_edge: profile.max_long_edge,
+        ..Default::default()
+    }
+}
+
+// Encoder flags that keep a fresh encode inside the profile: pixel format,
+// H.264 profile and level, audio sample rate. `audio_rate` is the source's.
+pub fn encode_args(target: TargetProfile, encoder: &str, audio_rate: Option<u32>) -> Vec<String> {
+    let profile = profile(target);
+    let mut args = vec![];
+    if let Some(pix) = profile.pix_fmts.first() {
+        args.extend(["-pix_fmt".to_string(), pix.to_string()]);
+    }
+    if encoders::profile(encoder).codec == "h264" {
+        if let Some(h264) = profile.h264_profiles.last() {
+            args.extend(["-profile:v".to_string(), h264.to_lowercase()]);
+        }
+        // Quick Sync takes the level as a number, the others by name
+        let level = match encoder.ends_with("_qsv") {
+            true => profile.max_h264_level.to_string(),
+            false => format!("{:.1}", profile.max_h264_level as f64 / 10.0),
+        };
+        args.extend(["-level:v".to_string(), level]);
+    }
+    if let Some(max) = profile.max_sample_rate.filter(|max| audio_rate.is_some_and(|rate| rate > *max)) {
+        args.extend(["-ar".to_string(), max.to_string()]);
+    }
+    args
+}
+
+// Whether a job's output can be made to pass `target` at all.
+pub fn check_target(target: TargetProfile, ext: &str, video_codec: &str) -> Result<(), String> {
+    let profile = profile(target);
+    if !profile.containers.contains(&ext) {
+        return Err(format!("{} players need {} files, not .{}", target.label(), profile.containers.join(", "), ext));
+    }
+    if !profile.video.contains(&video_codec) {
+        return Err(format!("{} players need {} video, not {}", target.label(), profile.video.join(", "), video_codec));
+    }
+    Ok(())
+}
+
+// After the encode, before the commit: the staged output is linted against
+// the profile. What a remux fixes (the index, mostly) is fixed in place, and
+// the fixes come back as warnings; anything else fails the job with the
+// rule it breaks, so a delivered file always passes.
+pub async fn conform(app: &AppHandle, staged: &str, ext: &str, target: TargetProfile) -> Result<Vec<String>, String> {
+    let broken = |v: &Violation| format!("The output doesn't pass the {} profile: {}", target.label(), v.message);
+    let media = probe::probe(app, staged).await?;
+    let found = lint(ext, &media, faststart(staged, ext), target);
+    if let Some(v) = found.iter().find(|v| v.fix != Fix::Remux) {
+        return Err(broken(v));
+    }
+    if found.is_empty() {
+        return Ok(vec![]);
+    }
+    let fixed = staging::temp_path_for(Path::new(staged));
+    let mut args: Vec<String> = ["-hide_banner", "-i", staged, "-map", "0", "-c", "copy"].iter().map(|s| s.to_string()).collect();
+    if MP4_FAMILY.contains(&ext) {
+        args.extend(["-movflags", "+faststart"].map(String::from));
+    }
+    args.push(fixed.to_string_lossy().to_string());
+    println!("🩹 Output misses the {} profile ({}), remuxing it", target.label(), found.iter().map(|v| v.rule).collect::<Vec<_>>().join(", "));
+    let written = match ffmpeg::run_quiet(app, args).await {
+        Ok(()) => staging::verify_remux(app, &media, &fixed).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = written {
+        staging::discard(&fixed);
+        return Err(e);
+    }
+    staging::commit(&fixed, Path::new(staged))?;
+    let remaining = lint(ext, &probe::probe(app, staged).await?, faststart(staged, ext), target);
+    if let Some(v) = remaining.first() {
+        return Err(broken(v));
+    }
+    Ok(found.into_iter().map(|v| format!("Fixed for {}: {}", target.label(), v.message)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{run, Harness, ENCODE};
+    use std::fs;
+
+    const TARGETS: [TargetProfile; 4] = [TargetProfile::Web, TargetProfile::Tv, TargetProfile::Mobile, TargetProfile::Office];
+
+    fn video(codec: &str) -> StreamInfo {
+        let h264 = codec == "h264";
+        StreamInfo {
+            index: 0,
+            codec_type: "video".to_string(),
+            codec_name: Some(codec.to_string()),
+            width: Some(1280),
+            height: Some(720),
+            pix_fmt: Some("yuv420p".to_string()),
+            profile: h264.then(|| "Main".to_string()),
+            level: h264.then_some(40),
+            ..Default::default()
+        }
+    }
+
+    fn audio(codec: &str) -> StreamInfo {
+        let profile = (codec == "aac").then(|| "LC".to_string());
+        StreamInfo { index: 1, codec_type: "audio".to_string(), codec_name: Some(codec.to_string()), sample_rate: Some(48000), profile, ..Default::default() }
+    }
+
+    fn media(streams: Vec<StreamInfo>) -> MediaInfo {
+        MediaInfo { has_video: true, has_audio: streams.len() > 1, streams, ..Default::default() }
+    }
+
+    fn recommendation(input: &str, media: &MediaInfo, target: TargetProfile) -> Recommendation {
+        recommend(input, media, target, |_| false).unwrap().recommendation
+    }
+
+    #[test]
+    fn every_target_has_one_profile_whose_containers_hold_its_video() {
+        assert_eq!(PROFILES.len(), TARGETS.len());
+        for target in TARGETS {
+            assert_eq!(PROFILES.iter().filter(|p| p.target == target).count(), 1, "{:?}", target);
+            let profile = profile(target);
+            for container in profile.containers {
+                assert!(support::container(container).is_some(), "{:?}: .{} isn't a known container", target, container);
+            }
+            // Or no remux could ever keep it
+            for codec in profile.video {
+                assert!(profile.containers.iter().any(|c| support::accepts_video(c, codec)), "{:?}: nowhere to put {}", target, codec);
+            }
+            assert!(profile.aac_profiles.is_empty() || profile.aac_profiles.contains(&"LC"), "{:?}", target);
+        }
+    }
+
+    #[test]
+    fn what_encode_args_asks_for_passes_the_same_profile() {
+        for target in TARGETS {
+            let args = encode_args(target, "libx264", Some(96000));
+            let flag = |name: &str| args.iter().position(|a| a == name).map(|i| args[i + 1].clone());
+            let profile = profile(target);
+            let h264 = profile.h264_profiles.iter().find(|p| p.to_lowercase() == flag("-profile:v").unwrap()).unwrap();
+            let level = (flag("-level:v").unwrap().parse::<f64>().unwrap() * 10.0).round() as i32;
+            let stream = StreamInfo { pix_fmt: flag("-pix_fmt"), profile: Some(h264.to_string()), level: Some(level), ..video("h264") };
+            assert!(video_violations(profile, &stream).is_empty(), "{:?}: {:?}", target, args);
+            assert_eq!(flag("-ar").map(|r| r.parse::<u32>().unwrap()), profile.max_sample_rate, "{:?}", target);
+        }
+        // Quick Sync takes the level as a number
+        let qsv = encode_args(TargetProfile::Tv, "h264_qsv", None);
+        assert!(qsv.windows(2).any(|w| w == ["-level:v", "41"]));
+    }
+
+    #[test]
+    fn a_capture_in_the_wrong_container_only_needs_a_remux() {
+        let clip = media(vec![video("h264"), audio("aac")]);
+        let Recommendation::RemuxSufficient { spec, audio_reencoded } = recommendation("/videos/capture.ts", &clip, TargetProfile::Web) else { panic!() };
+        assert!(!audio_reencoded);
+        assert_eq!(spec.output, "/videos/capture_remux.mp4");
+        assert!(spec.options.copy_only);
+        // A queued job has that name already
+        let taken = |p: &Path| p == Path::new("/videos/capture_remux.mp4");
+        let report = recommend("/videos/capture.ts", &clip, TargetProfile::Web, taken).unwrap();
+        let Recommendation::RemuxSufficient { spec, .. } = report.recommendation else { panic!() };
+        assert_eq!(spec.output, "/videos/capture_remux_2.mp4");
+
+        assert!(matches!(recommendation("/videos/capture.mp4", &clip, TargetProfile::Web), Recommendation::AlreadyCompatible));
+        // TVs play it from the stick as it is
+        assert!(matches!(recommendation("/videos/capture.ts", &clip, TargetProfile::Tv), Recommendation::AlreadyCompatible));
+    }
+
+    #[test]
+    fn audio_that_doesnt_play_is_encoded_again_around_the_copied_video() {
+        let clip = media(vec![video("h264"), audio("flac")]);
+        let Recommendation::RemuxSufficient { spec, audio_reencoded } = recommendation("/videos/concert.mkv", &clip, TargetProfile::Tv) else { panic!() };
+        assert!(audio_reencoded);
+        assert_eq!(spec.options.video_mode, VideoMode::Copy);
+        assert!(!spec.options.copy_only);
+        assert_eq!(spec.output, "/videos/concert_remux.mp4");
+    }
+
+    #[test]
+    fn the_video_stream_decides_when_it_has_to_be_encoded() {
+        let reasons = |stream: StreamInfo, target| match recommendation("/videos/in.mp4", &media(vec![stream, audio("aac")]), target) {
+            Recommendation::ReencodeNeeded { reasons } => reasons,
+            other => panic!("{:?}", other),
+        };
+        assert!(reasons(video("vp9"), TargetProfile::Tv)[0].contains("vp9 video"));
+        let high10 = StreamInfo { profile: Some("High 10".to_string()), level: Some(51), pix_fmt: Some("yuv420p10le".to_string()), ..video("h264") };
+        let tv = reasons(high10.clone(), TargetProfile::Tv);
+        assert_eq!(tv.len(), 2, "{:?}", tv);
+        assert!(tv[0].contains("High 10") && tv[1].contains("level 5.1"));
+        assert_eq!(reasons(high10, TargetProfile::Mobile).len(), 3);
+        let uhd = StreamInfo { width: Some(3840), height: Some(2160), ..video("h264") };
+        assert!(reasons(uhd, TargetProfile::Office)[0].contains("3840 px"));
+
+        assert!(recommend("/videos/song.mp3", &media(vec![audio("mp3")]), TargetProfile::Web, |_| false).is_err());
+    }
+
+    #[test]
+    fn every_recommendation_agrees_with_validation_and_the_lint() {
+        let mut remuxes = 0;
+        for ext in ["ts", "mkv", "mp4", "mov", "avi", "webm"] {
+            for codec in ["h264", "hevc", "vp9"] {
+                for sound in [Some("aac"), Some("flac"), Some("ac3"), None] {
+                    let mut streams = vec![video(codec)];
+                    streams.extend(sound.map(audio));
+                    let clip = media(streams);
+                    let input = format!("/videos/in.{}", ext);
+                    for target in TARGETS {
+                        let case = format!("{} {} {:?} for {:?}", ext, codec, sound, target);
+                        match recommendation(&input, &clip, target) {
+                            Recommendation::AlreadyCompatible => assert!(lint(ext, &clip, Some(true), target).is_empty(), "{}", case),
+                            Recommendation::RemuxSufficient { spec, audio_reencoded } => {
+                                remuxes += 1;
+                                assert!(spec.validate().is_ok(), "{}", case);
+                                assert_eq!(spec.options.copy_only, !audio_reencoded, "{}", case);
+                                let out = ext_of(&spec.output);
+                                assert!(profile(target).containers.contains(&out.as_str()), "{}", case);
+                                // Nothing left but what the audio re-encode takes care of
+                                let left = lint(&out, &clip, Some(true), target);
+                                assert!(left.iter().all(|v| audio_reencoded && v.fix == Fix::ReencodeAudio), "{}: {:?}", case, left);
+                            }
+                            Recommendation::ReencodeNeeded { reasons } => {
+                                assert!(!reasons.is_empty() && !reasons[0].starts_with("A remux"), "{}", case);
+                                assert!(lint(ext, &clip, Some(true), target).iter().any(|v| v.fix == Fix::ReencodeVideo), "{}", case);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        assert!(remuxes > 20, "{}", remuxes);
+    }
+
+    // --- OUTPUT LINT ---
+    // Top-level MP4 boxes, each just its header and `len` bytes of nothing
+    fn boxes(list: &[(&[u8; 4], usize)]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for (kind, len) in list {
+            bytes.extend(((len + 8) as u32).to_be_bytes());
+            bytes.extend(*kind);
+            bytes.extend(vec![0u8; *len]);
+        }
+        bytes
+    }
+
+    fn index_first(bytes: &[u8]) -> Option<bool> {
+        moov_before_mdat(&mut std::io::Cursor::new(bytes))
+    }
+
+    #[test]
+    fn the_index_position_comes_from_the_top_level_boxes() {
+        assert_eq!(index_first(&boxes(&[(b"ftyp", 16), (b"moov", 40), (b"mdat", 100)])), Some(true));
+        assert_eq!(index_first(&boxes(&[(b"ftyp", 16), (b"free", 8), (b"mdat", 100), (b"moov", 40)])), Some(false));
+        // A 64-bit size in front
+        let mut large = vec![0, 0, 0, 1];
+        large.extend(b"wide");
+        large.extend(20u64.to_be_bytes());
+        large.extend([0u8; 4]);
+        large.extend(boxes(&[(b"moov", 8)]));
+        assert_eq!(index_first(&large), Some(true));
+        // Cut short, or a size smaller than its own header
+        assert_eq!(index_first(&boxes(&[(b"ftyp", 16)])), None);
+        assert_eq!(index_first(&[0, 0, 0, 4, b'f', b'r', b'e', b'e', 0, 0]), None);
+        assert_eq!(index_first(&[]), None);
+    }
+
+    #[test]
+    fn each_rule_is_reported_alone_with_its_fix() {
+        use Fix::*;
+        use Severity::*;
+        let clip = |video: StreamInfo, audio: StreamInfo| media(vec![video, audio]);
+        let good = || clip(video("h264"), audio("aac"));
+        let check = |rule: &str, ext: &str, clip: MediaInfo, faststart: Option<bool>, target: TargetProfile, severity: Severity, fix: Fix| {
+            let found = lint(ext, &clip, faststart, target);
+            assert_eq!(found.len(), 1, "{}: {:?}", rule, found);
+            assert_eq!((found[0].rule, found[0].severity, found[0].fix), (rule, severity, fix), "{}", found[0].message);
+        };
+        check("container", "mkv", good(), None, TargetProfile::Web, Error, Remux);
+        check("video_codec", "mp4", clip(video("hevc"), audio("aac")), Some(true), TargetProfile::Web, Error, ReencodeVideo);
+        check("pix_fmt", "mp4", clip(StreamInfo { pix_fmt: Some("yuv444p".to_string()), ..video("h264") }, audio("aac")), Some(true), TargetProfile::Web, Error, ReencodeVideo);
+        check("h264_profile", "mp4", clip(StreamInfo { profile: Some("High".to_string()), ..video("h264") }, audio("aac")), Some(true), TargetProfile::Office, Error, ReencodeVideo);
+        check("h264_level", "mp4", clip(StreamInfo { level: Some(42), ..video("h264") }, audio("aac")), Some(true), TargetProfile::Tv, Error, ReencodeVideo);
+        check("frame_size", "mp4", clip(StreamInfo { width: Some(1080), height: Some(2340), ..video("h264") }, audio("aac")), Some(true), TargetProfile::Office, Error, ReencodeVideo);
+        check("audio_codec", "mp4", clip(video("h264"), audio("flac")), Some(true), TargetProfile::Mobile, Error, ReencodeAudio);
+        check("aac_profile", "mp4", clip(video("h264"), StreamInfo { profile: Some("HE-AAC".to_string()), ..audio("aac") }), Some(true), TargetProfile::Tv, Error, ReencodeAudio);
+        check("sample_rate", "mp4", clip(video("h264"), StreamInfo { sample_rate: Some(96000), ..audio("aac") }), Some(true), TargetProfile::Office, Error, ReencodeAudio);
+        check("faststart", "mp4", good(), Some(false), TargetProfile::Mobile, Warning, Remux);
+
+        for target in TARGETS {
+            assert!(lint("mp4", &good(), Some(true), target).is_empty(), "{:?}", target);
+        }
+        // TVs read from the stick, and elsewhere the index can't be checked
+        assert!(lint("mp4", &good(), Some(false), TargetProfile::Tv).is_empty());
+        assert!(lint("mp4", &good(), None, TargetProfile::Web).is_empty());
+        // A container none of whose siblings takes the video is a re-encode
+        let vp9 = lint("webm", &clip(video("vp9"), audio("aac")), None, TargetProfile::Office);
+        assert_eq!(vp9.iter().map(|v| (v.rule, v.fix)).collect::<Vec<_>>(), [("video_codec", ReencodeVideo), ("container", ReencodeVideo)]);
+    }
+
+    fn probe_json(video: &str, audio: &str) -> String {
+        format!(
+            r#"{{ "streams": [
+                {{ "index": 0, "codec_type": "video", "codec_name": "{}", "width": 1280, "height": 720, "pix_fmt": "yuv420p", "profile": "Main", "level": 40, "avg_frame_rate": "30/1" }},
+                {{ "index": 1, "codec_type": "audio", "codec_name": "{}", "profile": "LC", "sample_rate": "48000", "channels": 2 }}
+            ], "format": {{ "duration": "10.000000", "bit_rate": "4000000" }} }}"#,
+            video, audio
+        )
+    }
+
+    fn harness(name: &str, video: &str, audio: &str) -> Harness {
+        Harness::new(name, &format!(r#"{{ "ffprobe": {}, "runs": {} }}"#, probe_json(video, audio), ENCODE))
+    }
+
+    fn fix(h: &Harness, path: String, target: TargetProfile) -> Result<FixReport, String> {
+        let app = h.handle().clone();
+        run(async move { auto_fix_output(app, path, target).await })
+    }
+
+    fn remux(h: &Harness) -> Vec<String> {
+        h.runs().into_iter().find(|r| r.windows(2).any(|w| w == ["-c:v", "copy"])).expect("no remux ran")
+    }
+
+    #[test]
+    fn auto_fix_swaps_the_container_and_puts_the_index_first() {
+        let h = harness("fix-container", "h264", "aac");
+        let capture = h.input("capture.ts", 50_000);
+        let report = fix(&h, capture, TargetProfile::Web).unwrap();
+
+        assert_eq!(report.output, h.file("capture_fixed.mp4"));
+        assert_eq!(fs::metadata(&report.output).unwrap().len(), 4096);
+        assert_eq!(h.files(), ["capture.ts", "capture_fixed.mp4"]);
+        assert_eq!(report.fixed.iter().map(|v| v.rule).collect::<Vec<_>>(), ["container"]);
+        assert!(report.remaining.is_empty());
+        let args = remux(&h);
+        assert!(args.windows(2).any(|w| w == ["-c:a", "copy"]));
+        assert!(args.windows(2).any(|w| w == ["-movflags", "+faststart"]));
+    }
+
+    #[test]
+    fn auto_fix_moves_a_late_index() {
+        let h = harness("fix-faststart", "h264", "aac");
+        let late = h.file("late.mp4");
+        fs::write(&late, boxes(&[(b"ftyp", 16), (b"mdat", 4000), (b"moov", 200)])).unwrap();
+        let app = h.handle().clone();
+        let linted = run(async move { lint_output(app, late, TargetProfile::Mobile).await }).unwrap();
+        assert_eq!(linted.violations.iter().map(|v| (v.rule, v.severity)).collect::<Vec<_>>(), [("faststart", Severity::Warning)]);
+        assert!(linted.auto_fixable);
+
+        let report = fix(&h, h.file("late.mp4"), TargetProfile::Mobile).unwrap();
+        assert_eq!(report.output, h.file("late_fixed.mp4"));
+        assert_eq!(report.fixed.iter().map(|v| v.rule).collect::<Vec<_>>(), ["faststart"]);
+        assert!(remux(&h).windows(2).any(|w| w == ["-movflags", "+faststart"]));
+    }
+
+    #[test]
+    fn auto_fix_encodes_the_audio_and_copies_the_video() {
+        let h = harness("fix-audio", "h264", "flac");
+        let concert = h.input("concert.mkv", 50_000);
+        let report = fix(&h, concert, TargetProfile::Tv).unwrap();
+
+        assert_eq!(report.output, h.file("concert_fixed.mp4"));
+        let args = remux(&h);
+        assert!(args.windows(4).any(|w| w == ["-c:a", "aac", "-b:a", FIX_AUDIO_BITRATE]));
+        assert!(!args.iter().any(|a| a == "libx264"));
+        // The stub probes every file alike, so the copy still "has" flac
+        assert!(report.fixed.iter().any(|v| v.rule == "container"));
+    }
+
+    #[test]
+    fn auto_fix_leaves_what_only_a_video_encode_fixes() {
+        let h = harness("fix-refused", "hevc", "aac");
+        let error = fix(&h, h.input("clip.mp4", 50_000), TargetProfile::Office).unwrap_err();
+        assert!(error.starts_with("Only a video re-encode fixes"), "{}", error);
+        assert!(error.contains("hevc video"));
+        // A TV plays HEVC, so there the container is all there is to fix
+        let tv = fix(&h, h.input("clip.mkv", 50_000), TargetProfile::Tv).map(|r| r.output);
+        assert_eq!(tv, Ok(h.file("clip_fixed.mp4")));
+
+        let h = harness("fix-nothing", "h264", "aac");
+        let fine = h.file("fine.mp4");
+        fs::write(&fine, boxes(&[(b"ftyp", 16), (b"moov", 200), (b"mdat", 4000)])).unwrap();
+        assert!(fix(&h, fine, TargetProfile::Web).unwrap_err().contains("already plays there"));
+        assert!(!h.runs().iter().any(|r| r.iter().any(|a| a == "copy")));
+    }
+
+    // 4K 10-bit HEVC with 96 kHz audio: nothing about it plays in Office
+    const EXOTIC: &str = r#"{ "streams": [
+        { "index": 0, "codec_type": "video", "codec_name": "hevc", "width": 3840, "height": 2160, "pix_fmt": "yuv420p10le", "profile": "Main 10", "level": 153, "avg_frame_rate": "30/1" },
+        { "index": 1, "codec_type": "audio", "codec_name": "aac", "profile": "LC", "sample_rate": "96000", "channels": 2 }
+    ], "format": { "duration": "10.000000", "bit_rate": "40000000", "format_name": "mov,mp4,m4a,3gp,3g2,mj2" } }"#;
+
+    // What the office row's flags make of it, as ffprobe reads it back;
+    // `rate` is the audio's
+    fn encoded(rate: u32) -> String {
+        format!(
+            r#"{{ "streams": [
+                {{ "index": 0, "codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080, "pix_fmt": "yuv420p", "profile": "Main", "level": 41, "avg_frame_rate": "30/1" }},
+                {{ "index": 1, "codec_type": "audio", "codec_name": "aac", "profile": "LC", "sample_rate": "{}", "channels": 2 }}
+            ], "format": {{ "duration": "10.000000", "bit_rate": "8000000", "format_name": "mov,mp4,m4a,3gp,3g2,mj2" }} }}"#,
+            rate
+        )
+    }
+
+    fn office_job(name: &str, output: &str) -> (Harness, Result<crate::VideoJobResult, crate::errors::JobError>) {
+        let h = Harness::new(name, &format!(r#"{{ "ffprobe": {}, "ffprobe_output": {}, "runs": {} }}"#, EXOTIC, output, ENCODE));
+        let preset = crate::presets::builtins().into_iter().find(|p| p.name == "office_compatible").unwrap();
+        let request = VideoCompressRequest::new(h.input("exotic.mp4", 100_000), h.file("slides.mp4"), preset.options);
+        let result = crate::jobtests::run_video(&h, request);
+        (h, result)
+    }
+
+    #[test]
+    fn the_office_preset_encodes_an_exotic_source_to_what_its_lint_passes() {
+        let rules = |json: &str| lint("mp4", &crate::probe::tests::info(json), Some(true), TargetProfile::Office).iter().map(|v| v.rule).collect::<Vec<_>>();
+        assert_eq!(rules(EXOTIC), ["video_codec", "pix_fmt", "frame_size", "sample_rate"]);
+
+        let (h, result) = office_job("office-exotic", &encoded(48000));
+        let result = result.unwrap();
+        let encode = h.runs().into_iter().find(|r| r.iter().any(|a| a == "libx264")).unwrap();
+        let flag = |name: &str| encode.iter().position(|a| a == name).map(|i| encode[i + 1].as_str());
+        // The flags the row asks for, which `encoded` is what they make
+        assert_eq!(flag("-pix_fmt"), Some("yuv420p"));
+        assert_eq!(flag("-profile:v"), Some("main"));
+        assert_eq!(flag("-level:v"), Some("4.1"));
+        assert_eq!(flag("-c:a"), Some("aac"));
+        assert_eq!(flag("-ar"), Some("48000"));
+        assert!(flag("-filter:v:0").is_some_and(|vf| vf.contains("1920")), "{:?}", encode);
+
+        assert!(rules(&encoded(48000)).is_empty());
+        assert!(!result.warnings.iter().any(|w| w.contains("Office")), "{:?}", result.warnings);
+        assert_eq!(h.files(), ["exotic.mp4", "slides.mp4"]);
+    }
+
+    #[test]
+    fn an_office_output_that_still_misses_the_profile_fails_the_job() {
+        // Had the encode kept the source's rate
+        let (h, result) = office_job("office-missed", &encoded(96000));
+        let error = result.err().expect("96 kHz doesn't pass").to_string();
+        assert!(error.starts_with("The output doesn't pass the Office profile:"), "{}", error);
+        assert!(error.contains("96000"), "{}", error);
+        // Nothing is delivered
+        assert_eq!(h.files(), ["exotic.mp4"]);
+    }
+}
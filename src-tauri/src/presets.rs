@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::playability::{self, TargetProfile};
+use crate::quality::QualityOptions;
+use crate::request::{VideoCompressRequest, VideoOptions};
+use crate::store;
+use crate::support::VideoCodec;
+
+// Version of presets.json this build writes. Options missing from an older
+// file take their defaults; a newer file is read but never overwritten.
+pub const PRESETS_VERSION: u32 = 1;
+const MAX_NAME_CHARS: usize = 64;
+const MAX_DESCRIPTION_CHARS: usize = 200;
+
+// A named bundle of video options the UI (and the automation API) can offer.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Preset {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    // Built-ins can't be changed or deleted
+    #[serde(default, skip_deserializing)]
+    pub read_only: bool,
+    pub options: VideoOptions,
+}
+
+fn builtin(name: &str, description: &str, options: VideoOptions) -> Preset {
+    Preset { name: name.to_string(), description: description.to_string(), read_only: true, options }
+}
+
+pub fn builtins() -> Vec<Preset> {
+    vec![
+        builtin("default", "CPU encode with the container's default codecs.", VideoOptions::default()),
+        builtin("fast-gpu", "Hardware encode where the output container allows it.", VideoOptions { auto_gpu: true, ..Default::default() }),
+        builtin(
+            "long-recording",
+            "Resumable encode that keeps every subtitle track, for multi-hour inputs.",
+            VideoOptions { extract_incompatible_subs: true, resumable: true, ..Default::default() },
+        ),
+        builtin(
+            "Discord 8MB",
+            "H.264 under Discord's 8 MB upload limit, at most 720p.",
+            VideoOptions {
+                rate: QualityOptions { target_size_mb: Some(7.5), ..Default::default() },
+                max_long_edge: Some(1280),
+                ..Default::default()
+            },
+        ),
+        builtin(
+            "Web 1080p",
+            "H.264 at most 1080p and 30 fps, tags dropped, for the web.",
+            VideoOptions {
+                crf: Some(23),
+                max_long_edge: Some(1920),
+                max_fps: Some(30.0),
+                metadata: crate::metadata::MetadataMode::Strip,
+                ..Default::default()
+            },
+        ),
+        builtin(
+            "Archive HEVC",
+            "High-quality HEVC that keeps every audio track, for long-term storage.",
+            VideoOptions { codec: VideoCodec::Hevc, crf: Some(20), keep_all_streams: true, extract_incompatible_subs: true, ..Default::default() },
+        ),
+        builtin(
+            "office_compatible",
+            "Plays in PowerPoint and Outlook: conservative H.264 in mp4, checked (and fixed up) after the encode.",
+            playability::preset_options(TargetProfile::Office),
+        ),
+    ]
+}
+
+// What presets.json holds: the user's own presets.
+#[derive(Serialize, Deserialize, Default)]
+struct PresetFile {
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    presets: Vec<Preset>,
+}
+
+// ==========================================
+// PRESET STORE (managed state)
+// ==========================================
+// Lives in app_config_dir/presets.json, written like the other stores (see
+// store.rs). A file that doesn't parse is moved aside as
+// presets.json.corrupt-<time> so it can be looked at later, and loading
+// goes on with its backup or nothing.
+pub struct PresetStore {
+    path: Option<PathBuf>,
+    presets: Mutex<Vec<Preset>>,
+    // Written by a newer build: kept as it is
+    newer: bool,
+}
+
+fn quarantine(path: &Path) {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".corrupt-{}", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+    let target = path.with_file_name(name);
+    match fs::rename(path, &target) {
+        Ok(()) => println!("🩹 Moved the unreadable {} to {}", path.display(), target.display()),
+        Err(e) => println!("⚠️ Could not move the unreadable {} aside: {}", path.display(), e),
+    }
+}
+
+fn load_file(app: &AppHandle, path: &Path) -> PresetFile {
+    let text = match fs::read_to_string(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return PresetFile::default(),
+        other => other.ok(),
+    };
+    if let Some(file) = text.and_then(|t| serde_json::from_str(&t).ok()) {
+        return file;
+    }
+    let backup: Option<PresetFile> = fs::read_to_string(store::backup_path(path)).ok().and_then(|t| serde_json::from_str(&t).ok());
+    quarantine(path);
+    store::report_recovery(app, "presets", backup.is_some());
+    backup.unwrap_or_default()
+}
+
+impl PresetStore {
+    pub fn load(app: &AppHandle) -> Self {
+        let path = app.path().app_config_dir().ok().map(|dir| dir.join("presets.json"));
+        let file = path.as_deref().map(|p| load_file(app, p)).unwrap_or_default();
+        let newer = file.version > PRESETS_VERSION;
+        if newer {
+            println!("⚠️ presets.json is from a newer version of the app; saving presets is off");
+        }
+        // A user preset can't shadow a built-in, even one added after it was saved
+        let presets = file.presets.into_iter().filter(|p| find_builtin(&p.name).is_none()).collect();
+        PresetStore { path, presets: Mutex::new(presets), newer }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Vec<Preset>) -> Result<(), String>) -> Result<(), String> {
+        if self.newer {
+            return Err("presets.json was written by a newer version of the app, so it isn't changed from here".to_string());
+        }
+        let mut presets = self.presets.lock().unwrap();
+        let mut next = presets.clone();
+        f(&mut next)?;
+        self.write(&next)?;
+        *presets = next;
+        Ok(())
+    }
+
+    // Rewrites the file from what's loaded (repair_stores).
+    pub fn persist(&self) -> Result<(), String> {
+        if self.newer {
+            return Err("presets.json was written by a newer version of the app".to_string());
+        }
+        self.write(&self.presets.lock().unwrap())
+    }
+
+    fn write(&self, presets: &[Preset]) -> Result<(), String> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let file = PresetFile { version: PRESETS_VERSION, presets: presets.to_vec() };
+        let json = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+        store::write_atomic(path, json.as_bytes(), is_valid)
+    }
+}
+
+pub fn is_valid(text: &str) -> bool {
+    store::parses::<PresetFile>(text)
+}
+
+fn find_builtin(name: &str) -> Option<Preset> {
+    builtins().into_iter().find(|p| p.name.eq_ignore_ascii_case(name.trim()))
+}
+
+// Built-ins first, then the user's own.
+pub fn find(app: &AppHandle, name: &str) -> Option<Preset> {
+    find_builtin(name).or_else(|| {
+        let store = app.try_state::<PresetStore>()?;
+        let presets = store.presets.lock().unwrap();
+        presets.iter().find(|p| p.name == name.trim()).cloned()
+    })
+}
+
+// The options of preset `name`, or why there are none.
+pub fn options(app: &AppHandle, name: &str) -> Result<VideoOptions, String> {
+    find(app, name).map(|p| p.options).ok_or_else(|| format!("Unknown preset \"{}\"", name))
+}
+
+pub fn all(app: &AppHandle) -> Vec<Preset> {
+    let mut presets = builtins();
+    if let Some(store) = app.try_state::<PresetStore>() {
+        presets.extend(store.presets.lock().unwrap().iter().cloned());
+    }
+    presets
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("A preset needs a name".to_string());
+    }
+    if name.chars().count() > MAX_NAME_CHARS {
+        return Err(format!("Preset names are at most {} characters", MAX_NAME_CHARS));
+    }
+    if find_builtin(name).is_some() {
+        return Err(format!("\"{}\" is a built-in preset; pick another name", name));
+    }
+    Ok(())
+}
+
+// ==========================================
+// COMMANDS: PRESETS
+// ==========================================
+#[tauri::command]
+pub fn list_presets(app: AppHandle) -> Vec<Preset> {
+    all(&app)
+}
+
+// Saving under an existing name of the user's replaces that preset.
+#[tauri::command]
+pub fn save_preset(store: State<'_, PresetStore>, name: String, options: VideoOptions, description: Option<String>) -> Result<Preset, String> {
+    let name = name.trim().to_string();
+    check_name(&name)?;
+    let description = description.unwrap_or_default();
+    if description.chars().count() > MAX_DESCRIPTION_CHARS {
+        return Err(format!("Preset descriptions are at most {} characters", MAX_DESCRIPTION_CHARS));
+    }
+    // Checked like a request, against a placeholder file
+    VideoCompressRequest::new("preset".to_string(), "preset.mp4".to_string(), options.clone()).validate().map_err(|e| e.to_string())?;
+    let preset = Preset { name: name.clone(), description, read_only: false, options };
+    store.update(|presets| {
+        match presets.iter_mut().find(|p| p.name == name) {
+            Some(existing) => *existing = preset.clone(),
+            None => presets.push(preset.clone()),
+        }
+        Ok(())
+    })?;
+    Ok(preset)
+}
+
+#[tauri::command]
+pub fn delete_preset(store: State<'_, PresetStore>, name: String) -> Result<(), String> {
+    if find_builtin(&name).is_some() {
+        return Err(format!("\"{}\" is a built-in preset and can't be deleted", name));
+    }
+    store.update(|presets| {
+        let before = presets.len();
+        presets.retain(|p| p.name != name.trim());
+        if presets.len() == before {
+            return Err(format!("Unknown preset \"{}\"", name));
+        }
+        Ok(())
+    })
+}
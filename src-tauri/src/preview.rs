@@ -0,0 +1,178 @@
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::audiomode::AudioAction;
+use crate::cancel;
+use crate::explain::{self, Explanation};
+use crate::inputs;
+use crate::interlace::FieldReport;
+use crate::paths;
+use crate::plan::{self, EstimateBasis};
+use crate::presets;
+use crate::probe;
+use crate::quality::QualityOptions;
+use crate::queue::JobSpec;
+use crate::request::VideoCompressRequest;
+
+const OUTPUT_SUFFIX: &str = "_compressed";
+// Length of the sample encode behind `with_size_estimate`, and where it starts
+const SAMPLE_SECS: f64 = 10.0;
+const SAMPLE_POSITION: f64 = 0.4;
+
+// ==========================================
+// PRESET PREVIEW
+// ==========================================
+// "What does fast-gpu mean for THIS file", answered without encoding: the
+// preset's options come from presets::find like every queued preset's,
+// and the decisions come from explain::resolve, the same dry run plan_batch
+// shows. `spec` is the request enqueueing it would queue, so the preview and
+// the job can't drift apart. Deinterlacing, A/V offset and HDR handling
+// are decided at encode time; their explanations say so.
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ResolvedPlan {
+    pub preset: String,
+    // enqueue_jobs takes it as it is
+    pub spec: JobSpec,
+    pub encoder: String,
+    pub gpu: bool,
+    // Audio encoder, "copy", or None when no audio is written
+    pub audio: Option<String>,
+    pub quality: QualityOptions,
+    pub two_pass: bool,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<f64>,
+    // The video filter chain, as far as the probe decides it
+    pub filters: Option<String>,
+    pub estimated_output_bytes: u64,
+    pub estimated_wall_secs: f64,
+    pub estimate_basis: EstimateBasis,
+    // Sample encode scaled to the whole input (with_size_estimate)
+    pub sample_estimate_bytes: Option<u64>,
+    pub explanations: Vec<Explanation>,
+}
+
+// Encodes SAMPLE_SECS of the input with the same request through the normal
+// encode path, into the cache, and scales the size up. Resumable and upload
+// are off for it: they change where the output goes, not how big it is.
+pub(crate) async fn sample_bytes(app: &AppHandle, spec: &VideoCompressRequest, duration: f64) -> Result<u64, String> {
+    let dir = app.path().app_cache_dir().map_err(|e| e.to_string())?.join("preview");
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let ext = Path::new(&spec.output).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_else(|| "mp4".to_string());
+    let sample = cancel::TempFile::new(dir.join(format!("sample.{}", ext)));
+    let sample_path = sample.path().to_string_lossy().to_string();
+    let secs = SAMPLE_SECS.min(duration);
+    let mut request = spec.clone();
+    request.output = sample_path.clone();
+    request.options.start_secs = Some((duration * SAMPLE_POSITION).min(duration - secs).max(0.0));
+    request.options.limit_duration_secs = Some(secs);
+    request.options.end_secs = None;
+    request.options.resumable = false;
+    request.options.upload = false;
+    request.options.skip_if_larger = false;
+    request.options.extract_incompatible_subs = false;
+    crate::encode_video(app, request, &sample_path).await?;
+    let bytes = fs::metadata(sample.path()).map_err(|e| e.to_string())?.len();
+    Ok((bytes as f64 * duration / secs.max(0.1)).round() as u64)
+}
+
+// ==========================================
+// COMMAND: PREVIEW PRESET
+// ==========================================
+// `output` defaults to `<stem>_compressed.mp4` beside the input.
+#[tauri::command]
+pub async fn preview_preset(
+    app: AppHandle,
+    input: String,
+    preset_name: String,
+    output: Option<String>,
+    with_size_estimate: Option<bool>,
+) -> Result<ResolvedPlan, String> {
+    let options = presets::options(&app, &preset_name)?;
+    inputs::preflight(&input).map_err(|e| e.to_string())?;
+    let output = output.unwrap_or_else(|| paths::unused_sibling(Path::new(&input), OUTPUT_SUFFIX, "mp4", |_| false).to_string_lossy().to_string());
+    let request = VideoCompressRequest::new(input.clone(), output, options);
+    request.validate().map_err(|e| e.to_string())?;
+
+    let media = probe::probe(&app, &input).await?;
+    let prediction = explain::resolve(&app, &request, &media).await;
+    // A copied video keeps its frames as they are
+    let size = media.display_size().map(|s| if prediction.copy { s } else { prediction.filters.output_size(s) });
+    let fields = FieldReport::default();
+    let name = Path::new(&input).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let input_bytes = fs::metadata(&input).map(|m| m.len()).unwrap_or(0);
+    let (estimated_output_bytes, estimated_wall_secs, estimate_basis) = plan::estimate_probed(&app, "video", input_bytes, media.duration);
+
+    let sample_estimate_bytes = match (with_size_estimate.unwrap_or(false), media.duration) {
+        (true, Some(duration)) if media.has_video && !prediction.single_frame_image => Some(sample_bytes(&app, &request, duration).await?),
+        _ => None,
+    };
+    // What the encode will pass to -c:a, as the audio plan decides it
+    let audio = match (&prediction.audio_plan, media.has_audio) {
+        (_, false) => None,
+        (Some(plan), true) if plan.action == AudioAction::Removed => None,
+        (Some(plan), true) => Some(plan.encoder.to_string()),
+        (None, true) if request.options.copy_only => Some("copy".to_string()),
+        (None, true) => Some(prediction.audio.to_string()),
+    };
+
+    Ok(ResolvedPlan {
+        preset: preset_name,
+        encoder: if prediction.copy { "copy".to_string() } else { prediction.encoder.to_string() },
+        gpu: prediction.gpu,
+        audio,
+        quality: prediction.rate,
+        two_pass: prediction.two_pass,
+        width: size.map(|(w, _)| w),
+        height: size.map(|(_, h)| h),
+        fps: prediction.filters.fps_cap(Some(&media), &fields).filter(|_| !prediction.copy).or(media.fps),
+        filters: prediction.filters.build(Some(&media), &fields, &name, request.options.start_secs.unwrap_or(0.0)).filter(|_| !prediction.copy),
+        estimated_output_bytes,
+        estimated_wall_secs,
+        estimate_basis,
+        sample_estimate_bytes,
+        explanations: prediction.explanations,
+        spec: JobSpec::Video(Box::new(request)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{self, clip_scenario, Harness, ENCODE};
+    use crate::queue::{self, QueueStatus};
+
+    #[test]
+    fn enqueueing_a_preview_runs_the_plan_it_showed() {
+        let h = Harness::new("preview-enqueue", &clip_scenario(ENCODE));
+        let (app, input, output) = (h.handle().clone(), h.input("clip.mp4", 100_000), h.file("web.mp4"));
+        let preview = jobtests::run(async move { preview_preset(app, input, "Web 1080p".to_string(), Some(output), None).await }).unwrap();
+
+        let id = queue::enqueue(h.handle(), vec![preview.spec.clone()], None).unwrap()[0];
+        let job = h.settled(id);
+        assert_eq!(job.status, QueueStatus::Done, "{:?}", job.error);
+        assert_eq!(serde_json::to_value(&job.spec).unwrap(), serde_json::to_value(&preview.spec).unwrap());
+
+        // The job decided everything the preview did, and what the preview
+        // left to the encode is all that's missing
+        let sorted = |mut why: Vec<Explanation>| {
+            why.retain(|e| e.code != explain::DEFERRED);
+            why.sort_by(|a, b| a.code.cmp(&b.code));
+            why
+        };
+        let crf = preview.explanations.iter().find(|e| e.code == explain::RATE_QUALITY).and_then(|e| e.params.get("crf").cloned());
+        assert_eq!(sorted(job.explanations), sorted(preview.explanations));
+
+        let encode = h.runs().into_iter().find(|r| r.iter().any(|a| a == "-c:v")).unwrap();
+        let arg = |flag: &str| encode.windows(2).find(|w| w[0] == flag).map(|w| w[1].clone());
+        assert_eq!(arg("-c:v"), Some(preview.encoder));
+        assert_eq!(arg("-c:a"), preview.audio);
+        assert_eq!(arg("-crf"), crf);
+        assert_eq!(arg("-filter:v:0"), preview.filters);
+        assert!(!preview.two_pass && !encode.iter().any(|a| a == "-pass"));
+    }
+}
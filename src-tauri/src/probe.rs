@@ -0,0 +1,615 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::AppHandle;
+use crate::cancel;
+use crate::ffmpeg::{self, TrackedOutput};
+use crate::progress;
+
+// Inputs shorter than this skip two-pass and the detection passes: there's
+// too little material for either
+pub const SHORT_INPUT_SECS: f64 = 2.0;
+// ffprobe errors the lenient levels can get past: timestamps a camera
+// wrote badly, and streams whose parameters sit further in than the
+// default 5 MB / 5 s ffprobe reads
+const RECOVERABLE_PATTERNS: &[&str] = &[
+    "Could not find codec parameters",
+    "unspecified size",
+    "unspecified pixel format",
+    "non monotonically increasing dts",
+    "non monotonous DTS",
+    "Invalid timestamps",
+    "start time for stream",
+    "max_analyze_duration",
+];
+
+// --- RAW FFPROBE JSON ---
+// ffprobe prints most numbers as strings ("12.345000"), so everything is
+// optional here and converted in `MediaInfo::from_raw`.
+#[derive(Deserialize, Default)]
+struct RawProbe {
+    #[serde(default)]
+    streams: Vec<RawStream>,
+    format: Option<RawFormat>,
+}
+
+#[derive(Deserialize, Default)]
+struct RawStream {
+    index: Option<u32>,
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    sample_rate: Option<String>,
+    sample_fmt: Option<String>,
+    bits_per_raw_sample: Option<String>,
+    channels: Option<u32>,
+    r_frame_rate: Option<String>,
+    avg_frame_rate: Option<String>,
+    nb_frames: Option<String>,
+    duration: Option<String>,
+    bit_rate: Option<String>,
+    field_order: Option<String>,
+    pix_fmt: Option<String>,
+    profile: Option<String>,
+    level: Option<i32>,
+    #[serde(default)]
+    disposition: RawDisposition,
+    #[serde(default)]
+    tags: RawTags,
+    // Display matrix of phone recordings ("rotation": -90)
+    #[serde(default)]
+    side_data_list: Vec<RawSideData>,
+}
+
+#[derive(Deserialize, Default)]
+struct RawSideData {
+    rotation: Option<f64>,
+}
+
+#[derive(Deserialize, Default)]
+struct RawDisposition {
+    #[serde(default)]
+    attached_pic: u8,
+}
+
+#[derive(Deserialize, Default)]
+struct RawTags {
+    language: Option<String>,
+    timecode: Option<String>,
+    // Everything else (artist, album, REPLAYGAIN_*, ...)
+    #[serde(flatten)]
+    other: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Default)]
+struct RawFormat {
+    format_name: Option<String>,
+    duration: Option<String>,
+    size: Option<String>,
+    bit_rate: Option<String>,
+    #[serde(default)]
+    tags: RawTags,
+}
+
+// --- WHAT THE REST OF THE APP USES ---
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct StreamInfo {
+    // Absolute stream index in the input, as used by `-map 0:<index>`
+    pub index: u32,
+    // "video" | "audio" | "subtitle" | "data" | "attachment"
+    pub codec_type: String,
+    pub codec_name: Option<String>,
+    pub language: Option<String>,
+    // Embedded cover art rather than real video
+    pub attached_pic: bool,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub sample_rate: Option<u32>,
+    // ffmpeg sample format ("s16", "s32p", "fltp", ...)
+    pub sample_fmt: Option<String>,
+    // Real bit depth when a wider format carries it (24 in an s32 stream)
+    pub bits_per_sample: Option<u32>,
+    pub channels: Option<u32>,
+    pub frame_rate: Option<f64>,
+    // Seconds, when the stream says (Matroska keeps it in a DURATION tag)
+    pub duration: Option<f64>,
+    // bit/s, when the container records it per stream
+    pub bit_rate: Option<u64>,
+    // Video only: "yuv420p", "yuv420p10le", ...
+    pub pix_fmt: Option<String>,
+    // "High", "Main 10", ... and ffprobe's level number (41 = H.264 level 4.1)
+    pub profile: Option<String>,
+    pub level: Option<i32>,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct MediaInfo {
+    // ffprobe's format_name ("mov,mp4,m4a,3gp,3g2,mj2", "matroska,webm", ...)
+    pub container: Option<String>,
+    pub duration: Option<f64>,
+    pub size_bytes: Option<u64>,
+    // Overall bit/s
+    pub bit_rate: Option<u64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<f64>,
+    // Frame count from the container header (not available for every format)
+    pub frames: Option<u64>,
+    // Start timecode ("01:00:00:00") from the video stream or container tags
+    pub timecode: Option<String>,
+    // "progressive", "tt", "bb", "tb", "bt" (ffprobe's field_order), when the stream says
+    pub field_order: Option<String>,
+    // Clockwise degrees the player turns the picture (0, 90, 180, 270).
+    // width/height are the stored frame; see display_size
+    pub rotation: u32,
+    pub has_video: bool,
+    pub has_audio: bool,
+    pub streams: Vec<StreamInfo>,
+    // Container tags, plus the first audio stream's (Ogg/Opus keep them there)
+    pub tags: BTreeMap<String, String>,
+    // How lenient reading the file had to be (see ProbeLevel)
+    pub probe_level: ProbeLevel,
+}
+
+// ==========================================
+// LENIENT PROBING
+// ==========================================
+// Some GoPro / DJI files fail ffprobe with timestamp errors or "could not
+// find codec parameters" and still play fine: their stream parameters come
+// later than ffprobe looks, or their timestamps need regenerating. A probe
+// that fails that way (or succeeds with a video or audio stream it couldn't
+// size up) is tried again a level more lenient, the level that worked is
+// kept on the MediaInfo, and encode_video reads the input with the same
+// flags. Only a file no level can read counts as unreadable.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeLevel {
+    #[default]
+    Standard,
+    // Reads up to 100 MB / 100 s to find the stream parameters
+    DeepAnalysis,
+    // The same, with timestamps regenerated
+    GenPts,
+}
+
+impl ProbeLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProbeLevel::Standard => "standard",
+            ProbeLevel::DeepAnalysis => "deep_analysis",
+            ProbeLevel::GenPts => "genpts",
+        }
+    }
+
+    fn next(self) -> Option<Self> {
+        match self {
+            ProbeLevel::Standard => Some(ProbeLevel::DeepAnalysis),
+            ProbeLevel::DeepAnalysis => Some(ProbeLevel::GenPts),
+            ProbeLevel::GenPts => None,
+        }
+    }
+
+    // Input options (before `-i`) for ffprobe and the encode alike
+    pub fn input_args(&self) -> Vec<String> {
+        let deep = ["-analyzeduration", "100M", "-probesize", "100M"];
+        match self {
+            ProbeLevel::Standard => vec![],
+            ProbeLevel::DeepAnalysis => deep.map(String::from).to_vec(),
+            ProbeLevel::GenPts => deep.iter().chain(&["-fflags", "+genpts"]).map(|a| a.to_string()).collect(),
+        }
+    }
+}
+
+// Pure: whether a failed probe's stderr is worth a more lenient try.
+pub fn recoverable(stderr: &str) -> bool {
+    RECOVERABLE_PATTERNS.iter().any(|p| stderr.contains(p))
+}
+
+// Of the picture as shown, after rotation.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+    // Within 2% of square (1080x1080, 1080x1072 after a crop)
+    Square,
+}
+
+impl Orientation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Orientation::Portrait => "portrait",
+            Orientation::Landscape => "landscape",
+            Orientation::Square => "square",
+        }
+    }
+}
+
+// 90/270 from the display matrix or the older `rotate` tag. ffmpeg's
+// matrix angle is counter-clockwise (-90 is a phone held upright).
+fn rotation_of(stream: &RawStream) -> u32 {
+    let degrees = stream
+        .side_data_list
+        .iter()
+        .find_map(|d| d.rotation)
+        .map(|r| -r)
+        .or_else(|| stream.tags.other.get("rotate").and_then(|r| progress::parse_number(r)));
+    let Some(degrees) = degrees.filter(|d| d.is_finite()) else { return 0 };
+    ((degrees.round() as i64).rem_euclid(360) as u32 + 45) / 90 % 4 * 90
+}
+
+impl MediaInfo {
+    // Width and height as played: ffmpeg turns the frames the same way
+    // before any filter, so caps apply to these.
+    pub fn display_size(&self) -> Option<(u32, u32)> {
+        let (w, h) = (self.width?, self.height?);
+        Some(if self.rotation % 180 == 90 { (h, w) } else { (w, h) })
+    }
+
+    pub fn orientation(&self) -> Option<Orientation> {
+        let (w, h) = self.display_size()?;
+        let (long, short) = (w.max(h), w.min(h));
+        Some(if long - short <= long / 50 {
+            Orientation::Square
+        } else if h > w {
+            Orientation::Portrait
+        } else {
+            Orientation::Landscape
+        })
+    }
+
+    // A video stream without a size or an audio stream without a rate:
+    // ffprobe gave up on its parameters
+    pub fn incomplete(&self) -> bool {
+        self.streams.iter().any(|s| match s.codec_type.as_str() {
+            "video" => !s.attached_pic && (s.width.is_none() || s.height.is_none()),
+            "audio" => s.sample_rate.is_none() || s.channels.is_none(),
+            _ => false,
+        })
+    }
+
+    pub fn is_short(&self) -> bool {
+        self.duration.is_some_and(|d| d < SHORT_INPUT_SECS)
+    }
+
+    // A "video" that's one picture: stills saved as mp4, animated thumbnails
+    // that don't animate, some screen captures
+    pub fn is_single_frame(&self) -> bool {
+        self.has_video
+            && match (self.frames, self.duration, self.fps) {
+                (Some(n), _, _) => n == 1,
+                (None, Some(d), Some(fps)) => d * fps < 1.5,
+                _ => false,
+            }
+    }
+
+    fn from_raw(raw: RawProbe) -> Self {
+        // Cover art shows up as a one-frame video stream; it doesn't count as video
+        let video = raw
+            .streams
+            .iter()
+            .find(|s| s.codec_type.as_deref() == Some("video") && s.disposition.attached_pic == 0);
+        let has_audio = raw.streams.iter().any(|s| s.codec_type.as_deref() == Some("audio"));
+
+        let mut tags = raw.streams
+            .iter()
+            .find(|s| s.codec_type.as_deref() == Some("audio"))
+            .map(|s| s.tags.other.clone())
+            .unwrap_or_default();
+        if let Some(format) = &raw.format {
+            tags.extend(format.tags.other.clone());
+        }
+
+        let timecode = video
+            .and_then(|v| v.tags.timecode.clone())
+            .or_else(|| raw.format.as_ref().and_then(|f| f.tags.timecode.clone()));
+
+        let format = raw.format.as_ref();
+        MediaInfo {
+            container: format.and_then(|f| f.format_name.clone()),
+            size_bytes: format.and_then(|f| parse_count(f.size.as_deref())),
+            bit_rate: format.and_then(|f| parse_count(f.bit_rate.as_deref())),
+            duration: format
+                .and_then(|f| f.duration.as_deref())
+                .and_then(progress::parse_number)
+                .filter(|d| *d > 0.0),
+            width: video.and_then(|v| v.width),
+            height: video.and_then(|v| v.height),
+            // avg_frame_rate is "0/0" for some containers, r_frame_rate is the fallback
+            fps: video.and_then(|v| {
+                v.avg_frame_rate
+                    .as_deref()
+                    .and_then(parse_rate)
+                    .or_else(|| v.r_frame_rate.as_deref().and_then(parse_rate))
+            }),
+            frames: video.and_then(|v| v.nb_frames.as_deref()).and_then(|n| n.trim().parse().ok()),
+            timecode,
+            field_order: video.and_then(|v| v.field_order.clone()).filter(|f| f != "unknown"),
+            rotation: video.map(rotation_of).unwrap_or(0),
+            has_video: video.is_some(),
+            has_audio,
+            streams: raw
+                .streams
+                .iter()
+                .enumerate()
+                .map(|(i, s)| StreamInfo {
+                    index: s.index.unwrap_or(i as u32),
+                    codec_type: s.codec_type.clone().unwrap_or_default(),
+                    codec_name: s.codec_name.clone(),
+                    language: s.tags.language.clone(),
+                    attached_pic: s.disposition.attached_pic != 0,
+                    width: s.width,
+                    height: s.height,
+                    sample_rate: s.sample_rate.as_deref().and_then(|r| r.parse().ok()),
+                    sample_fmt: s.sample_fmt.clone(),
+                    bits_per_sample: s.bits_per_raw_sample.as_deref().and_then(|b| b.parse().ok()).filter(|&b| b > 0),
+                    channels: s.channels,
+                    frame_rate: s.avg_frame_rate.as_deref().and_then(parse_rate).or_else(|| s.r_frame_rate.as_deref().and_then(parse_rate)),
+                    duration: s
+                        .duration
+                        .as_deref()
+                        .and_then(progress::parse_number)
+                        .or_else(|| s.tags.other.get("DURATION").and_then(|d| progress::parse_timestamp(d)))
+                        .filter(|d| *d > 0.0),
+                    bit_rate: parse_count(s.bit_rate.as_deref()),
+                    pix_fmt: s.pix_fmt.clone(),
+                    profile: s.profile.clone(),
+                    // -99 is "unknown"
+                    level: s.level.filter(|l| *l > 0),
+                })
+                .collect(),
+            tags,
+            probe_level: ProbeLevel::Standard,
+        }
+    }
+}
+
+// "12345" -> 12345; ffprobe prints "N/A" for unknowns
+fn parse_count(value: Option<&str>) -> Option<u64> {
+    value.and_then(|v| v.trim().parse().ok())
+}
+
+// "30000/1001" -> 29.97, "25" -> 25.0, "0/0" -> None
+pub fn parse_rate(rate: &str) -> Option<f64> {
+    let value = match rate.split_once('/') {
+        Some((num, den)) => {
+            let num = progress::parse_number(num)?;
+            let den = progress::parse_number(den)?;
+            if den == 0.0 {
+                return None;
+            }
+            num / den
+        }
+        None => progress::parse_number(rate)?,
+    };
+    if value.is_finite() && value > 0.0 { Some(value) } else { None }
+}
+
+// ==========================================
+// HELPER: PROBE A FILE WITH THE FFPROBE SIDECAR
+// ==========================================
+pub async fn probe(app: &AppHandle, input: &str) -> Result<MediaInfo, String> {
+    let mut level = ProbeLevel::Standard;
+    let mut first_error: Option<String> = None;
+    loop {
+        let next = level.next();
+        match probe_at(app, input, level).await {
+            Ok(info) if info.incomplete() && next.is_some() => {}
+            // Incomplete even at the last level: still the best there is
+            Ok(info) => {
+                if level != ProbeLevel::Standard {
+                    println!("🔎 {} could only be read with {} demuxer flags", input, level.as_str());
+                }
+                return Ok(MediaInfo { probe_level: level, ..info });
+            }
+            Err(e) if e == cancel::CANCELLED => return Err(e),
+            Err(e) if recoverable(&e) && next.is_some() => {
+                first_error.get_or_insert(e);
+            }
+            Err(e) => {
+                return Err(match first_error {
+                    Some(first) => format!("{} (lenient demuxer flags didn't help either)", first),
+                    None => e,
+                })
+            }
+        }
+        level = next.unwrap_or(level);
+    }
+}
+
+async fn probe_at(app: &AppHandle, input: &str, level: ProbeLevel) -> Result<MediaInfo, String> {
+    cancel::check()?;
+    let mut args: Vec<String> = ["-v", "error"].map(String::from).to_vec();
+    args.extend(level.input_args());
+    args.extend(["-print_format", "json", "-show_format", "-show_streams", input].map(String::from));
+    let output = ffmpeg::ffprobe_command(app)?
+        .args(args)
+        .tracked_output(app)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !output.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Could not read media info: {}", stderr.trim()));
+    }
+
+    let raw: RawProbe = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Could not parse ffprobe output: {}", e))?;
+    Ok(MediaInfo::from_raw(raw))
+}
+
+// ==========================================
+// COMMAND: PROBE MEDIA
+// ==========================================
+// What the UI shows about a file before it's compressed. ffprobe exits fine
+// on some files it can't make sense of, so "no streams at all" counts as
+// unreadable too.
+#[tauri::command]
+pub async fn probe_media(app: AppHandle, input: String) -> Result<MediaInfo, String> {
+    if !std::path::Path::new(&input).is_file() {
+        return Err(format!("{} doesn't exist or isn't a file", input));
+    }
+    let info = probe(&app, &input).await?;
+    if info.streams.is_empty() {
+        return Err(format!("{} has no audio, video or subtitle streams ffprobe can read; the file may be corrupt or not media", input));
+    }
+    Ok(info)
+}
+
+// `info` is shared with the source-tool tests
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::jobtests::{run, run_video, video, Harness, CLIP, ENCODE};
+
+    pub fn info(json: &str) -> MediaInfo {
+        MediaInfo::from_raw(serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn comma_locale_numbers_read_like_point_ones() {
+        let german = info(
+            r#"{
+            "streams": [
+                { "index": 0, "codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080,
+                  "r_frame_rate": "30000/1001", "avg_frame_rate": "29,97", "duration": "12,512000" },
+                { "index": 1, "codec_type": "audio", "codec_name": "aac", "sample_rate": "48000",
+                  "tags": { "DURATION": "00:00:12,480000000" } }
+            ],
+            "format": { "duration": "12,512000", "bit_rate": "8000000", "size": "N/A" }
+        }"#,
+        );
+        assert_eq!(german.duration, Some(12.512));
+        assert_eq!(german.fps, Some(29.97));
+        assert_eq!(german.streams[0].duration, Some(12.512));
+        assert_eq!(german.streams[1].duration, Some(12.48));
+        assert_eq!(german.bit_rate, Some(8_000_000));
+        // Unreadable fields are missing, not a failed probe
+        assert_eq!(german.size_bytes, None);
+        assert_eq!(parse_rate("24000/1001").map(|r| (r * 1000.0).round()), Some(23976.0));
+        assert_eq!(parse_rate("0/0"), None);
+        assert_eq!(parse_rate("25,0"), Some(25.0));
+    }
+
+    #[test]
+    fn rotation_metadata_turns_the_displayed_frame() {
+        let phone = |rotation: &str| info(&format!(r#"{{ "streams": [{{ "codec_type": "video", "width": 1920, "height": 1080, {} }}] }}"#, rotation));
+        let upright = phone(r#""side_data_list": [{ "rotation": -90 }]"#);
+        assert_eq!(upright.rotation, 90);
+        assert_eq!(upright.display_size(), Some((1080, 1920)));
+        assert_eq!(upright.orientation(), Some(Orientation::Portrait));
+        // The older tag, clockwise already
+        assert_eq!(phone(r#""tags": { "rotate": "270" }"#).orientation(), Some(Orientation::Portrait));
+        let flipped = phone(r#""side_data_list": [{ "rotation": 180 }]"#);
+        assert_eq!((flipped.rotation, flipped.orientation()), (180, Some(Orientation::Landscape)));
+        assert_eq!(phone(r#""side_data_list": [{ "rotation": 0 }]"#).display_size(), Some((1920, 1080)));
+    }
+
+    // ffprobe's answers as a GoPro/DJI file gets them; CLIP is the one that
+    // finally reads
+    const NO_CODEC_PARAMETERS: &str = r#"{ "exit_code": 1, "stderr": "[mov,mp4,m4a,3gp,3g2,mj2 @ 0x600000c8c000] Could not find codec parameters for stream 0 (Video: hevc (hvc1 / 0x31637668), none, 3840x2160): unspecified size\nConsider increasing the value for the 'analyzeduration' (0) and 'probesize' (5000000) options" }"#;
+    const BAD_TIMESTAMPS: &str = r#"{ "exit_code": 1, "stderr": "[mov,mp4,m4a,3gp,3g2,mj2 @ 0x600000c8c000] Invalid timestamps stream=1, pts=-1024, dts=0, size=682" }"#;
+    const NOT_MEDIA: &str = r#"{ "exit_code": 1, "stderr": "notes.txt: Invalid data found when processing input" }"#;
+    // Exits fine without the video's size
+    const UNSIZED: &str = r#"{ "stdout": { "streams": [{ "index": 0, "codec_type": "video", "codec_name": "hevc" }], "format": { "duration": "10.000000" } } }"#;
+
+    fn answers(name: &str, probes: &[&str]) -> Harness {
+        let good = format!(r#"{{ "stdout": {} }}"#, CLIP);
+        let probes: Vec<&str> = probes.iter().map(|p| if *p == "CLIP" { good.as_str() } else { p }).collect();
+        Harness::new(name, &format!(r#"{{ "probes": [{}], "runs": {} }}"#, probes.join(","), ENCODE))
+    }
+
+    fn probed(h: &Harness) -> Result<MediaInfo, String> {
+        let (app, input) = (h.handle().clone(), h.input("clip.mp4", 100_000));
+        run(async move { probe(&app, &input).await })
+    }
+
+    // The options each ffprobe run got before the file
+    fn flags(h: &Harness) -> Vec<String> {
+        h.runs().iter().map(|r| r[2..r.iter().position(|a| a == "-print_format").unwrap()].join(" ")).collect()
+    }
+
+    const DEEP: &str = "-analyzeduration 100M -probesize 100M";
+    const GENPTS: &str = "-analyzeduration 100M -probesize 100M -fflags +genpts";
+
+    #[test]
+    fn only_the_errors_lenient_flags_can_help_are_recoverable() {
+        for stderr in [NO_CODEC_PARAMETERS, BAD_TIMESTAMPS, "non monotonically increasing dts to muxer", "max_analyze_duration 5000000 reached at 5013333"] {
+            assert!(recoverable(stderr), "{}", stderr);
+        }
+        for stderr in [NOT_MEDIA, "clip.mp4: No such file or directory", "moov atom not found", "Permission denied"] {
+            assert!(!recoverable(stderr), "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn a_readable_file_is_probed_once_with_no_lenient_flags() {
+        let h = answers("probe-standard", &["CLIP"]);
+        let info = probed(&h).unwrap();
+        assert_eq!(info.probe_level, ProbeLevel::Standard);
+        assert_eq!(flags(&h), [""]);
+    }
+
+    #[test]
+    fn parameters_further_in_are_found_by_a_deep_probe() {
+        let h = answers("probe-deep", &[NO_CODEC_PARAMETERS, "CLIP"]);
+        let info = probed(&h).unwrap();
+        assert_eq!(info.probe_level, ProbeLevel::DeepAnalysis);
+        assert_eq!(info.width, Some(1280));
+        assert_eq!(flags(&h), ["", DEEP]);
+    }
+
+    #[test]
+    fn bad_timestamps_go_on_to_regenerated_ones() {
+        let h = answers("probe-genpts", &[BAD_TIMESTAMPS, BAD_TIMESTAMPS, "CLIP"]);
+        assert_eq!(probed(&h).unwrap().probe_level, ProbeLevel::GenPts);
+        assert_eq!(flags(&h), ["", DEEP, GENPTS]);
+    }
+
+    #[test]
+    fn a_stream_ffprobe_couldnt_size_up_is_probed_deeper() {
+        let h = answers("probe-unsized", &[UNSIZED, "CLIP"]);
+        let info = probed(&h).unwrap();
+        assert_eq!((info.probe_level, info.incomplete()), (ProbeLevel::DeepAnalysis, false));
+        assert_eq!(flags(&h), ["", DEEP]);
+
+        // Still unsized at the last level: that's the best there is
+        let h = answers("probe-never-sized", &[UNSIZED]);
+        let info = probed(&h).unwrap();
+        assert_eq!((info.probe_level, info.incomplete()), (ProbeLevel::GenPts, true));
+        assert_eq!(flags(&h), ["", DEEP, GENPTS]);
+    }
+
+    #[test]
+    fn other_errors_fail_without_a_retry() {
+        let h = answers("probe-not-media", &[NOT_MEDIA]);
+        assert_eq!(probed(&h).err().unwrap(), "Could not read media info: notes.txt: Invalid data found when processing input");
+        assert_eq!(flags(&h), [""]);
+    }
+
+    #[test]
+    fn a_file_no_level_reads_fails_with_the_first_error() {
+        let h = answers("probe-unreadable", &[NO_CODEC_PARAMETERS, BAD_TIMESTAMPS]);
+        let error = probed(&h).err().unwrap();
+        assert!(error.starts_with("Could not read media info: [mov,mp4,m4a,3gp,3g2,mj2 @ 0x600000c8c000] Could not find codec parameters"));
+        assert!(error.ends_with("(lenient demuxer flags didn't help either)"));
+        assert_eq!(flags(&h), ["", DEEP, GENPTS]);
+
+        // ...or the first one that wasn't worth going on from
+        let h = answers("probe-gave-up", &[BAD_TIMESTAMPS, NOT_MEDIA]);
+        assert!(probed(&h).err().unwrap().contains("Invalid timestamps stream=1"));
+        assert_eq!(flags(&h), ["", DEEP]);
+    }
+
+    #[test]
+    fn the_encode_reads_the_input_with_the_level_that_worked() {
+        // The job probes the input twice, and a real file fails both times
+        let h = answers("probe-encode", &[NO_CODEC_PARAMETERS, "CLIP", NO_CODEC_PARAMETERS, "CLIP"]);
+        let result = run_video(&h, video(&h, "out.mp4")).unwrap();
+        let encode = h.runs().into_iter().find(|r| r.iter().any(|a| a == "libx264")).unwrap();
+        let input = encode.iter().position(|a| a == "-i").unwrap();
+        assert_eq!(encode[..input].join(" "), DEEP);
+        assert!(result.warnings.contains(&format!("The input could only be read with lenient demuxer flags ({}), which the encode used too", DEEP)));
+    }
+}
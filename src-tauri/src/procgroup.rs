@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::io::{self, BufReader, Read};
+use std::process::{Child, ChildStdin, Command as StdCommand, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use tauri::async_runtime::{channel, Receiver, Sender};
+use tauri::Manager;
+use tauri_plugin_shell::process::{CommandEvent, TerminatedPayload};
+
+use crate::AppHandle;
+use crate::pause;
+use crate::queue;
+
+// ==========================================
+// SPAWNED PROCESS GROUPS
+// ==========================================
+// Every ffmpeg we start is registered here, and stopping one always takes
+// everything it started: vmaf model helpers and filter scripts are
+// grandchildren that survive a plain kill, and some of them double-fork.
+// So each child is spawned into a group of its own, a process group on
+// Unix and a job object with KILL_ON_JOB_CLOSE on Windows, and the group
+// is what gets killed. Orphans stay in it even after they're re-parented.
+// The shell plugin can't do this, so `spawn` replaces its `spawn`, with the
+// same events.
+#[derive(Default)]
+pub struct SpawnedChildren {
+    // Pid -> the job it was spawned for (None outside any job), and its group
+    pids: Mutex<HashMap<u32, (Option<u64>, Group)>>,
+}
+
+// A child spawned by `spawn`, in its own group.
+pub struct GroupChild {
+    pid: u32,
+    group: Group,
+    // Kept open like the plugin does, so ffmpeg never reads EOF on stdin
+    _stdin: Option<ChildStdin>,
+}
+
+impl GroupChild {
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    // Kills the child and everything in its group.
+    pub fn kill(&self) {
+        self.group.kill();
+    }
+}
+
+// Spawns `command` into a new group. Events come the way the plugin sends
+// them: stdout / stderr line by line (delimiter kept), then Terminated once
+// both pipes are closed.
+pub fn spawn(command: impl Into<StdCommand>) -> io::Result<(Receiver<CommandEvent>, GroupChild)> {
+    let mut command: StdCommand = command.into();
+    command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let mut child = command.spawn()?;
+    let group = match Group::new(&child) {
+        Ok(group) => group,
+        Err(e) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+    };
+
+    let (tx, rx) = channel(1);
+    let readers = [
+        child.stdout.take().map(|out| read_lines(out, tx.clone(), CommandEvent::Stdout)),
+        child.stderr.take().map(|err| read_lines(err, tx.clone(), CommandEvent::Stderr)),
+    ];
+    let stdin = child.stdin.take();
+    let pid = child.id();
+    thread::spawn(move || wait(child, readers, tx));
+    Ok((rx, GroupChild { pid, group, _stdin: stdin }))
+}
+
+fn read_lines(pipe: impl Read + Send + 'static, tx: Sender<CommandEvent>, wrap: fn(Vec<u8>) -> CommandEvent) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        loop {
+            let mut line = Vec::new();
+            match tauri::utils::io::read_line(&mut reader, &mut line) {
+                Ok(0) => break,
+                Ok(_) => {
+                    if tx.blocking_send(wrap(line)).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    let _ = tx.blocking_send(CommandEvent::Error(e.to_string()));
+                    break;
+                }
+            }
+        }
+    })
+}
+
+// Terminated goes out after the last line, as with the plugin.
+fn wait(mut child: Child, readers: [Option<thread::JoinHandle<()>>; 2], tx: Sender<CommandEvent>) {
+    let status = child.wait();
+    for reader in readers.into_iter().flatten() {
+        let _ = reader.join();
+    }
+    let event = match status {
+        Ok(status) => CommandEvent::Terminated(TerminatedPayload {
+            code: status.code(),
+            #[cfg(unix)]
+            signal: std::os::unix::process::ExitStatusExt::signal(&status),
+            #[cfg(not(unix))]
+            signal: None,
+        }),
+        Err(e) => CommandEvent::Error(e.to_string()),
+    };
+    let _ = tx.blocking_send(event);
+}
+
+// Registration of one spawned child; it's dropped from the registry with this.
+pub struct TrackedChild {
+    app: AppHandle,
+    child: Option<GroupChild>,
+    pid: u32,
+}
+
+impl TrackedChild {
+    pub fn new(app: &AppHandle, child: GroupChild) -> Self {
+        let pid = child.pid();
+        let job_id = queue::running_job_id();
+        if let Some(children) = app.try_state::<SpawnedChildren>() {
+            children.pids.lock().unwrap().insert(pid, (job_id, child.group.clone()));
+        }
+        // The next pass of a job paused between two of them starts paused
+        if job_id.is_some_and(|id| pause::is_paused(app, id)) {
+            child.group.suspend(true);
+        }
+        TrackedChild { app: app.clone(), child: Some(child), pid }
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    // Kills the child and everything it started.
+    pub fn kill(mut self) {
+        if let Some(child) = self.child.take() {
+            child.kill();
+        }
+    }
+}
+
+impl Drop for TrackedChild {
+    fn drop(&mut self) {
+        if let Some(children) = self.app.try_state::<SpawnedChildren>() {
+            children.pids.lock().unwrap().remove(&self.pid);
+        }
+    }
+}
+
+// ==========================================
+// UNIX: PROCESS GROUPS
+// ==========================================
+// The child leads a group of its own (pgid = its pid), and whatever it
+// starts inherits the group unless it asks for another one.
+#[cfg(unix)]
+#[derive(Clone)]
+struct Group {
+    pgid: libc::pid_t,
+}
+
+#[cfg(unix)]
+impl Group {
+    fn new(child: &Child) -> io::Result<Self> {
+        Ok(Group { pgid: child.id() as libc::pid_t })
+    }
+
+    fn signal(&self, signal: libc::c_int) -> bool {
+        // SAFETY: killpg only sends a signal; a gone group is just ESRCH
+        unsafe { libc::killpg(self.pgid, signal) == 0 }
+    }
+
+    fn kill(&self) {
+        self.signal(libc::SIGKILL);
+    }
+
+    fn suspend(&self, suspend: bool) -> bool {
+        self.signal(if suspend { libc::SIGSTOP } else { libc::SIGCONT })
+    }
+}
+
+// ==========================================
+// WINDOWS: JOB OBJECTS
+// ==========================================
+// Processes a job member starts join the job too, and closing the last
+// handle kills them all, so if we die the encodes go with us. The child is
+// assigned right after spawning; anything it starts in that first instant
+// escapes, which ffmpeg never does before parsing its arguments.
+#[cfg(windows)]
+#[derive(Clone)]
+struct Group {
+    job: std::sync::Arc<Job>,
+}
+
+#[cfg(windows)]
+struct Job(windows_sys::Win32::Foundation::HANDLE);
+
+// SAFETY: a job handle can be used from any thread
+#[cfg(windows)]
+unsafe impl Send for Job {}
+#[cfg(windows)]
+unsafe impl Sync for Job {}
+
+#[cfg(windows)]
+impl Drop for Job {
+    fn drop(&mut self) {
+        // SAFETY: the handle is ours and closed once
+        unsafe { windows_sys::Win32::Foundation::CloseHandle(self.0) };
+    }
+}
+
+#[cfg(windows)]
+impl Group {
+    fn new(child: &Child) -> io::Result<Self> {
+        use std::os::windows::io::AsRawHandle;
+        use windows_sys::Win32::System::JobObjects::{
+            AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation, SetInformationJobObject,
+            JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+        };
+
+        // SAFETY: plain Win32 calls; the job handle is owned by Job from here on
+        unsafe {
+            let handle = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let job = Job(handle);
+            let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+            limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            let set = SetInformationJobObject(
+                job.0,
+                JobObjectExtendedLimitInformation,
+                &limits as *const _ as *const std::ffi::c_void,
+                std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            );
+            if set == 0 || AssignProcessToJobObject(job.0, child.as_raw_handle() as _) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Group { job: std::sync::Arc::new(job) })
+        }
+    }
+
+    fn kill(&self) {
+        // SAFETY: the handle stays open as long as self
+        unsafe { windows_sys::Win32::System::JobObjects::TerminateJobObject(self.job.0, 1) };
+    }
+
+    // Windows has no SIGSTOP; suspending every thread of every process in
+    // the job is the standard stand-in (what Process Explorer's "Suspend" does).
+    fn suspend(&self, suspend: bool) -> bool {
+        let mut any = false;
+        for pid in self.members() {
+            any |= set_suspended(pid, suspend);
+        }
+        any
+    }
+
+    fn members(&self) -> Vec<u32> {
+        use windows_sys::Win32::System::JobObjects::{JobObjectBasicProcessIdList, QueryInformationJobObject};
+
+        // The list header is followed by as many pids as fit
+        const MAX: usize = 256;
+        #[repr(C)]
+        struct List {
+            assigned: u32,
+            in_list: u32,
+            pids: [usize; MAX],
+        }
+        // SAFETY: the buffer is ours and sized right in the call
+        unsafe {
+            let mut list: List = std::mem::zeroed();
+            let ok = QueryInformationJobObject(
+                self.job.0,
+                JobObjectBasicProcessIdList,
+                &mut list as *mut _ as *mut std::ffi::c_void,
+                std::mem::size_of::<List>() as u32,
+                std::ptr::null_mut(),
+            );
+            if ok == 0 {
+                return vec![];
+            }
+            list.pids[..(list.in_list as usize).min(MAX)].iter().map(|pid| *pid as u32).collect()
+        }
+    }
+}
+
+#[cfg(windows)]
+fn set_suspended(pid: u32, suspend: bool) -> bool {
+    use windows_sys::Win32::Foundation::{CloseHandle, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::System::Diagnostics::ToolHelp::{CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32};
+    use windows_sys::Win32::System::Threading::{OpenThread, ResumeThread, SuspendThread, THREAD_SUSPEND_RESUME};
+
+    let mut any = false;
+    // SAFETY: plain Win32 calls on handles opened and closed right here
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            return false;
+        }
+        let mut entry: THREADENTRY32 = std::mem::zeroed();
+        entry.dwSize = std::mem::size_of::<THREADENTRY32>() as u32;
+        let mut more = Thread32First(snapshot, &mut entry) != 0;
+        while more {
+            if entry.th32OwnerProcessID == pid {
+                let thread = OpenThread(THREAD_SUSPEND_RESUME, 0, entry.th32ThreadID);
+                if !thread.is_null() {
+                    let count = if suspend { SuspendThread(thread) } else { ResumeThread(thread) };
+                    any |= count != u32::MAX;
+                    CloseHandle(thread);
+                }
+            }
+            more = Thread32Next(snapshot, &mut entry) != 0;
+        }
+        CloseHandle(snapshot);
+    }
+    any
+}
+
+// ==========================================
+
+// Root pids of the processes running for `job_id`.
+pub fn job_pids(app: &AppHandle, job_id: u64) -> Vec<u32> {
+    let Some(children) = app.try_state::<SpawnedChildren>() else { return vec![] };
+    let pids = children.pids.lock().unwrap();
+    pids.iter().filter(|(_, (job, _))| *job == Some(job_id)).map(|(pid, _)| *pid).collect()
+}
+
+// Stops everything running for `job_id` where it is, or lets it go on.
+// False when nothing could be.
+pub fn suspend_job(app: &AppHandle, job_id: u64, suspend: bool) -> bool {
+    let Some(children) = app.try_state::<SpawnedChildren>() else { return false };
+    let groups: Vec<Group> = children
+        .pids
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|(job, _)| *job == Some(job_id))
+        .map(|(_, group)| group.clone())
+        .collect();
+    let mut any = false;
+    for group in groups {
+        any |= group.suspend(suspend);
+    }
+    any
+}
+
+// Everything we spawned, and nothing else on the machine. Single jobs are
+// stopped through their cancellation token instead (see cancel.rs).
+pub fn kill_all(app: &AppHandle) -> usize {
+    let Some(children) = app.try_state::<SpawnedChildren>() else { return 0 };
+    let groups: Vec<Group> = children.pids.lock().unwrap().values().map(|(_, group)| group.clone()).collect();
+    for group in &groups {
+        group.kill();
+    }
+    groups.len()
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    // Gone, or a zombie nobody has reaped yet
+    fn dead(pid: u32) -> bool {
+        match std::fs::read_to_string(format!("/proc/{pid}/stat")) {
+            Ok(stat) => stat.rsplit(')').next().is_some_and(|rest| rest.trim_start().starts_with('Z')),
+            Err(_) => true,
+        }
+    }
+
+    fn wait_dead(pid: u32) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if dead(pid) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    fn sh(script: &str) -> StdCommand {
+        let mut command = StdCommand::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    fn next_line(rx: &mut Receiver<CommandEvent>) -> String {
+        match rx.blocking_recv() {
+            Some(CommandEvent::Stdout(line)) => String::from_utf8(line).unwrap().trim().to_string(),
+            other => panic!("expected a line, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn events_match_the_plugin() {
+        let (mut rx, _child) = spawn(sh("echo out; echo err >&2; exit 3")).unwrap();
+        let mut stdout = vec![];
+        let mut stderr = vec![];
+        let mut code = None;
+        while let Some(event) = rx.blocking_recv() {
+            match event {
+                CommandEvent::Stdout(line) => stdout.push(line),
+                CommandEvent::Stderr(line) => stderr.push(line),
+                CommandEvent::Terminated(payload) => code = payload.code,
+                other => panic!("unexpected {other:?}"),
+            }
+        }
+        assert_eq!(stdout, vec![b"out\n".to_vec()]);
+        assert_eq!(stderr, vec![b"err\n".to_vec()]);
+        assert_eq!(code, Some(3));
+    }
+
+    #[test]
+    fn kill_takes_grandchildren_and_orphans() {
+        // A plain grandchild, and one whose parent exits so it's re-parented
+        // away from the tree, then the shell waits
+        let script = "sleep 30 & echo $!; (sleep 30 & echo $!); wait";
+        let (mut rx, child) = spawn(sh(script)).unwrap();
+        let grandchild: u32 = next_line(&mut rx).parse().unwrap();
+        let orphan: u32 = next_line(&mut rx).parse().unwrap();
+        assert!(!dead(grandchild) && !dead(orphan));
+
+        child.kill();
+        assert!(wait_dead(child.pid()), "the child survived");
+        assert!(wait_dead(grandchild), "the grandchild survived");
+        assert!(wait_dead(orphan), "the orphan survived");
+        let terminated = std::iter::from_fn(|| rx.blocking_recv()).last();
+        assert!(matches!(terminated, Some(CommandEvent::Terminated(TerminatedPayload { signal: Some(libc::SIGKILL), .. }))));
+    }
+
+    #[test]
+    fn suspend_stops_the_whole_group() {
+        let (mut rx, child) = spawn(sh("sleep 30 & echo $!; wait")).unwrap();
+        let grandchild: u32 = next_line(&mut rx).parse().unwrap();
+        let state = |pid: u32| {
+            let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).unwrap();
+            stat.rsplit(')').next().unwrap().trim_start().chars().next().unwrap()
+        };
+        assert!(child.group.suspend(true));
+        let start = Instant::now();
+        while state(grandchild) != 'T' && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(state(grandchild), 'T');
+        assert!(child.group.suspend(false));
+        child.kill();
+        assert!(wait_dead(grandchild));
+    }
+}
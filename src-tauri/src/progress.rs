@@ -0,0 +1,496 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::duration::Expected;
+use crate::risk::StderrWarnings;
+
+// --- FFMPEG STDERR PROGRESS PARSING ---
+// ffmpeg prints lines like:
+//   frame=  240 fps= 60 q=28.0 size=    1024kB time=00:00:08.00 bitrate=1048.6kbits/s speed=2.0x
+
+// Pulls the raw value that follows `key=` (ffmpeg pads values with spaces).
+// The key has to start a word outside single quotes: ffmpeg quotes file
+// names ("from 'clip time=2.mov':"), and "out_time=" isn't "time=".
+pub fn field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let needle = format!("{}=", key);
+    let (mut quoted, mut word_start) = (false, true);
+    let mut start = None;
+    for (i, c) in line.char_indices() {
+        if word_start && !quoted && line[i..].starts_with(&needle) {
+            start = Some(i + needle.len());
+            break;
+        }
+        match c {
+            '\'' => quoted = !quoted,
+            '\r' | '\n' => quoted = false,
+            _ => {}
+        }
+        word_start = c.is_whitespace();
+    }
+    let rest = line[start?..].trim_start();
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let value = &rest[..end];
+    if value.is_empty() || value == "N/A" { None } else { Some(value) }
+}
+
+// A decimal number with either separator. ffmpeg builds that call
+// setlocale (some distro and system builds) print "2,5x" and
+// "00:00:08,00" under a German or French locale; we set LC_ALL=C on the
+// processes we start, but a build can ignore it.
+pub fn parse_number(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let n: f64 = if value.contains(',') && !value.contains('.') {
+        value.replacen(',', ".", 1).parse().ok()?
+    } else {
+        value.parse().ok()?
+    };
+    n.is_finite().then_some(n)
+}
+
+// "01:02:03.45" (or "01:02:03,45") -> 3723.45
+pub fn parse_timestamp(value: &str) -> Option<f64> {
+    let mut secs = 0.0;
+    for part in value.trim().trim_start_matches('-').split(':') {
+        secs = secs * 60.0 + parse_number(part)?;
+    }
+    Some(secs)
+}
+
+// A progress report rather than a log line: those start with the frame
+// count, or the size for audio-only outputs ("Lsize" on the last one). A
+// title or comment tag with "time=" in it is printed as a log line.
+pub fn is_report(line: &str) -> bool {
+    let line = line.trim_start();
+    ["frame=", "size=", "Lsize="].iter().any(|k| line.starts_with(k))
+}
+
+fn report_field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    is_report(line).then(|| field(line, key)).flatten()
+}
+
+// Encoded position of the output so far, in seconds.
+pub fn parse_time_secs(line: &str) -> Option<f64> {
+    report_field(line, "time").and_then(parse_timestamp)
+}
+
+// Percent done against a known total, or None when the total is unknown.
+pub fn percent(done_secs: f64, total_secs: Option<f64>) -> Option<f32> {
+    let total = total_secs.filter(|t| *t > 0.0)?;
+    Some(((done_secs / total) * 100.0).clamp(0.0, 100.0) as f32)
+}
+
+// Realtime multiplier, "speed=2.5x" -> 2.5. This is what ETA is based on:
+// fps means little for stream-copy jobs, which are I/O bound.
+pub fn parse_speed(line: &str) -> Option<f64> {
+    report_field(line, "speed")
+        .and_then(|v| parse_number(v.trim_end_matches('x')))
+        .filter(|s| *s > 0.0)
+}
+
+// Frames encoded so far.
+pub fn parse_frame(line: &str) -> Option<u64> {
+    report_field(line, "frame").and_then(|v| v.parse().ok())
+}
+
+// Encoding rate in frames per second (absent for audio-only jobs).
+pub fn parse_fps(line: &str) -> Option<f32> {
+    report_field(line, "fps").and_then(parse_number).map(|f| f as f32)
+}
+
+// Output written so far. ffmpeg's "kB" has always meant 1024 bytes; newer
+// builds just spell it "KiB". Some print a fraction ("1,5MiB").
+pub fn parse_size_bytes(line: &str) -> Option<u64> {
+    let value = report_field(line, "size").or_else(|| report_field(line, "Lsize"))?;
+    let digits = value.find(|c: char| !c.is_ascii_digit() && c != '.' && c != ',').unwrap_or(value.len());
+    let n = parse_number(&value[..digits])?;
+    let unit = match &value[digits..] {
+        "" | "B" => 1,
+        "kB" | "KiB" => 1024,
+        "mB" | "MB" | "MiB" => 1024 * 1024,
+        "GiB" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    Some((n * unit as f64) as u64)
+}
+
+// Encoded time may overshoot the container duration by this much before we
+// stop trusting the duration (VFR recordings, livestream dumps).
+const DURATION_TOLERANCE: f64 = 0.02;
+// ...and never less than this, or a sub-second clip trips it on rounding
+const MIN_DURATION_SLACK_SECS: f64 = 0.25;
+
+fn slack(total: f64) -> f64 {
+    (total * DURATION_TOLERANCE).max(MIN_DURATION_SLACK_SECS)
+}
+
+// Running this close to the read cap means the throttle, not the encoder, sets the pace
+const IO_BOUND_FRACTION: f64 = 0.9;
+
+// Projected output size: the average of the last few projections (about
+// five seconds of stats), so a high-motion scene doesn't swing it
+const PROJECTION_SAMPLES: usize = 10;
+// A projection this far over max_filesize_mb, once the encode is this far
+// through, is worth a `size-warning`
+const SIZE_WARNING_OVERSHOOT: f64 = 1.2;
+const SIZE_WARNING_AFTER: f64 = 0.25;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProgressUpdate {
+    pub out_time_secs: f64,
+    pub percent: Option<f32>,
+    pub speed: Option<f64>,
+    pub fps: Option<f32>,
+    pub out_size_bytes: Option<u64>,
+    // Remaining media time divided by the current speed (never above the read cap)
+    pub eta_secs: Option<f64>,
+    // "io" | "cpu", only for jobs with a read cap
+    pub bottleneck: Option<&'static str>,
+    // Size so far scaled up to the whole duration, smoothed
+    pub projected_size_bytes: Option<u64>,
+    // The projection just went past the size cap (once per run)
+    pub size_warning: bool,
+}
+
+// ==========================================
+// PROGRESS TRACKER
+// ==========================================
+// Percent against the probed duration, switching to frame count once the
+// encode runs >2% past that duration. Until the process terminates the
+// percentage never exceeds 99, so a lying duration can't show "100%" early.
+#[derive(Clone, Debug, Default)]
+pub struct ProgressTracker {
+    total_secs: Option<f64>,
+    total_frames: Option<f64>,
+    frame_basis: bool,
+    last_time: f64,
+    last_size_bytes: Option<u64>,
+    // `-readrate` the input is paced at, in x realtime
+    read_cap: Option<f64>,
+    // Share of the whole job this run covers, e.g. (50, 100) for the second
+    // pass of a two-pass encode; None is all of it
+    span: Option<(f32, f32)>,
+    // Known-bad ffmpeg complaints seen so far
+    pub stderr_warnings: StderrWarnings,
+    // The probed duration turned out to be wrong (too short or too long)
+    pub duration_mismatch: bool,
+    // Wall time of the current ffmpeg run and how much of it was paused
+    run_wall: Duration,
+    run_paused: Duration,
+    // max_filesize_mb, for size warnings
+    size_cap_bytes: Option<u64>,
+    projections: VecDeque<f64>,
+    size_warned: bool,
+    // No percentage or ETA until the run ends (stream copies race through
+    // the file in bursts, so a percentage would jump around)
+    indeterminate: bool,
+    // The run's last stderr lines (no progress lines), for summaries ffmpeg
+    // prints at the end, like a VMAF score
+    pub stderr_tail: Vec<String>,
+}
+
+impl ProgressTracker {
+    pub fn new(total_secs: Option<f64>, nb_frames: Option<u64>, fps: Option<f64>) -> Self {
+        let total_secs = total_secs.filter(|t| *t > 0.0);
+        let total_frames = nb_frames
+            .filter(|n| *n > 0)
+            .map(|n| n as f64)
+            .or_else(|| Some(fps? * total_secs?));
+        ProgressTracker { total_secs, total_frames, ..Default::default() }
+    }
+
+    // Totals already worked out for the output (see duration.rs).
+    pub fn expecting(expected: &Expected) -> Self {
+        ProgressTracker {
+            total_secs: expected.secs.filter(|t| *t > 0.0),
+            total_frames: expected.frames.filter(|f| *f > 0.0),
+            ..Default::default()
+        }
+    }
+
+    pub fn for_duration(total_secs: Option<f64>) -> Self {
+        Self::new(total_secs, None, None)
+    }
+
+    // Same tracker settings for a shorter remaining duration (resumed encodes).
+    pub fn reset_duration(self, total_secs: Option<f64>) -> Self {
+        Self { stderr_warnings: self.stderr_warnings.clone(), ..Self::for_duration(total_secs).with_read_cap(self.read_cap) }
+    }
+
+    pub fn with_read_cap(mut self, read_cap: Option<f64>) -> Self {
+        self.read_cap = read_cap;
+        self
+    }
+
+    pub fn with_size_cap(mut self, cap_mb: Option<f64>) -> Self {
+        self.size_cap_bytes = cap_mb.map(|mb| (mb * 1024.0 * 1024.0) as u64);
+        self
+    }
+
+    pub fn indeterminate(mut self) -> Self {
+        self.indeterminate = true;
+        self
+    }
+
+    pub fn size_cap_bytes(&self) -> Option<u64> {
+        self.size_cap_bytes
+    }
+
+    // Spans nest: a pass's (0, 50) inside a job's (15, 100) is (15, 57.5)
+    pub fn with_span(mut self, start: f32, end: f32) -> Self {
+        self.span = Some((self.scale(start), self.scale(end)));
+        self
+    }
+
+    // ffmpeg's speed is media time over its wall time, stopped time included.
+    pub fn set_paused(&mut self, run_wall: Duration, run_paused: Duration) {
+        self.run_wall = run_wall;
+        self.run_paused = run_paused;
+    }
+
+    fn active_speed(&self, speed: f64) -> f64 {
+        let active = self.run_wall.saturating_sub(self.run_paused).as_secs_f64();
+        if self.run_paused.is_zero() || active <= 0.0 {
+            return speed;
+        }
+        speed * self.run_wall.as_secs_f64() / active
+    }
+
+    fn scale(&self, percent: f32) -> f32 {
+        match self.span {
+            Some((start, end)) => start + percent / 100.0 * (end - start),
+            None => percent,
+        }
+    }
+
+    // What the run reports once ffmpeg exits: 100, or the end of its span
+    pub fn finished_percent(&self) -> f32 {
+        self.scale(100.0)
+    }
+
+    pub fn total_secs(&self) -> Option<f64> {
+        self.total_secs
+    }
+
+    pub fn update(&mut self, line: &str) -> Option<ProgressUpdate> {
+        let time = parse_time_secs(line)?;
+        self.last_time = time;
+        if let Some(size) = parse_size_bytes(line) {
+            self.last_size_bytes = Some(size);
+        }
+
+        if let Some(total) = self.total_secs {
+            if time > total + slack(total) {
+                self.duration_mismatch = true;
+                self.frame_basis = self.total_frames.is_some();
+            }
+        }
+
+        let raw = match (self.frame_basis, parse_frame(line), self.total_frames) {
+            (true, Some(frame), Some(total_frames)) => Some(frame as f64 / total_frames * 100.0),
+            _ => percent(time, self.total_secs).map(|p| p as f64),
+        }
+        .filter(|_| !self.indeterminate);
+        let speed = parse_speed(line).map(|s| self.active_speed(s));
+        let pace = match (speed, self.read_cap) {
+            (Some(s), Some(cap)) => Some(s.min(cap)),
+            (s, _) => s,
+        };
+        let eta_secs = match (self.total_secs, pace) {
+            (Some(total), Some(pace)) if !self.duration_mismatch && !self.indeterminate => Some((total - time).max(0.0) / pace),
+            _ => None,
+        };
+        let bottleneck = match (speed, self.read_cap) {
+            (Some(s), Some(cap)) => Some(if s >= cap * IO_BOUND_FRACTION { "io" } else { "cpu" }),
+            _ => None,
+        };
+        let done = raw.map(|p| p / 100.0);
+        let projected_size_bytes = self.project(done, parse_size_bytes(line));
+        let over_cap = match (projected_size_bytes, self.size_cap_bytes, done) {
+            (Some(projected), Some(cap), Some(done)) => done >= SIZE_WARNING_AFTER && projected as f64 > cap as f64 * SIZE_WARNING_OVERSHOOT,
+            _ => false,
+        };
+        let size_warning = over_cap && !self.size_warned;
+        self.size_warned |= over_cap;
+        Some(ProgressUpdate {
+            out_time_secs: time,
+            percent: raw.map(|p| self.scale(p.clamp(0.0, 99.0) as f32)),
+            speed,
+            fps: parse_fps(line),
+            out_size_bytes: parse_size_bytes(line),
+            eta_secs,
+            bottleneck,
+            projected_size_bytes,
+            size_warning,
+        })
+    }
+
+    // Size so far over the share done, averaged over the last
+    // PROJECTION_SAMPLES. Nothing for outputs that aren't written (-f null
+    // passes report no size).
+    fn project(&mut self, done: Option<f64>, size: Option<u64>) -> Option<u64> {
+        let done = done.filter(|d| *d > 0.0 && *d <= 1.0)?;
+        let size = size.filter(|s| *s > 0)?;
+        if self.projections.len() == PROJECTION_SAMPLES {
+            self.projections.pop_front();
+        }
+        self.projections.push_back(size as f64 / done);
+        Some((self.projections.iter().sum::<f64>() / self.projections.len() as f64) as u64)
+    }
+
+    // Called once ffmpeg exited successfully. An encode that ends well short of
+    // the probed duration means the container claimed more than was there.
+    pub fn finish(&mut self) {
+        if let Some(total) = self.total_secs {
+            if self.last_time > 0.0 && self.last_time < total - slack(total) {
+                self.duration_mismatch = true;
+            }
+        }
+    }
+
+    pub fn last_time(&self) -> f64 {
+        self.last_time
+    }
+
+    pub fn last_size_bytes(&self) -> Option<u64> {
+        self.last_size_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = "frame=  240 fps= 60 q=28.0 size=    1024kB time=00:00:08.00 bitrate=1048.6kbits/s speed=2.0x";
+
+    #[test]
+    fn fields_come_from_the_padded_report() {
+        assert_eq!(field(REPORT, "frame"), Some("240"));
+        assert_eq!(field(REPORT, "fps"), Some("60"));
+        assert_eq!(field(REPORT, "size"), Some("1024kB"));
+        assert_eq!(field(REPORT, "time"), Some("00:00:08.00"));
+        assert_eq!(field(REPORT, "speed"), Some("2.0x"));
+        assert_eq!(field(REPORT, "dup"), None);
+        assert_eq!(field("frame=    0 fps=0.0 time=N/A bitrate=N/A", "time"), None);
+    }
+
+    #[test]
+    fn a_key_has_to_start_a_word() {
+        assert_eq!(field("out_time=00:00:01.00 time=00:00:02.00", "time"), Some("00:00:02.00"));
+        assert_eq!(field("out_time_ms=1000000", "time"), None);
+        assert_eq!(field("Lsize=  512kB time=00:00:04.00", "size"), None);
+    }
+
+    #[test]
+    fn quoted_file_names_never_give_a_field() {
+        assert_eq!(field("Input #0, mov,mp4,m4a, from 'clip time=2.mov':", "time"), None);
+        assert_eq!(field("Output #0, mp4, to '/tmp/旅行 📹 time=9.mp4':", "time"), None);
+        assert_eq!(field("Input #0, matroska, from '\"quoted\" size=3 ….mkv':", "size"), None);
+        // The quote closes, so what follows is read again
+        assert_eq!(field("from 'a time=1.mov': time=00:00:03.00", "time"), Some("00:00:03.00"));
+    }
+
+    #[test]
+    fn only_report_lines_are_reports() {
+        assert!(is_report(REPORT));
+        assert!(is_report("   frame=1 fps=0.0 time=00:00:00.04"));
+        assert!(is_report("size=     256kB time=00:00:10.00 bitrate= 209.7kbits/s speed=40x"));
+        assert!(is_report("Lsize=     300kB time=00:00:12.00 bitrate= 204.8kbits/s speed=41x"));
+        assert!(!is_report("Input #0, mov,mp4,m4a, from 'clip time=2.mov':"));
+        assert!(!is_report("    title           : time=5 and counting"));
+        assert!(!is_report("[libx264 @ 0x55] frame I:1 Avg QP:20.00 size=  1234"));
+    }
+
+    #[test]
+    fn log_lines_with_time_in_them_are_no_progress() {
+        assert_eq!(parse_time_secs(REPORT), Some(8.0));
+        assert_eq!(parse_time_secs("Input #0, mov, from 'clip time=2.mov':"), None);
+        // An apostrophe in the name ends the quote early, and still
+        // only reports count
+        assert_eq!(parse_time_secs("Input #0, mov, from 'Bob's time=3.mov':"), None);
+        assert_eq!(parse_time_secs("    comment         : time=00:01:00.00"), None);
+        assert_eq!(parse_time_secs("frame=   12 fps=0.0 q=0.0 size=0kB time=00:00:00,50 speed=1,5x"), Some(0.5));
+    }
+
+    #[test]
+    fn a_comma_locale_report_reads_like_a_point_one() {
+        let german = "frame=  240 fps=59,9 q=28,0 size=    1,5MiB time=00:00:08,00 bitrate=1048,6kbits/s speed=2,5x";
+        assert_eq!(parse_frame(german), Some(240));
+        assert_eq!(parse_fps(german), Some(59.9));
+        assert_eq!(parse_size_bytes(german), Some(1_572_864));
+        assert_eq!(parse_time_secs(german), Some(8.0));
+        assert_eq!(parse_speed(german), Some(2.5));
+        assert_eq!(parse_timestamp("01:02:03,45"), Some(3723.45));
+        let update = ProgressTracker::for_duration(Some(16.0)).update(german).unwrap();
+        assert_eq!(update.percent, Some(50.0));
+
+        // A field that doesn't parse is missing; the rest of the line still counts
+        let torn = "frame=  240 fps=1.234,5 size=    1024kB time=00:00:08,00 speed=?x";
+        assert_eq!(parse_fps(torn), None);
+        assert_eq!(parse_speed(torn), None);
+        assert_eq!(parse_time_secs(torn), Some(8.0));
+        assert_eq!(parse_size_bytes(torn), Some(1024 * 1024));
+    }
+
+    fn report(frame: u64, secs: f64) -> String {
+        format!("frame={frame:5} fps= 60 q=28.0 size=    1024kB time=00:00:{secs:05.2} bitrate=1048.6kbits/s speed=2.0x")
+    }
+
+    #[test]
+    fn percent_follows_the_duration_while_it_holds() {
+        let mut tracker = ProgressTracker::new(Some(10.0), Some(600), None);
+        let update = tracker.update(&report(300, 5.0)).unwrap();
+        assert_eq!(update.percent, Some(50.0));
+        assert_eq!(update.eta_secs, Some(2.5));
+        // Within the slack is still the same duration
+        tracker.update(&report(606, 10.2)).unwrap();
+        assert!(!tracker.duration_mismatch);
+    }
+
+    #[test]
+    fn running_past_the_duration_switches_to_frames() {
+        let mut tracker = ProgressTracker::new(Some(10.0), Some(1200), None);
+        tracker.update(&report(300, 5.0)).unwrap();
+        let update = tracker.update(&report(720, 12.0)).unwrap();
+        assert!(tracker.duration_mismatch);
+        assert_eq!(update.percent, Some(60.0));
+        // The remaining time was worked out from the wrong duration
+        assert_eq!(update.eta_secs, None);
+    }
+
+    #[test]
+    fn the_frame_total_can_come_from_fps_and_duration() {
+        let mut tracker = ProgressTracker::new(Some(10.0), None, Some(30.0));
+        let update = tracker.update(&report(150, 12.0)).unwrap();
+        assert!(tracker.duration_mismatch);
+        assert_eq!(update.percent, Some(50.0));
+    }
+
+    #[test]
+    fn without_frames_a_wrong_duration_stops_at_99() {
+        let mut tracker = ProgressTracker::for_duration(Some(10.0));
+        let update = tracker.update(&report(1200, 20.0)).unwrap();
+        assert!(tracker.duration_mismatch);
+        assert_eq!(update.percent, Some(99.0));
+        assert_eq!(tracker.finished_percent(), 100.0);
+    }
+
+    #[test]
+    fn ending_well_short_of_the_duration_is_a_mismatch_too() {
+        let mut tracker = ProgressTracker::for_duration(Some(10.0));
+        tracker.update(&report(300, 9.8)).unwrap();
+        tracker.finish();
+        assert!(!tracker.duration_mismatch);
+
+        let mut tracker = ProgressTracker::for_duration(Some(10.0));
+        tracker.update(&report(300, 5.0)).unwrap();
+        tracker.finish();
+        assert!(tracker.duration_mismatch);
+    }
+
+    #[test]
+    fn zero_totals_are_no_totals() {
+        let mut tracker = ProgressTracker::new(Some(0.0), Some(0), Some(30.0));
+        assert_eq!(tracker.total_secs(), None);
+        assert_eq!(tracker.update(&report(300, 5.0)).unwrap().percent, None);
+    }
+}
@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+
+use crate::encoders;
+
+// Bitrates below this don't give a watchable picture at any size
+pub(crate) const MIN_VIDEO_KBPS: u32 = 50;
+// A size target that leaves less than this for the video isn't met, it's
+// refused: the result would be mush
+const MIN_TARGET_KBPS: u32 = 100;
+pub(crate) const MAX_VIDEO_KBPS: u32 = 200_000;
+// What the audio track is assumed to take out of a size budget
+pub(crate) const AUDIO_BUDGET_KBPS: u32 = 128;
+
+// ==========================================
+// VIDEO QUALITY / BITRATE
+// ==========================================
+// How good (or how small) the video should be, in one encoder-neutral form.
+// Everything is optional; with nothing set each container keeps its own
+// default (see encode_video).
+//
+// Mapping per encoder family, with `q` the CRF (from a level or given
+// directly); the per-encoder rows are in encoders.rs:
+//   libx264, libx265       -crf q
+//   libsvtav1              -crf q (q may go up to 63)
+//   *_nvenc                -cq q -b:v 0 (constant quality, no bitrate floor)
+//   *_qsv                  -global_quality q
+//   *_amf                  -rc cqp -qp_i q -qp_p q
+//   *_videotoolbox         -q:v 1-100, scaled from q (higher is better there)
+//   libvpx-vp9             -crf q -b:v 0 (q may go up to 63 on VP9)
+//   libtheora              -q:v 0-10, scaled from q
+// A bitrate (given, or the budget a size cap leaves) is `-b:v <kbps>k`
+// everywhere, plus `-maxrate`/`-bufsize` when it comes from a size cap.
+// If both a quality and a bitrate are given, the bitrate wins.
+//
+// `target_size_mb` aims for a size rather than capping it ("under 8 MB for
+// Discord"): libx264 and libvpx-vp9 run two passes at the budget bitrate;
+// encoders without two-pass get one capped VBR pass like max_filesize_mb.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct QualityOptions {
+    pub quality: Option<Quality>,
+    // Average video bitrate, in kbit/s
+    pub target_bitrate_kbps: Option<u32>,
+    // Size cap for the whole output; turned into a video bitrate from the
+    // duration, leaving room for the audio
+    pub max_filesize_mb: Option<f64>,
+    // Size to aim for, with a two-pass encode where the encoder has one
+    pub target_size_mb: Option<f64>,
+}
+
+// `"low"` / `"medium"` / `"high"`, or a raw CRF number.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Quality {
+    Level(QualityLevel),
+    Crf(u32),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityLevel {
+    Low,
+    Medium,
+    High,
+}
+
+// CRF scale of an encoder; VP9's and SVT-AV1's run further than x264's.
+pub fn crf_range(encoder: &str) -> std::ops::RangeInclusive<u32> {
+    0..=encoders::profile(encoder).crf_max
+}
+
+// Levels as CRF, on each encoder's own scale
+fn level_crf(encoder: &str, level: QualityLevel) -> u32 {
+    let (low, medium, high) = encoders::profile(encoder).levels;
+    match level {
+        QualityLevel::Low => low,
+        QualityLevel::Medium => medium,
+        QualityLevel::High => high,
+    }
+}
+
+impl Quality {
+    pub fn crf(&self, encoder: &str) -> u32 {
+        match *self {
+            Quality::Level(level) => level_crf(encoder, level),
+            Quality::Crf(crf) => crf,
+        }
+    }
+}
+
+// Flags that set quality or bitrate, so the ones a container branch picked
+// can be swapped out
+const RATE_FLAGS: &[&str] = &["-crf", "-cq", "-qp", "-q:v", "-global_quality", "-rc", "-qp_i", "-qp_p", "-b:v", "-maxrate", "-bufsize"];
+
+pub fn strip_rate_args(args: &mut Vec<String>) {
+    let mut i = 0;
+    while i < args.len() {
+        if RATE_FLAGS.contains(&args[i].as_str()) && i + 1 < args.len() {
+            args.drain(i..i + 2);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+impl QualityOptions {
+    pub fn is_set(&self) -> bool {
+        self.quality.is_some() || self.target_bitrate_kbps.is_some() || self.max_filesize_mb.is_some() || self.target_size_mb.is_some()
+    }
+
+    // Whether encode_video should run two passes with `encoder`
+    pub fn two_pass(&self, encoder: &str) -> bool {
+        self.target_size_mb.is_some() && encoders::profile(encoder).two_pass
+    }
+
+    // Range problems, as (field, message); `encoder` is None when the
+    // output format isn't known yet.
+    pub fn problems(&self, encoder: Option<&str>) -> Vec<(&'static str, String)> {
+        let mut problems = vec![];
+        if let (Some(Quality::Crf(crf)), Some(encoder)) = (self.quality, encoder) {
+            let range = crf_range(encoder);
+            if !range.contains(&crf) {
+                problems.push(("quality", format!("CRF {} is outside {}-{} for {}", crf, range.start(), range.end(), encoder)));
+            }
+        }
+        if let Some(kbps) = self.target_bitrate_kbps.filter(|k| !(MIN_VIDEO_KBPS..=MAX_VIDEO_KBPS).contains(k)) {
+            problems.push(("target_bitrate_kbps", format!("{} kbps is outside {}-{}", kbps, MIN_VIDEO_KBPS, MAX_VIDEO_KBPS)));
+        }
+        if let Some(mb) = self.max_filesize_mb.filter(|mb| !mb.is_finite() || *mb <= 0.0) {
+            problems.push(("max_filesize_mb", format!("{} MB isn't a usable size cap", mb)));
+        }
+        if let Some(mb) = self.target_size_mb {
+            if !mb.is_finite() || mb <= 0.0 {
+                problems.push(("target_size_mb", format!("{} MB isn't a usable size target", mb)));
+            }
+            if self.target_bitrate_kbps.is_some() || self.max_filesize_mb.is_some() {
+                problems.push(("target_size_mb", "A size target sets the bitrate itself; leave target_bitrate_kbps and max_filesize_mb empty".to_string()));
+            }
+        }
+        problems
+    }
+
+    // About how big an output these options make, when they pin the size
+    // down: a size cap or target, or a bitrate over a known duration (plus
+    // the audio's share). None for a quality, which says nothing about size.
+    pub fn expected_bytes(&self, duration_secs: Option<f64>) -> Option<u64> {
+        if let Some(mb) = self.target_size_mb.or(self.max_filesize_mb) {
+            return Some((mb * 1024.0 * 1024.0) as u64);
+        }
+        let kbps = self.target_bitrate_kbps? + AUDIO_BUDGET_KBPS;
+        let secs = duration_secs.filter(|s| *s > 0.0)?;
+        Some((kbps as f64 * 1024.0 / 8.0 * secs) as u64)
+    }
+
+    // Video bitrate a size cap (or target) leaves for `duration_secs` of
+    // output.
+    fn budget_kbps(&self, duration_secs: Option<f64>, has_audio: bool) -> Result<Option<u32>, String> {
+        let (field, mb, floor) = match (self.target_size_mb, self.max_filesize_mb) {
+            (Some(mb), _) => ("target_size_mb", mb, MIN_TARGET_KBPS),
+            (None, Some(mb)) => ("max_filesize_mb", mb, MIN_VIDEO_KBPS),
+            (None, None) => return Ok(None),
+        };
+        let secs = duration_secs
+            .filter(|s| *s > 0.0)
+            .ok_or_else(|| format!("{} needs the input's duration, and it couldn't be read", field))?;
+        let total_kbps = mb * 8.0 * 1024.0 / secs;
+        let video_kbps = total_kbps - if has_audio { AUDIO_BUDGET_KBPS as f64 } else { 0.0 };
+        if video_kbps < floor as f64 {
+            return Err(format!(
+                "{} MB for {:.0} seconds leaves {:.0} kbps for the video, below the {} kbps it needs to look like anything. Lower the resolution (max_height), shorten the clip or allow a bigger file",
+                mb, secs, video_kbps.max(0.0), floor
+            ));
+        }
+        Ok(Some(video_kbps.min(MAX_VIDEO_KBPS as f64) as u32))
+    }
+
+    // Encoder flags for these options, or None to keep the container
+    // defaults.
+    pub fn encoder_args(&self, encoder: &str, duration_secs: Option<f64>, has_audio: bool) -> Result<Option<Vec<String>>, String> {
+        let budget = self.budget_kbps(duration_secs, has_audio)?;
+        let bitrate = match (self.target_bitrate_kbps, budget) {
+            (Some(target), Some(cap)) => Some(target.min(cap)),
+            (target, cap) => target.or(cap),
+        };
+        if let Some(kbps) = bitrate {
+            let mut args = vec!["-b:v".to_string(), format!("{}k", kbps)];
+            // Two passes hit the average on their own
+            if let Some(cap) = budget.filter(|_| !self.two_pass(encoder)) {
+                args.extend(["-maxrate".to_string(), format!("{}k", cap), "-bufsize".to_string(), format!("{}k", cap * 2)]);
+            }
+            return Ok(Some(args));
+        }
+        Ok(self.quality.map(|q| encoders::quality_args(encoder, q.crf(encoder))))
+    }
+}
@@ -0,0 +1,1668 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{Manager, State};
+use tokio_util::sync::CancellationToken;
+
+use crate::AppHandle;
+use crate::active;
+use crate::audio;
+use crate::cancel;
+use crate::events::{self, Event};
+use crate::explain::Explanation;
+use crate::hardware;
+use crate::instance;
+use crate::outputs;
+use crate::paths;
+use crate::pause;
+use crate::pip;
+use crate::plan;
+use crate::rehearsal;
+use crate::request::{AudioCompressRequest, ImageCompressRequest, PipRequest, ValidationErrors, VideoCompressRequest};
+use crate::salvage;
+use crate::schedule;
+use crate::simple::{self, SimpleChoices};
+use crate::store;
+use crate::timeline::{self, TimelineEntry};
+use crate::volumes::{self, VolumeInfo};
+use crate::watch;
+
+// What a queued job should do: the same typed requests the compress commands take.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobSpec {
+    // Boxed: video requests carry far more options than the rest
+    Video(Box<VideoCompressRequest>),
+    Image(ImageCompressRequest),
+    Audio(AudioCompressRequest),
+    Pip(PipRequest),
+}
+
+impl JobSpec {
+    pub fn input(&self) -> &str {
+        match self {
+            JobSpec::Video(r) => &r.input,
+            JobSpec::Image(r) => &r.input,
+            JobSpec::Audio(r) => &r.input,
+            JobSpec::Pip(r) => &r.input,
+        }
+    }
+
+    pub fn output(&self) -> &str {
+        match self {
+            JobSpec::Video(r) => &r.output,
+            JobSpec::Image(r) => &r.output,
+            JobSpec::Audio(r) => &r.output,
+            JobSpec::Pip(r) => &r.output,
+        }
+    }
+
+    // Same names as history entries use
+    pub fn kind(&self) -> &'static str {
+        match self {
+            JobSpec::Video(_) => "video",
+            JobSpec::Image(_) => "image",
+            JobSpec::Audio(_) => "audio",
+            JobSpec::Pip(_) => "pip",
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        match self {
+            JobSpec::Video(r) => r.validate(),
+            JobSpec::Image(r) => r.validate(),
+            JobSpec::Audio(r) => r.validate(),
+            JobSpec::Pip(r) => r.validate(),
+        }
+    }
+
+    fn volumes(&self) -> Vec<VolumeInfo> {
+        match self {
+            JobSpec::Pip(r) => volumes::volumes_for(&[&r.input, &r.overlay, &r.output]),
+            _ => volumes::volumes_for(&[self.input(), self.output()]),
+        }
+    }
+
+    // What the job is expected to leave on its destination volume, for the
+    // dispatcher's reservations. None when it can't be told, or the job
+    // skips the disk-space check (`force`).
+    fn space_need(&self, app: &AppHandle) -> Option<SpaceNeed> {
+        let mount_point = volumes::volume_of(self.output())?.mount_point;
+        let input_bytes = fs::metadata(self.input()).map(|m| m.len()).unwrap_or(0);
+        let estimated = match self {
+            JobSpec::Video(r) if r.options.force => return None,
+            JobSpec::Video(r) => r.options.fit_size_mb.map(|mb| (mb * 1024.0 * 1024.0) as u64).or(r.options.rate.expected_bytes(None)),
+            _ => None,
+        };
+        let estimated = estimated.unwrap_or_else(|| plan::estimated_output_bytes(app, self.kind(), input_bytes));
+        Some(SpaceNeed { mount_point, required_bytes: outputs::required_bytes(estimated), written_bytes: None })
+    }
+
+    fn removable_destination(&self) -> bool {
+        volumes::volume_of(self.output()).is_some_and(|v| v.removable)
+    }
+
+    // Only video requests carry one; the rest get the queue's default
+    fn overwrite_policy(&self) -> Option<outputs::OverwritePolicy> {
+        match self {
+            JobSpec::Video(r) => r.overwrite_policy,
+            _ => None,
+        }
+    }
+
+    // Same job, written somewhere else.
+    pub fn with_output(mut self, output: String) -> Self {
+        match &mut self {
+            JobSpec::Video(r) => r.output = output,
+            JobSpec::Image(r) => r.output = output,
+            JobSpec::Audio(r) => r.output = output,
+            JobSpec::Pip(r) => r.output = output,
+        }
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum QueueStatus {
+    Queued,
+    // Next in line, but a spinning disk it reads or writes is busy
+    WaitingForDisk,
+    // Its output won't fit next to what running jobs and the jobs ahead of
+    // it have reserved on the same volume; re-checked whenever a job finishes
+    WaitingForSpace,
+    // Left out of tonight's plan (see nightplan.rs); runs in a later window
+    Deferred,
+    Running,
+    // Running, but its ffmpeg is suspended (see pause.rs)
+    Paused,
+    Done,
+    Failed,
+    Cancelled,
+    // The output drive was unplugged; see redirect_output
+    DestinationRemoved,
+}
+
+// Room a job holds on its destination volume while it runs.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SpaceNeed {
+    pub mount_point: String,
+    // The size estimate plus the preflight's margin
+    pub required_bytes: u64,
+    // Bytes written there so far while it runs, the finished output's size
+    // once it's done
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub written_bytes: Option<u64>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct QueuedJob {
+    pub id: u64,
+    pub spec: JobSpec,
+    pub priority: Priority,
+    pub status: QueueStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // Volumes of the input and output, detected at enqueue time
+    pub volumes: Vec<VolumeInfo>,
+    // Latest percentage of a running job (None until ffmpeg reports one)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<f32>,
+    // Id of the watch folder whose scanner enqueued this job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watch_folder: Option<String>,
+    // The output goes to a USB stick / SD card (checked at enqueue time)
+    pub removable_destination: bool,
+    // Estimated at enqueue time (see QueueState::space_free)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub space: Option<SpaceNeed>,
+    // Failed on a damaged input; a run with `salvage` on may save some of it
+    pub salvage_offered: bool,
+    // Started in rehearsal mode: its encode is simulated (see rehearsal.rs)
+    pub simulated: bool,
+    // Enqueue-time guess of its wall time (see plan::estimated_wall_secs)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_wall_secs: Option<f64>,
+    // Running in the express lane, past the concurrency limit
+    pub express: bool,
+    // Finished output kept on the local disk after its drive went away
+    #[serde(skip)]
+    pub stranded_output: Option<PathBuf>,
+    // Where its output is while it runs: the temp file, then wherever
+    // commit put it (see track_output)
+    #[serde(skip)]
+    pub output_file: Option<PathBuf>,
+    // Lifecycle events, served by get_job_timeline rather than with every snapshot
+    #[serde(skip)]
+    pub timeline: Vec<TimelineEntry>,
+    // Why the encode picked its settings (see explain.rs); set when it finishes
+    #[serde(skip)]
+    pub explanations: Vec<Explanation>,
+}
+
+// Payload of `queue-changed`: pending jobs in dispatch order, then the rest.
+#[derive(Serialize, Clone)]
+pub struct QueueSnapshot {
+    pub pending: Vec<QueuedJob>,
+    pub running: Vec<QueuedJob>,
+    pub finished: Vec<QueuedJob>,
+}
+
+// ==========================================
+// QUEUE STATE MACHINE
+// ==========================================
+// Pure bookkeeping, no I/O. Every change happens under the one lock in
+// `JobQueue`, so a job finishing while the user drags things around can't
+// lose or duplicate a job, and jobs are looked up by id, never by a cached
+// index. Each snapshot is a state the queue really was in, but not
+// necessarily the latest one: `mutate` saves and emits after letting go of
+// the lock, so the queue-changed events (and queue.json writes) of two racing
+// mutations can land in either order. Progress, timeline and express
+// bookkeeping change without an event at all.
+pub struct QueueState {
+    next_id: u64,
+    pub max_concurrent: usize,
+    // Concurrent jobs allowed per rotational disk, on top of max_concurrent
+    pub jobs_per_hdd: usize,
+    // Not started yet, in user order. Index in this vec is the "position".
+    pending: Vec<QueuedJob>,
+    running: Vec<QueuedJob>,
+    finished: Vec<QueuedJob>,
+    // Running jobs whose processes are being killed on request
+    cancelling: HashSet<u64>,
+    // Pending jobs held back until the night plan is over
+    deferred: HashSet<u64>,
+    // Jobs estimated to take at most this many seconds may start in the
+    // express lane; 0 turns it off
+    pub express_threshold_secs: f64,
+    // Running jobs are paused while an express job runs (for machines
+    // where the two can't share the encoder)
+    pub express_pauses_others: bool,
+    // Jobs the express job paused, resumed when it leaves the lane
+    express_paused: Vec<u64>,
+}
+
+pub const DEFAULT_EXPRESS_THRESHOLD_SECS: f64 = 120.0;
+// An express job running past this many times its estimate is demoted
+const EXPRESS_OVERRUN: f64 = 2.0;
+
+impl Default for QueueState {
+    fn default() -> Self {
+        QueueState {
+            next_id: 1,
+            max_concurrent: 1,
+            jobs_per_hdd: 1,
+            pending: vec![],
+            running: vec![],
+            finished: vec![],
+            cancelling: HashSet::new(),
+            deferred: HashSet::new(),
+            express_threshold_secs: DEFAULT_EXPRESS_THRESHOLD_SECS,
+            express_pauses_others: false,
+            express_paused: vec![],
+        }
+    }
+}
+
+impl QueueState {
+    #[allow(clippy::too_many_arguments)]
+    pub fn enqueue(
+        &mut self,
+        spec: JobSpec,
+        priority: Priority,
+        volumes: Vec<VolumeInfo>,
+        removable_destination: bool,
+        space: Option<SpaceNeed>,
+        watch_folder: Option<String>,
+        estimated_wall_secs: Option<f64>,
+    ) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.push(QueuedJob {
+            id,
+            spec,
+            priority,
+            status: QueueStatus::Queued,
+            error: None,
+            volumes,
+            progress: None,
+            watch_folder,
+            removable_destination,
+            space,
+            stranded_output: None,
+            output_file: None,
+            timeline: vec![],
+            explanations: vec![],
+            salvage_offered: false,
+            simulated: false,
+            estimated_wall_secs,
+            express: false,
+        });
+        id
+    }
+
+    // Moves a pending job to `new_index` among the pending jobs (clamped).
+    pub fn reorder(&mut self, job_id: u64, new_index: usize) -> Result<(), String> {
+        let from = self.pending_index(job_id)?;
+        let job = self.pending.remove(from);
+        let to = new_index.min(self.pending.len());
+        self.pending.insert(to, job);
+        Ok(())
+    }
+
+    pub fn set_priority(&mut self, job_id: u64, priority: Priority) -> Result<(), String> {
+        let index = self.pending_index(job_id)?;
+        self.pending[index].priority = priority;
+        Ok(())
+    }
+
+    // Pending jobs are finished right here. Running ones are only flagged and
+    // Ok(true) comes back: the caller cancels the job's token, and `complete`
+    // records them as cancelled rather than failed.
+    pub fn cancel(&mut self, job_id: u64) -> Result<bool, String> {
+        if self.running.iter().any(|j| j.id == job_id) {
+            self.cancelling.insert(job_id);
+            return Ok(true);
+        }
+        let index = self.pending_index(job_id)?;
+        let mut job = self.pending.remove(index);
+        job.status = QueueStatus::Cancelled;
+        self.finished.push(job);
+        Ok(false)
+    }
+
+    // An id for a job that runs outside the queue (see run_direct)
+    fn reserve_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn job(&self, job_id: u64) -> Option<&QueuedJob> {
+        self.running.iter().chain(self.pending.iter()).chain(self.finished.iter()).find(|j| j.id == job_id)
+    }
+
+    // False for unknown ids.
+    pub fn push_timeline(&mut self, job_id: u64, entry: TimelineEntry) -> bool {
+        let job = self.running.iter_mut().chain(self.pending.iter_mut()).chain(self.finished.iter_mut()).find(|j| j.id == job_id);
+        match job {
+            Some(job) => {
+                timeline::push(&mut job.timeline, entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // False unless `job_id` is running.
+    pub fn set_paused(&mut self, job_id: u64, paused: bool) -> bool {
+        let Some(job) = self.running.iter_mut().find(|j| j.id == job_id) else { return false };
+        job.status = if paused { QueueStatus::Paused } else { QueueStatus::Running };
+        true
+    }
+
+    fn set_simulated(&mut self, job_id: u64) {
+        if let Some(job) = self.running.iter_mut().find(|j| j.id == job_id) {
+            job.simulated = true;
+        }
+    }
+
+    fn set_progress(&mut self, job_id: u64, percent: f32) {
+        if let Some(job) = self.running.iter_mut().find(|j| j.id == job_id) {
+            job.progress = Some(percent);
+        }
+    }
+
+    fn pending_index(&self, job_id: u64) -> Result<usize, String> {
+        self.pending.iter().position(|j| j.id == job_id).ok_or_else(|| {
+            if self.running.iter().any(|j| j.id == job_id) {
+                format!("Job {} is already running", job_id)
+            } else {
+                format!("Job {} is not queued", job_id)
+            }
+        })
+    }
+
+    // Holds back `deferred` (replacing the previous set) and moves `first` to
+    // the front of the pending list in that order. Unknown ids are ignored.
+    pub fn plan(&mut self, first: &[u64], deferred: HashSet<u64>) {
+        for (index, id) in first.iter().enumerate() {
+            let _ = self.reorder(*id, index);
+        }
+        for job in self.pending.iter_mut() {
+            job.status = if deferred.contains(&job.id) { QueueStatus::Deferred } else { QueueStatus::Queued };
+        }
+        self.deferred = deferred;
+    }
+
+    // A job may start when every spinning disk it touches has a free slot.
+    fn disk_slots_free(&self, job: &QueuedJob) -> bool {
+        job.volumes.iter().filter(|v| v.rotational).all(|v| {
+            let busy = self.running.iter().filter(|r| r.volumes.contains(v)).count();
+            busy < self.jobs_per_hdd
+        })
+    }
+
+    // Bytes running jobs still hold on `mount_point`: what each reserved,
+    // less what it has written there already (free space counts that).
+    pub fn reserved_bytes(&self, mount_point: &str) -> u64 {
+        self.running
+            .iter()
+            .filter_map(|j| j.space.as_ref())
+            .filter(|s| s.mount_point == mount_point)
+            .map(|s| s.required_bytes.saturating_sub(s.written_bytes.unwrap_or(0)))
+            .sum()
+    }
+
+    // A job may start when its output fits in what's free on its volume
+    // (`free`, measured by the caller) minus what running jobs there still
+    // hold and what `ahead` (jobs before it in line that fit but haven't
+    // started) is holding for them. With nothing reserved it always may:
+    // waiting wouldn't free anything, and its own preflight says what's
+    // wrong. Unknown volumes and estimates pass.
+    fn space_free(&self, job: &QueuedJob, free: &HashMap<String, u64>, ahead: &HashMap<String, u64>) -> bool {
+        let Some(need) = &job.space else { return true };
+        let Some(&available) = free.get(&need.mount_point) else { return true };
+        let reserved = self.reserved_bytes(&need.mount_point) + ahead.get(&need.mount_point).copied().unwrap_or(0);
+        reserved == 0 || reserved.saturating_add(need.required_bytes) <= available
+    }
+
+    // Running jobs with a reservation and a known output file, with the
+    // volume the reservation is on, for the caller to measure.
+    pub fn writing(&self) -> Vec<(u64, PathBuf, String)> {
+        self.running
+            .iter()
+            .filter_map(|j| Some((j.id, j.output_file.clone()?, j.space.as_ref()?.mount_point.clone())))
+            .collect()
+    }
+
+    // Destination volumes of the pending jobs, for the caller to measure.
+    pub fn space_mounts(&self) -> HashSet<String> {
+        self.pending.iter().filter_map(|j| j.space.as_ref()).map(|s| s.mount_point.clone()).collect()
+    }
+
+    // Next job to start, ordered by (priority, position) and skipping jobs whose
+    // disk is busy or whose output won't fit; None when the concurrency limit is
+    // reached or nothing can run. Skipped jobs are marked waiting-for-disk or
+    // waiting-for-space so the UI can say why. Deferred jobs aren't considered
+    // at all. `free` is the free space of each volume in space_mounts.
+    // A job that fits but is held back (its disk is busy, or it's too long
+    // for the express lane) keeps its room: jobs behind it can't take it.
+    //
+    // At the limit, one job estimated under express_threshold_secs may still
+    // start, in the express lane: a 10-second clip doesn't wait for the movie
+    // in front of it. The express job doesn't count against the limit.
+    pub fn start_next(&mut self, free: &HashMap<String, u64>) -> Option<QueuedJob> {
+        let full = self.running.iter().filter(|j| !j.express).count() >= self.max_concurrent;
+        let express = full && self.express_threshold_secs > 0.0 && !self.running.iter().any(|j| j.express);
+        if full && !express {
+            return None;
+        }
+        let mut order: Vec<usize> = (0..self.pending.len()).filter(|&pos| !self.deferred.contains(&self.pending[pos].id)).collect();
+        order.sort_by_key(|&pos| (self.pending[pos].priority, pos));
+
+        let mut chosen = None;
+        let mut ahead: HashMap<String, u64> = HashMap::new();
+        for pos in order {
+            let disk = self.disk_slots_free(&self.pending[pos]);
+            let space = self.space_free(&self.pending[pos], free, &ahead);
+            let quick = !express || self.pending[pos].estimated_wall_secs.is_some_and(|s| s <= self.express_threshold_secs);
+            if let (true, Some(need)) = (space, &self.pending[pos].space) {
+                *ahead.entry(need.mount_point.clone()).or_default() += need.required_bytes;
+            }
+            if chosen.is_none() && disk && space && quick {
+                chosen = Some(pos);
+                continue;
+            }
+            self.pending[pos].status = match (disk, space) {
+                (false, _) => QueueStatus::WaitingForDisk,
+                (true, false) => QueueStatus::WaitingForSpace,
+                (true, true) => QueueStatus::Queued,
+            };
+        }
+
+        let mut job = self.pending.remove(chosen?);
+        job.status = QueueStatus::Running;
+        job.express = express;
+        self.running.push(job.clone());
+        Some(job)
+    }
+
+    // Running jobs an express job may pause: not in the lane, not paused.
+    fn pausable(&self) -> Vec<u64> {
+        self.running.iter().filter(|j| !j.express && j.status == QueueStatus::Running).map(|j| j.id).collect()
+    }
+
+    // `job_id` leaves the express lane (it finished, or it's demoted to a
+    // normal job); the jobs it paused come back to be resumed. None if it
+    // wasn't in the lane.
+    pub fn leave_express(&mut self, job_id: u64) -> Option<Vec<u64>> {
+        let job = self.running.iter_mut().find(|j| j.id == job_id && j.express)?;
+        job.express = false;
+        Some(std::mem::take(&mut self.express_paused))
+    }
+
+    // What a running job has written so far, or a finished job's real
+    // output size; either way it takes the place of that much of the
+    // estimate.
+    pub fn set_written(&mut self, job_id: u64, bytes: Option<u64>) {
+        let job = self.running.iter_mut().chain(self.finished.iter_mut()).find(|j| j.id == job_id);
+        if let Some(space) = job.and_then(|j| j.space.as_mut()) {
+            space.written_bytes = bytes;
+        }
+    }
+
+    // Unknown ids are ignored so a late completion can never corrupt the lists.
+    // A finished job no longer reserves space.
+    pub fn complete(&mut self, job_id: u64, result: Result<(), String>) {
+        let Some(index) = self.running.iter().position(|j| j.id == job_id) else { return };
+        let mut job = self.running.remove(index);
+        match result {
+            _ if self.cancelling.remove(&job_id) => job.status = QueueStatus::Cancelled,
+            Ok(()) => {
+                job.status = QueueStatus::Done;
+                job.progress = Some(100.0);
+            }
+            Err(e) => {
+                job.status = if e.starts_with(outputs::DESTINATION_REMOVED) {
+                    QueueStatus::DestinationRemoved
+                } else {
+                    QueueStatus::Failed
+                };
+                job.salvage_offered = salvage::offered(&job.spec, &e);
+                job.error = Some(e);
+            }
+        }
+        self.finished.push(job);
+    }
+
+    // Jobs that haven't finished, in the order they should be restored:
+    // interrupted ones first so resumable encodes pick up their parts right away.
+    fn unfinished(&self) -> Vec<PersistedJob> {
+        self.running
+            .iter()
+            .chain(self.pending.iter())
+            .map(|j| PersistedJob { spec: j.spec.clone(), priority: j.priority, watch_folder: j.watch_folder.clone() })
+            .collect()
+    }
+
+    pub fn snapshot(&self) -> QueueSnapshot {
+        QueueSnapshot {
+            pending: self.pending.clone(),
+            running: self.running.clone(),
+            finished: self.finished.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedJob {
+    spec: JobSpec,
+    priority: Priority,
+    #[serde(default)]
+    watch_folder: Option<String>,
+}
+
+// ==========================================
+// DISPATCHER (managed state)
+// ==========================================
+// Unfinished jobs are saved to queue.json on every change and restored at startup.
+pub struct JobQueue {
+    path: Option<PathBuf>,
+    state: Mutex<QueueState>,
+    // Cancellation token of every running job
+    tokens: Mutex<HashMap<u64, CancellationToken>>,
+}
+
+impl JobQueue {
+    pub fn load(app: &AppHandle) -> Self {
+        // A secondary instance keeps its queue in memory only (see instance.rs)
+        let path = app.path().app_data_dir().ok().map(|dir| dir.join("queue.json")).filter(|_| !instance::is_secondary(app));
+        let mut state = QueueState::default();
+        let restored: Vec<PersistedJob> = path.as_deref().and_then(|p| store::load_json(app, "queue", p)).unwrap_or_default();
+        if !restored.is_empty() {
+            println!("♻️ Restoring {} unfinished jobs", restored.len());
+        }
+        for job in restored {
+            let volumes = job.spec.volumes();
+            let removable = job.spec.removable_destination();
+            let space = job.spec.space_need(app);
+            let estimate = estimated_wall_secs(app, &job.spec);
+            state.enqueue(job.spec, job.priority, volumes, removable, space, job.watch_folder, estimate);
+        }
+        JobQueue { path, state: Mutex::new(state), tokens: Mutex::new(HashMap::new()) }
+    }
+
+    // The snapshot is taken under the lock, the save and emit happen outside it
+    fn mutate<T>(&self, app: &AppHandle, f: impl FnOnce(&mut QueueState) -> T) -> T {
+        let (out, snapshot, unfinished) = {
+            let mut state = self.state.lock().unwrap();
+            let out = f(&mut state);
+            (out, state.snapshot(), state.unfinished())
+        };
+        self.save(&unfinished);
+        events::emit(app, Event::QueueChanged(snapshot));
+        out
+    }
+
+    fn save(&self, jobs: &[PersistedJob]) {
+        if let Err(e) = self.write(jobs) {
+            println!("⚠️ Could not save queue: {}", e);
+        }
+    }
+
+    fn write(&self, jobs: &[PersistedJob]) -> Result<(), String> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let json = serde_json::to_string_pretty(jobs).map_err(|e| e.to_string())?;
+        store::write_atomic(path, json.as_bytes(), is_valid)
+    }
+
+    // Rewrites the file from what's loaded (repair_stores).
+    pub fn persist(&self) -> Result<(), String> {
+        if self.path.is_none() {
+            return Err("The queue isn't saved while another instance of the app is running".to_string());
+        }
+        let unfinished = self.state.lock().unwrap().unfinished();
+        self.write(&unfinished)
+    }
+}
+
+pub fn is_valid(text: &str) -> bool {
+    store::parses::<Vec<PersistedJob>>(text)
+}
+
+tokio::task_local! {
+    // Id of the queue job the current task is running, so progress can be
+    // attributed without threading the id through every encode helper.
+    static CURRENT_JOB: u64;
+    // Same for a job started straight from a command (run_direct)
+    static DIRECT_JOB: u64;
+}
+
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct JobProgress {
+    pub job_id: u64,
+    pub percent: f32,
+}
+
+// Records a progress update for the queue job running on this task, if any,
+// and sends it as `job-progress` for queue and direct jobs alike. Progress
+// isn't persisted and doesn't fire `queue-changed`.
+pub fn report_progress(app: &AppHandle, percent: Option<f32>) {
+    let Some(percent) = percent else { return };
+    if let Ok(job_id) = CURRENT_JOB.try_with(|id| *id) {
+        if let Some(queue) = app.try_state::<JobQueue>() {
+            queue.state.lock().unwrap().set_progress(job_id, percent);
+        }
+    }
+    if let Some(job_id) = running_job_id() {
+        events::emit(app, Event::JobProgress(JobProgress { job_id, percent }));
+    }
+}
+
+// Id of the queue job running on this task, if any.
+pub fn current_job_id() -> Option<u64> {
+    CURRENT_JOB.try_with(|id| *id).ok()
+}
+
+// The queue or direct job the current task is running.
+pub fn running_job_id() -> Option<u64> {
+    current_job_id().or_else(|| DIRECT_JOB.try_with(|id| *id).ok())
+}
+
+// Watch folder of the queue job running on this task, so history can be
+// filtered per folder.
+pub fn current_watch_folder(app: &AppHandle) -> Option<String> {
+    let job_id = CURRENT_JOB.try_with(|id| *id).ok()?;
+    let queue = app.try_state::<JobQueue>()?;
+    let state = queue.state.lock().unwrap();
+    state.job(job_id)?.watch_folder.clone()
+}
+
+pub fn push_timeline(app: &AppHandle, job_id: u64, entry: TimelineEntry) -> bool {
+    let Some(queue) = app.try_state::<JobQueue>() else { return false };
+    let pushed = queue.state.lock().unwrap().push_timeline(job_id, entry);
+    pushed
+}
+
+// Where the queue job running on this task has its output now: outputs.rs
+// calls this with the temp file when the job claims its output, and with
+// the final path when it's committed. With Rename, or a temp file in a work
+// folder, neither is spec.output().
+pub fn track_output(app: &AppHandle, path: &Path) {
+    let Some(job_id) = current_job_id() else { return };
+    let Some(queue) = app.try_state::<JobQueue>() else { return };
+    let mut state = queue.state.lock().unwrap();
+    if let Some(job) = state.running.iter_mut().find(|j| j.id == job_id) {
+        job.output_file = Some(path.to_path_buf());
+    }
+}
+
+// Size of `file` when it's on `mount_point`; what it takes from that volume.
+fn written_on(file: &Path, mount_point: &str) -> Option<u64> {
+    volumes::volume_of(&file.to_string_lossy()).filter(|v| v.mount_point == mount_point)?;
+    fs::metadata(file).ok().map(|m| m.len())
+}
+
+// Remembers a finished output that couldn't reach its removed drive.
+pub fn strand_output(app: &AppHandle, job_id: u64, staged: PathBuf) {
+    let Some(queue) = app.try_state::<JobQueue>() else { return };
+    let mut state = queue.state.lock().unwrap();
+    if let Some(job) = state.running.iter_mut().find(|j| j.id == job_id) {
+        job.stranded_output = Some(staged);
+    }
+}
+
+pub fn timeline(app: &AppHandle, job_id: u64) -> Option<Vec<TimelineEntry>> {
+    let queue = app.try_state::<JobQueue>()?;
+    let state = queue.state.lock().unwrap();
+    state.job(job_id).map(|j| j.timeline.clone())
+}
+
+pub fn set_explanations(app: &AppHandle, job_id: u64, explanations: Vec<Explanation>) {
+    let Some(queue) = app.try_state::<JobQueue>() else { return };
+    let mut state = queue.state.lock().unwrap();
+    if let Some(job) = state.running.iter_mut().find(|j| j.id == job_id) {
+        job.explanations = explanations;
+    }
+}
+
+pub fn explanations(app: &AppHandle, job_id: u64) -> Option<Vec<Explanation>> {
+    let queue = app.try_state::<JobQueue>()?;
+    let state = queue.state.lock().unwrap();
+    state.job(job_id).map(|j| j.explanations.clone())
+}
+
+// Payload of `job-started`, sent by every encode as it begins (queued or not).
+#[derive(Serialize, Clone, Default, JsonSchema)]
+pub struct JobStarted {
+    pub job_id: Option<u64>,
+    pub input: String,
+    pub output: String,
+    // Video encoder chosen for the job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoder: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub io_throttle_mbps: Option<u32>,
+    // The `-readrate` multiplier actually passed to ffmpeg
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub readrate: Option<f64>,
+    // Everything simple mode decided, for jobs it queued
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<serde_json::Value>")]
+    pub simple: Option<SimpleChoices>,
+}
+
+impl JobStarted {
+    pub fn new(input: &str, output: &str) -> Self {
+        JobStarted { input: input.to_string(), output: output.to_string(), ..Default::default() }
+    }
+
+    pub fn emit(mut self, app: &AppHandle) {
+        self.job_id = running_job_id();
+        self.simple = self.job_id.and_then(|id| simple::choices_for(app, id));
+        let mut params = vec![];
+        params.extend(self.encoder.clone().map(|e| ("encoder", e)));
+        params.extend(self.io_throttle_mbps.map(|m| ("io_throttle_mbps", m.to_string())));
+        timeline::record(app, timeline::ENCODE_STARTED, &params);
+        events::emit(app, Event::JobStarted(Box::new(self)));
+    }
+}
+
+// Starts as many jobs as the concurrency limit allows, and none outside the
+// schedule window.
+pub fn pump(app: &AppHandle) {
+    if !schedule::is_open(app) {
+        return;
+    }
+    let queue = app.state::<JobQueue>();
+    // Measured outside the lock, once a pump: what the jobs started here
+    // take is counted as their reservations, and what running jobs have
+    // written so far comes off theirs
+    let (mounts, writing) = {
+        let state = queue.state.lock().unwrap();
+        (state.space_mounts(), state.writing())
+    };
+    let free: HashMap<String, u64> =
+        mounts.into_iter().filter_map(|m| volumes::available_bytes(Path::new(&m)).map(|bytes| (m, bytes))).collect();
+    let written: Vec<(u64, Option<u64>)> = writing.iter().map(|(id, file, mount)| (*id, written_on(file, mount))).collect();
+    {
+        let mut state = queue.state.lock().unwrap();
+        for (id, bytes) in written {
+            state.set_written(id, bytes);
+        }
+    }
+    while let Some(job) = queue.mutate(app, |s| s.start_next(&free)) {
+        // Decided as the job starts, so turning rehearsal mode off only
+        // changes jobs that haven't
+        let simulated = rehearsal::enabled(app);
+        if simulated {
+            queue.mutate(app, |s| s.set_simulated(job.id));
+        }
+        if job.express {
+            start_express(app, &job);
+        }
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            println!("▶️ Queue: starting job {} ({})", job.id, job.spec.input());
+            timeline::record_for(&app, job.id, timeline::STARTED, &[]);
+            let token = CancellationToken::new();
+            app.state::<JobQueue>().tokens.lock().unwrap().insert(job.id, token.clone());
+            let run = async {
+                if simulated {
+                    rehearsal::run(&app, &job.spec, job.watch_folder.as_deref()).await
+                } else {
+                    run_spec(&app, job.spec.clone()).await
+                }
+            };
+            let result = cancel::settle(&token, cancel::scope(token.clone(), CURRENT_JOB.scope(job.id, run)).await);
+            app.state::<JobQueue>().tokens.lock().unwrap().remove(&job.id);
+            pause::forget(&app, job.id);
+            leave_express(&app, job.id);
+            let succeeded = result.is_ok();
+            // Where commit put it, which isn't always the requested path
+            let committed = app.state::<JobQueue>().state.lock().unwrap().job(job.id).and_then(|j| j.output_file.clone());
+            let written = committed.and_then(|f| fs::metadata(f).ok()).map(|m| m.len()).filter(|_| succeeded && !simulated);
+            app.state::<JobQueue>().mutate(&app, |s| {
+                s.complete(job.id, result);
+                s.set_written(job.id, written);
+            });
+            if let (true, false, Some(folder_id)) = (succeeded, simulated, &job.watch_folder) {
+                watch::after_job(&app, folder_id, job.spec.input());
+            }
+            pump(&app);
+            active::job_ended(&app, job.id);
+        });
+    }
+}
+
+// ==========================================
+// EXPRESS LANE
+// ==========================================
+// With express_pauses_others the jobs running when an express job starts
+// are paused (like pause_job) and resumed when it leaves the lane; jobs the
+// user paused themselves are left as they are. Without it, only when every
+// GPU session is taken (see hardware.rs): the jobs holding one are paused,
+// which gives their sessions to the express job. An express job that runs
+// past EXPRESS_OVERRUN times its estimate was misjudged: it's demoted to a
+// normal job, the paused ones are resumed, and the lane is free again.
+
+// Enqueue-time estimate of a spec; for the lane only, so without probing.
+fn estimated_wall_secs(app: &AppHandle, spec: &JobSpec) -> Option<f64> {
+    let bytes = fs::metadata(spec.input()).ok()?.len();
+    Some(plan::estimated_wall_secs(app, spec.kind(), bytes))
+}
+
+fn start_express(app: &AppHandle, job: &QueuedJob) {
+    let estimate = job.estimated_wall_secs.unwrap_or_default();
+    println!("🚀 Queue: job {} takes the express lane (~{:.0}s)", job.id, estimate);
+    timeline::record_for(app, job.id, timeline::EXPRESS_STARTED, &[("estimated_secs", format!("{:.0}", estimate))]);
+    let queue = app.state::<JobQueue>();
+    let others = {
+        let state = queue.state.lock().unwrap();
+        match state.express_pauses_others {
+            true => state.pausable(),
+            // Otherwise only what it takes to leave it a GPU session
+            false if hardware::sessions_full(app) => {
+                let holders = hardware::session_jobs(app);
+                state.pausable().into_iter().filter(|id| holders.contains(id)).collect()
+            }
+            false => vec![],
+        }
+    };
+    // A job between two ffmpeg runs has nothing to suspend; it keeps going
+    let paused: Vec<u64> = others.into_iter().filter(|id| pause::pause_job(app.clone(), *id).is_ok()).collect();
+    queue.state.lock().unwrap().express_paused = paused;
+
+    let (app, job_id) = (app.clone(), job.id);
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(Duration::from_secs_f64((estimate * EXPRESS_OVERRUN).max(1.0))).await;
+        if leave_express(&app, job_id) {
+            println!("🐢 Queue: job {} ran past twice its estimate, it's a normal job now", job_id);
+            timeline::record_for(&app, job_id, timeline::EXPRESS_DEMOTED, &[("estimated_secs", format!("{:.0}", estimate))]);
+            pump(&app);
+        }
+    });
+}
+
+// False if `job_id` wasn't in the lane (any more).
+fn leave_express(app: &AppHandle, job_id: u64) -> bool {
+    let queue = app.state::<JobQueue>();
+    // The snapshot that follows (the job's completion, or the pump after a
+    // demotion) shows it
+    let paused = queue.state.lock().unwrap().leave_express(job_id);
+    let Some(paused) = paused else { return false };
+    for id in paused.into_iter().filter(|id| pause::is_paused(app, *id)) {
+        let _ = pause::resume_job(app.clone(), id);
+    }
+    true
+}
+
+// ==========================================
+// DIRECT JOBS
+// ==========================================
+// compress_video / compress_image called by the UI without the queue. They
+// draw ids from the queue's counter and keep their token next to the queue
+// jobs' ones, so cancel_job and kill_ffmpeg reach them too; `job-started`
+// tells the UI the id while the command is still running.
+pub async fn run_direct<T>(app: &AppHandle, job: impl Future<Output = Result<T, String>>) -> (u64, Result<T, String>) {
+    let (id, token) = reserve_direct(app);
+    (id, run_reserved(app, id, token, job).await)
+}
+
+// An id and registered token up front, for callers that hand ids out before
+// the job starts (a batch cancelled early never starts it at all).
+pub fn reserve_direct(app: &AppHandle) -> (u64, CancellationToken) {
+    let queue = app.state::<JobQueue>();
+    let id = queue.state.lock().unwrap().reserve_id();
+    let token = CancellationToken::new();
+    queue.tokens.lock().unwrap().insert(id, token.clone());
+    (id, token)
+}
+
+pub async fn run_reserved<T>(app: &AppHandle, id: u64, token: CancellationToken, job: impl Future<Output = Result<T, String>>) -> Result<T, String> {
+    let result = if token.is_cancelled() {
+        Err(cancel::CANCELLED.to_string())
+    } else {
+        cancel::settle(&token, cancel::scope(token.clone(), DIRECT_JOB.scope(id, job)).await)
+    };
+    app.state::<JobQueue>().tokens.lock().unwrap().remove(&id);
+    pause::forget(app, id);
+    active::job_ended(app, id);
+    if result.as_ref().err().is_some_and(|e| e == cancel::CANCELLED) {
+        println!("🛑 Job {} cancelled", id);
+    }
+    result
+}
+
+pub async fn run_spec(app: &AppHandle, spec: JobSpec) -> Result<(), String> {
+    match spec {
+        JobSpec::Video(request) => crate::run_video_job(app, *request).await.map(|_| ()),
+        JobSpec::Image(request) => crate::run_image_job(app, request).await.map(|_| ()),
+        JobSpec::Audio(request) => audio::run_audio_job(app, request).await.map(|_| ()),
+        JobSpec::Pip(request) => pip::run_pip_job(app, request).await.map(|_| ()),
+    }
+}
+
+// ==========================================
+// COMMANDS
+// ==========================================
+// Shared by the Tauri command and the automation API. Nothing is queued
+// unless every spec is valid.
+pub fn enqueue(app: &AppHandle, specs: Vec<JobSpec>, priority: Option<Priority>) -> Result<Vec<u64>, String> {
+    enqueue_from(app, specs, priority, None)
+}
+
+// Same, stamping every job with the watch folder that found it.
+pub fn enqueue_from(
+    app: &AppHandle,
+    specs: Vec<JobSpec>,
+    priority: Option<Priority>,
+    watch_folder: Option<String>,
+) -> Result<Vec<u64>, String> {
+    for (i, spec) in specs.iter().enumerate() {
+        spec.validate().map_err(|e| format!("Job {}: {}", i + 1, e))?;
+    }
+    let priority = priority.unwrap_or_default();
+    // Disk detection happens outside the lock
+    let jobs: Vec<_> = specs.into_iter().map(|spec| {
+        let volumes = spec.volumes();
+        let removable = spec.removable_destination();
+        let space = spec.space_need(app);
+        let estimate = estimated_wall_secs(app, &spec);
+        (spec, volumes, removable, space, estimate)
+    }).collect();
+    let queue = app.state::<JobQueue>();
+    let ids: Vec<u64> = queue.mutate(app, |s| {
+        jobs.into_iter()
+            .map(|(spec, volumes, removable, space, estimate)| s.enqueue(spec, priority, volumes, removable, space, watch_folder.clone(), estimate))
+            .collect()
+    });
+    for id in &ids {
+        let mut params = vec![("priority", format!("{:?}", priority).to_lowercase())];
+        params.extend(watch_folder.clone().map(|f| ("watch_folder", f)));
+        timeline::record_for(app, *id, timeline::QUEUED, &params);
+    }
+    pump(app);
+    Ok(ids)
+}
+
+pub fn max_concurrent(app: &AppHandle) -> usize {
+    app.state::<JobQueue>().state.lock().unwrap().max_concurrent
+}
+
+pub fn snapshot(app: &AppHandle) -> QueueSnapshot {
+    app.state::<JobQueue>().state.lock().unwrap().snapshot()
+}
+
+// See QueueState::plan; starts whatever that lets through.
+pub fn plan(app: &AppHandle, first: &[u64], deferred: HashSet<u64>) {
+    app.state::<JobQueue>().mutate(app, |s| s.plan(first, deferred));
+    pump(app);
+}
+
+// See QueueState::set_paused; direct jobs aren't in the queue.
+pub fn set_paused(app: &AppHandle, job_id: u64, paused: bool) {
+    let queue = app.state::<JobQueue>();
+    let running = queue.state.lock().unwrap().running.iter().any(|j| j.id == job_id);
+    if running {
+        queue.mutate(app, |s| s.set_paused(job_id, paused));
+    }
+}
+
+pub fn find_job(app: &AppHandle, job_id: u64) -> Option<QueuedJob> {
+    app.state::<JobQueue>().state.lock().unwrap().job(job_id).cloned()
+}
+
+#[tauri::command]
+pub fn enqueue_jobs(app: AppHandle, specs: Vec<JobSpec>, priority: Option<Priority>) -> Result<Vec<u64>, String> {
+    enqueue(&app, specs, priority)
+}
+
+#[tauri::command]
+pub fn get_queue(app: AppHandle) -> QueueSnapshot {
+    snapshot(&app)
+}
+
+#[tauri::command]
+pub fn cancel_job(app: AppHandle, queue: State<'_, JobQueue>, job_id: u64) -> Result<(), String> {
+    let queued = queue.state.lock().unwrap().job(job_id).is_some();
+    if queued && !queue.mutate(&app, |s| s.cancel(job_id))? {
+        return Ok(());
+    }
+    // A suspended ffmpeg is let go first, so it dies like a running one
+    pause::forget(&app, job_id);
+    match queue.tokens.lock().unwrap().get(&job_id) {
+        Some(token) => token.cancel(),
+        // Neither in the queue nor a running direct job
+        None if !queued => return Err(format!("Job {} not found", job_id)),
+        None => {}
+    }
+    Ok(())
+}
+
+// Cancels every running job (the pending ones stay queued).
+pub fn cancel_running(app: &AppHandle) {
+    let Some(queue) = app.try_state::<JobQueue>() else { return };
+    let ids: Vec<u64> = queue.tokens.lock().unwrap().keys().copied().collect();
+    for id in ids {
+        let _ = cancel_job(app.clone(), queue.clone(), id);
+    }
+}
+
+// ==========================================
+// COMMAND: REDIRECT OUTPUT
+// ==========================================
+// For a job whose destination drive went away: a finished output is moved
+// to `new_path` (and the job counts as done), otherwise the job is queued
+// again writing there. The move goes through the output reservations like
+// any job's, so the job's overwrite_policy holds. Returns the id of the new
+// job, if one was queued.
+#[tauri::command]
+pub fn redirect_output(app: AppHandle, queue: State<'_, JobQueue>, job_id: u64, new_path: String) -> Result<Option<u64>, String> {
+    let job = find_job(&app, job_id).ok_or_else(|| format!("Job {} not found", job_id))?;
+    if job.status != QueueStatus::DestinationRemoved {
+        return Err(format!("Job {} didn't lose its destination", job_id));
+    }
+    let new_path = paths::secure_output(&app, &new_path, false)?;
+    paths::ensure_not_input(job.spec.input(), &new_path)?;
+    if let Some(staged) = job.stranded_output.filter(|p| p.exists()) {
+        let policy = job.spec.overwrite_policy().unwrap_or(outputs::OverwritePolicy::Rename);
+        let placed = outputs::place_file(&app, &staged, &new_path, policy)?;
+        let new_path = placed.to_string_lossy().to_string();
+        queue.mutate(&app, |s| {
+            if let Some(job) = s.finished.iter_mut().find(|j| j.id == job_id) {
+                job.spec = job.spec.clone().with_output(new_path.clone());
+                job.status = QueueStatus::Done;
+                job.error = None;
+                job.stranded_output = None;
+                job.output_file = Some(placed.clone());
+                job.progress = Some(100.0);
+            }
+        });
+        timeline::record_for(&app, job_id, timeline::REDIRECTED, &[("output", new_path)]);
+        return Ok(None);
+    }
+    let ids = enqueue(&app, vec![job.spec.with_output(new_path)], Some(job.priority))?;
+    let new_id = ids.first().copied();
+    // The old entry stays in the list as history, but can't be redirected twice
+    queue.mutate(&app, |s| {
+        if let Some(job) = s.finished.iter_mut().find(|j| j.id == job_id) {
+            job.status = QueueStatus::Failed;
+            job.error = new_id.map(|id| format!("Redirected to job {}", id));
+        }
+    });
+    timeline::record_for(&app, job_id, timeline::REDIRECTED, &[("job_id", new_id.unwrap_or(0).to_string())]);
+    Ok(new_id)
+}
+
+#[tauri::command]
+pub fn reorder_job(app: AppHandle, queue: State<'_, JobQueue>, job_id: u64, new_index: usize) -> Result<(), String> {
+    queue.mutate(&app, |s| s.reorder(job_id, new_index))
+}
+
+#[tauri::command]
+pub fn set_job_priority(app: AppHandle, queue: State<'_, JobQueue>, job_id: u64, priority: Priority) -> Result<(), String> {
+    queue.mutate(&app, |s| s.set_priority(job_id, priority))
+}
+
+// Each limit can be changed on its own; running jobs are never interrupted.
+// express_threshold_secs 0 turns the express lane off.
+#[tauri::command]
+pub fn set_queue_limits(
+    app: AppHandle,
+    queue: State<'_, JobQueue>,
+    max_concurrent: Option<usize>,
+    jobs_per_hdd: Option<usize>,
+    express_threshold_secs: Option<f64>,
+    express_pauses_others: Option<bool>,
+) {
+    queue.mutate(&app, |s| {
+        if let Some(n) = max_concurrent {
+            s.max_concurrent = n.max(1);
+        }
+        if let Some(n) = jobs_per_hdd {
+            s.jobs_per_hdd = n.max(1);
+        }
+        if let Some(secs) = express_threshold_secs {
+            s.express_threshold_secs = if secs.is_finite() { secs.max(0.0) } else { 0.0 };
+        }
+        if let Some(on) = express_pauses_others {
+            s.express_pauses_others = on;
+        }
+    });
+    pump(&app);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn spec(n: u64) -> JobSpec {
+        serde_json::from_value(serde_json::json!({ "kind": "image", "input": format!("/in/{}.png", n), "output": format!("/out/{}.webp", n) })).unwrap()
+    }
+
+    fn queue_of(count: u64) -> QueueState {
+        let mut state = QueueState { express_threshold_secs: 0.0, ..Default::default() };
+        for n in 0..count {
+            state.enqueue(spec(n), Priority::Normal, vec![], false, None, None, None);
+        }
+        state
+    }
+
+    fn ids(jobs: &[QueuedJob]) -> Vec<u64> {
+        jobs.iter().map(|j| j.id).collect()
+    }
+
+    // Every job is in exactly one list, and no more run than allowed
+    fn check(state: &QueueState, total: usize) {
+        let snapshot = state.snapshot();
+        assert!(snapshot.running.len() <= state.max_concurrent, "{} running", snapshot.running.len());
+        let mut all: Vec<u64> = [&snapshot.pending, &snapshot.running, &snapshot.finished].into_iter().flat_map(|l| ids(l)).collect();
+        all.sort_unstable();
+        assert_eq!(all, (1..=total as u64).collect::<Vec<_>>());
+        assert!(snapshot.pending.iter().all(|j| !matches!(j.status, QueueStatus::Running | QueueStatus::Done | QueueStatus::Failed | QueueStatus::Cancelled)));
+        assert!(snapshot.finished.iter().all(|j| matches!(j.status, QueueStatus::Done | QueueStatus::Failed | QueueStatus::Cancelled)));
+    }
+
+    #[test]
+    fn jobs_start_by_priority_then_position() {
+        let mut state = queue_of(4);
+        state.set_priority(3, Priority::High).unwrap();
+        state.set_priority(1, Priority::Low).unwrap();
+        state.reorder(4, 0).unwrap();
+        let free = HashMap::new();
+        let started: Vec<u64> = (0..4)
+            .map(|_| {
+                let job = state.start_next(&free).unwrap();
+                state.complete(job.id, Ok(()));
+                job.id
+            })
+            .collect();
+        assert_eq!(started, [3, 4, 2, 1]);
+    }
+
+    #[test]
+    fn only_pending_jobs_can_be_moved() {
+        let mut state = queue_of(3);
+        let running = state.start_next(&HashMap::new()).unwrap();
+        assert_eq!(state.reorder(running.id, 0), Err(format!("Job {} is already running", running.id)));
+        assert_eq!(state.set_priority(99, Priority::High), Err("Job 99 is not queued".to_string()));
+        // Past the end means last
+        state.reorder(2, 100).unwrap();
+        assert_eq!(ids(&state.snapshot().pending), [3, 2]);
+        // A late completion of something that isn't running changes nothing
+        state.complete(2, Ok(()));
+        state.complete(42, Err("gone".to_string()));
+        check(&state, 3);
+    }
+
+    #[test]
+    fn unfinished_jobs_are_saved_interrupted_first() {
+        let mut state = queue_of(3);
+        state.set_priority(3, Priority::High).unwrap();
+        let running = state.start_next(&HashMap::new()).unwrap();
+        assert_eq!(running.id, 3);
+        state.cancel(2).unwrap();
+
+        let saved = state.unfinished();
+        let inputs: Vec<&str> = saved.iter().map(|j| j.spec.input()).collect();
+        assert_eq!(inputs, ["/in/2.png", "/in/0.png"]);
+        assert_eq!(saved[0].priority, Priority::High);
+        let json = serde_json::to_string(&saved).unwrap();
+        assert!(is_valid(&json));
+        assert!(!is_valid(&json[..json.len() - 1]));
+    }
+
+    // Tiny xorshift so each thread gets its own repeatable sequence
+    fn rng(seed: u64) -> impl FnMut(u64) -> u64 {
+        let mut x = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        move |below| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x % below.max(1)
+        }
+    }
+
+    #[test]
+    fn reordering_survives_jobs_finishing_underneath_it() {
+        const JOBS: u64 = 200;
+        let mut initial = queue_of(JOBS);
+        initial.max_concurrent = 3;
+        let state = Arc::new(Mutex::new(initial));
+        let free = HashMap::new();
+
+        // Workers start and finish jobs, the way the pump and job tasks do
+        let workers: Vec<_> = (0..3)
+            .map(|w| {
+                let state = state.clone();
+                let free = free.clone();
+                std::thread::spawn(move || {
+                    let mut next = rng(w + 1);
+                    loop {
+                        let started = state.lock().unwrap().start_next(&free);
+                        let Some(job) = started else {
+                            let s = state.lock().unwrap();
+                            if s.snapshot().pending.is_empty() {
+                                break;
+                            }
+                            drop(s);
+                            std::thread::yield_now();
+                            continue;
+                        };
+                        std::thread::yield_now();
+                        let result = if next(4) == 0 { Err("ffmpeg failed".to_string()) } else { Ok(()) };
+                        state.lock().unwrap().complete(job.id, result);
+                    }
+                })
+            })
+            .collect();
+
+        // The user drags, reprioritises and cancels jobs by id, often ones
+        // that have just started or finished
+        let users: Vec<_> = (0..3)
+            .map(|u| {
+                let state = state.clone();
+                std::thread::spawn(move || {
+                    let mut next = rng(100 + u);
+                    for _ in 0..2000 {
+                        let id = 1 + next(JOBS);
+                        let mut s = state.lock().unwrap();
+                        let _ = match next(10) {
+                            0 => s.cancel(id).map(|_| ()),
+                            1..=3 => s.set_priority(id, [Priority::High, Priority::Normal, Priority::Low][next(3) as usize]),
+                            _ => s.reorder(id, next(JOBS) as usize),
+                        };
+                        check(&s, JOBS as usize);
+                    }
+                })
+            })
+            .collect();
+
+        for handle in users.into_iter().chain(workers) {
+            handle.join().unwrap();
+        }
+        let state = state.lock().unwrap();
+        check(&state, JOBS as usize);
+        let snapshot = state.snapshot();
+        assert!(snapshot.pending.is_empty() && snapshot.running.is_empty());
+        assert_eq!(snapshot.finished.len(), JOBS as usize);
+    }
+
+    // A job whose output is expected to take `bytes` on `mount`
+    fn sized(state: &mut QueueState, n: u64, mount: &str, bytes: u64, volumes: Vec<VolumeInfo>) -> u64 {
+        let space = SpaceNeed { mount_point: mount.to_string(), required_bytes: bytes, written_bytes: None };
+        state.enqueue(spec(n), Priority::Normal, volumes, false, Some(space), None, None)
+    }
+
+    fn status(state: &QueueState, id: u64) -> QueueStatus {
+        state.job(id).unwrap().status
+    }
+
+    fn free(mount: &str, bytes: u64) -> HashMap<String, u64> {
+        HashMap::from([(mount.to_string(), bytes)])
+    }
+
+    #[test]
+    fn jobs_start_while_their_outputs_fit_next_to_the_running_ones() {
+        let mut state = QueueState { max_concurrent: 3, express_threshold_secs: 0.0, ..Default::default() };
+        for n in 1..=3 {
+            sized(&mut state, n, "/mnt/small", 40, vec![]);
+        }
+        let other = sized(&mut state, 4, "/mnt/big", 40, vec![]);
+        let free = HashMap::from([("/mnt/small".to_string(), 100), ("/mnt/big".to_string(), 1000)]);
+
+        assert_eq!(state.start_next(&free).map(|j| j.id), Some(1));
+        assert_eq!(state.start_next(&free).map(|j| j.id), Some(2));
+        assert_eq!(state.reserved_bytes("/mnt/small"), 80);
+        // 80 + 40 > 100, but the other volume has room
+        assert_eq!(state.start_next(&free).map(|j| j.id), Some(other));
+        assert_eq!(status(&state, 3), QueueStatus::WaitingForSpace);
+        assert_eq!(state.reserved_bytes("/mnt/big"), 40);
+    }
+
+    #[test]
+    fn a_job_with_nothing_reserved_ahead_of_it_always_starts() {
+        let mut state = queue_of(0);
+        let id = sized(&mut state, 1, "/mnt/small", 500, vec![]);
+        assert_eq!(state.start_next(&free("/mnt/small", 100)).map(|j| j.id), Some(id));
+        // Unknown volumes and estimates don't hold anything back either
+        state.max_concurrent = 3;
+        state.enqueue(spec(2), Priority::Normal, vec![], false, None, None, None);
+        sized(&mut state, 3, "/mnt/unmeasured", 500, vec![]);
+        assert!(state.start_next(&free("/mnt/small", 100)).is_some());
+        assert!(state.start_next(&free("/mnt/small", 100)).is_some());
+    }
+
+    #[test]
+    fn a_job_that_fits_but_cant_start_yet_keeps_its_room() {
+        let hdd = VolumeInfo { mount_point: "/mnt/hdd".to_string(), rotational: true, removable: false };
+        let mut state = QueueState { max_concurrent: 3, express_threshold_secs: 0.0, ..Default::default() };
+        let reading = state.enqueue(spec(1), Priority::Normal, vec![hdd.clone()], false, None, None, None);
+        assert_eq!(state.start_next(&HashMap::new()).map(|j| j.id), Some(reading));
+
+        // Next in line reads the busy disk; the one behind it would take the
+        // room it needs
+        let blocked = sized(&mut state, 2, "/mnt/out", 60, vec![hdd]);
+        let behind = sized(&mut state, 3, "/mnt/out", 60, vec![]);
+        let room = free("/mnt/out", 100);
+        assert!(state.start_next(&room).is_none());
+        assert_eq!(status(&state, blocked), QueueStatus::WaitingForDisk);
+        assert_eq!(status(&state, behind), QueueStatus::WaitingForSpace);
+
+        // Once the disk is free the blocked job goes first
+        state.complete(reading, Ok(()));
+        assert_eq!(state.start_next(&room).map(|j| j.id), Some(blocked));
+        assert!(state.start_next(&room).is_none());
+        assert_eq!(status(&state, behind), QueueStatus::WaitingForSpace);
+    }
+
+    #[test]
+    fn reservations_shrink_as_output_is_written() {
+        let mut state = QueueState { max_concurrent: 2, express_threshold_secs: 0.0, ..Default::default() };
+        let first = sized(&mut state, 1, "/mnt/out", 80, vec![]);
+        let second = sized(&mut state, 2, "/mnt/out", 20, vec![]);
+        assert_eq!(state.start_next(&free("/mnt/out", 100)).map(|j| j.id), Some(first));
+
+        // 50 bytes in, the volume has 50 left and the first job needs 30 more
+        state.set_written(first, Some(50));
+        assert_eq!(state.reserved_bytes("/mnt/out"), 30);
+        assert_eq!(state.start_next(&free("/mnt/out", 50)).map(|j| j.id), Some(second));
+        // Writing past the estimate holds nothing more back
+        state.set_written(first, Some(120));
+        assert_eq!(state.reserved_bytes("/mnt/out"), 20);
+    }
+
+    #[test]
+    fn a_finished_job_gives_its_room_to_the_ones_waiting() {
+        let mut state = QueueState { max_concurrent: 2, express_threshold_secs: 0.0, ..Default::default() };
+        let first = sized(&mut state, 1, "/mnt/out", 70, vec![]);
+        let waiting = sized(&mut state, 2, "/mnt/out", 70, vec![]);
+        assert_eq!(state.start_next(&free("/mnt/out", 100)).map(|j| j.id), Some(first));
+        assert!(state.start_next(&free("/mnt/out", 100)).is_none());
+        assert_eq!(status(&state, waiting), QueueStatus::WaitingForSpace);
+
+        // The output came out at 25 bytes: 75 free, nothing reserved
+        state.complete(first, Ok(()));
+        state.set_written(first, Some(25));
+        assert_eq!(state.job(first).unwrap().space.as_ref().unwrap().written_bytes, Some(25));
+        assert_eq!(state.reserved_bytes("/mnt/out"), 0);
+        assert_eq!(state.start_next(&free("/mnt/out", 75)).map(|j| j.id), Some(waiting));
+    }
+
+    #[test]
+    fn a_failed_job_frees_its_room_too() {
+        let mut state = QueueState { max_concurrent: 2, express_threshold_secs: 0.0, ..Default::default() };
+        let first = sized(&mut state, 1, "/mnt/out", 70, vec![]);
+        let waiting = sized(&mut state, 2, "/mnt/out", 70, vec![]);
+        state.start_next(&free("/mnt/out", 100));
+        assert!(state.start_next(&free("/mnt/out", 100)).is_none());
+        state.complete(first, Err("ffmpeg failed".to_string()));
+        assert_eq!(state.start_next(&free("/mnt/out", 100)).map(|j| j.id), Some(waiting));
+    }
+
+    // ==========================================
+    // EXPRESS LANE
+    // ==========================================
+    fn estimated(state: &mut QueueState, n: u64, secs: f64) -> u64 {
+        state.enqueue(spec(n), Priority::Normal, vec![], false, None, None, Some(secs))
+    }
+
+    #[test]
+    fn at_the_limit_a_quick_job_takes_the_express_lane() {
+        let mut state = QueueState { max_concurrent: 1, express_threshold_secs: 60.0, ..Default::default() };
+        let long = estimated(&mut state, 1, 3600.0);
+        let slow = estimated(&mut state, 2, 600.0);
+        let quick = estimated(&mut state, 3, 10.0);
+        let also_quick = estimated(&mut state, 4, 5.0);
+
+        let first = state.start_next(&HashMap::new()).unwrap();
+        assert_eq!((first.id, first.express), (long, false));
+        // The slow one waits; the quick one behind it doesn't
+        let express = state.start_next(&HashMap::new()).unwrap();
+        assert_eq!((express.id, express.express), (quick, true));
+        assert_eq!(status(&state, slow), QueueStatus::Queued);
+        // One express job at a time
+        assert!(state.start_next(&HashMap::new()).is_none());
+        assert_eq!(status(&state, also_quick), QueueStatus::Queued);
+    }
+
+    #[test]
+    fn a_zero_threshold_turns_the_express_lane_off() {
+        let mut state = QueueState { max_concurrent: 1, express_threshold_secs: 0.0, ..Default::default() };
+        estimated(&mut state, 1, 3600.0);
+        estimated(&mut state, 2, 1.0);
+        assert!(!state.start_next(&HashMap::new()).unwrap().express);
+        assert!(state.start_next(&HashMap::new()).is_none());
+    }
+
+    #[test]
+    fn leaving_the_lane_hands_back_the_jobs_it_paused() {
+        let mut state = QueueState { max_concurrent: 1, express_threshold_secs: 60.0, ..Default::default() };
+        let long = estimated(&mut state, 1, 3600.0);
+        let quick = estimated(&mut state, 2, 10.0);
+        state.start_next(&HashMap::new());
+        state.start_next(&HashMap::new());
+        assert_eq!(state.pausable(), [long]);
+        state.express_paused = vec![long];
+
+        assert_eq!(state.leave_express(long), None);
+        assert_eq!(state.leave_express(quick), Some(vec![long]));
+        assert!(!state.job(quick).unwrap().express);
+        assert_eq!(state.leave_express(quick), None);
+        // The lane is free again for the next quick job
+        let next = estimated(&mut state, 3, 10.0);
+        let started = state.start_next(&HashMap::new()).unwrap();
+        assert_eq!((started.id, started.express), (next, true));
+    }
+
+    // ==========================================
+    // DESTINATION REMOVED, AND REDIRECTING
+    // ==========================================
+    use crate::jobtests::{self, clip_scenario, Harness, ENCODE};
+
+    // A job that was writing to `output` on a drive that went away, with its
+    // finished output kept at `stranded` when there is one
+    fn lost_destination(h: &Harness, output: &str, stranded: Option<&str>) -> u64 {
+        let spec = JobSpec::Video(Box::new(jobtests::video(h, output)));
+        let queue = h.handle().state::<JobQueue>();
+        let mut state = queue.state.lock().unwrap();
+        let id = state.enqueue(spec, Priority::Normal, vec![], true, None, None, None);
+        state.start_next(&HashMap::new()).unwrap();
+        if let Some(stranded) = stranded {
+            fs::write(stranded, "finished").unwrap();
+            state.running[0].stranded_output = Some(PathBuf::from(stranded));
+        }
+        state.complete(id, Err(format!("{}: the drive at /media/usb was disconnected (write error)", outputs::DESTINATION_REMOVED)));
+        id
+    }
+
+    #[test]
+    fn a_removed_destination_gets_its_own_status() {
+        let mut state = queue_of(2);
+        state.max_concurrent = 2;
+        let (first, second) = (state.start_next(&HashMap::new()).unwrap().id, state.start_next(&HashMap::new()).unwrap().id);
+        state.complete(first, Err(format!("{}: the drive at /media/usb was disconnected", outputs::DESTINATION_REMOVED)));
+        state.complete(second, Err("No space left on device".to_string()));
+        assert_eq!(status(&state, first), QueueStatus::DestinationRemoved);
+        assert_eq!(status(&state, second), QueueStatus::Failed);
+    }
+
+    #[test]
+    fn a_finished_output_is_moved_to_the_new_destination() {
+        let h = Harness::new("redirect-stranded", "{}");
+        fs::create_dir(h.file("elsewhere")).unwrap();
+        // Something is already there; the move doesn't replace it
+        fs::write(h.file("elsewhere/clip.mp4"), "theirs").unwrap();
+        let id = lost_destination(&h, "usb/clip.mp4", Some(&h.file("stranded.mp4")));
+
+        let queued = redirect_output(h.handle().clone(), h.handle().state(), id, h.file("elsewhere/clip.mp4")).unwrap();
+        assert_eq!(queued, None);
+        let placed = h.file("elsewhere/clip (1).mp4");
+        assert_eq!(fs::read_to_string(&placed).unwrap(), "finished");
+        assert_eq!(fs::read_to_string(h.file("elsewhere/clip.mp4")).unwrap(), "theirs");
+        assert!(!Path::new(&h.file("stranded.mp4")).exists());
+
+        let job = find_job(h.handle(), id).unwrap();
+        assert_eq!(job.status, QueueStatus::Done);
+        assert_eq!(job.spec.output(), placed);
+        assert_eq!(job.output_file, Some(PathBuf::from(&placed)));
+        assert!(job.stranded_output.is_none());
+        // Once is enough
+        assert!(redirect_output(h.handle().clone(), h.handle().state(), id, h.file("elsewhere/again.mp4")).is_err());
+    }
+
+    #[test]
+    fn an_unfinished_job_is_queued_again_for_the_new_destination() {
+        let h = Harness::new("redirect-requeue", &clip_scenario(ENCODE));
+        let id = lost_destination(&h, "usb/clip.mp4", None);
+
+        let new_id = redirect_output(h.handle().clone(), h.handle().state(), id, h.file("out.mp4")).unwrap().expect("a new job");
+        let old = find_job(h.handle(), id).unwrap();
+        assert_eq!(old.status, QueueStatus::Failed);
+        assert_eq!(old.error, Some(format!("Redirected to job {}", new_id)));
+
+        let redone = h.settled(new_id);
+        assert_eq!(redone.status, QueueStatus::Done, "{:?}", redone.error);
+        assert_eq!(redone.spec.output(), h.file("out.mp4"));
+        assert!(Path::new(&h.file("out.mp4")).exists());
+    }
+
+    #[test]
+    fn redirecting_checks_the_new_path() {
+        let h = Harness::new("redirect-checks", "{}");
+        let id = lost_destination(&h, "usb/clip.mp4", Some(&h.file("stranded.mp4")));
+        let redirect = |to: String| redirect_output(h.handle().clone(), h.handle().state(), id, to);
+
+        assert!(redirect(h.file("missing/clip.mp4")).unwrap_err().contains("doesn't exist"));
+        assert!(redirect("clip.mp4".to_string()).is_err());
+        // The input is never the output
+        assert!(redirect(h.file("clip.mp4")).is_err());
+        assert_eq!(find_job(h.handle(), id).unwrap().status, QueueStatus::DestinationRemoved);
+        assert!(Path::new(&h.file("stranded.mp4")).exists());
+        // Only jobs that lost their destination
+        let done = lost_destination(&h, "usb/other.mp4", None);
+        h.handle().state::<JobQueue>().state.lock().unwrap().finished.iter_mut().find(|j| j.id == done).unwrap().status = QueueStatus::Done;
+        assert!(redirect_output(h.handle().clone(), h.handle().state(), done, h.file("other.mp4")).is_err());
+    }
+
+    // ==========================================
+    // EXPRESS LANE, WITH JOBS RUNNING
+    // ==========================================
+    use crate::hardware::{GpuSessions, HwCache, HwCapabilities};
+    use crate::request::VideoOptions;
+
+    // The first run hangs (the long job, holding the limit), the second is
+    // the quick job's
+    const HANG_THEN_QUICK: &str = r#"[
+        { "stderr": [{ "line": "frame=30 fps=30 q=28.0 size=64kB time=00:00:01.00 bitrate=524.3kbits/s speed=1.0x" }], "hang": true },
+        { "stderr": [{ "line": "frame=300 fps=30 q=28.0 size=1024kB time=00:00:10.00 bitrate=838.9kbits/s speed=1.0x", "after_ms": 300 }], "output_bytes": 4096 }
+    ]"#;
+
+    fn codes(h: &Harness, id: u64) -> Vec<String> {
+        timeline(h.handle(), id).unwrap_or_default().into_iter().map(|e| e.code).collect()
+    }
+
+    // Starts the long job and waits for its encode, then queues the quick one.
+    // Both are on the scratch folder's disk, which may be a hard drive.
+    fn long_then_quick(h: &Harness, auto_gpu: bool) -> (u64, u64) {
+        let mut long = jobtests::video(h, "long.mp4");
+        let mut quick = VideoCompressRequest::new(h.input("quick.mov", 50_000), h.file("quick.mp4"), VideoOptions::default());
+        long.options.auto_gpu = auto_gpu;
+        quick.options.auto_gpu = auto_gpu;
+        let long = enqueue(h.handle(), vec![JobSpec::Video(Box::new(long))], None).unwrap()[0];
+        // Its progress, not just a pid: the probe before the encode has one too
+        h.wait_for("the long job's encode", |h| h.emitted("job-progress").iter().any(|p| p["job_id"] == long));
+        let quick = enqueue(h.handle(), vec![JobSpec::Video(Box::new(quick))], None).unwrap()[0];
+        (long, quick)
+    }
+
+    fn paused_then_resumed(codes: &[String]) -> bool {
+        let paused = codes.iter().position(|c| c == timeline::PAUSED);
+        let resumed = codes.iter().position(|c| c == timeline::RESUMED);
+        matches!((paused, resumed), (Some(p), Some(r)) if p < r)
+    }
+
+    fn cancel_and_settle(h: &Harness, ids: &[u64]) {
+        cancel_running(h.handle());
+        for id in ids {
+            h.settled(*id);
+        }
+    }
+
+    #[test]
+    fn the_express_job_pauses_the_others_and_resumes_them_when_done() {
+        let h = Harness::new("express-pauses", &clip_scenario(HANG_THEN_QUICK));
+        set_queue_limits(h.handle().clone(), h.handle().state(), Some(1), Some(2), Some(60.0), Some(true));
+        let (long, quick) = long_then_quick(&h, false);
+
+        let done = h.settled(quick);
+        assert_eq!(done.status, QueueStatus::Done, "{:?}", done.error);
+        assert!(codes(&h, quick).iter().any(|c| c == timeline::EXPRESS_STARTED), "{:?}", codes(&h, quick));
+        assert!(paused_then_resumed(&codes(&h, long)), "{:?}", codes(&h, long));
+        assert!(!pause::is_paused(h.handle(), long));
+        assert_eq!(find_job(h.handle(), long).unwrap().status, QueueStatus::Running);
+        cancel_and_settle(&h, &[long]);
+    }
+
+    #[test]
+    fn without_the_setting_nothing_is_paused_while_gpu_sessions_are_free() {
+        let h = Harness::new("express-no-pause", &clip_scenario(HANG_THEN_QUICK));
+        set_queue_limits(h.handle().clone(), h.handle().state(), Some(1), Some(2), Some(60.0), Some(false));
+        let (long, quick) = long_then_quick(&h, false);
+
+        assert_eq!(h.settled(quick).status, QueueStatus::Done);
+        assert!(!codes(&h, long).iter().any(|c| c == timeline::PAUSED), "{:?}", codes(&h, long));
+        cancel_and_settle(&h, &[long]);
+    }
+
+    #[test]
+    fn the_express_job_pauses_the_jobs_holding_the_last_gpu_session() {
+        let h = Harness::new("express-gpu-full", &clip_scenario(HANG_THEN_QUICK));
+        let nvenc = HwCapabilities { working: vec!["h264_nvenc".to_string()], preferred: Some("h264_nvenc".to_string()), ..Default::default() };
+        h.handle().state::<HwCache>().seed(nvenc);
+        h.handle().state::<GpuSessions>().set_limit(1);
+        set_queue_limits(h.handle().clone(), h.handle().state(), Some(1), Some(2), Some(60.0), Some(false));
+        let (long, quick) = long_then_quick(&h, true);
+
+        let done = h.settled(quick);
+        assert_eq!(done.status, QueueStatus::Done, "{:?}", done.error);
+        assert!(paused_then_resumed(&codes(&h, long)), "{:?}", codes(&h, long));
+        let encodes = h.runs().into_iter().filter(|r| r.iter().any(|a| a == "h264_nvenc")).count();
+        assert_eq!(encodes, 2);
+        cancel_and_settle(&h, &[long]);
+    }
+
+    #[test]
+    fn an_express_job_past_twice_its_estimate_is_demoted() {
+        let h = Harness::new("express-demoted", &clip_scenario(r#"[
+            { "stderr": [{ "line": "frame=30 fps=30 q=28.0 size=64kB time=00:00:01.00 bitrate=524.3kbits/s speed=1.0x" }], "hang": true },
+            { "stderr": [{ "line": "frame=30 fps=30 q=28.0 size=64kB time=00:00:01.00 bitrate=524.3kbits/s speed=1.0x" }], "hang": true }
+        ]"#));
+        set_queue_limits(h.handle().clone(), h.handle().state(), Some(1), Some(2), Some(60.0), Some(true));
+        let (long, quick) = long_then_quick(&h, false);
+
+        // Its estimate is a fraction of a second, so it goes after the 1 s floor
+        h.wait_for("the demotion", |h| codes(h, quick).iter().any(|c| c == timeline::EXPRESS_DEMOTED));
+        assert!(!find_job(h.handle(), quick).unwrap().express);
+        assert!(paused_then_resumed(&codes(&h, long)), "{:?}", codes(&h, long));
+        assert!(!pause::is_paused(h.handle(), long));
+        assert_eq!(find_job(h.handle(), quick).unwrap().status, QueueStatus::Running);
+        cancel_and_settle(&h, &[long, quick]);
+    }
+}
@@ -0,0 +1,302 @@
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tauri::{Manager, State};
+use tauri_plugin_clipboard_manager::ClipboardExt;
+
+use crate::AppHandle;
+use crate::errors::{self, JobError};
+use crate::paths;
+use crate::request::{self, ImageCompressRequest, VideoCompressRequest, VideoOptions};
+use crate::settings::SettingsStore;
+use crate::stats::JobStats;
+use crate::support::{self, MediaKind, VideoCodec};
+
+pub const DEFAULT_QUICK_KEEP_DAYS: u64 = 7;
+// Local time of the compress, to the second
+const STAMP: &str = "%Y%m%d-%H%M%S";
+const IMAGE_QUALITY: u32 = 80;
+const VIDEO_CRF: u32 = 26;
+// 1080p either way round: 1920x1080 landscape, 1080x1920 portrait
+const VIDEO_LONG_EDGE: u32 = 1920;
+
+// ==========================================
+// QUICK COMPRESS
+// ==========================================
+// Screenshot -> smaller file -> path in the clipboard, for pasting into a
+// chat. The file type picks the settings (images become WebP at 80, videos
+// 1080p H.264 at CRF 26, GIFs stay GIFs through the palette encoder) and
+// the output goes to the app's cache folder with a timestamped name, so
+// nothing lands next to the original. The jobs are ordinary compress_video
+// / compress_image jobs: routing, encoder detection and history all apply.
+//
+// The clipboard is set through the clipboard plugin; when that fails (no
+// display server, say) the path is still returned. Outputs older than
+// quick_compress_keep_days are removed at startup.
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuickKind {
+    Image,
+    Video,
+    Gif,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct QuickResult {
+    pub kind: QuickKind,
+    pub input: String,
+    pub output: String,
+    #[serde(flatten)]
+    pub stats: JobStats,
+    pub copied_to_clipboard: bool,
+    pub job_id: Option<u64>,
+}
+
+// Pure: what a quick compress does with a file of extension `ext`.
+pub fn kind_for(ext: &str) -> Option<QuickKind> {
+    match ext {
+        "gif" => Some(QuickKind::Gif),
+        _ => match support::container(ext)?.kind {
+            MediaKind::Image => Some(QuickKind::Image),
+            MediaKind::Video => Some(QuickKind::Video),
+            MediaKind::Audio => None,
+        },
+    }
+}
+
+fn output_ext(kind: QuickKind) -> &'static str {
+    match kind {
+        QuickKind::Image => "webp",
+        QuickKind::Video => "mp4",
+        QuickKind::Gif => "gif",
+    }
+}
+
+fn quick_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app.path().app_cache_dir().map_err(|e| e.to_string())?.join("quick"))
+}
+
+// <dir>/<stem>-<stamp>.<ext>, numbered when two land in the same second.
+fn output_path(dir: &Path, input: &Path, ext: &str) -> PathBuf {
+    let stem = input.file_stem().unwrap_or(OsStr::new("quick"));
+    let stamp = chrono::Local::now().format(STAMP);
+    let mut path = dir.join(paths::file_name("", stem, &format!("-{}", stamp), Some(OsStr::new(ext))));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(paths::file_name("", stem, &format!("-{}-{}", stamp, n), Some(OsStr::new(ext))));
+        n += 1;
+    }
+    path
+}
+
+fn copy_to_clipboard(app: &AppHandle, text: &str) -> bool {
+    match app.clipboard().write_text(text) {
+        Ok(()) => true,
+        Err(e) => {
+            println!("⚠️ Quick compress: couldn't set the clipboard: {}", e);
+            false
+        }
+    }
+}
+
+// Startup: quick outputs past their keep time. They're in the cache folder,
+// so nobody else points at them.
+pub fn sweep(app: &AppHandle) {
+    let Ok(dir) = quick_dir(app) else { return };
+    let days = app.try_state::<SettingsStore>().map_or(DEFAULT_QUICK_KEEP_DAYS, |s| s.get().quick_compress_keep_days);
+    let Some(cutoff) = SystemTime::now().checked_sub(Duration::from_secs(days * 24 * 60 * 60)) else { return };
+    let stale: Vec<PathBuf> = fs::read_dir(&dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|f| f.metadata().ok().and_then(|m| m.modified().ok()).is_some_and(|t| t < cutoff))
+        .map(|f| f.path())
+        .collect();
+    let removed = stale.iter().filter(|p| fs::remove_file(p).is_ok()).count();
+    if removed > 0 {
+        println!("🧹 Quick compress: removed {} outputs older than {} days", removed, days);
+    }
+}
+
+// ==========================================
+// COMMAND: QUICK COMPRESS
+// ==========================================
+#[tauri::command]
+pub async fn quick_compress(app: AppHandle, input: String) -> Result<QuickResult, JobError> {
+    let other = |message: String| JobError::Other { message };
+    let input = paths::secure_input(&input).map_err(other)?;
+    let ext = Path::new(&input).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let kind = kind_for(&ext).ok_or_else(|| other(format!("Quick compress takes images, videos and GIFs, not .{} files", ext)))?;
+    let dir = quick_dir(&app).map_err(other)?;
+    fs::create_dir_all(&dir).map_err(|e| errors::classify(&format!("Could not create {}: {}", dir.display(), e), vec![]))?;
+    let output = output_path(&dir, Path::new(&input), output_ext(kind)).to_string_lossy().to_string();
+    println!("⚡ Quick compress ({:?}): {} -> {}", kind, input, output);
+
+    let (input, output, stats, job_id) = match kind {
+        QuickKind::Image => {
+            let request = ImageCompressRequest {
+                version: request::REQUEST_VERSION,
+                input,
+                output,
+                create_dirs: false,
+                width: None,
+                height: None,
+                quality: Some(IMAGE_QUALITY),
+                skip_if_larger: false,
+                metadata: Default::default(),
+                process: Default::default(),
+                dry_run: false,
+                annotations: Default::default(),
+            };
+            let r = crate::run_direct_image(&app, request).await?;
+            (r.input, r.output, r.stats, r.job_id)
+        }
+        QuickKind::Video | QuickKind::Gif => {
+            let options = match kind {
+                QuickKind::Video => VideoOptions {
+                    auto_gpu: true,
+                    codec: VideoCodec::H264,
+                    crf: Some(VIDEO_CRF),
+                    max_long_edge: Some(VIDEO_LONG_EDGE),
+                    ..Default::default()
+                },
+                _ => VideoOptions::default(),
+            };
+            let r = crate::run_direct_video(&app, VideoCompressRequest::new(input, output, options)).await?;
+            (r.input, r.output, r.stats, r.job_id)
+        }
+    };
+    let copied_to_clipboard = copy_to_clipboard(&app, &output);
+    Ok(QuickResult { kind, input, output, stats, copied_to_clipboard, job_id })
+}
+
+// ==========================================
+// COMMAND: QUICK COMPRESS KEEP TIME
+// ==========================================
+#[tauri::command]
+pub fn set_quick_compress_keep_days(store: State<'_, SettingsStore>, days: u64) -> Result<(), String> {
+    if days == 0 {
+        return Err("Quick outputs need to be kept at least one day, or the path in the clipboard would point at nothing".to_string());
+    }
+    store.update(|s| s.quick_compress_keep_days = days).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{clip_scenario, run, Harness, ENCODE};
+
+    fn quick(h: &Harness, input: String) -> Result<QuickResult, JobError> {
+        let app = h.handle().clone();
+        run(async move { quick_compress(app, input).await })
+    }
+
+    // Whether the result says so or not, the path is what's in the
+    // clipboard when there's one to write to (a headless CI runner has none)
+    fn clipboard_agrees(h: &Harness, result: &QuickResult) {
+        let held = h.handle().clipboard().read_text().ok();
+        assert_eq!(result.copied_to_clipboard, held.as_deref() == Some(result.output.as_str()), "{:?}", held);
+    }
+
+    #[test]
+    fn the_file_type_picks_the_settings() {
+        assert_eq!(kind_for("png"), Some(QuickKind::Image));
+        assert_eq!(kind_for("jpg"), Some(QuickKind::Image));
+        assert_eq!(kind_for("mov"), Some(QuickKind::Video));
+        assert_eq!(kind_for("mkv"), Some(QuickKind::Video));
+        assert_eq!(kind_for("gif"), Some(QuickKind::Gif));
+        assert_eq!(kind_for("mp3"), None);
+        assert_eq!(kind_for("pdf"), None);
+        assert_eq!([QuickKind::Image, QuickKind::Video, QuickKind::Gif].map(output_ext), ["webp", "mp4", "gif"]);
+    }
+
+    #[test]
+    fn outputs_in_the_same_second_are_numbered() {
+        let dir = std::env::temp_dir().join(format!("compressio-quick-names-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let first = output_path(&dir, Path::new("/shots/Screen Recording.mov"), "mp4");
+        let name = first.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with("Screen Recording-") && name.ends_with(".mp4"), "{}", name);
+        fs::write(&first, b"").unwrap();
+        let second = output_path(&dir, Path::new("/shots/Screen Recording.mov"), "mp4");
+        // Unless the clock ticked over in between
+        if second.file_name().unwrap().to_string_lossy()[..name.len() - 4] == name[..name.len() - 4] {
+            assert!(second.to_string_lossy().ends_with("-2.mp4"), "{}", second.display());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_video_becomes_a_1080p_h264_in_the_cache_with_its_path_copied() {
+        let h = Harness::new("quick-video", &clip_scenario(ENCODE));
+        let result = quick(&h, h.input("Screen Recording.mov", 100_000)).unwrap();
+
+        assert_eq!(result.kind, QuickKind::Video);
+        let output = Path::new(&result.output);
+        assert_eq!(output.parent(), Some(quick_dir(h.handle()).unwrap().as_path()));
+        assert_eq!(output.extension(), Some(OsStr::new("mp4")));
+        assert_eq!(fs::metadata(output).unwrap().len(), 4096);
+        // Nothing next to the original
+        assert_eq!(h.files(), ["Screen Recording.mov"]);
+        let encode = h.runs().into_iter().find(|r| r.iter().any(|a| a == "libx264")).unwrap();
+        assert!(encode.windows(2).any(|w| w == ["-crf", "26"]), "{:?}", encode);
+        clipboard_agrees(&h, &result);
+    }
+
+    #[test]
+    fn an_image_becomes_webp() {
+        let h = Harness::new("quick-image", r#"{
+            "ffprobe": { "streams": [{ "index": 0, "codec_type": "video", "codec_name": "png", "width": 64, "height": 48 }], "format": { "format_name": "png_pipe" } },
+            "runs": [{ "output_bytes": 512 }]
+        }"#);
+        let input = h.file("shot.png");
+        image::RgbImage::from_pixel(64, 48, image::Rgb([200, 100, 50])).save(&input).unwrap();
+        let result = quick(&h, input).unwrap();
+
+        assert_eq!(result.kind, QuickKind::Image);
+        assert!(result.output.ends_with(".webp"), "{}", result.output);
+        assert!(Path::new(&result.output).exists());
+        assert_eq!(h.files(), ["shot.png"]);
+        clipboard_agrees(&h, &result);
+    }
+
+    #[test]
+    fn audio_and_unknown_files_are_refused_before_anything_runs() {
+        let h = Harness::new("quick-refused", "{}");
+        let error = quick(&h, h.input("memo.mp3", 1000)).expect_err("audio isn't for quick compress");
+        assert_eq!(error.to_string(), "Quick compress takes images, videos and GIFs, not .mp3 files");
+        assert!(h.runs().is_empty());
+        assert!(!quick_dir(h.handle()).unwrap().exists());
+    }
+
+    #[test]
+    fn a_failed_job_copies_nothing() {
+        let h = Harness::new("quick-failed", &clip_scenario(r#"[{ "stderr": [{ "line": "Conversion failed!" }], "exit_code": 1 }]"#));
+        assert!(quick(&h, h.input("clip.mp4", 100_000)).is_err());
+        assert!(h.handle().clipboard().read_text().is_err());
+    }
+
+    #[test]
+    fn the_sweep_removes_outputs_past_their_keep_time() {
+        let h = Harness::new("quick-sweep", "{}");
+        let dir = quick_dir(h.handle()).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        let age = |name: &str, days: u64| {
+            let file = fs::File::create(dir.join(name)).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60)).unwrap();
+        };
+        age("old.mp4", DEFAULT_QUICK_KEEP_DAYS + 1);
+        age("recent.webp", DEFAULT_QUICK_KEEP_DAYS - 1);
+        sweep(h.handle());
+        assert!(!dir.join("old.mp4").exists());
+        assert!(dir.join("recent.webp").exists());
+
+        set_quick_compress_keep_days(h.handle().state::<SettingsStore>(), 1).unwrap();
+        sweep(h.handle());
+        assert!(!dir.join("recent.webp").exists());
+        assert!(set_quick_compress_keep_days(h.handle().state::<SettingsStore>(), 0).is_err());
+    }
+}
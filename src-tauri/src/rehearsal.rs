@@ -0,0 +1,165 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::cancel;
+use crate::events::{self, Event};
+use crate::ffmpeg::ProgressPayload;
+use crate::history::{self, HistoryEntry, HistoryStore};
+use crate::plan::{self, PlannedFile};
+use crate::queue::{self, JobSpec};
+use crate::settings::SettingsStore;
+use crate::timeline;
+
+// A rehearsed job takes its estimated encode time sped up this much, but
+// never less or more than these
+const TIME_SCALE: f64 = 30.0;
+const MIN_SECS: f64 = 2.0;
+const MAX_SECS: f64 = 20.0;
+const TICK: Duration = Duration::from_millis(500);
+
+// ==========================================
+// REHEARSAL MODE (managed state)
+// ==========================================
+// For demos and reviews: the whole app clicks through as usual, but queue
+// jobs started while it's on don't encode. Each one is analyzed like a
+// batch plan (probe, predicted decisions, size and time estimate from this
+// machine's history), then plays back progress over a few seconds and
+// finishes with the estimate as its result. Nothing is written next to the
+// input or output: no temp file, no output, no upload, no watch-folder
+// post-actions (which the timeline notes instead). The history entry is
+// marked `simulated` and left out of stats and future estimates.
+//
+// Only for this session, and read as each job starts: turning it off
+// mid-queue lets the jobs still waiting run for real.
+#[derive(Default)]
+pub struct Rehearsal {
+    enabled: AtomicBool,
+}
+
+pub fn enabled(app: &AppHandle) -> bool {
+    app.try_state::<Rehearsal>().is_some_and(|r| r.enabled.load(Ordering::SeqCst))
+}
+
+// Pure: a simulated run `done` (0..1) of the way through its `secs`.
+fn progress(file: &PlannedFile, done: f64, secs: f64) -> ProgressPayload {
+    let media_secs = file.duration_secs.unwrap_or(secs);
+    ProgressPayload {
+        percent: Some((done * 100.0) as f32),
+        out_time_secs: media_secs * done,
+        total_secs: Some(media_secs),
+        speed: Some(media_secs / secs),
+        fps: None,
+        out_size_bytes: Some((file.estimated_output_bytes as f64 * done) as u64),
+        eta_secs: Some(secs * (1.0 - done)),
+        bottleneck: None,
+        projected_size_bytes: Some(file.estimated_output_bytes),
+        sample: None,
+    }
+}
+
+async fn play(app: &AppHandle, file: &PlannedFile) -> Result<(), String> {
+    if let Some(e) = &file.error {
+        return Err(e.clone());
+    }
+    let secs = (file.estimated_wall_secs / TIME_SCALE).clamp(MIN_SECS, MAX_SECS);
+    timeline::record(app, timeline::SIMULATED, &[
+        ("estimated_output_bytes", file.estimated_output_bytes.to_string()),
+        ("estimated_wall_secs", format!("{:.0}", file.estimated_wall_secs)),
+    ]);
+    let started = Instant::now();
+    loop {
+        if cancel::is_cancelled() {
+            return Err(cancel::CANCELLED.to_string());
+        }
+        let done = (started.elapsed().as_secs_f64() / secs).min(1.0);
+        let payload = progress(file, done, secs);
+        queue::report_progress(app, payload.percent);
+        events::emit(app, Event::CompressionProgress(payload));
+        if done >= 1.0 {
+            return Ok(());
+        }
+        tokio::time::sleep(TICK).await;
+    }
+}
+
+// The queue's stand-in for run_spec while rehearsal mode is on.
+pub async fn run(app: &AppHandle, spec: &JobSpec, watch_folder: Option<&str>) -> Result<(), String> {
+    let started = Instant::now();
+    println!("🎭 Rehearsing {} (nothing is encoded)", spec.input());
+    let history = app.try_state::<HistoryStore>().map(|h| h.all()).unwrap_or_default();
+    let file = plan::estimate_spec(app, spec, &history).await;
+    let result = play(app, &file).await;
+
+    if let Ok(()) = result {
+        if matches!(spec, JobSpec::Video(r) if r.options.upload) {
+            timeline::record(app, timeline::POST_ACTION_SKIPPED, &[("action", "upload".to_string())]);
+        }
+        let folders = app.try_state::<SettingsStore>().map(|s| s.get().watch_folders).unwrap_or_default();
+        if watch_folder.is_some_and(|id| folders.iter().any(|f| f.id == id && !f.template.post_actions.is_empty())) {
+            timeline::record(app, timeline::POST_ACTION_SKIPPED, &[("action", "watch_folder".to_string())]);
+        }
+    }
+
+    let mut entry = HistoryEntry::finished(spec.kind(), spec.input(), spec.output(), started, result.as_ref().err().cloned());
+    entry.simulated = true;
+    entry.output_bytes = if result.is_ok() { file.estimated_output_bytes } else { 0 };
+    entry.duration_secs = file.duration_secs;
+    entry.explanations = file.explanations.clone();
+    if let Some(job_id) = queue::current_job_id() {
+        queue::set_explanations(app, job_id, file.explanations);
+    }
+    history::record(app, entry);
+    result
+}
+
+// ==========================================
+// COMMANDS: REHEARSAL MODE
+// ==========================================
+#[tauri::command]
+pub fn set_rehearsal_mode(rehearsal: State<'_, Rehearsal>, enabled: bool) {
+    rehearsal.enabled.store(enabled, Ordering::SeqCst);
+    println!("🎭 Rehearsal mode {}", if enabled { "on" } else { "off" });
+}
+
+#[tauri::command]
+pub fn get_rehearsal_mode(rehearsal: State<'_, Rehearsal>) -> bool {
+    rehearsal.enabled.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{clip_scenario, Harness, ENCODE};
+    use crate::queue::QueueStatus;
+    use crate::request::{VideoCompressRequest, VideoOptions};
+
+    #[test]
+    fn a_rehearsed_batch_writes_nothing_to_its_outputs() {
+        let h = Harness::new("rehearsal-batch", &clip_scenario(ENCODE));
+        set_rehearsal_mode(h.handle().state(), true);
+        let specs: Vec<JobSpec> = ["one", "two"]
+            .iter()
+            .map(|name| {
+                let request = VideoCompressRequest::new(h.input(&format!("{}.mov", name), 100_000), h.file(&format!("{}.mp4", name)), VideoOptions::default());
+                JobSpec::Video(Box::new(request))
+            })
+            .collect();
+        let ids = queue::enqueue(h.handle(), specs, None).unwrap();
+
+        for id in &ids {
+            let job = h.settled(*id);
+            assert_eq!(job.status, QueueStatus::Done, "{:?}", job.error);
+            assert!(job.timeline.iter().any(|e| e.code == timeline::SIMULATED));
+        }
+        // The inputs, and nothing beside them: no output, no staged file
+        assert_eq!(h.files(), ["one.mov", "two.mov"]);
+        // Probed, never encoded
+        assert!(h.runs().iter().all(|r| !r.iter().any(|a| a == "-c:v")), "{:?}", h.runs());
+        assert!(!h.emitted("compression-progress").is_empty());
+        let history = h.handle().state::<HistoryStore>().all();
+        assert_eq!(history.len(), 2);
+        assert!(history.iter().all(|e| e.simulated && e.error.is_none()));
+    }
+}
@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::AppHandle;
+use crate::probe::{self, MediaInfo};
+use crate::request::VideoOptions;
+use crate::support;
+use crate::VideoMode;
+
+// ==========================================
+// REMUX MODE
+// ==========================================
+// "Just make this .mkv an .mp4": mode remux copies every stream into the
+// output container instead of compressing. The streams are checked against
+// the container table first; when the audio is what doesn't fit (DTS or
+// PCM for mp4) and allow_partial_transcode is set, the video is still
+// copied and only the audio is encoded again. The video itself is never
+// re-encoded here: a source whose video doesn't fit needs a compress job.
+//
+// A remux becomes copy_only (or video_mode copy for the partial one) before
+// the job runs, so cuts, subtitles and metadata work exactly as for those.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobMode {
+    #[default]
+    Compress,
+    Remux,
+}
+
+// What a remux turns into.
+#[derive(Debug, PartialEq)]
+pub enum RemuxPlan {
+    CopyAll,
+    // Video copied; the named audio codec is encoded again
+    ReencodeAudio(String),
+}
+
+// Pure: whether `media`'s streams can be copied into `ext`.
+pub fn plan(ext: &str, media: &MediaInfo, allow_partial_transcode: bool) -> Result<RemuxPlan, String> {
+    let container = support::container(ext).ok_or_else(|| format!(".{} isn't a container we can remux into", ext))?;
+    let streams = || media.streams.iter().filter(|s| !s.attached_pic);
+    if let Some(video) = streams().filter(|s| s.codec_type == "video").find_map(|s| s.codec_name.as_deref().filter(|c| !container.video.contains(c))) {
+        return Err(format!("{} video can't be copied into .{} (it takes {}); compress it instead", video, ext, container.video.join(", ")));
+    }
+    let Some(audio) = streams().filter(|s| s.codec_type == "audio").find_map(|s| s.codec_name.as_deref().filter(|c| !container.audio.contains(c))) else {
+        return Ok(RemuxPlan::CopyAll);
+    };
+    match allow_partial_transcode {
+        true => Ok(RemuxPlan::ReencodeAudio(audio.to_string())),
+        false => Err(format!(
+            "{} audio can't be copied into .{} (it takes {}); set allow_partial_transcode to re-encode just the audio",
+            audio,
+            ext,
+            container.audio.join(", ")
+        )),
+    }
+}
+
+// Turns a remux job's options into the copy they amount to; the warning is
+// for the result. Compress jobs are left alone.
+pub async fn resolve(app: &AppHandle, input: &str, output: &str, options: &mut VideoOptions) -> Result<Option<String>, String> {
+    if options.mode != JobMode::Remux {
+        return Ok(None);
+    }
+    let ext = Path::new(output).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let media = probe::probe(app, input).await?;
+    match plan(&ext, &media, options.allow_partial_transcode)? {
+        RemuxPlan::CopyAll => {
+            println!("📦 Remux: every stream is copied into .{}", ext);
+            options.copy_only = true;
+            Ok(None)
+        }
+        RemuxPlan::ReencodeAudio(codec) => {
+            println!("📦 Remux: video copied, {} audio re-encoded for .{}", codec, ext);
+            options.video_mode = VideoMode::Copy;
+            Ok(Some(format!("The {} audio doesn't fit .{}, so it was re-encoded; the video was copied as it is", codec, ext)))
+        }
+    }
+}
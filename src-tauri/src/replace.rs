@@ -0,0 +1,461 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::history::{self, HistoryEntry};
+use crate::instance::{self, Owner};
+use crate::probe::{self, MediaInfo};
+use crate::queue;
+use crate::request::{VideoCompressRequest, VideoOptions};
+use crate::staging;
+use crate::undo;
+use crate::VideoJobResult;
+
+// The swapped-in file may be this much shorter or longer than the original
+const DURATION_TOLERANCE_SECS: f64 = 1.0;
+
+// ==========================================
+// COMPRESS AND REPLACE IN PLACE
+// ==========================================
+// For media-server libraries: the compressed file ends up at the original
+// path, and the path is never empty for a media server to notice.
+//
+//   1. encode to a temp file next to the original (same volume)
+//   2. check it: it probes, has the original's kinds of streams, matches
+//      its length, and is smaller; copy over mtime, permissions and xattrs
+//   3. journal "swapping", hard-link the original to the backup (a copy
+//      where links aren't supported), then one rename temp -> original,
+//      which replaces it atomically
+//   4. probe the swapped-in file; journal "verified", delete the backup
+//
+// Anything failing before 4 puts the original back. A crash in between is
+// undone on the next start from the journal in app_data_dir/in-place: the
+// original is restored unless the swapped-in file had already passed its
+// final probe. While the temp file is still there the rename hasn't
+// happened, and the original never left.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Phase {
+    // The renames may have started; the backup is the original
+    Swapping,
+    // The new file is checked, only the backup is left to remove
+    Verified,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Journal {
+    owner: Owner,
+    original: PathBuf,
+    temp: PathBuf,
+    backup: PathBuf,
+    phase: Phase,
+}
+
+fn journal_dir(app: &AppHandle) -> Option<PathBuf> {
+    app.path().app_data_dir().ok().map(|d| d.join("in-place"))
+}
+
+fn write_journal(path: &Path, journal: &Journal) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(journal).map_err(|e| e.to_string())?;
+    let mut file = File::create(path).map_err(|e| format!("Could not write the swap journal: {}", e))?;
+    file.write_all(json.as_bytes()).and_then(|_| file.sync_all()).map_err(|e| format!("Could not write the swap journal: {}", e))
+}
+
+fn backup_path_for(original: &Path) -> PathBuf {
+    let name = original.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    original.with_file_name(format!(".{}.bak-{}", name, std::process::id()))
+}
+
+// ==========================================
+// SWAP / ROLLBACK
+// ==========================================
+// A second name for the original, or a copy of it with its attributes.
+fn make_backup(original: &Path, backup: &Path) -> Result<(), String> {
+    let _ = fs::remove_file(backup);
+    if fs::hard_link(original, backup).is_ok() {
+        return Ok(());
+    }
+    fs::copy(original, backup).map_err(|e| format!("Could not back up the original: {}", e))?;
+    copy_attributes(original, backup)
+}
+
+// Steps 3 and 4's renames. The path holds a file the whole way through.
+fn swap(journal: &Journal) -> Result<(), String> {
+    make_backup(&journal.original, &journal.backup)?;
+    fs::rename(&journal.temp, &journal.original).map_err(|e| format!("Could not move the new file into place: {}", e))
+}
+
+// Puts the original back from wherever the journal says it might be.
+fn roll_back(journal: &Journal) -> Result<(), String> {
+    if journal.temp.exists() {
+        // The rename never happened: the original is still in place
+        let _ = fs::remove_file(&journal.backup);
+        staging::discard(&journal.temp);
+        return Ok(());
+    }
+    if journal.backup.exists() {
+        // Over the new file, atomically
+        fs::rename(&journal.backup, &journal.original)
+            .map_err(|e| format!("Could not restore {} from {}: {}", journal.original.display(), journal.backup.display(), e))?;
+    }
+    Ok(())
+}
+
+fn finish(journal: &Journal) {
+    let _ = fs::remove_file(&journal.backup);
+}
+
+// The backup becomes this session's undo of the swap rather than going
+// straight away. Renamed under the undo id first: a second swap of the same
+// file reuses the backup path.
+fn keep_for_undo(app: &AppHandle, journal: &Journal) {
+    let id = undo::next_id();
+    let held = undo::held_path(&journal.original, id);
+    if fs::rename(&journal.backup, &held).is_err() {
+        finish(journal);
+        return;
+    }
+    let file = undo::HeldFile { path: journal.original.clone(), held, replace: true };
+    let description = format!("Replace {} in place", journal.original.display());
+    undo::register(app, id, undo::UndoKind::ReplaceInPlace, description, vec![file]);
+}
+
+// Settles one journal left by an instance that's gone.
+fn recover_one(path: &Path) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let journal: Journal = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+    if journal.owner.alive() {
+        return Ok(());
+    }
+    match journal.phase {
+        Phase::Swapping => {
+            println!("↩️ Restoring {} after an interrupted in-place swap", journal.original.display());
+            roll_back(&journal)?;
+        }
+        Phase::Verified => finish(&journal),
+    }
+    let _ = fs::remove_file(path);
+    Ok(())
+}
+
+// On startup, before the queue runs anything that could touch these files.
+pub fn recover(app: &AppHandle) {
+    if instance::is_secondary(app) {
+        return;
+    }
+    let Some(dir) = journal_dir(app) else { return };
+    for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+        if let Err(e) = recover_one(&entry.path()) {
+            println!("⚠️ In-place journal {}: {}", entry.path().display(), e);
+        }
+    }
+}
+
+// ==========================================
+// PRESERVED FILE ATTRIBUTES
+// ==========================================
+fn copy_attributes(from: &Path, to: &Path) -> Result<(), String> {
+    let meta = fs::metadata(from).map_err(|e| e.to_string())?;
+    fs::set_permissions(to, meta.permissions()).map_err(|e| format!("Could not copy permissions: {}", e))?;
+    if let Ok(modified) = meta.modified() {
+        let file = File::options().write(true).open(to).map_err(|e| e.to_string())?;
+        file.set_modified(modified).map_err(|e| format!("Could not copy the modification time: {}", e))?;
+    }
+    xattrs::copy(from, to);
+    Ok(())
+}
+
+// Best effort: a filesystem without xattrs has none to lose.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod xattrs {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    fn c_path(path: &Path) -> Option<CString> {
+        CString::new(path.as_os_str().as_bytes()).ok()
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn list(path: *const libc::c_char, buf: *mut libc::c_char, size: usize) -> isize {
+        libc::listxattr(path, buf, size)
+    }
+    #[cfg(target_os = "macos")]
+    unsafe fn list(path: *const libc::c_char, buf: *mut libc::c_char, size: usize) -> isize {
+        libc::listxattr(path, buf, size, 0)
+    }
+    #[cfg(target_os = "linux")]
+    unsafe fn get(path: *const libc::c_char, name: *const libc::c_char, buf: *mut libc::c_void, size: usize) -> isize {
+        libc::getxattr(path, name, buf, size)
+    }
+    #[cfg(target_os = "macos")]
+    unsafe fn get(path: *const libc::c_char, name: *const libc::c_char, buf: *mut libc::c_void, size: usize) -> isize {
+        libc::getxattr(path, name, buf, size, 0, 0)
+    }
+    #[cfg(target_os = "linux")]
+    unsafe fn set(path: *const libc::c_char, name: *const libc::c_char, value: *const libc::c_void, size: usize) -> i32 {
+        libc::setxattr(path, name, value, size, 0)
+    }
+    #[cfg(target_os = "macos")]
+    unsafe fn set(path: *const libc::c_char, name: *const libc::c_char, value: *const libc::c_void, size: usize) -> i32 {
+        libc::setxattr(path, name, value, size, 0, 0)
+    }
+
+    pub fn copy(from: &Path, to: &Path) {
+        let (Some(from), Some(to)) = (c_path(from), c_path(to)) else { return };
+        // SAFETY: every buffer is sized by the call that fills it, and the
+        // names come back NUL-separated from listxattr
+        unsafe {
+            let size = list(from.as_ptr(), std::ptr::null_mut(), 0);
+            if size <= 0 {
+                return;
+            }
+            let mut names = vec![0u8; size as usize];
+            let size = list(from.as_ptr(), names.as_mut_ptr() as *mut libc::c_char, names.len());
+            if size <= 0 {
+                return;
+            }
+            for name in names[..size as usize].split(|b| *b == 0).filter(|n| !n.is_empty()) {
+                let Ok(name) = CString::new(name) else { continue };
+                let len = get(from.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0);
+                if len < 0 {
+                    continue;
+                }
+                let mut value = vec![0u8; len as usize];
+                let len = get(from.as_ptr(), name.as_ptr(), value.as_mut_ptr() as *mut libc::c_void, value.len());
+                if len >= 0 {
+                    set(to.as_ptr(), name.as_ptr(), value.as_ptr() as *const libc::c_void, len as usize);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod xattrs {
+    pub fn copy(_from: &std::path::Path, _to: &std::path::Path) {}
+}
+
+// ==========================================
+// CHECKS
+// ==========================================
+fn size(path: &Path) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+async fn verify(app: &AppHandle, source: &MediaInfo, path: &Path) -> Result<(), String> {
+    let out = probe::probe(app, &path.to_string_lossy()).await.map_err(|e| format!("Verification failed: {}", e))?;
+    if source.has_video && !out.has_video {
+        return Err("Verification failed: the new file has no video".to_string());
+    }
+    if source.has_audio && !out.has_audio {
+        return Err("Verification failed: the new file has no audio".to_string());
+    }
+    if let (Some(a), Some(b)) = (source.duration, out.duration) {
+        if (a - b).abs() > DURATION_TOLERANCE_SECS {
+            return Err(format!("Verification failed: duration changed from {:.2}s to {:.2}s", a, b));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct InPlaceResult {
+    pub path: String,
+    pub encoder: String,
+    pub original_bytes: u64,
+    pub new_bytes: u64,
+    pub warnings: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<u64>,
+}
+
+// Options that don't make sense for a file that replaces its source
+fn check_options(options: &VideoOptions) -> Result<(), String> {
+    if options.limit_duration_secs.is_some() {
+        return Err("A preview can't replace the original".to_string());
+    }
+    if options.is_cut() {
+        return Err("A cut can't replace the original".to_string());
+    }
+    if options.extract_incompatible_subs {
+        return Err("extract_incompatible_subs isn't available in place: the subtitles would land next to the library file".to_string());
+    }
+    Ok(())
+}
+
+async fn replace(app: &AppHandle, input: &str, options: VideoOptions) -> Result<InPlaceResult, String> {
+    check_options(&options)?;
+    let original = PathBuf::from(input);
+    if !original.is_file() {
+        return Err("Input file not found".to_string());
+    }
+    let source = probe::probe(app, input).await?;
+    let temp = staging::temp_path_for(&original);
+    let temp_str = temp.to_string_lossy().to_string();
+
+    // The temp name stands in for the output, so nothing lands beside it
+    let request = VideoCompressRequest::new(input.to_string(), temp_str.clone(), options);
+    request.validate().map_err(|e| e.to_string())?;
+    let result: VideoJobResult = match crate::encode_video(app, request, &temp_str).await {
+        Ok(r) => r,
+        Err(e) => {
+            staging::discard(&temp);
+            return Err(e);
+        }
+    };
+
+    let (original_bytes, new_bytes) = (size(&original), size(&temp));
+    let checked = if new_bytes >= original_bytes {
+        Err(format!("The compressed file ({} bytes) isn't smaller than the original ({} bytes), so it was left alone", new_bytes, original_bytes))
+    } else {
+        match verify(app, &source, &temp).await {
+            Ok(()) => copy_attributes(&original, &temp),
+            Err(e) => Err(e),
+        }
+    };
+    if let Err(e) = checked {
+        staging::discard(&temp);
+        return Err(e);
+    }
+
+    let dir = journal_dir(app).ok_or("No app data folder for the swap journal")?;
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let journal_path = dir.join(format!("{}-{}.json", std::process::id(), stamp));
+    let mut journal = Journal { owner: Owner::current(), original: original.clone(), temp: temp.clone(), backup: backup_path_for(&original), phase: Phase::Swapping };
+    if let Err(e) = write_journal(&journal_path, &journal) {
+        staging::discard(&temp);
+        return Err(e);
+    }
+
+    let settled = match swap(&journal) {
+        Ok(()) => verify(app, &source, &original).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = settled {
+        let restored = roll_back(&journal);
+        let _ = fs::remove_file(&journal_path);
+        return Err(match restored {
+            Ok(()) => format!("{} (the original was restored)", e),
+            Err(restore) => format!("{}; {}", e, restore),
+        });
+    }
+
+    journal.phase = Phase::Verified;
+    let _ = write_journal(&journal_path, &journal);
+    keep_for_undo(app, &journal);
+    let _ = fs::remove_file(&journal_path);
+
+    println!("♻️ Replaced {} in place: {} -> {} bytes", original.display(), original_bytes, new_bytes);
+    Ok(InPlaceResult {
+        path: input.to_string(),
+        encoder: result.encoder,
+        original_bytes,
+        new_bytes,
+        warnings: result.warnings,
+        job_id: None,
+    })
+}
+
+// ==========================================
+// COMMAND: COMPRESS IN PLACE
+// ==========================================
+#[tauri::command]
+pub async fn compress_in_place(app: AppHandle, input: String, options: VideoOptions) -> Result<InPlaceResult, String> {
+    let started = Instant::now();
+    let (job_id, result) = queue::run_direct(&app, replace(&app, &input, options)).await;
+    let mut entry = HistoryEntry::finished("video", &input, &input, started, result.as_ref().err().cloned());
+    if let Ok(r) = &result {
+        // The input path holds the new file by now
+        entry.input_bytes = r.original_bytes;
+        entry.encoder = Some(r.encoder.clone());
+        entry.warnings = r.warnings.clone();
+    }
+    history::record(&app, entry);
+    result.map(|r| InPlaceResult { job_id: Some(job_id), ..r })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::TempDir;
+
+    fn setup(name: &str) -> (TempDir, Journal, PathBuf) {
+        let dir = TempDir::new(std::env::temp_dir().join(format!("replace-test-{}-{}", std::process::id(), name))).unwrap();
+        let original = dir.path().join("movie.mkv");
+        fs::write(&original, "original").unwrap();
+        let temp = staging::temp_path_for(&original);
+        fs::write(&temp, "new").unwrap();
+        // An owner that has gone: this pid, started at another time
+        let owner = Owner { started_at: 1, ..Owner::current() };
+        let journal = Journal { owner, original: original.clone(), temp, backup: backup_path_for(&original), phase: Phase::Swapping };
+        let journal_path = dir.path().join("journal.json");
+        write_journal(&journal_path, &journal).unwrap();
+        (dir, journal, journal_path)
+    }
+
+    fn contents(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn swap_leaves_the_new_file_and_a_backup() {
+        let (_dir, journal, _) = setup("swap");
+        swap(&journal).unwrap();
+        assert_eq!(contents(&journal.original), "new");
+        assert_eq!(contents(&journal.backup), "original");
+        assert!(!journal.temp.exists());
+    }
+
+    #[test]
+    fn crash_after_the_backup_keeps_the_original_in_place() {
+        let (_dir, journal, journal_path) = setup("crash-backup");
+        make_backup(&journal.original, &journal.backup).unwrap();
+        // The path was never empty...
+        assert_eq!(contents(&journal.original), "original");
+        // ...and recovery tidies up the rest
+        recover_one(&journal_path).unwrap();
+        assert_eq!(contents(&journal.original), "original");
+        assert!(!journal.backup.exists());
+        assert!(!journal.temp.exists());
+        assert!(!journal_path.exists());
+    }
+
+    #[test]
+    fn crash_after_the_rename_restores_the_original() {
+        let (_dir, journal, journal_path) = setup("crash-rename");
+        swap(&journal).unwrap();
+        recover_one(&journal_path).unwrap();
+        assert_eq!(contents(&journal.original), "original");
+        assert!(!journal.backup.exists());
+        assert!(!journal_path.exists());
+    }
+
+    #[test]
+    fn crash_after_verifying_keeps_the_new_file() {
+        let (_dir, mut journal, journal_path) = setup("crash-verified");
+        swap(&journal).unwrap();
+        journal.phase = Phase::Verified;
+        write_journal(&journal_path, &journal).unwrap();
+        recover_one(&journal_path).unwrap();
+        assert_eq!(contents(&journal.original), "new");
+        assert!(!journal.backup.exists());
+    }
+
+    #[test]
+    fn a_live_owner_s_journal_is_left_alone() {
+        let (_dir, mut journal, journal_path) = setup("live");
+        journal.owner = Owner::current();
+        write_journal(&journal_path, &journal).unwrap();
+        swap(&journal).unwrap();
+        recover_one(&journal_path).unwrap();
+        assert_eq!(contents(&journal.original), "new");
+        assert!(journal_path.exists());
+    }
+}
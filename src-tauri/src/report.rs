@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use tauri::State;
+
+use crate::AppHandle;
+use crate::clock;
+use crate::ffmpeg;
+use crate::flood;
+use crate::history::{self, DateRange, HistoryEntry, HistoryStore, JobStatus};
+use crate::outputs;
+use crate::paths;
+use crate::sizefit;
+use crate::verify;
+
+
+// An explicit list of history ids, the name of a batch group, or
+// `{ "watch_folder": id }` for everything a watch folder picked up.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum JobSelection {
+    Ids(Vec<u64>),
+    Group(String),
+    WatchFolder { watch_folder: String },
+}
+
+impl JobSelection {
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        match self {
+            JobSelection::Ids(ids) => ids.contains(&entry.id),
+            JobSelection::Group(group) => entry.group.as_deref() == Some(group.as_str()),
+            JobSelection::WatchFolder { watch_folder } => entry.watch_folder.as_deref() == Some(watch_folder.as_str()),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFilter {
+    #[default]
+    All,
+    FailedOnly,
+}
+
+const CSV_HEADER: [&str; 19] = [
+    "id", "group", "watch_folder", "input", "output", "status", "error",
+    "input_bytes", "output_bytes", "ratio", "encoder",
+    "duration_secs", "wall_time_secs", "warnings", "attention", "fit_rung", "timeline",
+    "finished_at_utc", "finished_at_local",
+];
+
+// "job.queued@1718000000000; job.started@..." (params are in the JSON report)
+fn timeline_cell(entry: &HistoryEntry) -> String {
+    entry.timeline.iter().map(|t| format!("{}@{}", t.code, t.at_ms)).collect::<Vec<_>>().join("; ")
+}
+
+// Inputs worth a closer look, beyond a plain failure
+fn attention(entry: &HistoryEntry) -> &'static str {
+    match &entry.error {
+        Some(e) if e.starts_with(ffmpeg::MEMORY_LIMIT_ERROR) => "memory-limit",
+        Some(e) if e.starts_with(flood::TOO_MANY_DECODE_ERRORS) => "decode-flood",
+        Some(e) if e.starts_with(ffmpeg::HW_ENCODE_FAILED) => "hardware-encode",
+        Some(e) if e.starts_with(verify::INCOMPLETE_OUTPUT) => "incomplete-output",
+        Some(e) if e.starts_with(outputs::WORK_VOLUME_FULL) => "work-volume-full",
+        Some(e) if e.starts_with(outputs::OUTPUT_VOLUME_FULL) => "output-volume-full",
+        Some(e) if e.starts_with(sizefit::SIZE_CAP_MISSED) => "size-cap-missed",
+        _ => "",
+    }
+}
+
+fn csv_row(entry: &HistoryEntry) -> Vec<String> {
+    let status = match entry.status {
+        JobStatus::Success => "success",
+        JobStatus::Failed => "failed",
+    };
+    vec![
+        entry.id.to_string(),
+        entry.group.clone().unwrap_or_default(),
+        entry.watch_folder.clone().unwrap_or_default(),
+        entry.input.clone(),
+        entry.output.clone(),
+        status.to_string(),
+        entry.error.clone().unwrap_or_default(),
+        entry.input_bytes.to_string(),
+        entry.output_bytes.to_string(),
+        entry.ratio().map(|r| format!("{:.4}", r)).unwrap_or_default(),
+        entry.encoder.clone().unwrap_or_default(),
+        entry.duration_secs.map(|d| format!("{:.3}", d)).unwrap_or_default(),
+        format!("{:.3}", entry.wall_time_secs),
+        entry.warnings.join("; "),
+        attention(entry).to_string(),
+        entry.fit.as_ref().map(|f| f.used_label()).unwrap_or_default(),
+        timeline_cell(entry),
+        clock::render_utc(entry.finished_at),
+        clock::render_local(entry.finished_at),
+    ]
+}
+
+fn write_csv(path: &Path, entries: &[HistoryEntry]) -> Result<(), String> {
+    // A real CSV writer: paths with commas, quotes or newlines get quoted properly
+    let mut writer = csv::Writer::from_path(path).map_err(|e| e.to_string())?;
+    writer.write_record(CSV_HEADER).map_err(|e| e.to_string())?;
+    for entry in entries {
+        writer.write_record(csv_row(entry)).map_err(|e| e.to_string())?;
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
+
+// The history entry plus readable finish times (it stores Unix seconds)
+#[derive(Serialize)]
+struct JsonRow<'a> {
+    #[serde(flatten)]
+    entry: &'a HistoryEntry,
+    finished_at_utc: String,
+    finished_at_local: String,
+}
+
+pub fn json_report(entries: &[HistoryEntry]) -> Result<String, String> {
+    let rows: Vec<JsonRow> = entries
+        .iter()
+        .map(|entry| JsonRow {
+            entry,
+            finished_at_utc: clock::render_utc(entry.finished_at),
+            finished_at_local: clock::render_local(entry.finished_at),
+        })
+        .collect();
+    serde_json::to_string_pretty(&rows).map_err(|e| e.to_string())
+}
+
+fn write_json(path: &Path, entries: &[HistoryEntry]) -> Result<(), String> {
+    fs::write(path, json_report(entries)?).map_err(|e| e.to_string())
+}
+
+// ==========================================
+// COMMAND: EXPORT BATCH REPORT
+// ==========================================
+// Returns the number of rows written.
+#[tauri::command]
+pub fn export_batch_report(
+    app: AppHandle,
+    history: State<'_, HistoryStore>,
+    job_ids_or_group: JobSelection,
+    path: String,
+    format: ReportFormat,
+    filter: Option<ReportFilter>,
+) -> Result<usize, String> {
+    let failed_only = filter.unwrap_or_default() == ReportFilter::FailedOnly;
+    let entries: Vec<HistoryEntry> = history
+        .all()
+        .into_iter()
+        .filter(|e| job_ids_or_group.matches(e))
+        .filter(|e| !failed_only || e.status == JobStatus::Failed)
+        .collect();
+
+    let path = paths::secure_output(&app, &path, false)?;
+    let path = Path::new(&path);
+    match format {
+        ReportFormat::Csv => write_csv(path, &entries)?,
+        ReportFormat::Json => write_json(path, &entries)?,
+    }
+    println!("📄 Wrote batch report with {} rows to {}", entries.len(), path.display());
+    Ok(entries.len())
+}
+
+// ==========================================
+// COMMAND: EXPORT HISTORY
+// ==========================================
+// "all", "prunable" (what the next compaction would archive and prune, see
+// history.rs) or a date range.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum HistoryRange {
+    Scope(HistoryScope),
+    Dates(DateRange),
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryScope {
+    All,
+    Prunable,
+}
+
+// Oldest first; the format follows the extension (.csv, else JSON) unless
+// given. The JSON reads back as history entries. Returns the rows written.
+#[tauri::command]
+pub fn export_history(
+    app: AppHandle,
+    history: State<'_, HistoryStore>,
+    path: String,
+    range: Option<HistoryRange>,
+    format: Option<ReportFormat>,
+) -> Result<usize, String> {
+    let entries = match range.unwrap_or(HistoryRange::Scope(HistoryScope::All)) {
+        HistoryRange::Scope(HistoryScope::All) => history.all(),
+        HistoryRange::Scope(HistoryScope::Prunable) => history.prunable(history::retention(&app), history::now_unix()),
+        HistoryRange::Dates(range) => history.all().into_iter().filter(|e| range.contains(e.finished_at)).collect(),
+    };
+    let path = Path::new(&path);
+    let csv = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+    match format.unwrap_or(if csv { ReportFormat::Csv } else { ReportFormat::Json }) {
+        ReportFormat::Csv => write_csv(path, &entries)?,
+        ReportFormat::Json => write_json(path, &entries)?,
+    }
+    println!("📄 Exported {} history entries to {}", entries.len(), path.display());
+    Ok(entries.len())
+}
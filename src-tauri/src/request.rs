@@ -0,0 +1,1049 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::path::Path;
+
+use crate::audio::AudioTarget;
+use crate::audiomode::{self, AudioMode};
+use crate::deterministic;
+use crate::duration::DurationPolicy;
+use crate::encoders;
+use crate::filters::{BlurRegion, FitCanvas, PadStyle, MAX_BLUR_REGIONS};
+use crate::filterspec::{self, FilterSpec};
+use crate::gif;
+use crate::hardware::EncoderPreference;
+use crate::metadata::MetadataMode;
+use crate::outputs::OverwritePolicy;
+use crate::playability::{self, TargetProfile};
+use crate::overlay::{OverlayPosition, TextOverlay};
+use crate::resources::{ProcessOptions, MAX_THREADS};
+use crate::quality::{self, QualityLevel, QualityOptions};
+use crate::remux::JobMode;
+use crate::subtitles::{self, SidecarSelection};
+use crate::support::{self, VideoCodec};
+use crate::vfr;
+use crate::VideoMode;
+
+// ==========================================
+// TYPED JOB REQUESTS
+// ==========================================
+// What the compress commands, the queue, watch folders and the automation
+// API all accept. `version` lets the shape change later without guessing
+// what an old client meant; requests without one are version 1.
+pub const REQUEST_VERSION: u32 = 1;
+
+pub(crate) const MAX_DIMENSION: u32 = 16384;
+pub(crate) const MAX_FPS: f64 = 240.0;
+pub(crate) const MAX_AV_OFFSET_MS: i64 = 60_000;
+pub(crate) const FONT_SIZE_RANGE: std::ops::RangeInclusive<u32> = 4..=512;
+pub(crate) const AUDIO_BITRATE_RANGE: std::ops::RangeInclusive<u32> = 32..=512;
+pub(crate) const FLAC_LEVEL_RANGE: std::ops::RangeInclusive<u32> = 0..=12;
+// The widest any encoder takes; validate checks the chosen one's own
+pub(crate) const CRF_RANGE: std::ops::RangeInclusive<u32> = 0..=63;
+pub(crate) const IMAGE_QUALITY_RANGE: std::ops::RangeInclusive<u32> = 1..=100;
+pub(crate) const EFFICIENCY_BPP_RANGE: std::ops::RangeInclusive<f64> = 0.005..=1.0;
+const PIP_SCALE_RANGE: std::ops::RangeInclusive<u32> = 5..=100;
+const PIP_MAX_VOLUME: f64 = 4.0;
+const PIP_CONTAINERS: &[&str] = &["mp4", "mkv", "mov", "m4v"];
+
+fn current_version() -> u32 {
+    REQUEST_VERSION
+}
+
+fn yes() -> bool {
+    true
+}
+
+// Every video option, without the paths. Watch-folder templates and presets
+// are made of these.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct VideoOptions {
+    pub auto_gpu: bool,
+    pub video_mode: VideoMode,
+    pub extract_incompatible_subs: bool,
+    pub resumable: bool,
+    pub overlay_text: Option<TextOverlay>,
+    pub blur_regions: Vec<BlurRegion>,
+    pub av_offset_ms: Option<i64>,
+    pub detect_av_offset: bool,
+    // EBU R128 loudness normalization of the audio, measured first (see
+    // loudness.rs)
+    pub normalize_audio: bool,
+    // Copy the source's audio, re-encode it or drop it; "auto" decides per
+    // track (see audiomode.rs)
+    pub audio: AudioMode,
+    pub deinterlace: bool,
+    pub detect_telecine: bool,
+    // Only encode the first N seconds, to check settings end-to-end
+    pub limit_duration_secs: Option<f64>,
+    // Cut: where in the source to start and stop, in seconds
+    pub start_secs: Option<f64>,
+    pub end_secs: Option<f64>,
+    // Audio and video of different lengths: which one the output follows
+    // (see duration::StreamLengths)
+    pub duration_policy: DurationPolicy,
+    // Lossless cut: every stream copied (`-c copy`), like video_mode copy
+    // but without re-encoding the audio either
+    pub copy_only: bool,
+    // "remux": change the container, copying the streams (see remux.rs)
+    pub mode: JobMode,
+    // A remux may re-encode audio the output container can't hold
+    pub allow_partial_transcode: bool,
+    // Cap on how fast the input is read, in Mbit/s (network shares)
+    pub io_throttle_mbps: Option<u32>,
+    // Overrides the encoder's default quality (-crf, or -cq on NVENC).
+    // Older spelling of `quality` as a number; only one of them may be set.
+    pub crf: Option<u32>,
+    // quality / target_bitrate_kbps / max_filesize_mb
+    #[serde(flatten)]
+    pub rate: QualityOptions,
+    // Downscale wider / taller sources to fit; smaller ones are left alone
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    // Cap on the longer side, whichever way the picture is turned: 1920 keeps
+    // a portrait phone clip at 1080x1920 and a landscape one at 1920x1080
+    pub max_long_edge: Option<u32>,
+    // Drop frames from sources faster than this
+    pub max_fps: Option<f64>,
+    // Fit into a fixed canvas with bars (colored or a blurred copy)
+    pub fit_canvas: Option<FitCanvas>,
+    // Encode for this player profile and fail unless the output passes its
+    // lint (see playability.rs, ENCODING FOR A PROFILE)
+    pub playback_target: Option<TargetProfile>,
+    // Score the output against the source afterwards (VMAF, else SSIM)
+    pub compute_quality_score: bool,
+    // Every frame keeps its source timestamp: no frame-rate conversion, and
+    // a frame-count check afterwards (see vfr.rs)
+    pub preserve_vfr: bool,
+    // Keep every stream and tag, change nothing but the targeted codecs,
+    // and prove it afterwards (see surgical.rs)
+    pub surgical: bool,
+    // Carry Dolby Vision / HDR10+ through a re-encode instead of converting
+    // to plain HDR10 (see hdr.rs)
+    pub preserve_dynamic_hdr: bool,
+    // HDR sources always leave as SDR (BT.709), even for HEVC / AV1
+    // output that could keep them HDR (see hdr::static_plan)
+    pub tonemap_to_sdr: bool,
+    // Single-frame inputs become a PNG next to `output` instead of a
+    // one-frame video
+    pub single_frame_as_image: bool,
+    // An output bigger than the input is deleted and reported as skipped
+    pub skip_if_larger: bool,
+    // Sources that are already lean (or a newer codec than the target's)
+    // aren't encoded at all; without it they only get an advisory (see
+    // efficiency.rs)
+    pub skip_optimized: bool,
+    // Bits per pixel under which a source counts as lean, instead of its
+    // resolution tier's
+    pub efficiency_threshold_bpp: Option<f64>,
+    // H.264 / HEVC / AV1 for the containers that take H.264
+    pub codec: VideoCodec,
+    // Index up front, 8-bit 4:2:0 and High@4.1 caps for browsers; None = on
+    // for mp4 / m4v / mov (see encoders::EncoderProfile::web_caps)
+    pub web_optimized: Option<bool>,
+    // One vendor's hardware encoder (or the CPU) instead of auto_gpu's
+    // order; None = auto (see hardware::choose)
+    pub encoder_preference: Option<EncoderPreference>,
+    // Frame rate and width of GIF output (see gif.rs)
+    pub gif_fps: Option<f64>,
+    pub gif_width: Option<u32>,
+    // Copy the result to the upload destination in settings (see upload.rs)
+    pub upload: bool,
+    // Keep the source's tags (default) or drop them all
+    pub metadata: MetadataMode,
+    // Every audio track instead of one (see audiotracks.rs)
+    pub keep_all_streams: bool,
+    // Subtitle files next to the input muxed in as soft tracks: "auto"
+    // (default), "none" or a list (see subtitles.rs, SIDECAR FILES)
+    pub include_sidecar_subs: SidecarSelection,
+    // Skip what can't be decoded and keep the rest (see salvage.rs)
+    pub salvage: bool,
+    // Start even when the disk-space preflight says the output won't fit
+    // (see outputs.rs)
+    pub force: bool,
+    // The request's own filter chain, merged with the app's (see filterspec.rs)
+    pub filters: Option<FilterSpec>,
+    // The recording tool's fixups, when the source is a known screen
+    // recording (simple mode sets it; see sourcetool.rs)
+    pub source_fixups: bool,
+    // Hard size cap, in MB: the encode goes down a ladder of CRF and
+    // resolution until the output fits (see sizefit.rs)
+    pub fit_size_mb: Option<f64>,
+    // Byte-identical output for identical input and options (see
+    // deterministic.rs)
+    pub deterministic: bool,
+    // threads / priority of the job's ffmpeg runs (see resources.rs)
+    #[serde(flatten)]
+    pub process: ProcessOptions,
+}
+
+// Free-form labels for finding the job in history later; they don't change
+// what the job does.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Annotations {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+pub const MAX_TAGS: usize = 20;
+pub const MAX_TAG_CHARS: usize = 64;
+pub const MAX_NOTE_CHARS: usize = 2000;
+
+impl Annotations {
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut issues = Issues(vec![]);
+        self.check(&mut issues);
+        issues.finish()
+    }
+
+    fn check(&self, issues: &mut Issues) {
+        if self.tags.len() > MAX_TAGS {
+            issues.add("tags", format!("At most {} tags are allowed", MAX_TAGS));
+        }
+        for tag in &self.tags {
+            if tag.trim().is_empty() {
+                issues.add("tags", "Tags can't be empty");
+            } else if tag.chars().count() > MAX_TAG_CHARS {
+                issues.add("tags", format!("Tag \"{}\" is longer than {} characters", tag, MAX_TAG_CHARS));
+            }
+        }
+        if self.note.as_ref().is_some_and(|n| n.chars().count() > MAX_NOTE_CHARS) {
+            issues.add("note", format!("The note is longer than {} characters", MAX_NOTE_CHARS));
+        }
+    }
+
+    // Trimmed tags without case-insensitive duplicates, and no blank note.
+    pub fn normalized(&self) -> Annotations {
+        let mut tags: Vec<String> = vec![];
+        for tag in self.tags.iter().map(|t| t.trim()).filter(|t| !t.is_empty()) {
+            if !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+                tags.push(tag.to_string());
+            }
+        }
+        let note = self.note.as_deref().map(str::trim).filter(|n| !n.is_empty()).map(String::from);
+        Annotations { tags, note }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VideoCompressRequest {
+    #[serde(default = "current_version")]
+    pub version: u32,
+    pub input: String,
+    pub output: String,
+    #[serde(flatten)]
+    pub options: VideoOptions,
+    // None = the usual: queue jobs rename, direct commands overwrite
+    #[serde(default)]
+    pub overwrite_policy: Option<OverwritePolicy>,
+    // Folder the temp output is written to, in place of the work_dir setting
+    #[serde(default)]
+    pub work_dir: Option<String>,
+    // Make the output's folder when it doesn't exist (see paths::secure)
+    #[serde(default)]
+    pub create_dirs: bool,
+    // Stop before ffmpeg starts and return its command (see dryrun.rs)
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(flatten)]
+    pub annotations: Annotations,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ImageCompressRequest {
+    #[serde(default = "current_version")]
+    pub version: u32,
+    pub input: String,
+    pub output: String,
+    // Make the output's folder when it doesn't exist (see paths::secure)
+    #[serde(default)]
+    pub create_dirs: bool,
+    // Target size in pixels; a missing side keeps the aspect ratio
+    #[serde(default, deserialize_with = "dimension")]
+    pub width: Option<u32>,
+    #[serde(default, deserialize_with = "dimension")]
+    pub height: Option<u32>,
+    // 1-100, higher is better; for JPEG, WebP and AVIF output (100 is
+    // lossless WebP). PNG is lossless: any quality means maximum compression
+    #[serde(default)]
+    pub quality: Option<u32>,
+    // See VideoOptions::skip_if_larger
+    #[serde(default)]
+    pub skip_if_larger: bool,
+    // See VideoOptions::metadata
+    #[serde(default)]
+    pub metadata: MetadataMode,
+    // See VideoOptions::process
+    #[serde(flatten)]
+    pub process: ProcessOptions,
+    // See VideoCompressRequest::dry_run
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(flatten)]
+    pub annotations: Annotations,
+}
+
+// Audio conversion; the format comes from the output extension
+// (mp3, m4a, opus, ogg, flac, wav).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AudioCompressRequest {
+    #[serde(default = "current_version")]
+    pub version: u32,
+    pub input: String,
+    pub output: String,
+    // Make the output's folder when it doesn't exist (see paths::secure)
+    #[serde(default)]
+    pub create_dirs: bool,
+    // Ignored for lossless targets
+    #[serde(default)]
+    pub bitrate_kbps: Option<u32>,
+    // VBR level instead of a fixed bitrate; a bitrate wins when both are set
+    #[serde(default)]
+    pub quality: Option<QualityLevel>,
+    // FLAC only, 0 (fastest) to 12 (smallest); 8 when not set
+    #[serde(default)]
+    pub compression_level: Option<u32>,
+    #[serde(default = "yes")]
+    pub keep_cover: bool,
+    // See VideoOptions::process
+    #[serde(flatten)]
+    pub process: ProcessOptions,
+    #[serde(flatten)]
+    pub annotations: Annotations,
+}
+
+// Where a picture-in-picture output's audio comes from.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PipAudio {
+    #[default]
+    Main,
+    Overlay,
+    Mix,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PipOptions {
+    pub position: OverlayPosition,
+    // Overlay width as a percentage of the main video's
+    pub scale_pct: u32,
+    // When the overlay starts, relative to the main input (negative skips
+    // the start of the overlay instead)
+    pub overlay_offset_secs: f64,
+    // Rounded corners on the overlay
+    pub rounded: bool,
+    pub audio_source: PipAudio,
+    // Levels for `mix` (1.0 = unchanged)
+    pub main_volume: f64,
+    pub overlay_volume: f64,
+}
+
+impl Default for PipOptions {
+    fn default() -> Self {
+        PipOptions {
+            position: OverlayPosition::BottomRight,
+            scale_pct: 25,
+            overlay_offset_secs: 0.0,
+            rounded: false,
+            audio_source: PipAudio::Main,
+            main_volume: 1.0,
+            overlay_volume: 1.0,
+        }
+    }
+}
+
+// Picture-in-picture: `overlay` composed into a corner of `input` (the main
+// video), one encode. The output is as long as the main input.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PipRequest {
+    #[serde(default = "current_version")]
+    pub version: u32,
+    pub input: String,
+    pub overlay: String,
+    pub output: String,
+    // Make the output's folder when it doesn't exist (see paths::secure)
+    #[serde(default)]
+    pub create_dirs: bool,
+    #[serde(flatten)]
+    pub options: PipOptions,
+    #[serde(flatten)]
+    pub annotations: Annotations,
+}
+
+// Width/height used to be strings ("0" or "" meaning "keep"), and queue.json
+// files from then are still around: accept those, numbers and null.
+fn dimension<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Number(u32),
+        Text(String),
+    }
+    match Option::<Raw>::deserialize(deserializer)? {
+        None | Some(Raw::Number(0)) => Ok(None),
+        Some(Raw::Number(n)) => Ok(Some(n)),
+        Some(Raw::Text(text)) => parse_dimension(&text).map_err(serde::de::Error::custom),
+    }
+}
+
+pub fn parse_dimension(text: &str) -> Result<Option<u32>, String> {
+    match text.trim() {
+        "" | "0" => Ok(None),
+        t => t.parse().map(Some).map_err(|_| format!("\"{}\" is not a size in pixels", text)),
+    }
+}
+
+// One problem with a request, tied to the field it's about.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ValidationIssue {
+    pub field: &'static str,
+    pub message: String,
+}
+
+// All the issues found, so the UI can flag every bad field at once.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ValidationErrors(pub Vec<ValidationIssue>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(|i| format!("{}: {}", i.field, i.message)).collect();
+        write!(f, "Invalid request: {}", parts.join("; "))
+    }
+}
+
+#[derive(Default)]
+struct Issues(Vec<ValidationIssue>);
+
+impl Issues {
+    fn add(&mut self, field: &'static str, message: impl Into<String>) {
+        self.0.push(ValidationIssue { field, message: message.into() });
+    }
+
+    fn finish(self) -> Result<(), ValidationErrors> {
+        if self.0.is_empty() { Ok(()) } else { Err(ValidationErrors(self.0)) }
+    }
+}
+
+fn check_common(issues: &mut Issues, version: u32, input: &str, output: &str) {
+    if version == 0 || version > REQUEST_VERSION {
+        issues.add("version", format!("Unsupported request version {} (this app understands up to {})", version, REQUEST_VERSION));
+    }
+    if input.trim().is_empty() {
+        issues.add("input", "No input file given");
+    }
+    if output.trim().is_empty() {
+        issues.add("output", "No output file given");
+    } else if Path::new(output).extension().is_none() {
+        issues.add("output", "The output needs a file extension so the format can be chosen");
+    }
+}
+
+fn check_process(issues: &mut Issues, process: &ProcessOptions) {
+    if let Some(threads) = process.threads.filter(|t| !(1..=MAX_THREADS).contains(t)) {
+        issues.add("threads", format!("{} threads is outside 1-{}", threads, MAX_THREADS));
+    }
+}
+
+fn output_ext(output: &str) -> String {
+    Path::new(output).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase()
+}
+
+impl VideoOptions {
+    fn check(&self, issues: &mut Issues, ext: &str) {
+        if self.blur_regions.len() > MAX_BLUR_REGIONS {
+            issues.add("blur_regions", format!("At most {} blur regions are supported", MAX_BLUR_REGIONS));
+        }
+        for (i, r) in self.blur_regions.iter().enumerate() {
+            if r.w == 0 || r.h == 0 {
+                issues.add("blur_regions", format!("Region {} has no area", i + 1));
+            }
+        }
+        if let Some(overlay) = &self.overlay_text {
+            if overlay.template.trim().is_empty() {
+                issues.add("overlay_text", "The overlay text is empty");
+            }
+            if let Some(size) = overlay.font_size.filter(|s| !FONT_SIZE_RANGE.contains(s)) {
+                issues.add("overlay_text", format!("Font size {} is outside {}-{}", size, FONT_SIZE_RANGE.start(), FONT_SIZE_RANGE.end()));
+            }
+        }
+        check_process(issues, &self.process);
+        for problem in self.filters.as_ref().map(FilterSpec::problems).unwrap_or_default() {
+            issues.add("filters", problem);
+        }
+        if let Some(ms) = self.av_offset_ms.filter(|ms| ms.abs() > MAX_AV_OFFSET_MS) {
+            issues.add("av_offset_ms", format!("{} ms is more than the {} ms limit", ms, MAX_AV_OFFSET_MS));
+        }
+        if let Some(secs) = self.limit_duration_secs {
+            if !secs.is_finite() || secs <= 0.0 {
+                issues.add("limit_duration_secs", "The preview length must be a positive number of seconds");
+            }
+            if self.resumable {
+                issues.add("limit_duration_secs", "Previews can't be resumable");
+            }
+        }
+        if let Some(start) = self.start_secs.filter(|s| !s.is_finite() || *s < 0.0) {
+            issues.add("start_secs", format!("{} isn't a usable start time", start));
+        }
+        if let Some(end) = self.end_secs {
+            if !end.is_finite() || end <= self.start_secs.unwrap_or(0.0) {
+                issues.add("end_secs", "The end of the cut must come after its start");
+            }
+        }
+        if self.resumable && self.is_cut() {
+            issues.add("start_secs", "A cut can't be combined with resumable encodes yet");
+        }
+        if self.crf.is_some() && self.rate.quality.is_some() {
+            issues.add("crf", "Set either crf or quality, not both");
+        }
+        // Same tables as get_support_matrix, so the UI never offers what fails here
+        let encoder = support::default_video_encoder(ext, self.wants_gpu(), self.codec);
+        // On the encoder's own scale: VP9 and SVT-AV1 go up to 63
+        let crf_range = quality::crf_range(encoder.unwrap_or("libx264"));
+        if let Some(crf) = self.crf.filter(|c| !crf_range.contains(c)) {
+            issues.add("crf", format!("{} is outside {}-{} for {}", crf, crf_range.start(), crf_range.end(), encoder.unwrap_or(ext)));
+        }
+        if self.preference().is_vendor() && !support::accepts_video(ext, self.codec.name()) {
+            issues.add("encoder_preference", format!(".{} output isn't encoded on the GPU, so encoder_preference can't apply", ext));
+        }
+        if self.codec != VideoCodec::H264 && support::video_container(ext).is_some_and(|c| !c.video.contains(&self.codec.name())) {
+            issues.add("codec", format!(".{} can't hold {} video", ext, self.codec.name()));
+        }
+        match encoder {
+            None if !ext.is_empty() => {
+                issues.add("output", format!(".{} isn't a video format we can write ({})", ext, support::video_extensions().join(", ")));
+            }
+            Some(encoder) if self.crf.is_some() && !self.copies_video() && !support::honors(encoder, "crf") => {
+                issues.add("crf", format!("{} output ignores crf", encoder));
+            }
+            Some(encoder) if self.rate.is_set() && !self.copies_video() && !support::honors(encoder, "quality") => {
+                issues.add("quality", format!("{} output ignores quality and bitrate settings", encoder));
+            }
+            _ => {}
+        }
+        for (field, problem) in self.rate.problems(encoder) {
+            issues.add(field, problem);
+        }
+        if self.rate.target_size_mb.is_some() && self.resumable {
+            issues.add("target_size_mb", "A size target can't be combined with resumable encodes: each part would need its own two passes");
+        }
+        if let Some(cap) = self.fit_size_mb {
+            if !cap.is_finite() || cap <= 0.0 {
+                issues.add("fit_size_mb", format!("{} MB isn't a usable size cap", cap));
+            }
+            if self.crf.is_some() || self.rate.is_set() {
+                issues.add("fit_size_mb", "fit_size_mb picks the quality itself; leave crf, quality and bitrate settings out");
+            }
+            if self.resumable || self.surgical || self.copies_video() || ext == "gif" {
+                issues.add("fit_size_mb", "fit_size_mb needs a plain re-encode (not resumable, surgical, copy or GIF)");
+            }
+            if let Some(encoder) = encoder.filter(|e| !matches!(encoders::profile(e).codec, "h264" | "hevc")) {
+                issues.add("fit_size_mb", format!("fit_size_mb works with H.264 and HEVC output, not {}", encoder));
+            }
+        }
+        if let Some(w) = self.max_width.filter(|w| *w < 16 || *w > MAX_DIMENSION) {
+            issues.add("max_width", format!("{} px is outside 16-{}", w, MAX_DIMENSION));
+        }
+        if let Some(h) = self.max_height.filter(|h| *h < 16 || *h > MAX_DIMENSION) {
+            issues.add("max_height", format!("{} px is outside 16-{}", h, MAX_DIMENSION));
+        }
+        if let Some(edge) = self.max_long_edge.filter(|e| *e < 16 || *e > MAX_DIMENSION) {
+            issues.add("max_long_edge", format!("{} px is outside 16-{}", edge, MAX_DIMENSION));
+        }
+        if let Some(canvas) = &self.fit_canvas {
+            for side in [canvas.width, canvas.height] {
+                if !(16..=MAX_DIMENSION).contains(&side) || side % 2 != 0 {
+                    issues.add("fit_canvas", format!("{} px isn't an even size within 16-{}", side, MAX_DIMENSION));
+                }
+            }
+            match &canvas.color {
+                Some(color) if !filterspec::valid_color(color) => {
+                    issues.add("fit_canvas", format!("\"{}\" isn't a color (a name like white, or #rrggbb, optionally @alpha)", color));
+                }
+                Some(_) if canvas.pad_style == PadStyle::Blur => {
+                    issues.add("fit_canvas", "color is for pad_style \"color\"; the blur style fills the bars from the picture");
+                }
+                _ => {}
+            }
+            if ext == "gif" {
+                issues.add("fit_canvas", "fit_canvas isn't available for GIF output");
+            }
+        }
+        if let Some(target) = self.playback_target {
+            if let Err(e) = playability::check_target(target, ext, self.codec.name()) {
+                issues.add("playback_target", e);
+            }
+            if self.copies_video() {
+                issues.add("playback_target", "playback_target encodes the video for the profile, so it can't be combined with a video copy");
+            }
+        }
+        if let Some(bpp) = self.efficiency_threshold_bpp.filter(|b| !EFFICIENCY_BPP_RANGE.contains(b)) {
+            issues.add("efficiency_threshold_bpp", format!("{} is outside {}-{}", bpp, EFFICIENCY_BPP_RANGE.start(), EFFICIENCY_BPP_RANGE.end()));
+        }
+        if let Some(fps) = self.max_fps.filter(|f| !f.is_finite() || *f < 1.0 || *f > MAX_FPS) {
+            issues.add("max_fps", format!("{} fps is outside 1-{}", fps, MAX_FPS));
+        }
+        if self.preserve_vfr {
+            if self.max_fps.is_some() {
+                issues.add("preserve_vfr", "preserve_vfr keeps every frame, so it can't be combined with max_fps");
+            }
+            if self.detect_telecine {
+                issues.add("preserve_vfr", "preserve_vfr keeps every frame, so it can't be combined with detect_telecine (which drops some)");
+            }
+            if !vfr::CONTAINERS.contains(&ext) {
+                issues.add("preserve_vfr", format!(".{} output can't keep variable frame timing; use {}", ext, vfr::CONTAINERS.join(", ")));
+            }
+        }
+        if self.tonemap_to_sdr && self.preserve_dynamic_hdr {
+            issues.add("tonemap_to_sdr", "tonemap_to_sdr makes the output SDR, so there's no dynamic HDR metadata left to preserve");
+        }
+        if self.deterministic {
+            for (option, why) in deterministic::conflicts(self) {
+                issues.add("deterministic", format!("deterministic output can't be combined with {}: {}", option, why));
+            }
+            if ext == "gif" {
+                issues.add("deterministic", "GIF output has no bitexact mode");
+            }
+        }
+        if self.io_throttle_mbps == Some(0) {
+            issues.add("io_throttle_mbps", "The read cap must be at least 1 Mbit/s (leave it empty for no cap)");
+        }
+        if self.resumable && self.av_offset_ms.is_some_and(|ms| ms != 0) {
+            issues.add("av_offset_ms", "A/V offset correction can't be combined with resumable encodes yet");
+        }
+        if self.normalize_audio {
+            if self.copy_only || self.mode == JobMode::Remux {
+                issues.add("normalize_audio", "copy_only and remux copy the audio as it is, so it can't be normalized");
+            }
+            if self.resumable {
+                issues.add("normalize_audio", "Loudness normalization can't be combined with resumable encodes: each part would be measured on its own");
+            }
+            if self.keep_all_streams {
+                issues.add("normalize_audio", "Loudness is measured on one audio track, so it can't be combined with keep_all_streams");
+            }
+            if ext == "gif" {
+                issues.add("normalize_audio", "GIF output has no audio");
+            }
+        }
+        if self.audio != AudioMode::Auto {
+            if self.copy_only || self.mode == JobMode::Remux {
+                issues.add("audio", "copy_only and remux copy the audio as it is");
+            }
+            if ext == "gif" {
+                issues.add("audio", "GIF output has no audio");
+            }
+        }
+        match &self.audio {
+            AudioMode::Copy => {
+                if let Some(option) = self.reencodes_audio() {
+                    issues.add("audio", format!("{} re-encodes the audio, so it can't be copied", option));
+                }
+            }
+            AudioMode::Transcode(t) => {
+                if let Some(kbps) = t.bitrate_kbps.filter(|k| !audiomode::BITRATE_RANGE.contains(k)) {
+                    issues.add("audio", format!("{} kbps is outside {}-{}", kbps, audiomode::BITRATE_RANGE.start(), audiomode::BITRATE_RANGE.end()));
+                }
+                if let Some(ch) = t.channels.filter(|c| *c < 1 || *c > audiomode::MAX_CHANNELS) {
+                    issues.add("audio", format!("{} channels is outside 1-{}", ch, audiomode::MAX_CHANNELS));
+                }
+            }
+            AudioMode::Remove => {
+                let needs_audio = [
+                    ("normalize_audio", self.normalize_audio),
+                    ("av_offset_ms", self.av_offset_ms.is_some_and(|ms| ms != 0)),
+                    ("detect_av_offset", self.detect_av_offset),
+                    ("keep_all_streams", self.keep_all_streams),
+                ];
+                for (option, _) in needs_audio.into_iter().filter(|(_, on)| *on) {
+                    issues.add("audio", format!("\"remove\" drops the audio, so {} has nothing to work on", option));
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(fps) = self.gif_fps.filter(|f| !f.is_finite() || *f < 1.0 || *f > gif::MAX_FPS) {
+            issues.add("gif_fps", format!("{} fps is outside 1-{}", fps, gif::MAX_FPS));
+        }
+        if let Some(w) = self.gif_width.filter(|w| *w < 16 || *w > MAX_DIMENSION) {
+            issues.add("gif_width", format!("{} px is outside 16-{}", w, MAX_DIMENSION));
+        }
+        if (self.gif_fps.is_some() || self.gif_width.is_some()) && ext != "gif" {
+            issues.add("gif_fps", "gif_fps and gif_width only apply to GIF output");
+        }
+
+        if self.surgical {
+            for option in self.non_surgical_options() {
+                issues.add("surgical", format!("{} would change more than the targeted streams", option));
+            }
+            if ext == "gif" {
+                issues.add("surgical", "GIF output can't keep the other streams");
+            }
+        }
+
+        if self.allow_partial_transcode && self.mode != JobMode::Remux {
+            issues.add("allow_partial_transcode", "allow_partial_transcode only applies to mode \"remux\"");
+        }
+        if let SidecarSelection::Files(files) = &self.include_sidecar_subs {
+            for file in files.iter().filter(|f| Path::new(f).extension().and_then(|e| subtitles::codec_for(&e.to_string_lossy())).is_none()) {
+                issues.add("include_sidecar_subs", format!("{} isn't a subtitle file (.srt, .ass, .ssa or .vtt)", file));
+            }
+            if !files.is_empty() && self.resumable {
+                issues.add("include_sidecar_subs", "Subtitle files can't be muxed into a resumable job's segments");
+            }
+            if !files.is_empty() && ext == "gif" {
+                issues.add("include_sidecar_subs", "GIF output can't carry subtitles");
+            }
+        }
+        // copy_only and remux are reported under video_mode too: the rules are the same
+        if self.copies_video() {
+            if let Some(option) = self.first_picture_option() {
+                issues.add("video_mode", format!("\"copy\" can't be combined with {}: it needs the video re-encoded", option));
+            }
+            if self.crf.is_some() || self.rate.is_set() {
+                issues.add("video_mode", "\"copy\" can't be combined with crf, quality or bitrate settings: nothing is re-encoded");
+            }
+            if self.resumable {
+                issues.add("video_mode", "\"copy\" can't be combined with resumable: copy jobs are quick to redo anyway");
+            }
+            if ext == "gif" {
+                issues.add("video_mode", "\"copy\" isn't possible for GIF output");
+            }
+        }
+    }
+
+    // Options that touch streams or timing beyond re-encoding the targeted
+    // streams; none of them may be set in surgical mode.
+    fn non_surgical_options(&self) -> Vec<&'static str> {
+        let set = [
+            ("overlay_text", self.overlay_text.is_some()),
+            ("blur_regions", !self.blur_regions.is_empty()),
+            ("filters", self.filters.is_some()),
+            ("deinterlace", self.deinterlace),
+            ("detect_telecine", self.detect_telecine),
+            ("max_width", self.max_width.is_some()),
+            ("max_height", self.max_height.is_some()),
+            ("max_long_edge", self.max_long_edge.is_some()),
+            ("max_fps", self.max_fps.is_some()),
+            ("fit_canvas", self.fit_canvas.is_some()),
+            ("playback_target", self.playback_target.is_some()),
+            ("tonemap_to_sdr", self.tonemap_to_sdr),
+            ("av_offset_ms", self.av_offset_ms.is_some_and(|ms| ms != 0)),
+            ("detect_av_offset", self.detect_av_offset),
+            ("normalize_audio", self.normalize_audio),
+            ("audio", self.audio != AudioMode::Auto),
+            ("limit_duration_secs", self.limit_duration_secs.is_some()),
+            ("start_secs", self.start_secs.is_some()),
+            ("end_secs", self.end_secs.is_some()),
+            ("duration_policy", self.duration_policy != DurationPolicy::Video),
+            ("copy_only", self.copy_only),
+            ("mode", self.mode == JobMode::Remux),
+            ("resumable", self.resumable),
+            ("extract_incompatible_subs", self.extract_incompatible_subs),
+            ("metadata", self.metadata == MetadataMode::Strip),
+            ("web_optimized", self.web_optimized == Some(true)),
+            ("include_sidecar_subs", matches!(&self.include_sidecar_subs, SidecarSelection::Files(f) if !f.is_empty())),
+        ];
+        set.into_iter().filter(|(_, on)| *on).map(|(name, _)| name).collect()
+    }
+
+    // The first option that needs the audio decoded and encoded again
+    pub fn reencodes_audio(&self) -> Option<&'static str> {
+        let set = [
+            ("normalize_audio", self.normalize_audio),
+            ("av_offset_ms", self.av_offset_ms.is_some_and(|ms| ms != 0)),
+            ("detect_av_offset", self.detect_av_offset),
+            ("playback_target", self.playback_target.is_some()),
+            ("resumable", self.resumable),
+            ("salvage", self.salvage),
+        ];
+        set.into_iter().find(|(_, on)| *on).map(|(name, _)| name)
+    }
+
+    pub fn preference(&self) -> EncoderPreference {
+        self.encoder_preference.unwrap_or_default()
+    }
+
+    // auto_gpu, unless encoder_preference names the encoder itself
+    pub fn auto_gpu_decides(&self) -> bool {
+        self.auto_gpu && !self.preference().is_explicit()
+    }
+
+    // A hardware encoder is asked for, either way
+    pub fn wants_gpu(&self) -> bool {
+        match self.preference() {
+            EncoderPreference::Auto => self.auto_gpu,
+            EncoderPreference::Cpu => false,
+            _ => true,
+        }
+    }
+
+    pub fn copies_video(&self) -> bool {
+        self.video_mode == VideoMode::Copy || self.copy_only || self.mode == JobMode::Remux
+    }
+
+    pub fn is_cut(&self) -> bool {
+        self.start_secs.is_some() || self.end_secs.is_some()
+    }
+
+    // Name of the first option that changes the picture, for error messages.
+    pub fn first_picture_option(&self) -> Option<&'static str> {
+        if self.overlay_text.is_some() {
+            Some("overlay_text")
+        } else if !self.blur_regions.is_empty() {
+            Some("blur_regions")
+        } else if self.filters.is_some() {
+            Some("filters")
+        } else if self.deinterlace {
+            Some("deinterlace")
+        } else if self.detect_telecine {
+            Some("detect_telecine")
+        } else if self.max_width.is_some() {
+            Some("max_width")
+        } else if self.max_height.is_some() {
+            Some("max_height")
+        } else if self.max_long_edge.is_some() {
+            Some("max_long_edge")
+        } else if self.max_fps.is_some() {
+            Some("max_fps")
+        } else if self.fit_canvas.is_some() {
+            Some("fit_canvas")
+        } else if self.tonemap_to_sdr {
+            Some("tonemap_to_sdr")
+        } else {
+            None
+        }
+    }
+}
+
+impl VideoCompressRequest {
+    pub fn new(input: String, output: String, options: VideoOptions) -> Self {
+        VideoCompressRequest { version: REQUEST_VERSION, input, output, options, overwrite_policy: None, work_dir: None, create_dirs: false, dry_run: false, annotations: Annotations::default() }
+    }
+
+    // Previews always get a `_preview` suffix, so they can't be mistaken for
+    // (or overwrite, or stand in for) the full encode.
+    pub fn with_preview_output(mut self) -> Self {
+        if self.options.limit_duration_secs.is_none() {
+            return self;
+        }
+        let path = Path::new(&self.output);
+        let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        if !stem.ends_with("_preview") {
+            let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+            self.output = path.with_file_name(format!("{}_preview{}", stem, ext)).to_string_lossy().to_string();
+        }
+        self
+    }
+
+    // Everything that can be checked without touching the files.
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut issues = Issues::default();
+        check_common(&mut issues, self.version, &self.input, &self.output);
+        self.annotations.check(&mut issues);
+        self.options.check(&mut issues, &output_ext(&self.output));
+        issues.finish()
+    }
+}
+
+impl ImageCompressRequest {
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut issues = Issues::default();
+        check_common(&mut issues, self.version, &self.input, &self.output);
+        self.annotations.check(&mut issues);
+        for (field, value) in [("width", self.width), ("height", self.height)] {
+            if let Some(v) = value.filter(|v| *v > MAX_DIMENSION) {
+                issues.add(field, format!("{} px is larger than the {} px limit", v, MAX_DIMENSION));
+            }
+        }
+        check_process(&mut issues, &self.process);
+        if let Some(q) = self.quality.filter(|q| !IMAGE_QUALITY_RANGE.contains(q)) {
+            issues.add("quality", format!("{} is outside {}-{}", q, IMAGE_QUALITY_RANGE.start(), IMAGE_QUALITY_RANGE.end()));
+        }
+        issues.finish()
+    }
+
+    // `-vf scale=...` value, or None to keep the original size.
+    pub fn scale_filter(&self) -> Option<String> {
+        match (self.width, self.height) {
+            (None, None) => None,
+            (w, h) => Some(format!("scale={}:{}", w.map(|w| w as i64).unwrap_or(-1), h.map(|h| h as i64).unwrap_or(-1))),
+        }
+    }
+
+    // Encoder arguments for `quality`, with the output format's usual encoder.
+    pub fn quality_args(&self) -> Vec<String> {
+        self.quality_args_for(support::image_encoder(&output_ext(&self.output)).unwrap_or_default())
+    }
+
+    // The same for a given encoder (AVIF can come from two).
+    pub fn quality_args_for(&self, encoder: &str) -> Vec<String> {
+        let q = self.quality.map(|q| q.clamp(1, 100));
+        let s = |v: &str| v.to_string();
+        match (encoder, q) {
+            // mjpeg's -q:v runs 2 (best) to 31 (worst)
+            ("mjpeg", Some(q)) => vec![s("-q:v"), (31 - (q * 29) / 100).to_string()],
+            // Lossless either way: any quality asks for the smallest file
+            ("png", Some(_)) => vec![s("-compression_level"), s("9"), s("-pred"), s("mixed")],
+            ("libwebp", Some(100)) => vec![s("-lossless"), s("1")],
+            ("libwebp", Some(q)) => vec![s("-quality"), q.to_string()],
+            // AVIF is one AV1 frame; CRF 63 (worst) down to 0
+            ("libaom-av1", q) => {
+                let mut args = vec![s("-c:v"), s("libaom-av1"), s("-still-picture"), s("1"), s("-pix_fmt"), s("yuv420p")];
+                if let Some(q) = q {
+                    args.extend([s("-crf"), (63 - (q * 63) / 100).to_string(), s("-b:v"), s("0")]);
+                }
+                args
+            }
+            ("libsvtav1", q) => {
+                let mut args = vec![s("-c:v"), s("libsvtav1"), s("-pix_fmt"), s("yuv420p")];
+                if let Some(q) = q {
+                    // svt's CRF starts at 1
+                    args.extend([s("-crf"), (63 - (q * 63) / 100).max(1).to_string()]);
+                }
+                args
+            }
+            _ => vec![],
+        }
+    }
+}
+
+impl AudioCompressRequest {
+    pub fn extension(&self) -> String {
+        output_ext(&self.output)
+    }
+
+    pub fn target(&self) -> Option<AudioTarget> {
+        AudioTarget::from_extension(&self.extension())
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut issues = Issues::default();
+        check_common(&mut issues, self.version, &self.input, &self.output);
+        self.annotations.check(&mut issues);
+        if !self.output.trim().is_empty() && self.target().is_none() {
+            issues.add("output", format!(".{} isn't a supported audio format (mp3, m4a, opus, ogg, flac, wav)", self.extension()));
+        }
+        check_process(&mut issues, &self.process);
+        if let Some(kbps) = self.bitrate_kbps.filter(|k| !AUDIO_BITRATE_RANGE.contains(k)) {
+            issues.add("bitrate_kbps", format!("{} kbps is outside {}-{}", kbps, AUDIO_BITRATE_RANGE.start(), AUDIO_BITRATE_RANGE.end()));
+        }
+        if let Some(level) = self.compression_level {
+            if self.target() != Some(AudioTarget::Flac) {
+                issues.add("compression_level", "compression_level only applies to .flac output");
+            } else if !FLAC_LEVEL_RANGE.contains(&level) {
+                issues.add("compression_level", format!("{} is outside {}-{}", level, FLAC_LEVEL_RANGE.start(), FLAC_LEVEL_RANGE.end()));
+            }
+        }
+        issues.finish()
+    }
+}
+
+impl PipRequest {
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut issues = Issues::default();
+        check_common(&mut issues, self.version, &self.input, &self.output);
+        self.annotations.check(&mut issues);
+        if self.overlay.trim().is_empty() {
+            issues.add("overlay", "No overlay file given");
+        } else if self.overlay == self.input {
+            issues.add("overlay", "The overlay and the main input are the same file");
+        }
+        let ext = output_ext(&self.output);
+        if !self.output.trim().is_empty() && !ext.is_empty() && !PIP_CONTAINERS.contains(&ext.as_str()) {
+            issues.add("output", format!(".{} isn't supported for picture-in-picture (mp4, mkv, mov, m4v)", ext));
+        }
+        let o = &self.options;
+        if !PIP_SCALE_RANGE.contains(&o.scale_pct) {
+            issues.add("scale_pct", format!("{}% is outside {}-{}", o.scale_pct, PIP_SCALE_RANGE.start(), PIP_SCALE_RANGE.end()));
+        }
+        if !o.overlay_offset_secs.is_finite() {
+            issues.add("overlay_offset_secs", "The offset must be a number of seconds");
+        }
+        for (field, volume) in [("main_volume", o.main_volume), ("overlay_volume", o.overlay_volume)] {
+            if !volume.is_finite() || !(0.0..=PIP_MAX_VOLUME).contains(&volume) {
+                issues.add(field, format!("The level must be between 0 and {}", PIP_MAX_VOLUME));
+            }
+        }
+        issues.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn video(value: serde_json::Value) -> VideoCompressRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn image(value: serde_json::Value) -> ImageCompressRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn fields(result: Result<(), ValidationErrors>) -> Vec<&'static str> {
+        result.err().map(|e| e.0.into_iter().map(|i| i.field).collect()).unwrap_or_default()
+    }
+
+    #[test]
+    fn every_bad_field_is_reported_at_once() {
+        let request = video(json!({"version": 2, "input": " ", "output": "out", "crf": 30, "quality": "high"}));
+        assert_eq!(fields(request.validate()), ["version", "input", "output", "crf"]);
+        let err = video(json!({"input": "in.mov", "output": ""})).validate().unwrap_err();
+        assert_eq!(err.to_string(), "Invalid request: output: No output file given");
+    }
+
+    #[test]
+    fn requests_without_a_version_are_version_1() {
+        let request = video(json!({"input": "in.mov", "output": "out.mp4"}));
+        assert_eq!(request.version, 1);
+        assert_eq!(request.validate(), Ok(()));
+        assert_eq!(fields(video(json!({"version": 0, "input": "in.mov", "output": "out.mp4"})).validate()), ["version"]);
+    }
+
+    #[test]
+    fn video_copy_refuses_options_that_need_a_reencode() {
+        let request = video(json!({"input": "in.mov", "output": "out.mkv", "video_mode": "copy", "deinterlace": true, "crf": 23, "resumable": true}));
+        let messages: Vec<String> = request.validate().unwrap_err().0.into_iter().filter(|i| i.field == "video_mode").map(|i| i.message).collect();
+        assert_eq!(messages.len(), 3);
+        assert!(messages[0].contains("deinterlace"));
+        let ok = video(json!({"input": "in.mov", "output": "out.mkv", "video_mode": "copy"}));
+        assert_eq!(ok.validate(), Ok(()));
+    }
+
+    #[test]
+    fn old_string_dimensions_still_load() {
+        let sized = |w: serde_json::Value| image(json!({"input": "a.png", "output": "b.png", "width": w})).width;
+        assert_eq!(sized(json!(640)), Some(640));
+        assert_eq!(sized(json!("640")), Some(640));
+        assert_eq!(sized(json!(" 0 ")), None);
+        assert_eq!(sized(json!("")), None);
+        assert_eq!(sized(json!(0)), None);
+        assert_eq!(sized(serde_json::Value::Null), None);
+        let bad = serde_json::from_value::<ImageCompressRequest>(json!({"input": "a.png", "output": "b.png", "width": "wide"}));
+        assert!(bad.unwrap_err().to_string().contains("\"wide\" is not a size in pixels"));
+    }
+
+    #[test]
+    fn image_requests_check_sizes_and_quality() {
+        let request = image(json!({"input": "a.png", "output": "b.jpg", "width": 20000, "quality": 0}));
+        assert_eq!(fields(request.validate()), ["width", "quality"]);
+        assert_eq!(image(json!({"input": "a.png", "output": "b.jpg", "height": 480})).scale_filter(), Some("scale=-1:480".to_string()));
+        assert_eq!(image(json!({"input": "a.png", "output": "b.jpg"})).scale_filter(), None);
+    }
+
+    #[test]
+    fn image_quality_maps_onto_each_encoder() {
+        let at = |quality: u32, output: &str| image(json!({"input": "a.png", "output": output, "quality": quality}));
+        assert_eq!(at(100, "b.jpg").quality_args_for("mjpeg"), ["-q:v", "2"]);
+        assert_eq!(at(1, "b.jpg").quality_args_for("mjpeg"), ["-q:v", "31"]);
+        assert_eq!(at(100, "b.webp").quality_args_for("libwebp"), ["-lossless", "1"]);
+        assert_eq!(at(80, "b.webp").quality_args_for("libwebp"), ["-quality", "80"]);
+        assert_eq!(at(100, "b.avif").quality_args_for("libsvtav1")[4..], ["-crf", "1"]);
+        assert_eq!(at(50, "b.avif").quality_args_for("libaom-av1")[6..], ["-crf", "32", "-b:v", "0"]);
+        assert!(image(json!({"input": "a.png", "output": "b.jpg"})).quality_args_for("mjpeg").is_empty());
+    }
+}
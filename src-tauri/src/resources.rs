@@ -0,0 +1,250 @@
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+
+pub const DEFAULT_MAX_MEMORY_MB: u64 = 4096;
+// Samples in a row over the limit before the job is killed; one spike is allowed
+const STRIKES: u32 = 2;
+pub const MAX_THREADS: u32 = 256;
+// nice values for Low / Background on Unix
+const LOW_NICE: i32 = 10;
+const BACKGROUND_NICE: i32 = 19;
+
+// ==========================================
+// PER-JOB MEMORY GUARD
+// ==========================================
+// Tracks the resident size of one ffmpeg child. Some malformed inputs make
+// the demuxer allocate until the machine swaps itself to death, long before
+// the OOM killer steps in; this catches it a couple of seconds in.
+pub struct MemoryGuard {
+    system: System,
+    pid: Pid,
+    limit_bytes: u64,
+    strikes: u32,
+    pub peak_bytes: u64,
+}
+
+impl MemoryGuard {
+    pub fn new(pid: u32, limit_mb: u64) -> Self {
+        MemoryGuard {
+            system: System::new(),
+            pid: Pid::from_u32(pid),
+            limit_bytes: limit_mb * 1024 * 1024,
+            strikes: 0,
+            peak_bytes: 0,
+        }
+    }
+
+    // True once the child has been over the limit for STRIKES samples in a row.
+    pub fn sample(&mut self) -> bool {
+        self.system.refresh_processes_specifics(
+            ProcessesToUpdate::Some(&[self.pid]),
+            true,
+            ProcessRefreshKind::nothing().with_memory(),
+        );
+        let Some(rss) = self.system.process(self.pid).map(|p| p.memory()) else { return false };
+        self.peak_bytes = self.peak_bytes.max(rss);
+        if rss > self.limit_bytes {
+            self.strikes += 1;
+        } else {
+            self.strikes = 0;
+        }
+        self.strikes >= STRIKES
+    }
+
+    pub fn peak_mb(&self) -> u64 {
+        self.peak_bytes / (1024 * 1024)
+    }
+}
+
+// Stricter, kernel-enforced cap on Linux: the child limits its own address
+// space (RLIMIT_AS) between fork and exec, so it's in place before ffmpeg
+// allocates anything. Address space runs well ahead of RSS, so this uses
+// twice the RSS limit to avoid false failures. If the limit can't be set the
+// spawn fails rather than running unguarded. False where there's no such cap.
+#[cfg(target_os = "linux")]
+pub fn hard_limit(command: &mut std::process::Command, limit_mb: u64) -> bool {
+    use std::os::unix::process::CommandExt;
+
+    let bytes = limit_mb.saturating_mul(2 * 1024 * 1024);
+    // SAFETY: getrlimit / setrlimit are async-signal-safe and nothing is allocated
+    unsafe {
+        command.pre_exec(move || {
+            let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+            if libc::getrlimit(libc::RLIMIT_AS, &mut limit) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            // Only ever lowered, which needs no privileges
+            let cap = (bytes as libc::rlim_t).min(limit.rlim_max);
+            let limit = libc::rlimit { rlim_cur: cap, rlim_max: cap };
+            if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    true
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn hard_limit(_command: &mut std::process::Command, _limit_mb: u64) -> bool {
+    false
+}
+
+// ==========================================
+// THREADS AND PRIORITY
+// ==========================================
+// An encode takes every core by default, which makes the machine useless
+// for anything else while it runs. A job can cap ffmpeg's threads (`-threads`
+// for the decoder and encoder, `-filter_threads` for the filters) and/or
+// run it at a lower OS priority, set on each ffmpeg right after it's
+// spawned. The job runs inside `scope` with its options, so every ffmpeg it
+// starts (passes, analysis runs) gets them without threading them through
+// each encode helper, and report() tells the job's stats what took effect.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessPriority {
+    #[default]
+    Normal,
+    // nice 10 / BELOW_NORMAL_PRIORITY_CLASS
+    Low,
+    // nice 19 / IDLE_PRIORITY_CLASS: only what nothing else wants
+    Background,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct ProcessOptions {
+    // None leaves it to ffmpeg (one per core)
+    pub threads: Option<u32>,
+    pub priority: ProcessPriority,
+}
+
+// `process` of the job stats.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProcessReport {
+    pub threads: Option<u32>,
+    pub priority: ProcessPriority,
+    // Whether the priority could be set on every ffmpeg the job ran; None
+    // when there was nothing to set (Normal, or no ffmpeg ran)
+    pub priority_applied: Option<bool>,
+}
+
+struct JobProcess {
+    options: ProcessOptions,
+    applied: Mutex<Option<bool>>,
+}
+
+tokio::task_local! {
+    static PROCESS: Arc<JobProcess>;
+}
+
+pub async fn scope<F: Future>(options: ProcessOptions, f: F) -> F::Output {
+    PROCESS.scope(Arc::new(JobProcess { options, applied: Mutex::new(None) }), f).await
+}
+
+fn current() -> Option<Arc<JobProcess>> {
+    PROCESS.try_with(Arc::clone).ok()
+}
+
+pub fn report() -> ProcessReport {
+    let Some(process) = current() else { return ProcessReport::default() };
+    let applied = *process.applied.lock().unwrap();
+    ProcessReport { threads: process.options.threads, priority: process.options.priority, priority_applied: applied }
+}
+
+// `args` of an ffmpeg run with the job's thread cap: global -filter_threads,
+// -threads before the first input (its decoder) and again before the
+// output, which ffmpeg takes for the encoder. Runs without an input
+// (-version, -encoders) are left alone.
+pub fn with_threads(args: Vec<String>) -> Vec<String> {
+    let Some(threads) = current().and_then(|p| p.options.threads) else { return args };
+    if !args.iter().any(|a| a == "-i") {
+        return args;
+    }
+    let n = threads.to_string();
+    let mut out = vec!["-filter_threads".to_string(), n.clone(), "-threads".to_string(), n.clone()];
+    let mut rest = args;
+    let output = rest.pop().unwrap_or_default();
+    out.extend(rest);
+    out.extend(["-threads".to_string(), n, output]);
+    out
+}
+
+// Sets the job's priority on a freshly spawned ffmpeg.
+pub fn apply_priority(pid: u32) {
+    let Some(process) = current() else { return };
+    let priority = process.options.priority;
+    if priority == ProcessPriority::Normal {
+        return;
+    }
+    let ok = set_priority(pid, priority);
+    if !ok {
+        println!("⚠️ Couldn't lower the priority of ffmpeg (pid {})", pid);
+    }
+    let mut applied = process.applied.lock().unwrap();
+    *applied = Some(applied.unwrap_or(true) && ok);
+}
+
+#[cfg(unix)]
+fn set_priority(pid: u32, priority: ProcessPriority) -> bool {
+    let nice = match priority {
+        ProcessPriority::Normal => 0,
+        ProcessPriority::Low => LOW_NICE,
+        ProcessPriority::Background => BACKGROUND_NICE,
+    };
+    // SAFETY: setpriority only reads its arguments
+    unsafe { libc::setpriority(libc::PRIO_PROCESS, pid as libc::id_t, nice) == 0 }
+}
+
+#[cfg(windows)]
+fn set_priority(pid: u32, priority: ProcessPriority) -> bool {
+    use windows_sys::Win32::Foundation::CloseHandle;
+    use windows_sys::Win32::System::Threading::{
+        OpenProcess, SetPriorityClass, BELOW_NORMAL_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS, PROCESS_SET_INFORMATION,
+    };
+
+    let class = match priority {
+        ProcessPriority::Normal => NORMAL_PRIORITY_CLASS,
+        ProcessPriority::Low => BELOW_NORMAL_PRIORITY_CLASS,
+        ProcessPriority::Background => IDLE_PRIORITY_CLASS,
+    };
+    // SAFETY: plain Win32 calls on a handle opened and closed right here
+    unsafe {
+        let process = OpenProcess(PROCESS_SET_INFORMATION, 0, pid);
+        if process.is_null() {
+            return false;
+        }
+        let ok = SetPriorityClass(process, class) != 0;
+        CloseHandle(process);
+        ok
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn set_priority(_pid: u32, _priority: ProcessPriority) -> bool {
+    false
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    fn ulimit_kb(limit_mb: Option<u64>) -> String {
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "ulimit -v"]);
+        if let Some(mb) = limit_mb {
+            assert!(hard_limit(&mut command, mb));
+        }
+        String::from_utf8(command.output().unwrap().stdout).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn hard_limit_caps_the_child_before_exec() {
+        // Twice the RSS limit, in KiB
+        assert_eq!(ulimit_kb(Some(512)), "1048576");
+        // ...and only the child
+        assert_ne!(ulimit_kb(None), "1048576");
+    }
+}
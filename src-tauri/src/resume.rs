@@ -0,0 +1,248 @@
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::events::Event;
+use crate::ffmpeg;
+use crate::outputs;
+use crate::paths;
+use crate::probe;
+use crate::progress::ProgressTracker;
+
+// Length of each intermediate part. Keyframes are forced on these boundaries
+// so every part starts clean and the resume point is exact.
+pub const SEGMENT_SECS: u32 = 60;
+
+// A part left over from an earlier run; `duration` is None when it can't be read.
+pub struct SegmentCheck {
+    pub path: PathBuf,
+    pub duration: Option<f64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ResumePlan {
+    // Number of leading parts that are kept as-is
+    pub keep: usize,
+    // Source timestamp to continue encoding from
+    pub start_secs: f64,
+    pub discard: Vec<PathBuf>,
+}
+
+// ==========================================
+// RESUME POINT
+// ==========================================
+// Keeps the longest prefix of readable parts. The final part is only kept when
+// it's a full SEGMENT_SECS long: anything shorter was being written when the
+// previous run died and gets re-encoded.
+pub fn resume_plan(segments: &[SegmentCheck]) -> ResumePlan {
+    let full = SEGMENT_SECS as f64 - 0.5;
+    let mut keep = 0;
+    let mut start_secs = 0.0;
+
+    for (i, seg) in segments.iter().enumerate() {
+        let is_last = i + 1 == segments.len();
+        match seg.duration {
+            Some(d) if d > 0.0 && (!is_last || d >= full) => {
+                keep += 1;
+                start_secs += d;
+            }
+            _ => break,
+        }
+    }
+
+    ResumePlan {
+        keep,
+        start_secs,
+        discard: segments[keep..].iter().map(|s| s.path.clone()).collect(),
+    }
+}
+
+// Resolved path, folded to lowercase on case-insensitive volumes, so
+// `Clip.mp4` and `clip.mp4` get the same work dir there.
+fn path_key(path: &str) -> String {
+    let resolved = paths::resolve(Path::new(path)).unwrap_or_else(|| PathBuf::from(path));
+    let key = resolved.to_string_lossy().to_string();
+    if paths::is_case_insensitive(&resolved) { key.to_lowercase() } else { key }
+}
+
+// Same input+output always maps to the same work dir, so a restarted job finds its parts.
+fn work_dir(app: &AppHandle, input: &str, output: &str) -> Result<PathBuf, String> {
+    let mut hasher = Sha256::new();
+    hasher.update(path_key(input).as_bytes());
+    hasher.update([0u8]);
+    hasher.update(path_key(output).as_bytes());
+    let key = format!("{:x}", hasher.finalize());
+    let base = match outputs::configured_work_dir(app) {
+        Some(dir) => dir,
+        None => app.path().app_cache_dir().map_err(|e| e.to_string())?,
+    };
+    Ok(base.join("jobs").join(&key[..16]))
+}
+
+fn list_segments(dir: &Path) -> Vec<PathBuf> {
+    let mut parts: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|rd| {
+            rd.filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| {
+                    let name = p.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                    name.starts_with("part_") && name.ends_with(".mkv")
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    // Zero-padded names, so lexical order is encode order
+    parts.sort();
+    parts
+}
+
+// Drops the parts of an unfinished encode, so a re-run with other
+// encoder settings starts over instead of appending to them.
+pub fn discard_parts(app: &AppHandle, input: &str, output: &str) {
+    if let Ok(dir) = work_dir(app, input, output) {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+// concat demuxer list; single quotes inside paths are escaped the ffmpeg way
+fn write_concat_list(dir: &Path, parts: &[PathBuf]) -> Result<PathBuf, String> {
+    let list: String = parts
+        .iter()
+        .map(|p| format!("file '{}'\n", p.to_string_lossy().replace('\'', "'\\''")))
+        .collect();
+    let path = dir.join("parts.txt");
+    fs::write(&path, list).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+// ==========================================
+// HELPER: SEGMENTED (RESUMABLE) ENCODE
+// ==========================================
+// `codec_args` builds the usual encoder/audio/mapping args that go between the
+// input and the output, given the source time the encode starts at (filters
+// like burned-in timecode need it). Parts are written as Matroska (survives
+// truncation best) and stream-copied into the real output at the end.
+// `input_args` go right before `-i` (read pacing).
+pub async fn encode_segmented(
+    app: &AppHandle,
+    input: &str,
+    output: &str,
+    input_args: &[String],
+    codec_args: impl Fn(f64) -> Vec<String>,
+    tracker: ProgressTracker,
+) -> Result<ProgressTracker, String> {
+    let total_secs = tracker.total_secs();
+    let dir = work_dir(app, input, output)?;
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+
+    let mut checks = vec![];
+    for path in list_segments(&dir) {
+        let duration = probe::probe(app, &path.to_string_lossy()).await.ok().and_then(|m| m.duration);
+        checks.push(SegmentCheck { path, duration });
+    }
+    let plan = resume_plan(&checks);
+    for path in &plan.discard {
+        let _ = fs::remove_file(path);
+    }
+    if plan.keep > 0 {
+        println!("⏩ Resuming at {:.1}s ({} parts already done)", plan.start_secs, plan.keep);
+    }
+
+    let remaining = total_secs.map(|t| (t - plan.start_secs).max(0.0));
+    let mut tracker = if plan.start_secs > 0.0 {
+        tracker.clone().reset_duration(remaining)
+    } else {
+        tracker
+    };
+    if remaining.is_none_or(|r| r > 0.05) {
+        let mut args: Vec<String> = vec![];
+        if plan.start_secs > 0.0 {
+            args.push("-ss".to_string());
+            args.push(format!("{:.3}", plan.start_secs));
+        }
+        args.extend(input_args.iter().cloned());
+        args.push("-i".to_string());
+        args.push(input.to_string());
+        args.extend(codec_args(plan.start_secs));
+        args.extend([
+            "-force_key_frames".to_string(), format!("expr:gte(t,n_forced*{})", SEGMENT_SECS),
+            "-f".to_string(), "segment".to_string(),
+            "-segment_time".to_string(), SEGMENT_SECS.to_string(),
+            "-segment_format".to_string(), "matroska".to_string(),
+            "-segment_start_number".to_string(), plan.keep.to_string(),
+            "-reset_timestamps".to_string(), "1".to_string(),
+            "-y".to_string(), dir.join("part_%05d.mkv").to_string_lossy().to_string(),
+        ]);
+        tracker = ffmpeg::run_with_progress(app, args, tracker, Event::CompressionProgress).await?;
+    }
+
+    let parts = list_segments(&dir);
+    if parts.is_empty() {
+        return Err("Segmented encode produced no output".to_string());
+    }
+    let list = write_concat_list(&dir, &parts)?;
+    ffmpeg::run_quiet(app, vec![
+        "-f".to_string(), "concat".to_string(),
+        "-safe".to_string(), "0".to_string(),
+        "-i".to_string(), list.to_string_lossy().to_string(),
+        "-map".to_string(), "0".to_string(),
+        "-c".to_string(), "copy".to_string(),
+        "-y".to_string(), output.to_string(),
+    ])
+    .await?;
+
+    let _ = fs::remove_dir_all(&dir);
+    Ok(tracker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::TempDir;
+
+    fn parts(durations: &[Option<f64>]) -> Vec<SegmentCheck> {
+        durations.iter().enumerate().map(|(i, &duration)| SegmentCheck { path: PathBuf::from(format!("part_{:05}.mkv", i)), duration }).collect()
+    }
+
+    #[test]
+    fn nothing_to_resume_starts_at_zero() {
+        assert_eq!(resume_plan(&[]), ResumePlan { keep: 0, start_secs: 0.0, discard: vec![] });
+    }
+
+    #[test]
+    fn full_parts_are_kept_and_a_short_last_one_is_redone() {
+        let plan = resume_plan(&parts(&[Some(60.0), Some(60.02), Some(31.0)]));
+        assert_eq!(plan.keep, 2);
+        assert!((plan.start_secs - 120.02).abs() < 1e-9);
+        assert_eq!(plan.discard, [PathBuf::from("part_00002.mkv")]);
+        // A full-length last part was finished
+        assert_eq!(resume_plan(&parts(&[Some(60.0), Some(59.8)])).keep, 2);
+    }
+
+    #[test]
+    fn an_unreadable_part_drops_everything_after_it() {
+        let plan = resume_plan(&parts(&[Some(60.0), None, Some(60.0), Some(60.0)]));
+        assert_eq!((plan.keep, plan.start_secs), (1, 60.0));
+        assert_eq!(plan.discard.len(), 3);
+        assert_eq!(resume_plan(&parts(&[Some(0.0), Some(60.0)])).keep, 0);
+    }
+
+    #[test]
+    fn only_parts_are_listed_in_encode_order() {
+        let dir = TempDir::new(std::env::temp_dir().join(format!("resume-test-{}-list", std::process::id()))).unwrap();
+        for name in ["part_00010.mkv", "part_00002.mkv", "parts.txt", "part_00001.mkv.tmp", "notes.mkv"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        let names: Vec<String> = list_segments(dir.path()).iter().map(|p| p.file_name().unwrap().to_string_lossy().to_string()).collect();
+        assert_eq!(names, ["part_00002.mkv", "part_00010.mkv"]);
+    }
+
+    #[test]
+    fn concat_lists_escape_single_quotes() {
+        let dir = TempDir::new(std::env::temp_dir().join(format!("resume-test-{}-concat", std::process::id()))).unwrap();
+        let list = write_concat_list(dir.path(), &[PathBuf::from("/w/part_00000.mkv"), PathBuf::from("/w/it's/part_00001.mkv")]).unwrap();
+        assert_eq!(fs::read_to_string(list).unwrap(), "file '/w/part_00000.mkv'\nfile '/w/it'\\''s/part_00001.mkv'\n");
+    }
+}
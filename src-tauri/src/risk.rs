@@ -0,0 +1,233 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Instant;
+use tauri::State;
+
+use crate::flood::FloodMeter;
+use crate::salvage::DamageMap;
+use crate::settings::SettingsStore;
+
+// ==========================================
+// STDERR WARNINGS THAT PREDICT BAD OUTPUT
+// ==========================================
+// ffmpeg exits 0 on plenty of files that come out stuttering or drifting out
+// of sync, but it usually complains on the way. These are the complaints
+// that mean something; everything else on stderr is noise.
+pub struct WarningPattern {
+    pub id: &'static str,
+    // Any of these substrings (matched case-insensitively) counts as a hit
+    pub needles: &'static [&'static str],
+    // This many hits and the job is high risk; below that it's only "low"
+    pub high_after: u32,
+    pub explanation: &'static str,
+}
+
+pub const WARNING_PATTERNS: &[WarningPattern] = &[
+    WarningPattern {
+        id: "non_monotonous_dts",
+        needles: &["non-monotonous dts", "non monotonically increasing dts"],
+        high_after: 5,
+        explanation: "Timestamps in the source jump backwards. Playback of the output may stutter or lose lip sync.",
+    },
+    WarningPattern {
+        id: "past_duration_too_large",
+        needles: &["past duration"],
+        high_after: 50,
+        explanation: "Frames arrive later than their timestamps promise, typical of variable frame rate recordings. Expect duplicated or dropped frames.",
+    },
+    WarningPattern {
+        id: "queue_backward_in_time",
+        needles: &["queue input is backward in time"],
+        high_after: 1,
+        explanation: "Audio packets arrived out of order while resampling. The audio may glitch or drift from the picture.",
+    },
+    WarningPattern {
+        id: "deprecated_pixel_format",
+        needles: &["deprecated pixel format used"],
+        high_after: u32::MAX,
+        explanation: "The source uses an old full-range pixel format. Colours can look slightly washed out or too contrasty.",
+    },
+    WarningPattern {
+        id: "decode_errors",
+        needles: &["error while decoding", "corrupt decoded frame", "concealing"],
+        high_after: 3,
+        explanation: "Parts of the source are damaged. The output will show blocky or frozen frames where they were.",
+    },
+];
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskLevel {
+    #[default]
+    None,
+    Low,
+    High,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PatternHit {
+    pub id: &'static str,
+    pub count: u32,
+    pub explanation: &'static str,
+}
+
+// Decode errors of a full verification that a risky encode triggered
+// (see verify.rs).
+#[derive(Serialize, Clone, Debug)]
+pub struct DeepVerify {
+    pub errors: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_error: Option<String>,
+}
+
+// `quality_risk` of a job result.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct QualityRisk {
+    pub level: RiskLevel,
+    pub patterns: Vec<PatternHit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deep_verify: Option<DeepVerify>,
+}
+
+// Fed every stderr line of a run.
+#[derive(Clone, Debug, Default)]
+pub struct StderrWarnings {
+    counts: BTreeMap<&'static str, u32>,
+    // Rate of decode errors, for flood::FLOOD_ERRORS_PER_SEC
+    decode_errors: FloodMeter,
+    // Salvage jobs: their own flood rate, always checked (0 = never flooded)
+    salvage_rate: Option<u32>,
+    // Media time of every decode error
+    pub damage: DamageMap,
+}
+
+// Pattern a single stderr line matches, if any.
+pub fn classify_line(line: &str) -> Option<&'static WarningPattern> {
+    let lower = line.to_lowercase();
+    WARNING_PATTERNS.iter().find(|p| p.needles.iter().any(|n| lower.contains(n)))
+}
+
+impl StderrWarnings {
+    pub fn for_salvage(errors_per_sec: u32) -> Self {
+        StderrWarnings { decode_errors: FloodMeter::with_threshold(errors_per_sec), salvage_rate: Some(errors_per_sec), ..Default::default() }
+    }
+
+    // `at` is how far into the output the encode was
+    pub fn observe(&mut self, line: &str, at: f64) {
+        // One chunk from the sidecar can hold several lines
+        for l in line.lines() {
+            if let Some(p) = classify_line(l) {
+                *self.counts.entry(p.id).or_default() += 1;
+                if p.id == "decode_errors" {
+                    self.decode_errors.record(Instant::now());
+                    self.damage.mark(at);
+                }
+            }
+        }
+    }
+
+    // An error per packet, for long enough to give up on the input.
+    // `abort_on_flood` is the setting; salvage jobs go by their own rate.
+    pub fn give_up(&self, abort_on_flood: bool) -> bool {
+        match self.salvage_rate {
+            Some(0) => false,
+            Some(_) => self.decode_errors.flooded(),
+            None => abort_on_flood && self.decode_errors.flooded(),
+        }
+    }
+
+    pub fn flood_threshold(&self) -> u32 {
+        self.decode_errors.threshold()
+    }
+
+    pub fn summary(&self) -> QualityRisk {
+        let mut level = RiskLevel::None;
+        let mut patterns = vec![];
+        for p in WARNING_PATTERNS {
+            let Some(&count) = self.counts.get(p.id) else { continue };
+            let this = if count >= p.high_after { RiskLevel::High } else { RiskLevel::Low };
+            if this > level {
+                level = this;
+            }
+            patterns.push(PatternHit { id: p.id, count, explanation: p.explanation });
+        }
+        QualityRisk { level, patterns, deep_verify: None }
+    }
+}
+
+// ==========================================
+// COMMAND: AUTO DEEP VERIFY
+// ==========================================
+// Risky encodes get full verification whatever the configured tier.
+#[tauri::command]
+pub fn set_deep_verify_on_risk(store: State<'_, SettingsStore>, enabled: bool) -> Result<(), String> {
+    store.update(|s| s.deep_verify_on_risk = enabled).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary_of(lines: &[&str]) -> QualityRisk {
+        let mut warnings = StderrWarnings::default();
+        for (i, line) in lines.iter().enumerate() {
+            warnings.observe(line, i as f64);
+        }
+        warnings.summary()
+    }
+
+    fn counts(risk: &QualityRisk) -> Vec<(&'static str, u32)> {
+        risk.patterns.iter().map(|p| (p.id, p.count)).collect()
+    }
+
+    #[test]
+    fn lines_are_classified_case_insensitively() {
+        let line = "[mp4 @ 0x5581] Application provided invalid, Non-Monotonous DTS to muxer in stream 0";
+        assert_eq!(classify_line(line).map(|p| p.id), Some("non_monotonous_dts"));
+        assert_eq!(classify_line("[h264 @ 0x1] concealing 1200 DC, 1200 AC, 1200 MV errors in P frame").map(|p| p.id), Some("decode_errors"));
+        assert!(classify_line("frame=  100 fps= 50 q=28.0 size=    1024kB time=00:00:04.00").is_none());
+    }
+
+    #[test]
+    fn a_clean_run_has_no_risk() {
+        let risk = summary_of(&["Input #0, mov,mp4", "frame=1 fps=1"]);
+        assert_eq!(risk.level, RiskLevel::None);
+        assert!(risk.patterns.is_empty());
+    }
+
+    #[test]
+    fn a_few_hits_are_low_and_enough_are_high() {
+        let dts = "Non-monotonous DTS in output stream 0:1";
+        let risk = summary_of(&[dts, dts]);
+        assert_eq!(risk.level, RiskLevel::Low);
+        assert_eq!(counts(&risk), [("non_monotonous_dts", 2)]);
+
+        let risk = summary_of(&[dts, "Queue input is backward in time", dts]);
+        assert_eq!(risk.level, RiskLevel::High);
+        assert_eq!(counts(&risk), [("non_monotonous_dts", 2), ("queue_backward_in_time", 1)]);
+    }
+
+    #[test]
+    fn deprecated_pixel_formats_are_never_high() {
+        let line = "[swscaler @ 0x1] deprecated pixel format used, make sure you did set range correctly";
+        assert_eq!(summary_of(&[line; 100]).level, RiskLevel::Low);
+    }
+
+    #[test]
+    fn one_chunk_can_hold_several_lines() {
+        let mut warnings = StderrWarnings::default();
+        warnings.observe("error while decoding MB 1 2\nerror while decoding MB 3 4\nframe=10", 5.0);
+        assert_eq!(counts(&warnings.summary()), [("decode_errors", 2)]);
+    }
+
+    #[test]
+    fn salvage_with_a_zero_rate_never_gives_up() {
+        let mut warnings = StderrWarnings::for_salvage(0);
+        for _ in 0..10_000 {
+            warnings.observe("corrupt decoded frame in stream 0", 1.0);
+        }
+        assert!(!warnings.give_up(true));
+        // Without the setting, a normal job doesn't give up either
+        assert!(!StderrWarnings::default().give_up(false));
+    }
+}
@@ -0,0 +1,343 @@
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::AppHandle;
+use crate::audio::{self, AudioTarget};
+use crate::native_image::ImageBackend;
+use crate::probe::{self, MediaInfo};
+use crate::request::{self, AudioCompressRequest, ImageCompressRequest, VideoCompressRequest, VideoOptions};
+use crate::stats::JobStats;
+use crate::support::{self, MediaKind};
+use crate::timeline;
+use crate::{explain, ImageJobResult, VideoJobResult};
+
+// Inputs that are pictures even when ffmpeg sees a video stream in them
+const IMAGE_INPUTS: &[&str] = &["jpg", "jpeg", "png", "webp", "avif", "bmp", "tif", "tiff", "gif", "apng"];
+
+// ==========================================
+// PIPELINE ROUTING
+// ==========================================
+// A frontend that sends a .png -> .jpg job to compress_video, or a .gif ->
+// .mp4 one to compress_image, gets the pipeline that fits instead of one
+// that half-works: each job's command is checked against what the input
+// turned out to be (probed) and what the output's extension asks for, and
+// handed over when there's a sensible other pipeline. The job keeps its
+// id, the timeline notes the hand-over, and the result still has the
+// command's own shape. A combination no pipeline makes sense of (a moving
+// video to a .jpg, a still to an .mp4) fails with what to do instead.
+//
+// Only jobs whose output extension isn't the command's own kind are
+// probed here, so correctly routed ones cost nothing extra. Audio jobs
+// need no routing: everything they accept already has audio checked.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pipeline {
+    Video,
+    Image,
+    Audio,
+}
+
+impl Pipeline {
+    fn name(self) -> &'static str {
+        match self {
+            Pipeline::Video => "video",
+            Pipeline::Image => "image",
+            Pipeline::Audio => "audio",
+        }
+    }
+}
+
+// What the probe says the input is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputKind {
+    // One picture (a one-frame video counts)
+    Still,
+    // A moving picture format: gif, animated webp / png
+    Animated,
+    Video,
+    Audio,
+    // Nothing ffmpeg can decode
+    Empty,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Route {
+    Stay,
+    To(Pipeline),
+    Refuse(String),
+}
+
+// More than one frame, when the probe can tell (stills often come back
+// without a frame count or duration)
+fn moving(media: &MediaInfo) -> Option<bool> {
+    match (media.frames, media.duration, media.fps) {
+        (Some(n), _, _) => Some(n > 1),
+        (None, Some(d), Some(fps)) => Some(d * fps >= 1.5),
+        _ => None,
+    }
+}
+
+// Pure
+pub fn input_kind(ext: &str, media: &MediaInfo) -> InputKind {
+    match (media.has_video, media.has_audio) {
+        (false, true) => InputKind::Audio,
+        (false, false) => InputKind::Empty,
+        _ if media.is_single_frame() => InputKind::Still,
+        _ if IMAGE_INPUTS.contains(&ext) => match moving(media) {
+            Some(true) => InputKind::Animated,
+            Some(false) => InputKind::Still,
+            None if ext == "gif" => InputKind::Animated,
+            None => InputKind::Still,
+        },
+        _ => InputKind::Video,
+    }
+}
+
+// Pure: what the output extension asks for; None for one no pipeline writes.
+pub fn output_kind(ext: &str) -> Option<Pipeline> {
+    if AudioTarget::from_extension(ext).is_some() {
+        return Some(Pipeline::Audio);
+    }
+    support::container(ext).map(|c| match c.kind {
+        MediaKind::Video => Pipeline::Video,
+        MediaKind::Image => Pipeline::Image,
+        MediaKind::Audio => Pipeline::Audio,
+    })
+}
+
+// Pure: the delegation table. `ext` is the output's, for the messages.
+pub fn decide(command: Pipeline, input: InputKind, has_audio: bool, output: Option<Pipeline>, ext: &str) -> Route {
+    match (command, output, input) {
+        (_, _, InputKind::Empty) => Route::Stay,
+        (Pipeline::Video, Some(Pipeline::Image), InputKind::Still) => Route::To(Pipeline::Image),
+        (Pipeline::Video, Some(Pipeline::Image), _) => Route::Refuse(format!(
+            "The input is a moving video, so it can't be compressed into a .{} image; generate_thumbnail writes a still of it",
+            ext
+        )),
+        (Pipeline::Video, Some(Pipeline::Audio), _) if has_audio => Route::To(Pipeline::Audio),
+        (Pipeline::Video, Some(Pipeline::Audio), _) => Route::Refuse(format!("The input has no audio to write to .{}", ext)),
+        (Pipeline::Image, Some(Pipeline::Video), InputKind::Animated | InputKind::Video) => Route::To(Pipeline::Video),
+        (Pipeline::Image, Some(Pipeline::Video), _) => {
+            Route::Refuse(format!("A single picture can't be compressed into a .{} video; pick an image format", ext))
+        }
+        (Pipeline::Image, Some(Pipeline::Audio), _) => Route::Refuse(format!("Images can't be written as .{} audio", ext)),
+        (Pipeline::Image, _, InputKind::Audio) => Route::Refuse("The input is audio and has no picture to compress".to_string()),
+        _ => Route::Stay,
+    }
+}
+
+fn ext_of(path: &str) -> String {
+    Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default()
+}
+
+// Probes only when the output isn't the command's own kind.
+async fn route(app: &AppHandle, command: Pipeline, input: &str, output: &str) -> Route {
+    let ext = ext_of(output);
+    let output_kind = output_kind(&ext);
+    if output_kind.is_none_or(|k| k == command) {
+        return Route::Stay;
+    }
+    // A probe that fails leaves the job to fail (or not) where it is
+    let Ok(media) = probe::probe(app, input).await else { return Route::Stay };
+    let route = decide(command, input_kind(&ext_of(input), &media), media.has_audio, output_kind, &ext);
+    if let Route::To(to) = route {
+        println!("🔀 {} -> .{} goes through the {} pipeline, not the {} one", input, ext, to.name(), command.name());
+        timeline::record(app, timeline::REROUTED, &[("from", command.name().to_string()), ("to", to.name().to_string())]);
+    }
+    route
+}
+
+fn len(path: &str) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+// None = the video pipeline is the right one.
+pub async fn from_video(app: &AppHandle, request: &VideoCompressRequest) -> Option<Result<VideoJobResult, String>> {
+    let to = match route(app, Pipeline::Video, &request.input, &request.output).await {
+        Route::Stay => return None,
+        Route::Refuse(message) => return Some(Err(message)),
+        Route::To(to) => to,
+    };
+    let warning = format!("The .{} output was written by the {} pipeline instead of the video one", ext_of(&request.output), to.name());
+    let explanation = explain::Explanation::new(explain::PIPELINE_REROUTED, &[("to", to.name().to_string())]);
+    Some(match to {
+        Pipeline::Image => {
+            let image = ImageCompressRequest { create_dirs: request.create_dirs, ..crate::image_request_for(request, request.output.clone()) };
+            Box::pin(crate::run_image_job(app, image)).await.map(|r| VideoJobResult {
+                dry_run: r.dry_run,
+                ..crate::routed_video_result(r.input, r.output, r.encoder, r.stats, warning, explanation)
+            })
+        }
+        Pipeline::Audio if request.dry_run => Err(format!("A .{} output goes through the audio pipeline, which dry runs don't cover yet", ext_of(&request.output))),
+        Pipeline::Audio => {
+            let started = Instant::now();
+            let audio = AudioCompressRequest {
+                version: request::REQUEST_VERSION,
+                input: request.input.clone(),
+                output: request.output.clone(),
+                create_dirs: request.create_dirs,
+                bitrate_kbps: None,
+                quality: None,
+                compression_level: None,
+                keep_cover: true,
+                process: request.options.process,
+                annotations: request.annotations.clone(),
+            };
+            Box::pin(audio::run_audio_job(app, audio)).await.map(|r| {
+                let stats = JobStats::new(len(&r.input), len(&r.output), started.elapsed(), false);
+                let mut result = crate::routed_video_result(r.input, r.output, r.codec, stats, warning, explanation);
+                result.warnings.extend(r.warnings);
+                result
+            })
+        }
+        Pipeline::Video => return None,
+    })
+}
+
+// None = the image pipeline is the right one.
+pub async fn from_image(app: &AppHandle, request: &ImageCompressRequest) -> Option<Result<ImageJobResult, String>> {
+    match route(app, Pipeline::Image, &request.input, &request.output).await {
+        Route::Stay | Route::To(Pipeline::Image | Pipeline::Audio) => None,
+        Route::Refuse(message) => Some(Err(message)),
+        Route::To(Pipeline::Video) => {
+            let options = VideoOptions {
+                skip_if_larger: request.skip_if_larger,
+                metadata: request.metadata,
+                process: request.process,
+                ..VideoOptions::default()
+            };
+            let video = VideoCompressRequest {
+                create_dirs: request.create_dirs,
+                dry_run: request.dry_run,
+                annotations: request.annotations.clone(),
+                ..VideoCompressRequest::new(request.input.clone(), request.output.clone(), options)
+            };
+            Some(Box::pin(crate::run_video_job(app, video)).await.map(|r| ImageJobResult {
+                input: r.input,
+                output: r.output,
+                backend: ImageBackend::Ffmpeg,
+                encoder: r.encoder,
+                stats: r.stats,
+                dry_run: r.dry_run,
+                job_id: None,
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{run, run_video, Harness, ENCODE};
+    use crate::probe::tests::info;
+
+    const PNG: &str = r#"{ "streams": [{ "index": 0, "codec_type": "video", "codec_name": "png", "width": 64, "height": 48 }], "format": { "format_name": "png_pipe" } }"#;
+    const GIF: &str = r#"{ "streams": [{ "index": 0, "codec_type": "video", "codec_name": "gif", "width": 320, "height": 240, "nb_frames": "40", "avg_frame_rate": "10/1" }], "format": { "duration": "4.000000", "format_name": "gif" } }"#;
+    const MP3: &str = r#"{ "streams": [{ "index": 0, "codec_type": "audio", "codec_name": "mp3", "channels": 2, "sample_rate": "44100" }], "format": { "duration": "10.000000", "format_name": "mp3" } }"#;
+    const MP4: &str = r#"{ "streams": [{ "index": 0, "codec_type": "video", "codec_name": "h264", "width": 1280, "height": 720, "avg_frame_rate": "30/1" }, { "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "48000" }], "format": { "duration": "10.000000" } }"#;
+
+    #[test]
+    fn the_probe_and_extension_say_what_the_input_is() {
+        assert_eq!(input_kind("png", &info(PNG)), InputKind::Still);
+        assert_eq!(input_kind("gif", &info(GIF)), InputKind::Animated);
+        assert_eq!(input_kind("mp3", &info(MP3)), InputKind::Audio);
+        assert_eq!(input_kind("mp4", &info(MP4)), InputKind::Video);
+        assert_eq!(input_kind("mp4", &info(r#"{ "streams": [] }"#)), InputKind::Empty);
+        // A gif that won't say how many frames it has is taken as moving,
+        // any other picture format as a still
+        let uncounted = info(&GIF.replace(r#""nb_frames": "40", "#, "").replace(r#""duration": "4.000000", "#, ""));
+        assert_eq!(input_kind("gif", &uncounted), InputKind::Animated);
+        assert_eq!(input_kind("webp", &uncounted), InputKind::Still);
+        // One frame is a still whatever it's called
+        let one_frame = info(&MP4.replace(r#""avg_frame_rate": "30/1""#, r#""nb_frames": "1""#));
+        assert_eq!(input_kind("mp4", &one_frame), InputKind::Still);
+    }
+
+    #[test]
+    fn output_extensions_pick_a_pipeline() {
+        assert_eq!(output_kind("jpg"), Some(Pipeline::Image));
+        assert_eq!(output_kind("mp4"), Some(Pipeline::Video));
+        assert_eq!(output_kind("opus"), Some(Pipeline::Audio));
+        assert_eq!(output_kind("mp3"), Some(Pipeline::Audio));
+        assert_eq!(output_kind("docx"), None);
+    }
+
+    #[test]
+    fn the_delegation_table() {
+        use InputKind::*;
+        use Pipeline::{Audio as ToAudio, Image as ToImage, Video as ToVideo};
+        let refused = |route: Route, says: &str| matches!(route, Route::Refuse(m) if m.contains(says));
+
+        // png -> jpg through compress_video
+        assert_eq!(decide(ToVideo, Still, false, Some(ToImage), "jpg"), Route::To(ToImage));
+        assert!(refused(decide(ToVideo, Video, true, Some(ToImage), "jpg"), "generate_thumbnail"));
+        assert!(refused(decide(ToVideo, Animated, false, Some(ToImage), "jpg"), "can't be compressed into a .jpg image"));
+        // mp3 -> opus through compress_video, and a video's soundtrack
+        assert_eq!(decide(ToVideo, Audio, true, Some(ToAudio), "opus"), Route::To(ToAudio));
+        assert_eq!(decide(ToVideo, Video, true, Some(ToAudio), "m4a"), Route::To(ToAudio));
+        assert!(refused(decide(ToVideo, Video, false, Some(ToAudio), "opus"), "no audio to write to .opus"));
+        // gif -> mp4 through compress_image
+        assert_eq!(decide(ToImage, Animated, false, Some(ToVideo), "mp4"), Route::To(ToVideo));
+        assert_eq!(decide(ToImage, Video, true, Some(ToVideo), "mp4"), Route::To(ToVideo));
+        assert!(refused(decide(ToImage, Still, false, Some(ToVideo), "mp4"), "pick an image format"));
+        assert!(refused(decide(ToImage, Still, false, Some(ToAudio), "mp3"), "as .mp3 audio"));
+        assert!(refused(decide(ToImage, Audio, true, Some(ToImage), "png"), "has no picture"));
+        // Rightly routed, or nothing to go by
+        assert_eq!(decide(ToVideo, Video, true, Some(ToVideo), "mp4"), Route::Stay);
+        assert_eq!(decide(ToImage, Animated, false, Some(ToImage), "webp"), Route::Stay);
+        assert_eq!(decide(ToVideo, Still, false, None, "docx"), Route::Stay);
+        assert_eq!(decide(ToVideo, Empty, false, Some(ToImage), "jpg"), Route::Stay);
+    }
+
+    #[test]
+    fn a_png_sent_to_compress_video_goes_through_the_image_pipeline() {
+        let h = Harness::new("route-png", &format!(r#"{{ "ffprobe": {}, "runs": [{{ "output_bytes": 512 }}] }}"#, PNG));
+        let input = h.file("photo.png");
+        image::RgbImage::from_pixel(64, 48, image::Rgb([200, 100, 50])).save(&input).unwrap();
+        let request = VideoCompressRequest::new(input, h.file("photo.jpg"), VideoOptions::default());
+        let result = run_video(&h, request).unwrap();
+
+        assert_eq!(result.output, h.file("photo.jpg"));
+        assert_eq!(result.warnings, ["The .jpg output was written by the image pipeline instead of the video one"]);
+        assert_eq!(result.explanations[0].params["to"], "image");
+        assert!(!h.runs().iter().flatten().any(|a| a == "libx264"));
+    }
+
+    #[test]
+    fn a_gif_sent_to_compress_image_goes_through_the_video_pipeline() {
+        let h = Harness::new("route-gif", &format!(r#"{{ "ffprobe": {}, "runs": {} }}"#, GIF, ENCODE));
+        let request = crate::jobtests::image_request(h.input("anim.gif", 50_000), h.file("anim.mp4"));
+        let app = h.handle().clone();
+        let result = run(async move { crate::run_direct_image(&app, request).await }).unwrap();
+
+        assert_eq!(result.output, h.file("anim.mp4"));
+        assert_eq!(result.backend, ImageBackend::Ffmpeg);
+        assert_eq!(result.encoder, "libx264");
+        assert!(h.runs().iter().any(|r| r.iter().any(|a| a == "libx264")));
+    }
+
+    #[test]
+    fn an_mp3_sent_to_compress_video_goes_through_the_audio_pipeline() {
+        let h = Harness::new("route-mp3", &format!(r#"{{ "ffprobe": {}, "runs": [{{ "output_bytes": 2048 }}] }}"#, MP3));
+        let request = VideoCompressRequest::new(h.input("song.mp3", 50_000), h.file("song.opus"), VideoOptions::default());
+        let result = run_video(&h, request).unwrap();
+
+        assert_eq!(result.output, h.file("song.opus"));
+        assert_eq!(result.warnings[0], "The .opus output was written by the audio pipeline instead of the video one");
+        let encode = h.runs().into_iter().find(|r| r.last().is_some_and(|a| a.ends_with(".opus"))).unwrap();
+        assert!(encode.iter().any(|a| a == "libopus"), "{:?}", encode);
+        assert!(!encode.iter().any(|a| a == "libx264"));
+    }
+
+    #[test]
+    fn a_moving_video_to_a_jpg_is_refused_before_anything_runs() {
+        let h = Harness::new("route-refused", &format!(r#"{{ "ffprobe": {}, "runs": {} }}"#, MP4, ENCODE));
+        let request = VideoCompressRequest::new(h.input("clip.mp4", 50_000), h.file("clip.jpg"), VideoOptions::default());
+        let error = run_video(&h, request).err().expect("a video can't become a jpg");
+        assert!(error.to_string().contains("generate_thumbnail"), "{}", error);
+        // Only the probe ran
+        assert_eq!(h.runs().len(), 1);
+        assert_eq!(h.files(), ["clip.mp4"]);
+    }
+}
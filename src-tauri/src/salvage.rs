@@ -0,0 +1,197 @@
+use serde::Serialize;
+use tauri::State;
+
+use crate::ffmpeg;
+use crate::flood;
+use crate::queue::JobSpec;
+use crate::settings::SettingsStore;
+use crate::verify;
+use crate::AppHandle;
+
+// Decode errors a second a salvage encode puts up with before giving up (0 = never)
+pub const DEFAULT_SALVAGE_ERRORS_PER_SEC: u32 = 2000;
+// Errors closer together than this (in media seconds) are one damaged stretch
+const DAMAGE_GAP_SECS: f64 = 1.0;
+// ffmpeg errors that mean the input is damaged rather than the settings wrong
+const DAMAGE_PATTERNS: &[&str] = &["Invalid data found when processing input", "moov atom not found", "error while decoding"];
+
+// ==========================================
+// SALVAGE MODE
+// ==========================================
+// A recording off a dying SD card fails the normal path: the decode-flood
+// guard gives up on it, or the output comes out short and read_back calls
+// it incomplete. With `salvage` the decoder is told to skip what it can't
+// read (-err_detect ignore_err, -fflags +discardcorrupt+genpts), the flood
+// guard moves to salvage_errors_per_sec, and a short output is the expected
+// outcome rather than a failure. What comes out is reported as salvaged:
+// how much of the timeline made it, and where the decode errors were.
+//
+// If the encode itself dies partway (not cancelled), what it had written is
+// kept: it's remuxed into a clean file and the job goes on to report what
+// was recovered, with a warning saying why it's short.
+//
+// A failed queue job whose error says the input is damaged is marked
+// `salvage_offered`, so the UI can offer to run it again this way.
+
+pub fn input_args() -> Vec<String> {
+    ["-err_detect", "ignore_err", "-fflags", "+discardcorrupt+genpts"].map(String::from).to_vec()
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct DamagedRange {
+    pub start_secs: f64,
+    pub end_secs: f64,
+}
+
+// Where in the timeline the decode errors were, as the encode went.
+#[derive(Clone, Debug, Default)]
+pub struct DamageMap {
+    ranges: Vec<DamagedRange>,
+    errors: u32,
+}
+
+impl DamageMap {
+    // A decode error while the encode was at `secs`
+    pub fn mark(&mut self, secs: f64) {
+        self.errors += 1;
+        match self.ranges.last_mut() {
+            Some(last) if secs >= last.start_secs && secs - last.end_secs <= DAMAGE_GAP_SECS => last.end_secs = last.end_secs.max(secs),
+            _ => self.ranges.push(DamagedRange { start_secs: secs, end_secs: secs }),
+        }
+    }
+
+    pub fn ranges(&self) -> &[DamagedRange] {
+        &self.ranges
+    }
+
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+}
+
+// `salvage` of a job result.
+#[derive(Serialize, Clone, Debug)]
+pub struct SalvageReport {
+    // What the source (after cuts) should have given, and what came out
+    pub expected_secs: Option<f64>,
+    pub output_secs: Option<f64>,
+    pub recovered_pct: Option<f64>,
+    pub skipped_secs: Option<f64>,
+    pub decode_errors: u32,
+    pub damaged: Vec<DamagedRange>,
+}
+
+pub fn report(expected_secs: Option<f64>, output_secs: Option<f64>, damage: &DamageMap) -> SalvageReport {
+    let recovered = match (expected_secs, output_secs) {
+        (Some(expected), Some(output)) if expected > 0.0 => Some((output / expected * 100.0).min(100.0)),
+        _ => None,
+    };
+    SalvageReport {
+        expected_secs,
+        output_secs,
+        recovered_pct: recovered,
+        skipped_secs: expected_secs.zip(output_secs).map(|(e, o)| (e - o).max(0.0)),
+        decode_errors: damage.errors(),
+        damaged: damage.ranges().to_vec(),
+    }
+}
+
+pub fn warning(report: &SalvageReport) -> String {
+    match report.recovered_pct {
+        Some(pct) => format!("Salvaged: {:.1}% of the timeline was recovered, {} decode errors were skipped", pct, report.decode_errors),
+        None => format!("Salvaged: {} decode errors were skipped; how much was recovered couldn't be measured", report.decode_errors),
+    }
+}
+
+// The salvage encode into `staged` failed with `error`; remux what it wrote
+// so the container is finished. Err (with the encode's error) if nothing was
+// written or the remux couldn't read it either.
+pub async fn keep_partial(app: &AppHandle, staged: &str, error: &str) -> Result<String, String> {
+    let written = std::fs::metadata(staged).map(|m| m.len()).unwrap_or(0);
+    if written == 0 {
+        return Err(error.to_string());
+    }
+    let partial = format!("{}.aborted", staged);
+    std::fs::rename(staged, &partial).map_err(|e| format!("{} (and what it wrote couldn't be moved aside: {})", error, e))?;
+    let mut args = input_args();
+    args.extend(["-i", &partial, "-map", "0", "-c", "copy", "-y", staged].map(String::from));
+    let remuxed = ffmpeg::run_quiet(app, args).await;
+    let _ = std::fs::remove_file(&partial);
+    if let Err(e) = remuxed {
+        let _ = std::fs::remove_file(staged);
+        return Err(format!("{} (and what it wrote couldn't be remuxed: {})", error, e));
+    }
+    println!("🩹 Salvage encode stopped partway, kept the {} bytes it wrote", written);
+    let reason = error.lines().next().unwrap_or(error);
+    Ok(format!("Salvage: the encode stopped partway ({}); what it had written was remuxed and kept", reason))
+}
+
+// The normal path refused a damaged input, and a salvage run might get
+// something out of it.
+pub fn offered(spec: &JobSpec, error: &str) -> bool {
+    let JobSpec::Video(request) = spec else { return false };
+    !request.options.salvage
+        && (error.starts_with(flood::TOO_MANY_DECODE_ERRORS)
+            || error.starts_with(verify::INCOMPLETE_OUTPUT)
+            || DAMAGE_PATTERNS.iter().any(|p| error.contains(p)))
+}
+
+// ==========================================
+// COMMAND: SALVAGE ERROR RATE
+// ==========================================
+#[tauri::command]
+pub fn set_salvage_error_rate(store: State<'_, SettingsStore>, errors_per_sec: u32) -> Result<(), String> {
+    store.update(|s| s.salvage_errors_per_sec = errors_per_sec).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jobtests::{run_video, video, Harness, CLIP};
+    use serde_json::{json, Value};
+
+    // The encode gets 4 s into the 10 s clip, writes 1 KB and dies
+    fn aborted(partial_bytes: u64) -> Value {
+        json!({
+            "stderr": [
+                { "line": "frame=120 fps=30 q=28.0 size=1kB time=00:00:04.00 bitrate=2.0kbits/s speed=1.0x" },
+                { "line": "[h264 @ 0x5581] error while decoding MB 40 12" }
+            ],
+            "partial_bytes": partial_bytes,
+            "abort": true
+        })
+    }
+
+    fn harness(name: &str, partial_bytes: u64) -> Harness {
+        let mut four_secs: Value = serde_json::from_str(CLIP).unwrap();
+        four_secs["format"]["duration"] = json!("4.000000");
+        let runs = json!([aborted(partial_bytes), { "output_bytes": 2048 }]);
+        Harness::new(name, &json!({ "ffprobe": serde_json::from_str::<Value>(CLIP).unwrap(), "ffprobe_output": four_secs, "runs": runs }).to_string())
+    }
+
+    #[test]
+    fn what_an_aborted_salvage_encode_wrote_is_remuxed_and_kept() {
+        let h = harness("salvage-aborted", 1024);
+        let mut request = video(&h, "out.mp4");
+        request.options.salvage = true;
+        let result = run_video(&h, request).unwrap();
+
+        assert_eq!(std::fs::metadata(h.file("out.mp4")).unwrap().len(), 2048);
+        assert_eq!(h.files(), ["clip.mp4", "out.mp4"]);
+        let report = result.salvage.expect("a salvage report");
+        assert!((report.recovered_pct.unwrap() - 40.0).abs() < 0.5, "{:?}", report);
+        assert!(result.warnings.iter().any(|w| w.contains("stopped partway")), "{:?}", result.warnings);
+        let remux = h.runs().into_iter().find(|r| r.windows(2).any(|w| w == ["-c", "copy"])).expect("a remux run");
+        assert!(remux.iter().any(|a| a.ends_with(".aborted")), "{:?}", remux);
+    }
+
+    #[test]
+    fn an_aborted_salvage_encode_that_wrote_nothing_fails() {
+        let h = harness("salvage-aborted-empty", 0);
+        let mut request = video(&h, "out.mp4");
+        request.options.salvage = true;
+        run_video(&h, request).err().expect("nothing to keep");
+
+        assert!(!h.runs().iter().any(|r| r.windows(2).any(|w| w == ["-c", "copy"])));
+        assert_eq!(h.files(), ["clip.mp4"]);
+    }
+}
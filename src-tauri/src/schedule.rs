@@ -0,0 +1,204 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::clock;
+use crate::nightplan;
+use crate::queue;
+use crate::settings::SettingsStore;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+// ==========================================
+// SCHEDULE WINDOW
+// ==========================================
+// Queued jobs only start between `start` and `end`, local wall-clock times
+// ("HH:MM"). An `end` at or before `start` means the window runs overnight.
+// Jobs that are already running when it closes are left to finish.
+//
+// The window follows the wall clock, so on DST nights it's an hour shorter
+// or longer in real time. A boundary the clocks skip (02:30 on a
+// spring-forward night) is taken as the moment after the jump; a boundary
+// that happens twice (01:30 on a fall-back night) counts the first time,
+// so a window never closes and then reopens during the repeated hour.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ScheduleWindow {
+    pub start: String,
+    pub end: String,
+}
+
+fn parse_time(field: &str, text: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(text.trim(), "%H:%M").map_err(|_| format!("{} \"{}\" isn't an HH:MM time", field, text))
+}
+
+impl ScheduleWindow {
+    fn times(&self) -> Result<(NaiveTime, NaiveTime), String> {
+        Ok((parse_time("start", &self.start)?, parse_time("end", &self.end)?))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let (start, end) = self.times()?;
+        if start == end {
+            return Err("The window needs different start and end times (clear it to run at any time)".to_string());
+        }
+        Ok(())
+    }
+
+    // Open and close instants of the window opening on local day `day`.
+    fn occurrence<Tz: TimeZone>(&self, tz: &Tz, day: NaiveDate) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let (start, end) = self.times().ok()?;
+        let close_day = if end <= start { day.succ_opt()? } else { day };
+        Some((clock::resolve(tz, day.and_time(start)), clock::resolve(tz, close_day.and_time(end))))
+    }
+
+    // Yesterday's occurrence can still be open after midnight, so the search
+    // starts there.
+    fn occurrences<Tz: TimeZone>(&self, tz: &Tz, now: DateTime<Utc>) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        let today = now.with_timezone(tz).date_naive();
+        (-1..=2).filter_map(|offset| self.occurrence(tz, today.checked_add_signed(chrono::Duration::days(offset))?)).collect()
+    }
+
+    pub fn is_open_in<Tz: TimeZone>(&self, tz: &Tz, now: DateTime<Utc>) -> bool {
+        self.occurrences(tz, now).into_iter().any(|(open, close)| open <= now && now < close)
+    }
+
+    // Close of the occurrence open at `now`
+    pub fn close_in<Tz: TimeZone>(&self, tz: &Tz, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.occurrences(tz, now).into_iter().find(|(open, close)| open <= &now && &now < close).map(|(_, close)| close)
+    }
+
+    // `now` while open
+    pub fn next_open_in<Tz: TimeZone>(&self, tz: &Tz, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.occurrences(tz, now).into_iter().find(|(open, close)| close > &now && open < close).map(|(open, _)| open.max(now))
+    }
+}
+
+fn window(app: &AppHandle) -> Option<ScheduleWindow> {
+    app.try_state::<SettingsStore>().and_then(|s| s.get().schedule_window)
+}
+
+// No window (or no settings yet) means jobs may start at any time.
+pub fn is_open(app: &AppHandle) -> bool {
+    window(app).is_none_or(|w| w.is_open_in(&Local, Utc::now()))
+}
+
+// The queue doesn't know about the clock, so this nudges it while the
+// window is open; pump() itself holds jobs back while it's closed. It also
+// notices the window opening and closing, for the night plan.
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut tonight = None;
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let open = window(&app).is_some() && is_open(&app);
+            if open && tonight.is_none() {
+                tonight = nightplan::open(&app).await;
+            }
+            if let Some(plan) = tonight.take_if(|_| !open) {
+                nightplan::close(&app, plan);
+            }
+            if open {
+                queue::pump(&app);
+            }
+        }
+    });
+}
+
+// ==========================================
+// COMMANDS: SCHEDULE WINDOW
+// ==========================================
+#[derive(Serialize, Clone, Debug)]
+pub struct ScheduleStatus {
+    pub window: Option<ScheduleWindow>,
+    pub open: bool,
+    // Unix seconds, plus both renderings; None when there's no window
+    pub next_open: Option<u64>,
+    pub next_open_utc: Option<String>,
+    pub next_open_local: Option<String>,
+}
+
+fn status(window: Option<ScheduleWindow>) -> ScheduleStatus {
+    let now = Utc::now();
+    let next_open = window.as_ref().and_then(|w| w.next_open_in(&Local, now)).map(|t| t.timestamp().max(0) as u64);
+    ScheduleStatus {
+        open: window.as_ref().is_none_or(|w| w.is_open_in(&Local, now)),
+        window,
+        next_open,
+        next_open_utc: next_open.map(clock::render_utc),
+        next_open_local: next_open.map(clock::render_local),
+    }
+}
+
+#[tauri::command]
+pub fn get_schedule_status(store: State<'_, SettingsStore>) -> ScheduleStatus {
+    status(store.get().schedule_window)
+}
+
+// None clears the window. Opening it up starts waiting jobs right away.
+#[tauri::command]
+pub fn set_schedule_window(
+    app: AppHandle,
+    store: State<'_, SettingsStore>,
+    window: Option<ScheduleWindow>,
+) -> Result<ScheduleStatus, String> {
+    if let Some(w) = &window {
+        w.validate()?;
+    }
+    let settings = store.update(|s| s.schedule_window = window)?;
+    queue::pump(&app);
+    Ok(status(settings.schedule_window))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::tests::{utc_at, Berlin2026};
+
+    fn window(start: &str, end: &str) -> ScheduleWindow {
+        ScheduleWindow { start: start.to_string(), end: end.to_string() }
+    }
+
+    #[test]
+    fn windows_need_two_different_times() {
+        assert!(window("22:00", "06:00").validate().is_ok());
+        assert_eq!(window("25:00", "06:00").validate(), Err("start \"25:00\" isn't an HH:MM time".to_string()));
+        assert!(window("22:00", "22:00").validate().unwrap_err().contains("different start and end"));
+    }
+
+    #[test]
+    fn overnight_windows_stay_open_past_midnight() {
+        let night = window("22:00", "06:00");
+        // Berlin summer time is UTC+2
+        assert!(night.is_open_in(&Berlin2026, utc_at(2026, 7, 1, 21, 0)));
+        assert!(night.is_open_in(&Berlin2026, utc_at(2026, 7, 2, 3, 59)));
+        assert!(!night.is_open_in(&Berlin2026, utc_at(2026, 7, 2, 4, 0)));
+        assert_eq!(night.close_in(&Berlin2026, utc_at(2026, 7, 1, 21, 0)), Some(utc_at(2026, 7, 2, 4, 0)));
+        assert_eq!(night.next_open_in(&Berlin2026, utc_at(2026, 7, 2, 10, 0)), Some(utc_at(2026, 7, 2, 20, 0)));
+        // While open, "next open" is now
+        assert_eq!(night.next_open_in(&Berlin2026, utc_at(2026, 7, 1, 21, 0)), Some(utc_at(2026, 7, 1, 21, 0)));
+    }
+
+    #[test]
+    fn a_boundary_the_clocks_skip_is_the_moment_after_the_jump() {
+        let early = window("02:30", "05:00");
+        let open = early.next_open_in(&Berlin2026, utc_at(2026, 3, 28, 23, 0)).unwrap();
+        assert_eq!(open, utc_at(2026, 3, 29, 1, 0));
+        // Two hours of real time instead of two and a half
+        assert_eq!(early.close_in(&Berlin2026, open), Some(utc_at(2026, 3, 29, 3, 0)));
+    }
+
+    #[test]
+    fn a_repeated_boundary_closes_the_first_time() {
+        let night = window("22:00", "02:30");
+        // 02:30 CEST is 00:30 UTC
+        assert_eq!(night.close_in(&Berlin2026, utc_at(2026, 10, 24, 22, 0)), Some(utc_at(2026, 10, 25, 0, 30)));
+        // 02:45 CEST, then 02:15 CET in the repeated hour: closed both times
+        assert!(!night.is_open_in(&Berlin2026, utc_at(2026, 10, 25, 0, 45)));
+        assert!(!night.is_open_in(&Berlin2026, utc_at(2026, 10, 25, 1, 15)));
+        // And it opens again at 22:00 CET
+        assert_eq!(night.next_open_in(&Berlin2026, utc_at(2026, 10, 25, 1, 15)), Some(utc_at(2026, 10, 25, 21, 0)));
+    }
+}
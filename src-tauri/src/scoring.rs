@@ -0,0 +1,154 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use tauri::{Manager, State};
+use tokio_util::sync::CancellationToken;
+
+use crate::AppHandle;
+use crate::cancel;
+use crate::capabilities;
+use crate::events::Event;
+use crate::ffmpeg;
+use crate::ladder::{self, LadderMetric};
+use crate::probe;
+use crate::progress::ProgressTracker;
+use crate::queue;
+use crate::support::{self, MediaKind};
+
+// ==========================================
+// QUALITY SCORE AFTER AN ENCODE
+// ==========================================
+// compute_quality_score: once the output is in place, a second ffmpeg run
+// compares it against the source with libvmaf (SSIM when the build has no
+// libvmaf) and the mean goes into the job's stats. Same scorers and parsers
+// as the quality ladder, over the whole output instead of a sample.
+//
+// The two sides are lined up first: the source is cut to what the output
+// covers (start_secs on), a scaled output is scaled back up to the source's
+// size, and a frame-rate cap is applied to the source too, or every frame
+// would be compared with the wrong one. The run has its own progress event
+// and its own token under the job's, so cancel_quality_score stops just the
+// scoring and the job still succeeds; cancelling the job stops both.
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct QualityScore {
+    pub metric: LadderMetric,
+    // VMAF 0-100, SSIM 0-1
+    pub score: f64,
+    // The output was scaled up to the source's size to be compared
+    pub rescaled: bool,
+}
+
+// Scoring runs by job id, for cancel_quality_score
+#[derive(Default)]
+pub struct QualityScoring {
+    tokens: Mutex<HashMap<u64, CancellationToken>>,
+}
+
+// What a job's output can't be scored as, if anything.
+pub fn unscorable(output: &str) -> Option<String> {
+    let ext = Path::new(output).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    match support::container(&ext).map(|c| c.kind) {
+        _ if ext == "gif" => Some("GIF output isn't quality-scored: the palette alone would sink any score".to_string()),
+        Some(MediaKind::Image) => Some("Image output isn't quality-scored".to_string()),
+        Some(MediaKind::Audio) => Some("Audio output has no picture to score".to_string()),
+        _ => None,
+    }
+}
+
+// Pure: the -lavfi graph comparing `[0:v]` (output) against `[1:v]`
+// (source). `scale_to` is the source's size when the output's differs,
+// `fps` the output's rate when the source is faster.
+pub fn compare_graph(metric: LadderMetric, scale_to: Option<(u32, u32)>, fps: Option<f64>) -> String {
+    let scale = scale_to.map(|(w, h)| format!("scale={}:{}:flags=bicubic,", w, h)).unwrap_or_default();
+    let fps = fps.map(|f| format!("fps={},", f)).unwrap_or_default();
+    let compare = match metric {
+        LadderMetric::Vmaf => "libvmaf",
+        LadderMetric::Ssim => "ssim",
+    };
+    format!("[0:v]{}format=yuv420p,setpts=PTS-STARTPTS[d];[1:v]{}format=yuv420p,setpts=PTS-STARTPTS[r];[d][r]{}", scale, fps, compare)
+}
+
+// `start_secs` is where in the source the output begins.
+pub async fn score(app: &AppHandle, output: &str, input: &str, start_secs: Option<f64>) -> Result<(QualityScore, Vec<String>), String> {
+    let mut warnings = vec![];
+    let metric = match capabilities::get(app).await {
+        Ok(caps) if caps.has_filter("libvmaf") => LadderMetric::Vmaf,
+        _ => {
+            warnings.push("This ffmpeg build has no libvmaf; the output was scored with SSIM instead".to_string());
+            LadderMetric::Ssim
+        }
+    };
+    let (out, source) = (probe::probe(app, output).await?, probe::probe(app, input).await?);
+    let (out_size, source_size) = (out.display_size(), source.display_size());
+    let scale_to = source_size.filter(|s| out_size.is_some_and(|o| o != *s));
+    let fps = out.fps.filter(|o| source.fps.is_some_and(|s| s > o + 0.01));
+    let graph = compare_graph(metric, scale_to, fps);
+
+    let mut args = vec!["-hide_banner".to_string(), "-i".to_string(), output.to_string()];
+    if let Some(start) = start_secs {
+        args.extend(["-ss".to_string(), format!("{:.3}", start)]);
+    }
+    if let Some(secs) = out.duration {
+        args.extend(["-t".to_string(), format!("{:.3}", secs)]);
+    }
+    args.extend(["-i".to_string(), input.to_string(), "-lavfi".to_string(), graph, "-f".to_string(), "null".to_string(), "-".to_string()]);
+    println!("📐 Scoring {} against its source ({:?})", output, metric);
+
+    let tracker = ffmpeg::run_with_progress(app, args, ProgressTracker::for_duration(out.duration), Event::QualityScoreProgress).await?;
+    let tail = tracker.stderr_tail.join("\n");
+    let value = match metric {
+        LadderMetric::Vmaf => ladder::parse_vmaf(&tail),
+        LadderMetric::Ssim => ladder::parse_ssim(&tail),
+    };
+    let score = value.ok_or_else(|| format!("Could not read the {:?} score", metric))?;
+    Ok((QualityScore { metric, score, rescaled: scale_to.is_some() }, warnings))
+}
+
+// The scoring pass of a finished job: None (with a warning) when it's
+// skipped, cancelled or fails, since the output itself is fine.
+pub async fn score_job(app: &AppHandle, output: &str, input: &str, start_secs: Option<f64>, warnings: &mut Vec<String>) -> Option<QualityScore> {
+    if let Some(why) = unscorable(output) {
+        warnings.push(why);
+        return None;
+    }
+    let token = cancel::current().map(|t| t.child_token()).unwrap_or_default();
+    let job_id = queue::current_job_id();
+    let scoring = app.try_state::<QualityScoring>();
+    if let (Some(id), Some(scoring)) = (job_id, &scoring) {
+        scoring.tokens.lock().unwrap().insert(id, token.clone());
+    }
+    let result = cancel::settle(&token, cancel::scope(token.clone(), score(app, output, input, start_secs)).await);
+    if let (Some(id), Some(scoring)) = (job_id, &scoring) {
+        scoring.tokens.lock().unwrap().remove(&id);
+    }
+    match result {
+        Ok((score, notes)) => {
+            warnings.extend(notes);
+            Some(score)
+        }
+        Err(e) if e == cancel::CANCELLED => {
+            warnings.push("Quality scoring was cancelled".to_string());
+            None
+        }
+        Err(e) => {
+            warnings.push(format!("The output couldn't be quality-scored: {}", e));
+            None
+        }
+    }
+}
+
+// ==========================================
+// COMMAND: CANCEL QUALITY SCORE
+// ==========================================
+#[tauri::command]
+pub fn cancel_quality_score(scoring: State<'_, QualityScoring>, job_id: u64) -> Result<(), String> {
+    match scoring.tokens.lock().unwrap().get(&job_id) {
+        Some(token) => {
+            token.cancel();
+            Ok(())
+        }
+        None => Err(format!("Job {} isn't scoring its output", job_id)),
+    }
+}
@@ -0,0 +1,376 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{Manager, State};
+use tauri_plugin_shell::process::CommandEvent;
+use tokio_util::sync::CancellationToken;
+
+use crate::AppHandle;
+use crate::cancel;
+use crate::capabilities;
+use crate::events::{self, Event};
+use crate::ffmpeg;
+use crate::hardware;
+use crate::probe::{self, MediaInfo};
+use crate::store;
+
+// The whole run, all steps together
+const GLOBAL_TIMEOUT: Duration = Duration::from_secs(180);
+
+// ==========================================
+// SELF TEST
+// ==========================================
+// Runs the encode paths on inputs it generates itself (lavfi testsrc2 +
+// sine, and a PNG), so a report says something about this machine and this
+// ffmpeg build, not about some file the user has. Everything is written to
+// app_cache_dir/self_test and removed afterwards; the report is kept in
+// app_data_dir/self_test.json for support requests.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Passed,
+    Failed,
+    Skipped,
+    Cancelled,
+    TimedOut,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StepReport {
+    pub name: String,
+    pub status: StepStatus,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // What was checked, e.g. "h264 640x360, 3.0s"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    pub duration_ms: u64,
+    pub steps: Vec<StepReport>,
+}
+
+// Payload of `self-test-progress`, sent as each step starts.
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct SelfTestProgress {
+    step: &'static str,
+    index: usize,
+    steps: usize,
+}
+
+// ==========================================
+// SELF TEST STATE (managed state)
+// ==========================================
+// Each step runs under a child of the run's token: cancelling the step only
+// ends that step, cancelling the run ends the current step and all the rest.
+#[derive(Default)]
+pub struct SelfTest {
+    running: AtomicBool,
+    run: Mutex<CancellationToken>,
+    step: Mutex<CancellationToken>,
+}
+
+struct RunningGuard<'a>(&'a SelfTest);
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.0.running.store(false, Ordering::SeqCst);
+    }
+}
+
+enum Outcome {
+    Done(Result<String, String>),
+    Cancelled,
+    TimedOut,
+}
+
+// Runs one ffmpeg to completion; cancellation comes in through the token
+// the step runs under.
+async fn run_ffmpeg(app: &AppHandle, args: Vec<String>) -> Result<(), Outcome> {
+    let as_outcome = |e: String| if e == cancel::CANCELLED { Outcome::Cancelled } else { Outcome::Done(Err(e)) };
+    let mut sidecar = ffmpeg::spawn(app, args).map_err(as_outcome)?;
+    let mut last_line = String::new();
+    while let Some(event) = sidecar.next().await.map_err(as_outcome)? {
+        match event {
+            CommandEvent::Stderr(bytes) => {
+                if let Some(line) = String::from_utf8_lossy(&bytes).lines().rfind(|l| !l.trim().is_empty()) {
+                    last_line = line.trim().to_string();
+                }
+            }
+            CommandEvent::Terminated(payload) => {
+                return match payload.code {
+                    Some(0) => Ok(()),
+                    code => Err(Outcome::Done(Err(format!("ffmpeg exited with {:?}: {}", code, last_line)))),
+                };
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn args(parts: &[&str]) -> Vec<String> {
+    parts.iter().map(|s| s.to_string()).collect()
+}
+
+fn expect_stream(info: &MediaInfo, codec: &str) -> Result<(), String> {
+    if info.streams.iter().any(|s| s.codec_name.as_deref() == Some(codec)) {
+        Ok(())
+    } else {
+        Err(format!("the output has no {} stream", codec))
+    }
+}
+
+fn describe(info: &MediaInfo) -> String {
+    let codecs: Vec<&str> = info.streams.iter().filter_map(|s| s.codec_name.as_deref()).collect();
+    format!(
+        "{} {}x{}, {:.1}s",
+        codecs.join("+"),
+        info.width.unwrap_or(0),
+        info.height.unwrap_or(0),
+        info.duration.unwrap_or(0.0)
+    )
+}
+
+// Encodes, then probes the output and applies `check`.
+async fn encode_and_check(
+    app: &AppHandle,
+    ffmpeg_args: Vec<String>,
+    output: &Path,
+    check: impl Fn(&MediaInfo) -> Result<(), String>,
+) -> Outcome {
+    if let Err(outcome) = run_ffmpeg(app, ffmpeg_args).await {
+        return outcome;
+    }
+    let result = match probe::probe(app, &output.to_string_lossy()).await {
+        Ok(info) => check(&info).map(|_| describe(&info)),
+        Err(e) => Err(format!("ffprobe couldn't read the output: {}", e)),
+    };
+    Outcome::Done(result)
+}
+
+// Why a step can't run, given which steps before it passed.
+fn missing_prerequisite(name: &str, passed: impl Fn(&str) -> bool, hardware: bool) -> Option<&'static str> {
+    match name {
+        "cpu_encode" | "gif" | "hardware_encode" if !passed("generate_video") => Some("needs generate_video"),
+        "hardware_encode" if !hardware => Some("no hardware H.264 encoder in this ffmpeg build"),
+        "image_resize" if !passed("generate_image") => Some("needs generate_image"),
+        "remux" if !passed("cpu_encode") => Some("needs cpu_encode"),
+        _ => None,
+    }
+}
+
+// Skipped steps (no hardware encoder, ...) don't fail the run
+fn run_passed(steps: &[StepReport]) -> bool {
+    steps.iter().all(|s| matches!(s.status, StepStatus::Passed | StepStatus::Skipped))
+}
+
+fn report_path(app: &AppHandle) -> Option<PathBuf> {
+    app.path().app_data_dir().ok().map(|dir| dir.join("self_test.json"))
+}
+
+fn save_report(app: &AppHandle, report: &SelfTestReport) {
+    let Some(path) = report_path(app) else { return };
+    if let Err(e) = store::save_json(&path, report) {
+        println!("⚠️ Could not save the self-test report: {}", e);
+    }
+}
+
+// ==========================================
+// COMMAND: SELF TEST
+// ==========================================
+#[tauri::command]
+pub async fn self_test(app: AppHandle, state: State<'_, SelfTest>) -> Result<SelfTestReport, String> {
+    if state.running.swap(true, Ordering::SeqCst) {
+        return Err("A self-test is already running".to_string());
+    }
+    let _running = RunningGuard(&state);
+    let run = CancellationToken::new();
+    *state.run.lock().unwrap() = run.clone();
+
+    let dir = app.path().app_cache_dir().map_err(|e| e.to_string())?.join("self_test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+    let (source, png) = (path("source.mkv"), path("source.png"));
+
+    let hardware = match capabilities::get(&app).await {
+        Ok(caps) => hardware::ENCODERS.iter().copied().find(|e| caps.has_encoder(e)),
+        Err(_) => None,
+    };
+
+    let names: [&'static str; 7] = ["generate_video", "generate_image", "cpu_encode", "hardware_encode", "image_resize", "gif", "remux"];
+    let started = Instant::now();
+    let deadline = started + GLOBAL_TIMEOUT;
+    let mut steps: Vec<StepReport> = vec![];
+
+    for (index, name) in names.iter().copied().enumerate() {
+        events::emit(&app, Event::SelfTestProgress(SelfTestProgress { step: name, index: index + 1, steps: names.len() }));
+        let step = run.child_token();
+        *state.step.lock().unwrap() = step.clone();
+        let step_started = Instant::now();
+        let passed = |n: &str| steps.iter().any(|s| s.name == n && s.status == StepStatus::Passed);
+
+        let skip_reason = if run.is_cancelled() {
+            Some(Outcome::Cancelled)
+        } else if Instant::now() >= deadline {
+            Some(Outcome::TimedOut)
+        } else {
+            missing_prerequisite(name, passed, hardware.is_some()).map(|why| Outcome::Done(Err(why.to_string())))
+        };
+
+        let (status, error, details) = match skip_reason {
+            Some(Outcome::Done(Err(why))) => (StepStatus::Skipped, None, Some(why)),
+            Some(Outcome::Cancelled) => (StepStatus::Cancelled, None, None),
+            Some(_) => (StepStatus::TimedOut, None, None),
+            None => {
+                let work = async { match name {
+                    "generate_video" => {
+                        let a = args(&[
+                            "-f", "lavfi", "-i", "testsrc2=size=640x360:rate=30:duration=3",
+                            "-f", "lavfi", "-i", "sine=frequency=440:duration=3",
+                            "-c:v", "ffv1", "-c:a", "pcm_s16le", "-y", &source,
+                        ]);
+                        encode_and_check(&app, a, Path::new(&source), |i| {
+                            if i.has_video && i.has_audio { Ok(()) } else { Err("expected video and audio".to_string()) }
+                        })
+                        .await
+                    }
+                    "generate_image" => {
+                        let a = args(&["-f", "lavfi", "-i", "testsrc2=size=800x600", "-frames:v", "1", "-y", &png]);
+                        encode_and_check(&app, a, Path::new(&png), |i| expect_stream(i, "png")).await
+                    }
+                    "cpu_encode" => {
+                        let out = path("cpu.mp4");
+                        let a = args(&["-i", &source, "-c:v", "libx264", "-preset", "veryfast", "-c:a", "aac", "-y", &out]);
+                        encode_and_check(&app, a, Path::new(&out), |i| {
+                            expect_stream(i, "h264")?;
+                            expect_stream(i, "aac")
+                        })
+                        .await
+                    }
+                    "hardware_encode" => {
+                        let encoder = hardware.unwrap_or_default();
+                        let out = path("hardware.mp4");
+                        let a = args(&["-i", &source, "-c:v", encoder, "-pix_fmt", "yuv420p", "-an", "-y", &out]);
+                        encode_and_check(&app, a, Path::new(&out), |i| expect_stream(i, "h264")).await
+                    }
+                    "image_resize" => {
+                        let out = path("resized.jpg");
+                        let a = args(&["-i", &png, "-vf", "scale=320:-1", "-y", &out]);
+                        encode_and_check(&app, a, Path::new(&out), |i| match i.width {
+                            Some(320) => Ok(()),
+                            w => Err(format!("expected 320 px wide, got {:?}", w)),
+                        })
+                        .await
+                    }
+                    "gif" => {
+                        let out = path("clip.gif");
+                        let a = args(&["-i", &source, "-vf", "fps=10,scale=320:-1:flags=lanczos", "-y", &out]);
+                        encode_and_check(&app, a, Path::new(&out), |i| expect_stream(i, "gif")).await
+                    }
+                    _ => {
+                        let (input, out) = (path("cpu.mp4"), path("remux.mkv"));
+                        let a = args(&["-i", &input, "-map", "0", "-c", "copy", "-y", &out]);
+                        encode_and_check(&app, a, Path::new(&out), |i| match i.streams.len() {
+                            2 => Ok(()),
+                            n => Err(format!("expected 2 streams after the remux, got {}", n)),
+                        })
+                        .await
+                    }
+                } };
+                let outcome = match tokio::time::timeout_at(deadline.into(), cancel::scope(step, work)).await {
+                    Ok(outcome) => outcome,
+                    // Dropping the step kills whatever it had running
+                    Err(_) => Outcome::TimedOut,
+                };
+                match outcome {
+                    Outcome::Done(Ok(details)) => (StepStatus::Passed, None, Some(details)),
+                    Outcome::Done(Err(e)) => (StepStatus::Failed, Some(e), None),
+                    Outcome::Cancelled => (StepStatus::Cancelled, None, None),
+                    Outcome::TimedOut => (StepStatus::TimedOut, Some(format!("over the {}s limit", GLOBAL_TIMEOUT.as_secs())), None),
+                }
+            }
+        };
+        println!("🧪 Self-test {}: {:?}", name, status);
+        steps.push(StepReport {
+            name: name.to_string(),
+            status,
+            duration_ms: step_started.elapsed().as_millis() as u64,
+            error,
+            details,
+        });
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+    let report = SelfTestReport { passed: run_passed(&steps), duration_ms: started.elapsed().as_millis() as u64, steps };
+    save_report(&app, &report);
+    Ok(report)
+}
+
+// Stops the step that's running; the next one starts right away.
+#[tauri::command]
+pub fn cancel_self_test_step(state: State<'_, SelfTest>) {
+    state.step.lock().unwrap().cancel();
+}
+
+// Stops the step that's running and marks all remaining ones cancelled.
+#[tauri::command]
+pub fn cancel_self_test(state: State<'_, SelfTest>) {
+    state.run.lock().unwrap().cancel();
+}
+
+// Report of the most recent run, for the diagnostics panel.
+#[tauri::command]
+pub fn last_self_test(app: AppHandle) -> Option<SelfTestReport> {
+    store::load_json(&app, "self-test report", &report_path(&app)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::StreamInfo;
+
+    fn step(name: &str, status: StepStatus) -> StepReport {
+        StepReport { name: name.to_string(), status, duration_ms: 0, error: None, details: None }
+    }
+
+    #[test]
+    fn steps_are_skipped_when_what_they_need_failed() {
+        let only = |ok: &'static [&'static str]| move |n: &str| ok.contains(&n);
+        assert_eq!(missing_prerequisite("generate_video", only(&[]), false), None);
+        assert_eq!(missing_prerequisite("gif", only(&["generate_image"]), true), Some("needs generate_video"));
+        // A failed source is the reason, not the missing encoder
+        assert_eq!(missing_prerequisite("hardware_encode", only(&[]), false), Some("needs generate_video"));
+        assert_eq!(missing_prerequisite("hardware_encode", only(&["generate_video"]), false), Some("no hardware H.264 encoder in this ffmpeg build"));
+        assert_eq!(missing_prerequisite("hardware_encode", only(&["generate_video"]), true), None);
+        assert_eq!(missing_prerequisite("image_resize", only(&["generate_video"]), true), Some("needs generate_image"));
+        assert_eq!(missing_prerequisite("remux", only(&["generate_video"]), true), Some("needs cpu_encode"));
+    }
+
+    #[test]
+    fn skipped_steps_dont_fail_the_run() {
+        assert!(run_passed(&[step("a", StepStatus::Passed), step("b", StepStatus::Skipped)]));
+        for status in [StepStatus::Failed, StepStatus::Cancelled, StepStatus::TimedOut] {
+            assert!(!run_passed(&[step("a", StepStatus::Passed), step("b", status)]));
+        }
+    }
+
+    #[test]
+    fn outputs_are_described_and_checked_by_codec() {
+        let codec = |name: &str| StreamInfo { codec_name: Some(name.to_string()), ..Default::default() };
+        let info = MediaInfo { streams: vec![codec("h264"), codec("aac")], width: Some(640), height: Some(360), duration: Some(3.0), ..Default::default() };
+        assert_eq!(describe(&info), "h264+aac 640x360, 3.0s");
+        assert_eq!(expect_stream(&info, "aac"), Ok(()));
+        assert_eq!(expect_stream(&info, "gif"), Err("the output has no gif stream".to_string()));
+    }
+}
@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::cleanup::ManagedFolder;
+use crate::history::{DEFAULT_HISTORY_MAX_AGE_DAYS, DEFAULT_HISTORY_MAX_ENTRIES};
+use crate::nightplan::DEFAULT_NIGHT_OVERRUN_MINUTES;
+use crate::salvage::DEFAULT_SALVAGE_ERRORS_PER_SEC;
+use crate::plan::DEFAULT_PLAN_TTL_MINUTES;
+use crate::resources::DEFAULT_MAX_MEMORY_MB;
+use crate::schedule::ScheduleWindow;
+use crate::sourcetool::SourceTool;
+use crate::store;
+use crate::quickshare::DEFAULT_QUICK_KEEP_DAYS;
+use crate::thumbs::DEFAULT_THUMBNAIL_CACHE_MB;
+use crate::upload::UploadTarget;
+use crate::verify::VerifyTier;
+use crate::watch::WatchFolder;
+
+pub const DEFAULT_AUTOMATION_PORT: u16 = 47821;
+
+// A downloadable ffmpeg executable. The checksum is pinned here, never taken
+// from the server the binary comes from.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExtendedBuild {
+    pub url: String,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Settings {
+    // Local HTTP automation API (off unless the user turns it on)
+    pub automation_api: bool,
+    pub automation_port: u16,
+    // Bearer token for the automation API, generated the first time it's enabled
+    pub automation_token: Option<String>,
+    // "linux-x86_64", "macos-aarch64", "windows-x86_64", ... -> build to fetch
+    pub extended_ffmpeg_builds: HashMap<String, ExtendedBuild>,
+    // The downloaded build currently in use (None = bundled sidecar)
+    pub extended_ffmpeg: Option<ExtendedBuild>,
+    // Kill an ffmpeg job whose resident memory stays above this
+    pub max_memory_mb: u64,
+    // Also cap the child with an address-space rlimit (Linux only)
+    pub strict_memory_limit: bool,
+    pub watch_folders: Vec<WatchFolder>,
+    // Mount point -> default read cap in Mbit/s for jobs touching that volume
+    pub volume_io_throttle: HashMap<String, u32>,
+    // Decode the whole output after an encode whose stderr looked risky
+    pub deep_verify_on_risk: bool,
+    // How much of every output is decoded again to check it (see verify.rs)
+    pub verify: VerifyTier,
+    // How long a batch plan stays executable (see plan.rs)
+    pub plan_ttl_minutes: u64,
+    // Size cap of the history grid's thumbnail cache
+    pub thumbnail_cache_mb: u64,
+    // quick_compress outputs older than this are removed at startup
+    pub quick_compress_keep_days: u64,
+    // History entries past these are archived and pruned (None = no limit;
+    // see history.rs, RETENTION)
+    pub history_max_entries: Option<u64>,
+    pub history_max_age_days: Option<u64>,
+    // Queued jobs only start inside this local-time window (None = any time)
+    pub schedule_window: Option<ScheduleWindow>,
+    // Plan each window's jobs to fit it when it opens (see nightplan.rs)
+    pub night_plan: bool,
+    // How far past the close a planned job may be predicted to run
+    pub night_overrun_minutes: u64,
+    // Output folders the user opted into automatic cleanup for
+    pub managed_folders: Vec<ManagedFolder>,
+    // Fail jobs whose input floods stderr with decode errors (see flood.rs)
+    pub abort_on_decode_flood: bool,
+    // Decode errors a second salvage jobs put up with (0 = any; see salvage.rs)
+    pub salvage_errors_per_sec: u32,
+    // Where temp outputs are written (None = next to each output; see set_work_dir)
+    pub work_dir: Option<String>,
+    // S3-compatible bucket for jobs with `upload` on; the secret is kept apart
+    pub upload_target: Option<UploadTarget>,
+    // Recording tools whose auto-mode fixups are off (see sourcetool.rs)
+    pub disabled_source_fixups: Vec<SourceTool>,
+    // macOS: drop the quarantine flag from finished outputs (see macos.rs)
+    pub clear_quarantine: bool,
+    // Closing the window leaves the app running in the tray, with its queue
+    // and scheduled jobs (see tray.rs)
+    pub close_to_tray: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            automation_api: false,
+            automation_port: DEFAULT_AUTOMATION_PORT,
+            automation_token: None,
+            extended_ffmpeg_builds: HashMap::new(),
+            extended_ffmpeg: None,
+            max_memory_mb: DEFAULT_MAX_MEMORY_MB,
+            strict_memory_limit: false,
+            watch_folders: vec![],
+            volume_io_throttle: HashMap::new(),
+            deep_verify_on_risk: false,
+            verify: VerifyTier::Basic,
+            plan_ttl_minutes: DEFAULT_PLAN_TTL_MINUTES,
+            thumbnail_cache_mb: DEFAULT_THUMBNAIL_CACHE_MB,
+            quick_compress_keep_days: DEFAULT_QUICK_KEEP_DAYS,
+            history_max_entries: Some(DEFAULT_HISTORY_MAX_ENTRIES),
+            history_max_age_days: Some(DEFAULT_HISTORY_MAX_AGE_DAYS),
+            schedule_window: None,
+            night_plan: false,
+            night_overrun_minutes: DEFAULT_NIGHT_OVERRUN_MINUTES,
+            managed_folders: vec![],
+            abort_on_decode_flood: false,
+            salvage_errors_per_sec: DEFAULT_SALVAGE_ERRORS_PER_SEC,
+            work_dir: None,
+            upload_target: None,
+            disabled_source_fixups: vec![],
+            clear_quarantine: true,
+            close_to_tray: false,
+        }
+    }
+}
+
+// ==========================================
+// SETTINGS STORE (managed state)
+// ==========================================
+// Lives in app_data_dir/settings.json; an unreadable file means the backup,
+// then defaults (see store.rs).
+pub struct SettingsStore {
+    path: Option<PathBuf>,
+    settings: Mutex<Settings>,
+}
+
+impl SettingsStore {
+    pub fn load(app: &AppHandle) -> Self {
+        let path = app.path().app_data_dir().ok().map(|dir| dir.join("settings.json"));
+        let settings = path.as_deref().and_then(|p| store::load_json(app, "settings", p)).unwrap_or_default();
+        SettingsStore { path, settings: Mutex::new(settings) }
+    }
+
+    pub fn get(&self) -> Settings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    // Applies `f` and writes the result to disk under the same lock.
+    pub fn update(&self, f: impl FnOnce(&mut Settings)) -> Result<Settings, String> {
+        let mut settings = self.settings.lock().unwrap();
+        f(&mut settings);
+        self.write(&settings)?;
+        Ok(settings.clone())
+    }
+
+    // Rewrites the file from what's loaded (repair_stores).
+    pub fn persist(&self) -> Result<(), String> {
+        self.write(&self.settings.lock().unwrap())
+    }
+
+    fn write(&self, settings: &Settings) -> Result<(), String> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let json = serde_json::to_string_pretty(settings).map_err(|e| e.to_string())?;
+        store::write_atomic(path, json.as_bytes(), is_valid)
+    }
+}
+
+pub fn is_valid(text: &str) -> bool {
+    store::parses::<Settings>(text)
+}
+
+// ==========================================
+// COMMAND: MEMORY LIMIT
+// ==========================================
+#[tauri::command]
+pub fn set_memory_limit(store: State<'_, SettingsStore>, max_memory_mb: u64, strict: Option<bool>) -> Result<(), String> {
+    if max_memory_mb < 256 {
+        return Err("The memory limit must be at least 256 MB".to_string());
+    }
+    store
+        .update(|s| {
+            s.max_memory_mb = max_memory_mb;
+            if let Some(strict) = strict {
+                s.strict_memory_limit = strict;
+            }
+        })
+        .map(|_| ())
+}
@@ -0,0 +1,407 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::capabilities;
+use crate::fingerprint;
+use crate::inputs;
+use crate::paths;
+use crate::playability::{self, Recommendation, TargetProfile};
+use crate::probe::{self, MediaInfo};
+use crate::queue::{self, JobSpec};
+use crate::request::{AudioCompressRequest, ImageCompressRequest, VideoCompressRequest, VideoOptions, REQUEST_VERSION};
+use crate::sourcetool;
+
+// ==========================================
+// SIMPLE MODE
+// ==========================================
+// One call for the basic UI: a file and a strength, everything else decided
+// here. The decisions end up in `job-started` (see queue::JobStarted) along
+// with the full request, so whatever simple mode did can be copied into the
+// regular API and tweaked from there.
+const OUTPUT_SUFFIX: &str = "_compressed";
+
+// Extensions that are still pictures even though ffprobe calls them video
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "avif", "bmp", "tif", "tiff"];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Strength {
+    Light,
+    Balanced,
+    Strong,
+}
+
+impl Strength {
+    // Row in the tier tables below
+    fn index(self) -> usize {
+        match self {
+            Strength::Light => 0,
+            Strength::Balanced => 1,
+            Strength::Strong => 2,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Video,
+    Image,
+    Audio,
+}
+
+// ==========================================
+// TIERS (light, balanced, strong)
+// ==========================================
+struct VideoTier {
+    crf: u32,
+    // Longer side in pixels, so a portrait phone clip gets the same "1080p"
+    // (1080x1920) as a landscape one
+    max_long_edge: u32,
+}
+
+struct ImageTier {
+    quality: u32,
+    // Longest side in pixels
+    max_side: u32,
+}
+
+struct AudioTier {
+    bitrate_kbps: u32,
+}
+
+const VIDEO_TIERS: [VideoTier; 3] = [
+    VideoTier { crf: 23, max_long_edge: 3840 },
+    VideoTier { crf: 27, max_long_edge: 1920 },
+    VideoTier { crf: 31, max_long_edge: 1280 },
+];
+
+const IMAGE_TIERS: [ImageTier; 3] = [
+    ImageTier { quality: 90, max_side: 3840 },
+    ImageTier { quality: 80, max_side: 2560 },
+    ImageTier { quality: 65, max_side: 1600 },
+];
+
+const AUDIO_TIERS: [AudioTier; 3] = [
+    AudioTier { bitrate_kbps: 192 },
+    AudioTier { bitrate_kbps: 128 },
+    AudioTier { bitrate_kbps: 96 },
+];
+
+// What simple mode chose for one job.
+#[derive(Serialize, Clone)]
+pub struct SimpleChoices {
+    pub strength: Strength,
+    pub media: MediaKind,
+    // One line per decision, in plain words
+    pub decisions: Vec<String>,
+    // The request that was queued; `enqueue_jobs` accepts it as-is
+    pub spec: JobSpec,
+}
+
+// What the machine can do, as far as the choices care.
+struct Hardware {
+    nvenc: bool,
+    webp: bool,
+    opus: bool,
+}
+
+async fn hardware(app: &AppHandle) -> Hardware {
+    let Ok(caps) = capabilities::get(app).await else {
+        return Hardware { nvenc: false, webp: false, opus: false };
+    };
+    // An nvenc-enabled build says nothing about the card actually being there
+    let nvidia = caps.has_encoder("h264_nvenc")
+        && fingerprint::collect(app).await.gpus.iter().any(|g| {
+            let g = g.to_lowercase();
+            g.contains("nvidia") || g.starts_with("0x10de")
+        });
+    Hardware { nvenc: nvidia, webp: caps.has_encoder("libwebp"), opus: caps.has_encoder("libopus") }
+}
+
+fn classify(ext: &str, media: &MediaInfo) -> Option<MediaKind> {
+    if media.has_video && IMAGE_EXTENSIONS.contains(&ext) {
+        Some(MediaKind::Image)
+    } else if media.has_video {
+        Some(MediaKind::Video)
+    } else if media.has_audio {
+        Some(MediaKind::Audio)
+    } else {
+        None
+    }
+}
+
+fn video_choices(input: &str, output: String, media: &MediaInfo, hw: &Hardware, strength: Strength, decisions: &mut Vec<String>) -> JobSpec {
+    let tier = &VIDEO_TIERS[strength.index()];
+    let long_edge = media.display_size().map(|(w, h)| w.max(h));
+    let max_long_edge = long_edge.filter(|e| *e > tier.max_long_edge).map(|_| tier.max_long_edge);
+    let orientation = media.orientation().map_or("unknown orientation", |o| o.as_str());
+    decisions.push("MP4 output, widely playable".to_string());
+    decisions.push(if hw.nvenc {
+        "NVIDIA hardware encoding (auto_gpu)".to_string()
+    } else {
+        "CPU encoding with libx264 (no usable NVIDIA GPU)".to_string()
+    });
+    decisions.push(format!("Quality crf {}", tier.crf));
+    decisions.push(match max_long_edge {
+        Some(e) => format!("Downscaled to a {} px long edge ({})", e, orientation),
+        None => format!("Kept the original resolution (long edge at most {} px, {})", tier.max_long_edge, orientation),
+    });
+    let file_name = Path::new(input).file_name().unwrap_or_default().to_string_lossy();
+    if let Some(detection) = sourcetool::detect(&file_name, media) {
+        decisions.push(format!("Screen recording ({}): that tool's fixups apply unless turned off in settings", detection.tool.as_str()));
+    }
+    let options = VideoOptions { auto_gpu: hw.nvenc, crf: Some(tier.crf), max_long_edge, source_fixups: true, ..Default::default() };
+    JobSpec::Video(Box::new(VideoCompressRequest::new(input.to_string(), output, options)))
+}
+
+fn image_output_ext(ext: &str, hw: &Hardware) -> &'static str {
+    match ext {
+        "jpg" | "jpeg" => "jpg",
+        _ if hw.webp => "webp",
+        // No WebP encoder: PNG keeps transparency, and only gets resized
+        _ => "png",
+    }
+}
+
+fn image_choices(input: &str, output: String, media: &MediaInfo, strength: Strength, decisions: &mut Vec<String>) -> JobSpec {
+    let tier = &IMAGE_TIERS[strength.index()];
+    let (w, h) = (media.width.unwrap_or(0), media.height.unwrap_or(0));
+    let (width, height) = if w.max(h) <= tier.max_side {
+        (None, None)
+    } else if w >= h {
+        (Some(tier.max_side), None)
+    } else {
+        (None, Some(tier.max_side))
+    };
+    let lossless = output.ends_with(".png");
+    decisions.push(format!("{} output", Path::new(&output).extension().unwrap_or_default().to_string_lossy().to_uppercase()));
+    if !lossless {
+        decisions.push(format!("Quality {}", tier.quality));
+    }
+    decisions.push(if width.is_some() || height.is_some() {
+        format!("Longest side scaled to {} px", tier.max_side)
+    } else {
+        format!("Kept the original size (at most {} px)", tier.max_side)
+    });
+    JobSpec::Image(ImageCompressRequest {
+        version: REQUEST_VERSION,
+        input: input.to_string(),
+        output,
+        create_dirs: false,
+        width,
+        height,
+        quality: (!lossless).then_some(tier.quality),
+        skip_if_larger: false,
+        metadata: Default::default(),
+        process: Default::default(),
+        dry_run: false,
+        annotations: Default::default(),
+    })
+}
+
+fn audio_output_ext(ext: &str, hw: &Hardware) -> &'static str {
+    match ext {
+        "mp3" => "mp3",
+        "opus" if hw.opus => "opus",
+        _ => "m4a",
+    }
+}
+
+fn audio_choices(input: &str, output: String, strength: Strength, decisions: &mut Vec<String>) -> JobSpec {
+    let tier = &AUDIO_TIERS[strength.index()];
+    decisions.push(format!("{} output", Path::new(&output).extension().unwrap_or_default().to_string_lossy().to_uppercase()));
+    decisions.push(format!("{} kbps", tier.bitrate_kbps));
+    decisions.push("Tags and cover art kept".to_string());
+    JobSpec::Audio(AudioCompressRequest {
+        version: REQUEST_VERSION,
+        input: input.to_string(),
+        output,
+        create_dirs: false,
+        bitrate_kbps: Some(tier.bitrate_kbps),
+        quality: None,
+        compression_level: None,
+        keep_cover: true,
+        process: Default::default(),
+        annotations: Default::default(),
+    })
+}
+
+// Outputs of jobs still waiting or running, which don't exist on disk yet.
+fn queued_outputs(app: &AppHandle) -> Vec<String> {
+    let snapshot = queue::snapshot(app);
+    snapshot.pending.iter().chain(snapshot.running.iter()).map(|j| j.spec.output().to_string()).collect()
+}
+
+// With a `target` the point is getting a video to play there: when the
+// streams already would, a remux is queued instead of a compression.
+pub async fn choose(app: &AppHandle, input: &str, strength: Strength, target: Option<TargetProfile>) -> Result<SimpleChoices, String> {
+    inputs::preflight(input).map_err(|e| e.to_string())?;
+    let media = probe::probe(app, input).await?;
+    let input_path = Path::new(input);
+    let ext = input_path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    let kind = classify(&ext, &media).ok_or_else(|| format!("{} has no video, image or audio to compress", input))?;
+    let queued = queued_outputs(app);
+
+    let mut playback_decisions = vec![];
+    if let Some(target) = target.filter(|_| kind == MediaKind::Video) {
+        let report = playability::recommend(input, &media, target, |p| queued.iter().any(|q| paths::same_file(q, &p.to_string_lossy())))?;
+        match report.recommendation {
+            Recommendation::RemuxSufficient { spec, audio_reencoded } => {
+                let mut decisions = vec![format!("The streams already play on {:?}: remuxed to {} instead of compressed", target, Path::new(&spec.output).extension().unwrap_or_default().to_string_lossy().to_uppercase())];
+                if audio_reencoded {
+                    decisions.push("Video copied, audio re-encoded".to_string());
+                }
+                return Ok(SimpleChoices { strength, media: kind, decisions, spec: JobSpec::Video(spec) });
+            }
+            Recommendation::AlreadyCompatible => playback_decisions.push(format!("Already plays on {:?}; compressed as usual", target)),
+            Recommendation::ReencodeNeeded { reasons } => playback_decisions.extend(reasons.into_iter().map(|r| format!("Re-encoded: {}", r))),
+        }
+    }
+    let hw = hardware(app).await;
+
+    let out_ext = match kind {
+        MediaKind::Video => "mp4",
+        MediaKind::Image => image_output_ext(&ext, &hw),
+        MediaKind::Audio => audio_output_ext(&ext, &hw),
+    };
+    let output = paths::unused_sibling(input_path, OUTPUT_SUFFIX, out_ext, |p| {
+        queued.iter().any(|q| paths::same_file(q, &p.to_string_lossy()))
+    })
+    .to_string_lossy()
+    .to_string();
+
+    let mut decisions = vec![format!("Treated as {}", match kind {
+        MediaKind::Video => "video",
+        MediaKind::Image => "an image",
+        MediaKind::Audio => "audio",
+    })];
+    decisions.extend(playback_decisions);
+    let spec = match kind {
+        MediaKind::Video => video_choices(input, output, &media, &hw, strength, &mut decisions),
+        MediaKind::Image => image_choices(input, output, &media, strength, &mut decisions),
+        MediaKind::Audio => audio_choices(input, output, strength, &mut decisions),
+    };
+    Ok(SimpleChoices { strength, media: kind, decisions, spec })
+}
+
+// ==========================================
+// CHOICES PER QUEUED JOB (managed state)
+// ==========================================
+#[derive(Default)]
+pub struct SimpleJobs {
+    // By (input, output): stored before the job id exists, and simple mode
+    // never queues two jobs for one output
+    choices: Mutex<HashMap<(String, String), SimpleChoices>>,
+}
+
+fn spec_key(spec: &JobSpec) -> (String, String) {
+    (spec.input().to_string(), spec.output().to_string())
+}
+
+// Handed out once, when the job starts.
+pub fn choices_for(app: &AppHandle, job_id: u64) -> Option<SimpleChoices> {
+    let jobs = app.try_state::<SimpleJobs>()?;
+    if jobs.choices.lock().unwrap().is_empty() {
+        return None;
+    }
+    // Not under our lock: find_job takes the queue's
+    let key = spec_key(&queue::find_job(app, job_id)?.spec);
+    let choices = jobs.choices.lock().unwrap().remove(&key);
+    choices
+}
+
+// ==========================================
+// COMMAND: COMPRESS SIMPLE
+// ==========================================
+// Returns the queue job id; progress and the result come through the usual events.
+#[tauri::command]
+pub async fn compress_simple(
+    app: AppHandle,
+    jobs: State<'_, SimpleJobs>,
+    input: String,
+    strength: Strength,
+    target_profile: Option<TargetProfile>,
+) -> Result<u64, String> {
+    let choices = choose(&app, &input, strength, target_profile).await?;
+    println!("🪄 Simple mode ({:?}): {}", strength, choices.decisions.join(", "));
+    // Stored first, so a job that starts right away still finds its
+    // choices when it emits `job-started`
+    let key = spec_key(&choices.spec);
+    let spec = choices.spec.clone();
+    jobs.choices.lock().unwrap().insert(key.clone(), choices);
+    let queued = queue::enqueue(&app, vec![spec], None).and_then(|ids| ids.first().copied().ok_or_else(|| "The job wasn't queued".to_string()));
+    if queued.is_err() {
+        jobs.choices.lock().unwrap().remove(&key);
+    }
+    queued
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quality;
+
+    fn tier_options(strength: Strength, nvenc: bool) -> VideoOptions {
+        let media = MediaInfo { width: Some(1920), height: Some(1080), has_video: true, ..Default::default() };
+        let hw = Hardware { nvenc, webp: true, opus: true };
+        match video_choices("/in/clip.mov", "/in/clip_compressed.mp4".to_string(), &media, &hw, strength, &mut vec![]) {
+            JobSpec::Video(request) => request.options,
+            _ => unreachable!(),
+        }
+    }
+
+    // What the encode adds for the tier, after the container's defaults were stripped
+    fn tier_args(strength: Strength, encoder: &str) -> Vec<String> {
+        let options = tier_options(strength, false);
+        let mut args: Vec<String> = ["-preset", "medium", "-cq", "30", "-qp", "24", "-b:v", "0"].map(String::from).to_vec();
+        let plan = crate::resolve_rate(&options.rate, options.crf, false, encoder, false, false);
+        quality::strip_rate_args(&mut args);
+        args.extend(plan.options.encoder_args(encoder, None, true).unwrap().unwrap());
+        args
+    }
+
+    fn strs(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn tiers_reach_every_encoder_family() {
+        assert_eq!(tier_args(Strength::Light, "libx264"), strs(&["-preset", "medium", "-crf", "23"]));
+        assert_eq!(tier_args(Strength::Balanced, "h264_nvenc"), strs(&["-preset", "medium", "-cq", "27", "-b:v", "0"]));
+        assert_eq!(tier_args(Strength::Strong, "h264_qsv"), strs(&["-preset", "medium", "-global_quality", "31"]));
+        assert_eq!(tier_args(Strength::Balanced, "h264_amf"), strs(&["-preset", "medium", "-rc", "cqp", "-qp_i", "27", "-qp_p", "27"]));
+        assert_eq!(tier_args(Strength::Light, "h264_videotoolbox"), strs(&["-preset", "medium", "-q:v", "56"]));
+        assert_eq!(tier_args(Strength::Strong, "libvpx-vp9"), strs(&["-preset", "medium", "-crf", "31", "-b:v", "0"]));
+    }
+
+    #[test]
+    fn tier_crfs_fit_every_encoder() {
+        for strength in [Strength::Light, Strength::Balanced, Strength::Strong] {
+            let crf = tier_options(strength, false).crf.unwrap();
+            for profile in crate::encoders::PROFILES {
+                assert!(quality::crf_range(profile.encoder).contains(&crf), "{} for {}", crf, profile.encoder);
+            }
+        }
+    }
+
+    #[test]
+    fn tiers_ask_for_the_gpu_only_with_nvenc() {
+        assert!(tier_options(Strength::Balanced, true).auto_gpu);
+        assert!(!tier_options(Strength::Balanced, false).auto_gpu);
+    }
+
+    #[test]
+    fn crf_is_checked_on_the_encoders_own_scale() {
+        let options = VideoOptions { crf: Some(60), ..Default::default() };
+        let webm = VideoCompressRequest::new("/in/a.mov".to_string(), "/in/a.webm".to_string(), options.clone());
+        assert!(webm.validate().is_ok());
+        let mp4 = VideoCompressRequest::new("/in/a.mov".to_string(), "/in/a.mp4".to_string(), options);
+        let issues = mp4.validate().unwrap_err().0;
+        assert!(issues.iter().any(|i| i.field == "crf" && i.message == "60 is outside 0-51 for libx264"), "{:?}", issues.iter().map(|i| &i.message).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,397 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::AppHandle;
+use crate::cancel;
+use crate::explain;
+use crate::preview;
+use crate::probe;
+use crate::quality;
+use crate::request::VideoCompressRequest;
+use crate::timeline;
+use crate::VideoJobResult;
+
+pub const SIZE_CAP_MISSED: &str = "SizeCapMissed";
+// The first rung, and what the sample is encoded at
+const BASE_CRF: u32 = 23;
+// +6 CRF about halves what x264/x265 write
+const CRF_HALVING: f64 = 6.0;
+// Bitrate grows slower than the pixel count
+const PIXEL_EXPONENT: f64 = 0.75;
+// (long edge, CRFs at it); None = the source's own size
+const STEPS: &[(Option<u32>, &[u32])] = &[
+    (None, &[23, 26]),
+    (Some(1280), &[24, 28]),
+    (Some(854), &[26, 30]),
+    (Some(640), &[28, 32]),
+];
+
+// ==========================================
+// SIZE-CAP LADDER
+// ==========================================
+// For "must be under N MB, but look as good as it can" (`fit_size_mb`):
+// rather than encode and hope, the job gets an ordered list of settings,
+// best-looking first, each with a predicted size. One sample encode at the
+// first rung (preview.rs's estimator) gives the bytes; the other rungs are
+// scaled from it by CRF and resolution. The encode starts at the first rung
+// predicted to fit, and only moves down when the real output misses the
+// cap, re-scaling the predictions by how far off the last one was. The
+// result and history say which rung it ended on.
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Rung {
+    pub crf: u32,
+    // None = the source's size (or the request's own cap)
+    pub max_long_edge: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub predicted_bytes: Option<u64>,
+}
+
+impl Rung {
+    pub fn label(&self) -> String {
+        match self.max_long_edge {
+            Some(edge) => format!("{}px crf {}", edge, self.crf),
+            None => format!("source crf {}", self.crf),
+        }
+    }
+}
+
+// `fit` of a job result and its history entry.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FitReport {
+    pub cap_bytes: u64,
+    pub rungs: Vec<Rung>,
+    // Rung the prediction picked, and the one the output fit on
+    pub first_choice: usize,
+    pub used: usize,
+    pub attempts: u32,
+    pub output_bytes: u64,
+}
+
+impl FitReport {
+    pub fn used_label(&self) -> String {
+        self.rungs.get(self.used).map(Rung::label).unwrap_or_default()
+    }
+}
+
+// What the ladder is made from.
+#[derive(Clone, Debug, Default)]
+pub struct FitAnalysis {
+    // Of the picture as shown, after any size cap of the request's
+    pub long_edge: Option<u32>,
+    // The whole output at the first rung, from the sample encode
+    pub sample_bytes: Option<u64>,
+    // The part of that that's audio, which no rung changes
+    pub audio_bytes: u64,
+}
+
+fn predict(analysis: &FitAnalysis, crf: u32, edge: Option<u32>) -> Option<u64> {
+    let sample = analysis.sample_bytes? as f64;
+    let audio = (analysis.audio_bytes as f64).min(sample * 0.9);
+    let crf_factor = 0.5f64.powf((crf as f64 - BASE_CRF as f64) / CRF_HALVING);
+    let size_factor = match (edge, analysis.long_edge) {
+        (Some(edge), Some(source)) if source > 0 => (edge as f64 / source as f64).powf(2.0 * PIXEL_EXPONENT),
+        _ => 1.0,
+    };
+    Some((audio + (sample - audio) * crf_factor * size_factor).round() as u64)
+}
+
+// Pure: the rungs for one file, best-looking first. Sizes at or above the
+// source's are left out; without a known size every step is kept.
+pub fn ladder(analysis: &FitAnalysis) -> Vec<Rung> {
+    STEPS
+        .iter()
+        .filter(|(edge, _)| match (edge, analysis.long_edge) {
+            (Some(edge), Some(source)) => *edge < source,
+            _ => true,
+        })
+        .flat_map(|(edge, crfs)| crfs.iter().map(move |crf| (*edge, *crf)))
+        .map(|(edge, crf)| Rung { crf, max_long_edge: edge, predicted_bytes: predict(analysis, crf, edge) })
+        .collect()
+}
+
+// Pure: the rung to try after `after` (None = the first try). The first
+// whose prediction, times `scale`, fits the cap; the last one when none is
+// predicted to; None once there's nothing further down. Rungs without a
+// prediction count as fitting.
+pub fn next_rung(rungs: &[Rung], cap_bytes: u64, after: Option<usize>, scale: f64) -> Option<usize> {
+    let from = after.map_or(0, |i| i + 1);
+    if from >= rungs.len() {
+        return None;
+    }
+    let fits = |r: &Rung| r.predicted_bytes.is_none_or(|p| (p as f64 * scale) <= cap_bytes as f64);
+    Some(rungs.iter().skip(from).position(fits).map_or(rungs.len() - 1, |i| from + i))
+}
+
+fn rung_request(request: &VideoCompressRequest, rung: &Rung) -> VideoCompressRequest {
+    let mut request = request.clone();
+    request.options.crf = Some(rung.crf);
+    request.options.max_long_edge = rung.max_long_edge.or(request.options.max_long_edge);
+    request
+}
+
+fn file_len(path: &str) -> u64 {
+    std::fs::metadata(Path::new(path)).map(|m| m.len()).unwrap_or(0)
+}
+
+fn mb(bytes: u64) -> f64 {
+    bytes as f64 / 1024.0 / 1024.0
+}
+
+async fn analyze(app: &AppHandle, request: &VideoCompressRequest) -> Result<FitAnalysis, String> {
+    let media = probe::probe(app, &request.input).await?;
+    let options = &request.options;
+    let long_edge = media.display_size().map(|(w, h)| w.max(h)).map(|e| options.max_long_edge.map_or(e, |cap| cap.min(e)));
+    let Some(duration) = media.duration.filter(|d| *d > 0.0) else {
+        return Ok(FitAnalysis { long_edge, ..Default::default() });
+    };
+    // What's left of the timeline after the cut and the preview length
+    let start = options.start_secs.unwrap_or(0.0);
+    let mut length = options.end_secs.unwrap_or(duration).min(duration) - start;
+    if let Some(limit) = options.limit_duration_secs {
+        length = length.min(limit);
+    }
+    let length = length.max(0.1);
+    let first = Rung { crf: BASE_CRF, max_long_edge: None, predicted_bytes: None };
+    // Dry runs don't start ffmpeg, so they go without predictions
+    let sample = if request.dry_run { Err("dry run".to_string()) } else { preview::sample_bytes(app, &rung_request(request, &first), duration).await };
+    let sample_bytes = match sample {
+        Err(_) if request.dry_run => None,
+        Ok(whole) => Some((whole as f64 * length / duration).round() as u64),
+        Err(e) => {
+            cancel::check()?;
+            println!("⚠️ Sample encode for the size ladder failed, going without predictions: {}", e);
+            None
+        }
+    };
+    let audio_bytes = if media.has_audio { (quality::AUDIO_BUDGET_KBPS as f64 * 1024.0 / 8.0 * length) as u64 } else { 0 };
+    Ok(FitAnalysis { long_edge, sample_bytes, audio_bytes })
+}
+
+// encode_video, down the ladder for jobs with `fit_size_mb`.
+pub async fn encode(app: &AppHandle, request: VideoCompressRequest, staged: &str) -> Result<VideoJobResult, String> {
+    let Some(cap_mb) = request.options.fit_size_mb else {
+        return crate::encode_video(app, request, staged).await;
+    };
+    let cap_bytes = (cap_mb * 1024.0 * 1024.0) as u64;
+    let analysis = analyze(app, &request).await?;
+    let rungs = ladder(&analysis);
+    let mut scale = 1.0;
+    let mut next = next_rung(&rungs, cap_bytes, None, scale);
+    let first_choice = next.unwrap_or(0);
+    let mut attempts = 0;
+    let mut last_bytes = 0;
+    while let Some(index) = next {
+        let rung = &rungs[index];
+        attempts += 1;
+        println!("📏 Size ladder: trying {} (rung {} of {})", rung.label(), index + 1, rungs.len());
+        let mut params = vec![("rung", rung.label()), ("index", index.to_string())];
+        params.extend(rung.predicted_bytes.map(|p| ("predicted_bytes", p.to_string())));
+        timeline::record(app, timeline::FIT_ATTEMPT, &params);
+        let mut result = crate::encode_video(app, rung_request(&request, rung), staged).await?;
+        // A dry run shows the first rung's command; nothing was encoded to measure
+        if let Some(plan) = result.dry_run.as_mut() {
+            let note = "A real run first encodes a sample to predict each rung's size, and may start further down the ladder".to_string();
+            plan.warnings.push(note.clone());
+            result.warnings.push(note);
+            return Ok(result);
+        }
+        last_bytes = file_len(staged);
+        if last_bytes <= cap_bytes {
+            let report = FitReport { cap_bytes, rungs: rungs.clone(), first_choice, used: index, attempts, output_bytes: last_bytes };
+            result.explanations.insert(0, explain::fit_rung(&report));
+            result.fit = Some(report);
+            return Ok(result);
+        }
+        println!("📏 {} came out at {:.1} MB, over the {:.1} MB cap", rung.label(), mb(last_bytes), cap_mb);
+        if let Some(predicted) = rung.predicted_bytes.filter(|p| *p > 0) {
+            scale = last_bytes as f64 / predicted as f64;
+        }
+        next = next_rung(&rungs, cap_bytes, Some(index), scale);
+    }
+    Err(format!(
+        "{}: even the smallest setting ({}) came out at {:.1} MB, over the {:.1} MB cap",
+        SIZE_CAP_MISSED,
+        rungs.last().map(Rung::label).unwrap_or_default(),
+        mb(last_bytes),
+        cap_mb
+    ))
+}
+
+// ==========================================
+// BATCH STATISTICS
+// ==========================================
+// What the cap cost a batch: where its jobs ended up on their ladders.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct FitSummary {
+    // Jobs with a size cap that fit it
+    pub jobs: usize,
+    // Fit on the rung the prediction picked
+    pub first_choice: usize,
+    // Needed a rung further down than predicted
+    pub escalated: usize,
+    // Ended below the first rung (lower CRF or resolution than the best)
+    pub degraded: usize,
+    // Didn't fit on any rung
+    pub missed: usize,
+    // Jobs per rung they ended on
+    pub by_rung: BTreeMap<String, usize>,
+    pub mean_attempts: f64,
+}
+
+// `missed` is the failed jobs' count.
+pub fn summarize<'a>(reports: impl IntoIterator<Item = &'a FitReport>, missed: usize) -> Option<FitSummary> {
+    let mut summary = FitSummary { missed, ..Default::default() };
+    let mut attempts = 0;
+    for report in reports {
+        summary.jobs += 1;
+        attempts += report.attempts;
+        if report.used == report.first_choice {
+            summary.first_choice += 1;
+        } else if report.used > report.first_choice {
+            summary.escalated += 1;
+        }
+        if report.used > 0 {
+            summary.degraded += 1;
+        }
+        *summary.by_rung.entry(report.used_label()).or_default() += 1;
+    }
+    if summary.jobs == 0 && summary.missed == 0 {
+        return None;
+    }
+    summary.mean_attempts = if summary.jobs > 0 { attempts as f64 / summary.jobs as f64 } else { 0.0 };
+    Some(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{clip_scenario, run_video, video, Harness};
+
+    fn analysis(long_edge: Option<u32>, sample_bytes: Option<u64>, audio_bytes: u64) -> FitAnalysis {
+        FitAnalysis { long_edge, sample_bytes, audio_bytes }
+    }
+
+    fn labels(rungs: &[Rung]) -> Vec<String> {
+        rungs.iter().map(Rung::label).collect()
+    }
+
+    // ==========================================
+    // THE LADDER
+    // ==========================================
+    #[test]
+    fn the_ladder_goes_down_in_quality_then_size() {
+        let rungs = ladder(&analysis(Some(1920), Some(10_000_000), 1_000_000));
+        assert_eq!(
+            labels(&rungs),
+            ["source crf 23", "source crf 26", "1280px crf 24", "1280px crf 28", "854px crf 26", "854px crf 30", "640px crf 28", "640px crf 32"]
+        );
+        // The sample is the first rung, and every rung below is predicted smaller
+        let predicted: Vec<u64> = rungs.iter().map(|r| r.predicted_bytes.unwrap()).collect();
+        assert_eq!(predicted[0], 10_000_000);
+        assert!(predicted.windows(2).all(|w| w[1] < w[0]), "{:?}", predicted);
+        // +6 CRF halves the video part; the audio stays
+        assert_eq!(predicted[1], 1_000_000 + (9_000_000.0 * 0.5f64.sqrt()).round() as u64);
+        // A third of the long edge is a ninth of the pixels, to the 0.75
+        assert_eq!(predict(&analysis(Some(1920), Some(10_000_000), 0), 23, Some(640)), Some((10_000_000.0 / 27f64.sqrt()).round() as u64));
+    }
+
+    #[test]
+    fn sizes_the_source_doesnt_exceed_are_left_out() {
+        let hd = ladder(&analysis(Some(1280), Some(4_000_000), 0));
+        assert_eq!(labels(&hd), ["source crf 23", "source crf 26", "854px crf 26", "854px crf 30", "640px crf 28", "640px crf 32"]);
+        assert_eq!(ladder(&analysis(Some(640), Some(4_000_000), 0)).len(), 2);
+        // Unknown size: every step, predicted as if at the source's
+        let unknown = ladder(&analysis(None, Some(4_000_000), 0));
+        assert_eq!(unknown.len(), 8);
+        assert_eq!(unknown[4].predicted_bytes, predict(&analysis(None, Some(4_000_000), 0), 26, None));
+        // No sample, no predictions
+        assert!(ladder(&analysis(Some(1920), None, 0)).iter().all(|r| r.predicted_bytes.is_none()));
+    }
+
+    #[test]
+    fn audio_counts_at_most_most_of_the_sample() {
+        // More audio than the sample itself would predict growth down the ladder
+        let odd = analysis(Some(1920), Some(1_000_000), 5_000_000);
+        let rungs = ladder(&odd);
+        assert!(rungs.windows(2).all(|w| w[1].predicted_bytes <= w[0].predicted_bytes));
+        assert!(rungs.iter().all(|r| r.predicted_bytes.unwrap() >= 900_000));
+    }
+
+    #[test]
+    fn the_first_rung_predicted_to_fit_is_tried_first() {
+        let rungs = ladder(&analysis(Some(1920), Some(10_000_000), 1_000_000));
+        assert_eq!(next_rung(&rungs, 20_000_000, None, 1.0), Some(0));
+        assert_eq!(next_rung(&rungs, 10_000_000, None, 1.0), Some(0));
+        assert_eq!(next_rung(&rungs, 5_500_000, None, 1.0), Some(2));
+        // Nothing predicted to fit: the smallest, rather than nothing
+        assert_eq!(next_rung(&rungs, 1_000, None, 1.0), Some(7));
+    }
+
+    #[test]
+    fn a_miss_rescales_what_comes_next() {
+        let rungs = ladder(&analysis(Some(1920), Some(10_000_000), 1_000_000));
+        // Spot on: the next rung down
+        assert_eq!(next_rung(&rungs, 5_500_000, Some(2), 1.0), Some(3));
+        // Half again as big as predicted: rungs that would now miss are skipped
+        assert_eq!(next_rung(&rungs, 5_500_000, Some(2), 1.5), Some(4));
+        assert_eq!(next_rung(&rungs, 5_500_000, Some(2), 10.0), Some(7));
+        // Never back up the ladder, and nothing after the last
+        assert_eq!(next_rung(&rungs, 20_000_000, Some(4), 1.0), Some(5));
+        assert_eq!(next_rung(&rungs, 20_000_000, Some(7), 1.0), None);
+        // Without predictions, one rung at a time
+        let blind = ladder(&analysis(Some(1920), None, 0));
+        assert_eq!(next_rung(&blind, 1, None, 1.0), Some(0));
+        assert_eq!(next_rung(&blind, 1, Some(0), 3.0), Some(1));
+    }
+
+    // ==========================================
+    // ENCODING DOWN IT
+    // ==========================================
+    // One ffmpeg run per entry: the sample encode first, then each attempt
+    fn runs(output_bytes: &[u64]) -> String {
+        let runs: Vec<String> = output_bytes
+            .iter()
+            .map(|bytes| format!(r#"{{ "stderr": [{{ "line": "frame=300 fps=30 q=28.0 size=1024kB time=00:00:10.00 bitrate=838.9kbits/s speed=1.0x" }}], "output_bytes": {} }}"#, bytes))
+            .collect();
+        format!("[{}]", runs.join(","))
+    }
+
+    fn capped(h: &Harness, mb: f64) -> VideoCompressRequest {
+        let mut request = video(h, "small.mp4");
+        request.options.fit_size_mb = Some(mb);
+        request
+    }
+
+    fn crfs(h: &Harness) -> Vec<String> {
+        h.runs().iter().filter_map(|args| args.iter().position(|a| a == "-crf").map(|i| args[i + 1].clone())).collect()
+    }
+
+    #[test]
+    fn the_encode_steps_down_until_the_output_fits() {
+        // The 1280x720 clip: its ladder starts at source size and goes down to 854 and 640
+        let h = Harness::new("sizefit-converges", &clip_scenario(&runs(&[2_000_000, 2_000_000, 900_000])));
+        let result = run_video(&h, capped(&h, 1.0)).unwrap();
+
+        let fit = result.fit.unwrap();
+        assert_eq!(labels(&fit.rungs), ["source crf 23", "source crf 26", "854px crf 26", "854px crf 30", "640px crf 28", "640px crf 32"]);
+        // Predicted to fit at 854px; twice the prediction there skipped two rungs
+        assert_eq!((fit.first_choice, fit.used, fit.attempts), (2, 5, 2));
+        assert_eq!((fit.cap_bytes, fit.output_bytes), (1_048_576, 900_000));
+        assert_eq!(fit.used_label(), "640px crf 32");
+        assert_eq!(crfs(&h), ["23", "26", "32"]);
+        assert_eq!(std::fs::metadata(h.file("small.mp4")).unwrap().len(), 900_000);
+    }
+
+    #[test]
+    fn the_encode_gives_up_past_the_last_rung() {
+        let h = Harness::new("sizefit-gives-up", &clip_scenario(&runs(&[2_000_000])));
+        let error = run_video(&h, capped(&h, 1.0)).err().expect("the cap was missed").to_string();
+        assert!(error.contains("SizeCapMissed: even the smallest setting (640px crf 32) came out at 1.9 MB, over the 1.0 MB cap"), "{}", error);
+        assert_eq!(crfs(&h), ["23", "26", "32"]);
+        // Nothing left at the output
+        assert!(!h.files().contains(&"small.mp4".to_string()));
+    }
+}
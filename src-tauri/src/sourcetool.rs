@@ -0,0 +1,304 @@
+use serde::{Deserialize, Serialize};
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::probe::MediaInfo;
+use crate::settings::SettingsStore;
+
+// ==========================================
+// SCREEN-RECORDING SOURCES
+// ==========================================
+// Recordings from the usual capture tools come with the same quirks every
+// time: Game Bar writes broken variable-rate timestamps, QuickTime screen
+// recordings are huge and keep their index at the end, and all of them are
+// screen content (flat areas, sharp text) that the encoders have tunings
+// for. The analysis phase recognizes the tool from the probe (container
+// tags, brand) and the file name the tool gives it; the job's result and
+// history entry say which one it was.
+//
+// The fixups only go on with `source_fixups` (simple mode sets it), each
+// one leaves an explanation, and a tool's fixups can be turned off in
+// settings (set_source_fixups).
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceTool {
+    Obs,
+    Sharex,
+    Quicktime,
+    GameBar,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Fixup {
+    // Output at a constant frame rate (-fps_mode cfr)
+    ConstantFrameRate,
+    // The encoder's screen-content tuning, where it has one
+    ScreenTuning,
+    // Index at the front of MP4/MOV, so playback starts before the download ends
+    Faststart,
+}
+
+impl SourceTool {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SourceTool::Obs => "obs",
+            SourceTool::Sharex => "sharex",
+            SourceTool::Quicktime => "quicktime",
+            SourceTool::GameBar => "game_bar",
+        }
+    }
+
+    pub fn fixups(self) -> &'static [Fixup] {
+        match self {
+            SourceTool::Obs | SourceTool::Sharex => &[Fixup::ScreenTuning],
+            SourceTool::Quicktime => &[Fixup::ScreenTuning, Fixup::Faststart],
+            SourceTool::GameBar => &[Fixup::ConstantFrameRate, Fixup::ScreenTuning],
+        }
+    }
+}
+
+impl Fixup {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Fixup::ConstantFrameRate => "constant_frame_rate",
+            Fixup::ScreenTuning => "screen_tuning",
+            Fixup::Faststart => "faststart",
+        }
+    }
+}
+
+// What gave the tool away.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Detection {
+    pub tool: SourceTool,
+    pub signals: Vec<&'static str>,
+}
+
+fn tag<'a>(media: &'a MediaInfo, key: &str) -> Option<&'a str> {
+    media.tags.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v.as_str())
+}
+
+// "2024-03-01 12-34-56": the timestamp OBS and Game Bar name recordings with
+fn is_timestamp(s: &str) -> bool {
+    let pattern = b"dddd-dd-dd dd-dd-dd";
+    s.len() == pattern.len() && s.bytes().zip(pattern).all(|(c, p)| if *p == b'd' { c.is_ascii_digit() } else { c == *p })
+}
+
+// What's before a trailing timestamp (with its separating space), if the
+// stem ends in one.
+fn before_timestamp(stem: &str) -> Option<&str> {
+    let split = stem.len().checked_sub(19)?;
+    let (head, tail) = (stem.get(..split)?, stem.get(split..)?);
+    is_timestamp(tail).then_some(head)
+}
+
+// ShareX's default name: `<process>_<10 random letters and digits>`
+fn sharex_name(stem: &str) -> bool {
+    let Some((process, random)) = stem.rsplit_once('_') else { return false };
+    !process.is_empty() && random.len() == 10 && random.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+// Pure: the tool a recording came from, by its tags and its file name.
+// Needs a tag, or a tool-specific name on the container that tool writes.
+pub fn detect(file_name: &str, media: &MediaInfo) -> Option<Detection> {
+    if !media.has_video {
+        return None;
+    }
+    let (stem, ext) = file_name.rsplit_once('.').unwrap_or((file_name, ""));
+    let ext = ext.to_lowercase();
+    let container = media.container.as_deref().unwrap_or("");
+    let encoder = tag(media, "encoder").unwrap_or("");
+    let brand = tag(media, "major_brand").unwrap_or("").trim();
+    let found = |tool, signals: Vec<&'static str>| Some(Detection { tool, signals });
+
+    if encoder.to_lowercase().contains("obs") {
+        return found(SourceTool::Obs, vec!["encoder_tag"]);
+    }
+    if container.contains("matroska") && ext == "mkv" && before_timestamp(stem) == Some("") {
+        return found(SourceTool::Obs, vec!["file_name", "container"]);
+    }
+    if container.contains("mov") && brand == "qt" {
+        let apple = media.tags.keys().any(|k| k.starts_with("com.apple.quicktime"));
+        let named = stem.starts_with("Screen Recording");
+        match (apple, named) {
+            (true, true) => return found(SourceTool::Quicktime, vec!["brand", "apple_tags", "file_name"]),
+            (false, true) => return found(SourceTool::Quicktime, vec!["brand", "file_name"]),
+            _ => {}
+        }
+    }
+    // Game Bar writes its own muxer's MP4, without ffmpeg's encoder tag
+    if ext == "mp4" && encoder.is_empty() && before_timestamp(stem).is_some_and(|title| !title.trim().is_empty()) {
+        return found(SourceTool::GameBar, vec!["file_name", "no_encoder_tag"]);
+    }
+    if encoder.starts_with("Lavf") && sharex_name(stem) {
+        return found(SourceTool::Sharex, vec!["file_name", "encoder_tag"]);
+    }
+    None
+}
+
+// The settings haven't turned the tool's fixups off.
+pub fn enabled(app: &AppHandle, tool: SourceTool) -> bool {
+    !app.try_state::<SettingsStore>().is_some_and(|s| s.get().disabled_source_fixups.contains(&tool))
+}
+
+// Output arguments for a fixup with this encoder and container, or None
+// when it has nothing to do there.
+pub fn args(fixup: Fixup, encoder: &str, ext: &str, copy_video: bool) -> Option<Vec<String>> {
+    let s = |v: &str| v.to_string();
+    match fixup {
+        Fixup::ConstantFrameRate if !copy_video => Some(vec![s("-fps_mode"), s("cfr")]),
+        // Hardware encoders have no screen tuning
+        Fixup::ScreenTuning if !copy_video => match encoder {
+            // Flat areas and sharp edges are what animation tuning is for
+            "libx264" | "libx265" => Some(vec![s("-tune"), s("animation")]),
+            "libsvtav1" => Some(vec![s("-svtav1-params"), s("scm=1")]),
+            "libvpx-vp9" => Some(vec![s("-tune-content"), s("screen")]),
+            _ => None,
+        },
+        Fixup::Faststart if matches!(ext, "mp4" | "m4v" | "mov") => Some(vec![s("-movflags"), s("+faststart")]),
+        _ => None,
+    }
+}
+
+// Adds `args` to `codec_args`, merging -movflags into one that's there
+// already (ffmpeg keeps only the last).
+pub fn push_args(codec_args: &mut Vec<String>, args: Vec<String>) {
+    if args.first().map(String::as_str) == Some("-movflags") {
+        if let Some(i) = codec_args.iter().position(|a| a == "-movflags") {
+            if let Some(flags) = codec_args.get_mut(i + 1) {
+                flags.push_str(&args[1]);
+                return;
+            }
+        }
+    }
+    codec_args.extend(args);
+}
+
+// ==========================================
+// COMMAND: PER-TOOL FIXUPS
+// ==========================================
+#[tauri::command]
+pub fn set_source_fixups(store: State<'_, SettingsStore>, tool: SourceTool, enabled: bool) -> Result<(), String> {
+    store
+        .update(|s| {
+            s.disabled_source_fixups.retain(|t| *t != tool);
+            if !enabled {
+                s.disabled_source_fixups.push(tool);
+            }
+        })
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::tests::info;
+
+    // ffprobe's output for a recording: one video stream, `format` the
+    // container's name and `tags` its own tags
+    fn recording(format: &str, tags: &str) -> MediaInfo {
+        info(&format!(
+            r#"{{
+                "streams": [
+                    {{ "index": 0, "codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080, "pix_fmt": "yuv420p", "avg_frame_rate": "60/1" }},
+                    {{ "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "48000" }}
+                ],
+                "format": {{ "format_name": "{}", "duration": "30.000000", "tags": {{ {} }} }}
+            }}"#,
+            format, tags
+        ))
+    }
+
+    const MP4: &str = "mov,mp4,m4a,3gp,3g2,mj2";
+    const MKV: &str = "matroska,webm";
+
+    fn detected(file_name: &str, media: &MediaInfo) -> Option<(SourceTool, Vec<&'static str>)> {
+        detect(file_name, media).map(|d| (d.tool, d.signals))
+    }
+
+    #[test]
+    fn obs_is_known_by_its_encoder_tag() {
+        let obs = recording(MP4, r#""major_brand": "isom", "encoder": "obs-output module (libobs version 30.0.2)""#);
+        assert_eq!(detected("gameplay.mp4", &obs), Some((SourceTool::Obs, vec!["encoder_tag"])));
+        // Matroska keeps its tags in capitals
+        let obs = recording(MKV, r#""ENCODER": "OBS Studio (29.1.3)""#);
+        assert_eq!(detected("anything.mkv", &obs), Some((SourceTool::Obs, vec!["encoder_tag"])));
+    }
+
+    #[test]
+    fn obs_matroska_is_known_by_its_timestamp_name() {
+        let mkv = recording(MKV, r#""ENCODER": "Lavf60.16.100""#);
+        assert_eq!(detected("2024-03-01 12-34-56.mkv", &mkv), Some((SourceTool::Obs, vec!["file_name", "container"])));
+        // A title before the timestamp, or another extension, isn't OBS's name
+        assert_eq!(detected("Stream 2024-03-01 12-34-56.mkv", &mkv), None);
+        assert_eq!(detected("2024-03-01 12-34-56.webm", &mkv), None);
+        assert_eq!(detected("2024-03-01 12-34-5.mkv", &mkv), None);
+    }
+
+    #[test]
+    fn quicktime_needs_its_brand_and_its_name() {
+        let apple = r#""major_brand": "qt  ", "minor_version": "0", "com.apple.quicktime.make": "Apple", "com.apple.quicktime.software": "macOS 14.3""#;
+        let name = "Screen Recording 2024-03-01 at 12.34.56.mov";
+        assert_eq!(detected(name, &recording(MP4, apple)), Some((SourceTool::Quicktime, vec!["brand", "apple_tags", "file_name"])));
+        assert_eq!(detected(name, &recording(MP4, r#""major_brand": "qt  ""#)), Some((SourceTool::Quicktime, vec!["brand", "file_name"])));
+        // An iPhone video has the tags, but isn't a screen recording
+        assert_eq!(detected("IMG_0042.MOV", &recording(MP4, apple)), None);
+        // Renamed to MP4's brand, it isn't QuickTime's own file any more
+        assert_eq!(detected(name, &recording(MP4, r#""major_brand": "mp42""#)), None);
+    }
+
+    #[test]
+    fn game_bar_is_known_by_its_name_and_missing_encoder() {
+        let game_bar = recording(MP4, r#""major_brand": "mp42", "minor_version": "0", "compatible_brands": "mp41isom""#);
+        let name = "Minecraft 2024-03-01 12-34-56.mp4";
+        assert_eq!(detected(name, &game_bar), Some((SourceTool::GameBar, vec!["file_name", "no_encoder_tag"])));
+        // Anything ffmpeg wrote has an encoder tag
+        let remuxed = recording(MP4, r#""major_brand": "isom", "encoder": "Lavf60.16.100""#);
+        assert_eq!(detected(name, &remuxed), None);
+        // The window title is part of the name
+        assert_eq!(detected(" 2024-03-01 12-34-56.mp4", &game_bar), None);
+        assert_eq!(detected("Minecraft 2024-03-01 12-34-56.mkv", &game_bar), None);
+    }
+
+    #[test]
+    fn sharex_is_known_by_its_name_and_ffmpegs_tag() {
+        let sharex = recording(MP4, r#""major_brand": "isom", "encoder": "Lavf58.76.100""#);
+        assert_eq!(detected("chrome_AbC123xYz9.mp4", &sharex), Some((SourceTool::Sharex, vec!["file_name", "encoder_tag"])));
+        assert_eq!(detected("chrome_AbC123xYz.mp4", &sharex), None);
+        assert_eq!(detected("_AbC123xYz9.mp4", &sharex), None);
+        assert_eq!(detected("chrome_AbC123xYz9.mp4", &recording(MP4, r#""major_brand": "isom", "encoder": "HandBrake 1.7.2""#)), None);
+    }
+
+    #[test]
+    fn other_files_and_audio_are_nobodys() {
+        let camera = recording(MP4, r#""major_brand": "mp42", "encoder": "Lavf60.16.100""#);
+        assert_eq!(detected("holiday.mp4", &camera), None);
+        let podcast = info(
+            r#"{
+                "streams": [{ "index": 0, "codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "48000" }],
+                "format": { "format_name": "mov,mp4,m4a,3gp,3g2,mj2", "tags": { "encoder": "obs-output module (libobs version 30.0.2)" } }
+            }"#,
+        );
+        assert_eq!(detected("2024-03-01 12-34-56.m4a", &podcast), None);
+    }
+
+    #[test]
+    fn fixups_give_args_where_they_apply() {
+        let args = |fixup, encoder, ext, copy| args(fixup, encoder, ext, copy).map(|a| a.join(" "));
+        assert_eq!(args(Fixup::ConstantFrameRate, "libx264", "mp4", false).as_deref(), Some("-fps_mode cfr"));
+        assert_eq!(args(Fixup::ConstantFrameRate, "libx264", "mp4", true), None);
+        assert_eq!(args(Fixup::ScreenTuning, "libx265", "mkv", false).as_deref(), Some("-tune animation"));
+        assert_eq!(args(Fixup::ScreenTuning, "libsvtav1", "mkv", false).as_deref(), Some("-svtav1-params scm=1"));
+        assert_eq!(args(Fixup::ScreenTuning, "libvpx-vp9", "webm", false).as_deref(), Some("-tune-content screen"));
+        assert_eq!(args(Fixup::ScreenTuning, "h264_nvenc", "mp4", false), None);
+        assert_eq!(args(Fixup::Faststart, "copy", "mov", true).as_deref(), Some("-movflags +faststart"));
+        assert_eq!(args(Fixup::Faststart, "libx264", "mkv", false), None);
+
+        let mut codec_args: Vec<String> = ["-movflags", "use_metadata_tags"].map(String::from).to_vec();
+        push_args(&mut codec_args, vec!["-movflags".to_string(), "+faststart".to_string()]);
+        push_args(&mut codec_args, vec!["-fps_mode".to_string(), "cfr".to_string()]);
+        assert_eq!(codec_args, ["-movflags", "use_metadata_tags+faststart", "-fps_mode", "cfr"]);
+    }
+}
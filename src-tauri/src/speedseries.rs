@@ -0,0 +1,274 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Instant;
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::progress::ProgressUpdate;
+use crate::queue;
+
+// Points kept per live job before it's thinned out, and what history keeps
+const LIVE_POINTS: usize = 512;
+pub const HISTORY_POINTS: usize = 120;
+// Jobs kept at all (like job logs)
+const MAX_JOBS: usize = 20;
+
+// ==========================================
+// ENCODE SPEED SERIES (managed state)
+// ==========================================
+// For the speed sparkline: every parsed `speed=` / `fps=` of a job's
+// ffmpeg runs, stamped with the seconds since the job's first sample, so a
+// high-motion scene or a throttling laptop shows up as a dip. Live series
+// are capped at LIVE_POINTS by thinning, the finished one goes into the
+// history entry at HISTORY_POINTS, and each progress event carries the
+// sample it added so a live chart never has to poll.
+//
+// Thinning keeps, per bucket, the slowest and the fastest sample rather
+// than an average: an average would smooth a short dip away, which is
+// exactly what the chart is for.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+pub struct SpeedSample {
+    // Wall seconds since the job's first sample
+    pub at_secs: f64,
+    // Where in the media the encode was
+    pub out_time_secs: f64,
+    pub speed: Option<f64>,
+    pub fps: Option<f32>,
+}
+
+impl SpeedSample {
+    fn value(&self) -> Option<f64> {
+        self.speed.or(self.fps.map(f64::from))
+    }
+}
+
+// Pure: `samples` down to at most `points` (two per bucket), each bucket's
+// min and max kept in time order.
+pub fn downsample(samples: &[SpeedSample], points: usize) -> Vec<SpeedSample> {
+    if samples.len() <= points {
+        return samples.to_vec();
+    }
+    let buckets = (points / 2).max(1);
+    let size = samples.len().div_ceil(buckets);
+    let mut out = Vec::with_capacity(buckets * 2);
+    for bucket in samples.chunks(size) {
+        let by = |a: &&SpeedSample, b: &&SpeedSample| a.value().unwrap_or(f64::NAN).total_cmp(&b.value().unwrap_or(f64::NAN));
+        let min = bucket.iter().filter(|s| s.value().is_some()).min_by(by);
+        let max = bucket.iter().filter(|s| s.value().is_some()).max_by(by);
+        match (min, max) {
+            (Some(min), Some(max)) if min == max => out.push(*min),
+            (Some(min), Some(max)) => {
+                let (first, second) = if min.at_secs <= max.at_secs { (min, max) } else { (max, min) };
+                out.extend([*first, *second]);
+            }
+            _ => out.push(bucket[0]),
+        }
+    }
+    out
+}
+
+// One job's samples, thinned to half the cap whenever it overflows.
+pub struct Series {
+    started: Instant,
+    samples: Vec<SpeedSample>,
+    cap: usize,
+}
+
+impl Series {
+    pub fn new(cap: usize) -> Self {
+        Series { started: Instant::now(), samples: vec![], cap: cap.max(2) }
+    }
+
+    pub fn push(&mut self, out_time_secs: f64, speed: Option<f64>, fps: Option<f32>) -> SpeedSample {
+        let sample = SpeedSample { at_secs: self.started.elapsed().as_secs_f64(), out_time_secs, speed, fps };
+        self.samples.push(sample);
+        if self.samples.len() > self.cap {
+            self.samples = downsample(&self.samples, self.cap / 2);
+        }
+        sample
+    }
+
+    pub fn samples(&self) -> &[SpeedSample] {
+        &self.samples
+    }
+}
+
+#[derive(Default)]
+pub struct SpeedSeries {
+    series: Mutex<HashMap<u64, Series>>,
+    order: Mutex<VecDeque<u64>>,
+}
+
+impl SpeedSeries {
+    fn push(&self, job_id: u64, update: &ProgressUpdate) -> SpeedSample {
+        let mut order = self.order.lock().unwrap();
+        let mut series = self.series.lock().unwrap();
+        let sample = series
+            .entry(job_id)
+            .or_insert_with(|| {
+                order.push_back(job_id);
+                Series::new(LIVE_POINTS)
+            })
+            .push(update.out_time_secs, update.speed, update.fps);
+        while order.len() > MAX_JOBS {
+            if let Some(old) = order.pop_front() {
+                series.remove(&old);
+            }
+        }
+        sample
+    }
+
+    pub fn get(&self, job_id: u64) -> Option<Vec<SpeedSample>> {
+        self.series.lock().unwrap().get(&job_id).map(|s| s.samples().to_vec())
+    }
+}
+
+// A progress update of the job on this task; the sample for its event.
+// Updates without a speed or fps (the very first lines) aren't samples.
+pub fn record(app: &AppHandle, update: &ProgressUpdate) -> Option<SpeedSample> {
+    if update.speed.is_none() && update.fps.is_none() {
+        return None;
+    }
+    let (job_id, store) = (queue::running_job_id()?, app.try_state::<SpeedSeries>()?);
+    Some(store.push(job_id, update))
+}
+
+// The finished job's series, thinned for its history entry.
+pub fn for_history(app: &AppHandle) -> Vec<SpeedSample> {
+    let (Some(job_id), Some(store)) = (queue::running_job_id(), app.try_state::<SpeedSeries>()) else { return vec![] };
+    store.get(job_id).map(|s| downsample(&s, HISTORY_POINTS)).unwrap_or_default()
+}
+
+// ==========================================
+// COMMAND: GET JOB SPEED SERIES
+// ==========================================
+// Kept for the last MAX_JOBS jobs; older ones are in their history entry.
+#[tauri::command]
+pub fn get_job_speed_series(series: State<'_, SpeedSeries>, job_id: u64) -> Result<Vec<SpeedSample>, String> {
+    series.get(job_id).ok_or_else(|| format!("No speed samples kept for job {}", job_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One sample a second, at `speeds`
+    fn samples(speeds: &[f64]) -> Vec<SpeedSample> {
+        speeds.iter().enumerate().map(|(i, &s)| SpeedSample { at_secs: i as f64, out_time_secs: i as f64 * s, speed: Some(s), fps: None }).collect()
+    }
+
+    fn times(samples: &[SpeedSample]) -> Vec<f64> {
+        samples.iter().map(|s| s.at_secs).collect()
+    }
+
+    fn update(speed: f64) -> ProgressUpdate {
+        ProgressUpdate {
+            out_time_secs: 0.0,
+            percent: None,
+            speed: Some(speed),
+            fps: None,
+            out_size_bytes: None,
+            eta_secs: None,
+            bottleneck: None,
+            projected_size_bytes: None,
+            size_warning: false,
+        }
+    }
+
+    #[test]
+    fn a_series_within_the_points_is_kept_as_is() {
+        let series = samples(&[1.0, 0.5, 2.0, 1.0]);
+        assert_eq!(downsample(&series, 4), series);
+        assert_eq!(downsample(&series, 10), series);
+        assert!(downsample(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn buckets_split_evenly_and_keep_their_own_extremes() {
+        // 10 samples into 2 buckets of 5: the dip and the spike sit either
+        // side of the boundary, so each stays in its own bucket
+        let series = samples(&[1.0, 1.1, 1.2, 1.3, 0.1, 3.0, 1.4, 1.5, 1.6, 1.7]);
+        let thinned = downsample(&series, 4);
+        assert_eq!(times(&thinned), [3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(thinned[1].speed, Some(0.1));
+        assert_eq!(thinned[2].speed, Some(3.0));
+
+        // 7 don't divide into 2: buckets of 4 and 3
+        let series = samples(&[2.0, 1.0, 3.0, 2.0, 5.0, 4.0, 6.0]);
+        assert_eq!(times(&downsample(&series, 4)), [1.0, 2.0, 5.0, 6.0]);
+
+        // An odd number of points rounds down to whole buckets, and at least one
+        assert_eq!(downsample(&series, 5).len(), 4);
+        assert_eq!(times(&downsample(&series, 1)), [1.0, 6.0]);
+    }
+
+    #[test]
+    fn each_bucket_keeps_its_min_and_max_in_time_order() {
+        // The max before the min
+        let thinned = downsample(&samples(&[1.0, 4.0, 2.0, 0.5, 1.0, 1.0]), 2);
+        assert_eq!(times(&thinned), [1.0, 3.0]);
+        assert_eq!((thinned[0].speed, thinned[1].speed), (Some(4.0), Some(0.5)));
+        // A flat bucket keeps its ends, a bucket of one just that
+        assert_eq!(times(&downsample(&samples(&[1.0; 6]), 2)), [0.0, 5.0]);
+        assert_eq!(times(&downsample(&samples(&[1.0, 2.0, 1.0, 2.0, 1.0, 2.0, 5.0]), 6)), [0.0, 1.0, 4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn a_short_dip_and_a_spike_survive_thinning() {
+        // A long steady encode with one second of throttling and one of a
+        // static scene, which an average would smooth away
+        let mut speeds = vec![1.0; 1000];
+        speeds[537] = 0.2;
+        speeds[100] = 3.0;
+        let thinned = downsample(&samples(&speeds), HISTORY_POINTS);
+        assert!(thinned.len() <= HISTORY_POINTS);
+        assert!(thinned.iter().any(|s| s.at_secs == 537.0 && s.speed == Some(0.2)));
+        assert!(thinned.iter().any(|s| s.at_secs == 100.0 && s.speed == Some(3.0)));
+        assert!(times(&thinned).windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn fps_stands_in_for_a_missing_speed() {
+        let mut series = samples(&[1.0, 1.0, 1.0, 1.0, 1.0, 2.0]);
+        for sample in &mut series[..4] {
+            sample.speed = None;
+        }
+        series[3].fps = Some(12.0);
+        // Nothing to go by in the first bucket: its first sample
+        let thinned = downsample(&series, 4);
+        assert_eq!(times(&thinned), [0.0, 3.0, 4.0]);
+        assert_eq!(thinned[1].fps, Some(12.0));
+    }
+
+    #[test]
+    fn a_live_series_thins_to_half_its_cap_when_it_overflows() {
+        let mut series = Series::new(8);
+        for speed in [1.0, 1.0, 0.1, 1.0, 1.0, 1.0, 1.0, 1.0] {
+            series.push(0.0, Some(speed), None);
+        }
+        assert_eq!(series.samples().len(), 8);
+        let last = series.push(0.0, Some(2.0), None);
+        assert!(series.samples().len() <= 4);
+        assert!(series.samples().iter().any(|s| s.speed == Some(0.1)));
+        assert_eq!(series.samples().last(), Some(&last));
+        for _ in 0..100 {
+            series.push(0.0, Some(1.0), None);
+        }
+        assert!(series.samples().len() <= 8);
+        assert!(series.samples().iter().any(|s| s.speed == Some(0.1)));
+    }
+
+    #[test]
+    fn only_the_latest_jobs_keep_their_series() {
+        let store = SpeedSeries::default();
+        for job in 0..=MAX_JOBS as u64 {
+            store.push(job, &update(1.0));
+        }
+        assert_eq!(store.get(0), None);
+        assert_eq!(store.get(1).map(|s| s.len()), Some(1));
+        assert_eq!(store.get(MAX_JOBS as u64).map(|s| s.len()), Some(1));
+    }
+}
@@ -0,0 +1,95 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::AppHandle;
+use crate::paths;
+use crate::probe::{self, MediaInfo};
+
+// ==========================================
+// STAGED OUTPUTS: WRITE TMP -> VERIFY -> RENAME
+// ==========================================
+// ffmpeg writes next to the destination (same volume, so the final rename is
+// atomic) under a name that keeps the extension, since ffmpeg picks the
+// container from it. The real path is only touched once the temp file checks out.
+
+pub fn temp_path_for(dest: &Path) -> PathBuf {
+    let middle = format!(".tmp-{}", std::process::id());
+    dest.with_file_name(paths::file_name(".", dest.file_stem().unwrap_or_default(), &middle, dest.extension()))
+}
+
+// A stream-copy must come out with the same streams and (near enough) the
+// same duration as its source.
+pub async fn verify_remux(app: &AppHandle, source: &MediaInfo, staged: &Path) -> Result<(), String> {
+    let out = probe::probe(app, &staged.to_string_lossy()).await?;
+    same_media(source, &out)
+}
+
+fn same_media(source: &MediaInfo, out: &MediaInfo) -> Result<(), String> {
+    if out.streams.len() != source.streams.len() {
+        return Err(format!(
+            "Verification failed: expected {} streams, got {}",
+            source.streams.len(),
+            out.streams.len()
+        ));
+    }
+    if let (Some(a), Some(b)) = (source.duration, out.duration) {
+        if (a - b).abs() > 0.5 {
+            return Err(format!("Verification failed: duration changed from {:.2}s to {:.2}s", a, b));
+        }
+    }
+    Ok(())
+}
+
+// Moves the staged file over `dest` (replacing it, which is the point for in-place edits).
+pub fn commit(staged: &Path, dest: &Path) -> Result<(), String> {
+    fs::rename(staged, dest).map_err(|e| {
+        let _ = fs::remove_file(staged);
+        format!("Could not move the new file into place: {}", e)
+    })
+}
+
+pub fn discard(staged: &Path) {
+    let _ = fs::remove_file(staged);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::StreamInfo;
+
+    fn media(streams: usize, duration: Option<f64>) -> MediaInfo {
+        MediaInfo { streams: vec![StreamInfo::default(); streams], duration, ..Default::default() }
+    }
+
+    #[test]
+    fn the_temp_file_is_hidden_next_to_the_destination_with_its_extension() {
+        let staged = temp_path_for(Path::new("/videos/Bob's clip.mp4"));
+        assert_eq!(staged, PathBuf::from(format!("/videos/.Bob's clip.tmp-{}.mp4", std::process::id())));
+        assert_eq!(staged.extension().unwrap(), "mp4");
+    }
+
+    #[test]
+    fn a_remux_has_to_keep_every_stream_and_the_duration() {
+        assert_eq!(same_media(&media(3, Some(60.0)), &media(3, Some(60.4))), Ok(()));
+        assert_eq!(same_media(&media(3, Some(60.0)), &media(3, None)), Ok(()));
+        assert_eq!(same_media(&media(3, Some(60.0)), &media(2, Some(60.0))), Err("Verification failed: expected 3 streams, got 2".to_string()));
+        assert!(same_media(&media(3, Some(60.0)), &media(3, Some(30.0))).unwrap_err().contains("60.00s to 30.00s"));
+    }
+
+    #[test]
+    fn commit_replaces_the_destination() {
+        let dir = crate::cancel::TempDir::new(std::env::temp_dir().join(format!("staging-test-{}-commit", std::process::id()))).unwrap();
+        let dest = dir.path().join("clip.mp4");
+        fs::write(&dest, b"old").unwrap();
+        let staged = temp_path_for(&dest);
+        fs::write(&staged, b"new").unwrap();
+        commit(&staged, &dest).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"new");
+        assert!(!staged.exists());
+        // A failed move doesn't leave the temp file behind
+        fs::write(&staged, b"newer").unwrap();
+        assert!(commit(&staged, &dir.path().join("missing/clip.mp4")).is_err());
+        assert!(!staged.exists());
+        assert_eq!(fs::read(&dest).unwrap(), b"new");
+    }
+}
@@ -0,0 +1,169 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tauri::State;
+
+use crate::clock;
+use crate::history::{HistoryEntry, HistoryStore, JobStatus};
+use crate::resources::{self, ProcessReport};
+use crate::scoring::QualityScore;
+
+// Running totals over the whole history. Bytes only count successful jobs:
+// a failed job didn't save (or cost) anything.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct Totals {
+    pub jobs: u64,
+    pub successful_jobs: u64,
+    pub failed_jobs: u64,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    // input - output; negative when outputs grew overall
+    pub saved_bytes: i64,
+}
+
+impl Totals {
+    fn add(&mut self, entry: &HistoryEntry) {
+        self.jobs += 1;
+        match entry.status {
+            JobStatus::Success => {
+                self.successful_jobs += 1;
+                self.input_bytes += entry.input_bytes;
+                self.output_bytes += entry.output_bytes;
+                self.saved_bytes += entry.input_bytes as i64 - entry.output_bytes as i64;
+            }
+            JobStatus::Failed => self.failed_jobs += 1,
+        }
+    }
+
+    fn merge(&mut self, other: &Totals) {
+        self.jobs += other.jobs;
+        self.successful_jobs += other.successful_jobs;
+        self.failed_jobs += other.failed_jobs;
+        self.input_bytes += other.input_bytes;
+        self.output_bytes += other.output_bytes;
+        self.saved_bytes += other.saved_bytes;
+    }
+}
+
+// ==========================================
+// ONE JOB
+// ==========================================
+// Returned (flattened) with compress_video / compress_image results, so the
+// UI can tell whether compressing helped.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct JobStats {
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    // output / input: below 1 the file got smaller
+    pub ratio: Option<f64>,
+    // Negative when the output grew
+    pub percent_saved: Option<f64>,
+    // Wall-clock time of the job, probe to final move
+    pub encode_ms: u64,
+    // The output came out bigger and skip_if_larger discarded it
+    pub skipped: bool,
+    // The thread cap and priority the job's ffmpeg ran with
+    pub process: ProcessReport,
+    // compute_quality_score's VMAF / SSIM of the output (see scoring.rs)
+    pub quality_score: Option<QualityScore>,
+}
+
+impl JobStats {
+    pub fn new(input_bytes: u64, output_bytes: u64, took: Duration, skipped: bool) -> Self {
+        let ratio = (input_bytes > 0).then(|| output_bytes as f64 / input_bytes as f64);
+        JobStats {
+            input_bytes,
+            output_bytes,
+            ratio,
+            percent_saved: ratio.map(|r| (1.0 - r) * 100.0),
+            encode_ms: took.as_millis() as u64,
+            skipped,
+            process: resources::report(),
+            quality_score: None,
+        }
+    }
+}
+
+// The lifetime totals, with the share they add up to.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct SavingsSummary {
+    #[serde(flatten)]
+    pub totals: Totals,
+    // Of all successful jobs' input; negative when outputs grew overall
+    pub percent_saved: Option<f64>,
+}
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct MonthStats {
+    // "2026-03", in the system's local zone
+    pub month: String,
+    #[serde(flatten)]
+    pub totals: Totals,
+}
+
+// Also what history.rs keeps of pruned entries (history-folded.json)
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub lifetime: Totals,
+    months: BTreeMap<String, Totals>,
+}
+
+impl Stats {
+    // Used on load (which also covers histories written before stats existed)
+    // and after deletions, so the totals can never drift from the entries.
+    pub fn from_entries(entries: &[HistoryEntry]) -> Self {
+        let mut stats = Stats::default();
+        for entry in entries {
+            stats.add(entry);
+        }
+        stats
+    }
+
+    // `folded` (entries no longer in the history) plus the ones that are
+    pub fn with_folded(folded: &Stats, entries: &[HistoryEntry]) -> Self {
+        let mut stats = Stats::from_entries(entries);
+        stats.lifetime.merge(&folded.lifetime);
+        for (month, totals) in &folded.months {
+            stats.months.entry(month.clone()).or_default().merge(totals);
+        }
+        stats
+    }
+
+    // Rehearsed jobs saved nothing
+    pub fn add(&mut self, entry: &HistoryEntry) {
+        if entry.simulated {
+            return;
+        }
+        self.lifetime.add(entry);
+        self.months.entry(clock::local_month(entry.finished_at)).or_default().add(entry);
+    }
+
+    // Oldest month first
+    pub fn by_month(&self) -> Vec<MonthStats> {
+        self.months
+            .iter()
+            .map(|(month, totals)| MonthStats { month: month.clone(), totals: totals.clone() })
+            .collect()
+    }
+}
+
+// ==========================================
+// COMMANDS
+// ==========================================
+#[tauri::command]
+pub fn get_lifetime_stats(history: State<'_, HistoryStore>) -> Totals {
+    history.stats().lifetime
+}
+
+#[tauri::command]
+pub fn get_savings_summary(history: State<'_, HistoryStore>) -> SavingsSummary {
+    let totals = history.stats().lifetime;
+    let percent_saved = (totals.input_bytes > 0).then(|| totals.saved_bytes as f64 / totals.input_bytes as f64 * 100.0);
+    SavingsSummary { totals, percent_saved }
+}
+
+#[tauri::command]
+pub fn get_stats_by_month(history: State<'_, HistoryStore>) -> Vec<MonthStats> {
+    history.stats().by_month()
+}
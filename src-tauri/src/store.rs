@@ -0,0 +1,273 @@
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::events::{self, Event};
+use crate::history::HistoryStore;
+use crate::presets::PresetStore;
+use crate::queue::JobQueue;
+use crate::settings::SettingsStore;
+
+// ==========================================
+// CRASH-SAFE STORE FILES
+// ==========================================
+// settings.json, queue.json and history.jsonl are all written the same way
+// (and so are the smaller files: capabilities, undo journals, ladder cache):
+// into a temp file that's fsynced and renamed over the old one, so a power
+// cut leaves either the old or the new version. Before the rename, the old
+// version (if it still reads) is copied to `<name>.bak`. Loading a file
+// that doesn't parse falls back to that backup, and only then to defaults;
+// either way `store-recovered` tells the UI it happened.
+
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct Recovered {
+    pub store: String,
+    pub from_backup: bool,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileState {
+    Ok,
+    Missing,
+    Corrupt,
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".bak")
+}
+
+pub fn inspect(path: &Path, is_valid: impl Fn(&str) -> bool) -> FileState {
+    match fs::read_to_string(path) {
+        Ok(text) if is_valid(&text) => FileState::Ok,
+        Ok(_) => FileState::Corrupt,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => FileState::Missing,
+        Err(_) => FileState::Corrupt,
+    }
+}
+
+pub fn parses<T: DeserializeOwned>(text: &str) -> bool {
+    serde_json::from_str::<T>(text).is_ok()
+}
+
+// Makes the rename itself durable, not just the file's contents.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) {
+    if let Ok(d) = File::open(dir) {
+        let _ = d.sync_all();
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) {}
+
+// Temp file + fsync + rename, keeping the previous readable version as `.bak`.
+pub fn write_atomic(path: &Path, contents: &[u8], is_valid: impl Fn(&str) -> bool) -> Result<(), String> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    let tmp = with_suffix(path, ".tmp");
+    {
+        let mut file = File::create(&tmp).map_err(|e| e.to_string())?;
+        file.write_all(contents).map_err(|e| e.to_string())?;
+        file.sync_all().map_err(|e| e.to_string())?;
+    }
+    if inspect(path, is_valid) == FileState::Ok {
+        // A copy, so there's never a moment without the main file
+        if let Err(e) = fs::copy(path, backup_path(path)) {
+            println!("⚠️ Could not back up {}: {}", path.display(), e);
+        }
+    }
+    fs::rename(&tmp, path).map_err(|e| e.to_string())?;
+    sync_dir(dir);
+    Ok(())
+}
+
+// Refreshes the backup from a main file that just loaded cleanly (history
+// only appends, so this is when its backup catches up).
+pub fn refresh_backup(path: &Path) {
+    let _ = fs::copy(path, backup_path(path));
+}
+
+pub fn report_recovery(app: &AppHandle, store: &str, from_backup: bool) {
+    if from_backup {
+        println!("🩹 {} was damaged, restored it from the backup", store);
+    } else {
+        println!("🩹 {} and its backup were damaged, starting from defaults", store);
+    }
+    events::emit(app, Event::StoreRecovered(Recovered { store: store.to_string(), from_backup }));
+}
+
+// What a store file gave, before anyone is told about it.
+#[derive(Debug, PartialEq)]
+pub enum Loaded<T> {
+    // A first start, not damage
+    Missing,
+    Main(T),
+    // The main file didn't parse; this is its backup
+    Backup(T),
+    // Neither did the backup
+    Lost,
+}
+
+impl<T> Loaded<T> {
+    pub fn value(self) -> Option<T> {
+        match self {
+            Loaded::Main(value) | Loaded::Backup(value) => Some(value),
+            Loaded::Missing | Loaded::Lost => None,
+        }
+    }
+}
+
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Loaded<T> {
+    let text = match fs::read_to_string(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Loaded::Missing,
+        other => other.ok(),
+    };
+    if let Some(value) = text.and_then(|t| serde_json::from_str(&t).ok()) {
+        return Loaded::Main(value);
+    }
+    match fs::read_to_string(backup_path(path)).ok().and_then(|t| serde_json::from_str(&t).ok()) {
+        Some(value) => Loaded::Backup(value),
+        None => Loaded::Lost,
+    }
+}
+
+// A missing file is a first start, not damage: None without an event.
+pub fn load_json<T: DeserializeOwned>(app: &AppHandle, store: &str, path: &Path) -> Option<T> {
+    let loaded = read_json(path);
+    if matches!(loaded, Loaded::Backup(_) | Loaded::Lost) {
+        report_recovery(app, store, matches!(loaded, Loaded::Backup(_)));
+    }
+    loaded.value()
+}
+
+// write_atomic for a JSON file that's read back as the same type.
+pub fn save_json<T: Serialize + DeserializeOwned>(path: &Path, value: &T) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+    write_atomic(path, json.as_bytes(), parses::<T>)
+}
+
+// Removes a store file along with its backup and any leftover temp file.
+pub fn remove(path: &Path) {
+    for path in [path.to_path_buf(), backup_path(path), with_suffix(path, ".tmp")] {
+        let _ = fs::remove_file(path);
+    }
+}
+
+// ==========================================
+// COMMAND: REPAIR STORES
+// ==========================================
+#[derive(Serialize, Clone, Debug)]
+pub struct StoreStatus {
+    pub store: &'static str,
+    pub main: FileState,
+    pub backup: FileState,
+    // The main file was damaged and has been rewritten from what's loaded
+    pub repaired: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn status(
+    store: &'static str,
+    path: Option<PathBuf>,
+    is_valid: impl Fn(&str) -> bool + Copy,
+    rewrite: impl FnOnce() -> Result<(), String>,
+) -> Option<StoreStatus> {
+    let path = path?;
+    let main = inspect(&path, is_valid);
+    let backup = inspect(&backup_path(&path), is_valid);
+    let (repaired, error) = match main {
+        FileState::Corrupt => match rewrite() {
+            Ok(()) => (true, None),
+            Err(e) => (false, Some(e)),
+        },
+        _ => (false, None),
+    };
+    Some(StoreStatus { store, main, backup, repaired, error })
+}
+
+// Checks every store file and its backup. The loaded state already went
+// through recovery at startup, so a damaged main file is rewritten from it.
+#[tauri::command]
+pub fn repair_stores(
+    app: AppHandle,
+    settings: State<'_, SettingsStore>,
+    history: State<'_, HistoryStore>,
+    queue: State<'_, JobQueue>,
+    presets: State<'_, PresetStore>,
+) -> Vec<StoreStatus> {
+    let data_dir = app.path().app_data_dir().ok();
+    let file = |name: &str| data_dir.as_ref().map(|d| d.join(name));
+    [
+        status("settings", file("settings.json"), crate::settings::is_valid, || settings.persist()),
+        status("history", file("history.jsonl"), crate::history::is_valid, || history.persist()),
+        status("queue", file("queue.json"), crate::queue::is_valid, || queue.persist()),
+        // app_config_dir, not app_data_dir
+        status("presets", presets.path().map(Path::to_path_buf), crate::presets::is_valid, || presets.persist()),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::TempDir;
+
+    fn folder(name: &str) -> TempDir {
+        TempDir::new(std::env::temp_dir().join(format!("store-test-{}-{}", std::process::id(), name))).unwrap()
+    }
+
+    #[test]
+    fn truncated_file_falls_back_to_the_backup() {
+        let dir = folder("truncated");
+        let path = dir.path().join("state.json");
+        save_json(&path, &vec![1, 2, 3]).unwrap();
+        save_json(&path, &vec![4, 5, 6]).unwrap();
+        assert_eq!(read_json::<Vec<i32>>(&path), Loaded::Main(vec![4, 5, 6]));
+
+        // A crash halfway through someone else's write
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &text[..text.len() / 2]).unwrap();
+        assert_eq!(read_json::<Vec<i32>>(&path), Loaded::Backup(vec![1, 2, 3]));
+
+        // The damaged file never becomes the backup
+        save_json(&path, &vec![7]).unwrap();
+        assert_eq!(read_json::<Vec<i32>>(&backup_path(&path)), Loaded::Main(vec![1, 2, 3]));
+        assert_eq!(read_json::<Vec<i32>>(&path), Loaded::Main(vec![7]));
+    }
+
+    #[test]
+    fn missing_and_lost_are_told_apart() {
+        let dir = folder("lost");
+        let path = dir.path().join("state.json");
+        assert_eq!(read_json::<Vec<i32>>(&path), Loaded::Missing);
+        fs::write(&path, "[1, 2").unwrap();
+        assert_eq!(read_json::<Vec<i32>>(&path), Loaded::Lost);
+        fs::write(backup_path(&path), "[").unwrap();
+        assert_eq!(read_json::<Vec<i32>>(&path), Loaded::Lost);
+    }
+
+    #[test]
+    fn remove_takes_the_backup_too() {
+        let dir = folder("remove");
+        let path = dir.path().join("state.json");
+        save_json(&path, &1).unwrap();
+        save_json(&path, &2).unwrap();
+        remove(&path);
+        assert!(!path.exists() && !backup_path(&path).exists());
+    }
+}
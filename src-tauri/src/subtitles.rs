@@ -0,0 +1,663 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cancel::TempFile;
+use crate::outputs;
+use crate::probe::StreamInfo;
+
+// What happens to one subtitle stream of the input.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum SubtitleAction {
+    Copy,
+    Convert { codec: String },
+    Extract { path: String },
+    Drop,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SubtitleOutcome {
+    pub index: u32,
+    pub codec: String,
+    pub language: Option<String>,
+    #[serde(flatten)]
+    pub action: SubtitleAction,
+    pub warning: Option<String>,
+    // Set for a subtitle file next to the input (see SIDECAR FILES); `index`
+    // is then its place among them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sidecar: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SubKind {
+    Text,
+    Bitmap,
+    Unknown,
+}
+
+fn kind_of(codec: &str) -> SubKind {
+    match codec {
+        "subrip" | "srt" | "ass" | "ssa" | "webvtt" | "mov_text" | "text" => SubKind::Text,
+        "hdmv_pgs_subtitle" | "dvd_subtitle" | "dvb_subtitle" | "xsub" => SubKind::Bitmap,
+        _ => SubKind::Unknown,
+    }
+}
+
+// ==========================================
+// DECISION TABLE
+// ==========================================
+// Pure: (source codec, target container, extract flag) -> action (+ warning).
+// `extract_path` is only used for bitmap subs that mp4/webm can't hold.
+pub fn decide(codec: &str, container: &str, extract_incompatible: bool, extract_path: &str) -> (SubtitleAction, Option<String>) {
+    let kind = kind_of(codec);
+    match container {
+        // Matroska holds everything as-is
+        "mkv" => (SubtitleAction::Copy, None),
+
+        "mp4" | "m4v" | "mov" => match kind {
+            SubKind::Text if codec == "mov_text" => (SubtitleAction::Copy, None),
+            SubKind::Text => {
+                let warning = matches!(codec, "ass" | "ssa")
+                    .then(|| "ASS/SSA styling (fonts, colours, positioning) is lost in MP4".to_string());
+                (SubtitleAction::Convert { codec: "mov_text".to_string() }, warning)
+            }
+            SubKind::Bitmap => bitmap_fallback(codec, container, extract_incompatible, extract_path),
+            SubKind::Unknown => (SubtitleAction::Drop, Some(format!("Unknown subtitle codec '{}' dropped", codec))),
+        },
+
+        "webm" => match kind {
+            SubKind::Text if codec == "webvtt" => (SubtitleAction::Copy, None),
+            SubKind::Text => {
+                let warning = matches!(codec, "ass" | "ssa")
+                    .then(|| "ASS/SSA styling is lost when converting to WebVTT".to_string());
+                (SubtitleAction::Convert { codec: "webvtt".to_string() }, warning)
+            }
+            SubKind::Bitmap => bitmap_fallback(codec, container, extract_incompatible, extract_path),
+            SubKind::Unknown => (SubtitleAction::Drop, Some(format!("Unknown subtitle codec '{}' dropped", codec))),
+        },
+
+        _ => (SubtitleAction::Drop, Some(format!("{} files can't carry subtitles; '{}' track dropped", container.to_uppercase(), codec))),
+    }
+}
+
+fn bitmap_fallback(codec: &str, container: &str, extract: bool, extract_path: &str) -> (SubtitleAction, Option<String>) {
+    // Only PGS has a standalone .sup format
+    if extract && codec == "hdmv_pgs_subtitle" {
+        return (
+            SubtitleAction::Extract { path: extract_path.to_string() },
+            Some(format!("Image-based subtitles can't go into {}; saved to {}", container.to_uppercase(), extract_path)),
+        );
+    }
+    let hint = if extract { " (only PGS subtitles can be extracted to .sup)" } else { "" };
+    (
+        SubtitleAction::Drop,
+        Some(format!("Image-based '{}' subtitles can't go into {} and were dropped{}", codec, container.to_uppercase(), hint)),
+    )
+}
+
+// `<output stem>.<stream index>[.<lang>].sup` next to the output
+fn sidecar_path(output: &str, stream: &StreamInfo) -> String {
+    let out = Path::new(output);
+    let stem = out.file_stem().and_then(|s| s.to_str()).unwrap_or("subtitles");
+    let name = match &stream.language {
+        Some(lang) => format!("{}.{}.{}.sup", stem, stream.index, lang),
+        None => format!("{}.{}.sup", stem, stream.index),
+    };
+    out.with_file_name(name).to_string_lossy().to_string()
+}
+
+// Extracted subtitles are named after the output. When the final move had
+// to pick another name (see outputs.rs), they're renamed to match.
+pub fn follow_output(outcomes: &mut [SubtitleOutcome], reserved: &Path, committed: &Path) {
+    if reserved == committed {
+        return;
+    }
+    for o in outcomes {
+        let SubtitleAction::Extract { path } = &mut o.action else { continue };
+        let Some(to) = outputs::follow(Path::new(path.as_str()), reserved, committed) else { continue };
+        match outputs::rename_noreplace(Path::new(path.as_str()), &to) {
+            Ok(()) => {
+                let to = to.to_string_lossy().to_string();
+                o.warning = o.warning.take().map(|w| w.replace(path.as_str(), &to));
+                *path = to;
+            }
+            Err(e) => println!("⚠️ {} kept its name ({})", path, e),
+        }
+    }
+}
+
+// Decides every subtitle stream of the input for the given output container.
+pub fn plan(streams: &[StreamInfo], output: &str, container: &str, extract_incompatible: bool) -> Vec<SubtitleOutcome> {
+    streams
+        .iter()
+        .filter(|s| s.codec_type == "subtitle")
+        .map(|s| {
+            let codec = s.codec_name.clone().unwrap_or_default();
+            let (action, warning) = decide(&codec, container, extract_incompatible, &sidecar_path(output, s));
+            SubtitleOutcome { index: s.index, codec, language: s.language.clone(), action, warning, sidecar: None }
+        })
+        .collect()
+}
+
+// `-map`/`-c:s` args for the streams that go into the output. Output subtitle
+// stream numbering follows the order they are mapped in; sidecar files are
+// inputs 1, 2, ... in the order they come (see Sidecars::input_args).
+pub fn mapping_args(outcomes: &[SubtitleOutcome]) -> Vec<String> {
+    let mut args = vec![];
+    let mut out_index = 0;
+    let mut sidecar_input = 0;
+    for o in outcomes {
+        let codec = match &o.action {
+            SubtitleAction::Copy => "copy".to_string(),
+            SubtitleAction::Convert { codec } => codec.clone(),
+            SubtitleAction::Extract { .. } | SubtitleAction::Drop => continue,
+        };
+        args.push("-map".to_string());
+        match &o.sidecar {
+            Some(_) => {
+                sidecar_input += 1;
+                args.push(format!("{}:0", sidecar_input));
+            }
+            None => args.push(format!("0:{}", o.index)),
+        }
+        args.push(format!("-c:s:{}", out_index));
+        args.push(codec);
+        // A file has no language tag of its own; it comes from the name
+        if let (Some(_), Some(language)) = (&o.sidecar, &o.language) {
+            args.push(format!("-metadata:s:s:{}", out_index));
+            args.push(format!("language={}", language));
+        }
+        out_index += 1;
+    }
+    args
+}
+
+// Extra ffmpeg runs (input, args) for subs that go to sidecar files.
+pub fn extraction_args(input: &str, outcomes: &[SubtitleOutcome]) -> Vec<Vec<String>> {
+    outcomes
+        .iter()
+        .filter_map(|o| match &o.action {
+            SubtitleAction::Extract { path } => Some(vec![
+                "-i".to_string(), input.to_string(),
+                "-map".to_string(), format!("0:{}", o.index),
+                "-c:s".to_string(), "copy".to_string(),
+                "-y".to_string(), path.clone(),
+            ]),
+            _ => None,
+        })
+        .collect()
+}
+
+// ==========================================
+// SIDECAR FILES
+// ==========================================
+// `clip.srt` (or `clip.en.srt`, `clip.pt-BR.ass`, `clip.vtt`) next to
+// `clip.mp4` goes into the output as a soft track, converted like an
+// embedded one would be for the container, with the language from the
+// name. include_sidecar_subs "auto" takes every matching file, "none"
+// none, and a list exactly those files. Auto leaves out what the output
+// container can't carry instead of warning about it.
+//
+// Subtitle files from the Windows world are often Windows-1252 (western)
+// or Windows-1250 (central European) rather than UTF-8, which ffmpeg reads
+// as mojibake. Those are decoded into a UTF-8 copy in the work folder
+// before muxing; telling the two apart is a guess from the letters used,
+// and the result says when it was one.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SidecarMode {
+    Auto,
+    None,
+}
+
+// "auto" | "none" | ["clip.en.srt", ...] (relative to the input's folder)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum SidecarSelection {
+    Mode(SidecarMode),
+    Files(Vec<String>),
+}
+
+impl Default for SidecarSelection {
+    fn default() -> Self {
+        SidecarSelection::Mode(SidecarMode::Auto)
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SidecarSub {
+    pub path: String,
+    // As ffmpeg names it: subrip, ass, webvtt
+    pub codec: String,
+    // ISO 639-2, as mp4 and Matroska tag it
+    pub language: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Windows1252,
+    Windows1250,
+}
+
+// (ISO 639-1, ISO 639-2/B, ISO 639-2/T) for the languages subtitle files
+// usually come in
+const LANGUAGES: &[(&str, &str, &str)] = &[
+    ("en", "eng", "eng"), ("de", "ger", "deu"), ("fr", "fre", "fra"), ("es", "spa", "spa"),
+    ("it", "ita", "ita"), ("pt", "por", "por"), ("nl", "dut", "nld"), ("sv", "swe", "swe"),
+    ("da", "dan", "dan"), ("no", "nor", "nor"), ("nb", "nob", "nob"), ("fi", "fin", "fin"),
+    ("pl", "pol", "pol"), ("cs", "cze", "ces"), ("sk", "slo", "slk"), ("sl", "slv", "slv"),
+    ("hr", "hrv", "hrv"), ("sr", "srp", "srp"), ("hu", "hun", "hun"), ("ro", "rum", "ron"),
+    ("bg", "bul", "bul"), ("ru", "rus", "rus"), ("uk", "ukr", "ukr"), ("el", "gre", "ell"),
+    ("tr", "tur", "tur"), ("ar", "ara", "ara"), ("he", "heb", "heb"), ("fa", "per", "fas"),
+    ("hi", "hin", "hin"), ("ja", "jpn", "jpn"), ("zh", "chi", "zho"), ("ko", "kor", "kor"),
+    ("vi", "vie", "vie"), ("th", "tha", "tha"), ("id", "ind", "ind"), ("ms", "may", "msa"),
+];
+
+// Name parts that describe the track rather than the language
+const TRACK_TAGS: &[&str] = &["forced", "sdh", "cc", "hi", "default"];
+
+// Windows-1250 letters whose Windows-1252 meaning (Œ, £, ¥, ³, ¹, ¾, ì,
+// ø, ù, or nothing) rarely turns up in subtitles: ś ť ź Ł Ą ł ą ľ ě ř ů ...
+const CENTRAL_EUROPEAN: &[u8] = &[0x8C, 0x8D, 0x8F, 0x9C, 0x9D, 0x9F, 0xA3, 0xA5, 0xB3, 0xB9, 0xBE, 0xEC, 0xF8, 0xF9];
+// ... and the Windows-1252 ones that are rare in Windows-1250 text: à À ñ ¿
+const WESTERN: &[u8] = &[0xE0, 0xC0, 0xF1, 0xBF];
+
+pub fn codec_for(ext: &str) -> Option<&'static str> {
+    match ext.to_lowercase().as_str() {
+        "srt" => Some("subrip"),
+        "ass" | "ssa" => Some("ass"),
+        "vtt" => Some("webvtt"),
+        _ => None,
+    }
+}
+
+// Pure: "en", "eng", "deu", "pt-BR", "en_US" -> ISO 639-2/B.
+pub fn language_code(tag: &str) -> Option<&'static str> {
+    let tag = tag.to_lowercase();
+    let primary = tag.split(['-', '_']).next().unwrap_or_default();
+    LANGUAGES.iter().find(|(one, b, t)| primary == *one || primary == *b || primary == *t).map(|(_, b, _)| *b)
+}
+
+// Pure: the language in name parts between the base name and the extension
+// (`clip.en.forced.srt` -> [en, forced]). None when a part is neither a
+// language nor a track tag, since then it's another file's name (`clip.part2`).
+fn parts_language(parts: &[&str]) -> Option<Option<&'static str>> {
+    let mut language = None;
+    for part in parts {
+        match language_code(part) {
+            Some(code) => language = language.or(Some(code)),
+            None if TRACK_TAGS.contains(&part.to_lowercase().as_str()) => {}
+            None => return None,
+        }
+    }
+    Some(language)
+}
+
+// Pure: whether `file_name` is a subtitle file for an input named
+// `input_stem` (the name without its extension); its codec and language.
+pub fn sidecar_match(input_stem: &str, file_name: &str) -> Option<(&'static str, Option<&'static str>)> {
+    let (rest, ext) = file_name.rsplit_once('.')?;
+    let codec = codec_for(ext)?;
+    if rest.to_lowercase() == input_stem.to_lowercase() {
+        return Some((codec, None));
+    }
+    let (base, tags) = rest.split_at_checked(input_stem.len())?;
+    let tags = tags.strip_prefix('.')?;
+    if base.to_lowercase() != input_stem.to_lowercase() {
+        return None;
+    }
+    let parts: Vec<&str> = tags.split('.').collect();
+    Some((codec, parts_language(&parts)?))
+}
+
+// Pure: the language of a subtitle file named by hand, from the name parts
+// after its base name.
+pub fn file_language(file_name: &str) -> Option<&'static str> {
+    let stem = file_name.rsplit_once('.').map_or(file_name, |(stem, _)| stem);
+    stem.split('.').skip(1).find_map(language_code)
+}
+
+// Pure: the encoding of a subtitle file, and whether it was a guess. A BOM
+// or valid UTF-8 is certain; anything else is Windows-1250 when its letters
+// look central European, else Windows-1252.
+pub fn sniff_encoding(bytes: &[u8]) -> (TextEncoding, bool) {
+    if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        return (TextEncoding::Utf8, false);
+    }
+    if bytes.starts_with(&[0xFF, 0xFE]) {
+        return (TextEncoding::Utf16Le, false);
+    }
+    if bytes.starts_with(&[0xFE, 0xFF]) {
+        return (TextEncoding::Utf16Be, false);
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return (TextEncoding::Utf8, false);
+    }
+    let count = |set: &[u8]| bytes.iter().filter(|b| set.contains(b)).count();
+    match count(CENTRAL_EUROPEAN) > count(WESTERN) {
+        true => (TextEncoding::Windows1250, true),
+        false => (TextEncoding::Windows1252, true),
+    }
+}
+
+impl TextEncoding {
+    fn label(self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "UTF-8",
+            TextEncoding::Utf16Le => "UTF-16LE",
+            TextEncoding::Utf16Be => "UTF-16BE",
+            TextEncoding::Windows1252 => "Windows-1252",
+            TextEncoding::Windows1250 => "Windows-1250",
+        }
+    }
+}
+
+// Pure: `bytes` in `encoding` as UTF-8 text, without a BOM.
+pub fn to_utf8(bytes: &[u8], encoding: TextEncoding) -> String {
+    let encoding = match encoding {
+        TextEncoding::Utf8 => encoding_rs::UTF_8,
+        TextEncoding::Utf16Le => encoding_rs::UTF_16LE,
+        TextEncoding::Utf16Be => encoding_rs::UTF_16BE,
+        TextEncoding::Windows1252 => encoding_rs::WINDOWS_1252,
+        TextEncoding::Windows1250 => encoding_rs::WINDOWS_1250,
+    };
+    encoding.decode_with_bom_removal(bytes).0.into_owned()
+}
+
+// Subtitle files next to `input` that belong to it, by name.
+pub fn discover(input: &str) -> Vec<SidecarSub> {
+    let path = Path::new(input);
+    let (Some(dir), Some(stem)) = (path.parent(), path.file_stem().map(|s| s.to_string_lossy().to_string())) else {
+        return vec![];
+    };
+    let mut found: Vec<SidecarSub> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|f| f.file_type().is_ok_and(|t| t.is_file()))
+        .filter_map(|f| {
+            let name = f.file_name().to_string_lossy().to_string();
+            let (codec, language) = sidecar_match(&stem, &name)?;
+            Some(SidecarSub { path: f.path().to_string_lossy().to_string(), codec: codec.to_string(), language: language.map(String::from) })
+        })
+        .collect();
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+    found
+}
+
+// The files include_sidecar_subs picks for `input`.
+pub fn select(selection: &SidecarSelection, input: &str) -> Result<Vec<SidecarSub>, String> {
+    let files = match selection {
+        SidecarSelection::Mode(SidecarMode::Auto) => return Ok(discover(input)),
+        SidecarSelection::Mode(SidecarMode::None) => return Ok(vec![]),
+        SidecarSelection::Files(files) => files,
+    };
+    let dir = Path::new(input).parent().unwrap_or(Path::new(""));
+    files
+        .iter()
+        .map(|file| {
+            let path: PathBuf = dir.join(file);
+            if !path.is_file() {
+                return Err(format!("Subtitle file not found: {}", path.display()));
+            }
+            let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            let ext = path.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+            let codec = codec_for(&ext).ok_or_else(|| format!("{} isn't a subtitle file (.srt, .ass, .ssa or .vtt)", name))?;
+            Ok(SidecarSub { path: path.to_string_lossy().to_string(), codec: codec.to_string(), language: file_language(&name).map(String::from) })
+        })
+        .collect()
+}
+
+// The sidecar tracks of a job writing `container`, decided like embedded
+// ones. Surgical and resumable jobs don't take any (their streams are
+// their own), so they don't get here.
+pub fn sidecar_plan(selection: &SidecarSelection, input: &str, container: &str) -> Result<Vec<SubtitleOutcome>, String> {
+    let auto = matches!(selection, SidecarSelection::Mode(_));
+    let outcomes = select(selection, input)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, sub)| {
+            let (action, warning) = decide(&sub.codec, container, false, "");
+            SubtitleOutcome { index: i as u32, codec: sub.codec, language: sub.language, action, warning, sidecar: Some(sub.path) }
+        })
+        .filter(|o| !auto || o.action != SubtitleAction::Drop)
+        .collect();
+    Ok(outcomes)
+}
+
+// The files ffmpeg reads for the muxed sidecar tracks, in mapping order;
+// the UTF-8 copies go when this is dropped.
+pub struct Sidecars {
+    inputs: Vec<String>,
+    _copies: Vec<TempFile>,
+}
+
+impl Sidecars {
+    // `-i` for each, after the main input's. `cut_args` seeks them along
+    // with it.
+    pub fn input_args(&self, cut_args: &[String]) -> Vec<String> {
+        let mut args = vec![];
+        for input in &self.inputs {
+            args.extend(cut_args.iter().cloned());
+            args.extend(["-i".to_string(), input.clone()]);
+        }
+        args
+    }
+}
+
+// Reads every muxed sidecar, writing a UTF-8 copy next to `staged` for the
+// ones that aren't UTF-8. One that can't be read is dropped with a warning.
+pub fn prepare(outcomes: &mut [SubtitleOutcome], staged: &str) -> Result<Sidecars, String> {
+    let mut sidecars = Sidecars { inputs: vec![], _copies: vec![] };
+    for o in outcomes.iter_mut().filter(|o| o.action != SubtitleAction::Drop) {
+        let Some(path) = o.sidecar.clone() else { continue };
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                o.action = SubtitleAction::Drop;
+                o.warning = Some(format!("Subtitle file {} couldn't be read and was left out: {}", path, e));
+                continue;
+            }
+        };
+        let (encoding, guessed) = sniff_encoding(&bytes);
+        if encoding == TextEncoding::Utf8 {
+            sidecars.inputs.push(path);
+            continue;
+        }
+        let ext = Path::new(&path).extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+        let copy = TempFile::new(PathBuf::from(format!("{}.sub{}.{}", staged, o.index, ext)));
+        fs::write(copy.path(), to_utf8(&bytes, encoding)).map_err(|e| format!("Could not write {}: {}", copy.path().display(), e))?;
+        println!("🔤 {} is {}; muxing a UTF-8 copy", path, encoding.label());
+        if guessed {
+            let note = format!("{} isn't UTF-8 and was read as {} (a guess from its letters); check accented characters", path, encoding.label());
+            o.warning = Some(match o.warning.take() {
+                Some(w) => format!("{}; {}", w, note),
+                None => note,
+            });
+        }
+        sidecars.inputs.push(copy.path().to_string_lossy().to_string());
+        sidecars._copies.push(copy);
+    }
+    Ok(sidecars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(index: u32, codec_type: &str, codec: &str, language: Option<&str>) -> StreamInfo {
+        StreamInfo { index, codec_type: codec_type.to_string(), codec_name: Some(codec.to_string()), language: language.map(String::from), ..Default::default() }
+    }
+
+    fn convert(codec: &str) -> SubtitleAction {
+        SubtitleAction::Convert { codec: codec.to_string() }
+    }
+
+    #[test]
+    fn each_codec_gets_the_action_its_container_allows() {
+        let extract = SubtitleAction::Extract { path: "out.2.sup".to_string() };
+        let table = [
+            ("subrip", "mkv", SubtitleAction::Copy),
+            ("hdmv_pgs_subtitle", "mkv", SubtitleAction::Copy),
+            ("some_new_codec", "mkv", SubtitleAction::Copy),
+            ("mov_text", "mp4", SubtitleAction::Copy),
+            ("subrip", "mp4", convert("mov_text")),
+            ("webvtt", "mov", convert("mov_text")),
+            ("ass", "m4v", convert("mov_text")),
+            ("hdmv_pgs_subtitle", "mp4", extract.clone()),
+            ("dvd_subtitle", "mp4", SubtitleAction::Drop),
+            ("some_new_codec", "mp4", SubtitleAction::Drop),
+            ("webvtt", "webm", SubtitleAction::Copy),
+            ("subrip", "webm", convert("webvtt")),
+            ("mov_text", "webm", convert("webvtt")),
+            ("hdmv_pgs_subtitle", "webm", extract),
+            ("dvb_subtitle", "webm", SubtitleAction::Drop),
+            ("subrip", "gif", SubtitleAction::Drop),
+            ("subrip", "mp3", SubtitleAction::Drop),
+        ];
+        for (codec, container, action) in table {
+            assert_eq!(decide(codec, container, true, "out.2.sup").0, action, "{} in {}", codec, container);
+        }
+    }
+
+    #[test]
+    fn lossless_choices_come_without_a_warning() {
+        assert_eq!(decide("ass", "mkv", false, ""), (SubtitleAction::Copy, None));
+        assert_eq!(decide("mov_text", "mp4", false, ""), (SubtitleAction::Copy, None));
+        assert_eq!(decide("subrip", "mp4", false, ""), (convert("mov_text"), None));
+        let (_, warning) = decide("ssa", "mp4", false, "");
+        assert!(warning.unwrap().contains("styling"));
+        let (_, warning) = decide("subrip", "avi", false, "");
+        assert!(warning.unwrap().contains("AVI files can't carry subtitles"));
+    }
+
+    #[test]
+    fn bitmap_subs_are_only_extracted_when_asked_and_only_as_pgs() {
+        assert_eq!(decide("hdmv_pgs_subtitle", "mp4", false, "x.sup").0, SubtitleAction::Drop);
+        let (action, warning) = decide("hdmv_pgs_subtitle", "mp4", true, "x.sup");
+        assert_eq!(action, SubtitleAction::Extract { path: "x.sup".to_string() });
+        assert!(warning.unwrap().contains("saved to x.sup"));
+        let (action, warning) = decide("dvd_subtitle", "mp4", true, "x.sup");
+        assert_eq!(action, SubtitleAction::Drop);
+        assert!(warning.unwrap().contains("only PGS subtitles can be extracted"));
+    }
+
+    #[test]
+    fn the_plan_covers_every_subtitle_track_and_nothing_else() {
+        let streams = [
+            stream(0, "video", "h264", None),
+            stream(1, "audio", "aac", Some("eng")),
+            stream(2, "subtitle", "subrip", Some("eng")),
+            stream(3, "subtitle", "hdmv_pgs_subtitle", Some("ger")),
+            stream(4, "subtitle", "hdmv_pgs_subtitle", None),
+        ];
+        let plan = plan(&streams, "/out/film.mp4", "mp4", true);
+        assert_eq!(plan.iter().map(|o| o.index).collect::<Vec<_>>(), [2, 3, 4]);
+        assert_eq!(plan[0].action, convert("mov_text"));
+        assert_eq!(plan[0].language.as_deref(), Some("eng"));
+        // Extracted files are named after the output, stream and language
+        assert_eq!(plan[1].action, SubtitleAction::Extract { path: "/out/film.3.ger.sup".to_string() });
+        assert_eq!(plan[2].action, SubtitleAction::Extract { path: "/out/film.4.sup".to_string() });
+        assert!(plan.iter().all(|o| o.sidecar.is_none()));
+    }
+
+    #[test]
+    fn kept_tracks_are_muxed_soft_and_renumbered_in_order() {
+        let streams = [
+            stream(2, "subtitle", "subrip", None),
+            stream(3, "subtitle", "hdmv_pgs_subtitle", None),
+            stream(5, "subtitle", "mov_text", None),
+        ];
+        let outcomes = plan(&streams, "out.mp4", "mp4", true);
+        let args = mapping_args(&outcomes);
+        // Soft tracks only: the picture is never touched, the extracted one isn't mapped
+        assert_eq!(args, ["-map", "0:2", "-c:s:0", "mov_text", "-map", "0:5", "-c:s:1", "copy"]);
+        assert!(!args.iter().any(|a| a == "-vf" || a.contains("subtitles=")));
+        assert_eq!(extraction_args("in.mkv", &outcomes), [["-i", "in.mkv", "-map", "0:3", "-c:s", "copy", "-y", "out.3.sup"]]);
+    }
+
+    #[test]
+    fn nothing_is_mapped_for_a_container_without_subtitles() {
+        let outcomes = plan(&[stream(2, "subtitle", "subrip", None)], "out.gif", "gif", true);
+        assert_eq!(outcomes[0].action, SubtitleAction::Drop);
+        assert!(mapping_args(&outcomes).is_empty());
+        assert!(extraction_args("in.mkv", &outcomes).is_empty());
+    }
+
+    #[test]
+    fn language_tags_become_iso_639_2_b() {
+        for (tag, code) in [("en", "eng"), ("EN", "eng"), ("en_US", "eng"), ("pt-BR", "por"), ("deu", "ger"), ("ger", "ger"), ("zho", "chi")] {
+            assert_eq!(language_code(tag), Some(code), "{}", tag);
+        }
+        assert_eq!(language_code("forced"), None);
+        assert_eq!(language_code(""), None);
+    }
+
+    #[test]
+    fn sidecars_are_matched_by_name_with_language_and_track_tags() {
+        assert_eq!(sidecar_match("clip", "clip.srt"), Some(("subrip", None)));
+        assert_eq!(sidecar_match("Clip", "clip.VTT"), Some(("webvtt", None)));
+        assert_eq!(sidecar_match("clip", "clip.en.srt"), Some(("subrip", Some("eng"))));
+        assert_eq!(sidecar_match("clip", "clip.pt-BR.ass"), Some(("ass", Some("por"))));
+        assert_eq!(sidecar_match("clip", "clip.forced.de.ssa"), Some(("ass", Some("ger"))));
+        assert_eq!(sidecar_match("clip", "clip.sdh.srt"), Some(("subrip", None)));
+        // Another file's name, another file's subtitles, or not subtitles
+        assert_eq!(sidecar_match("clip", "clip.part2.srt"), None);
+        assert_eq!(sidecar_match("clip", "clip2.srt"), None);
+        assert_eq!(sidecar_match("clip", "other.en.srt"), None);
+        assert_eq!(sidecar_match("clip", "clip.en.txt"), None);
+        assert_eq!(sidecar_match("clip", "clip"), None);
+    }
+
+    #[test]
+    fn a_file_named_by_hand_takes_the_first_language_after_its_base_name() {
+        assert_eq!(file_language("episode.en.forced.srt"), Some("eng"));
+        assert_eq!(file_language("episode.forced.fr.srt"), Some("fre"));
+        assert_eq!(file_language("fr.srt"), None);
+        assert_eq!(file_language("episode.srt"), None);
+    }
+
+    #[test]
+    fn encodings_are_certain_for_boms_and_utf8_and_guessed_otherwise() {
+        assert_eq!(sniff_encoding(b"\xEF\xBB\xBFhello"), (TextEncoding::Utf8, false));
+        assert_eq!(sniff_encoding(b"\xFF\xFEh\0i\0"), (TextEncoding::Utf16Le, false));
+        assert_eq!(sniff_encoding(b"\xFE\xFF\0h\0i"), (TextEncoding::Utf16Be, false));
+        assert_eq!(sniff_encoding("caf\u{e9} \u{17c}\u{f3}\u{142}w".as_bytes()), (TextEncoding::Utf8, false));
+        // "Pan Ła, zażółć gęsią jaśń" in Windows-1250, "Señor, à bientôt" in Windows-1252
+        assert_eq!(sniff_encoding(b"Pan \xA3a, za\xBF\xF3\xB3\xE6 g\xEAsi\xB9 ja\x9C\xF1"), (TextEncoding::Windows1250, true));
+        assert_eq!(sniff_encoding(b"Se\xF1or, \xE0 bient\xF4t"), (TextEncoding::Windows1252, true));
+    }
+
+    #[test]
+    fn text_is_decoded_to_utf8_without_a_bom() {
+        assert_eq!(to_utf8(b"\xEF\xBB\xBFhi", TextEncoding::Utf8), "hi");
+        assert_eq!(to_utf8(b"\xFF\xFEh\0i\0", TextEncoding::Utf16Le), "hi");
+        assert_eq!(to_utf8(b"\xFE\xFF\0h\0i", TextEncoding::Utf16Be), "hi");
+        assert_eq!(to_utf8(b"Se\xF1or", TextEncoding::Windows1252), "Se\u{f1}or");
+        assert_eq!(to_utf8(b"\xA3\xF3d\x9F", TextEncoding::Windows1250), "\u{141}\u{f3}d\u{17a}");
+    }
+
+    #[test]
+    fn sidecar_tracks_are_mapped_from_their_own_inputs_with_their_language() {
+        let sidecar = |index: u32, codec: &str, language: Option<&str>| {
+            let (action, warning) = decide(codec, "mp4", false, "");
+            SubtitleOutcome { index, codec: codec.to_string(), language: language.map(String::from), action, warning, sidecar: Some(format!("s{}", index)) }
+        };
+        let mut outcomes = plan(&[stream(2, "subtitle", "mov_text", Some("eng"))], "out.mp4", "mp4", false);
+        outcomes.extend([sidecar(0, "subrip", Some("ger")), sidecar(1, "ass", None)]);
+        assert_eq!(
+            mapping_args(&outcomes),
+            [
+                "-map", "0:2", "-c:s:0", "copy",
+                "-map", "1:0", "-c:s:1", "mov_text", "-metadata:s:s:1", "language=ger",
+                "-map", "2:0", "-c:s:2", "mov_text",
+            ]
+        );
+    }
+}
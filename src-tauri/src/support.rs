@@ -0,0 +1,454 @@
+use serde::{Deserialize, Serialize};
+
+use crate::AppHandle;
+use crate::audio::AudioTarget;
+use crate::capabilities;
+
+// Bumped whenever the shape of `SupportMatrix` changes
+pub const MATRIX_VERSION: u32 = 4;
+
+// ==========================================
+// SUPPORT TABLES
+// ==========================================
+// Which codecs each output container can hold, and what each codec's
+// encoders can do. Request validation and `get_support_matrix` both read
+// these tables, so the UI's greyed-out combinations are exactly the ones
+// validation rejects. The video encoder a container gets is still picked
+// in encode_video; `default_encoder` is that choice.
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Video,
+    Audio,
+    Image,
+}
+
+// `codec` of a video request. Only the containers that take H.264 (the
+// GPU-friendly group) switch codec; webm/ogg/gif keep their own.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    #[default]
+    H264,
+    Hevc,
+    Av1,
+}
+
+impl VideoCodec {
+    // As in CODECS
+    pub fn name(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "h264",
+            VideoCodec::Hevc => "hevc",
+            VideoCodec::Av1 => "av1",
+        }
+    }
+
+    // For messages
+    pub fn label(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "H.264",
+            VideoCodec::Hevc => "HEVC",
+            VideoCodec::Av1 => "AV1",
+        }
+    }
+
+    // What runs when no hardware encoder does
+    pub fn cpu_encoder(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "libx264",
+            VideoCodec::Hevc => "libx265",
+            VideoCodec::Av1 => "libsvtav1",
+        }
+    }
+}
+
+pub struct Container {
+    pub ext: &'static str,
+    pub kind: MediaKind,
+    pub video: &'static [&'static str],
+    pub audio: &'static [&'static str],
+    pub subtitles: &'static [&'static str],
+}
+
+pub struct Codec {
+    pub name: &'static str,
+    pub kind: MediaKind,
+    // Encoders we'd use for it, preferred first
+    pub encoders: &'static [&'static str],
+    pub alpha: bool,
+    pub ten_bit: bool,
+    pub lossless: bool,
+}
+
+// Request options, per encoder, that actually change its output
+pub struct EncoderOptions {
+    pub encoder: &'static str,
+    pub options: &'static [&'static str],
+}
+
+const MP4_VIDEO: &[&str] = &["h264", "hevc", "av1"];
+const MP4_AUDIO: &[&str] = &["aac", "mp3", "opus", "flac"];
+const MKV_SUBS: &[&str] = &["subrip", "ass", "webvtt", "hdmv_pgs_subtitle", "dvd_subtitle"];
+
+pub const CONTAINERS: &[Container] = &[
+    Container { ext: "mp4", kind: MediaKind::Video, video: MP4_VIDEO, audio: MP4_AUDIO, subtitles: &["mov_text"] },
+    Container { ext: "m4v", kind: MediaKind::Video, video: MP4_VIDEO, audio: MP4_AUDIO, subtitles: &["mov_text"] },
+    Container { ext: "mov", kind: MediaKind::Video, video: &["h264", "hevc", "prores"], audio: &["aac", "mp3", "pcm_s16le"], subtitles: &["mov_text"] },
+    Container {
+        ext: "mkv",
+        kind: MediaKind::Video,
+        video: &["h264", "hevc", "av1", "vp9", "theora"],
+        audio: &["aac", "opus", "vorbis", "mp3", "flac", "pcm_s16le"],
+        subtitles: MKV_SUBS,
+    },
+    Container { ext: "webm", kind: MediaKind::Video, video: &["vp9", "av1"], audio: &["opus", "vorbis"], subtitles: &["webvtt"] },
+    Container { ext: "avi", kind: MediaKind::Video, video: &["h264", "mpeg4"], audio: &["aac", "mp3", "pcm_s16le"], subtitles: &[] },
+    Container { ext: "flv", kind: MediaKind::Video, video: &["h264"], audio: &["aac", "mp3"], subtitles: &[] },
+    Container { ext: "ts", kind: MediaKind::Video, video: &["h264", "hevc"], audio: &["aac", "mp3", "opus"], subtitles: &[] },
+    // ASF: what Windows Media Player opens, so no H.264 / AAC here
+    Container { ext: "wmv", kind: MediaKind::Video, video: &["wmv2", "msmpeg4v3"], audio: &["wmav2"], subtitles: &[] },
+    Container { ext: "ogv", kind: MediaKind::Video, video: &["theora"], audio: &["vorbis"], subtitles: &[] },
+    Container { ext: "ogg", kind: MediaKind::Video, video: &["theora"], audio: &["vorbis"], subtitles: &[] },
+    Container { ext: "gif", kind: MediaKind::Video, video: &["gif"], audio: &[], subtitles: &[] },
+    Container { ext: "jpg", kind: MediaKind::Image, video: &["mjpeg"], audio: &[], subtitles: &[] },
+    Container { ext: "jpeg", kind: MediaKind::Image, video: &["mjpeg"], audio: &[], subtitles: &[] },
+    Container { ext: "png", kind: MediaKind::Image, video: &["png"], audio: &[], subtitles: &[] },
+    Container { ext: "webp", kind: MediaKind::Image, video: &["webp"], audio: &[], subtitles: &[] },
+    Container { ext: "avif", kind: MediaKind::Image, video: &["av1"], audio: &[], subtitles: &[] },
+];
+
+pub const CODECS: &[Codec] = &[
+    Codec { name: "h264", kind: MediaKind::Video, encoders: &["libx264", "h264_nvenc", "h264_qsv", "h264_amf", "h264_videotoolbox"], alpha: false, ten_bit: true, lossless: true },
+    Codec { name: "hevc", kind: MediaKind::Video, encoders: &["libx265", "hevc_nvenc", "hevc_qsv", "hevc_amf", "hevc_videotoolbox"], alpha: false, ten_bit: true, lossless: true },
+    Codec { name: "av1", kind: MediaKind::Video, encoders: &["libsvtav1", "av1_nvenc", "av1_qsv", "libaom-av1"], alpha: false, ten_bit: true, lossless: true },
+    Codec { name: "vp9", kind: MediaKind::Video, encoders: &["libvpx-vp9"], alpha: true, ten_bit: true, lossless: true },
+    Codec { name: "theora", kind: MediaKind::Video, encoders: &["libtheora"], alpha: false, ten_bit: false, lossless: false },
+    Codec { name: "prores", kind: MediaKind::Video, encoders: &["prores_ks"], alpha: true, ten_bit: true, lossless: false },
+    Codec { name: "mpeg4", kind: MediaKind::Video, encoders: &["mpeg4"], alpha: false, ten_bit: false, lossless: false },
+    Codec { name: "wmv2", kind: MediaKind::Video, encoders: &["wmv2"], alpha: false, ten_bit: false, lossless: false },
+    Codec { name: "msmpeg4v3", kind: MediaKind::Video, encoders: &["msmpeg4"], alpha: false, ten_bit: false, lossless: false },
+    Codec { name: "gif", kind: MediaKind::Video, encoders: &["gif"], alpha: true, ten_bit: false, lossless: false },
+    Codec { name: "mjpeg", kind: MediaKind::Image, encoders: &["mjpeg"], alpha: false, ten_bit: false, lossless: false },
+    Codec { name: "png", kind: MediaKind::Image, encoders: &["png"], alpha: true, ten_bit: true, lossless: true },
+    Codec { name: "webp", kind: MediaKind::Image, encoders: &["libwebp"], alpha: true, ten_bit: false, lossless: true },
+    Codec { name: "aac", kind: MediaKind::Audio, encoders: &["aac"], alpha: false, ten_bit: false, lossless: false },
+    Codec { name: "mp3", kind: MediaKind::Audio, encoders: &["libmp3lame"], alpha: false, ten_bit: false, lossless: false },
+    Codec { name: "opus", kind: MediaKind::Audio, encoders: &["libopus"], alpha: false, ten_bit: false, lossless: false },
+    Codec { name: "vorbis", kind: MediaKind::Audio, encoders: &["libvorbis"], alpha: false, ten_bit: false, lossless: false },
+    Codec { name: "flac", kind: MediaKind::Audio, encoders: &["flac"], alpha: false, ten_bit: false, lossless: true },
+    Codec { name: "pcm_s16le", kind: MediaKind::Audio, encoders: &["pcm_s16le"], alpha: false, ten_bit: false, lossless: true },
+    Codec { name: "wmav2", kind: MediaKind::Audio, encoders: &["wmav2"], alpha: false, ten_bit: false, lossless: false },
+];
+
+pub const ENCODER_OPTIONS: &[EncoderOptions] = &[
+    EncoderOptions { encoder: "libx264", options: &["crf", "quality", "target_bitrate_kbps", "max_filesize_mb", "target_size_mb", "max_width", "max_height", "max_long_edge", "max_fps", "deinterlace", "overlay_text", "blur_regions", "resumable"] },
+    EncoderOptions { encoder: "h264_nvenc", options: &["crf", "quality", "target_bitrate_kbps", "max_filesize_mb", "target_size_mb", "max_width", "max_height", "max_long_edge", "max_fps", "deinterlace", "overlay_text", "blur_regions", "resumable"] },
+    EncoderOptions { encoder: "libx265", options: &["crf", "quality", "target_bitrate_kbps", "max_filesize_mb", "target_size_mb", "max_width", "max_height", "max_long_edge", "max_fps", "deinterlace", "overlay_text", "blur_regions", "resumable"] },
+    EncoderOptions { encoder: "hevc_nvenc", options: &["crf", "quality", "target_bitrate_kbps", "max_filesize_mb", "target_size_mb", "max_width", "max_height", "max_long_edge", "max_fps", "deinterlace", "overlay_text", "blur_regions", "resumable"] },
+    EncoderOptions { encoder: "libsvtav1", options: &["crf", "quality", "target_bitrate_kbps", "max_filesize_mb", "target_size_mb", "max_width", "max_height", "max_long_edge", "max_fps", "deinterlace", "overlay_text", "blur_regions", "resumable"] },
+    EncoderOptions { encoder: "av1_nvenc", options: &["crf", "quality", "target_bitrate_kbps", "max_filesize_mb", "target_size_mb", "max_width", "max_height", "max_long_edge", "max_fps", "deinterlace", "overlay_text", "blur_regions", "resumable"] },
+    EncoderOptions { encoder: "libvpx-vp9", options: &["crf", "quality", "target_bitrate_kbps", "max_filesize_mb", "target_size_mb", "max_width", "max_height", "max_long_edge", "max_fps", "deinterlace", "overlay_text", "blur_regions", "resumable"] },
+    EncoderOptions { encoder: "libtheora", options: &["quality", "target_bitrate_kbps", "max_filesize_mb", "target_size_mb", "max_width", "max_height", "max_long_edge", "max_fps", "deinterlace", "overlay_text", "blur_regions", "resumable"] },
+    EncoderOptions { encoder: "wmv2", options: &["crf", "quality", "target_bitrate_kbps", "max_filesize_mb", "target_size_mb", "max_width", "max_height", "max_long_edge", "max_fps", "deinterlace", "overlay_text", "blur_regions"] },
+    EncoderOptions { encoder: "gif", options: &["gif_fps", "gif_width", "max_width", "max_height", "max_long_edge", "max_fps"] },
+    EncoderOptions { encoder: "mjpeg", options: &["width", "height", "quality"] },
+    EncoderOptions { encoder: "png", options: &["width", "height", "quality"] },
+    EncoderOptions { encoder: "libwebp", options: &["width", "height", "quality"] },
+    EncoderOptions { encoder: "aac", options: &["bitrate_kbps", "quality"] },
+    EncoderOptions { encoder: "libmp3lame", options: &["bitrate_kbps", "quality", "keep_cover"] },
+    EncoderOptions { encoder: "libopus", options: &["bitrate_kbps", "quality", "keep_cover"] },
+    EncoderOptions { encoder: "libvorbis", options: &["bitrate_kbps", "quality", "keep_cover"] },
+    EncoderOptions { encoder: "flac", options: &["compression_level", "keep_cover"] },
+    EncoderOptions { encoder: "pcm_s16le", options: &[] },
+];
+
+pub fn container(ext: &str) -> Option<&'static Container> {
+    CONTAINERS.iter().find(|c| c.ext == ext)
+}
+
+fn codec(name: &str) -> Option<&'static Codec> {
+    CODECS.iter().find(|c| c.name == name)
+}
+
+// Encoder ffmpeg uses for an image output ("jpg" -> mjpeg)
+pub fn image_encoder(ext: &str) -> Option<&'static str> {
+    let container = container(ext).filter(|c| c.kind == MediaKind::Image)?;
+    codec(container.video.first()?)?.encoders.first().copied()
+}
+
+// The codec an encoder writes ("libx265" -> hevc).
+pub fn codec_of(encoder: &str) -> Option<&'static str> {
+    CODECS.iter().find(|c| c.encoders.contains(&encoder)).map(|c| c.name)
+}
+
+pub fn video_container(ext: &str) -> Option<&'static Container> {
+    container(ext).filter(|c| c.kind == MediaKind::Video)
+}
+
+pub fn accepts_video(ext: &str, codec: &str) -> bool {
+    container(ext).is_some_and(|c| c.video.contains(&codec))
+}
+
+pub fn honors(encoder: &str, option: &str) -> bool {
+    ENCODER_OPTIONS.iter().find(|e| e.encoder == encoder).is_some_and(|e| e.options.contains(&option))
+}
+
+pub fn video_extensions() -> Vec<&'static str> {
+    CONTAINERS.iter().filter(|c| c.kind == MediaKind::Video).map(|c| c.ext).collect()
+}
+
+// The encoder encode_video picks for a video container (before the HDR and
+// copy overrides). The NVENC encoder of the codec stands in for whichever
+// hardware encoder hardware.rs finds.
+pub fn default_video_encoder(ext: &str, auto_gpu: bool, codec: VideoCodec) -> Option<&'static str> {
+    match ext {
+        _ if codec != VideoCodec::H264 && accepts_video(ext, codec.name()) && video_container(ext).is_some() => Some(match (auto_gpu, codec) {
+            (true, VideoCodec::Hevc) => "hevc_nvenc",
+            (true, VideoCodec::Av1) => "av1_nvenc",
+            _ => codec.cpu_encoder(),
+        }),
+        "webm" => Some("libvpx-vp9"),
+        "ogv" | "ogg" => Some("libtheora"),
+        "wmv" => Some("wmv2"),
+        "gif" => Some("gif"),
+        _ if auto_gpu && accepts_video(ext, "h264") => Some("h264_nvenc"),
+        _ => video_container(ext).map(|_| "libx264"),
+    }
+}
+
+fn default_audio_encoder(ext: &str) -> Option<&'static str> {
+    match ext {
+        "webm" => Some("libopus"),
+        "ogv" | "ogg" => Some("libvorbis"),
+        "wmv" => Some("wmav2"),
+        "gif" => None,
+        _ => video_container(ext).map(|_| "aac"),
+    }
+}
+
+// ==========================================
+// COMMAND CHECK (before anything is spawned)
+// ==========================================
+// Start of the error, which errors::classify reports as UnsupportedFormat
+pub const CODEC_MISMATCH: &str = "Codec not supported by container";
+
+// ffmpeg's -f names for the containers above; image2 writes any image
+// format, so the extension says which
+fn format_ext(format: &str) -> Option<&str> {
+    match format {
+        "matroska" => Some("mkv"),
+        "asf" => Some("wmv"),
+        "mpegts" => Some("ts"),
+        "image2" | "image2pipe" => None,
+        format => Some(format),
+    }
+}
+
+// Pure: every built ffmpeg command goes through this before it runs, so an
+// encoder the output's container can't hold fails here, with what it does
+// take, rather than as a mux error (or a file players refuse) later.
+// Only what it knows is checked: the output by its -f or extension, the
+// -c:v / -c:a encoders listed in CODECS; copies keep the source's codec
+// and null outputs hold anything.
+pub fn check_command(args: &[String]) -> Result<(), String> {
+    let Some(output) = args.last() else { return Ok(()) };
+    // Output options come after the last input
+    let start = args.iter().rposition(|a| a == "-i").map_or(0, |i| i + 2).min(args.len());
+    let output_args = &args[start..];
+    let value = |flags: &[&str]| output_args.windows(2).rev().find(|w| flags.contains(&w[0].as_str())).map(|w| w[1].as_str());
+    let ext = match value(&["-f"]).and_then(format_ext) {
+        Some(ext) => ext.to_string(),
+        None => std::path::Path::new(output).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default(),
+    };
+    let Some(container) = container(&ext) else { return Ok(()) };
+    for (flags, kind, allowed) in [(&["-c:v", "-vcodec"], "video", container.video), (&["-c:a", "-acodec"], "audio", container.audio)] {
+        let Some(encoder) = value(flags).filter(|e| !matches!(*e, "copy" | "none")) else { continue };
+        let Some(codec) = CODECS.iter().find(|c| c.encoders.contains(&encoder)) else { continue };
+        if !allowed.contains(&codec.name) {
+            let takes = if allowed.is_empty() { "none".to_string() } else { allowed.join(", ") };
+            return Err(format!("{}: .{} can't hold {} {} ({}); it takes {} {}", CODEC_MISMATCH, ext, codec.name, kind, encoder, kind, takes));
+        }
+    }
+    Ok(())
+}
+
+// ==========================================
+// COMMAND: GET SUPPORT MATRIX
+// ==========================================
+#[derive(Serialize, Clone, Debug)]
+pub struct ContainerEntry {
+    pub ext: &'static str,
+    pub kind: MediaKind,
+    pub video_codecs: Vec<&'static str>,
+    pub audio_codecs: Vec<&'static str>,
+    pub subtitle_codecs: Vec<&'static str>,
+    // What a job writing this container encodes with by default
+    pub default_video_encoder: Option<&'static str>,
+    pub default_audio_encoder: Option<&'static str>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct EncoderEntry {
+    pub name: &'static str,
+    // In the cached ffmpeg encoder list; false when it can't be read
+    pub installed: bool,
+    pub options: Vec<&'static str>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct CodecEntry {
+    pub codec: &'static str,
+    pub kind: MediaKind,
+    pub encoders: Vec<EncoderEntry>,
+    pub alpha: bool,
+    pub ten_bit: bool,
+    pub lossless: bool,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SupportMatrix {
+    pub version: u32,
+    // The ffmpeg encoder list was available; otherwise `installed` is all false
+    pub capabilities_known: bool,
+    pub containers: Vec<ContainerEntry>,
+    pub codecs: Vec<CodecEntry>,
+}
+
+fn audio_containers() -> Vec<ContainerEntry> {
+    ["mp3", "m4a", "aac", "opus", "ogg", "oga", "flac", "wav"]
+        .into_iter()
+        .filter_map(|ext| Some((ext, AudioTarget::from_extension(ext)?)))
+        .map(|(ext, target)| ContainerEntry {
+            ext,
+            kind: MediaKind::Audio,
+            video_codecs: vec![],
+            audio_codecs: CODECS.iter().filter(|c| c.encoders.contains(&target.codec())).map(|c| c.name).collect(),
+            subtitle_codecs: vec![],
+            default_video_encoder: None,
+            default_audio_encoder: Some(target.codec()),
+        })
+        .collect()
+}
+
+pub fn matrix(caps: Option<&capabilities::Capabilities>) -> SupportMatrix {
+    let mut containers: Vec<ContainerEntry> = CONTAINERS
+        .iter()
+        .map(|c| ContainerEntry {
+            ext: c.ext,
+            kind: c.kind,
+            video_codecs: c.video.to_vec(),
+            audio_codecs: c.audio.to_vec(),
+            subtitle_codecs: c.subtitles.to_vec(),
+            default_video_encoder: match c.kind {
+                MediaKind::Video => default_video_encoder(c.ext, false, VideoCodec::H264),
+                _ => c.video.first().and_then(|&v| codec(v)).and_then(|k| k.encoders.first().copied()),
+            },
+            default_audio_encoder: default_audio_encoder(c.ext),
+        })
+        .collect();
+    containers.extend(audio_containers());
+    let codecs = CODECS
+        .iter()
+        .map(|c| CodecEntry {
+            codec: c.name,
+            kind: c.kind,
+            encoders: c
+                .encoders
+                .iter()
+                .map(|&name| EncoderEntry {
+                    name,
+                    installed: caps.is_some_and(|caps| caps.has_encoder(name)),
+                    options: ENCODER_OPTIONS.iter().find(|e| e.encoder == name).map(|e| e.options.to_vec()).unwrap_or_default(),
+                })
+                .collect(),
+            alpha: c.alpha,
+            ten_bit: c.ten_bit,
+            lossless: c.lossless,
+        })
+        .collect();
+    SupportMatrix { version: MATRIX_VERSION, capabilities_known: caps.is_some(), containers, codecs }
+}
+
+#[tauri::command]
+pub async fn get_support_matrix(app: AppHandle) -> SupportMatrix {
+    let caps = capabilities::get(&app).await.ok();
+    matrix(caps.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn every_container_codec_is_in_the_codec_table() {
+        for container in CONTAINERS {
+            for name in container.video.iter().chain(container.audio) {
+                assert!(codec(name).is_some(), ".{} lists {}, which CODECS doesn't have", container.ext, name);
+            }
+        }
+    }
+
+    #[test]
+    fn default_encoders_fit_their_containers() {
+        for ext in video_extensions() {
+            for (gpu, codec) in [(false, VideoCodec::H264), (true, VideoCodec::H264), (false, VideoCodec::Hevc), (true, VideoCodec::Av1)] {
+                let encoder = default_video_encoder(ext, gpu, codec).unwrap();
+                assert!(accepts_video(ext, codec_of(encoder).unwrap()), ".{} got {}", ext, encoder);
+                assert_eq!(check_command(&args(&["-i", "in.mov", "-c:v", encoder, &format!("out.{}", ext)])), Ok(()), ".{} got {}", ext, encoder);
+            }
+        }
+        assert_eq!(default_video_encoder("mp4", true, VideoCodec::Hevc), Some("hevc_nvenc"));
+        assert_eq!(default_video_encoder("webm", false, VideoCodec::Hevc), Some("libvpx-vp9"));
+        assert_eq!(default_video_encoder("mkv", false, VideoCodec::Av1), Some("libsvtav1"));
+        assert_eq!(default_video_encoder("png", false, VideoCodec::H264), None);
+    }
+
+    #[test]
+    fn commands_the_container_cant_hold_fail_before_running() {
+        let error = check_command(&args(&["-i", "in.mov", "-c:v", "libx265", "out.webm"])).unwrap_err();
+        assert!(error.starts_with(CODEC_MISMATCH));
+        assert!(error.contains(".webm can't hold hevc video (libx265); it takes video vp9, av1"));
+        assert!(check_command(&args(&["-i", "in.mov", "-c:v", "libx264", "-c:a", "aac", "out.wmv"])).is_err());
+        // -f wins over the extension
+        assert!(check_command(&args(&["-i", "in.mov", "-c:v", "libx264", "-f", "webm", "out.mp4"])).is_err());
+        assert!(check_command(&args(&["-i", "in.mov", "-c:a", "aac", "out.gif"])).unwrap_err().contains("it takes audio none"));
+    }
+
+    #[test]
+    fn copies_null_outputs_and_input_options_pass() {
+        assert_eq!(check_command(&args(&["-i", "in.mkv", "-c:v", "copy", "-c:a", "copy", "out.webm"])), Ok(()));
+        assert_eq!(check_command(&args(&["-i", "in.mov", "-c:v", "libx265", "-f", "null", "-"])), Ok(()));
+        // A decoder chosen for the input isn't the output's encoder
+        assert_eq!(check_command(&args(&["-c:v", "h264", "-i", "in.mp4", "-c:v", "libvpx-vp9", "out.webm"])), Ok(()));
+        assert_eq!(check_command(&[]), Ok(()));
+    }
+
+    #[test]
+    fn lookups_by_encoder_and_extension() {
+        assert_eq!(codec_of("hevc_qsv"), Some("hevc"));
+        assert_eq!(codec_of("nonsense"), None);
+        assert_eq!(image_encoder("jpg"), Some("mjpeg"));
+        assert_eq!(image_encoder("webp"), Some("libwebp"));
+        assert_eq!(image_encoder("mp4"), None);
+        assert!(honors("libx264", "crf") && !honors("libtheora", "crf") && !honors("unknown", "crf"));
+    }
+
+    #[test]
+    fn the_matrix_marks_installed_encoders() {
+        let caps = capabilities::Capabilities { encoders: ["libx264".to_string()].into_iter().collect(), ..Default::default() };
+        let matrix = matrix(Some(&caps));
+        assert_eq!(matrix.version, MATRIX_VERSION);
+        assert!(matrix.capabilities_known);
+        let h264 = matrix.codecs.iter().find(|c| c.codec == "h264").unwrap();
+        assert!(h264.encoders.iter().all(|e| e.installed == (e.name == "libx264")));
+        let mp3 = matrix.containers.iter().find(|c| c.ext == "mp3").unwrap();
+        assert_eq!(mp3.default_audio_encoder, Some("libmp3lame"));
+        assert!(!super::matrix(None).capabilities_known);
+    }
+}
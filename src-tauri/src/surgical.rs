@@ -0,0 +1,248 @@
+use serde::Serialize;
+
+use crate::probe::{MediaInfo, StreamInfo};
+
+// ==========================================
+// SURGICAL MODE: CHANGE ONLY WHAT WAS ASKED FOR
+// ==========================================
+// For archivists: every stream is kept, only the targeted ones (the video
+// unless it's copied, and the audio) are re-encoded, and nothing else gets
+// touched: no filters, no autorotate, no dropped tags or chapters. The promise
+// is written down per stream before the encode and checked against ffprobe
+// of the output afterwards; any difference fails the job.
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamFate {
+    // Not targeted by the job, passed through as-is
+    Untouched,
+    // Targeted, and explicitly stream-copied (video in "copy" mode)
+    Copied,
+    ReEncoded,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LedgerEntry {
+    pub index: u32,
+    pub codec_type: String,
+    pub fate: StreamFate,
+    // Encoder used for re-encoded streams
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoder: Option<String>,
+    // Set once the output has been probed
+    pub verified: bool,
+}
+
+// codec_name ffprobe reports for what an encoder writes.
+fn codec_of_encoder(encoder: &str) -> &str {
+    match encoder {
+        "libx264" | "h264_nvenc" | "h264_videotoolbox" => "h264",
+        "libx265" => "hevc",
+        "libvpx-vp9" => "vp9",
+        "libtheora" => "theora",
+        "libopus" => "opus",
+        "libvorbis" => "vorbis",
+        other => other,
+    }
+}
+
+// What happens to each input stream. Cover art counts as "untouched": only
+// real video is a target.
+pub fn plan(streams: &[StreamInfo], video_encoder: &str, audio_encoder: &str) -> Vec<LedgerEntry> {
+    streams
+        .iter()
+        .map(|s| {
+            let (fate, encoder) = match s.codec_type.as_str() {
+                "video" if !s.attached_pic && video_encoder == "copy" => (StreamFate::Copied, None),
+                "video" if !s.attached_pic => (StreamFate::ReEncoded, Some(video_encoder.to_string())),
+                "audio" => (StreamFate::ReEncoded, Some(audio_encoder.to_string())),
+                _ => (StreamFate::Untouched, None),
+            };
+            LedgerEntry { index: s.index, codec_type: s.codec_type.clone(), fate, encoder, verified: false }
+        })
+        .collect()
+}
+
+// Output-side arguments: every stream mapped, codecs chosen per stream (so
+// cover art isn't caught by `-c:v`), tags and chapters carried over, and no
+// muxer version tag written.
+pub fn stream_args(ledger: &[LedgerEntry]) -> Vec<String> {
+    let mut args: Vec<String> = ["-map", "0", "-map_metadata", "0", "-map_chapters", "0", "-fflags", "+bitexact"]
+        .map(String::from)
+        .to_vec();
+    for (i, entry) in ledger.iter().enumerate() {
+        args.push(format!("-c:{}", i));
+        args.push(entry.encoder.clone().unwrap_or_else(|| "copy".to_string()));
+    }
+    args
+}
+
+// Input-side arguments. Re-encoding would otherwise apply the rotation
+// side data to the pixels.
+pub fn input_args(ledger: &[LedgerEntry]) -> Vec<String> {
+    let reencodes_video = ledger.iter().any(|e| e.codec_type == "video" && e.fate == StreamFate::ReEncoded);
+    if reencodes_video { vec!["-noautorotate".to_string()] } else { vec![] }
+}
+
+fn compare(entry: &LedgerEntry, input: &StreamInfo, output: &StreamInfo) -> Result<(), String> {
+    if input.codec_type != output.codec_type {
+        return Err(format!("became {}", output.codec_type));
+    }
+    let expected_codec = match &entry.encoder {
+        Some(encoder) => Some(codec_of_encoder(encoder).to_string()),
+        None => input.codec_name.clone(),
+    };
+    if expected_codec.is_some() && output.codec_name != expected_codec {
+        return Err(format!(
+            "codec is {} instead of {}",
+            output.codec_name.as_deref().unwrap_or("unknown"),
+            expected_codec.as_deref().unwrap_or("unknown")
+        ));
+    }
+    if (input.width, input.height) != (output.width, output.height) {
+        return Err(format!(
+            "size changed from {}x{} to {}x{}",
+            input.width.unwrap_or(0),
+            input.height.unwrap_or(0),
+            output.width.unwrap_or(0),
+            output.height.unwrap_or(0)
+        ));
+    }
+    if input.channels != output.channels {
+        return Err(format!("channels changed from {} to {}", input.channels.unwrap_or(0), output.channels.unwrap_or(0)));
+    }
+    // Opus only runs at 48 kHz, so a re-encode to it resamples by definition
+    let resamples = entry.encoder.as_deref() == Some("libopus");
+    if !resamples && input.sample_rate != output.sample_rate {
+        return Err(format!("sample rate changed from {} to {}", input.sample_rate.unwrap_or(0), output.sample_rate.unwrap_or(0)));
+    }
+    if input.language != output.language {
+        return Err(format!(
+            "language changed from {} to {}",
+            input.language.as_deref().unwrap_or("none"),
+            output.language.as_deref().unwrap_or("none")
+        ));
+    }
+    Ok(())
+}
+
+// Checks the output against the ledger; Err lists every broken promise.
+pub fn verify(mut ledger: Vec<LedgerEntry>, source: &MediaInfo, output: &MediaInfo) -> Result<Vec<LedgerEntry>, String> {
+    let mut problems = vec![];
+    if source.streams.len() != output.streams.len() {
+        problems.push(format!("{} streams went in, {} came out", source.streams.len(), output.streams.len()));
+    }
+    for (i, entry) in ledger.iter_mut().enumerate() {
+        let (Some(input), Some(out)) = (source.streams.get(i), output.streams.get(i)) else { continue };
+        match compare(entry, input, out) {
+            Ok(()) => entry.verified = true,
+            Err(e) => problems.push(format!("stream {} ({}) {}", entry.index, entry.codec_type, e)),
+        }
+    }
+    for (key, value) in &source.tags {
+        if key.eq_ignore_ascii_case("encoder") {
+            continue;
+        }
+        match output.tags.get(key) {
+            Some(v) if v == value => {}
+            Some(v) => problems.push(format!("tag {} changed from \"{}\" to \"{}\"", key, value, v)),
+            None => problems.push(format!("tag {} was dropped", key)),
+        }
+    }
+    if let (Some(a), Some(b)) = (source.duration, output.duration) {
+        if (a - b).abs() > 0.5 {
+            problems.push(format!("duration changed from {:.2}s to {:.2}s", a, b));
+        }
+    }
+    if problems.is_empty() {
+        Ok(ledger)
+    } else {
+        Err(format!("Surgical check failed, the output was kept for inspection: {}", problems.join("; ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(index: u32, codec_type: &str, codec: &str) -> StreamInfo {
+        StreamInfo { index, codec_type: codec_type.to_string(), codec_name: Some(codec.to_string()), ..Default::default() }
+    }
+
+    // A 1080p H.264 file with stereo AAC, a cover and a subtitle track
+    fn source() -> MediaInfo {
+        MediaInfo {
+            streams: vec![
+                StreamInfo { width: Some(1920), height: Some(1080), ..stream(0, "video", "h264") },
+                StreamInfo { channels: Some(2), sample_rate: Some(44100), language: Some("eng".to_string()), ..stream(1, "audio", "aac") },
+                StreamInfo { attached_pic: true, ..stream(2, "video", "mjpeg") },
+                stream(3, "subtitle", "subrip"),
+            ],
+            tags: [("title", "Wedding"), ("encoder", "Lavf58")].map(|(k, v)| (k.to_string(), v.to_string())).into_iter().collect(),
+            duration: Some(60.0),
+            ..Default::default()
+        }
+    }
+
+    // What a correct surgical encode to HEVC and Opus comes out as
+    fn output() -> MediaInfo {
+        let mut out = source();
+        out.streams[0].codec_name = Some("hevc".to_string());
+        out.streams[1].codec_name = Some("opus".to_string());
+        out.streams[1].sample_rate = Some(48000);
+        out.tags.insert("encoder".to_string(), "Lavf61".to_string());
+        out
+    }
+
+    #[test]
+    fn only_real_video_and_audio_are_targets() {
+        let ledger = plan(&source().streams, "libx265", "libopus");
+        let fates: Vec<StreamFate> = ledger.iter().map(|e| e.fate).collect();
+        assert_eq!(fates, [StreamFate::ReEncoded, StreamFate::ReEncoded, StreamFate::Untouched, StreamFate::Untouched]);
+        assert_eq!(stream_args(&ledger)[8..], ["-c:0", "libx265", "-c:1", "libopus", "-c:2", "copy", "-c:3", "copy"]);
+        assert_eq!(input_args(&ledger), ["-noautorotate"]);
+
+        let copied = plan(&source().streams, "copy", "aac");
+        assert_eq!(copied[0].fate, StreamFate::Copied);
+        assert!(input_args(&copied).is_empty());
+    }
+
+    #[test]
+    fn a_faithful_output_verifies_every_stream() {
+        let ledger = verify(plan(&source().streams, "libx265", "libopus"), &source(), &output()).unwrap();
+        assert!(ledger.iter().all(|e| e.verified));
+    }
+
+    #[test]
+    fn every_broken_promise_is_listed() {
+        let mut out = output();
+        out.streams[0].width = Some(1280);
+        out.streams[1].sample_rate = Some(44100);
+        out.streams[1].language = None;
+        out.streams.pop();
+        out.tags.remove("title");
+        out.duration = Some(58.0);
+        let err = verify(plan(&source().streams, "libx265", "aac"), &source(), &out).unwrap_err();
+        for problem in [
+            "4 streams went in, 3 came out",
+            "stream 0 (video) size changed from 1920x1080 to 1280x1080",
+            "stream 1 (audio) codec is opus instead of aac",
+            "tag title was dropped",
+            "duration changed from 60.00s to 58.00s",
+        ] {
+            assert!(err.contains(problem), "{problem} missing from {err}");
+        }
+    }
+
+    #[test]
+    fn only_opus_may_resample() {
+        let mut out = output();
+        out.streams[1].codec_name = Some("aac".to_string());
+        let err = verify(plan(&source().streams, "libx265", "aac"), &source(), &out).unwrap_err();
+        assert!(err.contains("sample rate changed from 44100 to 48000"));
+        // An untouched stream's codec has to stay what it was
+        let mut out = output();
+        out.streams[3].codec_name = Some("ass".to_string());
+        assert!(verify(plan(&source().streams, "libx265", "libopus"), &source(), &out).unwrap_err().contains("stream 3 (subtitle) codec is ass instead of subrip"));
+    }
+}
@@ -0,0 +1,167 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::settings::SettingsStore;
+use crate::volumes;
+
+// Pacing below this fraction of realtime would stall most encoders' lookahead
+const MIN_READRATE: f64 = 0.05;
+// What a throttled copy writes between checks of the clock
+const COPY_CHUNK: usize = 1024 * 1024;
+
+// ==========================================
+// IO THROTTLE
+// ==========================================
+// Caps how fast a job reads its input, for sources on a NAS or other
+// network share. ffmpeg's `-readrate` paces input in "x realtime", so the
+// cap in Mbit/s is turned into a multiplier using the input's average bitrate.
+// Writes are ours: a temp output staged on another volume (a work folder,
+// the local disk) is copied to the share in chunks, paced to the same cap.
+
+// The job's own cap wins; otherwise the default for the input's volume, then
+// the output's.
+pub fn resolve_mbps(app: &AppHandle, job_mbps: Option<u32>, input: &str, output: &str) -> Option<u32> {
+    if job_mbps.is_some() {
+        return job_mbps.filter(|m| *m > 0);
+    }
+    let settings = app.try_state::<SettingsStore>()?.get();
+    if settings.volume_io_throttle.is_empty() {
+        return None;
+    }
+    [input, output]
+        .iter()
+        .filter_map(|p| volumes::volume_of(p))
+        .find_map(|v| settings.volume_io_throttle.get(&v.mount_point).copied())
+        .filter(|m| *m > 0)
+}
+
+// The cap for writing the output: the job's own, else the output volume's
+// default.
+pub fn write_mbps(app: &AppHandle, job_mbps: Option<u32>, output: &str) -> Option<u32> {
+    if job_mbps.is_some() {
+        return job_mbps.filter(|m| *m > 0);
+    }
+    let settings = app.try_state::<SettingsStore>()?.get();
+    let volume = volumes::volume_of(output)?;
+    settings.volume_io_throttle.get(&volume.mount_point).copied().filter(|m| *m > 0)
+}
+
+// How long to wait after `copied` bytes so the average stays at
+// `bytes_per_sec`; pacing goes by the total since the start, so a stall
+// isn't made up with a burst above the cap.
+fn copy_pause(copied: u64, bytes_per_sec: f64, elapsed: Duration) -> Option<Duration> {
+    Duration::from_secs_f64(copied as f64 / bytes_per_sec).checked_sub(elapsed).filter(|d| !d.is_zero())
+}
+
+// fs::copy, at no more than `mbps` Mbit/s when there's a cap.
+pub fn copy_limited(from: &Path, to: &Path, mbps: Option<u32>) -> io::Result<u64> {
+    let Some(mbps) = mbps.filter(|m| *m > 0) else {
+        return fs::copy(from, to);
+    };
+    let bytes_per_sec = mbps as f64 * 1_000_000.0 / 8.0;
+    let mut reader = fs::File::open(from)?;
+    let mut writer = fs::File::create(to)?;
+    let mut buffer = vec![0; COPY_CHUNK];
+    let (started, mut copied) = (Instant::now(), 0u64);
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..read])?;
+        copied += read as u64;
+        if let Some(pause) = copy_pause(copied, bytes_per_sec, started.elapsed()) {
+            thread::sleep(pause);
+        }
+    }
+    writer.set_permissions(reader.metadata()?.permissions())?;
+    Ok(copied)
+}
+
+// `-readrate` value for a cap; None when the file's bitrate is already below
+// the cap (nothing to slow down) or can't be worked out.
+pub fn readrate_for(mbps: u32, input_bytes: u64, duration_secs: Option<f64>) -> Option<f64> {
+    let duration = duration_secs.filter(|d| *d > 0.0)?;
+    if input_bytes == 0 {
+        return None;
+    }
+    let file_bits_per_sec = input_bytes as f64 * 8.0 / duration;
+    let rate = mbps as f64 * 1_000_000.0 / file_bits_per_sec;
+    // Above ~50x realtime no encoder is IO-bound on the read side anyway
+    (rate < 50.0).then_some(rate.max(MIN_READRATE))
+}
+
+// ==========================================
+// COMMAND: PER-VOLUME DEFAULT
+// ==========================================
+// `mbps` None (or 0) removes the default for that volume.
+#[tauri::command]
+pub fn set_volume_io_throttle(store: State<'_, SettingsStore>, mount_point: String, mbps: Option<u32>) -> Result<(), String> {
+    store
+        .update(|s| match mbps.filter(|m| *m > 0) {
+            Some(m) => {
+                s.volume_io_throttle.insert(mount_point, m);
+            }
+            None => {
+                s.volume_io_throttle.remove(&mount_point);
+            }
+        })
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::TempDir;
+
+    fn folder(name: &str) -> TempDir {
+        TempDir::new(std::env::temp_dir().join(format!("throttle-test-{}-{}", std::process::id(), name))).unwrap()
+    }
+
+    #[test]
+    fn readrate_is_the_cap_over_the_files_bitrate() {
+        // 100 MB over 100 s is 8 Mbit/s; a 4 Mbit/s cap is half realtime
+        assert_eq!(readrate_for(4, 100_000_000, Some(100.0)), Some(0.5));
+        assert_eq!(readrate_for(1, 1_000_000_000, Some(10.0)), Some(MIN_READRATE));
+        // Already below the cap by far
+        assert_eq!(readrate_for(1000, 1_000_000, Some(100.0)), None);
+        assert_eq!(readrate_for(4, 0, Some(100.0)), None);
+        assert_eq!(readrate_for(4, 100_000_000, None), None);
+    }
+
+    #[test]
+    fn copies_pause_only_when_ahead_of_the_cap() {
+        // 1 MB/s: 2 MB copied after half a second is 1.5 s ahead
+        assert_eq!(copy_pause(2_000_000, 1_000_000.0, Duration::from_millis(500)), Some(Duration::from_millis(1500)));
+        assert_eq!(copy_pause(2_000_000, 1_000_000.0, Duration::from_secs(2)), None);
+        assert_eq!(copy_pause(1_000_000, 1_000_000.0, Duration::from_secs(3)), None);
+    }
+
+    #[test]
+    fn a_capped_copy_takes_as_long_as_the_cap_says() {
+        let dir = folder("capped");
+        let (from, to) = (dir.path().join("from.mp4"), dir.path().join("to.mp4"));
+        let data: Vec<u8> = (0..3 * COPY_CHUNK as u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&from, &data).unwrap();
+        let started = Instant::now();
+        // 80 Mbit/s is 10 MB/s, so ~3 MB takes ~0.3 s
+        assert_eq!(copy_limited(&from, &to, Some(80)).unwrap(), data.len() as u64);
+        assert!(started.elapsed() >= Duration::from_millis(280), "{:?}", started.elapsed());
+        assert_eq!(fs::read(&to).unwrap(), data);
+    }
+
+    #[test]
+    fn no_cap_is_a_plain_copy() {
+        let dir = folder("plain");
+        let (from, to) = (dir.path().join("from.mp4"), dir.path().join("to.mp4"));
+        fs::write(&from, b"not much").unwrap();
+        assert_eq!(copy_limited(&from, &to, None).unwrap(), 8);
+        assert_eq!(copy_limited(&from, &to, Some(0)).unwrap(), 8);
+        assert_eq!(fs::read(&to).unwrap(), b"not much");
+    }
+}
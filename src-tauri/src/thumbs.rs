@@ -0,0 +1,465 @@
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{Manager, State};
+use tokio::sync::OnceCell;
+
+use crate::AppHandle;
+use crate::ffmpeg;
+use crate::probe;
+use crate::settings::SettingsStore;
+
+pub const DEFAULT_THUMBNAIL_CACHE_MB: u64 = 256;
+// Requested sizes round up to one of these, so a grid at 1x, 1.25x and 2x
+// shares a few files instead of one per pixel size
+const SIZE_BUCKETS: &[u32] = &[64, 128, 256, 512, 1024];
+const SWEEP_INTERVAL: Duration = Duration::from_secs(300);
+const JPEG_QUALITY: u8 = 82;
+// generate_thumbnail's width range
+const FRAME_WIDTH_RANGE: std::ops::RangeInclusive<u32> = 16..=4096;
+// Timestamps are kept this far from the end, where a seek finds no frame
+const END_MARGIN_SECS: f64 = 0.1;
+
+// ==========================================
+// THUMBNAIL CACHE
+// ==========================================
+// The history grid asks for the same thumbnails over and over. They live in
+// app_cache_dir/thumbs/<hash of the canonical path>/<bucket>.jpg, next to
+// `mtime` holding the source's modification time: a changed source wipes
+// its directory. Images go through the `image` crate, video (and anything
+// it can't read) through a single ffmpeg frame. Files are touched on every
+// hit, and a background sweep removes the least recently used ones once
+// the cache is over `thumbnail_cache_mb`.
+//
+// Requests for a thumbnail that's still being made wait for that one
+// generation instead of starting their own.
+type Pending = Arc<OnceCell<Result<PathBuf, String>>>;
+
+#[derive(Default)]
+pub struct ThumbnailCache {
+    generating: Mutex<HashMap<PathBuf, Pending>>,
+    // Held while a directory is checked against its source's mtime
+    dirs: Mutex<()>,
+    // Directories of sources history no longer shows, for the next sweep
+    orphaned: Mutex<Vec<PathBuf>>,
+}
+
+fn bucket(size_px: u32) -> u32 {
+    SIZE_BUCKETS.iter().copied().find(|&b| b >= size_px).unwrap_or(SIZE_BUCKETS[SIZE_BUCKETS.len() - 1])
+}
+
+fn thumbs_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path().app_cache_dir().map(|d| d.join("thumbs")).map_err(|e| e.to_string())
+}
+
+fn mtime_secs(meta: &fs::Metadata) -> u64 {
+    meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs())
+}
+
+fn source_dir(root: &Path, source: &Path) -> PathBuf {
+    let hash = xxhash_rust::xxh3::xxh3_64(source.to_string_lossy().as_bytes());
+    root.join(format!("{:016x}", hash))
+}
+
+// The source's directory, emptied first if the source changed since.
+fn entry_dir(app: &AppHandle, cache: &ThumbnailCache, source: &Path) -> Result<PathBuf, String> {
+    let _guard = cache.dirs.lock().unwrap();
+    let meta = fs::metadata(source).map_err(|e| format!("Can't read {}: {}", source.display(), e))?;
+    let dir = source_dir(&thumbs_dir(app)?, source);
+    let stamp = dir.join("mtime");
+    let mtime = mtime_secs(&meta).to_string();
+    if fs::read_to_string(&stamp).ok().as_deref() != Some(mtime.as_str()) {
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        fs::write(&stamp, mtime).map_err(|e| e.to_string())?;
+    }
+    Ok(dir)
+}
+
+// Marks a cached file as used, for the LRU sweep.
+fn touch(path: &Path) {
+    if let Ok(file) = File::options().append(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+fn from_image(source: &Path, target: &Path, size: u32) -> Result<(), String> {
+    let img = image::open(source).map_err(|e| e.to_string())?;
+    let thumb = DynamicImage::ImageRgb8(img.thumbnail(size, size).to_rgb8());
+    let file = BufWriter::new(File::create(target).map_err(|e| e.to_string())?);
+    thumb.write_with_encoder(JpegEncoder::new_with_quality(file, JPEG_QUALITY)).map_err(|e| e.to_string())
+}
+
+// One frame a tenth of the way in, which skips black intros and fades.
+// Short clips use their first frame: seeking into one can land past the
+// only keyframe and give nothing.
+async fn from_video(app: &AppHandle, source: &str, target: &str, size: u32) -> Result<(), String> {
+    let media = probe::probe(app, source).await.ok();
+    let at = media.filter(|m| !m.is_short()).and_then(|m| m.duration).map_or(0.0, |d| d * 0.1);
+    let scale = format!("scale={s}:{s}:force_original_aspect_ratio=decrease", s = size);
+    ffmpeg::run_quiet(app, vec![
+        "-ss".to_string(), format!("{:.3}", at),
+        "-i".to_string(), source.to_string(),
+        "-frames:v".to_string(), "1".to_string(),
+        "-vf".to_string(), scale,
+        "-q:v".to_string(), "4".to_string(),
+        "-y".to_string(), target.to_string(),
+    ])
+    .await
+}
+
+async fn generate(app: &AppHandle, source: &Path, target: &Path, size: u32) -> Result<PathBuf, String> {
+    let partial = target.with_extension("part.jpg");
+    let readable = ImageFormat::from_path(source).is_ok_and(|f| f.reading_enabled());
+    let native = if readable {
+        let (s, p) = (source.to_path_buf(), partial.clone());
+        tauri::async_runtime::spawn_blocking(move || from_image(&s, &p, size)).await.map_err(|e| e.to_string())?
+    } else {
+        Err(String::new())
+    };
+    if native.is_err() {
+        from_video(app, &source.to_string_lossy(), &partial.to_string_lossy(), size).await?;
+    }
+    fs::rename(&partial, target).map_err(|e| e.to_string())?;
+    Ok(target.to_path_buf())
+}
+
+// The cached `target`, made by `make` unless it's there already (or being
+// made by another request).
+async fn cached<F: Future<Output = Result<PathBuf, String>>>(cache: &ThumbnailCache, target: PathBuf, make: impl FnOnce() -> F) -> Result<PathBuf, String> {
+    if target.exists() {
+        touch(&target);
+        return Ok(target);
+    }
+    let pending = cache.generating.lock().unwrap().entry(target.clone()).or_default().clone();
+    let result = pending.get_or_init(make).await.clone();
+    let mut generating = cache.generating.lock().unwrap();
+    if generating.get(&target).is_some_and(|p| Arc::ptr_eq(p, &pending)) {
+        generating.remove(&target);
+    }
+    result
+}
+
+async fn thumbnail(app: &AppHandle, cache: &ThumbnailCache, path: &str, size: u32) -> Result<PathBuf, String> {
+    let source = fs::canonicalize(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+    let target = entry_dir(app, cache, &source)?.join(format!("{}.jpg", size));
+    cached(cache, target.clone(), || generate(app, &source, &target, size)).await
+}
+
+// ==========================================
+// FRAME AT A TIMESTAMP
+// ==========================================
+// For queue thumbnails and before/after comparisons: one frame at a given
+// time and width, cached next to the grid thumbnails (same invalidation,
+// same sweep). Images have no timeline, so they're only scaled, and only
+// when they're wider than asked.
+fn image_frame(source: &Path, target: &Path, max_width: u32) -> Result<(), String> {
+    let img = image::open(source).map_err(|e| e.to_string())?;
+    let img = if img.width() > max_width { img.thumbnail(max_width, u32::MAX) } else { img };
+    let file = BufWriter::new(File::create(target).map_err(|e| e.to_string())?);
+    DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(JpegEncoder::new_with_quality(file, JPEG_QUALITY)).map_err(|e| e.to_string())
+}
+
+async fn video_frame(app: &AppHandle, source: &str, target: &str, at: f64, max_width: u32) -> Result<(), String> {
+    ffmpeg::run_quiet(app, vec![
+        "-ss".to_string(), format!("{:.3}", at),
+        "-i".to_string(), source.to_string(),
+        "-frames:v".to_string(), "1".to_string(),
+        "-vf".to_string(), format!("scale='min({},iw)':-1", max_width),
+        "-q:v".to_string(), "4".to_string(),
+        "-y".to_string(), target.to_string(),
+    ])
+    .await
+}
+
+// The frame's file and the time it was taken at, after clamping.
+async fn frame(app: &AppHandle, cache: &ThumbnailCache, input: &str, at: f64, max_width: u32) -> Result<(PathBuf, f64), String> {
+    let source = fs::canonicalize(input).map_err(|e| format!("Can't read {}: {}", input, e))?;
+    let image = ImageFormat::from_path(&source).is_ok_and(|f| f.reading_enabled());
+    let at = if image {
+        0.0
+    } else {
+        let media = probe::probe(app, input).await?;
+        if !media.has_video {
+            return Err(format!("{} has no video to take a frame from", input));
+        }
+        match media.duration.filter(|_| !media.is_single_frame()) {
+            Some(duration) => at.clamp(0.0, (duration - END_MARGIN_SECS).max(0.0)),
+            None => 0.0,
+        }
+    };
+    let target = entry_dir(app, cache, &source)?.join(format!("frame-{}ms-{}w.jpg", (at * 1000.0).round() as u64, max_width));
+    let make = || async {
+        let partial = target.with_extension("part.jpg");
+        if image {
+            let (s, p) = (source.clone(), partial.clone());
+            tauri::async_runtime::spawn_blocking(move || image_frame(&s, &p, max_width)).await.map_err(|e| e.to_string())??;
+        } else {
+            video_frame(app, &source.to_string_lossy(), &partial.to_string_lossy(), at, max_width).await?;
+        }
+        fs::rename(&partial, &target).map_err(|e| e.to_string())?;
+        Ok(target.clone())
+    };
+    Ok((cached(cache, target.clone(), make).await?, at))
+}
+
+// ==========================================
+// LRU SWEEP
+// ==========================================
+fn limit_bytes(app: &AppHandle) -> u64 {
+    let mb = app.try_state::<SettingsStore>().map_or(DEFAULT_THUMBNAIL_CACHE_MB, |s| s.get().thumbnail_cache_mb);
+    mb * 1024 * 1024
+}
+
+// Thumbnails of `paths` go on the next sweep, whatever their age (history
+// compaction pruned the entries that showed them; see history.rs).
+pub fn queue_cleanup(app: &AppHandle, paths: &[String]) {
+    let (Some(cache), Ok(root)) = (app.try_state::<ThumbnailCache>(), thumbs_dir(app)) else { return };
+    // Keyed by the canonical path, which a deleted file no longer has
+    let dirs = paths.iter().map(|p| source_dir(&root, &fs::canonicalize(p).unwrap_or_else(|_| PathBuf::from(p))));
+    cache.orphaned.lock().unwrap().extend(dirs.filter(|d| d.exists()));
+}
+
+// Least recently used thumbnails go first until the cache fits again, after
+// the queued orphans.
+pub fn sweep(app: &AppHandle) {
+    let Ok(root) = thumbs_dir(app) else { return };
+    let orphaned: Vec<PathBuf> = app.try_state::<ThumbnailCache>().map(|c| std::mem::take(&mut *c.orphaned.lock().unwrap())).unwrap_or_default();
+    let dropped = orphaned.iter().filter(|d| fs::remove_dir_all(d).is_ok()).count();
+    if dropped > 0 {
+        println!("🧹 Thumbnail cache: removed thumbnails of {} files no longer in history", dropped);
+    }
+    let removed = trim_to(&root, limit_bytes(app));
+    if removed > 0 {
+        println!("🧹 Thumbnail cache: removed {} old thumbnails", removed);
+    }
+}
+
+// Removes the oldest thumbnails under `root` until they fit in `limit`.
+// Returns how many went.
+fn trim_to(root: &Path, limit: u64) -> usize {
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = fs::read_dir(root)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|dir| fs::read_dir(dir.path()).ok())
+        .flatten()
+        .flatten()
+        .filter(|f| f.path().extension().is_some_and(|e| e == "jpg"))
+        .filter_map(|f| {
+            let meta = f.metadata().ok()?;
+            Some((meta.modified().ok()?, meta.len(), f.path()))
+        })
+        .collect();
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    if total <= limit {
+        return 0;
+    }
+    files.sort();
+    let mut removed = 0;
+    for (_, len, path) in files {
+        if total <= limit {
+            break;
+        }
+        if fs::remove_file(&path).is_ok() {
+            total -= len;
+            removed += 1;
+        }
+    }
+    removed
+}
+
+pub fn cache_bytes(app: &AppHandle) -> u64 {
+    thumbs_dir(app).map_or(0, |dir| crate::ladder::dir_size(&dir))
+}
+
+// Everything, sizes and frames alike. Returns the bytes freed.
+pub fn clear(app: &AppHandle) -> Result<u64, String> {
+    let dir = thumbs_dir(app)?;
+    let freed = crate::ladder::dir_size(&dir);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+    }
+    Ok(freed)
+}
+
+pub fn start_sweeper(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let handle = app.clone();
+            let _ = tauri::async_runtime::spawn_blocking(move || sweep(&handle)).await;
+            tokio::time::sleep(SWEEP_INTERVAL).await;
+        }
+    });
+}
+
+// ==========================================
+// COMMAND: GET THUMBNAIL
+// ==========================================
+// `size_px` is in CSS pixels; `scale_factor` is the window's current monitor
+// DPI scale, so a 2x screen gets a sharp thumbnail. Returns the JPEG's path.
+#[tauri::command]
+pub async fn get_thumbnail(
+    app: AppHandle,
+    cache: State<'_, ThumbnailCache>,
+    path: String,
+    size_px: u32,
+    scale_factor: Option<f64>,
+) -> Result<String, String> {
+    let physical = (size_px.max(1) as f64 * scale_factor.unwrap_or(1.0).clamp(0.5, 4.0)).round() as u32;
+    thumbnail(&app, &cache, &path, bucket(physical)).await.map(|p| p.to_string_lossy().to_string())
+}
+
+// ==========================================
+// COMMAND: GENERATE THUMBNAIL
+// ==========================================
+// One of `path` / `data` is set: the cached JPEG's path, or with
+// `as_base64` its bytes base64-encoded (for an <img> data URL).
+#[derive(Serialize, Clone, Debug)]
+pub struct FrameThumbnail {
+    // Where the frame was taken, after clamping to the file's length
+    pub at_secs: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+}
+
+#[tauri::command]
+pub async fn generate_thumbnail(
+    app: AppHandle,
+    cache: State<'_, ThumbnailCache>,
+    input: String,
+    timestamp_seconds: f64,
+    max_width: u32,
+    as_base64: Option<bool>,
+) -> Result<FrameThumbnail, String> {
+    if !FRAME_WIDTH_RANGE.contains(&max_width) {
+        return Err(format!("{} px is outside {}-{}", max_width, FRAME_WIDTH_RANGE.start(), FRAME_WIDTH_RANGE.end()));
+    }
+    let at = if timestamp_seconds.is_finite() { timestamp_seconds } else { 0.0 };
+    let (path, at_secs) = frame(&app, &cache, &input, at, max_width).await?;
+    if !as_base64.unwrap_or(false) {
+        return Ok(FrameThumbnail { at_secs, path: Some(path.to_string_lossy().to_string()), data: None });
+    }
+    let bytes = fs::read(&path).map_err(|e| e.to_string())?;
+    Ok(FrameThumbnail { at_secs, path: None, data: Some(base64::engine::general_purpose::STANDARD.encode(bytes)) })
+}
+
+// ==========================================
+// COMMAND: THUMBNAIL CACHE LIMIT
+// ==========================================
+#[tauri::command]
+pub fn set_thumbnail_cache_limit(app: AppHandle, store: State<'_, SettingsStore>, max_mb: u64) -> Result<(), String> {
+    if max_mb < 16 {
+        return Err("The thumbnail cache needs at least 16 MB".to_string());
+    }
+    store.update(|s| s.thumbnail_cache_mb = max_mb)?;
+    drop(tauri::async_runtime::spawn_blocking(move || sweep(&app)));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::TempDir;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn folder(name: &str) -> TempDir {
+        TempDir::new(std::env::temp_dir().join(format!("thumbs-test-{}-{}", std::process::id(), name))).unwrap()
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap().block_on(future)
+    }
+
+    #[test]
+    fn sizes_round_up_to_a_bucket() {
+        assert_eq!(bucket(1), 64);
+        assert_eq!(bucket(128), 128);
+        // 160px at 1.25x
+        assert_eq!(bucket(200), 256);
+        assert_eq!(bucket(5000), 1024);
+    }
+
+    #[test]
+    fn each_source_gets_its_own_directory() {
+        let root = Path::new("/cache/thumbs");
+        let a = source_dir(root, Path::new("/videos/a.mp4"));
+        assert_eq!(a, source_dir(root, Path::new("/videos/a.mp4")));
+        assert_ne!(a, source_dir(root, Path::new("/videos/b.mp4")));
+        assert_eq!(a.parent(), Some(root));
+    }
+
+    #[test]
+    fn requests_for_the_same_thumbnail_share_one_generation() {
+        let dir = folder("coalesce");
+        let cache = ThumbnailCache::default();
+        let target = dir.path().join("256.jpg");
+        let made = AtomicUsize::new(0);
+        let make = || async {
+            made.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            fs::write(&target, "jpeg").map_err(|e| e.to_string())?;
+            Ok(target.clone())
+        };
+        let (a, b) = block_on(async { tokio::join!(cached(&cache, target.clone(), make), cached(&cache, target.clone(), make)) });
+        assert_eq!((a.unwrap(), b.unwrap()), (target.clone(), target.clone()));
+        assert_eq!(made.load(Ordering::SeqCst), 1);
+        assert!(cache.generating.lock().unwrap().is_empty());
+
+        // Once it's on disk nothing is made at all
+        block_on(cached(&cache, target.clone(), make)).unwrap();
+        assert_eq!(made.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn a_failed_generation_is_tried_again_next_time() {
+        let dir = folder("retry");
+        let cache = ThumbnailCache::default();
+        let target = dir.path().join("64.jpg");
+        let failed = block_on(cached(&cache, target.clone(), || async { Err::<PathBuf, _>("ffmpeg failed".to_string()) }));
+        assert_eq!(failed, Err("ffmpeg failed".to_string()));
+        let made = block_on(cached(&cache, target.clone(), || async { Ok(PathBuf::from("made")) }));
+        assert_eq!(made, Ok(PathBuf::from("made")));
+    }
+
+    #[test]
+    fn the_sweep_removes_the_least_recently_used_first() {
+        let dir = folder("sweep");
+        let source = dir.path().join("0123456789abcdef");
+        fs::create_dir_all(&source).unwrap();
+        let now = SystemTime::now();
+        for (name, age) in [("old.jpg", 300), ("newer.jpg", 200), ("newest.jpg", 100)] {
+            let path = source.join(name);
+            fs::write(&path, vec![0u8; 1000]).unwrap();
+            File::options().append(true).open(&path).unwrap().set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        fs::write(source.join("mtime"), "12345").unwrap();
+
+        assert_eq!(trim_to(dir.path(), 3000), 0);
+        assert_eq!(trim_to(dir.path(), 1500), 2);
+        assert!(source.join("newest.jpg").exists() && !source.join("newer.jpg").exists() && !source.join("old.jpg").exists());
+        // The stamp isn't a thumbnail
+        assert!(source.join("mtime").exists());
+    }
+
+    #[test]
+    fn image_thumbnails_fit_the_bucket() {
+        let dir = folder("image");
+        let (source, target) = (dir.path().join("photo.png"), dir.path().join("128.jpg"));
+        DynamicImage::new_rgb8(400, 200).save(&source).unwrap();
+        from_image(&source, &target, 128).unwrap();
+        let thumb = image::open(&target).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (128, 64));
+    }
+}
@@ -0,0 +1,144 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::AppHandle;
+use crate::events::{self, Event};
+use crate::queue;
+
+// ==========================================
+// PER-JOB TIMELINE
+// ==========================================
+// Every lifecycle transition and notable decision of a queue job, for the
+// activity feed. Entries are a stable code plus string params, so the UI
+// renders (and translates) them itself instead of showing our English.
+// A job's list is capped, and so is every param, so a pathological job
+// can't grow without bound.
+pub const MAX_ENTRIES: usize = 64;
+pub const MAX_PARAMS: usize = 8;
+pub const MAX_PARAM_CHARS: usize = 160;
+
+// --- CODES ---
+pub const QUEUED: &str = "job.queued";
+pub const STARTED: &str = "job.started";
+pub const ANALYZED: &str = "job.analyzed";
+// One per decision taken because the input is very short (see encode_video)
+pub const SHORT_INPUT: &str = "job.short_input";
+pub const ENCODE_STARTED: &str = "encode.started";
+pub const PASS_FINISHED: &str = "encode.pass_finished";
+pub const HDR_DECISION: &str = "encode.hdr";
+// The hardware encode failed partway; the job is run again on the CPU
+pub const CPU_FALLBACK: &str = "encode.cpu_fallback";
+// One per rung of the size ladder that gets encoded (see sizefit.rs)
+pub const FIT_ATTEMPT: &str = "encode.fit_attempt";
+// The user suspended / resumed the job's ffmpeg (see pause.rs)
+pub const PAUSED: &str = "job.paused";
+pub const RESUMED: &str = "job.resumed";
+pub const ENCODE_FINISHED: &str = "encode.finished";
+pub const VERIFIED: &str = "encode.verified";
+pub const QUALITY_RISK: &str = "encode.quality_risk";
+// A salvage encode finished with part of the timeline (see salvage.rs)
+pub const SALVAGED: &str = "encode.salvaged";
+// The output came out larger than the input and skip_if_larger deleted it
+pub const SKIPPED_LARGER: &str = "job.skipped_larger";
+pub const FINISHED: &str = "job.finished";
+pub const FAILED: &str = "job.failed";
+pub const CANCELLED: &str = "job.cancelled";
+pub const REDIRECTED: &str = "job.redirected";
+// The job went to the pipeline its input and output fit (see routing.rs)
+pub const REROUTED: &str = "job.rerouted";
+// Started in the express lane past the concurrency limit, and taken out of
+// it again after running past twice its estimate (see queue.rs)
+pub const EXPRESS_STARTED: &str = "job.express_started";
+pub const EXPRESS_DEMOTED: &str = "job.express_demoted";
+// Rehearsal mode ran the analysis and simulated the encode (see rehearsal.rs)
+pub const SIMULATED: &str = "job.simulated";
+// ...and left out a post-action that would have touched files; `action`
+// is upload or watch_folder
+pub const POST_ACTION_SKIPPED: &str = "job.post_action_skipped";
+pub const HASH_RESUMED: &str = "archive.hash_resumed";
+// The file changed since its checkpoint, so its hash started over
+pub const HASH_RESTARTED: &str = "archive.hash_restarted";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TimelineEntry {
+    // Unix milliseconds
+    pub at_ms: u64,
+    pub code: String,
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+}
+
+// Payload of `job-timeline`.
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct TimelinePayload {
+    pub job_id: u64,
+    pub entry: TimelineEntry,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+pub(crate) fn truncate(value: &str) -> String {
+    match value.char_indices().nth(MAX_PARAM_CHARS) {
+        Some((end, _)) => format!("{}…", &value[..end]),
+        None => value.to_string(),
+    }
+}
+
+impl TimelineEntry {
+    pub fn new(code: &str, params: &[(&str, String)]) -> Self {
+        TimelineEntry {
+            at_ms: now_ms(),
+            code: code.to_string(),
+            params: params.iter().take(MAX_PARAMS).map(|(k, v)| (k.to_string(), truncate(v))).collect(),
+        }
+    }
+}
+
+// Adds an entry to a bounded list. Once full, the oldest entries after the
+// first ("queued") make room, so the start and the latest events survive.
+pub fn push(timeline: &mut Vec<TimelineEntry>, entry: TimelineEntry) {
+    if timeline.len() >= MAX_ENTRIES {
+        timeline.remove(1);
+    }
+    timeline.push(entry);
+}
+
+// Records an entry for `job_id` and emits it on `job-timeline`.
+pub fn record_for(app: &AppHandle, job_id: u64, code: &str, params: &[(&str, String)]) {
+    let entry = TimelineEntry::new(code, params);
+    if queue::push_timeline(app, job_id, entry.clone()) {
+        events::emit(app, Event::JobTimeline(TimelinePayload { job_id, entry }));
+    }
+}
+
+// Same, for the queue job running on this task; direct (unqueued) commands
+// have no job id and no timeline.
+pub fn record(app: &AppHandle, code: &str, params: &[(&str, String)]) {
+    if let Some(job_id) = queue::current_job_id() {
+        record_for(app, job_id, code, params);
+    }
+}
+
+// The closing entry, recorded as the job's history entry is written; returns
+// the finished timeline for it.
+pub fn finish(app: &AppHandle, error: Option<&str>, warnings: usize) -> Vec<TimelineEntry> {
+    let Some(job_id) = queue::current_job_id() else { return vec![] };
+    match error {
+        Some(crate::cancel::CANCELLED) => record_for(app, job_id, CANCELLED, &[]),
+        Some(e) => record_for(app, job_id, FAILED, &[("error", e.to_string())]),
+        None => record_for(app, job_id, FINISHED, &[("warnings", warnings.to_string())]),
+    }
+    queue::timeline(app, job_id).unwrap_or_default()
+}
+
+// ==========================================
+// COMMAND: GET JOB TIMELINE
+// ==========================================
+#[tauri::command]
+pub fn get_job_timeline(app: AppHandle, job_id: u64) -> Result<Vec<TimelineEntry>, String> {
+    queue::timeline(&app, job_id).ok_or_else(|| format!("Job {} not found", job_id))
+}
@@ -0,0 +1,238 @@
+use std::path::Path;
+use std::sync::Mutex;
+use tauri::menu::{Menu, MenuItem, PredefinedMenuItem};
+use tauri::tray::TrayIconBuilder;
+use tauri::{Manager, State, WebviewWindowBuilder};
+use tauri_plugin_notification::NotificationExt;
+
+use crate::{AppHandle, Runtime};
+use crate::active::{self, ActiveJob};
+use crate::cancel;
+use crate::history::{HistoryEntry, JobStatus};
+use crate::pause;
+use crate::queue::{self, JobQueue};
+use crate::settings::SettingsStore;
+
+const TRAY_ID: &str = "main";
+const WINDOW_LABEL: &str = "main";
+const IDLE_TEXT: &str = "No jobs running";
+
+// ==========================================
+// TRAY ICON
+// ==========================================
+// Always there, so a long encode doesn't need the window: the menu shows
+// the job on screen (the latest one started, when several run) with its
+// percentage, Pause/Resume and Cancel for it, Open and Quit. It shows what
+// active.rs gathers from the events the frontend gets, so it never has its
+// own idea of what's running. On macOS the percentage also sits next to
+// the icon.
+//
+// Closing the window while jobs are running or queued hides it instead of
+// ending the app (and every ffmpeg with it); the app then quits on its own
+// once the last one is done, unless close_to_tray is on. With close_to_tray,
+// closing never quits: queue and scheduled jobs (deferred.rs) carry on, and
+// the window is destroyed and built again from the config, so a closed app
+// doesn't keep a webview around. Either way the cleanup only runs on the
+// real exit (see lib.rs).
+
+// The menu items that change, once the tray exists.
+#[derive(Clone)]
+struct Items {
+    job: MenuItem<Runtime>,
+    percent: MenuItem<Runtime>,
+    pause: MenuItem<Runtime>,
+    cancel: MenuItem<Runtime>,
+}
+
+// ==========================================
+// MANAGED STATE
+// ==========================================
+#[derive(Default)]
+pub struct TrayStatus {
+    items: Mutex<Option<Items>>,
+}
+
+// The job the menu is about: the latest one started.
+fn shown(app: &AppHandle) -> Option<ActiveJob> {
+    active::latest(app).0
+}
+
+pub fn keeps_running(app: &AppHandle) -> bool {
+    app.try_state::<SettingsStore>().is_some_and(|s| s.get().close_to_tray)
+}
+
+// Anything the window closing would kill or strand.
+pub fn has_active_jobs(app: &AppHandle) -> bool {
+    let running = !active::snapshot(app).is_empty();
+    let queued = app.try_state::<JobQueue>().is_some_and(|_| {
+        let snapshot = queue::snapshot(app);
+        !snapshot.running.is_empty() || !snapshot.pending.is_empty()
+    });
+    running || queued
+}
+
+pub fn create(app: &AppHandle) -> Result<(), String> {
+    let item = |id: &str, text: &str, enabled: bool| MenuItem::with_id(app, id, text, enabled, None::<&str>).map_err(|e| e.to_string());
+    let items = Items {
+        job: item("job", IDLE_TEXT, false)?,
+        percent: item("percent", "", false)?,
+        pause: item("pause", "Pause", false)?,
+        cancel: item("cancel", "Cancel", false)?,
+    };
+    let open = item("open", "Open Compress I/O", true)?;
+    let quit = item("quit", "Quit", true)?;
+    let separator = || PredefinedMenuItem::separator(app).map_err(|e| e.to_string());
+    let menu = Menu::with_items(
+        app,
+        &[&items.job, &items.percent, &items.pause, &items.cancel, &separator()?, &open, &separator()?, &quit],
+    )
+    .map_err(|e| e.to_string())?;
+    let mut builder = TrayIconBuilder::with_id(TRAY_ID).menu(&menu).tooltip("Compress I/O").on_menu_event(|app, event| {
+        let outcome = match event.id().as_ref() {
+            "open" => show_window(app),
+            "pause" => toggle_pause(app),
+            "cancel" => cancel_shown(app),
+            // Exit runs the cleanup (see lib.rs)
+            "quit" => {
+                app.exit(0);
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = outcome {
+            println!("⚠️ Tray: {}", e);
+        }
+    });
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    builder.build(app).map_err(|e| e.to_string())?;
+    if let Some(status) = app.try_state::<TrayStatus>() {
+        *status.items.lock().unwrap() = Some(items);
+    }
+    Ok(())
+}
+
+fn show_window(app: &AppHandle) -> Result<(), String> {
+    if let Some(window) = app.get_webview_window(WINDOW_LABEL) {
+        window.show().map_err(|e| e.to_string())?;
+        return window.set_focus().map_err(|e| e.to_string());
+    }
+    let config = app.config().app.windows.iter().find(|w| w.label == WINDOW_LABEL).ok_or("The app has no main window configured")?;
+    WebviewWindowBuilder::from_config(app, config).and_then(|b| b.build()).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn toggle_pause(app: &AppHandle) -> Result<(), String> {
+    let Some(job) = shown(app) else { return Ok(()) };
+    match job.paused {
+        true => pause::resume_job(app.clone(), job.job_id),
+        false => pause::pause_job(app.clone(), job.job_id),
+    }
+}
+
+fn cancel_shown(app: &AppHandle) -> Result<(), String> {
+    let Some(job) = shown(app) else { return Ok(()) };
+    queue::cancel_job(app.clone(), app.state::<JobQueue>(), job.job_id)
+}
+
+// ==========================================
+// LIVE STATUS
+// ==========================================
+// A job ended (see active.rs); `removed` says whether it was one on the menu.
+pub fn job_ended(app: &AppHandle, removed: bool) {
+    if removed {
+        refresh(app);
+    }
+    quit_if_done(app);
+}
+
+// The window was only hidden for the jobs' sake: with the last one done,
+// the close the user asked for goes through.
+fn quit_if_done(app: &AppHandle) {
+    let hidden = app.get_webview_window(WINDOW_LABEL).is_some_and(|w| !w.is_visible().unwrap_or(true));
+    if hidden && !keeps_running(app) && !has_active_jobs(app) {
+        println!("🪟 Last job done with the window closed, quitting");
+        app.exit(0);
+    }
+}
+
+// What the menu shows changed. The locks are let go before touching the
+// menu, which runs on the main thread.
+pub fn refresh(app: &AppHandle) {
+    let Some(items) = app.try_state::<TrayStatus>().and_then(|s| s.items.lock().unwrap().clone()) else { return };
+    let (shown, others) = active::latest(app);
+    let name = |job: &ActiveJob| Path::new(&job.input).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let (job_text, percent_text) = match &shown {
+        Some(job) if job.paused => (name(job), format!("Paused at {:.0}%", job.percent)),
+        Some(job) => (name(job), format!("{:.0}%", job.percent)),
+        None => (IDLE_TEXT.to_string(), String::new()),
+    };
+    let percent_text = match others {
+        0 => percent_text,
+        n => format!("{} (+{} more running)", percent_text, n),
+    };
+    let _ = items.job.set_text(&job_text);
+    let _ = items.percent.set_text(&percent_text);
+    let _ = items.pause.set_text(if shown.as_ref().is_some_and(|j| j.paused) { "Resume" } else { "Pause" });
+    let _ = items.pause.set_enabled(shown.is_some());
+    let _ = items.cancel.set_enabled(shown.is_some());
+    if let Some(tray) = app.tray_by_id(TRAY_ID) {
+        let badge = shown.as_ref().map(|j| format!("{:.0}%", j.percent));
+        let tooltip = match &shown {
+            Some(_) => format!("Compress I/O: {} {}", job_text, percent_text),
+            None => "Compress I/O".to_string(),
+        };
+        let _ = tray.set_title(badge.as_deref());
+        let _ = tray.set_tooltip(Some(tooltip));
+    }
+}
+
+// ==========================================
+// FINISHED-JOB NOTIFICATIONS
+// ==========================================
+fn size(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 * 1024 {
+        format!("{:.1} GB", bytes as f64 / 1024.0 / 1024.0 / 1024.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / 1024.0 / 1024.0)
+    }
+}
+
+// Pure: the notification's title and body for a finished job.
+pub fn summary(entry: &HistoryEntry) -> (String, String) {
+    let name = Path::new(&entry.output).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| entry.output.clone());
+    if entry.status == JobStatus::Failed {
+        return (format!("Failed: {}", name), entry.error.clone().unwrap_or_default());
+    }
+    let body = match entry.output_bytes < entry.input_bytes && entry.input_bytes > 0 {
+        true => format!(
+            "{} → {}, saved {} ({:.0}%)",
+            size(entry.input_bytes),
+            size(entry.output_bytes),
+            size(entry.input_bytes - entry.output_bytes),
+            (entry.input_bytes - entry.output_bytes) as f64 * 100.0 / entry.input_bytes as f64
+        ),
+        false => format!("{} → {}, nothing saved", size(entry.input_bytes), size(entry.output_bytes)),
+    };
+    (format!("Done: {}", name), body)
+}
+
+// Jobs only (queue or direct): batch items and previews would be a flood.
+pub fn notify_finished(app: &AppHandle, entry: &HistoryEntry) {
+    if queue::running_job_id().is_none() || entry.simulated || entry.partial || entry.error.as_deref() == Some(cancel::CANCELLED) {
+        return;
+    }
+    let (title, body) = summary(entry);
+    if let Err(e) = app.notification().builder().title(title).body(body).show() {
+        println!("⚠️ Could not show the notification: {}", e);
+    }
+}
+
+// ==========================================
+// COMMAND: CLOSE TO TRAY
+// ==========================================
+#[tauri::command]
+pub fn set_close_to_tray(store: State<'_, SettingsStore>, enabled: bool) -> Result<(), String> {
+    store.update(|s| s.close_to_tray = enabled).map(|_| ())
+}
@@ -0,0 +1,334 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::Manager;
+
+use crate::AppHandle;
+use crate::instance::{self, Owner};
+use crate::store;
+
+// ==========================================
+// SESSION UNDO
+// ==========================================
+// What replace-in-place and managed-folder cleanup would delete is moved
+// aside instead (a hidden `.name.undo-<id>` next to it, so it's a rename on
+// the same volume), and the action goes into a journal in app_data_dir/undo,
+// one file per action. Until the app quits the user can put the files back.
+//
+// The session ends with the process: on the next start, actions of owners
+// that are gone are settled (the files held aside are deleted) and dropped.
+// The disk space only comes back then; that's the price of an undo.
+//
+// An action whose held files have disappeared (deleted by hand, a volume
+// that's gone) is reported as expired and can't be undone.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UndoKind {
+    ReplaceInPlace,
+    Cleanup,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UndoState {
+    Undoable,
+    Undone,
+    // Something held aside is gone
+    Expired,
+}
+
+// A file moved out of the way: `held` goes back to `path`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HeldFile {
+    pub path: PathBuf,
+    pub held: PathBuf,
+    // Whatever is at `path` by then is replaced (the new file of a
+    // replace-in-place); otherwise an occupied `path` blocks the undo
+    #[serde(default)]
+    pub replace: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UndoAction {
+    pub id: u64,
+    pub kind: UndoKind,
+    pub description: String,
+    pub at: u64,
+    pub files: Vec<HeldFile>,
+    pub state: UndoState,
+    owner: Owner,
+}
+
+fn journal_dir(app: &AppHandle) -> Option<PathBuf> {
+    app.path().app_data_dir().ok().map(|d| d.join("undo"))
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
+}
+
+fn journal_file(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{}.json", id))
+}
+
+fn save(dir: &Path, action: &UndoAction) -> Result<(), String> {
+    store::save_json(&journal_file(dir, action.id), action).map_err(|e| format!("Could not write the undo journal: {}", e))
+}
+
+// Journals only: their .bak and .tmp files sit next to them.
+fn load_all(dir: &Path) -> Vec<UndoAction> {
+    let mut actions: Vec<UndoAction> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|p| store::read_json(&p).value())
+        .collect();
+    actions.sort_by_key(|a| a.id);
+    actions
+}
+
+// Where `path` waits while it can still be undone.
+pub fn held_path(path: &Path, id: u64) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!(".{}.undo-{}", name, id))
+}
+
+// An id for a new action; the held paths are named after it.
+pub fn next_id() -> u64 {
+    now_nanos()
+}
+
+// Moves `path` aside for action `id`, in place of deleting it.
+pub fn hold(path: &Path, id: u64, replace: bool) -> Result<HeldFile, String> {
+    let held = held_path(path, id);
+    fs::rename(path, &held).map_err(|e| format!("Could not move {} aside: {}", path.display(), e))?;
+    Ok(HeldFile { path: path.to_path_buf(), held, replace })
+}
+
+// Journals an action whose files were set aside with `hold`. Without a
+// journal there's no undo, so the files are really deleted then.
+pub fn register(app: &AppHandle, id: u64, kind: UndoKind, description: String, files: Vec<HeldFile>) -> Option<u64> {
+    if files.is_empty() {
+        return None;
+    }
+    let action = UndoAction { id, kind, description, at: now_nanos() / 1_000_000_000, files, state: UndoState::Undoable, owner: Owner::current() };
+    match journal_dir(app).ok_or_else(|| "No app data folder".to_string()).and_then(|dir| save(&dir, &action)) {
+        Ok(()) => Some(id),
+        Err(e) => {
+            println!("⚠️ {}; deleting instead", e);
+            settle(&action);
+            None
+        }
+    }
+}
+
+fn settle(action: &UndoAction) {
+    if action.state == UndoState::Undone {
+        return;
+    }
+    for file in &action.files {
+        let _ = fs::remove_file(&file.held);
+    }
+}
+
+// Expired when a held file is gone. Only for actions not undone yet.
+fn refresh(action: &mut UndoAction) {
+    if action.state == UndoState::Undoable && action.files.iter().any(|f| !f.held.exists()) {
+        action.state = UndoState::Expired;
+    }
+}
+
+// On startup: settles what earlier sessions left behind.
+pub fn recover(app: &AppHandle) {
+    if instance::is_secondary(app) {
+        return;
+    }
+    let Some(dir) = journal_dir(app) else { return };
+    for action in load_all(&dir).into_iter().filter(|a| !a.owner.alive()) {
+        settle(&action);
+        store::remove(&journal_file(&dir, action.id));
+    }
+}
+
+fn undo(dir: &Path, action: &mut UndoAction) -> Result<(), String> {
+    match action.state {
+        UndoState::Undone => return Err("That was undone already".to_string()),
+        UndoState::Expired => return Err("That can't be undone any more: files it kept aside are gone".to_string()),
+        UndoState::Undoable => {}
+    }
+    if let Some(blocked) = action.files.iter().find(|f| !f.replace && f.path.exists()) {
+        return Err(format!("{} exists again, so it wasn't put back", blocked.path.display()));
+    }
+    let mut failed = vec![];
+    for file in &action.files {
+        if let Err(e) = fs::rename(&file.held, &file.path) {
+            failed.push(format!("{}: {}", file.path.display(), e));
+        }
+    }
+    action.state = UndoState::Undone;
+    save(dir, action)?;
+    if failed.is_empty() { Ok(()) } else { Err(format!("Some files couldn't be put back: {}", failed.join("; "))) }
+}
+
+// ==========================================
+// COMMANDS: UNDO
+// ==========================================
+// This session's actions, newest first, with what can still be undone.
+#[tauri::command]
+pub fn list_undoable_actions(app: AppHandle) -> Vec<UndoAction> {
+    let Some(dir) = journal_dir(&app) else { return vec![] };
+    let me = Owner::current();
+    let mut actions: Vec<UndoAction> = load_all(&dir).into_iter().filter(|a| a.owner == me).collect();
+    for action in actions.iter_mut() {
+        refresh(action);
+    }
+    actions.reverse();
+    actions
+}
+
+#[tauri::command]
+pub fn undo_action(app: AppHandle, action_id: u64) -> Result<UndoAction, String> {
+    let dir = journal_dir(&app).ok_or("No app data folder")?;
+    let mut action = load_all(&dir)
+        .into_iter()
+        .find(|a| a.id == action_id && a.owner == Owner::current())
+        .ok_or_else(|| format!("No undoable action {} in this session", action_id))?;
+    refresh(&mut action);
+    undo(&dir, &mut action)?;
+    println!("↩️ Undid {}", action.description);
+    Ok(action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::TempDir;
+    use crate::jobtests::Harness;
+
+    fn folder(name: &str) -> TempDir {
+        TempDir::new(std::env::temp_dir().join(format!("undo-test-{}-{}", std::process::id(), name))).unwrap()
+    }
+
+    fn action(id: u64, file: &Path) -> UndoAction {
+        let files = vec![HeldFile { path: file.to_path_buf(), held: held_path(file, id), replace: false }];
+        UndoAction { id, kind: UndoKind::Cleanup, description: format!("action {}", id), at: 0, files, state: UndoState::Undoable, owner: Owner::current() }
+    }
+
+    #[test]
+    fn a_truncated_journal_comes_back_from_its_backup() {
+        let dir = folder("truncated");
+        let file = dir.path().join("clip.mp4");
+        fs::write(&file, "original").unwrap();
+        let held = hold(&file, 7, false).unwrap();
+        let mut first = action(7, &file);
+        first.files = vec![held];
+        save(dir.path(), &first).unwrap();
+        // A second write (say undo marking it done) is cut short
+        save(dir.path(), &first).unwrap();
+        let journal = journal_file(dir.path(), 7);
+        let text = fs::read_to_string(&journal).unwrap();
+        fs::write(&journal, &text[..text.len() / 3]).unwrap();
+
+        let mut actions = load_all(dir.path());
+        assert_eq!(actions.len(), 1, "the backup mustn't show up as an action of its own");
+        undo(dir.path(), &mut actions[0]).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "original");
+        assert_eq!(load_all(dir.path())[0].state, UndoState::Undone);
+    }
+
+    #[test]
+    fn load_all_skips_what_isnt_a_journal() {
+        let dir = folder("skips");
+        let file = dir.path().join("clip.mp4");
+        save(dir.path(), &action(1, &file)).unwrap();
+        save(dir.path(), &action(2, &file)).unwrap();
+        save(dir.path(), &action(2, &file)).unwrap();
+        fs::write(dir.path().join("3.json.tmp"), "{").unwrap();
+        fs::write(dir.path().join("notes.txt"), "x").unwrap();
+        let ids: Vec<u64> = load_all(dir.path()).iter().map(|a| a.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    fn state(h: &Harness, id: u64) -> Option<UndoState> {
+        list_undoable_actions(h.handle().clone()).iter().find(|a| a.id == id).map(|a| a.state)
+    }
+
+    #[test]
+    fn a_replace_in_place_is_undone_once() {
+        let h = Harness::new("undo-replace", "{}");
+        let movie = h.input("movie.mkv", 10);
+        fs::write(&movie, "original").unwrap();
+        // What replace.rs does: the original aside, the new file in its place
+        let id = next_id();
+        let held = hold(Path::new(&movie), id, true).unwrap();
+        fs::write(&movie, "new").unwrap();
+        register(h.handle(), id, UndoKind::ReplaceInPlace, "Replace movie.mkv in place".to_string(), vec![held]);
+        assert_eq!(state(&h, id), Some(UndoState::Undoable));
+
+        let undone = undo_action(h.handle().clone(), id).unwrap();
+        assert_eq!(undone.state, UndoState::Undone);
+        assert_eq!(fs::read_to_string(&movie).unwrap(), "original");
+        assert_eq!(h.files(), ["movie.mkv"]);
+
+        // Twice is refused, and leaves the restored file alone
+        let again = undo_action(h.handle().clone(), id).unwrap_err();
+        assert!(again.contains("undone already"), "{}", again);
+        assert_eq!(fs::read_to_string(&movie).unwrap(), "original");
+        assert_eq!(state(&h, id), Some(UndoState::Undone));
+    }
+
+    #[test]
+    fn a_cleanup_whose_held_files_went_has_expired() {
+        let h = Harness::new("undo-expired", "{}");
+        let (a, b) = (h.input("a.mp4", 10), h.input("b.mp4", 20));
+        let id = next_id();
+        let held: Vec<HeldFile> = [&a, &b].iter().map(|p| hold(Path::new(p), id, false).unwrap()).collect();
+        let gone = held[1].held.clone();
+        register(h.handle(), id, UndoKind::Cleanup, "Clean up 2 files".to_string(), held);
+        assert_eq!(state(&h, id), Some(UndoState::Undoable));
+
+        // Deleted by hand: nothing is put back, not even the file still held
+        fs::remove_file(&gone).unwrap();
+        assert_eq!(state(&h, id), Some(UndoState::Expired));
+        let error = undo_action(h.handle().clone(), id).unwrap_err();
+        assert!(error.contains("can't be undone any more"), "{}", error);
+        assert!(!Path::new(&a).exists() && !Path::new(&b).exists());
+        assert_eq!(state(&h, id), Some(UndoState::Expired));
+    }
+
+    #[test]
+    fn a_cleaned_path_that_was_reused_blocks_the_undo() {
+        let h = Harness::new("undo-blocked", "{}");
+        let clip = h.input("clip.mp4", 10);
+        let id = next_id();
+        let held = hold(Path::new(&clip), id, false).unwrap();
+        register(h.handle(), id, UndoKind::Cleanup, "Clean up clip.mp4".to_string(), vec![held.clone()]);
+        fs::write(&clip, "someone else's").unwrap();
+        assert!(undo_action(h.handle().clone(), id).unwrap_err().contains("exists again"));
+        assert_eq!(fs::read_to_string(&clip).unwrap(), "someone else's");
+        assert!(held.held.exists());
+        assert_eq!(state(&h, id), Some(UndoState::Undoable));
+    }
+
+    #[test]
+    fn an_earlier_sessions_actions_are_settled_not_undone() {
+        let h = Harness::new("undo-session", "{}");
+        let clip = h.input("clip.mp4", 10);
+        let dir = journal_dir(h.handle()).unwrap();
+        let held = hold(Path::new(&clip), 9, false).unwrap();
+        // An owner that has gone: this pid, started at another time
+        let earlier = UndoAction { files: vec![held.clone()], owner: Owner { started_at: 1, ..Owner::current() }, ..action(9, Path::new(&clip)) };
+        save(&dir, &earlier).unwrap();
+
+        assert_eq!(state(&h, 9), None);
+        assert!(undo_action(h.handle().clone(), 9).unwrap_err().contains("No undoable action"));
+        recover(h.handle());
+        assert!(!held.held.exists());
+        assert!(!journal_file(&dir, 9).exists());
+        assert!(!Path::new(&clip).exists());
+    }
+}
@@ -0,0 +1,662 @@
+use chrono::Utc;
+use reqwest::{Method, StatusCode, Url};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::events::{self, Event};
+use crate::history::{HistoryStore, JobStatus};
+use crate::settings::SettingsStore;
+
+// Files up to this size go up in one PUT; bigger ones in parts of this size
+// (S3 wants at least 5 MiB per part except the last)
+const PART_SIZE: u64 = 8 * 1024 * 1024;
+// Tries per request, with 1s, 2s, 4s... in between
+const ATTEMPTS: u32 = 4;
+// The keyring entry's user name; its service is the app identifier
+const SECRET_USER: &str = "upload-secret-access-key";
+
+// ==========================================
+// UPLOADS (S3-COMPATIBLE)
+// ==========================================
+// A finished video job with `upload` on is copied to the bucket configured
+// in settings (S3, Backblaze B2, MinIO, R2...). Requests are signed with
+// AWS Signature V4 by hand: a handful of PUT/POST calls didn't justify an SDK.
+//
+// Integrity: every request carries the SHA-256 of its body in
+// x-amz-content-sha256, which the server checks against what it received,
+// and the object's size is compared with the local file at the end.
+//
+// A failed upload never fails the job. It's recorded on the history entry
+// and can be run again with retry_upload.
+//
+// The secret key isn't in settings.json but in the OS keyring (Keychain,
+// Credential Manager, Secret Service), and nowhere else.
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UploadTarget {
+    // "https://s3.us-west-004.backblazeb2.com", "http://localhost:9000"
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    // Key prefix, e.g. "exports/2026"
+    #[serde(default)]
+    pub prefix: String,
+    pub access_key_id: String,
+    // Bucket in the path instead of the host name (MinIO and most self-hosted)
+    #[serde(default)]
+    pub path_style: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadStatus {
+    Uploaded,
+    Failed,
+}
+
+// `upload` of a history entry and a job result.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadRecord {
+    pub status: UploadStatus,
+    pub bucket: String,
+    pub key: String,
+    pub bytes: u64,
+    // Requests that had to be sent again
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default)]
+    pub error: Option<String>,
+    pub finished_at: u64,
+}
+
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct UploadProgress {
+    pub history_id: u64,
+    pub key: String,
+    pub part: u32,
+    pub parts: u32,
+    pub uploaded_bytes: u64,
+    pub total_bytes: u64,
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(data);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+// RFC 3986 unreserved characters stay, everything else is %XX; `/` stays
+// in paths.
+fn uri_encode(value: &str, keep_slash: bool) -> String {
+    let mut out = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            b'/' if keep_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+struct Client {
+    http: reqwest::Client,
+    target: UploadTarget,
+    secret: String,
+    scheme: String,
+    // Host header, with the port when it isn't the default
+    host: String,
+    retries: u32,
+}
+
+impl Client {
+    fn new(target: UploadTarget, secret: String) -> Result<Self, String> {
+        let url = Url::parse(&target.endpoint).map_err(|e| format!("The upload endpoint isn't a URL: {}", e))?;
+        let host = url.host_str().ok_or("The upload endpoint has no host")?;
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        let host = if target.path_style { host } else { format!("{}.{}", target.bucket, host) };
+        Ok(Client { http: reqwest::Client::new(), scheme: url.scheme().to_string(), host, target, secret, retries: 0 })
+    }
+
+    fn path(&self, key: &str) -> String {
+        if self.target.path_style {
+            format!("/{}/{}", uri_encode(&self.target.bucket, false), uri_encode(key, true))
+        } else {
+            format!("/{}", uri_encode(key, true))
+        }
+    }
+
+    // Authorization and x-amz-* headers for one request.
+    fn sign(&self, method: &Method, path: &str, query: &str, payload_hash: &str) -> Vec<(&'static str, String)> {
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let scope = format!("{}/{}/s3/aws4_request", date, self.target.region);
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, query, self.host, payload_hash, amz_date, signed_headers, payload_hash
+        );
+        let to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", amz_date, scope, sha256_hex(canonical.as_bytes()));
+        let mut key = hmac(format!("AWS4{}", self.secret).as_bytes(), date.as_bytes());
+        for part in [self.target.region.as_str(), "s3", "aws4_request"] {
+            key = hmac(&key, part.as_bytes());
+        }
+        let signature: String = hmac(&key, to_sign.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+        vec![
+            ("authorization", format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", self.target.access_key_id, scope, signed_headers, signature)),
+            ("x-amz-content-sha256", payload_hash.to_string()),
+            ("x-amz-date", amz_date),
+        ]
+    }
+
+    // Signs and sends, retrying network errors, 5xx and 429. Any other
+    // error status comes back as Err with the server's message.
+    async fn send(&mut self, method: Method, key: &str, query: &[(&str, &str)], body: Vec<u8>) -> Result<reqwest::Response, String> {
+        let path = self.path(key);
+        let mut pairs: Vec<String> = query.iter().map(|(k, v)| format!("{}={}", uri_encode(k, false), uri_encode(v, false))).collect();
+        pairs.sort();
+        let query = pairs.join("&");
+        let url = if query.is_empty() { format!("{}://{}{}", self.scheme, self.host, path) } else { format!("{}://{}{}?{}", self.scheme, self.host, path, query) };
+        let payload_hash = sha256_hex(&body);
+
+        let mut last_error = String::new();
+        for attempt in 0..ATTEMPTS {
+            if attempt > 0 {
+                self.retries += 1;
+                tokio::time::sleep(Duration::from_secs(1 << (attempt - 1))).await;
+            }
+            let mut request = self.http.request(method.clone(), &url).body(body.clone());
+            for (name, value) in self.sign(&method, &path, &query, &payload_hash) {
+                request = request.header(name, value);
+            }
+            match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) if response.status().is_server_error() || response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    last_error = format!("{} {}", method, response.status());
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let text = response.text().await.unwrap_or_default();
+                    return Err(format!("{} {}: {}", method, status, s3_message(&text).unwrap_or(&text)));
+                }
+                Err(e) => last_error = e.to_string(),
+            }
+        }
+        Err(format!("Gave up after {} tries: {}", ATTEMPTS, last_error))
+    }
+}
+
+fn xml_value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = xml[start..].find(&format!("</{}>", tag))? + start;
+    Some(&xml[start..end])
+}
+
+fn s3_message(xml: &str) -> Option<&str> {
+    xml_value(xml, "Message").or_else(|| xml_value(xml, "Code"))
+}
+
+fn read_part(file: &mut File, offset: u64, len: u64) -> Result<Vec<u8>, String> {
+    let mut buf = vec![0u8; len as usize];
+    file.seek(SeekFrom::Start(offset)).and_then(|_| file.read_exact(&mut buf)).map_err(|e| e.to_string())?;
+    Ok(buf)
+}
+
+async fn multipart(app: &AppHandle, client: &mut Client, history_id: u64, key: &str, path: &Path, size: u64) -> Result<(), String> {
+    let created = client.send(Method::POST, key, &[("uploads", "")], vec![]).await?;
+    let text = created.text().await.map_err(|e| e.to_string())?;
+    let upload_id = xml_value(&text, "UploadId").ok_or("The server didn't return an upload id")?.to_string();
+
+    let result = async {
+        let mut file = File::open(path).map_err(|e| e.to_string())?;
+        let parts = size.div_ceil(PART_SIZE) as u32;
+        let mut etags = vec![];
+        for part in 1..=parts {
+            let offset = (part as u64 - 1) * PART_SIZE;
+            let body = read_part(&mut file, offset, PART_SIZE.min(size - offset))?;
+            let number = part.to_string();
+            let response = client.send(Method::PUT, key, &[("partNumber", &number), ("uploadId", &upload_id)], body).await?;
+            let etag = response.headers().get("etag").and_then(|v| v.to_str().ok()).ok_or("A part came back without an ETag")?;
+            etags.push(etag.to_string());
+            events::emit(app, Event::UploadProgress(UploadProgress {
+                history_id,
+                key: key.to_string(),
+                part,
+                parts,
+                uploaded_bytes: (offset + PART_SIZE).min(size),
+                total_bytes: size,
+            }));
+        }
+        let mut xml = String::from("<CompleteMultipartUpload>");
+        for (i, etag) in etags.iter().enumerate() {
+            xml.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", i + 1, etag));
+        }
+        xml.push_str("</CompleteMultipartUpload>");
+        let done = client.send(Method::POST, key, &[("uploadId", &upload_id)], xml.into_bytes()).await?;
+        // Completion can fail with a 200 and an <Error> body
+        let text = done.text().await.map_err(|e| e.to_string())?;
+        if text.contains("<Error>") {
+            return Err(format!("Completing the upload failed: {}", s3_message(&text).unwrap_or(&text)));
+        }
+        Ok(())
+    };
+    let result = result.await;
+    if result.is_err() {
+        // Otherwise the parts stay in the bucket (and on the bill)
+        let _ = client.send(Method::DELETE, key, &[("uploadId", &upload_id)], vec![]).await;
+    }
+    result
+}
+
+async fn put(app: &AppHandle, client: &mut Client, history_id: u64, key: &str, path: &Path, size: u64) -> Result<(), String> {
+    let body = fs::read(path).map_err(|e| e.to_string())?;
+    client.send(Method::PUT, key, &[], body).await?;
+    events::emit(app, Event::UploadProgress(UploadProgress { history_id, key: key.to_string(), part: 1, parts: 1, uploaded_bytes: size, total_bytes: size }));
+    Ok(())
+}
+
+fn secret_entry(app: &AppHandle) -> Result<keyring::Entry, String> {
+    keyring::Entry::new(&app.config().identifier, SECRET_USER).map_err(|e| format!("The system keyring isn't available: {}", e))
+}
+
+fn write_secret(app: &AppHandle, secret: &str) -> Result<(), String> {
+    secret_entry(app)?.set_password(secret).map_err(|e| format!("Could not save the secret key in the system keyring: {}", e))
+}
+
+fn read_secret(app: &AppHandle) -> Result<String, String> {
+    match secret_entry(app)?.get_password() {
+        Ok(secret) => Ok(secret),
+        Err(keyring::Error::NoEntry) => Err("The upload secret key is missing; set the destination up again".to_string()),
+        Err(e) => Err(format!("Could not read the secret key from the system keyring: {}", e)),
+    }
+}
+
+fn forget_secret(app: &AppHandle) -> Result<(), String> {
+    match secret_entry(app)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(format!("Could not remove the secret key from the system keyring: {}", e)),
+    }
+}
+
+fn object_key(target: &UploadTarget, path: &Path) -> String {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    match target.prefix.trim_matches('/') {
+        "" => name,
+        prefix => format!("{}/{}", prefix, name),
+    }
+}
+
+// Uploads `output` and records the outcome on history entry `history_id`.
+pub async fn upload(app: &AppHandle, history_id: u64, output: &str) -> UploadRecord {
+    let target = app.try_state::<SettingsStore>().and_then(|s| s.get().upload_target);
+    upload_to(app, history_id, output, target, read_secret).await
+}
+
+// upload with the destination and the way to its secret given
+async fn upload_to(app: &AppHandle, history_id: u64, output: &str, target: Option<UploadTarget>, secret: impl FnOnce(&AppHandle) -> Result<String, String>) -> UploadRecord {
+    let path = Path::new(output);
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let mut record = UploadRecord {
+        status: UploadStatus::Failed,
+        bucket: target.as_ref().map(|t| t.bucket.clone()).unwrap_or_default(),
+        key: target.as_ref().map(|t| object_key(t, path)).unwrap_or_default(),
+        bytes: size,
+        retries: 0,
+        error: None,
+        finished_at: 0,
+    };
+    let result = async {
+        let target = target.ok_or("No upload destination is set up")?;
+        let mut client = Client::new(target, secret(app)?.trim().to_string())?;
+        println!("☁️ Uploading {} to {}/{}", output, record.bucket, record.key);
+        let sent = if size > PART_SIZE {
+            multipart(app, &mut client, history_id, &record.key, path, size).await
+        } else {
+            put(app, &mut client, history_id, &record.key, path, size).await
+        };
+        record.retries = client.retries;
+        sent?;
+        let head = client.send(Method::HEAD, &record.key, &[], vec![]).await?;
+        let stored = head.headers().get("content-length").and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok());
+        match stored {
+            Some(stored) if stored != size => Err(format!("The bucket has {} bytes, the file {}", stored, size)),
+            _ => Ok(()),
+        }
+    };
+    match result.await {
+        Ok(()) => record.status = UploadStatus::Uploaded,
+        Err(e) => {
+            println!("⚠️ Upload failed: {}", e);
+            record.error = Some(e);
+        }
+    }
+    record.finished_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    if let Some(history) = app.try_state::<HistoryStore>() {
+        if let Err(e) = history.set_upload(history_id, record.clone()) {
+            println!("⚠️ Could not record the upload: {}", e);
+        }
+    }
+    record
+}
+
+// ==========================================
+// COMMAND: UPLOAD DESTINATION
+// ==========================================
+// `secret_access_key` is only needed when it changes; None as the target
+// turns uploads off and forgets the secret.
+#[tauri::command]
+pub fn set_upload_target(app: AppHandle, store: State<'_, SettingsStore>, target: Option<UploadTarget>, secret_access_key: Option<String>) -> Result<(), String> {
+    match &target {
+        Some(t) => {
+            Url::parse(&t.endpoint).map_err(|e| format!("The upload endpoint isn't a URL: {}", e))?;
+            if t.bucket.trim().is_empty() || t.region.trim().is_empty() || t.access_key_id.trim().is_empty() {
+                return Err("Bucket, region and access key id are all needed".to_string());
+            }
+            if let Some(secret) = secret_access_key.filter(|s| !s.trim().is_empty()) {
+                write_secret(&app, secret.trim())?;
+            }
+        }
+        None => forget_secret(&app)?,
+    }
+    store.update(|s| s.upload_target = target).map(|_| ())
+}
+
+// ==========================================
+// COMMAND: RETRY UPLOAD
+// ==========================================
+// `id` is the history entry's.
+#[tauri::command]
+pub async fn retry_upload(app: AppHandle, id: u64) -> Result<UploadRecord, String> {
+    let entry = app.state::<HistoryStore>().get(id).ok_or_else(|| format!("History entry {} not found", id))?;
+    if entry.status != JobStatus::Success || !Path::new(&entry.output).is_file() {
+        return Err("Only the output of a successful job that's still on disk can be uploaded".to_string());
+    }
+    Ok(upload(&app, id, &entry.output).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{run, Harness};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    // One request as the bucket got it
+    #[derive(Clone, Debug)]
+    struct Seen {
+        method: String,
+        path: String,
+        query: String,
+        headers: HashMap<String, String>,
+        body: Vec<u8>,
+    }
+
+    #[derive(Default)]
+    struct Bucket {
+        objects: HashMap<String, Vec<u8>>,
+        parts: HashMap<u32, Vec<u8>>,
+        seen: Vec<Seen>,
+        // Requests left to answer with a 503
+        unavailable: usize,
+        // A part number the bucket refuses
+        deny_part: Option<u32>,
+    }
+
+    // An S3 endpoint on localhost that behaves like MinIO for the calls an
+    // upload makes: it keeps objects, puts multipart uploads together and
+    // checks every body against its x-amz-content-sha256.
+    #[derive(Clone)]
+    struct FakeS3 {
+        endpoint: String,
+        bucket: Arc<Mutex<Bucket>>,
+    }
+
+    impl FakeS3 {
+        fn start(bucket: Bucket) -> FakeS3 {
+            let bucket = Arc::new(Mutex::new(bucket));
+            let endpoint = run({
+                let bucket = bucket.clone();
+                async move {
+                    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+                    tauri::async_runtime::spawn(async move {
+                        while let Ok((stream, _)) = listener.accept().await {
+                            tauri::async_runtime::spawn(serve(stream, bucket.clone()));
+                        }
+                    });
+                    endpoint
+                }
+            });
+            FakeS3 { endpoint, bucket }
+        }
+
+        fn target(&self) -> UploadTarget {
+            UploadTarget {
+                endpoint: self.endpoint.clone(),
+                region: "us-east-1".to_string(),
+                bucket: "exports".to_string(),
+                prefix: "2026/".to_string(),
+                access_key_id: "AKIDTEST".to_string(),
+                path_style: true,
+            }
+        }
+
+        fn object(&self, path: &str) -> Option<Vec<u8>> {
+            self.bucket.lock().unwrap().objects.get(path).cloned()
+        }
+
+        // "METHOD query" of every request, in order
+        fn calls(&self) -> Vec<String> {
+            self.bucket.lock().unwrap().seen.iter().map(|s| format!("{} {}", s.method, s.query).trim().to_string()).collect()
+        }
+    }
+
+    // Requests on one connection, until the client closes it
+    async fn serve(mut stream: TcpStream, bucket: Arc<Mutex<Bucket>>) {
+        let mut buf = vec![];
+        loop {
+            let head_end = loop {
+                if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break i + 4;
+                }
+                let mut chunk = [0u8; 64 * 1024];
+                match stream.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                }
+            };
+            let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+            let mut lines = head.lines();
+            let mut request_line = lines.next().unwrap().split(' ');
+            let method = request_line.next().unwrap().to_string();
+            let target = request_line.next().unwrap();
+            let (path, query) = target.split_once('?').unwrap_or((target, ""));
+            let (path, query) = (path.to_string(), query.to_string());
+            let headers: HashMap<String, String> = lines.filter_map(|l| l.split_once(':')).map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string())).collect();
+            let length: usize = headers.get("content-length").map_or(0, |l| l.parse().unwrap());
+            while buf.len() < head_end + length {
+                let mut chunk = [0u8; 64 * 1024];
+                match stream.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                }
+            }
+            let body = buf[head_end..head_end + length].to_vec();
+            buf.drain(..head_end + length);
+            let seen = Seen { method, path, query, headers, body };
+            let (status, extra, reply) = answer(&mut bucket.lock().unwrap(), seen);
+            let length = if extra.starts_with("content-length") { String::new() } else { format!("content-length: {}\r\n", reply.len()) };
+            let response = format!("HTTP/1.1 {}\r\n{}{}\r\n{}", status, length, extra, reply);
+            if stream.write_all(response.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn s3_error(code: &str, message: &str) -> String {
+        format!("<Error><Code>{}</Code><Message>{}</Message></Error>", code, message)
+    }
+
+    // Status line, extra headers and body for one request
+    fn answer(bucket: &mut Bucket, seen: Seen) -> (&'static str, String, String) {
+        bucket.seen.push(seen.clone());
+        if bucket.unavailable > 0 {
+            bucket.unavailable -= 1;
+            return ("503 Service Unavailable", String::new(), s3_error("SlowDown", "Please reduce your request rate."));
+        }
+        let signed = seen.headers.get("authorization").is_some_and(|a| a.starts_with("AWS4-HMAC-SHA256 Credential=AKIDTEST/"));
+        if !signed {
+            return ("403 Forbidden", String::new(), s3_error("AccessDenied", "Unsigned request"));
+        }
+        if seen.headers.get("x-amz-content-sha256") != Some(&sha256_hex(&seen.body)) {
+            return ("400 Bad Request", String::new(), s3_error("XAmzContentSHA256Mismatch", "The provided 'x-amz-content-sha256' header does not match what was computed."));
+        }
+        let query: HashMap<&str, &str> = seen.query.split('&').filter(|q| !q.is_empty()).map(|q| q.split_once('=').unwrap_or((q, ""))).collect();
+        match (seen.method.as_str(), query.get("partNumber"), query.contains_key("uploadId")) {
+            ("POST", _, false) => ("200 OK", String::new(), "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId></InitiateMultipartUploadResult>".to_string()),
+            ("PUT", Some(number), true) => {
+                let number: u32 = number.parse().unwrap();
+                if bucket.deny_part == Some(number) {
+                    return ("403 Forbidden", String::new(), s3_error("AccessDenied", "Access Denied."));
+                }
+                bucket.parts.insert(number, seen.body);
+                ("200 OK", format!("etag: \"part-{}\"\r\n", number), String::new())
+            }
+            ("POST", _, true) => {
+                let mut numbers: Vec<u32> = bucket.parts.keys().copied().collect();
+                numbers.sort();
+                let object = numbers.iter().flat_map(|n| bucket.parts[n].clone()).collect();
+                bucket.parts.clear();
+                bucket.objects.insert(seen.path, object);
+                ("200 OK", String::new(), "<CompleteMultipartUploadResult></CompleteMultipartUploadResult>".to_string())
+            }
+            ("DELETE", _, true) => {
+                bucket.parts.clear();
+                ("204 No Content", String::new(), String::new())
+            }
+            ("PUT", None, false) => {
+                bucket.objects.insert(seen.path, seen.body);
+                ("200 OK", String::new(), String::new())
+            }
+            ("HEAD", _, _) => match bucket.objects.get(&seen.path) {
+                Some(object) => ("200 OK", format!("content-length: {}\r\n", object.len()), String::new()),
+                None => ("404 Not Found", "content-length: 0\r\n".to_string(), String::new()),
+            },
+            _ => ("400 Bad Request", String::new(), s3_error("InvalidRequest", "Unexpected request")),
+        }
+    }
+
+    fn upload_file(h: &Harness, s3: &FakeS3, output: &str) -> UploadRecord {
+        let (app, output, target) = (h.handle().clone(), output.to_string(), s3.target());
+        run(async move { upload_to(&app, 1, &output, Some(target), |_| Ok("wJalrXUtnFEMI/K7MDENG".to_string())).await })
+    }
+
+    fn file_of(h: &Harness, name: &str, bytes: u64) -> (String, Vec<u8>) {
+        let content: Vec<u8> = (0..bytes).map(|i| (i % 253) as u8).collect();
+        let path = h.file(name);
+        fs::write(&path, &content).unwrap();
+        (path, content)
+    }
+
+    #[test]
+    fn a_small_file_goes_up_in_one_put() {
+        let h = Harness::new("upload-put", "{}");
+        let s3 = FakeS3::start(Bucket::default());
+        let (path, content) = file_of(&h, "clip small.mp4", 300_000);
+        let record = upload_file(&h, &s3, &path);
+        assert_eq!(record.status, UploadStatus::Uploaded, "{:?}", record.error);
+        assert_eq!((record.bucket.as_str(), record.key.as_str(), record.bytes), ("exports", "2026/clip small.mp4", 300_000));
+        assert_eq!(s3.object("/exports/2026/clip%20small.mp4"), Some(content));
+        assert_eq!(s3.calls(), ["PUT", "HEAD"]);
+        assert_eq!(h.emitted("upload-progress").len(), 1);
+    }
+
+    #[test]
+    fn a_big_file_goes_up_in_parts() {
+        let h = Harness::new("upload-multipart", "{}");
+        let s3 = FakeS3::start(Bucket::default());
+        let (path, content) = file_of(&h, "big.mp4", PART_SIZE * 2 + 100);
+        let record = upload_file(&h, &s3, &path);
+        assert_eq!(record.status, UploadStatus::Uploaded, "{:?}", record.error);
+        assert_eq!(s3.object("/exports/2026/big.mp4"), Some(content));
+        assert_eq!(s3.calls(), [
+            "POST uploads=",
+            "PUT partNumber=1&uploadId=upload-1",
+            "PUT partNumber=2&uploadId=upload-1",
+            "PUT partNumber=3&uploadId=upload-1",
+            "POST uploadId=upload-1",
+            "HEAD",
+        ]);
+        let progress = h.emitted("upload-progress");
+        let uploaded: Vec<u64> = progress.iter().map(|p| p["uploaded_bytes"].as_u64().unwrap()).collect();
+        assert_eq!(uploaded, [PART_SIZE, PART_SIZE * 2, PART_SIZE * 2 + 100]);
+    }
+
+    #[test]
+    fn a_busy_server_is_tried_again() {
+        let h = Harness::new("upload-retry", "{}");
+        let s3 = FakeS3::start(Bucket { unavailable: 1, ..Default::default() });
+        let (path, _) = file_of(&h, "clip.mp4", 1000);
+        let record = upload_file(&h, &s3, &path);
+        assert_eq!(record.status, UploadStatus::Uploaded, "{:?}", record.error);
+        assert_eq!(record.retries, 1);
+        assert_eq!(s3.calls(), ["PUT", "PUT", "HEAD"]);
+    }
+
+    #[test]
+    fn a_refused_part_fails_the_upload_and_aborts_it() {
+        let h = Harness::new("upload-abort", "{}");
+        let s3 = FakeS3::start(Bucket { deny_part: Some(2), ..Default::default() });
+        let (path, _) = file_of(&h, "big.mp4", PART_SIZE * 2 + 100);
+        let record = upload_file(&h, &s3, &path);
+        assert_eq!(record.status, UploadStatus::Failed);
+        assert_eq!(record.error.as_deref(), Some("PUT 403 Forbidden: Access Denied."));
+        assert_eq!(s3.calls().last().map(String::as_str), Some("DELETE uploadId=upload-1"));
+        assert_eq!(s3.object("/exports/2026/big.mp4"), None);
+    }
+
+    #[test]
+    fn without_a_destination_nothing_is_sent_and_no_secret_is_read() {
+        let h = Harness::new("upload-untargeted", "{}");
+        let (path, _) = file_of(&h, "clip.mp4", 1000);
+        let app = h.handle().clone();
+        let record = run(async move { upload_to(&app, 1, &path, None, |_| panic!("the secret was read")).await });
+        assert_eq!(record.status, UploadStatus::Failed);
+        assert_eq!(record.error.as_deref(), Some("No upload destination is set up"));
+    }
+
+    #[test]
+    fn object_keys_go_under_the_prefix() {
+        let mut target = FakeS3 { endpoint: String::new(), bucket: Default::default() }.target();
+        assert_eq!(object_key(&target, Path::new("/out/a b.mp4")), "2026/a b.mp4");
+        target.prefix = "/".to_string();
+        assert_eq!(object_key(&target, Path::new("/out/a b.mp4")), "a b.mp4");
+    }
+}
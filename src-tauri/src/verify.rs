@@ -0,0 +1,332 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use tauri::{Manager, State};
+use tauri_plugin_shell::process::CommandEvent;
+
+use crate::AppHandle;
+use crate::cancel;
+use crate::duration::Expected;
+use crate::ffmpeg;
+use crate::probe::{self, MediaInfo};
+use crate::risk::RiskLevel;
+use crate::settings::SettingsStore;
+
+// A finished encode that didn't pass read_back
+pub const INCOMPLETE_OUTPUT: &str = "Incomplete output";
+
+// Short windows a sampled check decodes, spread over the output
+const SAMPLE_WINDOWS: u32 = 8;
+const WINDOW_SECS: f64 = 2.0;
+// Truncation shows up at the end, so the last seconds are always decoded
+const TAIL_SECS: f64 = 10.0;
+// The byte-offset fallback for the tail reads at least this much
+const MIN_TAIL_BYTES: u64 = 4 * 1024 * 1024;
+
+// ==========================================
+// OUTPUT VERIFICATION TIERS
+// ==========================================
+// basic   the probe checks encode_video always does (duration, streams)
+// sampled decode SAMPLE_WINDOWS short windows spread over the output, plus
+//         the last TAIL_SECS, adding up decode errors
+// full    decode everything (as slow as playing the file back at top speed)
+//
+// Seeking close to EOF is unreliable in some containers (the demuxer lands
+// past the last keyframe and decodes nothing). When the tail comes back
+// empty it's decoded again from a byte offset near the end of the file,
+// which every stream-style container (ts, mkv, webm) can start from.
+//
+// A high-risk encode (see risk.rs) is upgraded to full when
+// deep_verify_on_risk is on.
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifyTier {
+    #[default]
+    Basic,
+    Sampled,
+    Full,
+}
+
+// How a sampled check got to the end of the output.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TailMethod {
+    Seek,
+    ByteOffset,
+    // Neither decoded a frame; counted as an error
+    Unreadable,
+}
+
+// `verification` of a job result.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct VerifyReport {
+    pub tier: VerifyTier,
+    // The configured tier, when a risky encode upgraded it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upgraded_from: Option<VerifyTier>,
+    // Seconds of output actually decoded
+    pub decoded_secs: f64,
+    pub errors: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tail: Option<TailMethod>,
+}
+
+#[derive(Default)]
+struct Decoded {
+    errors: u32,
+    first_error: Option<String>,
+    frames: u64,
+    secs: f64,
+}
+
+// Decodes `output` to nowhere. `-progress` goes to stdout whatever the log
+// level, so frames and time come from there; stderr only carries errors.
+async fn decode(app: &AppHandle, output: &str, input_args: &[String], secs: Option<f64>) -> Result<Decoded, String> {
+    let mut args = ["-v", "error", "-nostats", "-progress", "pipe:1"].map(String::from).to_vec();
+    args.extend(input_args.iter().cloned());
+    args.extend(["-i".to_string(), output.to_string()]);
+    if let Some(secs) = secs {
+        args.extend(["-t".to_string(), format!("{:.3}", secs)]);
+    }
+    args.extend(["-f", "null", "-"].map(String::from));
+
+    let mut sidecar = ffmpeg::spawn(app, args)?;
+    let mut decoded = Decoded::default();
+    while let Some(event) = sidecar.next().await? {
+        match event {
+            CommandEvent::Stdout(bytes) => {
+                for line in String::from_utf8_lossy(&bytes).lines() {
+                    if let Some(frames) = line.strip_prefix("frame=").and_then(|v| v.trim().parse().ok()) {
+                        decoded.frames = frames;
+                    }
+                    if let Some(us) = line.strip_prefix("out_time_us=").and_then(|v| v.trim().parse::<i64>().ok()) {
+                        decoded.secs = us.max(0) as f64 / 1_000_000.0;
+                    }
+                }
+            }
+            CommandEvent::Stderr(bytes) => {
+                for line in String::from_utf8_lossy(&bytes).lines().filter(|l| !l.trim().is_empty()) {
+                    decoded.errors += 1;
+                    decoded.first_error.get_or_insert_with(|| line.trim().to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(decoded)
+}
+
+fn add(report: &mut VerifyReport, decoded: Decoded) {
+    report.errors += decoded.errors;
+    report.decoded_secs += decoded.secs;
+    if report.first_error.is_none() {
+        report.first_error = decoded.first_error;
+    }
+}
+
+fn seek(at: f64) -> Vec<String> {
+    vec!["-ss".to_string(), format!("{:.3}", at)]
+}
+
+async fn sampled(app: &AppHandle, output: &str, duration: f64, report: &mut VerifyReport) -> Result<(), String> {
+    let body = duration - TAIL_SECS;
+    for i in 0..SAMPLE_WINDOWS {
+        let at = body * i as f64 / SAMPLE_WINDOWS as f64;
+        add(report, decode(app, output, &seek(at), Some(WINDOW_SECS)).await?);
+    }
+
+    let tail = decode(app, output, &seek(body), None).await?;
+    if tail.frames > 0 {
+        report.tail = Some(TailMethod::Seek);
+        add(report, tail);
+        return Ok(());
+    }
+    // Bytes for about twice the tail at the average bitrate
+    let size = fs::metadata(output).map(|m| m.len()).unwrap_or(0);
+    let tail_bytes = ((size as f64 * 2.0 * TAIL_SECS / duration) as u64).max(MIN_TAIL_BYTES);
+    let offset = size.saturating_sub(tail_bytes);
+    let tail = decode(app, output, &["-skip_initial_bytes".to_string(), offset.to_string()], None).await?;
+    if tail.frames > 0 {
+        // Starting mid-packet always costs a few errors until the next
+        // keyframe, so only "it decodes to the end" counts here
+        report.tail = Some(TailMethod::ByteOffset);
+        report.decoded_secs += tail.secs;
+    } else {
+        report.tail = Some(TailMethod::Unreadable);
+        report.errors += 1;
+        report.first_error.get_or_insert_with(|| "The last seconds of the output could not be decoded".to_string());
+    }
+    Ok(())
+}
+
+// The tier for a finished encode: the setting, or full for a risky one.
+pub fn tier_for(app: &AppHandle, risk: RiskLevel) -> (VerifyTier, Option<VerifyTier>) {
+    let settings = app.try_state::<SettingsStore>().map(|s| s.get()).unwrap_or_default();
+    if risk == RiskLevel::High && settings.deep_verify_on_risk && settings.verify != VerifyTier::Full {
+        return (VerifyTier::Full, Some(settings.verify));
+    }
+    (settings.verify, None)
+}
+
+// Every tier starts here, before a job counts as done: ffmpeg can exit 0
+// after a crash in a filter or a full disk and leave a file that looks
+// finished. One ffprobe can't open, or that's shorter than `expected`, fails
+// the job (so the staged file is deleted, never moved into place).
+pub async fn read_back(app: &AppHandle, staged: &str, expected: &Expected) -> Result<MediaInfo, String> {
+    let media = match probe::probe(app, staged).await {
+        Ok(media) => media,
+        Err(e) if e == cancel::CANCELLED => return Err(e),
+        Err(e) => return Err(format!("{}: the output can't be read back ({})", INCOMPLETE_OUTPUT, e)),
+    };
+    if expected.truncated(media.duration) {
+        return Err(format!(
+            "{}: the output is {:.1}s long, but {:.1}s was expected",
+            INCOMPLETE_OUTPUT,
+            media.duration.unwrap_or(0.0),
+            expected.secs.unwrap_or(0.0)
+        ));
+    }
+    Ok(media)
+}
+
+// `duration` is the output's; without one, or when the windows would cover
+// most of it anyway, sampled decodes everything.
+pub async fn run(app: &AppHandle, output: &str, risk: RiskLevel, duration: Option<f64>) -> Result<VerifyReport, String> {
+    let (tier, upgraded_from) = tier_for(app, risk);
+    let mut report = VerifyReport { tier, upgraded_from, ..Default::default() };
+    let sample_span = SAMPLE_WINDOWS as f64 * WINDOW_SECS + TAIL_SECS;
+    match (tier, duration) {
+        (VerifyTier::Basic, _) => {}
+        (VerifyTier::Sampled, Some(d)) if d > sample_span * 2.0 => {
+            println!("🔎 Sampling {} ({} windows and the end)", output, SAMPLE_WINDOWS);
+            sampled(app, output, d, &mut report).await?;
+        }
+        (VerifyTier::Sampled | VerifyTier::Full, _) => {
+            println!("🔎 Decoding all of {} to check it", output);
+            add(&mut report, decode(app, output, &[], None).await?);
+        }
+    }
+    Ok(report)
+}
+
+// ==========================================
+// COMMAND: VERIFICATION TIER
+// ==========================================
+#[tauri::command]
+pub fn set_verify_tier(store: State<'_, SettingsStore>, tier: VerifyTier) -> Result<(), String> {
+    store.update(|s| s.verify = tier).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobtests::{run_video, video, Harness};
+    use serde_json::{json, Value};
+
+    // A 2-minute clip, long enough for the sampled windows
+    fn long_clip() -> Value {
+        json!({
+            "streams": [{ "index": 0, "codec_type": "video", "codec_name": "h264", "width": 1280, "height": 720, "pix_fmt": "yuv420p", "r_frame_rate": "30/1", "avg_frame_rate": "30/1" }],
+            "format": { "duration": "120.000000", "bit_rate": "8000000", "format_name": "mov,mp4,m4a,3gp,3g2,mj2" }
+        })
+    }
+
+    // The encode, then the verification decodes in `decodes`
+    fn harness(name: &str, tier: VerifyTier, decodes: Vec<Value>, output_probe: Value) -> Harness {
+        let encode = json!({ "stderr": [{ "line": "frame=3600 fps=30 q=28.0 size=1024kB time=00:02:00.00 bitrate=69.9kbits/s speed=4.0x" }], "output_bytes": 4096 });
+        let runs: Vec<Value> = std::iter::once(encode).chain(decodes).collect();
+        let h = Harness::new(name, &json!({ "ffprobe": long_clip(), "ffprobe_output": output_probe, "runs": runs }).to_string());
+        set_verify_tier(h.handle().state(), tier).unwrap();
+        h
+    }
+
+    fn progress(frames: u64, secs: u64) -> Value {
+        json!({ "stdout": [format!("frame={}", frames), format!("out_time_us={}", secs * 1_000_000), "progress=end"] })
+    }
+
+    fn decodes(h: &Harness) -> Vec<Vec<String>> {
+        h.runs().into_iter().filter(|r| r.iter().any(|a| a == "-progress")).collect()
+    }
+
+    #[test]
+    fn a_clean_full_decode_verifies_the_output() {
+        let h = harness("verify-full-clean", VerifyTier::Full, vec![progress(3600, 120)], Value::Null);
+        let result = run_video(&h, video(&h, "out.mp4")).unwrap();
+
+        assert_eq!(result.verification.tier, VerifyTier::Full);
+        assert_eq!((result.verification.errors, result.verification.decoded_secs), (0, 120.0));
+        assert!(result.verified);
+        assert_eq!(decodes(&h).len(), 1);
+        assert!(fs::metadata(h.file("out.mp4")).is_ok());
+    }
+
+    #[test]
+    fn decode_errors_keep_the_output_but_leave_it_unverified() {
+        let errors = json!({ "stderr": [{ "line": "[h264 @ 0x5581] error while decoding MB 12 30" }, { "line": "[h264 @ 0x5581] concealing 840 DC errors" }] });
+        let h = harness("verify-full-errors", VerifyTier::Full, vec![errors], Value::Null);
+        let result = run_video(&h, video(&h, "out.mp4")).unwrap();
+
+        assert_eq!(result.verification.errors, 2);
+        assert_eq!(result.verification.first_error.as_deref(), Some("[h264 @ 0x5581] error while decoding MB 12 30"));
+        assert!(!result.verified);
+        assert!(result.warnings.iter().any(|w| w.contains("2 decode errors")), "{:?}", result.warnings);
+        assert_eq!(h.files(), ["clip.mp4", "out.mp4"]);
+    }
+
+    #[test]
+    fn sampling_falls_back_to_a_byte_offset_for_the_end() {
+        // Eight windows, a seek to the tail that decodes nothing, then the byte offset
+        let mut runs = vec![progress(60, 2); SAMPLE_WINDOWS as usize];
+        runs.extend([json!({}), progress(300, 10)]);
+        let h = harness("verify-sampled-offset", VerifyTier::Sampled, runs, Value::Null);
+        let result = run_video(&h, video(&h, "out.mp4")).unwrap();
+
+        let report = result.verification;
+        assert_eq!((report.tier, report.tail, report.errors), (VerifyTier::Sampled, Some(TailMethod::ByteOffset), 0));
+        assert_eq!(report.decoded_secs, SAMPLE_WINDOWS as f64 * WINDOW_SECS + TAIL_SECS);
+        let decodes = decodes(&h);
+        assert_eq!(decodes.len(), SAMPLE_WINDOWS as usize + 2);
+        assert!(decodes[..SAMPLE_WINDOWS as usize].iter().all(|d| d.windows(2).any(|w| w == ["-t", "2.000"])));
+        assert!(decodes.last().unwrap().iter().any(|a| a == "-skip_initial_bytes"));
+        assert!(result.verified);
+    }
+
+    #[test]
+    fn an_end_that_never_decodes_counts_as_an_error() {
+        // The last entry (nothing decoded) repeats for the tail and the byte offset
+        let mut runs = vec![progress(60, 2); SAMPLE_WINDOWS as usize];
+        runs.push(json!({}));
+        let h = harness("verify-sampled-unreadable", VerifyTier::Sampled, runs, Value::Null);
+        let result = run_video(&h, video(&h, "out.mp4")).unwrap();
+
+        assert_eq!(result.verification.tail, Some(TailMethod::Unreadable));
+        assert_eq!(result.verification.errors, 1);
+        assert!(!result.verified);
+        assert_eq!(h.files(), ["clip.mp4", "out.mp4"]);
+    }
+
+    #[test]
+    fn a_short_output_fails_the_job_and_is_removed() {
+        let mut short = long_clip();
+        short["format"]["duration"] = json!("30.000000");
+        let h = harness("verify-short", VerifyTier::Basic, vec![], short);
+        let error = run_video(&h, video(&h, "out.mp4")).err().expect("the job failed").to_string();
+
+        assert!(error.contains(INCOMPLETE_OUTPUT) && error.contains("30.0s long"), "{}", error);
+        assert_eq!(h.files(), ["clip.mp4"]);
+        assert!(decodes(&h).is_empty());
+    }
+
+    #[test]
+    fn an_output_the_probe_cant_read_fails_the_job_and_is_removed() {
+        let h = harness("verify-unreadable", VerifyTier::Full, vec![progress(3600, 120)], json!("not a probe answer"));
+        let error = run_video(&h, video(&h, "out.mp4")).err().expect("the job failed").to_string();
+
+        assert!(error.contains(INCOMPLETE_OUTPUT) && error.contains("can't be read back"), "{}", error);
+        assert_eq!(h.files(), ["clip.mp4"]);
+        // Never got as far as decoding it
+        assert!(decodes(&h).is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+use crate::AppHandle;
+use crate::cancel;
+use crate::ffmpeg::{self, TrackedOutput};
+
+// Containers that store a timestamp per frame. AVI (and the rest) assume
+// one fixed rate, so ffmpeg would duplicate or drop frames to fit it.
+pub const CONTAINERS: &[&str] = &["mp4", "m4v", "mov", "mkv", "webm"];
+// An output this many frames (or this share of them, whichever is more)
+// off the source's fails the frame-count check
+const TOLERANCE_FRAMES: f64 = 2.0;
+const TOLERANCE_RATIO: f64 = 0.005;
+
+// ==========================================
+// VARIABLE FRAME RATE PASSTHROUGH
+// ==========================================
+// Screen recordings often have long static stretches with hardly any
+// frames; forcing them to a constant rate fills those with duplicates and
+// the file balloons. `preserve_vfr` hands every frame to the encoder with
+// its source timestamp (no fps filter, no rate conversion), and afterwards
+// counts the output's frames against the source's: with VFR the length
+// alone can be right while frames went missing or got doubled.
+
+// Output timing flags. -fps_mode replaced -vsync in ffmpeg 5.1; older
+// builds only know the latter.
+pub fn timing_args(has_fps_mode: bool) -> Vec<String> {
+    let flag = if has_fps_mode { "-fps_mode" } else { "-vsync" };
+    vec![flag.to_string(), "passthrough".to_string()]
+}
+
+// Frames in the first video stream, by demuxing the whole file (the
+// header's count is missing from MKV and a guess in some MP4s).
+pub async fn count_frames(app: &AppHandle, path: &str) -> Result<u64, String> {
+    cancel::check()?;
+    let output = ffmpeg::ffprobe_command(app)?
+        .args(["-v", "error", "-select_streams", "v:0", "-count_packets", "-show_entries", "stream=nb_read_packets", "-of", "csv=p=0", path])
+        .tracked_output(app)
+        .await
+        .map_err(|e| e.to_string())?;
+    if !output.success() {
+        return Err(format!("Could not count the frames of {}: {}", path, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    text.trim().trim_end_matches(',').parse().map_err(|_| format!("ffprobe gave no frame count for {}", path))
+}
+
+// Pure: a warning when `actual` frames are further from `expected` than
+// the tolerance allows.
+pub fn check_frames(expected: f64, actual: u64) -> Option<String> {
+    let tolerance = TOLERANCE_FRAMES.max(expected * TOLERANCE_RATIO);
+    ((actual as f64 - expected).abs() > tolerance)
+        .then(|| format!("The output has {} frames, but the source's {:.0} were kept as they are", actual, expected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dryrun::{build_ffmpeg_args, Detected, Source};
+    use crate::explain;
+    use crate::request::{VideoCompressRequest, VideoOptions};
+    use crate::sourcetool::SourceTool;
+    use serde_json::json;
+
+    // The preserve_vfr issues of a request, one per conflict
+    fn conflicts(value: serde_json::Value) -> Vec<String> {
+        let request: VideoCompressRequest = serde_json::from_value(value).unwrap();
+        let Err(errors) = request.validate() else { return vec![] };
+        errors.0.into_iter().filter(|i| i.field == "preserve_vfr").map(|i| i.message).collect()
+    }
+
+    #[test]
+    fn vfr_and_rate_changes_are_rejected_together() {
+        let keep = "preserve_vfr keeps every frame, so it can't be combined with";
+        assert_eq!(
+            conflicts(json!({"input": "in.mov", "output": "out.mp4", "preserve_vfr": true, "max_fps": 30})),
+            [format!("{} max_fps", keep)]
+        );
+        assert_eq!(
+            conflicts(json!({"input": "in.mov", "output": "out.mp4", "preserve_vfr": true, "detect_telecine": true})),
+            [format!("{} detect_telecine (which drops some)", keep)]
+        );
+        // Every conflict at once, not just the first
+        assert_eq!(conflicts(json!({"input": "in.mov", "output": "out.mkv", "preserve_vfr": true, "max_fps": 30, "detect_telecine": true})).len(), 2);
+        // Each on its own is fine
+        for options in [json!({"preserve_vfr": true}), json!({"max_fps": 30}), json!({"detect_telecine": true}), json!({"preserve_vfr": false, "max_fps": 24})] {
+            let mut request = json!({"input": "in.mov", "output": "out.mp4"});
+            request.as_object_mut().unwrap().extend(options.as_object().unwrap().clone());
+            let request: VideoCompressRequest = serde_json::from_value(request).unwrap();
+            assert_eq!(request.validate(), Ok(()), "{}", options);
+        }
+    }
+
+    #[test]
+    fn vfr_needs_a_container_with_frame_timestamps() {
+        for ext in CONTAINERS {
+            assert!(conflicts(json!({"input": "in.mov", "output": format!("out.{}", ext), "preserve_vfr": true})).is_empty(), "{}", ext);
+        }
+        assert_eq!(
+            conflicts(json!({"input": "in.mov", "output": "out.avi", "preserve_vfr": true})),
+            [".avi output can't keep variable frame timing; use mp4, m4v, mov, mkv, webm"]
+        );
+        assert_eq!(conflicts(json!({"input": "in.mov", "output": "out.avi", "preserve_vfr": true, "max_fps": 30})).len(), 2);
+    }
+
+    #[test]
+    fn vfr_passes_timestamps_through_instead_of_a_cfr_fixup() {
+        let source = Source { ext: "mp4", tool: Some(SourceTool::GameBar), fixups: SourceTool::GameBar.fixups(), ..Default::default() };
+        let has = |args: &[String], pair: [&str; 2]| args.windows(2).any(|w| w == pair);
+
+        let cfr = build_ffmpeg_args(&VideoOptions::default(), &Detected::default(), &source).unwrap();
+        assert!(has(&cfr.codec_args, ["-fps_mode", "cfr"]));
+        assert!(!cfr.keep_timing);
+
+        let options = VideoOptions { preserve_vfr: true, ..Default::default() };
+        let vfr = build_ffmpeg_args(&options, &Detected::default(), &source).unwrap();
+        assert!(vfr.keep_timing);
+        assert!(has(&vfr.codec_args, ["-fps_mode", "passthrough"]));
+        assert!(!has(&vfr.codec_args, ["-fps_mode", "cfr"]));
+        // The screen tuning still goes on; the skipped fixup is explained
+        assert!(has(&vfr.codec_args, ["-tune", "animation"]));
+        assert!(vfr.explanations.iter().any(|e| e.code == explain::TIMING_CFR_SKIPPED && e.params["tool"] == "game_bar"));
+    }
+
+    #[test]
+    fn the_flag_follows_the_build() {
+        assert_eq!(timing_args(true), ["-fps_mode", "passthrough"]);
+        assert_eq!(timing_args(false), ["-vsync", "passthrough"]);
+    }
+
+    #[test]
+    fn frame_counts_are_checked_within_the_tolerance() {
+        // Two frames either way for short clips
+        assert_eq!(check_frames(300.0, 302), None);
+        assert_eq!(check_frames(300.0, 298), None);
+        assert_eq!(check_frames(300.0, 303).unwrap(), "The output has 303 frames, but the source's 300 were kept as they are");
+        assert!(check_frames(300.0, 297).is_some());
+        // 0.5% for long ones
+        assert_eq!(check_frames(100_000.0, 100_500), None);
+        assert_eq!(check_frames(100_000.0, 99_500), None);
+        assert!(check_frames(100_000.0, 100_501).is_some());
+        assert!(check_frames(100_000.0, 50_000).is_some());
+    }
+}
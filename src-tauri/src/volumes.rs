@@ -0,0 +1,85 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use sysinfo::{DiskKind, Disks};
+
+// The mounted volume a path lives on.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct VolumeInfo {
+    pub mount_point: String,
+    // Spinning disk: concurrent jobs on it get limited to avoid seek thrashing
+    pub rotational: bool,
+    // USB sticks, SD cards: can be unplugged while a job writes to them
+    pub removable: bool,
+}
+
+// Strips the `\\?\` verbatim prefix canonicalize() adds on Windows so paths
+// compare against the plain mount points sysinfo reports. Shares come back
+// as `\\?\UNC\server\share`, which is `\\server\share`.
+pub fn plain(path: &Path) -> PathBuf {
+    let s = path.to_string_lossy();
+    if let Some(share) = s.strip_prefix(r"\\?\UNC\") {
+        return PathBuf::from(format!(r"\\{}", share));
+    }
+    match s.strip_prefix(r"\\?\") {
+        Some(rest) => PathBuf::from(rest),
+        None => path.to_path_buf(),
+    }
+}
+
+// Outputs usually don't exist yet, so walk up to the nearest existing ancestor.
+fn existing_ancestor(path: &Path) -> Option<PathBuf> {
+    let mut current = Some(path);
+    while let Some(p) = current {
+        if let Ok(canonical) = p.canonicalize() {
+            return Some(plain(&canonical));
+        }
+        current = p.parent();
+    }
+    None
+}
+
+// ==========================================
+// HELPER: WHICH VOLUME IS THIS PATH ON?
+// ==========================================
+// Picks the longest matching mount point. Anything that can't be detected
+// comes back as a non-rotational volume so it never gets throttled.
+pub fn volume_of(path: &str) -> Option<VolumeInfo> {
+    let resolved = existing_ancestor(Path::new(path))?;
+    let disks = Disks::new_with_refreshed_list();
+
+    disks
+        .list()
+        .iter()
+        .filter(|d| resolved.starts_with(d.mount_point()))
+        .max_by_key(|d| d.mount_point().as_os_str().len())
+        .map(|d| VolumeInfo {
+            mount_point: d.mount_point().to_string_lossy().to_string(),
+            rotational: matches!(d.kind(), DiskKind::HDD),
+            removable: d.is_removable(),
+        })
+}
+
+// Free space on the volume `path` is on, for the work-dir preflight.
+pub fn available_bytes(path: &Path) -> Option<u64> {
+    let resolved = existing_ancestor(path)?;
+    let disks = Disks::new_with_refreshed_list();
+    disks
+        .list()
+        .iter()
+        .filter(|d| resolved.starts_with(d.mount_point()))
+        .max_by_key(|d| d.mount_point().as_os_str().len())
+        .map(|d| d.available_space())
+}
+
+// Volumes touched by a job (input and output), deduplicated.
+pub fn volumes_for(paths: &[&str]) -> Vec<VolumeInfo> {
+    let mut out: Vec<VolumeInfo> = vec![];
+    for path in paths {
+        if let Some(v) = volume_of(path) {
+            if !out.contains(&v) {
+                out.push(v);
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,690 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use notify::event::ModifyKind;
+use notify::{EventKind, RecursiveMode, Watcher};
+use tauri::{Manager, State};
+
+use crate::AppHandle;
+use crate::events::{self, Event};
+use crate::inputs::{self, InputError};
+use crate::instance;
+use crate::outputs;
+use crate::paths;
+use crate::presets;
+use crate::queue::{self, JobSpec};
+use crate::request::{VideoCompressRequest, VideoOptions};
+use crate::settings::SettingsStore;
+
+// How often watched folders are listed. A file is only picked up once its
+// size hasn't changed between two scans, so recordings still being written
+// are left alone.
+const SCAN_INTERVAL_SECS: u64 = 10;
+// A folder with file events is scanned this long after a change, and again
+// this long after that (see listen)
+const SETTLE: Duration = Duration::from_secs(2);
+
+pub(crate) const WATCH_EXTENSIONS: &[&str] = &["mp4", "mkv", "mov", "avi", "flv", "ts", "m4v", "wmv", "webm"];
+
+// Session watches write next to what they watch, into this folder
+const SESSION_OUTPUT_DIR: &str = "compressed";
+
+// Done to the original once its job succeeded.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PostAction {
+    MoveOriginalToTrash,
+    MoveOriginalTo { dir: String },
+}
+
+// What happens to every file that shows up in a watch folder.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JobTemplate {
+    // Name of a built-in preset, or...
+    #[serde(default)]
+    pub preset: Option<String>,
+    // ...an inline spec. Exactly one of the two.
+    #[serde(default)]
+    pub spec: Option<VideoOptions>,
+    // Output path; the file name may use {stem}, {ext} and {name} of the
+    // input, e.g. "~/Shares/clips/{stem}_discord.mp4"
+    pub output: String,
+    #[serde(default)]
+    pub post_actions: Vec<PostAction>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WatchFolder {
+    // Stable id derived from the folder path; stamped on queued jobs and history
+    pub id: String,
+    pub path: String,
+    pub template: JobTemplate,
+}
+
+// A watch started with start_watch_folder: lives until stop_watch_folder or
+// the app quits, and isn't saved anywhere.
+#[derive(Serialize, Clone, Debug)]
+pub struct SessionWatch {
+    pub id: String,
+    pub path: String,
+    pub output_dir: String,
+    pub options: VideoOptions,
+    // Output extension
+    pub container: String,
+}
+
+// Payload of `watch-file-picked-up`.
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct WatchPickedUp {
+    pub watch_id: String,
+    pub job_id: u64,
+    pub input: String,
+    pub output: String,
+}
+
+// Payload of `watch-file-rejected`.
+#[derive(Serialize, Clone, JsonSchema)]
+pub struct WatchRejected {
+    pub watch_id: String,
+    pub input: String,
+    pub error: InputError,
+}
+
+fn folder_id(path: &Path) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(path.to_string_lossy().as_bytes());
+    format!("wf-{:x}", hasher.finalize())[..11].to_string()
+}
+
+fn expand_home(app: &AppHandle, path: &str) -> String {
+    match (path.strip_prefix("~/"), app.path().home_dir()) {
+        (Some(rest), Ok(home)) => home.join(rest).to_string_lossy().to_string(),
+        _ => path.to_string(),
+    }
+}
+
+fn output_for(template: &str, input: &Path) -> String {
+    let stem = input.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let ext = input.extension().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let name = input.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    template.replace("{stem}", &stem).replace("{ext}", &ext).replace("{name}", &name)
+}
+
+// Creates `dir` if needed and proves a file can be written there.
+fn ensure_writable(dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Can't create {}: {}", dir.display(), e))?;
+    let probe = dir.join(".compress-io-write-test");
+    fs::write(&probe, b"").map_err(|e| format!("Can't write to {}: {}", dir.display(), e))?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+// ==========================================
+// TEMPLATE VALIDATION
+// ==========================================
+// Runs when the folder is saved, so a bad template fails in front of the
+// user instead of on the first file that arrives overnight.
+fn validate(app: &AppHandle, folder: &Path, template: &JobTemplate) -> Result<(), String> {
+    if !folder.is_dir() {
+        return Err(format!("{} is not a folder", folder.display()));
+    }
+    match (&template.preset, &template.spec) {
+        (Some(_), Some(_)) => return Err("Use either a preset or an inline spec, not both".to_string()),
+        (None, None) => return Err("A watch folder needs a preset or an inline spec".to_string()),
+        (Some(name), None) if presets::find(app, name).is_none() => {
+            return Err(format!("Unknown preset \"{}\"", name));
+        }
+        _ => {}
+    }
+
+    let output = PathBuf::from(expand_home(app, &template.output));
+    if let Some(options) = template_spec(app, template) {
+        let sample = VideoCompressRequest::new(folder.to_string_lossy().to_string(), output.to_string_lossy().to_string(), options);
+        sample.validate().map_err(|e| e.to_string())?;
+    }
+    let file_name = output.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    if !file_name.contains("{stem}") && !file_name.contains("{name}") {
+        return Err("The output file name must contain {stem} or {name}, or every file would overwrite the same output".to_string());
+    }
+    let out_dir = output.parent().filter(|d| !d.as_os_str().is_empty()).ok_or("The output needs a folder")?;
+    if out_dir.to_string_lossy().contains('{') {
+        return Err("Placeholders are only allowed in the output file name".to_string());
+    }
+    if paths::same_file(&folder.to_string_lossy(), &out_dir.to_string_lossy()) {
+        return Err("Outputs can't go into the watched folder itself".to_string());
+    }
+    ensure_writable(out_dir)?;
+
+    for action in &template.post_actions {
+        if let PostAction::MoveOriginalTo { dir } = action {
+            ensure_writable(Path::new(&expand_home(app, dir)))?;
+        }
+    }
+    Ok(())
+}
+
+fn template_spec(app: &AppHandle, template: &JobTemplate) -> Option<VideoOptions> {
+    if let Some(spec) = &template.spec {
+        return Some(spec.clone());
+    }
+    Some(presets::find(app, template.preset.as_deref()?)?.options)
+}
+
+// ==========================================
+// SCANNER (managed state)
+// ==========================================
+#[derive(Default)]
+pub struct WatchScanner {
+    // Size of each candidate at the previous scan
+    sizes: Mutex<HashMap<PathBuf, u64>>,
+    // Files already turned into jobs (or skipped) this session
+    handled: Mutex<HashSet<PathBuf>>,
+    // Files preflight turned down, at the size they had then
+    rejected: Mutex<HashMap<PathBuf, u64>>,
+    // Outputs of jobs a watcher queued: never picked up as inputs, so a
+    // watch on another watch's output folder doesn't compress its results
+    // again
+    produced: Mutex<HashSet<PathBuf>>,
+    session: Mutex<HashMap<String, SessionWatch>>,
+    // Session watches hearing about their folder from the OS; the periodic
+    // scan leaves these alone. Dropping one ends its listen task.
+    listening: Mutex<HashMap<String, notify::RecommendedWatcher>>,
+}
+
+// One folder as the scanner sees it, persisted or session.
+struct Watched {
+    id: String,
+    path: String,
+    video: VideoOptions,
+    // Output path template, home already expanded
+    output: String,
+}
+
+fn watched(app: &AppHandle, scanner: &WatchScanner) -> Vec<Watched> {
+    let persisted = app.state::<SettingsStore>().get().watch_folders.into_iter().filter_map(|f| {
+        Some(Watched { video: template_spec(app, &f.template)?, output: expand_home(app, &f.template.output), id: f.id, path: f.path })
+    });
+    let session = scanner.session.lock().unwrap().values().map(|w| Watched {
+        id: w.id.clone(),
+        path: w.path.clone(),
+        video: w.options.clone(),
+        output: session_output(w),
+    }).collect::<Vec<_>>();
+    persisted.chain(session).collect()
+}
+
+fn session_output(watch: &SessionWatch) -> String {
+    Path::new(&watch.output_dir).join(format!("{{stem}}.{}", watch.container)).to_string_lossy().to_string()
+}
+
+impl WatchScanner {
+    // The same preflight a dropped file gets. A rejection is reported once
+    // (Err(Some)), then skipped quietly (Err(None)) until the file's size
+    // changes, e.g. a failed download that's retried.
+    fn screen(&self, path: &Path, size: u64) -> Result<(), Option<InputError>> {
+        let mut rejected = self.rejected.lock().unwrap();
+        if rejected.get(path) == Some(&size) {
+            return Err(None);
+        }
+        match inputs::preflight(&path.to_string_lossy()) {
+            Ok(_) => {
+                rejected.remove(path);
+                Ok(())
+            }
+            Err(e) => {
+                rejected.insert(path.to_path_buf(), size);
+                Err(Some(e))
+            }
+        }
+    }
+}
+
+fn candidates(dir: &Path) -> Vec<(PathBuf, u64)> {
+    let Ok(entries) = fs::read_dir(dir) else { return vec![] };
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let path = e.path();
+            let ext = path.extension()?.to_string_lossy().to_lowercase();
+            let meta = e.metadata().ok()?;
+            (meta.is_file() && WATCH_EXTENSIONS.contains(&ext.as_str())).then_some((path, meta.len()))
+        })
+        .collect()
+}
+
+// The folder of watch `only`, or (the periodic scan) every folder that
+// doesn't get file events.
+fn scan(app: &AppHandle, only: Option<&str>) {
+    let scanner = app.state::<WatchScanner>();
+    let listening: HashSet<String> = scanner.listening.lock().unwrap().keys().cloned().collect();
+    let folders = watched(app, &scanner).into_iter().filter(|f| only.map_or(!listening.contains(&f.id), |id| f.id == id));
+    let snapshot = queue::snapshot(app);
+    let in_queue: HashSet<&str> = snapshot.pending.iter().chain(snapshot.running.iter()).map(|j| j.spec.input()).collect();
+
+    for folder in folders {
+        let mut specs = vec![];
+
+        for (path, size) in candidates(Path::new(&folder.path)) {
+            let stable = scanner.sizes.lock().unwrap().insert(path.clone(), size) == Some(size);
+            if !stable || scanner.handled.lock().unwrap().contains(&path) || scanner.produced.lock().unwrap().contains(&path) {
+                continue;
+            }
+            let input = path.to_string_lossy().to_string();
+            let output = output_for(&folder.output, &path);
+            // Restored from queue.json, or already compressed in an earlier session
+            if in_queue.contains(input.as_str()) || Path::new(&output).exists() {
+                scanner.handled.lock().unwrap().insert(path.clone());
+                continue;
+            }
+            match scanner.screen(&path, size) {
+                Ok(()) => {}
+                Err(Some(error)) => {
+                    println!("⚠️ Watch folder {}: skipping {}", folder.path, error);
+                    events::emit(app, Event::WatchFileRejected(WatchRejected { watch_id: folder.id.clone(), input, error }));
+                    continue;
+                }
+                Err(None) => continue,
+            }
+            scanner.handled.lock().unwrap().insert(path.clone());
+            specs.push(JobSpec::Video(Box::new(VideoCompressRequest::new(input, output, folder.video.clone()))));
+        }
+
+        if specs.is_empty() {
+            continue;
+        }
+        println!("👀 Watch folder {}: queueing {} new files", folder.path, specs.len());
+        let picked: Vec<(String, String)> = specs.iter().map(|s| (s.input().to_string(), s.output().to_string())).collect();
+        match queue::enqueue_from(app, specs, None, Some(folder.id.clone())) {
+            Ok(ids) => {
+                for (job_id, (input, output)) in ids.into_iter().zip(picked) {
+                    scanner.produced.lock().unwrap().insert(PathBuf::from(&output));
+                    events::emit(app, Event::WatchFilePickedUp(WatchPickedUp { watch_id: folder.id.clone(), job_id, input, output }));
+                }
+            }
+            Err(e) => println!("⚠️ Watch folder {}: {}", folder.path, e),
+        }
+    }
+}
+
+// Not in a secondary instance: both would pick up the same files.
+pub fn start(app: &AppHandle) {
+    if instance::is_secondary(app) {
+        println!("👥 Watch folders are off in this instance");
+        return;
+    }
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(SCAN_INTERVAL_SECS));
+        loop {
+            ticker.tick().await;
+            scan(&app, None);
+        }
+    });
+}
+
+// ==========================================
+// FILE EVENTS (session watches)
+// ==========================================
+// A session watch hears about new and growing files from the OS (inotify,
+// FSEvents, ReadDirectoryChangesW, through notify) instead of waiting for
+// the next scan. SETTLE after a change the folder is scanned, and again
+// SETTLE later, so the scanner's size rule still decides what's finished.
+// Where the OS can't watch the folder (some network shares) it's left to
+// the periodic scan.
+fn listen(app: &AppHandle, scanner: &WatchScanner, watch: &SessionWatch) {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        // Not reads, which the scan itself makes
+        let changed = event.is_ok_and(|e| match e.kind {
+            EventKind::Modify(ModifyKind::Metadata(_)) => false,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => true,
+            _ => false,
+        });
+        if changed {
+            let _ = tx.send(());
+        }
+    });
+    let watcher = watcher.and_then(|mut w| w.watch(Path::new(&watch.path), RecursiveMode::NonRecursive).map(|_| w));
+    let watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            println!("⚠️ No file events for {} ({}), scanning it every {}s instead", watch.path, e, SCAN_INTERVAL_SECS);
+            return;
+        }
+    };
+    scanner.listening.lock().unwrap().insert(watch.id.clone(), watcher);
+
+    let (app, id) = (app.clone(), watch.id.clone());
+    tauri::async_runtime::spawn(async move {
+        // What's there already first, then a round per batch of events. A
+        // file still being written sends more, so it's looked at again.
+        loop {
+            tokio::time::sleep(SETTLE).await;
+            while rx.try_recv().is_ok() {}
+            scan(&app, Some(&id));
+            tokio::time::sleep(SETTLE).await;
+            scan(&app, Some(&id));
+            // None: the watch was stopped
+            if rx.recv().await.is_none() {
+                return;
+            }
+        }
+    });
+}
+
+// ==========================================
+// POST-ACTIONS
+// ==========================================
+// Never over a file of the same name already in `dir`: `clip (1).mov` and
+// so on instead. Returns where it went.
+fn move_to(original: &Path, dir: &Path) -> Result<PathBuf, String> {
+    let name = original.file_name().ok_or("Input has no file name")?;
+    let requested = dir.join(name);
+    for n in 0..outputs::MAX_CANDIDATES {
+        let target = outputs::candidate(&requested, n);
+        match outputs::rename_noreplace(original, &target) {
+            Ok(()) => return Ok(target),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            // Different volume: copy, then remove
+            Err(_) => match copy_new(original, &target) {
+                Ok(()) => return fs::remove_file(original).map(|_| target).map_err(|e| e.to_string()),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.to_string()),
+            },
+        }
+    }
+    Err(format!("No free name for {} in {}", name.to_string_lossy(), dir.display()))
+}
+
+// A copy that fails instead of replacing `to`; a half-written copy goes.
+fn copy_new(from: &Path, to: &Path) -> io::Result<()> {
+    let mut source = fs::File::open(from)?;
+    let mut target = fs::File::options().write(true).create_new(true).open(to)?;
+    let copied = io::copy(&mut source, &mut target).and_then(|_| target.sync_all());
+    if copied.is_err() {
+        let _ = fs::remove_file(to);
+    }
+    copied
+}
+
+#[cfg(target_os = "windows")]
+fn move_to_trash(_app: &AppHandle, original: &Path) -> Result<(), String> {
+    // The path goes in through the environment, never into the script:
+    // PowerShell also ends a quoted string at a curly quote (Don’t.mp4)
+    let script = "Add-Type -AssemblyName Microsoft.VisualBasic; [Microsoft.VisualBasic.FileIO.FileSystem]::DeleteFile($env:COMPRESS_IO_TRASH, 'OnlyErrorDialogs', 'SendToRecycleBin')";
+    let status = std::process::Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", script])
+        .env("COMPRESS_IO_TRASH", original)
+        .status()
+        .map_err(|e| e.to_string())?;
+    if status.success() { Ok(()) } else { Err("The Recycle Bin refused the file".to_string()) }
+}
+
+#[cfg(target_os = "macos")]
+fn move_to_trash(app: &AppHandle, original: &Path) -> Result<(), String> {
+    let home = app.path().home_dir().map_err(|e| e.to_string())?;
+    move_to(original, &home.join(".Trash")).map(|_| ())
+}
+
+// freedesktop.org trash: the file plus a .trashinfo so file managers can restore it
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn move_to_trash(app: &AppHandle, original: &Path) -> Result<(), String> {
+    let data = app.path().data_dir().map_err(|e| e.to_string())?;
+    let trash = data.join("Trash");
+    fs::create_dir_all(trash.join("files")).map_err(|e| e.to_string())?;
+    fs::create_dir_all(trash.join("info")).map_err(|e| e.to_string())?;
+    let absolute = paths::resolve(original).unwrap_or_else(|| original.to_path_buf());
+    // The info file is named after the one in files/, which may have moved aside
+    let trashed = move_to(original, &trash.join("files"))?;
+    let name = trashed.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let info = format!("[Trash Info]\nPath={}\nDeletionDate={}\n", absolute.display(), deletion_date());
+    fs::write(trash.join("info").join(format!("{}.trashinfo", name)), info).map_err(|e| e.to_string())
+}
+
+// The spec wants local time, without a zone
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn deletion_date() -> String {
+    chrono::Local::now().format("%Y-%m-%dT%H:%M:%S").to_string()
+}
+
+// Called by the queue after a watch-folder job succeeded. Failures are only
+// logged: the output exists, and the original staying put is the safe outcome.
+pub fn after_job(app: &AppHandle, folder_id: &str, input: &str) {
+    let folders = app.state::<SettingsStore>().get().watch_folders;
+    let Some(folder) = folders.iter().find(|f| f.id == folder_id) else { return };
+    let original = Path::new(input);
+    for action in &folder.template.post_actions {
+        let result = match action {
+            PostAction::MoveOriginalToTrash => move_to_trash(app, original),
+            PostAction::MoveOriginalTo { dir } => move_to(original, Path::new(&expand_home(app, dir))).map(|_| ()),
+        };
+        if let Err(e) = result {
+            println!("⚠️ Post-action {:?} failed for {}: {}", action, input, e);
+            return;
+        }
+    }
+}
+
+// ==========================================
+// COMMANDS
+// ==========================================
+#[tauri::command]
+pub fn list_watch_folders(store: State<'_, SettingsStore>) -> Vec<WatchFolder> {
+    store.get().watch_folders
+}
+
+// Adds the folder, or replaces the template of one that's already watched.
+#[tauri::command]
+pub fn update_watch_folder(
+    app: AppHandle,
+    store: State<'_, SettingsStore>,
+    path: String,
+    template: JobTemplate,
+) -> Result<WatchFolder, String> {
+    let resolved = paths::resolve(Path::new(&path)).ok_or_else(|| format!("{} is not a folder", path))?;
+    validate(&app, &resolved, &template)?;
+    let folder = WatchFolder {
+        id: folder_id(&resolved),
+        path: resolved.to_string_lossy().to_string(),
+        template,
+    };
+    store.update(|s| match s.watch_folders.iter_mut().find(|f| f.id == folder.id) {
+        Some(existing) => *existing = folder.clone(),
+        None => s.watch_folders.push(folder.clone()),
+    })?;
+    Ok(folder)
+}
+
+#[tauri::command]
+pub fn remove_watch_folder(store: State<'_, SettingsStore>, id: String) -> Result<(), String> {
+    store.update(|s| s.watch_folders.retain(|f| f.id != id)).map(|_| ())
+}
+
+// ==========================================
+// COMMANDS: SESSION WATCHES
+// ==========================================
+// Watches `dir` until stop_watch_folder or quit, compressing every new video
+// into `<dir>/compressed` with `options`. Files are picked up by the same
+// scanner as the saved watch folders, once their size stops changing, but
+// on the folder's file events rather than every SCAN_INTERVAL_SECS.
+#[tauri::command]
+pub fn start_watch_folder(
+    app: AppHandle,
+    scanner: State<'_, WatchScanner>,
+    dir: String,
+    options: VideoOptions,
+    container: Option<String>,
+) -> Result<SessionWatch, String> {
+    if instance::is_secondary(&app) {
+        return Err("Watch folders are off in this instance; use the first window".to_string());
+    }
+    let resolved = paths::resolve(Path::new(&dir)).ok_or_else(|| format!("{} is not a folder", dir))?;
+    let container = container.unwrap_or_else(|| "mp4".to_string()).trim_start_matches('.').to_lowercase();
+    let watch = SessionWatch {
+        id: format!("ws-{}", &folder_id(&resolved)[3..]),
+        path: resolved.to_string_lossy().to_string(),
+        output_dir: resolved.join(SESSION_OUTPUT_DIR).to_string_lossy().to_string(),
+        options,
+        container,
+    };
+
+    // Watching the folder a watch writes into would compress its outputs again
+    let existing = watched(&app, &scanner);
+    if let Some(other) = existing.iter().find(|w| paths::same_file(&w.path, &watch.path)) {
+        return Err(format!("{} is watched already ({})", watch.path, other.id));
+    }
+    for other in &existing {
+        let out_dir = Path::new(&other.output).parent().map(|d| d.to_string_lossy().to_string()).unwrap_or_default();
+        if paths::same_file(&out_dir, &watch.path) {
+            return Err(format!("{} is where watch {} writes its outputs", watch.path, other.id));
+        }
+    }
+    let template = JobTemplate { preset: None, spec: Some(watch.options.clone()), output: session_output(&watch), post_actions: vec![] };
+    validate(&app, &resolved, &template)?;
+
+    println!("👀 Watching {} for this session", watch.path);
+    scanner.session.lock().unwrap().insert(watch.id.clone(), watch.clone());
+    listen(&app, &scanner, &watch);
+    Ok(watch)
+}
+
+// Files it already queued still run.
+#[tauri::command]
+pub fn stop_watch_folder(scanner: State<'_, WatchScanner>, id: String) -> Result<(), String> {
+    let watch = scanner.session.lock().unwrap().remove(&id).ok_or_else(|| format!("No session watch {}", id))?;
+    scanner.listening.lock().unwrap().remove(&id);
+    println!("👀 Stopped watching {}", watch.path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::TempDir;
+    use crate::jobtests::{clip_scenario, Harness, ENCODE};
+
+    // Enough of an MP4 for preflight
+    const CLIP: &[u8] = b"\0\0\0\x18ftypisom\0\0\x02\0isomiso2";
+
+    fn folder(name: &str) -> TempDir {
+        TempDir::new(std::env::temp_dir().join(format!("watch-test-{}-{}", std::process::id(), name))).unwrap()
+    }
+
+    #[test]
+    fn move_to_never_replaces_a_file_of_the_same_name() {
+        let (from, to) = (folder("move-from"), folder("move-to"));
+        fs::write(to.path().join("clip.mov"), "already there").unwrap();
+        fs::write(to.path().join("clip (1).mov"), "also there").unwrap();
+        let original = from.path().join("clip.mov");
+        fs::write(&original, "original").unwrap();
+
+        let moved = move_to(&original, to.path()).unwrap();
+        assert_eq!(moved, to.path().join("clip (2).mov"));
+        assert_eq!(fs::read_to_string(&moved).unwrap(), "original");
+        assert_eq!(fs::read_to_string(to.path().join("clip.mov")).unwrap(), "already there");
+        assert_eq!(fs::read_to_string(to.path().join("clip (1).mov")).unwrap(), "also there");
+        assert!(!original.exists());
+    }
+
+    #[test]
+    fn screen_reports_bad_files_once_per_size() {
+        let dir = folder("screen");
+        let scanner = WatchScanner::default();
+        let (empty, page, clip) = (dir.path().join("empty.mp4"), dir.path().join("page.mp4"), dir.path().join("clip.mp4"));
+        fs::write(&empty, "").unwrap();
+        fs::write(&page, "<!DOCTYPE html><html>404</html>").unwrap();
+        fs::write(&clip, b"\0\0\0\x18ftypisom\0\0\x02\0isomiso2").unwrap();
+
+        assert!(matches!(scanner.screen(&empty, 0), Err(Some(InputError::EmptyInput { .. }))));
+        assert!(matches!(scanner.screen(&page, 31), Err(Some(InputError::MisleadingExtension { .. }))));
+        assert_eq!(scanner.screen(&page, 31), Err(None));
+        assert_eq!(scanner.screen(&clip, 24), Ok(()));
+
+        // The download was retried and is a video now
+        fs::write(&page, b"\0\0\0\x18ftypisom\0\0\x02\0isomiso2").unwrap();
+        assert_eq!(scanner.screen(&page, 24), Ok(()));
+    }
+
+    #[test]
+    fn candidates_include_empty_files_for_preflight() {
+        let dir = folder("candidates");
+        fs::write(dir.path().join("empty.mp4"), "").unwrap();
+        fs::write(dir.path().join("notes.txt"), "x").unwrap();
+        let found = candidates(dir.path());
+        assert_eq!(found, vec![(dir.path().join("empty.mp4"), 0)]);
+    }
+
+    #[test]
+    fn copy_new_refuses_an_existing_target() {
+        let dir = folder("copy");
+        let (from, to) = (dir.path().join("a.mov"), dir.path().join("b.mov"));
+        fs::write(&from, "new").unwrap();
+        fs::write(&to, "old").unwrap();
+        assert_eq!(copy_new(&from, &to).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&to).unwrap(), "old");
+    }
+
+    fn picked_up(h: &Harness, input: &Path) -> Option<serde_json::Value> {
+        h.emitted("watch-file-picked-up").into_iter().find(|p| p["input"] == input.to_string_lossy().as_ref())
+    }
+
+    #[test]
+    fn a_session_watch_picks_files_up_on_file_events() {
+        let h = Harness::new("watch-events", &clip_scenario(ENCODE));
+        let dir = h.dir.join("incoming");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("before.mp4"), CLIP).unwrap();
+        let scanner = h.handle().state::<WatchScanner>();
+        let watch = start_watch_folder(h.handle().clone(), h.handle().state(), dir.to_string_lossy().to_string(), VideoOptions::default(), None).unwrap();
+        assert!(scanner.listening.lock().unwrap().contains_key(&watch.id));
+
+        // The tests run no periodic scan: the watch's own rounds find both
+        let folder = PathBuf::from(&watch.path);
+        h.wait_for("the file already there", |h| picked_up(h, &folder.join("before.mp4")).is_some());
+        fs::write(dir.join("after.mp4"), CLIP).unwrap();
+        h.wait_for("the new file", |h| picked_up(h, &folder.join("after.mp4")).is_some());
+        let picked = picked_up(&h, &folder.join("after.mp4")).unwrap();
+        assert_eq!(picked["watch_id"], watch.id.as_str());
+        assert_eq!(picked["output"], folder.join("compressed").join("after.mp4").to_string_lossy().as_ref());
+        h.settled(picked["job_id"].as_u64().unwrap());
+        assert_eq!(h.emitted("watch-file-picked-up").len(), 2);
+
+        stop_watch_folder(h.handle().state(), watch.id).unwrap();
+        assert!(scanner.listening.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_folder_without_file_events_is_left_to_the_periodic_scan() {
+        let h = Harness::new("watch-fallback", &clip_scenario(ENCODE));
+        let dir = h.dir.join("share");
+        let watch = SessionWatch {
+            id: "ws-fallback".to_string(),
+            path: dir.to_string_lossy().to_string(),
+            output_dir: dir.join(SESSION_OUTPUT_DIR).to_string_lossy().to_string(),
+            options: VideoOptions::default(),
+            container: "mp4".to_string(),
+        };
+        let scanner = h.handle().state::<WatchScanner>();
+        scanner.session.lock().unwrap().insert(watch.id.clone(), watch.clone());
+        // Not there to watch yet, like a share that mounts late
+        listen(h.handle(), &scanner, &watch);
+        assert!(scanner.listening.lock().unwrap().is_empty());
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("clip.mp4"), CLIP).unwrap();
+        scan(h.handle(), None);
+        assert!(picked_up(&h, &dir.join("clip.mp4")).is_none(), "picked up before its size was seen twice");
+        scan(h.handle(), None);
+        // Listener delivery can lag behind a concurrent emit
+        h.wait_for("the second scan to pick it up", |h| picked_up(h, &dir.join("clip.mp4")).is_some());
+        let picked = picked_up(&h, &dir.join("clip.mp4")).unwrap();
+        assert_eq!(picked["watch_id"], "ws-fallback");
+        h.settled(picked["job_id"].as_u64().unwrap());
+    }
+}
@@ -0,0 +1,265 @@
+// ==========================================
+// STUB FFMPEG SCENARIOS
+// ==========================================
+// Runs the stub-ffmpeg binary the way a job does (see src/ffmpeg.rs and
+// src/bin/stub-ffmpeg.rs): STUB_TOOL picks the tool, the scenario says what
+// happens, and the outputs (the last argument, and any that ends an
+// earlier output's options) get `output_bytes`.
+
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+
+const STUB: &str = env!("CARGO_BIN_EXE_stub-ffmpeg");
+
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(name: &str, scenario: &str) -> Scratch {
+        let dir = std::env::temp_dir().join(format!("stub-ffmpeg-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("scenario.json"), scenario).unwrap();
+        Scratch(dir)
+    }
+
+    fn scenario(&self) -> PathBuf {
+        self.0.join("scenario.json")
+    }
+
+    fn file(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+
+    fn command(&self, tool: &str, args: &[&str]) -> Command {
+        let mut command = Command::new(STUB);
+        command.args(args).env("STUB_TOOL", tool).env("STUB_FFMPEG_SCENARIO", self.scenario());
+        command
+    }
+
+    fn run(&self, tool: &str, args: &[&str]) -> Output {
+        self.command(tool, args).output().unwrap()
+    }
+
+    fn spawn(&self, args: &[&str]) -> Child {
+        self.command("ffmpeg", args).stderr(Stdio::piped()).spawn().unwrap()
+    }
+
+    // One JSON array of arguments per run
+    fn logged_runs(&self) -> Vec<Vec<String>> {
+        let log = fs::read_to_string(self.file("scenario.json.log")).unwrap_or_default();
+        log.lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn path(p: &Path) -> &str {
+    p.to_str().unwrap()
+}
+
+#[test]
+fn a_successful_encode_reports_progress_and_writes_the_output() {
+    let scratch = Scratch::new("success", r#"{
+        "runs": [{
+            "stderr": [
+                { "line": "frame=10 fps=5 time=00:00:01.00 bitrate=800kbits/s speed=1.0x" },
+                { "line": "frame=20 fps=5 time=00:00:02.00 bitrate=800kbits/s speed=1.0x", "after_ms": 20 }
+            ],
+            "output_bytes": 4096
+        }]
+    }"#);
+    let output = scratch.file("out.mp4");
+    let run = scratch.run("ffmpeg", &["-i", "in.mov", "-c:v", "libx264", path(&output)]);
+
+    assert!(run.status.success());
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    let times: Vec<&str> = stderr.lines().filter_map(|l| l.split("time=").nth(1)).map(|t| &t[..11]).collect();
+    assert_eq!(times, ["00:00:01.00", "00:00:02.00"]);
+    assert_eq!(fs::metadata(&output).unwrap().len(), 4096);
+    assert_eq!(scratch.logged_runs(), [["-i", "in.mov", "-c:v", "libx264", path(&output)]]);
+}
+
+#[test]
+fn a_run_prints_its_stdout_lines() {
+    let scratch = Scratch::new("stdout", r#"{ "runs": [{ "stdout": ["frame=30", "progress=end"] }] }"#);
+    let output = scratch.run("ffmpeg", &["-progress", "pipe:1", "-i", "in.mov", "-f", "null", "-"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "frame=30\nprogress=end\n");
+}
+
+#[test]
+fn every_output_of_a_run_is_written() {
+    let scratch = Scratch::new("outputs", r#"{ "runs": [{ "output_bytes": 64 }] }"#);
+    let (a, b) = (scratch.file("a.jpg"), scratch.file("b.jpg"));
+    let args = ["-y", "-i", "a.png", "-i", "b.png", "-map", "0:v:0", "-map_metadata", "-1", path(&a), "-map", "1:v:0", "-q:v", "2", path(&b)];
+    assert!(scratch.run("ffmpeg", &args).status.success());
+    assert_eq!(fs::metadata(&a).unwrap().len(), 64);
+    assert_eq!(fs::metadata(&b).unwrap().len(), 64);
+}
+
+#[test]
+fn echoed_outputs_differ_only_with_the_command_line() {
+    let scratch = Scratch::new("echo", r#"{ "runs": [{ "output_bytes": 40, "echo_args": true }] }"#);
+    let (a, b, c) = (scratch.file("a.mp4"), scratch.file("b.mp4"), scratch.file("c.mp4"));
+    for (crf, out) in [("23", &a), ("23", &b), ("28", &c)] {
+        assert!(scratch.run("ffmpeg", &["-i", "in.mov", "-crf", crf, path(out)]).status.success());
+    }
+    let read = |p: &Path| fs::read(p).unwrap();
+    assert_eq!(read(&a).len(), 40);
+    assert_eq!(read(&a), read(&b));
+    assert_ne!(read(&a), read(&c));
+    assert!(read(&a).starts_with(b"-i\x00in.mov\x00-crf\x0023"));
+}
+
+#[test]
+fn two_passes_take_the_runs_in_order() {
+    let scratch = Scratch::new("two-pass", r#"{
+        "runs": [
+            { "stderr": [{ "line": "pass 1" }] },
+            { "stderr": [{ "line": "pass 2" }], "output_bytes": 10 }
+        ]
+    }"#);
+    let output = scratch.file("out.mp4");
+    let first = scratch.run("ffmpeg", &["-pass", "1", "-f", "null", "/dev/null"]);
+    let second = scratch.run("ffmpeg", &["-pass", "2", path(&output)]);
+
+    assert!(first.status.success() && second.status.success());
+    assert!(String::from_utf8_lossy(&first.stderr).contains("pass 1"));
+    assert!(String::from_utf8_lossy(&second.stderr).contains("pass 2"));
+    assert_eq!(fs::metadata(&output).unwrap().len(), 10);
+    // A third run repeats the last entry
+    assert!(String::from_utf8_lossy(&scratch.run("ffmpeg", &[path(&scratch.file("third.mp4"))]).stderr).contains("pass 2"));
+    assert_eq!(scratch.logged_runs().len(), 3);
+}
+
+#[test]
+fn ffprobe_prints_the_scenarios_json() {
+    let scratch = Scratch::new("ffprobe", r#"{ "ffprobe": { "format": { "duration": "12.5" } }, "runs": [] }"#);
+    let run = scratch.run("ffprobe", &["-print_format", "json", "in.mov"]);
+
+    assert!(run.status.success());
+    let printed: serde_json::Value = serde_json::from_slice(&run.stdout).unwrap();
+    assert_eq!(printed["format"]["duration"], "12.5");
+}
+
+#[test]
+fn ffprobe_takes_the_scenarios_probes_in_turn() {
+    let scratch = Scratch::new("probes", r#"{
+        "probes": [
+            { "stderr": "Could not find codec parameters for stream 0", "exit_code": 1 },
+            { "stdout": { "format": { "duration": "12.5" } } }
+        ]
+    }"#);
+    let failed = scratch.run("ffprobe", &["in.mov"]);
+    assert_eq!(failed.status.code(), Some(1));
+    assert!(failed.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&failed.stderr).contains("Could not find codec parameters"));
+
+    // The last one repeats
+    for _ in 0..2 {
+        let run = scratch.run("ffprobe", &["-analyzeduration", "100M", "in.mov"]);
+        assert!(run.status.success());
+        let printed: serde_json::Value = serde_json::from_slice(&run.stdout).unwrap();
+        assert_eq!(printed["format"]["duration"], "12.5");
+    }
+    assert_eq!(scratch.logged_runs().len(), 3);
+}
+
+#[test]
+fn files_a_run_wrote_read_back_as_the_scenarios_output() {
+    let scratch = Scratch::new("probe-output", r#"{
+        "ffprobe": { "streams": [{ "codec_name": "hevc" }] },
+        "ffprobe_output": { "streams": [{ "codec_name": "h264" }] },
+        "runs": [{ "output_bytes": 16 }]
+    }"#);
+    let output = scratch.file("out.mp4");
+    let codec = |file: &str| {
+        let run = scratch.run("ffprobe", &["-print_format", "json", file]);
+        serde_json::from_slice::<serde_json::Value>(&run.stdout).unwrap()["streams"][0]["codec_name"].clone()
+    };
+    assert_eq!(codec(path(&output)), "hevc");
+    assert!(scratch.run("ffmpeg", &["-i", "in.mov", path(&output)]).status.success());
+    assert_eq!(codec(path(&output)), "h264");
+    assert_eq!(codec("in.mov"), "hevc");
+}
+
+#[test]
+fn a_failed_encode_exits_with_its_code_and_writes_nothing() {
+    let scratch = Scratch::new("failure", r#"{
+        "runs": [{
+            "stderr": [
+                { "line": "frame=10 fps=5 time=00:00:01.00 bitrate=800kbits/s speed=1.0x" },
+                { "line": "Error while opening encoder for output stream #0:0" }
+            ],
+            "exit_code": 187,
+            "output_bytes": null
+        }]
+    }"#);
+    let output = scratch.file("out.mp4");
+    let run = scratch.run("ffmpeg", &["-i", "in.mov", path(&output)]);
+
+    assert_eq!(run.status.code(), Some(187));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("Error while opening encoder"));
+    assert!(!output.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn an_aborted_encode_dies_from_a_signal() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let scratch = Scratch::new("abort", r#"{ "runs": [{ "abort": true, "output_bytes": 10 }] }"#);
+    let output = scratch.file("out.mp4");
+    let run = scratch.run("ffmpeg", &[path(&output)]);
+
+    assert_eq!(run.status.code(), None);
+    assert!(run.status.signal().is_some());
+    assert!(!output.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn an_aborted_encode_leaves_what_it_wrote_so_far() {
+    let scratch = Scratch::new("abort-partial", r#"{ "runs": [{ "abort": true, "partial_bytes": 6, "output_bytes": 10 }] }"#);
+    let output = scratch.file("out.mkv");
+    let run = scratch.run("ffmpeg", &[path(&output)]);
+
+    assert_eq!(run.status.code(), None);
+    assert_eq!(fs::metadata(&output).unwrap().len(), 6);
+}
+
+#[test]
+fn a_stalled_encode_can_be_cancelled() {
+    let scratch = Scratch::new("cancel", r#"{
+        "runs": [{
+            "stderr": [{ "line": "frame=10 fps=5 time=00:00:01.00 bitrate=800kbits/s speed=1.0x" }],
+            "hang": true,
+            "output_bytes": 10
+        }]
+    }"#);
+    let output = scratch.file("out.mp4");
+    let mut child = scratch.spawn(&["-i", "in.mov", path(&output)]);
+
+    // Wait for the progress line, then cancel the way a job does
+    let mut first = String::new();
+    BufReader::new(child.stderr.take().unwrap()).read_line(&mut first).unwrap();
+    assert!(first.contains("time=00:00:01.00"));
+    assert!(child.try_wait().unwrap().is_none(), "the stub should still be running");
+    child.kill().unwrap();
+
+    assert!(!child.wait().unwrap().success());
+    assert!(!output.exists());
+}
+
+#[test]
+fn a_missing_scenario_is_an_error() {
+    let run = Command::new(STUB).env("STUB_TOOL", "ffmpeg").env_remove("STUB_FFMPEG_SCENARIO").output().unwrap();
+    assert_eq!(run.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("STUB_FFMPEG_SCENARIO"));
+}
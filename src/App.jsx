@@ -156,8 +156,8 @@ export default function App() {
         await invoke("compress_image", { 
           input: filePath,
           output: outputPath,
-          width: dimSettings.width || "0", 
-          height: dimSettings.height || "0"
+          width: Number(dimSettings.width) || null,
+          height: Number(dimSettings.height) || null
         });
         setProgress(100); 
       } else {
@@ -174,8 +174,10 @@ export default function App() {
       
     } catch (e) { 
       console.error(e); 
-      setLogs("Error: " + e); 
-      await message(`Failed: ${e}`, { title: "Error", kind: "error" });
+      // Compress commands fail with { kind, message, ... }; ffmpeg failures add the stderr tail
+      const text = e?.message ?? String(e);
+      setLogs("Error: " + text + (e?.detail?.length ? "\n\n" + e.detail.join("\n") : ""));
+      await message(`Failed: ${text}`, { title: "Error", kind: "error" });
     } finally { 
       setIsProcessing(false); 
       setTimeLeft(null);